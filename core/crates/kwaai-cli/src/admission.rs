@@ -0,0 +1,223 @@
+//! Signed capability claims in server announcements, and the minimum a
+//! server must prove before it is routed through (`admission` in
+//! config.yaml).
+//!
+//! Every node signs a [`CapabilityAttestation`] of its hardware with its
+//! identity key and announces it under [`FIELD`] in its server info, valid
+//! for as long as the record. Clients use it when they pick servers for a
+//! chain:
+//!
+//!   - admission: with `admission.required`, [`admit_servers`] keeps only
+//!     the servers whose attestation passes the configured
+//!     [`AdmissionPolicy`](kwaai_p2p::AdmissionPolicy)
+//!   - ranking: [`rank_attested`] orders servers with
+//!     [`ReputationStore::rank_attested`], so ones whose claims a verifier
+//!     co-signed are preferred when reputation is otherwise equal
+//!
+//! Legacy announcements carry no attestation. They rank on reputation
+//! alone and are refused only when admission is required.
+
+use std::time::Duration;
+
+use kwaai_p2p::{CapabilityAttestation, NodeCapabilities, VerifiedCapabilities};
+use libp2p::identity::Keypair;
+use tracing::{info, warn};
+
+use crate::calibration::HardwareInfo;
+use crate::config::AdmissionConfig;
+use crate::reputation::ReputationStore;
+use crate::shard_cmd::BlockServerEntry;
+
+/// Key of the encoded attestation in the server-info fields map.
+pub const FIELD: &str = "capability_attestation";
+
+const MB: u64 = 1024 * 1024;
+
+/// What this node claims about itself: inference on `model` with the memory
+/// it can offer (VRAM when it has a GPU).
+///
+/// Compute power is left at zero; only a verifier's benchmark can vouch for
+/// it (see [`CapabilityAttestation::cosign`]).
+pub fn local_capabilities(model: &str, hardware: &HardwareInfo) -> NodeCapabilities {
+    let mut caps = NodeCapabilities::new(String::new());
+    caps.can_inference = true;
+    caps.model_ids = vec![model.to_string()];
+    caps.available_memory = match &hardware.gpu {
+        Some(gpu) => gpu.free_vram,
+        None => hardware.available_memory,
+    } / MB;
+    caps
+}
+
+/// Sign `capabilities` as `keypair`, valid for `ttl`, encoded for the
+/// announcement.
+pub fn issue(keypair: &Keypair, capabilities: &NodeCapabilities, ttl: Duration) -> Option<Vec<u8>> {
+    match CapabilityAttestation::issue(keypair, capabilities.clone(), ttl).and_then(|a| a.encode())
+    {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            warn!("Could not attest capabilities; announcing without them: {e}");
+            None
+        }
+    }
+}
+
+/// An announced attestation, decoded but not verified.
+pub fn decode(bytes: &[u8]) -> Option<CapabilityAttestation> {
+    CapabilityAttestation::decode(bytes).ok()
+}
+
+/// Whether `entry`'s attestation was issued by the server itself.
+fn own_attestation(entry: &BlockServerEntry) -> Option<&CapabilityAttestation> {
+    entry
+        .attestation
+        .as_ref()
+        .filter(|a| a.capabilities.peer_id == entry.peer_id.to_base58())
+}
+
+/// The verified claims of `entry`: its own attestation, signed and unexpired.
+pub fn verified(entry: &BlockServerEntry) -> Option<VerifiedCapabilities> {
+    own_attestation(entry)?.verify().ok()
+}
+
+/// Keep the servers `config` admits; all of them when admission is off.
+pub fn admit_servers(
+    chain: Vec<BlockServerEntry>,
+    config: &AdmissionConfig,
+) -> Vec<BlockServerEntry> {
+    let Some(policy) = config.policy() else {
+        return chain;
+    };
+    let before = chain.len();
+    let admitted: Vec<BlockServerEntry> = chain
+        .into_iter()
+        .filter(|entry| match own_attestation(entry) {
+            Some(attestation) => match policy.admit(attestation) {
+                Ok(_) => true,
+                Err(e) => {
+                    info!("admission: refusing {}: {e}", entry.peer_id);
+                    false
+                }
+            },
+            None => {
+                info!(
+                    "admission: refusing {}: no capability attestation",
+                    entry.peer_id
+                );
+                false
+            }
+        })
+        .collect();
+    if admitted.len() < before {
+        warn!(
+            "admission: {} of {before} server(s) refused",
+            before - admitted.len()
+        );
+    }
+    admitted
+}
+
+/// Rank `chain` by reputation lifted by verified attestations and record
+/// each entry's score in `trust_score`.
+///
+/// The chain stays ordered by `start_block`; servers starting at the same
+/// block come best first.
+pub fn rank_attested(chain: &mut [BlockServerEntry], store: &ReputationStore) {
+    let mut scored: Vec<(String, Option<VerifiedCapabilities>, BlockServerEntry)> = chain
+        .iter()
+        .map(|e| (e.peer_id.to_base58(), verified(e), e.clone()))
+        .collect();
+    store.rank_attested(&mut scored, |s| s.0.as_str(), |s| s.1.as_ref());
+    for (slot, (peer, attestation, mut entry)) in chain.iter_mut().zip(scored) {
+        entry.trust_score = Some(store.attested_selection_score(&peer, attestation.as_ref()));
+        *slot = entry;
+    }
+    chain.sort_by_key(|e| e.start_block);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::PeerId;
+
+    fn entry(keypair: &Keypair, attestation: Option<CapabilityAttestation>) -> BlockServerEntry {
+        BlockServerEntry {
+            peer_id: PeerId::from(keypair.public()),
+            start_block: 0,
+            end_block: 8,
+            public_name: String::new(),
+            throughput: 0.0,
+            trust_score: None,
+            adapters: vec![],
+            dtypes: vec![],
+            attestation,
+        }
+    }
+
+    fn attest(keypair: &Keypair, memory_mb: u64) -> CapabilityAttestation {
+        let mut caps = NodeCapabilities::new(String::new());
+        caps.available_memory = memory_mb;
+        let bytes = issue(keypair, &caps, Duration::from_secs(600)).unwrap();
+        decode(&bytes).unwrap()
+    }
+
+    #[test]
+    fn admission_off_keeps_every_server() {
+        let key = Keypair::generate_ed25519();
+        let chain = vec![entry(&key, None)];
+        assert_eq!(admit_servers(chain, &AdmissionConfig::default()).len(), 1);
+    }
+
+    #[test]
+    fn admission_needs_an_own_attestation_meeting_the_minimums() {
+        let (big, small, bare, thief) = (
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+        );
+        let chain = vec![
+            entry(&big, Some(attest(&big, 16_000))),
+            entry(&small, Some(attest(&small, 2_000))),
+            entry(&bare, None),
+            // Someone else's valid attestation does not vouch for this peer.
+            entry(&thief, Some(attest(&big, 16_000))),
+        ];
+        let config = AdmissionConfig {
+            required: true,
+            min_memory_mb: 8_000,
+            ..Default::default()
+        };
+        let admitted = admit_servers(chain, &config);
+        assert_eq!(admitted.len(), 1);
+        assert_eq!(admitted[0].peer_id, PeerId::from(big.public()));
+    }
+
+    #[test]
+    fn cosigned_servers_rank_first() {
+        let (plain, cosigned, verifier) = (
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+        );
+        let mut vouched = attest(&cosigned, 8_000);
+        vouched
+            .cosign(
+                &verifier,
+                kwaai_p2p::attestation::BenchmarkResult {
+                    compute_power: 0.0,
+                    available_memory: 8_000,
+                    measured_at: crate::reputation::now_secs(),
+                },
+            )
+            .unwrap();
+        let mut chain = vec![
+            entry(&plain, Some(attest(&plain, 8_000))),
+            entry(&cosigned, Some(vouched)),
+        ];
+        // Both serve blocks 0..8.
+        rank_attested(&mut chain, &ReputationStore::default());
+        assert_eq!(chain[0].peer_id, PeerId::from(cosigned.public()));
+        assert!(chain[0].trust_score.unwrap() > chain[1].trust_score.unwrap());
+    }
+}
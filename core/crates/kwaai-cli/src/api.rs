@@ -7,81 +7,358 @@
 //!   GET  /v1/models               — list available models
 //!   POST /v1/chat/completions     — chat (streaming or non-streaming)
 //!   POST /v1/completions          — legacy text completion
+//!   POST /v1/embeddings           — sentence embeddings (BERT-family models)
+//!
+//! Several models can be loaded at once; requests are routed by their
+//! `model` field (see [`crate::model_registry`]).
+//!
+//! Admin endpoints manage the server and the node running next to it:
+//!   GET    /admin/models          — loaded models with kind and defaults
+//!   POST   /admin/models          — load a model (`{"model", "id"?, "default"?}`)
+//!   DELETE /admin/models/{id}     — unload a model once its requests finish
+//!                                   (ids may contain `/`)
+//!   GET    /admin/peers           — the node's current p2pd connections
+//!   POST   /admin/announce        — make the node re-announce to the DHT now
+//!   GET    /admin/ledger          — signed receipts for served work
+//!                                   (`?peer=&days=&limit=`, see [`crate::ledger`])
+//!   GET    /admin/config          — live-tunable server settings
+//!   PATCH  /admin/config          — change some of them
+//!
+//! With `admin_token` set in config.yaml they require
+//! `Authorization: Bearer <token>`; without it they only answer clients on
+//! this machine. `/admin/peers` and `/admin/announce` go through the node's
+//! control socket and return 503 while no node is running.
+//!
+//! Generation and embedding requests share a bounded queue (`queue_depth`);
+//! while it is full they get 429 with `Retry-After` instead of waiting.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{ConnectInfo, Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{
         sse::{Event, Sse},
         IntoResponse, Json, Response,
     },
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use futures::stream;
-use kwaai_inference::{InferenceEngine, InferenceProvider, ModelHandle};
+use kwaai_inference::{
+    generation::MAX_TOP_LOGPROBS, ChatMessage, ChatTemplate, Embeddings, GenerateOptions,
+    Generation, InferenceEngine, InferenceError, InferenceProvider, ModelFormat, ModelHandle,
+    ResponseFormat, Sequence, TokenLogprob,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::convert::Infallible;
-use std::sync::{mpsc, Arc};
-use tracing::info;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::{Duration, Instant};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{debug, info, warn};
+
+use crate::api_keys::{self, KeyRing, Rejection};
+use crate::config::ApiConfig;
+use crate::control::{self, ControlMethod};
+use crate::listen::ApiListen;
+use crate::model_registry::{self, ModelKind, ModelRegistry, RegisteredModel, ResolveError};
+use crate::request_queue::{self, RequestQueue};
 
 // ---------------------------------------------------------------------------
 // Inference worker thread
 //
 // The ML model (especially with Metal backend on macOS) needs to run on a
 // stable, non-moving thread.  We spawn one OS thread that owns the engine
-// for its entire lifetime and communicate with it via a sync channel.
+// for its entire lifetime and communicate with it via a channel.  The
+// channel itself is unbounded; handlers take a `RequestQueue` slot before
+// sending work, so at most `queue_depth` requests are ever waiting on it.
+//
+// The thread runs a continuous-batching scheduler: up to `max_batch_size`
+// generations are active at once and every tick advances each of them by
+// one decode step, so a long completion no longer holds up the requests
+// queued behind it.  Finished sequences leave the batch and waiting ones
+// take their slots between ticks.
+//
+// The engine may hold several models; every request carries the handle of
+// the model it was routed to.  Loading a model runs on the same thread, so
+// active generations pause while it loads.  Unloading waits until no queued
+// or active generation still uses the model.
 // ---------------------------------------------------------------------------
 
+type Reply<T> = mpsc::SyncSender<kwaai_inference::InferenceResult<T>>;
+type GenerateReply = Reply<Generation>;
+
 enum WorkerMsg {
-    Generate {
-        prompt: String,
-        reply: mpsc::SyncSender<kwaai_inference::InferenceResult<String>>,
+    Generate(PendingGenerate),
+    Embed {
+        handle: ModelHandle,
+        inputs: Vec<String>,
+        reply: Reply<Embeddings>,
     },
+    /// Load a model; replies with its handle and whether it is an embedding model.
+    Load {
+        path: PathBuf,
+        format: ModelFormat,
+        reply: Reply<(ModelHandle, bool)>,
+    },
+    Unload {
+        handle: ModelHandle,
+        reply: Reply<()>,
+    },
+    /// The chat prompt format of a model.
+    ChatTemplate {
+        handle: ModelHandle,
+        reply: Reply<ChatTemplate>,
+    },
+    /// Change how many generations are decoded concurrently.
+    SetMaxBatch(usize),
+}
+
+/// A generate request waiting for a batch slot.
+struct PendingGenerate {
+    handle: ModelHandle,
+    prompt: String,
+    opts: GenerateOptions,
+    reply: GenerateReply,
 }
 
 struct InferenceWorker {
-    tx: mpsc::SyncSender<WorkerMsg>,
+    tx: mpsc::Sender<WorkerMsg>,
+}
+
+/// A generation that holds a batch slot.
+struct ActiveSequence {
+    seq: Sequence,
+    reply: GenerateReply,
+}
+
+/// Continuous-batching state owned by the worker thread.
+struct Scheduler {
+    engine: InferenceEngine,
+    max_batch: usize,
+    /// Generate requests waiting for a batch slot, oldest first.
+    waiting: VecDeque<PendingGenerate>,
+    active: Vec<ActiveSequence>,
+    /// Models to unload once no generation uses them.
+    unloading: Vec<(ModelHandle, Reply<()>)>,
+}
+
+impl Scheduler {
+    fn new(engine: InferenceEngine) -> Self {
+        let max_batch = engine.config().max_batch_size.max(1);
+        Self {
+            engine,
+            max_batch,
+            waiting: VecDeque::new(),
+            active: Vec::new(),
+            unloading: Vec::new(),
+        }
+    }
+
+    /// Serve `rx` until every sender is gone and all accepted work is done.
+    fn run(mut self, rx: mpsc::Receiver<WorkerMsg>) {
+        let mut open = true;
+        loop {
+            // Block for new work only when there is nothing left to step.
+            if self.active.is_empty() && self.waiting.is_empty() {
+                if !open {
+                    break;
+                }
+                match rx.recv() {
+                    Ok(msg) => self.accept(msg),
+                    Err(_) => break,
+                }
+            }
+            loop {
+                match rx.try_recv() {
+                    Ok(msg) => self.accept(msg),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        open = false;
+                        break;
+                    }
+                }
+            }
+            self.admit();
+            self.tick();
+            self.finish_unloads();
+        }
+    }
+
+    fn accept(&mut self, msg: WorkerMsg) {
+        match msg {
+            WorkerMsg::Generate(pending) => self.waiting.push_back(pending),
+            // A single encoder pass: run it between decode steps rather
+            // than making it wait for a batch slot.
+            WorkerMsg::Embed {
+                handle,
+                inputs,
+                reply,
+            } => {
+                let _ = reply.send(self.engine.embed(&handle, &inputs));
+            }
+            WorkerMsg::Load {
+                path,
+                format,
+                reply,
+            } => {
+                let loaded = self
+                    .engine
+                    .load_model(&path, format)
+                    .map(|h| (h, self.engine.is_embedding_model(&h)));
+                let _ = reply.send(loaded);
+            }
+            WorkerMsg::Unload { handle, reply } => self.unloading.push((handle, reply)),
+            WorkerMsg::ChatTemplate { handle, reply } => {
+                let _ = reply.send(self.engine.chat_template(&handle));
+            }
+            // Lowering it lets the active sequences finish; `admit` only
+            // refills up to the new limit.
+            WorkerMsg::SetMaxBatch(n) => self.max_batch = n.max(1),
+        }
+    }
+
+    /// Unload the models waiting for it that no generation uses any more.
+    fn finish_unloads(&mut self) {
+        if self.unloading.is_empty() {
+            return;
+        }
+        let (ready, busy): (Vec<_>, Vec<_>) = std::mem::take(&mut self.unloading)
+            .into_iter()
+            .partition(|(handle, _)| {
+                !self.active.iter().any(|a| a.seq.handle() == *handle)
+                    && !self.waiting.iter().any(|w| w.handle == *handle)
+            });
+        self.unloading = busy;
+        for (handle, reply) in ready {
+            let _ = reply.send(self.engine.unload(handle));
+        }
+    }
+
+    /// Start waiting requests while batch slots are free. A sequence that
+    /// needs the model's shared KV-cache only starts on an empty batch and
+    /// then runs alone.
+    fn admit(&mut self) {
+        while self.active.len() < self.max_batch {
+            if self.active.iter().any(|a| a.seq.is_exclusive()) {
+                break;
+            }
+            let Some(pending) = self.waiting.pop_front() else {
+                break;
+            };
+            match self
+                .engine
+                .start_sequence(&pending.handle, &pending.prompt, &pending.opts)
+            {
+                Ok(seq) if seq.is_exclusive() && !self.active.is_empty() => {
+                    self.waiting.push_front(pending);
+                    break;
+                }
+                Ok(seq) => self.active.push(ActiveSequence {
+                    seq,
+                    reply: pending.reply,
+                }),
+                Err(e) => {
+                    let _ = pending.reply.send(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Advance every active sequence by one step and reply to the ones that
+    /// finished.
+    fn tick(&mut self) {
+        let mut still_active = Vec::with_capacity(self.active.len());
+        for mut active in self.active.drain(..) {
+            match self.engine.step(&mut active.seq) {
+                Ok(false) => still_active.push(active),
+                Ok(true) => {
+                    let _ = active.reply.send(self.engine.finish_sequence(active.seq));
+                }
+                Err(e) => {
+                    let _ = active.reply.send(Err(e));
+                }
+            }
+        }
+        self.active = still_active;
+    }
 }
 
 impl InferenceWorker {
-    fn spawn(engine: InferenceEngine, handle: ModelHandle) -> Self {
-        let (tx, rx) = mpsc::sync_channel::<WorkerMsg>(4);
+    fn spawn(engine: InferenceEngine) -> Self {
+        let (tx, rx) = mpsc::channel::<WorkerMsg>();
         std::thread::Builder::new()
             .name("kwaai-inference".into())
-            .spawn(move || {
-                while let Ok(msg) = rx.recv() {
-                    match msg {
-                        WorkerMsg::Generate { prompt, reply } => {
-                            let result = engine.generate(&handle, &prompt);
-                            let _ = reply.send(result);
-                        }
-                    }
-                }
-            })
+            .spawn(move || Scheduler::new(engine).run(rx))
             .expect("failed to spawn inference thread");
         Self { tx }
     }
 
-    /// Run inference, returning a future that resolves once generation is done.
-    async fn generate(&self, prompt: String) -> Result<String> {
+    /// Send the message built by `msg` and wait for the worker's reply.
+    async fn call<T: Send + 'static>(&self, msg: impl FnOnce(Reply<T>) -> WorkerMsg) -> Result<T> {
         let (reply_tx, reply_rx) = mpsc::sync_channel(1);
         self.tx
-            .send(WorkerMsg::Generate {
-                prompt,
-                reply: reply_tx,
-            })
+            .send(msg(reply_tx))
             .map_err(|_| anyhow::anyhow!("inference worker disconnected"))?;
         // recv() blocks — offload to the blocking thread pool.
         tokio::task::spawn_blocking(move || {
             reply_rx
                 .recv()
                 .map_err(|_| anyhow::anyhow!("inference worker disconnected"))?
-                .map_err(|e| anyhow::anyhow!("{e}"))
+                .map_err(anyhow::Error::from)
         })
         .await?
     }
+
+    /// Run inference, returning a future that resolves once generation is done.
+    async fn generate(
+        &self,
+        handle: ModelHandle,
+        prompt: String,
+        opts: GenerateOptions,
+    ) -> Result<Generation> {
+        self.call(|reply| {
+            WorkerMsg::Generate(PendingGenerate {
+                handle,
+                prompt,
+                opts,
+                reply,
+            })
+        })
+        .await
+    }
+
+    /// Embed `inputs` on the worker thread.
+    async fn embed(&self, handle: ModelHandle, inputs: Vec<String>) -> Result<Embeddings> {
+        self.call(|reply| WorkerMsg::Embed {
+            handle,
+            inputs,
+            reply,
+        })
+        .await
+    }
+
+    async fn load(&self, path: PathBuf, format: ModelFormat) -> Result<(ModelHandle, bool)> {
+        self.call(|reply| WorkerMsg::Load {
+            path,
+            format,
+            reply,
+        })
+        .await
+    }
+
+    /// Unload `handle` once the generations using it have finished.
+    async fn unload(&self, handle: ModelHandle) -> Result<()> {
+        self.call(|reply| WorkerMsg::Unload { handle, reply }).await
+    }
+
+    async fn chat_template(&self, handle: ModelHandle) -> Result<ChatTemplate> {
+        self.call(|reply| WorkerMsg::ChatTemplate { handle, reply })
+            .await
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -90,25 +367,91 @@ impl InferenceWorker {
 
 struct AppState {
     worker: InferenceWorker,
-    model_id: String,
+    /// Loaded models; never held across an `.await`.
+    models: RwLock<ModelRegistry>,
+    /// Settings `PATCH /admin/config` can change.
+    settings: RwLock<Settings>,
+    /// Bearer token for the admin routes; without one they are local-only.
+    admin_token: Option<String>,
+    /// API keys for the `/v1` routes; none means open access.
+    keys: KeyRing,
+    /// Generation and embedding requests waiting or running.
+    queue: RequestQueue,
 }
 type AppStateRef = Arc<AppState>;
 
+/// Live-tunable server settings.
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    /// Default wall-clock budget per generation request, if any.
+    request_timeout: Option<Duration>,
+    /// Generations decoded concurrently by the worker.
+    max_batch: usize,
+}
+
+impl AppState {
+    fn settings(&self) -> Settings {
+        *self.settings.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Id and handle of the model that serves a `kind` request for `requested`.
+    fn route(
+        &self,
+        requested: &str,
+        kind: ModelKind,
+    ) -> Result<(String, ModelHandle), ResolveError> {
+        let models = self.models.read().unwrap_or_else(|e| e.into_inner());
+        models
+            .resolve(requested, kind)
+            .map(|m| (m.id.clone(), m.handle))
+    }
+}
+
+/// 429 for a request that found the queue full.
+fn queue_full(queue: &RequestQueue) -> Response {
+    debug!("Request queue full ({} requests)", queue.len());
+    let mut response = api_error(StatusCode::TOO_MANY_REQUESTS, request_queue::BUSY);
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(queue.retry_after().as_secs()),
+    );
+    response
+}
+
+fn route_error(e: ResolveError) -> Response {
+    let status = match e {
+        ResolveError::NotFound { .. } => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    api_error(status, &e.to_string())
+}
+
 // ---------------------------------------------------------------------------
 // OpenAI request types
 // ---------------------------------------------------------------------------
 
 #[derive(Debug, Deserialize)]
 struct ChatRequest {
-    #[allow(dead_code)]
+    #[serde(default)]
     model: String,
     messages: Vec<ChatMsg>,
     #[serde(default)]
     stream: bool,
-    #[allow(dead_code)]
     max_tokens: Option<u32>,
-    #[allow(dead_code)]
     temperature: Option<f64>,
+    /// Return the log-probability of each output token.
+    #[serde(default)]
+    logprobs: bool,
+    /// Number of most likely alternatives per position (requires `logprobs`).
+    top_logprobs: Option<u32>,
+    /// Up to 4 strings that end the completion; they are not returned.
+    stop: Option<OneOrMany>,
+    /// `{"type": "json_object"}` constrains the reply to a JSON object.
+    response_format: Option<ResponseFormatField>,
+    /// KwaaiNet extension: wall-clock budget in milliseconds. When it runs
+    /// out, the tokens produced so far are returned with
+    /// `finish_reason: "length"`. Overrides `--request-timeout`.
+    timeout_ms: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -119,15 +462,64 @@ struct ChatMsg {
 
 #[derive(Debug, Deserialize)]
 struct CompletionRequest {
-    #[allow(dead_code)]
+    #[serde(default)]
     model: String,
     prompt: String,
     #[serde(default)]
     stream: bool,
-    #[allow(dead_code)]
     max_tokens: Option<u32>,
-    #[allow(dead_code)]
     temperature: Option<f64>,
+    /// Legacy form: number of alternatives per position; `0` returns only
+    /// the sampled token's log-probability.
+    logprobs: Option<u32>,
+    /// Up to 4 strings that end the completion; they are not returned.
+    stop: Option<OneOrMany>,
+    /// KwaaiNet extension: wall-clock budget in milliseconds (see
+    /// [`ChatRequest::timeout_ms`]).
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingRequest {
+    #[serde(default)]
+    model: String,
+    input: OneOrMany,
+    /// Only `"float"` is supported.
+    encoding_format: Option<String>,
+}
+
+/// OpenAI `response_format`; only the `type` is read.
+#[derive(Debug, Deserialize)]
+struct ResponseFormatField {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl ResponseFormatField {
+    fn parse(&self) -> Option<ResponseFormat> {
+        match self.kind.as_str() {
+            "text" => Some(ResponseFormat::Text),
+            "json_object" => Some(ResponseFormat::JsonObject),
+            _ => None,
+        }
+    }
+}
+
+/// `input` and `stop` may be a single string or an array of strings.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(s) => vec![s],
+            OneOrMany::Many(v) => v,
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -162,6 +554,8 @@ struct ChatCompletionResponse {
 struct ChatChoice {
     index: u32,
     message: ChatMsg,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<ChatLogprobs>,
     finish_reason: &'static str,
 }
 
@@ -178,6 +572,8 @@ struct ChatChunk {
 struct ChunkChoice {
     index: u32,
     delta: Delta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<ChatLogprobs>,
     finish_reason: Option<&'static str>,
 }
 
@@ -203,6 +599,8 @@ struct CompletionResponse {
 struct CompletionChoice {
     text: String,
     index: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<CompletionLogprobs>,
     finish_reason: &'static str,
 }
 
@@ -213,22 +611,129 @@ struct Usage {
     total_tokens: u32,
 }
 
+impl From<&Generation> for Usage {
+    fn from(g: &Generation) -> Self {
+        Self {
+            prompt_tokens: g.prompt_tokens as u32,
+            completion_tokens: g.completion_tokens as u32,
+            total_tokens: (g.prompt_tokens + g.completion_tokens) as u32,
+        }
+    }
+}
+
+/// Chat-style `logprobs` object: `{"content": [...]}`.
+#[derive(Serialize)]
+struct ChatLogprobs {
+    content: Vec<ChatTokenLogprob>,
+}
+
+#[derive(Serialize)]
+struct ChatTokenLogprob {
+    token: String,
+    logprob: f32,
+    bytes: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_logprobs: Option<Vec<ChatTopLogprob>>,
+}
+
+#[derive(Serialize)]
+struct ChatTopLogprob {
+    token: String,
+    logprob: f32,
+    bytes: Vec<u8>,
+}
+
+impl ChatLogprobs {
+    fn new(tokens: &[TokenLogprob]) -> Self {
+        let content = tokens
+            .iter()
+            .map(|t| ChatTokenLogprob {
+                token: t.token.clone(),
+                logprob: t.logprob,
+                bytes: t.token.as_bytes().to_vec(),
+                top_logprobs: Some(
+                    t.top_logprobs
+                        .iter()
+                        .map(|a| ChatTopLogprob {
+                            token: a.token.clone(),
+                            logprob: a.logprob,
+                            bytes: a.token.as_bytes().to_vec(),
+                        })
+                        .collect(),
+                ),
+            })
+            .collect();
+        Self { content }
+    }
+}
+
+/// Legacy completions `logprobs` object (parallel arrays).
+#[derive(Serialize)]
+struct CompletionLogprobs {
+    tokens: Vec<String>,
+    token_logprobs: Vec<f32>,
+    top_logprobs: Vec<std::collections::HashMap<String, f32>>,
+    text_offset: Vec<usize>,
+}
+
+impl CompletionLogprobs {
+    fn new(tokens: &[TokenLogprob], prompt_len: usize) -> Self {
+        let mut out = Self {
+            tokens: Vec::with_capacity(tokens.len()),
+            token_logprobs: Vec::with_capacity(tokens.len()),
+            top_logprobs: Vec::with_capacity(tokens.len()),
+            text_offset: Vec::with_capacity(tokens.len()),
+        };
+        let mut offset = prompt_len;
+        for t in tokens {
+            out.tokens.push(t.token.clone());
+            out.token_logprobs.push(t.logprob);
+            out.top_logprobs.push(
+                t.top_logprobs
+                    .iter()
+                    .map(|a| (a.token.clone(), a.logprob))
+                    .collect(),
+            );
+            out.text_offset.push(offset);
+            offset += t.token.len();
+        }
+        out
+    }
+}
+
+#[derive(Serialize)]
+struct EmbeddingResponse {
+    object: &'static str,
+    data: Vec<EmbeddingObject>,
+    model: String,
+    usage: EmbeddingUsage,
+}
+
+#[derive(Serialize)]
+struct EmbeddingObject {
+    object: &'static str,
+    embedding: Vec<f32>,
+    index: u32,
+}
+
+#[derive(Serialize)]
+struct EmbeddingUsage {
+    prompt_tokens: u32,
+    total_tokens: u32,
+}
+
 // ---------------------------------------------------------------------------
 // Chat template
 // ---------------------------------------------------------------------------
 
-/// Format messages as a Llama 3 instruct prompt.
-/// Other popular formats (Mistral, ChatML) share a similar structure.
-fn build_prompt(messages: &[ChatMsg]) -> String {
-    let mut s = String::from("<|begin_of_text|>");
-    for msg in messages {
-        s.push_str(&format!(
-            "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
-            msg.role, msg.content
-        ));
-    }
-    s.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
-    s
+/// Format messages in the model's own chat format. BOS is added by the
+/// engine when the prompt is encoded.
+fn build_prompt(template: ChatTemplate, messages: &[ChatMsg]) -> String {
+    let messages: Vec<ChatMessage> = messages
+        .iter()
+        .map(|m| ChatMessage::new(&m.role, &m.content))
+        .collect();
+    template.render(&messages)
 }
 
 // ---------------------------------------------------------------------------
@@ -236,34 +741,79 @@ fn build_prompt(messages: &[ChatMsg]) -> String {
 // ---------------------------------------------------------------------------
 
 async fn list_models(State(state): State<AppStateRef>) -> Json<ModelsResponse> {
+    let models = state.models.read().unwrap_or_else(|e| e.into_inner());
     Json(ModelsResponse {
         object: "list",
-        data: vec![ModelObject {
-            id: state.model_id.clone(),
-            object: "model",
-            created: unix_now(),
-            owned_by: "kwaai",
-        }],
+        data: models
+            .models()
+            .map(|m| ModelObject {
+                id: m.id.clone(),
+                object: "model",
+                created: m.loaded_at,
+                owned_by: "kwaai",
+            })
+            .collect(),
     })
 }
 
 async fn chat_completions(
     State(state): State<AppStateRef>,
-    Json(req): Json<ChatRequest>,
+    Json(mut req): Json<ChatRequest>,
 ) -> Response {
-    let prompt = build_prompt(&req.messages);
-    let model_id = state.model_id.clone();
+    if req.top_logprobs.is_some() && !req.logprobs {
+        return api_error(
+            StatusCode::BAD_REQUEST,
+            "top_logprobs requires logprobs to be set to true",
+        );
+    }
+    let top_logprobs = req.top_logprobs.unwrap_or(0) as usize;
+    if top_logprobs > MAX_TOP_LOGPROBS {
+        return api_error(
+            StatusCode::BAD_REQUEST,
+            &format!("top_logprobs must be at most {MAX_TOP_LOGPROBS}"),
+        );
+    }
+    let mut opts = generate_options(req.max_tokens, req.temperature, req.logprobs, top_logprobs);
+    opts.deadline = request_deadline(state.settings().request_timeout, req.timeout_ms);
+    opts.stop = req.stop.take().map(OneOrMany::into_vec).unwrap_or_default();
+    if let Some(format) = &req.response_format {
+        match format.parse() {
+            Some(f) => opts.response_format = f,
+            None => {
+                return api_error(
+                    StatusCode::BAD_REQUEST,
+                    &format!("response_format type '{}' is not supported", format.kind),
+                )
+            }
+        }
+    }
 
-    let text = match state.worker.generate(prompt).await {
-        Ok(t) => t,
-        Err(e) => return api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    let (model_id, handle) = match state.route(&req.model, ModelKind::Generation) {
+        Ok(m) => m,
+        Err(e) => return route_error(e),
+    };
+    let prompt = match state.worker.chat_template(handle).await {
+        Ok(template) => build_prompt(template, &req.messages),
+        Err(e) => return generation_error(e),
+    };
+
+    let Some(_slot) = state.queue.try_enter() else {
+        return queue_full(&state.queue);
+    };
+    let generation = match state.worker.generate(handle, prompt, opts).await {
+        Ok(g) => g,
+        Err(e) => return generation_error(e),
     };
 
     let id = make_id("chatcmpl");
     let created = unix_now();
-    let n_tokens = estimate_tokens(&text);
+    let usage = Usage::from(&generation);
+    let tokens = usage.total_tokens;
+    let finish_reason = finish_reason(&generation);
+    let logprobs = generation.logprobs.as_deref().map(ChatLogprobs::new);
+    let text = generation.text;
 
-    if req.stream {
+    let response = if req.stream {
         // Deliver the entire response as a single SSE content chunk followed by [DONE].
         // Token-by-token streaming requires changes to the inference engine (future work).
         let chunk = ChatChunk {
@@ -277,7 +827,8 @@ async fn chat_completions(
                     role: None,
                     content: Some(text),
                 },
-                finish_reason: Some("stop"),
+                logprobs,
+                finish_reason: Some(finish_reason),
             }],
         };
         let data = match serde_json::to_string(&chunk) {
@@ -301,41 +852,73 @@ async fn chat_completions(
                     role: "assistant".into(),
                     content: text,
                 },
-                finish_reason: "stop",
+                logprobs,
+                finish_reason,
             }],
-            usage: Usage {
-                prompt_tokens: 0,
-                completion_tokens: n_tokens,
-                total_tokens: n_tokens,
-            },
+            usage,
         })
         .into_response()
-    }
+    };
+    counted(response, tokens)
 }
 
 async fn completions(
     State(state): State<AppStateRef>,
-    Json(req): Json<CompletionRequest>,
+    Json(mut req): Json<CompletionRequest>,
 ) -> Response {
+    let top_logprobs = req.logprobs.unwrap_or(0) as usize;
+    if top_logprobs > MAX_TOP_LOGPROBS {
+        return api_error(
+            StatusCode::BAD_REQUEST,
+            &format!("logprobs must be at most {MAX_TOP_LOGPROBS}"),
+        );
+    }
+    let mut opts = generate_options(
+        req.max_tokens,
+        req.temperature,
+        req.logprobs.is_some(),
+        top_logprobs,
+    );
+    opts.deadline = request_deadline(state.settings().request_timeout, req.timeout_ms);
+    opts.stop = req.stop.take().map(OneOrMany::into_vec).unwrap_or_default();
+
+    let (model_id, handle) = match state.route(&req.model, ModelKind::Generation) {
+        Ok(m) => m,
+        Err(e) => return route_error(e),
+    };
     let prompt = req.prompt.clone();
-    let model_id = state.model_id.clone();
 
-    let text = match state.worker.generate(prompt).await {
-        Ok(t) => t,
-        Err(e) => return api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    let Some(_slot) = state.queue.try_enter() else {
+        return queue_full(&state.queue);
+    };
+    let generation = match state.worker.generate(handle, prompt, opts).await {
+        Ok(g) => g,
+        Err(e) => return generation_error(e),
     };
 
     let id = make_id("cmpl");
     let created = unix_now();
-    let n_tokens = estimate_tokens(&text);
+    let usage = Usage::from(&generation);
+    let tokens = usage.total_tokens;
+    let finish_reason = finish_reason(&generation);
+    let logprobs = generation
+        .logprobs
+        .as_deref()
+        .map(|t| CompletionLogprobs::new(t, req.prompt.len()));
+    let text = generation.text;
 
-    if req.stream {
+    let response = if req.stream {
         let data = serde_json::json!({
             "id": id,
             "object": "text_completion",
             "created": created,
             "model": model_id,
-            "choices": [{ "text": &text, "index": 0, "finish_reason": "stop" }]
+            "choices": [{
+                "text": &text,
+                "index": 0,
+                "logprobs": logprobs,
+                "finish_reason": finish_reason,
+            }]
         })
         .to_string();
         let events: Vec<Result<Event, Infallible>> = vec![
@@ -352,15 +935,377 @@ async fn completions(
             choices: vec![CompletionChoice {
                 text,
                 index: 0,
-                finish_reason: "stop",
+                logprobs,
+                finish_reason,
             }],
-            usage: Usage {
-                prompt_tokens: 0,
-                completion_tokens: n_tokens,
-                total_tokens: n_tokens,
-            },
+            usage,
         })
         .into_response()
+    };
+    counted(response, tokens)
+}
+
+async fn embeddings(
+    State(state): State<AppStateRef>,
+    Json(req): Json<EmbeddingRequest>,
+) -> Response {
+    let (model_id, handle) = match state.route(&req.model, ModelKind::Embedding) {
+        Ok(m) => m,
+        Err(e) => return route_error(e),
+    };
+    if let Some(fmt) = req.encoding_format.as_deref() {
+        if fmt != "float" {
+            return api_error(
+                StatusCode::BAD_REQUEST,
+                &format!("encoding_format '{fmt}' is not supported; use 'float'"),
+            );
+        }
+    }
+    let inputs = req.input.into_vec();
+    if inputs.is_empty() {
+        return api_error(StatusCode::BAD_REQUEST, "input must not be empty");
+    }
+
+    let Some(_slot) = state.queue.try_enter() else {
+        return queue_full(&state.queue);
+    };
+    let result = match state.worker.embed(handle, inputs).await {
+        Ok(r) => r,
+        Err(e) => return api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+
+    let tokens = result.prompt_tokens as u32;
+    let response = Json(EmbeddingResponse {
+        object: "list",
+        data: result
+            .vectors
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| EmbeddingObject {
+                object: "embedding",
+                embedding,
+                index: i as u32,
+            })
+            .collect(),
+        model: model_id,
+        usage: EmbeddingUsage {
+            prompt_tokens: tokens,
+            total_tokens: tokens,
+        },
+    })
+    .into_response();
+    counted(response, tokens)
+}
+
+// ---------------------------------------------------------------------------
+// Admin
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct LoadModelRequest {
+    /// Ollama name (`llama3.1:8b`) or HuggingFace repo (`owner/model`).
+    model: String,
+    /// Id clients put in `model`; defaults to `model`. Loading an id that is
+    /// already loaded swaps the new copy in.
+    id: Option<String>,
+    /// Also serve requests that don't name a loaded model.
+    #[serde(default)]
+    default: bool,
+}
+
+#[derive(Serialize)]
+struct AdminModel {
+    #[serde(flatten)]
+    model: RegisteredModel,
+    default: bool,
+}
+
+/// Check the API key of a `/v1` request against its limits, then count the
+/// tokens the response used against the key's quota. Open when no keys are
+/// configured.
+async fn api_key_guard(State(state): State<AppStateRef>, req: Request, next: Next) -> Response {
+    if state.keys.is_open() {
+        return next.run(req).await;
+    }
+    let key = match state.keys.admit(bearer_token(req.headers())) {
+        Ok(key) => key,
+        Err(rejection) => {
+            if rejection != Rejection::Unauthorized {
+                debug!("Rejected request: {}", rejection.message());
+            }
+            let status = match rejection {
+                Rejection::Unauthorized => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::TOO_MANY_REQUESTS,
+            };
+            let mut response = api_error(status, &rejection.message());
+            if let Some(wait) = rejection.retry_after() {
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(wait.as_secs().max(1)),
+                );
+            }
+            return response;
+        }
+    };
+    let response = next.run(req).await;
+    if let Some(TokensUsed(tokens)) = response.extensions().get::<TokensUsed>() {
+        debug!("API key '{}' used {} tokens", state.keys.name(key), tokens);
+        state.keys.record(key, u64::from(*tokens));
+    }
+    response
+}
+
+/// CORS for `origins` (`*` for any); `None` when no origin is allowed.
+fn cors_layer(origins: &[String]) -> Result<Option<CorsLayer>> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let allow = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        let list = origins
+            .iter()
+            .map(|o| {
+                HeaderValue::from_str(o.trim_end_matches('/'))
+                    .map_err(|_| anyhow::anyhow!("invalid CORS origin '{o}'"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(list)
+    };
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow)
+            .allow_methods(Any)
+            .allow_headers(Any)
+            .expose_headers([header::RETRY_AFTER]),
+    ))
+}
+
+/// Let a request through to the admin routes if it carries the configured
+/// bearer token, or, with no token configured, if it comes from this machine.
+async fn admin_guard(
+    State(state): State<AppStateRef>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match &state.admin_token {
+        Some(token) => {
+            let given = bearer_token(req.headers());
+            if !given.is_some_and(|given| api_keys::secret_matches(given, token)) {
+                return api_error(StatusCode::UNAUTHORIZED, "missing or invalid admin token");
+            }
+        }
+        None if !addr.ip().is_loopback() => {
+            return api_error(
+                StatusCode::FORBIDDEN,
+                "admin endpoints are only available from localhost unless admin_token is set",
+            );
+        }
+        None => {}
+    }
+    next.run(req).await
+}
+
+/// Call the running node over its control socket.
+async fn node_call(method: ControlMethod) -> Response {
+    match control::call(method).await {
+        Ok(Some(result)) => Json(result).into_response(),
+        Ok(None) => api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no node is running (start one with: kwaainet start --daemon)",
+        ),
+        Err(e) => api_error(StatusCode::BAD_GATEWAY, &format!("{e:#}")),
+    }
+}
+
+async fn admin_peers() -> Response {
+    node_call(ControlMethod::Peers).await
+}
+
+async fn admin_announce() -> Response {
+    node_call(ControlMethod::Announce).await
+}
+
+#[derive(Serialize)]
+struct AdminConfig {
+    /// `0` when unlimited.
+    request_timeout_secs: u64,
+    max_batch: usize,
+    queue_depth: usize,
+    /// Requests waiting or running right now.
+    queued: usize,
+    default_model: Option<String>,
+    default_embedding_model: Option<String>,
+}
+
+/// Fields of `PATCH /admin/config`; omitted ones are left alone.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdminConfigPatch {
+    /// `0` removes the limit.
+    request_timeout_secs: Option<u64>,
+    max_batch: Option<usize>,
+    queue_depth: Option<usize>,
+    default_model: Option<String>,
+    default_embedding_model: Option<String>,
+}
+
+fn admin_config(state: &AppState) -> AdminConfig {
+    let settings = state.settings();
+    let models = state.models.read().unwrap_or_else(|e| e.into_inner());
+    AdminConfig {
+        request_timeout_secs: settings.request_timeout.map_or(0, |t| t.as_secs()),
+        max_batch: settings.max_batch,
+        queue_depth: state.queue.depth(),
+        queued: state.queue.len(),
+        default_model: models.default_id(ModelKind::Generation).map(String::from),
+        default_embedding_model: models.default_id(ModelKind::Embedding).map(String::from),
+    }
+}
+
+async fn admin_get_config(State(state): State<AppStateRef>) -> Json<AdminConfig> {
+    Json(admin_config(&state))
+}
+
+async fn admin_patch_config(
+    State(state): State<AppStateRef>,
+    Json(patch): Json<AdminConfigPatch>,
+) -> Response {
+    if patch.max_batch == Some(0) {
+        return api_error(StatusCode::BAD_REQUEST, "max_batch must be at least 1");
+    }
+    if patch.queue_depth == Some(0) {
+        return api_error(StatusCode::BAD_REQUEST, "queue_depth must be at least 1");
+    }
+    {
+        let mut models = state.models.write().unwrap_or_else(|e| e.into_inner());
+        // Check both defaults before changing either.
+        let defaults = [
+            (patch.default_model.as_deref(), ModelKind::Generation),
+            (
+                patch.default_embedding_model.as_deref(),
+                ModelKind::Embedding,
+            ),
+        ];
+        for (id, kind) in defaults {
+            if let Some(id) = id {
+                if let Err(e) = models.resolve_exact(id, kind) {
+                    return route_error(e);
+                }
+            }
+        }
+        for (id, _) in defaults {
+            if let Some(id) = id {
+                models.set_default(id);
+            }
+        }
+    }
+    let mut settings = state.settings.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(secs) = patch.request_timeout_secs {
+        settings.request_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+    }
+    if let Some(n) = patch.max_batch {
+        settings.max_batch = n;
+        let _ = state.worker.tx.send(WorkerMsg::SetMaxBatch(n));
+    }
+    if let Some(n) = patch.queue_depth {
+        state.queue.set_depth(n);
+    }
+    drop(settings);
+    info!("Admin config updated: {:?}", patch);
+    Json(admin_config(&state)).into_response()
+}
+
+async fn admin_list_models(State(state): State<AppStateRef>) -> Json<Vec<AdminModel>> {
+    let models = state.models.read().unwrap_or_else(|e| e.into_inner());
+    Json(
+        models
+            .models()
+            .map(|m| AdminModel {
+                model: m.clone(),
+                default: models.default_id(m.kind) == Some(m.id.as_str()),
+            })
+            .collect(),
+    )
+}
+
+async fn admin_load_model(
+    State(state): State<AppStateRef>,
+    Json(req): Json<LoadModelRequest>,
+) -> Response {
+    let id = req.id.unwrap_or_else(|| req.model.clone());
+    if id.is_empty() {
+        return api_error(StatusCode::BAD_REQUEST, "model must not be empty");
+    }
+    let name = req.model.clone();
+    let (path, format) =
+        match tokio::task::spawn_blocking(move || model_registry::locate_model(&name)).await {
+            Ok(Ok(found)) => found,
+            Ok(Err(e)) => return api_error(StatusCode::NOT_FOUND, &format!("{e:#}")),
+            Err(e) => return api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        };
+
+    info!("Loading model '{}' as '{}'", req.model, id);
+    let (handle, embedding) = match state.worker.load(path, format).await {
+        Ok(loaded) => loaded,
+        Err(e) => return api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let model = RegisteredModel {
+        id: id.clone(),
+        handle,
+        kind: if embedding {
+            ModelKind::Embedding
+        } else {
+            ModelKind::Generation
+        },
+        loaded_at: unix_now(),
+    };
+    let kind = model.kind;
+    let replaced = {
+        let mut models = state.models.write().unwrap_or_else(|e| e.into_inner());
+        let replaced = models.insert(model);
+        if req.default {
+            models.set_default(&id);
+        }
+        replaced
+    };
+    // New requests already go to the new copy; the old one is unloaded once
+    // the requests routed to it have finished.
+    if let Some(old) = replaced {
+        info!("Swapped in a new copy of '{}'", id);
+        let state = state.clone();
+        let id = id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = state.worker.unload(old).await {
+                warn!("Unloading the previous copy of '{}' failed: {}", id, e);
+            }
+        });
+    }
+    Json(serde_json::json!({
+        "id": id,
+        "kind": kind,
+        "replaced": replaced.is_some(),
+    }))
+    .into_response()
+}
+
+async fn admin_unload_model(State(state): State<AppStateRef>, Path(id): Path<String>) -> Response {
+    let removed = state
+        .models
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&id);
+    let Some(removed) = removed else {
+        return api_error(
+            StatusCode::NOT_FOUND,
+            &format!("model '{id}' is not loaded"),
+        );
+    };
+    info!("Unloading model '{}'", id);
+    match state.worker.unload(removed.handle).await {
+        Ok(()) => Json(serde_json::json!({ "id": id, "unloaded": true })).into_response(),
+        Err(e) => api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     }
 }
 
@@ -368,6 +1313,22 @@ async fn completions(
 // Helpers
 // ---------------------------------------------------------------------------
 
+/// Tokens a response used, read back by [`api_key_guard`] for quotas.
+#[derive(Clone, Copy)]
+struct TokensUsed(u32);
+
+fn counted(mut response: Response, tokens: u32) -> Response {
+    response.extensions_mut().insert(TokensUsed(tokens));
+    response
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
 fn unix_now() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -383,9 +1344,50 @@ fn make_id(prefix: &str) -> String {
     format!("{}-{}{:05}", prefix, unix_now(), nanos % 100_000)
 }
 
-/// Rough token count estimate (~4 chars/token on average).
-fn estimate_tokens(text: &str) -> u32 {
-    ((text.len() as u32) / 4).max(1)
+/// Map OpenAI request knobs onto engine options, keeping engine defaults
+/// for anything the client did not set.
+fn generate_options(
+    max_tokens: Option<u32>,
+    temperature: Option<f64>,
+    logprobs: bool,
+    top_logprobs: usize,
+) -> GenerateOptions {
+    let defaults = GenerateOptions::default();
+    GenerateOptions {
+        max_new_tokens: max_tokens.map_or(defaults.max_new_tokens, |n| n as usize),
+        temperature: temperature.unwrap_or(defaults.temperature),
+        logprobs,
+        top_logprobs,
+        ..defaults
+    }
+}
+
+/// Absolute deadline for a request: the per-request `timeout_ms` if given,
+/// otherwise the server default. Measured from arrival, so time spent queued
+/// behind other requests counts against the budget.
+fn request_deadline(default: Option<Duration>, timeout_ms: Option<u64>) -> Option<Instant> {
+    timeout_ms
+        .map(Duration::from_millis)
+        .or(default)
+        .map(|t| Instant::now() + t)
+}
+
+/// OpenAI `finish_reason` for a finished generation. A deadline cut is
+/// reported as `"length"`: the output is incomplete but valid.
+fn finish_reason(g: &Generation) -> &'static str {
+    g.finish_reason.as_openai()
+}
+
+/// A failed generation: the client's fault if the prompt does not fit the
+/// model's context or the options are invalid, the server's otherwise.
+fn generation_error(e: anyhow::Error) -> Response {
+    let status = match e.downcast_ref::<InferenceError>() {
+        Some(InferenceError::ContextLengthExceeded { .. } | InferenceError::InvalidInput(_)) => {
+            StatusCode::BAD_REQUEST
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    api_error(status, &e.to_string())
 }
 
 fn api_error(status: StatusCode, msg: &str) -> Response {
@@ -404,7 +1406,11 @@ fn api_error(status: StatusCode, msg: &str) -> Response {
         Json(ApiErr {
             error: ErrDetail {
                 message: msg.to_string(),
-                kind: "server_error",
+                kind: if status.is_client_error() {
+                    "invalid_request_error"
+                } else {
+                    "server_error"
+                },
             },
         }),
     )
@@ -415,45 +1421,188 @@ fn api_error(status: StatusCode, msg: &str) -> Response {
 // Server entry point
 // ---------------------------------------------------------------------------
 
+/// Who may use the server and how, from config.yaml and `serve` flags.
+pub struct ServerOptions {
+    /// Default wall-clock budget per generation request, if any.
+    pub request_timeout: Option<Duration>,
+    /// Generation and embedding requests accepted at once.
+    pub queue_depth: usize,
+    /// Bearer token for the admin routes.
+    pub admin_token: Option<String>,
+    /// API keys, limits and CORS origins for the `/v1` routes.
+    pub access: ApiConfig,
+}
+
 /// Start the OpenAI-compatible API server on `port`.
 ///
-/// Loads the model into `engine`, hands ownership to a background inference
-/// thread, then runs the axum HTTP server until Ctrl-C.
+/// Hands `engine` and the models already loaded into it to a background
+/// inference thread, then runs the axum HTTP server until Ctrl-C. More
+/// models can be loaded and unloaded through the admin endpoints. With
+/// `api.tls` configured the server speaks HTTPS only (see [`crate::tls`]).
+/// `listen` may also be a Unix socket (see [`crate::listen`]).
 pub async fn run_api_server(
-    port: u16,
+    listen: ApiListen,
     engine: InferenceEngine,
-    handle: ModelHandle,
-    model_id: String,
+    models: ModelRegistry,
+    options: ServerOptions,
 ) -> Result<()> {
+    let cors = cors_layer(&options.access.cors_origins)?;
+    let tls = crate::tls::load(&options.access.tls).context("setting up API server TLS")?;
+    if tls.is_some() && matches!(listen, ApiListen::Unix(_)) {
+        anyhow::bail!("api.tls cannot be used with a unix socket listener");
+    }
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    // Base URL and curl command line to reach the server from this machine.
+    let (base, mut curl) = match &listen {
+        ApiListen::Tcp(addr) if addr.ip().is_unspecified() => (
+            format!("{}://localhost:{}", scheme, addr.port()),
+            "curl".to_string(),
+        ),
+        ApiListen::Tcp(addr) => (format!("{}://{}", scheme, addr), "curl".to_string()),
+        ApiListen::Unix(path) => (
+            "http://localhost".to_string(),
+            format!("curl --unix-socket {}", path.display()),
+        ),
+    };
+    let example_model = models
+        .default_id(ModelKind::Generation)
+        .unwrap_or("default")
+        .to_string();
+    let loaded: Vec<String> = models
+        .models()
+        .map(|m| match m.kind {
+            ModelKind::Generation => m.id.clone(),
+            ModelKind::Embedding => format!("{} (embeddings)", m.id),
+        })
+        .collect();
+    let max_batch = engine.config().max_batch_size.max(1);
+    let token_required = options.admin_token.is_some();
     let state: AppStateRef = Arc::new(AppState {
-        worker: InferenceWorker::spawn(engine, handle),
-        model_id: model_id.clone(),
+        worker: InferenceWorker::spawn(engine),
+        models: RwLock::new(models),
+        settings: RwLock::new(Settings {
+            request_timeout: options.request_timeout,
+            max_batch,
+        }),
+        admin_token: options.admin_token,
+        keys: KeyRing::new(&options.access.keys),
+        queue: RequestQueue::new(options.queue_depth),
     });
+    let open = state.keys.is_open();
 
-    let app = Router::new()
+    let admin = Router::new()
+        .route(
+            "/admin/models",
+            get(admin_list_models).post(admin_load_model),
+        )
+        .route("/admin/models/*id", delete(admin_unload_model))
+        .route("/admin/peers", get(admin_peers))
+        .route("/admin/announce", post(admin_announce))
+        .route("/admin/ledger", get(crate::ledger::ledger_receipts))
+        .route(
+            "/admin/config",
+            get(admin_get_config).patch(admin_patch_config),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), admin_guard));
+    let mut app = Router::new()
         .route("/v1/models", get(list_models))
         .route("/v1/chat/completions", post(chat_completions))
         .route("/v1/completions", post(completions))
+        .route("/v1/embeddings", post(embeddings))
+        .route("/v1/network/state", get(crate::network_cmd::network_state))
+        .route_layer(middleware::from_fn_with_state(state.clone(), api_key_guard))
+        .merge(admin)
         .with_state(state);
+    if let Some(cors) = cors {
+        app = app.layer(cors);
+    }
 
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    let tcp = match &listen {
+        ApiListen::Tcp(addr) => Some(
+            tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("binding {}", addr))?,
+        ),
+        ApiListen::Unix(_) => None,
+    };
+    #[cfg(unix)]
+    let unix = match &listen {
+        ApiListen::Unix(path) => Some(crate::listen::bind_unix(path)?),
+        ApiListen::Tcp(_) => None,
+    };
 
     info!(
-        "KwaaiNet OpenAI API server ready — http://localhost:{}/v1  (model: {})",
-        port, model_id
+        "KwaaiNet OpenAI API server ready on {} — {}/v1  (models: {})",
+        listen,
+        base,
+        loaded.join(", ")
     );
     println!();
-    println!("  OpenAI base URL:  http://localhost:{}/v1", port);
-    println!("  Model:            {}", model_id);
+    match &listen {
+        ApiListen::Unix(path) => println!("  Socket:           {}", path.display()),
+        ApiListen::Tcp(_) => println!("  OpenAI base URL:  {}/v1", base),
+    }
+    println!("  Models:           {}", loaded.join(", "));
+    if let Some(ref tls) = tls {
+        let origin = if tls.self_signed {
+            " (self-signed)"
+        } else {
+            ""
+        };
+        println!("  TLS certificate:  {}{}", tls.cert_path.display(), origin);
+        if tls.self_signed {
+            curl = format!("curl --cacert {}", tls.cert_path.display());
+        }
+    }
+    if open {
+        let reach = match &listen {
+            ApiListen::Tcp(addr) => format!("reach port {}", addr.port()),
+            ApiListen::Unix(path) => format!("open {}", path.display()),
+        };
+        println!("  ⚠️  No API keys configured — anyone who can {reach} can use it.");
+        println!("      Add one with: kwaainet api-keys add NAME");
+    } else {
+        println!("  API keys:         required (Authorization: Bearer <key>)");
+    }
     println!();
     println!("  Try it:");
-    println!("    curl http://localhost:{}/v1/models", port);
-    println!("    curl http://localhost:{}/v1/chat/completions \\", port);
+    let auth = if open {
+        ""
+    } else {
+        " -H 'Authorization: Bearer <key>'"
+    };
+    println!("    {} {}/v1/models{}", curl, base, auth);
+    println!("    {} {}/v1/chat/completions{} \\", curl, base, auth);
     println!("      -H 'Content-Type: application/json' \\");
-    println!("      -d '{{\"model\":\"{}\",\"messages\":[{{\"role\":\"user\",\"content\":\"Hello!\"}}]}}'", model_id);
+    println!("      -d '{{\"model\":\"{}\",\"messages\":[{{\"role\":\"user\",\"content\":\"Hello!\"}}]}}'", example_model);
+    println!();
+    if token_required {
+        println!("  Load another model (admin_token from config.yaml):");
+    } else {
+        println!("  Load another model (from this machine):");
+    }
+    println!(
+        "    {} {}/admin/models -d '{{\"model\":\"qwen2.5:7b\"}}' \\",
+        curl, base
+    );
+    if token_required {
+        println!("      -H 'Authorization: Bearer <admin_token>' \\");
+    }
+    println!("      -H 'Content-Type: application/json'");
     println!();
 
-    axum::serve(listener, app).await?;
+    #[cfg(unix)]
+    if let Some(listener) = unix {
+        return crate::listen::serve_unix(listener, app).await;
+    }
+    let listener = tcp.context("unix sockets are not supported on this platform")?;
+    if let Some(tls) = tls {
+        return crate::tls::serve(listener, tls.config, app).await;
+    }
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
@@ -0,0 +1,292 @@
+//! API keys, rate limits and token quotas for the OpenAI-compatible API.
+//!
+//! Keys live in the `api.keys` list of config.yaml (see
+//! [`crate::config::ApiConfig`]) and are managed with `kwaainet api-keys`.
+//! Each key may carry a requests-per-minute limit, enforced with a token
+//! bucket so short bursts up to the per-minute figure are allowed, and a
+//! daily token quota counted in prompt plus completion tokens per UTC day.
+//!
+//! Usage is counted in memory, so quotas start afresh when the server
+//! restarts. A request admitted under its quota always completes; the
+//! tokens it used count against the next one.
+
+use chrono::{NaiveDate, Utc};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::ApiKeyConfig;
+
+/// Prefix of generated keys, so they are recognisable in configs and logs.
+const KEY_PREFIX: &str = "kwn-";
+
+/// Why a request was turned away.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// No key, or one that isn't configured
+    Unauthorized,
+    /// The key's requests-per-minute limit is used up
+    RateLimited { retry_after: Duration },
+    /// The key's tokens for today are used up
+    QuotaExhausted { retry_after: Duration },
+}
+
+impl Rejection {
+    pub fn message(&self) -> String {
+        match self {
+            Self::Unauthorized => "missing or invalid API key".to_string(),
+            Self::RateLimited { .. } => "rate limit exceeded for this API key".to_string(),
+            Self::QuotaExhausted { .. } => {
+                "daily token quota exhausted for this API key; it resets at 00:00 UTC".to_string()
+            }
+        }
+    }
+
+    /// Seconds a client should wait before retrying, if waiting helps
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Unauthorized => None,
+            Self::RateLimited { retry_after } | Self::QuotaExhausted { retry_after } => {
+                Some(*retry_after)
+            }
+        }
+    }
+}
+
+/// Index of the key a request was admitted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyId(usize);
+
+/// The configured keys and their live usage.
+pub struct KeyRing {
+    keys: Vec<KeyState>,
+}
+
+struct KeyState {
+    config: ApiKeyConfig,
+    digest: [u8; 32],
+    usage: Mutex<Usage>,
+}
+
+struct Usage {
+    /// Requests still allowed right now (token bucket)
+    bucket: f64,
+    refilled: Instant,
+    day: NaiveDate,
+    tokens_today: u64,
+}
+
+impl KeyRing {
+    pub fn new(keys: &[ApiKeyConfig]) -> Self {
+        let now = Instant::now();
+        let today = Utc::now().date_naive();
+        Self {
+            keys: keys
+                .iter()
+                .map(|config| KeyState {
+                    digest: Sha256::digest(config.key.as_bytes()).into(),
+                    usage: Mutex::new(Usage {
+                        bucket: config.requests_per_minute.unwrap_or(0) as f64,
+                        refilled: now,
+                        day: today,
+                        tokens_today: 0,
+                    }),
+                    config: config.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// No keys configured: the API is open to everyone.
+    pub fn is_open(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn name(&self, id: KeyId) -> &str {
+        &self.keys[id.0].config.name
+    }
+
+    /// Check `bearer` and take one request from its rate limit.
+    pub fn admit(&self, bearer: Option<&str>) -> Result<KeyId, Rejection> {
+        self.admit_at(bearer, Instant::now(), Utc::now().date_naive())
+    }
+
+    /// Count `tokens` against the key's daily quota.
+    pub fn record(&self, id: KeyId, tokens: u64) {
+        self.record_at(id, tokens, Utc::now().date_naive());
+    }
+
+    fn admit_at(
+        &self,
+        bearer: Option<&str>,
+        now: Instant,
+        today: NaiveDate,
+    ) -> Result<KeyId, Rejection> {
+        let id = bearer
+            .and_then(|b| self.find(b))
+            .ok_or(Rejection::Unauthorized)?;
+        let key = &self.keys[id.0];
+        let mut usage = key.usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage.roll_over(today);
+
+        if let Some(limit) = key.config.tokens_per_day {
+            if usage.tokens_today >= limit {
+                return Err(Rejection::QuotaExhausted {
+                    retry_after: until_utc_midnight(),
+                });
+            }
+        }
+        if let Some(rpm) = key.config.requests_per_minute {
+            let per_sec = rpm as f64 / 60.0;
+            let elapsed = now.saturating_duration_since(usage.refilled).as_secs_f64();
+            usage.bucket = (usage.bucket + elapsed * per_sec).min(rpm as f64);
+            usage.refilled = now;
+            if usage.bucket < 1.0 {
+                let wait = if per_sec > 0.0 {
+                    (1.0 - usage.bucket) / per_sec
+                } else {
+                    60.0
+                };
+                return Err(Rejection::RateLimited {
+                    retry_after: Duration::from_secs_f64(wait.ceil()),
+                });
+            }
+            usage.bucket -= 1.0;
+        }
+        Ok(id)
+    }
+
+    fn record_at(&self, id: KeyId, tokens: u64, today: NaiveDate) {
+        let mut usage = self.keys[id.0]
+            .usage
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        usage.roll_over(today);
+        usage.tokens_today = usage.tokens_today.saturating_add(tokens);
+    }
+
+    /// The key matching `bearer`, comparing digests of every key so the
+    /// time taken doesn't reveal how much of a key was right.
+    fn find(&self, bearer: &str) -> Option<KeyId> {
+        let digest = Sha256::digest(bearer.as_bytes());
+        let mut found = None;
+        for (i, key) in self.keys.iter().enumerate() {
+            let diff = key
+                .digest
+                .iter()
+                .zip(digest.iter())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+            if diff == 0 {
+                found = Some(KeyId(i));
+            }
+        }
+        found
+    }
+}
+
+impl Usage {
+    fn roll_over(&mut self, today: NaiveDate) {
+        if self.day != today {
+            self.day = today;
+            self.tokens_today = 0;
+        }
+    }
+}
+
+/// Compare secrets in time independent of where they differ.
+pub fn secret_matches(given: &str, expected: &str) -> bool {
+    let (a, b) = (Sha256::digest(given), Sha256::digest(expected));
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// A new random API key.
+pub fn generate_key() -> String {
+    use rand::RngCore;
+    let mut bytes = [0u8; 24];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+/// The start of `key`, enough to tell keys apart in listings.
+pub fn key_hint(key: &str) -> String {
+    let shown: String = key.chars().take(KEY_PREFIX.len() + 6).collect();
+    format!("{}…", shown)
+}
+
+fn until_utc_midnight() -> Duration {
+    let now = Utc::now();
+    let midnight = (now.date_naive() + chrono::Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
+        .and_utc();
+    (midnight - now).to_std().unwrap_or(Duration::from_secs(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str, rpm: Option<u32>, tokens: Option<u64>) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: name.to_string(),
+            key: format!("kwn-{name}"),
+            requests_per_minute: rpm,
+            tokens_per_day: tokens,
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_finds_the_right_one() {
+        let ring = KeyRing::new(&[key("a", None, None), key("b", None, None)]);
+        assert_eq!(ring.admit(None), Err(Rejection::Unauthorized));
+        assert_eq!(ring.admit(Some("kwn-c")), Err(Rejection::Unauthorized));
+        let id = ring.admit(Some("kwn-b")).unwrap();
+        assert_eq!(ring.name(id), "b");
+        assert!(KeyRing::new(&[]).is_open());
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let ring = KeyRing::new(&[key("a", Some(2), None)]);
+        let (start, today) = (Instant::now(), Utc::now().date_naive());
+        assert!(ring.admit_at(Some("kwn-a"), start, today).is_ok());
+        assert!(ring.admit_at(Some("kwn-a"), start, today).is_ok());
+        let rejected = ring.admit_at(Some("kwn-a"), start, today).unwrap_err();
+        assert_eq!(
+            rejected,
+            Rejection::RateLimited {
+                retry_after: Duration::from_secs(30)
+            }
+        );
+        // Two per minute: one request back after 30 seconds.
+        let later = start + Duration::from_secs(30);
+        assert!(ring.admit_at(Some("kwn-a"), later, today).is_ok());
+        assert!(ring.admit_at(Some("kwn-a"), later, today).is_err());
+    }
+
+    #[test]
+    fn token_quota_resets_each_day() {
+        let ring = KeyRing::new(&[key("a", None, Some(100))]);
+        let (now, today) = (Instant::now(), Utc::now().date_naive());
+        let id = ring.admit_at(Some("kwn-a"), now, today).unwrap();
+        ring.record_at(id, 120, today);
+        assert!(matches!(
+            ring.admit_at(Some("kwn-a"), now, today),
+            Err(Rejection::QuotaExhausted { .. })
+        ));
+        let tomorrow = today + chrono::Days::new(1);
+        assert!(ring.admit_at(Some("kwn-a"), now, tomorrow).is_ok());
+    }
+
+    #[test]
+    fn generated_keys_are_unique_and_hinted() {
+        let (a, b) = (generate_key(), generate_key());
+        assert_ne!(a, b);
+        assert!(a.starts_with(KEY_PREFIX) && a.len() == KEY_PREFIX.len() + 48);
+        assert_eq!(key_hint(&a).chars().count(), KEY_PREFIX.len() + 7);
+        assert!(secret_matches(&a, &a) && !secret_matches(&a, &b));
+    }
+}
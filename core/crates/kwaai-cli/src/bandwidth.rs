@@ -0,0 +1,192 @@
+//! Bandwidth caps and contribution hours for home contributors.
+//!
+//! `bandwidth.upload_mbps` and `bandwidth.download_mbps` cap P2P tensor
+//! transfers (block RPCs, shard downloads) and DHT traffic through one
+//! process-wide token-bucket [`BandwidthLimiter`]. `bandwidth.schedule`
+//! limits when the node contributes at all: outside the window the node
+//! loop withdraws its DHT announcement and re-announces once it reopens.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{bail, Context, Result};
+use chrono::Timelike;
+use kwaai_p2p::{BandwidthLimiter, Throttled};
+use tracing::info;
+
+use crate::config::{BandwidthConfig, KwaaiNetConfig};
+
+static LIMITER: OnceLock<Arc<BandwidthLimiter>> = OnceLock::new();
+
+fn mbps_to_bytes_per_sec(mbps: f64) -> u64 {
+    (mbps * 1_000_000.0 / 8.0).max(1.0) as u64
+}
+
+fn build(config: &BandwidthConfig) -> Arc<BandwidthLimiter> {
+    let limiter = BandwidthLimiter::new(
+        config.upload_mbps.map(mbps_to_bytes_per_sec),
+        config.download_mbps.map(mbps_to_bytes_per_sec),
+    );
+    if limiter.is_limited() {
+        let show =
+            |mbps: Option<f64>| mbps.map_or("unlimited".to_string(), |m| format!("{m} Mbit/s"));
+        info!(
+            "Bandwidth limits: upload {}, download {}",
+            show(config.upload_mbps),
+            show(config.download_mbps)
+        );
+    }
+    Arc::new(limiter)
+}
+
+/// Install the process-wide limiter. Called once at node startup.
+pub fn init(config: &BandwidthConfig) -> Arc<BandwidthLimiter> {
+    LIMITER.get_or_init(|| build(config)).clone()
+}
+
+/// The process-wide limiter, built from the config on first use when
+/// [`init`] was not called (e.g. by `kwaainet shard` commands).
+pub fn limiter() -> Arc<BandwidthLimiter> {
+    LIMITER
+        .get_or_init(|| {
+            let config = KwaaiNetConfig::load_or_create()
+                .map(|c| c.bandwidth)
+                .unwrap_or_default();
+            build(&config)
+        })
+        .clone()
+}
+
+/// Pace `stream` with the process-wide limiter.
+pub fn throttle<S>(stream: S) -> Throttled<S> {
+    Throttled::new(stream, limiter())
+}
+
+/// Clamp a measured download rate (bits/s) to the configured cap, so the
+/// throughput a node announces reflects what it will actually serve.
+pub fn cap_measured_bps(measured_bps: f64) -> f64 {
+    match limiter().download_limit() {
+        Some(cap) if measured_bps > 0.0 => measured_bps.min(cap as f64 * 8.0),
+        _ => measured_bps,
+    }
+}
+
+/// Daily window of local time in which the node contributes, e.g.
+/// `22:00-07:00`. Windows that end before they start wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContributionSchedule {
+    /// Minute of the day the window opens
+    start: u32,
+    /// Minute of the day the window closes
+    end: u32,
+}
+
+impl ContributionSchedule {
+    /// Whether `minute` (of the day, local time) falls inside the window.
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// Whether the window is open right now.
+    pub fn is_open_now(&self) -> bool {
+        let now = chrono::Local::now();
+        self.contains(now.hour() * 60 + now.minute())
+    }
+}
+
+fn parse_time(s: &str) -> Result<u32> {
+    let (h, m) = s
+        .trim()
+        .split_once(':')
+        .with_context(|| format!("'{s}' is not HH:MM"))?;
+    let h: u32 = h.parse().with_context(|| format!("bad hour in '{s}'"))?;
+    let m: u32 = m.parse().with_context(|| format!("bad minute in '{s}'"))?;
+    if h > 23 || m > 59 {
+        bail!("'{s}' is not a time of day");
+    }
+    Ok(h * 60 + m)
+}
+
+impl FromStr for ContributionSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .with_context(|| format!("schedule '{s}' must look like 22:00-07:00"))?;
+        let schedule = Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        };
+        if schedule.start == schedule.end {
+            bail!("schedule '{s}' is empty (unset it to contribute around the clock)");
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for ContributionSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> u32 {
+        h * 60 + m
+    }
+
+    #[test]
+    fn overnight_window_wraps_past_midnight() {
+        let s: ContributionSchedule = "22:00-07:00".parse().unwrap();
+        assert!(s.contains(at(22, 0)));
+        assert!(s.contains(at(3, 30)));
+        assert!(!s.contains(at(7, 0)));
+        assert!(!s.contains(at(12, 0)));
+        assert_eq!(s.to_string(), "22:00-07:00");
+    }
+
+    #[test]
+    fn daytime_window() {
+        let s: ContributionSchedule = "9:30-17:00".parse().unwrap();
+        assert!(!s.contains(at(9, 29)));
+        assert!(s.contains(at(9, 30)));
+        assert!(!s.contains(at(17, 0)));
+        assert_eq!(s.to_string(), "09:30-17:00");
+    }
+
+    #[test]
+    fn rejects_malformed_schedules() {
+        for bad in [
+            "",
+            "22:00",
+            "25:00-07:00",
+            "22:00-07:60",
+            "08:00-08:00",
+            "ten-eleven",
+        ] {
+            assert!(bad.parse::<ContributionSchedule>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn mbps_converts_to_bytes() {
+        assert_eq!(mbps_to_bytes_per_sec(8.0), 1_000_000);
+        assert_eq!(mbps_to_bytes_per_sec(0.5), 62_500);
+    }
+}
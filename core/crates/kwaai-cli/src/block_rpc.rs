@@ -14,13 +14,19 @@
 //!   │    shape, data}                               │
 //! ```
 //!
-//! Tensor data is serialised as raw little-endian bytes:
-//! - Token IDs: `u32-LE` each
-//! - Hidden states / logits: `f16-LE` each (native half precision)
+//! Tensors travel as [`TensorWire`] fields flattened into the message, so
+//! each payload names its dtype, shape and byte order:
+//! - Token IDs: `u32`
+//! - Hidden states / logits: `f16` (native half precision)
+//!
+//! Nodes that predate TensorWire send only `shape` and `data`; those decode
+//! as little-endian f16, and token IDs are recognised by `payload_type`.
 
 use anyhow::{bail, Context, Result};
-use candle_core::{DType, Device, Tensor};
+use candle_core::{DType, Device};
+use kwaai_compression::{TensorWire, WireDType};
 use kwaai_inference::TransformerShard;
+use kwaai_p2p::ResponseStatus;
 use kwaai_p2p_daemon::P2PClient;
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
@@ -28,6 +34,9 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
+use crate::handoff::{DrainGate, SHUTTING_DOWN};
+use crate::request_queue::{RequestQueue, BUSY};
+
 // ── Lazy-load cell ─────────────────────────────────────────────────────────────
 
 /// Shared, lazily populated shard slot.
@@ -74,10 +83,22 @@ pub struct InferenceRequest {
     pub seq_pos: u32,
     /// Whether `data` contains token IDs or hidden states.
     pub payload_type: PayloadType,
-    /// Tensor shape: `[seq_len]` for token IDs, `[1, seq_len, hidden_dim]` for hidden states.
-    pub shape: Vec<u32>,
-    /// Raw tensor bytes (u32-LE for token IDs, f16-LE for hidden states).
-    pub data: Vec<u8>,
+    /// `[seq_len]` u32 token IDs or `[1, seq_len, hidden_dim]` f16 hidden states.
+    #[serde(flatten)]
+    pub tensor: TensorWire,
+    /// LoRA adapter to run this session with (`None` = base model). Servers
+    /// announce the adapters they have loaded in their DHT record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_adapter: Option<String>,
+    /// Have the last node return logits for every position instead of only
+    /// the last one (speculative decoding verifies all drafts at once).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub all_logits: bool,
+    /// Block server mode: return the hidden states after this server's
+    /// blocks even when it holds the last block (the caller applies the
+    /// final norm and LM head itself).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden_only: bool,
 }
 
 /// Sent by a block server back to the coordinator.
@@ -86,70 +107,73 @@ pub struct InferenceResponse {
     pub session_id: u64,
     /// Whether `data` contains hidden states or final logits.
     pub response_type: ResponseType,
-    pub shape: Vec<u32>,
-    /// Raw f16-LE tensor bytes.
-    pub data: Vec<u8>,
+    /// f16 output tensor; empty when `error` is set.
+    #[serde(flatten)]
+    pub tensor: TensorWire,
     /// Set when the server encountered an error.
     pub error: Option<String>,
+    /// Set while the server is shutting down: this session is still served,
+    /// but new sessions should be started elsewhere.
+    #[serde(default)]
+    pub draining: bool,
+    /// `Busy` when the server's request queue was full and the request was
+    /// not run; `error` says so too, for coordinators predating this field.
+    #[serde(default)]
+    pub status: ResponseStatus,
 }
 
-// ── Tensor ↔ bytes helpers ────────────────────────────────────────────────────
-
-/// Serialise a `Tensor` to raw `f16-LE` bytes.
-pub fn tensor_to_f16_bytes(tensor: &Tensor) -> Result<(Vec<u32>, Vec<u8>)> {
-    // Normalise to F16 on CPU for serialisation
-    let t = tensor
-        .to_dtype(DType::F16)
-        .context("to_dtype F16")?
-        .flatten_all()
-        .context("flatten")?;
-
-    let f16_vec: Vec<half::f16> = t.to_vec1().context("to_vec1 f16")?;
-    let shape: Vec<u32> = tensor.dims().iter().map(|&d| d as u32).collect();
-
-    // SAFETY: f16 is repr(transparent) over u16; we transmute to raw bytes
-    let bytes: Vec<u8> = f16_vec.iter().flat_map(|v| v.to_le_bytes()).collect();
-
-    Ok((shape, bytes))
-}
-
-/// Deserialise `f16-LE` bytes back to a `Tensor` on the given device.
-pub fn f16_bytes_to_tensor(bytes: &[u8], shape: &[u32], device: &Device) -> Result<Tensor> {
-    if !bytes.len().is_multiple_of(2) {
-        bail!(
-            "f16 byte buffer length {} is not a multiple of 2",
-            bytes.len()
-        );
+impl InferenceResponse {
+    /// A response for a request that could not be served: `message` goes in
+    /// both `error` and an [`ResponseStatus::Error`] status.
+    pub fn failed(message: impl Into<String>, draining: bool) -> Self {
+        let message = message.into();
+        Self {
+            session_id: 0,
+            response_type: ResponseType::HiddenStates,
+            tensor: TensorWire::default(),
+            error: Some(message.clone()),
+            draining,
+            status: ResponseStatus::Error(message),
+        }
     }
-    let f16_vec: Vec<half::f16> = bytes
-        .chunks_exact(2)
-        .map(|c| half::f16::from_le_bytes([c[0], c[1]]))
-        .collect();
-    let shape_usize: Vec<usize> = shape.iter().map(|&d| d as usize).collect();
-    Tensor::from_vec(f16_vec, shape_usize.as_slice(), device).context("Tensor::from_vec f16")
 }
 
-/// Serialise token IDs to raw `u32-LE` bytes.
-pub fn token_ids_to_bytes(ids: &[u32]) -> (Vec<u32>, Vec<u8>) {
-    let shape = vec![ids.len() as u32];
-    let bytes = ids.iter().flat_map(|id| id.to_le_bytes()).collect();
-    (shape, bytes)
-}
+// ── Tensor helpers ────────────────────────────────────────────────────────────
 
-/// Deserialise `u32-LE` bytes to a token ID slice.
-pub fn bytes_to_token_ids(bytes: &[u8]) -> Result<Vec<u32>> {
-    if !bytes.len().is_multiple_of(4) {
+/// Decode the token IDs of a [`PayloadType::TokenIds`] request.
+///
+/// Requests from coordinators predating TensorWire carry no dtype tag (it
+/// defaults to f16), but their bytes are still `u32-LE` token IDs.
+pub fn decode_token_ids(tensor: &TensorWire) -> Result<Vec<u32>> {
+    if tensor.dtype == WireDType::U32 {
+        return Ok(tensor.to_u32_vec()?);
+    }
+    if !tensor.data.len().is_multiple_of(4) {
         bail!(
             "token_id byte buffer length {} is not a multiple of 4",
-            bytes.len()
+            tensor.data.len()
         );
     }
-    Ok(bytes
+    Ok(tensor
+        .data
         .chunks_exact(4)
         .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
         .collect())
 }
 
+/// Number of positions a request adds to its session's KV cache.
+fn request_len(req: &InferenceRequest) -> u64 {
+    match req.payload_type {
+        // u32 IDs, whatever dtype tag the request carries.
+        PayloadType::TokenIds => req.tensor.data.len() as u64 / 4,
+        // `[1, seq_len, hidden_dim]`
+        PayloadType::HiddenStates => match req.tensor.shape.as_slice() {
+            [.., seq_len, _] => *seq_len,
+            _ => 0,
+        },
+    }
+}
+
 // ── Client ────────────────────────────────────────────────────────────────────
 
 /// Call a block server's inference handler and return the decoded response.
@@ -187,11 +211,16 @@ pub async fn call_block_forward(
             tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
         }
 
+        let limiter = crate::bandwidth::limiter();
+        limiter.upload(req_bytes.len()).await;
         let resp_bytes = match client
             .call_unary_handler(&peer_bytes, INFERENCE_PROTO, &req_bytes)
             .await
         {
-            Ok(b) => b,
+            Ok(b) => {
+                limiter.download(b.len()).await;
+                b
+            }
             Err(e) => {
                 let e_str = format!("{e:#}");
                 let is_transient = e_str.contains("stream reset")
@@ -213,6 +242,12 @@ pub async fn call_block_forward(
         let response: InferenceResponse =
             rmp_serde::from_slice(&resp_bytes).context("deserialise InferenceResponse")?;
 
+        match &response.status {
+            ResponseStatus::Busy => bail!("Peer {peer_id}: {BUSY}"),
+            ResponseStatus::Error(err) => bail!("Remote inference error: {err}"),
+            _ => {}
+        }
+        // Servers predating the error status only set `error`.
         if let Some(ref err) = response.error {
             bail!("Remote inference error: {err}");
         }
@@ -234,12 +269,21 @@ pub async fn call_block_forward(
 /// returns a "node warming up" error response — the coordinator can retry
 /// after a short back-off.
 ///
+/// Requests and responses count against the node's bandwidth caps (see
+/// [`crate::bandwidth`]).
+///
+/// Requests pass through `drain`, which refuses new sessions once the shard
+/// server has begun shutting down, and `queue`, which answers new sessions
+/// with [`ResponseStatus::Busy`] while it is full.
+///
 /// The returned closure is `'static + Send + Sync` so it can be registered
 /// with the p2p daemon.
 #[allow(clippy::type_complexity)]
 pub fn make_block_rpc_handler(
     shard: ShardCell,
     device: Device,
+    drain: DrainGate,
+    queue: RequestQueue,
 ) -> impl Fn(
     Vec<u8>,
 ) -> std::pin::Pin<
@@ -250,31 +294,33 @@ pub fn make_block_rpc_handler(
     move |data: Vec<u8>| {
         let shard = shard.clone();
         let device = device.clone();
+        let drain = drain.clone();
+        let queue = queue.clone();
         Box::pin(async move {
+            let limiter = crate::bandwidth::limiter();
+            limiter.download(data.len()).await;
+
             // Read the shard cell and clone the Arc (drops the read lock immediately).
             let shard_arc: Option<Arc<TransformerShard>> = {
                 let guard = shard.read().await;
                 guard.as_ref().cloned()
             };
 
-            match shard_arc {
+            let reply = match shard_arc {
                 None => {
                     // Model not yet loaded — return a structured error so the coordinator
                     // can back-off and retry rather than treating this as a fatal failure.
-                    let resp = InferenceResponse {
-                        session_id: 0,
-                        response_type: ResponseType::HiddenStates,
-                        shape: vec![],
-                        data: vec![],
-                        error: Some("node warming up — model loading in background".to_string()),
-                    };
+                    let resp = InferenceResponse::failed(
+                        "node warming up — model loading in background",
+                        drain.is_draining(),
+                    );
                     rmp_serde::to_vec_named(&resp).map_err(|e| {
                         kwaai_p2p_daemon::error::Error::Protocol(format!(
                             "Failed to serialise warming-up response: {e}"
                         ))
                     })
                 }
-                Some(s) => match handle_inference_request(s, device, data).await {
+                Some(s) => match handle_inference_request(s, device, data, &drain, &queue).await {
                     Ok(resp) => rmp_serde::to_vec_named(&resp).map_err(|e| {
                         kwaai_p2p_daemon::error::Error::Protocol(format!(
                             "Failed to serialise response: {e}"
@@ -282,13 +328,7 @@ pub fn make_block_rpc_handler(
                     }),
                     Err(e) => {
                         error!("Inference request failed: {e:#}");
-                        let resp = InferenceResponse {
-                            session_id: 0,
-                            response_type: ResponseType::HiddenStates,
-                            shape: vec![],
-                            data: vec![],
-                            error: Some(e.to_string()),
-                        };
+                        let resp = InferenceResponse::failed(e.to_string(), drain.is_draining());
                         rmp_serde::to_vec_named(&resp).map_err(|e| {
                             kwaai_p2p_daemon::error::Error::Protocol(format!(
                                 "Failed to serialise error response: {e}"
@@ -296,7 +336,11 @@ pub fn make_block_rpc_handler(
                         })
                     }
                 },
+            };
+            if let Ok(bytes) = &reply {
+                limiter.upload(bytes.len()).await;
             }
+            reply
         })
     }
 }
@@ -307,13 +351,82 @@ pub fn make_block_rpc_handler(
 /// tokio worker thread is not blocked for the duration of compute — this lets other
 /// async tasks (announcements, spinner updates, etc.) make progress while the GPU/CPU
 /// is crunching.
+///
+/// While `drain` is draining, prefill requests (`seq_pos == 0`) are refused with
+/// [`SHUTTING_DOWN`]; later steps of sessions already running here are served.
+/// The same goes for a full `queue`, or contribution paused by the resource
+/// governor, except that the refusal is a [`ResponseStatus::Busy`] response.
+/// Forward passes wait their turn under the governor's GPU duty cycle.
+///
+/// Hidden states are run through [`TransformerShard::forward_hidden`], which
+/// checks them against the model; with `hidden_only` set even the last node
+/// answers with hidden states instead of logits.
 pub async fn handle_inference_request(
     shard: Arc<TransformerShard>,
     device: Device,
     raw: Vec<u8>,
+    drain: &DrainGate,
+    queue: &RequestQueue,
 ) -> Result<InferenceResponse> {
     let req: InferenceRequest =
         rmp_serde::from_slice(&raw).context("deserialise InferenceRequest")?;
+    // Unset for the local bypass, which is not called through p2pd.
+    let requester = kwaai_p2p_daemon::persistent::caller_peer()
+        .and_then(|bytes| PeerId::from_bytes(&bytes).ok())
+        .map(|peer| peer.to_base58());
+
+    let Some(_in_flight) = drain.admit(req.seq_pos) else {
+        bail!(SHUTTING_DOWN);
+    };
+    // A session's KV cache lives only here, so its later steps are always
+    // taken; only new sessions can be sent elsewhere.
+    let governor = crate::resources::governor();
+    if req.seq_pos == 0 && governor.is_paused() {
+        debug!(session = req.session_id, "Contribution paused — busy");
+        return Ok(InferenceResponse {
+            session_id: req.session_id,
+            response_type: ResponseType::HiddenStates,
+            tensor: TensorWire::default(),
+            error: Some(crate::resources::PAUSED.to_string()),
+            draining: drain.is_draining(),
+            status: ResponseStatus::Busy,
+        });
+    }
+    let _slot = if req.seq_pos == 0 {
+        let Some(slot) = queue.try_enter() else {
+            debug!(session = req.session_id, "Request queue full — busy");
+            return Ok(InferenceResponse {
+                session_id: req.session_id,
+                response_type: ResponseType::HiddenStates,
+                tensor: TensorWire::default(),
+                error: Some(BUSY.to_string()),
+                draining: drain.is_draining(),
+                status: ResponseStatus::Busy,
+            });
+        };
+        slot
+    } else {
+        queue.enter()
+    };
+
+    // Reserve the session's cache up to the end of this step. New sessions
+    // that do not fit go elsewhere; a running one that cannot grow fails.
+    let sessions = crate::sessions::sessions();
+    let cache_len = u64::from(req.seq_pos) + request_len(&req);
+    if let Err(e) = sessions.reserve(req.session_id, cache_len) {
+        if req.seq_pos != 0 {
+            bail!(e);
+        }
+        debug!(session = req.session_id, "{e} — busy");
+        return Ok(InferenceResponse {
+            session_id: req.session_id,
+            response_type: ResponseType::HiddenStates,
+            tensor: TensorWire::default(),
+            error: Some(e.to_string()),
+            draining: drain.is_draining(),
+            status: ResponseStatus::Busy,
+        });
+    }
 
     let session_id = req.session_id;
     let seq_pos = req.seq_pos as usize;
@@ -329,8 +442,11 @@ pub async fn handle_inference_request(
 
     // Run the synchronous forward pass on the blocking thread pool so the
     // tokio runtime stays responsive to other tasks during compute.
+    governor.wait_turn().await;
+    let compute_start = std::time::Instant::now();
     let (output, is_logits) =
         tokio::task::spawn_blocking(move || -> Result<(candle_core::Tensor, bool)> {
+            shard.set_session_adapter(session_id, req.active_adapter.as_deref())?;
             let fwd_start = std::time::Instant::now();
             let result = match req.payload_type {
                 PayloadType::TokenIds => {
@@ -340,8 +456,12 @@ pub async fn handle_inference_request(
                             start_blk
                         );
                     }
-                    let token_ids = bytes_to_token_ids(&req.data).context("decode token IDs")?;
-                    if is_last {
+                    let token_ids = decode_token_ids(&req.tensor).context("decode token IDs")?;
+                    let is_last = is_last && !req.hidden_only;
+                    if is_last && req.all_logits {
+                        let logits = shard.forward_full_all(session_id, &token_ids, seq_pos)?;
+                        (logits, true)
+                    } else if is_last {
                         let logits = shard.forward_full(session_id, &token_ids, seq_pos)?;
                         (logits, true)
                     } else {
@@ -350,14 +470,21 @@ pub async fn handle_inference_request(
                     }
                 }
                 PayloadType::HiddenStates => {
-                    let hidden = f16_bytes_to_tensor(&req.data, &req.shape, &device)
+                    let hidden = req
+                        .tensor
+                        .to_tensor(&device)
                         .context("decode hidden states")?;
-                    if is_last {
-                        let logits = shard.forward_last(session_id, hidden, seq_pos)?;
-                        (logits, true)
+                    if is_last && req.hidden_only {
+                        (shard.forward_hidden(session_id, &hidden, seq_pos)?, false)
+                    } else if is_last {
+                        let hidden = hidden.to_dtype(DType::F16)?;
+                        if req.all_logits {
+                            (shard.forward_last_all(session_id, hidden, seq_pos)?, true)
+                        } else {
+                            (shard.forward_last(session_id, hidden, seq_pos)?, true)
+                        }
                     } else {
-                        let out = shard.forward_middle(session_id, hidden, seq_pos)?;
-                        (out, false)
+                        (shard.forward_hidden(session_id, &hidden, seq_pos)?, false)
                     }
                 }
             };
@@ -372,10 +499,22 @@ pub async fn handle_inference_request(
         })
         .await
         .map_err(|e| anyhow::anyhow!("forward pass panicked: {e}"))??;
+    governor.record_busy(compute_start.elapsed());
+    let tokens = cache_len - seq_pos as u64;
+    crate::history::count_served(tokens, seq_pos == 0);
+    crate::ledger::submit(crate::ledger::ReceiptDraft {
+        requester,
+        session_id,
+        start_block: start_blk as u32,
+        end_block: end_blk as u32,
+        tokens,
+        timestamp_ms: chrono::Utc::now().timestamp_millis(),
+    });
 
-    // Serialise output tensor to f16 bytes
+    // Serialise output tensor as f16
     let ser_start = std::time::Instant::now();
-    let (shape, data) = tensor_to_f16_bytes(&output).context("serialise output tensor")?;
+    let tensor =
+        TensorWire::from_tensor(&output, WireDType::F16).context("serialise output tensor")?;
     let ser_ms = ser_start.elapsed().as_secs_f64() * 1000.0;
     debug!(ser_ms = format!("{ser_ms:.1}"), "response serialization");
 
@@ -386,9 +525,10 @@ pub async fn handle_inference_request(
         } else {
             ResponseType::HiddenStates
         },
-        shape,
-        data,
+        tensor,
         error: None,
+        draining: drain.is_draining(),
+        status: ResponseStatus::Ok,
     })
 }
 
@@ -397,27 +537,26 @@ pub async fn handle_inference_request(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use candle_core::Tensor;
 
     #[test]
     fn token_ids_round_trip() {
         let ids = vec![1u32, 42, 999, 32000];
-        let (shape, bytes) = token_ids_to_bytes(&ids);
-        assert_eq!(shape, vec![4]);
-        let decoded = bytes_to_token_ids(&bytes).unwrap();
-        assert_eq!(decoded, ids);
+        let wire = TensorWire::from_u32(&ids);
+        assert_eq!(wire.shape, vec![4]);
+        assert_eq!(decode_token_ids(&wire).unwrap(), ids);
     }
 
     #[test]
-    fn f16_bytes_round_trip() {
-        use candle_core::{DType, Device, Tensor};
+    fn f16_tensor_round_trip() {
         let device = Device::Cpu;
         let data = vec![1.0f32, 2.0, 3.0, 4.0];
         let tensor = Tensor::from_vec(data.clone(), (1usize, 1usize, 4usize), &device)
             .unwrap()
             .to_dtype(DType::F16)
             .unwrap();
-        let (shape, bytes) = tensor_to_f16_bytes(&tensor).unwrap();
-        let recovered = f16_bytes_to_tensor(&bytes, &shape, &device).unwrap();
+        let wire = TensorWire::from_tensor(&tensor, WireDType::F16).unwrap();
+        let recovered = wire.to_tensor(&device).unwrap();
         assert_eq!(recovered.dims(), tensor.dims());
         // Check values approximately (f16 has limited precision)
         let vals: Vec<half::f16> = recovered.flatten_all().unwrap().to_vec1().unwrap();
@@ -426,20 +565,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn request_len_counts_new_positions() {
+        let mut req = InferenceRequest {
+            session_id: 1,
+            seq_pos: 0,
+            payload_type: PayloadType::TokenIds,
+            tensor: TensorWire::from_u32(&[1, 2, 3, 4, 5]),
+            active_adapter: None,
+            all_logits: false,
+            hidden_only: false,
+        };
+        assert_eq!(request_len(&req), 5);
+
+        req.payload_type = PayloadType::HiddenStates;
+        req.tensor = TensorWire {
+            shape: vec![1, 3, 4096],
+            ..TensorWire::default()
+        };
+        assert_eq!(request_len(&req), 3);
+    }
+
     #[test]
     fn inference_request_msgpack_round_trip() {
         let req = InferenceRequest {
             session_id: 12345,
             seq_pos: 7,
             payload_type: PayloadType::HiddenStates,
-            shape: vec![1, 1, 4096],
-            data: vec![0u8; 8192],
+            tensor: TensorWire {
+                shape: vec![1, 1, 4096],
+                data: vec![0u8; 8192],
+                ..TensorWire::default()
+            },
+            active_adapter: None,
+            all_logits: false,
+            hidden_only: false,
         };
         let bytes = rmp_serde::to_vec_named(&req).unwrap();
         let decoded: InferenceRequest = rmp_serde::from_slice(&bytes).unwrap();
         assert_eq!(decoded.session_id, req.session_id);
         assert_eq!(decoded.seq_pos, req.seq_pos);
-        assert_eq!(decoded.shape, req.shape);
+        assert_eq!(decoded.tensor, req.tensor);
         assert_eq!(decoded.payload_type, req.payload_type);
     }
 
@@ -448,16 +614,21 @@ mod tests {
         let resp = InferenceResponse {
             session_id: 99,
             response_type: ResponseType::Logits,
-            shape: vec![1, 32000],
-            data: vec![0u8; 64000],
+            tensor: TensorWire {
+                shape: vec![1, 32000],
+                data: vec![0u8; 64000],
+                ..TensorWire::default()
+            },
             error: None,
+            draining: false,
+            status: ResponseStatus::Ok,
         };
         let bytes = rmp_serde::to_vec_named(&resp).unwrap();
         let decoded: InferenceResponse = rmp_serde::from_slice(&bytes).unwrap();
         assert_eq!(decoded.session_id, resp.session_id);
         assert_eq!(decoded.response_type, resp.response_type);
-        assert_eq!(decoded.shape, resp.shape);
-        assert_eq!(decoded.data.len(), resp.data.len());
+        assert_eq!(decoded.tensor.shape, resp.tensor.shape);
+        assert_eq!(decoded.tensor.data.len(), resp.tensor.data.len());
         assert!(decoded.error.is_none());
     }
 
@@ -466,31 +637,105 @@ mod tests {
         let resp = InferenceResponse {
             session_id: 1,
             response_type: ResponseType::HiddenStates,
-            shape: vec![],
-            data: vec![],
+            tensor: TensorWire::default(),
             error: Some("session expired".to_string()),
+            draining: false,
+            status: ResponseStatus::Busy,
         };
         let bytes = rmp_serde::to_vec_named(&resp).unwrap();
         let decoded: InferenceResponse = rmp_serde::from_slice(&bytes).unwrap();
         assert_eq!(decoded.error.as_deref(), Some("session expired"));
+        assert_eq!(decoded.status, ResponseStatus::Busy);
+    }
+
+    #[test]
+    fn failed_response_carries_an_error_status() {
+        let resp = InferenceResponse::failed("node warming up", true);
+        let bytes = rmp_serde::to_vec_named(&resp).unwrap();
+        let decoded: InferenceResponse = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(
+            decoded.status,
+            ResponseStatus::Error("node warming up".to_string())
+        );
+        assert_eq!(decoded.error.as_deref(), Some("node warming up"));
+        assert!(decoded.draining);
+        assert!(decoded.tensor.data.is_empty());
+    }
+
+    #[test]
+    fn inference_response_from_older_server_is_not_draining() {
+        // Servers predating graceful shutdown don't send `draining`.
+        #[derive(Serialize)]
+        struct OldResponse {
+            session_id: u64,
+            response_type: ResponseType,
+            shape: Vec<u32>,
+            data: Vec<u8>,
+            error: Option<String>,
+        }
+        let bytes = rmp_serde::to_vec_named(&OldResponse {
+            session_id: 3,
+            response_type: ResponseType::Logits,
+            shape: vec![1],
+            data: vec![0, 0],
+            error: None,
+        })
+        .unwrap();
+        let decoded: InferenceResponse = rmp_serde::from_slice(&bytes).unwrap();
+        assert!(!decoded.draining);
+        assert_eq!(decoded.status, ResponseStatus::Ok);
+    }
+
+    #[test]
+    fn messages_from_nodes_without_tensor_wire_still_decode() {
+        #[derive(Serialize)]
+        struct OldRequest {
+            session_id: u64,
+            seq_pos: u32,
+            payload_type: PayloadType,
+            shape: Vec<u32>,
+            data: Vec<u8>,
+        }
+        let ids = [5u32, 300_000];
+        let bytes = rmp_serde::to_vec_named(&OldRequest {
+            session_id: 1,
+            seq_pos: 0,
+            payload_type: PayloadType::TokenIds,
+            shape: vec![2],
+            data: ids.iter().flat_map(|id| id.to_le_bytes()).collect(),
+        })
+        .unwrap();
+        let req: InferenceRequest = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decode_token_ids(&req.tensor).unwrap(), ids);
+        assert!(!req.all_logits && !req.hidden_only);
+
+        let half = half::f16::from_f32(0.5).to_le_bytes();
+        let bytes = rmp_serde::to_vec_named(&OldRequest {
+            session_id: 1,
+            seq_pos: 1,
+            payload_type: PayloadType::HiddenStates,
+            shape: vec![1, 1, 2],
+            data: [half, half].concat(),
+        })
+        .unwrap();
+        let req: InferenceRequest = rmp_serde::from_slice(&bytes).unwrap();
+        let hidden = req.tensor.to_tensor(&Device::Cpu).unwrap();
+        assert_eq!(hidden.dims(), &[1, 1, 2]);
+        assert_eq!(hidden.dtype(), DType::F16);
     }
 
     #[test]
     fn token_ids_round_trip_empty() {
-        let ids: Vec<u32> = vec![];
-        let (shape, bytes) = token_ids_to_bytes(&ids);
-        assert_eq!(shape, vec![0]);
-        let decoded = bytes_to_token_ids(&bytes).unwrap();
-        assert!(decoded.is_empty());
+        let wire = TensorWire::from_u32(&[]);
+        assert_eq!(wire.shape, vec![0]);
+        assert!(decode_token_ids(&wire).unwrap().is_empty());
     }
 
     #[test]
     fn token_ids_round_trip_single() {
-        let ids = vec![0u32];
-        let (shape, bytes) = token_ids_to_bytes(&ids);
-        assert_eq!(shape, vec![1]);
-        let decoded = bytes_to_token_ids(&bytes).unwrap();
-        assert_eq!(decoded, ids);
+        let wire = TensorWire::from_u32(&[0]);
+        assert_eq!(wire.shape, vec![1]);
+        assert_eq!(decode_token_ids(&wire).unwrap(), [0]);
     }
 
     #[test]
@@ -514,8 +759,7 @@ mod tests {
     }
 
     #[test]
-    fn f16_bytes_large_tensor() {
-        use candle_core::{DType, Device, Tensor};
+    fn f16_large_tensor() {
         let device = Device::Cpu;
         // Simulate a hidden state: [1, 1, 4096]
         let data: Vec<f32> = (0..4096).map(|i| (i as f32) * 0.001).collect();
@@ -523,10 +767,10 @@ mod tests {
             .unwrap()
             .to_dtype(DType::F16)
             .unwrap();
-        let (shape, bytes) = tensor_to_f16_bytes(&tensor).unwrap();
-        assert_eq!(shape, vec![1, 1, 4096]);
-        assert_eq!(bytes.len(), 4096 * 2); // 2 bytes per f16
-        let recovered = f16_bytes_to_tensor(&bytes, &shape, &device).unwrap();
+        let wire = TensorWire::from_tensor(&tensor, WireDType::F16).unwrap();
+        assert_eq!(wire.shape, vec![1, 1, 4096]);
+        assert_eq!(wire.size_bytes(), 4096 * 2); // 2 bytes per f16
+        let recovered = wire.to_tensor(&device).unwrap();
         assert_eq!(recovered.dims(), &[1, 1, 4096]);
     }
 }
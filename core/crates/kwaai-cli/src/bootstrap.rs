@@ -0,0 +1,296 @@
+//! Bootstrap peer health checking, ranking and rotation.
+//!
+//! Every DHT store seeds its lookup with the bootstrap peers in list order,
+//! so with a static list the first entry takes all the traffic and an outage
+//! of that one host stalls every announcement until its timeout expires.
+//!
+//! [`BootstrapManager`] pings every known bootstrap peer over libp2p (no
+//! `connect_peer()` — see `store_on_closest` for why), ranks them reachable
+//! first and then by RTT, and demotes the current leader whenever an
+//! announcement fails so the next one is tried first on the following round.
+//!
+//! When `bootstrap_list_url` is configured, an up-to-date list is fetched
+//! from that HTTPS endpoint at startup and every [`LIST_REFRESH`]. Fetched
+//! peers are added to the configured ones, never replace them, and the last
+//! good list is cached in `~/.kwaainet/bootstrap_peers.json` so a node can
+//! still use it when the endpoint is down.
+
+use anyhow::{bail, Context, Result};
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::{path::PathBuf, time::Duration};
+use tracing::{debug, warn};
+
+/// How often the bootstrap list is re-fetched from `bootstrap_list_url`.
+pub const LIST_REFRESH: Duration = Duration::from_secs(6 * 3600);
+
+/// Per-peer ping timeout during a health check.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest list accepted from the endpoint; anything longer is truncated.
+const MAX_FETCHED: usize = 32;
+
+#[derive(Debug, Clone)]
+struct BootstrapPeer {
+    addr: String,
+    peer_id: PeerId,
+    /// Last measured round-trip time; `None` until a ping succeeds.
+    rtt: Option<Duration>,
+    /// Failed pings or announcements since the last success.
+    failures: u32,
+}
+
+/// Known bootstrap peers with their latest health.
+#[derive(Debug, Clone, Default)]
+pub struct BootstrapManager {
+    peers: Vec<BootstrapPeer>,
+}
+
+impl BootstrapManager {
+    /// Track `addrs` in the given order. Entries without a valid `/p2p/`
+    /// peer ID are skipped with a warning; duplicates are dropped.
+    pub fn new(addrs: &[String]) -> Self {
+        let mut manager = Self::default();
+        manager.merge(addrs);
+        manager
+    }
+
+    /// Add peers not tracked yet. Returns how many were new.
+    pub fn merge(&mut self, addrs: &[String]) -> usize {
+        let mut added = 0;
+        for addr in addrs {
+            let Some(peer_id) = peer_id_of(addr) else {
+                warn!("Ignoring bootstrap peer without a valid /p2p/ peer ID: {addr}");
+                continue;
+            };
+            if self.peers.iter().any(|p| p.peer_id == peer_id) {
+                continue;
+            }
+            self.peers.push(BootstrapPeer {
+                addr: addr.clone(),
+                peer_id,
+                rtt: None,
+                failures: 0,
+            });
+            added += 1;
+        }
+        added
+    }
+
+    /// Addresses in preference order: fewest recent failures first, then
+    /// lowest RTT, then peers that were never measured. Ties keep the
+    /// configured order.
+    pub fn ranked(&self) -> Vec<String> {
+        let mut peers: Vec<&BootstrapPeer> = self.peers.iter().collect();
+        peers.sort_by_key(|p| (p.failures, p.rtt.unwrap_or(Duration::MAX)));
+        peers.into_iter().map(|p| p.addr.clone()).collect()
+    }
+
+    /// Record a health observation for `peer`: an RTT on success, `None` on
+    /// failure.
+    pub fn record(&mut self, peer: &PeerId, rtt: Option<Duration>) {
+        let Some(p) = self.peers.iter_mut().find(|p| p.peer_id == *peer) else {
+            return;
+        };
+        match rtt {
+            Some(rtt) => {
+                p.rtt = Some(rtt);
+                p.failures = 0;
+            }
+            None => p.failures = p.failures.saturating_add(1),
+        }
+    }
+
+    /// Demote the current best peer after an announcement reached nobody,
+    /// so the next attempt starts from a different one.
+    pub fn rotate(&mut self) {
+        let Some(best) = self.ranked().into_iter().next() else {
+            return;
+        };
+        if let Some(p) = self.peers.iter_mut().find(|p| p.addr == best) {
+            p.failures = p.failures.saturating_add(1);
+            debug!("Rotating away from bootstrap peer {}", p.addr);
+        }
+    }
+
+    /// Ping every bootstrap peer concurrently and record the results.
+    /// Returns how many answered.
+    pub async fn probe_all(&mut self, client: &P2PClient) -> usize {
+        let results = futures::future::join_all(self.peers.iter().map(|p| async move {
+            let rtt = client
+                .ping_peer(&p.peer_id.to_bytes(), PROBE_TIMEOUT)
+                .await
+                .ok();
+            (p.peer_id, rtt)
+        }))
+        .await;
+        let mut reachable = 0;
+        for (peer, rtt) in results {
+            debug!(
+                peer = %peer,
+                rtt_ms = rtt.map(|d| d.as_secs_f64() * 1000.0),
+                "bootstrap probe"
+            );
+            reachable += usize::from(rtt.is_some());
+            self.record(&peer, rtt);
+        }
+        reachable
+    }
+
+    /// One-line ranking for logs, e.g. `bootstrap-2.kwaai.ai 41ms, 18.219.43.67 down`.
+    pub fn summary(&self) -> String {
+        self.ranked()
+            .iter()
+            .filter_map(|addr| self.peers.iter().find(|p| &p.addr == addr))
+            .map(|p| {
+                let host = host_of(&p.addr);
+                match (p.failures, p.rtt) {
+                    (0, Some(rtt)) => format!("{host} {}ms", rtt.as_millis()),
+                    (0, None) => format!("{host} untested"),
+                    _ => format!("{host} down"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Fetch a bootstrap list from `url`, cache it, and return it.
+pub async fn fetch_list(http: &reqwest::Client, url: &str) -> Result<Vec<String>> {
+    if !url.starts_with("https://") {
+        bail!("bootstrap_list_url must be an https:// URL");
+    }
+    let body = http
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("fetching bootstrap list from {url}"))?
+        .text()
+        .await?;
+    let list = parse_list(&body)?;
+    if let Err(e) = std::fs::write(cache_file(), serde_json::to_string_pretty(&list)?) {
+        warn!("Could not cache bootstrap list: {e}");
+    }
+    Ok(list)
+}
+
+/// The last list fetched from `bootstrap_list_url`, if any.
+pub fn load_cached() -> Vec<String> {
+    std::fs::read_to_string(cache_file())
+        .ok()
+        .and_then(|s| parse_list(&s).ok())
+        .unwrap_or_default()
+}
+
+fn cache_file() -> PathBuf {
+    crate::config::kwaainet_dir().join("bootstrap_peers.json")
+}
+
+/// Accepts a JSON array of multiaddrs or `{"bootstrap_peers": [...]}`.
+/// Entries that are not multiaddrs ending in a `/p2p/` peer ID are dropped.
+fn parse_list(body: &str) -> Result<Vec<String>> {
+    let value: serde_json::Value =
+        serde_json::from_str(body).context("bootstrap list is not valid JSON")?;
+    let entries = match &value {
+        serde_json::Value::Array(a) => a,
+        serde_json::Value::Object(o) => match o.get("bootstrap_peers") {
+            Some(serde_json::Value::Array(a)) => a,
+            _ => bail!("bootstrap list object has no \"bootstrap_peers\" array"),
+        },
+        _ => bail!("bootstrap list must be a JSON array"),
+    };
+    let list: Vec<String> = entries
+        .iter()
+        .filter_map(|v| v.as_str())
+        .filter(|s| peer_id_of(s).is_some())
+        .take(MAX_FETCHED)
+        .map(str::to_string)
+        .collect();
+    if list.is_empty() {
+        bail!("bootstrap list contains no usable multiaddrs");
+    }
+    Ok(list)
+}
+
+/// Peer ID from the trailing `/p2p/` component of a multiaddr.
+pub(crate) fn peer_id_of(addr: &str) -> Option<PeerId> {
+    let ma: Multiaddr = addr.parse().ok()?;
+    ma.iter().find_map(|p| match p {
+        Protocol::P2p(id) => Some(id),
+        _ => None,
+    })
+}
+
+/// DNS name or IP of a multiaddr, for logs.
+fn host_of(addr: &str) -> String {
+    let parts: Vec<&str> = addr.split('/').collect();
+    parts
+        .windows(2)
+        .find(|w| matches!(w[0], "dns" | "dns4" | "dns6" | "ip4" | "ip6"))
+        .map(|w| w[1].to_string())
+        .unwrap_or_else(|| addr.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str =
+        "/dns/bootstrap-1.kwaai.ai/tcp/8000/p2p/QmQhRuheeCLEsVD3RsnknM75gPDDqxAb8DhnWgro7KhaJc";
+    const B: &str =
+        "/dns/bootstrap-2.kwaai.ai/tcp/8000/p2p/Qmd3A8N5aQBATe2SYvNikaeCS9CAKN4E86jdCPacZ6RZJY";
+    const C: &str =
+        "/ip4/203.0.113.7/tcp/8000/p2p/12D3KooWRBy97UB99e3J6hiPesre1MZeuNQvfan4gBziswrRJsNK";
+
+    fn manager() -> BootstrapManager {
+        BootstrapManager::new(&[A.to_string(), B.to_string(), C.to_string()])
+    }
+
+    #[test]
+    fn ranks_reachable_by_rtt_then_untested_then_failed() {
+        let mut m = manager();
+        assert_eq!(m.ranked(), [A, B, C], "untested peers keep config order");
+
+        m.record(&peer_id_of(A).unwrap(), None);
+        m.record(&peer_id_of(C).unwrap(), Some(Duration::from_millis(80)));
+        assert_eq!(m.ranked(), [C, B, A]);
+
+        m.record(&peer_id_of(B).unwrap(), Some(Duration::from_millis(20)));
+        assert_eq!(m.ranked(), [B, C, A]);
+
+        // A success clears the failure count.
+        m.record(&peer_id_of(A).unwrap(), Some(Duration::from_millis(50)));
+        assert_eq!(m.ranked(), [B, A, C]);
+        assert!(m.summary().starts_with("bootstrap-2.kwaai.ai 20ms"));
+    }
+
+    #[test]
+    fn rotate_moves_leader_behind_the_rest() {
+        let mut m = manager();
+        m.rotate();
+        assert_eq!(m.ranked(), [B, C, A]);
+        m.rotate();
+        m.rotate();
+        // Everyone failed once: back to config order.
+        assert_eq!(m.ranked(), [A, B, C]);
+    }
+
+    #[test]
+    fn merge_skips_duplicates_and_invalid_entries() {
+        let mut m = BootstrapManager::new(&[A.to_string(), "/ip4/1.2.3.4/tcp/1".to_string()]);
+        assert_eq!(m.ranked(), [A]);
+        assert_eq!(m.merge(&[A.to_string(), B.to_string()]), 1);
+        assert_eq!(m.ranked(), [A, B]);
+    }
+
+    #[test]
+    fn parses_both_list_shapes() {
+        let arr = format!(r#"["{A}", "not-a-multiaddr", 7]"#);
+        assert_eq!(parse_list(&arr).unwrap(), [A]);
+        let obj = format!(r#"{{"bootstrap_peers": ["{B}", "{C}"]}}"#);
+        assert_eq!(parse_list(&obj).unwrap(), [B, C]);
+        assert!(parse_list(r#"{"peers": []}"#).is_err());
+        assert!(parse_list(r#"["/ip4/1.2.3.4/tcp/1"]"#).is_err());
+    }
+}
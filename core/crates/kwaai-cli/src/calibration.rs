@@ -1,25 +1,28 @@
 //! Hardware calibration — estimate optimal block count from available RAM/VRAM
+//!
+//! GPUs are found through the CUDA driver when the binary is built with CUDA,
+//! else `nvidia-smi`, else Apple Silicon's unified memory. All CUDA devices
+//! count: a multi-GPU node splits its blocks across them (`device_map`).
+//!
+//! A block's memory is its weights plus the KV cache it holds for
+//! `cache_tokens` tokens ([`BlockSize`]). Both come from the model's own
+//! files when they are on disk — safetensors headers for a HuggingFace
+//! snapshot, the GGUF header for an Ollama model or a `.gguf` path — and
+//! from a per-model-name estimate otherwise. [`benchmark_blocks`] replaces
+//! the weight figure with a measurement by loading a few real blocks and
+//! running a short forward pass through them (`kwaainet calibrate --benchmark`).
 
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use kwaai_inference::{DeviceType, ModelFootprint, TransformerShard};
+use kwaai_p2p::AcceleratorInfo;
+use serde::Serialize;
 use sysinfo::System;
 use tracing::debug;
 
-/// Known model block counts (total blocks in the full model)
-fn model_total_blocks(model: &str) -> u32 {
-    let model = model.to_lowercase();
-    if model.contains("llama-3") && model.contains("8b") {
-        32
-    } else if model.contains("llama-3") && model.contains("70b") {
-        80
-    } else if model.contains("llama-2") && model.contains("7b") {
-        32
-    } else if model.contains("llama-2") && model.contains("13b") {
-        40
-    } else if model.contains("llama-2") && model.contains("70b") {
-        80
-    } else {
-        32 // safe default (includes mistral-7b and unknown models)
-    }
-}
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
 
 /// Memory per block in bytes (float16)
 fn bytes_per_block_f16(model: &str) -> u64 {
@@ -37,14 +40,98 @@ fn bytes_per_block_f16(model: &str) -> u64 {
     } // ~250 MB (7-8B)
 }
 
-#[derive(Debug, Clone)]
+/// Where a [`BlockSize`] came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockSizing {
+    /// Guessed from the model name
+    Estimated,
+    /// Tensor shapes in the model's weight files
+    ModelFiles,
+    /// Weights measured by loading real blocks (`--benchmark`)
+    Measured,
+}
+
+/// Memory one block of a model takes on a node
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BlockSize {
+    pub total_blocks: u32,
+    /// Weights at the f16 compute dtype
+    pub weight_bytes: u64,
+    /// KV cache for `cache_tokens` tokens; 0 when the model's attention
+    /// shape is unknown
+    pub kv_cache_bytes: u64,
+    pub sizing: BlockSizing,
+}
+
+impl BlockSize {
+    /// Size the blocks of `model` from its files when they are on disk,
+    /// else from its name and the model registry, with the KV cache for
+    /// `cache_tokens` tokens.
+    pub fn for_model(model: &str, cache_tokens: u64) -> Self {
+        match model_footprint(model) {
+            Some(fp) => Self {
+                total_blocks: fp.num_blocks() as u32,
+                weight_bytes: fp.mean_block_weight_bytes(),
+                kv_cache_bytes: fp.kv_cache_bytes(cache_tokens),
+                sizing: BlockSizing::ModelFiles,
+            },
+            None => Self {
+                total_blocks: crate::model_catalog::identify(model).num_blocks as u32,
+                weight_bytes: bytes_per_block_f16(model),
+                kv_cache_bytes: 0,
+                sizing: BlockSizing::Estimated,
+            },
+        }
+    }
+
+    /// Replace the weight figure with one measured by [`benchmark_blocks`].
+    pub fn measured(self, weight_bytes: u64) -> Self {
+        Self {
+            weight_bytes,
+            sizing: BlockSizing::Measured,
+            ..self
+        }
+    }
+
+    pub fn bytes_per_block(&self) -> u64 {
+        (self.weight_bytes + self.kv_cache_bytes).max(1)
+    }
+}
+
+/// Read the footprint of `model` from local files: a `.gguf` path, a
+/// HuggingFace snapshot, or an Ollama model. `None` when none is on disk.
+pub fn model_footprint(model: &str) -> Option<ModelFootprint> {
+    let path = Path::new(model);
+    let result = if path.extension().and_then(|e| e.to_str()) == Some("gguf") {
+        ModelFootprint::from_gguf(path).map_err(anyhow::Error::from)
+    } else if let Ok(dir) = crate::hf::resolve_snapshot(model) {
+        ModelFootprint::from_safetensors_dir(&dir).map_err(anyhow::Error::from)
+    } else {
+        crate::ollama::find_model_blob(model)
+            .and_then(|blob| ModelFootprint::from_gguf(&blob).map_err(anyhow::Error::from))
+    };
+    match result {
+        Ok(fp) if fp.num_blocks() > 0 => Some(fp),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("No model files to size {model} from: {e:#}");
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct GpuInfo {
+    /// Model of the first device
     pub name: String,
+    /// Number of devices; VRAM figures are summed over all of them
+    pub devices: usize,
     pub total_vram: u64,
     pub free_vram: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct HardwareInfo {
     pub total_memory: u64,
     pub available_memory: u64,
@@ -52,7 +139,7 @@ pub struct HardwareInfo {
     pub gpu: Option<GpuInfo>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CalibrationProfile {
     pub min_blocks: u32,
     pub recommended_blocks: u32,
@@ -62,6 +149,8 @@ pub struct CalibrationProfile {
     pub total_blocks: u32,
     /// Whether the recommendation is based on GPU VRAM (true) or system RAM (false).
     pub gpu_based: bool,
+    /// Per-block memory the recommendation assumes
+    pub block_size: BlockSize,
 }
 
 impl CalibrationProfile {
@@ -75,8 +164,19 @@ impl CalibrationProfile {
     }
 }
 
-/// Detect NVIDIA GPU via nvidia-smi.
-/// Returns name, total VRAM (bytes), free VRAM (bytes).
+/// Detect CUDA devices through the driver (CUDA builds only).
+fn detect_cuda_gpus() -> Option<GpuInfo> {
+    let devices = kwaai_inference::cuda_devices();
+    let first = devices.first()?;
+    Some(GpuInfo {
+        name: first.name.clone(),
+        devices: devices.len(),
+        total_vram: devices.iter().map(|d| d.total_memory).sum(),
+        free_vram: devices.iter().map(|d| d.free_memory).sum(),
+    })
+}
+
+/// Detect NVIDIA GPUs via nvidia-smi.
 fn detect_nvidia_gpu() -> Option<GpuInfo> {
     let output = std::process::Command::new("nvidia-smi")
         .args([
@@ -88,21 +188,43 @@ fn detect_nvidia_gpu() -> Option<GpuInfo> {
     if !output.status.success() {
         return None;
     }
-    let text = String::from_utf8_lossy(&output.stdout);
-    let line = text.lines().next()?.trim();
-    let parts: Vec<&str> = line.splitn(3, ',').map(|s| s.trim()).collect();
-    if parts.len() < 3 {
-        return None;
+    parse_nvidia_smi(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse `name, total MiB, free MiB` lines, one per GPU.
+fn parse_nvidia_smi(text: &str) -> Option<GpuInfo> {
+    let mut gpu: Option<GpuInfo> = None;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let parts: Vec<&str> = line.splitn(3, ',').map(|s| s.trim()).collect();
+        if parts.len() < 3 {
+            continue;
+        }
+        let (Ok(total_mib), Ok(free_mib)) = (parts[1].parse::<u64>(), parts[2].parse::<u64>())
+        else {
+            continue;
+        };
+        let g = gpu.get_or_insert_with(|| GpuInfo {
+            name: parts[0].to_string(),
+            devices: 0,
+            total_vram: 0,
+            free_vram: 0,
+        });
+        g.devices += 1;
+        g.total_vram += total_mib * MIB;
+        g.free_vram += free_mib * MIB;
+    }
+    gpu
+}
+
+/// Share of unified memory Metal lets the GPU keep resident
+/// (`recommendedMaxWorkingSetSize`): about two thirds up to 36 GB of RAM,
+/// three quarters above.
+fn metal_working_set(total: u64) -> u64 {
+    if total > 36 * GIB {
+        total / 4 * 3
+    } else {
+        total / 3 * 2
     }
-    let name = parts[0].to_string();
-    // nvidia-smi reports MiB
-    let total_mib: u64 = parts[1].parse().ok()?;
-    let free_mib: u64 = parts[2].parse().ok()?;
-    Some(GpuInfo {
-        name,
-        total_vram: total_mib * 1024 * 1024,
-        free_vram: free_mib * 1024 * 1024,
-    })
 }
 
 /// On macOS with Apple Silicon, GPU shares unified memory with the CPU.
@@ -118,15 +240,18 @@ fn detect_apple_gpu(sys: &System) -> Option<GpuInfo> {
     if !brand.contains("Apple") {
         return None;
     }
-    // Unified memory — GPU and CPU share the same pool
+    // Unified memory — GPU and CPU share the same pool, of which the GPU
+    // may only wire its working set.
     let total = sys.total_memory();
     let available = sys
         .available_memory()
         .max(total.saturating_sub(sys.used_memory()));
+    let working_set = metal_working_set(total);
     Some(GpuInfo {
         name: brand.trim().to_string(),
-        total_vram: total,
-        free_vram: available,
+        devices: 1,
+        total_vram: working_set,
+        free_vram: available.min(working_set),
     })
 }
 
@@ -149,8 +274,11 @@ impl CalibrationEngine {
             .available_memory()
             .max(total.saturating_sub(sys.used_memory()));
 
-        // GPU detection: NVIDIA first, then Apple Silicon unified memory
-        let gpu = detect_nvidia_gpu().or_else(|| detect_apple_gpu(&sys));
+        // GPU detection: CUDA driver, then nvidia-smi, then Apple Silicon
+        // unified memory
+        let gpu = detect_cuda_gpus()
+            .or_else(detect_nvidia_gpu)
+            .or_else(|| detect_apple_gpu(&sys));
         debug!(?gpu, "GPU detection result");
 
         let hardware = HardwareInfo {
@@ -163,13 +291,16 @@ impl CalibrationEngine {
         Self { hardware }
     }
 
-    pub fn calibrate(&self, model: &str) -> CalibrationProfile {
-        let total_blocks = model_total_blocks(model);
-        let bytes_per_block = bytes_per_block_f16(model);
+    /// Block counts that fit this hardware at `block_size` per block
+    /// (see [`BlockSize::for_model`]).
+    pub fn calibrate(&self, block_size: BlockSize) -> CalibrationProfile {
+        let total_blocks = block_size.total_blocks.max(1);
+        let bytes_per_block = block_size.bytes_per_block();
 
-        // Reserve: 512 MB for GPU overhead, 2 GB for CPU/OS overhead.
-        let gpu_reserve: u64 = 512 * 1024 * 1024;
-        let cpu_reserve: u64 = 2 * 1024 * 1024 * 1024;
+        // Reserve: 512 MB per GPU for overhead, 2 GB for CPU/OS overhead.
+        let gpu_reserve: u64 =
+            512 * MIB * self.hardware.gpu.as_ref().map_or(1, |g| g.devices.max(1)) as u64;
+        let cpu_reserve: u64 = 2 * GIB;
 
         // Max blocks: based on total hardware capacity (what the machine can do).
         // Available now: based on what is currently free.
@@ -205,6 +336,209 @@ impl CalibrationEngine {
             available_now_blocks,
             total_blocks,
             gpu_based,
+            block_size,
+        }
+    }
+
+    /// Accelerator details to announce when computing on `device`.
+    ///
+    /// The detected GPU's name and VRAM only apply to GPU devices; a node
+    /// running on the CPU reports no VRAM even if a GPU is present.
+    pub fn accelerator_info(&self, device: DeviceType) -> AcceleratorInfo {
+        let gpu = self.hardware.gpu.as_ref().filter(|_| device.is_gpu());
+        AcceleratorInfo {
+            device_type: device.kind().to_string(),
+            device_name: gpu.map(|g| g.name.clone()),
+            vram_mb: gpu.map_or(0, |g| g.total_vram / MIB),
+            dtypes: device
+                .supported_dtypes()
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            quant_types: device
+                .supported_quant_types()
+                .iter()
+                .map(|q| q.to_string())
+                .collect(),
+            backend: device.backend().to_string(),
+        }
+    }
+}
+
+/// Result of a short real forward pass through a few blocks
+/// (`kwaainet calibrate --benchmark`).
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkResult {
+    /// Device the blocks ran on
+    pub device: String,
+    /// Blocks loaded and run
+    pub blocks: usize,
+    /// Memory one loaded block took; 0 when it could not be measured
+    pub bytes_per_block: u64,
+    /// Single-token decode steps per second through one block
+    pub inference_rps: f64,
+    /// Prefill tokens per second through one block
+    pub forward_rps: f64,
+    pub hidden_size: usize,
+}
+
+/// Load `blocks` blocks of `model` from the local HuggingFace cache onto
+/// `device`, measure the memory they take and run the shard benchmark
+/// through them. Blocking; takes seconds to a minute.
+///
+/// Middle blocks are used when the model has enough, so the embedding and
+/// LM head do not inflate the per-block figure.
+pub fn benchmark_blocks(model: &str, device: DeviceType, blocks: usize) -> Result<BenchmarkResult> {
+    let dir = crate::hf::resolve_snapshot(model).with_context(|| {
+        format!("{model} is not downloaded; fetch it with `kwaainet shard download`")
+    })?;
+    let config_path = dir.join("config.json");
+    let total = std::fs::read_to_string(&config_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v["num_hidden_layers"].as_u64())
+        .map(|n| n as usize)
+        .with_context(|| format!("reading num_hidden_layers from {}", config_path.display()))?;
+    let start = if total > blocks + 1 { 1 } else { 0 };
+    let end = (start + blocks.max(1)).min(total);
+    let (is_first, is_last) = (start == 0, end == total);
+    if !crate::hf::blocks_are_cached(&dir, start, end, is_first, is_last) {
+        bail!(
+            "weights for blocks [{start}, {end}) of {model} are not cached; \
+             fetch them with `kwaainet shard download`"
+        );
+    }
+    let paths: Vec<PathBuf> =
+        match crate::hf::weight_files_for_blocks(&dir, start, end, is_first, is_last) {
+            Some(files) if !files.is_empty() => files.iter().map(|f| dir.join(f)).collect(),
+            _ => std::fs::read_dir(&dir)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("safetensors"))
+                .collect(),
+        };
+    let refs: Vec<&std::path::Path> = paths.iter().map(|p| p.as_path()).collect();
+
+    let candle_device = device
+        .to_candle_device()
+        .context("Failed to create compute device")?;
+    let before = memory_in_use(device);
+    let shard = TransformerShard::load(&refs, &config_path, &candle_device, start, end)
+        .context("Failed to load blocks for the benchmark")?;
+    let after = memory_in_use(device);
+    let rates = shard.benchmark(
+        crate::shard_cmd::BENCH_DECODE_STEPS,
+        crate::shard_cmd::BENCH_PREFILL_TOKENS,
+    )?;
+
+    let loaded = end - start;
+    let bytes_per_block = match (before, after) {
+        (Some(b), Some(a)) if a > b => (a - b) / loaded as u64,
+        _ => 0,
+    };
+    debug!(bytes_per_block, ?rates, "Calibration benchmark");
+    Ok(BenchmarkResult {
+        device: device.to_string(),
+        blocks: loaded,
+        bytes_per_block,
+        inference_rps: rates.inference_rps,
+        forward_rps: rates.forward_rps,
+        hidden_size: shard.cfg.hidden_dim,
+    })
+}
+
+/// Memory in use where `device` allocates: device memory for CUDA, this
+/// process's resident memory otherwise (unified on Apple Silicon).
+fn memory_in_use(device: DeviceType) -> Option<u64> {
+    if let DeviceType::Cuda(ordinal) = device {
+        return kwaai_inference::cuda_devices()
+            .into_iter()
+            .find(|d| d.ordinal == ordinal)
+            .map(|d| d.total_memory.saturating_sub(d.free_memory));
+    }
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut sys = System::new();
+    sys.refresh_process(pid);
+    sys.process(pid).map(|p| p.memory())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(gpu: Option<GpuInfo>) -> CalibrationEngine {
+        CalibrationEngine {
+            hardware: HardwareInfo {
+                total_memory: 64 * GIB,
+                available_memory: 48 * GIB,
+                cpu_cores: 16,
+                gpu,
+            },
+        }
+    }
+
+    #[test]
+    fn sums_vram_over_every_nvidia_gpu() {
+        let gpu = parse_nvidia_smi(
+            "NVIDIA GeForce RTX 4090, 24564, 23000\nNVIDIA GeForce RTX 4090, 24564, 1000\n",
+        )
+        .unwrap();
+        assert_eq!(gpu.devices, 2);
+        assert_eq!(gpu.total_vram, 2 * 24564 * MIB);
+        assert_eq!(gpu.free_vram, 24000 * MIB);
+        assert!(parse_nvidia_smi("").is_none());
+        assert!(parse_nvidia_smi("No devices were found").is_none());
+    }
+
+    #[test]
+    fn metal_working_set_is_a_share_of_unified_memory() {
+        assert_eq!(metal_working_set(24 * GIB), 16 * GIB);
+        assert_eq!(metal_working_set(64 * GIB), 48 * GIB);
+    }
+
+    fn estimated(model: &str) -> BlockSize {
+        BlockSize {
+            total_blocks: kwaai_p2p::hivemind::DEFAULT_NUM_BLOCKS as u32,
+            weight_bytes: bytes_per_block_f16(model),
+            kv_cache_bytes: 0,
+            sizing: BlockSizing::Estimated,
         }
     }
+
+    #[test]
+    fn measured_block_size_drives_the_profile() {
+        let gpu = GpuInfo {
+            name: "test".into(),
+            devices: 2,
+            total_vram: 2 * 8 * GIB,
+            free_vram: 2 * 8 * GIB,
+        };
+        let e = engine(Some(gpu));
+        let size = estimated("llama-2-7b");
+        let profile = e.calibrate(size);
+        assert!(profile.gpu_based);
+        // Twice the estimated footprint fits half as many blocks.
+        let measured = e.calibrate(size.measured(2 * size.weight_bytes));
+        assert!(measured.max_blocks < profile.max_blocks);
+        assert_eq!(measured.block_size.sizing, BlockSizing::Measured);
+        assert_eq!(e.calibrate(size.measured(1)).max_blocks, 32);
+    }
+
+    #[test]
+    fn kv_cache_counts_against_every_block() {
+        let e = engine(None);
+        // 62 GiB usable after the 2 GiB CPU reserve.
+        let weights = BlockSize {
+            total_blocks: 80,
+            weight_bytes: GIB,
+            kv_cache_bytes: 0,
+            sizing: BlockSizing::ModelFiles,
+        };
+        assert_eq!(e.calibrate(weights).max_blocks, 62);
+        let with_kv = BlockSize {
+            kv_cache_bytes: GIB,
+            ..weights
+        };
+        assert_eq!(e.calibrate(with_kv).max_blocks, 31);
+    }
 }
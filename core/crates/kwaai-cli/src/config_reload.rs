@@ -0,0 +1,165 @@
+//! Hot reload of config.yaml into a running node.
+//!
+//! SIGHUP and `kwaainet config reload` (the `reload` control method) make the
+//! node re-read config.yaml. Settings in [`HOT_SETTINGS`] take effect at once;
+//! any other setting that changed is reported back as needing a restart, and
+//! the node keeps running with its old value until then.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::KwaaiNetConfig;
+
+/// Settings a running node applies on reload, as dotted config paths. A path
+/// covers everything below it.
+pub const HOT_SETTINGS: &[&str] = &[
+    "log_level",
+    "public_name",
+    "announce_interval_secs",
+    "start_block",
+    "blocks",
+    "health_monitoring.alerting",
+    "rpc_limits.per_peer_rps",
+    "rpc_limits.per_peer_burst",
+    "rpc_limits.global_rps",
+    "rpc_limits.global_burst",
+    "rpc_limits.max_concurrent",
+    "rpc_limits.max_streams_per_peer",
+    "rpc_limits.max_message_bytes",
+    // Re-read by crate::peer_filter whenever config.yaml changes.
+    "peer_filter",
+];
+
+/// What a reload changed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReloadReport {
+    /// Changed settings now in effect
+    pub applied: Vec<String>,
+    /// Changed settings that only take effect after a restart
+    pub restart_required: Vec<String>,
+    /// Changed settings that were rejected, with the reason
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn touches(&self, setting: &str) -> bool {
+        self.applied.iter().any(|s| covers(setting, s))
+    }
+}
+
+/// Whether `path` is `setting` or lies below it
+fn covers(setting: &str, path: &str) -> bool {
+    path == setting
+        || path
+            .strip_prefix(setting)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+pub fn is_hot(path: &str) -> bool {
+    HOT_SETTINGS.iter().any(|hot| covers(hot, path))
+}
+
+/// Copy the hot settings of `fresh` into `config` and sort every setting
+/// that differs into applied and restart-required.
+pub fn apply(config: &mut KwaaiNetConfig, fresh: &KwaaiNetConfig) -> ReloadReport {
+    let mut report = ReloadReport::default();
+    for path in changed_settings(config, fresh) {
+        if is_hot(&path) {
+            report.applied.push(path);
+        } else {
+            report.restart_required.push(path);
+        }
+    }
+
+    config.log_level = fresh.log_level.clone();
+    config.public_name = fresh.public_name.clone();
+    config.announce_interval_secs = fresh.announce_interval_secs;
+    config.start_block = fresh.start_block;
+    config.blocks = fresh.blocks;
+    config.health_monitoring.alerting = fresh.health_monitoring.alerting.clone();
+    config.peer_filter = fresh.peer_filter.clone();
+    let (limits, new) = (&mut config.rpc_limits, &fresh.rpc_limits);
+    limits.per_peer_rps = new.per_peer_rps;
+    limits.per_peer_burst = new.per_peer_burst;
+    limits.global_rps = new.global_rps;
+    limits.global_burst = new.global_burst;
+    limits.max_concurrent = new.max_concurrent;
+    limits.max_streams_per_peer = new.max_streams_per_peer;
+    limits.max_message_bytes = new.max_message_bytes;
+    report
+}
+
+/// Settings that differ between `old` and `new`, as dotted paths to the
+/// innermost differing keys
+pub fn changed_settings(old: &KwaaiNetConfig, new: &KwaaiNetConfig) -> Vec<String> {
+    let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        return Vec::new();
+    };
+    let mut changed = Vec::new();
+    diff("", &old, &new, &mut changed);
+    changed
+}
+
+fn diff(path: &str, old: &Value, new: &Value, out: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => diff(&child, x, y, out),
+                    _ => out.push(child),
+                }
+            }
+        }
+        _ if old != new => out.push(path.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_changes_into_hot_and_restart() {
+        let mut running = KwaaiNetConfig::default();
+        let mut fresh = running.clone();
+        fresh.public_name = Some("alice-m4".into());
+        fresh.rpc_limits.per_peer_rps = running.rpc_limits.per_peer_rps * 2.0;
+        fresh.rpc_limits.max_value_bytes = running.rpc_limits.max_value_bytes / 2;
+        fresh.port = running.port + 1;
+
+        let report = apply(&mut running, &fresh);
+        assert_eq!(report.applied, ["public_name", "rpc_limits.per_peer_rps"]);
+        assert_eq!(
+            report.restart_required,
+            ["port", "rpc_limits.max_value_bytes"]
+        );
+        assert!(report.touches("public_name"));
+        assert!(!report.touches("blocks"));
+
+        // Hot settings are now in effect; the rest waits for a restart.
+        assert_eq!(running.public_name.as_deref(), Some("alice-m4"));
+        assert_eq!(
+            running.rpc_limits.per_peer_rps,
+            fresh.rpc_limits.per_peer_rps
+        );
+        assert_ne!(running.port, fresh.port);
+        assert_eq!(changed_settings(&running, &fresh).len(), 2);
+    }
+
+    #[test]
+    fn hot_paths_cover_their_children_only() {
+        assert!(is_hot("health_monitoring.alerting.webhook_url"));
+        assert!(!is_hot("health_monitoring.reachability_interval"));
+        assert!(!is_hot("blocks_extra"));
+    }
+}
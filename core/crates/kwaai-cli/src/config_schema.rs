@@ -0,0 +1,501 @@
+//! Known config.yaml settings with their types and ranges.
+//!
+//! `kwaainet config set` checks the key and value against [`SETTINGS`]
+//! before changing anything, and `KwaaiNetConfig::load_or_create` checks the
+//! whole file with [`validate`]. Without this serde ignores keys it does not
+//! know, so a typo such as `blocs: 4` silently left the default in place.
+//!
+//! Sections (`rpc_limits`, `api.tls`, …) are not listed themselves: a key is
+//! a section when some setting lies below it.
+
+use anyhow::Result;
+use serde_yaml::Value;
+use std::fmt;
+
+use crate::experiments::Experiment;
+
+/// What a setting holds
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Bool,
+    /// Whole number in `min..=max`
+    Int {
+        min: u64,
+        max: u64,
+    },
+    /// Number greater than zero
+    Positive,
+    /// Percentage in `min..=100`, with or without `%`
+    Percent {
+        min: u8,
+    },
+    Text,
+    OneOf(&'static [&'static str]),
+    /// Comma-separated on the command line, a YAML list in the file
+    List,
+    /// Structured section whose contents only the parser checks
+    Table,
+    /// `experiments`: known flag names mapped to true/false
+    Flags,
+}
+
+#[derive(Debug)]
+pub struct Setting {
+    /// Dotted path in config.yaml
+    pub key: &'static str,
+    pub kind: Kind,
+    /// Accepted by `kwaainet config set`; the rest are edited in the file
+    pub settable: bool,
+    /// `none` (or an empty value) clears it on the command line
+    pub clearable: bool,
+}
+
+const fn set(key: &'static str, kind: Kind) -> Setting {
+    Setting {
+        key,
+        kind,
+        settable: true,
+        clearable: false,
+    }
+}
+
+const fn clearable(key: &'static str, kind: Kind) -> Setting {
+    Setting {
+        key,
+        kind,
+        settable: true,
+        clearable: true,
+    }
+}
+
+const fn file_only(key: &'static str, kind: Kind) -> Setting {
+    Setting {
+        key,
+        kind,
+        settable: false,
+        clearable: false,
+    }
+}
+
+const U16: u64 = u16::MAX as u64;
+const U32: u64 = u32::MAX as u64;
+const ANY: u64 = u64::MAX;
+const NON_NEGATIVE: Kind = Kind::Int { min: 0, max: ANY };
+const POSITIVE_INT: Kind = Kind::Int { min: 1, max: ANY };
+const POSITIVE_U32: Kind = Kind::Int { min: 1, max: U32 };
+const PORT: Kind = Kind::Int { min: 1, max: U16 };
+
+pub const SETTINGS: &[Setting] = &[
+    set("model", Kind::Text),
+    set("blocks", POSITIVE_U32),
+    set("start_block", Kind::Int { min: 0, max: U32 }),
+    set("port", Kind::Int { min: 0, max: U16 }),
+    set("use_gpu", Kind::Bool),
+    clearable("device_map", Kind::Text),
+    set("log_level", Kind::Text),
+    set("inference_url", Kind::Text),
+    set("public_name", Kind::Text),
+    set("public_ip", Kind::Text),
+    set("public_port", PORT),
+    set("announce_addr", Kind::Text),
+    set("no_relay", Kind::Bool),
+    clearable("bootstrap_list_url", Kind::Text),
+    clearable("dht_access_token", Kind::Text),
+    clearable("dht_authority_key", Kind::Text),
+    set("dht_require_auth", Kind::Bool),
+    set("sign_announcements", Kind::Bool),
+    set("dht_persist", Kind::Bool),
+    set(
+        "dht_backend",
+        Kind::OneOf(&["hivemind_daemon", "hivemind_native", "kademlia"]),
+    ),
+    clearable("swarm_psk", Kind::Text),
+    set("identify_min_confirmations", POSITIVE_INT),
+    set("identify_timeout_secs", POSITIVE_INT),
+    set("learn_public_addr", Kind::Bool),
+    set("public_ip_check_secs", NON_NEGATIVE),
+    set("throughput_refresh_secs", NON_NEGATIVE),
+    set("announce_interval_secs", NON_NEGATIVE),
+    set("record_ttl_secs", NON_NEGATIVE),
+    set("auto_rebalance", Kind::Bool),
+    set("rebalance_interval_secs", POSITIVE_INT),
+    set("rebalance_min_redundancy", POSITIVE_INT),
+    set("shard_p2p_fetch", Kind::Bool),
+    set("lora_adapters", Kind::List),
+    set("shutdown_grace_secs", NON_NEGATIVE),
+    set("queue_depth", POSITIVE_INT),
+    set("cache_tokens", POSITIVE_INT),
+    set("session_idle_secs", POSITIVE_INT),
+    clearable("api_listen", Kind::Text),
+    clearable("admin_token", Kind::Text),
+    set("api.cors_origins", Kind::List),
+    clearable("api.tls.cert", Kind::Text),
+    clearable("api.tls.key", Kind::Text),
+    set("api.tls.self_signed", Kind::Bool),
+    set("api.tls.self_signed_names", Kind::List),
+    set("logging.rotation", Kind::OneOf(&["size", "daily"])),
+    set("logging.max_size_mb", NON_NEGATIVE),
+    set("logging.max_files", NON_NEGATIVE),
+    set("logging.max_age_days", NON_NEGATIVE),
+    set("contribute.storage", Kind::Bool),
+    set("contribute.shards", Kind::Bool),
+    set("contribute.auto_update", Kind::Bool),
+    clearable("bandwidth.upload_mbps", Kind::Positive),
+    clearable("bandwidth.download_mbps", Kind::Positive),
+    clearable("bandwidth.schedule", Kind::Text),
+    clearable("geoip.database", Kind::Text),
+    clearable("geoip.service_url", Kind::Text),
+    clearable("resources.max_threads", POSITIVE_INT),
+    clearable("resources.gpu_utilization", Kind::Percent { min: 5 }),
+    set("resources.pause_on_battery", Kind::Bool),
+    clearable(
+        "resources.pause_above_cpu_percent",
+        Kind::Percent { min: 1 },
+    ),
+    set("rpc_limits.per_peer_rps", Kind::Positive),
+    set("rpc_limits.per_peer_burst", POSITIVE_U32),
+    set("rpc_limits.global_rps", Kind::Positive),
+    set("rpc_limits.global_burst", POSITIVE_U32),
+    set("rpc_limits.max_concurrent", POSITIVE_INT),
+    set("rpc_limits.max_streams_per_peer", POSITIVE_INT),
+    set("rpc_limits.max_message_bytes", POSITIVE_INT),
+    set("rpc_limits.max_value_bytes", POSITIVE_INT),
+    set("rpc_limits.max_record_ttl_secs", POSITIVE_INT),
+    file_only("experiments", Kind::Flags),
+    file_only("identity_key", Kind::Text),
+    file_only("initial_peers", Kind::List),
+    file_only("trusted_relays", Kind::List),
+    file_only("force_private", Kind::Bool),
+    file_only("model_dht_prefix", Kind::Text),
+    file_only("model_repository", Kind::Text),
+    file_only("vpk_enabled", Kind::Bool),
+    file_only("vpk_mode", Kind::Text),
+    file_only("vpk_local_port", PORT),
+    file_only("ollama_manage", Kind::Bool),
+    file_only("ollama_port", PORT),
+    file_only("admission", Kind::Table),
+    file_only("api.keys", Kind::List),
+    file_only("health_monitoring", Kind::Table),
+    file_only("moe", Kind::Table),
+    file_only("peer_filter", Kind::Table),
+    file_only("reputation", Kind::Table),
+    file_only("storage", Kind::Table),
+    file_only("rag", Kind::Table),
+    file_only("rag_kbs", Kind::Table),
+];
+
+fn find(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|s| s.key == key)
+}
+
+fn is_section(key: &str) -> bool {
+    SETTINGS.iter().any(|s| {
+        s.key
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.starts_with('.'))
+    })
+}
+
+/// The setting `kwaainet config set` may change as `key`, or an error naming
+/// the closest known key.
+pub fn settable(key: &str) -> Result<&'static Setting> {
+    match find(key) {
+        Some(setting) if setting.settable => Ok(setting),
+        Some(_) => anyhow::bail!(
+            "{key} cannot be set with `kwaainet config set`; edit {} instead",
+            crate::config::config_file().display()
+        ),
+        None => {
+            let keys = SETTINGS.iter().filter(|s| s.settable).map(|s| s.key);
+            let hint = suggest(key, keys)
+                .map(|k| format!(" Did you mean '{k}'?"))
+                .unwrap_or_default();
+            anyhow::bail!(
+                "Unknown config key '{key}'.{hint} Run `kwaainet config set --help` to see valid keys."
+            )
+        }
+    }
+}
+
+impl Setting {
+    /// Check a value given on the command line.
+    pub fn check(&self, value: &str) -> Result<()> {
+        if self.clearable && matches!(value, "" | "none") {
+            return Ok(());
+        }
+        let ok = match self.kind {
+            Kind::Bool => matches!(
+                value.to_lowercase().as_str(),
+                "true" | "false" | "1" | "0" | "yes" | "no"
+            ),
+            Kind::Int { min, max } => value.parse::<u64>().is_ok_and(|n| (min..=max).contains(&n)),
+            Kind::Positive => value.parse::<f64>().is_ok_and(positive),
+            Kind::Percent { min } => value
+                .trim_end_matches('%')
+                .parse::<u8>()
+                .is_ok_and(|p| (min..=100).contains(&p)),
+            Kind::OneOf(choices) => choices.contains(&value),
+            Kind::Text | Kind::List | Kind::Table | Kind::Flags => true,
+        };
+        if ok {
+            Ok(())
+        } else {
+            anyhow::bail!("{} must be {}, got {:?}", self.key, self.expected(), value)
+        }
+    }
+
+    /// Check a value read from config.yaml; `None` when it fits.
+    fn check_yaml(&self, value: &Value) -> Option<String> {
+        let ok = match (self.kind, value) {
+            (_, Value::Null) => true,
+            (Kind::Bool, v) => v.is_bool(),
+            (Kind::Int { min, max }, v) => v.as_u64().is_some_and(|n| (min..=max).contains(&n)),
+            (Kind::Positive, v) => v.as_f64().is_some_and(positive),
+            (Kind::Percent { min }, v) => v
+                .as_u64()
+                .is_some_and(|p| (u64::from(min)..=100).contains(&p)),
+            (Kind::Text, v) => !v.is_mapping() && !v.is_sequence(),
+            (Kind::OneOf(choices), v) => v.as_str().is_some_and(|s| choices.contains(&s)),
+            (Kind::List, v) => v.is_sequence(),
+            (Kind::Table, v) => v.is_mapping(),
+            (Kind::Flags, Value::Mapping(flags)) => {
+                return flags.iter().find_map(|(name, on)| {
+                    let name = name.as_str().unwrap_or_default();
+                    if Experiment::from_key(name).is_none() {
+                        Some(format!(
+                            "unknown experiment '{name}' (known: {})",
+                            Experiment::known_keys()
+                        ))
+                    } else if !on.is_bool() {
+                        Some(format!("experiments.{name} must be true or false"))
+                    } else {
+                        None
+                    }
+                });
+            }
+            (Kind::Flags, _) => false,
+        };
+        (!ok).then(|| format!("expected {}, got {}", self.expected(), describe(value)))
+    }
+
+    fn expected(&self) -> String {
+        match self.kind {
+            Kind::Bool => "true or false".into(),
+            Kind::Int { min, max: ANY } => format!("a whole number ≥ {min}"),
+            Kind::Int { min, max } => format!("a whole number from {min} to {max}"),
+            Kind::Positive => "a number greater than 0".into(),
+            Kind::Percent { min } => format!("a percentage from {min} to 100"),
+            Kind::Text => "text".into(),
+            Kind::OneOf(choices) => format!("one of {}", choices.join(", ")),
+            Kind::List => "a list".into(),
+            Kind::Table | Kind::Flags => "a section of settings".into(),
+        }
+    }
+}
+
+fn positive(x: f64) -> bool {
+    x.is_finite() && x > 0.0
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Mapping(_) => "a section".into(),
+        Value::Sequence(_) => "a list".into(),
+        Value::String(s) => format!("{s:?}"),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Whole-file validation
+// ---------------------------------------------------------------------------
+
+/// One finding in config.yaml
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// 1-based line of the offending key, when it can be found
+    pub line: Option<usize>,
+    pub key: String,
+    pub message: String,
+    /// Unknown keys are ignored by the node; anything else stops it loading
+    pub fatal: bool,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// Check every key and value in the YAML `text`. Text that does not parse
+/// yields no problems; the config parser reports where it breaks.
+pub fn validate(text: &str) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if let Ok(Value::Mapping(root)) = serde_yaml::from_str::<Value>(text) {
+        walk(text, &[], &root, &mut problems);
+    }
+    problems
+}
+
+fn walk(text: &str, parent: &[&str], map: &serde_yaml::Mapping, out: &mut Vec<Problem>) {
+    for (name, value) in map {
+        let Some(name) = name.as_str() else { continue };
+        let path: Vec<&str> = parent.iter().copied().chain([name]).collect();
+        let key = path.join(".");
+        let problem = |message: String, fatal: bool| Problem {
+            line: line_of(text, &path),
+            key: key.clone(),
+            message,
+            fatal,
+        };
+        if let Some(setting) = find(&key) {
+            if let Some(message) = setting.check_yaml(value) {
+                out.push(problem(message, true));
+            }
+        } else if is_section(&key) {
+            match value {
+                Value::Mapping(children) => walk(text, &path, children, out),
+                Value::Null => {}
+                other => out.push(problem(
+                    format!("expected a section of settings, got {}", describe(other)),
+                    true,
+                )),
+            }
+        } else {
+            let siblings = SETTINGS
+                .iter()
+                .filter_map(|s| child_name(s.key, parent))
+                .collect::<Vec<_>>();
+            let hint = suggest(name, siblings.iter().copied())
+                .map(|k| format!(" — did you mean '{k}'?"))
+                .unwrap_or_default();
+            out.push(problem(format!("unknown setting, ignored{hint}"), false));
+        }
+    }
+}
+
+/// The first segment of `key` below `parent`, if `key` lies below it
+fn child_name<'a>(key: &'a str, parent: &[&str]) -> Option<&'a str> {
+    let mut rest = key;
+    for segment in parent {
+        rest = rest.strip_prefix(segment)?.strip_prefix('.')?;
+    }
+    rest.split('.').next()
+}
+
+/// 1-based line where the nested key `path` is defined
+fn line_of(text: &str, path: &[&str]) -> Option<usize> {
+    let mut lines = text.lines().enumerate();
+    let mut parent_indent: Option<usize> = None;
+    let mut found = None;
+    for segment in path {
+        let (i, indent) = lines.by_ref().find_map(|(i, line)| {
+            let body = line.trim_start();
+            let indent = line.len() - body.len();
+            let defines = body
+                .trim_start_matches(['"', '\''])
+                .strip_prefix(segment)
+                .is_some_and(|rest| rest.trim_start_matches(['"', '\'']).starts_with(':'));
+            (defines && parent_indent.is_none_or(|p| indent > p)).then_some((i, indent))
+        })?;
+        parent_indent = Some(indent);
+        found = Some(i + 1);
+    }
+    found
+}
+
+/// The candidate closest to `key`, if it is close enough to be a typo
+fn suggest<'a>(key: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (key.chars().count() / 3).clamp(1, 3);
+    candidates
+        .map(|c| (edit_distance(key, c), c))
+        .filter(|&(d, _)| d <= limit)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_suggests_the_closest_key() {
+        let err = settable("blocs").unwrap_err().to_string();
+        assert!(err.contains("Did you mean 'blocks'?"), "{err}");
+        let err = settable("rpc_limits.per_peer_rp").unwrap_err().to_string();
+        assert!(err.contains("'rpc_limits.per_peer_rps'"), "{err}");
+        assert!(settable("initial_peers").is_err());
+
+        let blocks = settable("blocks").unwrap();
+        assert!(blocks.check("4").is_ok());
+        assert!(blocks.check("0").is_err());
+        assert!(blocks.check("four").is_err());
+        let gpu = settable("resources.gpu_utilization").unwrap();
+        assert!(gpu.check("50%").is_ok() && gpu.check("none").is_ok());
+        assert!(gpu.check("2").is_err());
+    }
+
+    #[test]
+    fn validate_reports_keys_with_lines() {
+        let text = "\
+model: Llama-3.1-8B-Instruct
+blocs: 4
+port: 70000
+rpc_limits:
+  per_peer_rps: 10.0
+  global_brust: 50
+logging:
+  rotation: weekly
+experiments:
+  quic: true
+";
+        let problems = validate(text);
+        let summary: Vec<(Option<usize>, &str, bool)> = problems
+            .iter()
+            .map(|p| (p.line, p.key.as_str(), p.fatal))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some(2), "blocs", false),
+                (Some(3), "port", true),
+                (Some(6), "rpc_limits.global_brust", false),
+                (Some(8), "logging.rotation", true),
+            ]
+        );
+        assert!(problems[0].message.contains("did you mean 'blocks'"));
+        assert!(problems[2].message.contains("'global_burst'"));
+        assert_eq!(
+            problems[3].to_string(),
+            "line 8: logging.rotation: expected one of size, daily, got \"weekly\""
+        );
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        let text = serde_yaml::to_string(&crate::config::KwaaiNetConfig::default()).unwrap();
+        let problems = validate(&text);
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+}
@@ -0,0 +1,478 @@
+//! Local control channel between the CLI and a running node.
+//!
+//! `run_node` serves a small JSON-RPC 2.0 protocol so CLI commands can query
+//! and nudge the live node instead of reading status files or restarting the
+//! process. Framing is one JSON object per line in each direction.
+//!
+//! ## Transports
+//!
+//!   - Unix: socket at `~/.kwaainet/run/control.sock` (0600, so only the user
+//!     that started the node can dial in)
+//!   - Windows: named pipe `\\.\pipe\kwaainet-control`
+//!
+//! `KWAAINET_CONTROL_SOCKET` overrides either, for running several nodes on
+//! one machine alongside `KWAAINET_SOCKET`.
+//!
+//! ## Methods
+//!
+//! | method      | result                                                   |
+//! |-------------|----------------------------------------------------------|
+//! | `status`    | peer ID, addresses, announce state, connection count     |
+//! | `peers`     | current p2pd connections as `[{peer_id, addrs}]`         |
+//! | `announce`  | re-announce to the DHT now → `{announced}`               |
+//! | `reconnect` | re-dial bootstrap peers in place → `{connected, dialled}` |
+//! | `filter`    | re-read `peer_filter`, drop rejected peers → `{disconnected}` |
+//! | `reload`    | re-read config.yaml (see `crate::config_reload`) → `{applied, restart_required}` |
+//!
+//! The server does no node work itself: each call is handed to the
+//! `run_node` event loop as a [`ControlRequest`], because the loop owns the
+//! p2pd client and announce state. Calls are therefore answered between
+//! other event-loop work, not concurrently with it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+/// Windows named pipe the node listens on.
+#[cfg(windows)]
+const PIPE_NAME: &str = r"\\.\pipe\kwaainet-control";
+
+/// Requests waiting for the event loop. Small: the CLI issues one at a time.
+const QUEUE_DEPTH: usize = 8;
+
+/// Upper bound on one request line; anything longer is not from our CLI.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+// JSON-RPC 2.0 error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INTERNAL_ERROR: i64 = -32603;
+/// Server-defined: the event loop has exited (node shutting down).
+const NODE_UNAVAILABLE: i64 = -32000;
+
+/// Operations the node exposes over the control channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlMethod {
+    Status,
+    Peers,
+    Announce,
+    Reconnect,
+    Filter,
+    Reload,
+}
+
+impl ControlMethod {
+    fn as_str(self) -> &'static str {
+        match self {
+            ControlMethod::Status => "status",
+            ControlMethod::Peers => "peers",
+            ControlMethod::Announce => "announce",
+            ControlMethod::Reconnect => "reconnect",
+            ControlMethod::Filter => "filter",
+            ControlMethod::Reload => "reload",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        serde_json::from_value(Value::String(s.to_string())).ok()
+    }
+
+    /// How long the CLI waits for an answer. Announce and reconnect make
+    /// network round trips to every bootstrap peer, as does a reload that
+    /// changes what is announced.
+    fn timeout(self) -> Duration {
+        match self {
+            ControlMethod::Status | ControlMethod::Peers | ControlMethod::Filter => {
+                Duration::from_secs(10)
+            }
+            ControlMethod::Announce | ControlMethod::Reconnect | ControlMethod::Reload => {
+                Duration::from_secs(120)
+            }
+        }
+    }
+}
+
+/// One call forwarded to the node event loop. The loop must answer on
+/// `reply`; dropping it reports [`NODE_UNAVAILABLE`] to the caller.
+pub struct ControlRequest {
+    pub method: ControlMethod,
+    pub reply: oneshot::Sender<std::result::Result<Value, String>>,
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcResponse {
+    jsonrpc: String,
+    id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcResponse {
+    fn ok(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn err(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Server
+// ---------------------------------------------------------------------------
+
+/// Control socket path (Unix) or pipe name (Windows).
+pub fn endpoint() -> PathBuf {
+    if let Ok(p) = std::env::var("KWAAINET_CONTROL_SOCKET") {
+        return PathBuf::from(p);
+    }
+    #[cfg(unix)]
+    {
+        crate::config::run_dir().join("control.sock")
+    }
+    #[cfg(windows)]
+    {
+        PathBuf::from(PIPE_NAME)
+    }
+}
+
+/// Drop-to-shutdown handle for the control server task. Removes the Unix
+/// socket file so a stale entry never looks like a live node.
+pub struct ControlServerHandle {
+    task: tokio::task::JoinHandle<()>,
+    #[cfg(unix)]
+    path: PathBuf,
+}
+
+impl Drop for ControlServerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+        #[cfg(unix)]
+        {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Bind the control endpoint and return the receiving end the event loop
+/// should poll.
+pub fn spawn() -> Result<(ControlServerHandle, mpsc::Receiver<ControlRequest>)> {
+    spawn_at(endpoint())
+}
+
+fn spawn_at(path: PathBuf) -> Result<(ControlServerHandle, mpsc::Receiver<ControlRequest>)> {
+    let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+
+    #[cfg(unix)]
+    {
+        use tokio::net::UnixListener;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+        // Stale socket from a previous run blocks bind() with EADDRINUSE. The
+        // PID lock guarantees no other node owns it.
+        if path.exists() {
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener =
+            UnixListener::bind(&path).with_context(|| format!("binding {}", path.display()))?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+        }
+        info!("Control socket listening at {}", path.display());
+
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_conn(stream, tx.clone()));
+                    }
+                    Err(e) => warn!("Control socket accept error: {}", e),
+                }
+            }
+        });
+        Ok((ControlServerHandle { task, path }, rx))
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let name = path.to_string_lossy().into_owned();
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&name)
+            .with_context(|| format!("creating named pipe {}", name))?;
+        info!("Control pipe listening at {}", name);
+
+        let task = tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.connect().await {
+                    warn!("Control pipe connect error: {}", e);
+                    continue;
+                }
+                // A new instance must exist before the next client dials in.
+                let next = match ServerOptions::new().create(&name) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("Control pipe: cannot create next instance: {}", e);
+                        return;
+                    }
+                };
+                let connected = std::mem::replace(&mut server, next);
+                tokio::spawn(serve_conn(connected, tx.clone()));
+            }
+        });
+        Ok((ControlServerHandle { task }, rx))
+    }
+}
+
+/// Answer requests on one client connection until it closes.
+async fn serve_conn<S>(stream: S, tx: mpsc::Sender<ControlRequest>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    loop {
+        let line = match read_line(&mut reader).await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(e) => {
+                debug!("Control connection closed: {}", e);
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = dispatch(&line, &tx).await;
+        let mut out = match serde_json::to_vec(&response) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Control response encode failed: {}", e);
+                return;
+            }
+        };
+        out.push(b'\n');
+        if writer.write_all(&out).await.is_err() || writer.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn dispatch(line: &str, tx: &mpsc::Sender<ControlRequest>) -> RpcResponse {
+    let req: RpcRequest = match serde_json::from_str(line) {
+        Ok(r) => r,
+        Err(e) => return RpcResponse::err(Value::Null, PARSE_ERROR, e.to_string()),
+    };
+    let Some(method) = ControlMethod::parse(&req.method) else {
+        return RpcResponse::err(
+            req.id,
+            METHOD_NOT_FOUND,
+            format!("unknown method '{}'", req.method),
+        );
+    };
+
+    let (reply, answer) = oneshot::channel();
+    if tx.send(ControlRequest { method, reply }).await.is_err() {
+        return RpcResponse::err(req.id, NODE_UNAVAILABLE, "node is shutting down");
+    }
+    match answer.await {
+        Ok(Ok(result)) => RpcResponse::ok(req.id, result),
+        Ok(Err(msg)) => RpcResponse::err(req.id, INTERNAL_ERROR, msg),
+        Err(_) => RpcResponse::err(req.id, NODE_UNAVAILABLE, "node is shutting down"),
+    }
+}
+
+/// Read one `\n`-terminated line, refusing lines over [`MAX_LINE_BYTES`].
+/// `Ok(None)` means the peer closed the connection.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    let n = reader
+        .take(MAX_LINE_BYTES as u64 + 1)
+        .read_line(&mut line)
+        .await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if n > MAX_LINE_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "control message too long",
+        ));
+    }
+    Ok(Some(line))
+}
+
+// ---------------------------------------------------------------------------
+// Client
+// ---------------------------------------------------------------------------
+
+/// Call `method` on the running node.
+///
+/// Returns `Ok(None)` when nothing is listening on the control endpoint — the
+/// node is stopped, still starting up, or predates the control channel — so
+/// callers can fall back to their file- or signal-based path.
+pub async fn call(method: ControlMethod) -> Result<Option<Value>> {
+    call_at(&endpoint(), method).await
+}
+
+async fn call_at(path: &std::path::Path, method: ControlMethod) -> Result<Option<Value>> {
+    #[cfg(unix)]
+    let stream = match tokio::net::UnixStream::connect(path).await {
+        Ok(s) => s,
+        Err(_) => return Ok(None),
+    };
+    #[cfg(windows)]
+    let stream = match tokio::net::windows::named_pipe::ClientOptions::new().open(path.as_os_str())
+    {
+        Ok(s) => s,
+        Err(_) => return Ok(None),
+    };
+
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method.as_str(),
+    });
+    let exchange = async {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut line = serde_json::to_vec(&request)?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+        writer.flush().await?;
+
+        let reply = read_line(&mut BufReader::new(reader))
+            .await?
+            .context("node closed the control connection without replying")?;
+        let resp: RpcResponse =
+            serde_json::from_str(&reply).context("malformed control response")?;
+        match (resp.result, resp.error) {
+            (_, Some(e)) => anyhow::bail!("node returned error {}: {}", e.code, e.message),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(Value::Null),
+        }
+    };
+    tokio::time::timeout(method.timeout(), exchange)
+        .await
+        .with_context(|| format!("node did not answer '{}' in time", method.as_str()))?
+        .map(Some)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Answer every forwarded request with `{"method": <name>}`, except
+    /// `reconnect`, which fails.
+    fn echo_loop(mut rx: mpsc::Receiver<ControlRequest>) {
+        tokio::spawn(async move {
+            while let Some(req) = rx.recv().await {
+                let answer = match req.method {
+                    ControlMethod::Reconnect => Err("no bootstrap peers".to_string()),
+                    m => Ok(json!({ "method": m.as_str() })),
+                };
+                let _ = req.reply.send(answer);
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn round_trip_through_event_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let (_handle, rx) = spawn_at(path.clone()).unwrap();
+        echo_loop(rx);
+
+        let status = call_at(&path, ControlMethod::Status).await.unwrap();
+        assert_eq!(status, Some(json!({ "method": "status" })));
+        let announce = call_at(&path, ControlMethod::Announce).await.unwrap();
+        assert_eq!(announce, Some(json!({ "method": "announce" })));
+
+        let err = call_at(&path, ControlMethod::Reconnect).await.unwrap_err();
+        assert!(err.to_string().contains("no bootstrap peers"));
+    }
+
+    #[tokio::test]
+    async fn missing_endpoint_is_not_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("absent.sock");
+        assert!(call_at(&path, ControlMethod::Status)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_drop_removes_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let (handle, _rx) = spawn_at(path.clone()).unwrap();
+        assert!(path.exists());
+        drop(handle);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn unknown_method_and_bad_json() {
+        let (tx, _rx) = mpsc::channel(1);
+        let resp = dispatch(r#"{"jsonrpc":"2.0","id":7,"method":"reboot"}"#, &tx).await;
+        assert_eq!(resp.id, json!(7));
+        assert_eq!(resp.error.unwrap().code, METHOD_NOT_FOUND);
+
+        let resp = dispatch("not json", &tx).await;
+        assert_eq!(resp.error.unwrap().code, PARSE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn dropped_reply_reports_unavailable() {
+        let (tx, mut rx) = mpsc::channel::<ControlRequest>(1);
+        tokio::spawn(async move {
+            let req = rx.recv().await.unwrap();
+            drop(req.reply);
+        });
+        let resp = dispatch(r#"{"jsonrpc":"2.0","id":1,"method":"status"}"#, &tx).await;
+        assert_eq!(resp.error.unwrap().code, NODE_UNAVAILABLE);
+    }
+}
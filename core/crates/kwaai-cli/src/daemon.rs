@@ -1,16 +1,17 @@
 //! Daemon process lifecycle management
 //!
-//! Handles PID files, lock files, status files, start/stop/restart,
-//! and process health queries via sysinfo.
+//! Handles PID files, lock files, status and heartbeat files,
+//! start/stop/restart, and process health queries via sysinfo.
 
 use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use sysinfo::{Pid, System};
 use tracing::{debug, info, warn};
 
 use crate::config::{log_dir, run_dir};
+use crate::liveness::{Heartbeat, Liveness};
 
 // ---------------------------------------------------------------------------
 // Status file
@@ -28,6 +29,39 @@ pub struct NodeStatus {
     pub threads: Option<u32>,
     pub started_at: Option<u64>,
     pub health_monitoring: Option<serde_json::Value>,
+    /// DHT announcement progress reported by the running node. `None` until
+    /// the node reaches the announce step (or for status files written by
+    /// older versions).
+    #[serde(default)]
+    pub announce: Option<AnnounceStatus>,
+}
+
+/// Whether the node's records have reached the DHT yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnounceState {
+    /// Initial announcement has not succeeded yet; retrying in the background.
+    Announcing,
+    /// At least one bootstrap peer accepted the block records.
+    Announced,
+}
+
+impl AnnounceState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnounceState::Announcing => "announcing",
+            AnnounceState::Announced => "announced",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnounceStatus {
+    pub state: AnnounceState,
+    /// Announcement attempts made so far (including the initial one).
+    pub attempts: u32,
+    /// Unix timestamp of the first successful announcement.
+    pub announced_at: Option<u64>,
 }
 
 // ---------------------------------------------------------------------------
@@ -38,6 +72,7 @@ pub struct DaemonManager {
     pub pid_file: PathBuf,
     pub lock_file: PathBuf,
     pub status_file: PathBuf,
+    pub heartbeat_file: PathBuf,
 }
 
 impl DaemonManager {
@@ -45,10 +80,17 @@ impl DaemonManager {
         let run = run_dir();
         std::fs::create_dir_all(&run).ok();
         std::fs::create_dir_all(log_dir()).ok();
+        Self::in_run_dir(&run)
+    }
+
+    /// Manager for the node whose run directory is `run`, e.g. another
+    /// profile's; creates nothing.
+    pub fn in_run_dir(run: &Path) -> Self {
         Self {
             pid_file: run.join("kwaainet.pid"),
             lock_file: run.join("kwaainet.lock"),
             status_file: run.join("kwaainet.status"),
+            heartbeat_file: run.join("kwaainet.heartbeat"),
         }
     }
 
@@ -68,6 +110,7 @@ impl DaemonManager {
 
     pub fn remove_pid(&self) {
         let _ = std::fs::remove_file(&self.pid_file);
+        let _ = std::fs::remove_file(&self.heartbeat_file);
     }
 
     /// Signal the running daemon to re-read config and re-announce its block
@@ -169,6 +212,7 @@ impl DaemonManager {
             .unwrap_or_default()
             .as_secs();
         let uptime_secs = now.saturating_sub(started_at);
+        let saved = self.read_status();
 
         NodeStatus {
             running: true,
@@ -180,7 +224,8 @@ impl DaemonManager {
             connections: None,
             threads: None,
             started_at: Some(started_at),
-            health_monitoring: self.read_status().and_then(|s| s.health_monitoring),
+            health_monitoring: saved.as_ref().and_then(|s| s.health_monitoring.clone()),
+            announce: saved.and_then(|s| s.announce),
         }
     }
 
@@ -188,7 +233,6 @@ impl DaemonManager {
     // Status file (JSON)
     // -----------------------------------------------------------------------
 
-    #[allow(dead_code)]
     pub fn write_status(&self, status: &NodeStatus) -> Result<()> {
         let text = serde_json::to_string_pretty(status).context("serializing status")?;
         std::fs::write(&self.status_file, text)
@@ -200,6 +244,63 @@ impl DaemonManager {
         serde_json::from_str(&text).ok()
     }
 
+    /// Record the node's DHT announcement progress in the status file,
+    /// preserving any other fields already written there.
+    pub fn write_announce_status(&self, announce: AnnounceStatus) {
+        let mut status = self.read_status().unwrap_or_default();
+        status.announce = Some(announce);
+        if let Err(e) = self.write_status(&status) {
+            warn!("Could not update announce status: {}", e);
+        }
+    }
+
+    /// Record the node's health report in the status file, preserving any
+    /// other fields already written there.
+    pub fn write_health_status(&self, health: serde_json::Value) {
+        let mut status = self.read_status().unwrap_or_default();
+        status.health_monitoring = Some(health);
+        if let Err(e) = self.write_status(&status) {
+            warn!("Could not update health status: {}", e);
+        }
+    }
+
+    /// Drop the announcement section so a stopped node doesn't report a stale
+    /// "announced" state on the next start.
+    pub fn clear_announce_status(&self) {
+        if let Some(mut status) = self.read_status() {
+            status.announce = None;
+            let _ = self.write_status(&status);
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Heartbeat (see crate::liveness)
+    // -----------------------------------------------------------------------
+
+    pub fn write_heartbeat(&self, beat: &Heartbeat) {
+        let text = serde_json::to_string(beat).unwrap_or_default();
+        if let Err(e) = std::fs::write(&self.heartbeat_file, text) {
+            warn!("Could not write heartbeat: {}", e);
+        }
+    }
+
+    pub fn read_heartbeat(&self) -> Option<Heartbeat> {
+        let text = std::fs::read_to_string(&self.heartbeat_file).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Whether the node in `status` (from [`get_status`](Self::get_status))
+    /// is working, going by its heartbeat.
+    pub fn liveness(&self, status: &NodeStatus) -> Liveness {
+        let pid = status.pid.filter(|_| status.running);
+        Liveness::assess(
+            pid,
+            status.uptime_secs.unwrap_or(0),
+            self.read_heartbeat().as_ref(),
+            crate::liveness::unix_now(),
+        )
+    }
+
     // -----------------------------------------------------------------------
     // Stop
     // -----------------------------------------------------------------------
@@ -216,8 +317,13 @@ impl DaemonManager {
             kill(NixPid::from_raw(pid as i32), Signal::SIGTERM)
                 .with_context(|| format!("SIGTERM to PID {}", pid))?;
 
-            // Wait up to 10 seconds then SIGKILL
-            for _ in 0..20 {
+            // The node drains its shard server and says goodbye to peers
+            // before exiting, so allow the grace period on top of the usual
+            // 10 seconds before resorting to SIGKILL.
+            let grace_secs = crate::config::KwaaiNetConfig::load_or_create()
+                .map(|c| c.shutdown_grace_secs)
+                .unwrap_or(30);
+            for _ in 0..(grace_secs + 10) * 2 {
                 std::thread::sleep(Duration::from_millis(500));
                 let mut sys = System::new();
                 sys.refresh_process(Pid::from_u32(pid));
@@ -307,16 +413,19 @@ impl DaemonManager {
 /// process was terminated by SIGTERM (which bypasses Rust destructors, so
 /// the kwaai-p2p-daemon Drop impl never fires to clean them up).
 /// Without this, a new daemon start fails because p2pd can't bind the port.
+///
+/// Only p2pd processes of the active profile are killed (see [`is_own_p2pd`]).
 pub fn kill_orphaned_p2pd() {
-    use sysinfo::ProcessRefreshKind;
+    use sysinfo::{ProcessRefreshKind, UpdateKind};
 
     let mut sys = System::new();
-    sys.refresh_processes_specifics(ProcessRefreshKind::new());
+    sys.refresh_processes_specifics(ProcessRefreshKind::new().with_cmd(UpdateKind::OnlyIfNotSet));
 
+    let socket = std::env::var("KWAAINET_SOCKET").ok();
     let mut found = false;
     for (pid, process) in sys.processes() {
         let name = process.name();
-        if name == "p2pd" || name == "p2pd.exe" {
+        if (name == "p2pd" || name == "p2pd.exe") && is_own_p2pd(process.cmd(), socket.as_deref()) {
             info!("Killing orphaned p2pd process (PID {})", pid);
             found = true;
             #[cfg(unix)]
@@ -341,6 +450,18 @@ pub fn kill_orphaned_p2pd() {
     }
 }
 
+/// Whether a p2pd with command line `cmd` belongs to this node.
+///
+/// A node with its own control socket (`KWAAINET_SOCKET`, set for named
+/// profiles) owns the p2pd listening on it; otherwise every p2pd except those
+/// of named profiles.
+fn is_own_p2pd(cmd: &[String], socket: Option<&str>) -> bool {
+    match socket {
+        Some(socket) => cmd.iter().any(|arg| arg.contains(socket)),
+        None => !cmd.iter().any(|arg| arg.contains("kwaai-p2pd-")),
+    }
+}
+
 // ---------------------------------------------------------------------------
 // ShardManager — manages the background `shard serve` child process
 // ---------------------------------------------------------------------------
@@ -376,12 +497,51 @@ impl ShardManager {
         run_dir().join("shard.ready")
     }
 
+    /// Arguments the shard child was last spawned with, one per line, so a
+    /// respawn serves the same blocks.
+    pub fn args_file() -> PathBuf {
+        run_dir().join("shard.args")
+    }
+
     /// Returns true only when the shard process is alive AND the model is fully
     /// loaded (i.e. shard_cmd has written the `shard.ready` sentinel file).
     pub fn shard_is_ready() -> bool {
         Self::ready_file().exists() && Self::new().is_running()
     }
 
+    /// LoRA adapters the ready shard has loaded, one per line of `shard.ready`.
+    pub fn served_adapters() -> Vec<String> {
+        if !Self::shard_is_ready() {
+            return Vec::new();
+        }
+        std::fs::read_to_string(Self::ready_file())
+            .map(|text| {
+                text.lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Free attention-cache tokens the ready shard last reported
+    /// (see [`crate::sessions`]).
+    pub fn capacity_file() -> PathBuf {
+        run_dir().join("shard.capacity")
+    }
+
+    /// Cache tokens left on the ready shard; `None` when it is not serving
+    /// or has not reported yet.
+    pub fn cache_tokens_left() -> Option<u64> {
+        if !Self::shard_is_ready() {
+            return None;
+        }
+        std::fs::read_to_string(Self::capacity_file())
+            .ok()
+            .and_then(|t| t.trim().parse().ok())
+    }
+
     pub fn is_running(&self) -> bool {
         match self.read_pid() {
             Some(pid) => {
@@ -395,30 +555,39 @@ impl ShardManager {
 
     /// Stop the shard serve child, if running.
     ///
-    /// Sends SIGTERM and waits up to 5 s for a clean exit, then sends SIGKILL
-    /// so the CUDA context (and VRAM) is always freed before returning.
+    /// Sends SIGTERM, which makes shard serve drain its in-flight sessions,
+    /// and waits up to `shutdown_grace_secs` + 5 s for a clean exit before
+    /// sending SIGKILL so the CUDA context (and VRAM) is always freed before
+    /// returning.
     pub fn stop_process(&self) {
         let Some(pid) = self.read_pid() else { return };
         info!("Stopping shard server PID {}", pid);
 
         #[cfg(unix)]
         {
+            use nix::errno::Errno;
             use nix::sys::signal::{kill, Signal};
             use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
             use nix::unistd::Pid as NixPid;
             let nix_pid = NixPid::from_raw(pid as i32);
+            let grace_secs = crate::config::KwaaiNetConfig::load_or_create()
+                .map(|c| c.shutdown_grace_secs)
+                .unwrap_or(30);
             let _ = kill(nix_pid, Signal::SIGTERM);
-            for _ in 0..10 {
+            for _ in 0..(grace_secs + 5) * 2 {
                 std::thread::sleep(Duration::from_millis(500));
                 // Use waitpid(WNOHANG) rather than sysinfo — sysinfo sees zombies as
                 // still-running, causing the loop to exhaust and SIGKILL a dead process.
-                match waitpid(nix_pid, Some(WaitPidFlag::WNOHANG)) {
-                    Ok(WaitStatus::StillAlive) => {} // still running, keep waiting
-                    _ => {
-                        // Exited cleanly (or ECHILD — already reaped).
-                        self.remove_pid();
-                        return;
-                    }
+                let exited = match waitpid(nix_pid, Some(WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::StillAlive) => false,
+                    // Not our child (e.g. `kwaainet stop` from another shell):
+                    // probe with signal 0 instead.
+                    Err(Errno::ECHILD) => kill(nix_pid, None).is_err(),
+                    _ => true,
+                };
+                if exited {
+                    self.remove_pid();
+                    return;
                 }
             }
             warn!(
@@ -439,12 +608,13 @@ impl ShardManager {
         self.remove_pid();
     }
 
-    /// Spawn `kwaainet shard serve --auto --auto-rebalance` as a detached
-    /// background process, appending output to `shard.log`.
+    /// Spawn `kwaainet shard serve --auto --auto-rebalance` (plus
+    /// `extra_args`) as a detached background process, appending output to
+    /// `shard.log`.
     ///
     /// Kills any already-running shard child first so its CUDA context is freed
     /// before the new process allocates GPU memory.
-    pub fn spawn_shard_child() -> Result<u32> {
+    pub fn spawn_shard_child(extra_args: &[String]) -> Result<u32> {
         let mgr = Self::new();
         if mgr.is_running() {
             info!("Existing shard child running — stopping it before respawn");
@@ -462,8 +632,11 @@ impl ShardManager {
             .open(&log)
             .with_context(|| format!("opening shard log {}", log.display()))?;
 
+        let _ = std::fs::write(Self::args_file(), extra_args.join("\n"));
+
         let mut cmd = std::process::Command::new(&exe);
         cmd.args(["shard", "serve", "--auto-rebalance"]);
+        cmd.args(extra_args);
 
         #[cfg(unix)]
         {
@@ -491,6 +664,20 @@ impl ShardManager {
         std::mem::forget(child);
         Ok(pid)
     }
+
+    /// Spawn the shard child again with the `extra_args` it was last started
+    /// with (e.g. `--blocks N`), as after a p2pd restart.
+    pub fn respawn_shard_child() -> Result<u32> {
+        let args: Vec<String> = std::fs::read_to_string(Self::args_file())
+            .map(|text| {
+                text.lines()
+                    .filter(|l| !l.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self::spawn_shard_child(&args)
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -0,0 +1,181 @@
+//! Hivemind access-token authorization for private swarms.
+//!
+//! Public Petals swarms send unauthenticated DHT requests. A private swarm
+//! instead has an authority that issues each member an access token for its
+//! Ed25519 identity key (see [`kwaai_hivemind_dht::auth`]). To join one, save
+//! the token and point the config at it together with the authority's key:
+//!
+//! ```yaml
+//! dht_access_token: /home/alice/.kwaainet/access_token.json
+//! dht_authority_key: 3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29
+//! dht_require_auth: true   # also reject unauthenticated requests to this node
+//! ```
+//!
+//! The token file is JSON with `username`, `expiration_time`, and hex-encoded
+//! `public_key` and `signature`. Keys and signatures are hex everywhere.
+
+use anyhow::{bail, Context, Result};
+use kwaai_hivemind_dht::auth::AuthRequest;
+use kwaai_hivemind_dht::{AccessToken, TokenAuthorizer};
+use libp2p::identity::{ed25519, Keypair};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+use tracing::{info, warn};
+
+use crate::config::KwaaiNetConfig;
+use crate::identity::NodeIdentity;
+
+/// On-disk form of an [`AccessToken`].
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenFile {
+    username: String,
+    public_key: String,
+    expiration_time: String,
+    signature: String,
+}
+
+impl TokenFile {
+    fn into_token(self) -> Result<AccessToken> {
+        Ok(AccessToken {
+            username: self.username,
+            public_key: hex::decode(&self.public_key).context("token public_key is not hex")?,
+            expiration_time: self.expiration_time,
+            signature: hex::decode(&self.signature).context("token signature is not hex")?,
+        })
+    }
+}
+
+/// Parse a hex-encoded Ed25519 authority public key.
+pub fn parse_authority_key(s: &str) -> Result<ed25519::PublicKey> {
+    let bytes = hex::decode(s.trim()).context("authority key is not hex")?;
+    ed25519::PublicKey::try_from_bytes(&bytes)
+        .map_err(|_| anyhow::anyhow!("authority key must be a 32-byte Ed25519 public key"))
+}
+
+fn load_token(path: &Path) -> Result<AccessToken> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading access token {}", path.display()))?;
+    serde_json::from_str::<TokenFile>(&text)
+        .with_context(|| format!("parsing access token {}", path.display()))?
+        .into_token()
+}
+
+/// Build the authorizer described by `config` for the node's `keypair`.
+/// `Ok(None)` when no access token is configured.
+pub fn load(config: &KwaaiNetConfig, keypair: &Keypair) -> Result<Option<Arc<TokenAuthorizer>>> {
+    let Some(token_path) = &config.dht_access_token else {
+        if config.dht_require_auth {
+            bail!("dht_require_auth is set but no dht_access_token is configured");
+        }
+        return Ok(None);
+    };
+    let Some(authority) = &config.dht_authority_key else {
+        bail!("dht_access_token is set but dht_authority_key is missing");
+    };
+    let authority = parse_authority_key(authority)?;
+    let token = load_token(token_path)?;
+    let keypair = keypair
+        .clone()
+        .try_into_ed25519()
+        .map_err(|_| anyhow::anyhow!("DHT authorization needs an Ed25519 identity key"))?;
+    let authorizer = TokenAuthorizer::new(keypair, token, authority)
+        .map_err(|e| anyhow::anyhow!("access token rejected: {e}"))?;
+    Ok(Some(Arc::new(authorizer)))
+}
+
+static AUTHORIZER: OnceLock<Option<Arc<TokenAuthorizer>>> = OnceLock::new();
+
+/// Load and install the process-wide authorizer. Called once at node
+/// startup so a broken token fails the start instead of every request.
+pub fn init(config: &KwaaiNetConfig, keypair: &Keypair) -> Result<Option<Arc<TokenAuthorizer>>> {
+    let authorizer = load(config, keypair)?;
+    if let Some(a) = &authorizer {
+        info!(
+            "DHT authorization enabled as '{}' (token expires {}, require_auth={})",
+            a.local_token().username,
+            a.local_token().expiration_time,
+            config.dht_require_auth
+        );
+    }
+    let _ = AUTHORIZER.set(authorizer.clone());
+    Ok(authorizer)
+}
+
+/// The process-wide authorizer, loaded from the config on first use when
+/// [`init`] was not called (e.g. by `kwaainet shard` commands).
+pub fn authorizer() -> Option<Arc<TokenAuthorizer>> {
+    AUTHORIZER
+        .get_or_init(|| {
+            let config = KwaaiNetConfig::load_or_create().ok()?;
+            config.dht_access_token.as_ref()?;
+            let identity = match &config.identity_key {
+                Some(path) => NodeIdentity::load_from(path),
+                None => NodeIdentity::load_or_create(),
+            };
+            match identity.and_then(|id| load(&config, &id.keypair)) {
+                Ok(a) => a,
+                Err(e) => {
+                    warn!("DHT authorization disabled: {e:#}");
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+/// Sign `request` with this node's access token, if one is configured.
+pub fn sign_request<R: AuthRequest>(request: &mut R) {
+    if let Some(a) = authorizer() {
+        a.sign_request(request, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kwaai_hivemind_dht::auth::issue_access_token;
+    use std::time::Duration;
+
+    #[test]
+    fn loads_token_file_for_identity_key() {
+        let dir = std::env::temp_dir().join(format!("kwaainet-dht-auth-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let authority = ed25519::Keypair::generate();
+        let node = Keypair::generate_ed25519();
+        let node_ed = node.clone().try_into_ed25519().unwrap();
+        let token = issue_access_token(
+            &authority,
+            "alice",
+            &node_ed.public(),
+            Duration::from_secs(3600),
+        );
+        let file = TokenFile {
+            username: token.username.clone(),
+            public_key: hex::encode(&token.public_key),
+            expiration_time: token.expiration_time.clone(),
+            signature: hex::encode(&token.signature),
+        };
+        let path = dir.join("token.json");
+        std::fs::write(&path, serde_json::to_string(&file).unwrap()).unwrap();
+
+        let mut config = KwaaiNetConfig {
+            dht_access_token: Some(path),
+            dht_authority_key: Some(hex::encode(authority.public().to_bytes())),
+            ..Default::default()
+        };
+        let authorizer = load(&config, &node).unwrap().expect("authorizer");
+        assert_eq!(authorizer.local_token().username, "alice");
+
+        // A token for someone else's key is refused.
+        assert!(load(&config, &Keypair::generate_ed25519()).is_err());
+
+        config.dht_access_token = None;
+        assert!(load(&config, &node).unwrap().is_none());
+        config.dht_require_auth = true;
+        assert!(load(&config, &node).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,114 @@
+//! The DHT behind `dht_backend` in config.yaml, as a [`DhtBackend`].
+//!
+//! Subsystems that only need records and provider lists (shard-transfer
+//! provider announcements, for one) go through [`open`] instead of talking
+//! to p2pd directly, so the same code runs on every backend:
+//!
+//! - `hivemind_daemon` (the default): Hivemind DHT RPCs through this node's
+//!   p2pd, via [`P2pdDhtRpc`]
+//! - `hivemind_native` / `kademlia`: an in-process swarm opened by
+//!   [`kwaai_p2p::open_backend`]
+//!
+//! Every backend runs under the node identity.
+
+use anyhow::{Context, Result};
+use kwaai_hivemind_dht::{
+    client::DhtRpc,
+    protocol::{FindRequest, FindResponse, StoreRequest, StoreResponse},
+    AuthorizedRpc,
+};
+use kwaai_p2p::{DhtBackend, DhtBackendKind, HivemindBackend};
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::PeerId;
+use prost::Message as _;
+
+use crate::config::KwaaiNetConfig;
+use crate::identity::NodeIdentity;
+
+/// [`DhtRpc`] over p2pd unary handlers, subject to the bandwidth caps.
+#[derive(Clone)]
+pub struct P2pdDhtRpc {
+    client: P2PClient,
+}
+
+impl P2pdDhtRpc {
+    pub fn new(client: P2PClient) -> Self {
+        Self { client }
+    }
+
+    async fn call(
+        &self,
+        peer: PeerId,
+        proto: &str,
+        bytes: &[u8],
+    ) -> kwaai_hivemind_dht::Result<Vec<u8>> {
+        let limiter = crate::bandwidth::limiter();
+        limiter.upload(bytes.len()).await;
+        let resp = self
+            .client
+            .call_unary_handler(&peer.to_bytes(), proto, bytes)
+            .await
+            .map_err(|e| kwaai_hivemind_dht::Error::Network(e.to_string()))?;
+        limiter.download(resp.len()).await;
+        Ok(resp)
+    }
+}
+
+#[async_trait::async_trait]
+impl DhtRpc for P2pdDhtRpc {
+    async fn find(
+        &mut self,
+        peer: PeerId,
+        request: FindRequest,
+    ) -> kwaai_hivemind_dht::Result<FindResponse> {
+        let resp = self
+            .call(peer, "DHTProtocol.rpc_find", &request.encode_to_vec())
+            .await?;
+        Ok(FindResponse::decode(&resp[..])?)
+    }
+
+    async fn store(
+        &mut self,
+        peer: PeerId,
+        request: StoreRequest,
+    ) -> kwaai_hivemind_dht::Result<StoreResponse> {
+        let resp = self
+            .call(peer, "DHTProtocol.rpc_store", &request.encode_to_vec())
+            .await?;
+        Ok(StoreResponse::decode(&resp[..])?)
+    }
+}
+
+/// This node's identity: `identity_key` when set, else the default key.
+pub fn node_identity(config: &KwaaiNetConfig) -> Result<NodeIdentity> {
+    match &config.identity_key {
+        Some(path) => NodeIdentity::load_from(path),
+        None => NodeIdentity::load_or_create(),
+    }
+}
+
+/// Open the DHT backend `config` selects, as this node.
+pub async fn open(config: &KwaaiNetConfig) -> Result<Box<dyn DhtBackend>> {
+    let identity = node_identity(config)?;
+    let net_cfg = config.network_config();
+    match net_cfg.dht_backend {
+        DhtBackendKind::HivemindDaemon => {
+            let client = P2PClient::connect(&crate::shard_cmd::daemon_socket())
+                .await
+                .context("connecting to p2pd for the DHT")?;
+            let rpc = P2pdDhtRpc::new(client);
+            let backend: Box<dyn DhtBackend> = match crate::dht_auth::authorizer() {
+                Some(authorizer) => Box::new(HivemindBackend::new(
+                    AuthorizedRpc::new(rpc, authorizer),
+                    identity.peer_id,
+                    &net_cfg,
+                )?),
+                None => Box::new(HivemindBackend::new(rpc, identity.peer_id, &net_cfg)?),
+            };
+            Ok(backend)
+        }
+        _ => kwaai_p2p::open_backend(&net_cfg, identity.keypair)
+            .await
+            .with_context(|| format!("opening the {:?} DHT backend", net_cfg.dht_backend)),
+    }
+}
@@ -0,0 +1,205 @@
+//! Runtime experiment flags for previewing new subsystems.
+//!
+//! Large features land behind a flag first so contributor nodes can opt into
+//! a preview without a special build, and so a misbehaving preview can be
+//! switched off again with a config edit instead of a downgrade. Flags live
+//! in the `experiments` map of `config.yaml`:
+//!
+//! ```yaml
+//! experiments:
+//!   quic: true
+//! ```
+//!
+//! `kwaainet config set experiments.quic true` writes the same entry. For a
+//! single run, `KWAAINET_EXPERIMENTS=quic,-other` turns flags on (or off, with
+//! a leading `-`) on top of the config.
+//!
+//! The set is resolved once per process ([`active`]); changes take effect on
+//! the next restart. Enabled flags are reported by `kwaainet status`, in the
+//! control-socket snapshot and in the node's DHT announcement, so the network
+//! map can label per-node metrics with the previews a node is running.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::OnceLock,
+};
+use tracing::{info, warn};
+
+/// Environment variable with per-process flag overrides.
+pub const EXPERIMENTS_ENV: &str = "KWAAINET_EXPERIMENTS";
+
+/// A subsystem that can be switched on at runtime. All default to off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Experiment {
+    /// Listen for libp2p connections over QUIC (UDP) on the node port in
+    /// addition to TCP.
+    Quic,
+}
+
+impl Experiment {
+    pub const ALL: &'static [Experiment] = &[Experiment::Quic];
+
+    /// Name used in config keys, the environment override and labels.
+    pub fn key(self) -> &'static str {
+        match self {
+            Experiment::Quic => "quic",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Experiment::Quic => "also listen for peers over QUIC (UDP) on the node port",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.key() == key)
+    }
+
+    /// Comma-separated list of every known flag, for error messages.
+    pub fn known_keys() -> String {
+        Self::ALL
+            .iter()
+            .map(|e| e.key())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for Experiment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.key())
+    }
+}
+
+/// The resolved set of enabled experiments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Experiments {
+    enabled: BTreeSet<Experiment>,
+    /// Flag names from config or the environment that this build does not
+    /// know. Kept so they can be reported instead of silently dropped.
+    unknown: Vec<String>,
+}
+
+impl Experiments {
+    /// Resolve flags from the config map, then apply `env` overrides
+    /// (`"a,b,-c"`).
+    pub fn resolve(config: &BTreeMap<String, bool>, env: Option<&str>) -> Self {
+        let mut out = Self::default();
+        let mut set = |name: &str, on: bool| match Experiment::from_key(name) {
+            Some(e) if on => {
+                out.enabled.insert(e);
+            }
+            Some(e) => {
+                out.enabled.remove(&e);
+            }
+            None => out.unknown.push(name.to_string()),
+        };
+        for (name, on) in config {
+            set(name, *on);
+        }
+        for item in env.unwrap_or_default().split(',') {
+            let item = item.trim();
+            match item.strip_prefix('-') {
+                Some(name) => set(name, false),
+                None if !item.is_empty() => set(item, true),
+                None => {}
+            }
+        }
+        out
+    }
+
+    pub fn is_enabled(&self, experiment: Experiment) -> bool {
+        self.enabled.contains(&experiment)
+    }
+
+    /// Enabled flag names, in a stable order.
+    pub fn enabled_keys(&self) -> Vec<&'static str> {
+        self.enabled.iter().map(|e| e.key()).collect()
+    }
+
+    pub fn unknown(&self) -> &[String] {
+        &self.unknown
+    }
+
+    /// Single label value for metrics and logs: `"quic"`, `"a,b"`, or
+    /// `"none"`.
+    pub fn label(&self) -> String {
+        if self.enabled.is_empty() {
+            "none".to_string()
+        } else {
+            self.enabled_keys().join(",")
+        }
+    }
+}
+
+static ACTIVE: OnceLock<Experiments> = OnceLock::new();
+
+/// Experiments in effect for this process, resolved from `config.yaml` and
+/// [`EXPERIMENTS_ENV`] on first use.
+pub fn active() -> &'static Experiments {
+    ACTIVE.get_or_init(|| {
+        let config = crate::config::KwaaiNetConfig::load_or_create()
+            .map(|c| c.experiments)
+            .unwrap_or_default();
+        let env = std::env::var(EXPERIMENTS_ENV).ok();
+        let resolved = Experiments::resolve(&config, env.as_deref());
+        for name in resolved.unknown() {
+            warn!(
+                "Ignoring unknown experiment '{name}' (known: {})",
+                Experiment::known_keys()
+            );
+        }
+        if !resolved.enabled.is_empty() {
+            info!("Experiments enabled: {}", resolved.label());
+        }
+        resolved
+    })
+}
+
+/// Whether `experiment` is switched on for this process.
+pub fn enabled(experiment: Experiment) -> bool {
+    active().is_enabled(experiment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, bool)]) -> BTreeMap<String, bool> {
+        entries.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn everything_is_off_by_default() {
+        let e = Experiments::resolve(&BTreeMap::new(), None);
+        assert!(!e.is_enabled(Experiment::Quic));
+        assert_eq!(e.label(), "none");
+        assert!(e.unknown().is_empty());
+    }
+
+    #[test]
+    fn env_overrides_config_both_ways() {
+        let on = Experiments::resolve(&map(&[("quic", false)]), Some("quic"));
+        assert!(on.is_enabled(Experiment::Quic));
+        assert_eq!(on.label(), "quic");
+
+        let off = Experiments::resolve(&map(&[("quic", true)]), Some(" -quic ,"));
+        assert!(!off.is_enabled(Experiment::Quic));
+    }
+
+    #[test]
+    fn unknown_flags_are_reported_not_enabled() {
+        let e = Experiments::resolve(&map(&[("warp_drive", true)]), Some("teleport"));
+        assert_eq!(e.enabled_keys(), Vec::<&str>::new());
+        assert_eq!(e.unknown(), ["warp_drive", "teleport"]);
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for e in Experiment::ALL {
+            assert_eq!(Experiment::from_key(e.key()), Some(*e));
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Mixture-of-experts offload between nodes.
+//!
+//! A node running a Mixtral-style GGUF model can keep only some experts of
+//! each MoE layer in memory (`moe.local_experts` in config.yaml) and run the
+//! others on peers (`moe.peers`). The runner hands those calls to
+//! [`kwaai_distributed::PeerExperts`], which reaches the peers through
+//! [`P2pExpertClient`] over [`EXPERT_PROTO`]. A node with `moe.serve` set
+//! answers those calls from the experts it loads with
+//! [`make_expert_handler`].
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, Tensor};
+use kwaai_distributed::{
+    DistributedError, DistributedResult, ExpertClient, ExpertId, ExpertRegistry, GgufExpert,
+    PeerExperts,
+};
+use kwaai_inference::moe::{ExpertFfn, ExpertOffload};
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{info, warn};
+
+use crate::config::MoeConfig;
+
+/// libp2p protocol string for remote expert calls.
+pub const EXPERT_PROTO: &str = "/kwaai/expert/1.0.0";
+
+/// Hidden states on the wire, as f32.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct WireTensor {
+    shape: Vec<usize>,
+    data: Vec<f32>,
+}
+
+impl WireTensor {
+    fn from_tensor(t: &Tensor) -> candle_core::Result<Self> {
+        Ok(Self {
+            shape: t.dims().to_vec(),
+            data: t.to_dtype(DType::F32)?.flatten_all()?.to_vec1()?,
+        })
+    }
+
+    fn to_tensor(&self, device: &Device) -> candle_core::Result<Tensor> {
+        Tensor::from_slice(&self.data, self.shape.as_slice(), device)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ExpertRequest {
+    layer: usize,
+    expert: usize,
+    input: WireTensor,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum ExpertReply {
+    Output(WireTensor),
+    Error(String),
+}
+
+// ── Client ────────────────────────────────────────────────────────────────────
+
+/// Runs experts on peers through the local p2pd.
+pub struct P2pExpertClient;
+
+#[async_trait]
+impl ExpertClient for P2pExpertClient {
+    async fn forward(
+        &self,
+        peer_id: &str,
+        expert: ExpertId,
+        input: &Tensor,
+    ) -> DistributedResult<Tensor> {
+        let peer: PeerId = peer_id
+            .parse()
+            .map_err(|e| DistributedError::NetworkError(format!("bad peer id {peer_id}: {e}")))?;
+        let (layer, index) = expert.moe_parts();
+        let request = ExpertRequest {
+            layer,
+            expert: index,
+            input: WireTensor::from_tensor(input)?,
+        };
+        let payload = rmp_serde::to_vec_named(&request)
+            .map_err(|e| DistributedError::Internal(format!("encoding expert request: {e}")))?;
+
+        let client = P2PClient::connect(&crate::shard_cmd::daemon_socket())
+            .await
+            .map_err(|e| DistributedError::NetworkError(format!("p2pd: {e}")))?;
+        let reply = client
+            .call_unary_handler(&peer.to_bytes(), EXPERT_PROTO, &payload)
+            .await
+            .map_err(|e| DistributedError::RemoteCallFailed(e.to_string()))?;
+        match rmp_serde::from_slice(&reply) {
+            Ok(ExpertReply::Output(out)) => {
+                Ok(out.to_tensor(input.device())?.to_dtype(input.dtype())?)
+            }
+            Ok(ExpertReply::Error(e)) => Err(DistributedError::RemoteCallFailed(e)),
+            Err(e) => Err(DistributedError::RemoteCallFailed(format!(
+                "invalid expert reply: {e}"
+            ))),
+        }
+    }
+}
+
+// ── Offload ───────────────────────────────────────────────────────────────────
+
+fn read_gguf_header(path: &Path) -> Result<(std::fs::File, gguf_file::Content)> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let ct = gguf_file::Content::read(&mut file)
+        .with_context(|| format!("reading GGUF header of {}", path.display()))?;
+    Ok((file, ct))
+}
+
+fn meta_usize(ct: &gguf_file::Content, key: &str) -> Option<usize> {
+    match ct.metadata.get(key)? {
+        gguf_file::Value::U32(v) => Some(*v as usize),
+        gguf_file::Value::U64(v) => Some(*v as usize),
+        _ => None,
+    }
+}
+
+/// `(block_count, hidden_dim)` from GGUF metadata.
+fn model_shape(ct: &gguf_file::Content) -> Result<(usize, usize)> {
+    let arch = match ct.metadata.get("general.architecture") {
+        Some(gguf_file::Value::String(a)) => a.clone(),
+        _ => bail!("GGUF metadata has no general.architecture"),
+    };
+    let blocks = meta_usize(ct, &format!("{arch}.block_count"))
+        .with_context(|| format!("GGUF metadata is missing {arch}.block_count"))?;
+    let hidden = meta_usize(ct, &format!("{arch}.embedding_length"))
+        .with_context(|| format!("GGUF metadata is missing {arch}.embedding_length"))?;
+    Ok((blocks, hidden))
+}
+
+/// Registry of the remote experts in `cfg`, for a model with `blocks` layers.
+fn remote_registry(cfg: &MoeConfig, blocks: usize) -> Result<ExpertRegistry> {
+    let mut registry = ExpertRegistry::new();
+    for (peer, experts) in &cfg.peers {
+        peer.parse::<PeerId>()
+            .with_context(|| format!("moe.peers: '{peer}' is not a PeerId"))?;
+        for layer in 0..blocks {
+            for &expert in experts {
+                registry.register_remote(ExpertId::moe(layer, expert), peer.clone());
+            }
+        }
+    }
+    Ok(registry)
+}
+
+/// The expert offload `cfg` asks for on the GGUF model at `model`, or `None`
+/// when `moe.local_experts` is empty and every expert is loaded here.
+///
+/// Must be set on the engine before the model is loaded.
+pub fn offload_for_model(cfg: &MoeConfig, model: &Path) -> Result<Option<ExpertOffload>> {
+    if cfg.local_experts.is_empty() {
+        return Ok(None);
+    }
+    let (_, ct) = read_gguf_header(model)?;
+    let (blocks, _) = model_shape(&ct)?;
+    let registry = remote_registry(cfg, blocks)?;
+    let peers = PeerExperts::new(registry, Arc::new(P2pExpertClient), cfg.timeout_ms)
+        .context("starting the expert offload thread")?;
+    info!(
+        local = ?cfg.local_experts,
+        peers = cfg.peers.len(),
+        "MoE expert offload enabled"
+    );
+    Ok(Some(
+        peers.into_offload(cfg.local_experts.iter().copied().collect()),
+    ))
+}
+
+// ── Serving ───────────────────────────────────────────────────────────────────
+
+/// Load experts `serve` of every MoE layer of the GGUF model at `model`.
+/// Layers without experts (dense layers of mixed models) are skipped.
+fn load_served_experts(model: &Path, serve: &BTreeSet<usize>) -> Result<ExpertRegistry> {
+    let (mut file, ct) = read_gguf_header(model)?;
+    let (blocks, hidden) = model_shape(&ct)?;
+    let mut registry = ExpertRegistry::new();
+    for layer in 0..blocks {
+        if !ct
+            .tensor_infos
+            .contains_key(&format!("blk.{layer}.ffn_gate_inp.weight"))
+        {
+            continue;
+        }
+        for &expert in serve {
+            let ffn = ExpertFfn::load(&ct, &mut file, layer, expert, &Device::Cpu)
+                .with_context(|| format!("loading expert {expert} of layer {layer}"))?;
+            registry.register_local(Box::new(GgufExpert::new(layer, expert, ffn, hidden)));
+        }
+    }
+    Ok(registry)
+}
+
+fn run_request(experts: &ExpertRegistry, request: &ExpertRequest) -> Result<Tensor, String> {
+    let id = ExpertId::moe(request.layer, request.expert);
+    let expert = experts.get_local(id).ok_or_else(|| {
+        format!(
+            "expert {} of layer {} is not served here",
+            request.expert, request.layer
+        )
+    })?;
+    let input = request
+        .input
+        .to_tensor(&Device::Cpu)
+        .map_err(|e| format!("invalid input: {e}"))?;
+    if input.dims().last() != Some(&expert.hidden_dim()) {
+        return Err(format!(
+            "input shape {:?} does not match hidden size {}",
+            input.dims(),
+            expert.hidden_dim()
+        ));
+    }
+    // GgufExpert computes synchronously; this runs on a blocking thread.
+    futures::executor::block_on(expert.forward(&input)).map_err(|e| e.to_string())
+}
+
+/// Build a unary handler for [`EXPERT_PROTO`] that runs the requested
+/// expert from `experts`. Replies with an [`ExpertReply`].
+#[allow(clippy::type_complexity)]
+pub fn make_expert_handler(
+    experts: Arc<ExpertRegistry>,
+) -> impl Fn(
+    Vec<u8>,
+) -> std::pin::Pin<
+    Box<dyn std::future::Future<Output = kwaai_p2p_daemon::error::Result<Vec<u8>>> + Send>,
+> + Send
+       + Sync
+       + 'static {
+    move |data: Vec<u8>| {
+        let experts = experts.clone();
+        Box::pin(async move {
+            let request: ExpertRequest = rmp_serde::from_slice(&data).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("invalid expert request: {e}"))
+            })?;
+            let reply = tokio::task::spawn_blocking(move || run_request(&experts, &request))
+                .await
+                .unwrap_or_else(|e| Err(format!("expert task failed: {e}")));
+            let reply =
+                match reply.and_then(|t| WireTensor::from_tensor(&t).map_err(|e| e.to_string())) {
+                    Ok(out) => ExpertReply::Output(out),
+                    Err(e) => {
+                        warn!("Expert call failed: {e}");
+                        ExpertReply::Error(e)
+                    }
+                };
+            rmp_serde::to_vec_named(&reply).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("encoding expert reply: {e}"))
+            })
+        })
+    }
+}
+
+/// Load the experts `cfg.serve` asks for from the node's model, or `None`
+/// when this node serves none.
+pub async fn served_experts(
+    cfg: &MoeConfig,
+    model: PathBuf,
+) -> Result<Option<Arc<ExpertRegistry>>> {
+    if cfg.serve.is_empty() {
+        return Ok(None);
+    }
+    let serve: BTreeSet<usize> = cfg.serve.iter().copied().collect();
+    let registry =
+        tokio::task::spawn_blocking(move || load_served_experts(&model, &serve)).await??;
+    Ok(Some(Arc::new(registry)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use kwaai_distributed::Expert;
+
+    /// Doubles its input.
+    struct Double(ExpertId);
+
+    #[async_trait]
+    impl Expert for Double {
+        fn id(&self) -> ExpertId {
+            self.0
+        }
+        async fn forward(&self, input: &Tensor) -> DistributedResult<Tensor> {
+            Ok((input * 2.0)?)
+        }
+        fn hidden_dim(&self) -> usize {
+            4
+        }
+        fn is_ready(&self) -> bool {
+            true
+        }
+    }
+
+    fn request(layer: usize, expert: usize, x: &Tensor) -> Vec<u8> {
+        rmp_serde::to_vec_named(&ExpertRequest {
+            layer,
+            expert,
+            input: WireTensor::from_tensor(x).unwrap(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn handler_runs_served_experts_only() {
+        let mut registry = ExpertRegistry::new();
+        registry.register_local(Box::new(Double(ExpertId::moe(1, 2))));
+        let handler = make_expert_handler(Arc::new(registry));
+        let x = Tensor::ones((2, 4), DType::F32, &Device::Cpu).unwrap();
+
+        let reply = handler(request(1, 2, &x)).await.unwrap();
+        let ExpertReply::Output(out) = rmp_serde::from_slice(&reply).unwrap() else {
+            panic!("expected an output");
+        };
+        assert_eq!(out.shape, vec![2, 4]);
+        assert_eq!(out.data, vec![2.0; 8]);
+
+        let reply = handler(request(0, 2, &x)).await.unwrap();
+        assert!(matches!(
+            rmp_serde::from_slice(&reply).unwrap(),
+            ExpertReply::Error(_)
+        ));
+    }
+
+    #[test]
+    fn remote_registry_covers_every_layer_and_rejects_bad_peers() {
+        let peer = PeerId::random().to_base58();
+        let mut cfg = MoeConfig::default();
+        cfg.peers.insert(peer.clone(), vec![5, 6]);
+        let registry = remote_registry(&cfg, 3).unwrap();
+        assert_eq!(registry.get_remote_peer(ExpertId::moe(2, 6)), Some(&peer));
+        assert_eq!(registry.get_remote_peer(ExpertId::moe(3, 6)), None);
+
+        cfg.peers.insert("not-a-peer".to_string(), vec![1]);
+        assert!(remote_registry(&cfg, 3).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Peer geolocation for `kwaainet peers` and the network state API.
+//!
+//! Resolves the public address a peer is reached at to its country and
+//! autonomous system. `geoip.database` points at a local iptoasn.com table
+//! ([`GeoIpDb`]), consulted first and without any network traffic;
+//! `geoip.service_url` names an HTTP service for addresses the table does not
+//! cover. With neither set, enrichment is off and peers carry no IP info.
+//!
+//! The service's JSON is read leniently so the common free services work
+//! unchanged: ipinfo.io (`country`, `org: "AS15169 Google LLC"`), ip-api.com
+//! (`countryCode`, `as`) and ipapi.co (`country_code`, `asn`, `org`).
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use kwaai_p2p::geo::public_ip;
+use kwaai_p2p::{GeoIpDb, PeerIpInfo};
+use libp2p::Multiaddr;
+use tracing::{debug, info, warn};
+
+use crate::config::{GeoIpConfig, KwaaiNetConfig};
+
+/// Per-lookup timeout for the HTTP service.
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(3);
+
+static RESOLVER: OnceLock<Option<GeoIp>> = OnceLock::new();
+
+/// Resolves addresses through the configured database and service, caching
+/// every answer for the life of the process.
+pub struct GeoIp {
+    db: Option<GeoIpDb>,
+    service_url: Option<String>,
+    http: reqwest::Client,
+    cache: Mutex<HashMap<IpAddr, PeerIpInfo>>,
+}
+
+impl GeoIp {
+    /// Resolver for `config`, or `None` when no source is configured.
+    pub fn new(config: &GeoIpConfig) -> Option<Self> {
+        if !config.is_enabled() {
+            return None;
+        }
+        let db = config
+            .database
+            .as_ref()
+            .and_then(|path| match GeoIpDb::open(path) {
+                Ok(db) => {
+                    info!("GeoIP: {} ranges from {}", db.len(), path.display());
+                    Some(db)
+                }
+                Err(e) => {
+                    warn!("GeoIP database {} unreadable: {e}", path.display());
+                    None
+                }
+            });
+        let http = reqwest::Client::builder()
+            .timeout(SERVICE_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Some(Self {
+            db,
+            service_url: config.service_url.clone(),
+            http,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Country and ASN of `ip`; only the address when no source knows it.
+    pub async fn lookup(&self, ip: IpAddr) -> PeerIpInfo {
+        if let Some(hit) = self.cache.lock().unwrap().get(&ip) {
+            return hit.clone();
+        }
+        let mut info = self.db.as_ref().and_then(|db| db.lookup(ip));
+        if info.is_none() {
+            if let Some(url) = &self.service_url {
+                info = self.query_service(url, ip).await;
+            }
+        }
+        let info = info.unwrap_or_else(|| PeerIpInfo::unresolved(ip));
+        self.cache.lock().unwrap().insert(ip, info.clone());
+        info
+    }
+
+    /// Info for the first public, non-relayed address in `addrs`.
+    pub async fn lookup_addrs(&self, addrs: &[Multiaddr]) -> Option<PeerIpInfo> {
+        Some(self.lookup(public_ip(addrs)?).await)
+    }
+
+    async fn query_service(&self, url: &str, ip: IpAddr) -> Option<PeerIpInfo> {
+        let url = url.replace("{ip}", &ip.to_string());
+        let response = match self.http.get(&url).send().await {
+            Ok(r) if r.status().is_success() => r,
+            Ok(r) => {
+                debug!("GeoIP service answered {} for {ip}", r.status());
+                return None;
+            }
+            Err(e) => {
+                debug!("GeoIP service unreachable for {ip}: {e}");
+                return None;
+            }
+        };
+        let body: serde_json::Value = response.json().await.ok()?;
+        let info = parse_service_json(ip, &body);
+        info.is_resolved().then_some(info)
+    }
+}
+
+/// Read the country and AS fields of a lookup service's JSON answer.
+fn parse_service_json(ip: IpAddr, body: &serde_json::Value) -> PeerIpInfo {
+    let field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| body[*k].as_str())
+            .filter(|s| !s.is_empty())
+    };
+    // "AS15169 Google LLC" (ipinfo `org`, ip-api `as`) or bare "AS15169".
+    let (asn, as_name) = match field(&["asn", "as", "org"]) {
+        Some(s) => {
+            let (number, name) = s.split_once(' ').unwrap_or((s, ""));
+            let asn = number.trim_start_matches("AS").parse().ok();
+            let name = if name.is_empty() {
+                field(&["org", "isp"]).filter(|o| !o.starts_with("AS"))
+            } else {
+                Some(name)
+            };
+            (asn.or_else(|| body["asn"].as_u64().map(|n| n as u32)), name)
+        }
+        None => (body["asn"].as_u64().map(|n| n as u32), field(&["isp"])),
+    };
+    PeerIpInfo {
+        ip: ip.to_string(),
+        country: field(&["country_code", "countryCode", "country"]).map(String::from),
+        asn,
+        as_name: as_name.map(String::from),
+    }
+}
+
+/// The process-wide resolver, built from config.yaml on first use; `None`
+/// when enrichment is off.
+pub fn resolver() -> Option<&'static GeoIp> {
+    RESOLVER
+        .get_or_init(|| {
+            let config = KwaaiNetConfig::load_or_create()
+                .map(|c| c.geoip)
+                .unwrap_or_default();
+            GeoIp::new(&config)
+        })
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_common_service_formats() {
+        let ip: IpAddr = "8.8.8.8".parse().unwrap();
+        let expected = |name: &str| PeerIpInfo {
+            ip: "8.8.8.8".into(),
+            country: Some("US".into()),
+            asn: Some(15169),
+            as_name: Some(name.into()),
+        };
+
+        let ipinfo = json!({ "ip": "8.8.8.8", "country": "US", "org": "AS15169 Google LLC" });
+        assert_eq!(parse_service_json(ip, &ipinfo), expected("Google LLC"));
+
+        let ip_api = json!({ "countryCode": "US", "country": "United States",
+                             "as": "AS15169 Google LLC", "isp": "Google LLC" });
+        assert_eq!(parse_service_json(ip, &ip_api), expected("Google LLC"));
+
+        let ipapi_co = json!({ "country_code": "US", "asn": "AS15169", "org": "GOOGLE" });
+        assert_eq!(parse_service_json(ip, &ipapi_co), expected("GOOGLE"));
+
+        let empty = parse_service_json(ip, &json!({}));
+        assert!(!empty.is_resolved());
+    }
+}
@@ -145,6 +145,9 @@ fn build_inference_state(cfg: &KwaaiNetConfig) -> Result<InferenceState> {
     } else {
         let blob = crate::ollama::resolve_model_blob(&model_id)
             .with_context(|| format!("resolving Ollama blob for {model_id}"))?;
+        let offload = crate::experts::offload_for_model(&cfg.moe, &blob)
+            .context("setting up MoE expert offload")?;
+        engine.set_expert_offload(offload);
         let handle = engine
             .load_model(&blob, ModelFormat::Gguf)
             .with_context(|| format!("loading GGUF blob at {}", blob.display()))?;
@@ -419,12 +422,7 @@ fn classify_generate_error(msg: &str) -> ErrorCode {
 /// serve its assigned block range. Reads the `~/.kwaainet/run/shard.ready`
 /// touchfile the shard server writes when it's bound + warm.
 fn shard_ready_path_exists() -> bool {
-    let p = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join(".kwaainet")
-        .join("run")
-        .join("shard.ready");
-    p.exists()
+    crate::daemon::ShardManager::ready_file().exists()
 }
 
 /// Per-id counter used to give worker tasks a unique log span. Reused
@@ -555,6 +553,12 @@ async fn spawn_session_shard_run(
         // tag-space so they can be added without a breaking change.
         max_tokens: None,
         circuit_id: None,
+        // The budget starts when the frame arrives, so chain discovery
+        // counts against it too.
+        deadline: req
+            .timeout_ms
+            .map(|ms| Instant::now() + std::time::Duration::from_millis(ms.into())),
+        stop: Vec::new(),
     };
 
     let cancels_for_cleanup = cancels.clone();
@@ -593,17 +597,20 @@ async fn spawn_session_shard_run(
                                 break;
                             }
                         }
-                        Some(crate::shard_cmd::ShardRunEvent::Done) => {
+                        Some(crate::shard_cmd::ShardRunEvent::Done { truncated_by_deadline }) => {
                             // Emit a final done=true token so multi-token
                             // clients can flush their UI state, then the
-                            // structural Done terminator for the op id.
+                            // structural Done terminator for the op id. A
+                            // deadline cut reports "length", as the OpenAI
+                            // API does.
+                            let finish_reason = if truncated_by_deadline { "length" } else { "stop" };
                             let _ = out_tx
                                 .send(Ok(ServerFrame {
                                     id,
                                     body: Some(server_frame::Body::Token(ChatToken {
                                         text: String::new(),
                                         done: true,
-                                        finish_reason: Some("stop".to_string()),
+                                        finish_reason: Some(finish_reason.to_string()),
                                     })),
                                 }))
                                 .await;
@@ -808,11 +815,10 @@ fn stream_via_llama_cpp(
 // Bind / serve
 // ---------------------------------------------------------------------------
 
-/// Resolve `~/.kwaainet/run/kwaai.sock`.
+/// Resolve `~/.kwaainet/run/kwaai.sock` (in the active profile's directory).
 #[cfg(unix)]
 fn unix_socket_path() -> PathBuf {
-    let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    home.join(".kwaainet").join(UNIX_SOCKET_RELPATH)
+    crate::config::kwaainet_dir().join(UNIX_SOCKET_RELPATH)
 }
 
 /// Spawn the gRPC server task(s) and return a handle that, when dropped,
@@ -0,0 +1,386 @@
+//! Graceful block handoff when a node or shard server stops.
+//!
+//! Without this, a stopping node vanished mid-session: its DHT records
+//! lingered until TTL expiry and coordinators only found out when the next
+//! hop failed. Shutdown now runs in three steps:
+//!
+//! 1. The node re-announces its `ServerInfo` with state=OFFLINE
+//!    (`node::unannounce`), so fresh chain lookups skip it.
+//! 2. The node sends a [`Goodbye`] to every connected peer over
+//!    [`GOODBYE_PROTO`]. Receivers check that it comes from the departing
+//!    peer itself and record the departure in
+//!    `~/.kwaainet/run/departed_peers.json`, and `discover_chain` leaves the
+//!    peer out until the record expires — covering the window before the
+//!    OFFLINE record has propagated.
+//! 3. The shard server drains through a [`DrainGate`]: new sessions are
+//!    refused with [`SHUTTING_DOWN`] (coordinators fail over to the next
+//!    candidate), sessions already in progress keep being served, and every
+//!    response carries `draining: true`. The process exits once it has been
+//!    idle for a moment or `shutdown_grace_secs` runs out.
+
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// libp2p protocol string for shutdown notifications.
+pub const GOODBYE_PROTO: &str = "/kwaai/goodbye/1.0.0";
+
+/// Error returned to requests that would start a new session on a draining
+/// server.
+pub const SHUTTING_DOWN: &str = "node shutting down — start the session on another server";
+
+/// A drain is complete once nothing has been in flight for this long.
+/// Decode steps of an active session arrive in quick succession, so a short
+/// quiet window tells "between tokens" apart from "session finished".
+const DRAIN_QUIET: Duration = Duration::from_secs(2);
+
+/// How long past the sender's grace period a departure record is honoured.
+/// By then the OFFLINE announcement has reached the DHT.
+const DEPARTURE_MARGIN_SECS: u64 = 60;
+
+/// Longest sender grace period honoured; a peer can't keep itself (or, via
+/// a huge value, the departures file) out of routing for longer than this.
+const MAX_GOODBYE_GRACE_SECS: u64 = 600;
+
+// ── Drain gate ────────────────────────────────────────────────────────────────
+
+/// Tracks in-flight inference requests and refuses new sessions while the
+/// shard server shuts down.
+///
+/// Cheap to clone; all clones share the same state.
+#[derive(Clone, Default)]
+pub struct DrainGate(Arc<DrainState>);
+
+#[derive(Default)]
+struct DrainState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    last_activity: Mutex<Option<Instant>>,
+}
+
+impl DrainState {
+    fn touch(&self) {
+        if let Ok(mut t) = self.last_activity.lock() {
+            *t = Some(Instant::now());
+        }
+    }
+}
+
+/// Held for the duration of one request; releases its slot on drop.
+pub struct InFlight(Arc<DrainState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.touch();
+    }
+}
+
+/// How a drain ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainOutcome {
+    /// Every in-flight request finished.
+    Idle,
+    /// The grace period ran out with requests still running.
+    TimedOut { in_flight: usize },
+}
+
+impl DrainGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Admit a request at `seq_pos`.
+    ///
+    /// Returns `None` when draining and the request would start a new
+    /// session (`seq_pos == 0`). Later steps of an existing session are
+    /// still admitted — their KV cache lives here and nowhere else.
+    pub fn admit(&self, seq_pos: u32) -> Option<InFlight> {
+        if seq_pos == 0 && self.is_draining() {
+            return None;
+        }
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.0.touch();
+        Some(InFlight(self.0.clone()))
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.0.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Stop accepting new sessions.
+    pub fn start(&self) {
+        self.0.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Wait until the server has gone quiet, or `grace` elapses.
+    pub async fn drained(&self, grace: Duration) -> DrainOutcome {
+        self.drained_with(grace, DRAIN_QUIET).await
+    }
+
+    async fn drained_with(&self, grace: Duration, quiet: Duration) -> DrainOutcome {
+        let deadline = Instant::now() + grace;
+        loop {
+            let idle_for = self
+                .0
+                .last_activity
+                .lock()
+                .ok()
+                .and_then(|t| *t)
+                .map_or(quiet, |t| t.elapsed());
+            if self.in_flight() == 0 && idle_for >= quiet {
+                return DrainOutcome::Idle;
+            }
+            if Instant::now() >= deadline {
+                return DrainOutcome::TimedOut {
+                    in_flight: self.in_flight(),
+                };
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+// ── Goodbye notification ──────────────────────────────────────────────────────
+
+/// Sent to every connected peer when a node shuts down.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Goodbye {
+    /// Base58 peer ID of the departing node. Receivers only accept a goodbye
+    /// whose sender is this peer.
+    pub peer_id: String,
+    pub start_block: u32,
+    pub end_block: u32,
+    /// How long the sender keeps serving sessions already in progress.
+    pub grace_secs: u64,
+}
+
+/// Send `goodbye` to every connected peer and return how many acknowledged.
+///
+/// Calls run concurrently; each is bounded by `timeout` so an unreachable
+/// peer cannot hold up shutdown.
+pub async fn notify_peers(client: &mut P2PClient, goodbye: &Goodbye, timeout: Duration) -> usize {
+    let payload = match rmp_serde::to_vec_named(goodbye) {
+        Ok(b) => b,
+        Err(e) => {
+            warn!("Goodbye: failed to serialise: {e}");
+            return 0;
+        }
+    };
+    let peers = match client.list_peers().await {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("Goodbye: could not list peers: {e}");
+            return 0;
+        }
+    };
+    let ids: HashSet<Vec<u8>> = peers.into_iter().map(|p| p.id).collect();
+
+    let client = &*client;
+    let calls = ids.iter().map(|id| {
+        let payload = &payload;
+        async move {
+            tokio::time::timeout(
+                timeout,
+                client.call_unary_handler(id, GOODBYE_PROTO, payload),
+            )
+            .await
+            .is_ok_and(|r| r.is_ok())
+        }
+    });
+    futures::future::join_all(calls)
+        .await
+        .into_iter()
+        .filter(|ok| *ok)
+        .count()
+}
+
+/// Until when (unix seconds) to treat the sender of `goodbye` as departed.
+/// The goodbye must come from the peer it names, as authenticated by p2pd,
+/// so nobody can take other peers out of routing.
+fn departure_until(goodbye: &Goodbye, caller: Option<&PeerId>, now: u64) -> Result<u64, String> {
+    let Some(caller) = caller else {
+        return Err("goodbye from an unknown caller".to_string());
+    };
+    if goodbye.peer_id != caller.to_base58() {
+        return Err(format!(
+            "goodbye for {} sent by {}",
+            goodbye.peer_id,
+            caller.to_base58()
+        ));
+    }
+    Ok(now
+        .saturating_add(goodbye.grace_secs.min(MAX_GOODBYE_GRACE_SECS))
+        .saturating_add(DEPARTURE_MARGIN_SECS))
+}
+
+/// Build a unary handler for [`GOODBYE_PROTO`] that records the sender's
+/// departure. Replies with `b"ok"`.
+#[allow(clippy::type_complexity)]
+pub fn make_goodbye_handler() -> impl Fn(
+    Vec<u8>,
+) -> std::pin::Pin<
+    Box<dyn std::future::Future<Output = kwaai_p2p_daemon::error::Result<Vec<u8>>> + Send>,
+> + Send
+       + Sync
+       + 'static {
+    |data: Vec<u8>| {
+        // Read while p2pd's caller is still in scope.
+        let caller = kwaai_p2p_daemon::persistent::caller_peer()
+            .and_then(|bytes| PeerId::from_bytes(&bytes).ok());
+        Box::pin(async move {
+            let goodbye: Goodbye = rmp_serde::from_slice(&data).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("invalid goodbye: {e}"))
+            })?;
+            let until = departure_until(&goodbye, caller.as_ref(), now_secs()).map_err(|e| {
+                warn!("Goodbye rejected: {e}");
+                kwaai_p2p_daemon::error::Error::Protocol(e)
+            })?;
+            info!(
+                peer = %goodbye.peer_id,
+                blocks = format!("[{}, {})", goodbye.start_block, goodbye.end_block),
+                grace_secs = goodbye.grace_secs,
+                "Peer is shutting down"
+            );
+            record_departure(&goodbye.peer_id, until);
+            Ok(b"ok".to_vec())
+        })
+    }
+}
+
+// ── Departed peers ────────────────────────────────────────────────────────────
+
+/// Serialises read-modify-write of the departures file within this process.
+static DEPARTURES_LOCK: Mutex<()> = Mutex::new(());
+
+fn departures_file() -> PathBuf {
+    crate::config::run_dir().join("departed_peers.json")
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn load_departures() -> HashMap<String, u64> {
+    std::fs::read_to_string(departures_file())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn prune(departures: &mut HashMap<String, u64>, now: u64) {
+    departures.retain(|_, until| *until > now);
+}
+
+/// Remember that `peer_id` is leaving; honoured until `until` (unix seconds).
+pub fn record_departure(peer_id: &str, until: u64) {
+    let _guard = DEPARTURES_LOCK.lock();
+    let mut departures = load_departures();
+    prune(&mut departures, now_secs());
+    departures.insert(peer_id.to_string(), until);
+    let _ = std::fs::create_dir_all(crate::config::run_dir());
+    if let Ok(json) = serde_json::to_string(&departures) {
+        if let Err(e) = std::fs::write(departures_file(), json) {
+            warn!("Could not write departed_peers.json: {e}");
+        }
+    }
+}
+
+/// Base58 IDs of peers that announced their shutdown and should not be
+/// picked for new sessions.
+pub fn departed_peers() -> HashSet<String> {
+    let mut departures = load_departures();
+    prune(&mut departures, now_secs());
+    departures.into_keys().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draining_refuses_new_sessions_only() {
+        let gate = DrainGate::new();
+        assert!(gate.admit(0).is_some());
+        gate.start();
+        assert!(gate.admit(0).is_none());
+        let step = gate.admit(17).expect("existing session keeps going");
+        assert_eq!(gate.in_flight(), 1);
+        drop(step);
+        assert_eq!(gate.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_in_flight_requests() {
+        let gate = DrainGate::new();
+        gate.start();
+        let step = gate.admit(5).unwrap();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            drop(step);
+        });
+        let outcome = gate
+            .drained_with(Duration::from_secs(5), Duration::from_millis(50))
+            .await;
+        assert_eq!(outcome, DrainOutcome::Idle);
+        release.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_grace() {
+        let gate = DrainGate::new();
+        gate.start();
+        let _step = gate.admit(5).unwrap();
+        let outcome = gate
+            .drained_with(Duration::from_millis(200), Duration::from_millis(50))
+            .await;
+        assert_eq!(outcome, DrainOutcome::TimedOut { in_flight: 1 });
+    }
+
+    #[test]
+    fn goodbye_must_come_from_its_peer_and_departures_expire() {
+        let g = Goodbye {
+            peer_id: "12D3KooWExample".to_string(),
+            start_block: 8,
+            end_block: 16,
+            grace_secs: 30,
+        };
+        let bytes = rmp_serde::to_vec_named(&g).unwrap();
+        assert_eq!(rmp_serde::from_slice::<Goodbye>(&bytes).unwrap(), g);
+
+        let peer = PeerId::random();
+        let mut g = Goodbye {
+            peer_id: peer.to_base58(),
+            ..g
+        };
+        assert_eq!(departure_until(&g, Some(&peer), 1000), Ok(1000 + 30 + 60));
+        assert!(departure_until(&g, Some(&PeerId::random()), 1000).is_err());
+        assert!(departure_until(&g, None, 1000).is_err());
+        g.grace_secs = u64::MAX;
+        assert_eq!(
+            departure_until(&g, Some(&peer), 1000),
+            Ok(1000 + MAX_GOODBYE_GRACE_SECS + 60)
+        );
+
+        let mut d = HashMap::from([("a".to_string(), 100), ("b".to_string(), 300)]);
+        prune(&mut d, 200);
+        assert_eq!(d.into_keys().collect::<Vec<_>>(), vec!["b".to_string()]);
+    }
+}
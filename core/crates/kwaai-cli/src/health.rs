@@ -1,12 +1,24 @@
 //! Health monitoring with exponential backoff reconnection
+//!
+//! Also home to the reachability self-check: the node periodically asks
+//! peers to dial its announced addresses back over [`DIALBACK_PROTO`], and
+//! checks that a relay holding its circuit address is still connected.
+//! Repeated failures trigger a reconnection and finally a fallback to relay
+//! circuits; see [`ReachabilityMonitor`].
 #![allow(dead_code)]
 
 use anyhow::Result;
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 use tracing::{debug, info, warn};
 
-use crate::config::HealthConfig;
+use crate::config::{HealthConfig, ReconnectionConfig};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct HealthMetrics {
@@ -149,24 +161,454 @@ impl HealthMonitor {
     }
 }
 
+// ── Reachability self-check ──────────────────────────────────────────────────
+
+/// libp2p protocol for asking a peer to dial our addresses back.
+pub const DIALBACK_PROTO: &str = "/kwaai/dialback/1.0.0";
+
+/// Most addresses one dial-back request may ask for, so the handler cannot
+/// be turned into a port scanner.
+const MAX_DIALBACK_ADDRS: usize = 4;
+
+/// Timeout of each dial made by the handler.
+const DIALBACK_DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Peers asked per probe; any one of them reaching us is enough.
+const MAX_DIALBACK_HELPERS: usize = 3;
+
+/// Sent over [`DIALBACK_PROTO`]: the addresses the caller announces.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialBackRequest {
+    pub addrs: Vec<String>,
+}
+
+/// Reply to a [`DialBackRequest`]: the addresses that accepted a connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialBackResponse {
+    pub reachable: Vec<String>,
+}
+
+/// Build a unary handler for [`DIALBACK_PROTO`] that opens a TCP connection
+/// to each requested address.
+///
+/// Only public TCP addresses are dialled, at most [`MAX_DIALBACK_ADDRS`] per
+/// request; circuit and LAN addresses are skipped.
+#[allow(clippy::type_complexity)]
+pub fn make_dialback_handler() -> impl Fn(
+    Vec<u8>,
+) -> std::pin::Pin<
+    Box<dyn std::future::Future<Output = kwaai_p2p_daemon::error::Result<Vec<u8>>> + Send>,
+> + Send
+       + Sync
+       + 'static {
+    |data: Vec<u8>| {
+        Box::pin(async move {
+            let request: DialBackRequest = rmp_serde::from_slice(&data).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("invalid dial-back request: {e}"))
+            })?;
+            let dials = request
+                .addrs
+                .into_iter()
+                .take(MAX_DIALBACK_ADDRS)
+                .filter_map(|addr| dialable_socket_addr(&addr).map(|sa| (addr, sa)))
+                .map(|(addr, sa)| async move {
+                    let ok = tokio::time::timeout(
+                        DIALBACK_DIAL_TIMEOUT,
+                        tokio::net::TcpStream::connect(sa),
+                    )
+                    .await
+                    .is_ok_and(|r| r.is_ok());
+                    debug!(
+                        "Dial-back to {}: {}",
+                        addr,
+                        if ok { "ok" } else { "failed" }
+                    );
+                    ok.then_some(addr)
+                });
+            let reachable = futures::future::join_all(dials)
+                .await
+                .into_iter()
+                .flatten()
+                .collect();
+            rmp_serde::to_vec_named(&DialBackResponse { reachable }).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("encoding dial-back reply: {e}"))
+            })
+        })
+    }
+}
+
+/// The TCP socket address of a public, direct multiaddr.
+fn dialable_socket_addr(addr: &str) -> Option<SocketAddr> {
+    let ma: Multiaddr = addr.parse().ok()?;
+    let mut ip = None;
+    let mut port = None;
+    for proto in ma.iter() {
+        match proto {
+            Protocol::P2pCircuit => return None,
+            Protocol::Ip4(a) if crate::node::is_globally_routable_v4(a) => ip = Some(IpAddr::V4(a)),
+            Protocol::Ip6(a) if !a.is_loopback() && !a.is_unspecified() => ip = Some(IpAddr::V6(a)),
+            Protocol::Tcp(p) => port = Some(p),
+            _ => {}
+        }
+    }
+    Some(SocketAddr::new(ip?, port?))
+}
+
+/// Relay peers named in circuit addresses (`.../p2p/<relay>/p2p-circuit`)
+/// or trusted relay multiaddrs.
+fn relay_peers<'a>(addrs: impl IntoIterator<Item = &'a String>) -> Vec<PeerId> {
+    addrs
+        .into_iter()
+        .filter_map(|addr| {
+            let ma: Multiaddr = addr.parse().ok()?;
+            let protos: Vec<Protocol> = ma.iter().collect();
+            let end = protos
+                .iter()
+                .position(|p| matches!(p, Protocol::P2pCircuit))
+                .unwrap_or(protos.len());
+            protos[..end].iter().rev().find_map(|p| match p {
+                Protocol::P2p(id) => Some(*id),
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+/// Whether the node can be reached from outside, as last probed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    /// Not probed yet, or nothing could be checked (no announced address
+    /// and no peer answering [`DIALBACK_PROTO`]).
+    Unknown,
+    /// A peer dialled one of our direct addresses back.
+    Public,
+    /// No direct address works, but a relay holding our circuit is connected.
+    Relayed,
+    /// Neither a dial-back nor a relay path worked.
+    Unreachable,
+}
+
+impl Reachability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reachability::Unknown => "unknown",
+            Reachability::Public => "public",
+            Reachability::Relayed => "relayed",
+            Reachability::Unreachable => "unreachable",
+        }
+    }
+}
+
+/// Probe this node's reachability.
+///
+/// Up to [`MAX_DIALBACK_HELPERS`] peers (bootstrap peers first, then any
+/// other connected peer) are asked concurrently to dial the direct addresses
+/// in `addrs` back. Failing that, the node counts as relayed when a relay
+/// from a circuit address in `addrs` or from `trusted_relays` is connected.
+pub async fn probe_reachability(
+    client: &P2PClient,
+    bootstrap_peers: &[String],
+    addrs: &[String],
+    trusted_relays: &[String],
+) -> Reachability {
+    let connected: HashSet<PeerId> = match client.list_peers().await {
+        Ok(peers) => peers
+            .iter()
+            .filter_map(|p| PeerId::from_bytes(&p.id).ok())
+            .collect(),
+        Err(e) => {
+            warn!("Reachability probe: could not list peers: {}", e);
+            return Reachability::Unknown;
+        }
+    };
+
+    let direct: Vec<String> = addrs
+        .iter()
+        .filter(|a| dialable_socket_addr(a).is_some())
+        .cloned()
+        .collect();
+    let mut answered = false;
+    if !direct.is_empty() {
+        let mut helpers: Vec<PeerId> = bootstrap_peers
+            .iter()
+            .filter_map(|a| crate::bootstrap::peer_id_of(a))
+            .collect();
+        for peer in &connected {
+            if !helpers.contains(peer) {
+                helpers.push(*peer);
+            }
+        }
+        helpers.truncate(MAX_DIALBACK_HELPERS);
+        let payload = rmp_serde::to_vec_named(&DialBackRequest {
+            addrs: direct.clone(),
+        })
+        .unwrap_or_default();
+        let calls = helpers.iter().map(|peer| {
+            let payload = &payload;
+            async move {
+                let reply = tokio::time::timeout(
+                    DIALBACK_DIAL_TIMEOUT * 2,
+                    client.call_unary_handler(&peer.to_bytes(), DIALBACK_PROTO, payload),
+                )
+                .await;
+                match reply {
+                    Ok(Ok(bytes)) => rmp_serde::from_slice::<DialBackResponse>(&bytes).ok(),
+                    Ok(Err(e)) => {
+                        debug!("Dial-back via {} failed: {}", peer, e);
+                        None
+                    }
+                    Err(_) => {
+                        debug!("Dial-back via {} timed out", peer);
+                        None
+                    }
+                }
+            }
+        });
+        for reply in futures::future::join_all(calls).await.into_iter().flatten() {
+            answered = true;
+            if !reply.reachable.is_empty() {
+                return Reachability::Public;
+            }
+        }
+    }
+
+    let relays = relay_peers(addrs.iter().chain(trusted_relays));
+    if relays.iter().any(|r| connected.contains(r)) {
+        Reachability::Relayed
+    } else if answered || !relays.is_empty() {
+        Reachability::Unreachable
+    } else {
+        Reachability::Unknown
+    }
+}
+
+/// What the node should do after a probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    None,
+    /// Re-dial the bootstrap peers.
+    Reconnect,
+    /// Restart p2pd as a private node so AutoRelay reserves a relay circuit.
+    RelayFallback,
+}
+
+/// Reachability reported in the health status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReachabilityStatus {
+    pub state: Reachability,
+    pub consecutive_failures: u32,
+    pub probes_total: u64,
+    pub last_probe: Option<String>,
+    pub reconnections_triggered: u64,
+    /// Whether the node fell back to relay circuits after failed probes.
+    pub relay_fallback: bool,
+}
+
+/// Folds probe results into a [`ReachabilityStatus`] and decides on recovery.
+///
+/// Every `failure_threshold` consecutive unreachable probes trigger a
+/// reconnection; from twice the threshold on, a node that may still switch
+/// to relays falls back to them once. [`Reachability::Unknown`] neither
+/// counts as a failure nor resets the count.
+pub struct ReachabilityMonitor {
+    status: ReachabilityStatus,
+    failure_threshold: u32,
+    reconnection_enabled: bool,
+    allow_relay_fallback: bool,
+}
+
+impl ReachabilityMonitor {
+    pub fn new(config: &HealthConfig, allow_relay_fallback: bool) -> Self {
+        Self {
+            status: ReachabilityStatus {
+                state: Reachability::Unknown,
+                consecutive_failures: 0,
+                probes_total: 0,
+                last_probe: None,
+                reconnections_triggered: 0,
+                relay_fallback: false,
+            },
+            failure_threshold: config.failure_threshold.max(1),
+            reconnection_enabled: config.reconnection.enabled,
+            allow_relay_fallback,
+        }
+    }
+
+    pub fn record(&mut self, state: Reachability) -> RecoveryAction {
+        let status = &mut self.status;
+        status.probes_total += 1;
+        status.last_probe = Some(chrono::Utc::now().to_rfc3339());
+        status.state = state;
+        match state {
+            Reachability::Unreachable => status.consecutive_failures += 1,
+            Reachability::Unknown => {}
+            Reachability::Public | Reachability::Relayed => status.consecutive_failures = 0,
+        }
+
+        let failures = status.consecutive_failures;
+        if state != Reachability::Unreachable
+            || !self.reconnection_enabled
+            || !failures.is_multiple_of(self.failure_threshold)
+        {
+            return RecoveryAction::None;
+        }
+        if failures >= 2 * self.failure_threshold
+            && self.allow_relay_fallback
+            && !status.relay_fallback
+        {
+            status.relay_fallback = true;
+            return RecoveryAction::RelayFallback;
+        }
+        status.reconnections_triggered += 1;
+        RecoveryAction::Reconnect
+    }
+
+    pub fn status(&self) -> &ReachabilityStatus {
+        &self.status
+    }
+}
+
+// ── Reconnection ─────────────────────────────────────────────────────────────
+
+/// The node's link to the bootstrap peers, as last sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    /// At least one bootstrap peer answers.
+    Connected,
+    /// No bootstrap peer answers; re-dialling with backoff.
+    Reconnecting,
+}
+
+/// Change in the link reported by [`ReconnectionManager::observe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    None,
+    /// The bootstrap peers stopped answering; the node should announce
+    /// itself as JOINING and start re-dialling.
+    Lost,
+    /// A bootstrap peer answers again after `attempts` re-dials; the node
+    /// should announce itself as ONLINE.
+    Restored {
+        attempts: u32,
+        down_secs: u64,
+    },
+}
+
+/// Reconnection progress reported in the health status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconnectionStatus {
+    pub state: LinkState,
+    /// Failed re-dials since the link was lost.
+    pub attempts: u32,
+    pub losses_total: u64,
+    /// Rounds that used up `max_attempts` and restarted p2pd.
+    pub p2pd_restarts: u64,
+    pub lost_at: Option<String>,
+}
+
+/// Re-dials the bootstrap peers after the node loses them, honouring
+/// `health_monitoring.reconnection`.
+///
+/// Connectivity samples go through [`observe`](Self::observe). Once the link
+/// is lost, the caller re-dials after each [`next_delay`](Self::next_delay)
+/// and reports failures to [`attempt_failed`](Self::attempt_failed); after
+/// `max_attempts` failures the round ends with a p2pd restart and a fresh
+/// round begins, so a node never gives up on the network for good.
+pub struct ReconnectionManager {
+    config: ReconnectionConfig,
+    status: ReconnectionStatus,
+    lost_since: Option<std::time::Instant>,
+}
+
+impl ReconnectionManager {
+    pub fn new(config: &ReconnectionConfig) -> Self {
+        Self {
+            config: config.clone(),
+            status: ReconnectionStatus {
+                state: LinkState::Connected,
+                attempts: 0,
+                losses_total: 0,
+                p2pd_restarts: 0,
+                lost_at: None,
+            },
+            lost_since: None,
+        }
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.status.state == LinkState::Reconnecting
+    }
+
+    /// Fold in whether any bootstrap peer answered.
+    pub fn observe(&mut self, reachable: bool, now: std::time::Instant) -> LinkEvent {
+        let status = &mut self.status;
+        match (status.state, reachable) {
+            (LinkState::Connected, false) if self.config.enabled => {
+                status.state = LinkState::Reconnecting;
+                status.attempts = 0;
+                status.losses_total += 1;
+                status.lost_at = Some(chrono::Utc::now().to_rfc3339());
+                self.lost_since = Some(now);
+                LinkEvent::Lost
+            }
+            (LinkState::Reconnecting, true) => {
+                status.state = LinkState::Connected;
+                status.lost_at = None;
+                let down = self.lost_since.take().map(|t| now.duration_since(t));
+                LinkEvent::Restored {
+                    attempts: std::mem::take(&mut status.attempts),
+                    down_secs: down.unwrap_or_default().as_secs(),
+                }
+            }
+            _ => LinkEvent::None,
+        }
+    }
+
+    /// Record a failed re-dial. Returns true when that used up
+    /// `max_attempts`: the caller should restart p2pd, and the next round
+    /// starts over from `initial_delay`.
+    pub fn attempt_failed(&mut self) -> bool {
+        self.status.attempts += 1;
+        if self.status.attempts < self.config.max_attempts.max(1) {
+            return false;
+        }
+        self.status.attempts = 0;
+        self.status.p2pd_restarts += 1;
+        true
+    }
+
+    /// Delay before the next re-dial.
+    pub fn next_delay(&self) -> Duration {
+        backoff_delay(&self.config, self.status.attempts + 1)
+    }
+
+    pub fn status(&self) -> &ReconnectionStatus {
+        &self.status
+    }
+}
+
 /// Compute backoff delay for attempt N (1-based).
-pub fn backoff_delay(
-    attempt: u32,
-    initial_delay: u64,
-    max_delay: u64,
-    multiplier: f64,
-    jitter: bool,
-    jitter_factor: f64,
-) -> Duration {
-    let delay = (initial_delay as f64 * multiplier.powi(attempt as i32 - 1)) as u64;
-    let delay = delay.min(max_delay);
-    let delay = if jitter {
-        let jitter_amount = (delay as f64 * jitter_factor * rand_f64()) as u64;
-        delay.saturating_sub(jitter_amount / 2) + jitter_amount / 2
+///
+/// `backoff_strategy` is `exponential` (the default), `linear` or `fixed`;
+/// with `jitter`, the delay is spread by up to `jitter_factor` of itself,
+/// half either way.
+pub fn backoff_delay(config: &ReconnectionConfig, attempt: u32) -> Duration {
+    let initial = config.initial_delay as f64;
+    let n = attempt.max(1);
+    let delay = match config.backoff_strategy.as_str() {
+        "fixed" => initial,
+        "linear" => initial * n as f64,
+        _ => initial * config.backoff_multiplier.powi(n.min(64) as i32 - 1),
+    };
+    let delay = delay.min(config.max_delay as f64);
+    let delay = if config.jitter {
+        delay * (1.0 + config.jitter_factor * (rand_f64() - 0.5))
     } else {
         delay
     };
-    Duration::from_secs(delay)
+    Duration::from_secs_f64(delay.max(0.0))
 }
 
 fn rand_f64() -> f64 {
@@ -178,3 +620,109 @@ fn rand_f64() -> f64 {
         .subsec_nanos();
     (seed as f64) / (u32::MAX as f64)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KwaaiNetConfig;
+
+    const RELAY: &str = "QmNV5G3hq2UmAck2htEgsqrmPFBff5goFZAdmKDcZLBZLX";
+
+    #[test]
+    fn dials_only_public_tcp_addrs() {
+        assert_eq!(
+            dialable_socket_addr("/ip4/198.51.100.7/tcp/8080"),
+            Some("198.51.100.7:8080".parse().unwrap())
+        );
+        assert_eq!(dialable_socket_addr("/ip4/192.168.1.4/tcp/8080"), None);
+        assert_eq!(dialable_socket_addr("/ip4/127.0.0.1/tcp/8080"), None);
+        assert_eq!(
+            dialable_socket_addr("/ip4/198.51.100.7/udp/8080/quic-v1"),
+            None
+        );
+        let circuit = format!("/ip4/198.51.100.7/tcp/8000/p2p/{RELAY}/p2p-circuit");
+        assert_eq!(dialable_socket_addr(&circuit), None);
+    }
+
+    #[test]
+    fn finds_relays_of_circuit_addrs() {
+        let relay: PeerId = RELAY.parse().unwrap();
+        let addrs = vec![
+            format!("/ip4/198.51.100.7/tcp/8000/p2p/{RELAY}/p2p-circuit"),
+            format!("/dns/relay.example/tcp/8000/p2p/{RELAY}"),
+            "/ip4/198.51.100.8/tcp/8080".to_string(),
+        ];
+        assert_eq!(relay_peers(&addrs), vec![relay, relay]);
+    }
+
+    #[test]
+    fn repeated_failures_reconnect_then_fall_back_to_relay() {
+        let config = KwaaiNetConfig::default().health_monitoring;
+        let threshold = config.failure_threshold;
+        let mut monitor = ReachabilityMonitor::new(&config, true);
+
+        let mut actions = Vec::new();
+        for _ in 0..3 * threshold {
+            actions.push(monitor.record(Reachability::Unreachable));
+        }
+        let triggered: Vec<(usize, RecoveryAction)> = actions
+            .into_iter()
+            .enumerate()
+            .filter(|(_, a)| *a != RecoveryAction::None)
+            .collect();
+        let t = threshold as usize;
+        assert_eq!(
+            triggered,
+            vec![
+                (t - 1, RecoveryAction::Reconnect),
+                (2 * t - 1, RecoveryAction::RelayFallback),
+                (3 * t - 1, RecoveryAction::Reconnect),
+            ]
+        );
+        assert!(monitor.status().relay_fallback);
+
+        assert_eq!(monitor.record(Reachability::Unknown), RecoveryAction::None);
+        assert_eq!(monitor.status().consecutive_failures, 3 * threshold);
+        assert_eq!(monitor.record(Reachability::Relayed), RecoveryAction::None);
+        assert_eq!(monitor.status().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn reconnects_with_backoff_and_restarts_after_max_attempts() {
+        let mut config = KwaaiNetConfig::default().health_monitoring.reconnection;
+        config.jitter = false;
+        config.max_attempts = 3;
+        assert_eq!(backoff_delay(&config, 1), Duration::from_secs(30));
+        assert_eq!(backoff_delay(&config, 3), Duration::from_secs(120));
+        assert_eq!(backoff_delay(&config, 20), Duration::from_secs(1800));
+        config.backoff_strategy = "linear".into();
+        assert_eq!(backoff_delay(&config, 3), Duration::from_secs(90));
+
+        let now = std::time::Instant::now();
+        let mut manager = ReconnectionManager::new(&config);
+        assert_eq!(manager.observe(true, now), LinkEvent::None);
+        assert_eq!(manager.observe(false, now), LinkEvent::Lost);
+        assert_eq!(manager.observe(false, now), LinkEvent::None);
+        assert_eq!(manager.next_delay(), Duration::from_secs(30));
+        assert!(!manager.attempt_failed());
+        assert_eq!(manager.next_delay(), Duration::from_secs(60));
+        assert!(!manager.attempt_failed());
+        // The third failure ends the round; the next starts from scratch.
+        assert!(manager.attempt_failed());
+        assert_eq!(manager.next_delay(), Duration::from_secs(30));
+        assert!(!manager.attempt_failed());
+        assert_eq!(
+            manager.observe(true, now + Duration::from_secs(200)),
+            LinkEvent::Restored {
+                attempts: 1,
+                down_secs: 200
+            }
+        );
+        assert!(!manager.is_reconnecting());
+        assert_eq!(manager.status().p2pd_restarts, 1);
+
+        config.enabled = false;
+        let mut disabled = ReconnectionManager::new(&config);
+        assert_eq!(disabled.observe(false, now), LinkEvent::None);
+    }
+}
@@ -145,31 +145,51 @@ pub fn blocks_are_cached(
     is_first: bool,
     is_last: bool,
 ) -> bool {
-    let index_path = snapshot_dir.join("model.safetensors.index.json");
-    let Ok(text) = std::fs::read_to_string(&index_path) else {
+    let Some(needed) =
+        weight_files_for_blocks(snapshot_dir, start_block, end_block, is_first, is_last)
+    else {
         // No index file — can't verify which shards are needed.
         // Return false so download_for_blocks fetches the index and checks.
         return false;
     };
-    let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) else {
-        return false;
-    };
-    let Some(weight_map) = json["weight_map"].as_object() else {
+    if needed.is_empty() {
         // No weight_map — single-file model; just check that any shard exists.
         return has_safetensors_shards(snapshot_dir);
-    };
-
-    let needed: std::collections::HashSet<String> = weight_map
-        .iter()
-        .filter(|(tensor_name, _)| {
-            is_tensor_needed(tensor_name, start_block, end_block, is_first, is_last)
-        })
-        .filter_map(|(_, file_val)| file_val.as_str().map(String::from))
-        .collect();
+    }
 
     needed.iter().all(|f| snapshot_dir.join(f).exists())
 }
 
+/// The weight files in `snapshot_dir` that hold the tensors needed for
+/// `[start_block, end_block)`, read from `model.safetensors.index.json`.
+///
+/// `None` when the index is missing or unreadable; empty when it has no
+/// `weight_map` (single-file model).
+pub fn weight_files_for_blocks(
+    snapshot_dir: &Path,
+    start_block: usize,
+    end_block: usize,
+    is_first: bool,
+    is_last: bool,
+) -> Option<std::collections::HashSet<String>> {
+    let index_path = snapshot_dir.join("model.safetensors.index.json");
+    let text = std::fs::read_to_string(&index_path).ok()?;
+    let json = serde_json::from_str::<serde_json::Value>(&text).ok()?;
+    let Some(weight_map) = json["weight_map"].as_object() else {
+        return Some(Default::default());
+    };
+
+    Some(
+        weight_map
+            .iter()
+            .filter(|(tensor_name, _)| {
+                is_tensor_needed(tensor_name, start_block, end_block, is_first, is_last)
+            })
+            .filter_map(|(_, file_val)| file_val.as_str().map(String::from))
+            .collect(),
+    )
+}
+
 /// Return the list of directories to search for HuggingFace model caches.
 fn cache_roots() -> Result<Vec<PathBuf>> {
     let home = dirs::home_dir().ok_or_else(|| anyhow!("cannot determine home directory"))?;
@@ -210,6 +230,20 @@ fn cache_roots() -> Result<Vec<PathBuf>> {
 
 // ── Download ──────────────────────────────────────────────────────────────────
 
+/// Where a downloaded snapshot of `model_id` at `revision` is stored:
+/// `$HF_HOME/models--{owner}--{name}/snapshots/{revision}/`, with `HF_HOME`
+/// defaulting to `~/.cache/huggingface/hub`.
+pub fn snapshot_dir(model_id: &str, revision: &str) -> Result<PathBuf> {
+    let cache_root = if let Ok(hf_home) = std::env::var("HF_HOME") {
+        PathBuf::from(hf_home)
+    } else {
+        let home = dirs::home_dir().ok_or_else(|| anyhow!("cannot determine home directory"))?;
+        home.join(".cache/huggingface/hub")
+    };
+    let dir_name = format!("models--{}", model_id.replace('/', "--"));
+    Ok(cache_root.join(dir_name).join("snapshots").join(revision))
+}
+
 /// Download a HuggingFace model SafeTensors snapshot to the local cache.
 ///
 /// Uses the HuggingFace Hub HTTP API — no Python or `huggingface-cli` required.
@@ -220,14 +254,6 @@ fn cache_roots() -> Result<Vec<PathBuf>> {
 ///
 /// Pass `hf_token` (or set `HF_TOKEN` env var) for private/gated models.
 pub async fn download(model_id: &str, hf_token: Option<&str>) -> Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| anyhow!("cannot determine home directory"))?;
-
-    let cache_root = if let Ok(hf_home) = std::env::var("HF_HOME") {
-        PathBuf::from(hf_home)
-    } else {
-        home.join(".cache/huggingface/hub")
-    };
-
     let token = hf_token
         .map(String::from)
         .or_else(|| std::env::var("HF_TOKEN").ok())
@@ -286,8 +312,7 @@ pub async fn download(model_id: &str, hf_token: Option<&str>) -> Result<PathBuf>
     }
 
     // Create the snapshot directory.
-    let dir_name = format!("models--{}", model_id.replace('/', "--"));
-    let snapshot_dir = cache_root.join(&dir_name).join("snapshots").join(&sha);
+    let snapshot_dir = snapshot_dir(model_id, &sha)?;
     std::fs::create_dir_all(&snapshot_dir)
         .with_context(|| format!("Cannot create cache dir: {}", snapshot_dir.display()))?;
 
@@ -347,14 +372,6 @@ pub async fn download_for_blocks(
     is_last: bool,
     hf_token: Option<&str>,
 ) -> Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| anyhow!("cannot determine home directory"))?;
-
-    let cache_root = if let Ok(hf_home) = std::env::var("HF_HOME") {
-        PathBuf::from(hf_home)
-    } else {
-        home.join(".cache/huggingface/hub")
-    };
-
     let token = hf_token
         .map(String::from)
         .or_else(|| std::env::var("HF_TOKEN").ok())
@@ -394,8 +411,7 @@ pub async fn download_for_blocks(
         .to_string();
 
     // Create snapshot directory early so we can download the index file.
-    let dir_name = format!("models--{}", model_id.replace('/', "--"));
-    let snapshot_dir = cache_root.join(&dir_name).join("snapshots").join(&sha);
+    let snapshot_dir = snapshot_dir(model_id, &sha)?;
     std::fs::create_dir_all(&snapshot_dir)
         .with_context(|| format!("Cannot create cache dir: {}", snapshot_dir.display()))?;
 
@@ -564,8 +580,100 @@ fn is_tensor_needed(
     is_first || is_last
 }
 
+/// How the Hub identifies a file's content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileHash {
+    /// Hex SHA-256 of an LFS file
+    Sha256(String),
+    /// Hex git blob id (SHA-1 of `blob <len>\0` + content) of a regular file
+    GitBlob(String),
+}
+
+impl FileHash {
+    pub fn hex(&self) -> &str {
+        match self {
+            Self::Sha256(h) | Self::GitBlob(h) => h,
+        }
+    }
+}
+
+/// An inference file of a snapshot, as the Hub lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HubFile {
+    pub size: u64,
+    pub hash: FileHash,
+}
+
+/// Size and content hash of every inference file of `model_id` at
+/// `revision`, keyed by file name. Files the Hub lists without a hash are
+/// left out.
+pub async fn revision_files(
+    model_id: &str,
+    revision: &str,
+    hf_token: Option<&str>,
+) -> Result<std::collections::HashMap<String, HubFile>> {
+    let token = hf_token
+        .map(String::from)
+        .or_else(|| std::env::var("HF_TOKEN").ok())
+        .or_else(|| std::env::var("HUGGING_FACE_HUB_TOKEN").ok());
+    let client = build_hf_client(token.as_deref())?;
+    let api_url = format!(
+        "https://huggingface.co/api/models/{}/revision/{}?blobs=true",
+        model_id, revision
+    );
+    let resp = client
+        .get(&api_url)
+        .send()
+        .await
+        .context("Failed to reach HuggingFace Hub for file hashes")?;
+    match resp.status().as_u16() {
+        200 => {}
+        401 | 403 => bail!(
+            "Model '{}' requires authentication. Pass --hf-token or set HF_TOKEN env var.",
+            model_id
+        ),
+        404 => bail!(
+            "Revision {} of '{}' not found on HuggingFace Hub",
+            revision,
+            model_id
+        ),
+        s => bail!("HuggingFace API returned HTTP {}", s),
+    }
+    let meta: serde_json::Value = resp
+        .json()
+        .await
+        .context("Failed to parse HuggingFace API response")?;
+    parse_revision_files(&meta)
+}
+
+fn parse_revision_files(
+    meta: &serde_json::Value,
+) -> Result<std::collections::HashMap<String, HubFile>> {
+    let siblings = meta["siblings"]
+        .as_array()
+        .ok_or_else(|| anyhow!("HF API response missing 'siblings' field"))?;
+    Ok(siblings
+        .iter()
+        .filter_map(|s| {
+            let name = s["rfilename"].as_str().filter(|f| should_download(f))?;
+            let lfs = &s["lfs"];
+            let file = match (lfs["sha256"].as_str(), lfs["size"].as_u64()) {
+                (Some(sha), Some(size)) => HubFile {
+                    size,
+                    hash: FileHash::Sha256(sha.to_ascii_lowercase()),
+                },
+                _ => HubFile {
+                    size: s["size"].as_u64()?,
+                    hash: FileHash::GitBlob(s["blobId"].as_str()?.to_ascii_lowercase()),
+                },
+            };
+            Some((name.to_string(), file))
+        })
+        .collect())
+}
+
 /// Returns true for files needed by SafeTensors distributed inference.
-fn should_download(fname: &str) -> bool {
+pub fn should_download(fname: &str) -> bool {
     let lower = fname.to_ascii_lowercase();
     matches!(
         lower.as_str(),
@@ -657,7 +765,7 @@ async fn download_file(
     Ok(())
 }
 
-fn fmt_bytes(bytes: u64) -> String {
+pub fn fmt_bytes(bytes: u64) -> String {
     if bytes >= 1_073_741_824 {
         format!("{:.1} GB", bytes as f64 / 1_073_741_824.0)
     } else if bytes >= 1_048_576 {
@@ -671,7 +779,36 @@ fn fmt_bytes(bytes: u64) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::is_tensor_needed;
+    use super::{is_tensor_needed, parse_revision_files, FileHash, HubFile};
+
+    #[test]
+    fn revision_files_take_lfs_or_blob_hashes() {
+        let meta = serde_json::json!({
+            "siblings": [
+                {"rfilename": "config.json", "size": 2, "blobId": "9E26DFEEB6E641A33DAE4961196235BDB965B21B"},
+                {"rfilename": "model.safetensors", "size": 130,
+                 "blobId": "ffff", "lfs": {"sha256": "ab12", "size": 16}},
+                {"rfilename": "README.md", "size": 5, "blobId": "abcd"},
+                {"rfilename": "tokenizer.json"}
+            ]
+        });
+        let files = parse_revision_files(&meta).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files["config.json"],
+            HubFile {
+                size: 2,
+                hash: FileHash::GitBlob("9e26dfeeb6e641a33dae4961196235bdb965b21b".into())
+            }
+        );
+        assert_eq!(
+            files["model.safetensors"],
+            HubFile {
+                size: 16,
+                hash: FileHash::Sha256("ab12".into())
+            }
+        );
+    }
 
     // Serving blocks [8, 16), middle of the model — no embed, no norm/head.
     #[test]
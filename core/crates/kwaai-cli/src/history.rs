@@ -0,0 +1,386 @@
+//! Local run history: uptime sessions, connection samples, announce outcomes
+//! and served work, kept in SQLite at `~/.kwaainet/history.db`.
+//!
+//! The node daemon and the shard server are separate processes, so both
+//! write here through short-lived connections; SQLite's WAL mode and busy
+//! timeout serialise them. Recording is best-effort: a failed write is
+//! logged and never stops the node.
+//!
+//! Read back by `kwaainet history` (see `history_cmd.rs`).
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::config::kwaainet_dir;
+
+/// Rows older than this are pruned when a session starts.
+const RETENTION_SECS: i64 = 90 * 86_400;
+
+/// How often the shard server writes its served-work counters.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the node samples its connection count.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(300);
+
+pub fn history_db() -> PathBuf {
+    kwaainet_dir().join("history.db")
+}
+
+/// What a sample row measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Connected peers at sample time.
+    Connections,
+    /// One announce attempt that reached the DHT.
+    AnnounceOk,
+    /// One announce attempt that did not.
+    AnnounceFailed,
+    /// Positions run through this node's blocks since the last flush.
+    TokensServed,
+    /// Inference sessions opened on this node since the last flush.
+    SessionsServed,
+}
+
+impl Metric {
+    fn as_str(self) -> &'static str {
+        match self {
+            Metric::Connections => "connections",
+            Metric::AnnounceOk => "announce_ok",
+            Metric::AnnounceFailed => "announce_failed",
+            Metric::TokensServed => "tokens_served",
+            Metric::SessionsServed => "sessions_served",
+        }
+    }
+}
+
+fn now_secs() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+// ---------------------------------------------------------------------------
+// Store
+// ---------------------------------------------------------------------------
+
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("opening history database {}", path.display()))?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS uptime (
+                 id         INTEGER PRIMARY KEY,
+                 started_at INTEGER NOT NULL,
+                 last_seen  INTEGER NOT NULL,
+                 ended_at   INTEGER
+             );
+             CREATE TABLE IF NOT EXISTS samples (
+                 ts     INTEGER NOT NULL,
+                 metric TEXT NOT NULL,
+                 value  INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS samples_ts ON samples (ts);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Self::open(&history_db())
+    }
+
+    /// Open an uptime session at `now` and return its id.
+    pub fn start_session(&self, now: i64) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO uptime (started_at, last_seen) VALUES (?1, ?1)",
+            params![now],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        let cutoff = now - RETENTION_SECS;
+        self.conn
+            .execute("DELETE FROM samples WHERE ts < ?1", params![cutoff])?;
+        self.conn.execute(
+            "DELETE FROM uptime WHERE COALESCE(ended_at, last_seen) < ?1",
+            params![cutoff],
+        )?;
+        Ok(id)
+    }
+
+    /// Extend session `id` to `now`. A session that is never closed (a crash)
+    /// counts up to its last heartbeat.
+    pub fn touch_session(&self, id: i64, now: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE uptime SET last_seen = ?2 WHERE id = ?1",
+            params![id, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn end_session(&self, id: i64, now: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE uptime SET last_seen = ?2, ended_at = ?2 WHERE id = ?1",
+            params![id, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn record(&self, metric: Metric, value: u64, now: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO samples (ts, metric, value) VALUES (?1, ?2, ?3)",
+            params![now, metric.as_str(), value as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Uptime sessions overlapping `[since, until)`, oldest first.
+    pub fn sessions(&self, since: i64, until: i64) -> Result<Vec<UptimeSession>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT started_at, last_seen, ended_at FROM uptime
+             WHERE started_at < ?2 AND last_seen >= ?1 ORDER BY started_at",
+        )?;
+        let rows = stmt.query_map(params![since, until], |r| {
+            Ok(UptimeSession {
+                started_at: r.get(0)?,
+                last_seen: r.get(1)?,
+                ended_at: r.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Per-bucket aggregates over `[since, until)` in steps of `bucket_secs`.
+    pub fn buckets(&self, since: i64, until: i64, bucket_secs: i64) -> Result<Vec<Bucket>> {
+        let sessions = self.sessions(since, until)?;
+        let mut buckets: Vec<Bucket> = (since..until)
+            .step_by(bucket_secs.max(1) as usize)
+            .map(|start| {
+                let end = (start + bucket_secs).min(until);
+                Bucket {
+                    start,
+                    uptime_secs: sessions.iter().map(|s| s.overlap(start, end)).sum(),
+                    ..Bucket::default()
+                }
+            })
+            .collect();
+
+        let mut stmt = self.conn.prepare(
+            "SELECT (ts - ?1) / ?3, metric, COUNT(*), SUM(value), MAX(value) FROM samples
+             WHERE ts >= ?1 AND ts < ?2 GROUP BY 1, 2",
+        )?;
+        let mut rows = stmt.query(params![since, until, bucket_secs.max(1)])?;
+        while let Some(row) = rows.next()? {
+            let idx: i64 = row.get(0)?;
+            let Some(bucket) = buckets.get_mut(idx as usize) else {
+                continue;
+            };
+            let metric: String = row.get(1)?;
+            let (count, sum, max): (i64, i64, i64) = (row.get(2)?, row.get(3)?, row.get(4)?);
+            match metric.as_str() {
+                "connections" => {
+                    bucket.avg_connections = Some(sum as f64 / count as f64);
+                    bucket.max_connections = max as u64;
+                }
+                "announce_ok" => bucket.announce_ok = count as u64,
+                "announce_failed" => bucket.announce_failed = count as u64,
+                "tokens_served" => bucket.tokens_served = sum as u64,
+                "sessions_served" => bucket.sessions_served = sum as u64,
+                _ => {}
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// Start of the oldest recorded session, if any.
+    pub fn first_seen(&self) -> Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row("SELECT MIN(started_at) FROM uptime", [], |r| r.get(0))
+            .optional()?
+            .flatten())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UptimeSession {
+    pub started_at: i64,
+    pub last_seen: i64,
+    /// `None` while running, or when the process died without closing it.
+    pub ended_at: Option<i64>,
+}
+
+impl UptimeSession {
+    /// Seconds of this session inside `[start, end)`.
+    pub fn overlap(&self, start: i64, end: i64) -> u64 {
+        let from = self.started_at.max(start);
+        let to = self.ended_at.unwrap_or(self.last_seen).min(end);
+        (to - from).max(0) as u64
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Bucket {
+    /// Unix time the bucket starts at.
+    pub start: i64,
+    pub uptime_secs: u64,
+    /// `None` when no connection sample fell in the bucket.
+    pub avg_connections: Option<f64>,
+    pub max_connections: u64,
+    pub announce_ok: u64,
+    pub announce_failed: u64,
+    pub tokens_served: u64,
+    pub sessions_served: u64,
+}
+
+// ---------------------------------------------------------------------------
+// Recording
+// ---------------------------------------------------------------------------
+
+/// Run `f` against the default store on the blocking pool, logging failures.
+fn spawn_write<F>(what: &'static str, f: F)
+where
+    F: FnOnce(&HistoryStore) -> Result<()> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = HistoryStore::open_default().and_then(|store| f(&store)) {
+            debug!("History: could not record {what}: {e:#}");
+        }
+    });
+}
+
+/// Record one sample from an async context without blocking it.
+pub fn record(metric: Metric, value: u64) {
+    let now = now_secs();
+    spawn_write(metric.as_str(), move |store| {
+        store.record(metric, value, now)
+    });
+}
+
+/// The node's uptime session, opened when the node starts.
+pub struct NodeSession {
+    id: Option<i64>,
+}
+
+impl NodeSession {
+    pub fn start() -> Self {
+        let id = HistoryStore::open_default()
+            .and_then(|store| store.start_session(now_secs()))
+            .map_err(|e| debug!("History: could not start session: {e:#}"))
+            .ok();
+        Self { id }
+    }
+
+    /// Extend the session and record the current connection count.
+    pub fn sample(&self, connections: Option<usize>) {
+        let Some(id) = self.id else { return };
+        let now = now_secs();
+        spawn_write("connection sample", move |store| {
+            store.touch_session(id, now)?;
+            if let Some(n) = connections {
+                store.record(Metric::Connections, n as u64, now)?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Close the session; called on clean shutdown.
+    pub fn end(self) {
+        let Some(id) = self.id else { return };
+        if let Err(e) = HistoryStore::open_default().and_then(|s| s.end_session(id, now_secs())) {
+            debug!("History: could not end session: {e:#}");
+        }
+    }
+}
+
+static TOKENS_SERVED: AtomicU64 = AtomicU64::new(0);
+static SESSIONS_SERVED: AtomicU64 = AtomicU64::new(0);
+
+/// Count work served by this process; written out by [`run_flush`].
+pub fn count_served(tokens: u64, new_session: bool) {
+    TOKENS_SERVED.fetch_add(tokens, Ordering::Relaxed);
+    if new_session {
+        SESSIONS_SERVED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Write the served-work counters every [`FLUSH_INTERVAL`]. Runs for the
+/// life of the shard server.
+pub async fn run_flush() {
+    let mut tick = tokio::time::interval(FLUSH_INTERVAL);
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let tokens = TOKENS_SERVED.swap(0, Ordering::Relaxed);
+        let sessions = SESSIONS_SERVED.swap(0, Ordering::Relaxed);
+        if tokens == 0 && sessions == 0 {
+            continue;
+        }
+        let now = now_secs();
+        spawn_write("served work", move |store| {
+            store.record(Metric::TokensServed, tokens, now)?;
+            store.record(Metric::SessionsServed, sessions, now)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> (HistoryStore, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        (
+            HistoryStore::open(&dir.path().join("history.db")).unwrap(),
+            dir,
+        )
+    }
+
+    #[test]
+    fn sessions_count_uptime_per_bucket() {
+        let (store, _dir) = temp_store();
+        let a = store.start_session(1_000).unwrap();
+        store.touch_session(a, 1_500).unwrap();
+        store.end_session(a, 1_600).unwrap();
+        // Crashed: never closed, counts to its last heartbeat.
+        let b = store.start_session(2_000).unwrap();
+        store.touch_session(b, 2_300).unwrap();
+
+        let buckets = store.buckets(1_000, 3_000, 1_000).unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].uptime_secs, 600);
+        assert_eq!(buckets[1].uptime_secs, 300);
+        assert_eq!(store.first_seen().unwrap(), Some(1_000));
+    }
+
+    #[test]
+    fn samples_aggregate_by_metric() {
+        let (store, _dir) = temp_store();
+        store.record(Metric::Connections, 4, 10).unwrap();
+        store.record(Metric::Connections, 8, 20).unwrap();
+        store.record(Metric::AnnounceOk, 1, 30).unwrap();
+        store.record(Metric::AnnounceFailed, 1, 40).unwrap();
+        store.record(Metric::AnnounceOk, 1, 50).unwrap();
+        store.record(Metric::TokensServed, 120, 60).unwrap();
+        store.record(Metric::TokensServed, 30, 150).unwrap();
+        store.record(Metric::SessionsServed, 2, 150).unwrap();
+
+        let buckets = store.buckets(0, 200, 100).unwrap();
+        assert_eq!(buckets[0].avg_connections, Some(6.0));
+        assert_eq!(buckets[0].max_connections, 8);
+        assert_eq!((buckets[0].announce_ok, buckets[0].announce_failed), (2, 1));
+        assert_eq!(buckets[0].tokens_served, 120);
+        assert_eq!(buckets[1].avg_connections, None);
+        assert_eq!(buckets[1].tokens_served, 30);
+        assert_eq!(buckets[1].sessions_served, 2);
+    }
+}
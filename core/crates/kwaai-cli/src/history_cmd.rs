@@ -0,0 +1,182 @@
+//! `kwaainet history` — uptime, connections, announces and served work from
+//! the local run history, as a table with sparkline charts or as JSON for
+//! external dashboards.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cli::HistoryArgs;
+use crate::display::*;
+use crate::history::{Bucket, HistoryStore, UptimeSession};
+
+/// Everything `--json` exports.
+#[derive(Serialize)]
+struct HistoryExport {
+    generated_at: i64,
+    since: i64,
+    until: i64,
+    bucket_secs: i64,
+    totals: Totals,
+    sessions: Vec<UptimeSession>,
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Serialize, Default)]
+struct Totals {
+    uptime_secs: u64,
+    uptime_percent: f64,
+    sessions: usize,
+    avg_connections: Option<f64>,
+    max_connections: u64,
+    announce_ok: u64,
+    announce_failed: u64,
+    tokens_served: u64,
+    sessions_served: u64,
+}
+
+impl Totals {
+    fn from_buckets(buckets: &[Bucket], window_secs: i64, sessions: usize) -> Self {
+        let conn: Vec<f64> = buckets.iter().filter_map(|b| b.avg_connections).collect();
+        let uptime_secs = buckets.iter().map(|b| b.uptime_secs).sum();
+        Totals {
+            uptime_secs,
+            uptime_percent: 100.0 * uptime_secs as f64 / window_secs.max(1) as f64,
+            sessions,
+            avg_connections: (!conn.is_empty())
+                .then(|| conn.iter().sum::<f64>() / conn.len() as f64),
+            max_connections: buckets.iter().map(|b| b.max_connections).max().unwrap_or(0),
+            announce_ok: buckets.iter().map(|b| b.announce_ok).sum(),
+            announce_failed: buckets.iter().map(|b| b.announce_failed).sum(),
+            tokens_served: buckets.iter().map(|b| b.tokens_served).sum(),
+            sessions_served: buckets.iter().map(|b| b.sessions_served).sum(),
+        }
+    }
+}
+
+pub fn run(args: HistoryArgs, json: bool) -> Result<()> {
+    let store = HistoryStore::open_default()?;
+
+    // Buckets are aligned to UTC hours/days so consecutive runs line up.
+    let bucket_secs: i64 = if args.hourly { 3_600 } else { 86_400 };
+    let now = chrono::Utc::now().timestamp();
+    let count = if args.hourly {
+        i64::from(args.days) * 24
+    } else {
+        i64::from(args.days)
+    }
+    .max(1);
+    let since = (now / bucket_secs - (count - 1)) * bucket_secs;
+
+    let buckets = store.buckets(since, now, bucket_secs)?;
+    let sessions = store.sessions(since, now)?;
+    let totals = Totals::from_buckets(&buckets, now - since, sessions.len());
+
+    if json {
+        let export = HistoryExport {
+            generated_at: now,
+            since,
+            until: now,
+            bucket_secs,
+            totals,
+            sessions,
+            buckets,
+        };
+        println!("{}", serde_json::to_string_pretty(&export)?);
+        return Ok(());
+    }
+
+    print_box_header(&format!("📈 Node History — last {} day(s)", args.days));
+
+    if store.first_seen()?.is_none() {
+        print_info("No history recorded yet.");
+        print_info("It is collected while the node runs: kwaainet start --daemon");
+        return Ok(());
+    }
+
+    println!(
+        "  Uptime:        {} ({:.1}%) over {} session(s)",
+        format_uptime(totals.uptime_secs),
+        totals.uptime_percent,
+        totals.sessions
+    );
+    match totals.avg_connections {
+        Some(avg) => println!(
+            "  Connections:   {avg:.1} avg, {} max",
+            totals.max_connections
+        ),
+        None => println!("  Connections:   no samples"),
+    }
+    println!(
+        "  Announces:     {} ok, {} failed",
+        totals.announce_ok, totals.announce_failed
+    );
+    println!(
+        "  Served:        {} tokens in {} session(s)",
+        totals.tokens_served, totals.sessions_served
+    );
+    println!();
+
+    let label = |b: &Bucket| {
+        let format = if args.hourly {
+            "%m-%d %H:00"
+        } else {
+            "%Y-%m-%d"
+        };
+        chrono::DateTime::from_timestamp(b.start, 0)
+            .map(|t| t.format(format).to_string())
+            .unwrap_or_default()
+    };
+    println!(
+        "  {:<12}  {:>7}  {:>9}  {:>9}  {:>10}  {:>8}",
+        "UTC", "UPTIME", "CONN AVG", "ANNOUNCE", "TOKENS", "SESSIONS"
+    );
+    println!("  {}", "─".repeat(67));
+    for b in &buckets {
+        let uptime = 100.0 * b.uptime_secs as f64 / bucket_secs as f64;
+        println!(
+            "  {:<12}  {:>6.1}%  {:>9}  {:>9}  {:>10}  {:>8}",
+            label(b),
+            uptime.min(100.0),
+            b.avg_connections
+                .map(|c| format!("{c:.1}"))
+                .unwrap_or_else(|| "-".to_string()),
+            format!("{}/{}", b.announce_ok, b.announce_ok + b.announce_failed),
+            b.tokens_served,
+            b.sessions_served,
+        );
+    }
+    println!();
+
+    let series = |f: fn(&Bucket) -> f64| buckets.iter().map(f).collect::<Vec<_>>();
+    println!(
+        "  Uptime       {}",
+        sparkline(&series(|b| b.uptime_secs as f64))
+    );
+    println!(
+        "  Connections  {}",
+        sparkline(&series(|b| b.avg_connections.unwrap_or(0.0)))
+    );
+    println!(
+        "  Tokens       {}",
+        sparkline(&series(|b| b.tokens_served as f64))
+    );
+    println!();
+    print_info("Export for dashboards: kwaainet history --json");
+    Ok(())
+}
+
+/// One block character per value, scaled to the largest.
+fn sparkline(values: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().cloned().fold(0.0f64, f64::max);
+    values
+        .iter()
+        .map(|&v| {
+            if max <= 0.0 || v <= 0.0 {
+                ' '
+            } else {
+                BARS[((v / max) * 7.0).round() as usize]
+            }
+        })
+        .collect()
+}
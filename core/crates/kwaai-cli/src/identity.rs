@@ -28,8 +28,7 @@ use kwaai_trust::{verify, CredentialStore, TrustScore, VerifiableCredential};
 
 /// The node's persistent Ed25519 identity
 pub struct NodeIdentity {
-    /// The full keypair — retained for Phase 4 peer endorsement signing
-    #[allow(dead_code)]
+    /// The full keypair — signs DHT requests in private swarms
     pub keypair: Keypair,
     pub peer_id: PeerId,
 }
@@ -82,40 +82,6 @@ async fn read_frame<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Vec<u8>>
     Ok(buf)
 }
 
-// ── Protocol helpers ──────────────────────────────────────────────────────────
-
-/// Read and discard the gogo-protobuf delimited `StreamInfo` message that
-/// go-libp2p-daemon sends to a registered stream handler before piping data.
-///
-/// Wire format: varint(len) || proto_bytes  (same varint encoding as protobuf).
-async fn read_p2pd_stream_info<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<()> {
-    // Decode varint length prefix (≤ 10 bytes for u64).
-    let mut len: u64 = 0;
-    let mut shift = 0u32;
-    for _ in 0..10 {
-        let mut b = [0u8; 1];
-        reader
-            .read_exact(&mut b)
-            .await
-            .context("read p2pd StreamInfo varint")?;
-        len |= ((b[0] & 0x7F) as u64) << shift;
-        if b[0] & 0x80 == 0 {
-            break;
-        }
-        shift += 7;
-        if shift > 63 {
-            anyhow::bail!("p2pd StreamInfo varint overflow");
-        }
-    }
-    // Read and discard the message body.
-    let mut buf = vec![0u8; len as usize];
-    reader
-        .read_exact(&mut buf)
-        .await
-        .context("read p2pd StreamInfo body")?;
-    Ok(())
-}
-
 // ── Server ────────────────────────────────────────────────────────────────────
 
 /// Start the inference-mux server: binds a local TCP port, registers it with
@@ -123,20 +89,29 @@ async fn read_p2pd_stream_info<R: AsyncReadExt + Unpin>(reader: &mut R) -> Resul
 ///
 /// Call from `cmd_shard_serve()` alongside the unary proxy handlers.
 pub async fn start_inference_mux_server(client: &mut P2PClient) -> Result<JoinHandle<()>> {
+    let (addr, handle) = serve_inference_mux().await?;
+    if let Err(e) = client
+        .register_stream_handler(&addr, vec![MUX_PROTO.to_string()])
+        .await
+    {
+        handle.abort();
+        return Err(e).context("register inference-mux stream handler");
+    }
+    info!("inference-mux: listening on {addr}, registered as {MUX_PROTO}");
+    Ok(handle)
+}
+
+/// Bind a local TCP port and spawn the inference-mux accept loop without
+/// registering it with the daemon. Returns the multiaddr to register for
+/// `MUX_PROTO`.
+pub async fn serve_inference_mux() -> Result<(String, JoinHandle<()>)> {
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
         .context("bind inference-mux server")?;
     let port = listener.local_addr()?.port();
     let addr = format!("/ip4/127.0.0.1/tcp/{port}");
 
-    client
-        .register_stream_handler(&addr, vec![MUX_PROTO.to_string()])
-        .await
-        .context("register inference-mux stream handler")?;
-
-    info!("inference-mux: listening on {addr}, registered as {MUX_PROTO}");
-
-    Ok(tokio::spawn(async move {
+    let handle = tokio::spawn(async move {
         loop {
             match listener.accept().await {
                 Ok((stream, peer)) => {
@@ -149,23 +124,28 @@ pub async fn start_inference_mux_server(client: &mut P2PClient) -> Result<JoinHa
                 }
             }
         }
-    }))
+    });
+    Ok((addr, handle))
 }
 
 /// Handle one connected client stream — reads MuxRequest frames concurrently,
 /// calls local Ollama for each, writes MuxResponse frames back in any order.
-async fn handle_mux_stream_server(stream: TcpStream) {
-    let (mut reader, writer) = stream.into_split();
-    let writer = Arc::new(Mutex::new(writer));
-
-    // go-libp2p-daemon sends a gogo-protobuf StreamInfo message before piping data.
-    // Consume it before entering the mux frame loop.
-    if let Err(e) = read_p2pd_stream_info(&mut reader).await {
-        warn!("inference-mux server: failed to read p2pd StreamInfo prologue: {e}");
-        return;
+async fn handle_mux_stream_server(mut stream: TcpStream) {
+    // go-libp2p-daemon sends a gogo-protobuf StreamInfo message before piping
+    // data; it names the remote peer the filter is checked against.
+    match crate::peer_filter::admit_stream(&mut stream).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return,
+        Err(e) => {
+            warn!("inference-mux server: failed to read p2pd StreamInfo prologue: {e:#}");
+            return;
+        }
     }
     debug!("inference-mux server: StreamInfo prologue consumed — entering mux frame loop");
 
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+
     loop {
         let frame = match read_frame(&mut reader).await {
             Ok(f) => f,
@@ -271,7 +251,7 @@ impl InferenceMuxClient {
         #[cfg(unix)]
         let addr = format!("/unix/{sock}");
         #[cfg(not(unix))]
-        let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+        let addr = kwaai_p2p_daemon::default_daemon_addr();
 
         let p2p = P2PClient::connect(&addr)
             .await
@@ -619,3 +599,29 @@ fn parse_http_request(raw: &[u8]) -> Option<(String, String, Vec<u8>)> {
     let path = parts.next()?.to_string();
     Some((method, path, body))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn filtered_peer_stream_is_closed() {
+        let denied = PeerId::random();
+        crate::peer_filter::deny_for_test(&[&denied.to_base58()]);
+        let addr: libp2p::Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+
+        let (mut client, server) =
+            crate::peer_filter::forwarded_stream(&denied, &addr, MUX_PROTO).await;
+        handle_mux_stream_server(server).await;
+        assert_eq!(client.read(&mut [0u8; 1]).await.unwrap(), 0);
+
+        // Anyone else gets the frame loop, which runs until the client leaves.
+        let (client, server) =
+            crate::peer_filter::forwarded_stream(&PeerId::random(), &addr, MUX_PROTO).await;
+        let serving = tokio::spawn(handle_mux_stream_server(server));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!serving.is_finished());
+        drop(client);
+        serving.await.unwrap();
+    }
+}
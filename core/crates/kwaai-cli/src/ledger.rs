@@ -0,0 +1,424 @@
+//! Contribution ledger: a signed receipt for every forward request this node
+//! serves, kept in SQLite at `~/.kwaainet/ledger.db`.
+//!
+//! Each receipt names the requester, the blocks and number of positions
+//! processed and when, and is signed with the node's identity key, so it
+//! can later be presented to a reward settlement layer and checked by
+//! anyone holding the receipt. Nothing is settled here yet; this is the
+//! local record such a layer would start from.
+//!
+//! The shard server signs and writes receipts on a dedicated thread so the
+//! request path only pays for a channel send. Read back by
+//! `kwaainet ledger` (see `ledger_cmd.rs`) and `GET /admin/ledger` on
+//! `kwaainet serve`.
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use libp2p::identity::{Keypair, PublicKey};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::config::kwaainet_dir;
+
+/// Domain tag prefixed to the signed bytes, versioning the receipt format.
+pub const RECEIPT_DOMAIN: &str = "kwaainet-receipt-v1";
+
+/// Receipts written per transaction at most.
+const WRITE_BATCH: usize = 256;
+
+pub fn ledger_db() -> PathBuf {
+    kwaainet_dir().join("ledger.db")
+}
+
+/// One served forward request, before it is signed.
+#[derive(Debug, Clone)]
+pub struct ReceiptDraft {
+    /// Base58 peer ID of the caller; `None` for local bypass requests.
+    pub requester: Option<String>,
+    pub session_id: u64,
+    pub start_block: u32,
+    pub end_block: u32,
+    /// Positions run through the blocks.
+    pub tokens: u64,
+    /// Unix time in milliseconds.
+    pub timestamp_ms: i64,
+}
+
+/// A [`ReceiptDraft`] signed by the serving node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// Base58 peer ID of the serving node.
+    pub server: String,
+    pub requester: Option<String>,
+    pub session_id: u64,
+    pub start_block: u32,
+    pub end_block: u32,
+    pub tokens: u64,
+    pub timestamp_ms: i64,
+    /// Hex protobuf encoding of the server's public key.
+    pub public_key: String,
+    /// Hex signature over [`Receipt::signing_bytes`].
+    pub signature: String,
+}
+
+impl Receipt {
+    pub fn sign(draft: ReceiptDraft, keypair: &Keypair) -> Result<Self> {
+        let public = keypair.public();
+        let mut receipt = Receipt {
+            server: public.to_peer_id().to_base58(),
+            requester: draft.requester,
+            session_id: draft.session_id,
+            start_block: draft.start_block,
+            end_block: draft.end_block,
+            tokens: draft.tokens,
+            timestamp_ms: draft.timestamp_ms,
+            public_key: hex::encode(public.encode_protobuf()),
+            signature: String::new(),
+        };
+        let signature = keypair
+            .sign(&receipt.signing_bytes())
+            .context("signing receipt")?;
+        receipt.signature = hex::encode(signature);
+        Ok(receipt)
+    }
+
+    /// The bytes the signature covers: one field per line after
+    /// [`RECEIPT_DOMAIN`], so other implementations can rebuild them.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        format!(
+            "{RECEIPT_DOMAIN}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.server,
+            self.requester.as_deref().unwrap_or(""),
+            self.session_id,
+            self.start_block,
+            self.end_block,
+            self.tokens,
+            self.timestamp_ms
+        )
+        .into_bytes()
+    }
+
+    /// Check that the signature is valid and the key belongs to `server`.
+    pub fn verify(&self) -> Result<()> {
+        let key = hex::decode(&self.public_key).context("public key is not hex")?;
+        let key = PublicKey::try_decode_protobuf(&key).context("decoding public key")?;
+        if key.to_peer_id().to_base58() != self.server {
+            bail!("public key does not belong to server {}", self.server);
+        }
+        let signature = hex::decode(&self.signature).context("signature is not hex")?;
+        if !key.verify(&self.signing_bytes(), &signature) {
+            bail!("signature does not match");
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Store
+// ---------------------------------------------------------------------------
+
+/// Which receipts [`Ledger::query`] returns.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReceiptFilter {
+    /// Requester peer ID or prefix.
+    pub peer: Option<String>,
+    /// Only receipts from the last N days.
+    pub days: Option<u32>,
+    /// Newest N receipts; all when unset.
+    pub limit: Option<usize>,
+}
+
+impl ReceiptFilter {
+    fn since_ms(&self) -> i64 {
+        self.days.map_or(0, |d| {
+            chrono::Utc::now().timestamp_millis() - i64::from(d) * 86_400_000
+        })
+    }
+}
+
+/// Receipts and tokens per requester.
+#[derive(Debug, Clone, Serialize)]
+pub struct RequesterTotal {
+    pub requester: Option<String>,
+    pub requests: u64,
+    pub sessions: u64,
+    pub tokens: u64,
+}
+
+pub struct Ledger {
+    conn: Connection,
+}
+
+impl Ledger {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn =
+            Connection::open(path).with_context(|| format!("opening ledger {}", path.display()))?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS receipts (
+                 id           INTEGER PRIMARY KEY,
+                 server       TEXT NOT NULL,
+                 requester    TEXT,
+                 session_id   INTEGER NOT NULL,
+                 start_block  INTEGER NOT NULL,
+                 end_block    INTEGER NOT NULL,
+                 tokens       INTEGER NOT NULL,
+                 timestamp_ms INTEGER NOT NULL,
+                 public_key   TEXT NOT NULL,
+                 signature    TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS receipts_ts ON receipts (timestamp_ms);
+             CREATE INDEX IF NOT EXISTS receipts_requester ON receipts (requester);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Self::open(&ledger_db())
+    }
+
+    pub fn append(&mut self, receipts: &[Receipt]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO receipts (server, requester, session_id, start_block, end_block,
+                                       tokens, timestamp_ms, public_key, signature)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for r in receipts {
+                stmt.execute(params![
+                    r.server,
+                    r.requester,
+                    r.session_id as i64,
+                    r.start_block,
+                    r.end_block,
+                    r.tokens as i64,
+                    r.timestamp_ms,
+                    r.public_key,
+                    r.signature,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Receipts matching `filter`, newest first.
+    pub fn query(&self, filter: &ReceiptFilter) -> Result<Vec<Receipt>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT server, requester, session_id, start_block, end_block, tokens,
+                    timestamp_ms, public_key, signature
+             FROM receipts
+             WHERE timestamp_ms >= ?1 AND (?2 IS NULL OR requester LIKE ?2 || '%')
+             ORDER BY timestamp_ms DESC, id DESC LIMIT ?3",
+        )?;
+        let limit = filter.limit.map_or(-1, |n| n as i64);
+        let rows = stmt.query_map(params![filter.since_ms(), filter.peer, limit], |r| {
+            Ok(Receipt {
+                server: r.get(0)?,
+                requester: r.get(1)?,
+                session_id: r.get::<_, i64>(2)? as u64,
+                start_block: r.get(3)?,
+                end_block: r.get(4)?,
+                tokens: r.get::<_, i64>(5)? as u64,
+                timestamp_ms: r.get(6)?,
+                public_key: r.get(7)?,
+                signature: r.get(8)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Per-requester totals over `filter`'s time window, most tokens first.
+    pub fn totals(&self, filter: &ReceiptFilter) -> Result<Vec<RequesterTotal>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT requester, COUNT(*), COUNT(DISTINCT session_id), SUM(tokens)
+             FROM receipts
+             WHERE timestamp_ms >= ?1 AND (?2 IS NULL OR requester LIKE ?2 || '%')
+             GROUP BY requester ORDER BY 4 DESC",
+        )?;
+        let rows = stmt.query_map(params![filter.since_ms(), filter.peer], |r| {
+            Ok(RequesterTotal {
+                requester: r.get(0)?,
+                requests: r.get::<_, i64>(1)? as u64,
+                sessions: r.get::<_, i64>(2)? as u64,
+                tokens: r.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Recording
+// ---------------------------------------------------------------------------
+
+static WRITER: OnceLock<mpsc::Sender<ReceiptDraft>> = OnceLock::new();
+
+/// Start the thread that signs receipts with `keypair` and writes them.
+/// Called once by the shard server; until then [`submit`] drops receipts.
+pub fn init(keypair: Keypair) {
+    let (tx, rx) = mpsc::channel::<ReceiptDraft>();
+    if WRITER.set(tx).is_err() {
+        return;
+    }
+    let spawned = std::thread::Builder::new()
+        .name("kwaainet-ledger".into())
+        .spawn(move || {
+            let mut ledger = match Ledger::open_default() {
+                Ok(l) => l,
+                Err(e) => {
+                    warn!("Contribution ledger unavailable: {e:#}");
+                    return;
+                }
+            };
+            info!("Contribution ledger: {}", ledger_db().display());
+            while let Ok(first) = rx.recv() {
+                let drafts = std::iter::once(first).chain(rx.try_iter().take(WRITE_BATCH - 1));
+                let receipts: Vec<Receipt> = drafts
+                    .filter_map(|d| {
+                        Receipt::sign(d, &keypair)
+                            .map_err(|e| warn!("Ledger: {e:#}"))
+                            .ok()
+                    })
+                    .collect();
+                if let Err(e) = ledger.append(&receipts) {
+                    warn!(
+                        "Ledger: could not write {} receipt(s): {e:#}",
+                        receipts.len()
+                    );
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Contribution ledger unavailable: {e}");
+    }
+}
+
+/// Queue a receipt for signing and storage.
+pub fn submit(draft: ReceiptDraft) {
+    if let Some(tx) = WRITER.get() {
+        if tx.send(draft).is_err() {
+            debug!("Ledger writer stopped; receipt dropped");
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTTP
+// ---------------------------------------------------------------------------
+
+/// `GET /admin/ledger?peer=&days=&limit=` — receipts and per-requester
+/// totals, newest receipts first (100 unless `limit` is given).
+pub async fn ledger_receipts(Query(mut filter): Query<ReceiptFilter>) -> Response {
+    filter.limit.get_or_insert(100);
+    let result = tokio::task::spawn_blocking(move || -> Result<_> {
+        let ledger = Ledger::open_default()?;
+        let totals = ledger.totals(&filter)?;
+        let receipts = ledger.query(&filter)?;
+        Ok(serde_json::json!({ "totals": totals, "receipts": receipts }))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|r| r);
+    match result {
+        Ok(body) => Json(body).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": { "message": format!("{e:#}"), "type": "server_error" }
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draft(requester: &str, session_id: u64, tokens: u64, timestamp_ms: i64) -> ReceiptDraft {
+        ReceiptDraft {
+            requester: Some(requester.to_string()),
+            session_id,
+            start_block: 0,
+            end_block: 8,
+            tokens,
+            timestamp_ms,
+        }
+    }
+
+    #[test]
+    fn signed_receipt_verifies_and_detects_tampering() {
+        let keypair = Keypair::generate_ed25519();
+        let receipt = Receipt::sign(draft("12D3KooWCaller", 7, 42, 1_000), &keypair).unwrap();
+        assert_eq!(receipt.server, keypair.public().to_peer_id().to_base58());
+        receipt.verify().unwrap();
+
+        let mut inflated = receipt.clone();
+        inflated.tokens = 4_200;
+        assert!(inflated.verify().is_err());
+
+        let mut forged = receipt;
+        forged.server = Keypair::generate_ed25519()
+            .public()
+            .to_peer_id()
+            .to_base58();
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn query_filters_and_totals_per_requester() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ledger = Ledger::open(&dir.path().join("ledger.db")).unwrap();
+        let keypair = Keypair::generate_ed25519();
+        let now = chrono::Utc::now().timestamp_millis();
+        let receipts: Vec<Receipt> = [
+            draft("peerA", 1, 10, now - 3 * 86_400_000),
+            draft("peerA", 1, 1, now - 2_000),
+            draft("peerA", 2, 5, now - 1_000),
+            draft("peerB", 3, 20, now),
+        ]
+        .into_iter()
+        .map(|d| Receipt::sign(d, &keypair).unwrap())
+        .collect();
+        ledger.append(&receipts).unwrap();
+
+        let all = ledger.query(&ReceiptFilter::default()).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0], receipts[3]);
+        all.iter().for_each(|r| r.verify().unwrap());
+
+        let recent_a = ReceiptFilter {
+            peer: Some("peerA".into()),
+            days: Some(1),
+            limit: None,
+        };
+        assert_eq!(ledger.query(&recent_a).unwrap().len(), 2);
+        let totals = ledger.totals(&recent_a).unwrap();
+        assert_eq!(totals.len(), 1);
+        assert_eq!(
+            (totals[0].requests, totals[0].sessions, totals[0].tokens),
+            (2, 2, 6)
+        );
+
+        let newest = ReceiptFilter {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ledger.query(&newest).unwrap(), vec![receipts[3].clone()]);
+    }
+}
@@ -0,0 +1,170 @@
+//! `kwaainet ledger` — list, total and verify the signed receipts in the
+//! local contribution ledger.
+
+use anyhow::Result;
+
+use crate::cli::{LedgerAction, LedgerArgs};
+use crate::display::*;
+use crate::ledger::{Ledger, ReceiptFilter};
+
+pub fn run(args: LedgerArgs, json: bool) -> Result<()> {
+    let ledger = Ledger::open_default()?;
+    match args.action {
+        LedgerAction::List { peer, days, limit } => list(
+            &ledger,
+            &ReceiptFilter {
+                peer,
+                days,
+                limit: Some(limit),
+            },
+            json,
+        ),
+        LedgerAction::Summary { days } => summary(
+            &ledger,
+            &ReceiptFilter {
+                days,
+                ..Default::default()
+            },
+            json,
+        ),
+        LedgerAction::Verify => verify(&ledger, json),
+    }
+}
+
+fn requester_label(requester: Option<&str>) -> String {
+    requester.map_or_else(|| "local".to_string(), |p| truncate(p, 20))
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        format!("{}…", s.chars().take(max - 1).collect::<String>())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// list
+// ---------------------------------------------------------------------------
+
+fn list(ledger: &Ledger, filter: &ReceiptFilter, json: bool) -> Result<()> {
+    let receipts = ledger.query(filter)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&receipts)?);
+        return Ok(());
+    }
+
+    print_box_header("🧾 Contribution Ledger");
+    if receipts.is_empty() {
+        print_info("No receipts recorded yet.");
+        print_info("Receipts are written while `kwaainet shard serve` answers requests.");
+        return Ok(());
+    }
+
+    println!(
+        "  {:<19}  {:<20}  {:>10}  {:>9}  {:>7}",
+        "TIME (UTC)", "REQUESTER", "SESSION", "BLOCKS", "TOKENS"
+    );
+    println!("  {}", "─".repeat(73));
+    for r in &receipts {
+        let time = chrono::DateTime::from_timestamp_millis(r.timestamp_ms)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        println!(
+            "  {:<19}  {:<20}  {:>10}  {:>9}  {:>7}",
+            time,
+            requester_label(r.requester.as_deref()),
+            r.session_id,
+            format!("{}–{}", r.start_block, r.end_block),
+            r.tokens,
+        );
+    }
+    println!("  {}", "─".repeat(73));
+    println!("  {} receipt(s) shown.", receipts.len());
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// summary
+// ---------------------------------------------------------------------------
+
+fn summary(ledger: &Ledger, filter: &ReceiptFilter, json: bool) -> Result<()> {
+    let totals = ledger.totals(filter)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&totals)?);
+        return Ok(());
+    }
+
+    print_box_header("🧾 Contribution Summary");
+    if totals.is_empty() {
+        print_info("No receipts recorded yet.");
+        return Ok(());
+    }
+
+    println!(
+        "  {:<20}  {:>10}  {:>10}  {:>12}",
+        "REQUESTER", "REQUESTS", "SESSIONS", "TOKENS"
+    );
+    println!("  {}", "─".repeat(58));
+    for t in &totals {
+        println!(
+            "  {:<20}  {:>10}  {:>10}  {:>12}",
+            requester_label(t.requester.as_deref()),
+            t.requests,
+            t.sessions,
+            t.tokens
+        );
+    }
+    println!("  {}", "─".repeat(58));
+    println!(
+        "  {:<20}  {:>10}  {:>10}  {:>12}",
+        "total",
+        totals.iter().map(|t| t.requests).sum::<u64>(),
+        totals.iter().map(|t| t.sessions).sum::<u64>(),
+        totals.iter().map(|t| t.tokens).sum::<u64>()
+    );
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// verify
+// ---------------------------------------------------------------------------
+
+fn verify(ledger: &Ledger, json: bool) -> Result<()> {
+    let receipts = ledger.query(&ReceiptFilter::default())?;
+    let invalid: Vec<_> = receipts
+        .iter()
+        .filter_map(|r| r.verify().err().map(|e| (r, e)))
+        .collect();
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "checked": receipts.len(),
+                "invalid": invalid
+                    .iter()
+                    .map(|(r, e)| serde_json::json!({ "receipt": r, "error": format!("{e:#}") }))
+                    .collect::<Vec<_>>(),
+            }))?
+        );
+    } else if invalid.is_empty() {
+        print_success(&format!("All {} receipt(s) verified.", receipts.len()));
+    } else {
+        for (r, e) in &invalid {
+            print_error(&format!(
+                "session {} at {}: {e:#}",
+                r.session_id, r.timestamp_ms
+            ));
+        }
+        print_error(&format!(
+            "{} of {} receipt(s) failed verification.",
+            invalid.len(),
+            receipts.len()
+        ));
+    }
+    if !invalid.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Where `kwaainet serve` accepts connections (`api_listen` in config.yaml,
+//! `--listen`).
+//!
+//! Either a TCP address (`127.0.0.1:11435`, `[::]:8080`) or, on Unix, a
+//! domain socket (`unix:/run/kwaainet/api.sock`). A socket avoids port
+//! clashes on shared hosts and leaves access control to file permissions:
+//! it is created mode 0660, so granting a group access is a `chgrp` away.
+//! Requests over the socket count as local for the admin routes.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+/// Port of the API server when nothing else is configured.
+pub const DEFAULT_API_PORT: u16 = 11435;
+
+/// Address the API server listens on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiListen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Default for ApiListen {
+    fn default() -> Self {
+        Self::port(DEFAULT_API_PORT)
+    }
+}
+
+impl ApiListen {
+    /// All interfaces on `port`.
+    pub fn port(port: u16) -> Self {
+        Self::Tcp(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+    }
+
+    /// Whether something already accepts connections there.
+    pub fn in_use(&self) -> bool {
+        match self {
+            Self::Tcp(addr) => match std::net::TcpListener::bind(addr) {
+                Ok(_) => false,
+                Err(e) => e.kind() == std::io::ErrorKind::AddrInUse,
+            },
+            #[cfg(unix)]
+            Self::Unix(path) => std::os::unix::net::UnixStream::connect(path).is_ok(),
+            #[cfg(not(unix))]
+            Self::Unix(_) => false,
+        }
+    }
+}
+
+impl FromStr for ApiListen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if !cfg!(unix) {
+                anyhow::bail!("unix sockets are not supported on this platform");
+            }
+            if path.is_empty() {
+                anyhow::bail!("unix: needs a socket path, e.g. unix:/run/kwaainet/api.sock");
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.parse().map(Self::Tcp).map_err(|_| {
+            anyhow::anyhow!("expected HOST:PORT or unix:PATH (e.g. 127.0.0.1:11435), got {s:?}")
+        })
+    }
+}
+
+impl fmt::Display for ApiListen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Serve HTTP/1.1 requests from one connection until it closes.
+///
+/// `peer` is what handlers see through `ConnectInfo<SocketAddr>`.
+pub async fn serve_connection<I>(io: I, app: Router, peer: SocketAddr)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app = app.layer(Extension(ConnectInfo(peer)));
+    if let Err(e) = http1::Builder::new()
+        .serve_connection(TokioIo::new(io), TowerToHyperService::new(app))
+        .with_upgrades()
+        .await
+    {
+        debug!("API connection from {} ended: {}", peer, e);
+    }
+}
+
+/// Create the Unix socket at `path`, mode 0660.
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use anyhow::Context;
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    // A socket file left by a server that exited blocks bind(); the caller
+    // has checked nothing listens on it any more.
+    if path.exists() {
+        std::fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("binding {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+/// Serve `app` on a Unix socket until the process exits.
+#[cfg(unix)]
+pub async fn serve_unix(listener: tokio::net::UnixListener, app: Router) -> anyhow::Result<()> {
+    let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_connection(stream, app.clone(), local));
+            }
+            Err(e) => {
+                tracing::warn!("API server accept failed: {}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tcp_and_unix_addresses() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<ApiListen>().unwrap(),
+            ApiListen::Tcp(SocketAddr::from(([127, 0, 0, 1], 8080)))
+        );
+        assert!("[::1]:8080".parse::<ApiListen>().is_ok());
+        assert!("localhost".parse::<ApiListen>().is_err());
+        assert!("unix:".parse::<ApiListen>().is_err());
+        #[cfg(unix)]
+        {
+            let listen: ApiListen = "unix:/run/kwaainet/api.sock".parse().unwrap();
+            assert_eq!(listen, ApiListen::Unix("/run/kwaainet/api.sock".into()));
+            assert_eq!(listen.to_string(), "unix:/run/kwaainet/api.sock");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_over_unix_socket_as_local_client() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+        // A stale socket file from an earlier run is replaced.
+        std::fs::write(&path, b"").unwrap();
+        let listener = bind_unix(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let app = Router::new().route(
+            "/v1/models",
+            axum::routing::get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move {
+                peer.ip().is_loopback().to_string()
+            }),
+        );
+        tokio::spawn(serve_unix(listener, app));
+        assert!(ApiListen::Unix(path.clone()).in_use());
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /v1/models HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("true"), "{response}");
+    }
+}
@@ -0,0 +1,236 @@
+//! Whether a running node is actually working, not just alive as a PID.
+//!
+//! The node event loop writes a heartbeat to `run/kwaainet.heartbeat` every
+//! [`BEAT_INTERVAL`], with the time of its last successful announce and the
+//! last time a DHT peer answered it or sent it a request. A node whose PID
+//! exists but whose heartbeat is older than [`HUNG_AFTER`] is hung:
+//! `kwaainet status` says so.
+//!
+//! The same beat feeds a watchdog so service managers restart a hung node on
+//! their own. Under systemd it pings the unit's `WatchdogSec=`; launchd has
+//! no watchdog, so there the node watches its own heartbeat from a separate
+//! thread (`KWAAINET_WATCHDOG_SECS`, set in the plist) and exits for
+//! `KeepAlive` to restart it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+/// How often the node event loop writes its heartbeat.
+pub const BEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A heartbeat this old means the node is hung. Generous, since a single
+/// event loop step (an announce round, an auto-update) can take minutes.
+pub const HUNG_AFTER: Duration = Duration::from_secs(300);
+
+/// How long a node may take to reach its event loop before a missing
+/// heartbeat counts as hung. Startup bootstraps, loads the model and
+/// announces, which can take several minutes on a slow link.
+const STARTUP_GRACE: Duration = Duration::from_secs(900);
+
+/// Set by the launchd plist: exit when the event loop has been silent this
+/// many seconds.
+pub const WATCHDOG_ENV: &str = "KWAAINET_WATCHDOG_SECS";
+
+/// Written by the node event loop every [`BEAT_INTERVAL`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub pid: u32,
+    /// Unix time of this beat
+    pub at: u64,
+    /// Unix time of the last announce a bootstrap peer accepted
+    pub last_announce: Option<u64>,
+    /// Unix time a DHT peer last answered the node or sent it a request
+    pub last_dht_response: Option<u64>,
+}
+
+/// What the heartbeat says about the node behind a PID file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Liveness {
+    NotRunning,
+    /// Still starting up; no heartbeat yet
+    Starting,
+    Alive {
+        heartbeat_age_secs: u64,
+    },
+    /// The process exists but its event loop stopped beating
+    Hung {
+        silent_secs: u64,
+    },
+}
+
+impl Liveness {
+    /// Judge the node with PID `pid`, up for `uptime_secs`, from its last
+    /// heartbeat (if any) at unix time `now`.
+    pub fn assess(pid: Option<u32>, uptime_secs: u64, beat: Option<&Heartbeat>, now: u64) -> Self {
+        let Some(pid) = pid else {
+            return Self::NotRunning;
+        };
+        match beat.filter(|b| b.pid == pid) {
+            Some(beat) => {
+                let age = now.saturating_sub(beat.at);
+                if age >= HUNG_AFTER.as_secs() {
+                    Self::Hung { silent_secs: age }
+                } else {
+                    Self::Alive {
+                        heartbeat_age_secs: age,
+                    }
+                }
+            }
+            // A beat from another PID is left over from an earlier run.
+            None if uptime_secs < STARTUP_GRACE.as_secs() => Self::Starting,
+            None => Self::Hung {
+                silent_secs: uptime_secs,
+            },
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Passes the event loop's beats on to the service manager's watchdog.
+pub struct Watchdog {
+    /// Unix time of the last event loop beat; 0 until the loop starts
+    last_beat: Arc<AtomicU64>,
+    #[cfg(unix)]
+    notify: Option<Arc<std::os::unix::net::UnixDatagram>>,
+}
+
+impl Watchdog {
+    /// Set up whichever watchdog the environment asks for. Until the event
+    /// loop's first [`beat`](Self::beat), systemd is pinged from a background
+    /// task so a long startup is not mistaken for a hang.
+    pub fn from_env() -> Self {
+        let watchdog = Self {
+            last_beat: Arc::new(AtomicU64::new(0)),
+            #[cfg(unix)]
+            notify: systemd_notify_socket().map(Arc::new),
+        };
+
+        #[cfg(unix)]
+        if let Some(socket) = watchdog.notify.clone() {
+            debug!("systemd watchdog enabled");
+            let last_beat = watchdog.last_beat.clone();
+            tokio::spawn(async move {
+                let mut tick = tokio::time::interval(BEAT_INTERVAL);
+                while last_beat.load(Ordering::Relaxed) == 0 {
+                    tick.tick().await;
+                    let _ = socket.send(b"WATCHDOG=1");
+                }
+            });
+        }
+
+        let limit = std::env::var(WATCHDOG_ENV)
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|secs| *secs > 0);
+        if let Some(limit) = limit {
+            debug!("Exiting if the event loop is silent for {}s", limit);
+            let last_beat = watchdog.last_beat.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(BEAT_INTERVAL);
+                let last = last_beat.load(Ordering::Relaxed);
+                let silent = unix_now().saturating_sub(last);
+                if last != 0 && silent >= limit {
+                    error!(
+                        "Node event loop silent for {}s — exiting so the service manager restarts it",
+                        silent
+                    );
+                    std::process::exit(1);
+                }
+            });
+        }
+        watchdog
+    }
+
+    /// Record an event loop beat.
+    pub fn beat(&self) {
+        self.last_beat.store(unix_now(), Ordering::Relaxed);
+        #[cfg(unix)]
+        if let Some(socket) = &self.notify {
+            let _ = socket.send(b"WATCHDOG=1");
+        }
+    }
+}
+
+/// `$NOTIFY_SOCKET`, connected, when systemd expects watchdog pings from
+/// this process.
+#[cfg(unix)]
+fn systemd_notify_socket() -> Option<std::os::unix::net::UnixDatagram> {
+    use std::os::unix::net::UnixDatagram;
+
+    std::env::var_os("WATCHDOG_USEC")?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let path = std::env::var("NOTIFY_SOCKET").ok()?;
+    let socket = UnixDatagram::unbound().ok()?;
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name).ok()?;
+        socket.connect_addr(&addr).ok()?;
+        return Some(socket);
+    }
+    socket.connect(&path).ok()?;
+    Some(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beat(pid: u32, at: u64) -> Heartbeat {
+        Heartbeat {
+            pid,
+            at,
+            last_announce: None,
+            last_dht_response: None,
+        }
+    }
+
+    #[test]
+    fn stale_heartbeat_means_hung() {
+        let now = 10_000;
+        assert_eq!(
+            Liveness::assess(Some(7), 600, Some(&beat(7, now - 20)), now),
+            Liveness::Alive {
+                heartbeat_age_secs: 20
+            }
+        );
+        assert_eq!(
+            Liveness::assess(Some(7), 600, Some(&beat(7, now - 300)), now),
+            Liveness::Hung { silent_secs: 300 }
+        );
+        assert_eq!(
+            Liveness::assess(None, 0, Some(&beat(7, now)), now),
+            Liveness::NotRunning
+        );
+    }
+
+    #[test]
+    fn missing_heartbeat_is_starting_until_grace_runs_out() {
+        let now = 10_000;
+        // A beat left behind by a previous PID doesn't count.
+        let old = beat(6, now - 5);
+        assert_eq!(
+            Liveness::assess(Some(7), 60, Some(&old), now),
+            Liveness::Starting
+        );
+        assert_eq!(
+            Liveness::assess(Some(7), 3600, None, now),
+            Liveness::Hung { silent_secs: 3600 }
+        );
+    }
+}
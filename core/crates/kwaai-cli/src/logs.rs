@@ -0,0 +1,494 @@
+//! Log rotation, retention and filtering for `~/.kwaainet/logs`
+//!
+//! The node, shard server and storage API write their logs through stdout
+//! and stderr, which `spawn_daemon_child`, systemd or launchd redirect to a
+//! file opened in append mode. A log is therefore rotated copy-and-truncate
+//! style: its contents are copied to `<name>.<YYYYMMDD-HHMMSS>` and the file
+//! is truncated in place, so every writer keeps its descriptor and carries on
+//! at the new end. Lines written between the copy and the truncation are lost.
+//!
+//! The log subscriber itself is installed here too, with a reloadable
+//! filter so a running node can switch `log_level` without restarting.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use tracing::{debug, warn, Level};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+use crate::config::{LogRotation, LoggingConfig};
+
+/// Logs written by kwaainet processes, rotated by the node
+pub const ACTIVE_LOGS: [&str; 3] = ["kwaainet.log", "shard.log", "storage_serve.log"];
+
+/// Timestamp suffix of rotated files; sorts chronologically as text
+const ROTATED_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// How often the node checks whether its logs need rotating
+const ROTATION_CHECK: Duration = Duration::from_secs(60);
+
+// ---------------------------------------------------------------------------
+// Subscriber
+// ---------------------------------------------------------------------------
+
+/// Handle to swap the filter installed by [`init_tracing`]
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Filter for `level`. hnsw_rs and kwaai_storage are silenced at INFO — they
+/// emit noisy index-load messages that are implementation detail, not
+/// user-facing.
+fn level_filter(level: &str) -> String {
+    format!(
+        "{level},hnsw_rs=warn,kwaai_storage=warn,tantivy=warn,{}={level}",
+        env!("CARGO_PKG_NAME")
+    )
+}
+
+/// Install the subscriber at INFO, or with `RUST_LOG` when set. Logs go to
+/// stderr as text, or with `json` to stdout as one JSON object per line, the
+/// shape container log collectors expect.
+pub fn init_tracing(json: bool) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level_filter("info")));
+    let (filter, handle) = reload::Layer::new(filter);
+    let (text, json) = if json {
+        (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(std::io::stdout),
+            ),
+        )
+    } else {
+        (
+            Some(tracing_subscriber::fmt::layer().with_writer(std::io::stderr)),
+            None,
+        )
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .init();
+    let _ = FILTER.set(handle);
+}
+
+/// Switch to `level` (`log_level` in config.yaml), e.g. `debug` or
+/// `info,kwaai_p2p=trace`. `RUST_LOG` takes precedence: while it is set
+/// this does nothing.
+pub fn set_log_level(level: &str) -> Result<()> {
+    if std::env::var_os("RUST_LOG").is_some() {
+        debug!("RUST_LOG is set — ignoring log_level {}", level);
+        return Ok(());
+    }
+    let filter = EnvFilter::try_new(level_filter(level))
+        .with_context(|| format!("invalid log_level {:?}", level))?;
+    FILTER
+        .get()
+        .context("logging is not initialised")?
+        .reload(filter)
+        .context("reloading the log filter")
+}
+
+// ---------------------------------------------------------------------------
+// Rotation
+// ---------------------------------------------------------------------------
+
+/// Rotated files of `name` in `dir`, oldest first, with their rotation time
+pub fn rotated_files(dir: &Path, name: &str) -> Vec<(DateTime<Utc>, PathBuf)> {
+    let prefix = format!("{}.", name);
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let file_name = e.file_name();
+            let stamp = file_name.to_str()?.strip_prefix(&prefix)?;
+            let at = NaiveDateTime::parse_from_str(stamp, ROTATED_FORMAT).ok()?;
+            Some((at.and_utc(), e.path()))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Rotates and prunes the logs in one directory
+pub struct LogRotator {
+    dir: PathBuf,
+    config: LoggingConfig,
+    /// Day the rotator started; a log never rotated is treated as rotated then
+    started: NaiveDate,
+}
+
+impl LogRotator {
+    pub fn new(dir: PathBuf, config: LoggingConfig) -> Self {
+        Self {
+            dir,
+            config,
+            started: Utc::now().date_naive(),
+        }
+    }
+
+    /// Rotate every active log that is due, then apply retention.
+    ///
+    /// Returns the rotated files that were created.
+    pub fn run_once(&self, now: DateTime<Utc>) -> Vec<PathBuf> {
+        let mut created = Vec::new();
+        for name in ACTIVE_LOGS {
+            match self.rotate_if_due(name, now) {
+                Ok(Some(path)) => created.push(path),
+                Ok(None) => {}
+                Err(e) => warn!("Could not rotate {}: {:#}", name, e),
+            }
+            self.prune(name, now);
+        }
+        created
+    }
+
+    fn is_due(&self, name: &str, len: u64, now: DateTime<Utc>) -> bool {
+        if len == 0 {
+            return false;
+        }
+        let max_bytes = self.config.max_size_mb.saturating_mul(1024 * 1024);
+        if max_bytes > 0 && len >= max_bytes {
+            return true;
+        }
+        if self.config.rotation == LogRotation::Daily {
+            let last = rotated_files(&self.dir, name)
+                .last()
+                .map(|(at, _)| at.date_naive())
+                .unwrap_or(self.started)
+                .max(self.started);
+            return last < now.date_naive();
+        }
+        false
+    }
+
+    fn rotate_if_due(&self, name: &str, now: DateTime<Utc>) -> Result<Option<PathBuf>> {
+        let path = self.dir.join(name);
+        let Ok(meta) = std::fs::metadata(&path) else {
+            return Ok(None);
+        };
+        if !self.is_due(name, meta.len(), now) {
+            return Ok(None);
+        }
+        let rotated = self
+            .dir
+            .join(format!("{}.{}", name, now.format(ROTATED_FORMAT)));
+        std::fs::copy(&path, &rotated)
+            .with_context(|| format!("copying to {}", rotated.display()))?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_len(0))
+            .with_context(|| format!("truncating {}", path.display()))?;
+        debug!("Rotated {} to {}", path.display(), rotated.display());
+        Ok(Some(rotated))
+    }
+
+    /// Delete rotated files beyond `max_files` or older than `max_age_days`
+    fn prune(&self, name: &str, now: DateTime<Utc>) {
+        let files = rotated_files(&self.dir, name);
+        let excess = match self.config.max_files {
+            0 => 0,
+            keep => files.len().saturating_sub(keep),
+        };
+        let max_age = chrono::Duration::days(self.config.max_age_days as i64);
+        for (i, (at, path)) in files.iter().enumerate() {
+            let expired = self.config.max_age_days > 0 && now - *at > max_age;
+            if i < excess || expired {
+                if let Err(e) = std::fs::remove_file(path) {
+                    warn!("Could not delete old log {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Rotate the logs in `dir` every minute, for the lifetime of the node
+pub async fn run_rotation(dir: PathBuf, config: LoggingConfig) {
+    let rotator = std::sync::Arc::new(LogRotator::new(dir, config));
+    let mut interval = tokio::time::interval(ROTATION_CHECK);
+    loop {
+        interval.tick().await;
+        let rotator = rotator.clone();
+        // Copying a log of max_size_mb is blocking file I/O.
+        let _ = tokio::task::spawn_blocking(move || rotator.run_once(Utc::now())).await;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Filtering
+// ---------------------------------------------------------------------------
+
+/// Filters of `kwaainet logs`; an entry must match all of them
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub since: Option<DateTime<Utc>>,
+    /// Least severe level shown
+    pub level: Option<Level>,
+    /// Lowercased text a line must contain
+    pub grep: Option<String>,
+}
+
+impl LogFilter {
+    pub fn new(since: Option<&str>, level: Option<&str>, grep: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            since: since.map(|s| parse_since(s, Utc::now())).transpose()?,
+            level: level
+                .map(|l| {
+                    l.parse()
+                        .map_err(|_| anyhow::anyhow!("Unknown level: {}", l))
+                })
+                .transpose()?,
+            grep: grep.map(str::to_lowercase),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.level.is_none() && self.grep.is_none()
+    }
+}
+
+/// Applies a [`LogFilter`] line by line
+///
+/// Lines that don't start a tracing entry (continuations, panics, p2pd
+/// output) belong to the entry before them; until the first entry is seen
+/// they only pass when no time or level filter is set.
+pub struct LineMatcher<'a> {
+    filter: &'a LogFilter,
+    current: Option<(DateTime<Utc>, Level)>,
+}
+
+impl<'a> LineMatcher<'a> {
+    pub fn new(filter: &'a LogFilter) -> Self {
+        Self {
+            filter,
+            current: None,
+        }
+    }
+
+    pub fn matches(&mut self, line: &str) -> bool {
+        let plain = strip_ansi(line);
+        if let Some(entry) = parse_entry(&plain) {
+            self.current = Some(entry);
+        }
+        if self.filter.since.is_some() || self.filter.level.is_some() {
+            let Some((at, level)) = self.current else {
+                return false;
+            };
+            if self.filter.since.is_some_and(|since| at < since) {
+                return false;
+            }
+            // More verbose levels compare greater.
+            if self.filter.level.is_some_and(|min| level > min) {
+                return false;
+            }
+        }
+        self.filter
+            .grep
+            .as_deref()
+            .is_none_or(|text| plain.to_lowercase().contains(text))
+    }
+}
+
+/// The last `limit` lines of log `name` and its rotated files that match
+pub fn filtered_lines(
+    dir: &Path,
+    name: &str,
+    filter: &LogFilter,
+    limit: usize,
+) -> Result<Vec<String>> {
+    let mut files: Vec<PathBuf> = rotated_files(dir, name)
+        .into_iter()
+        .filter(|(at, _)| filter.since.is_none_or(|since| *at >= since))
+        .map(|(_, path)| path)
+        .collect();
+    files.push(dir.join(name));
+
+    let mut matcher = LineMatcher::new(filter);
+    let mut lines = VecDeque::with_capacity(limit.min(4096));
+    for path in files {
+        let Ok(file) = std::fs::File::open(&path) else {
+            continue;
+        };
+        for line in std::io::BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("reading {}", path.display()))?;
+            if matcher.matches(&line) {
+                if lines.len() == limit {
+                    lines.pop_front();
+                }
+                if limit > 0 {
+                    lines.push_back(line);
+                }
+            }
+        }
+    }
+    Ok(lines.into())
+}
+
+/// Timestamp and level of a line that starts a tracing entry
+fn parse_entry(line: &str) -> Option<(DateTime<Utc>, Level)> {
+    let mut parts = line.split_whitespace();
+    let at = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+    let level = parts.next()?.parse().ok()?;
+    Some((at.with_timezone(&Utc), level))
+}
+
+/// Remove ANSI escape sequences (tracing colours its output by default)
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a byte in '@'..='~'.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parse `--since`: `30s`, `15m`, `2h`, `7d`, a date or an RFC 3339 timestamp
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+    if let Some(unit) = value.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        if let Ok(n) = value[..value.len() - 1].parse::<i64>() {
+            let ago = match unit {
+                's' => chrono::Duration::seconds(n),
+                'm' => chrono::Duration::minutes(n),
+                'h' => chrono::Duration::hours(n),
+                'd' => chrono::Duration::days(n),
+                _ => anyhow::bail!(
+                    "Unknown unit '{}' in --since {}; use s, m, h or d",
+                    unit,
+                    value
+                ),
+            };
+            return Ok(now - ago);
+        }
+    }
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    anyhow::bail!(
+        "Invalid --since {}: expected a duration (30m, 2h, 7d), a date (2025-06-01) or an RFC 3339 timestamp",
+        value
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn config(rotation: LogRotation, max_size_mb: u64, max_files: usize) -> LoggingConfig {
+        LoggingConfig {
+            rotation,
+            max_size_mb,
+            max_files,
+            max_age_days: 0,
+        }
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("kwaainet.log");
+        let rotator = LogRotator::new(dir.path().to_path_buf(), config(LogRotation::Size, 1, 2));
+
+        std::fs::write(&log, "small").unwrap();
+        assert!(rotator.run_once(at("2025-06-01T10:00:00Z")).is_empty());
+
+        for minute in 1..=3 {
+            std::fs::write(&log, vec![b'x'; 1024 * 1024]).unwrap();
+            let now = at(&format!("2025-06-01T10:0{}:00Z", minute));
+            assert_eq!(rotator.run_once(now).len(), 1);
+            assert_eq!(std::fs::metadata(&log).unwrap().len(), 0);
+        }
+        let kept = rotated_files(dir.path(), "kwaainet.log");
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].0, at("2025-06-01T10:02:00Z"));
+        assert!(dir.path().join("kwaainet.log.20250601-100300").exists());
+    }
+
+    #[test]
+    fn daily_rotation_and_age_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("shard.log");
+        let mut cfg = config(LogRotation::Daily, 0, 0);
+        cfg.max_age_days = 7;
+        let mut rotator = LogRotator::new(dir.path().to_path_buf(), cfg);
+        rotator.started = NaiveDate::from_ymd_opt(2025, 6, 1).unwrap();
+        std::fs::write(dir.path().join("shard.log.20250501-000000"), "old").unwrap();
+
+        std::fs::write(&log, "day one").unwrap();
+        assert!(rotator.run_once(at("2025-06-01T23:59:00Z")).is_empty());
+        assert_eq!(rotator.run_once(at("2025-06-02T00:01:00Z")).len(), 1);
+        std::fs::write(&log, "day two").unwrap();
+        assert!(rotator.run_once(at("2025-06-02T12:00:00Z")).is_empty());
+
+        let kept: Vec<_> = rotated_files(dir.path(), "shard.log")
+            .into_iter()
+            .map(|(at, _)| at)
+            .collect();
+        assert_eq!(kept, [at("2025-06-02T00:01:00Z")]);
+    }
+
+    #[test]
+    fn filters_by_level_time_and_text() {
+        let filter = LogFilter {
+            since: Some(at("2025-06-01T10:00:00Z")),
+            level: Some(Level::WARN),
+            grep: Some("relay".to_string()),
+        };
+        let mut m = LineMatcher::new(&filter);
+        assert!(!m.matches("stray line before any entry"));
+        assert!(!m.matches("2025-06-01T09:00:00.000000Z  WARN kwaainet: relay lost"));
+        assert!(!m.matches("2025-06-01T10:30:00.000000Z  INFO kwaainet: relay ok"));
+        assert!(m.matches(
+            "\x1b[2m2025-06-01T10:31:00.000000Z\x1b[0m \x1b[33m WARN\x1b[0m kwaainet: Relay lost"
+        ));
+        // Continuation lines inherit the entry's level and time.
+        assert!(m.matches("    caused by: relay reservation expired"));
+        assert!(m.matches("2025-06-01T10:32:00.000000Z ERROR kwaainet: relay down"));
+    }
+
+    #[test]
+    fn parses_since() {
+        let now = at("2025-06-02T12:00:00Z");
+        assert_eq!(parse_since("2h", now).unwrap(), at("2025-06-02T10:00:00Z"));
+        assert_eq!(parse_since("1d", now).unwrap(), at("2025-06-01T12:00:00Z"));
+        assert_eq!(
+            parse_since("2025-06-01", now).unwrap(),
+            at("2025-06-01T00:00:00Z")
+        );
+        assert_eq!(
+            parse_since("2025-06-01T08:00:00+02:00", now).unwrap(),
+            at("2025-06-01T06:00:00Z")
+        );
+        assert!(parse_since("2w", now).is_err());
+        assert!(parse_since("yesterday", now).is_err());
+    }
+}
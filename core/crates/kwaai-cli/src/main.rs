@@ -1,26 +1,55 @@
 //! kwaainet – KwaaiNet node CLI
 
+mod admission;
 mod api;
+mod api_keys;
+mod bandwidth;
 mod block_rpc;
+mod bootstrap;
 mod calibration;
 mod circuit_breaker;
 mod cli;
 mod config;
+mod config_reload;
+mod config_schema;
+mod control;
 mod daemon;
+mod dht_auth;
+mod dht_backend;
 mod display;
+mod experiments;
+mod experts;
+mod geoip;
 mod grpc_server;
+mod handoff;
 mod health;
 mod hf;
+mod history;
+mod history_cmd;
 mod identity;
 mod inference_mux;
+mod ledger;
+mod ledger_cmd;
+mod listen;
+mod liveness;
 mod llama_local;
+mod logs;
 mod map;
+mod matchmaking;
+mod model_catalog;
+mod model_registry;
+mod models_cmd;
 mod monitor;
+mod network_cmd;
+mod next_pings;
 mod node;
 mod ollama;
 mod ollama_proxy;
 mod p2p_cmd;
+mod peer_filter;
+mod probes;
 mod progress;
+mod public_ip;
 #[cfg(feature = "rag")]
 mod rag_api;
 #[cfg(feature = "rag")]
@@ -28,15 +57,22 @@ mod rag_cmd;
 mod rebalancer;
 mod reputation;
 mod reputation_cmd;
+mod request_queue;
+mod resources;
+mod rpc_limits;
+mod schema;
 mod service;
+mod sessions;
 mod setup;
 mod shard_api;
 mod shard_cmd;
+mod shard_transfer;
 #[cfg(feature = "storage")]
 mod storage;
 #[cfg(feature = "storage")]
 mod storage_rpc;
 mod throughput;
+mod tls;
 mod uninstall;
 mod updater;
 mod vpk;
@@ -44,13 +80,12 @@ mod vpk_bench;
 
 use anyhow::{Context as _, Result};
 use clap::Parser;
-use tracing_subscriber::EnvFilter;
 
-use cli::{Cli, Command, MonitorAction, ServeArgs, ServiceAction};
+use cli::{ApiKeysAction, Cli, Command, MonitorAction, ProfilesAction, ServeArgs, ServiceAction};
 use config::KwaaiNetConfig;
-use daemon::{DaemonManager, ShardManager, StorageApiManager};
+use daemon::{AnnounceState, DaemonManager, ShardManager, StorageApiManager};
 use display::*;
-use kwaai_inference::{EngineConfig, InferenceEngine, InferenceProvider, ModelFormat};
+use kwaai_inference::{EngineConfig, InferenceEngine, InferenceProvider, ModelFormat, ModelHandle};
 
 /// Add the binary's directory to the library search path so bundled CUDA
 /// runtime libraries (.so/.dll) are found by cudarc's dynamic loading.
@@ -98,35 +133,39 @@ fn model_is_locally_available(model: &str) -> bool {
 async fn main() -> Result<()> {
     setup_cuda_library_path();
     let cli = Cli::parse();
+    if let Some(profile) = &cli.profile {
+        config::activate_profile(profile)?;
+    }
 
-    // Initialise logging (RUST_LOG overrides config default).
-    // hnsw_rs and kwaai_storage are silenced at INFO — they emit noisy
-    // index-load messages that are implementation detail, not user-facing.
-    let default_filter = format!(
-        "info,hnsw_rs=warn,kwaai_storage=warn,tantivy=warn,{}=info",
-        env!("CARGO_PKG_NAME")
-    );
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&default_filter)),
-        )
-        .init();
+    // Initialise logging (RUST_LOG overrides config default). Container mode
+    // logs JSON to stdout for the orchestrator's log collector.
+    logs::init_tracing(matches!(&cli.command, Command::Start(a) if a.no_daemon_files));
 
     // Spawn a background update check that runs concurrently with the command.
     // Uses a 24-hour on-disk cache so it only hits the network once per day.
-    // Skipped for `update` (redundant) and `run-node` (internal daemon process).
-    let skip_update_hint = matches!(cli.command, Command::Update(_) | Command::RunNode);
+    // Skipped for `update` (redundant), `run-node` (internal daemon process),
+    // and `completions` / `schema`, whose stdout is consumed by other tools.
+    // Also skipped in --json mode so the output stays parseable.
+    let skip_update_hint = cli.json
+        || matches!(
+            cli.command,
+            Command::Update(_) | Command::RunNode | Command::Completions(_) | Command::Schema(_)
+        )
+        || matches!(&cli.command, Command::Start(a) if a.no_daemon_files);
     let update_task = (!skip_update_hint)
         .then(|| tokio::spawn(async { updater::UpdateChecker::new().check(false).await }));
 
+    let json = cli.json;
     match cli.command {
         // -------------------------------------------------------------------
         // Internal: run the native node (used in daemon mode)
         // -------------------------------------------------------------------
         Command::RunNode => {
             let cfg = KwaaiNetConfig::load_or_create()?;
-            node::run_node(&cfg).await?;
+            if let Err(e) = logs::set_log_level(&cfg.log_level) {
+                tracing::warn!("{:#}", e);
+            }
+            node::run_node(&cfg, &node::RunOptions::default()).await?;
         }
 
         // -------------------------------------------------------------------
@@ -266,9 +305,32 @@ async fn main() -> Result<()> {
 
             let mgr = DaemonManager::new();
 
+            // Container mode: the orchestrator owns the process, so there is no
+            // PID file to check and no lock to take.
+            if args.no_daemon_files {
+                let opts = node::RunOptions {
+                    daemon_files: false,
+                    health_listen: Some(args.health_listen.unwrap_or_else(probes::default_listen)),
+                };
+                if let Err(e) = logs::set_log_level(&cfg.log_level) {
+                    tracing::warn!("{:#}", e);
+                }
+                tracing::info!(
+                    model = %cfg.model,
+                    blocks = cfg.blocks,
+                    port = cfg.port,
+                    "Starting KwaaiNet node in the foreground without daemon files"
+                );
+                node::run_node(&cfg, &opts).await?;
+                return Ok(());
+            }
+
             if mgr.is_running() && !args.concurrent {
                 print_warning("A KwaaiNet node is already running. Use --concurrent to allow multiple instances.");
                 print_info("Stop the existing node with: kwaainet stop");
+                print_info(
+                    "Or run another node with its own config: kwaainet --profile NAME start",
+                );
                 std::process::exit(1);
             }
 
@@ -302,7 +364,12 @@ async fn main() -> Result<()> {
                 let shard_available = shard_explicit || model_is_locally_available(&cfg.model);
                 let enough_ram = shard_explicit || system_total_ram_bytes() >= SHARD_MIN_RAM_BYTES;
                 if policy.shards && shard_available && enough_ram {
-                    match ShardManager::spawn_shard_child() {
+                    // The shard server loads just this many blocks.
+                    let shard_args: Vec<String> = args
+                        .blocks
+                        .map(|b| vec!["--blocks".to_string(), b.to_string()])
+                        .unwrap_or_default();
+                    match ShardManager::spawn_shard_child(&shard_args) {
                         Ok(shard_pid) => {
                             ShardManager::new().write_pid(shard_pid);
                             print_success(&format!("Shard serving started  (PID {})", shard_pid));
@@ -348,7 +415,11 @@ async fn main() -> Result<()> {
                 print_separator();
             } else {
                 // Foreground – run until Ctrl-C
-                node::run_node(&cfg).await?;
+                let opts = node::RunOptions {
+                    health_listen: args.health_listen,
+                    ..Default::default()
+                };
+                node::run_node(&cfg, &opts).await?;
             }
         }
 
@@ -412,7 +483,7 @@ async fn main() -> Result<()> {
                 && model_is_locally_available(&restart_cfg.model)
                 && system_total_ram_bytes() >= SHARD_MIN_RAM_BYTES
             {
-                match ShardManager::spawn_shard_child() {
+                match ShardManager::respawn_shard_child() {
                     Ok(pid) => {
                         ShardManager::new().write_pid(pid);
                         print_success(&format!("Shard serving restarted (PID {})", pid));
@@ -440,6 +511,19 @@ async fn main() -> Result<()> {
             let shard_mgr = ShardManager::new();
             let shard_running = shard_mgr.is_running();
             let shard_pid = shard_mgr.read_pid();
+            // Live view from the node itself; absent while it is still
+            // starting up or when it predates the control socket. A socket
+            // that accepts but never answers points at a hung event loop.
+            let (live, control_stuck) = if status.running {
+                match control::call(control::ControlMethod::Status).await {
+                    Ok(live) => (live, false),
+                    Err(_) => (None, true),
+                }
+            } else {
+                (None, false)
+            };
+            let liveness = mgr.liveness(&status);
+            let heartbeat = mgr.read_heartbeat().filter(|b| Some(b.pid) == status.pid);
 
             if args.json {
                 #[derive(serde::Serialize)]
@@ -449,17 +533,34 @@ async fn main() -> Result<()> {
                     uptime_secs: Option<u64>,
                     cpu_percent: Option<f32>,
                     memory_mb: Option<f64>,
+                    announce_state: Option<&'static str>,
+                    announce_attempts: Option<u32>,
+                    announced_at: Option<u64>,
+                    liveness: liveness::Liveness,
+                    last_announce: Option<u64>,
+                    last_dht_response: Option<u64>,
+                    control_responding: Option<bool>,
                     shard_running: bool,
                     shard_pid: Option<u32>,
+                    live: Option<serde_json::Value>,
                 }
+                let announce = status.announce.as_ref();
                 let out = StatusJson {
                     running: status.running,
                     pid: status.pid,
                     uptime_secs: status.uptime_secs,
                     cpu_percent: status.cpu_percent,
                     memory_mb: status.memory_mb,
+                    announce_state: announce.map(|a| a.state.as_str()),
+                    announce_attempts: announce.map(|a| a.attempts),
+                    announced_at: announce.and_then(|a| a.announced_at),
+                    liveness,
+                    last_announce: heartbeat.as_ref().and_then(|b| b.last_announce),
+                    last_dht_response: heartbeat.as_ref().and_then(|b| b.last_dht_response),
+                    control_responding: status.running.then_some(!control_stuck),
                     shard_running,
                     shard_pid,
+                    live,
                 };
                 println!("{}", serde_json::to_string(&out).unwrap_or_default());
             } else {
@@ -485,6 +586,118 @@ async fn main() -> Result<()> {
                     println!("  ⏱️  Uptime:  {}", uptime);
                     println!("  💻 CPU:     {}", cpu);
                     println!("  🧠 Memory:  {}", mem);
+                    match status.announce {
+                        Some(ref a) if a.state == AnnounceState::Announced => {
+                            println!("  📡 DHT:     Announced");
+                        }
+                        Some(ref a) => {
+                            println!(
+                                "  📡 DHT:     Announcing (attempt {}, retrying in background)",
+                                a.attempts
+                            );
+                        }
+                        None => println!("  📡 DHT:     Starting"),
+                    }
+                    match liveness {
+                        liveness::Liveness::Hung { silent_secs } => {
+                            println!(
+                                "  💔 Health:  Hung — no heartbeat for {}",
+                                format_uptime(silent_secs)
+                            );
+                            print_info("Restart it with: kwaainet restart");
+                        }
+                        _ if control_stuck => {
+                            println!("  💔 Health:  Not answering on the control socket");
+                            print_info("If this persists, restart it with: kwaainet restart");
+                        }
+                        liveness::Liveness::Alive { .. } => {
+                            let now = liveness::unix_now();
+                            let dht = heartbeat
+                                .as_ref()
+                                .and_then(|b| b.last_dht_response)
+                                .map(|t| {
+                                    format!(
+                                        "last DHT response {} ago",
+                                        format_uptime(now.saturating_sub(t))
+                                    )
+                                })
+                                .unwrap_or_else(|| "no DHT response yet".to_string());
+                            println!("  💓 Health:  Alive ({})", dht);
+                        }
+                        _ => {}
+                    }
+                    if let Some(ref live) = live {
+                        println!("  🆔 Peer ID: {}", live["peer_id"].as_str().unwrap_or("?"));
+                        match live["connections"].as_u64() {
+                            Some(n) => println!("  🔗 Peers:   {} connected", n),
+                            None => println!("  🔗 Peers:   unknown (p2pd not answering)"),
+                        }
+                        let reach = if live["using_relay"].as_bool().unwrap_or(false) {
+                            "via relay"
+                        } else {
+                            "direct"
+                        };
+                        let probe = &live["reachability"];
+                        match probe["state"].as_str() {
+                            Some("unreachable") => println!(
+                                "  🌐 Reach:   {} — unreachable ({} failed probe(s))",
+                                reach,
+                                probe["consecutive_failures"].as_u64().unwrap_or(0)
+                            ),
+                            Some(state) if state != "unknown" => {
+                                println!("  🌐 Reach:   {} — verified {}", reach, state)
+                            }
+                            _ => println!("  🌐 Reach:   {}", reach),
+                        }
+                        let dht = &live["dht_health"];
+                        if !dht.is_null() {
+                            let ops = |k: &str| {
+                                format!(
+                                    "{}/{}",
+                                    dht[k]["succeeded"].as_u64().unwrap_or(0),
+                                    dht[k]["succeeded"].as_u64().unwrap_or(0)
+                                        + dht[k]["failed"].as_u64().unwrap_or(0)
+                                )
+                            };
+                            println!(
+                                "  🕸️  Swarm:   {} peer(s), {} in routing table, {} queries pending",
+                                dht["connected_peers"].as_u64().unwrap_or(0),
+                                dht["routing_table_size"].as_u64().unwrap_or(0),
+                                dht["pending_dht_queries"].as_u64().unwrap_or(0)
+                            );
+                            println!(
+                                "             puts {} · provides {} · lookups {} ok",
+                                ops("dht_put"),
+                                ops("dht_provide"),
+                                ops("dht_query")
+                            );
+                        }
+                        let limits = &live["rpc_limits"];
+                        let dropped: u64 = [
+                            "peer_rate_limited",
+                            "global_rate_limited",
+                            "over_capacity",
+                            "peer_over_capacity",
+                            "oversized_messages",
+                            "timed_out",
+                        ]
+                        .iter()
+                        .filter_map(|k| limits[*k].as_u64())
+                        .sum();
+                        if dropped > 0 {
+                            println!("  🛡️  Limits:  {} DHT request(s) dropped", dropped);
+                        }
+                        let running: Vec<&str> = live["experiments"]
+                            .as_array()
+                            .map(|a| a.iter().filter_map(|v| v.as_str()).collect())
+                            .unwrap_or_default();
+                        if !running.is_empty() {
+                            println!("  🧪 Preview: {}", running.join(", "));
+                        }
+                        if live["pending_restart"].as_bool().unwrap_or(false) {
+                            print_info("Address change detected — p2pd restart pending until idle");
+                        }
+                    }
                 } else {
                     println!("  🔴 Status:  Not running");
                     print_info("Start with: kwaainet start --daemon");
@@ -521,42 +734,71 @@ async fn main() -> Result<()> {
         // logs
         // -------------------------------------------------------------------
         Command::Logs(args) => {
-            let log_path = if args.shard {
-                config::log_dir().join("shard.log")
+            let log_name = if args.shard {
+                "shard.log"
             } else if args.storage {
-                config::log_dir().join("storage_serve.log")
+                "storage_serve.log"
             } else {
-                config::log_file()
+                "kwaainet.log"
             };
-
-            if !log_path.exists() {
+            let log_dir = config::log_dir();
+            let log_path = log_dir.join(log_name);
+            let filter = logs::LogFilter::new(
+                args.since.as_deref(),
+                args.level.as_deref(),
+                args.grep.as_deref(),
+            )?;
+
+            if !log_path.exists() && logs::rotated_files(&log_dir, log_name).is_empty() {
                 print_warning("No log file found. Start the node first: kwaainet start --daemon");
                 return Ok(());
             }
 
+            // Filters search the rotated files as well; without them only the
+            // live file is shown, as before.
+            if filter.is_empty() {
+                print_last_lines(&log_path, args.lines);
+            } else {
+                for line in logs::filtered_lines(&log_dir, log_name, &filter, args.lines)? {
+                    println!("{}", line);
+                }
+            }
+
             if args.follow {
                 // Tail -f style
-                let mut pos = {
-                    let meta = std::fs::metadata(&log_path)?;
-                    meta.len()
-                };
-                // Print last N lines first
-                print_last_lines(&log_path, args.lines);
+                let mut pos = std::fs::metadata(&log_path).map(|m| m.len()).unwrap_or(0);
+                let mut matcher = logs::LineMatcher::new(&filter);
+                let mut partial = String::new();
                 loop {
                     tokio::time::sleep(std::time::Duration::from_millis(250)).await;
-                    let meta = std::fs::metadata(&log_path)?;
+                    let Ok(meta) = std::fs::metadata(&log_path) else {
+                        continue;
+                    };
+                    if meta.len() < pos {
+                        // Rotated: the file was truncated in place.
+                        pos = 0;
+                    }
                     if meta.len() > pos {
                         let mut file = std::fs::File::open(&log_path)?;
                         use std::io::{Read, Seek, SeekFrom};
                         file.seek(SeekFrom::Start(pos))?;
                         let mut buf = String::new();
                         file.read_to_string(&mut buf)?;
-                        print!("{}", buf);
                         pos = meta.len();
+                        if filter.is_empty() {
+                            print!("{}", buf);
+                            continue;
+                        }
+                        partial.push_str(&buf);
+                        while let Some(end) = partial.find('\n') {
+                            let line: String = partial.drain(..=end).collect();
+                            let line = line.trim_end_matches(['\r', '\n']);
+                            if matcher.matches(line) {
+                                println!("{}", line);
+                            }
+                        }
                     }
                 }
-            } else {
-                print_last_lines(&log_path, args.lines);
             }
         }
 
@@ -565,6 +807,9 @@ async fn main() -> Result<()> {
         // -------------------------------------------------------------------
         Command::Config(args) => {
             use cli::ConfigAction;
+            if let Some(ConfigAction::Validate) = args.action {
+                return validate_config_command(args.json);
+            }
             let mut cfg = KwaaiNetConfig::load_or_create()?;
 
             match args.action {
@@ -601,13 +846,98 @@ async fn main() -> Result<()> {
                             );
                             println!("  🔐 vpk_access:   P2P relay via /kwaai/storage/1.0.0");
                         }
+                        println!();
+                        for e in experiments::Experiment::ALL {
+                            let on = cfg.experiments.get(e.key()).copied().unwrap_or(false);
+                            println!(
+                                "  🧪 experiments.{:<8} {:<5}  {}",
+                                e.key(),
+                                on,
+                                e.description()
+                            );
+                        }
                         print_separator();
                     }
                 }
+                Some(ConfigAction::Set { key, value }) if key == "model" => {
+                    // Prefer the model as the network serves it, so the node
+                    // announces under the prefix its other servers use.
+                    let catalog = model_catalog::current(&cfg).await;
+                    let entry = catalog.as_ref().and_then(|(c, _)| c.find(&value));
+                    print_box_header("⚙️  Configuration Updated");
+                    match entry {
+                        Some(entry) => {
+                            model_catalog::select(&mut cfg, entry);
+                            cfg.save()?;
+                            print_success(&format!("Set model = {}", cfg.model));
+                            println!(
+                                "  Served by the network as {} ({} blocks)",
+                                entry.dht_prefix, entry.num_blocks
+                            );
+                        }
+                        None => {
+                            cfg.set_key(&key, &value)?;
+                            print_success(&format!("Set model = {}", value));
+                            if catalog.is_some_and(|(c, _)| !c.is_empty()) {
+                                print_warning(&format!(
+                                    "{value} is not in the network's model catalog; \
+                                     you may be its only server"
+                                ));
+                                print_info(
+                                    "See what the network serves: kwaainet models available",
+                                );
+                            }
+                        }
+                    }
+                    print_separator();
+                }
                 Some(ConfigAction::Set { key, value }) => {
                     cfg.set_key(&key, &value)?;
                     print_box_header("⚙️  Configuration Updated");
                     print_success(&format!("Set {} = {}", key, value));
+                    if DaemonManager::new().is_running() {
+                        if config_reload::is_hot(&key) {
+                            print_info("Apply it to the running node: kwaainet config reload");
+                        } else {
+                            print_info("Takes effect when the node restarts: kwaainet restart");
+                        }
+                    }
+                    print_separator();
+                }
+                Some(ConfigAction::Reload) => {
+                    let mgr = DaemonManager::new();
+                    if !mgr.is_running() {
+                        print_warning("No running node — config.yaml is read when it starts.");
+                        return Ok(());
+                    }
+                    let Some(result) = control::call(control::ControlMethod::Reload).await? else {
+                        // Node is still starting or predates the control socket.
+                        mgr.signal_reannounce();
+                        print_info("Node not answering on the control socket — reload signalled.");
+                        return Ok(());
+                    };
+                    if args.json {
+                        println!("{}", serde_json::to_string_pretty(&result)?);
+                        return Ok(());
+                    }
+                    let report: config_reload::ReloadReport = serde_json::from_value(result)?;
+                    print_box_header("⚙️  Configuration Reloaded");
+                    if report.applied.is_empty() && report.restart_required.is_empty() {
+                        print_info("No changes since the node last read config.yaml.");
+                    }
+                    for setting in &report.applied {
+                        print_success(&format!("Applied {}", setting));
+                    }
+                    for e in &report.errors {
+                        print_error(&format!("Rejected {}", e));
+                    }
+                    if !report.restart_required.is_empty() {
+                        print_warning(&format!(
+                            "Needs a restart to take effect: {}",
+                            report.restart_required.join(", ")
+                        ));
+                        print_info("Restart the node: kwaainet restart");
+                    }
                     print_separator();
                 }
             }
@@ -667,7 +997,7 @@ async fn main() -> Result<()> {
                 let sock = std::env::var("KWAAINET_SOCKET")
                     .unwrap_or_else(|_| kwaai_p2p_daemon::DEFAULT_SOCKET_NAME.to_string());
                 let addr = format!("/unix/{sock}");
-                let mut c = P2PClient::connect(&addr).await?;
+                let c = P2PClient::connect(&addr).await?;
                 let peer_id = c.identify().await?;
                 let peers = c.list_peers().await.unwrap_or_default();
                 anyhow::Ok((peer_id, peers.len()))
@@ -716,16 +1046,124 @@ async fn main() -> Result<()> {
             print_separator();
         }
 
+        // -------------------------------------------------------------------
+        // profiles
+        // -------------------------------------------------------------------
+        Command::Profiles(args) => match args.action.unwrap_or(ProfilesAction::List) {
+            ProfilesAction::List => {
+                let active = config::active_profile();
+                let active = active.as_deref().unwrap_or(config::DEFAULT_PROFILE);
+                let rows: Vec<serde_json::Value> = config::list_profiles()
+                    .into_iter()
+                    .map(|name| {
+                        let dir = config::profile_dir(&name);
+                        let cfg = KwaaiNetConfig::load_profile(&name);
+                        let running = DaemonManager::in_run_dir(&dir.join("run")).is_running();
+                        serde_json::json!({
+                            "name": name,
+                            "active": name == active,
+                            "running": running,
+                            "port": cfg.as_ref().map(|c| c.port),
+                            "model": cfg.as_ref().map(|c| c.model.clone()),
+                            "public_name": cfg.as_ref().and_then(|c| c.public_name.clone()),
+                            "dir": dir,
+                        })
+                    })
+                    .collect();
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&rows)?);
+                } else {
+                    print_box_header("🗂  KwaaiNet Profiles");
+                    let mut ports = std::collections::HashMap::new();
+                    for row in &rows {
+                        let name = row["name"].as_str().unwrap_or_default();
+                        let marker = if row["active"] == true { "*" } else { " " };
+                        let state = if row["running"] == true {
+                            "🟢 running"
+                        } else {
+                            "🔴 stopped"
+                        };
+                        match row["port"].as_u64() {
+                            Some(port) => {
+                                println!(
+                                    "  {} {:<16} {}  port {:<5}  {}",
+                                    marker,
+                                    name,
+                                    state,
+                                    port,
+                                    row["model"].as_str().unwrap_or("-")
+                                );
+                                if let Some(other) = ports.insert(port, name) {
+                                    print_warning(&format!(
+                                        "Profiles '{}' and '{}' both use port {}",
+                                        other, name, port
+                                    ));
+                                }
+                            }
+                            None => {
+                                println!("  {} {:<16} {}  (no config yet)", marker, name, state)
+                            }
+                        }
+                        println!("      {}", row["dir"].as_str().unwrap_or_default());
+                    }
+                    println!();
+                    print_info("Select a profile with: kwaainet --profile NAME <command>");
+                    print_separator();
+                }
+            }
+        },
+
         // -------------------------------------------------------------------
         // service
         // -------------------------------------------------------------------
         Command::Service(args) => {
             let svc = service::get_service_manager();
             match args.action {
-                ServiceAction::Install => {
+                ServiceAction::Install(args) => {
+                    let opts = service::InstallOptions {
+                        scope: if args.system {
+                            service::ServiceScope::System
+                        } else {
+                            service::ServiceScope::User
+                        },
+                        restart: service::RestartPolicy::parse(&args.restart)?,
+                        restart_sec: args.restart_sec,
+                        memory_max: args.memory_max,
+                        cpu_quota: args.cpu_quota,
+                        sandbox: !args.no_sandbox,
+                        log_target: match args.log_target.as_str() {
+                            "journal" => service::LogTarget::Journal,
+                            _ => service::LogTarget::File,
+                        },
+                    };
                     print_box_header("🔧 Installing Auto-Start Service");
-                    svc.install()?;
-                    print_success("Auto-start service installed. KwaaiNet will start on boot.");
+                    svc.install(&opts)?;
+                    match opts.scope {
+                        service::ServiceScope::System => {
+                            print_success("System service installed. KwaaiNet will start on boot.")
+                        }
+                        service::ServiceScope::User => {
+                            print_success(
+                                "Auto-start service installed. KwaaiNet will start on login.",
+                            );
+                            #[cfg(target_os = "linux")]
+                            print_info(
+                                "To start it at boot without logging in: loginctl enable-linger $USER",
+                            );
+                        }
+                    }
+                    if opts.log_target == service::LogTarget::Journal {
+                        let unit = svc
+                            .status()
+                            .path
+                            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                            .unwrap_or_else(|| "kwaainet.service".to_string());
+                        print_info(&format!(
+                            "Logs go to the journal: journalctl {}-u {} -f",
+                            if args.system { "" } else { "--user " },
+                            unit
+                        ));
+                    }
                     print_separator();
                 }
                 ServiceAction::Uninstall => {
@@ -748,6 +1186,16 @@ async fn main() -> Result<()> {
                     if let Some(pid) = st.pid {
                         println!("  PID:       {}", pid);
                     }
+                    if let (Some(scope), Some(path)) = (st.scope, &st.path) {
+                        println!("  Scope:     {}", scope.as_str());
+                        println!("  Unit:      {}", path.display());
+                    }
+                    if !st.settings.is_empty() {
+                        println!();
+                        for (label, value) in &st.settings {
+                            println!("  {:<11}{}", format!("{label}:"), value);
+                        }
+                    }
                     print_separator();
                 }
                 ServiceAction::Restart => {
@@ -765,7 +1213,29 @@ async fn main() -> Result<()> {
         Command::Reconnect => {
             print_box_header("🔄 P2P Network Reconnection");
             let mgr = DaemonManager::new();
-            if mgr.is_running() {
+            // Prefer re-dialling in place over the control socket; restart
+            // only when the node can't be reached that way.
+            let live = if mgr.is_running() {
+                control::call(control::ControlMethod::Reconnect).await
+            } else {
+                Ok(None)
+            };
+            if let Ok(Some(result)) = live {
+                let connected = result["connected"].as_u64().unwrap_or(0);
+                let dialled = result["dialled"].as_u64().unwrap_or(0);
+                if connected > 0 {
+                    print_success(&format!(
+                        "Re-dialled bootstrap peers: {}/{} connected.",
+                        connected, dialled
+                    ));
+                } else {
+                    print_warning(&format!(
+                        "No bootstrap peer reachable ({} dialled). Check your network, \
+                         or restart the node: kwaainet restart",
+                        dialled
+                    ));
+                }
+            } else if mgr.is_running() {
                 mgr.stop_process()?;
                 let pid = DaemonManager::spawn_daemon_child(&[])?;
                 print_success(&format!(
@@ -790,6 +1260,12 @@ async fn main() -> Result<()> {
         // -------------------------------------------------------------------
         Command::Monitor(args) => match args.action {
             MonitorAction::Stats => {
+                if json {
+                    // `null` until the node has collected a sample.
+                    let stats = monitor::load_stats();
+                    println!("{}", serde_json::to_string_pretty(&stats)?);
+                    return Ok(());
+                }
                 print_box_header("📈 P2P Connection Statistics");
                 match monitor::load_stats() {
                     Some(stats) => {
@@ -824,17 +1300,52 @@ async fn main() -> Result<()> {
                 if let Some(t) = a.threshold {
                     cfg.disconnection_threshold_minutes = t;
                 }
-                if let Some(url) = a.webhook {
-                    cfg.webhook_url = Some(url);
+                if let Some(url) = &a.webhook {
+                    cfg.webhook_url = Some(url.clone());
                 }
                 if let Some(m) = a.min_connections {
                     cfg.min_connections = m;
                 }
 
-                if a.enable || a.disable || a.threshold.is_some() || a.min_connections.is_some() {
+                if a.enable
+                    || a.disable
+                    || a.threshold.is_some()
+                    || a.webhook.is_some()
+                    || a.min_connections.is_some()
+                {
                     monitor::save_alert_config(&cfg)?;
                 }
 
+                // Channel settings live in config.yaml (health_monitoring.alerting).
+                let mut node_cfg = KwaaiNetConfig::load_or_create()?;
+                let alerting = &mut node_cfg.health_monitoring.alerting;
+                let channel_changes = a.enable_channel.iter().map(|c| (c, true));
+                let channel_changes =
+                    channel_changes.chain(a.disable_channel.iter().map(|c| (c, false)));
+                let mut alerting_changed = false;
+                for (channel, on) in channel_changes {
+                    match channel.as_str() {
+                        "webhook" => alerting.channels.webhook = on,
+                        "email" => alerting.channels.email = on,
+                        _ => alerting.channels.desktop = on,
+                    }
+                    alerting_changed = true;
+                }
+                for (field, value) in [
+                    (&mut alerting.email, &a.email),
+                    (&mut alerting.smtp_url, &a.smtp_url),
+                    (&mut alerting.email_from, &a.email_from),
+                ] {
+                    if let Some(v) = value {
+                        *field = Some(v.clone());
+                        alerting_changed = true;
+                    }
+                }
+                if alerting_changed {
+                    node_cfg.save()?;
+                }
+                let alerting = &node_cfg.health_monitoring.alerting;
+
                 println!(
                     "  Enabled:    {}",
                     if cfg.enabled { "✅ Yes" } else { "❌ No" }
@@ -848,6 +1359,51 @@ async fn main() -> Result<()> {
                     "  Webhook:    {}",
                     cfg.webhook_url.as_deref().unwrap_or("Not configured")
                 );
+                println!(
+                    "  Email:      {}",
+                    match (&alerting.email, &alerting.smtp_url) {
+                        (Some(to), Some(_)) => to.clone(),
+                        (Some(to), None) => format!("{} (no --smtp-url set)", to),
+                        _ => "Not configured".to_string(),
+                    }
+                );
+                let on_off = |on: bool| if on { "on" } else { "off" };
+                println!(
+                    "  Channels:   webhook {}, email {}, desktop {}",
+                    on_off(alerting.channels.webhook),
+                    on_off(alerting.channels.email),
+                    on_off(alerting.channels.desktop)
+                );
+
+                if a.test {
+                    println!();
+                    let channels = monitor::AlertChannel::configured(&cfg, alerting);
+                    if channels.is_empty() {
+                        print_warning("No alert channel is configured and enabled.");
+                    }
+                    let node = node_cfg
+                        .public_name
+                        .clone()
+                        .unwrap_or_else(|| "kwaainet".to_string());
+                    let http = reqwest::Client::builder()
+                        .timeout(std::time::Duration::from_secs(10))
+                        .build()?;
+                    for channel in &channels {
+                        match channel.deliver(&http, &monitor::Alert::Test, &node).await {
+                            Ok(()) => {
+                                print_success(&format!("Test alert sent via {}", channel.name()))
+                            }
+                            Err(e) => print_error(&format!(
+                                "Test alert via {} failed: {:#}",
+                                channel.name(),
+                                e
+                            )),
+                        }
+                    }
+                    if !channels.is_empty() && !cfg.enabled && !alerting.enabled {
+                        print_info("Alerts are disabled; enable them with: kwaainet monitor alert --enable");
+                    }
+                }
                 print_separator();
             }
         },
@@ -859,6 +1415,33 @@ async fn main() -> Result<()> {
             print_box_header("🔄 KwaaiNet Update");
             let checker = updater::UpdateChecker::new();
             println!("  Current version: v{}", checker.current_version);
+
+            if args.rollback {
+                println!();
+                let daemon_was_running = stop_node_for_update().await;
+                let result = updater::rollback();
+                match &result {
+                    Ok(restored) => {
+                        for name in restored {
+                            println!("  Restored {}", name);
+                        }
+                        println!();
+                        print_success("Rolled back to the previously installed binaries.");
+                        print_info(&format!(
+                            "v{} won't be offered again automatically; reinstall it with: kwaainet update",
+                            updater::CURRENT_VERSION
+                        ));
+                    }
+                    Err(e) => print_error(&format!("Rollback failed: {e:#}")),
+                }
+                // A failed rollback leaves the current binaries in place.
+                if daemon_was_running && (result.is_err() || !args.no_restart) {
+                    restart_node_after_update();
+                }
+                print_separator();
+                return Ok(());
+            }
+
             println!("  Checking for updates…");
             println!();
 
@@ -890,142 +1473,16 @@ async fn main() -> Result<()> {
                     } else {
                         // Gracefully stop tracked services so the daemon can
                         // unannounce from DHT before the binary swap.
-                        #[cfg(windows)]
-                        let daemon_was_running = {
-                            let shard_mgr = ShardManager::new();
-                            if shard_mgr.is_running() {
-                                shard_mgr.stop_process();
-                                print_info("Shard server stopping…");
-                            }
-                            let storage_mgr = StorageApiManager::new();
-                            if storage_mgr.is_running() {
-                                storage_mgr.stop_process();
-                                print_info("Storage API stopping…");
-                            }
-                            let node_mgr = DaemonManager::new();
-                            let was = node_mgr.is_running();
-                            if was {
-                                let _ = node_mgr.stop_process();
-                                print_info("Daemon stopping…");
-                            }
-                            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                            was
-                        };
+                        let daemon_was_running = stop_node_for_update().await;
                         println!("  Installing v{}…", info.version);
                         println!();
-                        #[cfg(not(windows))]
-                        let daemon_was_running = {
-                            let dm = DaemonManager::new();
-                            let running = dm.is_running();
-                            if running {
-                                print_info("Stopping daemon before swap…");
-                                let _ = dm.stop_process();
-                                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                            }
-                            running
-                        };
-                        #[cfg(not(windows))]
-                        if let Err(e) = checker.install_update(&info.version).await {
-                            // Install failed — restart the daemon we stopped so the
-                            // node stays reachable even though the update didn't land.
-                            if daemon_was_running {
-                                let _ = std::process::Command::new(
-                                    std::env::current_exe().unwrap_or_else(|_| "kwaainet".into()),
-                                )
-                                .args(["start", "--daemon"])
-                                .stdin(std::process::Stdio::null())
-                                .stdout(std::process::Stdio::null())
-                                .stderr(std::process::Stdio::null())
-                                .spawn();
-                            }
-                            print_error(&format!("Update failed: {e:#}"));
-                            print_separator();
-                            return Ok(());
-                        }
-                        #[cfg(windows)]
-                        {
-                            let install_dir = std::env::current_exe()
-                                .ok()
-                                .and_then(|p| p.parent().map(|d| d.to_path_buf()))
-                                .unwrap_or_else(|| {
-                                    dirs::home_dir()
-                                        .map(|h| h.join(".cargo").join("bin"))
-                                        .unwrap_or_default()
-                                });
-
-                            if let Err(e) = checker.install_update(&info.version).await {
-                                if daemon_was_running {
-                                    let _ = std::process::Command::new(
-                                        install_dir.join("kwaainet.exe"),
-                                    )
-                                    .args(["start", "--daemon"])
-                                    .stdin(std::process::Stdio::null())
-                                    .stdout(std::process::Stdio::null())
-                                    .stderr(std::process::Stdio::null())
-                                    .spawn();
-                                }
-                                print_error(&format!("Update failed: {e:#}"));
-                                print_separator();
-                                return Ok(());
-                            }
-
-                            println!();
-                            if daemon_was_running {
-                                match std::process::Command::new(install_dir.join("kwaainet.exe"))
-                                    .args(["start", "--daemon"])
-                                    .stdin(std::process::Stdio::null())
-                                    .stdout(std::process::Stdio::null())
-                                    .stderr(std::process::Stdio::null())
-                                    .spawn()
-                                {
-                                    Ok(_) => print_success(&format!(
-                                        "Updated to v{} — daemon restarted with new binary.",
-                                        info.version
-                                    )),
-                                    Err(e) => print_warning(&format!(
-                                        "Updated to v{} but daemon restart failed ({e}).\n  Run: kwaainet start --daemon",
-                                        info.version
-                                    )),
-                                }
-                            } else {
-                                print_success(&format!(
-                                    "Updated to v{}. Run 'kwaainet start --daemon' to start the node.",
-                                    info.version
-                                ));
-                            }
-                        }
-                        #[cfg(not(windows))]
-                        let current_bin = std::env::current_exe()
-                            .ok()
-                            .map(|p| {
-                                let s = p.to_string_lossy().into_owned();
-                                if let Some(clean) = s.strip_suffix(" (deleted)") {
-                                    std::path::PathBuf::from(clean)
-                                } else {
-                                    p
-                                }
-                            })
-                            .unwrap_or_else(|| std::path::PathBuf::from("kwaainet"));
-
-                        #[cfg(not(windows))]
-                        let restart_daemon = |label: &str| {
-                            let _ = std::process::Command::new(&current_bin)
-                                .args(["start", "--daemon"])
-                                .stdin(std::process::Stdio::null())
-                                .stdout(std::process::Stdio::null())
-                                .stderr(std::process::Stdio::null())
-                                .spawn();
-                            println!("  {label}");
-                        };
-
-                        #[cfg(not(windows))]
                         match checker.install_update(&info.version).await {
                             Ok(()) => {
                                 println!();
-                                #[cfg(not(windows))]
-                                if daemon_was_running {
-                                    restart_daemon(&format!(
-                                        "✅ Updated to v{} — daemon restarted with new binary.",
+                                if daemon_was_running && !args.no_restart {
+                                    restart_node_after_update();
+                                    print_success(&format!(
+                                        "Updated to v{} — daemon restarted with new binary.",
                                         info.version
                                     ));
                                 } else {
@@ -1034,18 +1491,17 @@ async fn main() -> Result<()> {
                                         info.version
                                     ));
                                 }
+                                print_info("Undo with: kwaainet update --rollback");
                             }
                             Err(e) => {
                                 println!();
-                                print_error(&format!("Update failed: {e}"));
-                                // Restart daemon with the EXISTING binary — the install was
-                                // aborted (e.g. CUDA archive not yet published), so the binary
-                                // on disk is unchanged and safe to run.
-                                #[cfg(not(windows))]
+                                print_error(&format!("Update failed: {e:#}"));
+                                // Nothing was swapped (e.g. CUDA archive not yet
+                                // published, checksum mismatch), so the binary on
+                                // disk is unchanged and safe to run.
                                 if daemon_was_running {
-                                    restart_daemon(
-                                        "Restarting daemon with existing binary — GPU inference preserved.",
-                                    );
+                                    restart_node_after_update();
+                                    print_info("Daemon restarted with the existing binary.");
                                 }
                             }
                         }
@@ -1062,6 +1518,44 @@ async fn main() -> Result<()> {
             let cfg = KwaaiNetConfig::load_or_create()?;
             let model = args.model.unwrap_or_else(|| cfg.model.clone());
 
+            if json {
+                let engine = calibration::CalibrationEngine::new();
+                let bench = if args.benchmark {
+                    Some(calibration_benchmark(&cfg, &model, args.benchmark_blocks).await?)
+                } else {
+                    None
+                };
+                let size = calibration::BlockSize::for_model(&model, cfg.cache_tokens);
+                let profile = engine.calibrate(match bench {
+                    Some(ref b) if b.bytes_per_block > 0 => size.measured(b.bytes_per_block),
+                    _ => size,
+                });
+                let applied = match args.apply.as_deref() {
+                    Some(apply) => {
+                        let Some(new_blocks) = profile.get_blocks(apply) else {
+                            anyhow::bail!(
+                                "Unknown profile '{}'. Use: min, recommended, or max",
+                                apply
+                            );
+                        };
+                        let mut cfg = KwaaiNetConfig::load_or_create()?;
+                        cfg.blocks = new_blocks;
+                        cfg.save()?;
+                        Some(serde_json::json!({ "profile": apply, "blocks": new_blocks }))
+                    }
+                    None => None,
+                };
+                let out = serde_json::json!({
+                    "model": model,
+                    "hardware": engine.hardware,
+                    "benchmark": bench,
+                    "profile": profile,
+                    "applied": applied,
+                });
+                println!("{}", serde_json::to_string_pretty(&out)?);
+                return Ok(());
+            }
+
             print_box_header("🔧 KwaaiNet Block Calibration");
             println!("  Model: {}", model);
             println!();
@@ -1076,9 +1570,15 @@ async fn main() -> Result<()> {
             );
             println!("    CPU cores: {}", hw.cpu_cores);
             if let Some(ref gpu) = hw.gpu {
+                let count = if gpu.devices > 1 {
+                    format!(" ×{}", gpu.devices)
+                } else {
+                    String::new()
+                };
                 println!(
-                    "    GPU: {} ({} total / {} free)",
+                    "    GPU: {}{} ({} total / {} free)",
                     gpu.name,
+                    count,
                     format_bytes(gpu.total_vram),
                     format_bytes(gpu.free_vram)
                 );
@@ -1087,13 +1587,62 @@ async fn main() -> Result<()> {
             }
             println!();
 
-            let profile = engine.calibrate(&model);
+            let mut bytes_per_block = None;
+            if args.benchmark {
+                println!(
+                    "  Benchmarking {} block(s) of {}…",
+                    args.benchmark_blocks, model
+                );
+                let bench = calibration_benchmark(&cfg, &model, args.benchmark_blocks).await?;
+                println!("    Device: {}", bench.device);
+                if bench.bytes_per_block > 0 {
+                    println!(
+                        "    Memory per block: {}",
+                        format_bytes(bench.bytes_per_block)
+                    );
+                    bytes_per_block = Some(bench.bytes_per_block);
+                } else {
+                    print_warning("Could not measure block memory; using the estimate");
+                }
+                println!(
+                    "    Per block: {:.1} decode steps/s, {:.0} prefill tokens/s",
+                    bench.inference_rps, bench.forward_rps
+                );
+                println!();
+            }
+
+            let size = calibration::BlockSize::for_model(&model, cfg.cache_tokens);
+            let profile = engine.calibrate(match bytes_per_block {
+                Some(bytes) => size.measured(bytes),
+                None => size,
+            });
             println!("  Total model blocks: {}", profile.total_blocks);
             if profile.gpu_based {
                 println!("  Based on: GPU VRAM");
             } else {
                 println!("  Based on: System RAM");
             }
+            let size = &profile.block_size;
+            let source = match size.sizing {
+                calibration::BlockSizing::Estimated => "estimated from the model name",
+                calibration::BlockSizing::ModelFiles => "from the model files",
+                calibration::BlockSizing::Measured => "measured",
+            };
+            if size.kv_cache_bytes > 0 {
+                println!(
+                    "  Block size: {} weights + {} KV cache for {} tokens ({})",
+                    format_bytes(size.weight_bytes),
+                    format_bytes(size.kv_cache_bytes),
+                    cfg.cache_tokens,
+                    source
+                );
+            } else {
+                println!(
+                    "  Block size: {} ({})",
+                    format_bytes(size.weight_bytes),
+                    source
+                );
+            }
             println!();
             println!("  Recommendations:");
             println!("    🔹 Minimum:       {} blocks", profile.min_blocks);
@@ -1130,6 +1679,13 @@ async fn main() -> Result<()> {
             }
         }
 
+        // -------------------------------------------------------------------
+        // models
+        // -------------------------------------------------------------------
+        Command::Models(args) => {
+            models_cmd::run(args, json).await?;
+        }
+
         // -------------------------------------------------------------------
         // load-model
         // -------------------------------------------------------------------
@@ -1378,7 +1934,7 @@ async fn main() -> Result<()> {
                 } else {
                     None
                 }
-                .or_else(|| ollama::resolve_model_blob(&model).ok())
+                .or_else(|| ollama::find_model_blob(&model).ok())
                 .or_else(|| {
                     // Scan ~/.kwaainet/models/
                     let models_dir = dirs::home_dir()?.join(".kwaainet/models");
@@ -1651,6 +2207,10 @@ async fn main() -> Result<()> {
             serve_command(args).await?;
         }
 
+        Command::ApiKeys(args) => {
+            api_keys_command(args.action.unwrap_or(ApiKeysAction::List), json)?;
+        }
+
         // -------------------------------------------------------------------
         // identity
         // -------------------------------------------------------------------
@@ -1665,6 +2225,13 @@ async fn main() -> Result<()> {
             p2p_cmd::run(args).await?;
         }
 
+        // -------------------------------------------------------------------
+        // network
+        // -------------------------------------------------------------------
+        Command::Network(args) => {
+            network_cmd::run(args).await?;
+        }
+
         // -------------------------------------------------------------------
         // vpk
         // -------------------------------------------------------------------
@@ -1672,6 +2239,20 @@ async fn main() -> Result<()> {
             vpk::run(args).await?;
         }
 
+        // -------------------------------------------------------------------
+        // history
+        // -------------------------------------------------------------------
+        Command::History(args) => {
+            history_cmd::run(args, json)?;
+        }
+
+        // -------------------------------------------------------------------
+        // ledger
+        // -------------------------------------------------------------------
+        Command::Ledger(args) => {
+            ledger_cmd::run(args, json)?;
+        }
+
         // -------------------------------------------------------------------
         // reputation
         // -------------------------------------------------------------------
@@ -1679,6 +2260,45 @@ async fn main() -> Result<()> {
             reputation_cmd::run(args).await?;
         }
 
+        Command::Announce => {
+            print_box_header("📡 DHT Announcement");
+            let mgr = DaemonManager::new();
+            if !mgr.is_running() {
+                print_error("No running node found. Start it first: kwaainet start --daemon");
+                std::process::exit(1);
+            }
+            match control::call(control::ControlMethod::Announce).await? {
+                Some(result) if result["announced"].as_bool() == Some(true) => {
+                    print_success("Announced — the node's block records reached the DHT.");
+                }
+                Some(_) => {
+                    print_warning("Announcement reached no DHT peer; the node keeps retrying.");
+                }
+                None => {
+                    // Node is still starting or predates the control socket.
+                    mgr.signal_reannounce();
+                    print_info("Node not answering on the control socket — re-announce signalled.");
+                }
+            }
+            print_separator();
+        }
+
+        Command::Block(args) => {
+            peer_filter::block_command(&args.target, true).await?;
+        }
+
+        Command::Unblock(args) => {
+            peer_filter::block_command(&args.target, false).await?;
+        }
+
+        Command::Peers(args) => {
+            if args.known {
+                reputation_cmd::peers(args)?;
+            } else {
+                p2p_cmd::connected_peers(args).await?;
+            }
+        }
+
         // -------------------------------------------------------------------
         // storage (requires --features storage)
         // -------------------------------------------------------------------
@@ -1793,6 +2413,17 @@ async fn main() -> Result<()> {
             print_error("RAG support not compiled. Rebuild with: cargo build --features rag");
         }
 
+        // -------------------------------------------------------------------
+        // completions / schema
+        // -------------------------------------------------------------------
+        Command::Completions(args) => {
+            schema::print_completions(args.shell);
+        }
+
+        Command::Schema(args) => {
+            schema::print_schema(args.json);
+        }
+
         // -------------------------------------------------------------------
         // setup
         // -------------------------------------------------------------------
@@ -1844,6 +2475,176 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+// ---------------------------------------------------------------------------
+// API keys helper
+// ---------------------------------------------------------------------------
+
+/// `kwaainet config validate`
+fn validate_config_command(json: bool) -> Result<()> {
+    let path = config::config_file();
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            print_info(&format!(
+                "{} does not exist yet; defaults apply.",
+                path.display()
+            ));
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let mut problems = config_schema::validate(&text);
+    // The parser repeats what the schema already caught; show it only for
+    // problems the schema does not know about (syntax errors, sections).
+    let parsed = serde_yaml::from_str::<KwaaiNetConfig>(&text);
+    if let (false, Err(e)) = (problems.iter().any(|p| p.fatal), parsed) {
+        problems.push(config_schema::Problem {
+            line: e.location().map(|l| l.line()),
+            key: "config.yaml".to_string(),
+            message: e.to_string(),
+            fatal: true,
+        });
+    }
+    let fatal = problems.iter().any(|p| p.fatal);
+
+    if json {
+        let rows: Vec<serde_json::Value> = problems
+            .iter()
+            .map(|p| {
+                serde_json::json!({
+                    "line": p.line,
+                    "key": p.key,
+                    "message": p.message,
+                    "fatal": p.fatal,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "path": path,
+                "valid": !fatal,
+                "problems": rows,
+            }))?
+        );
+    } else {
+        print_box_header("⚙️  Configuration Check");
+        println!("  {}", path.display());
+        println!();
+        if problems.is_empty() {
+            print_success("No problems found.");
+        }
+        for problem in &problems {
+            if problem.fatal {
+                print_error(&problem.to_string());
+            } else {
+                print_warning(&problem.to_string());
+            }
+        }
+        print_separator();
+    }
+    if fatal {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn api_keys_command(action: ApiKeysAction, json: bool) -> Result<()> {
+    let mut cfg = KwaaiNetConfig::load_or_create()?;
+    match action {
+        ApiKeysAction::List => {
+            if json {
+                // Never print the keys themselves.
+                let keys: Vec<serde_json::Value> = cfg
+                    .api
+                    .keys
+                    .iter()
+                    .map(|k| {
+                        serde_json::json!({
+                            "name": k.name,
+                            "key_hint": api_keys::key_hint(&k.key),
+                            "requests_per_minute": k.requests_per_minute,
+                            "tokens_per_day": k.tokens_per_day,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&keys)?);
+                return Ok(());
+            }
+            print_box_header("🔑 API Keys");
+            if cfg.api.keys.is_empty() {
+                print_info("No API keys — `kwaainet serve` is open to anyone who can reach it.");
+                print_info("Add one with: kwaainet api-keys add NAME");
+            }
+            for k in &cfg.api.keys {
+                let limit = |v: Option<String>| v.unwrap_or_else(|| "unlimited".to_string());
+                println!(
+                    "  {:<16} {}  {} req/min  {} tokens/day",
+                    k.name,
+                    api_keys::key_hint(&k.key),
+                    limit(k.requests_per_minute.map(|n| n.to_string())),
+                    limit(k.tokens_per_day.map(|n| n.to_string())),
+                );
+            }
+            if !cfg.api.cors_origins.is_empty() {
+                println!();
+                println!("  CORS origins: {}", cfg.api.cors_origins.join(", "));
+            }
+            print_separator();
+        }
+        ApiKeysAction::Add {
+            name,
+            requests_per_minute,
+            tokens_per_day,
+            key,
+        } => {
+            if cfg.api.keys.iter().any(|k| k.name == name) {
+                anyhow::bail!("An API key named '{}' already exists", name);
+            }
+            let key = match key {
+                Some(k) if k.len() < 16 || k.contains(char::is_whitespace) => {
+                    anyhow::bail!("API keys must be at least 16 characters without spaces")
+                }
+                Some(k) => k,
+                None => api_keys::generate_key(),
+            };
+            if cfg.api.keys.iter().any(|k| k.key == key) {
+                anyhow::bail!("That key is already in use");
+            }
+            cfg.api.keys.push(config::ApiKeyConfig {
+                name: name.clone(),
+                key: key.clone(),
+                requests_per_minute,
+                tokens_per_day,
+            });
+            cfg.save()?;
+            if json {
+                println!("{}", serde_json::json!({ "name": name, "key": key }));
+            } else {
+                print_success(&format!("Added API key '{}':", name));
+                println!("  {}", key);
+                print_info("It is stored in config.yaml. Restart `kwaainet serve` to apply.");
+            }
+        }
+        ApiKeysAction::Remove { name } => {
+            let before = cfg.api.keys.len();
+            cfg.api.keys.retain(|k| k.name != name);
+            if cfg.api.keys.len() == before {
+                anyhow::bail!("No API key named '{}'", name);
+            }
+            cfg.save()?;
+            if cfg.api.keys.is_empty() {
+                print_warning("That was the last key: `kwaainet serve` will be open to anyone.");
+            }
+            print_success(&format!(
+                "Removed API key '{}'. Restart `kwaainet serve` to apply.",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // Serve helper
 // ---------------------------------------------------------------------------
@@ -1852,19 +2653,27 @@ async fn serve_command(args: ServeArgs) -> Result<()> {
     let cfg = KwaaiNetConfig::load_or_create()?;
     let model = args.model.unwrap_or_else(|| cfg.model.clone());
 
+    // --listen, then --port, then api_listen in config.yaml.
+    let listen = match (&args.listen, args.port, &cfg.api_listen) {
+        (Some(addr), _, _) => addr.parse()?,
+        (None, Some(port), _) => listen::ApiListen::port(port),
+        (None, None, Some(addr)) => addr.parse().context("api_listen in config.yaml")?,
+        (None, None, None) => listen::ApiListen::default(),
+    };
+    let port = match &listen {
+        listen::ApiListen::Tcp(addr) => addr.port(),
+        listen::ApiListen::Unix(_) => listen::DEFAULT_API_PORT,
+    };
+
     print_box_header("🌐 KwaaiNet OpenAI API Server");
     println!("  Model:  {}", model);
-    println!("  Port:   {}", args.port);
+    println!("  Listen: {}", listen);
     println!();
 
-    if crate::daemon::port_in_use(args.port) {
+    if listen.in_use() {
         print_warning(&format!(
-            "Port {} is already in use — API server may already be running.",
-            args.port
-        ));
-        print_info(&format!(
-            "Check with: curl http://localhost:{}/v1/models",
-            args.port
+            "{} is already in use — API server may already be running.",
+            listen
         ));
         print_separator();
         return Ok(());
@@ -1878,6 +2687,7 @@ async fn serve_command(args: ServeArgs) -> Result<()> {
     };
     let engine_config = EngineConfig {
         max_memory: ((system_ram as f64 * 0.85) as usize).max(4 * 1024 * 1024 * 1024),
+        max_batch_size: args.max_batch.max(1),
         ..EngineConfig::default()
     };
 
@@ -1889,59 +2699,126 @@ async fn serve_command(args: ServeArgs) -> Result<()> {
         }
     };
 
-    let is_hf = model.contains('/') && !model.starts_with("hf.co/");
-    let handle = if is_hf {
-        let snapshot = match hf::resolve_snapshot(&model) {
-            Ok(p) => p,
-            Err(e) => {
-                print_error(&format!("{e}"));
-                return Ok(());
-            }
-        };
-        println!("  Loading SafeTensors shards…");
-        match engine.load_model(&snapshot, ModelFormat::SafeTensors) {
-            Ok(h) => h,
+    // Expert offload applies to the main model; set it before loading.
+    if let Ok((path, ModelFormat::Gguf)) = model_registry::locate_model(&model) {
+        match experts::offload_for_model(&cfg.moe, &path) {
+            Ok(offload) => engine.set_expert_offload(offload),
             Err(e) => {
-                print_error(&format!("{e}"));
+                print_error(&format!("MoE expert offload: {e:#}"));
                 return Ok(());
             }
         }
-    } else {
-        let blob = match ollama::resolve_model_blob(&model) {
-            Ok(p) => p,
-            Err(e) => {
-                print_error(&format!("{e}"));
-                return Ok(());
-            }
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // The main model first, so it becomes the default for its kind.
+    let mut models = model_registry::ModelRegistry::default();
+    for name in std::iter::once(&model).chain(&args.extra_models) {
+        if *name != model {
+            println!("  Extra model: {}", name);
+        }
+        let Some(handle) = load_serve_model(&mut engine, name, port) else {
+            return Ok(());
         };
-        println!("  Loading GGUF blob…");
-        match engine.load_model(&blob, ModelFormat::Gguf) {
-            Ok(h) => h,
-            Err(e) => {
-                let msg = e.to_string();
-                print_error(&msg.to_string());
-                if msg.contains("unknown dtype") {
-                    print_info("This model uses a quantization type not yet supported by the");
-                    print_info("candle inference backend. Use llama.cpp instead:");
-                    print_info(&format!(
-                        "  kwaainet shard api --model-path {} --port {}",
-                        blob.display(),
-                        args.port
-                    ));
-                    print_info("llama.cpp supports all GGUF quantization types including IQ* and Q4_0_8_8.");
-                }
-                return Ok(());
-            }
+        models.insert(model_registry::RegisteredModel {
+            id: name.clone(),
+            handle,
+            kind: if engine.is_embedding_model(&handle) {
+                model_registry::ModelKind::Embedding
+            } else {
+                model_registry::ModelKind::Generation
+            },
+            loaded_at: now,
+        });
+    }
+
+    if let Some(name) = args.embedding_model {
+        println!("  Embedding model: {}", name);
+        let Some(handle) = load_serve_model(&mut engine, &name, port) else {
+            return Ok(());
+        };
+        if !engine.is_embedding_model(&handle) {
+            print_error(&format!("'{name}' is not an embedding model"));
+            return Ok(());
         }
-    };
+        models.insert(model_registry::RegisteredModel {
+            id: name.clone(),
+            handle,
+            kind: model_registry::ModelKind::Embedding,
+            loaded_at: now,
+        });
+        models.set_default(&name);
+    }
 
-    print_success("Model loaded — starting API server");
+    print_success("Models loaded — starting API server");
     print_separator();
 
-    api::run_api_server(args.port, engine, handle, model).await?;
+    let request_timeout = args.request_timeout.map(std::time::Duration::from_secs);
+    api::run_api_server(
+        listen,
+        engine,
+        models,
+        api::ServerOptions {
+            request_timeout,
+            queue_depth: args.queue_depth.unwrap_or(cfg.queue_depth),
+            admin_token: cfg.admin_token.clone(),
+            access: cfg.api.clone(),
+        },
+    )
+    .await?;
     Ok(())
 }
 
+/// Resolve `model` (HF repo or Ollama name) and load it into `engine`,
+/// printing a user-facing error and returning `None` on failure.
+fn load_serve_model(engine: &mut InferenceEngine, model: &str, port: u16) -> Option<ModelHandle> {
+    let (path, format) = match model_registry::locate_model(model) {
+        Ok(found) => found,
+        Err(e) => {
+            print_error(&format!("{e}"));
+            return None;
+        }
+    };
+    match format {
+        ModelFormat::SafeTensors => println!("  Loading SafeTensors shards…"),
+        _ => println!("  Loading GGUF blob…"),
+    }
+    match engine.load_model(&path, format) {
+        Ok(h) => Some(h),
+        Err(e) => {
+            let msg = e.to_string();
+            print_error(&msg);
+            let unsupported_arch = matches!(
+                e,
+                kwaai_inference::InferenceError::UnsupportedArchitecture { .. }
+            );
+            if format == ModelFormat::Gguf && (unsupported_arch || msg.contains("unknown dtype")) {
+                if unsupported_arch {
+                    print_info("The candle inference backend cannot run this architecture.");
+                    print_info("Use llama.cpp instead:");
+                } else {
+                    print_info("This model uses a quantization type not yet supported by the");
+                    print_info("candle inference backend. Use llama.cpp instead:");
+                }
+                print_info(&format!(
+                    "  kwaainet shard api --model-path {} --port {}",
+                    path.display(),
+                    port
+                ));
+                if !unsupported_arch {
+                    print_info(
+                        "llama.cpp supports all GGUF quantization types including IQ* and Q4_0_8_8.",
+                    );
+                }
+            }
+            None
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Helpers
 // ---------------------------------------------------------------------------
@@ -1955,6 +2832,81 @@ fn system_total_ram_bytes() -> u64 {
     sys.total_memory()
 }
 
+/// Stop the node before its binary is swapped; returns whether it was running.
+///
+/// On Windows the shard server and storage API are stopped too, since they
+/// run from the same executable.
+async fn stop_node_for_update() -> bool {
+    #[cfg(windows)]
+    {
+        let shard_mgr = ShardManager::new();
+        if shard_mgr.is_running() {
+            shard_mgr.stop_process();
+            print_info("Shard server stopping…");
+        }
+        let storage_mgr = StorageApiManager::new();
+        if storage_mgr.is_running() {
+            storage_mgr.stop_process();
+            print_info("Storage API stopping…");
+        }
+    }
+    let node_mgr = DaemonManager::new();
+    let running = node_mgr.is_running();
+    if running {
+        print_info("Stopping daemon before swap…");
+        let _ = node_mgr.stop_process();
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    }
+    running
+}
+
+/// Start the daemon from the installed binary.
+///
+/// Spawned via its path rather than current_exe(): after a swap, Linux
+/// resolves /proc/self/exe to the old, unlinked inode.
+fn restart_node_after_update() {
+    let bin = updater::installed_binary();
+    if let Err(e) = std::process::Command::new(&bin)
+        .args(["start", "--daemon"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+    {
+        print_warning(&format!(
+            "Could not restart the daemon ({e}). Run: kwaainet start --daemon"
+        ));
+    }
+}
+
+/// Run the `calibrate --benchmark` forward pass on the device `shard serve`
+/// would pick, and keep the measured per-block rates in the throughput
+/// cache for the next announcement.
+async fn calibration_benchmark(
+    cfg: &KwaaiNetConfig,
+    model: &str,
+    blocks: usize,
+) -> Result<calibration::BenchmarkResult> {
+    let device = if cfg.use_gpu {
+        kwaai_inference::DeviceType::detect_best_logged()
+    } else {
+        kwaai_inference::DeviceType::Cpu
+    };
+    let name = model.to_string();
+    let bench =
+        tokio::task::spawn_blocking(move || calibration::benchmark_blocks(&name, device, blocks))
+            .await
+            .context("calibration benchmark panicked")??;
+    let rates = kwaai_inference::BlockThroughput {
+        inference_rps: bench.inference_rps,
+        forward_rps: bench.forward_rps,
+    };
+    if let Err(e) = throughput::save_block_rates(model, bench.hidden_size, &rates) {
+        tracing::warn!("Could not save block throughput: {e}");
+    }
+    Ok(bench)
+}
+
 fn print_last_lines(path: &std::path::Path, n: usize) {
     match std::fs::read_to_string(path) {
         Ok(text) => {
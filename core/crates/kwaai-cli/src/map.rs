@@ -66,7 +66,7 @@ pub async fn fetch_map(endpoint: &str) -> Result<MapState> {
 // ---------------------------------------------------------------------------
 
 /// Reduce a name to lowercase alphanumeric chars for comparison.
-fn normalize(s: &str) -> String {
+pub(crate) fn normalize(s: &str) -> String {
     s.chars()
         .filter(|c| c.is_alphanumeric())
         .collect::<String>()
@@ -0,0 +1,129 @@
+//! Averaging-group matchmaking between nodes.
+//!
+//! [`kwaai_distributed::Matchmaker`] finds averaging partners through the
+//! DHT and asks earlier nodes to lead it ([`MatchmakingRpc::join`]). Here
+//! those join requests travel over p2pd on [`MATCHMAKING_PROTO`]:
+//! [`P2pMatchmakingRpc`] sends them and [`make_join_handler`] answers them
+//! from the node's matchmaker ([`matchmaker`]), which the node registers at
+//! startup. A join reply only arrives once the leader starts its group, so
+//! the call waits up to the matchmaking timeout.
+//!
+//! The client side is for averaging rounds
+//! ([`kwaai_distributed::DecentralizedAverager::average`]), which the node
+//! does not run yet.
+#![allow(dead_code)]
+
+use std::sync::{Arc, OnceLock};
+
+use async_trait::async_trait;
+use kwaai_distributed::{
+    DistributedError, DistributedResult, JoinRequest, JoinResponse, Matchmaker, MatchmakingConfig,
+    MatchmakingRpc,
+};
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::PeerId;
+
+/// libp2p protocol string for averaging-group join requests.
+pub const MATCHMAKING_PROTO: &str = "/kwaai/matchmaking/1.0.0";
+
+static MATCHMAKER: OnceLock<Arc<Matchmaker>> = OnceLock::new();
+
+/// Install the node's matchmaker, grouping under `model`. Called once at
+/// node startup.
+pub fn init(peer_id: &PeerId, model: &str) -> Arc<Matchmaker> {
+    MATCHMAKER
+        .get_or_init(|| {
+            let config = MatchmakingConfig {
+                prefix: model.to_string(),
+                ..MatchmakingConfig::default()
+            };
+            Arc::new(Matchmaker::new(peer_id.to_base58(), config))
+        })
+        .clone()
+}
+
+/// The node's matchmaker, once [`init`] ran.
+pub fn matchmaker() -> Option<Arc<Matchmaker>> {
+    MATCHMAKER.get().cloned()
+}
+
+// ── Client ────────────────────────────────────────────────────────────────────
+
+/// Sends join requests to group leaders through the local p2pd.
+pub struct P2pMatchmakingRpc;
+
+#[async_trait]
+impl MatchmakingRpc for P2pMatchmakingRpc {
+    async fn join(&self, leader: &str, request: JoinRequest) -> DistributedResult<JoinResponse> {
+        let peer: PeerId = leader
+            .parse()
+            .map_err(|e| DistributedError::NetworkError(format!("bad peer id {leader}: {e}")))?;
+        let payload = rmp_serde::to_vec_named(&request)
+            .map_err(|e| DistributedError::Internal(format!("encoding join request: {e}")))?;
+
+        let client = P2PClient::connect(&crate::shard_cmd::daemon_socket())
+            .await
+            .map_err(|e| DistributedError::NetworkError(format!("p2pd: {e}")))?;
+        let reply = client
+            .call_unary_handler(&peer.to_bytes(), MATCHMAKING_PROTO, &payload)
+            .await
+            .map_err(|e| DistributedError::RemoteCallFailed(e.to_string()))?;
+        rmp_serde::from_slice(&reply)
+            .map_err(|e| DistributedError::RemoteCallFailed(format!("invalid join reply: {e}")))
+    }
+}
+
+// ── Server ────────────────────────────────────────────────────────────────────
+
+/// Build a unary handler for [`MATCHMAKING_PROTO`] that answers join
+/// requests from `matchmaker`. Replies with a [`JoinResponse`].
+#[allow(clippy::type_complexity)]
+pub fn make_join_handler(
+    matchmaker: Arc<Matchmaker>,
+) -> impl Fn(
+    Vec<u8>,
+) -> std::pin::Pin<
+    Box<dyn std::future::Future<Output = kwaai_p2p_daemon::error::Result<Vec<u8>>> + Send>,
+> + Send
+       + Sync
+       + 'static {
+    move |data: Vec<u8>| {
+        let matchmaker = matchmaker.clone();
+        Box::pin(async move {
+            let request: JoinRequest = rmp_serde::from_slice(&data).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("invalid join request: {e}"))
+            })?;
+            let reply = matchmaker.handle_join(request).await;
+            rmp_serde::to_vec_named(&reply).map_err(|e| {
+                kwaai_p2p_daemon::error::Error::Protocol(format!("encoding join reply: {e}"))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn handler_rejects_joins_while_not_gathering() {
+        let leader = Arc::new(Matchmaker::new(
+            PeerId::random().to_base58(),
+            MatchmakingConfig::default(),
+        ));
+        let handler = make_join_handler(leader);
+        let request = JoinRequest {
+            peer_id: PeerId::random().to_base58(),
+            round: 1,
+            group_key: "m_averaging.0b".to_string(),
+        };
+        let reply = handler(rmp_serde::to_vec_named(&request).unwrap())
+            .await
+            .unwrap();
+        assert!(matches!(
+            rmp_serde::from_slice(&reply).unwrap(),
+            JoinResponse::Rejected(_)
+        ));
+        assert!(handler(b"not a request".to_vec()).await.is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! The model catalog: models the swarm serves, read from the
+//! `_petals.models` DHT registry through the local node (decoding lives in
+//! [`kwaai_p2p::catalog`]).
+//!
+//! Each read is cached in `~/.kwaainet/model_catalog.json`, so
+//! `kwaainet models available` and `kwaainet config set model` still know
+//! the last catalog seen while the node is stopped.
+
+use anyhow::{bail, Result};
+use kwaai_p2p::hivemind::num_blocks_from_config;
+use kwaai_p2p::{catalog, CatalogEntry, ModelCatalog, ModelIdentity};
+use std::path::PathBuf;
+use tracing::debug;
+
+use crate::config::{kwaainet_dir, KwaaiNetConfig};
+use crate::network_cmd::dht_client;
+use crate::shard_cmd::find_on_bootstrap;
+
+pub fn cache_file() -> PathBuf {
+    kwaainet_dir().join("model_catalog.json")
+}
+
+/// The catalog saved by the last successful [`fetch`], if any
+pub fn load_cached() -> Option<ModelCatalog> {
+    let text = std::fs::read_to_string(cache_file()).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+fn save(catalog: &ModelCatalog) -> Result<()> {
+    let path = cache_file();
+    std::fs::create_dir_all(path.parent().expect("cache_file has a parent"))?;
+    std::fs::write(&path, serde_json::to_string_pretty(catalog)?)?;
+    Ok(())
+}
+
+/// Read the registry from the bootstrap peers through the running node and
+/// cache the result.
+pub async fn fetch(cfg: &KwaaiNetConfig) -> Result<ModelCatalog> {
+    let (client, our_dhtid, bootstrap_peers) = dht_client(cfg).await?;
+    let key = catalog::registry_key();
+    let found = find_on_bootstrap(
+        &client,
+        &our_dhtid,
+        std::slice::from_ref(&key),
+        &bootstrap_peers,
+    )
+    .await;
+    if found.responded.is_empty() {
+        bail!("no bootstrap peer answered the DHT query");
+    }
+    let mut catalog = ModelCatalog::new(chrono::Utc::now().timestamp().max(0) as u64);
+    for (_, result) in found.values.get(&key).into_iter().flatten() {
+        catalog.add_value(result.result_type, &result.value);
+    }
+    save(&catalog)?;
+    Ok(catalog)
+}
+
+/// The freshest catalog available: from the network when the node is
+/// running, else the cached one. The flag tells whether it is fresh.
+pub async fn current(cfg: &KwaaiNetConfig) -> Option<(ModelCatalog, bool)> {
+    match fetch(cfg).await {
+        Ok(catalog) => Some((catalog, true)),
+        Err(e) => {
+            debug!("Model catalog not fetched: {e:#}");
+            load_cached().map(|catalog| (catalog, false))
+        }
+    }
+}
+
+/// Point `cfg` at a catalog model: its HuggingFace ID (or prefix when the
+/// repository is not on HuggingFace), and the prefix and repository its
+/// servers announce with.
+pub fn select(cfg: &mut KwaaiNetConfig, entry: &CatalogEntry) {
+    cfg.model = entry.model_id().unwrap_or(&entry.dht_prefix).to_string();
+    cfg.model_dht_prefix = Some(entry.dht_prefix.clone());
+    cfg.model_repository = Some(entry.repository.clone());
+}
+
+/// Blocks in `model` per its local files: the HuggingFace `config.json`,
+/// else the GGUF header (a `.gguf` path or an Ollama model).
+pub fn local_num_blocks(model: &str) -> Option<usize> {
+    let from_config = crate::hf::resolve_snapshot(model).ok().and_then(|dir| {
+        let text = std::fs::read_to_string(dir.join("config.json")).ok()?;
+        num_blocks_from_config(&serde_json::from_str(&text).ok()?)
+    });
+    from_config.or_else(|| crate::calibration::model_footprint(model).map(|fp| fp.num_blocks()))
+}
+
+/// Prefix, repository and block count of `model` from the cached catalog
+/// and the local model files (see [`ModelIdentity::resolve`]).
+pub fn identify(model: &str) -> ModelIdentity {
+    ModelIdentity::resolve(model, load_cached().as_ref(), local_num_blocks(model))
+}
+
+/// DHT prefix of `model`, from the cached catalog or derived from the name
+/// (cheaper than [`identify`]: no model files are read).
+pub fn dht_prefix(model: &str) -> String {
+    ModelIdentity::resolve(model, load_cached().as_ref(), None).dht_prefix
+}
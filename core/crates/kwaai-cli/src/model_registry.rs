@@ -0,0 +1,276 @@
+//! Models hosted by the OpenAI-compatible API server (`kwaainet serve`).
+//!
+//! One inference engine can keep several models loaded at once. The
+//! registry maps the ids clients send in the `model` field to engine
+//! handles, and tracks which model answers requests that don't name a
+//! loaded one. Models are added and removed at runtime through the API
+//! server's admin endpoint.
+
+use anyhow::Result;
+use kwaai_inference::{ModelFormat, ModelHandle};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// What a loaded model can serve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelKind {
+    /// Chat and text completions
+    Generation,
+    /// `/v1/embeddings`
+    Embedding,
+}
+
+/// A model loaded into the engine under a client-visible id
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisteredModel {
+    pub id: String,
+    #[serde(skip)]
+    pub handle: ModelHandle,
+    pub kind: ModelKind,
+    /// Unix time the model was loaded
+    pub loaded_at: u64,
+}
+
+/// Why a request's `model` could not be routed
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResolveError {
+    /// No model with this id is loaded and there is no single model to fall back to
+    NotFound {
+        requested: String,
+        loaded: Vec<String>,
+    },
+    /// The model is loaded but serves the other kind of request
+    WrongKind { id: String, kind: ModelKind },
+    /// No model of the wanted kind is loaded at all
+    NoneLoaded(ModelKind),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { requested, loaded } => write!(
+                f,
+                "model '{}' is not loaded (loaded: {})",
+                requested,
+                loaded.join(", ")
+            ),
+            Self::WrongKind { id, kind } => match kind {
+                ModelKind::Embedding => write!(f, "'{id}' is an embedding model"),
+                ModelKind::Generation => write!(f, "'{id}' is not an embedding model"),
+            },
+            Self::NoneLoaded(ModelKind::Generation) => write!(f, "no generation model loaded"),
+            Self::NoneLoaded(ModelKind::Embedding) => write!(
+                f,
+                "no embedding model loaded; load one with POST /admin/models \
+                 or restart with --embedding-model <model>"
+            ),
+        }
+    }
+}
+
+/// Ids → loaded models, plus the default model of each kind
+#[derive(Debug, Default)]
+pub struct ModelRegistry {
+    models: BTreeMap<String, RegisteredModel>,
+    default_generation: Option<String>,
+    default_embedding: Option<String>,
+}
+
+impl ModelRegistry {
+    /// Register `model`, returning the handle of the model it replaces.
+    ///
+    /// The first model of each kind becomes that kind's default.
+    pub fn insert(&mut self, model: RegisteredModel) -> Option<ModelHandle> {
+        let id = model.id.clone();
+        let kind = model.kind;
+        let replaced = self.models.insert(id.clone(), model).map(|m| m.handle);
+        // A swap may change the kind, so don't leave the old default behind.
+        for k in [ModelKind::Generation, ModelKind::Embedding] {
+            if k != kind && self.default_id(k) == Some(id.as_str()) {
+                *self.default_slot(k) = self.first_of(k);
+            }
+        }
+        if self.default_id(kind).is_none() {
+            *self.default_slot(kind) = Some(id);
+        }
+        replaced
+    }
+
+    /// Unregister `id`; a removed default is replaced by another model of its kind.
+    pub fn remove(&mut self, id: &str) -> Option<RegisteredModel> {
+        let removed = self.models.remove(id)?;
+        if self.default_id(removed.kind) == Some(id) {
+            *self.default_slot(removed.kind) = self.first_of(removed.kind);
+        }
+        Some(removed)
+    }
+
+    /// Make `id` the model for requests that don't name a loaded one.
+    pub fn set_default(&mut self, id: &str) -> bool {
+        let Some(kind) = self.models.get(id).map(|m| m.kind) else {
+            return false;
+        };
+        *self.default_slot(kind) = Some(id.to_string());
+        true
+    }
+
+    pub fn default_id(&self, kind: ModelKind) -> Option<&str> {
+        match kind {
+            ModelKind::Generation => self.default_generation.as_deref(),
+            ModelKind::Embedding => self.default_embedding.as_deref(),
+        }
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = &RegisteredModel> {
+        self.models.values()
+    }
+
+    /// The model that serves a `kind` request naming `requested`.
+    ///
+    /// A loaded id is used as is. An empty or `default` name goes to the
+    /// default model, and so does any other name while only one model of
+    /// the kind is loaded, so single-model clients keep working whatever
+    /// they put in `model`.
+    pub fn resolve(
+        &self,
+        requested: &str,
+        kind: ModelKind,
+    ) -> Result<&RegisteredModel, ResolveError> {
+        if self.models.contains_key(requested) {
+            return self.resolve_exact(requested, kind);
+        }
+        let default = self
+            .default_id(kind)
+            .and_then(|id| self.models.get(id))
+            .ok_or(ResolveError::NoneLoaded(kind))?;
+        let single = self.models.values().filter(|m| m.kind == kind).count() == 1;
+        if requested.is_empty() || requested == "default" || single {
+            return Ok(default);
+        }
+        Err(ResolveError::NotFound {
+            requested: requested.to_string(),
+            loaded: self.models.keys().cloned().collect(),
+        })
+    }
+
+    /// The loaded model `id`, which must serve `kind` requests.
+    pub fn resolve_exact(
+        &self,
+        id: &str,
+        kind: ModelKind,
+    ) -> Result<&RegisteredModel, ResolveError> {
+        let model = self.models.get(id).ok_or_else(|| ResolveError::NotFound {
+            requested: id.to_string(),
+            loaded: self.models.keys().cloned().collect(),
+        })?;
+        if model.kind != kind {
+            return Err(ResolveError::WrongKind {
+                id: model.id.clone(),
+                kind: model.kind,
+            });
+        }
+        Ok(model)
+    }
+
+    fn default_slot(&mut self, kind: ModelKind) -> &mut Option<String> {
+        match kind {
+            ModelKind::Generation => &mut self.default_generation,
+            ModelKind::Embedding => &mut self.default_embedding,
+        }
+    }
+
+    fn first_of(&self, kind: ModelKind) -> Option<String> {
+        self.models
+            .values()
+            .find(|m| m.kind == kind)
+            .map(|m| m.id.clone())
+    }
+}
+
+/// Locate `model` on disk: `owner/name` in the HuggingFace cache
+/// (SafeTensors), anything else in Ollama's local store (GGUF).
+pub fn locate_model(model: &str) -> Result<(PathBuf, ModelFormat)> {
+    if model.contains('/') && !model.starts_with("hf.co/") {
+        Ok((
+            crate::hf::resolve_snapshot(model)?,
+            ModelFormat::SafeTensors,
+        ))
+    } else {
+        Ok((crate::ollama::find_model_blob(model)?, ModelFormat::Gguf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(id: &str, handle: u64, kind: ModelKind) -> RegisteredModel {
+        RegisteredModel {
+            id: id.to_string(),
+            handle: ModelHandle::new(handle),
+            kind,
+            loaded_at: 0,
+        }
+    }
+
+    #[test]
+    fn routes_by_id_and_falls_back_while_one_model_is_loaded() {
+        let mut registry = ModelRegistry::default();
+        registry.insert(model("llama", 1, ModelKind::Generation));
+        registry.insert(model("nomic", 2, ModelKind::Embedding));
+
+        let gen = ModelKind::Generation;
+        assert_eq!(registry.resolve("llama", gen).unwrap().handle.id(), 1);
+        assert_eq!(registry.resolve("gpt-4o", gen).unwrap().id, "llama");
+        assert_eq!(
+            registry.resolve("", ModelKind::Embedding).unwrap().id,
+            "nomic"
+        );
+        assert_eq!(
+            registry.resolve("nomic", gen).unwrap_err(),
+            ResolveError::WrongKind {
+                id: "nomic".into(),
+                kind: ModelKind::Embedding
+            }
+        );
+
+        registry.insert(model("qwen", 3, gen));
+        assert_eq!(registry.resolve("qwen", gen).unwrap().handle.id(), 3);
+        assert_eq!(registry.resolve("default", gen).unwrap().id, "llama");
+        assert!(matches!(
+            registry.resolve("gpt-4o", gen),
+            Err(ResolveError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn swapping_and_removing_keep_a_default() {
+        let mut registry = ModelRegistry::default();
+        let gen = ModelKind::Generation;
+        assert_eq!(
+            registry.resolve("x", gen).unwrap_err(),
+            ResolveError::NoneLoaded(gen)
+        );
+
+        registry.insert(model("a", 1, gen));
+        registry.insert(model("b", 2, gen));
+        assert_eq!(
+            registry.insert(model("a", 3, gen)),
+            Some(ModelHandle::new(1))
+        );
+        assert_eq!(registry.default_id(gen), Some("a"));
+
+        assert!(registry.set_default("b"));
+        assert!(!registry.set_default("missing"));
+        registry.remove("b");
+        assert_eq!(registry.default_id(gen), Some("a"));
+        assert_eq!(registry.resolve("", gen).unwrap().handle.id(), 3);
+
+        registry.insert(model("a", 4, ModelKind::Embedding));
+        assert_eq!(registry.default_id(gen), None);
+        assert_eq!(registry.default_id(ModelKind::Embedding), Some("a"));
+    }
+}
@@ -0,0 +1,149 @@
+//! `kwaainet models` — the models in Ollama's local store: list them with
+//! size and quantization, or pull one from its registry; and the models the
+//! network serves, from the model catalog.
+
+use anyhow::Result;
+
+use crate::cli::{ModelsAction, ModelsArgs};
+use crate::config::KwaaiNetConfig;
+use crate::display::*;
+use crate::{model_catalog, ollama};
+
+pub async fn run(args: ModelsArgs, json: bool) -> Result<()> {
+    match args.action.unwrap_or(ModelsAction::List) {
+        ModelsAction::List => list(json),
+        ModelsAction::Available => available(json).await,
+        ModelsAction::Pull { model } => pull(&model, json).await,
+    }
+}
+
+fn list(json: bool) -> Result<()> {
+    let models = ollama::local_models();
+    // The model `start` and `serve` pick for the configured one, if any.
+    let configured = KwaaiNetConfig::load_or_create()
+        .ok()
+        .and_then(|cfg| ollama::find_model_blob(&cfg.model).ok());
+    let is_configured =
+        |name: &str| configured.is_some() && ollama::resolve_model_blob(name).ok() == configured;
+
+    if json {
+        let out: Vec<serde_json::Value> = models
+            .iter()
+            .map(|m| {
+                let mut v = serde_json::to_value(m).unwrap_or_default();
+                v["configured"] = is_configured(&m.name).into();
+                v
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+
+    print_box_header("📦 Local Models");
+    if models.is_empty() {
+        println!("  No models in the Ollama store.");
+        println!();
+        print_info("Pull one with: kwaainet models pull llama3.1:8b");
+        print_separator();
+        return Ok(());
+    }
+    println!(
+        "  {:40} {:>10}  {:>8}  {:10}  FAMILY",
+        "NAME", "SIZE", "PARAMS", "QUANT"
+    );
+    for m in &models {
+        let marker = if is_configured(&m.name) { "*" } else { " " };
+        println!(
+            "{marker} {:40} {:>10}  {:>8}  {:10}  {}",
+            m.name,
+            format_bytes(m.size),
+            m.parameter_size.as_deref().unwrap_or("-"),
+            m.quantization.as_deref().unwrap_or("-"),
+            m.family.as_deref().unwrap_or("-"),
+        );
+    }
+    if configured.is_some() {
+        println!();
+        println!("  * used for the configured model");
+    }
+    print_separator();
+    Ok(())
+}
+
+async fn pull(model: &str, json: bool) -> Result<()> {
+    if !json {
+        print_box_header("📥 Pull Model");
+        println!("  Model: {model}");
+        println!();
+    }
+    let blob = ollama::pull_model(model, !json).await?;
+    if json {
+        let out = serde_json::json!({ "model": model, "blob": blob });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+    } else {
+        println!();
+        print_success(&format!("Pulled {model}"));
+        println!("  Blob: {}", blob.display());
+        print_separator();
+    }
+    Ok(())
+}
+
+async fn available(json: bool) -> Result<()> {
+    let cfg = KwaaiNetConfig::load_or_create()?;
+    let Some((catalog, fresh)) = model_catalog::current(&cfg).await else {
+        anyhow::bail!(
+            "cannot read the model catalog: the node is not running and none is cached \
+             (start it with `kwaainet start --daemon`)"
+        );
+    };
+    let configured = catalog.find(&cfg.model).map(|e| e.dht_prefix.as_str());
+
+    if json {
+        let out = serde_json::json!({
+            "fresh": fresh,
+            "fetched_at": catalog.fetched_at,
+            "configured": configured,
+            "models": catalog.entries,
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+
+    print_box_header("🌐 Models on the Network");
+    if !fresh {
+        let when = chrono::DateTime::from_timestamp(catalog.fetched_at as i64, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default();
+        print_warning(&format!(
+            "Node not reachable; showing the catalog read {when}"
+        ));
+        println!();
+    }
+    if catalog.is_empty() {
+        println!("  No models registered.");
+        print_separator();
+        return Ok(());
+    }
+    println!("  {:36} {:>6}  MODEL", "DHT PREFIX", "BLOCKS");
+    for e in &catalog.entries {
+        let marker = if Some(e.dht_prefix.as_str()) == configured {
+            "*"
+        } else {
+            " "
+        };
+        println!(
+            "{marker} {:36} {:>6}  {}",
+            e.dht_prefix,
+            e.num_blocks,
+            e.model_id().unwrap_or(&e.repository)
+        );
+    }
+    println!();
+    if configured.is_some() {
+        println!("  * configured model");
+    }
+    print_info("Serve one with: kwaainet config set model <MODEL or DHT PREFIX>");
+    print_separator();
+    Ok(())
+}
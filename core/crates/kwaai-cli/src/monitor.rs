@@ -1,11 +1,15 @@
-//! P2P connection monitoring and alert configuration
+//! P2P connection monitoring, alert configuration and webhook delivery
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use tracing::debug;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
 
-use crate::config::run_dir;
+use crate::config::{run_dir, AlertingConfig};
 
 fn monitor_file() -> PathBuf {
     run_dir().join("monitor.json")
@@ -81,3 +85,537 @@ pub fn save_alert_config(cfg: &AlertConfig) -> Result<()> {
     debug!("Saved alert config to {}", path.display());
     Ok(())
 }
+
+// ---------------------------------------------------------------------------
+// Alert engine
+// ---------------------------------------------------------------------------
+
+/// Identical alerts (same event and component) are sent at most once per
+/// window, so a crash loop or a flapping link doesn't flood any channel.
+const DEDUP_WINDOW: Duration = Duration::from_secs(600);
+
+/// Delivery attempts per alert and channel; retries back off from
+/// [`RETRY_BASE`].
+const DELIVERY_ATTEMPTS: u32 = 3;
+const RETRY_BASE: Duration = Duration::from_secs(2);
+
+/// An event worth telling the operator about.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Alert {
+    /// Connections stayed below `min_connections` for the threshold.
+    Disconnected {
+        connections: u32,
+        min_connections: u32,
+        down_secs: u64,
+    },
+    /// Connections recovered after a [`Alert::Disconnected`].
+    Reconnected { connections: u32, down_secs: u64 },
+    /// A component died: `p2pd`, or the node itself on its previous run.
+    Crashed { component: String, detail: String },
+    /// Sent by `kwaainet monitor alert --test`.
+    Test,
+}
+
+impl Alert {
+    fn dedup_key(&self) -> String {
+        match self {
+            Alert::Disconnected { .. } => "disconnected".to_string(),
+            Alert::Reconnected { .. } => "reconnected".to_string(),
+            Alert::Crashed { component, .. } => format!("crashed:{}", component),
+            Alert::Test => "test".to_string(),
+        }
+    }
+
+    /// One-line title and a human-readable body, for email and desktop.
+    fn summary(&self, node: &str) -> (String, String) {
+        match self {
+            Alert::Disconnected {
+                connections,
+                min_connections,
+                down_secs,
+            } => (
+                format!("KwaaiNet node {} disconnected", node),
+                format!(
+                    "{} connection(s), below the minimum of {}, for {} minute(s).",
+                    connections,
+                    min_connections,
+                    down_secs / 60
+                ),
+            ),
+            Alert::Reconnected {
+                connections,
+                down_secs,
+            } => (
+                format!("KwaaiNet node {} reconnected", node),
+                format!(
+                    "Back to {} connection(s) after {} minute(s) offline.",
+                    connections,
+                    down_secs / 60
+                ),
+            ),
+            Alert::Crashed { component, detail } => (
+                format!("KwaaiNet node {}: {} crashed", node, component),
+                detail.clone(),
+            ),
+            Alert::Test => (
+                format!("KwaaiNet node {}: test alert", node),
+                "Alerts from this node reach you on this channel.".to_string(),
+            ),
+        }
+    }
+}
+
+/// Where alerts are delivered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertChannel {
+    Webhook {
+        url: String,
+    },
+    Email {
+        smtp_url: String,
+        from: String,
+        to: String,
+    },
+    Desktop,
+}
+
+impl AlertChannel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertChannel::Webhook { .. } => "webhook",
+            AlertChannel::Email { .. } => "email",
+            AlertChannel::Desktop => "desktop",
+        }
+    }
+
+    /// The channels that are both enabled and configured.
+    ///
+    /// The webhook URL of `monitor alert` takes precedence over the one in
+    /// `health_monitoring.alerting`.
+    pub fn configured(alert: &AlertConfig, alerting: &AlertingConfig) -> Vec<AlertChannel> {
+        let mut channels = Vec::new();
+        let webhook = alert
+            .webhook_url
+            .clone()
+            .or_else(|| alerting.webhook_url.clone())
+            .filter(|u| !u.is_empty());
+        if let (true, Some(url)) = (alerting.channels.webhook, webhook) {
+            channels.push(AlertChannel::Webhook { url });
+        }
+        if let (true, Some(to), Some(smtp_url)) = (
+            alerting.channels.email,
+            alerting.email.clone().filter(|e| !e.is_empty()),
+            alerting.smtp_url.clone().filter(|u| !u.is_empty()),
+        ) {
+            let from = alerting.email_from.clone().unwrap_or_else(|| to.clone());
+            channels.push(AlertChannel::Email { smtp_url, from, to });
+        }
+        if alerting.channels.desktop {
+            channels.push(AlertChannel::Desktop);
+        }
+        channels
+    }
+
+    /// Deliver `alert`, retrying transient failures.
+    pub async fn deliver(&self, http: &reqwest::Client, alert: &Alert, node: &str) -> Result<()> {
+        match self {
+            AlertChannel::Webhook { url } => {
+                let mut payload = serde_json::to_value(alert).unwrap_or_default();
+                payload["node"] = serde_json::Value::from(node);
+                payload["timestamp"] = serde_json::Value::from(chrono::Utc::now().to_rfc3339());
+                with_retries(|| send_webhook(http, url, &payload)).await
+            }
+            AlertChannel::Email { smtp_url, from, to } => {
+                let (subject, body) = alert.summary(node);
+                with_retries(|| send_email(smtp_url, from, to, &subject, &body)).await
+            }
+            AlertChannel::Desktop => {
+                let (title, body) = alert.summary(node);
+                show_desktop_notification(title, body).await
+            }
+        }
+    }
+}
+
+/// A failed delivery attempt.
+enum DeliveryError {
+    /// Worth retrying (network trouble, server errors).
+    Transient(String),
+    /// Retrying cannot help (rejected request, bad configuration).
+    Permanent(String),
+}
+
+async fn with_retries<F, Fut>(mut attempt: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<(), DeliveryError>>,
+{
+    let mut n = 1;
+    loop {
+        match attempt().await {
+            Ok(()) => return Ok(()),
+            Err(DeliveryError::Permanent(e)) => bail!("{}", e),
+            Err(DeliveryError::Transient(e)) if n >= DELIVERY_ATTEMPTS => {
+                bail!("{} (after {} attempts)", e, n)
+            }
+            Err(DeliveryError::Transient(e)) => {
+                debug!("Alert delivery attempt {} failed: {}", n, e);
+                tokio::time::sleep(RETRY_BASE * 2u32.pow(n - 1)).await;
+                n += 1;
+            }
+        }
+    }
+}
+
+/// POST `payload` to `url`; client errors (4xx) are not retried.
+async fn send_webhook(
+    http: &reqwest::Client,
+    url: &str,
+    payload: &serde_json::Value,
+) -> std::result::Result<(), DeliveryError> {
+    match http.post(url).json(payload).send().await {
+        Ok(resp) if resp.status().is_success() => Ok(()),
+        Ok(resp) if resp.status().is_client_error() => Err(DeliveryError::Permanent(format!(
+            "webhook rejected the alert: HTTP {}",
+            resp.status()
+        ))),
+        Ok(resp) => Err(DeliveryError::Transient(format!("HTTP {}", resp.status()))),
+        Err(e) => Err(DeliveryError::Transient(e.to_string())),
+    }
+}
+
+/// Send a plain-text email through the SMTP server at `smtp_url`.
+async fn send_email(
+    smtp_url: &str,
+    from: &str,
+    to: &str,
+    subject: &str,
+    body: &str,
+) -> std::result::Result<(), DeliveryError> {
+    use lettre::{
+        message::header::ContentType, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    };
+
+    let permanent = |what: &str, e: &dyn std::fmt::Display| {
+        DeliveryError::Permanent(format!("{}: {}", what, e))
+    };
+    let message = Message::builder()
+        .from(from.parse().map_err(|e| permanent("invalid sender", &e))?)
+        .to(to.parse().map_err(|e| permanent("invalid recipient", &e))?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())
+        .map_err(|e| permanent("building email", &e))?;
+    let mailer = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+        .map_err(|e| permanent("invalid smtp_url", &e))?
+        .timeout(Some(Duration::from_secs(30)))
+        .build();
+    match mailer.send(message).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.is_permanent() => Err(permanent("SMTP server rejected the alert", &e)),
+        Err(e) => Err(DeliveryError::Transient(e.to_string())),
+    }
+}
+
+#[cfg(feature = "desktop-notify")]
+async fn show_desktop_notification(title: String, body: String) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .appname("KwaaiNet")
+            .summary(&title)
+            .body(&body)
+            .show()
+            .map(|_| ())
+    })
+    .await??;
+    Ok(())
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+async fn show_desktop_notification(_title: String, _body: String) -> Result<()> {
+    bail!("this build has no desktop notification support (feature `desktop-notify`)")
+}
+
+/// Decides which alerts to send, from connection samples and crash reports.
+///
+/// `monitor alert` settings (threshold, minimum connections, webhook) come
+/// from [`AlertConfig`]; which events fire and on which channels comes from
+/// the `alerting` section of `health_monitoring`. Either one enables
+/// alerting.
+pub struct AlertEngine {
+    enabled: bool,
+    channels: Vec<AlertChannel>,
+    threshold: Duration,
+    min_connections: u32,
+    on_disconnect: bool,
+    on_reconnect: bool,
+    on_critical: bool,
+    /// Public name of this node, included in every alert.
+    node: String,
+    below_since: Option<Instant>,
+    disconnect_alerted: bool,
+    last_sent: HashMap<String, Instant>,
+}
+
+impl AlertEngine {
+    pub fn new(alert: &AlertConfig, alerting: &AlertingConfig, node: &str) -> Self {
+        Self {
+            enabled: alert.enabled || alerting.enabled,
+            channels: AlertChannel::configured(alert, alerting),
+            threshold: Duration::from_secs(alert.disconnection_threshold_minutes as u64 * 60),
+            min_connections: alert.min_connections,
+            on_disconnect: alerting.on_disconnect,
+            on_reconnect: alerting.on_reconnect,
+            on_critical: alerting.on_critical,
+            node: node.to_string(),
+            below_since: None,
+            disconnect_alerted: false,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Switch to new alert settings (config reload), keeping the outage
+    /// being tracked and the cooldowns of alerts already sent.
+    pub fn reconfigure(&mut self, alert: &AlertConfig, alerting: &AlertingConfig, node: &str) {
+        let fresh = Self::new(alert, alerting, node);
+        *self = Self {
+            below_since: self.below_since,
+            disconnect_alerted: self.disconnect_alerted,
+            last_sent: std::mem::take(&mut self.last_sent),
+            ..fresh
+        };
+    }
+
+    /// Whether alerts are enabled and have somewhere to go.
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.channels.is_empty()
+    }
+
+    /// Fold in the current connection count.
+    ///
+    /// Fires [`Alert::Disconnected`] once the count has stayed below the
+    /// minimum for the threshold, and [`Alert::Reconnected`] when it
+    /// recovers after that.
+    pub fn observe_connections(&mut self, connections: u32, now: Instant) -> Option<Alert> {
+        if connections < self.min_connections {
+            let since = *self.below_since.get_or_insert(now);
+            let down = now.duration_since(since);
+            if self.disconnect_alerted || down < self.threshold {
+                return None;
+            }
+            self.disconnect_alerted = true;
+            if !self.on_disconnect {
+                return None;
+            }
+            return self.admit(
+                Alert::Disconnected {
+                    connections,
+                    min_connections: self.min_connections,
+                    down_secs: down.as_secs(),
+                },
+                now,
+            );
+        }
+
+        let since = self.below_since.take()?;
+        if !std::mem::take(&mut self.disconnect_alerted) || !self.on_reconnect {
+            return None;
+        }
+        self.admit(
+            Alert::Reconnected {
+                connections,
+                down_secs: now.duration_since(since).as_secs(),
+            },
+            now,
+        )
+    }
+
+    /// Report a crash of `component`.
+    pub fn crashed(&mut self, component: &str, detail: &str, now: Instant) -> Option<Alert> {
+        if !self.on_critical {
+            return None;
+        }
+        self.admit(
+            Alert::Crashed {
+                component: component.to_string(),
+                detail: detail.to_string(),
+            },
+            now,
+        )
+    }
+
+    fn admit(&mut self, alert: Alert, now: Instant) -> Option<Alert> {
+        if !self.is_active() {
+            return None;
+        }
+        let key = alert.dedup_key();
+        if let Some(at) = self.last_sent.get(&key) {
+            if now.duration_since(*at) < DEDUP_WINDOW {
+                debug!("Suppressing duplicate {} alert", key);
+                return None;
+            }
+        }
+        self.last_sent.insert(key, now);
+        Some(alert)
+    }
+
+    /// Deliver `alert` on every channel in the background, so a slow
+    /// channel never stalls the caller.
+    pub fn dispatch(&self, http: &reqwest::Client, alert: Alert) {
+        for channel in self.channels.clone() {
+            let (http, alert, node) = (http.clone(), alert.clone(), self.node.clone());
+            tokio::spawn(async move {
+                let event = alert.dedup_key();
+                match channel.deliver(&http, &alert, &node).await {
+                    Ok(()) => info!("Sent {} alert via {}", event, channel.name()),
+                    Err(e) => warn!(
+                        "Could not deliver {} alert via {}: {:#}",
+                        event,
+                        channel.name(),
+                        e
+                    ),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AlertChannels;
+
+    fn engine(alerting: AlertingConfig) -> AlertEngine {
+        let alert = AlertConfig {
+            enabled: true,
+            webhook_url: Some("http://127.0.0.1:9/hook".to_string()),
+            ..Default::default()
+        };
+        AlertEngine::new(&alert, &alerting, "test-node")
+    }
+
+    fn alerting() -> AlertingConfig {
+        AlertingConfig {
+            on_disconnect: true,
+            on_reconnect: true,
+            on_critical: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn disconnect_alert_waits_for_threshold_then_reconnect_follows() {
+        let mut engine = engine(alerting());
+        let t0 = Instant::now();
+        let minute = Duration::from_secs(60);
+
+        assert_eq!(engine.observe_connections(0, t0), None);
+        assert_eq!(engine.observe_connections(0, t0 + 4 * minute), None);
+        assert_eq!(
+            engine.observe_connections(0, t0 + 5 * minute),
+            Some(Alert::Disconnected {
+                connections: 0,
+                min_connections: 1,
+                down_secs: 300,
+            })
+        );
+        // Once per outage
+        assert_eq!(engine.observe_connections(0, t0 + 6 * minute), None);
+        assert_eq!(
+            engine.observe_connections(3, t0 + 7 * minute),
+            Some(Alert::Reconnected {
+                connections: 3,
+                down_secs: 420,
+            })
+        );
+        assert_eq!(engine.observe_connections(3, t0 + 8 * minute), None);
+    }
+
+    #[test]
+    fn short_outages_and_disabled_events_stay_quiet() {
+        let mut engine = engine(AlertingConfig {
+            on_disconnect: false,
+            ..alerting()
+        });
+        let t0 = Instant::now();
+        assert_eq!(engine.observe_connections(0, t0), None);
+        assert_eq!(
+            engine.observe_connections(2, t0 + Duration::from_secs(60)),
+            None
+        );
+        assert_eq!(
+            engine.observe_connections(0, t0 + Duration::from_secs(120)),
+            None
+        );
+        assert_eq!(
+            engine.observe_connections(0, t0 + Duration::from_secs(600)),
+            None
+        );
+        // The outage was past the threshold, so its end is still reported
+        assert!(engine
+            .observe_connections(1, t0 + Duration::from_secs(660))
+            .is_some());
+    }
+
+    #[test]
+    fn crash_alerts_are_deduplicated_per_component() {
+        let mut engine = engine(alerting());
+        let t0 = Instant::now();
+        assert!(engine.crashed("p2pd", "exit 2", t0).is_some());
+        assert!(engine
+            .crashed("p2pd", "exit 2", t0 + Duration::from_secs(5))
+            .is_none());
+        assert!(engine
+            .crashed("node", "PID 42", t0 + Duration::from_secs(5))
+            .is_some());
+        assert!(engine
+            .crashed("p2pd", "exit 2", t0 + DEDUP_WINDOW)
+            .is_some());
+    }
+
+    #[test]
+    fn inactive_without_channels() {
+        let alert = AlertConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let mut engine = AlertEngine::new(&alert, &alerting(), "test-node");
+        assert!(!engine.is_active());
+        assert!(engine.crashed("p2pd", "exit 2", Instant::now()).is_none());
+    }
+
+    #[test]
+    fn channels_need_both_flag_and_settings() {
+        let alert = AlertConfig {
+            webhook_url: Some("https://hooks.example/kwaai".to_string()),
+            ..Default::default()
+        };
+        let mut alerting = AlertingConfig {
+            email: Some("ops@example.com".to_string()),
+            channels: AlertChannels::default(),
+            ..Default::default()
+        };
+        let names = |a: &AlertingConfig| -> Vec<&'static str> {
+            AlertChannel::configured(&alert, a)
+                .iter()
+                .map(AlertChannel::name)
+                .collect()
+        };
+        // No SMTP server yet, desktop off by default
+        assert_eq!(names(&alerting), ["webhook"]);
+
+        alerting.smtp_url = Some("smtps://user:pw@smtp.example.com".to_string());
+        alerting.channels.desktop = true;
+        alerting.channels.webhook = false;
+        assert_eq!(names(&alerting), ["email", "desktop"]);
+        assert_eq!(
+            AlertChannel::configured(&alert, &alerting)[0],
+            AlertChannel::Email {
+                smtp_url: "smtps://user:pw@smtp.example.com".to_string(),
+                from: "ops@example.com".to_string(),
+                to: "ops@example.com".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! `kwaainet network state` — the swarm serving a model, as on map.kwaai.ai
+//!
+//! Reads every block key of the model from the bootstrap peers through the
+//! local p2pd and folds the server announcements into a
+//! [`SwarmState`]: which servers are up, the blocks each one serves, and
+//! whether the model is fully covered. The same view is served as
+//! `GET /v1/network/state` by `kwaainet serve` and `kwaainet shard api`.
+//! With `geoip.*` configured, each server is also located by country and ASN
+//! (see `crate::geoip`).
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use kwaai_p2p::state::{ServerRow, ServerState};
+use kwaai_p2p::{ModelHealth, ModelStateBuilder, NetworkConfig, SignatureStatus, SwarmState};
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::{Multiaddr, PeerId};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+
+use crate::cli::{NetworkAction, NetworkArgs};
+use crate::config::KwaaiNetConfig;
+use crate::display::*;
+use crate::geoip::GeoIp;
+use crate::shard_cmd::{daemon_socket, find_on_bootstrap};
+
+pub async fn run(args: NetworkArgs) -> Result<()> {
+    match args.action {
+        NetworkAction::State { model, json } => state(&model, json).await,
+    }
+}
+
+/// The local node's p2pd, our DHT ID and the bootstrap peers to query —
+/// what a DHT lookup through [`find_on_bootstrap`] needs.
+pub async fn dht_client(cfg: &KwaaiNetConfig) -> Result<(P2PClient, Vec<u8>, Vec<String>)> {
+    let client = P2PClient::connect(&daemon_socket())
+        .await
+        .context("cannot connect to the KwaaiNet node — is it running?")?;
+    let peer_id_hex = client.identify().await.context("identify peer")?;
+    let our_peer_id =
+        PeerId::from_bytes(&hex::decode(&peer_id_hex)?).context("parse our peer ID")?;
+    let our_dhtid = Sha1::new()
+        .chain_update(our_peer_id.to_bytes())
+        .finalize()
+        .to_vec();
+    let bootstrap_peers: Vec<String> = if cfg.initial_peers.is_empty() {
+        NetworkConfig::with_petals_bootstrap().bootstrap_peers
+    } else {
+        cfg.initial_peers.clone()
+    };
+    Ok((client, our_dhtid, bootstrap_peers))
+}
+
+/// Gather the state of `models` (the configured model when empty).
+pub async fn query_state(models: &[String]) -> Result<SwarmState> {
+    let cfg = KwaaiNetConfig::load_or_create()?;
+    let (client, our_dhtid, bootstrap_peers) = dht_client(&cfg).await?;
+
+    let configured = [cfg.model.clone()];
+    let models = if models.is_empty() {
+        &configured[..]
+    } else {
+        models
+    };
+    let mut reports = Vec::with_capacity(models.len());
+    for name in models {
+        // Resolve the prefix and block count the same way the model's own
+        // servers do when they announce.
+        let identity = cfg.identity_for(name);
+        let mut builder =
+            ModelStateBuilder::new(name.clone(), identity.dht_prefix, identity.num_blocks);
+        let keys = builder.keys();
+        let found = find_on_bootstrap(&client, &our_dhtid, &keys, &bootstrap_peers).await;
+        if found.responded.is_empty() {
+            anyhow::bail!("no bootstrap peer answered the DHT query");
+        }
+        for key in &keys {
+            for (_, result) in found.values.get(key).into_iter().flatten() {
+                builder.add_value(result.result_type, &result.value);
+            }
+        }
+        let mut report = builder.finish();
+        if let Some(geoip) = crate::geoip::resolver() {
+            locate(&client, geoip, &mut report.server_rows).await;
+        }
+        reports.push(report);
+    }
+    Ok(SwarmState::new(reports))
+}
+
+/// Seconds to spend looking up the address of a server we are not
+/// connected to.
+const FIND_PEER_TIMEOUT_SECS: i64 = 5;
+
+/// Fill in `peer_ip_info` from the address each server is connected at, or
+/// failing that, the addresses it advertises in the DHT.
+async fn locate(client: &P2PClient, geoip: &GeoIp, rows: &mut [ServerRow]) {
+    let to_multiaddrs = |addrs: Vec<Vec<u8>>| -> Vec<Multiaddr> {
+        addrs
+            .into_iter()
+            .filter_map(|a| Multiaddr::try_from(a).ok())
+            .collect()
+    };
+    let connected: HashMap<Vec<u8>, Vec<Multiaddr>> = client
+        .list_peers()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|p| (p.id, to_multiaddrs(p.addrs)))
+        .collect();
+
+    let lookups = rows.iter().map(|row| async {
+        let peer = row.record.peer_id.parse::<PeerId>().ok()?.to_bytes();
+        let addrs = match connected.get(&peer) {
+            Some(addrs) => addrs.clone(),
+            None => {
+                let found = client
+                    .dht_find_peer(peer, Some(FIND_PEER_TIMEOUT_SECS))
+                    .await
+                    .ok()?;
+                to_multiaddrs(found.addrs)
+            }
+        };
+        geoip.lookup_addrs(&addrs).await
+    });
+    let located = futures::future::join_all(lookups).await;
+    for (row, info) in rows.iter_mut().zip(located) {
+        row.peer_ip_info = info;
+    }
+}
+
+async fn state(models: &[String], json: bool) -> Result<()> {
+    let swarm = query_state(models).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&swarm)?);
+        return Ok(());
+    }
+
+    print_box_header("🗺  KwaaiNet Network State");
+    for report in &swarm.model_reports {
+        let health = match report.state {
+            ModelHealth::Healthy => "healthy",
+            ModelHealth::Broken => "broken",
+            ModelHealth::Offline => "offline",
+        };
+        println!("  Model:  {} ({})", report.name, report.dht_prefix);
+        println!(
+            "  State:  {} — {}/{} blocks covered by {} server(s)",
+            health,
+            report.blocks_covered,
+            report.num_blocks,
+            report.server_rows.len()
+        );
+        if let Some(gap) = report.first_gap() {
+            println!("  First uncovered block: {}", gap);
+        }
+        println!();
+        if report.server_rows.is_empty() {
+            print_info("No servers announced this model.");
+            println!();
+            continue;
+        }
+        println!(
+            "  {:<5}  {:<5}  {:<10} {:<8} {:>8}  NAME",
+            "START", "END", "PEER", "STATE", "TOK/S"
+        );
+        println!("  {}", "─".repeat(60));
+        for row in &report.server_rows {
+            let r = &row.record;
+            let state = match r.state {
+                ServerState::Online => "online",
+                ServerState::Joining => "joining",
+                ServerState::Offline => "offline",
+                ServerState::Unknown => "unknown",
+            };
+            let location = row
+                .peer_ip_info
+                .as_ref()
+                .map(|info| format!("  [{}]", info))
+                .unwrap_or_default();
+            let signature = match r.signature {
+                SignatureStatus::Valid => " (signed)",
+                SignatureStatus::Invalid => " (bad signature)",
+                SignatureStatus::Expired => " (expired signature)",
+                SignatureStatus::Unsigned => "",
+            };
+            println!(
+                "  {:>5}  {:>5}  {:<10} {:<8} {:>8.1}  {}{}{}",
+                r.start_block,
+                r.end_block,
+                row.short_peer_id,
+                state,
+                r.throughput,
+                r.public_name.as_deref().unwrap_or("—"),
+                signature,
+                location
+            );
+        }
+        println!();
+    }
+    println!("  {} distinct peer(s)", swarm.num_peers);
+    print_separator();
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct StateQuery {
+    /// Model to report on; the server's configured model when omitted.
+    model: Option<String>,
+}
+
+/// `GET /v1/network/state[?model=NAME]` — [`query_state`] as JSON.
+pub async fn network_state(Query(query): Query<StateQuery>) -> Response {
+    match query_state(query.model.as_slice()).await {
+        Ok(swarm) => Json(swarm).into_response(),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": { "message": format!("{e:#}"), "type": "server_error" }
+            })),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,231 @@
+//! Round-trip times to likely next hops, published as `next_pings`.
+//!
+//! Petals clients build inference chains by shortest path, where the cost of
+//! going from server A to server B includes the RTT A reports for B in its
+//! `next_pings`. A server that leaves the map empty looks equally far from
+//! everyone, so clients route around it or pick arbitrary continuations.
+//!
+//! Like the Petals server, every [`PING_INTERVAL`] we look up which servers
+//! hold the blocks right after ours — a chain can hand off anywhere inside
+//! our span, so servers starting within `(start, end]` are candidates, and
+//! those holding block `end` are sampled separately as the most likely
+//! continuation. Each candidate is pinged over libp2p and the RTTs are
+//! smoothed with an exponential moving average. Unreachable peers are
+//! reported as `inf`, which Petals reads as "do not route this way".
+
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::PeerId;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::shard_cmd::{discover_chain, BlockServerEntry};
+
+/// How often candidates are rediscovered and pinged.
+pub const PING_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Per-probe timeout; a peer that does not answer in time counts as
+/// unreachable for this round.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Candidates sampled from each group (mid-span and continuation), same cap
+/// as Petals' `max_pinged`.
+const MAX_PINGED: usize = 5;
+
+/// Weight of the newest sample in the moving average.
+const EMA_ALPHA: f64 = 0.2;
+
+/// Entries not refreshed for this long are dropped from `next_pings`.
+const EXPIRY: Duration = Duration::from_secs(600);
+
+/// Smoothed RTTs to peers, shared between the ping task and the announcer.
+#[derive(Clone, Default)]
+pub struct PingTable(Arc<Mutex<HashMap<PeerId, (f64, Instant)>>>);
+
+impl PingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold one probe result into the average. `None` marks the peer
+    /// unreachable until it answers again.
+    pub fn record(&self, peer: PeerId, rtt: Option<Duration>) {
+        let Ok(mut table) = self.0.lock() else {
+            return;
+        };
+        let sample = rtt.map_or(f64::INFINITY, |d| d.as_secs_f64());
+        let smoothed = match table.get(&peer) {
+            Some((prev, _)) if prev.is_finite() && sample.is_finite() => {
+                EMA_ALPHA * sample + (1.0 - EMA_ALPHA) * prev
+            }
+            _ => sample,
+        };
+        table.insert(peer, (smoothed, Instant::now()));
+    }
+
+    /// Current `next_pings` map: base58 peer ID → RTT in seconds.
+    pub fn snapshot(&self) -> HashMap<String, f64> {
+        let Ok(mut table) = self.0.lock() else {
+            return HashMap::new();
+        };
+        table.retain(|_, (_, at)| at.elapsed() < EXPIRY);
+        table
+            .iter()
+            .map(|(peer, (rtt, _))| (peer.to_base58(), *rtt))
+            .collect()
+    }
+}
+
+/// Peers worth pinging from a node serving `[start, end)`: up to
+/// [`MAX_PINGED`] servers whose span begins inside ours, plus up to
+/// [`MAX_PINGED`] servers holding block `end`.
+pub fn candidates(
+    chain: &[BlockServerEntry],
+    our_peer_id: &PeerId,
+    start: usize,
+    end: usize,
+) -> Vec<PeerId> {
+    let others = || chain.iter().filter(|e| e.peer_id != *our_peer_id);
+    let mut picked: Vec<PeerId> = Vec::new();
+    let mut seen: HashSet<PeerId> = HashSet::new();
+    let continuation = others()
+        .filter(|e| e.start_block <= end && end < e.end_block)
+        .take(MAX_PINGED);
+    let mid_span = others()
+        .filter(|e| start < e.start_block && e.start_block < end)
+        .take(MAX_PINGED);
+    for entry in continuation.chain(mid_span) {
+        if seen.insert(entry.peer_id) {
+            picked.push(entry.peer_id);
+        }
+    }
+    picked
+}
+
+/// Periodically ping likely next hops of this node's block range and record
+/// the results in `table`. Runs until the process exits.
+///
+/// Opens its own control connection to p2pd each round so a long probe never
+/// stalls the node's main loop, and so p2pd restarts are picked up.
+pub async fn run(
+    daemon_addr: String,
+    our_peer_id: PeerId,
+    bootstrap_peers: Vec<String>,
+    table: PingTable,
+) {
+    let mut ticker = tokio::time::interval(PING_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let Ok(config) = crate::config::KwaaiNetConfig::load_or_create() else {
+            continue;
+        };
+        let start = config.start_block as usize;
+        let end = config.effective_end_block() as usize;
+        let total = config.model_total_blocks() as usize;
+        // The last span has no next hop; an empty span has nothing to route.
+        if end <= start || end >= total {
+            continue;
+        }
+
+        let mut client = match P2PClient::connect(&daemon_addr).await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("next_pings: cannot connect to p2pd: {e}");
+                continue;
+            }
+        };
+        let chain = discover_chain(
+            &mut client,
+            &our_peer_id,
+            &config.effective_dht_prefix(),
+            total,
+            &bootstrap_peers,
+        )
+        .await;
+        let peers = candidates(&chain, &our_peer_id, start, end);
+        if peers.is_empty() {
+            continue;
+        }
+
+        let client = &client;
+        let results = futures::future::join_all(peers.iter().map(|peer| async move {
+            (
+                *peer,
+                client.ping_peer(&peer.to_bytes(), PING_TIMEOUT).await.ok(),
+            )
+        }))
+        .await;
+        for (peer, rtt) in results {
+            debug!(
+                peer = %peer,
+                rtt_ms = rtt.map(|d| d.as_secs_f64() * 1000.0),
+                "next_pings probe"
+            );
+            table.record(peer, rtt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(start: usize, end: usize) -> BlockServerEntry {
+        BlockServerEntry {
+            peer_id: PeerId::random(),
+            start_block: start,
+            end_block: end,
+            public_name: String::new(),
+            throughput: 0.0,
+            trust_score: None,
+            adapters: vec![],
+            dtypes: vec![],
+            attestation: None,
+        }
+    }
+
+    #[test]
+    fn candidates_are_continuations_and_mid_span_servers() {
+        let us = entry(8, 16);
+        let continuation = entry(16, 24);
+        let overlapping = entry(12, 20);
+        let covering_end = entry(0, 32);
+        let before_us = entry(0, 8);
+        let chain = vec![
+            us.clone(),
+            continuation.clone(),
+            overlapping.clone(),
+            covering_end.clone(),
+            before_us,
+        ];
+
+        let picked = candidates(&chain, &us.peer_id, 8, 16);
+        assert_eq!(picked.len(), 3);
+        for p in [
+            continuation.peer_id,
+            overlapping.peer_id,
+            covering_end.peer_id,
+        ] {
+            assert!(picked.contains(&p));
+        }
+    }
+
+    #[test]
+    fn rtts_are_smoothed_and_unreachable_is_infinite() {
+        let table = PingTable::new();
+        let peer = PeerId::random();
+        table.record(peer, Some(Duration::from_millis(100)));
+        table.record(peer, Some(Duration::from_millis(200)));
+        let rtt = table.snapshot()[&peer.to_base58()];
+        assert!((rtt - 0.12).abs() < 1e-9, "got {rtt}");
+
+        table.record(peer, None);
+        assert!(table.snapshot()[&peer.to_base58()].is_infinite());
+        // A fresh answer replaces the infinity outright.
+        table.record(peer, Some(Duration::from_millis(50)));
+        assert!((table.snapshot()[&peer.to_base58()] - 0.05).abs() < 1e-9);
+    }
+}
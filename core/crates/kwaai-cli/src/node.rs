@@ -6,28 +6,32 @@
 
 use anyhow::{Context, Result};
 use kwaai_hivemind_dht::{
+    client::RoutingConfig,
     codec::DHTRequest,
     protocol::{NodeInfo, RequestAuthInfo, StoreRequest},
+    validate,
     value::get_dht_time,
-    DHTStorage,
+    AuthorizedRpc, DHTStorage, MaintenanceConfig,
 };
-use kwaai_p2p::NetworkConfig;
-use kwaai_p2p_daemon::{stream, P2PDaemon};
+use kwaai_p2p_daemon::{stream, DaemonBuilder, DaemonEvent, DaemonSupervisor, P2PDaemon};
 use libp2p::PeerId;
 use sha1::{Digest, Sha1};
 use std::{
     collections::HashMap,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{io::AsyncWriteExt, net::TcpListener, signal, sync::RwLock};
 use tracing::{info, warn};
 
 use crate::config::KwaaiNetConfig;
+use crate::control::ControlMethod;
 use crate::daemon::{DaemonManager, ShardManager};
+use crate::dht_backend::P2pdDhtRpc;
+use crate::health::{LinkEvent, RecoveryAction};
 use crate::identity::NodeIdentity;
 
 type SharedStorage = Arc<RwLock<DHTStorage>>;
@@ -108,9 +112,16 @@ struct DHTServerInfo {
     torch_dtype: String,
     using_relay: bool,
     cache_tokens_left: i64,
-    #[allow(dead_code)]
+    /// Smoothed RTT in seconds to servers likely to follow us in a chain,
+    /// keyed by base58 peer ID (see `crate::next_pings`).
     next_pings: HashMap<String, f64>,
-    #[allow(dead_code)]
+    /// Per-block decode and prefill rates from the shard benchmark, and the
+    /// tokens/s the measured bandwidth can carry. 0.0 = not measured, in
+    /// which case the field is left out of the announcement.
+    inference_rps: f64,
+    forward_rps: f64,
+    network_rps: f64,
+    /// LoRA adapters the local shard has loaded (see `shard serve --adapter`).
     adapters: Vec<String>,
     /// Compact JSON representations of the node's valid Verifiable Credentials.
     /// Empty when no credentials are stored; included in the DHT fields map
@@ -126,6 +137,26 @@ struct DHTServerInfo {
     /// (which do not carry the DHT subkey). Unknown fields are silently ignored
     /// by legacy Python Hivemind clients.
     peer_id_b58: String,
+
+    /// Experiment flags this node runs with (see `crate::experiments`), so
+    /// the map can label its metrics by preview. Omitted when empty.
+    experiments: Vec<String>,
+
+    /// Set while the node re-dials lost bootstrap peers; it then announces
+    /// JOINING rather than ONLINE. Not part of the announcement itself.
+    reconnecting: bool,
+
+    /// Identity key the announcement is signed with (`sign_announcements`).
+    /// None leaves it unsigned, as Python servers announce.
+    signer: Option<libp2p::identity::Keypair>,
+
+    /// Device, dtypes and quantization formats the node computes with, so
+    /// clients can pick compatible servers. Omitted when None.
+    accelerator: Option<kwaai_p2p::AcceleratorInfo>,
+
+    /// Identity key and hardware claim, attested afresh in every
+    /// announcement (see `crate::admission`). Omitted when None.
+    attestation: Option<(libp2p::identity::Keypair, kwaai_p2p::NodeCapabilities)>,
 }
 
 impl DHTServerInfo {
@@ -149,12 +180,49 @@ impl DHTServerInfo {
             version: concat!("kwaai-", env!("CARGO_PKG_VERSION")).to_string(),
             torch_dtype: "float16".to_string(),
             using_relay: relay,
-            cache_tokens_left: 100_000,
+            cache_tokens_left: ShardManager::cache_tokens_left().unwrap_or(100_000) as i64,
+            inference_rps: 0.0,
+            forward_rps: 0.0,
+            network_rps: 0.0,
             next_pings: HashMap::new(),
-            adapters: vec![],
+            adapters: ShardManager::served_adapters(),
             trust_attestations,
             vpk_info,
             peer_id_b58,
+            experiments: crate::experiments::active()
+                .enabled_keys()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            reconnecting: false,
+            signer: None,
+            accelerator: None,
+            attestation: None,
+        }
+    }
+
+    /// Re-read whether the local shard is serving, with which adapters and
+    /// how much attention cache it has left.
+    fn refresh_shard_state(&mut self) {
+        self.state = if self.reconnecting {
+            1 // JOINING
+        } else if ShardManager::shard_is_ready() {
+            2
+        } else {
+            0
+        };
+        self.adapters = ShardManager::served_adapters();
+        if let Some(left) = ShardManager::cache_tokens_left() {
+            self.cache_tokens_left = left as i64;
+        }
+    }
+
+    /// Copy the measured rates for `model` from the throughput cache.
+    fn set_measured_rates(&mut self, model: &str, dl_bps: f64) {
+        if let Some(entry) = crate::throughput::load(model) {
+            self.inference_rps = entry.inference_rps;
+            self.forward_rps = entry.forward_rps;
+            self.network_rps = crate::throughput::network_rps(entry.hidden_size, dl_bps);
         }
     }
 
@@ -188,14 +256,44 @@ impl DHTServerInfo {
                 rmpv::Value::from("cache_tokens_left"),
                 rmpv::Value::from(self.cache_tokens_left),
             ),
-            (rmpv::Value::from("adapters"), rmpv::Value::Array(vec![])),
-            (rmpv::Value::from("next_pings"), rmpv::Value::Map(vec![])),
+            (
+                rmpv::Value::from("adapters"),
+                rmpv::Value::Array(
+                    self.adapters
+                        .iter()
+                        .map(|a| rmpv::Value::from(a.as_str()))
+                        .collect(),
+                ),
+            ),
+            (
+                rmpv::Value::from("next_pings"),
+                rmpv::Value::Map(
+                    self.next_pings
+                        .iter()
+                        .map(|(peer, rtt)| {
+                            (rmpv::Value::from(peer.as_str()), rmpv::Value::from(*rtt))
+                        })
+                        .collect(),
+                ),
+            ),
             (
                 rmpv::Value::from("peer_id"),
                 rmpv::Value::from(self.peer_id_b58.as_str()),
             ),
         ];
 
+        // Measured rates only — Petals clients treat these as real figures,
+        // so an unmeasured 0.0 is better left out than announced.
+        for (key, rps) in [
+            ("inference_rps", self.inference_rps),
+            ("forward_rps", self.forward_rps),
+            ("network_rps", self.network_rps),
+        ] {
+            if rps > 0.0 {
+                fields.push((rmpv::Value::from(key), rmpv::Value::from(rps)));
+            }
+        }
+
         // Include trust attestations when present — zero-cost for nodes without VCs.
         // Legacy clients (Python Hivemind, old map viewers) ignore unknown fields.
         if !self.trust_attestations.is_empty() {
@@ -217,11 +315,52 @@ impl DHTServerInfo {
             fields.push((rmpv::Value::from("vpk"), vpk.to_msgpack_value()));
         }
 
-        let inner = rmpv::Value::Array(vec![
+        if let Some(ref accel) = self.accelerator {
+            fields.push((rmpv::Value::from("accelerator"), accel.to_msgpack_value()));
+        }
+
+        // Valid as long as the record itself.
+        if let Some((ref keypair, ref caps)) = self.attestation {
+            let ttl = RECORD_TTL
+                .get()
+                .copied()
+                .unwrap_or(kwaai_p2p::config::DEFAULT_RECORD_TTL);
+            if let Some(bytes) = crate::admission::issue(keypair, caps, ttl) {
+                fields.push((
+                    rmpv::Value::from(crate::admission::FIELD),
+                    rmpv::Value::Binary(bytes),
+                ));
+            }
+        }
+
+        if !self.experiments.is_empty() {
+            fields.push((
+                rmpv::Value::from("experiments"),
+                rmpv::Value::Array(
+                    self.experiments
+                        .iter()
+                        .map(|e| rmpv::Value::from(e.as_str()))
+                        .collect(),
+                ),
+            ));
+        }
+
+        let mut inner = rmpv::Value::Array(vec![
             rmpv::Value::from(self.state),
             rmpv::Value::from(self.throughput),
             rmpv::Value::Map(fields),
         ]);
+        // Signed last, over every field above (see kwaai_p2p::state), and
+        // valid as long as the record itself.
+        if let Some(ref keypair) = self.signer {
+            let ttl = RECORD_TTL
+                .get()
+                .copied()
+                .unwrap_or(kwaai_p2p::config::DEFAULT_RECORD_TTL);
+            if !kwaai_p2p::state::sign_server_info(&mut inner, keypair, ttl) {
+                warn!("Could not sign server info; announcing it unsigned");
+            }
+        }
 
         let mut inner_bytes = Vec::new();
         rmpv::encode::write_value(&mut inner_bytes, &inner)?;
@@ -272,7 +411,26 @@ fn dht_id(raw_key: &str) -> Vec<u8> {
 // Public entry point
 // ---------------------------------------------------------------------------
 
-pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
+/// How [`run_node`] runs beyond what config.yaml says.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Write the PID file; off in containers, where the orchestrator tracks
+    /// the process and a stale file from a previous container is meaningless
+    pub daemon_files: bool,
+    /// Serve `/healthz` and `/readyz` here (see crate::probes)
+    pub health_listen: Option<std::net::SocketAddr>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            daemon_files: true,
+            health_listen: None,
+        }
+    }
+}
+
+pub async fn run_node(config: &KwaaiNetConfig, opts: &RunOptions) -> Result<()> {
     // Register SIGHUP handler BEFORE writing the PID file.  The shard
     // auto-rebalance path sends SIGHUP to the daemon PID to trigger a
     // re-announce.  If an old shard is still running when a new daemon starts,
@@ -287,13 +445,33 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         signal(SignalKind::hangup()).expect("SIGHUP handler")
     };
 
-    // PID tracking
+    let started_at = Instant::now();
+
+    // PID tracking. A clean shutdown removes the PID file, so one left
+    // behind by an earlier process means that run crashed.
     let daemon_mgr = DaemonManager::new();
-    daemon_mgr
-        .write_pid(std::process::id())
-        .context("writing PID")?;
+    let crashed_pid = if opts.daemon_files {
+        let crashed = daemon_mgr
+            .read_pid()
+            .filter(|pid| *pid != std::process::id());
+        daemon_mgr
+            .write_pid(std::process::id())
+            .context("writing PID")?;
+        crashed
+    } else {
+        None
+    };
     info!("KwaaiNet node starting (PID {})", std::process::id());
 
+    // Liveness / readiness probes, up before the slow startup below so an
+    // orchestrator sees the node as alive (but not ready) while it bootstraps.
+    let probes = std::sync::Arc::new(crate::probes::Probes::default());
+    if let Some(addr) = opts.health_listen {
+        crate::probes::spawn(addr, probes.clone()).await?;
+    }
+    let watchdog = crate::liveness::Watchdog::from_env();
+    let history = crate::history::NodeSession::start();
+
     // -----------------------------------------------------------------------
     // gRPC IPC surface — bind FIRST, before any of the p2p / DHT /
     // inference init. This is a UI/management surface for kwaainet, so
@@ -325,6 +503,10 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     };
     let node_did = node_identity.did();
     info!("Node DID: {}", node_did);
+    let dht_authorizer = crate::dht_auth::init(config, &node_identity.keypair)
+        .context("loading DHT access token")?;
+    crate::bandwidth::init(&config.bandwidth);
+    write_swarm_key(config).context("writing private swarm key")?;
 
     // Load valid VCs for this node's DID to include in DHT announcements
     let trust_attestations = match kwaai_trust::CredentialStore::open_default() {
@@ -355,14 +537,7 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         }
     };
 
-    let public_name = format!(
-        "{}/v{}",
-        config
-            .public_name
-            .clone()
-            .unwrap_or_else(|| "kwaainet-node".to_string()),
-        env!("CARGO_PKG_VERSION"),
-    );
+    let mut public_name = display_name(&config);
 
     info!(
         model = %config.model,
@@ -372,13 +547,43 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         "Configuring KwaaiNet node"
     );
 
-    // Bootstrap peers — prefer config, fall back to Petals defaults
-    let net_cfg = NetworkConfig::with_petals_bootstrap();
-    let bootstrap_peers: Vec<String> = if config.initial_peers.is_empty() {
-        net_cfg.bootstrap_peers.clone()
+    // Bootstrap peers — prefer config, fall back to Petals defaults, plus any
+    // list published at bootstrap_list_url. The manager re-ranks them by
+    // health once p2pd is up; until then the configured order is used.
+    let mut net_cfg = config.network_config();
+    net_cfg
+        .validate_announce_timing()
+        .context("invalid announce_interval_secs / record_ttl_secs")?;
+    let _ = RECORD_TTL.set(net_cfg.record_ttl);
+    let configured_peers = if config.initial_peers.is_empty() {
+        &net_cfg.bootstrap_peers
     } else {
-        config.initial_peers.clone()
+        &config.initial_peers
     };
+    let mut bootstrap = crate::bootstrap::BootstrapManager::new(configured_peers);
+    let bootstrap_http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    let mut bootstrap_list_fetched_at: Option<Instant> = None;
+    if let Some(url) = &config.bootstrap_list_url {
+        let list = match crate::bootstrap::fetch_list(&bootstrap_http, url).await {
+            Ok(list) => {
+                bootstrap_list_fetched_at = Some(Instant::now());
+                list
+            }
+            Err(e) => {
+                warn!(
+                    "Bootstrap list unavailable ({:#}) — using the cached copy",
+                    e
+                );
+                crate::bootstrap::load_cached()
+            }
+        };
+        let added = bootstrap.merge(&list);
+        info!("  Bootstrap list: {} peer(s), {} new", list.len(), added);
+    }
+    let mut bootstrap_peers: Vec<String> = bootstrap.ranked();
 
     // -----------------------------------------------------------------------
     // Step 1: Start p2pd
@@ -390,7 +595,7 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     }
 
     // p2pd listens for P2P traffic on the configured port
-    let host_addr = format!("/ip4/0.0.0.0/tcp/{}", config.port);
+    let host_addrs = listen_addrs(config);
 
     // Announce address: prefer explicit announce_addr, fall back to public_ip.
     // announce_addr is a raw multiaddr (e.g. /dns/kwaainet/tcp/8080).
@@ -400,7 +605,7 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     // `port` otherwise.
     // An empty string public_ip is treated as "no public IP".
     let announce_port = config.public_port.unwrap_or(config.port);
-    let announce_addr = config.announce_addr.clone().or_else(|| {
+    let mut announce_addr = config.announce_addr.clone().or_else(|| {
         config
             .public_ip
             .as_deref()
@@ -452,10 +657,11 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         // to public, even if it actually is. Only honour an explicit opt-in.
         .force_reachability_private(config.force_private)
         .nat_portmap(true)
-        .host_addrs([host_addr.clone()])
+        .host_addrs(&host_addrs)
         .bootstrap_peers(bootstrap_peers.clone())
         .trusted_relays(trusted_relays.clone())
         .with_identity_key(&identity_key_path);
+    let builder = with_swarm_key(builder);
 
     let builder = if let Some(ref addr) = announce_addr {
         builder.announce_addrs([addr.as_str()])
@@ -482,8 +688,11 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         builder
     };
 
-    let mut daemon = builder.spawn().await.context("starting p2pd")?;
-    let mut client = daemon.client().await.context("p2pd client")?;
+    // The supervisor keeps this builder for crash restarts and re-registers
+    // every handler added through it on the fresh daemon.
+    let (mut supervisor, mut client) = DaemonSupervisor::spawn(builder)
+        .await
+        .context("starting p2pd")?;
 
     let peer_id_hex = client.identify().await.context("identify peer")?;
     let peer_id = PeerId::from_bytes(&hex::decode(&peer_id_hex)?).context("parse peer ID")?;
@@ -493,7 +702,33 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     // Step 2: DHT storage
     // -----------------------------------------------------------------------
     info!("[2/6] Initialising DHT storage...");
-    let storage: SharedStorage = Arc::new(RwLock::new(DHTStorage::new(peer_id)));
+    let mut dht_storage = with_record_validators(
+        DHTStorage::new(peer_id).with_max_value_size(config.rpc_limits.max_value_bytes),
+        config,
+    );
+    if let Some(authorizer) = dht_authorizer {
+        // Only authenticated swarms know who signed a STORE.
+        dht_storage = dht_storage
+            .with_authorizer(authorizer, config.dht_require_auth)
+            .with_validator(validate::SubkeyMatchesSigner);
+    }
+    if config.dht_persist {
+        dht_storage = persist_dht_storage(dht_storage);
+    }
+    let storage: SharedStorage = Arc::new(RwLock::new(dht_storage));
+
+    // The in-process DHT backends run a swarm of their own beside p2pd;
+    // `kwaainet status` reports its health.
+    let dht_backend = match net_cfg.dht_backend {
+        kwaai_p2p::DhtBackendKind::HivemindDaemon => None,
+        kind => match crate::dht_backend::open(config).await {
+            Ok(backend) => Some(backend),
+            Err(e) => {
+                warn!("Cannot open the {:?} DHT backend: {:#}", kind, e);
+                None
+            }
+        },
+    };
 
     // -----------------------------------------------------------------------
     // Step 3: Register Hivemind RPC stream handlers with p2pd
@@ -504,8 +739,9 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         .context("binding RPC handler listener")?;
     let handler_addr = handler_listener.local_addr()?;
 
-    client
+    supervisor
         .register_stream_handler(
+            &client,
             &format!("/ip4/127.0.0.1/tcp/{}", handler_addr.port()),
             vec![
                 "DHTProtocol.rpc_ping".to_string(),
@@ -520,40 +756,116 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     // Register the /kwaai/p2p/hello/1.0.0 handler so any peer can DM us.
     // Lives alongside the Hivemind RPC handlers because both belong to the
     // node's "while we're alive, please answer these" surface area.
-    client
+    supervisor
         .add_unary_handler(
+            &client,
             kwaai_p2p_daemon::hello::HELLO_PROTO,
-            kwaai_p2p_daemon::hello::make_handler(),
+            crate::peer_filter::guard(kwaai_p2p_daemon::hello::make_handler()),
             false,
         )
         .await
         .context("registering p2p hello handler")?;
 
+    // Goodbye — peers announce their shutdown so we stop routing new sessions
+    // to them before their OFFLINE record reaches the DHT.
+    let _ = supervisor
+        .add_unary_handler(
+            &client,
+            crate::handoff::GOODBYE_PROTO,
+            crate::peer_filter::guard(crate::handoff::make_goodbye_handler()),
+            false,
+        )
+        .await;
+
+    // Dial-back — peers ask us to check that their announced addresses
+    // accept connections from outside (see crate::health).
+    let _ = supervisor
+        .add_unary_handler(
+            &client,
+            crate::health::DIALBACK_PROTO,
+            crate::peer_filter::guard(crate::health::make_dialback_handler()),
+            false,
+        )
+        .await;
+
     // Ollama proxy — lets remote peers route LLM requests to our local Ollama.
     let proxy_handler = crate::ollama_proxy::make_ollama_proxy_handler();
-    let _ = client
+    let _ = supervisor
         .add_unary_handler(
+            &client,
             crate::ollama_proxy::OLLAMA_PROXY_PROTO,
-            proxy_handler,
+            crate::peer_filter::guard(proxy_handler),
             false,
         )
         .await;
 
     // Shard proxy — lets remote peers route requests to our local shard API.
     let shard_proxy_handler = crate::ollama_proxy::make_shard_proxy_handler();
-    let _ = client
+    let _ = supervisor
         .add_unary_handler(
+            &client,
             crate::ollama_proxy::SHARD_PROXY_PROTO,
-            shard_proxy_handler,
+            crate::peer_filter::guard(shard_proxy_handler),
             false,
         )
         .await;
 
+    // Matchmaking — peers looking for an averaging group ask us to lead it
+    // (see crate::matchmaking).
+    let matchmaker = crate::matchmaking::init(&peer_id, &config.model);
+    let _ = supervisor
+        .add_unary_handler(
+            &client,
+            crate::matchmaking::MATCHMAKING_PROTO,
+            crate::peer_filter::guard(crate::matchmaking::make_join_handler(matchmaker)),
+            false,
+        )
+        .await;
+
+    // Expert serving — peers offloading MoE experts run them on us
+    // (see crate::experts).
+    if !config.moe.serve.is_empty() {
+        let served = match crate::ollama::resolve_model_blob(&config.model) {
+            Ok(blob) => crate::experts::served_experts(&config.moe, blob).await,
+            Err(e) => Err(e),
+        };
+        match served {
+            Ok(Some(experts)) => {
+                let _ = supervisor
+                    .add_unary_handler(
+                        &client,
+                        crate::experts::EXPERT_PROTO,
+                        crate::peer_filter::guard(crate::experts::make_expert_handler(experts)),
+                        false,
+                    )
+                    .await;
+                info!("Serving MoE experts {:?}", config.moe.serve);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Not serving MoE experts: {e:#}"),
+        }
+    }
+
     // Inference-mux server — persistent multiplexed stream handler.
     // Registering here means any node running `kwaainet start` supports
     // `mux://PEER_ID` in --inference-urls, not just shard-serve nodes.
-    match crate::inference_mux::start_inference_mux_server(&mut client).await {
-        Ok(_) => info!("inference-mux server registered successfully"),
+    match crate::inference_mux::serve_inference_mux().await {
+        Ok((mux_addr, mux_handle)) => {
+            match supervisor
+                .register_stream_handler(
+                    &client,
+                    &mux_addr,
+                    vec![crate::inference_mux::MUX_PROTO.to_string()],
+                )
+                .await
+            {
+                Ok(()) => info!("inference-mux server registered successfully"),
+                Err(e) => {
+                    mux_handle.abort();
+                    warn!("inference-mux server registration failed: {e:#}");
+                }
+            }
+        }
         Err(e) => warn!("inference-mux server registration failed: {e:#}"),
     }
 
@@ -562,27 +874,25 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     // -----------------------------------------------------------------------
     info!("[4/6] Bootstrapping...");
     dial_and_wait_for_bootstrap(&mut client, &bootstrap_peers).await?;
+    let reachable = bootstrap.probe_all(&client).await;
+    bootstrap_peers = bootstrap.ranked();
+    info!(
+        "  Bootstrap peers: {} of {} reachable ({})",
+        reachable,
+        bootstrap_peers.len(),
+        bootstrap.summary()
+    );
 
     // If p2pd crashed during bootstrap (Kademlia walk goroutine panic in
     // go-libp2p-kad-dht), restart it now before proceeding to announce.
     // This avoids a wasted announce attempt and surfaces the crash output.
-    if !daemon.is_running() {
-        let stderr = daemon.captured_stderr().await;
-        if !stderr.is_empty() {
-            warn!("p2pd crash output:\n{}", stderr.trim());
-        }
+    if !supervisor.is_running() {
         warn!("⚠️  p2pd crashed during bootstrap — restarting immediately…");
-        restart_p2pd(
-            &mut daemon,
-            &mut client,
-            &p2pd_path,
-            config,
-            &bootstrap_peers,
-            &announce_addr,
-            handler_addr,
-        )
-        .await
-        .context("p2pd restart after bootstrap crash")?;
+        supervisor
+            .restart(&mut client)
+            .await
+            .context("p2pd restart after bootstrap crash")?;
+        after_p2pd_restart(&mut client, config, &bootstrap_peers).await;
         info!("✅ p2pd restarted after bootstrap crash — continuing to announce");
     }
 
@@ -605,18 +915,11 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     //   trusted_relays config and let the relay path stand.
     let mut discovered_addrs: Vec<String>;
     if announce_addr.is_none() && config.trusted_relays.is_empty() {
-        (daemon, client, discovered_addrs) = discover_and_restart_with_announce(
-            daemon,
-            client,
-            &host_addr,
+        discovered_addrs = discover_and_restart_with_announce(
+            &mut supervisor,
+            &mut client,
+            config,
             &bootstrap_peers,
-            &identity_key_path,
-            &p2pd_path,
-            &handler_addr,
-            config.no_relay,
-            config.port,
-            config.identify_min_confirmations,
-            config.identify_timeout_secs,
         )
         .await?;
     } else {
@@ -648,34 +951,41 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         all_addrs_are_relay(&discovered_addrs)
     };
 
-    // Measure network bandwidth once at startup (1 MiB Cloudflare probe).
+    // Measure network bandwidth (1 MiB Cloudflare probe) once a compute
+    // measurement exists, and again every `throughput_refresh_secs`.
     // Stored so re-announcements can recompute effective_tps without re-probing.
-    let dl_bps: f64 = if crate::throughput::load(&config.model).is_some() {
+    let span_blocks = config
+        .effective_end_block()
+        .saturating_sub(config.start_block);
+    let mut dl_bps: f64 = 0.0;
+    let mut bandwidth_probed_at: Option<Instant> = None;
+    if crate::throughput::load(&config.model).is_some() {
         info!("  Measuring network bandwidth (1 MiB probe)...");
-        let bps = crate::throughput::measure_download_bps().await;
-        if bps > 0.0 {
-            info!("  Network:  {:.1} Mbps download", bps / 1_000_000.0);
+        dl_bps =
+            crate::bandwidth::cap_measured_bps(crate::throughput::measure_download_bps().await);
+        bandwidth_probed_at = Some(Instant::now());
+        if dl_bps > 0.0 {
+            info!("  Network:  {:.1} Mbps download", dl_bps / 1_000_000.0);
         } else {
             info!("  Network:  measurement failed — using compute limit only");
         }
-        bps
-    } else {
-        0.0
-    };
+    }
 
-    let throughput = compute_effective_tps(&config.model, dl_bps, using_relay);
+    let throughput = compute_effective_tps(&config.model, span_blocks, dl_bps, using_relay);
     if let Some(ref entry) = crate::throughput::load(&config.model) {
         info!(
-            "  Compute:  {:.1} tok/s (measured, hidden_dim={})",
-            entry.compute_tps, entry.hidden_size
+            "  Compute:  {:.1} tok/s over {} block(s) (measured, hidden_dim={})",
+            entry.span_tps(span_blocks),
+            span_blocks,
+            entry.hidden_size
         );
         info!(
             "  Effective: {:.1} tok/s  connection={} (min({:.1}, {:.1}×{}))",
             throughput,
             if using_relay { "relay" } else { "direct" },
-            entry.compute_tps,
+            entry.span_tps(span_blocks),
             if dl_bps > 0.0 {
-                dl_bps / (entry.hidden_size as f64 * 16.0)
+                crate::throughput::network_rps(entry.hidden_size, dl_bps)
             } else {
                 f64::INFINITY
             },
@@ -683,21 +993,18 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         );
     } else {
         info!(
-            "  Throughput: {:.1} tok/s (default — run `kwaainet benchmark` to measure)",
+            "  Throughput: {:.1} tok/s (default — measured once the shard server has loaded its blocks)",
             throughput
         );
     }
 
-    // Use the canonical DHT prefix from the map (set during startup model selection).
-    // Falls back to a computed prefix if the map wasn't consulted (e.g. --model override).
-    let prefix = config.effective_dht_prefix();
-    let repository = config.model_repository.clone().unwrap_or_else(|| {
-        if config.model.contains('/') {
-            format!("https://huggingface.co/{}", config.model)
-        } else {
-            format!("https://huggingface.co/meta-llama/{}", config.model)
-        }
-    });
+    // Use the canonical DHT prefix from the map (set during startup model selection),
+    // else the one the model registry lists, else one derived from the model name.
+    let kwaai_p2p::ModelIdentity {
+        dht_prefix: prefix,
+        repository,
+        ..
+    } = config.model_identity();
 
     info!("  DHT prefix:  {}", prefix);
     info!("  Repository:  {}", repository);
@@ -767,7 +1074,30 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         vpk_info,
         peer_id.to_base58(),
     );
-    announce(
+    server_info.set_measured_rates(&config.model, dl_bps);
+    if config.sign_announcements {
+        server_info.signer = Some(node_identity.keypair.clone());
+    }
+    // The device `shard serve` picks with the same config (see shard_cmd).
+    let device = if config.use_gpu {
+        kwaai_inference::DeviceType::detect_best()
+    } else {
+        kwaai_inference::DeviceType::Cpu
+    };
+    let calibration = crate::calibration::CalibrationEngine::new();
+    server_info.accelerator = Some(calibration.accelerator_info(device));
+    server_info.attestation = Some((
+        node_identity.keypair.clone(),
+        crate::admission::local_capabilities(&config.model, &calibration.hardware),
+    ));
+
+    // A failed initial announcement (bootstrap peers temporarily down) must
+    // neither abort startup nor leave the node invisible until the 300 s
+    // re-announce tick — AnnounceRetry drives background retries from the
+    // event loop until one succeeds.
+    let mut announce_retry = AnnounceRetry::new();
+    daemon_mgr.clear_announce_status();
+    let announced = announce(
         &mut client,
         peer_id,
         &storage,
@@ -781,30 +1111,20 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         None,
     )
     .await
-    .context("initial DHT announcement")?;
+    .unwrap_or_else(|e| {
+        warn!("Initial DHT announcement failed: {:#}", e);
+        false
+    });
 
     // If p2pd crashed during announce (Kademlia race despite the sleep above),
     // restart it immediately rather than waiting 120 s for the watchdog tick.
-    if !daemon.is_running() {
-        let stderr = daemon.captured_stderr().await;
-        if !stderr.is_empty() {
-            warn!("p2pd crash output:\n{}", stderr.trim());
-        }
+    if !supervisor.is_running() {
         warn!("⚠️  p2pd crashed during initial announce — restarting immediately…");
-        match restart_p2pd(
-            &mut daemon,
-            &mut client,
-            &p2pd_path,
-            config,
-            &bootstrap_peers,
-            &announce_addr,
-            handler_addr,
-        )
-        .await
-        {
+        match supervisor.restart(&mut client).await {
             Ok(()) => {
+                after_p2pd_restart(&mut client, config, &bootstrap_peers).await;
                 info!("✅ p2pd restarted — retrying initial announce…");
-                if let Err(e) = announce(
+                match announce(
                     &mut client,
                     peer_id,
                     &storage,
@@ -819,14 +1139,33 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                 )
                 .await
                 {
-                    warn!(
-                        "Initial announce retry failed: {} — will retry at 300s tick",
-                        e
-                    );
+                    Ok(ok) => announce_retry.record(ok, &daemon_mgr),
+                    Err(e) => {
+                        warn!("Initial announce retry failed: {}", e);
+                        announce_retry.record(false, &daemon_mgr);
+                    }
                 }
             }
-            Err(e) => warn!("p2pd restart failed: {} — will retry at 120s tick", e),
+            Err(e) => {
+                warn!(
+                    "p2pd restart failed: {} — the supervisor retries with backoff",
+                    e
+                );
+                announce_retry.record(announced, &daemon_mgr);
+            }
         }
+    } else {
+        announce_retry.record(announced, &daemon_mgr);
+    }
+
+    // Deferred announcement timer — only polled while announce_retry is
+    // pending. Parked far in the future once the node is announced.
+    let mut retry_announce = Box::pin(tokio::time::sleep(announce_retry.next_delay()));
+    if announce_retry.is_pending() {
+        warn!(
+            "⏳ Node not yet visible on the DHT — retrying announcement in the background (next in {}s)",
+            announce_retry.next_delay().as_secs()
+        );
     }
 
     info!("✅ KwaaiNet node running");
@@ -848,20 +1187,22 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     let mut config = config.clone();
     let storage_clone = storage.clone();
 
-    // Re-announce every 300 s ± 30 s (jittered so nodes don't thundering-herd
-    // the bootstrap peers after a network partition or mass restart).
-    // DHT TTL is 360 s, so 270–330 s keeps every record refreshed with
-    // at least 30 s headroom.  One observation per peer per cycle is recorded
-    // in the reputation store, piggybacked on the STORE RPC latency.
-    let mut rep_store = crate::reputation::ReputationStore::load();
-    let mut next_announce = Box::pin(tokio::time::sleep(Duration::from_secs(jitter_secs(
-        300, 30,
-    ))));
+    // Re-announce every `announce_interval_secs` ± 10% (jittered so nodes
+    // don't thundering-herd the bootstrap peers after a network partition or
+    // mass restart). `validate_announce_timing` guarantees the record TTL
+    // outlasts the longest jittered wait, so every record is refreshed before
+    // it lapses.  One observation per peer per cycle is recorded in the
+    // reputation store, piggybacked on the STORE RPC latency.
+    let mut rep_store = crate::reputation::load_store();
+    let mut next_announce = Box::pin(tokio::time::sleep(net_cfg.next_announce_delay()));
 
     // Tracks the number of RPC stream handler tasks currently in-flight.
     // Used to gate p2pd restarts: we defer any restart until this reaches zero
     // so we never tear down the daemon mid-request.
     let active_rpc_streams: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    // Unix time an incoming DHT request was last answered, for the heartbeat.
+    let last_rpc_served: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+    let rpc_limiter = Arc::new(crate::rpc_limits::RpcLimiter::new(&config.rpc_limits));
 
     // When IDENTIFY detects an address change while RPC streams are active we
     // store the new addresses here and apply the restart at the next reannounce
@@ -884,13 +1225,79 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                                  //   (we skip the initial discover for the same reason), set
                                  //   pending_restart, and the subsequent restart would tear down the
                                  //   relay reservation that makes the node reachable in the first place.
-    let explicit_announce = announce_addr.is_some() || !config.trusted_relays.is_empty();
+    let mut explicit_announce = announce_addr.is_some() || !config.trusted_relays.is_empty();
+
+    // Public IP watch — the counterpart of the IDENTIFY check for nodes that
+    // build their announce address from a configured `public_ip`. An
+    // explicit `announce_addr` (possibly a DNS name) is left alone.
+    let track_public_ip = config.announce_addr.is_none()
+        && config.public_ip.as_deref().is_some_and(|ip| !ip.is_empty())
+        && config.public_ip_check_secs > 0;
+    let mut public_ip_check =
+        tokio::time::interval(Duration::from_secs(config.public_ip_check_secs.max(30)));
+    public_ip_check.tick().await;
+    let ip_http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap_or_default();
 
     // Fast p2pd crash detection: poll every 10 s instead of waiting for the
     // 300 s re-announce tick. Skips the first tick so we don't immediately
-    // check right after bootstrap completed.
+    // check right after bootstrap completed. The supervisor restarts a dead
+    // daemon with backoff and reports it on `daemon_events`.
     let mut p2pd_heartbeat = tokio::time::interval(Duration::from_secs(10));
     p2pd_heartbeat.tick().await;
+    let mut daemon_events = supervisor.subscribe();
+
+    // Reachability self-check: peers dial our announced addresses back and
+    // we check our relay is still connected. Repeated failures re-dial the
+    // bootstrap peers and eventually fall back to relay circuits — unless
+    // p2pd already runs as a private node, in which case it relays anyway.
+    let health = &config.health_monitoring;
+    let check_reachability = health.enabled && health.reachability_interval > 0;
+    let mut reachability = crate::health::ReachabilityMonitor::new(health, !config.force_private);
+    let mut reachability_check =
+        tokio::time::interval(Duration::from_secs(health.reachability_interval.max(30)));
+    reachability_check.tick().await;
+
+    // Bootstrap link watch: every health.check_interval the bootstrap peers
+    // are pinged. Once none answers, the node announces JOINING and re-dials
+    // them on health_monitoring.reconnection's backoff until one answers,
+    // then announces ONLINE again.
+    let watch_link = health.enabled && health.reconnection.enabled;
+    let mut reconnection = crate::health::ReconnectionManager::new(&health.reconnection);
+    let mut link_check = tokio::time::interval(Duration::from_secs(health.check_interval.max(10)));
+    link_check.tick().await;
+    let mut reconnect_timer = Box::pin(tokio::time::sleep(Duration::ZERO));
+    daemon_mgr.write_health_status(health_status(&reachability, &reconnection));
+
+    // Webhook alerts: connections are sampled every 60 s; crashes of p2pd
+    // (and of this node's previous run) are reported as they happen.
+    let mut alerts = crate::monitor::AlertEngine::new(
+        &crate::monitor::load_alert_config(),
+        &config.health_monitoring.alerting,
+        &public_name,
+    );
+    let alert_http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    let mut alert_check = tokio::time::interval(Duration::from_secs(60));
+    alert_check.tick().await;
+    if let Some(pid) = crashed_pid {
+        let detail = format!("previous run (PID {}) did not shut down cleanly", pid);
+        if let Some(alert) = alerts.crashed("node", &detail, Instant::now()) {
+            alerts.dispatch(&alert_http, alert);
+        }
+    }
+
+    // Peer filter: drop connections from peers config.yaml refuses.
+    let mut peer_sweep = tokio::time::interval(crate::peer_filter::SWEEP_INTERVAL);
+    peer_sweep.tick().await;
+
+    // Run history: extend the uptime session and sample connections.
+    let mut history_sample = tokio::time::interval(crate::history::SAMPLE_INTERVAL);
+    history_sample.tick().await;
 
     // Relay circuit keepalive: send a trivial identify RPC to p2pd every 60 s.
     // This keeps the p2pd unix-socket warm and exercises the p2pd ↔ relay TCP
@@ -898,6 +1305,22 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     let mut relay_keepalive = tokio::time::interval(Duration::from_secs(60));
     relay_keepalive.tick().await;
 
+    // Rotate the node, shard and storage logs and apply retention.
+    tokio::spawn(crate::logs::run_rotation(
+        crate::config::log_dir(),
+        config.logging.clone(),
+    ));
+
+    // Latency probes to the servers after our span, published as next_pings
+    // so Petals clients can route low-latency chains through us.
+    let next_pings = crate::next_pings::PingTable::new();
+    tokio::spawn(crate::next_pings::run(
+        client.daemon_addr().to_string(),
+        peer_id,
+        bootstrap_peers.clone(),
+        next_pings.clone(),
+    ));
+
     // Ollama health watcher: spawn a background task that polls
     // http://localhost:<port>/api/tags every 15 s. Sends `true` on each recovery
     // (down→up transition) so the main loop can re-announce immediately.
@@ -961,6 +1384,56 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     // used to race the new process's own startup checks).
     let mut pending_update_version: Option<String> = None;
 
+    // DHT storage maintenance: prune expired records every 30 s and renew our
+    // own records if they come within 30 s of expiry. The periodic
+    // re-announce normally refreshes them first (≥ 30 s headroom), so this
+    // only fires when that loop falls behind; renewed records are pushed to
+    // the bootstrap peers from the event loop below.
+    let (republish_tx, mut republish_rx) = tokio::sync::mpsc::unbounded_channel();
+    let maintenance = storage.read().await.spawn_maintenance(
+        MaintenanceConfig {
+            interval: Duration::from_secs(30),
+            republish_window: 30.0,
+        },
+        Some(republish_tx),
+    );
+
+    // Local control channel for `kwaainet status / peers / announce /
+    // reconnect`. Bound only now, once the event loop that answers it is
+    // about to run; until then the CLI falls back to the status file. Like
+    // the gRPC surface, failing to bind is logged but non-fatal.
+    let (_control_handle, mut control_rx) = match crate::control::spawn() {
+        Ok((handle, rx)) => (Some(handle), rx),
+        Err(e) => {
+            warn!("Control socket unavailable: {:#}", e);
+            (None, tokio::sync::mpsc::channel(1).1)
+        }
+    };
+
+    // Contribution hours (bandwidth.schedule): outside the window the node
+    // withdraws its announcement and stops re-announcing until it reopens.
+    // The first tick fires immediately, so a node started off-hours
+    // unannounces straight away.
+    let schedule: Option<crate::bandwidth::ContributionSchedule> = match config
+        .bandwidth
+        .schedule
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+    {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            warn!("Ignoring bandwidth.schedule: {:#}", e);
+            None
+        }
+    };
+    if let Some(schedule) = schedule {
+        info!("Contributing only between {} (local time)", schedule);
+    }
+    let mut schedule_check = tokio::time::interval(Duration::from_secs(60));
+    let mut outside_schedule = false;
+    let mut heartbeat = tokio::time::interval(crate::liveness::BEAT_INTERVAL);
+
     loop {
         tokio::select! {
             // Incoming RPC stream from p2pd
@@ -968,20 +1441,69 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                 match result {
                     Ok((mut stream, addr)) => {
                         info!("Incoming RPC from {}", addr);
+                        // Dropping the stream closes it; the remote sees a
+                        // failed call rather than a queued one.
+                        let Some(permit) = rpc_limiter.try_acquire() else {
+                            tracing::debug!("RPC handler limit reached, closing stream from {}", addr);
+                            continue;
+                        };
                         let s = storage_clone.clone();
+                        let limiter = rpc_limiter.clone();
                         let counter = active_rpc_streams.clone();
+                        let served = last_rpc_served.clone();
                         counter.fetch_add(1, Ordering::Relaxed);
                         tokio::spawn(async move {
-                            if let Err(e) = handle_rpc_stream(&mut stream, s).await {
-                                warn!("RPC handler error: {}", e);
+                            let handled = tokio::time::timeout(
+                                crate::rpc_limits::HANDLER_TIMEOUT,
+                                handle_rpc_stream(&mut stream, s, &limiter),
+                            )
+                            .await;
+                            match handled {
+                                Ok(Ok(())) => {
+                                    served.store(crate::liveness::unix_now(), Ordering::Relaxed)
+                                }
+                                Ok(Err(e)) => warn!("RPC handler error: {}", e),
+                                Err(_) => {
+                                    limiter.record_timeout();
+                                    tracing::debug!("RPC from {} timed out", addr);
+                                }
                             }
                             counter.fetch_sub(1, Ordering::Relaxed);
+                            drop(permit);
                         });
                     }
                     Err(e) => warn!("Accept error: {}", e),
                 }
             }
 
+            // Heartbeat for `kwaainet status`, the service manager's
+            // watchdog (see crate::liveness) and the health endpoints, which
+            // also learn whether the node is ready to serve.
+            _ = heartbeat.tick() => {
+                watchdog.beat();
+                probes.beat(if outside_schedule {
+                    Some("outside contribution hours")
+                } else if announce_retry.is_pending() {
+                    Some("not yet announced on the DHT")
+                } else if !supervisor.is_running() {
+                    Some("p2pd is not running")
+                } else {
+                    None
+                });
+                if opts.daemon_files {
+                    let served = last_rpc_served.load(Ordering::Relaxed);
+                    let last_dht_response = announce_retry
+                        .last_ok()
+                        .max((served != 0).then_some(served));
+                    daemon_mgr.write_heartbeat(&crate::liveness::Heartbeat {
+                        pid: std::process::id(),
+                        at: crate::liveness::unix_now(),
+                        last_announce: announce_retry.last_ok(),
+                        last_dht_response,
+                    });
+                }
+            }
+
             // SIGHUP (Unix) / never (Windows) — re-read config and re-announce.
             // Uses #[cfg] inside the arm expression to avoid a conditional arm,
             // which is unsupported by tokio::select!.
@@ -989,63 +1511,88 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                 #[cfg(unix)] { sighup.recv().await; }
                 #[cfg(not(unix))] { std::future::pending::<Option<()>>().await; }
             } => {
-                info!("SIGHUP received — re-reading config and re-announcing");
-                if let Ok(fresh) = KwaaiNetConfig::load_or_create() {
-                    if fresh.start_block != config.start_block || fresh.blocks != config.blocks {
-                        info!(
-                            "Block range updated: [{}–{}) → [{}–{})",
-                            config.start_block, config.effective_end_block(),
-                            fresh.start_block, fresh.start_block + fresh.blocks,
-                        );
-                        config.start_block = fresh.start_block;
-                        config.blocks = fresh.blocks;
+                info!("SIGHUP received — reloading config and re-announcing");
+                reload_config(
+                    &mut config, &mut net_cfg, &rpc_limiter, &mut alerts, &mut public_name,
+                );
+                server_info.public_name = public_name.clone();
+                if outside_schedule {
+                    info!("Outside contribution hours — re-announce deferred until the window opens");
+                    continue;
+                }
+                let sb = config.start_block as i32;
+                let eb = config.effective_end_block() as i32;
+                server_info.start_block = sb;
+                server_info.end_block = eb;
+                server_info.refresh_shard_state();
+                match announce(
+                    &mut client, peer_id, &storage, &bootstrap_peers,
+                    &prefix, &repository, config.model_total_blocks(),
+                    sb, eb, &server_info, None,
+                ).await {
+                    Ok(true) if announce_retry.is_pending() => {
+                        announce_retry.record(true, &daemon_mgr);
                     }
+                    Ok(_) => {}
+                    Err(e) => warn!("Re-announce after SIGHUP failed: {}", e),
                 }
+            }
+
+            // Deferred initial announcement: retry with fast-then-slow
+            // backoff until the block records first reach a DHT peer.
+            _ = &mut retry_announce, if announce_retry.is_pending() && !outside_schedule => {
                 let sb = config.start_block as i32;
                 let eb = config.effective_end_block() as i32;
                 server_info.start_block = sb;
                 server_info.end_block = eb;
-                server_info.state = if ShardManager::shard_is_ready() { 2 } else { 0 };
-                if let Err(e) = announce(
+                server_info.refresh_shard_state();
+                info!(
+                    "Retrying initial DHT announcement (attempt {})...",
+                    announce_retry.attempts() + 1
+                );
+                let ok = match announce(
                     &mut client, peer_id, &storage, &bootstrap_peers,
                     &prefix, &repository, config.model_total_blocks(),
                     sb, eb, &server_info, None,
                 ).await {
-                    warn!("Re-announce after SIGHUP failed: {}", e);
+                    Ok(ok) => ok,
+                    Err(e) => {
+                        warn!("Deferred announce failed: {}", e);
+                        false
+                    }
+                };
+                if !ok {
+                    bootstrap.rotate();
+                    bootstrap_peers = bootstrap.ranked();
+                }
+                announce_retry.record(ok, &daemon_mgr);
+                if announce_retry.is_pending() {
+                    let delay = announce_retry.next_delay();
+                    info!("Announcement still pending — next attempt in {}s", delay.as_secs());
+                    retry_announce.as_mut().reset(tokio::time::Instant::now() + delay);
+                }
+            }
+
+            // Own records renewed by the storage maintenance task
+            Some(req) = republish_rx.recv() => {
+                let n = req.keys.len();
+                let (ok, _) = store_on_closest(&mut client, peer_id, &bootstrap_peers, req).await;
+                if ok {
+                    info!("Republished {} expiring DHT records", n);
+                } else {
+                    warn!("Republishing {} expiring DHT records reached no DHT peer", n);
                 }
             }
 
             // Periodic re-announcement (300 s ± 30 s jitter)
             _ = &mut next_announce => {
-                // p2pd watchdog: restart if the child process died unexpectedly.
-                if !daemon.is_running() {
-                    let stderr = daemon.captured_stderr().await;
-                    if !stderr.is_empty() {
-                        warn!("p2pd crash output:\n{}", stderr.trim());
-                    }
-                    warn!("⚠️  p2pd process died — attempting restart…");
-                    match restart_p2pd(
-                        &mut daemon, &mut client, &p2pd_path, &config,
-                        &bootstrap_peers, &announce_addr, handler_addr,
-                    ).await {
-                        Ok(()) => {
-                            info!("✅ p2pd restarted and handlers re-registered");
-                            // Routing table is empty right after restart; announcing now
-                            // fails with "no peer in table". Delay 30 s so bootstrap
-                            // peers can populate the routing table first.
-                            next_announce
-                                .as_mut()
-                                .reset(tokio::time::Instant::now() + Duration::from_secs(30));
-                            continue;
-                        }
-                        Err(e) => {
-                            warn!("p2pd restart failed: {} — will retry in 120s", e);
-                            next_announce
-                                .as_mut()
-                                .reset(tokio::time::Instant::now() + Duration::from_secs(120));
-                            continue;
-                        }
-                    }
+                // p2pd is down: the heartbeat supervisor restarts it and
+                // DaemonEvent::Restarted re-arms this timer.
+                if !supervisor.is_running() {
+                    next_announce
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + Duration::from_secs(30));
+                    continue;
                 }
 
                 // If IDENTIFY detected an address change while RPC streams were
@@ -1062,39 +1609,33 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                         for addr in new_addrs {
                             info!("  {}", addr);
                         }
-                        if let Err(e) = restart_p2pd_with_addrs(
-                            &mut daemon, &mut client, new_addrs,
-                            &host_addr, &bootstrap_peers, &identity_key_path,
-                            &p2pd_path, &handler_addr, config.no_relay,
-                        ).await {
+                        // The supervisor keeps the new addresses even if this
+                        // restart fails and its backoff retries use them, so
+                        // the change counts as applied either way.
+                        // DaemonEvent::Restarted schedules the re-announce.
+                        if let Err(e) = supervisor
+                            .restart_with(&mut client, |b| {
+                                b.without_announce_addrs()
+                                    .announce_addrs(new_addrs.iter().map(String::as_str))
+                            })
+                            .await
+                        {
                             warn!("Deferred p2pd restart failed: {}", e);
-                        } else {
-                            discovered_addrs = new_addrs.clone();
-                            server_info.using_relay = all_addrs_are_relay(&discovered_addrs);
-                            pending_restart = None;
-                            // p2pd's routing table is empty immediately after restart;
-                            // delay re-announcement so bootstrap peers can populate it first.
-                            next_announce
-                                .as_mut()
-                                .reset(tokio::time::Instant::now() + Duration::from_secs(30));
                         }
+                        discovered_addrs = new_addrs.clone();
+                        server_info.using_relay = all_addrs_are_relay(&discovered_addrs);
+                        pending_restart = None;
+                        next_announce
+                            .as_mut()
+                            .reset(tokio::time::Instant::now() + Duration::from_secs(30));
+                        continue;
                     }
                 }
 
                 // Re-read config to pick up start_block changes written by
                 // `shard serve` (via signal_reannounce) or `kwaainet config set`.
                 // On Windows this also drains the reannounce.flag file.
-                if let Ok(fresh) = KwaaiNetConfig::load_or_create() {
-                    if fresh.start_block != config.start_block || fresh.blocks != config.blocks {
-                        info!(
-                            "Block range updated: [{}–{}) → [{}–{})",
-                            config.start_block, config.effective_end_block(),
-                            fresh.start_block, fresh.start_block + fresh.blocks,
-                        );
-                        config.start_block = fresh.start_block;
-                        config.blocks = fresh.blocks;
-                    }
-                }
+                reload_block_range(&mut config);
                 #[cfg(not(unix))]
                 {
                     let flag = crate::config::run_dir().join("reannounce.flag");
@@ -1103,8 +1644,31 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                     }
                 }
 
+                // Re-probe bandwidth once a compute measurement exists (the
+                // shard benchmark usually lands after startup) and then
+                // every throughput_refresh_secs.
+                let probe_due = match bandwidth_probed_at {
+                    None => true,
+                    Some(t) => {
+                        config.throughput_refresh_secs > 0
+                            && t.elapsed() >= Duration::from_secs(config.throughput_refresh_secs)
+                    }
+                };
+                if probe_due && crate::throughput::load(&config.model).is_some() {
+                    let bps = crate::bandwidth::cap_measured_bps(
+                        crate::throughput::measure_download_bps().await,
+                    );
+                    bandwidth_probed_at = Some(Instant::now());
+                    if bps > 0.0 {
+                        tracing::debug!("Network: {:.1} Mbps download", bps / 1_000_000.0);
+                        dl_bps = bps;
+                    }
+                }
+
                 // Refresh throughput from cache.
-                let fresh_tps = compute_effective_tps(&config.model, dl_bps, using_relay);
+                let span_blocks = (server_info.end_block - server_info.start_block).max(0) as u32;
+                let fresh_tps =
+                    compute_effective_tps(&config.model, span_blocks, dl_bps, using_relay);
                 if (fresh_tps - server_info.throughput).abs() > 0.05 {
                     info!(
                         "Throughput updated: {:.1} → {:.1} tok/s",
@@ -1112,6 +1676,8 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                     );
                     server_info.throughput = fresh_tps;
                 }
+                server_info.set_measured_rates(&config.model, dl_bps);
+                server_info.next_pings = next_pings.snapshot();
 
                 // Re-check VPK health so that storage serve started after the
                 // daemon comes online without requiring a full daemon restart.
@@ -1146,7 +1712,10 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                 // new binary. The respawn itself is deferred to after this
                 // process's own cleanup (see bottom of run_node) rather than
                 // fired here — see maybe_auto_update()'s doc comment for why.
-                let auto_update = KwaaiNetConfig::load_or_create()
+                // Never in a container (no daemon files): the image is the
+                // unit of upgrade and a respawned daemon would escape it.
+                let auto_update = opts.daemon_files
+                    && KwaaiNetConfig::load_or_create()
                     .map(|c| c.contribute_policy(false).auto_update)
                     .unwrap_or(false);
                 if auto_update {
@@ -1156,24 +1725,62 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                     }
                 }
 
+                // Refresh the published bootstrap list, then re-rank every
+                // bootstrap peer by reachability and RTT.
+                if let Some(url) = &config.bootstrap_list_url {
+                    let due = bootstrap_list_fetched_at
+                        .is_none_or(|t| t.elapsed() >= crate::bootstrap::LIST_REFRESH);
+                    if due {
+                        match crate::bootstrap::fetch_list(&bootstrap_http, url).await {
+                            Ok(list) => {
+                                bootstrap_list_fetched_at = Some(Instant::now());
+                                let added = bootstrap.merge(&list);
+                                if added > 0 {
+                                    info!("Bootstrap list refreshed: {} new peer(s)", added);
+                                }
+                            }
+                            Err(e) => warn!("Bootstrap list refresh failed: {:#}", e),
+                        }
+                    }
+                }
+                bootstrap.probe_all(&client).await;
+                bootstrap_peers = bootstrap.ranked();
+                tracing::debug!("Bootstrap ranking: {}", bootstrap.summary());
+
+                if outside_schedule {
+                    next_announce
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + net_cfg.next_announce_delay());
+                    continue;
+                }
+
                 let sb = config.start_block as i32;
                 let eb = config.effective_end_block() as i32;
                 server_info.start_block = sb;
                 server_info.end_block = eb;
-                server_info.state = if ShardManager::shard_is_ready() { 2 } else { 0 };
+                server_info.refresh_shard_state();
                 info!("Re-announcing to DHT (shard_ready={})...", ShardManager::shard_is_ready());
-                if let Err(e) = announce(
+                match announce(
                     &mut client, peer_id, &storage, &bootstrap_peers,
                     &prefix, &repository, config.model_total_blocks(),
                     sb, eb, &server_info, Some(&mut rep_store),
                 ).await {
-                    warn!("Re-announce failed: {}", e);
+                    Ok(true) if announce_retry.is_pending() => {
+                        announce_retry.record(true, &daemon_mgr);
+                    }
+                    Ok(true) => {}
+                    Ok(false) => {
+                        // Start the next round from a different bootstrap peer.
+                        bootstrap.rotate();
+                        bootstrap_peers = bootstrap.ranked();
+                    }
+                    Err(e) => warn!("Re-announce failed: {}", e),
                 }
 
                 // Schedule the next tick with fresh jitter.
                 next_announce
                     .as_mut()
-                    .reset(tokio::time::Instant::now() + Duration::from_secs(jitter_secs(300, 30)));
+                    .reset(tokio::time::Instant::now() + net_cfg.next_announce_delay());
             }
 
             // Periodic IDENTIFY address check (every 5 minutes).
@@ -1181,6 +1788,7 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
             _ = identify_check.tick(), if !explicit_announce => {
                 info!("Checking announce addresses via IDENTIFY...");
                 let fresh = collect_observed_addresses(&mut client, config.identify_min_confirmations, Duration::from_secs(config.identify_timeout_secs), config.port).await;
+                let fresh = with_learned_public_addr(&mut client, config, fresh).await;
                 info!("  IDENTIFY result: {} addr(s) confirmed", fresh.len());
                 if !fresh.is_empty() {
                     save_relay_addr_cache(&fresh);
@@ -1203,39 +1811,206 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
                     // apply the restart once the node is idle.
                     pending_restart = Some(fresh);
                     info!("  p2pd restart deferred until node is idle");
+                    // Apply it at once if nothing is in flight; otherwise the
+                    // re-announce arm keeps retrying every tick.
+                    next_announce.as_mut().reset(tokio::time::Instant::now());
                 }
             }
 
+            // Public IP change check for nodes with `public_ip` configured.
+            _ = public_ip_check.tick(), if track_public_ip => {
+                let current = config
+                    .public_ip
+                    .as_deref()
+                    .and_then(|ip| ip.parse::<std::net::Ipv4Addr>().ok());
+                let detected = crate::public_ip::detect_public_ip(
+                    &mut client,
+                    &ip_http,
+                    Duration::from_secs(5),
+                )
+                .await;
+                if let Some(ip) = detected.filter(|ip| Some(*ip) != current) {
+                    let port = config.public_port.unwrap_or(config.port);
+                    let addr = format!("/ip4/{}/tcp/{}", ip, port);
+                    info!(
+                        "Public IP changed: {} → {} — re-announcing as {}",
+                        current.map_or_else(|| "unknown".to_string(), |c| c.to_string()),
+                        ip,
+                        addr
+                    );
+                    config.public_ip = Some(ip.to_string());
+                    match KwaaiNetConfig::load_or_create() {
+                        Ok(mut saved) => {
+                            saved.public_ip = Some(ip.to_string());
+                            if let Err(e) = saved.save() {
+                                warn!("Could not save new public_ip to config: {}", e);
+                            }
+                        }
+                        Err(e) => warn!("Could not save new public_ip to config: {}", e),
+                    }
+                    // Crash restarts of p2pd read `announce_addr`; the
+                    // deferred restart below applies the change now.
+                    announce_addr = Some(addr.clone());
+                    pending_restart = Some(vec![addr]);
+                    next_announce.as_mut().reset(tokio::time::Instant::now());
+                }
+            }
+
+            // Reachability self-check (every health.reachability_interval).
+            _ = reachability_check.tick(), if check_reachability && supervisor.is_running() => {
+                let mut addrs: Vec<String> = announce_addr.iter().cloned().collect();
+                addrs.extend(discovered_addrs.iter().cloned());
+                let state = crate::health::probe_reachability(
+                    &client, &bootstrap_peers, &addrs, &config.trusted_relays,
+                )
+                .await;
+                let previous = reachability.status().state;
+                let action = reachability.record(state);
+                if state != previous {
+                    info!("Reachability: {} → {}", previous.as_str(), state.as_str());
+                }
+                daemon_mgr.write_health_status(health_status(&reachability, &reconnection));
+                match action {
+                    RecoveryAction::None => {}
+                    RecoveryAction::Reconnect => {
+                        warn!(
+                            "Node unreachable for {} consecutive probes — re-dialling bootstrap peers",
+                            reachability.status().consecutive_failures
+                        );
+                        redial_bootstrap(&client, &bootstrap_peers).await;
+                    }
+                    RecoveryAction::RelayFallback => {
+                        warn!(
+                            "Node unreachable for {} consecutive probes — falling back to relay circuits",
+                            reachability.status().consecutive_failures
+                        );
+                        // DaemonEvent::Restarted schedules the re-announce.
+                        if let Err(e) = supervisor
+                            .restart_with(&mut client, |b| {
+                                b.without_announce_addrs().force_reachability_private(true)
+                            })
+                            .await
+                        {
+                            warn!("p2pd restart for relay fallback failed: {}", e);
+                        }
+                        // Our only address is now the relay circuit; the
+                        // IDENTIFY restart cycle would tear its reservation
+                        // down, so stop running it.
+                        announce_addr = None;
+                        discovered_addrs.clear();
+                        pending_restart = None;
+                        explicit_announce = true;
+                        server_info.using_relay = true;
+                    }
+                }
+            }
+
+            // Bootstrap link watch (every health.check_interval).
+            _ = link_check.tick(), if watch_link && supervisor.is_running() && !reconnection.is_reconnecting() => {
+                let reachable = bootstrap.probe_all(&client).await > 0;
+                if reconnection.observe(reachable, Instant::now()) == LinkEvent::Lost {
+                    let delay = reconnection.next_delay();
+                    warn!(
+                        "No bootstrap peer answers — announcing JOINING and re-dialling in {}s",
+                        delay.as_secs()
+                    );
+                    server_info.reconnecting = true;
+                    server_info.refresh_shard_state();
+                    reconnect_timer.as_mut().reset(tokio::time::Instant::now() + delay);
+                    daemon_mgr.write_health_status(health_status(&reachability, &reconnection));
+                }
+            }
+
+            // Re-dial the bootstrap peers on the reconnection backoff.
+            _ = &mut reconnect_timer, if reconnection.is_reconnecting() => {
+                let reachable = redial_bootstrap(&client, &bootstrap_peers).await > 0
+                    && bootstrap.probe_all(&client).await > 0;
+                match reconnection.observe(reachable, Instant::now()) {
+                    LinkEvent::Restored { attempts, down_secs } => {
+                        info!(
+                            "✅ Bootstrap peers reachable again after {}s ({} failed re-dial(s)) — announcing ONLINE",
+                            down_secs, attempts
+                        );
+                        server_info.reconnecting = false;
+                        next_announce.as_mut().reset(tokio::time::Instant::now());
+                    }
+                    _ => {
+                        if reconnection.attempt_failed() {
+                            warn!(
+                                "No bootstrap peer after {} re-dials — restarting p2pd",
+                                config.health_monitoring.reconnection.max_attempts
+                            );
+                            // DaemonEvent::Restarted schedules the re-announce.
+                            if let Err(e) = supervisor.restart(&mut client).await {
+                                warn!("p2pd restart for reconnection failed: {}", e);
+                            }
+                        }
+                        let delay = reconnection.next_delay();
+                        info!(
+                            "Bootstrap peers still unreachable — next re-dial in {}s (attempt {})",
+                            delay.as_secs(),
+                            reconnection.status().attempts + 1
+                        );
+                        reconnect_timer.as_mut().reset(tokio::time::Instant::now() + delay);
+                    }
+                }
+                daemon_mgr.write_health_status(health_status(&reachability, &reconnection));
+            }
+
             // Fast p2pd crash detection (every 10 s).
             // Catches crashes much sooner than the 300 s re-announce tick.
             // Skips the actual announce — that's still done at the 300 s tick.
             _ = p2pd_heartbeat.tick() => {
-                if !daemon.is_running() {
-                    let stderr = daemon.captured_stderr().await;
-                    if !stderr.is_empty() {
-                        warn!("p2pd crash output:\n{}", stderr.trim());
-                    }
-                    warn!("⚠️  p2pd heartbeat: process died — restarting immediately…");
-                    match restart_p2pd(
-                        &mut daemon, &mut client, &p2pd_path, &config,
-                        &bootstrap_peers, &announce_addr, handler_addr,
-                    ).await {
-                        Ok(()) => {
-                            info!("✅ p2pd restarted (heartbeat) — re-announce in 30 s");
-                            next_announce
-                                .as_mut()
-                                .reset(tokio::time::Instant::now() + Duration::from_secs(30));
+                supervisor.check(&mut client).await;
+            }
+
+            // p2pd lifecycle events from the supervisor.
+            Ok(event) = daemon_events.recv() => {
+                match event {
+                    // All are logged by the supervisor itself.
+                    DaemonEvent::Crashed { stderr } => {
+                        let detail = stderr.lines().last().unwrap_or("process exited").to_string();
+                        if let Some(alert) = alerts.crashed("p2pd", &detail, Instant::now()) {
+                            alerts.dispatch(&alert_http, alert);
                         }
-                        Err(e) => {
-                            warn!("p2pd restart failed: {} — will retry in 120 s", e);
-                            next_announce
-                                .as_mut()
-                                .reset(tokio::time::Instant::now() + Duration::from_secs(120));
+                    }
+                    DaemonEvent::RestartFailed { .. } | DaemonEvent::GaveUp { .. } => {}
+                    DaemonEvent::Restarted { restarts } => {
+                        info!("p2pd restart #{} — re-announce in 30 s", restarts);
+                        after_p2pd_restart(&mut client, &config, &bootstrap_peers).await;
+                        // Routing table is empty right after restart; announcing now
+                        // fails with "no peer in table". Delay 30 s so bootstrap
+                        // peers can populate the routing table first.
+                        next_announce
+                            .as_mut()
+                            .reset(tokio::time::Instant::now() + Duration::from_secs(30));
+                    }
+                }
+            }
+
+            // Connection sample for disconnect/reconnect alerts (every 60 s).
+            _ = alert_check.tick(), if alerts.is_active() && supervisor.is_running() => {
+                match client.list_peers().await {
+                    Ok(peers) => {
+                        if let Some(alert) = alerts.observe_connections(peers.len() as u32, Instant::now()) {
+                            alerts.dispatch(&alert_http, alert);
                         }
                     }
+                    Err(e) => tracing::debug!("Alert check: could not list peers: {}", e),
                 }
             }
 
+            // Peer filter sweep (every 15 s).
+            _ = peer_sweep.tick(), if supervisor.is_running() => {
+                crate::peer_filter::sweep(&client, true).await;
+            }
+
+            // Run history sample (every 5 min).
+            _ = history_sample.tick() => {
+                let connections = client.list_peers().await.map(|p| p.len()).ok();
+                history.sample(connections);
+            }
+
             // Relay circuit keepalive (every 60 s).
             // Sends a trivial identify RPC to p2pd to keep the unix socket and
             // the p2pd ↔ relay TCP connection alive, preventing idle NAT
@@ -1251,30 +2026,199 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
             // transition so clients learn the host is usable again without
             // waiting for the next 300 s tick.
             Some(()) = ollama_recovery_rx.recv() => {
+                if outside_schedule {
+                    info!("Ollama recovered — outside contribution hours, not re-announcing");
+                    continue;
+                }
                 info!("Ollama recovered — triggering immediate re-announce");
                 let sb = config.start_block as i32;
                 let eb = config.effective_end_block() as i32;
                 server_info.start_block = sb;
                 server_info.end_block = eb;
-                if let Err(e) = announce(
+                match announce(
                     &mut client, peer_id, &storage, &bootstrap_peers,
                     &prefix, &repository, config.model_total_blocks(),
                     sb, eb, &server_info, None,
                 ).await {
-                    warn!("Re-announce after Ollama recovery failed: {}", e);
+                    Ok(true) if announce_retry.is_pending() => {
+                        announce_retry.record(true, &daemon_mgr);
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Re-announce after Ollama recovery failed: {}", e),
                 }
             }
 
+            // Contribution hours: withdraw when the window closes, re-announce
+            // on the next tick once it opens again.
+            _ = schedule_check.tick(), if schedule.is_some() => {
+                let open = schedule.is_some_and(|s| s.is_open_now());
+                if !open && !outside_schedule {
+                    info!("Outside contribution hours — withdrawing DHT announcement");
+                    unannounce(
+                        &mut client,
+                        peer_id,
+                        &storage,
+                        &bootstrap_peers,
+                        &prefix,
+                        &server_info,
+                    )
+                    .await;
+                    outside_schedule = true;
+                } else if open && outside_schedule {
+                    info!("Contribution hours started — re-announcing");
+                    outside_schedule = false;
+                    next_announce.as_mut().reset(tokio::time::Instant::now());
+                    if announce_retry.is_pending() {
+                        retry_announce.as_mut().reset(tokio::time::Instant::now());
+                    }
+                }
+            }
+
+            // Request from the CLI over the control socket (see crate::control).
+            Some(req) = control_rx.recv() => {
+                let result = match req.method {
+                    ControlMethod::Status => {
+                        let connections = client.list_peers().await.map(|p| p.len()).ok();
+                        let mut addrs: Vec<String> = announce_addr.iter().cloned().collect();
+                        addrs.extend(discovered_addrs.iter().cloned());
+                        Ok(serde_json::json!({
+                            "pid": std::process::id(),
+                            "version": env!("CARGO_PKG_VERSION"),
+                            "uptime_secs": started_at.elapsed().as_secs(),
+                            "peer_id": peer_id.to_base58(),
+                            "public_name": public_name,
+                            "model": config.model,
+                            "dht_prefix": prefix,
+                            "start_block": config.start_block,
+                            "end_block": config.effective_end_block(),
+                            "announce_addrs": addrs,
+                            "using_relay": server_info.using_relay,
+                            "throughput": server_info.throughput,
+                            "announce": {
+                                "state": if announce_retry.is_pending() { "announcing" } else { "announced" },
+                                "attempts": announce_retry.attempts(),
+                                "announced_at": announce_retry.announced_at,
+                            },
+                            "reachability": reachability.status(),
+                            "reconnection": reconnection.status(),
+                            "p2pd_running": supervisor.is_running(),
+                            "p2pd_restarts": supervisor.restarts(),
+                            "connections": connections,
+                            "active_rpc_streams": active_rpc_streams.load(Ordering::Relaxed),
+                            "rpc_limits": rpc_limiter.stats(),
+                            "dht_storage": storage.read().await.metrics(),
+                            "dht_health": match &dht_backend {
+                                Some(backend) => backend.health_snapshot().await,
+                                None => None,
+                            },
+                            "pending_restart": pending_restart.is_some(),
+                            "contributing": !outside_schedule,
+                            "experiments": crate::experiments::active().enabled_keys(),
+                        }))
+                    }
+                    ControlMethod::Peers => client
+                        .list_peers()
+                        .await
+                        .map(|peers| {
+                            let rows: Vec<serde_json::Value> = peers
+                                .iter()
+                                .map(|p| {
+                                    let id = PeerId::from_bytes(&p.id)
+                                        .map(|id| id.to_base58())
+                                        .unwrap_or_else(|_| format!("0x{}", hex::encode(&p.id)));
+                                    let addrs: Vec<String> = p
+                                        .addrs
+                                        .iter()
+                                        .filter_map(|a| libp2p::Multiaddr::try_from(a.clone()).ok())
+                                        .map(|m| m.to_string())
+                                        .collect();
+                                    serde_json::json!({ "peer_id": id, "addrs": addrs })
+                                })
+                                .collect();
+                            serde_json::Value::Array(rows)
+                        })
+                        .map_err(|e| format!("LIST_PEERS failed: {}", e)),
+                    ControlMethod::Announce if outside_schedule => Err(
+                        "outside contribution hours (bandwidth.schedule) — not announcing".to_string(),
+                    ),
+                    ControlMethod::Announce => {
+                        info!("Announce requested over control socket");
+                        reload_block_range(&mut config);
+                        let sb = config.start_block as i32;
+                        let eb = config.effective_end_block() as i32;
+                        server_info.start_block = sb;
+                        server_info.end_block = eb;
+                        server_info.refresh_shard_state();
+                        match announce(
+                            &mut client, peer_id, &storage, &bootstrap_peers,
+                            &prefix, &repository, config.model_total_blocks(),
+                            sb, eb, &server_info, None,
+                        ).await {
+                            Ok(ok) => {
+                                if ok && announce_retry.is_pending() {
+                                    announce_retry.record(true, &daemon_mgr);
+                                }
+                                Ok(serde_json::json!({ "announced": ok }))
+                            }
+                            Err(e) => Err(format!("announce failed: {:#}", e)),
+                        }
+                    }
+                    ControlMethod::Reload => {
+                        info!("Config reload requested over control socket");
+                        let report = reload_config(
+                            &mut config, &mut net_cfg, &rpc_limiter, &mut alerts, &mut public_name,
+                        );
+                        server_info.public_name = public_name.clone();
+                        let reannounce = ["public_name", "start_block", "blocks"]
+                            .iter()
+                            .any(|s| report.touches(s));
+                        if reannounce && !outside_schedule {
+                            let sb = config.start_block as i32;
+                            let eb = config.effective_end_block() as i32;
+                            server_info.start_block = sb;
+                            server_info.end_block = eb;
+                            server_info.refresh_shard_state();
+                            if let Err(e) = announce(
+                                &mut client, peer_id, &storage, &bootstrap_peers,
+                                &prefix, &repository, config.model_total_blocks(),
+                                sb, eb, &server_info, None,
+                            ).await {
+                                warn!("Re-announce after config reload failed: {}", e);
+                            }
+                        }
+                        serde_json::to_value(&report).map_err(|e| e.to_string())
+                    }
+                    ControlMethod::Reconnect => {
+                        info!("Reconnect requested over control socket — re-dialling bootstrap peers");
+                        let connected = redial_bootstrap(&client, &bootstrap_peers).await;
+                        Ok(serde_json::json!({
+                            "dialled": bootstrap_peers.len(),
+                            "connected": connected,
+                        }))
+                    }
+                    ControlMethod::Filter => {
+                        crate::peer_filter::invalidate();
+                        let disconnected = crate::peer_filter::sweep(&client, true).await;
+                        Ok(serde_json::json!({ "disconnected": disconnected }))
+                    }
+                };
+                let _ = req.reply.send(result);
+            }
+
             // Shutdown signal
             _ = shutdown_signal() => {
                 info!("Shutdown signal received");
+                probes.set_not_ready("shutting down");
                 break;
             }
         }
     }
 
+    // Stop renewing our records before telling the DHT we are offline.
+    maintenance.abort();
+
     // Unannounce before shutting down p2pd so the map reflects the node as
-    // offline immediately rather than waiting up to 360 s for TTL expiry.
+    // offline immediately rather than waiting for the record TTL to expire.
     info!("Unannouncing from DHT...");
     unannounce(
         &mut client,
@@ -1286,8 +2230,32 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     )
     .await;
 
-    let _ = daemon.shutdown().await;
-    daemon_mgr.remove_pid();
+    // Tell connected peers directly; the OFFLINE record takes a while to
+    // reach every DHT node they might query.
+    let goodbye = crate::handoff::Goodbye {
+        peer_id: peer_id.to_base58(),
+        start_block: server_info.start_block.max(0) as u32,
+        end_block: server_info.end_block.max(0) as u32,
+        grace_secs: config.shutdown_grace_secs,
+    };
+    let acked = crate::handoff::notify_peers(&mut client, &goodbye, Duration::from_secs(3)).await;
+    info!("Sent goodbye to connected peers ({acked} acknowledged)");
+
+    // Let the shard server drain while p2pd is still up to carry its
+    // in-flight sessions. `kwaainet stop` normally stops it first; this
+    // covers a SIGTERM or Ctrl-C delivered to the node directly.
+    let shard_mgr = ShardManager::new();
+    if shard_mgr.is_running() {
+        info!("Draining shard server...");
+        let _ = tokio::task::spawn_blocking(move || shard_mgr.stop_process()).await;
+    }
+
+    let _ = supervisor.shutdown().await;
+    history.end();
+    daemon_mgr.clear_announce_status();
+    if opts.daemon_files {
+        daemon_mgr.remove_pid();
+    }
 
     // Respawn AFTER this process's own cleanup has fully completed — the PID
     // file is gone and p2pd is down. Previously the new `start --daemon`
@@ -1303,10 +2271,10 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
     // deleted the file, closes that window entirely.
     if let Some(version) = pending_update_version {
         // Resolve via PATH, not current_exe(): install_update() replaces the
-        // binary by unlinking the old file and renaming the new one into its
+        // binary by renaming the old file aside and the new one into its
         // place (ETXTBSY-safe while this process still has it open). On
         // Linux, current_exe() resolves through /proc/self/exe, which after
-        // an unlink+rename keeps pointing at the old (deleted) inode — so
+        // the swap keeps pointing at the old inode — so
         // spawning via current_exe() here would silently relaunch the old
         // binary, making the "respawned with new binary" log line a lie.
         // PATH lookup re-resolves the path fresh, picking up the new file.
@@ -1332,19 +2300,228 @@ pub async fn run_node(config: &KwaaiNetConfig) -> Result<()> {
         }
     }
 
-    info!("KwaaiNet node stopped");
-    Ok(())
+    info!("KwaaiNet node stopped");
+    Ok(())
+}
+
+/// Pick up `start_block` / `blocks` changes written by `shard serve` (via
+/// `signal_reannounce`) or `kwaainet config set` before re-announcing.
+fn reload_block_range(config: &mut KwaaiNetConfig) {
+    if let Ok(fresh) = KwaaiNetConfig::load_or_create() {
+        if fresh.start_block != config.start_block || fresh.blocks != config.blocks {
+            info!(
+                "Block range updated: [{}–{}) → [{}–{})",
+                config.start_block,
+                config.effective_end_block(),
+                fresh.start_block,
+                fresh.start_block + fresh.blocks,
+            );
+            config.start_block = fresh.start_block;
+            config.blocks = fresh.blocks;
+        }
+    }
+}
+
+/// Name announced in the DHT and shown on the map: `public_name` (or
+/// `kwaainet-node`) with the version appended.
+fn display_name(config: &KwaaiNetConfig) -> String {
+    format!(
+        "{}/v{}",
+        config.public_name.as_deref().unwrap_or("kwaainet-node"),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Re-read config.yaml and apply the settings a running node can change in
+/// place (see `crate::config_reload`). New `start_block` / `blocks` and
+/// `public_name` only land in `config`; re-announcing them is up to the
+/// caller.
+fn reload_config(
+    config: &mut KwaaiNetConfig,
+    net_cfg: &mut kwaai_p2p::NetworkConfig,
+    rpc_limiter: &crate::rpc_limits::RpcLimiter,
+    alerts: &mut crate::monitor::AlertEngine,
+    public_name: &mut String,
+) -> crate::config_reload::ReloadReport {
+    let fresh = match KwaaiNetConfig::load_or_create() {
+        Ok(fresh) => fresh,
+        Err(e) => {
+            warn!("Config reload failed: {:#}", e);
+            return crate::config_reload::ReloadReport {
+                errors: vec![format!("cannot read config: {e:#}")],
+                ..Default::default()
+            };
+        }
+    };
+    let previous = config.clone();
+    let mut report = crate::config_reload::apply(config, &fresh);
+
+    let reject = |report: &mut crate::config_reload::ReloadReport, setting: &str, e: String| {
+        report.applied.retain(|s| s != setting);
+        report.errors.push(format!("{setting}: {e}"));
+    };
+    let candidate = config.network_config();
+    match candidate.validate_announce_timing() {
+        Ok(()) => *net_cfg = candidate,
+        Err(e) => {
+            config.announce_interval_secs = previous.announce_interval_secs;
+            reject(&mut report, "announce_interval_secs", e.to_string());
+        }
+    }
+    if let Err(e) = crate::logs::set_log_level(&config.log_level) {
+        config.log_level = previous.log_level.clone();
+        reject(&mut report, "log_level", format!("{e:#}"));
+    }
+    if report.touches("start_block") || report.touches("blocks") {
+        info!(
+            "Block range updated: [{}–{}) → [{}–{})",
+            previous.start_block,
+            previous.effective_end_block(),
+            config.start_block,
+            config.effective_end_block(),
+        );
+    }
+    if report.touches("rpc_limits") {
+        rpc_limiter.reconfigure(&config.rpc_limits);
+    }
+    *public_name = display_name(config);
+    alerts.reconfigure(
+        &crate::monitor::load_alert_config(),
+        &config.health_monitoring.alerting,
+        public_name,
+    );
+    crate::peer_filter::invalidate();
+
+    if report.applied.is_empty() && report.restart_required.is_empty() {
+        info!("Config reloaded — no changes");
+    }
+    if !report.applied.is_empty() {
+        info!("Config reloaded — applied: {}", report.applied.join(", "));
+    }
+    if !report.restart_required.is_empty() {
+        warn!(
+            "Changed settings need a restart to take effect: {}",
+            report.restart_required.join(", ")
+        );
+    }
+    for e in &report.errors {
+        warn!("Config reload rejected {}", e);
+    }
+    report
 }
 
 // ---------------------------------------------------------------------------
 // DHT announcement / unannouncement
 // ---------------------------------------------------------------------------
 
+/// Tracks the initial DHT announcement until it first succeeds.
+///
+/// Retries start fast (5 s, doubling for the first few attempts) so a node
+/// whose bootstrap peers blip at startup shows up on the map within seconds
+/// of them coming back, then settle to a slow ~60 s cadence so a prolonged
+/// outage doesn't hammer the bootstraps. Progress is mirrored into the status
+/// file so `kwaainet status` can report "announcing" vs "announced".
+struct AnnounceRetry {
+    attempts: u32,
+    announced_at: Option<u64>,
+    /// Unix time of the latest successful announcement, first or repeat
+    last_ok: Option<u64>,
+}
+
+impl AnnounceRetry {
+    /// Attempts that use the doubling fast schedule before switching to slow.
+    const FAST_ATTEMPTS: u32 = 4;
+    const FAST_BASE_SECS: u64 = 5;
+    const SLOW_SECS: u64 = 60;
+
+    fn new() -> Self {
+        Self {
+            attempts: 0,
+            announced_at: None,
+            last_ok: None,
+        }
+    }
+
+    fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    fn last_ok(&self) -> Option<u64> {
+        self.last_ok
+    }
+
+    fn is_pending(&self) -> bool {
+        self.announced_at.is_none()
+    }
+
+    /// Delay before the next retry, based on how many attempts failed so far.
+    fn next_delay(&self) -> Duration {
+        if self.attempts <= Self::FAST_ATTEMPTS {
+            Duration::from_secs(Self::FAST_BASE_SECS << self.attempts.saturating_sub(1))
+        } else {
+            Duration::from_secs(jitter_secs(Self::SLOW_SECS, 10))
+        }
+    }
+
+    /// Record the outcome of one announcement attempt and publish the
+    /// resulting state to the status file.
+    fn record(&mut self, ok: bool, daemon_mgr: &DaemonManager) {
+        use crate::daemon::{AnnounceState, AnnounceStatus};
+        use crate::history::Metric;
+
+        crate::history::record(
+            if ok {
+                Metric::AnnounceOk
+            } else {
+                Metric::AnnounceFailed
+            },
+            1,
+        );
+        let now = crate::liveness::unix_now();
+        if ok {
+            self.last_ok = Some(now);
+        }
+        if !self.is_pending() {
+            return;
+        }
+        self.attempts += 1;
+        let state = if ok {
+            self.announced_at = Some(now);
+            if self.attempts > 1 {
+                info!(
+                    "✅ Deferred announcement succeeded after {} attempts — node is now visible on the DHT",
+                    self.attempts
+                );
+            }
+            AnnounceState::Announced
+        } else {
+            AnnounceState::Announcing
+        };
+        daemon_mgr.write_announce_status(AnnounceStatus {
+            state,
+            attempts: self.attempts,
+            announced_at: self.announced_at,
+        });
+    }
+}
+
+/// Lifetime of this node's DHT records (`record_ttl_secs`), fixed at startup.
+static RECORD_TTL: OnceLock<Duration> = OnceLock::new();
+
+/// Expiration time for a record stored now.
+fn record_expiration() -> f64 {
+    let ttl = RECORD_TTL
+        .get()
+        .copied()
+        .unwrap_or(kwaai_p2p::config::DEFAULT_RECORD_TTL);
+    get_dht_time() + ttl.as_secs_f64()
+}
+
 /// Remove this node's DHT records immediately on clean shutdown.
 ///
 /// Sends STORE requests with already-expired timestamps and state=-1 (offline)
 /// to all bootstrap peers. Bootstrap peers drop expired records immediately
-/// instead of waiting for the 360 s TTL to elapse naturally.
+/// instead of waiting for the record TTL to elapse naturally.
 async fn unannounce(
     client: &mut kwaai_p2p_daemon::P2PClient,
     peer_id: PeerId,
@@ -1363,17 +2540,25 @@ async fn unannounce(
         torch_dtype: server_info.torch_dtype.clone(),
         using_relay: server_info.using_relay,
         cache_tokens_left: 0,
+        inference_rps: 0.0,
+        forward_rps: 0.0,
+        network_rps: 0.0,
         next_pings: HashMap::new(),
         adapters: vec![],
         trust_attestations: vec![],
         vpk_info: None,
         peer_id_b58: server_info.peer_id_b58.clone(),
+        experiments: vec![],
+        reconnecting: false,
+        signer: server_info.signer.clone(),
+        accelerator: None,
+        attestation: None,
     };
-    // Use the same 360 s TTL as a regular announcement — Hivemind bootstrap
-    // peers reject updates with a shorter TTL than the existing record.
+    // Use the same TTL as a regular announcement — Hivemind bootstrap peers
+    // reject updates with a shorter TTL than the existing record.
     // State=-1 tells map.kwaai.ai the node is offline immediately; the record
-    // then expires naturally after 360 s (same as a missed re-announcement).
-    let expired = get_dht_time() + 360.0;
+    // then expires naturally (same as a missed re-announcement).
+    let expired = record_expiration();
 
     let info_bytes = match offline_info.to_msgpack() {
         Ok(b) => b,
@@ -1417,7 +2602,7 @@ async fn unannounce(
         let g = storage.read().await;
         let _ = g.handle_store(block_req.clone());
     }
-    send_to_bootstrap(client, bootstrap_peers, block_req).await;
+    store_on_closest(client, peer_id, bootstrap_peers, block_req).await;
 
     // VPK record — only if this node had VPK enabled
     if let Some(ref vpk) = server_info.vpk_info {
@@ -1435,13 +2620,59 @@ async fn unannounce(
                 let g = storage.read().await;
                 let _ = g.handle_store(vpk_req.clone());
             }
-            send_to_bootstrap(client, bootstrap_peers, vpk_req).await;
+            store_on_closest(client, peer_id, bootstrap_peers, vpk_req).await;
         }
     }
 
     info!("Unannounced from DHT — node removed from map");
 }
 
+/// Back `storage` with the on-disk record store in the data directory.
+/// Failing to open it is not fatal: the node runs with in-memory storage.
+#[cfg(feature = "dht-persistence")]
+fn persist_dht_storage(storage: DHTStorage) -> DHTStorage {
+    let path = crate::config::kwaainet_dir().join("dht");
+    let fallback = storage.clone();
+    match storage.with_persistence(&path) {
+        Ok(storage) => storage,
+        Err(e) => {
+            warn!("DHT persistence unavailable ({}): {}", path.display(), e);
+            fallback
+        }
+    }
+}
+
+/// Validate incoming records: sane key sizes and expirations, and Petals
+/// `ServerInfo` values under this model's block keys and the inference
+/// nodes registry.
+fn with_record_validators(storage: DHTStorage, config: &KwaaiNetConfig) -> DHTStorage {
+    let prefix = config.effective_dht_prefix();
+    let block_keys = (0..config.model_total_blocks())
+        .map(|block| dht_id(&format!("{}.{}", prefix, block)))
+        .chain([dht_id(crate::shard_cmd::INFERENCE_NODES_DHT_KEY)]);
+    let max_ttl = config
+        .rpc_limits
+        .max_record_ttl_secs
+        .max(config.record_ttl_secs);
+    storage
+        .with_validator(validate::SizeLimit::default())
+        .with_validator(validate::ExpirationLimit {
+            max_ttl: max_ttl as f64,
+        })
+        .with_validator(validate::ServerInfoSchema::new(block_keys))
+}
+
+#[cfg(not(feature = "dht-persistence"))]
+fn persist_dht_storage(storage: DHTStorage) -> DHTStorage {
+    warn!("dht_persist is set but this build lacks the dht-persistence feature");
+    storage
+}
+
+/// Publish this node's block, model-registry, inference and VPK records.
+///
+/// Returns `Ok(true)` when the block records reached at least one DHT peer —
+/// the condition for the node to show up on the map. `Err` is reserved for
+/// local serialisation failures.
 #[allow(clippy::too_many_arguments)]
 async fn announce(
     client: &mut kwaai_p2p_daemon::P2PClient,
@@ -1455,7 +2686,7 @@ async fn announce(
     end_block: i32,
     server_info: &DHTServerInfo,
     rep: Option<&mut crate::reputation::ReputationStore>,
-) -> Result<()> {
+) -> Result<bool> {
     info!(
         "DHT prefix: {} (blocks .{} – .{})",
         prefix,
@@ -1469,6 +2700,7 @@ async fn announce(
 
     // Build block STORE request — always announce configured blocks so the node
     // appears on the map. State=0 (joining) when shard is not yet loaded.
+    let blocks_ok;
     {
         let mut keys = Vec::new();
         let mut subkeys = Vec::new();
@@ -1480,7 +2712,7 @@ async fn announce(
             keys.push(dht_id(&format!("{}.{}", prefix, block)));
             subkeys.push(subkey.clone());
             values.push(info_bytes.clone());
-            expirations.push(get_dht_time() + 360.0);
+            expirations.push(record_expiration());
             in_cache.push(false);
         }
 
@@ -1497,12 +2729,13 @@ async fn announce(
         // Store locally
         {
             let g = storage.read().await;
-            let _ = g.handle_store(block_req.clone());
+            let _ = g.handle_store_own(block_req.clone());
         }
 
-        // Push to bootstrap peers; piggyback reputation observations on the
-        // STORE latency — no extra RPCs needed.
-        let (ok, timings) = send_to_bootstrap(client, bootstrap_peers, block_req).await;
+        // Push to the peers closest to each block key; piggyback reputation
+        // observations on the STORE latency — no extra RPCs needed.
+        let (ok, timings) = store_on_closest(client, peer_id, bootstrap_peers, block_req).await;
+        blocks_ok = ok;
         if ok {
             info!("✅ Announced {} blocks", end_block - start_block);
         } else {
@@ -1546,16 +2779,16 @@ async fn announce(
         keys: vec![dht_id("_petals.models")],
         subkeys: vec![rmp_serde::to_vec(&prefix)?],
         values: vec![model_info.to_msgpack()?],
-        expiration_time: vec![get_dht_time() + 360.0],
+        expiration_time: vec![record_expiration()],
         in_cache: vec![false],
         peer: Some(node_info.clone()),
     };
 
     {
         let g = storage.read().await;
-        let _ = g.handle_store(registry_req.clone());
+        let _ = g.handle_store_own(registry_req.clone());
     }
-    if send_to_bootstrap(client, bootstrap_peers, registry_req)
+    if store_on_closest(client, peer_id, bootstrap_peers, registry_req)
         .await
         .0
     {
@@ -1568,22 +2801,25 @@ async fn announce(
     // peer so `p2p://auto` in rag chat/query can discover it without knowing
     // block coverage.  Uses the same DHTServerInfo msgpack encoding as block
     // records so `discover_inference_peer()` can reuse `decode_server_info_ext`.
-    // Key: _kwaai.inference.nodes  subkey: msgpack(peer_id_base58)  TTL: 360 s
+    // Key: _kwaai.inference.nodes  subkey: msgpack(peer_id_base58)  TTL: record_ttl_secs
     {
         let inf_req = StoreRequest {
             auth: Some(RequestAuthInfo::new()),
             keys: vec![dht_id(crate::shard_cmd::INFERENCE_NODES_DHT_KEY)],
             subkeys: vec![subkey.clone()],
             values: vec![info_bytes.clone()],
-            expiration_time: vec![get_dht_time() + 360.0],
+            expiration_time: vec![record_expiration()],
             in_cache: vec![false],
             peer: Some(node_info.clone()),
         };
         {
             let g = storage.read().await;
-            let _ = g.handle_store(inf_req.clone());
+            let _ = g.handle_store_own(inf_req.clone());
         }
-        if send_to_bootstrap(client, bootstrap_peers, inf_req).await.0 {
+        if store_on_closest(client, peer_id, bootstrap_peers, inf_req)
+            .await
+            .0
+        {
             info!("✅ Announced to _kwaai.inference.nodes");
         } else {
             warn!("❌ Inference nodes announcement failed");
@@ -1593,218 +2829,215 @@ async fn announce(
     // VPK nodes registry — advertise this node's VPK capability when enabled.
     // Key: _kwaai.vpk.nodes  subkey: msgpack(peer_id_base58)
     // Value: msgpack({ mode, endpoint, capacity_gb, tenant_count, vpk_version })
-    // TTL: 360 s (refreshed every 120 s together with block records)
+    // TTL: record_ttl_secs (refreshed together with block records)
     if let Some(ref vpk) = server_info.vpk_info {
         let vpk_req = StoreRequest {
             auth: Some(RequestAuthInfo::new()),
             keys: vec![dht_id("_kwaai.vpk.nodes")],
             subkeys: vec![subkey.clone()],
             values: vec![vpk.to_msgpack_bytes()?],
-            expiration_time: vec![get_dht_time() + 360.0],
+            expiration_time: vec![record_expiration()],
             in_cache: vec![false],
             peer: Some(node_info),
         };
 
         {
             let g = storage.read().await;
-            let _ = g.handle_store(vpk_req.clone());
+            let _ = g.handle_store_own(vpk_req.clone());
         }
-        if send_to_bootstrap(client, bootstrap_peers, vpk_req).await.0 {
+        if store_on_closest(client, peer_id, bootstrap_peers, vpk_req)
+            .await
+            .0
+        {
             info!("✅ Announced VPK capability to _kwaai.vpk.nodes");
         } else {
             warn!("❌ VPK nodes announcement failed");
         }
     }
 
-    Ok(())
+    Ok(blocks_ok)
 }
 
-/// Send a STORE request to each bootstrap peer.
+/// Store `req` on the DHT peers closest to each of its keys.
+///
+/// Walks the Hivemind DHT with iterative FIND lookups and sends STORE to the
+/// closest responsive peers per key (see
+/// [`kwaai_hivemind_dht::client::store_on_closest`]). The walk is seeded with
+/// the bootstrap peers plus every peer p2pd is currently connected to, so an
+/// announcement still reaches the network while the bootstrap peers are down.
 ///
 /// Returns `(any_success, per_peer_timings)` where timings are
-/// `(peer_id_str, addr, latency_ms, success)` — one entry per bootstrap peer.
+/// `(peer_id_str, addr, latency_ms, success)` — one entry per STORE RPC;
+/// `addr` is the bootstrap multiaddr for bootstrap peers and empty otherwise.
 /// Callers that record reputation use the timings; others discard them.
 ///
 /// Does NOT call connect_peer() — p2pd already has bootstrap addresses from
 /// its own DHT bootstrap (via -b flag) and dials internally when needed.
 /// Calling connect_peer() to Hivemind bootstrap servers crashes p2pd's
 /// background goroutines via the gRPC control path.
-async fn send_to_bootstrap(
+async fn store_on_closest(
     client: &mut kwaai_p2p_daemon::P2PClient,
+    local_peer_id: PeerId,
     bootstrap_peers: &[String],
     req: StoreRequest,
 ) -> (bool, Vec<(String, String, f64, bool)>) {
-    if bootstrap_peers.is_empty() {
-        return (false, vec![]);
-    }
-
-    use prost::Message;
-    let mut bytes = Vec::new();
-    if let Err(e) = req.encode(&mut bytes) {
-        warn!("Encode STORE request failed: {}", e);
-        return (false, vec![]);
-    }
-
-    let mut succeeded = 0usize;
-    let mut timings: Vec<(String, String, f64, bool)> = Vec::with_capacity(bootstrap_peers.len());
-
+    let mut bootstrap: HashMap<PeerId, &String> = HashMap::new();
+    let mut seeds: Vec<PeerId> = Vec::new();
     for addr in bootstrap_peers {
         let Some(peer_id_str) = addr.split("/p2p/").nth(1) else {
             warn!("Bootstrap peer has no /p2p/ component: {}", addr);
             continue;
         };
-        let bp = match peer_id_str.parse::<PeerId>() {
-            Ok(p) => p,
-            Err(e) => {
-                warn!("Invalid peer ID in {}: {}", addr, e);
-                continue;
-            }
-        };
-
-        let t0 = std::time::Instant::now();
-        let result = tokio::time::timeout(
-            Duration::from_secs(30),
-            client.call_unary_handler(&bp.to_bytes(), "DHTProtocol.rpc_store", &bytes),
-        )
-        .await;
-        let latency_ms = t0.elapsed().as_secs_f64() * 1000.0;
-
-        let rpc_ok = matches!(&result, Ok(Ok(_)));
-        timings.push((peer_id_str.to_string(), addr.clone(), latency_ms, rpc_ok));
-
-        match result {
-            Ok(Ok(resp_bytes)) => {
-                use kwaai_hivemind_dht::protocol::StoreResponse;
-                if let Ok(resp) = StoreResponse::decode(&resp_bytes[..]) {
-                    let ok = resp.store_ok.iter().filter(|&&s| s).count();
-                    info!(
-                        "STORE response from {}: {}/{} stored",
-                        peer_id_str,
-                        ok,
-                        resp.store_ok.len()
-                    );
-                    if ok > 0 {
-                        succeeded += 1;
-                    }
-                }
+        match peer_id_str.parse::<PeerId>() {
+            Ok(p) => {
+                bootstrap.insert(p, addr);
+                seeds.push(p);
             }
-            Ok(Err(e)) => warn!("STORE RPC failed ({}): {}", addr, e),
-            Err(_) => {
-                warn!("STORE RPC timeout ({}): exceeded 30s", addr);
+            Err(e) => warn!("Invalid peer ID in {}: {}", addr, e),
+        }
+    }
+    // Connected peers come after the bootstrap peers so they are only tried
+    // once the bootstrap peers are exhausted or unreachable.
+    if let Ok(peers) = client.list_peers().await {
+        for p in peers
+            .iter()
+            .filter_map(|info| PeerId::from_bytes(&info.id).ok())
+        {
+            if !seeds.contains(&p) {
+                seeds.push(p);
             }
         }
     }
+    if seeds.is_empty() {
+        return (false, vec![]);
+    }
+
+    let n_keys = req.keys.len();
+    let routing = RoutingConfig::default();
+    let outcome = match crate::dht_auth::authorizer() {
+        Some(authorizer) => {
+            let mut rpc = AuthorizedRpc::new(P2pdDhtRpc::new(client.clone()), authorizer);
+            kwaai_hivemind_dht::client::store_on_closest(
+                &mut rpc,
+                local_peer_id,
+                req,
+                &seeds,
+                &routing,
+            )
+            .await
+        }
+        None => {
+            kwaai_hivemind_dht::client::store_on_closest(
+                &mut P2pdDhtRpc::new(client.clone()),
+                local_peer_id,
+                req,
+                &seeds,
+                &routing,
+            )
+            .await
+        }
+    };
+
+    let timings = outcome
+        .attempts
+        .iter()
+        .map(|a| {
+            let addr = bootstrap.get(&a.peer).map(|s| s.to_string());
+            (
+                a.peer.to_base58(),
+                addr.unwrap_or_default(),
+                a.latency.as_secs_f64() * 1000.0,
+                a.ok,
+            )
+        })
+        .collect();
 
-    if succeeded > 0 {
+    let stored_keys = outcome.stored_on.iter().filter(|p| !p.is_empty()).count();
+    if outcome.any_stored() {
         info!(
-            "✅ Announced to {} of {} bootstrap peers",
-            succeeded,
-            bootstrap_peers.len()
+            "✅ Stored {}/{} keys on {} closest peers",
+            stored_keys,
+            n_keys,
+            outcome.peers_reached()
         );
     } else {
         warn!(
-            "❌ Announcement failed on all {} bootstrap peers — see warnings above",
-            bootstrap_peers.len()
+            "❌ STORE reached no peer ({} seeds, {} attempts) — see warnings above",
+            seeds.len(),
+            outcome.attempts.len()
         );
     }
-    (succeeded > 0, timings)
+    (outcome.any_stored(), timings)
 }
 
-/// Unregister DHT stream handlers, shut down p2pd, rebuild and spawn it with
-/// the supplied set of announce addresses, reconnect the client, and re-register
-/// handlers. Used by the deferred-restart path (reannounce tick), where new
-/// addresses learned via IDENTIFY need to be promoted to the announce set.
-///
-/// Distinct from the `restart_p2pd` function above (which restarts after a p2pd
-/// crash with the original announce_addr); this one takes an explicit
-/// `announce_addrs` slice. Both share the same daemon-spawning shape.
-///
-/// `remove_stream_handler` failures are non-fatal (daemon may already be
-/// unresponsive). All other failures are returned as `Err` for the caller to
-/// handle at the appropriate severity.
-#[allow(clippy::too_many_arguments)]
-async fn restart_p2pd_with_addrs(
-    daemon: &mut kwaai_p2p_daemon::P2PDaemon,
-    client: &mut kwaai_p2p_daemon::P2PClient,
-    announce_addrs: &[String],
-    host_addr: &str,
-    bootstrap_peers: &[String],
-    identity_key_path: &std::path::Path,
-    p2pd_path: &Option<std::path::PathBuf>,
-    handler_addr: &std::net::SocketAddr,
-    no_relay: bool,
-) -> anyhow::Result<()> {
-    let handler_addr_str = format!("/ip4/127.0.0.1/tcp/{}", handler_addr.port());
-    let dht_protocols = vec![
-        "DHTProtocol.rpc_ping".to_string(),
-        "DHTProtocol.rpc_store".to_string(),
-        "DHTProtocol.rpc_find".to_string(),
-    ];
-
-    // Unregister handlers before shutdown so the listener port is freed
-    // cleanly before we rebind. Non-fatal if the daemon is already gone.
-    if let Err(e) = client
-        .remove_stream_handler(&handler_addr_str, dht_protocols.clone())
-        .await
-    {
-        warn!("remove_stream_handler before restart: {}", e);
+/// Addresses p2pd listens on: TCP on the configured port, plus QUIC on the
+/// same UDP port when the `quic` experiment is enabled. QUIC is skipped in a
+/// private swarm since it has no room for the pnet handshake.
+fn listen_addrs(config: &KwaaiNetConfig) -> Vec<String> {
+    let port = config.port;
+    let mut addrs = vec![format!("/ip4/0.0.0.0/tcp/{port}")];
+    if crate::experiments::enabled(crate::experiments::Experiment::Quic) {
+        if config.swarm_psk.is_some() {
+            warn!("QUIC does not support private swarms; listening on TCP only");
+        } else {
+            addrs.push(format!("/ip4/0.0.0.0/udp/{port}/quic-v1"));
+        }
     }
-    daemon.shutdown().await?;
+    addrs
+}
 
-    let builder = P2PDaemon::builder()
-        .dht(true)
-        .relay(!no_relay)
-        .auto_relay(true)
-        .auto_nat(true)
-        .nat_portmap(true)
-        .host_addrs([host_addr])
-        .bootstrap_peers(bootstrap_peers.to_vec())
-        .announce_addrs(announce_addrs.iter().map(|s| s.as_str()))
-        .with_identity_key(identity_key_path);
-    let builder = if let Some(ref path) = p2pd_path {
-        builder.with_binary_path(path)
-    } else {
-        builder
+fn swarm_key_path() -> std::path::PathBuf {
+    crate::config::run_dir().join("swarm.key")
+}
+
+/// Write `swarm_psk` as a `swarm.key` file for p2pd's `-psk` flag, or remove
+/// a stale one when the node is no longer in a private swarm.
+fn write_swarm_key(config: &KwaaiNetConfig) -> Result<()> {
+    let path = swarm_key_path();
+    let Some(psk) = &config.swarm_psk else {
+        let _ = std::fs::remove_file(&path);
+        return Ok(());
     };
-    let builder = if let Ok(sock) = std::env::var("KWAAINET_SOCKET") {
-        #[cfg(unix)]
-        let sock_addr = format!("/unix/{}", sock);
-        #[cfg(not(unix))]
-        let sock_addr = sock;
-        builder.with_listen_addr(sock_addr)
+    let key = kwaai_p2p::config::parse_swarm_psk(psk)?;
+    std::fs::create_dir_all(crate::config::run_dir())?;
+    std::fs::write(&path, kwaai_p2p::config::swarm_key_file_contents(&key))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    info!("Private swarm enabled (key in {})", path.display());
+    Ok(())
+}
+
+/// Pass the private swarm key written by [`write_swarm_key`] to p2pd.
+fn with_swarm_key(builder: DaemonBuilder) -> DaemonBuilder {
+    let path = swarm_key_path();
+    if path.exists() {
+        builder.with_swarm_key(path)
     } else {
         builder
-    };
-
-    *daemon = builder.spawn().await.context("restarting p2pd")?;
-    *client = daemon
-        .client()
-        .await
-        .context("p2pd client reconnect after restart")?;
-
-    client
-        .register_stream_handler(&handler_addr_str, dht_protocols)
-        .await
-        .context("re-registering stream handlers after restart")?;
-    info!("p2pd restarted and handlers re-registered");
-
-    // shard serve registered /kwaai/inference/1.0.0 with the old p2pd instance.
-    // Since that instance is gone, restart shard serve so it re-registers.
-    {
-        let shard_mgr = ShardManager::new();
-        if shard_mgr.is_running() {
-            shard_mgr.stop_process();
-            let _ = std::fs::remove_file(ShardManager::ready_file());
-        }
-        match ShardManager::spawn_shard_child() {
-            Ok(pid) => {
-                shard_mgr.write_pid(pid);
-                info!("✅ shard serve restarted after p2pd restart (PID {})", pid);
-            }
-            Err(e) => warn!("failed to restart shard serve after p2pd restart: {}", e),
-        }
     }
+}
 
+/// Restart p2pd announcing `announce_addrs` in place of its previous announce
+/// set. Used when addresses learned via IDENTIFY need to be promoted to the
+/// announce set; the supervisor keeps them for later crash restarts.
+async fn restart_with_announce_addrs(
+    supervisor: &mut DaemonSupervisor,
+    client: &mut kwaai_p2p_daemon::P2PClient,
+    announce_addrs: &[String],
+    config: &crate::config::KwaaiNetConfig,
+    bootstrap_peers: &[String],
+) -> anyhow::Result<()> {
+    supervisor
+        .restart_with(client, |b| {
+            b.without_announce_addrs()
+                .announce_addrs(announce_addrs.iter().map(String::as_str))
+        })
+        .await
+        .context("restarting p2pd")?;
+    after_p2pd_restart(client, config, bootstrap_peers).await;
     Ok(())
 }
 
@@ -1858,29 +3091,18 @@ fn load_relay_addr_cache(max_age_secs: u64) -> Vec<String> {
 /// When no explicit `announce_addr` or `public_ip` is configured, we rely on the
 /// libp2p IDENTIFY protocol: after bootstrap peers connect they report our observed
 /// addresses back to us. Once `min_confirmations` independent responses agree we
-/// shut the initial p2pd down, rebuild it with the confirmed addresses as its
-/// announce addrs, and return the new daemon + client along with those addresses.
+/// restart p2pd with the confirmed addresses as its announce addrs and return
+/// those addresses, reconciled with the address peers observe us at (see
+/// [`with_learned_public_addr`]).
 ///
-/// If IDENTIFY yields nothing the original daemon is returned unchanged and the
+/// If IDENTIFY yields nothing the daemon is left running unchanged and the
 /// returned address list is empty (the node will fall back to relay mode).
-#[allow(clippy::too_many_arguments)]
 async fn discover_and_restart_with_announce(
-    mut daemon: kwaai_p2p_daemon::P2PDaemon,
-    mut client: kwaai_p2p_daemon::P2PClient,
-    host_addr: &str,
+    supervisor: &mut DaemonSupervisor,
+    client: &mut kwaai_p2p_daemon::P2PClient,
+    config: &crate::config::KwaaiNetConfig,
     bootstrap_peers: &[String],
-    identity_key_path: &std::path::Path,
-    p2pd_path: &Option<std::path::PathBuf>,
-    handler_addr: &std::net::SocketAddr,
-    no_relay: bool,
-    port: u16,
-    min_confirmations: usize,
-    timeout_secs: u64,
-) -> anyhow::Result<(
-    kwaai_p2p_daemon::P2PDaemon,
-    kwaai_p2p_daemon::P2PClient,
-    Vec<String>,
-)> {
+) -> anyhow::Result<Vec<String>> {
     // Fast path: reuse relay addresses cached from the previous session.
     // AutoRelay takes ~7 minutes to establish a fresh reservation; the cache
     // eliminates that gap on every cold restart. TTL is 2 hours.
@@ -1890,34 +3112,22 @@ async fn discover_and_restart_with_announce(
         for addr in &cached {
             info!("  - {}", addr);
         }
-        restart_p2pd_with_addrs(
-            &mut daemon,
-            &mut client,
-            &cached,
-            host_addr,
-            bootstrap_peers,
-            identity_key_path,
-            p2pd_path,
-            handler_addr,
-            no_relay,
-        )
-        .await?;
-        return Ok((daemon, client, cached));
+        restart_with_announce_addrs(supervisor, client, &cached, config, bootstrap_peers).await?;
+        return Ok(cached);
     }
 
     info!("No explicit announce address — discovering addresses via IDENTIFY...");
 
-    // Give bootstrap peer(s) a moment to complete the IDENTIFY exchange before
-    // polling. The TCP connection is established by the time we're called, but
-    // IDENTIFY runs asynchronously after it.
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    // IDENTIFY runs asynchronously after the bootstrap connections are up;
+    // collect_observed_addresses polls until enough peers have reported.
     let discovered_addrs = collect_observed_addresses(
-        &mut client,
-        min_confirmations,
-        Duration::from_secs(timeout_secs),
-        port,
+        client,
+        config.identify_min_confirmations,
+        Duration::from_secs(config.identify_timeout_secs),
+        config.port,
     )
     .await;
+    let discovered_addrs = with_learned_public_addr(client, config, discovered_addrs).await;
 
     if discovered_addrs.is_empty() {
         warn!(
@@ -1925,7 +3135,7 @@ async fn discover_and_restart_with_announce(
              — node may appear Unreachable on map.kwaai.ai. \
              Set public_ip or announce_addr in config to override."
         );
-        return Ok((daemon, client, discovered_addrs));
+        return Ok(discovered_addrs);
     }
 
     info!("Confirmed announce address(es) — restarting p2pd:");
@@ -1934,20 +3144,16 @@ async fn discover_and_restart_with_announce(
     }
     save_relay_addr_cache(&discovered_addrs);
 
-    restart_p2pd_with_addrs(
-        &mut daemon,
-        &mut client,
+    restart_with_announce_addrs(
+        supervisor,
+        client,
         &discovered_addrs,
-        host_addr,
+        config,
         bootstrap_peers,
-        identity_key_path,
-        p2pd_path,
-        handler_addr,
-        no_relay,
     )
     .await?;
 
-    Ok((daemon, client, discovered_addrs))
+    Ok(discovered_addrs)
 }
 
 /// Poll `identify_with_addrs()` until `min_confirmations` separate responses
@@ -2024,6 +3230,52 @@ async fn collect_observed_addresses(
     confirmed
 }
 
+/// Reconcile `addrs` with the address connected peers agree they see us at:
+/// announce it when no direct address carries that IP (nothing confirmed
+/// yet, relay circuits only), and drop direct IPv4 addresses they no longer
+/// see us at — after a restart p2pd reports back the addresses it was told
+/// to announce, so a stale learned address would otherwise stick. Relay
+/// circuits stay as the fallback. A node with a forwarded port thus needs no
+/// `public_ip`. Off with `learn_public_addr`.
+async fn with_learned_public_addr(
+    client: &mut kwaai_p2p_daemon::P2PClient,
+    config: &crate::config::KwaaiNetConfig,
+    mut addrs: Vec<String>,
+) -> Vec<String> {
+    use libp2p::multiaddr::Protocol;
+    use libp2p::Multiaddr;
+
+    if !config.learn_public_addr {
+        return addrs;
+    }
+    let Some(observed) = crate::public_ip::learn_public_addr(client, Duration::from_secs(5)).await
+    else {
+        return addrs;
+    };
+    let direct_ip4 = |addr: &String| {
+        if addr.contains("/p2p-circuit") {
+            return None;
+        }
+        addr.parse::<Multiaddr>()
+            .ok()?
+            .iter()
+            .find_map(|p| match p {
+                Protocol::Ip4(ip) => Some(ip),
+                _ => None,
+            })
+    };
+    addrs.retain(|addr| direct_ip4(addr).is_none_or(|ip| ip == observed.ip));
+    if !addrs.iter().any(|addr| direct_ip4(addr).is_some()) {
+        let addr = observed.multiaddr(config.public_port.unwrap_or(config.port));
+        info!(
+            "Learned public address from peer observations: {} → {}",
+            observed, addr
+        );
+        addrs.insert(0, addr);
+    }
+    addrs
+}
+
 /// Decide whether a multiaddr from p2pd's `host.Addrs()` is suitable to
 /// announce.
 ///
@@ -2086,7 +3338,7 @@ fn is_announceable_addr(ma: &libp2p::Multiaddr) -> bool {
 /// (`192.0.2.0/24`, `198.51.100.0/24`, `203.0.113.0/24`) here — they are
 /// reserved by IANA but not LAN-private, and our nat-test topology uses
 /// `198.51.100.0/24` as a simulated public network.
-fn is_globally_routable_v4(a: std::net::Ipv4Addr) -> bool {
+pub(crate) fn is_globally_routable_v4(a: std::net::Ipv4Addr) -> bool {
     if a.is_unspecified()
         || a.is_loopback()
         || a.is_link_local()
@@ -2110,6 +3362,32 @@ fn all_addrs_are_relay(addrs: &[String]) -> bool {
     !addrs.is_empty() && addrs.iter().all(|s| s.contains("/p2p-circuit"))
 }
 
+/// Health section of the status file.
+fn health_status(
+    reachability: &crate::health::ReachabilityMonitor,
+    reconnection: &crate::health::ReconnectionManager,
+) -> serde_json::Value {
+    serde_json::json!({
+        "reachability": reachability.status(),
+        "reconnection": reconnection.status(),
+    })
+}
+
+/// Dial every bootstrap peer again; returns how many connected.
+async fn redial_bootstrap(
+    client: &kwaai_p2p_daemon::P2PClient,
+    bootstrap_peers: &[String],
+) -> usize {
+    let mut connected = 0usize;
+    for addr in bootstrap_peers {
+        match client.connect_peer(addr).await {
+            Ok(()) => connected += 1,
+            Err(e) => warn!("Reconnect: dial {} failed: {}", addr, e),
+        }
+    }
+    connected
+}
+
 /// Wait for p2pd's own DHT bootstrap to establish connections.
 ///
 /// p2pd bootstraps independently using the -b addresses it was started with.
@@ -2222,11 +3500,32 @@ async fn dial_and_wait_for_bootstrap(
 // Incoming RPC stream handler
 // ---------------------------------------------------------------------------
 
-async fn handle_rpc_stream(tcp: &mut tokio::net::TcpStream, storage: SharedStorage) -> Result<()> {
-    let info = stream::parse_stream_info(tcp)
-        .await
-        .map_err(|e| anyhow::anyhow!("parse stream info: {}", e))?;
+async fn handle_rpc_stream(
+    tcp: &mut tokio::net::TcpStream,
+    storage: SharedStorage,
+    rpc_limiter: &crate::rpc_limits::RpcLimiter,
+) -> Result<()> {
+    let Some(info) = crate::peer_filter::admit_stream(tcp).await? else {
+        return Ok(());
+    };
     info!("RPC {}", info.proto);
+    // Held until the stream is answered.
+    let mut peer_stream = None;
+    if let Ok(peer) = PeerId::from_bytes(&info.peer) {
+        peer_stream = rpc_limiter.try_acquire_peer(&peer);
+        if peer_stream.is_none() {
+            tracing::debug!(
+                "Dropping {} from {}: too many open streams",
+                info.proto,
+                peer
+            );
+            return Ok(());
+        }
+        if let Err(rejection) = rpc_limiter.admit(&peer) {
+            tracing::debug!("Dropping {} from {}: {}", info.proto, peer, rejection);
+            return Ok(());
+        }
+    }
 
     use prost::Message as _;
 
@@ -2234,10 +3533,21 @@ async fn handle_rpc_stream(tcp: &mut tokio::net::TcpStream, storage: SharedStora
     // wrapper containing the actual DHT payload in callUnary.data.
     // Unwrapping and re-wrapping uses helpers in kwaai-p2p-daemon to avoid a
     // prost version conflict (p2pd uses 0.13, workspace uses 0.12).
-    let (outer_bytes, _) = read_rpc_message(tcp).await?;
+    let max_len = rpc_limiter.max_message_bytes();
+    let (outer_bytes, _) = match read_rpc_message(tcp, max_len).await {
+        Ok(message) => message,
+        Err(e) if e.is::<MessageTooLarge>() => {
+            rpc_limiter.record_oversized();
+            tracing::debug!("Dropping {}: {}", info.proto, e);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
     if outer_bytes.is_empty() {
         return Ok(());
     }
+    let limiter = crate::bandwidth::limiter();
+    limiter.download(outer_bytes.len()).await;
 
     let (call_id, dht_data) = stream::unwrap_stream_handler_request(&outer_bytes)
         .map_err(|e| anyhow::anyhow!("{}", e))?;
@@ -2275,19 +3585,36 @@ async fn handle_rpc_stream(tcp: &mut tokio::net::TcpStream, storage: SharedStora
 
     // Wrap in PersistentConnectionResponse and write back varint-framed.
     let framed = stream::wrap_stream_handler_response(call_id, raw_dht_response);
+    limiter.upload(framed.len()).await;
     tcp.write_all(&framed).await?;
     tcp.flush().await?;
     Ok(())
 }
 
+/// A request message longer than `rpc_limits.max_message_bytes`.
+#[derive(Debug)]
+struct MessageTooLarge(usize);
+
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message exceeds {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
 /// Read one RPC message from a p2pd-forwarded stream.
 ///
 /// p2pd uses varint-length-prefixed messages: [varint N][N payload bytes].
 /// Returns (payload_bytes, varint_framed=true).
 ///
-/// Falls back to read_to_end when the varint appears invalid (oversized or
-/// no bytes follow), returning (bytes, varint_framed=false).
-async fn read_rpc_message(tcp: &mut tokio::net::TcpStream) -> Result<(Vec<u8>, bool)> {
+/// Falls back to reading to the end of the stream when the varint appears
+/// invalid, returning (bytes, varint_framed=false). Messages longer than
+/// `max_len` fail with [`MessageTooLarge`] without being read in full.
+async fn read_rpc_message(
+    tcp: &mut tokio::net::TcpStream,
+    max_len: usize,
+) -> Result<(Vec<u8>, bool)> {
     use tokio::io::AsyncReadExt as _;
 
     // Peek at the first byte to decide if this could be a varint prefix.
@@ -2312,22 +3639,26 @@ async fn read_rpc_message(tcp: &mut tokio::net::TcpStream) -> Result<(Vec<u8>, b
     }
 
     if let Ok((len, _)) = unsigned_varint::decode::usize(&varint_buf) {
-        // Sanity cap: reject if larger than 10 MiB (a legitimate store
-        // request from the bootstrap can be ~50 KiB for a full network view).
-        if len <= 10 * 1024 * 1024 {
-            let mut payload = vec![0u8; len];
-            tcp.read_exact(&mut payload)
-                .await
-                .map_err(|e| anyhow::anyhow!("read_exact({} bytes): {}", len, e))?;
-            return Ok((payload, true));
+        if len > max_len {
+            return Err(MessageTooLarge(max_len).into());
         }
+        let mut payload = vec![0u8; len];
+        tcp.read_exact(&mut payload)
+            .await
+            .map_err(|e| anyhow::anyhow!("read_exact({} bytes): {}", len, e))?;
+        return Ok((payload, true));
     }
 
-    // Varint missing or oversized — collect remaining bytes (raw protobuf).
-    let mut rest = Vec::new();
-    let _ = tcp.read_to_end(&mut rest).await;
+    // Varint missing — collect remaining bytes (raw protobuf), reading one
+    // byte past the limit to tell a full-size message from an oversized one.
     let mut all = varint_buf;
-    all.extend(rest);
+    let _ = tcp
+        .take((max_len + 1).saturating_sub(all.len()) as u64)
+        .read_to_end(&mut all)
+        .await;
+    if all.len() > max_len {
+        return Err(MessageTooLarge(max_len).into());
+    }
     Ok((all, false))
 }
 
@@ -2418,7 +3749,8 @@ async fn maybe_auto_update() -> Option<String> {
 // Signal handling
 // ---------------------------------------------------------------------------
 
-async fn shutdown_signal() {
+/// Resolve on Ctrl-C or (on Unix) SIGTERM.
+pub(crate) async fn shutdown_signal() {
     #[cfg(unix)]
     {
         let mut sigterm =
@@ -2470,88 +3802,28 @@ fn port_is_free(port: u16) -> bool {
 /// Compute effective throughput from the cached benchmark result.
 ///
 /// Re-reads `~/.kwaainet/throughput_cache.json` on every call so that a
-/// `kwaainet benchmark` run after the daemon started is reflected within
-/// the next re-announcement cycle (120 s).
+/// shard benchmark or `kwaainet benchmark` run after the daemon started is
+/// reflected within the next re-announcement cycle.
 ///
-/// `dl_bps` is the download bandwidth measured at startup and reused here
-/// to avoid a slow network probe on every re-announce.
-fn compute_effective_tps(model: &str, dl_bps: f64, using_relay: bool) -> f64 {
+/// `dl_bps` is the most recent bandwidth probe, reused here to avoid a slow
+/// network probe on every re-announce.
+fn compute_effective_tps(model: &str, span_blocks: u32, dl_bps: f64, using_relay: bool) -> f64 {
     match crate::throughput::load(model) {
-        Some(entry) => crate::throughput::effective_tps(&entry, dl_bps, using_relay),
+        Some(entry) => crate::throughput::effective_tps(&entry, span_blocks, dl_bps, using_relay),
         None => 10.0, // fallback until benchmark is run
     }
 }
 
-/// Spawn a fresh p2pd and reconnect the P2PClient.
-/// Called by the watchdog when the daemon detects p2pd has exited.
-async fn restart_p2pd(
-    daemon: &mut P2PDaemon,
+/// Node-side follow-up once the supervisor has restarted p2pd: rejoin the
+/// DHT and restart the local services that hold their own p2pd connection.
+async fn after_p2pd_restart(
     client: &mut kwaai_p2p_daemon::P2PClient,
-    p2pd_path: &Option<std::path::PathBuf>,
     config: &crate::config::KwaaiNetConfig,
     bootstrap_peers: &[String],
-    announce_addr: &Option<String>,
-    handler_addr: std::net::SocketAddr,
-) -> Result<()> {
-    // Drop the dead daemon handle (reaps the zombie if still pending).
-    let _ = daemon.shutdown().await;
-
-    let host_addr = format!("/ip4/0.0.0.0/tcp/{}", config.port);
-    let identity_key_path = config
-        .identity_key
-        .clone()
-        .unwrap_or_else(crate::identity::NodeIdentity::key_file_path);
-
-    let builder = P2PDaemon::builder()
-        .dht(true)
-        .bootstrap(!bootstrap_peers.is_empty())
-        .relay(!config.no_relay)
-        .auto_relay(true)
-        .auto_nat(true)
-        .force_reachability_private(config.force_private)
-        .nat_portmap(true)
-        .host_addrs([host_addr])
-        .bootstrap_peers(bootstrap_peers.to_vec())
-        .trusted_relays(config.trusted_relays.clone())
-        .with_identity_key(&identity_key_path);
-
-    let builder = match announce_addr {
-        Some(addr) => builder.announce_addrs([addr.as_str()]),
-        None => builder,
-    };
-    let builder = match p2pd_path {
-        Some(path) => builder.with_binary_path(path),
-        None => builder,
-    };
-    let builder = if let Ok(sock) = std::env::var("KWAAINET_SOCKET") {
-        #[cfg(unix)]
-        let addr = format!("/unix/{}", sock);
-        #[cfg(not(unix))]
-        let addr = sock;
-        builder.with_listen_addr(addr)
-    } else {
-        builder
-    };
-
-    let mut new_daemon = builder.spawn().await.context("restarting p2pd")?;
-    let mut new_client = new_daemon.client().await.context("p2pd client (restart)")?;
-
-    new_client
-        .register_stream_handler(
-            &format!("/ip4/127.0.0.1/tcp/{}", handler_addr.port()),
-            vec![
-                "DHTProtocol.rpc_ping".to_string(),
-                "DHTProtocol.rpc_store".to_string(),
-                "DHTProtocol.rpc_find".to_string(),
-            ],
-        )
-        .await
-        .context("re-registering stream handlers")?;
-
-    dial_and_wait_for_bootstrap(&mut new_client, bootstrap_peers).await?;
-
-    *daemon = new_daemon;
-    *client = new_client;
+) {
+    if let Err(e) = dial_and_wait_for_bootstrap(client, bootstrap_peers).await {
+        warn!("Bootstrap after p2pd restart failed: {:#}", e);
+    }
 
     // shard serve holds a persistent connection to p2pd and registers
     // /kwaai/inference/1.0.0 once at startup. Since p2pd just restarted,
@@ -2563,7 +3835,7 @@ async fn restart_p2pd(
             shard_mgr.stop_process();
             let _ = std::fs::remove_file(ShardManager::ready_file());
         }
-        match ShardManager::spawn_shard_child() {
+        match ShardManager::respawn_shard_child() {
             Ok(pid) => {
                 shard_mgr.write_pid(pid);
                 info!("✅ shard serve restarted after p2pd restart (PID {})", pid);
@@ -2590,8 +3862,6 @@ async fn restart_p2pd(
             Err(e) => warn!("failed to restart storage API after p2pd restart: {}", e),
         }
     }
-
-    Ok(())
 }
 
 fn find_p2pd_binary() -> Option<std::path::PathBuf> {
@@ -2627,3 +3897,28 @@ fn find_p2pd_binary() -> Option<std::path::PathBuf> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn filtered_peer_dht_request_is_dropped() {
+        let storage: SharedStorage = Arc::new(RwLock::new(DHTStorage::new(PeerId::random())));
+        let limiter = crate::rpc_limits::RpcLimiter::new(&Default::default());
+        let denied = PeerId::random();
+        crate::peer_filter::deny_for_test(&[&denied.to_base58()]);
+        let addr: libp2p::Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+
+        // Only the admitted peer reaches the rate limiter.
+        for (peer, accepted) in [(denied, 0), (PeerId::random(), 1)] {
+            let (client, mut server) =
+                crate::peer_filter::forwarded_stream(&peer, &addr, "DHTProtocol.rpc_ping").await;
+            drop(client);
+            handle_rpc_stream(&mut server, storage.clone(), &limiter)
+                .await
+                .unwrap();
+            assert_eq!(limiter.stats().accepted, accepted);
+        }
+    }
+}
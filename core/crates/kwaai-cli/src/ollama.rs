@@ -13,9 +13,24 @@
 //!
 //! We detect which layout is in use by checking whether `<dir>/blobs/`
 //! exists directly (custom) or only under `<dir>/models/blobs/` (default).
-
-use anyhow::{anyhow, Context, Result};
+//!
+//! [`pull_model`] speaks the registry's manifest protocol to download models
+//! into the same store, so `kwaainet models pull` works without Ollama
+//! installed and Ollama sees what it pulled.
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::StreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+/// Registry `pull` uses for bare and `namespace/name` references
+const DEFAULT_REGISTRY: &str = "registry.ollama.ai";
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.docker.distribution.manifest.v2+json";
+const MODEL_MEDIA_TYPE: &str = "application/vnd.ollama.image.model";
 
 /// Resolve an Ollama model reference to the GGUF blob path on disk.
 ///
@@ -42,7 +57,7 @@ pub fn resolve_model_blob(model_ref: &str) -> Result<PathBuf> {
 
     let model_layer = layers
         .iter()
-        .find(|l| l["mediaType"].as_str() == Some("application/vnd.ollama.image.model"))
+        .find(|l| l["mediaType"].as_str() == Some(MODEL_MEDIA_TYPE))
         .ok_or_else(|| anyhow!("No model layer found in manifest"))?;
 
     let digest = model_layer["digest"]
@@ -73,15 +88,25 @@ fn find_manifest(model_ref: &str, manifests_root: &Path) -> Result<PathBuf> {
     // Candidates tried in order:
     //   1. registry.ollama.ai/library/<name>/<tag>  — standard Ollama library
     //   2. <name>/<tag>                              — fully-qualified (hf.co/…)
+    //   3. registry.ollama.ai/<namespace>/<name>/<tag> — user namespaces
     // Use separate join() calls to avoid forward-slash handling differences on Windows.
-    let candidates = [
+    let mut candidates = vec![
         manifests_root
-            .join("registry.ollama.ai")
+            .join(DEFAULT_REGISTRY)
             .join("library")
             .join(name)
             .join(tag),
         manifests_root.join(name).join(tag),
     ];
+    if let Some((namespace, model)) = name.split_once('/').filter(|(_, m)| !m.contains('/')) {
+        candidates.push(
+            manifests_root
+                .join(DEFAULT_REGISTRY)
+                .join(namespace)
+                .join(model)
+                .join(tag),
+        );
+    }
 
     for path in &candidates {
         if path.exists() {
@@ -89,16 +114,19 @@ fn find_manifest(model_ref: &str, manifests_root: &Path) -> Result<PathBuf> {
         }
     }
 
+    let searched: Vec<String> = candidates
+        .iter()
+        .map(|p| format!("  {}", p.display()))
+        .collect();
     Err(anyhow!(
         "Model '{}' not found in {}.\n\
-         Searched:\n  {}\n  {}\n\
-         Either the model is not pulled (run: ollama pull {}) \
+         Searched:\n{}\n\
+         Either the model is not pulled (run: kwaainet models pull {}) \
          or Ollama is storing models elsewhere.\n\
          Workaround: set OLLAMA_MODELS to your Ollama models directory.",
         model_ref,
         manifests_root.display(),
-        candidates[0].display(),
-        candidates[1].display(),
+        searched.join("\n"),
         model_ref,
     ))
 }
@@ -108,12 +136,35 @@ fn find_manifest(model_ref: &str, manifests_root: &Path) -> Result<PathBuf> {
 /// Scans every known Ollama manifests directory in priority order and returns
 /// deduplicated model refs suitable for passing to [`resolve_model_blob`].
 pub fn list_local_models() -> Vec<String> {
+    let mut models: Vec<String> = collect_local_manifests()
+        .into_iter()
+        .map(|(model_ref, _)| model_ref)
+        .collect();
+    models.sort();
+    models.dedup();
+    models
+}
+
+/// Every installed model reference with the manifest it was read from, in
+/// root priority order (a ref may appear under more than one root).
+fn collect_local_manifests() -> Vec<(String, PathBuf)> {
+    let mut models = Vec::new();
+    for root in manifest_roots() {
+        if root.is_dir() {
+            collect_manifest_models(&root, &root, &mut models);
+        }
+    }
+    models
+}
+
+/// Manifest directories to scan, in the same priority order as
+/// [`find_ollama_roots`]. Blobs live in the sibling `blobs/` directory.
+fn manifest_roots() -> Vec<PathBuf> {
     let home = match dirs::home_dir() {
         Some(h) => h,
         None => return Vec::new(),
     };
 
-    // Probe roots in the same priority order as find_ollama_roots().
     let mut roots: Vec<PathBuf> = Vec::new();
     if let Ok(custom) = std::env::var("OLLAMA_MODELS") {
         roots.push(PathBuf::from(custom).join("manifests"));
@@ -127,21 +178,11 @@ pub fn list_local_models() -> Vec<String> {
     }
     roots.push(PathBuf::from("/usr/share/ollama/.ollama/models").join("manifests"));
     roots.push(home.join(".ollama").join("models").join("manifests"));
-
-    let mut models: Vec<String> = Vec::new();
-    for root in &roots {
-        if root.is_dir() {
-            collect_manifest_models(root, root, &mut models);
-        }
-    }
-
-    models.sort();
-    models.dedup();
-    models
+    roots
 }
 
 /// Recursively walk `dir` under `root` and collect model refs.
-fn collect_manifest_models(root: &Path, dir: &Path, out: &mut Vec<String>) {
+fn collect_manifest_models(root: &Path, dir: &Path, out: &mut Vec<(String, PathBuf)>) {
     let Ok(entries) = std::fs::read_dir(dir) else {
         return;
     };
@@ -156,7 +197,7 @@ fn collect_manifest_models(root: &Path, dir: &Path, out: &mut Vec<String>) {
             collect_manifest_models(root, &path, out);
         } else if path.is_file() {
             if let Some(model_ref) = manifest_path_to_ref(&path, root) {
-                out.push(model_ref);
+                out.push((model_ref, path));
             }
         }
     }
@@ -167,6 +208,7 @@ fn collect_manifest_models(root: &Path, dir: &Path, out: &mut Vec<String>) {
 /// Expected path structures relative to manifests root:
 /// - `registry.ollama.ai/library/<name>/<tag>` → `"<name>:<tag>"` (or just `"<name>"` for latest)
 /// - `hf.co/<org>/<model>/<tag>`               → `"hf.co/<org>/<model>:<tag>"`
+/// - `registry.ollama.ai/<ns>/<name>/<tag>`    → `"<ns>/<name>:<tag>"`
 /// - `<name>/<tag>`                             → `"<name>:<tag>"`
 fn manifest_path_to_ref(manifest: &Path, root: &Path) -> Option<String> {
     let rel = manifest.strip_prefix(root).ok()?;
@@ -188,6 +230,12 @@ fn manifest_path_to_ref(manifest: &Path, root: &Path) -> Option<String> {
         } else {
             format!("hf.co/{}/{}:{}", org, model, tag)
         }),
+        // registry.ollama.ai/<namespace>/<name>/<tag>
+        [DEFAULT_REGISTRY, namespace, name, tag] => Some(if *tag == "latest" {
+            format!("{}/{}", namespace, name)
+        } else {
+            format!("{}/{}:{}", namespace, name, tag)
+        }),
         // <name>/<tag>  (flat custom layout)
         [name, tag] => Some(if *tag == "latest" {
             name.to_string()
@@ -252,6 +300,296 @@ fn find_ollama_roots() -> Result<(PathBuf, PathBuf)> {
     Ok((default.join("manifests"), default.join("blobs")))
 }
 
+/// An installed model as `kwaainet models list` shows it
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalModel {
+    /// Reference to pass to [`resolve_model_blob`], e.g. `llama3.1:8b`
+    pub name: String,
+    /// Bytes of all layers (weights, template, license, …)
+    pub size: u64,
+    /// Parameter count as the registry labels it, e.g. `8.0B`
+    pub parameter_size: Option<String>,
+    /// GGUF file type, e.g. `Q4_K_M`
+    pub quantization: Option<String>,
+    pub family: Option<String>,
+}
+
+/// Installed models with sizes and quantization, sorted by name. Details
+/// come from each manifest and its config blob; a model whose config blob
+/// is missing is listed without them.
+pub fn local_models() -> Vec<LocalModel> {
+    let mut models: Vec<LocalModel> = Vec::new();
+    for (name, manifest_path) in collect_local_manifests() {
+        if models.iter().any(|m| m.name == name) {
+            continue; // shadowed by a higher-priority root
+        }
+        let Some(manifest) = std::fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        else {
+            continue;
+        };
+        // <root>/manifests/<registry>/… → <root>/blobs
+        let blobs = manifest_path
+            .ancestors()
+            .find(|p| p.file_name().is_some_and(|n| n == "manifests"))
+            .and_then(|p| p.parent())
+            .map(|p| p.join("blobs"));
+        let config = manifest["config"]["digest"]
+            .as_str()
+            .zip(blobs)
+            .and_then(|(digest, blobs)| {
+                std::fs::read_to_string(blobs.join(digest.replace(':', "-"))).ok()
+            })
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+            .unwrap_or_default();
+        let text = |key: &str| config[key].as_str().map(str::to_string);
+        models.push(LocalModel {
+            name,
+            size: manifest["layers"]
+                .as_array()
+                .map(|layers| layers.iter().filter_map(|l| l["size"].as_u64()).sum())
+                .unwrap_or(0),
+            parameter_size: text("model_type"),
+            quantization: text("file_type"),
+            family: text("model_family"),
+        });
+    }
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    models
+}
+
+/// Resolve `model` to a local GGUF blob, falling back to the installed
+/// model whose name matches it — so a configured HuggingFace id such as
+/// `unsloth/Llama-3.1-8B-Instruct` finds a pulled `llama3.1:8b`.
+pub fn find_model_blob(model: &str) -> Result<PathBuf> {
+    let exact = resolve_model_blob(model);
+    if exact.is_ok() {
+        return exact;
+    }
+    match best_local_match(model, &list_local_models()) {
+        Some(local) => {
+            debug!("Using local Ollama model {local} for {model}");
+            resolve_model_blob(local)
+        }
+        None => exact,
+    }
+}
+
+/// The installed reference naming the same model as `model`: a tagged
+/// name whose name and tag both appear in the model name (`llama3.1:8b` in
+/// `Llama-3.1-8B-Instruct`), or an untagged one equal to it. Untagged names
+/// must match exactly since their size is unknown. The longest match wins.
+fn best_local_match<'a>(model: &str, locals: &'a [String]) -> Option<&'a str> {
+    let base = model.rsplit('/').next().unwrap_or(model);
+    let target = crate::map::normalize(base.split(':').next().unwrap_or(base));
+    if target.is_empty() {
+        return None;
+    }
+    locals
+        .iter()
+        .filter_map(|local| {
+            let name = local.rsplit('/').next().unwrap_or(local);
+            let norm = crate::map::normalize(name);
+            let matches = if name.contains(':') {
+                target.contains(&norm)
+            } else {
+                norm == target
+            };
+            matches.then_some((norm.len(), local.as_str()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, local)| local)
+}
+
+/// Where a model reference lives in a registry
+#[derive(Debug, PartialEq)]
+struct RegistryRef {
+    host: String,
+    /// `library/llama3.1`, `org/model`
+    repository: String,
+    tag: String,
+}
+
+impl RegistryRef {
+    /// `llama3.1:8b` → registry.ollama.ai `library/llama3.1` `8b`;
+    /// `user/model` → registry.ollama.ai `user/model` `latest`;
+    /// `hf.co/org/model:Q4_K_M` → hf.co `org/model` `Q4_K_M`.
+    fn parse(model_ref: &str) -> Result<Self> {
+        let (name, tag) = match model_ref.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, tag),
+            _ => (model_ref, "latest"),
+        };
+        let parts: Vec<&str> = name.split('/').collect();
+        if name.is_empty() || tag.is_empty() || parts.iter().any(|p| p.is_empty()) {
+            bail!("Invalid model reference '{model_ref}'");
+        }
+        let (host, repository) = match parts.as_slice() {
+            [model] => (DEFAULT_REGISTRY.to_string(), format!("library/{model}")),
+            [namespace, model] => (DEFAULT_REGISTRY.to_string(), format!("{namespace}/{model}")),
+            [host, rest @ ..] if host.contains('.') => (host.to_string(), rest.join("/")),
+            _ => bail!("Invalid model reference '{model_ref}'"),
+        };
+        Ok(Self {
+            host,
+            repository,
+            tag: tag.to_string(),
+        })
+    }
+
+    fn url(&self, kind: &str, reference: &str) -> String {
+        format!(
+            "https://{}/v2/{}/{kind}/{reference}",
+            self.host, self.repository
+        )
+    }
+
+    /// Manifest location under the manifests root, as Ollama lays it out
+    fn manifest_path(&self, manifests_root: &Path) -> PathBuf {
+        let mut path = manifests_root.join(&self.host);
+        for part in self.repository.split('/') {
+            path = path.join(part);
+        }
+        path.join(&self.tag)
+    }
+}
+
+/// Download `model_ref` from its registry into the local Ollama store and
+/// return the path of its GGUF blob. Blobs already present are kept; each
+/// download is checked against its sha256 digest before it is used.
+/// Progress goes to stdout when `show_progress` is set.
+pub async fn pull_model(model_ref: &str, show_progress: bool) -> Result<PathBuf> {
+    use std::io::Write as _;
+
+    let reference = RegistryRef::parse(model_ref)?;
+    let (manifests_root, blobs_root) = find_ollama_roots()?;
+    let client = reqwest::Client::builder()
+        .user_agent(concat!("kwaainet/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    let resp = client
+        .get(reference.url("manifests", &reference.tag))
+        .header(reqwest::header::ACCEPT, MANIFEST_MEDIA_TYPE)
+        .send()
+        .await
+        .with_context(|| format!("Cannot reach {}", reference.host))?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        bail!("'{model_ref}' not found on {}", reference.host);
+    }
+    if !resp.status().is_success() {
+        bail!(
+            "HTTP {} fetching the manifest of {model_ref}",
+            resp.status()
+        );
+    }
+    let manifest_text = resp.text().await?;
+    let manifest: serde_json::Value =
+        serde_json::from_str(&manifest_text).context("Manifest is not valid JSON")?;
+
+    let layers = manifest["layers"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Manifest has no 'layers' array"))?;
+    if !layers
+        .iter()
+        .any(|l| l["mediaType"].as_str() == Some(MODEL_MEDIA_TYPE))
+    {
+        bail!("{model_ref} has no GGUF model layer");
+    }
+    let blobs: Vec<(&str, u64)> = std::iter::once(&manifest["config"])
+        .chain(layers)
+        .filter_map(|l| Some((l["digest"].as_str()?, l["size"].as_u64().unwrap_or(0))))
+        .collect();
+
+    std::fs::create_dir_all(&blobs_root)
+        .with_context(|| format!("Cannot create {}", blobs_root.display()))?;
+    for (idx, (digest, size)) in blobs.iter().enumerate() {
+        let short = &digest[digest.len().saturating_sub(12)..];
+        let dest = blobs_root.join(blob_file_name(digest)?);
+        if dest.exists() {
+            if !show_progress {
+                continue;
+            }
+            println!(
+                "  [{:2}/{}] {short}  ✓  {} (already present)",
+                idx + 1,
+                blobs.len(),
+                crate::hf::fmt_bytes(*size)
+            );
+            continue;
+        }
+        let resp = client.get(reference.url("blobs", digest)).send().await?;
+        if !resp.status().is_success() {
+            bail!("HTTP {} downloading blob {digest}", resp.status());
+        }
+
+        let tmp = blobs_root.join(format!("{}-partial", blob_file_name(digest)?));
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        let mut hasher = Sha256::new();
+        let mut downloaded: u64 = 0;
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            if !show_progress {
+                continue;
+            }
+            if let Some(pct) = (downloaded * 100).checked_div(*size) {
+                print!(
+                    "\r  [{:2}/{}] {short}  {pct}%  ({}/{})",
+                    idx + 1,
+                    blobs.len(),
+                    crate::hf::fmt_bytes(downloaded),
+                    crate::hf::fmt_bytes(*size)
+                );
+                let _ = std::io::stdout().flush();
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        let actual = format!("sha256:{}", hex::encode(hasher.finalize()));
+        if actual != *digest {
+            let _ = std::fs::remove_file(&tmp);
+            bail!("Blob {digest} failed verification (got {actual})");
+        }
+        std::fs::rename(&tmp, &dest)
+            .with_context(|| format!("Failed to rename {} -> {}", tmp.display(), dest.display()))?;
+        if show_progress {
+            print!(
+                "\r\x1b[K  [{:2}/{}] {short}  ✓  {}\n",
+                idx + 1,
+                blobs.len(),
+                crate::hf::fmt_bytes(downloaded)
+            );
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    // The manifest goes last so a half-finished pull is never listed.
+    let manifest_path = reference.manifest_path(&manifests_root);
+    if let Some(parent) = manifest_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Cannot create {}", parent.display()))?;
+    }
+    std::fs::write(&manifest_path, manifest_text)
+        .with_context(|| format!("Cannot write {}", manifest_path.display()))?;
+
+    resolve_model_blob(model_ref)
+}
+
+/// `sha256:abc…` → `sha256-abc…`, refusing anything that is not a plain
+/// sha256 digest so a manifest cannot name a path outside the blob store
+fn blob_file_name(digest: &str) -> Result<String> {
+    match digest.strip_prefix("sha256:") {
+        Some(hex) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(format!("sha256-{hex}"))
+        }
+        _ => bail!("Unsupported blob digest '{digest}'"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -294,4 +632,76 @@ mod tests {
             "path should contain library: {err}"
         );
     }
+
+    #[test]
+    fn parses_registry_references() {
+        let r = RegistryRef::parse("llama3.1:8b").unwrap();
+        assert_eq!(
+            (r.host.as_str(), r.repository.as_str(), r.tag.as_str()),
+            ("registry.ollama.ai", "library/llama3.1", "8b")
+        );
+        let r = RegistryRef::parse("user/model").unwrap();
+        assert_eq!(
+            (r.repository.as_str(), r.tag.as_str()),
+            ("user/model", "latest")
+        );
+        let r = RegistryRef::parse("hf.co/org/model-GGUF:Q4_K_M").unwrap();
+        assert_eq!(
+            (r.host.as_str(), r.repository.as_str(), r.tag.as_str()),
+            ("hf.co", "org/model-GGUF", "Q4_K_M")
+        );
+        assert_eq!(
+            r.url("manifests", &r.tag),
+            "https://hf.co/v2/org/model-GGUF/manifests/Q4_K_M"
+        );
+        assert!(RegistryRef::parse("").is_err());
+        assert!(RegistryRef::parse("a/b/c").is_err());
+    }
+
+    #[test]
+    fn pulled_manifests_list_under_the_same_reference() {
+        let root = PathBuf::from("/models/manifests");
+        for model_ref in [
+            "llama3.1:8b",
+            "qwen3",
+            "user/model:v2",
+            "hf.co/org/model:Q8_0",
+        ] {
+            let path = RegistryRef::parse(model_ref).unwrap().manifest_path(&root);
+            assert_eq!(
+                manifest_path_to_ref(&path, &root).as_deref(),
+                Some(model_ref),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn blob_names_must_be_sha256_digests() {
+        let hex = "a".repeat(64);
+        assert_eq!(
+            blob_file_name(&format!("sha256:{hex}")).unwrap(),
+            format!("sha256-{hex}")
+        );
+        assert!(blob_file_name("sha256:../../etc/passwd").is_err());
+        assert!(blob_file_name("md5:abc").is_err());
+    }
+
+    #[test]
+    fn configured_model_matches_local_ollama_name() {
+        let locals: Vec<String> = ["llama3.1", "llama3.1:70b", "llama3.1:8b", "qwen3:0.6b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            best_local_match("unsloth/Llama-3.1-8B-Instruct", &locals),
+            Some("llama3.1:8b")
+        );
+        assert_eq!(best_local_match("Qwen3-0.6B", &locals), Some("qwen3:0.6b"));
+        assert_eq!(best_local_match("llama3.1", &locals), Some("llama3.1"));
+        // An untagged pull could be any size.
+        assert_eq!(best_local_match("Llama-3.1-405B", &locals), None);
+        assert_eq!(best_local_match("mistral-7b", &locals), None);
+    }
 }
@@ -2,19 +2,26 @@
 //!
 //! All commands talk only to the local p2pd over its IPC socket. `info` and
 //! `peers list` return p2pd's in-memory view; `peers find` issues an active
-//! Kademlia lookup via p2pd.
+//! Kademlia lookup via p2pd. The top-level `kwaainet peers` view lives here
+//! too: it adds ping/identify probes and the DHT-advertised block ranges on
+//! top of `LIST_PEERS`, taking the connection list from the node's control
+//! socket when it is up.
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
-use kwaai_p2p::NetworkConfig;
+use kwaai_p2p::{NetworkConfig, PeerIpInfo};
+use kwaai_p2p_daemon::probe::PeerIdentity;
 use kwaai_p2p_daemon::P2PClient;
 use libp2p::{Multiaddr, PeerId};
 
-use crate::cli::{P2pAction, P2pArgs, PeersAction, PeersArgs};
+use crate::cli::{P2pAction, P2pArgs, PeersAction, PeersArgs, PeersCmdArgs};
 use crate::config::KwaaiNetConfig;
+use crate::control::{self, ControlMethod};
 use crate::display::*;
-use crate::shard_cmd::daemon_socket;
+use crate::reputation::load_store;
+use crate::shard_cmd::{daemon_socket, discover_chain, BlockServerEntry};
 
 pub async fn run(args: P2pArgs) -> Result<()> {
     match args.action {
@@ -154,7 +161,7 @@ fn trusted_relay_peer_ids() -> HashSet<PeerId> {
 // ---------------------------------------------------------------------------
 
 async fn info() -> Result<()> {
-    let Some(mut client) = connect_p2pd().await? else {
+    let Some(client) = connect_p2pd().await? else {
         return Ok(());
     };
 
@@ -219,7 +226,7 @@ async fn info() -> Result<()> {
 // ---------------------------------------------------------------------------
 
 async fn peers_list() -> Result<()> {
-    let Some(mut client) = connect_p2pd().await? else {
+    let Some(client) = connect_p2pd().await? else {
         return Ok(());
     };
 
@@ -367,7 +374,7 @@ async fn peers_find(peer_id_str: String, timeout: i64) -> Result<()> {
         .parse()
         .context("invalid peer ID (expected base58, e.g. 12D3KooW…)")?;
 
-    let Some(mut client) = connect_p2pd().await? else {
+    let Some(client) = connect_p2pd().await? else {
         return Ok(());
     };
 
@@ -732,3 +739,383 @@ fn print_response(resp: &[u8]) {
         }
     }
 }
+
+// ---------------------------------------------------------------------------
+// connected peers (`kwaainet peers`)
+// ---------------------------------------------------------------------------
+
+/// Which side opened a connection. p2pd doesn't report this, so it is
+/// inferred from identify: a connection whose remote address is one of the
+/// peer's own listen addresses was dialled by us; anything else (typically
+/// an ephemeral source port) was dialled by the peer.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Direction {
+    Outbound,
+    Inbound,
+    Unknown,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Outbound => "out",
+            Direction::Inbound => "in",
+            Direction::Unknown => "?",
+        }
+    }
+}
+
+/// Strip a trailing `/p2p/<id>` so connection and listen addrs compare equal.
+fn without_peer_id(m: &Multiaddr) -> Multiaddr {
+    let mut m = m.clone();
+    if matches!(m.iter().last(), Some(libp2p::multiaddr::Protocol::P2p(_))) {
+        m.pop();
+    }
+    m
+}
+
+fn infer_direction(conn: &Multiaddr, listen_addrs: Option<&[Multiaddr]>) -> Direction {
+    let Some(listen_addrs) = listen_addrs else {
+        return Direction::Unknown;
+    };
+    let conn = without_peer_id(conn);
+    if listen_addrs.iter().any(|l| without_peer_id(l) == conn) {
+        Direction::Outbound
+    } else {
+        Direction::Inbound
+    }
+}
+
+/// One connected peer, with every live connection to it and whatever the
+/// probes and the DHT could tell us.
+struct ConnectedPeer {
+    id_bytes: Vec<u8>,
+    id_str: String,
+    conns: Vec<Multiaddr>,
+    latency: Option<Duration>,
+    identity: Option<PeerIdentity>,
+    server: Option<BlockServerEntry>,
+    trust_score: f64,
+    ip_info: Option<PeerIpInfo>,
+}
+
+impl ConnectedPeer {
+    fn listen_addrs(&self) -> Option<Vec<Multiaddr>> {
+        self.identity.as_ref().map(|id| {
+            id.listen_addrs
+                .iter()
+                .filter_map(|a| Multiaddr::try_from(a.clone()).ok())
+                .collect()
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let listen = self.listen_addrs();
+        let conns: Vec<serde_json::Value> = self
+            .conns
+            .iter()
+            .map(|m| {
+                serde_json::json!({
+                    "addr": m.to_string(),
+                    "relayed": is_relayed(m),
+                    "direction": infer_direction(m, listen.as_deref()).as_str(),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "peer_id": self.id_str,
+            "connections": conns,
+            "latency_ms": self.latency.map(|d| d.as_secs_f64() * 1000.0),
+            "protocols": self.identity.as_ref().map(|id| &id.protocols),
+            "agent_version": self.identity.as_ref().and_then(|id| id.agent_version.as_ref()),
+            "server_info": self.server.as_ref().map(|s| serde_json::json!({
+                "public_name": s.public_name,
+                "start_block": s.start_block,
+                "end_block": s.end_block,
+                "throughput": s.throughput,
+            })),
+            "trust_score": self.trust_score,
+            "ip_info": self.ip_info,
+        })
+    }
+}
+
+/// Look up the block ranges peers advertise for the configured model, keyed
+/// by base58 peer ID. Uses its own control connection so it can run while
+/// the probes are in flight on another.
+async fn advertised_servers(daemon_addr: &str) -> HashMap<String, BlockServerEntry> {
+    let Ok(mut client) = P2PClient::connect(daemon_addr).await else {
+        return HashMap::new();
+    };
+    let Some(our_peer_id) = client
+        .identify()
+        .await
+        .ok()
+        .and_then(|h| hex::decode(h).ok())
+        .and_then(|b| PeerId::from_bytes(&b).ok())
+    else {
+        return HashMap::new();
+    };
+    let cfg = KwaaiNetConfig::load_or_create().unwrap_or_default();
+    let bootstrap_peers = if cfg.initial_peers.is_empty() {
+        NetworkConfig::with_petals_bootstrap().bootstrap_peers
+    } else {
+        cfg.initial_peers.clone()
+    };
+    discover_chain(
+        &mut client,
+        &our_peer_id,
+        &cfg.effective_dht_prefix(),
+        cfg.model_total_blocks() as usize,
+        &bootstrap_peers,
+    )
+    .await
+    .into_iter()
+    .map(|e| (e.peer_id.to_base58(), e))
+    .collect()
+}
+
+/// Current connections as `(peer id bytes, remote addr)`. Asks the node over
+/// its control socket first and falls back to p2pd directly when the node
+/// isn't answering (still starting up, or an older build).
+async fn live_connections(client: &mut P2PClient) -> Result<Vec<(Vec<u8>, Option<Multiaddr>)>> {
+    if let Ok(Some(serde_json::Value::Array(rows))) = control::call(ControlMethod::Peers).await {
+        return Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let id = row["peer_id"].as_str()?.parse::<PeerId>().ok()?;
+                let addr = row["addrs"]
+                    .as_array()
+                    .and_then(|a| a.first())
+                    .and_then(|a| a.as_str())
+                    .and_then(|a| a.parse().ok());
+                Some((id.to_bytes(), addr))
+            })
+            .collect());
+    }
+
+    let infos = client
+        .list_peers()
+        .await
+        .context("LIST_PEERS request to p2pd failed")?;
+    Ok(infos
+        .into_iter()
+        .map(|info| {
+            let addr = info
+                .addrs
+                .first()
+                .and_then(|a| Multiaddr::try_from(a.clone()).ok());
+            (info.id, addr)
+        })
+        .collect())
+}
+
+pub async fn connected_peers(args: PeersCmdArgs) -> Result<()> {
+    let Some(mut client) = connect_p2pd().await? else {
+        return Ok(());
+    };
+
+    let conns = live_connections(&mut client).await?;
+
+    // One entry per connection; fold them per peer.
+    let mut by_peer: BTreeMap<String, ConnectedPeer> = BTreeMap::new();
+    for (id_bytes, addr) in &conns {
+        let id_str = PeerId::from_bytes(id_bytes)
+            .map(|p| p.to_base58())
+            .unwrap_or_else(|_| format!("0x{}", hex::encode(id_bytes)));
+        let peer = by_peer
+            .entry(id_str.clone())
+            .or_insert_with(|| ConnectedPeer {
+                id_bytes: id_bytes.clone(),
+                id_str,
+                conns: Vec::new(),
+                latency: None,
+                identity: None,
+                server: None,
+                trust_score: 0.0,
+                ip_info: None,
+            });
+        peer.conns.extend(addr.iter().cloned());
+    }
+    let mut peers: Vec<ConnectedPeer> = by_peer.into_values().collect();
+
+    let timeout = Duration::from_secs(args.timeout);
+    let probes = async {
+        if args.no_probe {
+            return Vec::new();
+        }
+        let client = &client;
+        futures::future::join_all(peers.iter().map(|p| async move {
+            let (latency, identity) = tokio::join!(
+                client.ping_peer(&p.id_bytes, timeout),
+                client.identify_peer(&p.id_bytes, timeout),
+            );
+            (latency.ok(), identity.ok())
+        }))
+        .await
+    };
+    let servers = async {
+        if args.no_dht {
+            HashMap::new()
+        } else {
+            advertised_servers(client.daemon_addr()).await
+        }
+    };
+    let locations = async {
+        let Some(geoip) = crate::geoip::resolver() else {
+            return Vec::new();
+        };
+        futures::future::join_all(peers.iter().map(|p| geoip.lookup_addrs(&p.conns))).await
+    };
+    let (probed, mut servers, mut located) = tokio::join!(probes, servers, locations);
+
+    let store = load_store();
+    for (i, peer) in peers.iter_mut().enumerate() {
+        if let Some((latency, identity)) = probed.get(i).cloned() {
+            peer.latency = latency;
+            peer.identity = identity;
+        }
+        peer.server = servers.remove(&peer.id_str);
+        peer.trust_score = store.selection_score(&peer.id_str);
+        peer.ip_info = located.get_mut(i).and_then(Option::take);
+    }
+
+    // Block servers first, then fastest; unprobed peers sort last.
+    peers.sort_by(|a, b| {
+        b.server
+            .is_some()
+            .cmp(&a.server.is_some())
+            .then_with(|| {
+                a.latency
+                    .unwrap_or(Duration::MAX)
+                    .cmp(&b.latency.unwrap_or(Duration::MAX))
+            })
+            .then_with(|| a.id_str.cmp(&b.id_str))
+    });
+    if let Some(limit) = args.limit {
+        peers.truncate(limit);
+    }
+
+    if args.json {
+        let rows: Vec<serde_json::Value> = peers.iter().map(ConnectedPeer::to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    print_box_header("🛰  KwaaiNet — Connected Peers");
+
+    if peers.is_empty() {
+        println!("  (no active connections)");
+        print_separator();
+        return Ok(());
+    }
+
+    let bootstraps = bootstrap_peer_ids();
+    for peer in &peers {
+        let is_bootstrap = peer
+            .id_str
+            .parse::<PeerId>()
+            .is_ok_and(|pid| bootstraps.contains(&pid));
+        let label = if is_bootstrap {
+            "  \x1b[36m(bootstrap)\x1b[0m"
+        } else {
+            ""
+        };
+        let latency = peer
+            .latency
+            .map(|d| format!("{:.1} ms", d.as_secs_f64() * 1000.0))
+            .unwrap_or_else(|| "—".to_string());
+        println!("  {}{}", peer.id_str, label);
+        println!(
+            "      latency {}  ·  trust {:.3}{}",
+            latency,
+            peer.trust_score,
+            peer.identity
+                .as_ref()
+                .and_then(|id| id.agent_version.as_deref())
+                .map(|a| format!("  ·  agent {}", a))
+                .unwrap_or_default()
+        );
+
+        let listen = peer.listen_addrs();
+        for m in &peer.conns {
+            println!(
+                "      {} {:<3} {}",
+                fmt_kind(classify_addr(m)),
+                infer_direction(m, listen.as_deref()).as_str(),
+                m
+            );
+        }
+
+        if let Some(info) = &peer.ip_info {
+            println!("      location {}  ({})", info, info.ip);
+        }
+
+        if let Some(s) = &peer.server {
+            let tput = if s.throughput > 0.0 {
+                format!(", {:.1} tok/s", s.throughput)
+            } else {
+                String::new()
+            };
+            println!(
+                "      serves blocks {}–{} as \"{}\"{}",
+                s.start_block, s.end_block, s.public_name, tput
+            );
+        }
+
+        if let Some(id) = &peer.identity {
+            if !id.protocols.is_empty() {
+                println!("      protocols: {}", id.protocols.join(", "));
+            }
+        }
+        println!();
+    }
+
+    print_info(&format!(
+        "{} peer(s) over {} connection(s); {} advertising blocks",
+        peers.len(),
+        conns.len(),
+        peers.iter().filter(|p| p.server.is_some()).count()
+    ));
+    if args.no_probe {
+        print_info("Probes skipped (--no-probe): no latency, protocols or direction.");
+    } else {
+        print_info("Direction (out/in) is inferred from the peer's identify listen addresses.");
+    }
+    print_separator();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ma(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn direction_from_listen_addrs() {
+        let listen = [ma("/ip4/10.0.0.2/tcp/4001")];
+        assert_eq!(
+            infer_direction(&ma("/ip4/10.0.0.2/tcp/4001"), Some(&listen)),
+            Direction::Outbound
+        );
+        assert_eq!(
+            infer_direction(&ma("/ip4/10.0.0.2/tcp/53122"), Some(&listen)),
+            Direction::Inbound
+        );
+        assert_eq!(
+            infer_direction(&ma("/ip4/10.0.0.2/tcp/4001"), None),
+            Direction::Unknown
+        );
+    }
+
+    #[test]
+    fn direction_ignores_trailing_peer_id() {
+        let id = PeerId::random();
+        let listen = [ma("/ip4/10.0.0.2/tcp/4001")];
+        let conn = ma(&format!("/ip4/10.0.0.2/tcp/4001/p2p/{id}"));
+        assert_eq!(infer_direction(&conn, Some(&listen)), Direction::Outbound);
+    }
+}
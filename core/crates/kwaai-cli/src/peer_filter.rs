@@ -0,0 +1,505 @@
+//! Peer allow/deny lists (`peer_filter` in config.yaml).
+//!
+//! Entries are PeerIds, IP addresses or CIDR ranges matched against the
+//! addresses a peer is connected from. A deny match always wins; a
+//! non-empty allow list admits only the peers it matches.
+//!
+//! Enforced in three places:
+//!
+//!   - connections: the node sweeps its p2pd connections every
+//!     [`SWEEP_INTERVAL`] and disconnects rejected peers, and sweeps at once
+//!     after `kwaainet block` (control method `filter`)
+//!   - stream handlers (Hivemind DHT, shard transfer, inference mux) read
+//!     the stream's header through [`admit_stream`] and close streams from
+//!     rejected peers before reading a request
+//!   - unary handlers wrapped with [`guard`] (inference, proxies, goodbye,
+//!     dial-back, storage, hello) answer rejected callers with an error
+//!
+//! The lists are re-read when config.yaml changes, so edits and
+//! `kwaainet block` reach both the node and the shard server without a
+//! restart. Streams are matched against the address they arrived from.
+//! Unary requests carry no address, so CIDR entries are matched there
+//! against the addresses seen by the last sweep; a peer admitted only by an
+//! allow range is refused until a sweep has seen it.
+
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+use crate::config::{config_file, KwaaiNetConfig, PeerFilterConfig};
+
+/// How often connections are checked against the filter.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(15);
+
+/// config.yaml is checked for changes at most this often.
+const RELOAD_CHECK: Duration = Duration::from_secs(2);
+
+/// One allow or deny entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    Peer(PeerId),
+    Net(IpNet),
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(net) = s.parse::<IpNet>() {
+            return Ok(Rule::Net(net));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(Rule::Net(IpNet::from(ip)));
+        }
+        s.parse::<PeerId>()
+            .map(Rule::Peer)
+            .with_context(|| format!("{s:?} is not a PeerId, IP address or CIDR range"))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct RuleSet {
+    peers: HashSet<PeerId>,
+    nets: Vec<IpNet>,
+}
+
+impl RuleSet {
+    fn from_entries(entries: &[String]) -> Self {
+        let mut set = RuleSet::default();
+        for entry in entries {
+            match entry.parse() {
+                Ok(Rule::Peer(peer)) => {
+                    set.peers.insert(peer);
+                }
+                Ok(Rule::Net(net)) => set.nets.push(net),
+                Err(e) => warn!("peer_filter: ignoring entry: {e:#}"),
+            }
+        }
+        set
+    }
+
+    fn is_empty(&self) -> bool {
+        self.peers.is_empty() && self.nets.is_empty()
+    }
+
+    fn matches(&self, peer: &PeerId, addrs: &[IpAddr]) -> bool {
+        self.peers.contains(peer)
+            || addrs
+                .iter()
+                .any(|ip| self.nets.iter().any(|net| net.contains(ip)))
+    }
+}
+
+/// Compiled allow and deny lists.
+#[derive(Debug, Clone, Default)]
+pub struct PeerFilter {
+    allow: RuleSet,
+    deny: RuleSet,
+}
+
+impl PeerFilter {
+    /// Build from config; malformed entries are logged and skipped.
+    pub fn from_config(config: &PeerFilterConfig) -> Self {
+        Self {
+            allow: RuleSet::from_entries(&config.allow),
+            deny: RuleSet::from_entries(&config.deny),
+        }
+    }
+
+    /// Whether every peer is accepted.
+    pub fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn has_nets(&self) -> bool {
+        !self.allow.nets.is_empty() || !self.deny.nets.is_empty()
+    }
+
+    /// Why `peer`, connected from `addrs`, is refused; `None` if accepted.
+    pub fn rejects(&self, peer: &PeerId, addrs: &[IpAddr]) -> Option<&'static str> {
+        if self.deny.matches(peer, addrs) {
+            Some("peer is blocked")
+        } else if !self.allow.is_empty() && !self.allow.matches(peer, addrs) {
+            Some("peer is not on the allow list")
+        } else {
+            None
+        }
+    }
+}
+
+/// IP addresses in `addrs`, skipping relayed and non-IP ones.
+pub fn ips(addrs: &[Multiaddr]) -> Vec<IpAddr> {
+    addrs
+        .iter()
+        .filter(|a| !a.iter().any(|p| p == Protocol::P2pCircuit))
+        .filter_map(|a| {
+            a.iter().find_map(|p| match p {
+                Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+                Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Process-wide filter
+// ---------------------------------------------------------------------------
+
+struct Cached {
+    filter: Arc<PeerFilter>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+static CURRENT: OnceLock<Mutex<Cached>> = OnceLock::new();
+
+/// Addresses connected peers were last seen at, filled by [`sweep`].
+static SEEN: OnceLock<Mutex<HashMap<PeerId, Vec<IpAddr>>>> = OnceLock::new();
+
+fn config_modified() -> Option<SystemTime> {
+    std::fs::metadata(config_file())
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+fn load() -> PeerFilter {
+    match KwaaiNetConfig::load_or_create() {
+        Ok(config) => PeerFilter::from_config(&config.peer_filter),
+        Err(e) => {
+            warn!("peer_filter: could not read config, keeping no filter: {e:#}");
+            PeerFilter::default()
+        }
+    }
+}
+
+/// The filter from config.yaml, reloaded when the file changes.
+pub fn current() -> Arc<PeerFilter> {
+    let cell = CURRENT.get_or_init(|| {
+        Mutex::new(Cached {
+            modified: config_modified(),
+            filter: Arc::new(load()),
+            checked: Instant::now(),
+        })
+    });
+    let mut cached = cell.lock().unwrap();
+    // Tests set the filter directly (see `deny_for_test`).
+    if cached.checked.elapsed() >= RELOAD_CHECK && !cfg!(test) {
+        cached.checked = Instant::now();
+        let modified = config_modified();
+        if modified != cached.modified {
+            cached.modified = modified;
+            cached.filter = Arc::new(load());
+            info!("peer_filter: reloaded from config");
+        }
+    }
+    cached.filter.clone()
+}
+
+/// Force the next [`current`] call to re-read config.yaml.
+pub fn invalidate() {
+    if let Some(cell) = CURRENT.get() {
+        let mut cached = cell.lock().unwrap();
+        cached.modified = None;
+        cached.checked = Instant::now() - RELOAD_CHECK;
+    }
+}
+
+/// Add `entries` to the process-wide deny list. Tests share the filter, so
+/// they only ever add entries of their own.
+#[cfg(test)]
+pub fn deny_for_test(entries: &[&str]) {
+    let cell = CURRENT.get_or_init(|| {
+        Mutex::new(Cached {
+            modified: None,
+            filter: Arc::new(PeerFilter::default()),
+            checked: Instant::now(),
+        })
+    });
+    let mut cached = cell.lock().unwrap();
+    let mut filter = (*cached.filter).clone();
+    let added = RuleSet::from_entries(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>());
+    filter.deny.peers.extend(added.peers);
+    filter.deny.nets.extend(added.nets);
+    cached.filter = Arc::new(filter);
+}
+
+/// A connected TCP pair whose server end starts with the `StreamInfo`
+/// header p2pd sends for a stream from `peer` at `addr`.
+#[cfg(test)]
+pub async fn forwarded_stream(
+    peer: &PeerId,
+    addr: &Multiaddr,
+    proto: &str,
+) -> (TcpStream, TcpStream) {
+    use tokio::io::AsyncWriteExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let info = kwaai_p2p_daemon::p2pd::StreamInfo {
+        peer: peer.to_bytes(),
+        addr: addr.to_vec(),
+        proto: proto.to_string(),
+    };
+    client
+        .write_all(&kwaai_p2p_daemon::stream::encode_stream_info(&info))
+        .await
+        .unwrap();
+    (client, server)
+}
+
+fn seen_addrs(peer: &PeerId) -> Vec<IpAddr> {
+    SEEN.get()
+        .and_then(|seen| seen.lock().unwrap().get(peer).cloned())
+        .unwrap_or_default()
+}
+
+/// Why `peer` is refused, using the addresses the last sweep saw it at.
+pub fn rejects_peer(peer: &PeerId) -> Option<&'static str> {
+    let filter = current();
+    if filter.is_open() {
+        return None;
+    }
+    filter.rejects(peer, &seen_addrs(peer))
+}
+
+/// Why the remote of a p2pd-forwarded stream is refused, judged by the
+/// address the stream arrived from (or, for relayed streams, the addresses
+/// the last sweep saw). `None` if accepted.
+pub fn rejects_stream(info: &kwaai_p2p_daemon::p2pd::StreamInfo) -> Option<&'static str> {
+    let filter = current();
+    if filter.is_open() {
+        return None;
+    }
+    let Ok(peer) = PeerId::from_bytes(&info.peer) else {
+        return Some("stream from an unknown peer");
+    };
+    let remote: Vec<Multiaddr> = Multiaddr::try_from(info.addr.clone()).into_iter().collect();
+    let addrs = match ips(&remote) {
+        addrs if addrs.is_empty() => seen_addrs(&peer),
+        addrs => addrs,
+    };
+    filter.rejects(&peer, &addrs)
+}
+
+/// Read the `StreamInfo` header p2pd sends ahead of a forwarded stream and
+/// check its remote against the filter. Returns the header, or `None` when
+/// the stream should be closed unanswered.
+pub async fn admit_stream(
+    stream: &mut TcpStream,
+) -> Result<Option<kwaai_p2p_daemon::p2pd::StreamInfo>> {
+    let info = kwaai_p2p_daemon::stream::parse_stream_info(stream)
+        .await
+        .context("read p2pd StreamInfo")?;
+    if let Some(reason) = rejects_stream(&info) {
+        tracing::debug!(
+            "peer_filter: refused {} stream from {:?} ({reason})",
+            info.proto,
+            PeerId::from_bytes(&info.peer).ok()
+        );
+        return Ok(None);
+    }
+    Ok(Some(info))
+}
+
+/// Check connected peers against the filter, remember their addresses and,
+/// with `disconnect`, drop the rejected ones. Returns how many were dropped.
+pub async fn sweep(client: &kwaai_p2p_daemon::P2PClient, disconnect: bool) -> usize {
+    let filter = current();
+    if filter.is_open() {
+        return 0;
+    }
+    let peers = match client.list_peers().await {
+        Ok(peers) => peers,
+        Err(e) => {
+            tracing::debug!("peer_filter: could not list peers: {e}");
+            return 0;
+        }
+    };
+    let mut seen = HashMap::new();
+    let mut rejected = Vec::new();
+    for info in &peers {
+        let Ok(peer) = PeerId::from_bytes(&info.id) else {
+            continue;
+        };
+        let addrs: Vec<Multiaddr> = info
+            .addrs
+            .iter()
+            .filter_map(|a| Multiaddr::try_from(a.clone()).ok())
+            .collect();
+        let addrs = ips(&addrs);
+        if let Some(reason) = filter.rejects(&peer, &addrs) {
+            rejected.push((peer, reason));
+        }
+        if filter.has_nets() {
+            seen.insert(peer, addrs);
+        }
+    }
+    *SEEN.get_or_init(Default::default).lock().unwrap() = seen;
+
+    if !disconnect {
+        return 0;
+    }
+    let mut dropped = 0;
+    for (peer, reason) in rejected {
+        match client.disconnect_peer(&peer.to_bytes()).await {
+            Ok(()) => {
+                info!("peer_filter: disconnected {peer} ({reason})");
+                dropped += 1;
+            }
+            Err(e) => warn!("peer_filter: could not disconnect {peer}: {e}"),
+        }
+    }
+    dropped
+}
+
+/// Keep the address cache of a process that only serves unary handlers
+/// (the shard server) fresh; the node does its own sweeps.
+pub async fn run_address_refresh(client: kwaai_p2p_daemon::P2PClient) {
+    let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        tick.tick().await;
+        sweep(&client, false).await;
+    }
+}
+
+/// Boxed future returned by a [`guard`]ed handler.
+pub type GuardFuture =
+    std::pin::Pin<Box<dyn std::future::Future<Output = kwaai_p2p_daemon::Result<Vec<u8>>> + Send>>;
+
+/// Wrap a unary handler so callers the filter rejects get an error.
+pub fn guard<F, Fut>(handler: F) -> impl Fn(Vec<u8>) -> GuardFuture + Send + Sync + 'static
+where
+    F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = kwaai_p2p_daemon::Result<Vec<u8>>> + Send + 'static,
+{
+    move |data| {
+        let caller = kwaai_p2p_daemon::persistent::caller_peer()
+            .and_then(|bytes| PeerId::from_bytes(&bytes).ok());
+        if let Some(reason) = caller.as_ref().and_then(rejects_peer) {
+            tracing::debug!("peer_filter: refused unary call from {caller:?} ({reason})");
+            return Box::pin(std::future::ready(Err(kwaai_p2p_daemon::Error::Protocol(
+                reason.to_string(),
+            ))));
+        }
+        Box::pin(handler(data))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// kwaainet block / unblock
+// ---------------------------------------------------------------------------
+
+/// Add `target` to the deny list (or remove it with `block == false`) and
+/// apply the change to the running node.
+pub async fn block_command(target: &str, block: bool) -> Result<()> {
+    use crate::display::*;
+
+    let entry = match target.parse::<Rule>()? {
+        Rule::Peer(peer) => peer.to_base58(),
+        Rule::Net(net) => net.to_string(),
+    };
+    let mut config = KwaaiNetConfig::load_or_create()?;
+    let deny = &mut config.peer_filter.deny;
+    let listed = deny.iter().any(|e| e.trim() == entry);
+    match (block, listed) {
+        (true, true) => {
+            print_info(&format!("{entry} is already blocked."));
+            return Ok(());
+        }
+        (false, false) => {
+            print_warning(&format!("{entry} is not on the block list."));
+            return Ok(());
+        }
+        (true, false) => deny.push(entry.clone()),
+        (false, true) => deny.retain(|e| e.trim() != entry),
+    }
+    config.save()?;
+    if block {
+        print_success(&format!("Blocked {entry}"));
+    } else {
+        print_success(&format!("Unblocked {entry}"));
+    }
+
+    match crate::control::call(crate::control::ControlMethod::Filter).await? {
+        Some(result) => {
+            let dropped = result["disconnected"].as_u64().unwrap_or(0);
+            print_info(&format!(
+                "Applied to the running node ({dropped} peer(s) disconnected)."
+            ));
+        }
+        None => print_info("No node answering on the control socket; applies on next start."),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> PeerFilter {
+        PeerFilter::from_config(&PeerFilterConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn parses_peers_addresses_and_ranges() {
+        let peer = PeerId::random();
+        assert_eq!(peer.to_base58().parse::<Rule>().unwrap(), Rule::Peer(peer));
+        assert_eq!(
+            "10.0.0.0/8".parse::<Rule>().unwrap(),
+            Rule::Net("10.0.0.0/8".parse().unwrap())
+        );
+        assert_eq!(
+            "2001:db8::1".parse::<Rule>().unwrap(),
+            Rule::Net("2001:db8::1/128".parse().unwrap())
+        );
+        assert!("not-a-peer".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn deny_wins_and_allow_list_is_exclusive() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let lan: IpAddr = "192.168.1.7".parse().unwrap();
+        let wan: IpAddr = "203.0.113.9".parse().unwrap();
+
+        assert!(filter(&[], &[]).is_open());
+
+        let deny = filter(&[], &[&a.to_base58(), "203.0.113.0/24"]);
+        assert!(deny.rejects(&a, &[lan]).is_some());
+        assert!(deny.rejects(&b, &[wan]).is_some());
+        assert!(deny.rejects(&b, &[lan]).is_none());
+
+        let allow = filter(&["192.168.0.0/16"], &[&a.to_base58()]);
+        assert!(allow.rejects(&b, &[lan]).is_none());
+        assert!(allow.rejects(&b, &[wan]).is_some());
+        // Unknown addresses do not satisfy an allow range
+        assert!(allow.rejects(&b, &[]).is_some());
+        assert!(allow.rejects(&a, &[lan]).is_some());
+    }
+
+    #[test]
+    fn relayed_addresses_are_not_the_peer_ip() {
+        let direct: Multiaddr = "/ip4/198.51.100.4/tcp/8080".parse().unwrap();
+        let relayed: Multiaddr = "/ip4/203.0.113.1/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            ips(&[direct, relayed]),
+            vec!["198.51.100.4".parse::<IpAddr>().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Liveness and readiness endpoints for container orchestrators
+//! (`kwaainet start --foreground --no-daemon-files`, `--health-listen`).
+//!
+//! | path       | 200 when                                                     |
+//! |------------|--------------------------------------------------------------|
+//! | `/healthz` | the node is starting up, or its event loop beat within [`HUNG_AFTER`] |
+//! | `/readyz`  | the node is announced on the DHT, p2pd is up and it is within its contribution hours |
+//!
+//! Anything else answers 503. Both bodies are JSON with a `status` and, when
+//! not OK, a `reason`. The probes share the event loop heartbeat with
+//! `kwaainet status` and the service watchdog, so all three agree on when
+//! the node is hung.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::liveness::HUNG_AFTER;
+
+/// Port of the probe server when `--health-listen` is not given.
+pub const DEFAULT_HEALTH_PORT: u16 = 11436;
+
+/// All interfaces on [`DEFAULT_HEALTH_PORT`].
+pub fn default_listen() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, DEFAULT_HEALTH_PORT))
+}
+
+#[derive(Debug)]
+struct ProbeState {
+    /// Last event loop beat; `None` until the loop starts
+    last_beat: Option<Instant>,
+    /// Why the node is not ready, or `None` when it is
+    not_ready: Option<&'static str>,
+}
+
+/// Node state the probes answer from, updated by the node event loop.
+#[derive(Debug)]
+pub struct Probes {
+    state: Mutex<ProbeState>,
+}
+
+impl Default for Probes {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(ProbeState {
+                last_beat: None,
+                not_ready: Some("starting"),
+            }),
+        }
+    }
+}
+
+impl Probes {
+    /// Record that the event loop is running and whether the node is ready.
+    pub fn beat(&self, not_ready: Option<&'static str>) {
+        let mut state = self.state.lock().unwrap();
+        state.last_beat = Some(Instant::now());
+        state.not_ready = not_ready;
+    }
+
+    /// Mark the node not ready, e.g. while shutting down.
+    pub fn set_not_ready(&self, reason: &'static str) {
+        self.state.lock().unwrap().not_ready = Some(reason);
+    }
+
+    fn liveness_at(&self, now: Instant) -> (StatusCode, Value) {
+        match self.state.lock().unwrap().last_beat {
+            None => (StatusCode::OK, json!({ "status": "starting" })),
+            Some(beat) => {
+                let silent = now.saturating_duration_since(beat);
+                if silent < HUNG_AFTER {
+                    (StatusCode::OK, json!({ "status": "ok" }))
+                } else {
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        json!({
+                            "status": "stalled",
+                            "reason": format!("event loop silent for {}s", silent.as_secs()),
+                        }),
+                    )
+                }
+            }
+        }
+    }
+
+    fn readiness_at(&self, now: Instant) -> (StatusCode, Value) {
+        let (live, _) = self.liveness_at(now);
+        if live != StatusCode::OK {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "status": "not ready", "reason": "event loop stalled" }),
+            );
+        }
+        match self.state.lock().unwrap().not_ready {
+            None => (StatusCode::OK, json!({ "status": "ready" })),
+            Some(reason) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "status": "not ready", "reason": reason }),
+            ),
+        }
+    }
+}
+
+async fn healthz(State(probes): State<Arc<Probes>>) -> (StatusCode, Json<Value>) {
+    let (code, body) = probes.liveness_at(Instant::now());
+    (code, Json(body))
+}
+
+async fn readyz(State(probes): State<Arc<Probes>>) -> (StatusCode, Json<Value>) {
+    let (code, body) = probes.readiness_at(Instant::now());
+    (code, Json(body))
+}
+
+/// Bind `addr` and serve the probes in the background. A port that can't be
+/// bound is an error: an orchestrator relying on the probes would otherwise
+/// restart the node forever.
+pub async fn spawn(addr: SocketAddr, probes: Arc<Probes>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding health endpoints on {addr}"))?;
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(probes);
+    info!("Health endpoints on http://{addr}/healthz and /readyz");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("Health endpoint server stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn live_while_starting_ready_once_announced() {
+        let probes = Probes::default();
+        let now = Instant::now();
+        assert_eq!(probes.liveness_at(now).0, StatusCode::OK);
+        let (code, body) = probes.readiness_at(now);
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["reason"], "starting");
+
+        probes.beat(Some("announcing on the DHT"));
+        assert_eq!(
+            probes.readiness_at(Instant::now()).1["reason"],
+            "announcing on the DHT"
+        );
+        probes.beat(None);
+        assert_eq!(probes.readiness_at(Instant::now()).0, StatusCode::OK);
+    }
+
+    #[test]
+    fn silent_event_loop_fails_both_probes() {
+        let probes = Probes::default();
+        probes.beat(None);
+        let later = Instant::now() + HUNG_AFTER + Duration::from_secs(1);
+        let (code, body) = probes.liveness_at(later);
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "stalled");
+        assert_eq!(
+            probes.readiness_at(later).0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! This node's public address, as the rest of the network sees it.
+//!
+//! Nodes that set `public_ip` — typically home users with a forwarded port —
+//! announce `/ip4/<public_ip>/tcp/<port>` verbatim, so when the ISP hands out
+//! a new address the announcement keeps pointing at the dead one until
+//! someone edits the config. [`detect_public_ip`] catches that change.
+//!
+//! Nodes that set neither `public_ip` nor `announce_addr` learn it instead:
+//! [`learn_public_addr`] asks connected peers what address they see us
+//! connecting from (the `observedAddr` field of libp2p identify — the same
+//! signal AutoNAT relies on), adds the direct addresses the daemon reports
+//! for itself, and reconciles them into one [`ObservedAddr`]. p2pd dials
+//! from its listen port, so behind a port-preserving NAT or a forwarded
+//! port the observed port is the one peers can reach us at.
+
+use kwaai_p2p_daemon::P2PClient;
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use std::{collections::HashMap, fmt, net::Ipv4Addr, time::Duration};
+use tracing::debug;
+
+/// Peers asked per check.
+const MAX_OBSERVERS: usize = 6;
+
+/// Peers that must report the same address before it is believed. One peer
+/// could be lying or sit behind the same carrier NAT as us.
+const MIN_AGREEING_OBSERVERS: usize = 2;
+
+/// Plain-text "what is my IP" services, tried in order.
+const ECHO_SERVICES: &[&str] = &[
+    "https://api.ipify.org",
+    "https://ifconfig.me/ip",
+    "https://icanhazip.com",
+];
+
+/// Our address as reported by several observers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObservedAddr {
+    pub ip: Ipv4Addr,
+    /// TCP port, when the observers that agree on `ip` also agree on it
+    pub port: Option<u16>,
+    /// Observers that reported `ip`
+    pub observers: usize,
+}
+
+impl ObservedAddr {
+    /// Announce multiaddr, with `fallback_port` when no port was agreed on
+    pub fn multiaddr(&self, fallback_port: u16) -> String {
+        format!(
+            "/ip4/{}/tcp/{}",
+            self.ip,
+            self.port.unwrap_or(fallback_port)
+        )
+    }
+}
+
+impl fmt::Display for ObservedAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.ip, port)?,
+            None => write!(f, "{}", self.ip)?,
+        }
+        write!(f, " ({} observers)", self.observers)
+    }
+}
+
+/// Best current guess at this node's public IPv4 address.
+pub async fn detect_public_ip(
+    client: &mut P2PClient,
+    http: &reqwest::Client,
+    timeout: Duration,
+) -> Option<Ipv4Addr> {
+    if let Some(addr) = learn_public_addr(client, timeout).await {
+        debug!("Public IP {addr} confirmed by peer observations");
+        return Some(addr.ip);
+    }
+    let ip = from_echo_service(http).await;
+    if let Some(ip) = ip {
+        debug!("Public IP {ip} reported by echo service");
+    }
+    ip
+}
+
+/// Our public address as connected peers and the daemon see it, if enough
+/// of them agree (see [`reconcile`]).
+pub async fn learn_public_addr(client: &mut P2PClient, timeout: Duration) -> Option<ObservedAddr> {
+    let peers = client.list_peers().await.ok()?;
+    // The daemon's own direct addresses count as one more observer: go-libp2p
+    // only lists an observed address there once identify confirmed it.
+    let from_daemon = client
+        .identify_with_addrs()
+        .await
+        .map(|(_, addrs)| addrs)
+        .unwrap_or_default();
+    let client = &*client;
+    let probes = peers
+        .iter()
+        .take(MAX_OBSERVERS)
+        .map(|p| client.identify_peer(&p.id, timeout));
+    let mut observed: Vec<Vec<(Ipv4Addr, Option<u16>)>> = futures::future::join_all(probes)
+        .await
+        .into_iter()
+        .filter_map(|r| r.ok()?.observed_addr)
+        .filter_map(|bytes| Multiaddr::try_from(bytes).ok())
+        .filter_map(|ma| public_ipv4(&ma))
+        .map(|addr| vec![addr])
+        .collect();
+    observed.push(
+        from_daemon
+            .into_iter()
+            .filter_map(|bytes| Multiaddr::try_from(bytes).ok())
+            .filter_map(|ma| public_ipv4(&ma))
+            .collect(),
+    );
+    reconcile(&observed, MIN_AGREEING_OBSERVERS)
+}
+
+async fn from_echo_service(http: &reqwest::Client) -> Option<Ipv4Addr> {
+    for url in ECHO_SERVICES {
+        let Ok(resp) = http.get(*url).send().await else {
+            continue;
+        };
+        let Ok(body) = resp.text().await else {
+            continue;
+        };
+        if let Some(ip) = body
+            .trim()
+            .parse::<Ipv4Addr>()
+            .ok()
+            .filter(|ip| crate::node::is_globally_routable_v4(*ip))
+        {
+            return Some(ip);
+        }
+    }
+    None
+}
+
+/// The globally routable IPv4 and TCP port of a direct (non-relayed)
+/// multiaddr.
+fn public_ipv4(ma: &Multiaddr) -> Option<(Ipv4Addr, Option<u16>)> {
+    if ma.iter().any(|p| matches!(p, Protocol::P2pCircuit)) {
+        return None;
+    }
+    let ip = ma.iter().find_map(|p| match p {
+        Protocol::Ip4(ip) if crate::node::is_globally_routable_v4(ip) => Some(ip),
+        _ => None,
+    })?;
+    let port = ma.iter().find_map(|p| match p {
+        Protocol::Tcp(port) => Some(port),
+        _ => None,
+    });
+    Some((ip, port))
+}
+
+/// Reconcile observations, one list per observer: the IP reported by the
+/// most observers, if at least `min` agree on it, with the port at least
+/// `min` of those report for it.
+fn reconcile(observed: &[Vec<(Ipv4Addr, Option<u16>)>], min: usize) -> Option<ObservedAddr> {
+    // Per IP: observers reporting it, and observers reporting each port.
+    let mut votes: HashMap<Ipv4Addr, (usize, HashMap<u16, usize>)> = HashMap::new();
+    for observer in observed {
+        let mut addrs = observer.clone();
+        addrs.sort();
+        addrs.dedup();
+        for (i, (ip, port)) in addrs.iter().enumerate() {
+            let (n, ports) = votes.entry(*ip).or_default();
+            if i == 0 || addrs[i - 1].0 != *ip {
+                *n += 1;
+            }
+            if let Some(port) = port {
+                *ports.entry(*port).or_default() += 1;
+            }
+        }
+    }
+    let (ip, (observers, ports)) = votes
+        .into_iter()
+        .filter(|(_, (n, _))| *n >= min)
+        .max_by_key(|(ip, (n, _))| (*n, *ip))?;
+    let port = ports
+        .into_iter()
+        .filter(|(_, n)| *n >= min)
+        .max_by_key(|(port, n)| (*n, *port))
+        .map(|(port, _)| port);
+    Some(ObservedAddr {
+        ip,
+        port,
+        observers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(ip: &str, port: u16) -> Vec<(Ipv4Addr, Option<u16>)> {
+        vec![(ip.parse().unwrap(), Some(port))]
+    }
+
+    #[test]
+    fn reconcile_needs_agreeing_observers() {
+        let a = "203.0.113.7";
+        let b = "198.51.100.9";
+        assert_eq!(reconcile(&[obs(a, 8080)], 2), None);
+        assert_eq!(reconcile(&[obs(a, 8080), obs(b, 8080)], 2), None);
+        let found = reconcile(&[obs(a, 8080), obs(b, 8080), obs(a, 8080)], 2).unwrap();
+        assert_eq!(found.ip, a.parse::<Ipv4Addr>().unwrap());
+        assert_eq!(found.observers, 2);
+        assert_eq!(found.multiaddr(9000), "/ip4/203.0.113.7/tcp/8080");
+    }
+
+    #[test]
+    fn reconcile_keeps_ip_when_ports_disagree() {
+        // A symmetric NAT maps every connection to a fresh port: the IP is
+        // still learned, the port falls back to the configured one.
+        let a = "203.0.113.7";
+        let found = reconcile(&[obs(a, 40001), obs(a, 40002), obs(a, 40003)], 2).unwrap();
+        assert_eq!((found.observers, found.port), (3, None));
+        assert_eq!(found.multiaddr(8080), "/ip4/203.0.113.7/tcp/8080");
+
+        // The daemon listing two ports for one IP is still one observer.
+        let daemon = vec![
+            (a.parse().unwrap(), Some(8080)),
+            (a.parse().unwrap(), Some(8081)),
+        ];
+        let found = reconcile(&[daemon, obs(a, 8080)], 2).unwrap();
+        assert_eq!((found.observers, found.port), (2, Some(8080)));
+    }
+
+    #[test]
+    fn public_ipv4_ignores_private_and_relayed_addrs() {
+        let direct: Multiaddr = "/ip4/203.0.113.7/tcp/51234".parse().unwrap();
+        let lan: Multiaddr = "/ip4/192.168.1.20/tcp/8080".parse().unwrap();
+        let cgnat: Multiaddr = "/ip4/100.64.3.2/tcp/8080".parse().unwrap();
+        let relayed: Multiaddr =
+            "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWRBy97UB99e3J6hiPesre1MZeuNQvfan4gBziswrRJsNK/p2p-circuit"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            public_ipv4(&direct),
+            Some(("203.0.113.7".parse().unwrap(), Some(51234)))
+        );
+        assert_eq!(public_ipv4(&lan), None);
+        assert_eq!(public_ipv4(&cgnat), None);
+        assert_eq!(public_ipv4(&relayed), None);
+    }
+}
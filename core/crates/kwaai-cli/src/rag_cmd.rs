@@ -1454,7 +1454,7 @@ async fn cmd_chat(
             #[cfg(unix)]
             let addr = format!("/unix/{sock}");
             #[cfg(not(unix))]
-            let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+            let addr = kwaai_p2p_daemon::default_daemon_addr();
             let p2p = std::sync::Arc::new(
                 P2PClient::connect(&addr)
                     .await
@@ -2167,7 +2167,7 @@ async fn cmd_rebuild(
                 #[cfg(unix)]
                 let addr = format!("/unix/{sock}");
                 #[cfg(not(unix))]
-                let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+                let addr = kwaai_p2p_daemon::default_daemon_addr();
                 let p2p = Arc::new(
                     P2PClient::connect(&addr)
                         .await
@@ -2318,7 +2318,7 @@ async fn cmd_reembed_vectors(embed_url: Option<String>, kb: String) -> Result<()
                 #[cfg(unix)]
                 let addr = format!("/unix/{sock}");
                 #[cfg(not(unix))]
-                let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+                let addr = kwaai_p2p_daemon::default_daemon_addr();
                 let p2p = std::sync::Arc::new(
                     P2PClient::connect(&addr)
                         .await
@@ -2888,7 +2888,7 @@ async fn cmd_graph(action: GraphAction, kb: String) -> Result<()> {
                     #[cfg(unix)]
                     let addr = format!("/unix/{sock}");
                     #[cfg(not(unix))]
-                    let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+                    let addr = kwaai_p2p_daemon::default_daemon_addr();
                     let p2p = Arc::new(
                         P2PClient::connect(&addr)
                             .await
@@ -5232,7 +5232,7 @@ async fn cmd_coref(
         #[cfg(unix)]
         let addr = format!("/unix/{sock}");
         #[cfg(not(unix))]
-        let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+        let addr = kwaai_p2p_daemon::default_daemon_addr();
         let p2p = std::sync::Arc::new(
             P2PClient::connect(&addr)
                 .await
@@ -5990,7 +5990,7 @@ async fn cmd_extract_relations(
         #[cfg(unix)]
         let addr = format!("/unix/{sock}");
         #[cfg(not(unix))]
-        let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+        let addr = kwaai_p2p_daemon::default_daemon_addr();
         let p2p = std::sync::Arc::new(
             P2PClient::connect(&addr)
                 .await
@@ -7294,7 +7294,7 @@ async fn cmd_dream(action: DreamAction, kb: String) -> Result<()> {
                     #[cfg(unix)]
                     let addr = format!("/unix/{sock}");
                     #[cfg(not(unix))]
-                    let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+                    let addr = kwaai_p2p_daemon::default_daemon_addr();
                     let p2p = std::sync::Arc::new(
                         P2PClient::connect(&addr)
                             .await
@@ -7827,7 +7827,7 @@ async fn cmd_eval(
             #[cfg(unix)]
             let addr = format!("/unix/{sock}");
             #[cfg(not(unix))]
-            let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+            let addr = kwaai_p2p_daemon::default_daemon_addr();
             let p2p = std::sync::Arc::new(
                 P2PClient::connect(&addr)
                     .await
@@ -8780,7 +8780,7 @@ async fn cmd_enrich_entities(
             #[cfg(unix)]
             let addr = format!("/unix/{sock}");
             #[cfg(not(unix))]
-            let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+            let addr = kwaai_p2p_daemon::default_daemon_addr();
             let p2p = std::sync::Arc::new(
                 P2PClient::connect(&addr)
                     .await
@@ -9858,7 +9858,7 @@ async fn cmd_graph_timeline(action: TimelineAction, kb: &str) -> Result<()> {
                     #[cfg(unix)]
                     let addr = format!("/unix/{sock}");
                     #[cfg(not(unix))]
-                    let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+                    let addr = kwaai_p2p_daemon::default_daemon_addr();
                     let p2p = std::sync::Arc::new(
                         P2PClient::connect(&addr)
                             .await
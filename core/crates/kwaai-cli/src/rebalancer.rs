@@ -12,15 +12,46 @@
 //! 2. If `min(coverage[our_start..our_end]) < min_redundancy` → stay put.
 //!    (we are sole or insufficient coverage of our own range; moving would
 //!    create a gap.)
-//! 3. Find the first block `i` where `coverage[i] == 0` (uncovered gap).
-//!    If none → network is fully covered, no move needed.
-//! 4. Return `Some((i, min(i + target_blocks, total_blocks)))`.
+//! 3. If some block has `coverage[i] == 0` (uncovered gap), move to
+//!    `[i, min(i + target_blocks, total_blocks))` for the first such block.
+//! 4. Otherwise find the `target_blocks` window whose weakest block has the
+//!    lowest coverage. Move there only if our range would still be better
+//!    covered than that window after the move — i.e. our range's minimum
+//!    other-peer coverage exceeds the window's by at least
+//!    [`MIN_COVERAGE_ADVANTAGE`]. The margin keeps two nodes from swapping
+//!    places on every check.
 
 use libp2p::PeerId;
 
 use crate::shard_cmd::BlockServerEntry;
 
-/// Decide whether this node should move its blocks to fill a gap.
+/// How much better covered (in other peers per block) our range must be than
+/// the weakest window before we move into it. With a margin of 2, the old
+/// range is still strictly better covered than the new one after the move.
+pub const MIN_COVERAGE_ADVANTAGE: usize = 2;
+
+/// Number of *other* peers serving each block.
+pub fn coverage(
+    chain: &[BlockServerEntry],
+    our_peer_id: &PeerId,
+    total_blocks: usize,
+) -> Vec<usize> {
+    let mut coverage = vec![0usize; total_blocks];
+    for entry in chain {
+        if &entry.peer_id == our_peer_id {
+            continue;
+        }
+        let s = entry.start_block.min(total_blocks);
+        let e = entry.end_block.min(total_blocks);
+        for c in &mut coverage[s..e] {
+            *c += 1;
+        }
+    }
+    coverage
+}
+
+/// Decide whether this node should move its blocks to fill a gap or
+/// reinforce an under-covered span.
 ///
 /// Returns `Some((new_start, new_end))` if a rebalance is warranted,
 /// or `None` if the node should keep serving its current range.
@@ -38,17 +69,7 @@ pub fn check_rebalance(
     }
 
     // Build per-block coverage count, excluding ourselves.
-    let mut coverage = vec![0usize; total_blocks];
-    for entry in chain {
-        if &entry.peer_id == our_peer_id {
-            continue;
-        }
-        let s = entry.start_block.min(total_blocks);
-        let e = entry.end_block.min(total_blocks);
-        for c in &mut coverage[s..e] {
-            *c += 1;
-        }
-    }
+    let coverage = coverage(chain, our_peer_id, total_blocks);
 
     // Step 2 — stay put if our range is not sufficiently covered by others.
     let our_min_coverage = coverage[our_start.min(total_blocks)..our_end.min(total_blocks)]
@@ -60,12 +81,35 @@ pub fn check_rebalance(
         return None;
     }
 
-    // Step 3 — find the first uncovered block.
-    let gap_start = coverage.iter().position(|&c| c == 0)?;
+    // Step 3 — move to the first uncovered block.
+    if let Some(gap_start) = coverage.iter().position(|&c| c == 0) {
+        let gap_end = (gap_start + target_blocks).min(total_blocks);
+        return Some((gap_start, gap_end));
+    }
 
-    // Step 4 — propose a new range starting at the gap.
-    let gap_end = (gap_start + target_blocks).min(total_blocks);
-    Some((gap_start, gap_end))
+    // Step 4 — reinforce the weakest window if it is clearly weaker than ours.
+    let target = target_blocks.clamp(1, total_blocks);
+    let (start, window_min) = weakest_window(&coverage, target);
+    if start == our_start || our_min_coverage < window_min + MIN_COVERAGE_ADVANTAGE {
+        return None;
+    }
+    Some((start, start + target))
+}
+
+/// The `target`-block window whose least-covered block has the lowest
+/// coverage; ties go to the lower total coverage, then the lower start.
+/// Returns `(start, min_coverage)`.
+fn weakest_window(coverage: &[usize], target: usize) -> (usize, usize) {
+    let n_windows = coverage.len().saturating_sub(target) + 1;
+    (0..n_windows)
+        .map(|i| {
+            let w = &coverage[i..i + target];
+            let min = w.iter().copied().min().unwrap_or(0);
+            (min, w.iter().sum::<usize>(), i)
+        })
+        .min()
+        .map(|(min, _, i)| (i, min))
+        .unwrap_or((0, 0))
 }
 
 /// Choose the best block range for a new or rebalancing node to serve.
@@ -92,17 +136,7 @@ pub fn pick_gap_from_chain(
     let target = target_blocks.min(total_blocks);
 
     // Count per-block coverage excluding ourselves.
-    let mut coverage = vec![0usize; total_blocks];
-    for e in chain {
-        if &e.peer_id == our_peer_id {
-            continue;
-        }
-        let s = e.start_block.min(total_blocks);
-        let end = e.end_block.min(total_blocks);
-        for c in &mut coverage[s..end] {
-            *c += 1;
-        }
-    }
+    let coverage = coverage(chain, our_peer_id, total_blocks);
 
     let min_cov = coverage.iter().copied().min().unwrap_or(0);
 
@@ -147,6 +181,9 @@ mod tests {
             public_name: format!("node-{}", start),
             trust_score: None,
             throughput: 0.0,
+            adapters: vec![],
+            dtypes: vec![],
+            attestation: None,
         }
     }
 
@@ -225,6 +262,46 @@ mod tests {
         assert_eq!(result, Some((8, 16)), "Should pick the lowest gap first");
     }
 
+    /// No gaps, but one span has a single server while ours has three others.
+    #[test]
+    fn rebalance_reinforces_under_covered_span() {
+        let our_peer = fake_peer(1);
+        let (b, c, d) = (fake_peer(2), fake_peer(3), fake_peer(4));
+        // Coverage by others: [0,8)=3, [8,16)=1, [16,32)=2.
+        let chain = vec![
+            make_entry(our_peer, 0, 8),
+            make_entry(b, 0, 32),
+            make_entry(c, 0, 8),
+            make_entry(c, 16, 32),
+            make_entry(d, 0, 8),
+            make_entry(d, 16, 32),
+        ];
+        let result = check_rebalance(&chain, &our_peer, 0, 8, 32, 8, 1);
+        assert_eq!(result, Some((8, 16)), "Should reinforce the thin span");
+    }
+
+    /// A one-peer difference is not worth a move — it would only swap places.
+    #[test]
+    fn no_rebalance_for_marginal_coverage_difference() {
+        let our_peer = fake_peer(1);
+        let (b, c) = (fake_peer(2), fake_peer(3));
+        // Coverage by others: [0,8)=2, [8,32)=1.
+        let chain = vec![
+            make_entry(our_peer, 0, 8),
+            make_entry(b, 0, 32),
+            make_entry(c, 0, 8),
+        ];
+        assert_eq!(check_rebalance(&chain, &our_peer, 0, 8, 32, 8, 1), None);
+    }
+
+    #[test]
+    fn weakest_window_prefers_lowest_minimum() {
+        // [1,3) and [2,4) both bottom out at 0; [1,3) carries less in total.
+        let cov = [1, 1, 0, 5, 5, 5];
+        assert_eq!(weakest_window(&cov, 2), (1, 0));
+        assert_eq!(weakest_window(&cov, 6), (0, 0));
+    }
+
     // ── pick_gap_from_chain tests ───────────────────────────────────────────
 
     /// Empty chain — first gap is at block 0.
@@ -1,10 +1,11 @@
 //! `kwaainet reputation` — display and manage the local peer trust store.
+//! `kwaainet peers` — rank known peers in the order peer selection uses them.
 
 use anyhow::Result;
 
-use crate::cli::{ReputationAction, ReputationArgs};
+use crate::cli::{PeersCmdArgs, ReputationAction, ReputationArgs};
 use crate::display::*;
-use crate::reputation::{ReputationStore, TrustTier};
+use crate::reputation::{load_store, TrustTier};
 
 pub async fn run(args: ReputationArgs) -> Result<()> {
     match args.action {
@@ -24,7 +25,7 @@ const TABLE_SEP: &str =
     "  ───────────────────────────────────────────────────────────────────────────";
 
 fn list() -> Result<()> {
-    let store = ReputationStore::load();
+    let store = load_store();
     let scored = store.all_scored();
 
     print_box_header("Peer Reputation — Local Trust View");
@@ -66,7 +67,7 @@ fn list() -> Result<()> {
 // ---------------------------------------------------------------------------
 
 fn show(peer_id: &str) -> Result<()> {
-    let store = ReputationStore::load();
+    let store = load_store();
 
     // Accept prefix match.
     let matched = store
@@ -115,7 +116,7 @@ fn show(peer_id: &str) -> Result<()> {
 // ---------------------------------------------------------------------------
 
 fn reset_peer(peer_id: &str) -> Result<()> {
-    let mut store = ReputationStore::load();
+    let mut store = load_store();
 
     // Accept prefix match.
     let full_id = store
@@ -134,6 +135,87 @@ fn reset_peer(peer_id: &str) -> Result<()> {
     Ok(())
 }
 
+// ---------------------------------------------------------------------------
+// peers
+// ---------------------------------------------------------------------------
+
+// Column widths (chars):  #=3  NAME=18  TIER=10  RANK=6  OK=5  LATENCY=9  TPUT=5  PEER_ID=13
+const PEERS_SEP: &str =
+    "  ─────────────────────────────────────────────────────────────────────────────────────";
+
+pub fn peers(args: PeersCmdArgs) -> Result<()> {
+    let store = load_store();
+    let mut ranked = store.all_scored();
+    ranked.sort_by(|a, b| b.1.selection_score().total_cmp(&a.1.selection_score()));
+    if let Some(limit) = args.limit {
+        ranked.truncate(limit);
+    }
+
+    if args.json {
+        let rows: Vec<serde_json::Value> = ranked
+            .iter()
+            .map(|(peer_id, score)| {
+                let name = store.peer_record(peer_id).map(|r| r.public_name.as_str());
+                serde_json::json!({
+                    "peer_id": peer_id,
+                    "public_name": name,
+                    "tier": score.tier.as_str(),
+                    "selection_score": score.selection_score(),
+                    "score": score.score,
+                    "samples": score.sample_count,
+                    "success_rate": score.availability,
+                    "avg_latency_ms": score.avg_latency_ms,
+                    "throughput_ratio": score.throughput_ratio,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    print_box_header("Peers — Ranked by Local Reputation");
+
+    if ranked.is_empty() {
+        print_info("No peer observations recorded yet.");
+        print_info("Run `kwaainet shard run` to start collecting data.");
+        return Ok(());
+    }
+
+    println!(
+        "  {:>3}  {:<18}  {:<10}  {:<6}  {:<5}  {:<9}  {:<5}  PEER ID",
+        "#", "PEER NAME", "TIER", "RANK", "OK", "LATENCY", "TPUT"
+    );
+    println!("{PEERS_SEP}");
+
+    for (i, (peer_id, score)) in ranked.iter().enumerate() {
+        let name = store
+            .peer_record(peer_id)
+            .map(|r| r.public_name.as_str())
+            .unwrap_or("unknown");
+        let tput = score
+            .throughput_ratio
+            .map(|r| format!("{:.0}%", r * 100.0))
+            .unwrap_or_else(|| "—".to_string());
+
+        println!(
+            "  {:>3}  {:<18}  {:<10}  {:<6.3}  {:<5}  {:<9}  {:<5}  {}",
+            i + 1,
+            truncate(name, 18),
+            tier_badge(score.tier),
+            score.selection_score(),
+            format!("{:.0}%", score.availability * 100.0),
+            format_latency(score.avg_latency_ms, score.sample_count),
+            tput,
+            truncate(peer_id, 13),
+        );
+    }
+
+    println!("{PEERS_SEP}");
+    print_info("RANK blends the trust score with a neutral prior until a peer has 5 samples.");
+    print_info("OK = call success rate · TPUT = measured / advertised throughput");
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // Helpers
 // ---------------------------------------------------------------------------
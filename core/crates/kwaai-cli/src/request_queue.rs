@@ -0,0 +1,127 @@
+//! Bounded request queue for the OpenAI API server and the shard server.
+//!
+//! Both take requests faster than they can run them. Each request holds a
+//! [`QueueSlot`] while it waits or runs, and once `depth` slots are taken
+//! new requests are turned away instead of piling up: `kwaainet serve` answers
+//! 429 with `Retry-After`, and the shard server's inference handler answers
+//! `ResponseStatus::Busy` so coordinators pick another peer.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Error text of requests turned away by a full queue. Coordinators match
+/// on it to move on without blacklisting the peer.
+pub const BUSY: &str = "server busy — request queue full, retry shortly";
+
+/// Shared queue state; clones count against the same depth.
+#[derive(Clone)]
+pub struct RequestQueue(Arc<QueueState>);
+
+struct QueueState {
+    depth: AtomicUsize,
+    queued: AtomicUsize,
+    /// Moving average of how long a slot is held, in milliseconds.
+    avg_hold_ms: AtomicU64,
+}
+
+/// Held from admission until the request is answered; frees its place on drop.
+pub struct QueueSlot {
+    state: Arc<QueueState>,
+    since: Instant,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.state.queued.fetch_sub(1, Ordering::SeqCst);
+        let held = self.since.elapsed().as_millis() as u64;
+        let _ = self
+            .state
+            .avg_hold_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                Some(if avg == 0 { held } else { (avg * 7 + held) / 8 })
+            });
+    }
+}
+
+impl RequestQueue {
+    /// A queue admitting up to `depth` requests at once (at least one).
+    pub fn new(depth: usize) -> Self {
+        Self(Arc::new(QueueState {
+            depth: AtomicUsize::new(depth.max(1)),
+            queued: AtomicUsize::new(0),
+            avg_hold_ms: AtomicU64::new(0),
+        }))
+    }
+
+    /// Take a slot, or `None` while the queue is full.
+    pub fn try_enter(&self) -> Option<QueueSlot> {
+        let depth = self.depth();
+        self.0
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < depth).then_some(n + 1)
+            })
+            .ok()?;
+        Some(self.slot())
+    }
+
+    /// Take a slot even past the depth, for work that must not be refused.
+    pub fn enter(&self) -> QueueSlot {
+        self.0.queued.fetch_add(1, Ordering::SeqCst);
+        self.slot()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.depth.load(Ordering::SeqCst)
+    }
+
+    /// Change the depth; requests already admitted keep their slots.
+    pub fn set_depth(&self, depth: usize) {
+        self.0.depth.store(depth.max(1), Ordering::SeqCst);
+    }
+
+    /// Requests waiting or running.
+    pub fn len(&self) -> usize {
+        self.0.queued.load(Ordering::SeqCst)
+    }
+
+    /// How long a turned-away client should wait: about one request's
+    /// service time, and at least a second.
+    pub fn retry_after(&self) -> Duration {
+        let avg = self.0.avg_hold_ms.load(Ordering::Relaxed);
+        Duration::from_secs(avg.div_ceil(1000).max(1))
+    }
+
+    fn slot(&self) -> QueueSlot {
+        QueueSlot {
+            state: self.0.clone(),
+            since: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_past_depth_until_a_slot_frees() {
+        let queue = RequestQueue::new(2);
+        let a = queue.try_enter().unwrap();
+        let _b = queue.try_enter().unwrap();
+        assert!(queue.try_enter().is_none());
+
+        // Forced entries go past the depth and still count.
+        let forced = queue.enter();
+        assert_eq!(queue.len(), 3);
+        drop(forced);
+        drop(a);
+        assert_eq!(queue.len(), 1);
+        assert!(queue.try_enter().is_some());
+
+        queue.set_depth(1);
+        assert!(queue.try_enter().is_none());
+        assert_eq!(queue.retry_after(), Duration::from_secs(1));
+    }
+}
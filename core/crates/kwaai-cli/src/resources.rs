@@ -0,0 +1,239 @@
+//! Resource governor for the shard server.
+//!
+//! Keeps background contribution from getting in the owner's way:
+//!
+//! - `resources.max_threads` caps the CPU threads used for inference.
+//! - `resources.gpu_utilization` duty-cycles the GPU, resting between
+//!   forward passes so its average load stays at the given percentage.
+//! - `resources.pause_on_battery` and `resources.pause_above_cpu_percent`
+//!   pause contribution while the host runs on battery or is busy with
+//!   other work. A paused server answers new sessions `Busy`, so
+//!   coordinators route around it; sessions already running here finish.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use candle_core::Device;
+use kwaai_inference::{limit_cpu_threads, DutyCycle};
+use sysinfo::System;
+use tracing::{debug, info};
+
+use crate::config::ResourcesConfig;
+
+/// Error text of new sessions turned away while contribution is paused.
+pub const PAUSED: &str = "contribution paused — host on battery or busy";
+
+/// How often the host is checked for battery power and interactive load.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Load must drop this many points below the threshold before resuming,
+/// so a host hovering around it does not flap.
+const RESUME_MARGIN: f32 = 10.0;
+
+static GOVERNOR: OnceLock<Arc<Governor>> = OnceLock::new();
+
+/// Shared governor state consulted by the inference handler.
+#[derive(Default)]
+pub struct Governor {
+    duty: Option<DutyCycle>,
+    paused: AtomicBool,
+}
+
+impl Governor {
+    /// Whether new sessions should be turned away.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the GPU duty cycle allows the next forward pass.
+    pub async fn wait_turn(&self) {
+        if let Some(duty) = &self.duty {
+            duty.wait_turn().await;
+        }
+    }
+
+    /// Record a forward pass that kept the device busy for `busy`.
+    pub fn record_busy(&self, busy: Duration) {
+        if let Some(duty) = &self.duty {
+            duty.record_busy(busy);
+        }
+    }
+}
+
+/// Apply `config` for a shard server computing on `device` and start the
+/// host monitor when pausing is enabled. Called once, before the model loads.
+pub fn init(config: &ResourcesConfig, device: &Device) -> Arc<Governor> {
+    GOVERNOR
+        .get_or_init(|| {
+            if let Some(max) = config.max_threads {
+                limit_cpu_threads(max);
+            }
+            let duty = match config.gpu_utilization {
+                Some(percent) if !device.is_cpu() && percent < 100 => {
+                    info!("Capping GPU utilization at {}%", percent);
+                    Some(DutyCycle::new(f64::from(percent) / 100.0))
+                }
+                _ => None,
+            };
+            let governor = Arc::new(Governor {
+                duty,
+                paused: AtomicBool::new(false),
+            });
+            if config.pause_on_battery || config.pause_above_cpu_percent.is_some() {
+                tokio::spawn(monitor(governor.clone(), config.clone()));
+            }
+            governor
+        })
+        .clone()
+}
+
+/// The process-wide governor; unrestricted when [`init`] was not called.
+pub fn governor() -> Arc<Governor> {
+    GOVERNOR.get_or_init(Default::default).clone()
+}
+
+async fn monitor(governor: Arc<Governor>, config: ResourcesConfig) {
+    let mut sys = System::new();
+    let pid = sysinfo::get_current_pid().ok();
+    let mut tick = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tick.tick().await;
+        let battery = config.pause_on_battery.then(on_battery).flatten();
+        let load = config
+            .pause_above_cpu_percent
+            .map(|_| interactive_load(&mut sys, pid));
+        let was = governor.is_paused();
+        let now = should_pause(was, &config, battery, load);
+        debug!(?battery, ?load, paused = now, "Resource check");
+        if now != was {
+            governor.paused.store(now, Ordering::Relaxed);
+            if now {
+                info!(
+                    "Pausing contribution ({})",
+                    if battery == Some(true) {
+                        "on battery".to_string()
+                    } else {
+                        format!("host CPU {:.0}% busy", load.unwrap_or(0.0))
+                    }
+                );
+            } else {
+                info!("Resuming contribution");
+            }
+        }
+    }
+}
+
+/// Decide whether contribution should be paused given the latest readings.
+fn should_pause(
+    paused: bool,
+    config: &ResourcesConfig,
+    on_battery: Option<bool>,
+    load: Option<f32>,
+) -> bool {
+    if config.pause_on_battery && on_battery == Some(true) {
+        return true;
+    }
+    match (config.pause_above_cpu_percent, load) {
+        (Some(limit), Some(load)) => {
+            let limit = f32::from(limit);
+            if paused {
+                load > limit - RESUME_MARGIN
+            } else {
+                load > limit
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Host CPU usage (percent of all cores) not caused by this process.
+fn interactive_load(sys: &mut System, pid: Option<sysinfo::Pid>) -> f32 {
+    sys.refresh_cpu_usage();
+    let total = sys.global_cpu_info().cpu_usage();
+    let own = pid
+        .filter(|&pid| sys.refresh_process(pid))
+        .and_then(|pid| sys.process(pid))
+        .map_or(0.0, |p| p.cpu_usage() / sys.cpus().len().max(1) as f32);
+    (total - own).max(0.0)
+}
+
+/// Whether the host is running on battery; `None` when it cannot tell.
+#[cfg(target_os = "linux")]
+fn on_battery() -> Option<bool> {
+    let mut has_battery = false;
+    for entry in std::fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let path = entry.path();
+        let kind = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+        match kind.trim() {
+            "Mains" | "USB" => {
+                let online = std::fs::read_to_string(path.join("online")).unwrap_or_default();
+                if online.trim() == "1" {
+                    return Some(false);
+                }
+            }
+            "Battery" => has_battery = true,
+            _ => {}
+        }
+    }
+    has_battery.then_some(true)
+}
+
+/// Whether the host is running on battery; `None` when it cannot tell.
+#[cfg(target_os = "macos")]
+fn on_battery() -> Option<bool> {
+    let out = std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    let first = text.lines().next()?;
+    Some(first.contains("Battery Power"))
+}
+
+/// Whether the host is running on battery; `None` when it cannot tell.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn on_battery() -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(pause_on_battery: bool, cpu: Option<u8>) -> ResourcesConfig {
+        ResourcesConfig {
+            pause_on_battery,
+            pause_above_cpu_percent: cpu,
+            ..ResourcesConfig::default()
+        }
+    }
+
+    #[test]
+    fn pauses_on_battery_only_when_enabled() {
+        assert!(should_pause(false, &cfg(true, None), Some(true), None));
+        assert!(!should_pause(false, &cfg(true, None), Some(false), None));
+        assert!(!should_pause(false, &cfg(true, None), None, None));
+        assert!(!should_pause(false, &cfg(false, None), Some(true), None));
+    }
+
+    #[test]
+    fn cpu_threshold_has_hysteresis() {
+        let c = cfg(false, Some(60));
+        assert!(!should_pause(false, &c, None, Some(55.0)));
+        assert!(should_pause(false, &c, None, Some(75.0)));
+        // Still above threshold − margin: stay paused.
+        assert!(should_pause(true, &c, None, Some(55.0)));
+        assert!(!should_pause(true, &c, None, Some(45.0)));
+    }
+
+    #[tokio::test]
+    async fn unrestricted_governor_never_waits() {
+        let g = Governor::default();
+        g.record_busy(Duration::from_secs(10));
+        let start = std::time::Instant::now();
+        g.wait_turn().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert!(!g.is_paused());
+    }
+}
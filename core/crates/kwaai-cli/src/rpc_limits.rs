@@ -0,0 +1,395 @@
+//! Abuse protection for incoming Hivemind DHT RPCs (`rpc_limits` in
+//! config.yaml).
+//!
+//! Every stream p2pd forwards to the node's RPC listener passes three checks:
+//!
+//!   - concurrency caps: beyond `max_concurrent` in-flight handlers, or
+//!     `max_streams_per_peer` from one peer, new streams are closed before
+//!     the request is read
+//!   - a deadline: a handler still reading or answering after
+//!     [`HANDLER_TIMEOUT`] is dropped, so slow peers can't pin its slot
+//!   - per-peer and global token buckets: requests over the rate are dropped
+//!     unanswered, so a flooding peer only wastes its own round trips
+//!   - size limits: request messages over `max_message_bytes` are not read,
+//!     and STORE values over `max_value_bytes` are refused by the DHT storage
+//!
+//! Rejections are counted and reported by `kwaainet status` (`live.rpc_limits`
+//! with `--json`).
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::info;
+
+use crate::config::RpcLimitsConfig;
+
+/// Peers idle this long are forgotten when the bucket table is pruned.
+const PEER_IDLE: Duration = Duration::from_secs(300);
+
+/// The bucket table is pruned once it grows past this many peers.
+const PRUNE_AT: usize = 4096;
+
+/// Longest a single RPC stream may take, from accept to the last byte of
+/// the response. DHT requests are small; an honest peer is done in well
+/// under a second.
+pub const HANDLER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Token bucket refilled continuously at `rate` tokens per second.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(burst: u32, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(burst.max(1)),
+            last: now,
+        }
+    }
+
+    /// Take one token if available.
+    fn take(&mut self, rate: f64, burst: u32, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(f64::from(burst.max(1)));
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Why a request was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The peer exceeded `per_peer_rps`
+    PeerRate,
+    /// All peers together exceeded `global_rps`
+    GlobalRate,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Rejection::PeerRate => "per-peer rate limit",
+            Rejection::GlobalRate => "global rate limit",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    peer_rate_limited: AtomicU64,
+    global_rate_limited: AtomicU64,
+    over_capacity: AtomicU64,
+    peer_over_capacity: AtomicU64,
+    oversized_messages: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+/// Rejection counters, cumulative since the node started.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RpcLimitStats {
+    /// Requests that passed the rate limits
+    pub accepted: u64,
+    /// Requests dropped by the per-peer rate limit
+    pub peer_rate_limited: u64,
+    /// Requests dropped by the global rate limit
+    pub global_rate_limited: u64,
+    /// Streams closed because `max_concurrent` handlers were running
+    pub over_capacity: u64,
+    /// Streams closed because the peer had `max_streams_per_peer` running
+    pub peer_over_capacity: u64,
+    /// Requests whose message exceeded `max_message_bytes`
+    pub oversized_messages: u64,
+    /// Streams dropped after [`HANDLER_TIMEOUT`]
+    pub timed_out: u64,
+    /// Handlers running now
+    pub in_flight: usize,
+}
+
+/// Rate, concurrency and size limits shared by all RPC handler tasks.
+pub struct RpcLimiter {
+    config: RwLock<RpcLimitsConfig>,
+    global: Mutex<TokenBucket>,
+    peers: Mutex<HashMap<PeerId, TokenBucket>>,
+    slots: Mutex<Arc<Semaphore>>,
+    /// Streams in flight per peer
+    streams: Mutex<HashMap<PeerId, usize>>,
+    counters: Counters,
+}
+
+/// One of a peer's `max_streams_per_peer` stream slots, released on drop.
+pub struct PeerStream<'a> {
+    limiter: &'a RpcLimiter,
+    peer: PeerId,
+}
+
+impl Drop for PeerStream<'_> {
+    fn drop(&mut self) {
+        let mut streams = self.limiter.streams.lock().unwrap();
+        if let Some(n) = streams.get_mut(&self.peer) {
+            *n -= 1;
+            if *n == 0 {
+                streams.remove(&self.peer);
+            }
+        }
+    }
+}
+
+impl RpcLimiter {
+    pub fn new(config: &RpcLimitsConfig) -> Self {
+        let max_concurrent = config.max_concurrent.max(1);
+        info!(
+            "DHT RPC limits: {} req/s per peer, {} req/s total, {} concurrent",
+            config.per_peer_rps, config.global_rps, max_concurrent
+        );
+        Self {
+            config: RwLock::new(config.clone()),
+            global: Mutex::new(TokenBucket::full(config.global_burst, Instant::now())),
+            peers: Mutex::new(HashMap::new()),
+            slots: Mutex::new(Arc::new(Semaphore::new(max_concurrent))),
+            streams: Mutex::new(HashMap::new()),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Switch to new limits (config reload). Buckets and counters carry
+    /// over; a new `max_concurrent` gets a fresh set of slots, so handlers
+    /// already running finish on the old ones.
+    pub fn reconfigure(&self, config: &RpcLimitsConfig) {
+        let mut current = self.config.write().unwrap();
+        if config.max_concurrent != current.max_concurrent {
+            *self.slots.lock().unwrap() = Arc::new(Semaphore::new(config.max_concurrent.max(1)));
+        }
+        *current = config.clone();
+        info!(
+            "DHT RPC limits now: {} req/s per peer, {} req/s total, {} concurrent",
+            config.per_peer_rps,
+            config.global_rps,
+            config.max_concurrent.max(1)
+        );
+    }
+
+    /// Reserve a handler slot, or `None` when `max_concurrent` handlers are
+    /// already running. The slot is released when the permit is dropped.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let slots = self.slots.lock().unwrap().clone();
+        let permit = slots.try_acquire_owned().ok();
+        if permit.is_none() {
+            self.counters.over_capacity.fetch_add(1, Ordering::Relaxed);
+        }
+        permit
+    }
+
+    /// Reserve one of `peer`'s stream slots, or `None` when it already has
+    /// `max_streams_per_peer` streams in flight.
+    pub fn try_acquire_peer(&self, peer: &PeerId) -> Option<PeerStream<'_>> {
+        let max = self.config.read().unwrap().max_streams_per_peer.max(1);
+        let mut streams = self.streams.lock().unwrap();
+        let n = streams.entry(*peer).or_default();
+        if *n >= max {
+            self.counters
+                .peer_over_capacity
+                .fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        *n += 1;
+        Some(PeerStream {
+            limiter: self,
+            peer: *peer,
+        })
+    }
+
+    /// Count a stream dropped after [`HANDLER_TIMEOUT`].
+    pub fn record_timeout(&self) {
+        self.counters.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Charge one request from `peer` against the rate limits.
+    pub fn admit(&self, peer: &PeerId) -> Result<(), Rejection> {
+        self.admit_at(peer, Instant::now())
+    }
+
+    fn admit_at(&self, peer: &PeerId, now: Instant) -> Result<(), Rejection> {
+        let (rate, burst, global_rate, global_burst) = {
+            let c = self.config.read().unwrap();
+            (
+                c.per_peer_rps,
+                c.per_peer_burst,
+                c.global_rps,
+                c.global_burst,
+            )
+        };
+        let peer_ok = {
+            let mut peers = self.peers.lock().unwrap();
+            if peers.len() >= PRUNE_AT {
+                peers.retain(|_, b| now.saturating_duration_since(b.last) < PEER_IDLE);
+            }
+            peers
+                .entry(*peer)
+                .or_insert_with(|| TokenBucket::full(burst, now))
+                .take(rate, burst, now)
+        };
+        if !peer_ok {
+            self.counters
+                .peer_rate_limited
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::PeerRate);
+        }
+        let global_ok = self
+            .global
+            .lock()
+            .unwrap()
+            .take(global_rate, global_burst, now);
+        if !global_ok {
+            self.counters
+                .global_rate_limited
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::GlobalRate);
+        }
+        self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Largest request message a handler reads.
+    pub fn max_message_bytes(&self) -> usize {
+        self.config.read().unwrap().max_message_bytes
+    }
+
+    /// Count a request dropped for exceeding `max_message_bytes`.
+    pub fn record_oversized(&self) {
+        self.counters
+            .oversized_messages
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RpcLimitStats {
+        RpcLimitStats {
+            accepted: self.counters.accepted.load(Ordering::Relaxed),
+            peer_rate_limited: self.counters.peer_rate_limited.load(Ordering::Relaxed),
+            global_rate_limited: self.counters.global_rate_limited.load(Ordering::Relaxed),
+            over_capacity: self.counters.over_capacity.load(Ordering::Relaxed),
+            peer_over_capacity: self.counters.peer_over_capacity.load(Ordering::Relaxed),
+            oversized_messages: self.counters.oversized_messages.load(Ordering::Relaxed),
+            timed_out: self.counters.timed_out.load(Ordering::Relaxed),
+            in_flight: self.config.read().unwrap().max_concurrent.max(1)
+                - self.slots.lock().unwrap().available_permits(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(per_peer_rps: f64, per_peer_burst: u32, global_burst: u32) -> RpcLimitsConfig {
+        RpcLimitsConfig {
+            per_peer_rps,
+            per_peer_burst,
+            global_rps: 0.0,
+            global_burst,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn per_peer_burst_then_refill() {
+        let limiter = RpcLimiter::new(&limits(2.0, 3, 100));
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let t0 = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.admit_at(&a, t0), Ok(()));
+        }
+        assert_eq!(limiter.admit_at(&a, t0), Err(Rejection::PeerRate));
+        // Another peer has its own bucket.
+        assert_eq!(limiter.admit_at(&b, t0), Ok(()));
+        // Half a second at 2 req/s refills one token.
+        assert_eq!(
+            limiter.admit_at(&a, t0 + Duration::from_millis(500)),
+            Ok(())
+        );
+
+        let stats = limiter.stats();
+        assert_eq!(stats.accepted, 5);
+        assert_eq!(stats.peer_rate_limited, 1);
+    }
+
+    #[test]
+    fn global_limit_and_concurrency_cap() {
+        let limiter = RpcLimiter::new(&RpcLimitsConfig {
+            max_concurrent: 1,
+            ..limits(100.0, 100, 2)
+        });
+        let t0 = Instant::now();
+        assert!(limiter.admit_at(&PeerId::random(), t0).is_ok());
+        assert!(limiter.admit_at(&PeerId::random(), t0).is_ok());
+        assert_eq!(
+            limiter.admit_at(&PeerId::random(), t0),
+            Err(Rejection::GlobalRate)
+        );
+
+        let permit = limiter.try_acquire().expect("free slot");
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.stats().in_flight, 1);
+        drop(permit);
+        assert!(limiter.try_acquire().is_some());
+
+        let stats = limiter.stats();
+        assert_eq!(stats.global_rate_limited, 1);
+        assert_eq!(stats.over_capacity, 1);
+    }
+
+    #[test]
+    fn one_peer_cannot_take_every_slot() {
+        let limiter = RpcLimiter::new(&RpcLimitsConfig {
+            max_streams_per_peer: 2,
+            ..Default::default()
+        });
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let first = limiter.try_acquire_peer(&a).expect("first stream");
+        let _second = limiter.try_acquire_peer(&a).expect("second stream");
+        assert!(limiter.try_acquire_peer(&a).is_none());
+        assert!(limiter.try_acquire_peer(&b).is_some());
+        drop(first);
+        assert!(limiter.try_acquire_peer(&a).is_some());
+        assert_eq!(limiter.stats().peer_over_capacity, 1);
+    }
+
+    #[test]
+    fn reconfigure_applies_new_limits() {
+        let limiter = RpcLimiter::new(&RpcLimitsConfig {
+            max_concurrent: 1,
+            ..limits(0.0, 1, 100)
+        });
+        let (peer, t0) = (PeerId::random(), Instant::now());
+        let _running = limiter.try_acquire().expect("free slot");
+        assert_eq!(limiter.admit_at(&peer, t0), Ok(()));
+        assert_eq!(limiter.admit_at(&peer, t0), Err(Rejection::PeerRate));
+
+        limiter.reconfigure(&RpcLimitsConfig {
+            max_concurrent: 2,
+            ..limits(10.0, 1, 100)
+        });
+        // The empty bucket now refills at the new rate.
+        assert_eq!(
+            limiter.admit_at(&peer, t0 + Duration::from_millis(100)),
+            Ok(())
+        );
+        // Two fresh slots; the handler already running keeps its old one.
+        let _a = limiter.try_acquire().expect("first new slot");
+        let _b = limiter.try_acquire().expect("second new slot");
+        assert!(limiter.try_acquire().is_none());
+    }
+}
@@ -0,0 +1,233 @@
+//! Shell completions and a machine-readable dump of the command tree.
+//!
+//! Both are generated from the clap definitions in [`crate::cli`], so
+//! wrappers and GUIs that consume `kwaainet schema --json` stay in sync with
+//! the CLI without a hand-maintained copy of every flag.
+
+use clap::{Arg, ArgAction, CommandFactory};
+use clap_complete::Shell;
+use serde_json::{json, Value};
+
+use crate::cli::Cli;
+
+/// Bumped when the shape of the schema JSON changes incompatibly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Write a completion script for `shell` to stdout.
+pub fn print_completions(shell: Shell) {
+    let mut cmd = Cli::command();
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+}
+
+/// Full command/flag schema for the `kwaainet` binary.
+///
+/// Hidden (internal) commands and arguments are left out.
+pub fn schema() -> Value {
+    let cmd = Cli::command();
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "name": cmd.get_name(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "about": cmd.get_about().map(|s| s.to_string()),
+        "commands": subcommands(&cmd),
+    })
+}
+
+/// Print the schema: pretty JSON, or an indented command tree for humans.
+pub fn print_schema(as_json: bool) {
+    let schema = schema();
+    if as_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema).unwrap_or_default()
+        );
+        return;
+    }
+    if let Some(commands) = schema["commands"].as_array() {
+        print_tree(commands, 0);
+    }
+}
+
+fn print_tree(commands: &[Value], depth: usize) {
+    for c in commands {
+        let name = c["name"].as_str().unwrap_or_default();
+        let about = c["about"].as_str().unwrap_or_default();
+        let indent = "  ".repeat(depth);
+        println!("{indent}{name:<width$}  {about}", width = 24 - depth * 2);
+        if let Some(subs) = c["subcommands"].as_array() {
+            print_tree(subs, depth + 1);
+        }
+    }
+}
+
+fn subcommands(cmd: &clap::Command) -> Vec<Value> {
+    cmd.get_subcommands()
+        .filter(|c| !c.is_hide_set())
+        .map(command)
+        .collect()
+}
+
+fn command(cmd: &clap::Command) -> Value {
+    json!({
+        "name": cmd.get_name(),
+        "about": cmd.get_about().map(|s| s.to_string()),
+        "aliases": cmd.get_visible_aliases().collect::<Vec<_>>(),
+        "args": cmd
+            .get_arguments()
+            .filter(|a| !a.is_hide_set())
+            .map(argument)
+            .collect::<Vec<_>>(),
+        "subcommands": subcommands(cmd),
+    })
+}
+
+fn argument(arg: &Arg) -> Value {
+    let kind = if arg.is_positional() {
+        "positional"
+    } else {
+        match arg.get_action() {
+            ArgAction::SetTrue | ArgAction::SetFalse => "flag",
+            ArgAction::Count => "count",
+            _ => "option",
+        }
+    };
+    let takes_value = arg.get_action().takes_values();
+    let possible_values: Vec<String> = if takes_value {
+        arg.get_possible_values()
+            .iter()
+            .filter(|v| !v.is_hide_set())
+            .map(|v| v.get_name().to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    json!({
+        "id": arg.get_id().as_str(),
+        "kind": kind,
+        "long": arg.get_long(),
+        "short": arg.get_short().map(String::from),
+        "help": arg.get_help().map(|s| s.to_string()),
+        "value_names": arg
+            .get_value_names()
+            .map(|v| v.iter().map(|s| s.to_string()).collect::<Vec<_>>()),
+        "required": arg.is_required_set(),
+        "multiple": matches!(arg.get_action(), ArgAction::Append),
+        "global": arg.is_global_set(),
+        "default": arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        "possible_values": possible_values,
+        "env": arg.get_env().map(|e| e.to_string_lossy().into_owned()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Building the full `Cli` command in a debug build needs more than the
+    /// 2 MiB a test thread gets by default.
+    fn on_big_stack(f: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(16 * 1024 * 1024)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn find<'a>(commands: &'a Value, name: &str) -> &'a Value {
+        commands
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == name)
+            .unwrap_or_else(|| panic!("command {name} missing from schema"))
+    }
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        on_big_stack(|| Cli::command().debug_assert());
+    }
+
+    #[test]
+    fn json_flag_is_global() {
+        on_big_stack(|| {
+            use crate::cli::Command;
+            use clap::Parser;
+
+            let cli = Cli::try_parse_from(["kwaainet", "--json", "status"]).unwrap();
+            let Command::Status(args) = cli.command else {
+                panic!("expected status");
+            };
+            assert!(cli.json && args.json);
+
+            let cli = Cli::try_parse_from(["kwaainet", "peers", "--json"]).unwrap();
+            let Command::Peers(args) = cli.command else {
+                panic!("expected peers");
+            };
+            assert!(cli.json && args.json);
+
+            let cli = Cli::try_parse_from(["kwaainet", "monitor", "stats", "--json"]).unwrap();
+            assert!(cli.json);
+        });
+    }
+
+    #[test]
+    fn schema_covers_nested_commands_and_flags() {
+        on_big_stack(check_schema);
+    }
+
+    fn check_schema() {
+        let s = schema();
+        assert_eq!(s["name"], "kwaainet");
+        assert_eq!(s["schema_version"], SCHEMA_VERSION);
+
+        let commands = &s["commands"];
+        assert!(commands
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|c| c["name"] != "run-node"));
+
+        let peers = find(commands, "peers");
+        let timeout = peers["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|a| a["long"] == "timeout")
+            .unwrap();
+        assert_eq!(timeout["kind"], "option");
+        assert_eq!(timeout["default"], json!(["5"]));
+
+        let completions = find(commands, "completions");
+        let shell = &completions["args"][0];
+        assert_eq!(shell["kind"], "positional");
+        assert!(shell["possible_values"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("zsh")));
+
+        let shard = find(commands, "shard");
+        find(&shard["subcommands"], "serve");
+    }
+
+    #[test]
+    fn completions_generate_for_every_shell() {
+        on_big_stack(check_completions);
+    }
+
+    fn check_completions() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut buf = Vec::new();
+            clap_complete::generate(shell, &mut Cli::command(), "kwaainet", &mut buf);
+            let script = String::from_utf8(buf).unwrap();
+            assert!(script.contains("kwaainet"), "{shell} script empty");
+            assert!(script.contains("completions"), "{shell} misses subcommands");
+        }
+    }
+}
@@ -1,14 +1,26 @@
 //! Auto-start service management
 //!
-//! macOS: launchd plist at ~/Library/LaunchAgents/ai.kwaai.kwaainet.plist
-//! Linux: systemd user unit at ~/.config/systemd/user/kwaainet.service
+//! macOS: launchd agent at ~/Library/LaunchAgents/ai.kwaai.kwaainet.plist,
+//!        or with `--system` a daemon in /Library/LaunchDaemons
+//! Linux: systemd user unit at ~/.config/systemd/user/kwaainet.service,
+//!        or with `--system` a system unit in /etc/systemd/system
+//!
+//! A named profile (`--profile NAME`) gets its own service,
+//! `ai.kwaai.kwaainet.NAME` / `kwaainet-NAME.service`, running with
+//! `KWAAINET_PROFILE=NAME`.
+//!
+//! System services are installed with sudo and run as the invoking user
+//! (`SUDO_USER`), with `KWAAINET_HOME` pointing at that user's data.
+//! [`InstallOptions`] picks the restart policy, resource limits, sandboxing
+//! and where the node's output goes; `status` reads them back from the
+//! installed unit.
 
 use anyhow::{Context, Result};
-use std::path::PathBuf;
-use tracing::info;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 
 pub trait ServiceManager {
-    fn install(&self) -> Result<()>;
+    fn install(&self, opts: &InstallOptions) -> Result<()>;
     fn uninstall(&self) -> Result<()>;
     fn status(&self) -> ServiceStatus;
     fn restart(&self) -> Result<()>;
@@ -21,8 +33,173 @@ pub struct ServiceStatus {
     pub loaded: bool,
     pub running: bool,
     pub pid: Option<u32>,
+    /// Scope of the installed service
+    pub scope: Option<ServiceScope>,
+    /// Unit file or plist of the installed service
+    pub path: Option<PathBuf>,
+    /// Settings read back from the installed unit, as (label, value)
+    pub settings: Vec<(String, String)>,
+}
+
+impl ServiceStatus {
+    fn not_installed() -> Self {
+        Self {
+            installed: false,
+            loaded: false,
+            running: false,
+            pid: None,
+            scope: None,
+            path: None,
+            settings: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceScope {
+    /// Started with the user's session (systemd user manager / LaunchAgent)
+    User,
+    /// Started at boot by the system manager (needs root to install)
+    System,
+}
+
+impl ServiceScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServiceScope::User => "user",
+            ServiceScope::System => "system",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Always,
+    OnFailure,
+    Never,
+}
+
+impl RestartPolicy {
+    /// `always`, `on-failure` or `no`, as systemd spells them
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(RestartPolicy::Always),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "no" | "never" => Ok(RestartPolicy::Never),
+            _ => anyhow::bail!("restart policy must be always, on-failure or no"),
+        }
+    }
+
+    #[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+    fn as_systemd(self) -> &'static str {
+        match self {
+            RestartPolicy::Always => "always",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Never => "no",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    /// Append to kwaainet.log in the log directory (read by `kwaainet logs`)
+    File,
+    /// systemd journal (`journalctl -u kwaainet`); Linux only
+    Journal,
 }
 
+/// How `kwaainet service install` sets the service up
+#[derive(Debug, Clone)]
+pub struct InstallOptions {
+    pub scope: ServiceScope,
+    pub restart: RestartPolicy,
+    /// Seconds to wait before restarting
+    pub restart_sec: u64,
+    /// systemd `MemoryMax=`, e.g. `8G` or `50%`
+    pub memory_max: Option<String>,
+    /// systemd `CPUQuota=` in percent of one CPU (200 = two full cores)
+    pub cpu_quota: Option<u32>,
+    /// Add the sandboxing options of [`SANDBOX_OPTIONS`]
+    pub sandbox: bool,
+    pub log_target: LogTarget,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        Self {
+            scope: ServiceScope::User,
+            restart: RestartPolicy::Always,
+            restart_sec: 10,
+            memory_max: None,
+            cpu_quota: None,
+            sandbox: true,
+            log_target: LogTarget::File,
+        }
+    }
+}
+
+impl InstallOptions {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(max) = &self.memory_max {
+            if !is_memory_size(max) {
+                anyhow::bail!(
+                    "--memory-max must be a size such as 512M, 8G or 50% (or infinity), got {max:?}"
+                );
+            }
+        }
+        if self.cpu_quota == Some(0) {
+            anyhow::bail!("--cpu-quota must be a positive percentage");
+        }
+        Ok(())
+    }
+}
+
+/// A systemd memory size: bytes with an optional K/M/G/T suffix, a
+/// percentage of physical memory, or `infinity`
+fn is_memory_size(s: &str) -> bool {
+    if s == "infinity" {
+        return true;
+    }
+    if let Some(percent) = s.strip_suffix('%') {
+        return percent.parse::<f64>().is_ok_and(|p| p > 0.0 && p <= 100.0);
+    }
+    let digits = s.trim_end_matches(['K', 'M', 'G', 'T']);
+    s.len() - digits.len() <= 1 && !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Sandboxing applied to every unit: seccomp-based, so it works for user
+/// services too. `PrivateTmp` is left out because p2pd's socket lives in
+/// /tmp, where the CLI has to reach it.
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+const SANDBOX_OPTIONS: &[&str] = &[
+    "NoNewPrivileges=yes",
+    "LockPersonality=yes",
+    "RestrictRealtime=yes",
+    "RestrictSUIDSGID=yes",
+    "RestrictNamespaces=yes",
+    "SystemCallArchitectures=native",
+];
+
+/// Sandboxing that needs the system manager (mount namespaces). Devices
+/// stay visible for the GPU. `ProtectSystem=strict` makes /tmp read-only
+/// too, so units that use it also get [`SHARED_TMP`] back as writable.
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+const SYSTEM_SANDBOX_OPTIONS: &[&str] = &[
+    "ProtectSystem=strict",
+    "ProtectHome=read-only",
+    "ProtectKernelTunables=yes",
+    "ProtectKernelModules=yes",
+    "ProtectKernelLogs=yes",
+    "ProtectControlGroups=yes",
+    "ProtectClock=yes",
+];
+
+/// Where p2pd binds its socket (`/tmp/kwaai-p2pd*.sock`) and the updater,
+/// shard transfers and DHT auth stage files (`std::env::temp_dir()`, which is
+/// /tmp for a service without `TMPDIR`).
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+const SHARED_TMP: &str = "/tmp";
+
 pub fn get_service_manager() -> Box<dyn ServiceManager> {
     #[cfg(target_os = "macos")]
     return Box::new(LaunchdManager);
@@ -34,6 +211,175 @@ pub fn get_service_manager() -> Box<dyn ServiceManager> {
     return Box::new(NoopManager);
 }
 
+/// systemd unit name of the active profile
+#[cfg(target_os = "linux")]
+fn service_name() -> String {
+    match crate::config::active_profile() {
+        Some(profile) => format!("kwaainet-{}", profile),
+        None => "kwaainet".to_string(),
+    }
+}
+
+/// Account and data directory a service runs with.
+///
+/// User services run as the current user with the usual directories. A
+/// system service is installed through sudo, so it runs as `SUDO_USER` and
+/// finds that user's data through `KWAAINET_HOME`.
+#[cfg(unix)]
+struct RunAs {
+    /// `None` for a user service
+    user: Option<nix::unistd::User>,
+    /// `KWAAINET_HOME` to set, for a system service
+    base_dir: Option<PathBuf>,
+    /// Data directory of the active profile
+    data_dir: PathBuf,
+    /// Home directory, for the caches the node writes outside its data
+    /// directory
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    home: PathBuf,
+}
+
+#[cfg(unix)]
+impl RunAs {
+    fn resolve(scope: ServiceScope) -> Result<Self> {
+        if scope == ServiceScope::User {
+            return Ok(Self {
+                user: None,
+                base_dir: None,
+                data_dir: crate::config::kwaainet_dir(),
+                home: dirs::home_dir().unwrap_or_default(),
+            });
+        }
+        require_root()?;
+        let name = std::env::var("SUDO_USER")
+            .ok()
+            .filter(|u| !u.is_empty() && u != "root")
+            .context(
+                "run `sudo kwaainet service install --system` from the account the node \
+                 should run as",
+            )?;
+        let user = nix::unistd::User::from_name(&name)?
+            .with_context(|| format!("no such user: {name}"))?;
+        let base_dir = std::env::var_os("KWAAINET_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| user.dir.join(".kwaainet"));
+        let data_dir = match crate::config::active_profile() {
+            Some(profile) => base_dir.join("profiles").join(profile),
+            None => base_dir.clone(),
+        };
+        Ok(Self {
+            home: user.dir.clone(),
+            user: Some(user),
+            base_dir: Some(base_dir),
+            data_dir,
+        })
+    }
+
+    fn log_file(&self) -> PathBuf {
+        self.data_dir.join("logs").join("kwaainet.log")
+    }
+
+    /// Create the log directory, owned by the account the service runs as.
+    fn create_log_dir(&self) -> Result<()> {
+        let dir = self.data_dir.join("logs");
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        if let Some(user) = &self.user {
+            for path in [&self.data_dir, &dir] {
+                nix::unistd::chown(path, Some(user.uid), Some(user.gid))
+                    .with_context(|| format!("chown {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn require_root() -> Result<()> {
+    if !nix::unistd::geteuid().is_root() {
+        anyhow::bail!("the system service needs root — run this command with sudo");
+    }
+    Ok(())
+}
+
+/// Lines of the `[Service]` section (or top-level keys) of a unit file as
+/// (key, value), in order
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn parse_unit(text: &str) -> Vec<(String, String)> {
+    let mut section = String::new();
+    let mut entries = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.to_string();
+        } else if section == "Service" {
+            if let Some((key, value)) = line.split_once('=') {
+                entries.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+    entries
+}
+
+/// What `kwaainet service status` shows of a parsed systemd unit
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn describe_unit(entries: &[(String, String)]) -> Vec<(String, String)> {
+    let get = |key: &str| {
+        entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+    let mut settings = Vec::new();
+    let restart = get("Restart").unwrap_or_else(|| "no".to_string());
+    let restart = match get("RestartSec") {
+        Some(sec) if restart != "no" => format!("{restart} (after {sec}s)"),
+        _ => restart,
+    };
+    settings.push(("Restart".to_string(), restart));
+    if let Some(sec) = get("WatchdogSec") {
+        settings.push(("Watchdog".to_string(), format!("restart if hung {sec}s")));
+    }
+    if let Some(user) = get("User") {
+        settings.push(("Runs as".to_string(), user));
+    }
+    for (key, label) in [("MemoryMax", "Memory max"), ("CPUQuota", "CPU quota")] {
+        settings.push((
+            label.to_string(),
+            get(key).unwrap_or_else(|| "—".to_string()),
+        ));
+    }
+    let logs = match get("StandardOutput") {
+        Some(out) if out == "journal" => "journald".to_string(),
+        Some(out) => out
+            .strip_prefix("append:")
+            .or_else(|| out.strip_prefix("file:"))
+            .unwrap_or(&out)
+            .to_string(),
+        None => "journald".to_string(),
+    };
+    settings.push(("Logs".to_string(), logs));
+    let sandboxed = SANDBOX_OPTIONS
+        .iter()
+        .chain(SYSTEM_SANDBOX_OPTIONS)
+        .filter(|opt| {
+            let (key, value) = opt.split_once('=').unwrap_or_default();
+            get(key).as_deref() == Some(value)
+        })
+        .count();
+    settings.push((
+        "Sandboxing".to_string(),
+        if sandboxed == 0 {
+            "off".to_string()
+        } else {
+            format!("{sandboxed} option(s)")
+        },
+    ));
+    settings
+}
+
 // ---------------------------------------------------------------------------
 // macOS – launchd
 // ---------------------------------------------------------------------------
@@ -43,22 +389,80 @@ struct LaunchdManager;
 
 #[cfg(target_os = "macos")]
 impl LaunchdManager {
-    fn plist_path() -> PathBuf {
-        let home = std::env::var("HOME").unwrap_or_default();
-        PathBuf::from(home).join("Library/LaunchAgents/ai.kwaai.kwaainet.plist")
+    fn label() -> String {
+        match crate::config::active_profile() {
+            Some(profile) => format!("ai.kwaai.kwaainet.{}", profile),
+            None => "ai.kwaai.kwaainet".to_string(),
+        }
     }
 
-    fn plist_content() -> Result<String> {
-        let exe = std::env::current_exe().context("finding own executable")?;
-        let log_dir = crate::config::log_dir();
-        Ok(format!(
-            r#"<?xml version="1.0" encoding="UTF-8"?>
+    fn plist_path(scope: ServiceScope) -> PathBuf {
+        let dir = match scope {
+            ServiceScope::User => {
+                let home = std::env::var("HOME").unwrap_or_default();
+                PathBuf::from(home).join("Library/LaunchAgents")
+            }
+            ServiceScope::System => PathBuf::from("/Library/LaunchDaemons"),
+        };
+        dir.join(format!("{}.plist", Self::label()))
+    }
+
+    /// Scope of the installed plist, system first
+    fn installed_scope() -> Option<ServiceScope> {
+        [ServiceScope::System, ServiceScope::User]
+            .into_iter()
+            .find(|&scope| Self::plist_path(scope).exists())
+    }
+
+    /// `launchctl` target of the service in `scope`
+    fn domain_target(scope: ServiceScope) -> String {
+        match scope {
+            ServiceScope::User => format!("gui/{}/{}", nix::unistd::getuid(), Self::label()),
+            ServiceScope::System => format!("system/{}", Self::label()),
+        }
+    }
+}
+
+/// launchd plist for a node run by `exe`.
+#[cfg(any(target_os = "macos", test))]
+fn launchd_plist(
+    label: &str,
+    exe: &Path,
+    environment: &[(&str, String)],
+    user_name: Option<&str>,
+    log: &Path,
+    opts: &InstallOptions,
+) -> String {
+    let mut extra = String::new();
+    if !environment.is_empty() {
+        extra.push_str("\n    <key>EnvironmentVariables</key>\n    <dict>");
+        for (key, value) in environment {
+            extra.push_str(&format!(
+                "\n        <key>{key}</key>\n        <string>{value}</string>"
+            ));
+        }
+        extra.push_str("\n    </dict>");
+    }
+    if let Some(user) = user_name {
+        extra.push_str(&format!(
+            "\n    <key>UserName</key>\n    <string>{user}</string>"
+        ));
+    }
+    let keep_alive = match opts.restart {
+        RestartPolicy::Always => "<true/>".to_string(),
+        RestartPolicy::OnFailure => "<dict>\n        <key>SuccessfulExit</key>\n        \
+                                     <false/>\n    </dict>"
+            .to_string(),
+        RestartPolicy::Never => "<false/>".to_string(),
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN"
   "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
 <plist version="1.0">
 <dict>
     <key>Label</key>
-    <string>ai.kwaai.kwaainet</string>
+    <string>{label}</string>{extra}
     <key>ProgramArguments</key>
     <array>
         <string>{exe}</string>
@@ -67,27 +471,106 @@ impl LaunchdManager {
     <key>RunAtLoad</key>
     <true/>
     <key>KeepAlive</key>
-    <true/>
+    {keep_alive}
+    <key>ThrottleInterval</key>
+    <integer>{throttle}</integer>
     <key>StandardOutPath</key>
     <string>{log}</string>
     <key>StandardErrorPath</key>
     <string>{log}</string>
 </dict>
 </plist>"#,
-            exe = exe.display(),
-            log = log_dir.join("kwaainet.log").display(),
-        ))
+        exe = exe.display(),
+        throttle = opts.restart_sec,
+        log = log.display(),
+    )
+}
+
+/// Value following `<key>{key}</key>` in a plist: the text of a
+/// `<string>`/`<integer>` element, `true`/`false`, or `dict`
+#[cfg(any(target_os = "macos", test))]
+fn plist_value(text: &str, key: &str) -> Option<String> {
+    let rest = text
+        .split_once(&format!("<key>{key}</key>"))?
+        .1
+        .trim_start();
+    if rest.starts_with("<true/>") {
+        return Some("true".to_string());
+    }
+    if rest.starts_with("<false/>") {
+        return Some("false".to_string());
+    }
+    if rest.starts_with("<dict>") {
+        return Some("dict".to_string());
+    }
+    let open_end = rest.find('>')?;
+    let close = rest.find("</")?;
+    Some(rest.get(open_end + 1..close)?.trim().to_string())
+}
+
+/// What `kwaainet service status` shows of an installed plist
+#[cfg(any(target_os = "macos", test))]
+fn describe_plist(text: &str) -> Vec<(String, String)> {
+    let restart = match plist_value(text, "KeepAlive").as_deref() {
+        Some("true") => "always",
+        Some("dict") => "on-failure",
+        _ => "no",
+    };
+    let restart = match plist_value(text, "ThrottleInterval") {
+        Some(sec) if restart != "no" => format!("{restart} (after {sec}s)"),
+        _ => restart.to_string(),
+    };
+    let mut settings = vec![("Restart".to_string(), restart)];
+    if let Some(sec) = plist_value(text, crate::liveness::WATCHDOG_ENV) {
+        settings.push(("Watchdog".to_string(), format!("restart if hung {sec}s")));
+    }
+    if let Some(user) = plist_value(text, "UserName") {
+        settings.push(("Runs as".to_string(), user));
+    }
+    if let Some(log) = plist_value(text, "StandardOutPath") {
+        settings.push(("Logs".to_string(), log));
     }
+    settings
 }
 
 #[cfg(target_os = "macos")]
 impl ServiceManager for LaunchdManager {
-    fn install(&self) -> Result<()> {
-        let path = Self::plist_path();
+    fn install(&self, opts: &InstallOptions) -> Result<()> {
+        opts.validate()?;
+        if opts.log_target == LogTarget::Journal {
+            anyhow::bail!("journald logging is only available on Linux");
+        }
+        if opts.memory_max.is_some() || opts.cpu_quota.is_some() || opts.sandbox {
+            warn!("launchd does not enforce memory/CPU limits or sandboxing — not applied");
+        }
+        let run_as = RunAs::resolve(opts.scope)?;
+        run_as.create_log_dir()?;
+        let exe = std::env::current_exe().context("finding own executable")?;
+        let mut environment = Vec::new();
+        if let Some(base) = &run_as.base_dir {
+            environment.push(("KWAAINET_HOME", base.display().to_string()));
+        }
+        if let Some(profile) = crate::config::active_profile() {
+            environment.push(("KWAAINET_PROFILE", profile));
+        }
+        // launchd has no watchdog; the node exits when hung instead.
+        environment.push((
+            crate::liveness::WATCHDOG_ENV,
+            crate::liveness::HUNG_AFTER.as_secs().to_string(),
+        ));
+        let plist = launchd_plist(
+            &Self::label(),
+            &exe,
+            &environment,
+            run_as.user.as_ref().map(|u| u.name.as_str()),
+            &run_as.log_file(),
+            opts,
+        );
+        let path = Self::plist_path(opts.scope);
         std::fs::create_dir_all(path.parent().unwrap())?;
-        std::fs::write(&path, Self::plist_content()?)?;
+        std::fs::write(&path, plist)?;
         std::process::Command::new("launchctl")
-            .args(["load", &path.to_string_lossy()])
+            .args(["load", "-w", &path.to_string_lossy()])
             .status()
             .context("launchctl load")?;
         info!("Installed launchd service at {}", path.display());
@@ -95,65 +578,77 @@ impl ServiceManager for LaunchdManager {
     }
 
     fn uninstall(&self) -> Result<()> {
-        let path = Self::plist_path();
-        if path.exists() {
-            // Only call `launchctl unload` when the service is actually loaded.
-            // Calling unload on a plist that was never loaded prints "Unload
-            // failed: 5: Input/output error" to stdout — noise with no effect.
-            let loaded = std::process::Command::new("launchctl")
-                .args(["list", "ai.kwaai.kwaainet"])
-                .output()
-                .map(|o| o.status.success())
-                .unwrap_or(false);
-            if loaded {
-                // Capture output so launchctl noise doesn't leak to the terminal.
-                let _ = std::process::Command::new("launchctl")
-                    .args(["unload", &path.to_string_lossy()])
-                    .output();
-            }
-            std::fs::remove_file(&path)?;
+        let Some(scope) = Self::installed_scope() else {
+            return Ok(());
+        };
+        if scope == ServiceScope::System {
+            require_root()?;
+        }
+        let path = Self::plist_path(scope);
+        // Only call `launchctl unload` when the service is actually loaded.
+        // Calling unload on a plist that was never loaded prints "Unload
+        // failed: 5: Input/output error" to stdout — noise with no effect.
+        let loaded = std::process::Command::new("launchctl")
+            .args(["print", &Self::domain_target(scope)])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if loaded {
+            // Capture output so launchctl noise doesn't leak to the terminal.
+            let _ = std::process::Command::new("launchctl")
+                .args(["unload", &path.to_string_lossy()])
+                .output();
         }
+        std::fs::remove_file(&path)?;
         Ok(())
     }
 
     fn status(&self) -> ServiceStatus {
-        let path = Self::plist_path();
-        let installed = path.exists();
-        if !installed {
-            return ServiceStatus {
-                installed: false,
-                loaded: false,
-                running: false,
-                pid: None,
-            };
-        }
+        let Some(scope) = Self::installed_scope() else {
+            return ServiceStatus::not_installed();
+        };
+        let path = Self::plist_path(scope);
         let out = std::process::Command::new("launchctl")
-            .args(["list", "ai.kwaai.kwaainet"])
+            .args(["print", &Self::domain_target(scope)])
             .output()
-            .ok();
-        let running = out.as_ref().map(|o| o.status.success()).unwrap_or(false);
+            .ok()
+            .filter(|o| o.status.success());
+        let printed = out
+            .as_ref()
+            .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+            .unwrap_or_default();
+        let pid = printed
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("pid = "))
+            .and_then(|p| p.trim().parse().ok());
         ServiceStatus {
-            installed,
-            loaded: running,
-            running,
-            pid: None,
+            installed: true,
+            loaded: out.is_some(),
+            running: pid.is_some(),
+            pid,
+            scope: Some(scope),
+            settings: std::fs::read_to_string(&path)
+                .map(|text| describe_plist(&text))
+                .unwrap_or_default(),
+            path: Some(path),
         }
     }
 
     fn restart(&self) -> Result<()> {
-        let path = Self::plist_path();
-        std::process::Command::new("launchctl")
-            .args(["unload", &path.to_string_lossy()])
-            .status()?;
+        let scope = Self::installed_scope().context("the service is not installed")?;
+        if scope == ServiceScope::System {
+            require_root()?;
+        }
         std::process::Command::new("launchctl")
-            .args(["load", &path.to_string_lossy()])
-            .status()?;
+            .args(["kickstart", "-k", &Self::domain_target(scope)])
+            .status()
+            .context("launchctl kickstart")?;
         Ok(())
     }
 }
 
 // ---------------------------------------------------------------------------
-// Linux – systemd (user)
+// Linux – systemd
 // ---------------------------------------------------------------------------
 
 #[cfg(target_os = "linux")]
@@ -161,74 +656,212 @@ struct SystemdManager;
 
 #[cfg(target_os = "linux")]
 impl SystemdManager {
-    fn unit_path() -> PathBuf {
-        let home = std::env::var("HOME").unwrap_or_default();
-        PathBuf::from(home).join(".config/systemd/user/kwaainet.service")
+    fn unit_path(scope: ServiceScope) -> PathBuf {
+        let dir = match scope {
+            ServiceScope::User => {
+                let home = std::env::var("HOME").unwrap_or_default();
+                PathBuf::from(home).join(".config/systemd/user")
+            }
+            ServiceScope::System => PathBuf::from("/etc/systemd/system"),
+        };
+        dir.join(format!("{}.service", service_name()))
     }
 
-    fn unit_content() -> Result<String> {
-        let exe = std::env::current_exe().context("finding own executable")?;
-        let log = crate::config::log_dir().join("kwaainet.log");
-        Ok(format!(
-            "[Unit]\nDescription=KwaaiNet Node\nAfter=network.target\n\n\
-             [Service]\nExecStart={exe} run-node\nRestart=always\nRestartSec=10\n\
-             StandardOutput=append:{log}\nStandardError=append:{log}\n\n\
-             [Install]\nWantedBy=default.target\n",
-            exe = exe.display(),
-            log = log.display(),
-        ))
+    /// Scope of the installed unit, system first
+    fn installed_scope() -> Option<ServiceScope> {
+        [ServiceScope::System, ServiceScope::User]
+            .into_iter()
+            .find(|&scope| Self::unit_path(scope).exists())
+    }
+
+    fn systemctl(scope: ServiceScope, args: &[&str]) -> std::process::Command {
+        let mut cmd = std::process::Command::new("systemctl");
+        if scope == ServiceScope::User {
+            cmd.arg("--user");
+        }
+        cmd.args(args);
+        cmd
+    }
+}
+
+/// Everything a systemd unit for the node depends on besides the options
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+struct UnitParams<'a> {
+    exe: &'a Path,
+    environment: Vec<(&'static str, String)>,
+    user: Option<&'a str>,
+    log: &'a Path,
+    /// Paths left writable under `ProtectSystem=strict` / `ProtectHome`
+    writable: Vec<PathBuf>,
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn systemd_unit(params: &UnitParams, opts: &InstallOptions) -> String {
+    let mut service = vec!["Type=simple".to_string()];
+    if let Some(user) = params.user {
+        service.push(format!("User={user}"));
+    }
+    for (key, value) in &params.environment {
+        service.push(format!("Environment={key}={value}"));
     }
+    service.push(format!("ExecStart={} run-node", params.exe.display()));
+    // SIGHUP reloads config.yaml (see crate::config_reload).
+    service.push("ExecReload=/bin/kill -HUP $MAINPID".to_string());
+    service.push(format!("Restart={}", opts.restart.as_systemd()));
+    service.push(format!("RestartSec={}", opts.restart_sec));
+    // The node pings the watchdog from its event loop (see crate::liveness);
+    // systemd kills and restarts it when the pings stop.
+    service.push(format!(
+        "WatchdogSec={}",
+        crate::liveness::HUNG_AFTER.as_secs()
+    ));
+    service.push("NotifyAccess=main".to_string());
+    if let Some(max) = &opts.memory_max {
+        service.push(format!("MemoryMax={max}"));
+    }
+    if let Some(quota) = opts.cpu_quota {
+        service.push(format!("CPUQuota={quota}%"));
+    }
+    match opts.log_target {
+        LogTarget::File => {
+            service.push(format!("StandardOutput=append:{}", params.log.display()));
+            service.push(format!("StandardError=append:{}", params.log.display()));
+        }
+        LogTarget::Journal => {
+            service.push("StandardOutput=journal".to_string());
+            service.push("StandardError=journal".to_string());
+        }
+    }
+    if opts.sandbox {
+        service.extend(SANDBOX_OPTIONS.iter().map(|o| o.to_string()));
+        if opts.scope == ServiceScope::System {
+            service.extend(SYSTEM_SANDBOX_OPTIONS.iter().map(|o| o.to_string()));
+            let writable: Vec<String> = params
+                .writable
+                .iter()
+                .map(PathBuf::as_path)
+                .chain([Path::new(SHARED_TMP)])
+                .map(|p| format!("-{}", p.display()))
+                .collect();
+            service.push(format!("ReadWritePaths={}", writable.join(" ")));
+        }
+    }
+    let wanted_by = match opts.scope {
+        ServiceScope::User => "default.target",
+        ServiceScope::System => "multi-user.target",
+    };
+    format!(
+        "[Unit]\nDescription=KwaaiNet Node\nWants=network-online.target\n\
+         After=network-online.target\n\n[Service]\n{}\n\n[Install]\nWantedBy={wanted_by}\n",
+        service.join("\n"),
+    )
 }
 
 #[cfg(target_os = "linux")]
 impl ServiceManager for SystemdManager {
-    fn install(&self) -> Result<()> {
-        let path = Self::unit_path();
+    fn install(&self, opts: &InstallOptions) -> Result<()> {
+        opts.validate()?;
+        let run_as = RunAs::resolve(opts.scope)?;
+        if opts.log_target == LogTarget::File {
+            run_as.create_log_dir()?;
+        }
+        let exe = std::env::current_exe().context("finding own executable")?;
+        let mut environment = Vec::new();
+        if let Some(base) = &run_as.base_dir {
+            environment.push(("KWAAINET_HOME", base.display().to_string()));
+        }
+        if let Some(profile) = crate::config::active_profile() {
+            environment.push(("KWAAINET_PROFILE", profile));
+        }
+        // The node writes its data directory and model caches, and replaces
+        // its own binary when auto-update is on.
+        let mut writable = vec![
+            run_as.data_dir.clone(),
+            run_as.home.join(".cache"),
+            run_as.home.join(".ollama"),
+        ];
+        let auto_update = std::fs::read_to_string(run_as.data_dir.join("config.yaml"))
+            .ok()
+            .and_then(|text| serde_yaml::from_str::<crate::config::KwaaiNetConfig>(&text).ok())
+            .is_none_or(|cfg| cfg.contribute.auto_update);
+        if let (true, Some(dir)) = (auto_update, exe.parent()) {
+            writable.push(dir.to_path_buf());
+        }
+        let log = run_as.log_file();
+        let params = UnitParams {
+            exe: &exe,
+            environment,
+            user: run_as.user.as_ref().map(|u| u.name.as_str()),
+            log: &log,
+            writable,
+        };
+        let path = Self::unit_path(opts.scope);
         std::fs::create_dir_all(path.parent().unwrap())?;
-        std::fs::write(&path, Self::unit_content()?)?;
-        std::process::Command::new("systemctl")
-            .args(["--user", "daemon-reload"])
-            .status()?;
-        std::process::Command::new("systemctl")
-            .args(["--user", "enable", "--now", "kwaainet"])
-            .status()?;
-        info!("Installed systemd user service");
+        std::fs::write(&path, systemd_unit(&params, opts))?;
+        Self::systemctl(opts.scope, &["daemon-reload"]).status()?;
+        Self::systemctl(opts.scope, &["enable", "--now", &service_name()]).status()?;
+        info!(
+            "Installed systemd {} service at {}",
+            opts.scope.as_str(),
+            path.display()
+        );
         Ok(())
     }
 
     fn uninstall(&self) -> Result<()> {
-        std::process::Command::new("systemctl")
-            .args(["--user", "disable", "--now", "kwaainet"])
-            .status()?;
-        let path = Self::unit_path();
-        if path.exists() {
-            std::fs::remove_file(&path)?;
-        }
-        std::process::Command::new("systemctl")
-            .args(["--user", "daemon-reload"])
-            .status()?;
+        let Some(scope) = Self::installed_scope() else {
+            return Ok(());
+        };
+        if scope == ServiceScope::System {
+            require_root()?;
+        }
+        Self::systemctl(scope, &["disable", "--now", &service_name()]).status()?;
+        std::fs::remove_file(Self::unit_path(scope))?;
+        Self::systemctl(scope, &["daemon-reload"]).status()?;
         Ok(())
     }
 
     fn status(&self) -> ServiceStatus {
-        let installed = Self::unit_path().exists();
-        let out = std::process::Command::new("systemctl")
-            .args(["--user", "is-active", "kwaainet"])
+        let Some(scope) = Self::installed_scope() else {
+            return ServiceStatus::not_installed();
+        };
+        let path = Self::unit_path(scope);
+        let running = Self::systemctl(scope, &["is-active", &service_name()])
             .output()
-            .ok();
-        let running = out.map(|o| o.status.success()).unwrap_or(false);
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        let pid = Self::systemctl(
+            scope,
+            &["show", "-p", "MainPID", "--value", &service_name()],
+        )
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse().ok())
+        .filter(|&pid| pid > 0);
+        let settings = match std::fs::read_to_string(&path) {
+            Ok(text) => describe_unit(&parse_unit(&text)),
+            Err(e) => {
+                warn!("Cannot read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
         ServiceStatus {
-            installed,
-            loaded: installed,
+            installed: true,
+            loaded: true,
             running,
-            pid: None,
+            pid,
+            scope: Some(scope),
+            path: Some(path),
+            settings,
         }
     }
 
     fn restart(&self) -> Result<()> {
-        std::process::Command::new("systemctl")
-            .args(["--user", "restart", "kwaainet"])
-            .status()?;
+        let scope = Self::installed_scope().context("the service is not installed")?;
+        if scope == ServiceScope::System {
+            require_root()?;
+        }
+        Self::systemctl(scope, &["restart", &service_name()]).status()?;
         Ok(())
     }
 }
@@ -240,21 +873,153 @@ impl ServiceManager for SystemdManager {
 #[allow(dead_code)]
 struct NoopManager;
 impl ServiceManager for NoopManager {
-    fn install(&self) -> Result<()> {
+    fn install(&self, _opts: &InstallOptions) -> Result<()> {
         anyhow::bail!("Service management not supported on this platform")
     }
     fn uninstall(&self) -> Result<()> {
         anyhow::bail!("Service management not supported on this platform")
     }
     fn status(&self) -> ServiceStatus {
-        ServiceStatus {
-            installed: false,
-            loaded: false,
-            running: false,
-            pid: None,
-        }
+        ServiceStatus::not_installed()
     }
     fn restart(&self) -> Result<()> {
         anyhow::bail!("Service management not supported on this platform")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_unit_round_trips_through_status() {
+        let opts = InstallOptions {
+            scope: ServiceScope::System,
+            restart: RestartPolicy::OnFailure,
+            memory_max: Some("8G".into()),
+            cpu_quota: Some(200),
+            ..InstallOptions::default()
+        };
+        assert!(opts.validate().is_ok());
+        let params = UnitParams {
+            exe: Path::new("/usr/local/bin/kwaainet"),
+            environment: vec![("KWAAINET_HOME", "/home/alice/.kwaainet".into())],
+            user: Some("alice"),
+            log: Path::new("/home/alice/.kwaainet/logs/kwaainet.log"),
+            writable: vec![PathBuf::from("/home/alice/.kwaainet")],
+        };
+        let unit = systemd_unit(&params, &opts);
+        assert!(unit.contains("ReadWritePaths=-/home/alice/.kwaainet -/tmp\n"));
+        assert!(unit.contains("WantedBy=multi-user.target"));
+
+        let settings = describe_unit(&parse_unit(&unit));
+        let get = |label: &str| {
+            settings
+                .iter()
+                .find(|(l, _)| l == label)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(get("Restart"), Some("on-failure (after 10s)"));
+        assert_eq!(get("Watchdog"), Some("restart if hung 300s"));
+        assert_eq!(get("Runs as"), Some("alice"));
+        assert_eq!(get("Memory max"), Some("8G"));
+        assert_eq!(get("CPU quota"), Some("200%"));
+        assert_eq!(get("Logs"), Some("/home/alice/.kwaainet/logs/kwaainet.log"));
+        let all = SANDBOX_OPTIONS.len() + SYSTEM_SANDBOX_OPTIONS.len();
+        assert_eq!(get("Sandboxing"), Some(format!("{all} option(s)").as_str()));
+    }
+
+    #[test]
+    fn user_unit_skips_system_sandboxing() {
+        let opts = InstallOptions {
+            log_target: LogTarget::Journal,
+            ..InstallOptions::default()
+        };
+        let params = UnitParams {
+            exe: Path::new("/home/bob/.local/bin/kwaainet"),
+            environment: Vec::new(),
+            user: None,
+            log: Path::new("/home/bob/.kwaainet/logs/kwaainet.log"),
+            writable: Vec::new(),
+        };
+        let unit = systemd_unit(&params, &opts);
+        assert!(unit.contains("NoNewPrivileges=yes"));
+        assert!(!unit.contains("ProtectSystem"));
+        assert!(!unit.contains("User="));
+        let settings = describe_unit(&parse_unit(&unit));
+        assert!(settings.contains(&("Logs".into(), "journald".into())));
+    }
+
+    // The socket and temp paths checked are the Linux ones.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn system_unit_leaves_sockets_and_staging_writable() {
+        let opts = InstallOptions {
+            scope: ServiceScope::System,
+            ..InstallOptions::default()
+        };
+        let params = UnitParams {
+            exe: Path::new("/usr/local/bin/kwaainet"),
+            environment: Vec::new(),
+            user: Some("alice"),
+            log: Path::new("/home/alice/.kwaainet/logs/kwaainet.log"),
+            writable: vec![PathBuf::from("/home/alice/.kwaainet")],
+        };
+        let unit = parse_unit(&systemd_unit(&params, &opts));
+        assert!(unit.contains(&("ProtectSystem".into(), "strict".into())));
+        let writable: Vec<PathBuf> = unit
+            .iter()
+            .filter(|(k, _)| k == "ReadWritePaths")
+            .flat_map(|(_, v)| v.split_whitespace())
+            .map(|p| PathBuf::from(p.trim_start_matches('-')))
+            .collect();
+        let covered = |path: &Path| writable.iter().any(|w| path.starts_with(w));
+        assert!(covered(Path::new(kwaai_p2p_daemon::DEFAULT_SOCKET_NAME)));
+        assert!(covered(Path::new(&crate::config::profile_p2pd_socket(
+            "gpu1"
+        ))));
+        assert!(covered(&crate::updater::update_temp_dir()));
+    }
+
+    #[test]
+    fn plist_settings_are_read_back() {
+        let opts = InstallOptions {
+            restart: RestartPolicy::OnFailure,
+            restart_sec: 30,
+            ..InstallOptions::default()
+        };
+        let plist = launchd_plist(
+            "ai.kwaai.kwaainet",
+            Path::new("/usr/local/bin/kwaainet"),
+            &[("KWAAINET_PROFILE", "gpu1".into())],
+            Some("alice"),
+            Path::new("/Users/alice/.kwaainet/logs/kwaainet.log"),
+            &opts,
+        );
+        assert_eq!(
+            plist_value(&plist, "KWAAINET_PROFILE").as_deref(),
+            Some("gpu1")
+        );
+        assert_eq!(
+            describe_plist(&plist),
+            [
+                ("Restart".to_string(), "on-failure (after 30s)".to_string()),
+                ("Runs as".to_string(), "alice".to_string()),
+                (
+                    "Logs".to_string(),
+                    "/Users/alice/.kwaainet/logs/kwaainet.log".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_sizes() {
+        for ok in ["512M", "8G", "1073741824", "50%", "infinity"] {
+            assert!(is_memory_size(ok), "{ok}");
+        }
+        for bad in ["", "8GB", "G", "-1G", "150%", "lots"] {
+            assert!(!is_memory_size(bad), "{bad}");
+        }
+    }
+}
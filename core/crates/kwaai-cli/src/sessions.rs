@@ -0,0 +1,124 @@
+//! Attention-cache accounting for the shard server.
+//!
+//! Every remote session running through this server's blocks reserves the
+//! tokens its KV cache holds out of `cache_tokens`. The node announces what
+//! is left as `cache_tokens_left`, so coordinators can pick servers with room;
+//! a new session that does not fit is answered `Busy`. Sessions idle for
+//! `session_idle_secs` are expired and their caches dropped.
+//!
+//! The shard server writes the free capacity to `shard.capacity` in the run
+//! directory and has the node re-announce once it has moved far enough that
+//! the DHT record would mislead coordinators.
+
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use kwaai_distributed::{SessionConfig, SessionManager};
+use kwaai_inference::TransformerShard;
+use tracing::{debug, info};
+
+use crate::config::KwaaiNetConfig;
+
+/// How often idle sessions are expired.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Capacity changes are collected for this long before being written out,
+/// so a burst of decode steps costs one update.
+const ANNOUNCE_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Re-announce when free capacity moved by at least 1/this of the total.
+const ANNOUNCE_FRACTION: u64 = 16;
+
+static SESSIONS: OnceLock<Arc<SessionManager>> = OnceLock::new();
+
+/// Set up session tracking from `config`. Called once by the shard server.
+pub fn init(config: &KwaaiNetConfig) -> Arc<SessionManager> {
+    SESSIONS
+        .get_or_init(|| {
+            info!(
+                "Attention cache: {} tokens, sessions expire after {}s idle",
+                config.cache_tokens, config.session_idle_secs
+            );
+            Arc::new(SessionManager::new(SessionConfig {
+                cache_tokens: config.cache_tokens,
+                idle_timeout: Duration::from_secs(config.session_idle_secs),
+            }))
+        })
+        .clone()
+}
+
+/// The process-wide session manager, with default limits when [`init`] was
+/// not called.
+pub fn sessions() -> Arc<SessionManager> {
+    SESSIONS
+        .get_or_init(|| Arc::new(SessionManager::new(SessionConfig::default())))
+        .clone()
+}
+
+/// Whether the capacity announced as `announced` should be replaced by
+/// `left`: on a large enough move, or on filling up or emptying out.
+fn needs_reannounce(announced: u64, left: u64, total: u64) -> bool {
+    let step = (total / ANNOUNCE_FRACTION).max(1);
+    left != announced && (announced.abs_diff(left) >= step || left == 0 || left == total)
+}
+
+/// Expire idle sessions, dropping their KV caches from `shard`, and keep the
+/// node's announced capacity current. Runs until `shard` is dropped; start it
+/// once the initial capacity was written with [`write_capacity`].
+pub async fn maintain(sessions: Arc<SessionManager>, shard: Arc<TransformerShard>) {
+    // Don't keep a shard replaced by a rebalance alive.
+    let shard = Arc::downgrade(&shard);
+    let total = sessions.cache_tokens();
+    let mut capacity = sessions.subscribe();
+    let mut announced = *capacity.borrow_and_update();
+
+    let mut expire = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = expire.tick() => {
+                let Some(shard) = shard.upgrade() else {
+                    break;
+                };
+                for id in sessions.expire_idle() {
+                    shard.close_session(id);
+                }
+            }
+            changed = capacity.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                tokio::time::sleep(ANNOUNCE_DEBOUNCE).await;
+                let left = *capacity.borrow_and_update();
+                write_capacity(left);
+                if needs_reannounce(announced, left, total) {
+                    debug!(announced, left, "Cache capacity changed — re-announcing");
+                    announced = left;
+                    crate::daemon::DaemonManager::new().signal_reannounce();
+                }
+            }
+        }
+    }
+}
+
+/// Record `left` free cache tokens for the node's next announcement.
+pub fn write_capacity(left: u64) {
+    let _ = std::fs::write(
+        crate::daemon::ShardManager::capacity_file(),
+        left.to_string(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reannounces_on_large_moves_and_edges() {
+        let total = 1600;
+        assert!(!needs_reannounce(1600, 1550, total));
+        assert!(needs_reannounce(1600, 1500, total));
+        assert!(needs_reannounce(40, 0, total));
+        assert!(needs_reannounce(1590, 1600, total));
+        assert!(!needs_reannounce(0, 0, total));
+    }
+}
@@ -26,7 +26,7 @@ use serde::{Deserialize, Serialize};
 use std::{convert::Infallible, sync::Arc};
 use tokio::sync::Mutex;
 
-use crate::block_rpc::{f16_bytes_to_tensor, token_ids_to_bytes, InferenceRequest, PayloadType};
+use crate::block_rpc::{InferenceRequest, PayloadType};
 use crate::cli::ShardApiArgs;
 use crate::config::KwaaiNetConfig;
 use crate::display::*;
@@ -35,6 +35,7 @@ use crate::shard_cmd::{
     daemon_socket, discover_chain, forward_through_chain, load_circuit_by_id, sample_token,
     BlockServerEntry,
 };
+use kwaai_compression::TensorWire;
 
 // ── llama.cpp fast path (macOS Metal acceleration) ──────────────────────────
 
@@ -257,13 +258,14 @@ async fn run_inference(
     };
 
     loop {
-        let (shape, data) = token_ids_to_bytes(&current_ids);
         let request = InferenceRequest {
             session_id,
             seq_pos: seq_pos as u32,
             payload_type: PayloadType::TokenIds,
-            shape,
-            data,
+            tensor: TensorWire::from_u32(&current_ids),
+            active_adapter: None,
+            all_logits: false,
+            hidden_only: false,
         };
 
         let logits_bytes = match forward_through_chain(
@@ -291,13 +293,14 @@ async fn run_inference(
                     Ok(new_path) => {
                         pinned_path = new_path;
                         // Retry once with rebuilt path
-                        let (s2, d2) = token_ids_to_bytes(&current_ids);
                         let retry = InferenceRequest {
                             session_id,
                             seq_pos: seq_pos as u32,
                             payload_type: PayloadType::TokenIds,
-                            shape: s2,
-                            data: d2,
+                            tensor: TensorWire::from_u32(&current_ids),
+                            active_adapter: None,
+                            all_logits: false,
+                            hidden_only: false,
                         };
                         match forward_through_chain(
                             &mut client_guard,
@@ -330,8 +333,8 @@ async fn run_inference(
             }
         };
 
-        let logits_shape = &logits_bytes.shape;
-        let logits_tensor = match f16_bytes_to_tensor(&logits_bytes.data, logits_shape, &device) {
+        let logits_shape = &logits_bytes.tensor.shape;
+        let logits_tensor = match logits_bytes.tensor.to_tensor(&device) {
             Ok(t) => t,
             Err(e) => {
                 let _ = tx.send(format!("[tensor error: {e}]")).await;
@@ -686,7 +689,7 @@ fn detect_gguf_path(
     }
 
     // Auto-detect via Ollama (try the config model name)
-    if let Ok(path) = crate::ollama::resolve_model_blob(model_ref) {
+    if let Ok(path) = crate::ollama::find_model_blob(model_ref) {
         return Some(path);
     }
 
@@ -731,16 +734,9 @@ pub async fn run(args: ShardApiArgs) -> Result<()> {
 
     let cfg = KwaaiNetConfig::load_or_create()?;
     let model_ref = args.model.as_deref().unwrap_or(&cfg.model).to_string();
-    let dht_prefix = match &cfg.model_dht_prefix {
-        Some(p) => p.clone(),
-        None => {
-            let base = model_ref.split('/').next_back().unwrap_or(&model_ref);
-            base.replace('.', "-")
-        }
-    };
-    let total_blocks = args
-        .total_blocks
-        .unwrap_or_else(|| cfg.model_total_blocks() as usize);
+    let identity = cfg.identity_for(&model_ref);
+    let dht_prefix = identity.dht_prefix;
+    let total_blocks = args.total_blocks.unwrap_or(identity.num_blocks);
 
     // Resolve tokenizer directory
     let model_dir: std::path::PathBuf = if let Some(ref p) = args.model_path {
@@ -860,6 +856,13 @@ pub async fn run(args: ShardApiArgs) -> Result<()> {
         chain
     };
 
+    // Drop servers that fail `admission`, then rank the rest for each block
+    // by local reputation.
+    let mut chain = crate::admission::admit_servers(chain, &cfg.admission);
+    if cfg.reputation.enabled {
+        crate::shard_cmd::enrich_with_reputation(&mut chain, &crate::reputation::load_store());
+    }
+
     for (i, entry) in chain.iter().enumerate() {
         println!(
             "  [{:>2}] blocks {:>3}–{:>3}  {}",
@@ -921,6 +924,7 @@ pub async fn run(args: ShardApiArgs) -> Result<()> {
         .route("/v1/models", get(list_models))
         .route("/v1/chat/completions", post(chat_completions))
         .route("/v1/completions", post(completions))
+        .route("/v1/network/state", get(crate::network_cmd::network_state))
         .with_state(state);
 
     let addr = format!("0.0.0.0:{}", args.port);
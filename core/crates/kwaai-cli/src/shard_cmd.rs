@@ -15,24 +15,20 @@
 //! ```
 
 use anyhow::{bail, Context, Result};
-use kwaai_hivemind_dht::protocol::{FindRequest, FindResponse, NodeInfo, RequestAuthInfo};
-use kwaai_inference::{DeviceType, TransformerShard};
+use kwaai_hivemind_dht::protocol::{FindResponse, NodeInfo};
+use kwaai_inference::{
+    CausalLm, DeviceType, EngineConfig, GenerateOptions, InferenceEngine, TransformerShard,
+};
 use kwaai_p2p::NetworkConfig;
 use kwaai_p2p_daemon::{P2PClient, DEFAULT_SOCKET_NAME};
 use libp2p::PeerId;
 use prost::Message as _;
 use sha1::{Digest, Sha1};
-use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-    sync::Arc,
-    time::Duration,
-};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 use tokio::sync::RwLock;
 
 use crate::block_rpc::{
-    call_block_forward, f16_bytes_to_tensor, make_block_rpc_handler, token_ids_to_bytes,
-    InferenceRequest, PayloadType, ShardCell,
+    call_block_forward, make_block_rpc_handler, InferenceRequest, PayloadType, ShardCell,
 };
 use crate::cli::{
     CircuitAction, CircuitCloseArgs, CircuitCreateArgs, ShardAction, ShardArgs, ShardChainArgs,
@@ -41,30 +37,36 @@ use crate::cli::{
 use crate::config::KwaaiNetConfig;
 use crate::display::*;
 use crate::hf;
-use crate::reputation::{now_secs, PeerObservation, ReputationStore};
+use crate::reputation::{load_store, now_secs, PeerObservation, ReputationStore};
+use kwaai_compression::TensorWire;
 
 // ── Entrypoint ────────────────────────────────────────────────────────────────
 
 /// Outcome of a `cmd_shard_serve` invocation.
 enum ShardServeExit {
-    /// User pressed Ctrl-C — stop serving entirely.
+    /// User pressed Ctrl-C or the process got SIGTERM — stop serving entirely.
     UserStop,
-    /// Rebalancer signalled that blocks should move — re-run serve with `--auto`.
-    Rebalance,
+    /// Rebalancer decided to move to `[start, end)` — re-run serve on that range.
+    Rebalance(usize, usize),
 }
 
 pub async fn run(args: ShardArgs) -> Result<()> {
     match args.action {
         ShardAction::Serve(a) => {
-            // When --auto-rebalance is active we loop: after each rebalance
-            // signal we re-run cmd_shard_serve so pick_gap_blocks() re-queries
-            // the DHT and loads a fresh shard at the new block range.
+            // When auto-rebalance is active we loop: after each rebalance
+            // signal we re-run cmd_shard_serve on the range the rebalancer
+            // chose. The explicit-range path saves it to config.yaml and
+            // signals the daemon, which re-announces the new range.
+            let mut serve_args = a.clone();
             loop {
-                match cmd_shard_serve(a.clone()).await? {
+                match cmd_shard_serve(serve_args.clone()).await? {
                     ShardServeExit::UserStop => break,
-                    ShardServeExit::Rebalance => {
-                        print_info("Rebalancing — re-discovering gap and reloading shard…");
-                        // Next iteration calls pick_gap_blocks() fresh (default auto path).
+                    ShardServeExit::Rebalance(start, end) => {
+                        print_info(&format!(
+                            "Rebalancing — reloading shard for blocks [{start}, {end})…"
+                        ));
+                        serve_args.start_block = Some(start as u32);
+                        serve_args.auto = false;
                     }
                 }
             }
@@ -84,6 +86,20 @@ pub async fn run(args: ShardArgs) -> Result<()> {
     }
 }
 
+/// Split a `NAME=DIR` adapter spec; a bare `DIR` is named after its last
+/// path component.
+fn parse_adapter_spec(spec: &str) -> (String, PathBuf) {
+    if let Some((name, dir)) = spec.split_once('=') {
+        return (name.trim().to_string(), PathBuf::from(dir.trim()));
+    }
+    let dir = PathBuf::from(spec.trim());
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| spec.trim().to_string());
+    (name, dir)
+}
+
 // ── download ──────────────────────────────────────────────────────────────────
 
 pub async fn cmd_shard_download(args: ShardDownloadArgs) -> Result<()> {
@@ -94,17 +110,57 @@ pub async fn cmd_shard_download(args: ShardDownloadArgs) -> Result<()> {
     println!("  Model: {}", model_id);
     println!();
 
-    let snapshot_dir = match (args.start_block, args.blocks) {
+    let from_peers = args
+        .from_peer
+        .iter()
+        .map(|p| p.parse::<PeerId>().context("invalid --from-peer peer ID"))
+        .collect::<Result<Vec<_>>>()?;
+
+    let range = match (args.start_block, args.blocks) {
         (Some(start), Some(blocks)) => {
             let total = cfg.model_total_blocks() as usize;
             let end = (start + blocks).min(total);
-            let is_first = start == 0;
-            let is_last = end >= total;
             println!(
                 "  Blocks: [{}, {}) of {} (selective download)",
                 start, end, total
             );
             println!();
+            Some((start, end, start == 0, end >= total))
+        }
+        _ => None,
+    };
+
+    if args.p2p && range.is_none() {
+        anyhow::bail!("--p2p needs --start-block and --blocks");
+    }
+    let from_swarm = match range {
+        Some(blocks) if args.p2p => {
+            let found = crate::shard_transfer::download_from_swarm(
+                &model_id,
+                blocks,
+                args.hf_token.as_deref(),
+            )
+            .await?;
+            if found.is_none() {
+                print_warning("No peers provide these blocks; downloading from HuggingFace");
+            }
+            found
+        }
+        _ => None,
+    };
+
+    let snapshot_dir = match (from_swarm, from_peers.is_empty(), range) {
+        (Some(dir), _, _) => dir,
+        (None, false, range) => {
+            crate::shard_transfer::download_from_peers(
+                &from_peers,
+                &model_id,
+                range,
+                args.hf_token.as_deref(),
+            )
+            .await?
+        }
+        (None, true, Some((start, end, is_first, is_last))) => {
             hf::download_for_blocks(
                 &model_id,
                 start,
@@ -115,7 +171,7 @@ pub async fn cmd_shard_download(args: ShardDownloadArgs) -> Result<()> {
             )
             .await?
         }
-        _ => hf::download(&model_id, args.hf_token.as_deref()).await?,
+        (None, true, None) => hf::download(&model_id, args.hf_token.as_deref()).await?,
     };
 
     println!();
@@ -320,9 +376,31 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     } else {
         DeviceType::Cpu
     };
+    // Multi-GPU nodes spread their blocks over `device_map`; its first
+    // device receives the incoming hidden states.
+    let device_map = match (&cfg.device_map, device_type) {
+        (Some(map), DeviceType::Cuda(_) | DeviceType::Metal(_)) => Some(
+            map.parse::<kwaai_inference::DeviceMap>()
+                .map_err(|e| anyhow::anyhow!("invalid device_map '{map}': {e}"))?,
+        ),
+        _ => None,
+    };
+    let device_type = device_map.as_ref().map_or(device_type, |m| m.devices[0]);
     let device = device_type
         .to_candle_device()
         .context("Failed to create compute device")?;
+    crate::resources::init(&cfg.resources, &device);
+    let sessions = crate::sessions::init(&cfg);
+    tokio::spawn(crate::history::run_flush());
+    // Receipts are signed with the node's own key, so they name its PeerId.
+    let identity = match &cfg.identity_key {
+        Some(path) => crate::identity::NodeIdentity::load_from(path),
+        None => crate::identity::NodeIdentity::load_or_create(),
+    };
+    match identity {
+        Ok(identity) => crate::ledger::init(identity.keypair),
+        Err(e) => tracing::warn!("Contribution ledger disabled: {e:#}"),
+    }
 
     // ── Phase 1 complete — connect to p2pd and register placeholder handler ──
     // The node will appear on the map immediately while the model loads in the
@@ -347,18 +425,36 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     // Shared cell: None until the background load task writes Some(shard).
     let shard_cell: ShardCell = Arc::new(RwLock::new(None));
 
-    let handler = make_block_rpc_handler(shard_cell.clone(), device.clone());
+    // Shared with every request path so shutdown can refuse new sessions
+    // while letting in-flight ones finish.
+    let drain = crate::handoff::DrainGate::new();
+
+    // Bounds the forward passes waiting or running here; new sessions past
+    // it are answered Busy.
+    let queue = crate::request_queue::RequestQueue::new(cfg.queue_depth);
+
+    let handler = make_block_rpc_handler(
+        shard_cell.clone(),
+        device.clone(),
+        drain.clone(),
+        queue.clone(),
+    );
     client
-        .add_unary_handler(crate::block_rpc::INFERENCE_PROTO, handler, false)
+        .add_unary_handler(
+            crate::block_rpc::INFERENCE_PROTO,
+            crate::peer_filter::guard(handler),
+            false,
+        )
         .await
         .context("Failed to register inference handler with p2pd")?;
+    tokio::spawn(crate::peer_filter::run_address_refresh(client.clone()));
 
     // Ollama proxy — lets remote nodes route LLM requests to our local Ollama.
     let proxy_handler = crate::ollama_proxy::make_ollama_proxy_handler();
     let _ = client
         .add_unary_handler(
             crate::ollama_proxy::OLLAMA_PROXY_PROTO,
-            proxy_handler,
+            crate::peer_filter::guard(proxy_handler),
             false,
         )
         .await;
@@ -368,7 +464,7 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     let _ = client
         .add_unary_handler(
             crate::ollama_proxy::SHARD_PROXY_PROTO,
-            shard_proxy_handler,
+            crate::peer_filter::guard(shard_proxy_handler),
             false,
         )
         .await;
@@ -416,7 +512,9 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     // Start local TCP bypass server so `shard run` on the same machine can
     // call us without triggering libp2p's "dial to self" rejection.
     let _ = std::fs::create_dir_all(crate::config::run_dir());
-    match start_local_inference_server(shard_cell.clone(), device.clone()).await {
+    match start_local_inference_server(shard_cell.clone(), device.clone(), drain.clone(), queue)
+        .await
+    {
         Ok(port) => {
             if let Err(e) = std::fs::write(local_server_port_file(), port.to_string()) {
                 tracing::warn!("Could not write shard_local.port: {e}");
@@ -433,7 +531,19 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     let model_path_bg = args.model_path.clone();
     let hf_token_bg = args.hf_token.clone();
     let device_bg = device.clone();
+    let device_map_bg = device_map.clone();
     let total_blocks_bg = cfg.model_total_blocks() as usize;
+    let drain_bg = drain.clone();
+    let sessions_bg = sessions.clone();
+    let refresh_secs_bg = cfg.throughput_refresh_secs;
+    let p2p_fetch_bg = cfg.shard_p2p_fetch;
+    let cfg_bg = cfg.clone();
+    let adapters_bg: Vec<String> = cfg
+        .lora_adapters
+        .iter()
+        .chain(&args.adapters)
+        .cloned()
+        .collect();
 
     tokio::spawn(async move {
         let result: anyhow::Result<()> = async {
@@ -449,45 +559,72 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
                 match cached {
                     Some(d) => d,
                     None => {
-                        print_info(&format!(
-                            "Downloading model files for blocks [{}, {})…",
-                            start_block, end_block
-                        ));
-                        hf::download_for_blocks(
-                            &model_id_bg,
-                            start_block,
-                            end_block,
-                            is_first,
-                            is_last,
-                            hf_token_bg.as_deref(),
-                        )
-                        .await
-                        .context("selective download for blocks")?
+                        let blocks = (start_block, end_block, is_first, is_last);
+                        let from_swarm = if p2p_fetch_bg {
+                            match crate::shard_transfer::download_from_swarm(
+                                &model_id_bg,
+                                blocks,
+                                hf_token_bg.as_deref(),
+                            )
+                            .await
+                            {
+                                Ok(found) => found,
+                                Err(e) => {
+                                    tracing::warn!(
+                                        "P2P shard fetch failed, using HuggingFace: {e:#}"
+                                    );
+                                    None
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        if let Some(d) = from_swarm {
+                            d
+                        } else {
+                            print_info(&format!(
+                                "Downloading model files for blocks [{}, {})…",
+                                start_block, end_block
+                            ));
+                            hf::download_for_blocks(
+                                &model_id_bg,
+                                start_block,
+                                end_block,
+                                is_first,
+                                is_last,
+                                hf_token_bg.as_deref(),
+                            )
+                            .await
+                            .context("selective download for blocks")?
+                        }
                     }
                 }
             };
 
-            let config_path = model_dir.join("config.json");
-            let safetensors: Vec<PathBuf> = collect_safetensors(&model_dir)?;
-            if safetensors.is_empty() {
-                anyhow::bail!(
-                    "No .safetensors files found in {}. \
-                     Pass --model-path to a HuggingFace snapshot directory.",
-                    model_dir.display()
-                );
-            }
-            let paths: Vec<&Path> = safetensors.iter().map(|p| p.as_path()).collect();
-
             print_info(&format!(
-                "Loading shard ({} file(s), blocks [{}, {}))…",
-                safetensors.len(),
+                "Loading shard from {} (blocks [{}, {}))…",
+                model_dir.display(),
                 start_block,
                 end_block
             ));
-            let shard = Arc::new(
-                TransformerShard::load(&paths, &config_path, &device_bg, start_block, end_block)
-                    .context("Failed to load transformer shard")?,
+            if let Some(map) = &device_map_bg {
+                print_info(&format!("Splitting blocks across {map}"));
+            }
+            // Only the blocks in range (plus the embedding or LM head at the
+            // ends of the model) are read from the memory-mapped weights.
+            let mut engine = InferenceEngine::with_device(
+                EngineConfig {
+                    device: device_type,
+                    device_map: device_map_bg.clone(),
+                    max_memory: usize::MAX,
+                    ..EngineConfig::default()
+                },
+                device_bg.clone(),
             );
+            let handle = engine
+                .load_model_blocks(&model_dir, start_block..end_block)
+                .context("Failed to load transformer shard")?;
+            let shard = engine.blocks(&handle)?;
 
             print_success(&format!(
                 "Shard ready  ({} blocks, is_first={}, is_last={})",
@@ -496,23 +633,62 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
                 shard.is_last()
             ));
 
+            for spec in &adapters_bg {
+                let (name, dir) = parse_adapter_spec(spec);
+                match shard.load_adapter(&name, &dir) {
+                    Ok(()) => print_info(&format!("LoRA adapter '{name}' loaded")),
+                    Err(e) => print_warning(&format!("Skipping LoRA adapter '{name}': {e}")),
+                }
+            }
+
             // Make the shard available to the RPC handler.
             *cell_bg.write().await = Some(shard.clone());
 
+            // Offer the snapshot to peers and announce our layers in the DHT
+            // so new nodes can fetch them from us instead of HuggingFace.
+            // A single GGUF file is not a snapshot peers can fetch.
+            if model_dir.is_dir() {
+                match P2PClient::connect(&daemon_socket()).await {
+                    Ok(mut c) => match crate::shard_transfer::start_shard_transfer_server(
+                        &mut c,
+                        model_id_bg.clone(),
+                        model_dir.clone(),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            crate::shard_transfer::spawn_provider_loop(
+                                cfg_bg.clone(),
+                                model_id_bg.clone(),
+                                start_block,
+                                end_block,
+                            );
+                        }
+                        Err(e) => tracing::warn!("shard-transfer server failed to start: {e:#}"),
+                    },
+                    Err(e) => tracing::warn!("shard-transfer server: cannot reach p2pd: {e:#}"),
+                }
+            }
+
             // Signal daemon that inference is live — daemon will re-announce
             // with real block coverage instead of [0, 0).
+            // The ready file lists the loaded adapters for the announcement.
+            crate::sessions::write_capacity(sessions_bg.cache_tokens_left());
             let ready_file = crate::daemon::ShardManager::ready_file();
-            let _ = std::fs::write(&ready_file, "");
+            let _ = std::fs::write(&ready_file, shard.adapter_names().join("\n"));
             crate::daemon::DaemonManager::new().signal_reannounce();
 
-            // Background GC task: evict idle sessions every 30 s.
-            tokio::spawn(async move {
-                let mut interval = tokio::time::interval(Duration::from_secs(30));
-                loop {
-                    interval.tick().await;
-                    shard.gc_sessions();
-                }
-            });
+            // Measure what these blocks actually do on this device so the
+            // node announces real throughput instead of a default.
+            tokio::spawn(benchmark_loop(
+                shard.clone(),
+                model_id_bg.clone(),
+                drain_bg,
+                refresh_secs_bg,
+            ));
+
+            // Expire idle sessions and re-announce as cache capacity moves.
+            tokio::spawn(crate::sessions::maintain(sessions_bg, shard));
 
             Ok(())
         }
@@ -532,7 +708,7 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     // tokio::select! compiles with the same shape in both branches.
 
     let cfg_rb = KwaaiNetConfig::load_or_create()?;
-    let do_rebalance = args.auto_rebalance;
+    let do_rebalance = args.auto_rebalance || cfg_rb.auto_rebalance;
     let interval_secs = cfg_rb.rebalance_interval_secs;
     let min_redundancy = cfg_rb.rebalance_min_redundancy;
     let total_blocks_rb = cfg_rb.model_total_blocks() as usize;
@@ -546,15 +722,14 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
     let daemon_addr_rb = daemon_socket();
 
     // oneshot used by the rebalancer to signal the main loop.
-    let rebalance_fut: std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> =
+    let rebalance_fut: std::pin::Pin<Box<dyn std::future::Future<Output = (usize, usize)> + Send>> =
         if do_rebalance {
-            let (rebalance_tx, rebalance_rx) = tokio::sync::oneshot::channel::<()>();
+            let (rebalance_tx, rebalance_rx) = tokio::sync::oneshot::channel::<(usize, usize)>();
 
             tokio::spawn(async move {
                 // Jitter: 0–60 s derived from our peer ID's last byte so nodes with
                 // the same rebalance_interval_secs don't all fire at the same instant.
-                let jitter_secs: u64 = if let Ok(mut c) = P2PClient::connect(&daemon_addr_rb).await
-                {
+                let jitter_secs: u64 = if let Ok(c) = P2PClient::connect(&daemon_addr_rb).await {
                     if let Ok(h) = c.identify().await {
                         hex::decode(&h)
                             .ok()
@@ -610,7 +785,7 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
                     )
                     .await;
 
-                    if crate::rebalancer::check_rebalance(
+                    if let Some((new_start, new_end)) = crate::rebalancer::check_rebalance(
                         &chain,
                         &pid,
                         start_block,
@@ -618,14 +793,13 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
                         total_blocks_rb,
                         target_blocks_rb,
                         min_redundancy,
-                    )
-                    .is_some()
-                    {
+                    ) {
                         print_info(&format!(
                             "Rebalance: blocks [{start_block},{end_block}) have \
-                             ≥{min_redundancy} other node(s); gap detected — moving."
+                             ≥{min_redundancy} other node(s); [{new_start},{new_end}) \
+                             is under-covered — moving."
                         ));
-                        let _ = rebalance_tx.send(());
+                        let _ = rebalance_tx.send((new_start, new_end));
                         break;
                     }
                     print_info("Rebalance check: coverage OK, no move needed.");
@@ -633,33 +807,114 @@ async fn cmd_shard_serve(args: ShardServeArgs) -> Result<ShardServeExit> {
             });
 
             Box::pin(async move {
-                let _ = rebalance_rx.await;
+                match rebalance_rx.await {
+                    Ok(range) => range,
+                    // Rebalancer task ended without a decision — never fire.
+                    Err(_) => futures::future::pending().await,
+                }
             })
         } else {
-            Box::pin(futures::future::pending::<()>())
+            Box::pin(futures::future::pending::<(usize, usize)>())
         };
 
-    // ── Wait: Ctrl-C or rebalance signal ─────────────────────────────────────
+    // ── Wait: Ctrl-C / SIGTERM or rebalance signal ───────────────────────────
     let exit = tokio::select! {
-        res = tokio::signal::ctrl_c() => {
-            res.context("ctrl-c handler")?;
-            ShardServeExit::UserStop
-        }
-        _ = rebalance_fut => {
-            ShardServeExit::Rebalance
-        }
+        _ = crate::node::shutdown_signal() => ShardServeExit::UserStop,
+        (start, end) = rebalance_fut => ShardServeExit::Rebalance(start, end),
     };
 
-    let _ = std::fs::remove_file(local_server_port_file());
+    // ── Drain: refuse new sessions, let in-flight ones finish ────────────────
+    // Dropping the ready file and re-announcing flips our DHT state back to
+    // JOINING, so coordinators stop picking us for new sessions while the
+    // ones already holding KV cache here run to completion.
+    drain.start();
     let _ = std::fs::remove_file(crate::daemon::ShardManager::ready_file());
+    crate::daemon::DaemonManager::new().signal_reannounce();
+    let grace = Duration::from_secs(cfg.shutdown_grace_secs);
+    if drain.in_flight() > 0 {
+        print_info(&format!(
+            "Draining {} in-flight request(s) (up to {}s)…",
+            drain.in_flight(),
+            grace.as_secs()
+        ));
+    }
+    match drain.drained(grace).await {
+        crate::handoff::DrainOutcome::Idle => {}
+        crate::handoff::DrainOutcome::TimedOut { in_flight } => print_warning(&format!(
+            "Grace period of {}s elapsed with {in_flight} request(s) still running",
+            grace.as_secs()
+        )),
+    }
+
+    // KV caches go with this shard; a rebalanced server starts empty.
+    sessions.clear();
+    let _ = std::fs::remove_file(crate::daemon::ShardManager::capacity_file());
+    let _ = std::fs::remove_file(local_server_port_file());
     println!();
     match exit {
         ShardServeExit::UserStop => print_info("Shard server stopped."),
-        ShardServeExit::Rebalance => print_info("Shard server stopping for rebalance."),
+        ShardServeExit::Rebalance(..) => print_info("Shard server stopping for rebalance."),
     }
     Ok(exit)
 }
 
+// ── Throughput benchmark ──────────────────────────────────────────────────────
+
+/// Decode steps and prefill length of one benchmark run — enough to average
+/// out scheduler noise while staying a few seconds long on CPU.
+pub(crate) const BENCH_DECODE_STEPS: usize = 16;
+pub(crate) const BENCH_PREFILL_TOKENS: usize = 128;
+
+/// Benchmark the loaded shard, record the per-block rates in the throughput
+/// cache and ask the node to re-announce. Repeats every `refresh_secs`
+/// (0 = once), skipping rounds while requests are being served so the
+/// benchmark neither slows real sessions nor measures a contended device.
+async fn benchmark_loop(
+    shard: Arc<TransformerShard>,
+    model: String,
+    drain: crate::handoff::DrainGate,
+    refresh_secs: u64,
+) {
+    loop {
+        if drain.is_draining() {
+            return;
+        }
+        if drain.in_flight() == 0 {
+            let bench = shard.clone();
+            let started = std::time::Instant::now();
+            let result = tokio::task::spawn_blocking(move || {
+                bench.benchmark(BENCH_DECODE_STEPS, BENCH_PREFILL_TOKENS)
+            })
+            .await;
+            match result {
+                Ok(Ok(rates)) => {
+                    let blocks = (shard.end_block - shard.start_block).max(1) as f64;
+                    tracing::info!(
+                        inference_rps = format!("{:.1}", rates.inference_rps),
+                        forward_rps = format!("{:.1}", rates.forward_rps),
+                        span_tps = format!("{:.1}", rates.inference_rps / blocks),
+                        took_ms = started.elapsed().as_millis() as u64,
+                        "Block throughput measured"
+                    );
+                    match crate::throughput::save_block_rates(&model, shard.cfg.hidden_dim, &rates)
+                    {
+                        Ok(()) => crate::daemon::DaemonManager::new().signal_reannounce(),
+                        Err(e) => tracing::warn!("Could not save block throughput: {e}"),
+                    }
+                }
+                Ok(Err(e)) => tracing::warn!("Block throughput benchmark failed: {e}"),
+                Err(e) => tracing::warn!("Block throughput benchmark panicked: {e}"),
+            }
+        } else {
+            tracing::debug!("Skipping throughput benchmark — shard is busy");
+        }
+        if refresh_secs == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(refresh_secs)).await;
+    }
+}
+
 // ── run --local (in-process, no networking) ───────────────────────────────────
 
 /// Load the model in-process and run inference without any P2P or TCP overhead.
@@ -713,9 +968,12 @@ async fn cmd_shard_run_local(args: ShardRunArgs) -> Result<()> {
 
     // If `shard serve` is already running on this machine, reuse its loaded model
     // via the local TCP bypass server instead of loading the model a second time.
+    // Speculative decoding needs all-position logits, which the bypass loop
+    // does not request, so --draft-model always loads the model in-process.
     let local_port: Option<u16> = std::fs::read_to_string(local_server_port_file())
         .ok()
         .and_then(|s| s.trim().parse().ok())
+        .filter(|_| args.draft_model.is_none())
         .filter(|&port| {
             // Quick liveness check: verify the port is still accepting connections.
             // A stale port file is common after an unclean shutdown.
@@ -797,6 +1055,10 @@ async fn cmd_shard_run_local(args: ShardRunArgs) -> Result<()> {
     println!();
 
     let session_id: u64 = args.session_id.unwrap_or_else(rand_session_id);
+    if args.draft_model.is_some() {
+        let mut target = kwaai_inference::ShardSession::new(&shard, session_id);
+        return run_speculative(&args, device_type, &formatted_prompt, &mut target);
+    }
     let max_tokens = args.max_tokens;
     let temperature = args.temperature;
     let top_k = args.top_k;
@@ -895,6 +1157,123 @@ async fn cmd_shard_run_local(args: ShardRunArgs) -> Result<()> {
     Ok(())
 }
 
+// ── speculative decoding (--draft-model) ─────────────────────────────────────
+
+/// The block chain as a [`CausalLm`] target: each call runs the tokens
+/// through the pinned path and asks the last node for every position's logits.
+struct ChainTarget<'a> {
+    client: &'a mut P2PClient,
+    path: &'a [BlockServerEntry],
+    total_blocks: usize,
+    session_id: u64,
+    our_peer_id: &'a PeerId,
+    failed_peers: &'a mut std::collections::HashSet<PeerId>,
+    reputation: Option<Arc<std::sync::Mutex<ReputationStore>>>,
+    adapter: Option<String>,
+}
+
+impl CausalLm for ChainTarget<'_> {
+    fn forward_all(
+        &mut self,
+        tokens: &[u32],
+        pos: usize,
+    ) -> kwaai_inference::InferenceResult<candle_core::Tensor> {
+        use kwaai_inference::InferenceError;
+        let request = InferenceRequest {
+            session_id: self.session_id,
+            seq_pos: pos as u32,
+            payload_type: PayloadType::TokenIds,
+            tensor: TensorWire::from_u32(tokens),
+            active_adapter: self.adapter.clone(),
+            all_logits: true,
+            hidden_only: false,
+        };
+        // Called from inside `block_in_place`, so blocking on the chain is fine.
+        let response = tokio::runtime::Handle::current()
+            .block_on(forward_through_chain(
+                self.client,
+                self.path,
+                self.total_blocks,
+                self.session_id,
+                pos as u32,
+                request,
+                Some(self.our_peer_id),
+                self.failed_peers,
+                None,
+                self.reputation.clone(),
+            ))
+            .map_err(|e| InferenceError::InferenceFailed(format!("{e:#}")))?;
+        let logits = response
+            .tensor
+            .to_tensor(&candle_core::Device::Cpu)
+            .map_err(|e| InferenceError::InferenceFailed(format!("decode logits: {e}")))?;
+        // [1, seq_len, vocab] → [seq_len, vocab]
+        logits.squeeze(0).map_err(InferenceError::from)
+    }
+}
+
+/// Temperature for speculative decoding, following [`sample_token`]'s rule
+/// that 1.0 with no top-k/top-p means greedy.
+fn speculative_temperature(temperature: f32, top_k: usize, top_p: f32) -> f64 {
+    if (temperature <= 0.0 || temperature == 1.0) && top_k == 0 && top_p >= 1.0 {
+        0.0
+    } else {
+        temperature.max(0.0) as f64
+    }
+}
+
+/// Generate with `--draft-model` proposing tokens and `target` verifying them.
+fn run_speculative(
+    args: &ShardRunArgs,
+    device: kwaai_inference::DeviceType,
+    prompt: &str,
+    target: &mut dyn CausalLm,
+) -> Result<()> {
+    let engine = InferenceEngine::new(EngineConfig {
+        device,
+        draft_model: args.draft_model.clone(),
+        speculative_tokens: args.draft_tokens,
+        ..EngineConfig::default()
+    })?;
+    let opts = GenerateOptions {
+        max_new_tokens: args.max_tokens,
+        temperature: speculative_temperature(args.temperature, args.top_k, args.top_p),
+        ..GenerateOptions::default()
+    };
+    if let Some(dir) = &args.draft_model {
+        println!("  Draft model:  {}", dir.display());
+    }
+    println!("  Draft tokens: {}", args.draft_tokens);
+    println!();
+
+    let start = std::time::Instant::now();
+    let generation =
+        tokio::task::block_in_place(|| engine.generate_speculative(target, prompt, &opts))
+            .context("speculative generation")?;
+    let secs = start.elapsed().as_secs_f64();
+
+    println!("  Assistant:");
+    println!("  {}", generation.text);
+    println!();
+    print_success(&format!(
+        "Generated {} tok  •  {:.1} tok/s  •  {:.1}s",
+        generation.completion_tokens,
+        generation.completion_tokens as f64 / secs.max(1e-9),
+        secs
+    ));
+    if let Some(stats) = generation.speculative {
+        println!(
+            "  Drafts accepted: {}/{} ({:.0}%)  •  {:.2} tok per target pass",
+            stats.accepted,
+            stats.drafted,
+            stats.acceptance_rate() * 100.0,
+            stats.tokens_per_round()
+        );
+    }
+    print_separator();
+    Ok(())
+}
+
 // ── run via remote Ollama (mux:// or http://) ────────────────────────────────
 
 /// Bypass block sharding: proxy the prompt to a remote Ollama via mux:// or
@@ -1014,17 +1393,9 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
     let cfg = KwaaiNetConfig::load_or_create()?;
 
     let model_ref = args.model.as_deref().unwrap_or(&cfg.model);
-    // If a model was passed on the CLI that differs from config, build a temporary
-    // config with that model name so effective_dht_prefix() derives the right key.
-    let dht_prefix = if args.model.is_some() && args.model.as_deref() != Some(&cfg.model) {
-        let base = model_ref.split('/').next_back().unwrap_or(model_ref);
-        base.replace('.', "-")
-    } else {
-        cfg.effective_dht_prefix()
-    };
-    let total_blocks = args
-        .total_blocks
-        .unwrap_or_else(|| cfg.model_total_blocks() as usize);
+    let identity = cfg.identity_for(model_ref);
+    let dht_prefix = identity.dht_prefix;
+    let total_blocks = args.total_blocks.unwrap_or(identity.num_blocks);
 
     print_box_header("🔗 KwaaiNet Distributed Inference");
     println!("  Model:        {}", model_ref);
@@ -1120,22 +1491,49 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
     let _ = using_circuit; // used for display above
     println!("{} node(s)", chain.len());
 
-    // Load reputation store and enrich chain entries with local trust scores.
-    let rep_store = if cfg.reputation.enabled {
-        let store = ReputationStore::load();
-        let mut enriched = chain;
-        for entry in &mut enriched {
-            let peer_b58 = entry.peer_id.to_base58();
-            let score = store.score(&peer_b58);
-            entry.trust_score = Some(score.score);
+    // Every block must run with the adapter, so keep only servers that load it.
+    let chain = match args.adapter.as_deref() {
+        Some(adapter) => {
+            let with_adapter = filter_by_adapter(chain, adapter);
+            if with_adapter.is_empty() {
+                print_warning(&format!(
+                    "No block servers announce LoRA adapter {adapter:?}."
+                ));
+                print_separator();
+                return Ok(());
+            }
+            println!("  Adapter:      {adapter} ({} node(s))", with_adapter.len());
+            with_adapter
         }
-        // Re-assign so borrow checker is happy.
-        let rep = Arc::new(std::sync::Mutex::new(store));
-        (enriched, Some(rep))
+        None => chain,
+    };
+
+    // Activations stay in one dtype end to end, so every hop must support it.
+    let chain = match args.dtype.as_deref() {
+        Some(dtype) => {
+            let with_dtype = filter_by_dtype(chain, dtype);
+            if with_dtype.is_empty() {
+                print_warning(&format!(
+                    "No block servers announce an accelerator supporting {dtype}."
+                ));
+                print_separator();
+                return Ok(());
+            }
+            println!("  Dtype:        {dtype} ({} node(s))", with_dtype.len());
+            with_dtype
+        }
+        None => chain,
+    };
+
+    // Load reputation store and enrich chain entries with local trust scores.
+    let mut chain = crate::admission::admit_servers(chain, &cfg.admission);
+    let reputation = if cfg.reputation.enabled {
+        let store = load_store();
+        enrich_with_reputation(&mut chain, &store);
+        Some(Arc::new(std::sync::Mutex::new(store)))
     } else {
-        (chain, None)
+        None
     };
-    let (chain, reputation) = rep_store;
 
     // Validate coverage
     let covered = coverage_check(&chain, total_blocks);
@@ -1250,6 +1648,25 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
     }
     println!();
 
+    if args.draft_model.is_some() {
+        let mut target = ChainTarget {
+            client: &mut client,
+            path: &pinned_path,
+            total_blocks,
+            session_id,
+            our_peer_id: &our_peer_id,
+            failed_peers: &mut failed_peers,
+            reputation: reputation.clone(),
+            adapter: args.adapter.clone(),
+        };
+        let device_type = if args.no_gpu {
+            kwaai_inference::DeviceType::Cpu
+        } else {
+            kwaai_inference::DeviceType::detect_best_logged()
+        };
+        return run_speculative(&args, device_type, &formatted_prompt, &mut target);
+    }
+
     let show_stats = args.stats;
     let mut token_times_ms: Vec<f64> = Vec::new();
     // Per-token hop breakdowns: outer index = token, inner = hops for that token.
@@ -1266,13 +1683,14 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
         let token_start = std::time::Instant::now();
 
         // Build first request
-        let (shape, data) = token_ids_to_bytes(&current_ids);
         let request = InferenceRequest {
             session_id,
             seq_pos: seq_pos as u32,
             payload_type: PayloadType::TokenIds,
-            shape,
-            data,
+            tensor: TensorWire::from_u32(&current_ids),
+            active_adapter: args.adapter.clone(),
+            all_logits: false,
+            hidden_only: false,
         };
 
         // Forward through the pinned path
@@ -1304,13 +1722,14 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
                 pinned_path = build_pinned_path(&chain, total_blocks, &failed_peers)?;
                 token_hops.clear();
                 // Retry this token with the new path
-                let (shape2, data2) = token_ids_to_bytes(&current_ids);
                 let retry_req = InferenceRequest {
                     session_id,
                     seq_pos: seq_pos as u32,
                     payload_type: PayloadType::TokenIds,
-                    shape: shape2,
-                    data: data2,
+                    tensor: TensorWire::from_u32(&current_ids),
+                    active_adapter: args.adapter.clone(),
+                    all_logits: false,
+                    hidden_only: false,
                 };
                 let result = forward_through_chain(
                     &mut client,
@@ -1356,9 +1775,11 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
 
         // logits_bytes.data is f16 bytes of shape [1, 1, vocab_size] or [1, seq_len, vocab_size]
         // We need only the last position
-        let logits_shape = &logits_bytes.shape;
+        let logits_shape = &logits_bytes.tensor.shape;
         let device = candle_core::Device::Cpu;
-        let logits_tensor = f16_bytes_to_tensor(&logits_bytes.data, logits_shape, &device)
+        let logits_tensor = logits_bytes
+            .tensor
+            .to_tensor(&device)
             .context("decode logits tensor")?;
 
         // Take last token position: [1, seq_len, vocab_size] → [vocab_size]
@@ -1498,8 +1919,7 @@ pub async fn cmd_shard_run(args: ShardRunArgs) -> Result<()> {
 // stdout decoration (box headers, spinners, per-hop stats, etc.) is unchanged
 // and verifiable byte-for-byte. The shared substrate is the existing pub
 // helpers — `discover_chain`, `build_pinned_path`, `forward_through_chain`,
-// `sample_token`, `f16_bytes_to_tensor`, `token_ids_to_bytes`,
-// `BpeTokenizer::from_file` — so the only duplication is the ~50-line outer
+// `sample_token`, `TensorWire`, `BpeTokenizer::from_file` — so the only duplication is the ~50-line outer
 // loop driver, which differs between the two consumers anyway.
 
 /// Options for [`run_streaming`].
@@ -1513,6 +1933,12 @@ pub struct ShardRunOptions {
     pub max_tokens: Option<usize>,
     /// Pre-formed circuit id to use instead of fresh DHT discovery.
     pub circuit_id: Option<String>,
+    /// Stop generating once this instant passes. The tokens already sent
+    /// stand and the run ends with `Done { truncated_by_deadline: true }`.
+    pub deadline: Option<std::time::Instant>,
+    /// Strings that end generation. Neither they nor anything after them
+    /// is sent.
+    pub stop: Vec<String>,
 }
 
 /// Events emitted by [`run_streaming`]. Every successful generation ends with
@@ -1521,8 +1947,9 @@ pub enum ShardRunEvent {
     /// A decoded text piece for the next generated token. Multiple `Token`s
     /// arrive before the terminator.
     Token(String),
-    /// Generation finished cleanly (EOS hit or `max_tokens` reached).
-    Done,
+    /// Generation finished cleanly: EOS hit, `max_tokens` reached, or the
+    /// deadline passed (`truncated_by_deadline`).
+    Done { truncated_by_deadline: bool },
     /// Unrecoverable error mid-generation. No further events follow.
     Error(anyhow::Error),
 }
@@ -1555,8 +1982,12 @@ pub fn run_streaming(opts: ShardRunOptions) -> impl futures::Stream<Item = Shard
     tokio::spawn(async move {
         let result = run_streaming_inner(opts, tx.clone()).await;
         match result {
-            Ok(()) => {
-                let _ = tx.send(ShardRunEvent::Done).await;
+            Ok(truncated_by_deadline) => {
+                let _ = tx
+                    .send(ShardRunEvent::Done {
+                        truncated_by_deadline,
+                    })
+                    .await;
             }
             Err(e) => {
                 let _ = tx.send(ShardRunEvent::Error(e)).await;
@@ -1575,20 +2006,15 @@ pub fn run_streaming(opts: ShardRunOptions) -> impl futures::Stream<Item = Shard
 /// piece into `tx` as a `ShardRunEvent::Token`. The terminator (`Done` or
 /// `Error`) is emitted by the caller in `run_streaming` based on the
 /// `Result` returned from here, so the worker never sends `Done`/`Error`
-/// itself.
+/// itself. Returns `true` if generation was cut short by `opts.deadline`.
 async fn run_streaming_inner(
     opts: ShardRunOptions,
     tx: tokio::sync::mpsc::Sender<ShardRunEvent>,
-) -> Result<()> {
+) -> Result<bool> {
     let cfg = KwaaiNetConfig::load_or_create()?;
     let model_ref = opts.model.as_deref().unwrap_or(&cfg.model).to_string();
-    let dht_prefix = if opts.model.is_some() && opts.model.as_deref() != Some(&cfg.model) {
-        let base = model_ref.split('/').next_back().unwrap_or(&model_ref);
-        base.replace('.', "-")
-    } else {
-        cfg.effective_dht_prefix()
-    };
-    let total_blocks = cfg.model_total_blocks() as usize;
+    let identity = cfg.identity_for(&model_ref);
+    let (dht_prefix, total_blocks) = (identity.dht_prefix, identity.num_blocks);
 
     // Default max_tokens matches the CLI's clap default (see ShardRunArgs).
     let max_tokens = opts.max_tokens.unwrap_or(200);
@@ -1652,16 +2078,13 @@ async fn run_streaming_inner(
     };
 
     // Enrich with reputation scores if enabled (mirrors cmd_shard_run).
-    let (chain, reputation) = if cfg.reputation.enabled {
-        let store = ReputationStore::load();
-        let mut enriched = chain;
-        for entry in &mut enriched {
-            let peer_b58 = entry.peer_id.to_base58();
-            entry.trust_score = Some(store.score(&peer_b58).score);
-        }
-        (enriched, Some(Arc::new(std::sync::Mutex::new(store))))
+    let mut chain = crate::admission::admit_servers(chain, &cfg.admission);
+    let reputation = if cfg.reputation.enabled {
+        let store = load_store();
+        enrich_with_reputation(&mut chain, &store);
+        Some(Arc::new(std::sync::Mutex::new(store)))
     } else {
-        (chain, None)
+        None
     };
 
     // ── Tokenize / format prompt (matches cmd_shard_run exactly) ──
@@ -1691,7 +2114,14 @@ async fn run_streaming_inner(
     if let Some(bos) = tokenizer.bos_token_id() {
         token_ids.insert(0, bos);
     }
-    let eos_id = tokenizer.eos_token_id().unwrap_or(2);
+    let mut stop_ids = vec![tokenizer.eos_token_id().unwrap_or(2)];
+    stop_ids.extend(
+        ["<|eot_id|>", "<|im_end|>"]
+            .iter()
+            .filter_map(|t| tokenizer.token_to_id(t)),
+    );
+    kwaai_inference::StopCriteria::validate(&opts.stop)?;
+    let mut criteria = kwaai_inference::StopCriteria::new(stop_ids, &opts.stop, max_tokens);
     let session_id: u64 = rand_session_id();
 
     // Best-effort dial of every server in the chain (matches CLI).
@@ -1705,66 +2135,91 @@ async fn run_streaming_inner(
     let mut failed_peers: std::collections::HashSet<PeerId> = std::collections::HashSet::new();
     let mut pinned_path = build_pinned_path(&chain, total_blocks, &failed_peers)?;
 
-    let mut generated_ids: Vec<u32> = Vec::new();
     let mut seq_pos: usize = 0;
     let mut current_ids = token_ids.clone();
 
     loop {
-        let (shape, data) = token_ids_to_bytes(&current_ids);
+        if opts
+            .deadline
+            .is_some_and(|d| std::time::Instant::now() >= d)
+        {
+            flush_held_back(&tx, &mut criteria).await;
+            return Ok(true);
+        }
+
         let request = InferenceRequest {
             session_id,
             seq_pos: seq_pos as u32,
             payload_type: PayloadType::TokenIds,
-            shape,
-            data,
+            tensor: TensorWire::from_u32(&current_ids),
+            active_adapter: None,
+            all_logits: false,
+            hidden_only: false,
         };
 
-        let logits_bytes = match forward_through_chain(
-            &mut client,
-            &pinned_path,
-            total_blocks,
-            session_id,
-            seq_pos as u32,
-            request,
-            Some(&our_peer_id),
-            &mut failed_peers,
-            None,
-            reputation.clone(),
-        )
-        .await
-        {
-            Ok(r) => r,
-            Err(_) => {
-                // Rebuild path on transient failure and retry once,
-                // matching cmd_shard_run's recovery behaviour.
-                pinned_path = build_pinned_path(&chain, total_blocks, &failed_peers)?;
-                let (shape2, data2) = token_ids_to_bytes(&current_ids);
-                let retry_req = InferenceRequest {
-                    session_id,
-                    seq_pos: seq_pos as u32,
-                    payload_type: PayloadType::TokenIds,
-                    shape: shape2,
-                    data: data2,
-                };
-                forward_through_chain(
-                    &mut client,
-                    &pinned_path,
-                    total_blocks,
-                    session_id,
-                    seq_pos as u32,
-                    retry_req,
-                    Some(&our_peer_id),
-                    &mut failed_peers,
-                    None,
-                    reputation.clone(),
-                )
-                .await?
+        let step = async {
+            match forward_through_chain(
+                &mut client,
+                &pinned_path,
+                total_blocks,
+                session_id,
+                seq_pos as u32,
+                request,
+                Some(&our_peer_id),
+                &mut failed_peers,
+                None,
+                reputation.clone(),
+            )
+            .await
+            {
+                Ok(r) => Ok::<_, anyhow::Error>(r),
+                Err(_) => {
+                    // Rebuild path on transient failure and retry once,
+                    // matching cmd_shard_run's recovery behaviour.
+                    pinned_path = build_pinned_path(&chain, total_blocks, &failed_peers)?;
+                    let retry_req = InferenceRequest {
+                        session_id,
+                        seq_pos: seq_pos as u32,
+                        payload_type: PayloadType::TokenIds,
+                        tensor: TensorWire::from_u32(&current_ids),
+                        active_adapter: None,
+                        all_logits: false,
+                        hidden_only: false,
+                    };
+                    forward_through_chain(
+                        &mut client,
+                        &pinned_path,
+                        total_blocks,
+                        session_id,
+                        seq_pos as u32,
+                        retry_req,
+                        Some(&our_peer_id),
+                        &mut failed_peers,
+                        None,
+                        reputation.clone(),
+                    )
+                    .await
+                }
             }
         };
+        // A hop still in flight when the deadline passes is abandoned; the
+        // partial output already streamed is the result.
+        let logits_bytes = match opts.deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline.into(), step).await {
+                Ok(r) => r?,
+                Err(_) => {
+                    flush_held_back(&tx, &mut criteria).await;
+                    return Ok(true);
+                }
+            },
+            None => step.await?,
+        };
 
-        let logits_shape = &logits_bytes.shape;
+        let logits_shape = &logits_bytes.tensor.shape;
         let device = candle_core::Device::Cpu;
-        let logits_tensor = f16_bytes_to_tensor(&logits_bytes.data, logits_shape, &device)
+        let logits_tensor = logits_bytes
+            .tensor
+            .to_tensor(&device)
             .context("decode logits tensor")?;
         let last_logits = if logits_shape.len() == 3 && logits_shape[1] > 1 {
             use candle_core::IndexOp as _;
@@ -1775,23 +2230,33 @@ async fn run_streaming_inner(
         };
         let next_id = sample_token(&last_logits, temperature, top_k, top_p)? as u32;
 
-        if let Ok(piece) = tokenizer.decode(&[next_id]) {
-            // If the consumer has dropped, stop generating — they're gone.
-            if tx.send(ShardRunEvent::Token(piece)).await.is_err() {
-                return Ok(());
-            }
+        // Text that might begin a stop string is held back until the next
+        // token settles it, so a stop string is never sent.
+        let piece = criteria.push(&tokenizer, next_id)?;
+        // If the consumer has dropped, stop generating — they're gone.
+        if !piece.is_empty() && tx.send(ShardRunEvent::Token(piece)).await.is_err() {
+            return Ok(false);
         }
-
-        generated_ids.push(next_id);
         seq_pos += current_ids.len();
 
-        if next_id == eos_id || generated_ids.len() >= max_tokens {
+        if criteria.is_finished() {
             break;
         }
         current_ids = vec![next_id];
     }
 
-    Ok(())
+    Ok(false)
+}
+
+/// Send the text `criteria` still holds back when the deadline ends a run.
+async fn flush_held_back(
+    tx: &tokio::sync::mpsc::Sender<ShardRunEvent>,
+    criteria: &mut kwaai_inference::StopCriteria,
+) {
+    let rest = criteria.finish(kwaai_inference::FinishReason::Deadline);
+    if !rest.is_empty() {
+        let _ = tx.send(ShardRunEvent::Token(rest)).await;
+    }
 }
 
 // ── status ────────────────────────────────────────────────────────────────────
@@ -1876,7 +2341,7 @@ pub async fn cmd_shard_chain(args: ShardChainArgs) -> Result<()> {
 
     // Load reputation store for trust tier display.
     let rep_store = if cfg.reputation.enabled {
-        Some(ReputationStore::load())
+        Some(load_store())
     } else {
         None
     };
@@ -1949,8 +2414,94 @@ pub struct BlockServerEntry {
     pub public_name: String,
     /// Tokens/sec claimed by the peer in its DHT announcement (0.0 = unknown).
     pub throughput: f64,
-    /// Local trust score for this peer (None until enriched from ReputationStore).
+    /// Local selection score for this peer (None until enriched from ReputationStore).
     pub trust_score: Option<f64>,
+    /// LoRA adapters the peer announced as loaded.
+    pub adapters: Vec<String>,
+    /// Dtypes the peer's accelerator computes in (empty = not announced).
+    pub dtypes: Vec<String>,
+    /// Signed capability claim from the announcement, not yet verified
+    /// (see `crate::admission`).
+    pub attestation: Option<kwaai_p2p::CapabilityAttestation>,
+}
+
+/// Keep the servers that announced LoRA adapter `adapter` as loaded.
+pub fn filter_by_adapter(chain: Vec<BlockServerEntry>, adapter: &str) -> Vec<BlockServerEntry> {
+    chain
+        .into_iter()
+        .filter(|e| e.adapters.iter().any(|a| a == adapter))
+        .collect()
+}
+
+/// Keep the servers whose announced accelerator computes in `dtype`.
+pub fn filter_by_dtype(chain: Vec<BlockServerEntry>, dtype: &str) -> Vec<BlockServerEntry> {
+    let want = kwaai_p2p::normalize_dtype(dtype);
+    chain
+        .into_iter()
+        .filter(|e| {
+            e.dtypes
+                .iter()
+                .any(|d| kwaai_p2p::normalize_dtype(d) == want)
+        })
+        .collect()
+}
+
+/// Fill in `trust_score` for every entry from the local reputation store.
+///
+/// Uses the selection score, so peers without history rank at a neutral prior
+/// rather than below every peer that has been observed once, lifted for
+/// servers with a co-signed capability attestation. Servers for the same
+/// blocks are ranked best first (see [`crate::admission::rank_attested`]).
+pub fn enrich_with_reputation(chain: &mut [BlockServerEntry], store: &ReputationStore) {
+    crate::admission::rank_attested(chain, store);
+}
+
+/// Read `keys` from every bootstrap peer at once.
+///
+/// Dials the peers concurrently, then fans batched FIND requests out to all
+/// of them (see [`kwaai_hivemind_dht::find_values`]), so a lookup over every
+/// block of a model costs about one round trip rather than one per peer.
+pub async fn find_on_bootstrap(
+    client: &P2PClient,
+    our_dhtid: &[u8],
+    keys: &[Vec<u8>],
+    bootstrap_peers: &[String],
+) -> kwaai_hivemind_dht::FoundValues {
+    let dials = bootstrap_peers.iter().filter_map(|addr| {
+        let peer = addr.split("/p2p/").nth(1)?.parse::<PeerId>().ok()?;
+        Some(async move { client.connect_peer(addr).await.ok().map(|_| peer) })
+    });
+    let peers: Vec<PeerId> = futures::future::join_all(dials)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    if peers.is_empty() {
+        return Default::default();
+    }
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    kwaai_hivemind_dht::find_values(
+        &peers,
+        keys,
+        Some(NodeInfo {
+            node_id: our_dhtid.to_vec(),
+        }),
+        &Default::default(),
+        |peer, mut request| async move {
+            crate::dht_auth::sign_request(&mut request);
+            let bytes = client
+                .call_unary_handler(
+                    &peer.to_bytes(),
+                    "DHTProtocol.rpc_find",
+                    &request.encode_to_vec(),
+                )
+                .await
+                .map_err(|e| kwaai_hivemind_dht::Error::Network(e.to_string()))?;
+            Ok(FindResponse::decode(&bytes[..])?)
+        },
+    )
+    .await
 }
 
 /// Query bootstrap peers for all block keys of `dht_prefix` and return a
@@ -1967,66 +2518,35 @@ pub async fn discover_chain(
         .finalize()
         .to_vec();
 
-    // All block keys in a single FindRequest
     let keys: Vec<Vec<u8>> = (0..total_blocks)
         .map(|b| block_dht_id(dht_prefix, b))
         .collect();
+    let found = find_on_bootstrap(client, &our_dhtid, &keys, bootstrap_peers).await;
 
-    let find_req = FindRequest {
-        auth: Some(RequestAuthInfo::new()),
-        keys,
-        peer: Some(NodeInfo { node_id: our_dhtid }),
-    };
-    let mut req_bytes = Vec::new();
-    if find_req.encode(&mut req_bytes).is_err() {
-        return vec![];
-    }
-
+    // Results come in bootstrap-peer order, so the first peer's record of a
+    // server wins as before.
     let mut servers: HashMap<String, BlockServerEntry> = HashMap::new();
-
-    for addr in bootstrap_peers {
-        let Some(peer_str) = addr.split("/p2p/").nth(1) else {
-            continue;
-        };
-        let bp = match peer_str.parse::<PeerId>() {
-            Ok(p) => p,
-            Err(_) => continue,
-        };
-        if client.connect_peer(addr).await.is_err() {
-            continue;
-        }
-        tokio::time::sleep(Duration::from_millis(400)).await;
-
-        let resp_bytes = match client
-            .call_unary_handler(&bp.to_bytes(), "DHTProtocol.rpc_find", &req_bytes)
-            .await
-        {
-            Ok(b) => b,
-            Err(_) => continue,
-        };
-
-        let Ok(resp) = FindResponse::decode(&resp_bytes[..]) else {
-            continue;
-        };
-
-        for result in resp.results {
-            if result.value.is_empty() {
-                continue;
-            }
-            let rt = result.result_type;
-            if rt == 1 {
+    for key in &keys {
+        for (_, result) in found.values.get(key).into_iter().flatten() {
+            if result.result_type == 1 {
                 // FoundRegular — single value, peer_id embedded in map
                 if let Some((key, entry)) = decode_server_info_regular(&result.value) {
                     servers.entry(key).or_insert(entry);
                 }
-            } else if rt == 2 {
+            } else if result.result_type == 2 {
                 // FoundDictionary — multiple subkeys (Python Hivemind)
                 decode_server_info_dictionary(&result.value, &mut servers);
             }
         }
     }
 
-    let mut chain: Vec<BlockServerEntry> = servers.into_values().collect();
+    // Peers that sent us a goodbye are on their way out even if their OFFLINE
+    // record hasn't reached the DHT nodes we asked yet.
+    let departed = crate::handoff::departed_peers();
+    let mut chain: Vec<BlockServerEntry> = servers
+        .into_values()
+        .filter(|e| !departed.contains(&e.peer_id.to_base58()))
+        .collect();
     chain.sort_by_key(|e| e.start_block);
     chain
 }
@@ -2052,8 +2572,6 @@ pub async fn discover_inference_peer(
     dht_prefix: Option<&str>,
     total_blocks: Option<usize>,
 ) -> Option<String> {
-    use prost::Message as _;
-
     let our_dhtid = Sha1::new()
         .chain_update(our_peer_id.to_bytes())
         .finalize()
@@ -2064,62 +2582,26 @@ pub async fn discover_inference_peer(
         let packed = rmp_serde::to_vec(INFERENCE_NODES_DHT_KEY).ok()?;
         Sha1::new().chain_update(&packed).finalize().to_vec()
     };
-    let find_req = FindRequest {
-        auth: Some(RequestAuthInfo::new()),
-        keys: vec![inf_key],
-        peer: Some(NodeInfo {
-            node_id: our_dhtid.clone(),
-        }),
-    };
-    let mut req_bytes = Vec::new();
-    find_req.encode(&mut req_bytes).ok()?;
+    let found = find_on_bootstrap(client, &our_dhtid, &[inf_key], bootstrap_peers).await;
 
     let mut candidates: Vec<(f64, PeerId, String)> = Vec::new(); // (throughput, peer_id, name)
 
-    for addr in bootstrap_peers {
-        let Some(peer_str) = addr.split("/p2p/").nth(1) else {
-            continue;
-        };
-        let bp = match peer_str.parse::<PeerId>() {
-            Ok(p) => p,
-            Err(_) => continue,
-        };
-        if client.connect_peer(addr).await.is_err() {
-            continue;
-        }
-        tokio::time::sleep(Duration::from_millis(400)).await;
-        let resp_bytes = match client
-            .call_unary_handler(&bp.to_bytes(), "DHTProtocol.rpc_find", &req_bytes)
-            .await
-        {
-            Ok(b) => b,
-            Err(_) => continue,
-        };
-        let Ok(resp) = FindResponse::decode(&resp_bytes[..]) else {
-            continue;
-        };
-        for result in resp.results {
-            if result.value.is_empty() {
-                continue;
-            }
-            // Values stored under _kwaai.inference.nodes use the same
-            // DHTServerInfo msgpack encoding as block records.
-            if result.result_type == 1 {
-                if let Some((state, _, _, name, peer_id_b58, version, tps)) =
-                    decode_server_info_ext(&result.value)
-                {
-                    if state == 2 && version_meets_minimum(&version) {
-                        if let Ok(pid) = peer_id_b58.parse::<PeerId>() {
-                            candidates.push((tps, pid, name));
-                        }
+    for result in found.results() {
+        // Values stored under _kwaai.inference.nodes use the same
+        // DHTServerInfo msgpack encoding as block records.
+        if result.result_type == 1 {
+            if let Some(info) = decode_server_info_ext(&result.value) {
+                if info.state == 2 && version_meets_minimum(&info.version) {
+                    if let Ok(pid) = info.peer_id_b58.parse::<PeerId>() {
+                        candidates.push((info.throughput, pid, info.public_name));
                     }
                 }
-            } else if result.result_type == 2 {
-                let mut tmp: HashMap<String, BlockServerEntry> = HashMap::new();
-                decode_server_info_dictionary(&result.value, &mut tmp);
-                for (_, e) in tmp {
-                    candidates.push((e.throughput, e.peer_id, e.public_name));
-                }
+            }
+        } else if result.result_type == 2 {
+            let mut tmp: HashMap<String, BlockServerEntry> = HashMap::new();
+            decode_server_info_dictionary(&result.value, &mut tmp);
+            for (_, e) in tmp {
+                candidates.push((e.throughput, e.peer_id, e.public_name));
             }
         }
     }
@@ -2214,20 +2696,10 @@ async fn pick_gap_blocks(
         crate::rebalancer::pick_gap_from_chain(&chain, our_peer_id, total_blocks, target_blocks);
 
     // Log when joining as redundant (network is fully covered by others).
-    let other_min_cov = {
-        let mut cov = vec![0usize; total_blocks];
-        for e in &chain {
-            if e.peer_id == *our_peer_id {
-                continue;
-            }
-            let s = e.start_block.min(total_blocks);
-            let e2 = e.end_block.min(total_blocks);
-            for c in &mut cov[s..e2] {
-                *c += 1;
-            }
-        }
-        cov.iter().copied().min().unwrap_or(0)
-    };
+    let other_min_cov = crate::rebalancer::coverage(&chain, our_peer_id, total_blocks)
+        .into_iter()
+        .min()
+        .unwrap_or(0);
     if other_min_cov > 0 {
         print_info(&format!(
             "Network fully covered (min {} node(s)/block) — \
@@ -2248,33 +2720,22 @@ async fn pick_gap_blocks(
 /// synthesise a stable key from `public_name:start_block` so they still count
 /// for gap detection even though they cannot be routed to directly.
 fn decode_server_info_regular(bytes: &[u8]) -> Option<(String, BlockServerEntry)> {
-    let (state, start_block, end_block, public_name, peer_id_b58, version, throughput) =
-        decode_server_info_ext(bytes)?;
+    let info = decode_server_info_ext(bytes)?;
     // Only include ONLINE nodes (state=2); skip JOINING (0) and OFFLINE (-1).
-    if state != 2 {
+    if info.state != 2 {
         return None;
     }
-    if !version_meets_minimum(&version) {
+    if !version_meets_minimum(&info.version) {
         return None;
     }
-    let (dedup_key, peer_id) = match peer_id_b58.parse::<PeerId>() {
+    let (dedup_key, peer_id) = match info.peer_id_b58.parse::<PeerId>() {
         Ok(pid) => (pid.to_base58(), pid),
         Err(_) => {
-            let key = format!("legacy:{}:{}", public_name, start_block);
+            let key = format!("legacy:{}:{}", info.public_name, info.start_block);
             (key, PeerId::random())
         }
     };
-    Some((
-        dedup_key,
-        BlockServerEntry {
-            peer_id,
-            start_block,
-            end_block,
-            public_name,
-            throughput,
-            trust_score: None,
-        },
-    ))
+    Some((dedup_key, info.into_entry(peer_id)))
 }
 
 /// Parse `Ext(80, [expiry, created, [[subkey_bytes, value_bytes, expiry], …]])`
@@ -2334,24 +2795,15 @@ fn decode_server_info_dictionary(bytes: &[u8], out: &mut HashMap<String, BlockSe
             Err(_) => continue,
         };
 
-        if let Some((state, start_block, end_block, public_name, _, version, throughput)) =
-            decode_server_info_ext(value_bytes)
-        {
-            if state != 2 {
+        if let Some(info) = decode_server_info_ext(value_bytes) {
+            if info.state != 2 {
                 continue;
             }
-            if !version_meets_minimum(&version) {
+            if !version_meets_minimum(&info.version) {
                 continue;
             }
-            let key = peer_id_b58.clone();
-            out.entry(key).or_insert(BlockServerEntry {
-                peer_id,
-                start_block,
-                end_block,
-                public_name,
-                throughput,
-                trust_score: None,
-            });
+            out.entry(peer_id_b58)
+                .or_insert_with(|| info.into_entry(peer_id));
         }
     }
 }
@@ -2392,11 +2844,38 @@ pub fn snap_to_valid_blocks(n: usize) -> usize {
         .unwrap_or(&4)
 }
 
-/// Core decoder: `Ext(64, msgpack([state, throughput, {start_block, end_block, …}]))`
-/// Returns `(state, start_block, end_block, public_name, peer_id_b58, version, throughput)`.
-fn decode_server_info_ext(
-    bytes: &[u8],
-) -> Option<(i32, usize, usize, String, String, String, f64)> {
+/// Fields of a DHT server-info record (`Ext(64, …)`).
+struct ServerInfoRecord {
+    state: i32,
+    throughput: f64,
+    start_block: usize,
+    end_block: usize,
+    public_name: String,
+    peer_id_b58: String,
+    version: String,
+    adapters: Vec<String>,
+    dtypes: Vec<String>,
+    attestation: Option<kwaai_p2p::CapabilityAttestation>,
+}
+
+impl ServerInfoRecord {
+    fn into_entry(self, peer_id: PeerId) -> BlockServerEntry {
+        BlockServerEntry {
+            peer_id,
+            start_block: self.start_block,
+            end_block: self.end_block,
+            public_name: self.public_name,
+            throughput: self.throughput,
+            trust_score: None,
+            adapters: self.adapters,
+            dtypes: self.dtypes,
+            attestation: self.attestation,
+        }
+    }
+}
+
+/// Core decoder: `Ext(64, msgpack([state, throughput, {start_block, end_block, …}]))`.
+fn decode_server_info_ext(bytes: &[u8]) -> Option<ServerInfoRecord> {
     let val = rmpv::decode::read_value(&mut &bytes[..]).ok()?;
     let inner_bytes = match &val {
         rmpv::Value::Ext(64, b) => b.as_slice(),
@@ -2409,36 +2888,38 @@ fn decode_server_info_ext(
     }
     let map = arr[2].as_map()?;
 
-    let get_i = |k: &str| -> Option<i64> {
-        map.iter()
-            .find(|(ky, _)| ky.as_str() == Some(k))
-            .and_then(|(_, v)| v.as_i64())
-    };
-    let get_s = |k: &str| -> String {
+    let get = |k: &str| {
         map.iter()
             .find(|(ky, _)| ky.as_str() == Some(k))
-            .and_then(|(_, v)| v.as_str())
-            .unwrap_or("")
-            .to_string()
+            .map(|(_, v)| v)
     };
-
-    let state = arr[0].as_i64().unwrap_or(0) as i32;
-    let throughput = arr[1].as_f64().unwrap_or(0.0);
-    let start_block = get_i("start_block")? as usize;
-    let end_block = get_i("end_block")? as usize;
-    let public_name = get_s("public_name");
-    let peer_id_b58 = get_s("peer_id");
-    let version = get_s("version");
-
-    Some((
-        state,
-        start_block,
-        end_block,
-        public_name,
-        peer_id_b58,
-        version,
-        throughput,
-    ))
+    let get_i = |k: &str| -> Option<i64> { get(k).and_then(|v| v.as_i64()) };
+    let get_s = |k: &str| -> String { get(k).and_then(|v| v.as_str()).unwrap_or("").to_string() };
+
+    Some(ServerInfoRecord {
+        state: arr[0].as_i64().unwrap_or(0) as i32,
+        throughput: arr[1].as_f64().unwrap_or(0.0),
+        start_block: get_i("start_block")? as usize,
+        end_block: get_i("end_block")? as usize,
+        public_name: get_s("public_name"),
+        peer_id_b58: get_s("peer_id"),
+        version: get_s("version"),
+        adapters: get("adapters")
+            .and_then(|v| v.as_array())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        dtypes: get("accelerator")
+            .and_then(kwaai_p2p::AcceleratorInfo::from_msgpack_value)
+            .map(|a| a.dtypes)
+            .unwrap_or_default(),
+        attestation: get(crate::admission::FIELD)
+            .and_then(|v| v.as_slice())
+            .and_then(crate::admission::decode),
+    })
 }
 
 // ── Pinned path ──────────────────────────────────────────────────────────────
@@ -2446,9 +2927,9 @@ fn decode_server_info_ext(
 /// Build a deterministic, non-overlapping peer path for a session.
 ///
 /// Greedy walk from block 0: at each position, pick the widest-coverage
-/// candidate (largest `end_block`) not in `failed_peers`, then advance to
-/// that candidate's `end_block`.  Returns an ordered list of entries that
-/// together cover `[0, total_blocks)`.
+/// candidate (largest `end_block`) not in `failed_peers`, breaking ties by
+/// `trust_score`, then advance to that candidate's `end_block`.  Returns an
+/// ordered list of entries that together cover `[0, total_blocks)`.
 pub fn build_pinned_path(
     chain: &[BlockServerEntry],
     total_blocks: usize,
@@ -2461,7 +2942,13 @@ pub fn build_pinned_path(
             .iter()
             .filter(|e| e.start_block <= pos && e.end_block > pos)
             .filter(|e| !failed_peers.contains(&e.peer_id))
-            .max_by_key(|e| e.end_block);
+            .max_by(|a, b| {
+                a.end_block.cmp(&b.end_block).then_with(|| {
+                    a.trust_score
+                        .unwrap_or(0.0)
+                        .total_cmp(&b.trust_score.unwrap_or(0.0))
+                })
+            });
         match best {
             Some(entry) => {
                 pos = entry.end_block;
@@ -2501,6 +2988,10 @@ pub struct SerializableEntry {
     pub start_block: usize,
     pub end_block: usize,
     pub public_name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adapters: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dtypes: Vec<String>,
 }
 
 impl SerializableEntry {
@@ -2510,6 +3001,8 @@ impl SerializableEntry {
             start_block: e.start_block,
             end_block: e.end_block,
             public_name: e.public_name.clone(),
+            adapters: e.adapters.clone(),
+            dtypes: e.dtypes.clone(),
         }
     }
 
@@ -2522,6 +3015,9 @@ impl SerializableEntry {
             public_name: self.public_name.clone(),
             throughput: 0.0,
             trust_score: None,
+            adapters: self.adapters.clone(),
+            dtypes: self.dtypes.clone(),
+            attestation: None,
         })
     }
 }
@@ -2641,6 +3137,11 @@ async fn cmd_circuit_create(args: CircuitCreateArgs) -> Result<()> {
 
     println!("  Found {} node(s)", chain.len());
 
+    let mut chain = crate::admission::admit_servers(chain, &cfg.admission);
+    if cfg.reputation.enabled {
+        enrich_with_reputation(&mut chain, &load_store());
+    }
+
     // Build pinned path
     let failed_peers = std::collections::HashSet::new();
     let pinned_path = build_pinned_path(&chain, total_blocks, &failed_peers)?;
@@ -2860,6 +3361,12 @@ pub async fn forward_through_chain(
 
             match result {
                 Ok(resp) => {
+                    if resp.draining {
+                        tracing::debug!(
+                            "Peer {} is shutting down — finishing this session there",
+                            candidate.peer_id
+                        );
+                    }
                     // Record successful hop in local reputation store.
                     if let Some(ref rep) = reputation {
                         if let Ok(mut store) = rep.lock() {
@@ -2890,8 +3397,10 @@ pub async fn forward_through_chain(
                             session_id,
                             seq_pos,
                             payload_type: PayloadType::HiddenStates,
-                            shape: resp.shape.clone(),
-                            data: resp.data.clone(),
+                            tensor: resp.tensor.clone(),
+                            active_adapter: request.active_adapter.take(),
+                            all_logits: request.all_logits,
+                            hidden_only: request.hidden_only,
                         };
                     }
                     response = Some(resp);
@@ -2899,6 +3408,16 @@ pub async fn forward_through_chain(
                     break;
                 }
                 Err(e) => {
+                    let err_str = format!("{e:#}");
+                    // A busy peer is healthy, just full: try the next
+                    // candidate without counting it as a failure.
+                    if err_str.contains(crate::request_queue::BUSY) {
+                        tracing::debug!(
+                            "Peer {} is busy — trying the next candidate",
+                            candidate.peer_id
+                        );
+                        continue;
+                    }
                     // Record failed hop in local reputation store.
                     if let Some(ref rep) = reputation {
                         if let Ok(mut store) = rep.lock() {
@@ -2915,7 +3434,6 @@ pub async fn forward_through_chain(
                             );
                         }
                     }
-                    let err_str = format!("{e:#}");
                     let is_transient = err_str.contains("stream reset")
                         || err_str.contains("early eof")
                         || err_str.contains("connection closed");
@@ -3011,13 +3529,14 @@ async fn run_via_local_bypass(
     loop {
         let token_start = std::time::Instant::now();
 
-        let (shape, data) = token_ids_to_bytes(&current_ids);
         let request = InferenceRequest {
             session_id,
             seq_pos: seq_pos as u32,
             payload_type: PayloadType::TokenIds,
-            shape,
-            data,
+            tensor: TensorWire::from_u32(&current_ids),
+            active_adapter: None,
+            all_logits: false,
+            hidden_only: false,
         };
 
         let response = local_inference_call(port, &request).await?;
@@ -3030,7 +3549,9 @@ async fn run_via_local_bypass(
             );
         }
 
-        let logits = f16_bytes_to_tensor(&response.data, &response.shape, &cpu)
+        let logits = response
+            .tensor
+            .to_tensor(&cpu)
             .context("decode logits from bypass response")?;
 
         // Stop prefill spinner on first token
@@ -3126,6 +3647,8 @@ pub fn shard_api_port_file() -> std::path::PathBuf {
 async fn start_local_inference_server(
     shard: ShardCell,
     device: candle_core::Device,
+    drain: crate::handoff::DrainGate,
+    queue: crate::request_queue::RequestQueue,
 ) -> Result<u16> {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpListener;
@@ -3142,6 +3665,8 @@ async fn start_local_inference_server(
             };
             let shard = shard.clone();
             let device = device.clone();
+            let drain = drain.clone();
+            let queue = queue.clone();
             tokio::spawn(async move {
                 // Framing: 4-byte LE length prefix + msgpack bytes
                 let mut len_buf = [0u8; 4];
@@ -3162,30 +3687,28 @@ async fn start_local_inference_server(
 
                 let resp_bytes = match shard_arc {
                     None => {
-                        let err_resp = crate::block_rpc::InferenceResponse {
-                            session_id: 0,
-                            response_type: crate::block_rpc::ResponseType::HiddenStates,
-                            shape: vec![],
-                            data: vec![],
-                            error: Some(
-                                "node warming up — model loading in background".to_string(),
-                            ),
-                        };
+                        let err_resp = crate::block_rpc::InferenceResponse::failed(
+                            "node warming up — model loading in background",
+                            drain.is_draining(),
+                        );
                         rmp_serde::to_vec_named(&err_resp).unwrap_or_default()
                     }
                     Some(s) => {
-                        match crate::block_rpc::handle_inference_request(s, device.clone(), buf)
-                            .await
+                        match crate::block_rpc::handle_inference_request(
+                            s,
+                            device.clone(),
+                            buf,
+                            &drain,
+                            &queue,
+                        )
+                        .await
                         {
                             Ok(r) => rmp_serde::to_vec_named(&r).unwrap_or_default(),
                             Err(e) => {
-                                let err_resp = crate::block_rpc::InferenceResponse {
-                                    session_id: 0,
-                                    response_type: crate::block_rpc::ResponseType::HiddenStates,
-                                    shape: vec![],
-                                    data: vec![],
-                                    error: Some(e.to_string()),
-                                };
+                                let err_resp = crate::block_rpc::InferenceResponse::failed(
+                                    e.to_string(),
+                                    drain.is_draining(),
+                                );
                                 rmp_serde::to_vec_named(&err_resp).unwrap_or_default()
                             }
                         }
@@ -3248,7 +3771,8 @@ fn block_dht_id(prefix: &str, block: usize) -> Vec<u8> {
     Sha1::new().chain_update(&packed).finalize().to_vec()
 }
 
-/// UDS socket path for p2pd.
+/// IPC address for p2pd: a Unix socket, or on Windows the named pipe (TCP if
+/// p2pd cannot listen on one).
 /// Override with `KWAAINET_SOCKET=/tmp/my.sock` to point at a different p2pd instance
 /// (e.g. when running multiple nodes on the same machine).
 pub fn daemon_socket() -> String {
@@ -3259,7 +3783,8 @@ pub fn daemon_socket() -> String {
         format!("/unix/{}", sock)
     };
     #[cfg(not(unix))]
-    let addr = "/ip4/127.0.0.1/tcp/5005".to_string();
+    let addr = std::env::var("KWAAINET_SOCKET")
+        .unwrap_or_else(|_| kwaai_p2p_daemon::default_daemon_addr());
     addr
 }
 
@@ -3272,18 +3797,6 @@ fn coverage_check(chain: &[BlockServerEntry], total_blocks: usize) -> bool {
     covered.iter().all(|&c| c)
 }
 
-/// Collect all `*.safetensors` files in a directory (sorted for determinism).
-fn collect_safetensors(dir: &Path) -> Result<Vec<PathBuf>> {
-    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
-        .with_context(|| format!("Reading directory {}", dir.display()))?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|p| p.extension().and_then(|x| x.to_str()) == Some("safetensors"))
-        .collect();
-    paths.sort();
-    Ok(paths)
-}
-
 /// Sample the next token id from logits using temperature + top-k + top-p (nucleus) filtering.
 /// Falls back to greedy argmax when temperature == 1.0, top_k == 0, top_p >= 1.0.
 pub fn sample_token(
@@ -3403,6 +3916,100 @@ mod tests {
         assert!(version_meets_minimum("kwaai-1.0.0"));
     }
 
+    #[test]
+    fn speculative_temperature_follows_sampling_rule() {
+        assert_eq!(speculative_temperature(1.0, 0, 1.0), 0.0);
+        assert_eq!(speculative_temperature(0.0, 0, 1.0), 0.0);
+        assert_eq!(speculative_temperature(0.7, 0, 1.0), 0.699999988079071);
+        assert_eq!(speculative_temperature(1.0, 40, 1.0), 1.0);
+    }
+
+    #[test]
+    fn adapter_specs_name_the_adapter() {
+        assert_eq!(
+            parse_adapter_spec("chat = /adapters/chat-v2"),
+            ("chat".to_string(), PathBuf::from("/adapters/chat-v2"))
+        );
+        assert_eq!(
+            parse_adapter_spec("/adapters/sql/"),
+            ("sql".to_string(), PathBuf::from("/adapters/sql/"))
+        );
+    }
+
+    #[test]
+    fn server_info_carries_adapters() {
+        let fields = rmpv::Value::Map(vec![
+            (rmpv::Value::from("start_block"), rmpv::Value::from(0)),
+            (rmpv::Value::from("end_block"), rmpv::Value::from(8)),
+            (
+                rmpv::Value::from("version"),
+                rmpv::Value::from("kwaai-0.4.0"),
+            ),
+            (
+                rmpv::Value::from("adapters"),
+                rmpv::Value::Array(vec![rmpv::Value::from("chat")]),
+            ),
+        ]);
+        let mut inner = Vec::new();
+        rmpv::encode::write_value(
+            &mut inner,
+            &rmpv::Value::Array(vec![2.into(), 10.0.into(), fields]),
+        )
+        .unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &rmpv::Value::Ext(64, inner)).unwrap();
+
+        let info = decode_server_info_ext(&bytes).unwrap();
+        assert_eq!(info.adapters, ["chat"]);
+        let entry = info.into_entry(PeerId::random());
+        let chain = vec![
+            entry,
+            BlockServerEntry {
+                adapters: vec![],
+                ..decode_server_info_ext(&bytes)
+                    .unwrap()
+                    .into_entry(PeerId::random())
+            },
+        ];
+        assert_eq!(filter_by_adapter(chain, "chat").len(), 1);
+    }
+
+    #[test]
+    fn server_info_carries_accelerator_dtypes() {
+        let accel = kwaai_p2p::AcceleratorInfo {
+            device_type: "cuda".into(),
+            dtypes: vec!["bfloat16".into(), "float16".into()],
+            ..Default::default()
+        };
+        let fields = rmpv::Value::Map(vec![
+            (rmpv::Value::from("start_block"), rmpv::Value::from(0)),
+            (rmpv::Value::from("end_block"), rmpv::Value::from(8)),
+            (rmpv::Value::from("accelerator"), accel.to_msgpack_value()),
+        ]);
+        let mut inner = Vec::new();
+        rmpv::encode::write_value(
+            &mut inner,
+            &rmpv::Value::Array(vec![2.into(), 10.0.into(), fields]),
+        )
+        .unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &rmpv::Value::Ext(64, inner)).unwrap();
+
+        let entry = decode_server_info_ext(&bytes)
+            .unwrap()
+            .into_entry(PeerId::random());
+        assert_eq!(entry.dtypes, ["bfloat16", "float16"]);
+        let chain = vec![
+            entry.clone(),
+            BlockServerEntry {
+                dtypes: vec![],
+                ..entry
+            },
+        ];
+        assert_eq!(filter_by_dtype(chain.clone(), "bf16").len(), 1);
+        assert!(filter_by_dtype(chain, "float32").is_empty());
+    }
+
     #[test]
     fn test_snap_to_valid_blocks() {
         assert_eq!(snap_to_valid_blocks(1), 4);
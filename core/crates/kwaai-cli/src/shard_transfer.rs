@@ -0,0 +1,1122 @@
+//! Peer-to-peer model shard distribution.
+//!
+//! A `kwaainet shard serve` node offers the SafeTensors snapshot it loaded on
+//! [`SHARD_TRANSFER_PROTO`] and provides `model-shard:<model>:<layer>` in the
+//! DHT (the `dht_backend` one, see `crate::dht_backend`) for every block it
+//! serves. A node that is missing weight files finds
+//! those providers and fetches the files from them instead of HuggingFace:
+//!
+//! - every provider is asked for its file listing; the expected size and
+//!   hash of each file come from the HuggingFace Hub for the snapshot's
+//!   revision, never from peers, and a file is only fetched from peers whose
+//!   listing agrees with them,
+//! - files are split into [`PIECE_SIZE`] pieces fetched from several peers in
+//!   parallel, the least busy peer first, and a failed piece is retried on
+//!   another peer,
+//! - the assembled file is verified against the Hub's hash (SHA-256 for LFS
+//!   files, the git blob id otherwise) before it is moved into the
+//!   HuggingFace cache layout. Files the Hub has no hash for are not fetched.
+//!
+//! Pieces move with the chunked streaming protocol from
+//! [`kwaai_p2p::chunked`]. Partial downloads are kept as `<file>.part` next to
+//! a `<file>.part.pieces` record of finished pieces, so an interrupted download
+//! resumes where it stopped.
+//!
+//! ## Wire format
+//! Every stream starts with one `[4-byte BE length][msgpack(ShardTransferRequest)]`
+//! frame answered by one `ShardTransferReply` frame. A `Sending` reply is
+//! followed by a chunked transfer of the requested byte range.
+
+use anyhow::{bail, Context, Result};
+use kwaai_p2p::chunked::{self, ChunkSink, ChunkSource, TransferConfig, TransferProgress};
+use kwaai_p2p::{DhtBackend, DhtBackendKind, Throttled};
+use kwaai_p2p_daemon::{P2PClient, P2PStream};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::{JoinHandle, JoinSet},
+};
+use tracing::{debug, info, warn};
+
+use crate::config::KwaaiNetConfig;
+use crate::hf;
+
+pub const SHARD_TRANSFER_PROTO: &str = "/kwaai/shard-transfer/1.0.0";
+
+/// Bytes per piece; each piece is one chunked transfer from one peer.
+pub const PIECE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Pieces in flight at once, across all peers.
+const MAX_PARALLEL_PIECES: usize = 6;
+
+/// Failed pieces after which a peer is no longer asked for this file.
+const MAX_PEER_FAILURES: u32 = 3;
+
+/// Largest request/reply frame accepted.
+const MAX_MESSAGE_LEN: u32 = 1024 * 1024;
+
+/// How often a serving node re-announces its Kademlia provider records.
+/// Hivemind records expire after `record_ttl_secs`, so on those backends
+/// providers are re-announced every `announce_interval_secs` instead.
+const PROVIDE_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+
+// ── Wire types ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ShardTransferRequest {
+    /// Which model and files does the peer serve?
+    List,
+    /// Send one file of the snapshot, or `range = (offset, len)` of it.
+    Fetch {
+        file: String,
+        #[serde(default)]
+        range: Option<(u64, u64)>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ShardTransferReply {
+    Listing {
+        model: String,
+        revision: String,
+        files: Vec<ShardFile>,
+    },
+    /// `size` bytes follow as a chunked transfer.
+    Sending {
+        size: u64,
+    },
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardFile {
+    pub name: String,
+    pub size: u64,
+    /// Hex SHA-256 of the file, once the serving node knows it.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+// ── Frame I/O ─────────────────────────────────────────────────────────────────
+
+async fn write_message<W, T>(writer: &mut W, msg: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let payload = rmp_serde::to_vec_named(msg)?;
+    writer
+        .write_all(&(payload.len() as u32).to_be_bytes())
+        .await?;
+    writer.write_all(&payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn read_message<R, T>(reader: &mut R) -> Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: for<'de> Deserialize<'de>,
+{
+    let mut len_buf = [0u8; 4];
+    reader
+        .read_exact(&mut len_buf)
+        .await
+        .context("read message length")?;
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_MESSAGE_LEN {
+        bail!("shard-transfer message too large: {len} bytes");
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut buf)
+        .await
+        .context("read message body")?;
+    Ok(rmp_serde::from_slice(&buf)?)
+}
+
+// ── DHT provider records ──────────────────────────────────────────────────────
+
+/// DHT key announcing that a node serves `layer` of `model`.
+pub fn shard_provider_key(model: &str, layer: usize) -> String {
+    format!("model-shard:{model}:{layer}")
+}
+
+/// Provide every layer in `[start, end)`; returns how many were announced.
+pub async fn provide_blocks(
+    dht: &mut dyn DhtBackend,
+    model: &str,
+    start: usize,
+    end: usize,
+) -> usize {
+    let mut announced = 0;
+    for layer in start..end {
+        let key = shard_provider_key(model, layer);
+        match dht.provide(&key).await {
+            Ok(()) => announced += 1,
+            Err(e) => debug!("provide {key}: {e}"),
+        }
+    }
+    announced
+}
+
+/// How often providers must be re-announced on `config`'s DHT backend.
+fn provide_interval(config: &KwaaiNetConfig) -> Duration {
+    match config.dht_backend {
+        DhtBackendKind::Kademlia => PROVIDE_INTERVAL,
+        _ => Duration::from_secs(config.announce_interval_secs.max(1)),
+    }
+}
+
+/// Keep `[start, end)` of `model` provided for as long as the node runs.
+pub fn spawn_provider_loop(
+    config: KwaaiNetConfig,
+    model: String,
+    start: usize,
+    end: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut dht = None;
+        loop {
+            if dht.is_none() {
+                match crate::dht_backend::open(&config).await {
+                    Ok(backend) => dht = Some(backend),
+                    Err(e) => warn!("shard-transfer: cannot open the DHT to provide: {e:#}"),
+                }
+            }
+            if let Some(dht) = dht.as_deref_mut() {
+                let n = provide_blocks(dht, &model, start, end).await;
+                info!(
+                    "shard-transfer: provided {n}/{} layers of {model}",
+                    end - start
+                );
+            }
+            tokio::time::sleep(provide_interval(&config)).await;
+        }
+    })
+}
+
+/// Peers providing any layer in `[start, end)` of `model`, those covering
+/// the most layers first. `exclude` (normally this node) is left out.
+pub async fn find_block_providers(
+    dht: &dyn DhtBackend,
+    model: &str,
+    start: usize,
+    end: usize,
+    exclude: Option<PeerId>,
+) -> Vec<PeerId> {
+    let mut coverage: HashMap<PeerId, usize> = HashMap::new();
+    for layer in start..end {
+        let key = shard_provider_key(model, layer);
+        let providers = match dht.get_providers(&key).await {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("find providers {key}: {e}");
+                continue;
+            }
+        };
+        for peer in providers {
+            if Some(peer) != exclude {
+                *coverage.entry(peer).or_default() += 1;
+            }
+        }
+    }
+    let mut peers: Vec<_> = coverage.into_iter().collect();
+    peers.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    peers.into_iter().map(|(peer, _)| peer).collect()
+}
+
+// ── Server ────────────────────────────────────────────────────────────────────
+
+struct ServedSnapshot {
+    model: String,
+    dir: PathBuf,
+    /// File name → hex SHA-256, filled in the background at startup.
+    hashes: RwLock<HashMap<String, String>>,
+}
+
+impl ServedSnapshot {
+    fn new(model: String, dir: PathBuf) -> Self {
+        Self {
+            model,
+            dir,
+            hashes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Files a peer may fetch: the inference files `hf::download` would
+    /// have fetched, at the top level of the snapshot.
+    fn files(&self) -> Result<Vec<ShardFile>> {
+        let hashes = self.hashes.read().expect("hash map poisoned");
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(path) = self.resolve(&name) {
+                // Follows HF cache symlinks into blobs/.
+                files.push(ShardFile {
+                    size: std::fs::metadata(&path)?.len(),
+                    sha256: hashes.get(&name).cloned(),
+                    name,
+                });
+            }
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(files)
+    }
+
+    fn resolve(&self, name: &str) -> Option<PathBuf> {
+        if !is_transfer_name(name) {
+            return None;
+        }
+        let path = self.dir.join(name);
+        std::fs::metadata(&path)
+            .is_ok_and(|m| m.is_file())
+            .then_some(path)
+    }
+
+    /// Hash every served file. LFS files in the HF cache are symlinks to
+    /// `blobs/<sha256>`, so most hashes come for free.
+    fn compute_hashes(&self) -> Result<()> {
+        for file in self.files()? {
+            if file.sha256.is_some() {
+                continue;
+            }
+            let path = self.dir.join(&file.name);
+            let from_blob = std::fs::read_link(&path).ok().and_then(|target| {
+                let blob = target.file_name()?.to_str()?.to_string();
+                (blob.len() == 64 && blob.bytes().all(|b| b.is_ascii_hexdigit())).then_some(blob)
+            });
+            let hash = match from_blob {
+                Some(h) => h,
+                None => sha256_file(&path)?,
+            };
+            self.hashes
+                .write()
+                .expect("hash map poisoned")
+                .insert(file.name, hash);
+        }
+        Ok(())
+    }
+}
+
+/// Whether peers may exchange a file called `name`: an inference file at
+/// the top level of a snapshot, never a path.
+fn is_transfer_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !name.contains(['/', '\\'])
+        && hf::should_download(name)
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Git blob id of a file: SHA-1 over `blob <len>\0` and the contents.
+fn git_blob_sha1(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", file.metadata()?.len()));
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn hash_matches(path: &Path, expected: &hf::FileHash) -> std::io::Result<bool> {
+    let actual = match expected {
+        hf::FileHash::Sha256(_) => sha256_file(path)?,
+        hf::FileHash::GitBlob(_) => git_blob_sha1(path)?,
+    };
+    Ok(actual == expected.hex())
+}
+
+/// Stable per-piece transfer id so a reconnecting peer resumes the same
+/// piece, and restarts when the file changed underneath it.
+fn transfer_id(name: &str, path: &Path, offset: u64) -> Result<u64> {
+    let meta = std::fs::metadata(path)?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let digest = Sha1::digest(format!("{name}:{}:{mtime}:{offset}", meta.len()));
+    Ok(u64::from_be_bytes(digest[..8].try_into().expect("8 bytes")))
+}
+
+/// A byte range of a file, sent or received as its own transfer.
+struct FileRange {
+    file: File,
+    base: u64,
+    len: u64,
+}
+
+impl ChunkSource for FileRange {
+    fn total_len(&mut self) -> std::io::Result<u64> {
+        Ok(self.len)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.base + offset))?;
+        self.file.read_exact(buf)
+    }
+}
+
+impl ChunkSink for FileRange {
+    /// The `.part` file is sized up front; only check the peer agrees.
+    fn prepare(&mut self, total_len: u64) -> std::io::Result<()> {
+        if total_len != self.len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "peer offered {total_len} bytes for a {}-byte piece",
+                    self.len
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.base + offset))?;
+        self.file.write_all(data)
+    }
+}
+
+/// Start the shard-transfer server for the snapshot in `snapshot_dir`: binds a
+/// local TCP port, registers it with the daemon as the handler for
+/// `SHARD_TRANSFER_PROTO`, and spawns an accept loop.
+pub async fn start_shard_transfer_server(
+    client: &mut P2PClient,
+    model: String,
+    snapshot_dir: PathBuf,
+) -> Result<JoinHandle<()>> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("bind shard-transfer server")?;
+    let port = listener.local_addr()?.port();
+    let addr = format!("/ip4/127.0.0.1/tcp/{port}");
+
+    client
+        .register_stream_handler(&addr, vec![SHARD_TRANSFER_PROTO.to_string()])
+        .await
+        .context("register shard-transfer stream handler")?;
+
+    info!(
+        "shard-transfer: serving {} from {}",
+        model,
+        snapshot_dir.display()
+    );
+    let served = Arc::new(ServedSnapshot::new(model, snapshot_dir));
+
+    let hashing = served.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = hashing.compute_hashes() {
+            warn!("shard-transfer: hashing snapshot failed: {e:#}");
+        }
+    });
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    debug!("shard-transfer server: accepted connection from {peer}");
+                    let served = served.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_shard_stream(stream, &served).await {
+                            warn!("shard-transfer server: {e:#}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("shard-transfer server accept error: {e}");
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+async fn handle_shard_stream(mut stream: TcpStream, served: &ServedSnapshot) -> Result<()> {
+    if crate::peer_filter::admit_stream(&mut stream)
+        .await?
+        .is_none()
+    {
+        return Ok(());
+    }
+    let mut stream = crate::bandwidth::throttle(stream);
+    let request: ShardTransferRequest = read_message(&mut stream).await?;
+    debug!("shard-transfer server: {request:?}");
+
+    match request {
+        ShardTransferRequest::List => {
+            let reply = match served.files() {
+                Ok(files) => ShardTransferReply::Listing {
+                    model: served.model.clone(),
+                    revision: served
+                        .dir
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    files,
+                },
+                Err(e) => ShardTransferReply::Error(format!("listing snapshot: {e}")),
+            };
+            write_message(&mut stream, &reply).await
+        }
+        ShardTransferRequest::Fetch { file, range } => {
+            let Some(path) = served.resolve(&file) else {
+                let reply = ShardTransferReply::Error(format!("not serving '{file}'"));
+                return write_message(&mut stream, &reply).await;
+            };
+            let file_len = std::fs::metadata(&path)?.len();
+            let (base, len) = range.unwrap_or((0, file_len));
+            if base.checked_add(len).is_none_or(|end| end > file_len) {
+                let reply = ShardTransferReply::Error(format!("range outside '{file}'"));
+                return write_message(&mut stream, &reply).await;
+            }
+            write_message(&mut stream, &ShardTransferReply::Sending { size: len }).await?;
+            let mut source = FileRange {
+                file: File::open(&path)?,
+                base,
+                len,
+            };
+            let id = transfer_id(&file, &path, base)?;
+            chunked::send_chunked(&mut stream, &mut source, id, &TransferConfig::default()).await?;
+            debug!("shard-transfer: sent {file} [{base}, +{len})");
+            Ok(())
+        }
+    }
+}
+
+// ── Client ────────────────────────────────────────────────────────────────────
+
+async fn open_request(
+    peer_id: PeerId,
+    request: &ShardTransferRequest,
+) -> Result<(Throttled<P2PStream>, ShardTransferReply)> {
+    let p2p = P2PClient::connect(&crate::shard_cmd::daemon_socket())
+        .await
+        .context("connect to p2pd for shard transfer")?;
+    // See InferenceMuxClient::connect: the daemon socket becomes the data channel.
+    let stream = p2p
+        .stream_open_raw(&peer_id.to_bytes(), vec![SHARD_TRANSFER_PROTO.to_string()])
+        .await
+        .with_context(|| format!("open shard-transfer stream to {}", peer_id.to_base58()))?;
+    let mut stream = crate::bandwidth::throttle(stream);
+    write_message(&mut stream, request).await?;
+    let reply = read_message(&mut stream).await?;
+    if let ShardTransferReply::Error(e) = reply {
+        bail!("peer {} refused: {e}", peer_id.to_base58());
+    }
+    Ok((stream, reply))
+}
+
+/// A peer's answer to [`ShardTransferRequest::List`].
+#[derive(Debug, Clone)]
+pub struct PeerListing {
+    pub peer: PeerId,
+    pub model: String,
+    pub revision: String,
+    pub files: Vec<ShardFile>,
+}
+
+/// Ask `peer_id` which model snapshot it serves.
+pub async fn list_peer_files(peer_id: PeerId) -> Result<PeerListing> {
+    match open_request(peer_id, &ShardTransferRequest::List).await? {
+        (
+            _,
+            ShardTransferReply::Listing {
+                model,
+                revision,
+                files,
+            },
+        ) => Ok(PeerListing {
+            peer: peer_id,
+            model,
+            revision,
+            files,
+        }),
+        (_, other) => bail!("unexpected reply to List: {other:?}"),
+    }
+}
+
+/// A file to download and the peers that can send it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PlannedFile {
+    name: String,
+    size: u64,
+    hash: hf::FileHash,
+    peers: Vec<PeerId>,
+}
+
+/// Merge listings of the same revision into one download plan, taking size
+/// and hash from `expected` (the Hub's listing of that revision). Files the
+/// Hub doesn't list or that aren't plain inference file names are skipped,
+/// and a peer is only used for a file when its size and, if it reported
+/// one, its SHA-256 agree with the Hub.
+fn plan_files(
+    listings: &[PeerListing],
+    expected: &HashMap<String, hf::HubFile>,
+) -> Vec<PlannedFile> {
+    let mut by_name: HashMap<&str, Vec<PeerId>> = HashMap::new();
+    for listing in listings {
+        for f in &listing.files {
+            if !is_transfer_name(&f.name) {
+                warn!(
+                    "shard-transfer: {} listed an invalid file name {:?}",
+                    listing.peer.to_base58(),
+                    f.name
+                );
+                continue;
+            }
+            let Some(hub) = expected.get(&f.name) else {
+                debug!("shard-transfer: {} has no hash on the Hub; skipped", f.name);
+                continue;
+            };
+            let sha_disagrees = match (&hub.hash, &f.sha256) {
+                (hf::FileHash::Sha256(want), Some(got)) => !want.eq_ignore_ascii_case(got),
+                _ => false,
+            };
+            if f.size != hub.size || sha_disagrees {
+                warn!(
+                    "shard-transfer: {} serves a different {}; not using it for that file",
+                    listing.peer.to_base58(),
+                    f.name
+                );
+                continue;
+            }
+            by_name
+                .entry(f.name.as_str())
+                .or_default()
+                .push(listing.peer);
+        }
+    }
+
+    let mut plan: Vec<PlannedFile> = by_name
+        .into_iter()
+        .map(|(name, mut peers)| {
+            peers.sort();
+            peers.dedup();
+            let hub = &expected[name];
+            PlannedFile {
+                name: name.to_string(),
+                size: hub.size,
+                hash: hub.hash.clone(),
+                peers,
+            }
+        })
+        .collect();
+    plan.sort_by(|a, b| a.name.cmp(&b.name));
+    plan
+}
+
+/// Which pieces of a `.part` file are already on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PieceState {
+    size: u64,
+    /// Hex hash the finished file must have
+    hash: String,
+    done: Vec<bool>,
+}
+
+impl PieceState {
+    fn new(size: u64, hash: String) -> Self {
+        Self {
+            size,
+            hash,
+            done: vec![false; size.div_ceil(PIECE_SIZE) as usize],
+        }
+    }
+
+    fn piece_range(&self, idx: usize) -> (u64, u64) {
+        let offset = idx as u64 * PIECE_SIZE;
+        (offset, (self.size - offset).min(PIECE_SIZE))
+    }
+
+    fn missing(&self) -> VecDeque<usize> {
+        (0..self.done.len()).filter(|&i| !self.done[i]).collect()
+    }
+}
+
+fn part_paths(dest: &Path) -> (PathBuf, PathBuf) {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    (
+        dest.with_file_name(format!("{name}.part")),
+        dest.with_file_name(format!("{name}.part.pieces")),
+    )
+}
+
+/// Pick the peer for piece `idx`: the least busy one that has not failed too
+/// often, spreading ties across peers by piece index.
+fn pick_peer(
+    peers: &[PeerId],
+    idx: usize,
+    busy: &HashMap<PeerId, usize>,
+    failures: &HashMap<PeerId, u32>,
+) -> Option<PeerId> {
+    (0..peers.len())
+        .map(|i| peers[(idx + i) % peers.len()])
+        .filter(|p| failures.get(p).copied().unwrap_or(0) < MAX_PEER_FAILURES)
+        .min_by_key(|p| busy.get(p).copied().unwrap_or(0))
+}
+
+/// Fetch one piece into the `.part` file, resuming within the piece when
+/// `progress` is from an earlier attempt against the same peer.
+async fn fetch_piece(
+    peer: PeerId,
+    name: String,
+    part: PathBuf,
+    (offset, len): (u64, u64),
+    mut progress: Option<TransferProgress>,
+) -> (Result<()>, Option<TransferProgress>) {
+    let result = async {
+        let request = ShardTransferRequest::Fetch {
+            file: name,
+            range: Some((offset, len)),
+        };
+        let (mut stream, reply) = open_request(peer, &request).await?;
+        match reply {
+            ShardTransferReply::Sending { size } if size == len => {}
+            other => bail!("unexpected reply to Fetch: {other:?}"),
+        }
+        let mut sink = FileRange {
+            file: std::fs::OpenOptions::new().write(true).open(&part)?,
+            base: offset,
+            len,
+        };
+        chunked::receive_chunked(
+            &mut stream,
+            &mut sink,
+            &mut progress,
+            &TransferConfig::default(),
+        )
+        .await?;
+        sink.file.sync_data()?;
+        Ok(())
+    }
+    .await;
+    (result, progress)
+}
+
+/// Download `file` into `dest` from its peers, several pieces at a time.
+async fn fetch_file(file: &PlannedFile, dest: &Path) -> Result<()> {
+    let (part, state_path) = part_paths(dest);
+    let mut state = std::fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|t| serde_json::from_str::<PieceState>(&t).ok())
+        .filter(|s| s.size == file.size && s.hash == file.hash.hex() && part.exists())
+        .unwrap_or_else(|| PieceState::new(file.size, file.hash.hex().to_string()));
+    if !state.done.contains(&true) {
+        File::create(&part)?.set_len(file.size)?;
+    }
+
+    let mut queue = state.missing();
+    let mut failures: HashMap<PeerId, u32> = HashMap::new();
+    let mut busy: HashMap<PeerId, usize> = HashMap::new();
+    let mut progress: HashMap<(usize, PeerId), TransferProgress> = HashMap::new();
+    let mut tasks = JoinSet::new();
+
+    loop {
+        while tasks.len() < MAX_PARALLEL_PIECES {
+            let Some(&idx) = queue.front() else { break };
+            let Some(peer) = pick_peer(&file.peers, idx, &busy, &failures) else {
+                break;
+            };
+            queue.pop_front();
+            *busy.entry(peer).or_default() += 1;
+            let piece = fetch_piece(
+                peer,
+                file.name.clone(),
+                part.clone(),
+                state.piece_range(idx),
+                progress.remove(&(idx, peer)),
+            );
+            tasks.spawn(async move { (idx, peer, piece.await) });
+        }
+
+        let Some(joined) = tasks.join_next().await else {
+            if queue.is_empty() {
+                break;
+            }
+            bail!("every peer failed to send '{}'", file.name);
+        };
+        let (idx, peer, (result, piece_progress)) = joined?;
+        *busy.entry(peer).or_default() -= 1;
+        match result {
+            Ok(()) => {
+                state.done[idx] = true;
+                let _ = std::fs::write(&state_path, serde_json::to_string(&state)?);
+            }
+            Err(e) => {
+                warn!(
+                    "shard-transfer: piece {idx} of {} from {} failed: {e:#}",
+                    file.name,
+                    peer.to_base58()
+                );
+                *failures.entry(peer).or_default() += 1;
+                if let Some(p) = piece_progress {
+                    progress.insert((idx, peer), p);
+                }
+                queue.push_back(idx);
+            }
+        }
+    }
+
+    let (part_path, expected) = (part.clone(), file.hash.clone());
+    if !tokio::task::spawn_blocking(move || hash_matches(&part_path, &expected)).await?? {
+        let _ = std::fs::remove_file(&part);
+        let _ = std::fs::remove_file(&state_path);
+        bail!(
+            "hash mismatch for '{}': expected {}",
+            file.name,
+            file.hash.hex()
+        );
+    }
+    std::fs::rename(&part, dest).with_context(|| format!("move {} into place", part.display()))?;
+    let _ = std::fs::remove_file(&state_path);
+    Ok(())
+}
+
+async fn fetch_into(snapshot_dir: &Path, file: &PlannedFile) -> Result<()> {
+    if !is_transfer_name(&file.name) {
+        bail!("refusing to write '{}' outside the snapshot", file.name);
+    }
+    let dest = snapshot_dir.join(&file.name);
+    if std::fs::metadata(&dest).is_ok_and(|m| m.len() == file.size) {
+        println!(
+            "  {}  — already cached ({})",
+            file.name,
+            hf::fmt_bytes(file.size)
+        );
+        return Ok(());
+    }
+    fetch_file(file, &dest)
+        .await
+        .with_context(|| format!("Failed to fetch '{}'", file.name))?;
+    println!(
+        "  {}  — {} from {} peer(s)",
+        file.name,
+        hf::fmt_bytes(file.size),
+        file.peers.len()
+    );
+    Ok(())
+}
+
+/// Download `model_id` from peers running `kwaainet shard serve` into the
+/// HuggingFace cache layout, returning the snapshot directory.
+///
+/// With `blocks = Some((start, end, is_first, is_last))` only the weight files
+/// for that block range are fetched, like [`hf::download_for_blocks`].
+/// `hf_token` is only used to look up the expected file hashes on the Hub.
+pub async fn download_from_peers(
+    peers: &[PeerId],
+    model_id: &str,
+    blocks: Option<(usize, usize, bool, bool)>,
+    hf_token: Option<&str>,
+) -> Result<PathBuf> {
+    let mut listings = Vec::new();
+    for result in futures::future::join_all(peers.iter().map(|&p| list_peer_files(p))).await {
+        match result {
+            Ok(l) if l.model == model_id => listings.push(l),
+            Ok(l) => debug!("{} serves {}, not {model_id}", l.peer.to_base58(), l.model),
+            Err(e) => warn!("shard-transfer: listing failed: {e:#}"),
+        }
+    }
+
+    // Everyone must serve the same snapshot; go with the most common one.
+    let mut revisions: HashMap<&str, usize> = HashMap::new();
+    for l in &listings {
+        *revisions.entry(l.revision.as_str()).or_default() += 1;
+    }
+    let Some(revision) = revisions
+        .into_iter()
+        .max_by_key(|(rev, n)| (*n, *rev))
+        .map(|(rev, _)| rev.to_string())
+    else {
+        bail!("no peer serves '{model_id}'");
+    };
+    if revision.is_empty() || revision.contains(['/', '\\']) || revision == ".." {
+        bail!("peers sent an invalid snapshot revision '{revision}'");
+    }
+    listings.retain(|l| l.revision == revision);
+
+    // Peers only say what they have; what the files must contain comes from
+    // the Hub.
+    let expected = hf::revision_files(model_id, &revision, hf_token)
+        .await
+        .context("Cannot look up the expected file hashes, so peer files can't be verified")?;
+
+    let snapshot_dir = hf::snapshot_dir(model_id, &revision)?;
+    std::fs::create_dir_all(&snapshot_dir)
+        .with_context(|| format!("Cannot create cache dir: {}", snapshot_dir.display()))?;
+
+    println!("  Peers:  {}", listings.len());
+    println!("  Commit: {}", &revision[..revision.len().min(12)]);
+    println!("  Dest:   {}", snapshot_dir.display());
+    println!();
+
+    // Metadata (including the safetensors index) first, so the weight files
+    // for a block range can be picked from the index.
+    let (metadata, weights): (Vec<_>, Vec<_>) = plan_files(&listings, &expected)
+        .into_iter()
+        .partition(|f| !f.name.ends_with(".safetensors"));
+    for f in &metadata {
+        fetch_into(&snapshot_dir, f).await?;
+    }
+
+    let needed = blocks.and_then(|(start, end, is_first, is_last)| {
+        hf::weight_files_for_blocks(&snapshot_dir, start, end, is_first, is_last)
+            .filter(|n| !n.is_empty())
+    });
+    for f in &weights {
+        if needed.as_ref().is_none_or(|n| n.contains(&f.name)) {
+            fetch_into(&snapshot_dir, f).await?;
+        }
+    }
+
+    Ok(snapshot_dir)
+}
+
+/// Download the files for `[start, end)` from peers that provide those
+/// blocks in the DHT. `Ok(None)` when nobody provides them.
+pub async fn download_from_swarm(
+    model_id: &str,
+    (start, end, is_first, is_last): (usize, usize, bool, bool),
+    hf_token: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    let config = KwaaiNetConfig::load_or_create()?;
+    let dht = crate::dht_backend::open(&config)
+        .await
+        .context("open the DHT to find shard providers")?;
+    let local = crate::dht_backend::node_identity(&config)
+        .ok()
+        .map(|id| id.peer_id);
+    let peers = find_block_providers(dht.as_ref(), model_id, start, end, local).await;
+    if peers.is_empty() {
+        return Ok(None);
+    }
+    info!(
+        "shard-transfer: {} peer(s) provide blocks [{start}, {end}) of {model_id}",
+        peers.len()
+    );
+    download_from_peers(
+        &peers,
+        model_id,
+        Some((start, end, is_first, is_last)),
+        hf_token,
+    )
+    .await
+    .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_only_inference_files_in_snapshot() {
+        let dir = std::env::temp_dir().join(format!("kwaainet-shard-xfer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), b"{}").unwrap();
+        std::fs::write(dir.join("model-00001-of-00002.safetensors"), [0u8; 16]).unwrap();
+        std::fs::write(dir.join("README.md"), b"hi").unwrap();
+        let served = ServedSnapshot::new("owner/model".into(), dir.clone());
+
+        served.compute_hashes().unwrap();
+        let files = served.files().unwrap();
+        assert_eq!(
+            files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+            ["config.json", "model-00001-of-00002.safetensors"]
+        );
+        assert_eq!(files[1].size, 16);
+        assert_eq!(
+            files[0].sha256.as_deref(),
+            Some("44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a")
+        );
+        assert!(served.resolve("README.md").is_none());
+        assert!(served.resolve("../secret.safetensors").is_none());
+        assert!(served.resolve("..").is_none());
+        assert!(served.resolve("missing.safetensors").is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn hub(entries: &[(&str, u64, hf::FileHash)]) -> HashMap<String, hf::HubFile> {
+        entries
+            .iter()
+            .map(|(name, size, hash)| {
+                let file = hf::HubFile {
+                    size: *size,
+                    hash: hash.clone(),
+                };
+                (name.to_string(), file)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filtered_peers_are_refused_before_the_request() {
+        let dir =
+            std::env::temp_dir().join(format!("kwaainet-shard-filter-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), b"{}").unwrap();
+        let served = ServedSnapshot::new("owner/model".into(), dir.clone());
+        let (denied, other) = (PeerId::random(), PeerId::random());
+        crate::peer_filter::deny_for_test(&[&denied.to_base58(), "203.0.113.0/24"]);
+        let lan: libp2p::Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+        let blocked_net: libp2p::Multiaddr = "/ip4/203.0.113.9/tcp/4001".parse().unwrap();
+
+        for (peer, addr, answered) in [
+            (denied, &lan, false),
+            // Denied by the address the stream came from.
+            (other, &blocked_net, false),
+            (other, &lan, true),
+        ] {
+            let (mut client, server) =
+                crate::peer_filter::forwarded_stream(&peer, addr, SHARD_TRANSFER_PROTO).await;
+            write_message(&mut client, &ShardTransferRequest::List)
+                .await
+                .unwrap();
+            handle_shard_stream(server, &served).await.unwrap();
+            let reply = read_message::<_, ShardTransferReply>(&mut client).await;
+            assert_eq!(reply.is_ok(), answered, "{peer} at {addr}");
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn plan_takes_hashes_from_the_hub_not_peers() {
+        let peers: Vec<PeerId> = (0..5).map(|_| PeerId::random()).collect();
+        let file = |size, sha: Option<&str>| ShardFile {
+            name: "model.safetensors".into(),
+            size,
+            sha256: sha.map(String::from),
+        };
+        let listing = |peer, files| PeerListing {
+            peer,
+            model: "m".into(),
+            revision: "r".into(),
+            files,
+        };
+        let expected = hub(&[("model.safetensors", 10, hf::FileHash::Sha256("good".into()))]);
+        // The "evil" majority doesn't outvote the Hub.
+        let plan = plan_files(
+            &[
+                listing(peers[0], vec![file(10, Some("good"))]),
+                listing(peers[1], vec![file(10, Some("evil"))]),
+                listing(peers[2], vec![file(10, Some("evil"))]),
+                listing(peers[3], vec![file(10, None)]),
+                listing(peers[4], vec![file(11, None)]),
+            ],
+            &expected,
+        );
+
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].hash, hf::FileHash::Sha256("good".into()));
+        let mut want = vec![peers[0], peers[3]];
+        want.sort();
+        assert_eq!(plan[0].peers, want);
+
+        // Nothing is planned without a hash to check it against.
+        assert!(plan_files(&[listing(peers[0], vec![file(10, None)])], &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn plan_skips_names_that_are_paths() {
+        let peer = PeerId::random();
+        let names = [
+            "../model.safetensors",
+            "sub/model.safetensors",
+            "sub\\model.safetensors",
+            "/model.safetensors",
+        ];
+        let expected = hub(&names.map(|n| (n, 1, hf::FileHash::Sha256("h".into()))));
+        let listing = PeerListing {
+            peer,
+            model: "m".into(),
+            revision: "r".into(),
+            files: names
+                .iter()
+                .map(|n| ShardFile {
+                    name: n.to_string(),
+                    size: 1,
+                    sha256: None,
+                })
+                .collect(),
+        };
+        assert!(plan_files(&[listing], &expected).is_empty());
+    }
+
+    #[test]
+    fn git_blob_hash_matches_hub_blob_id() {
+        let path = std::env::temp_dir().join(format!("kwaainet-blob-{}.json", std::process::id()));
+        std::fs::write(&path, b"{}").unwrap();
+        let blob = hf::FileHash::GitBlob("9e26dfeeb6e641a33dae4961196235bdb965b21b".into());
+        assert!(hash_matches(&path, &blob).unwrap());
+        assert!(!hash_matches(&path, &hf::FileHash::GitBlob("00".into())).unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn pieces_spread_over_peers_and_skip_failing_ones() {
+        let peers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        let mut busy = HashMap::new();
+        let mut failures = HashMap::new();
+
+        let picks: Vec<_> = (0..3)
+            .map(|idx| {
+                let p = pick_peer(&peers, idx, &busy, &failures).unwrap();
+                *busy.entry(p).or_insert(0) += 1;
+                p
+            })
+            .collect();
+        assert_eq!(picks, peers);
+
+        failures.insert(peers[0], MAX_PEER_FAILURES);
+        failures.insert(peers[1], MAX_PEER_FAILURES);
+        assert_eq!(pick_peer(&peers, 0, &busy, &failures), Some(peers[2]));
+        failures.insert(peers[2], MAX_PEER_FAILURES);
+        assert_eq!(pick_peer(&peers, 0, &busy, &failures), None);
+    }
+
+    #[test]
+    fn piece_ranges_cover_file() {
+        let state = PieceState::new(2 * PIECE_SIZE + 5, String::new());
+        assert_eq!(state.done.len(), 3);
+        assert_eq!(state.piece_range(0), (0, PIECE_SIZE));
+        assert_eq!(state.piece_range(2), (2 * PIECE_SIZE, 5));
+        assert_eq!(state.missing(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn provider_key_names_model_and_layer() {
+        assert_eq!(
+            shard_provider_key("owner/model", 7),
+            "model-shard:owner/model:7"
+        );
+    }
+
+    #[test]
+    fn partial_downloads_sit_next_to_destination() {
+        let (part, pieces) = part_paths(Path::new("/cache/snap/model.safetensors"));
+        assert_eq!(part, Path::new("/cache/snap/model.safetensors.part"));
+        assert_eq!(
+            pieces,
+            Path::new("/cache/snap/model.safetensors.part.pieces")
+        );
+    }
+}
@@ -227,7 +227,11 @@ async fn serve() -> Result<()> {
     let _p2p_client = match kwaai_p2p_daemon::P2PClient::connect(&daemon_addr).await {
         Ok(p2p_client) => {
             match p2p_client
-                .add_unary_handler(crate::storage_rpc::STORAGE_PROTO, handler, false)
+                .add_unary_handler(
+                    crate::storage_rpc::STORAGE_PROTO,
+                    crate::peer_filter::guard(handler),
+                    false,
+                )
                 .await
             {
                 Ok(()) => print_success(&format!(
@@ -236,6 +240,7 @@ async fn serve() -> Result<()> {
                 )),
                 Err(e) => print_warning(&format!("P2P handler registration failed: {e}")),
             }
+            tokio::spawn(crate::peer_filter::run_address_refresh(p2p_client.clone()));
             Some(p2p_client)
         }
         Err(_) => {
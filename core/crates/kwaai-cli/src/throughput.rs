@@ -12,29 +12,62 @@
 //!                 1.0  if direct connection
 //! ```
 //!
+//! `compute_tps` comes from the shard server's own benchmark when it has one:
+//! `shard serve` runs random hidden states through the blocks it loaded and
+//! records per-block rates (`inference_rps`, `forward_rps`), re-measuring
+//! every `throughput_refresh_secs` while idle. A span of `n` blocks then
+//! decodes at `inference_rps / n` tokens/s. Without that measurement the
+//! full-model decode rate from `kwaainet benchmark` is used.
+//!
 //! ## Cache file
 //!
 //! `~/.kwaainet/throughput_cache.json` — keyed by model name.
-//! Format: `{ "model-name": { "compute_tps": <f64>, "hidden_size": <usize> } }`
+//! Format: `{ "model-name": { "compute_tps": <f64>, "hidden_size": <usize>,
+//! "inference_rps": <f64>, "forward_rps": <f64> } }` — the two rates are
+//! absent until `shard serve` has measured them.
 //!
 //! Older entries written as plain `f64` by the previous version are silently
 //! ignored (the deserializer returns `None` for those keys).
 
 use crate::config::kwaainet_dir;
 use anyhow::Result;
+use kwaai_inference::BlockThroughput;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
 // ── Cache entry ───────────────────────────────────────────────────────────────
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ThroughputEntry {
     /// Decode throughput in tokens/second measured by `kwaainet generate`.
     pub compute_tps: f64,
     /// Hidden dimension of the model architecture (number of F16 elements per
     /// token passed between layers). Used in the network-bandwidth formula.
     pub hidden_size: usize,
+    /// Single-token decode steps/second through one block, measured by
+    /// `shard serve` on the blocks it serves. 0 until measured.
+    #[serde(default)]
+    pub inference_rps: f64,
+    /// Prefill tokens/second through one block, measured alongside
+    /// `inference_rps`. 0 until measured.
+    #[serde(default)]
+    pub forward_rps: f64,
+}
+
+impl ThroughputEntry {
+    /// Decode tokens/second this node sustains through a span of
+    /// `span_blocks` blocks.
+    ///
+    /// Prefers the per-block shard benchmark, which was taken on the blocks
+    /// and device actually being served; falls back to `compute_tps`.
+    pub fn span_tps(&self, span_blocks: u32) -> f64 {
+        if self.inference_rps > 0.0 && span_blocks > 0 {
+            self.inference_rps / span_blocks as f64
+        } else {
+            self.compute_tps
+        }
+    }
 }
 
 // ── File path ─────────────────────────────────────────────────────────────────
@@ -47,17 +80,28 @@ pub fn cache_file() -> PathBuf {
 
 /// Persist a decode throughput measurement for `model`.
 pub fn save(model: &str, compute_tps: f64, hidden_size: usize) -> Result<()> {
+    update(model, |entry| {
+        entry.compute_tps = compute_tps;
+        entry.hidden_size = hidden_size;
+    })
+}
+
+/// Persist the per-block rates measured by `shard serve` for `model`.
+pub fn save_block_rates(model: &str, hidden_size: usize, rates: &BlockThroughput) -> Result<()> {
+    update(model, |entry| {
+        entry.hidden_size = hidden_size;
+        entry.inference_rps = rates.inference_rps;
+        entry.forward_rps = rates.forward_rps;
+    })
+}
+
+/// Read-modify-write the entry for `model`, keeping fields `f` leaves alone.
+fn update(model: &str, f: impl FnOnce(&mut ThroughputEntry)) -> Result<()> {
     let path = cache_file();
     std::fs::create_dir_all(path.parent().expect("cache_file has a parent"))?;
 
     let mut cache: HashMap<String, ThroughputEntry> = load_cache();
-    cache.insert(
-        model.to_string(),
-        ThroughputEntry {
-            compute_tps,
-            hidden_size,
-        },
-    );
+    f(cache.entry(model.to_string()).or_default());
     std::fs::write(&path, serde_json::to_string_pretty(&cache)?)?;
     Ok(())
 }
@@ -138,6 +182,17 @@ pub async fn measure_download_bps() -> f64 {
 
 // ── Petals formula ────────────────────────────────────────────────────────────
 
+/// Tokens/second the network link can carry: `download_bps / (hidden_size × 16)`.
+///
+/// Each token exchange transfers `hidden_size` F16 elements = hidden_size × 16
+/// bits. Returns `0.0` when either input is unknown.
+pub fn network_rps(hidden_size: usize, download_bps: f64) -> f64 {
+    if download_bps <= 0.0 || hidden_size == 0 {
+        return 0.0;
+    }
+    download_bps / (hidden_size as f64 * 16.0)
+}
+
 /// Compute effective throughput using the Petals formula.
 ///
 /// ```text
@@ -145,18 +200,24 @@ pub async fn measure_download_bps() -> f64 {
 /// effective_tps = min(compute_tps, network_rps × relay_penalty)
 /// ```
 ///
-/// If `download_bps == 0` (measurement failed), network is not the bottleneck
-/// and we return `compute_tps` unchanged — a conservative but safe fallback.
-pub fn effective_tps(entry: &ThroughputEntry, download_bps: f64, using_relay: bool) -> f64 {
+/// `compute_tps` is [`ThroughputEntry::span_tps`] for the `span_blocks` this
+/// node serves. If `download_bps == 0` (measurement failed), network is not
+/// the bottleneck and we return `compute_tps` unchanged — a conservative but
+/// safe fallback.
+pub fn effective_tps(
+    entry: &ThroughputEntry,
+    span_blocks: u32,
+    download_bps: f64,
+    using_relay: bool,
+) -> f64 {
     let penalty = if using_relay { RELAY_PENALTY } else { 1.0 };
+    let compute_tps = entry.span_tps(span_blocks);
 
-    if download_bps <= 0.0 || entry.hidden_size == 0 {
-        return entry.compute_tps;
+    let network_rps = network_rps(entry.hidden_size, download_bps);
+    if network_rps <= 0.0 {
+        return compute_tps;
     }
-
-    // Each token exchange transfers `hidden_size` F16 elements = hidden_size × 16 bits.
-    let network_rps = download_bps / (entry.hidden_size as f64 * 16.0);
-    entry.compute_tps.min(network_rps * penalty)
+    compute_tps.min(network_rps * penalty)
 }
 
 // ── Tests ─────────────────────────────────────────────────────────────────────
@@ -172,8 +233,9 @@ mod tests {
         let entry = ThroughputEntry {
             compute_tps: 20.0,
             hidden_size: 4096,
+            ..Default::default()
         };
-        let tps = effective_tps(&entry, 100_000_000.0, true);
+        let tps = effective_tps(&entry, 8, 100_000_000.0, true);
         assert!((tps - 20.0).abs() < 0.01, "expected 20.0, got {tps}");
     }
 
@@ -184,8 +246,9 @@ mod tests {
         let entry = ThroughputEntry {
             compute_tps: 100.0,
             hidden_size: 4096,
+            ..Default::default()
         };
-        let tps = effective_tps(&entry, 1_000_000.0, true);
+        let tps = effective_tps(&entry, 8, 1_000_000.0, true);
         // network_rps = 1_000_000 / (4096 * 16) = 15.26, × 0.2 = 3.05
         assert!(tps < 5.0, "expected network-bound (<5), got {tps}");
         assert!(tps > 2.0, "expected >2, got {tps}");
@@ -197,8 +260,9 @@ mod tests {
         let entry = ThroughputEntry {
             compute_tps: 100.0,
             hidden_size: 4096,
+            ..Default::default()
         };
-        let tps = effective_tps(&entry, 1_000_000.0, false);
+        let tps = effective_tps(&entry, 8, 1_000_000.0, false);
         assert!(tps > 14.0 && tps < 16.0, "expected ~15.3, got {tps}");
     }
 
@@ -208,7 +272,25 @@ mod tests {
         let entry = ThroughputEntry {
             compute_tps: 7.5,
             hidden_size: 4096,
+            ..Default::default()
+        };
+        assert_eq!(effective_tps(&entry, 8, 0.0, true), 7.5);
+    }
+
+    #[test]
+    fn test_span_tps_prefers_block_benchmark() {
+        let mut entry = ThroughputEntry {
+            compute_tps: 7.5,
+            hidden_size: 4096,
+            ..Default::default()
         };
-        assert_eq!(effective_tps(&entry, 0.0, true), 7.5);
+        assert_eq!(entry.span_tps(8), 7.5);
+
+        // 80 decode steps/s per block across an 8-block span → 10 tok/s.
+        entry.inference_rps = 80.0;
+        assert_eq!(entry.span_tps(8), 10.0);
+        assert_eq!(effective_tps(&entry, 8, 0.0, false), 10.0);
+        // No span (nothing served yet) → fall back to the full-model figure.
+        assert_eq!(entry.span_tps(0), 7.5);
     }
 }
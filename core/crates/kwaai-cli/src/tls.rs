@@ -0,0 +1,213 @@
+//! HTTPS for the OpenAI-compatible API server (`api.tls` in config.yaml).
+//!
+//! `kwaainet serve` terminates TLS itself with rustls, so remote clients can
+//! reach `/v1` securely without a reverse proxy in front. The certificate
+//! comes from one of two places:
+//!
+//!   - `api.tls.cert` / `api.tls.key`: PEM files, e.g. from Let's Encrypt
+//!   - `api.tls.self_signed`: a certificate for localhost and
+//!     `api.tls.self_signed_names`, generated on first start and kept in
+//!     `tls/` under the KwaaiNet directory. Clients must trust it explicitly
+//!     (`curl --cacert`); delete the files to regenerate it.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::config::ApiTlsConfig;
+
+/// Clients that have not finished the handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS settings resolved for the API server.
+pub struct ApiTls {
+    pub config: Arc<ServerConfig>,
+    /// Certificate being served
+    pub cert_path: PathBuf,
+    /// Whether `cert_path` was generated by [`load`]
+    pub self_signed: bool,
+}
+
+/// Build the rustls config for `tls`, or `None` when HTTPS is off.
+pub fn load(tls: &ApiTlsConfig) -> Result<Option<ApiTls>> {
+    load_in(tls, &crate::config::kwaainet_dir().join("tls"))
+}
+
+fn load_in(tls: &ApiTlsConfig, self_signed_dir: &Path) -> Result<Option<ApiTls>> {
+    if !tls.enabled() {
+        return Ok(None);
+    }
+    let (cert_path, key_path, self_signed) = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone(), false),
+        (Some(_), None) | (None, Some(_)) => {
+            bail!("api.tls.cert and api.tls.key must be set together")
+        }
+        (None, None) => {
+            let cert = self_signed_dir.join("api-cert.pem");
+            let key = self_signed_dir.join("api-key.pem");
+            if !cert.exists() || !key.exists() {
+                generate_self_signed(&tls.self_signed_names, &cert, &key)?;
+            }
+            (cert, key, true)
+        }
+    };
+
+    let certs = read_certs(&cert_path)?;
+    let key = read_key(&key_path)?;
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| format!("{} does not match its key", cert_path.display()))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(ApiTls {
+        config: Arc::new(config),
+        cert_path,
+        self_signed,
+    }))
+}
+
+/// Write a self-signed certificate for localhost and `names`.
+fn generate_self_signed(names: &[String], cert_path: &Path, key_path: &Path) -> Result<()> {
+    let mut sans = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    for name in names {
+        if !sans.contains(name) {
+            sans.push(name.clone());
+        }
+    }
+    let cert = rcgen::generate_simple_self_signed(sans.clone())
+        .context("generating self-signed certificate")?;
+
+    if let Some(dir) = cert_path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    std::fs::write(key_path, cert.serialize_private_key_pem())
+        .with_context(|| format!("writing {}", key_path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(key_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::fs::write(cert_path, cert.serialize_pem()?)
+        .with_context(|| format!("writing {}", cert_path.display()))?;
+    info!(
+        "Generated self-signed API certificate for {} ({})",
+        sans.join(", "),
+        cert_path.display()
+    );
+    Ok(())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .with_context(|| format!("parsing {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("{} holds no PEM certificate", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let pem = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let items = rustls_pemfile::read_all(&mut pem.as_slice())
+        .with_context(|| format!("parsing {}", path.display()))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("{} holds no PEM private key", path.display()))
+}
+
+/// Serve `app` over HTTPS on `listener` until the process exits.
+///
+/// Handlers see the client address through `ConnectInfo<SocketAddr>`, as
+/// with `axum::serve` over plain HTTP.
+pub async fn serve(listener: TcpListener, tls: Arc<ServerConfig>, app: Router) -> Result<()> {
+    let acceptor = TlsAcceptor::from(tls);
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Typically EMFILE; back off instead of spinning.
+                warn!("API server accept failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {} timed out", peer);
+                    return;
+                }
+            };
+            crate::listen::serve_connection(stream, app, peer).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_signed_is_generated_once_and_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = ApiTlsConfig {
+            self_signed: true,
+            self_signed_names: vec!["node.example.org".into()],
+            ..Default::default()
+        };
+        let first = load_in(&tls, dir.path()).unwrap().expect("TLS enabled");
+        assert!(first.self_signed);
+        let pem = std::fs::read(&first.cert_path).unwrap();
+
+        // Reused on the next start rather than regenerated.
+        let second = load_in(&tls, dir.path()).unwrap().unwrap();
+        assert_eq!(std::fs::read(&second.cert_path).unwrap(), pem);
+
+        // The same files work as an explicit cert/key pair.
+        let explicit = ApiTlsConfig {
+            cert: Some(dir.path().join("api-cert.pem")),
+            key: Some(dir.path().join("api-key.pem")),
+            ..Default::default()
+        };
+        assert!(!load_in(&explicit, dir.path()).unwrap().unwrap().self_signed);
+    }
+
+    #[test]
+    fn disabled_or_half_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_in(&ApiTlsConfig::default(), dir.path())
+            .unwrap()
+            .is_none());
+        let cert_only = ApiTlsConfig {
+            cert: Some(dir.path().join("cert.pem")),
+            ..Default::default()
+        };
+        assert!(load_in(&cert_only, dir.path()).is_err());
+    }
+}
@@ -1,8 +1,8 @@
-//! Self-update checker via GitHub releases API
+//! Self-update via GitHub releases: check, verified install and rollback
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::debug;
 
 const RELEASES_URL: &str = "https://api.github.com/repos/Kwaai-AI-Lab/KwaaiNet/releases/latest";
@@ -47,10 +47,13 @@ impl UpdateChecker {
     }
 
     /// Check for a newer release. Returns `Some(UpdateInfo)` if one exists.
+    ///
+    /// Without `force` the result may come from a 24-hour cache, and a
+    /// version left by `kwaainet update --rollback` is not offered.
     pub async fn check(&self, force: bool) -> Result<Option<UpdateInfo>> {
         if !force {
             if let Some(cached) = self.load_cache() {
-                return Ok(cached);
+                return Ok(cached.filter(|info| !is_skipped(info)));
             }
         }
 
@@ -82,7 +85,8 @@ impl UpdateChecker {
         };
 
         self.save_cache(&update)?;
-        Ok(update)
+        // A version the user rolled back from is only offered on request.
+        Ok(update.filter(|info| force || !is_skipped(info)))
     }
 
     fn load_cache(&self) -> Option<Option<UpdateInfo>> {
@@ -120,375 +124,525 @@ impl UpdateChecker {
         Ok(())
     }
 
-    /// Download and install the update for this platform.
-    /// `version` is the target version string (e.g. "0.4.13"), used to build
-    /// version-specific archive URLs so we don't have to re-resolve "latest".
+    /// Download, verify and install release `version` (e.g. "0.4.13").
+    ///
+    /// The archive's SHA-256 is checked against the `.sha256` file published
+    /// next to it, then `kwaainet` and `p2pd` (plus bundled CUDA libraries)
+    /// are swapped in by rename. Each replaced file is kept as `<name>.old`
+    /// for [`rollback`]; nothing is touched if any step before the swap fails.
     pub async fn install_update(&self, version: &str) -> Result<()> {
-        #[cfg(unix)]
-        {
-            // On Linux, prefer the CUDA-enabled binary for NVIDIA GPU machines.
-            // Falls back to the CPU installer when the CUDA archive isn't published yet.
-            #[cfg(not(target_os = "macos"))]
-            if nvidia_smi_async().await {
-                return self.install_cuda_linux(version).await;
-            }
-
-            self.install_cpu_linux(version).await?;
+        let target = release_target().context("No prebuilt release for this platform")?;
+        let install_dir = install_dir()?;
+        let archive_name = self.select_archive(version, target, &install_dir).await?;
+        let cuda = archive_name.contains("-cuda");
+
+        let temp = update_temp_dir();
+        let archive = temp.join(&archive_name);
+        if cuda {
+            print!("  NVIDIA GPU detected — downloading CUDA build for v{version}…");
+        } else {
+            print!("  Downloading v{version} for {target}…");
+        }
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        let result = self
+            .download_verified(&release_asset_url(version, &archive_name), &archive)
+            .await;
+        if result.is_err() {
+            let _ = std::fs::remove_file(&archive);
+        }
+        result?;
+        println!(" done (checksum verified).");
+
+        let staging = temp.join("kwaainet-update-staging");
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::create_dir_all(&staging).context("Creating staging dir")?;
+        let result = extract_archive(&archive, &staging)
+            .and_then(|()| staged_files(&staging, cuda))
+            .and_then(|files| install_files(&files, &install_dir));
+        let _ = std::fs::remove_file(&archive);
+        let _ = std::fs::remove_dir_all(&staging);
+        for name in result? {
+            println!("  Installed {}", name);
         }
 
-        #[cfg(windows)]
-        {
-            // Resolve 8.3 short names (e.g. METRO_~1) in the temp dir.
-            // Expand-Archive -LiteralPath rejects 8.3 paths because .NET's
-            // ZipFile doesn't call GetLongPathName internally.
-            // canonicalize() returns \\?\-prefixed paths on Windows; strip
-            // that prefix so the paths are usable in PS1 single-quoted strings.
-            let canonical_temp = std::env::temp_dir()
-                .canonicalize()
-                .map(|p| {
-                    let s = p.to_string_lossy();
-                    if let Some(rest) = s.strip_prefix("\\\\?\\") {
-                        std::path::PathBuf::from(rest)
-                    } else {
-                        p
-                    }
-                })
-                .unwrap_or_else(|_| std::env::temp_dir());
-
-            let install_dir = std::env::current_exe()
-                .ok()
-                .and_then(|p| p.parent().map(|d| d.to_path_buf()))
-                .unwrap_or_else(|| {
-                    dirs::home_dir()
-                        .map(|h| h.join(".cargo").join("bin"))
-                        .unwrap_or_default()
-                });
-
-            // Prefer the CUDA-enabled zip for NVIDIA GPU machines when it exists.
-            // The CI job `build-cuda-artifacts` publishes
-            // kwaainet-x86_64-pc-windows-msvc-cuda.zip alongside the CPU zip.
-            // If the artifact isn't published yet (or the machine has no GPU) we
-            // fall back to the standard CPU zip without error.
-            let cpu_url = format!(
-                "https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/download/v{version}/kwaainet-x86_64-pc-windows-msvc.zip"
-            );
-            let archive_url = if nvidia_smi_windows().await {
-                // DLLs already present → use the lean binary-only zip (~30 MB, fast).
-                // DLLs missing       → use the full zip with bundled CUDA runtime (~978 MB, one-time).
-                let dlls_present = install_dir.join("cublas64_12.dll").exists();
-                let cuda_url = if dlls_present {
-                    format!(
-                        "https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/download/v{version}/kwaainet-x86_64-pc-windows-msvc-cuda.zip"
-                    )
-                } else {
-                    format!(
-                        "https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/download/v{version}/kwaainet-x86_64-pc-windows-msvc-cuda-full.zip"
-                    )
-                };
-                let client = reqwest::Client::builder()
-                    .user_agent(format!("kwaainet/{}", CURRENT_VERSION))
-                    .timeout(std::time::Duration::from_secs(10))
-                    .build()?;
-                let available = client
-                    .head(&cuda_url)
-                    .send()
-                    .await
-                    .map(|r| r.status().is_success())
-                    .unwrap_or(false);
-                if available {
-                    cuda_url
-                } else {
-                    println!();
-                    println!(
-                        "  ⚠  NVIDIA GPU detected but CUDA build for v{version} isn't published yet."
-                    );
-                    println!(
-                        "  Installing CPU build now — run `kwaainet update` again later for GPU support."
-                    );
-                    println!();
-                    cpu_url
-                }
-            } else {
-                cpu_url
-            };
-
-            let is_cuda = archive_url.contains("-cuda");
-            let zip_path = canonical_temp.join("kwaainet-update.zip");
-            if is_cuda {
-                let dlls_present = install_dir.join("cublas64_12.dll").exists();
-                if dlls_present {
-                    print!("  NVIDIA GPU detected — downloading CUDA update for v{version} (binary only)…");
-                } else {
-                    print!("  NVIDIA GPU detected — downloading CUDA build for v{version} (first install, includes runtime DLLs)…");
-                }
-            } else {
-                print!("  Downloading v{version} for Windows…");
-            }
-            let _ = std::io::Write::flush(&mut std::io::stdout());
-            self.download_to(&archive_url, &zip_path).await?;
-            println!(" done.");
-
-            let kwaainet_exe = install_dir.join("kwaainet.exe");
-
-            // ── Extract zip ──────────────────────────────────────────────────
-            // Canonicalize removes 8.3 short names (e.g. METRO_~1) so that
-            // zip::ZipArchive can open the file on all Windows configurations.
-            let tmp = canonical_temp.join("kwaainet-upd-extract");
-            if tmp.exists() {
-                std::fs::remove_dir_all(&tmp).context("Clearing old extract dir")?;
-            }
-            std::fs::create_dir_all(&tmp).context("Creating extract dir")?;
-
-            print!("  Extracting…");
-            let _ = std::io::Write::flush(&mut std::io::stdout());
-            {
-                let zip_file = std::fs::File::open(&zip_path).context("Opening downloaded zip")?;
-                let mut archive = zip::ZipArchive::new(zip_file).context("Reading zip archive")?;
-                for i in 0..archive.len() {
-                    let mut entry = archive.by_index(i)?;
-                    let raw_name = entry.name().to_string();
-                    // Strip directory structure — prevents path-traversal writes.
-                    let fname = std::path::Path::new(&raw_name)
-                        .file_name()
-                        .map(|n| n.to_string_lossy().into_owned())
-                        .unwrap_or_default();
-                    if fname.is_empty() {
-                        continue;
-                    }
-                    // Only extract executables; include DLLs for CUDA zips.
-                    if !fname.ends_with(".exe") && !(is_cuda && fname.ends_with(".dll")) {
-                        continue;
-                    }
-                    let dest = tmp.join(&fname);
-                    let mut out = std::fs::File::create(&dest)
-                        .with_context(|| format!("Creating {fname}"))?;
-                    std::io::copy(&mut entry, &mut out)
-                        .with_context(|| format!("Extracting {fname}"))?;
-                }
-            }
-            println!(" done.");
-
-            // ── Swap binary ──────────────────────────────────────────────────
-            // On Windows Vista+ the OS loader opens EXEs with FILE_SHARE_DELETE,
-            // so a running process can rename its own executable — the memory
-            // mapping stays valid because it points to the file data, not the name.
-            // We rename the old binary aside first, then copy the new files in.
-            // The .old file is kept so that the user can manually restore it if
-            // the daemon fails to start with the new binary.
-            let exe_bak = install_dir.join("kwaainet.exe.old");
-            let _ = std::fs::remove_file(&exe_bak); // clean up any previous backup
-            std::fs::rename(&kwaainet_exe, &exe_bak)
-                .context("Could not rename kwaainet.exe — is another process holding it?")?;
-
-            for entry in std::fs::read_dir(&tmp).context("Reading extract dir")? {
-                let entry = entry?;
-                let name = entry.file_name();
-                let dest = install_dir.join(&name);
-                std::fs::copy(entry.path(), &dest)
-                    .with_context(|| format!("Installing {}", name.to_string_lossy()))?;
-                println!("  Installed {}", name.to_string_lossy());
+        // macOS 26+ kills unsigned binaries even after quarantine removal.
+        #[cfg(target_os = "macos")]
+        for bin in BINARIES {
+            let path = install_dir.join(bin);
+            if path.exists() {
+                let _ = std::process::Command::new("xattr")
+                    .args(["-d", "com.apple.quarantine"])
+                    .arg(&path)
+                    .output();
+                let _ = std::process::Command::new("codesign")
+                    .args(["-s", "-", "--force"])
+                    .arg(&path)
+                    .output();
             }
-
-            // ── Clean up ─────────────────────────────────────────────────────
-            let _ = std::fs::remove_file(&zip_path);
-            let _ = std::fs::remove_dir_all(&tmp);
-            // kwaainet.exe.old intentionally kept for manual rollback.
         }
 
-        #[cfg(not(any(unix, windows)))]
-        anyhow::bail!("Self-update is not supported on this platform");
-
+        clear_skipped_version();
         Ok(())
     }
 
-    /// Run the cargo-dist shell installer (CPU build, all non-GPU Unix paths).
-    #[cfg(unix)]
-    async fn install_cpu_linux(&self, version: &str) -> Result<()> {
-        let url = "https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/latest/download/kwaainet-installer.sh";
-        let tmp = std::env::temp_dir().join("kwaainet-installer.sh");
-        self.download_to(url, &tmp).await?;
-
-        use std::os::unix::fs::PermissionsExt;
-        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755))?;
-
-        let status = std::process::Command::new("sh")
-            .arg(&tmp)
-            .status()
-            .context("Failed to launch installer")?;
-
-        let _ = std::fs::remove_file(&tmp);
-        if !status.success() {
-            anyhow::bail!("Installer exited with {}", status);
+    /// File name of the release archive to install for `target`.
+    ///
+    /// NVIDIA machines get the CUDA build where one is published.
+    async fn select_archive(
+        &self,
+        version: &str,
+        target: &str,
+        install_dir: &Path,
+    ) -> Result<String> {
+        let cpu = format!("kwaainet-{target}.{ARCHIVE_EXT}");
+
+        #[cfg(all(unix, not(target_os = "macos")))]
+        if target == "x86_64-unknown-linux-gnu" && nvidia_smi_async().await {
+            return self.cuda_linux_archive(version).await;
         }
 
-        // macOS 26+ kills unsigned binaries even after quarantine removal.
-        #[cfg(target_os = "macos")]
-        {
-            let install_dir = dirs::home_dir()
-                .map(|h| h.join(".cargo/bin"))
-                .unwrap_or_default();
-            for bin in &["kwaainet", "p2pd"] {
-                let path = install_dir.join(bin);
-                if path.exists() {
-                    let _ = std::process::Command::new("xattr")
-                        .args(["-d", "com.apple.quarantine"])
-                        .arg(&path)
-                        .output();
-                    let _ = std::process::Command::new("codesign")
-                        .args(["-s", "-", "--force"])
-                        .arg(&path)
-                        .output();
-                }
+        #[cfg(windows)]
+        if nvidia_smi_windows().await {
+            // DLLs already present → use the lean binary-only zip (~30 MB, fast).
+            // DLLs missing       → use the full zip with bundled CUDA runtime (~978 MB, one-time).
+            // The CI job `build-cuda-artifacts` publishes both alongside the CPU zip.
+            let cuda = if install_dir.join("cublas64_12.dll").exists() {
+                format!("kwaainet-{target}-cuda.zip")
+            } else {
+                format!("kwaainet-{target}-cuda-full.zip")
+            };
+            if self.is_published(&release_asset_url(version, &cuda)).await {
+                return Ok(cuda);
             }
+            println!();
+            println!("  ⚠  NVIDIA GPU detected but CUDA build for v{version} isn't published yet.");
+            println!(
+                "  Installing CPU build now — run `kwaainet update` again later for GPU support."
+            );
+            println!();
         }
 
-        let _ = version;
-        Ok(())
+        let _ = install_dir;
+        Ok(cpu)
     }
 
-    /// Download and install the CUDA-enabled Linux binary directly.
-    /// When the CUDA archive isn't published yet (async CI, ~90 min after release),
-    /// falls back to the CPU installer with a clear warning rather than blocking.
+    /// The CUDA Linux archive for `version`.
+    ///
+    /// Fails while it isn't published yet (async CI, ~90 min after release)
+    /// rather than replacing a GPU-enabled binary with the CPU build.
     #[cfg(all(unix, not(target_os = "macos")))]
-    async fn install_cuda_linux(&self, version: &str) -> Result<()> {
-        let cuda_url = format!(
-            "https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/download/v{version}/kwaainet-x86_64-unknown-linux-gnu-cuda.tar.xz"
-        );
+    async fn cuda_linux_archive(&self, version: &str) -> Result<String> {
+        let name = "kwaainet-x86_64-unknown-linux-gnu-cuda.tar.xz".to_string();
+        if !self.is_published(&release_asset_url(version, &name)).await {
+            anyhow::bail!(
+                "NVIDIA GPU detected but the CUDA build for v{version} isn't published yet \
+                 (CI takes ~90 min after release).\n\
+                 Update skipped — your current GPU-enabled binary is unchanged.\n\
+                 Try again in ~1 hour or watch: \
+                 https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/tag/v{version}"
+            );
+        }
+        Ok(name)
+    }
 
-        let client = reqwest::Client::builder()
+    async fn is_published(&self, url: &str) -> bool {
+        let Ok(client) = reqwest::Client::builder()
             .user_agent(format!("kwaainet/{}", CURRENT_VERSION))
             .timeout(std::time::Duration::from_secs(10))
-            .build()?;
-        let cuda_available = client
-            .head(&cuda_url)
+            .build()
+        else {
+            return false;
+        };
+        client
+            .head(url)
             .send()
             .await
             .map(|r| r.status().is_success())
-            .unwrap_or(false);
+            .unwrap_or(false)
+    }
 
-        if !cuda_available {
+    /// Download `url` to `path` and check it against `<url>.sha256`.
+    async fn download_verified(&self, url: &str, path: &Path) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .user_agent(format!("kwaainet/{}", CURRENT_VERSION))
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+        let checksum_url = format!("{url}.sha256");
+        let resp = client.get(&checksum_url).send().await?;
+        if !resp.status().is_success() {
             anyhow::bail!(
-                "NVIDIA GPU detected but the CUDA build for v{version} isn't published yet \
-                 (CI takes ~90 min after release).\n\
-                 Update skipped — your current GPU-enabled binary is unchanged.\n\
-                 Try again in ~1 hour or watch: \
-                 https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/tag/v{version}"
+                "No checksum published for the release (HTTP {}): {}",
+                resp.status(),
+                checksum_url
             );
         }
+        let expected = parse_checksum(&resp.text().await?)
+            .with_context(|| format!("Malformed checksum file: {checksum_url}"))?;
 
-        print!("  NVIDIA GPU detected — downloading CUDA binary for v{version}…");
-        let _ = std::io::Write::flush(&mut std::io::stdout());
+        self.download_to(url, path).await?;
+        let actual = sha256_file(path)?;
+        if actual != expected {
+            anyhow::bail!(
+                "Checksum mismatch for {url}: expected {expected}, got {actual}. \
+                 The download may be corrupt or tampered with; nothing was installed."
+            );
+        }
+        Ok(())
+    }
+
+    async fn download_to(&self, url: &str, path: &Path) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .user_agent(format!("kwaainet/{}", CURRENT_VERSION))
+            .connect_timeout(std::time::Duration::from_secs(30))
+            .build()?;
+        let mut resp = client.get(url).send().await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Download failed (HTTP {}): {}", resp.status(), url);
+        }
+        // Stream to disk: the full CUDA zip is close to 1 GB.
+        let mut file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        while let Some(chunk) = resp.chunk().await? {
+            std::io::Write::write_all(&mut file, &chunk)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Release artifacts and installation
+// ---------------------------------------------------------------------------
+
+const RELEASE_DOWNLOAD_URL: &str = "https://github.com/Kwaai-AI-Lab/KwaaiNet/releases/download";
+
+/// Archive format cargo-dist publishes for this platform
+const ARCHIVE_EXT: &str = if cfg!(windows) { "zip" } else { "tar.xz" };
+
+/// Binaries shipped in every release archive
+#[cfg(not(windows))]
+const BINARIES: [&str; 2] = ["kwaainet", "p2pd"];
+#[cfg(windows)]
+const BINARIES: [&str; 2] = ["kwaainet.exe", "p2pd.exe"];
+
+/// Suffix of the copies an update keeps for `kwaainet update --rollback`
+const BACKUP_SUFFIX: &str = ".old";
+
+fn release_asset_url(version: &str, name: &str) -> String {
+    format!("{RELEASE_DOWNLOAD_URL}/v{version}/{name}")
+}
+
+/// Target triple of this build, as used in the release archive names.
+fn release_target() -> Option<&'static str> {
+    let musl = cfg!(target_env = "musl");
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("macos", "aarch64") => Some("aarch64-apple-darwin"),
+        ("macos", "x86_64") => Some("x86_64-apple-darwin"),
+        ("linux", "x86_64") if musl => Some("x86_64-unknown-linux-musl"),
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("linux", "aarch64") if musl => Some("aarch64-unknown-linux-musl"),
+        ("linux", "aarch64") => Some("aarch64-unknown-linux-gnu"),
+        ("windows", "x86_64") => Some("x86_64-pc-windows-msvc"),
+        _ => None,
+    }
+}
 
-        let archive = std::env::temp_dir().join("kwaainet-cuda-update.tar.xz");
-        self.download_to(&cuda_url, &archive).await?;
-        println!(" done.");
+/// Directory holding the running `kwaainet` binary.
+///
+/// Falls back to `~/.cargo/bin` when that directory is not writable.
+pub fn install_dir() -> Result<PathBuf> {
+    // Strip " (deleted)" that Linux appends to /proc/self/exe after a
+    // previous in-place swap.
+    let exe_path = std::env::current_exe().ok().map(|p| {
+        let s = p.to_string_lossy().into_owned();
+        if let Some(clean) = s.strip_suffix(" (deleted)") {
+            PathBuf::from(clean)
+        } else {
+            p
+        }
+    });
+    let candidate = exe_path
+        .as_deref()
+        .and_then(|p| p.parent())
+        .map(|d| d.to_path_buf())
+        .or_else(|| dirs::home_dir().map(|h| h.join(".cargo").join("bin")))
+        .context("Cannot determine install directory")?;
+
+    if std::fs::metadata(&candidate)
+        .map(|m| !m.permissions().readonly())
+        .unwrap_or(false)
+    {
+        return Ok(candidate);
+    }
+    let fallback = dirs::home_dir()
+        .map(|h| h.join(".cargo").join("bin"))
+        .context("Cannot determine fallback install directory")?;
+    if candidate != fallback {
+        println!(
+            "  ⚠  {} is not writable — using {} instead.",
+            candidate.display(),
+            fallback.display()
+        );
+    }
+    std::fs::create_dir_all(&fallback)?;
+    Ok(fallback)
+}
 
-        // Derive install dir from the running binary's path. Strip " (deleted)"
-        // that Linux appends to /proc/self/exe after a previous in-place swap.
-        let exe_path = std::env::current_exe().ok().map(|p| {
-            let s = p.to_string_lossy().into_owned();
-            if let Some(clean) = s.strip_suffix(" (deleted)") {
-                std::path::PathBuf::from(clean)
+/// Path of the installed `kwaainet` binary
+pub fn installed_binary() -> PathBuf {
+    install_dir()
+        .map(|dir| dir.join(BINARIES[0]))
+        .unwrap_or_else(|_| PathBuf::from("kwaainet"))
+}
+
+/// Temp dir for downloads, without Windows 8.3 short names.
+///
+/// zip::ZipArchive and PowerShell reject 8.3 paths (e.g. METRO_~1), and
+/// canonicalize() returns \\?\-prefixed paths on Windows, so strip that.
+pub fn update_temp_dir() -> PathBuf {
+    std::env::temp_dir()
+        .canonicalize()
+        .map(|p| {
+            let s = p.to_string_lossy();
+            if let Some(rest) = s.strip_prefix("\\\\?\\") {
+                PathBuf::from(rest)
             } else {
                 p
             }
-        });
-        let install_dir_candidate = exe_path
-            .as_deref()
-            .and_then(|p| p.parent())
-            .map(|d| d.to_path_buf())
-            .or_else(|| dirs::home_dir().map(|h| h.join(".cargo/bin")))
-            .context("Cannot determine install directory")?;
-
-        // Verify we can actually write there; if not, fall back to ~/.cargo/bin.
-        let install_dir = if std::fs::metadata(&install_dir_candidate)
-            .map(|m| !m.permissions().readonly())
-            .unwrap_or(false)
+        })
+        .unwrap_or_else(|_| std::env::temp_dir())
+}
+
+/// SHA-256 from a `<hash> *<file>` checksum file, lowercased
+fn parse_checksum(text: &str) -> Option<String> {
+    let hash = text.split_whitespace().next()?;
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn extract_archive(archive: &Path, dest: &Path) -> Result<()> {
+    if archive.extension().is_some_and(|e| e == "zip") {
+        let file = std::fs::File::open(archive).context("Opening downloaded zip")?;
+        let mut zip = zip::ZipArchive::new(file).context("Reading zip archive")?;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i)?;
+            // Strip directory structure — prevents path-traversal writes.
+            let Some(name) = Path::new(entry.name()).file_name().map(|n| n.to_owned()) else {
+                continue;
+            };
+            if entry.is_dir() {
+                continue;
+            }
+            let mut out = std::fs::File::create(dest.join(&name))
+                .with_context(|| format!("Creating {}", name.to_string_lossy()))?;
+            std::io::copy(&mut entry, &mut out)
+                .with_context(|| format!("Extracting {}", name.to_string_lossy()))?;
+        }
+        return Ok(());
+    }
+    let status = std::process::Command::new("tar")
+        .arg("-xJf")
+        .arg(archive)
+        .arg("-C")
+        .arg(dest)
+        .status()
+        .context("Failed to extract archive (is tar installed?)")?;
+    if !status.success() {
+        anyhow::bail!("tar exited with {status}");
+    }
+    Ok(())
+}
+
+/// Whether an archive entry is installed next to the binary
+fn is_installable(name: &str, cuda: bool) -> bool {
+    BINARIES.contains(&name)
+        || (cuda && (name.ends_with(".dll") || name.ends_with(".so") || name.contains(".so.")))
+}
+
+/// Installable files anywhere under the extracted archive
+fn staged_files(dir: &Path, cuda: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("Reading extracted archive at {}", dir.display()))?
         {
-            install_dir_candidate
-        } else {
-            let fallback = dirs::home_dir()
-                .map(|h| h.join(".cargo/bin"))
-                .context("Cannot determine fallback install directory")?;
-            if install_dir_candidate != fallback {
-                println!(
-                    "  ⚠  {} is not writable — installing to {} instead.",
-                    install_dir_candidate.display(),
-                    fallback.display()
-                );
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .file_name()
+                .is_some_and(|n| is_installable(&n.to_string_lossy(), cuda))
+            {
+                files.push(path);
             }
-            std::fs::create_dir_all(&fallback)?;
-            fallback
-        };
-        debug!("CUDA install dir: {}", install_dir.display());
+        }
+    }
+    if !files.iter().any(|f| f.ends_with(BINARIES[0])) {
+        anyhow::bail!("Release archive does not contain {}", BINARIES[0]);
+    }
+    Ok(files)
+}
 
-        let tmp = std::env::temp_dir().join("kwaainet-cuda-extract");
-        let _ = std::fs::remove_dir_all(&tmp);
-        std::fs::create_dir_all(&tmp)?;
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(BACKUP_SUFFIX);
+    path.with_file_name(name)
+}
 
-        let status = std::process::Command::new("tar")
-            .args(["-xJf"])
-            .arg(&archive)
-            .arg("-C")
-            .arg(&tmp)
-            .status()
-            .context("Failed to extract CUDA archive (is tar installed?)")?;
+fn staging_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
 
-        let _ = std::fs::remove_file(&archive);
-        if !status.success() {
-            anyhow::bail!("tar exited with {status}");
+/// Copy `files` into `install_dir`, keeping each replaced file as `<name>.old`.
+///
+/// Everything is first copied next to its destination, so the swap itself is
+/// only renames: a running binary can be renamed on Linux, macOS and Windows
+/// (the loader keeps the old file alive), whereas overwriting it in place
+/// fails with ETXTBSY. If any swap fails the earlier ones are undone.
+/// Returns the installed file names.
+fn install_files(files: &[PathBuf], install_dir: &Path) -> Result<Vec<String>> {
+    let mut staged = Vec::new();
+    for file in files {
+        let name = file.file_name().context("Staged file has no name")?;
+        let dest = install_dir.join(name);
+        let tmp = staging_path(&dest);
+        std::fs::copy(file, &tmp)
+            .with_context(|| format!("Installing {} (staging)", name.to_string_lossy()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755))?;
         }
+        staged.push((tmp, dest));
+    }
 
-        let subdir = tmp.join("kwaainet-x86_64-unknown-linux-gnu-cuda");
-        use std::os::unix::fs::PermissionsExt;
-        for entry in std::fs::read_dir(&subdir)
-            .with_context(|| format!("Reading extracted archive at {}", subdir.display()))?
-        {
-            let entry = entry?;
-            let name = entry.file_name();
-            let dest = install_dir.join(&name);
-            // Write to a temp file then rename atomically to avoid ETXTBSY
-            // (Linux won't let you overwrite a binary that is currently executing).
-            let tmp_dest = install_dir.join(format!(".{}.tmp", name.to_string_lossy()));
-            std::fs::copy(entry.path(), &tmp_dest)
-                .with_context(|| format!("Installing {} (staging)", name.to_string_lossy()))?;
-            let name_str = name.to_string_lossy();
-            if name_str == "kwaainet" || name_str == "p2pd" {
-                std::fs::set_permissions(&tmp_dest, std::fs::Permissions::from_mode(0o755))?;
+    let mut swapped: Vec<(PathBuf, bool)> = Vec::new();
+    for (tmp, dest) in &staged {
+        let backup = backup_path(dest);
+        let had_previous = dest.exists();
+        let result = (|| -> std::io::Result<()> {
+            if had_previous {
+                let _ = std::fs::remove_file(&backup);
+                std::fs::rename(dest, &backup)?;
             }
-            // Unlink the destination first so rename() succeeds even when `dest`
-            // is the currently-executing binary (some Linux kernels return ETXTBSY
-            // for rename(2) over a running ELF; unlink always succeeds and the old
-            // inode stays alive until the process exits).
-            let _ = std::fs::remove_file(&dest);
-            std::fs::rename(&tmp_dest, &dest).with_context(|| {
-                format!(
-                    "Installing {} ({} -> {})",
-                    name_str,
-                    tmp_dest.display(),
-                    dest.display()
-                )
-            })?;
+            std::fs::rename(tmp, dest)
+        })();
+        if let Err(e) = result {
+            for (dest, had_previous) in swapped.iter().rev() {
+                let _ = std::fs::remove_file(dest);
+                if *had_previous {
+                    let _ = std::fs::rename(backup_path(dest), dest);
+                }
+            }
+            if had_previous && !dest.exists() {
+                let _ = std::fs::rename(&backup, dest);
+            }
+            for (tmp, _) in &staged {
+                let _ = std::fs::remove_file(tmp);
+            }
+            return Err(e).with_context(|| format!("Installing {}", dest.display()));
         }
-        let _ = std::fs::remove_dir_all(&tmp);
-        println!("  CUDA binary installed to {}.", install_dir.display());
-        Ok(())
+        swapped.push((dest.clone(), had_previous));
     }
 
-    async fn download_to(&self, url: &str, path: &std::path::Path) -> Result<()> {
-        let client = reqwest::Client::builder()
-            .user_agent(format!("kwaainet/{}", CURRENT_VERSION))
-            .timeout(std::time::Duration::from_secs(120))
-            .build()?;
-        let resp = client.get(url).send().await?;
-        if !resp.status().is_success() {
-            anyhow::bail!("Download failed (HTTP {}): {}", resp.status(), url);
+    Ok(staged
+        .iter()
+        .filter_map(|(_, dest)| dest.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Swap the installed binaries with the copies kept by the last update.
+///
+/// The replaced files become the new backups, so running it twice undoes the
+/// rollback. The version being rolled back from is skipped by the automatic
+/// update check until a newer one is released. Returns the restored names.
+pub fn rollback() -> Result<Vec<String>> {
+    let restored = restore_backups(&install_dir()?)?;
+    skip_version(CURRENT_VERSION)?;
+    Ok(restored)
+}
+
+fn restore_backups(install_dir: &Path) -> Result<Vec<String>> {
+    let mut restored = Vec::new();
+    for entry in std::fs::read_dir(install_dir)
+        .with_context(|| format!("Reading {}", install_dir.display()))?
+    {
+        let backup = entry?.path();
+        let Some(name) = backup
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_suffix(BACKUP_SUFFIX))
+            .filter(|n| is_installable(n, true))
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let dest = install_dir.join(&name);
+        let tmp = staging_path(&dest);
+        if dest.exists() {
+            std::fs::rename(&dest, &tmp).with_context(|| format!("Moving {} aside", name))?;
         }
-        let bytes = resp.bytes().await?;
-        std::fs::write(path, &bytes)
-            .with_context(|| format!("Failed to write installer to {}", path.display()))?;
-        Ok(())
+        std::fs::rename(&backup, &dest).with_context(|| format!("Restoring {}", name))?;
+        if tmp.exists() {
+            std::fs::rename(&tmp, &backup)
+                .with_context(|| format!("Keeping {} for re-applying", name))?;
+        }
+        restored.push(name);
     }
+    if restored.is_empty() {
+        anyhow::bail!(
+            "No previous version to roll back to in {}",
+            install_dir.display()
+        );
+    }
+    restored.sort();
+    Ok(restored)
+}
+
+// ---------------------------------------------------------------------------
+// Skipped version (set by rollback)
+// ---------------------------------------------------------------------------
+
+fn skip_file() -> PathBuf {
+    crate::config::run_dir().join("update_skip")
+}
+
+fn skip_version(version: &str) -> Result<()> {
+    let path = skip_file();
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, version)?;
+    // The cached check may still offer the version just rolled back from.
+    let _ = std::fs::remove_file(cache_file());
+    Ok(())
+}
+
+fn skipped_version() -> Option<String> {
+    std::fs::read_to_string(skip_file())
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn is_skipped(info: &UpdateInfo) -> bool {
+    skipped_version().is_some_and(|v| v == info.version)
+}
+
+fn clear_skipped_version() {
+    let _ = std::fs::remove_file(skip_file());
 }
 
 /// Query nvidia-smi asynchronously with a 4-second timeout.
@@ -565,6 +719,66 @@ mod tests {
         assert!(!is_newer("0.4.0", "0.4.1"));
     }
 
+    #[test]
+    fn parses_checksum_files() {
+        let hash = "A".repeat(64);
+        assert_eq!(
+            parse_checksum(&format!(
+                "{hash} *kwaainet-x86_64-unknown-linux-gnu.tar.xz\n"
+            )),
+            Some("a".repeat(64))
+        );
+        assert_eq!(
+            parse_checksum(&format!("{hash}  kwaainet.zip")),
+            Some("a".repeat(64))
+        );
+        assert_eq!(parse_checksum("deadbeef *kwaainet.zip"), None);
+        assert_eq!(parse_checksum(""), None);
+    }
+
+    #[test]
+    fn install_keeps_backups_and_rollback_swaps_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let staged = tempfile::tempdir().unwrap();
+        let install = dir.path();
+        let kwaainet = staged.path().join(BINARIES[0]);
+        let p2pd = staged.path().join(BINARIES[1]);
+
+        std::fs::write(&kwaainet, "v1").unwrap();
+        std::fs::write(&p2pd, "p1").unwrap();
+        install_files(&[kwaainet.clone(), p2pd.clone()], install).unwrap();
+        assert!(!backup_path(&install.join(BINARIES[0])).exists());
+
+        std::fs::write(&kwaainet, "v2").unwrap();
+        std::fs::write(&p2pd, "p2").unwrap();
+        let names = install_files(&[kwaainet, p2pd], install).unwrap();
+        assert_eq!(names, BINARIES);
+        let read = |name: &str| std::fs::read_to_string(install.join(name)).unwrap();
+        assert_eq!(read(BINARIES[0]), "v2");
+        assert_eq!(
+            std::fs::read_to_string(backup_path(&install.join(BINARIES[0]))).unwrap(),
+            "v1"
+        );
+
+        assert_eq!(restore_backups(install).unwrap(), BINARIES);
+        assert_eq!(
+            (read(BINARIES[0]), read(BINARIES[1])),
+            ("v1".into(), "p1".into())
+        );
+        // Rolling back again re-applies the update.
+        restore_backups(install).unwrap();
+        assert_eq!(read(BINARIES[0]), "v2");
+    }
+
+    #[test]
+    fn only_binaries_and_cuda_libraries_are_installed() {
+        assert!(is_installable(BINARIES[0], false));
+        assert!(!is_installable("README.md", true));
+        assert!(!is_installable("libcublas.so.12", false));
+        assert!(is_installable("libcublas.so.12", true));
+        assert!(is_installable("cublas64_12.dll", true));
+    }
+
     /// On a Windows machine with an NVIDIA GPU, nvidia_smi_windows() must return
     /// true within the 4-second timeout.  Run on any CI/dev box that has a GPU.
     #[tokio::test]
@@ -620,14 +834,14 @@ mod tests {
         );
     }
 
-    /// Verifies that when the CUDA archive isn't published yet, install_cuda_linux
-    /// returns Err (no CPU fallback, no binary is touched).
+    /// Verifies that when the CUDA archive isn't published yet, the update
+    /// fails before downloading (no CPU fallback, no binary is touched).
     #[tokio::test]
     #[cfg(all(unix, not(target_os = "macos")))]
     async fn cuda_update_bails_when_archive_missing() {
         // v0.4.70 never had a CUDA archive — safe version to test against.
         let checker = UpdateChecker::new();
-        let result = checker.install_cuda_linux("0.4.70").await;
+        let result = checker.cuda_linux_archive("0.4.70").await;
         let err = result.expect_err("should bail when CUDA archive is missing");
         let msg = err.to_string();
         assert!(
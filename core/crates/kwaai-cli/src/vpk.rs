@@ -173,7 +173,7 @@ async fn discover(json_output: bool) -> Result<()> {
     // Respects KWAAINET_SOCKET so multi-instance setups (KWAAINET_HOME) work.
     let daemon_addr = crate::shard_cmd::daemon_socket();
 
-    let mut client = match P2PClient::connect(&daemon_addr).await {
+    let client = match P2PClient::connect(&daemon_addr).await {
         Ok(c) => c,
         Err(_) => {
             print_error("Cannot connect to the KwaaiNet node — is it running?");
@@ -204,11 +204,12 @@ async fn discover(json_output: bool) -> Result<()> {
         .chain_update(peer_id.to_bytes())
         .finalize()
         .to_vec();
-    let find_req = FindRequest {
+    let mut find_req = FindRequest {
         auth: Some(RequestAuthInfo::new()),
         keys: vec![key],
         peer: Some(NodeInfo { node_id: our_dhtid }),
     };
+    crate::dht_auth::sign_request(&mut find_req);
     let mut req_bytes = Vec::new();
     find_req.encode(&mut req_bytes)?;
 
@@ -1,10 +1,27 @@
 //! Benchmarks for compression operations
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use candle_core::{DType, Device, Tensor};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use kwaai_compression::{BlockwiseQuantizer, Compressor};
 
-fn compression_benchmark(_c: &mut Criterion) {
-    // Placeholder benchmark
-    // TODO: Add actual compression benchmarks
+fn compression_benchmark(c: &mut Criterion) {
+    let quantizer = BlockwiseQuantizer::new(64);
+    let elements = 1 << 20;
+    let base = Tensor::randn(0f32, 1.0, elements, &Device::Cpu).unwrap();
+
+    let mut group = c.benchmark_group("blockwise_8bit");
+    group.throughput(Throughput::Elements(elements as u64));
+    for dtype in [DType::F32, DType::F16, DType::BF16] {
+        let tensor = base.to_dtype(dtype).unwrap();
+        let compressed = quantizer.compress(&tensor).unwrap();
+        group.bench_function(format!("quantize_{dtype:?}"), |b| {
+            b.iter(|| quantizer.compress(&tensor).unwrap())
+        });
+        group.bench_function(format!("dequantize_{dtype:?}"), |b| {
+            b.iter(|| quantizer.decompress(&compressed).unwrap())
+        });
+    }
+    group.finish();
 }
 
 criterion_group!(benches, compression_benchmark);
@@ -0,0 +1,431 @@
+//! Delta encoding for parameter synchronization
+//!
+//! Between two synchronization rounds most parameter blocks barely move.
+//! [`DeltaCompressor`] diff-encodes each tensor against the version the
+//! receiver already holds, drops blocks whose change is within tolerance,
+//! and 8-bit quantizes the rest.
+//!
+//! The sender diffs against what the receiver *reconstructed*, not against
+//! the exact previous value, so quantization error and skipped blocks are
+//! carried into the next delta instead of accumulating as drift. A full
+//! keyframe is sent for the first version of a tensor, after a shape change,
+//! and every `keyframe_interval` versions.
+
+use crate::{
+    BlockwiseQuantizer, CompressedData, CompressionError, CompressionResult, Compressor,
+    QuantizedTensor,
+};
+use candle_core::{Device, Tensor};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
+/// Largest tensor, in elements, a delta may describe
+const MAX_ELEMENTS: usize = u32::MAX as usize;
+
+/// One tensor of a delta-encoded parameter set
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeltaTensor {
+    /// Position of the tensor in the parameter set
+    pub index: u32,
+    /// Version this delta produces
+    pub version: u64,
+    /// Version the delta applies to; `None` for a keyframe
+    pub base_version: Option<u64>,
+    /// Shape of the tensor
+    pub shape: Vec<usize>,
+    /// Blocks carried in `values`, ascending; empty for a keyframe, which
+    /// carries every block
+    pub blocks: Vec<u32>,
+    /// Quantized changes of the carried blocks, back to back
+    pub values: QuantizedTensor,
+}
+
+impl DeltaTensor {
+    /// Whether this is a full keyframe rather than a delta
+    pub fn is_keyframe(&self) -> bool {
+        self.base_version.is_none()
+    }
+
+    /// Element count of `shape`, unless it overflows or exceeds
+    /// [`MAX_ELEMENTS`] (the shape comes from the peer)
+    fn num_elements(&self) -> Option<usize> {
+        self.shape
+            .iter()
+            .try_fold(1usize, |n, &d| n.checked_mul(d))
+            .filter(|&n| n <= MAX_ELEMENTS)
+    }
+}
+
+impl CompressedData for DeltaTensor {
+    fn compression_ratio(&self) -> f32 {
+        let compressed = self.size_bytes();
+        if compressed > 0 {
+            self.original_size_bytes() as f32 / compressed as f32
+        } else {
+            1.0
+        }
+    }
+
+    fn size_bytes(&self) -> usize {
+        // quantized values + u32 block indices
+        self.values.size_bytes() + self.blocks.len() * 4
+    }
+
+    fn original_size_bytes(&self) -> usize {
+        // f32 original
+        self.num_elements().unwrap_or(0).saturating_mul(4)
+    }
+}
+
+/// Reconstructed state of one tensor
+struct Base {
+    version: u64,
+    shape: Vec<usize>,
+    data: Vec<f32>,
+}
+
+/// Stateful delta encoder/decoder for one direction of one link
+///
+/// Use one instance to encode what is sent to a peer and a separate one to
+/// decode what is received from it; both sides then agree on every base.
+pub struct DeltaCompressor {
+    quantizer: BlockwiseQuantizer,
+    tolerance: f32,
+    keyframe_interval: u64,
+    bases: HashMap<u32, Base>,
+}
+
+impl DeltaCompressor {
+    /// Create a delta compressor quantizing in blocks of `block_size`
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            quantizer: BlockwiseQuantizer::new(block_size.max(1)),
+            tolerance: 0.0,
+            keyframe_interval: 0,
+            bases: HashMap::new(),
+        }
+    }
+
+    /// Skip blocks whose largest change is at most `tolerance`
+    ///
+    /// Skipped changes are not lost: they stay in the difference to the
+    /// receiver's base and go out once they exceed the tolerance.
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance.max(0.0);
+        self
+    }
+
+    /// Send a keyframe every `interval` versions (0 = only when needed)
+    pub fn with_keyframe_interval(mut self, interval: u64) -> Self {
+        self.keyframe_interval = interval;
+        self
+    }
+
+    /// Get the block size
+    pub fn block_size(&self) -> usize {
+        self.quantizer.block_size()
+    }
+
+    /// Current version of tensor `index`, if any
+    pub fn version(&self, index: u32) -> Option<u64> {
+        self.bases.get(&index).map(|b| b.version)
+    }
+
+    /// Forget every base, so the next encode sends keyframes
+    pub fn reset(&mut self) {
+        self.bases.clear();
+    }
+
+    /// Encode the next version of a parameter set
+    pub fn encode(&mut self, tensors: &[Tensor]) -> CompressionResult<Vec<DeltaTensor>> {
+        tensors
+            .iter()
+            .enumerate()
+            .map(|(i, t)| self.encode_one(i as u32, t))
+            .collect()
+    }
+
+    /// Apply deltas produced by the peer's [`encode`](Self::encode)
+    ///
+    /// Every delta is applied before any base is updated, so this fails
+    /// without changing any base when one of them does not apply to the
+    /// version held here; [`reset`](Self::reset) both sides to resync.
+    pub fn decode(&mut self, deltas: &[DeltaTensor]) -> CompressionResult<Vec<Tensor>> {
+        let mut seen = HashSet::new();
+        let mut decoded = Vec::with_capacity(deltas.len());
+        for delta in deltas {
+            if !seen.insert(delta.index) {
+                return Err(CompressionError::InvalidData(format!(
+                    "tensor {} appears twice",
+                    delta.index
+                )));
+            }
+            self.check_base(delta)?;
+            let data = self.apply(delta)?;
+            let tensor = Tensor::from_vec(data.clone(), delta.shape.as_slice(), &Device::Cpu)?;
+            decoded.push((tensor, data));
+        }
+        Ok(deltas
+            .iter()
+            .zip(decoded)
+            .map(|(delta, (tensor, data))| {
+                self.bases.insert(
+                    delta.index,
+                    Base {
+                        version: delta.version,
+                        shape: delta.shape.clone(),
+                        data,
+                    },
+                );
+                tensor
+            })
+            .collect())
+    }
+
+    fn encode_one(&mut self, index: u32, tensor: &Tensor) -> CompressionResult<DeltaTensor> {
+        let shape = tensor.dims().to_vec();
+        let data = tensor
+            .flatten_all()?
+            .to_vec1::<f32>()
+            .map_err(|e| CompressionError::TensorError(e.to_string()))?;
+        let block_size = self.block_size();
+
+        let previous = self.bases.get(&index);
+        let version = previous.map_or(1, |b| b.version + 1);
+        let keyframe = match previous {
+            Some(base) => {
+                base.shape != shape
+                    || (self.keyframe_interval > 0
+                        && version.is_multiple_of(self.keyframe_interval))
+            }
+            None => true,
+        };
+
+        let (base_version, blocks, changes) = if keyframe {
+            (None, Vec::new(), data.clone())
+        } else {
+            let base = previous.unwrap();
+            let diff: Vec<f32> = data.iter().zip(&base.data).map(|(x, b)| x - b).collect();
+            let mut blocks = Vec::new();
+            let mut changes = Vec::new();
+            for (i, block) in diff.chunks(block_size).enumerate() {
+                let max_abs = block.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
+                if max_abs > self.tolerance {
+                    blocks.push(i as u32);
+                    changes.extend_from_slice(block);
+                }
+            }
+            (Some(base.version), blocks, changes)
+        };
+
+        let len = changes.len();
+        let values = self
+            .quantizer
+            .compress(&Tensor::from_vec(changes, len, &Device::Cpu)?)?;
+        let delta = DeltaTensor {
+            index,
+            version,
+            base_version,
+            shape,
+            blocks,
+            values,
+        };
+
+        // Track what the receiver will reconstruct, not the exact input
+        let reconstructed = self.apply(&delta)?;
+        self.bases.insert(
+            index,
+            Base {
+                version,
+                shape: delta.shape.clone(),
+                data: reconstructed,
+            },
+        );
+        debug!(
+            index,
+            version,
+            keyframe,
+            blocks = delta.blocks.len(),
+            ratio = delta.compression_ratio(),
+            "Delta-encoded tensor"
+        );
+        Ok(delta)
+    }
+
+    fn check_base(&self, delta: &DeltaTensor) -> CompressionResult<()> {
+        let Some(expected) = delta.base_version else {
+            return Ok(());
+        };
+        match self.bases.get(&delta.index) {
+            Some(base) if base.version == expected && base.shape == delta.shape => Ok(()),
+            Some(base) => Err(CompressionError::InvalidData(format!(
+                "delta for tensor {} applies to version {expected}, have version {}",
+                delta.index, base.version
+            ))),
+            None => Err(CompressionError::InvalidData(format!(
+                "delta for tensor {} applies to version {expected}, have no base",
+                delta.index
+            ))),
+        }
+    }
+
+    /// Values after applying `delta` to its base
+    fn apply(&self, delta: &DeltaTensor) -> CompressionResult<Vec<f32>> {
+        let changes = self
+            .quantizer
+            .decompress(&delta.values)?
+            .to_vec1::<f32>()
+            .map_err(|e| CompressionError::TensorError(e.to_string()))?;
+        let len = delta.num_elements().ok_or_else(|| {
+            CompressionError::InvalidData(format!(
+                "tensor {} shape {:?} is too large",
+                delta.index, delta.shape
+            ))
+        })?;
+        if delta.is_keyframe() {
+            if changes.len() != len {
+                return Err(CompressionError::ShapeMismatch {
+                    expected: delta.shape.clone(),
+                    actual: vec![changes.len()],
+                });
+            }
+            return Ok(changes);
+        }
+
+        let mut data = self.bases[&delta.index].data.clone();
+        let block_size = self.block_size();
+        let mut changes = changes.as_slice();
+        for &block in &delta.blocks {
+            let start = block as usize * block_size;
+            let end = (start + block_size).min(len);
+            if start >= end || changes.len() < end - start {
+                return Err(CompressionError::InvalidData(format!(
+                    "block {block} out of range for tensor {}",
+                    delta.index
+                )));
+            }
+            for (x, c) in data[start..end].iter_mut().zip(&changes[..end - start]) {
+                *x += c;
+            }
+            changes = &changes[end - start..];
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tensor(data: &[f32]) -> Tensor {
+        Tensor::from_vec(data.to_vec(), data.len(), &Device::Cpu).unwrap()
+    }
+
+    fn values(t: &Tensor) -> Vec<f32> {
+        t.to_vec1().unwrap()
+    }
+
+    #[test]
+    fn test_only_changed_blocks_are_sent() {
+        // Above the quantization error of the keyframe
+        let mut sender = DeltaCompressor::new(8).with_tolerance(0.05);
+        let mut receiver = DeltaCompressor::new(8);
+        let mut params: Vec<f32> = (0..64).map(|i| i as f32 * 0.1).collect();
+
+        let first = sender.encode(&[tensor(&params)]).unwrap();
+        assert!(first[0].is_keyframe());
+        receiver.decode(&first).unwrap();
+
+        params[3] += 0.5;
+        params[60] -= 0.25;
+        let second = sender.encode(&[tensor(&params)]).unwrap();
+        assert_eq!(second[0].base_version, Some(1));
+        assert_eq!(second[0].blocks, vec![0, 7]);
+        assert!(second[0].size_bytes() < first[0].size_bytes() / 2);
+
+        let got = values(&receiver.decode(&second).unwrap()[0]);
+        for (want, got) in params.iter().zip(&got) {
+            assert!((want - got).abs() < 0.05, "want={want} got={got}");
+        }
+        assert_eq!(receiver.version(0), Some(2));
+    }
+
+    #[test]
+    fn test_error_feedback_prevents_drift() {
+        let mut sender = DeltaCompressor::new(16).with_tolerance(0.01);
+        let mut receiver = DeltaCompressor::new(16);
+        let mut params = vec![1.0f32; 32];
+        for step in 0..200 {
+            // Small steps, most below tolerance on their own
+            for (i, p) in params.iter_mut().enumerate() {
+                *p += 0.003 * ((i + step) % 3) as f32;
+            }
+            let deltas = sender.encode(&[tensor(&params)]).unwrap();
+            receiver.decode(&deltas).unwrap();
+        }
+        let deltas = sender.encode(&[tensor(&params)]).unwrap();
+        let got = values(&receiver.decode(&deltas).unwrap()[0]);
+        for (want, got) in params.iter().zip(&got) {
+            assert!((want - got).abs() < 0.02, "want={want} got={got}");
+        }
+    }
+
+    #[test]
+    fn test_keyframes_on_interval_and_shape_change() {
+        let mut sender = DeltaCompressor::new(4).with_keyframe_interval(3);
+        let t = tensor(&[1.0, 2.0, 3.0, 4.0]);
+        let kinds: Vec<bool> = (0..4)
+            .map(|_| sender.encode(std::slice::from_ref(&t)).unwrap()[0].is_keyframe())
+            .collect();
+        assert_eq!(kinds, vec![true, false, true, false]);
+
+        let reshaped = sender.encode(&[tensor(&[1.0, 2.0])]).unwrap();
+        assert!(reshaped[0].is_keyframe());
+    }
+
+    #[test]
+    fn test_stale_base_is_rejected() {
+        let mut sender = DeltaCompressor::new(4);
+        let mut receiver = DeltaCompressor::new(4);
+        let first = sender.encode(&[tensor(&[1.0; 4])]).unwrap();
+        let second = sender.encode(&[tensor(&[2.0; 4])]).unwrap();
+
+        // The keyframe never arrived
+        assert!(receiver.decode(&second).is_err());
+        assert_eq!(receiver.version(0), None);
+
+        receiver.decode(&first).unwrap();
+        receiver.decode(&second).unwrap();
+        assert!(receiver.decode(&second).is_err());
+    }
+
+    #[test]
+    fn test_failed_decode_leaves_every_base_unchanged() {
+        let mut sender = DeltaCompressor::new(4);
+        let mut receiver = DeltaCompressor::new(4);
+        let params = [tensor(&[1.0; 4]), tensor(&[2.0; 8])];
+        receiver.decode(&sender.encode(&params).unwrap()).unwrap();
+
+        let mut deltas = sender
+            .encode(&[tensor(&[1.5; 4]), tensor(&[2.5; 8])])
+            .unwrap();
+        // The second delta passes the base check but names a block past the end
+        deltas[1].blocks = vec![9];
+        assert!(receiver.decode(&deltas).is_err());
+        assert_eq!(receiver.version(0), Some(1));
+        assert_eq!(receiver.version(1), Some(1));
+    }
+
+    #[test]
+    fn test_oversized_shape_is_rejected() {
+        let mut sender = DeltaCompressor::new(4);
+        let mut deltas = sender.encode(&[tensor(&[1.0; 4])]).unwrap();
+        deltas[0].shape = vec![usize::MAX, 2];
+        assert_eq!(deltas[0].original_size_bytes(), 0);
+        assert!(DeltaCompressor::new(4).decode(&deltas).is_err());
+
+        deltas[0].shape = vec![1 << 20, 1 << 20];
+        assert!(DeltaCompressor::new(4).decode(&deltas).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! SIMD kernels for blockwise quantization
+//!
+//! Eight f32 lanes at a time via `wide`, which lowers to SSE/AVX on x86,
+//! NEON on aarch64 and SIMD128 on wasm, with a scalar fallback elsewhere.
+//! Tails shorter than a vector are handled by the scalar path, which
+//! computes the same values.
+
+use wide::f32x8;
+
+const LANES: usize = 8;
+
+fn load(chunk: &[f32]) -> f32x8 {
+    let lanes: [f32; LANES] = chunk.try_into().expect("chunk of LANES elements");
+    f32x8::from(lanes)
+}
+
+/// Largest absolute value in `values` (0 when empty)
+pub(crate) fn max_abs(values: &[f32]) -> f32 {
+    let mut chunks = values.chunks_exact(LANES);
+    let mut acc = f32x8::ZERO;
+    for chunk in &mut chunks {
+        acc = acc.max(load(chunk).abs());
+    }
+    let vector_max = acc.to_array().into_iter().fold(0.0f32, f32::max);
+    chunks
+        .remainder()
+        .iter()
+        .map(|x| x.abs())
+        .fold(vector_max, f32::max)
+}
+
+/// Append `values / scale`, rounded and clamped to ±127, to `out`
+pub(crate) fn quantize(values: &[f32], scale: f32, out: &mut Vec<i8>) {
+    let (scale_v, lo, hi) = (
+        f32x8::splat(scale),
+        f32x8::splat(-127.0),
+        f32x8::splat(127.0),
+    );
+    let mut chunks = values.chunks_exact(LANES);
+    for chunk in &mut chunks {
+        let q = (load(chunk) / scale_v).round().max(lo).min(hi).round_int();
+        out.extend(q.to_array().map(|v| v as i8));
+    }
+    out.extend(
+        chunks
+            .remainder()
+            .iter()
+            .map(|&v| (v / scale).round_ties_even().clamp(-127.0, 127.0) as i8),
+    );
+}
+
+/// Append `q * scale` to `out`
+pub(crate) fn dequantize(q: &[i8], scale: f32, out: &mut Vec<f32>) {
+    let scale_v = f32x8::splat(scale);
+    let mut chunks = q.chunks_exact(LANES);
+    for chunk in &mut chunks {
+        let lanes: [i8; LANES] = chunk.try_into().expect("chunk of LANES elements");
+        out.extend((f32x8::from(lanes.map(f32::from)) * scale_v).to_array());
+    }
+    out.extend(chunks.remainder().iter().map(|&v| v as f32 * scale));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernels_match_scalar_reference() {
+        // Lengths around the vector width exercise the tails
+        for len in [0, 1, 7, 8, 9, 31, 64, 67] {
+            let values: Vec<f32> = (0..len).map(|i| (i as f32 * 1.7).sin() * 3.0).collect();
+            let expected_max = values.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
+            assert_eq!(max_abs(&values), expected_max, "len={len}");
+
+            let scale = expected_max.max(1e-3) / 127.0;
+            let mut q = Vec::new();
+            quantize(&values, scale, &mut q);
+            let reference: Vec<i8> = values
+                .iter()
+                .map(|&v| (v / scale).round_ties_even().clamp(-127.0, 127.0) as i8)
+                .collect();
+            assert_eq!(q, reference, "len={len}");
+
+            let mut back = Vec::new();
+            dequantize(&q, scale, &mut back);
+            let reference: Vec<f32> = q.iter().map(|&v| v as f32 * scale).collect();
+            assert_eq!(back, reference, "len={len}");
+        }
+    }
+}
@@ -6,8 +6,9 @@
 //! and tensor transfer, including:
 //!
 //! - **Blockwise 8-bit Quantization**: ~4x compression with minimal accuracy loss
-//! - **Sparse Gradient Compression**: Top-K selection for bandwidth efficiency
-//! - **Delta Encoding**: Only transfer changes
+//! - **Sparse Gradient Compression**: Top-K selection and 2:4 structured sparsity
+//! - **Delta Encoding**: Only transfer changed blocks between parameter versions
+//! - **TensorWire**: Self-describing tensor format for P2P transfers
 //!
 //! ## Example
 //!
@@ -26,13 +27,20 @@
 //! # Ok::<(), Box<dyn std::error::Error>>(())
 //! ```
 
+pub mod delta;
 pub mod error;
+mod kernels;
 pub mod quantization;
 pub mod sparse;
+pub mod wire;
 
+pub use delta::{DeltaCompressor, DeltaTensor};
 pub use error::{CompressionError, CompressionResult};
 pub use quantization::{BlockwiseQuantizer, QuantizedTensor};
-pub use sparse::{SparseGradient, TopKCompressor};
+pub use sparse::{
+    SparseGradient, StructuredSparseCompressor, StructuredSparseTensor, TopKCompressor,
+};
+pub use wire::{Endianness, TensorWire, WireCodec, WireDType};
 
 use candle_core::Tensor;
 
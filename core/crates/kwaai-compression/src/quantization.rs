@@ -1,10 +1,11 @@
 //! Blockwise 8-bit quantization
 //!
 //! Implements Hivemind-style blockwise quantization for efficient
-//! gradient and tensor compression.
+//! gradient and tensor compression. f32, f16 and bf16 tensors are accepted
+//! and decompress back to their original dtype.
 
-use crate::{CompressedData, CompressionError, CompressionResult, Compressor};
-use candle_core::{Device, Tensor};
+use crate::{kernels, CompressedData, CompressionError, CompressionResult, Compressor, WireDType};
+use candle_core::{DType, Device, Tensor};
 use half::f16;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
@@ -39,8 +40,17 @@ impl Compressor for BlockwiseQuantizer {
             tensor.dims(),
             self.block_size
         );
+        let dtype = match WireDType::from_candle(tensor.dtype())? {
+            dtype @ (WireDType::F32 | WireDType::F16 | WireDType::BF16) => dtype,
+            other => {
+                return Err(CompressionError::InvalidData(format!(
+                    "cannot quantize {other:?} tensors"
+                )))
+            }
+        };
         let data = tensor
             .flatten_all()?
+            .to_dtype(DType::F32)?
             .to_vec1::<f32>()
             .map_err(|e| CompressionError::TensorError(e.to_string()))?;
 
@@ -48,21 +58,10 @@ impl Compressor for BlockwiseQuantizer {
         let mut scales = Vec::with_capacity(data.len() / self.block_size + 1);
 
         for block in data.chunks(self.block_size) {
-            // Find max absolute value in block
-            let max_abs = block.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
-
+            let max_abs = kernels::max_abs(block);
             let scale = if max_abs > 0.0 { max_abs / 127.0 } else { 1.0 };
             scales.push(f16::from_f32(scale));
-
-            // Quantize block
-            for &val in block {
-                let q = if scale > 0.0 {
-                    (val / scale).round().clamp(-127.0, 127.0) as i8
-                } else {
-                    0i8
-                };
-                quantized.push(q);
-            }
+            kernels::quantize(block, scale, &mut quantized);
         }
 
         let qt = QuantizedTensor {
@@ -70,6 +69,7 @@ impl Compressor for BlockwiseQuantizer {
             scales,
             shape: tensor.dims().to_vec(),
             block_size: self.block_size,
+            dtype,
         };
         debug!(
             "Quantized tensor: ratio={:.2}x, {} bytes",
@@ -89,13 +89,19 @@ impl Compressor for BlockwiseQuantizer {
                 .get(block_idx)
                 .map(|s| s.to_f32())
                 .unwrap_or(1.0);
-            for &q in block {
-                data.push(q as f32 * scale);
-            }
+            kernels::dequantize(block, scale, &mut data);
         }
 
-        Tensor::from_vec(data, compressed.shape.as_slice(), &Device::Cpu)
-            .map_err(|e| CompressionError::TensorError(e.to_string()))
+        let tensor = Tensor::from_vec(data, compressed.shape.as_slice(), &Device::Cpu)
+            .map_err(|e| CompressionError::TensorError(e.to_string()))?;
+        match compressed.dtype.to_candle() {
+            Some(DType::F32) => Ok(tensor),
+            Some(dtype) => Ok(tensor.to_dtype(dtype)?),
+            None => Err(CompressionError::InvalidData(format!(
+                "cannot dequantize to {:?}",
+                compressed.dtype
+            ))),
+        }
     }
 }
 
@@ -110,6 +116,13 @@ pub struct QuantizedTensor {
     pub shape: Vec<usize>,
     /// Block size used for quantization
     pub block_size: usize,
+    /// Element type of the original tensor, restored on decompression
+    #[serde(default = "default_source_dtype")]
+    pub dtype: WireDType,
+}
+
+fn default_source_dtype() -> WireDType {
+    WireDType::F32
 }
 
 impl CompressedData for QuantizedTensor {
@@ -129,8 +142,7 @@ impl CompressedData for QuantizedTensor {
     }
 
     fn original_size_bytes(&self) -> usize {
-        // f32 original
-        self.data.len() * 4
+        self.data.len() * self.dtype.size()
     }
 }
 
@@ -216,6 +228,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_half_precision_roundtrip_keeps_dtype() {
+        let quantizer = BlockwiseQuantizer::new(32);
+        let data: Vec<f32> = (0..96).map(|i| (i as f32 * 0.3).cos() * 4.0).collect();
+        let original = Tensor::from_vec(data.clone(), &[3, 32], &Device::Cpu).unwrap();
+
+        for dtype in [DType::F16, DType::BF16] {
+            let tensor = original.to_dtype(dtype).unwrap();
+            let compressed = quantizer.compress(&tensor).unwrap();
+            // 2-byte elements in, 1 byte plus a 2-byte scale per block of 32 out
+            assert!((compressed.compression_ratio() - 2.0 * 32.0 / 34.0).abs() < 1e-3);
+
+            let back = quantizer.decompress(&compressed).unwrap();
+            assert_eq!(back.dtype(), dtype);
+            assert_eq!(back.dims(), &[3, 32]);
+            let got: Vec<f32> = back
+                .to_dtype(DType::F32)
+                .unwrap()
+                .flatten_all()
+                .unwrap()
+                .to_vec1()
+                .unwrap();
+            for (orig, got) in data.iter().zip(&got) {
+                assert!(
+                    (orig - got).abs() < 0.06,
+                    "{dtype:?}: orig={orig} got={got}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_tensors_are_rejected() {
+        let quantizer = BlockwiseQuantizer::new(8);
+        let tensor = Tensor::new(&[1u32, 2, 3], &Device::Cpu).unwrap();
+        assert!(quantizer.compress(&tensor).is_err());
+    }
+
     #[test]
     fn test_size_bytes_less_than_original() {
         let quantizer = BlockwiseQuantizer::new(64);
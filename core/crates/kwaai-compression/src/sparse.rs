@@ -1,7 +1,7 @@
 //! Sparse gradient compression
 //!
-//! Implements top-K selection and other sparsification methods
-//! for gradient compression.
+//! Implements top-K selection and N:M structured sparsity for gradient
+//! and parameter compression.
 
 use crate::{CompressedData, CompressionError, CompressionResult, Compressor};
 use candle_core::{Device, Tensor};
@@ -125,6 +125,229 @@ impl CompressedData for SparseGradient {
     }
 }
 
+/// N:M structured sparsity compressor (2:4 by default)
+///
+/// Keeps the `n` largest-magnitude values in every group of `m` consecutive
+/// elements. The result has a fixed density and a fixed-width index per kept
+/// value, the layout sparse tensor cores consume (2:4 with 2-bit indices),
+/// and costs far less metadata than Top-K's 32-bit positions.
+pub struct StructuredSparseCompressor {
+    /// Values kept per group
+    n: usize,
+    /// Group size
+    m: usize,
+}
+
+impl StructuredSparseCompressor {
+    /// Largest supported group size
+    pub const MAX_GROUP: usize = 16;
+
+    /// Create an N:M compressor
+    ///
+    /// # Arguments
+    /// * `n` - Values kept per group (1 - `m`)
+    /// * `m` - Group size (1 - [`MAX_GROUP`](Self::MAX_GROUP))
+    pub fn new(n: usize, m: usize) -> Self {
+        let m = m.clamp(1, Self::MAX_GROUP);
+        Self {
+            n: n.clamp(1, m),
+            m,
+        }
+    }
+
+    /// Values kept per group
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Group size
+    pub fn m(&self) -> usize {
+        self.m
+    }
+}
+
+impl Default for StructuredSparseCompressor {
+    fn default() -> Self {
+        Self::new(2, 4)
+    }
+}
+
+impl Compressor for StructuredSparseCompressor {
+    type Compressed = StructuredSparseTensor;
+
+    fn compress(&self, tensor: &Tensor) -> CompressionResult<StructuredSparseTensor> {
+        debug!(
+            "Structured {}:{} compress tensor shape={:?}",
+            self.n,
+            self.m,
+            tensor.dims()
+        );
+        let data = tensor
+            .flatten_all()?
+            .to_vec1::<f32>()
+            .map_err(|e| CompressionError::TensorError(e.to_string()))?;
+
+        let groups = data.len().div_ceil(self.m);
+        let mut values = Vec::with_capacity(groups * self.n);
+        let mut indices = IndexPacker::new(index_bits(self.m), groups * self.n);
+        let mut order: Vec<usize> = Vec::with_capacity(self.m);
+
+        for group in data.chunks(self.m) {
+            order.clear();
+            order.extend(0..group.len());
+            order.sort_by(|&a, &b| group[b].abs().total_cmp(&group[a].abs()));
+            order.truncate(self.n);
+            // A short last group is padded with zeros past its end
+            order.extend(group.len()..group.len() + self.n.saturating_sub(order.len()));
+            order.sort_unstable();
+            for &i in &order {
+                values.push(group.get(i).copied().unwrap_or(0.0));
+                indices.push(i as u8);
+            }
+        }
+
+        let st = StructuredSparseTensor {
+            values,
+            indices: indices.finish(),
+            n: self.n,
+            m: self.m,
+            original_size: data.len(),
+            shape: tensor.dims().to_vec(),
+        };
+        debug!(
+            "Structured sparse: kept {}/{} values, ratio={:.2}x",
+            st.values.len(),
+            data.len(),
+            st.compression_ratio()
+        );
+        Ok(st)
+    }
+
+    fn decompress(&self, compressed: &StructuredSparseTensor) -> CompressionResult<Tensor> {
+        let (n, m) = (compressed.n, compressed.m);
+        if n == 0 || n > m || m > Self::MAX_GROUP {
+            return Err(CompressionError::InvalidData(format!(
+                "invalid {n}:{m} structured sparsity"
+            )));
+        }
+        let groups = compressed.original_size.div_ceil(m);
+        if compressed.values.len() != groups * n {
+            return Err(CompressionError::InvalidData(format!(
+                "{} values for {groups} groups of {n}",
+                compressed.values.len()
+            )));
+        }
+
+        let bits = index_bits(m);
+        let mut data = vec![0.0f32; groups * m];
+        for (k, &value) in compressed.values.iter().enumerate() {
+            let offset = unpack_index(&compressed.indices, bits, k).ok_or_else(|| {
+                CompressionError::InvalidData("truncated structured sparsity indices".into())
+            })?;
+            if offset >= m {
+                return Err(CompressionError::InvalidData(format!(
+                    "index {offset} outside a group of {m}"
+                )));
+            }
+            data[(k / n) * m + offset] = value;
+        }
+        data.truncate(compressed.original_size);
+
+        Tensor::from_vec(data, compressed.shape.as_slice(), &Device::Cpu)
+            .map_err(|e| CompressionError::TensorError(e.to_string()))
+    }
+}
+
+/// N:M structured sparse representation
+///
+/// `values` holds exactly `n` values per group of `m` elements, in element
+/// order; `indices` holds their in-group positions bit-packed LSB first, 2
+/// bits each for groups of up to 4, 3 bits up to 8, 4 bits up to 16.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StructuredSparseTensor {
+    /// Kept values, `n` per group
+    pub values: Vec<f32>,
+    /// Packed in-group positions of `values`
+    pub indices: Vec<u8>,
+    /// Values kept per group
+    pub n: usize,
+    /// Group size
+    pub m: usize,
+    /// Original tensor size
+    pub original_size: usize,
+    /// Original shape
+    pub shape: Vec<usize>,
+}
+
+impl CompressedData for StructuredSparseTensor {
+    fn compression_ratio(&self) -> f32 {
+        let original = self.original_size_bytes();
+        let compressed = self.size_bytes();
+        if compressed > 0 {
+            original as f32 / compressed as f32
+        } else {
+            1.0
+        }
+    }
+
+    fn size_bytes(&self) -> usize {
+        // f32 values + packed indices
+        self.values.len() * 4 + self.indices.len()
+    }
+
+    fn original_size_bytes(&self) -> usize {
+        self.original_size * 4 // f32
+    }
+}
+
+/// Bits needed for an in-group position
+fn index_bits(m: usize) -> u32 {
+    m.next_power_of_two().trailing_zeros().max(1)
+}
+
+/// Packs fixed-width indices LSB first
+struct IndexPacker {
+    bits: u32,
+    bytes: Vec<u8>,
+    used: u32,
+}
+
+impl IndexPacker {
+    fn new(bits: u32, count: usize) -> Self {
+        Self {
+            bits,
+            bytes: Vec::with_capacity((count * bits as usize).div_ceil(8)),
+            used: 8,
+        }
+    }
+
+    fn push(&mut self, index: u8) {
+        for bit in 0..self.bits {
+            if self.used == 8 {
+                self.bytes.push(0);
+                self.used = 0;
+            }
+            let last = self.bytes.last_mut().expect("pushed above");
+            *last |= ((index >> bit) & 1) << self.used;
+            self.used += 1;
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+fn unpack_index(bytes: &[u8], bits: u32, k: usize) -> Option<usize> {
+    let mut index = 0;
+    for bit in 0..bits as usize {
+        let pos = k * bits as usize + bit;
+        let byte = bytes.get(pos / 8)?;
+        index |= (((byte >> (pos % 8)) & 1) as usize) << bit;
+    }
+    Some(index)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,4 +449,54 @@ mod tests {
         let decompressed = compressor.decompress(&compressed).unwrap();
         assert_eq!(decompressed.dims(), &[3, 10]);
     }
+
+    #[test]
+    fn test_two_four_keeps_two_largest_per_group() {
+        let compressor = StructuredSparseCompressor::default();
+        let data = vec![0.1f32, -3.0, 2.0, 0.5, 4.0, 0.0, 0.0, -1.0];
+        let tensor = Tensor::from_vec(data, &[2, 4], &Device::Cpu).unwrap();
+        let compressed = compressor.compress(&tensor).unwrap();
+        assert_eq!(compressed.values, vec![-3.0, 2.0, 4.0, -1.0]);
+        // Four 2-bit indices: 1, 2, 0, 3
+        assert_eq!(compressed.indices, vec![0b11_00_10_01]);
+
+        let recovered: Vec<f32> = compressor
+            .decompress(&compressed)
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .to_vec1()
+            .unwrap();
+        assert_eq!(recovered, vec![0.0, -3.0, 2.0, 0.0, 4.0, 0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn test_structured_short_last_group_and_wide_groups() {
+        let compressor = StructuredSparseCompressor::new(3, 8);
+        let data: Vec<f32> = (0..11).map(|i| (i as f32 - 5.0) * 0.5).collect();
+        let tensor = Tensor::from_vec(data.clone(), &[11], &Device::Cpu).unwrap();
+        let compressed = compressor.compress(&tensor).unwrap();
+        assert_eq!(compressed.values.len(), 6);
+
+        let recovered: Vec<f32> = compressor
+            .decompress(&compressed)
+            .unwrap()
+            .to_vec1()
+            .unwrap();
+        assert_eq!(recovered.len(), 11);
+        // The last group has 3 elements, all kept
+        assert_eq!(&recovered[8..], &data[8..]);
+        assert_eq!(recovered.iter().filter(|v| **v != 0.0).count(), 6);
+    }
+
+    #[test]
+    fn test_structured_rejects_corrupt_layout() {
+        let compressor = StructuredSparseCompressor::default();
+        let tensor = Tensor::from_vec(vec![1.0f32; 8], &[8], &Device::Cpu).unwrap();
+        let mut compressed = compressor.compress(&tensor).unwrap();
+        compressed.indices.clear();
+        assert!(compressor.decompress(&compressed).is_err());
+        compressed.values.pop();
+        assert!(compressor.decompress(&compressed).is_err());
+    }
 }
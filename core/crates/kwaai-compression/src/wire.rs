@@ -0,0 +1,413 @@
+//! TensorWire: self-describing tensor serialization for P2P transfers
+//!
+//! Every tensor that crosses the network carries its element type, shape,
+//! optional strides, byte order and compression codec next to the raw
+//! bytes, so the receiver never has to guess how a buffer was produced.
+//!
+//! Decoding a plain (uncompressed) payload in native byte order and
+//! contiguous layout goes straight from the byte buffer into Candle storage
+//! via [`Tensor::from_raw_buffer`], without an intermediate typed `Vec`.
+//! Non-native byte order or strided layouts are normalised first.
+
+use crate::{CompressionError, CompressionResult, Compressor, QuantizedTensor};
+use candle_core::{DType, Device, Tensor};
+use half::{bf16, f16};
+use serde::{Deserialize, Serialize};
+
+/// Element type of [`TensorWire::data`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireDType {
+    F32,
+    /// Default for payloads from peers that predate TensorWire, which always
+    /// sent half precision.
+    #[default]
+    F16,
+    BF16,
+    /// Only valid together with a [`WireCodec`] that dequantizes it.
+    I8,
+    U32,
+}
+
+impl WireDType {
+    /// Bytes per element.
+    pub fn size(self) -> usize {
+        match self {
+            WireDType::F32 | WireDType::U32 => 4,
+            WireDType::F16 | WireDType::BF16 => 2,
+            WireDType::I8 => 1,
+        }
+    }
+
+    /// The wire type for a Candle dtype, if it has one.
+    pub fn from_candle(dtype: DType) -> CompressionResult<Self> {
+        match dtype {
+            DType::F32 => Ok(WireDType::F32),
+            DType::F16 => Ok(WireDType::F16),
+            DType::BF16 => Ok(WireDType::BF16),
+            DType::U32 => Ok(WireDType::U32),
+            other => Err(CompressionError::InvalidData(format!(
+                "dtype {other:?} has no TensorWire encoding"
+            ))),
+        }
+    }
+
+    /// The Candle dtype a plain payload decodes to. `None` for `I8`, which
+    /// Candle has no type for.
+    pub fn to_candle(self) -> Option<DType> {
+        match self {
+            WireDType::F32 => Some(DType::F32),
+            WireDType::F16 => Some(DType::F16),
+            WireDType::BF16 => Some(DType::BF16),
+            WireDType::U32 => Some(DType::U32),
+            WireDType::I8 => None,
+        }
+    }
+}
+
+/// Byte order of multi-byte elements in [`TensorWire::data`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Byte order of this machine.
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+}
+
+/// Compression applied to [`TensorWire::data`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireCodec {
+    /// Blockwise 8-bit quantization (see [`crate::BlockwiseQuantizer`]):
+    /// `data` holds one `i8` per element, scaled by one factor per block,
+    /// and dequantizes to `output` (f32 when absent).
+    Blockwise8Bit {
+        block_size: usize,
+        scales: Vec<f16>,
+        #[serde(default)]
+        output: Option<WireDType>,
+    },
+}
+
+/// A tensor as sent between peers.
+///
+/// Fields other than `shape` and `data` default when absent, so a message
+/// with only those two decodes as contiguous little-endian f16 — the format
+/// KwaaiNet used before TensorWire.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TensorWire {
+    /// Element type of `data`
+    #[serde(default)]
+    pub dtype: WireDType,
+    /// Dimensions, outermost first
+    pub shape: Vec<u64>,
+    /// Per-dimension strides in elements; empty means contiguous row-major
+    #[serde(default)]
+    pub strides: Vec<u64>,
+    /// Byte order of `data`
+    #[serde(default)]
+    pub endianness: Endianness,
+    /// Compression applied to `data`, if any
+    #[serde(default)]
+    pub codec: Option<WireCodec>,
+    /// Raw element bytes
+    pub data: Vec<u8>,
+}
+
+impl TensorWire {
+    /// Encode `tensor` as contiguous little-endian `dtype` elements,
+    /// converting it first if needed. Use [`from_quantized`](Self::from_quantized)
+    /// for 8-bit payloads.
+    pub fn from_tensor(tensor: &Tensor, dtype: WireDType) -> CompressionResult<Self> {
+        let flat = match dtype.to_candle() {
+            Some(dt) => tensor.to_dtype(dt)?.flatten_all()?,
+            None => {
+                return Err(CompressionError::InvalidData(
+                    "int8 tensors must be sent with a quantization codec".to_string(),
+                ))
+            }
+        };
+        let data: Vec<u8> = match dtype {
+            WireDType::F32 => le_bytes(flat.to_vec1::<f32>()?, f32::to_le_bytes),
+            WireDType::F16 => le_bytes(flat.to_vec1::<f16>()?, f16::to_le_bytes),
+            WireDType::BF16 => le_bytes(flat.to_vec1::<bf16>()?, bf16::to_le_bytes),
+            WireDType::U32 => le_bytes(flat.to_vec1::<u32>()?, u32::to_le_bytes),
+            WireDType::I8 => unreachable!("rejected above"),
+        };
+        Ok(Self {
+            dtype,
+            shape: tensor.dims().iter().map(|&d| d as u64).collect(),
+            data,
+            ..Self::default()
+        })
+    }
+
+    /// Encode a 1-D `u32` tensor, e.g. token IDs.
+    pub fn from_u32(values: &[u32]) -> Self {
+        Self {
+            dtype: WireDType::U32,
+            shape: vec![values.len() as u64],
+            data: le_bytes(values.to_vec(), u32::to_le_bytes),
+            ..Self::default()
+        }
+    }
+
+    /// Wrap a blockwise-quantized tensor; decodes back to its original dtype.
+    pub fn from_quantized(q: &QuantizedTensor) -> Self {
+        Self {
+            dtype: WireDType::I8,
+            shape: q.shape.iter().map(|&d| d as u64).collect(),
+            codec: Some(WireCodec::Blockwise8Bit {
+                block_size: q.block_size,
+                scales: q.scales.clone(),
+                output: Some(q.dtype),
+            }),
+            data: q.data.iter().map(|&v| v as u8).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Number of elements described by `shape`.
+    pub fn num_elements(&self) -> usize {
+        self.shape.iter().product::<u64>() as usize
+    }
+
+    /// Payload size in bytes, excluding metadata.
+    pub fn size_bytes(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Decode into a Candle tensor on `device`. Plain payloads keep their
+    /// dtype; quantized payloads are dequantized to the codec's output dtype.
+    pub fn to_tensor(&self, device: &Device) -> CompressionResult<Tensor> {
+        let shape = self.shape_usize();
+        if let Some(WireCodec::Blockwise8Bit {
+            block_size,
+            scales,
+            output,
+        }) = &self.codec
+        {
+            if *block_size == 0 {
+                return Err(CompressionError::InvalidData(
+                    "quantization block size is zero".to_string(),
+                ));
+            }
+            let q = QuantizedTensor {
+                data: self.contiguous_bytes()?.iter().map(|&b| b as i8).collect(),
+                scales: scales.clone(),
+                shape,
+                block_size: *block_size,
+                dtype: output.unwrap_or(WireDType::F32),
+            };
+            return crate::BlockwiseQuantizer::new(*block_size)
+                .decompress(&q)?
+                .to_device(device)
+                .map_err(Into::into);
+        }
+        let Some(dtype) = self.dtype.to_candle() else {
+            return Err(CompressionError::InvalidData(
+                "int8 payload without a quantization codec".to_string(),
+            ));
+        };
+        let bytes = self.contiguous_bytes()?;
+        Tensor::from_raw_buffer(&bytes, dtype, &shape, device).map_err(Into::into)
+    }
+
+    /// Decode a `u32` payload, e.g. token IDs.
+    pub fn to_u32_vec(&self) -> CompressionResult<Vec<u32>> {
+        if self.dtype != WireDType::U32 || self.codec.is_some() {
+            return Err(CompressionError::InvalidData(format!(
+                "expected a plain u32 payload, got {:?}",
+                self.dtype
+            )));
+        }
+        Ok(self
+            .contiguous_bytes()?
+            .chunks_exact(4)
+            .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+            .collect())
+    }
+
+    fn shape_usize(&self) -> Vec<usize> {
+        self.shape.iter().map(|&d| d as usize).collect()
+    }
+
+    /// `data` as contiguous row-major elements in native byte order,
+    /// borrowed when it already is.
+    fn contiguous_bytes(&self) -> CompressionResult<std::borrow::Cow<'_, [u8]>> {
+        let elem = self.dtype.size();
+        let numel = self.num_elements();
+        let contiguous = self.strides.is_empty() || self.strides == row_major(&self.shape);
+
+        let mut bytes = if contiguous {
+            if self.data.len() != numel * elem {
+                return Err(CompressionError::ShapeMismatch {
+                    expected: vec![numel * elem],
+                    actual: vec![self.data.len()],
+                });
+            }
+            std::borrow::Cow::Borrowed(self.data.as_slice())
+        } else {
+            std::borrow::Cow::Owned(self.gather(elem, numel)?)
+        };
+
+        if elem > 1 && self.endianness != Endianness::native() {
+            for chunk in bytes.to_mut().chunks_exact_mut(elem) {
+                chunk.reverse();
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Copy a strided payload into row-major order.
+    fn gather(&self, elem: usize, numel: usize) -> CompressionResult<Vec<u8>> {
+        if self.strides.len() != self.shape.len() {
+            return Err(CompressionError::InvalidData(format!(
+                "{} strides for {} dimensions",
+                self.strides.len(),
+                self.shape.len()
+            )));
+        }
+        let max_offset: u64 = self
+            .shape
+            .iter()
+            .zip(&self.strides)
+            .map(|(&d, &s)| d.saturating_sub(1) * s)
+            .sum();
+        if numel > 0 && (max_offset as usize + 1) * elem > self.data.len() {
+            return Err(CompressionError::InvalidData(
+                "strides reach past the end of the payload".to_string(),
+            ));
+        }
+
+        let mut out = Vec::with_capacity(numel * elem);
+        let mut index = vec![0u64; self.shape.len()];
+        for _ in 0..numel {
+            let offset: u64 = index.iter().zip(&self.strides).map(|(i, s)| i * s).sum();
+            let start = offset as usize * elem;
+            out.extend_from_slice(&self.data[start..start + elem]);
+            // Advance the multi-index, last dimension fastest.
+            for dim in (0..index.len()).rev() {
+                index[dim] += 1;
+                if index[dim] < self.shape[dim] {
+                    break;
+                }
+                index[dim] = 0;
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn le_bytes<T, const N: usize>(values: Vec<T>, to_le: fn(T) -> [u8; N]) -> Vec<u8> {
+    values.into_iter().flat_map(to_le).collect()
+}
+
+/// Row-major strides for `shape`.
+fn row_major(shape: &[u64]) -> Vec<u64> {
+    let mut strides = vec![1u64; shape.len()];
+    for i in (0..shape.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * shape[i + 1];
+    }
+    strides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Tensor {
+        let data: Vec<f32> = (0..24).map(|i| i as f32 * 0.25 - 3.0).collect();
+        Tensor::from_vec(data, &[2, 3, 4], &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn float_dtypes_round_trip_with_shape() {
+        let t = sample();
+        for dtype in [WireDType::F32, WireDType::F16, WireDType::BF16] {
+            let wire = TensorWire::from_tensor(&t, dtype).unwrap();
+            assert_eq!(wire.shape, [2, 3, 4]);
+            assert_eq!(wire.size_bytes(), 24 * dtype.size());
+            let back = wire.to_tensor(&Device::Cpu).unwrap();
+            assert_eq!(back.dtype(), dtype.to_candle().unwrap());
+            assert_eq!(back.dims(), &[2, 3, 4]);
+            let got: Vec<f32> = back
+                .to_dtype(DType::F32)
+                .unwrap()
+                .flatten_all()
+                .unwrap()
+                .to_vec1()
+                .unwrap();
+            let want: Vec<f32> = t.flatten_all().unwrap().to_vec1().unwrap();
+            assert_eq!(got, want, "{dtype:?}");
+        }
+    }
+
+    #[test]
+    fn big_endian_and_strided_payloads_are_normalised() {
+        // A transposed 2x3 u32 view of [[0, 1], [2, 3], [4, 5]], big-endian.
+        let wire = TensorWire {
+            dtype: WireDType::U32,
+            shape: vec![2, 3],
+            strides: vec![1, 2],
+            endianness: Endianness::Big,
+            codec: None,
+            data: (0u32..6).flat_map(u32::to_be_bytes).collect(),
+        };
+        assert_eq!(wire.to_u32_vec().unwrap(), [0, 2, 4, 1, 3, 5]);
+
+        let bad = TensorWire {
+            strides: vec![1, 3],
+            ..wire
+        };
+        assert!(bad.to_u32_vec().is_err());
+    }
+
+    #[test]
+    fn quantized_payload_decodes_to_f32() {
+        use crate::BlockwiseQuantizer;
+        let t = sample();
+        let q = BlockwiseQuantizer::new(8).compress(&t).unwrap();
+        let wire = TensorWire::from_quantized(&q);
+        assert_eq!(wire.size_bytes(), 24);
+        let back = wire.to_tensor(&Device::Cpu).unwrap();
+        assert_eq!(back.dtype(), DType::F32);
+        assert_eq!(back.dims(), &[2, 3, 4]);
+        assert!(TensorWire::from_tensor(&t, WireDType::I8).is_err());
+    }
+
+    #[test]
+    fn quantized_half_payload_decodes_to_half() {
+        use crate::BlockwiseQuantizer;
+        let t = sample().to_dtype(DType::BF16).unwrap();
+        let q = BlockwiseQuantizer::new(8).compress(&t).unwrap();
+        let wire = TensorWire::from_quantized(&q);
+        let bytes = bincode::serialize(&wire).unwrap();
+        let back: TensorWire = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(back.to_tensor(&Device::Cpu).unwrap().dtype(), DType::BF16);
+    }
+
+    #[test]
+    fn survives_bincode_with_metadata() {
+        let wire = TensorWire::from_u32(&[7, 8, 9]);
+        let bytes = bincode::serialize(&wire).unwrap();
+        let back: TensorWire = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(back, wire);
+        assert_eq!(back.to_u32_vec().unwrap(), [7, 8, 9]);
+        assert!(TensorWire::from_tensor(&sample(), WireDType::F32)
+            .unwrap()
+            .to_u32_vec()
+            .is_err());
+    }
+}
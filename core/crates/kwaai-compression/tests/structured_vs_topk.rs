@@ -0,0 +1,69 @@
+//! 2:4 structured sparsity against Top-K at the same density
+
+use candle_core::{Device, Tensor};
+use kwaai_compression::{CompressedData, Compressor, StructuredSparseCompressor, TopKCompressor};
+
+/// Deterministic gradient-like values: mostly small, a few large
+fn gradient(len: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let u = (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+            u * u * u * 8.0
+        })
+        .collect()
+}
+
+fn squared_error<C: Compressor>(compressor: &C, tensor: &Tensor) -> (f32, C::Compressed) {
+    let compressed = compressor.compress(tensor).unwrap();
+    let recovered = compressor.decompress(&compressed).unwrap();
+    let err = (tensor - recovered)
+        .unwrap()
+        .sqr()
+        .unwrap()
+        .sum_all()
+        .unwrap()
+        .to_scalar::<f32>()
+        .unwrap();
+    (err, compressed)
+}
+
+#[test]
+fn two_four_error_is_bounded_by_topk_and_half_the_energy() {
+    let data = gradient(4096);
+    let tensor = Tensor::from_vec(data.clone(), &[64, 64], &Device::Cpu).unwrap();
+    let energy: f32 = data.iter().map(|v| v * v).sum();
+
+    let (structured_err, structured) =
+        squared_error(&StructuredSparseCompressor::default(), &tensor);
+    let (topk_err, topk) = squared_error(&TopKCompressor::new(0.5), &tensor);
+
+    assert_eq!(structured.values.len(), topk.values.len());
+    // Top-K picks the best half globally, 2:4 the best half of every group
+    assert!(
+        topk_err <= structured_err,
+        "topk {topk_err} vs 2:4 {structured_err}"
+    );
+    assert!(structured_err <= 0.5 * energy);
+    // 2-bit positions instead of 32-bit ones
+    assert!(structured.size_bytes() < topk.size_bytes());
+    assert!(structured.compression_ratio() > 1.8);
+}
+
+#[test]
+fn already_two_four_sparse_data_is_exact_for_both() {
+    let data: Vec<f32> = gradient(1024)
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| if i % 4 < 2 { v + 1.0 } else { 0.0 })
+        .collect();
+    let tensor = Tensor::from_vec(data, &[1024], &Device::Cpu).unwrap();
+
+    let (structured_err, _) = squared_error(&StructuredSparseCompressor::default(), &tensor);
+    let (topk_err, _) = squared_error(&TopKCompressor::new(0.5), &tensor);
+    assert_eq!(structured_err, 0.0);
+    assert_eq!(topk_err, 0.0);
+}
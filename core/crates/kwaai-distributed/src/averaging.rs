@@ -3,11 +3,22 @@
 //! Implements gradient/parameter averaging without a central server.
 
 use crate::error::{DistributedError, DistributedResult};
+use crate::matchmaking::{GroupInfo, Matchmaker, MatchmakingRpc};
+use crate::progress::{GlobalProgress, ProgressTracker};
 use async_trait::async_trait;
-use candle_core::Tensor;
-use kwaai_compression::{BlockwiseQuantizer, Compressor, QuantizedTensor};
+use candle_core::{DType, Device, Tensor};
+use kwaai_compression::{
+    BlockwiseQuantizer, Compressor, DeltaCompressor, DeltaTensor, QuantizedTensor, TensorWire,
+    WireDType,
+};
+use kwaai_p2p::chunked::{self, ChunkSink, ChunkSource, TransferConfig, TransferProgress};
+use kwaai_p2p::{DhtOperations, EncryptedStream, PayloadCipher, ReputationStore};
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::{debug, info, warn};
 
 /// Result of an averaging step
@@ -54,6 +65,265 @@ pub trait ParameterAverager: Send + Sync {
     fn clear(&mut self);
 }
 
+/// Encrypts every stream of an inner transport for the member at the other
+/// end
+///
+/// Group members are named by peer id, from which
+/// [`PayloadCipher::for_peer`] derives the key both ends share, so relays
+/// carrying the exchange see only ciphertext (see [`EncryptedStream`]).
+pub struct EncryptedTransport<T> {
+    inner: T,
+    keypair: Keypair,
+}
+
+impl<T> EncryptedTransport<T> {
+    /// Encrypt `inner`'s streams as the node owning `keypair`
+    pub fn new(inner: T, keypair: Keypair) -> Self {
+        Self { inner, keypair }
+    }
+}
+
+#[async_trait]
+impl<T: AveragingTransport> AveragingTransport for EncryptedTransport<T> {
+    type Stream = EncryptedStream<T::Stream>;
+
+    async fn connect(&self, peer: &str, round: u64) -> DistributedResult<Self::Stream> {
+        let remote: PeerId = peer
+            .parse()
+            .map_err(|e| DistributedError::NetworkError(format!("bad peer id {peer}: {e}")))?;
+        let cipher = PayloadCipher::for_peer(&self.keypair, &remote)?;
+        let stream = self.inner.connect(peer, round).await?;
+        Ok(EncryptedStream::new(
+            stream,
+            cipher,
+            &self.keypair.public().to_peer_id(),
+            &remote,
+        ))
+    }
+}
+
+/// Receiver's answer to a parameter transfer: applied
+const PARAMS_APPLIED: u8 = 0;
+/// Receiver's answer to a parameter transfer: the deltas did not apply to
+/// its version, send keyframes
+const PARAMS_NEED_KEYFRAME: u8 = 1;
+/// Receiver's answer to a parameter transfer: refused
+const PARAMS_REJECTED: u8 = 2;
+
+/// How an averaging group combines its members' gradients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllReduceStrategy {
+    /// Every member sends its full gradients to every other member
+    ///
+    /// Simple, but each member uploads `N - 1` copies of its gradients.
+    #[default]
+    Naive,
+    /// Members form a ring and pass one chunk to their right neighbour per
+    /// step (reduce-scatter, then all-gather)
+    ///
+    /// Each member uploads about `2 (N - 1) / N` of its gradients whatever
+    /// the group size, and every link carries traffic at every step.
+    Ring,
+}
+
+/// Streams between the members of an averaging group
+///
+/// Both members of a pair ask for the stream to each other and get the two
+/// ends of one connection (for example, the member with the smaller peer id
+/// dials and the other accepts). Each pair uses its stream once per round.
+#[async_trait]
+pub trait AveragingTransport: Send + Sync {
+    /// Stream carrying one exchange
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send;
+
+    /// Open, or accept, the stream to `peer` for `round`
+    async fn connect(&self, peer: &str, round: u64) -> DistributedResult<Self::Stream>;
+}
+
+/// Gradients as a chunk source, encoded one tensor at a time
+///
+/// Each tensor is a record: its encoded length (8 bytes, little endian),
+/// then its bincode [`TensorWire`]. Only the record being read is held.
+struct GradientSource<'a> {
+    averager: &'a DecentralizedAverager,
+    gradients: &'a [Tensor],
+    /// Start of every record, then the total length
+    offsets: Vec<u64>,
+    /// Index and bytes of the record last read from
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl<'a> GradientSource<'a> {
+    fn new(
+        averager: &'a DecentralizedAverager,
+        gradients: &'a [Tensor],
+    ) -> DistributedResult<Self> {
+        let mut source = Self {
+            averager,
+            gradients,
+            offsets: vec![0],
+            current: None,
+        };
+        // Sizing every record up front keeps the transfer resumable
+        let mut end = 0;
+        for i in 0..gradients.len() {
+            end += source.record(i)?.len() as u64;
+            source.offsets.push(end);
+        }
+        Ok(source)
+    }
+
+    fn record(&self, index: usize) -> DistributedResult<Vec<u8>> {
+        let wire = self.averager.encode_wire(&self.gradients[index])?;
+        let body = bincode::serialize(&wire)
+            .map_err(|e| DistributedError::Internal(format!("encode gradients: {e}")))?;
+        let mut record = Vec::with_capacity(8 + body.len());
+        record.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        record.extend_from_slice(&body);
+        Ok(record)
+    }
+}
+
+impl ChunkSource for GradientSource<'_> {
+    fn total_len(&mut self) -> std::io::Result<u64> {
+        Ok(self.offsets.last().copied().unwrap_or(0))
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let pos = offset + filled as u64;
+            let index = self.offsets.partition_point(|&o| o <= pos) - 1;
+            if index >= self.gradients.len() {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            if self.current.as_ref().map(|(i, _)| *i) != Some(index) {
+                let record = self
+                    .record(index)
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+                self.current = Some((index, record));
+            }
+            let record = &self.current.as_ref().expect("record loaded above").1;
+            let start = (pos - self.offsets[index]) as usize;
+            let n = (record.len() - start).min(buf.len() - filled);
+            buf[filled..filled + n].copy_from_slice(&record[start..start + n]);
+            filled += n;
+        }
+        Ok(())
+    }
+}
+
+/// Receiving end of a gradient transfer, kept across reconnects
+///
+/// Gradients are decoded as their bytes arrive, so only the tensor still
+/// in flight is buffered. See
+/// [`receive_gradients`](DecentralizedAverager::receive_gradients).
+#[derive(Default)]
+pub struct GradientSink {
+    total_len: u64,
+    written: u64,
+    /// Bytes of the record not yet complete
+    record: Vec<u8>,
+    tensors: Vec<Tensor>,
+}
+
+impl ChunkSink for GradientSink {
+    fn prepare(&mut self, total_len: u64) -> std::io::Result<()> {
+        *self = Self {
+            total_len,
+            ..Self::default()
+        };
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        if offset != self.written {
+            return Err(invalid(format!(
+                "gradient bytes at {offset}, expected {}",
+                self.written
+            )));
+        }
+        self.written += data.len() as u64;
+        self.record.extend_from_slice(data);
+        while self.record.len() >= 8 {
+            let len = u64::from_le_bytes(self.record[..8].try_into().expect("8 bytes"));
+            if len > self.total_len {
+                return Err(invalid(format!("gradient record of {len} bytes")));
+            }
+            let end = 8 + len as usize;
+            if self.record.len() < end {
+                break;
+            }
+            let wire: TensorWire = bincode::deserialize(&self.record[8..end])
+                .map_err(|e| invalid(format!("decode gradients: {e}")))?;
+            let tensor = wire
+                .to_tensor(&Device::Cpu)
+                .map_err(|e| invalid(format!("decode gradients: {e}")))?;
+            self.tensors.push(tensor);
+            self.record.drain(..end);
+        }
+        Ok(())
+    }
+}
+
+/// What an averaging round goes through to reach its group
+///
+/// `progress` tells when a round is due, the matchmaker finds the group
+/// through `dht` and `rpc`, and `transport` carries the exchange with its
+/// members.
+pub struct AveragingSwarm<'a, D: ?Sized, T: ?Sized> {
+    /// This node's training progress
+    pub progress: &'a ProgressTracker,
+    /// Finds the group; its peer id is this node's
+    pub matchmaker: &'a Matchmaker,
+    /// DHT the matchmaker announces readiness in
+    pub dht: &'a mut D,
+    /// Carries join requests to group leaders
+    pub rpc: &'a dyn MatchmakingRpc,
+    /// Carries the exchange with group members
+    pub transport: &'a T,
+}
+
+/// This node's place in an all-reduce ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingPosition {
+    /// Index of this node in the ring
+    pub rank: usize,
+    /// Number of members in the ring
+    pub world: usize,
+    /// Peer this node receives chunks from
+    pub left: String,
+    /// Peer this node sends chunks to
+    pub right: String,
+}
+
+impl RingPosition {
+    /// Place `me` in a ring with `group`
+    ///
+    /// Members are ordered by peer id, so every member derives the same
+    /// ring from the same group without further coordination. Returns
+    /// `None` when there is nobody to average with.
+    pub fn of(me: &str, group: &[String]) -> Option<Self> {
+        let mut members: Vec<&str> = group.iter().map(String::as_str).collect();
+        members.push(me);
+        members.sort_unstable();
+        members.dedup();
+        let world = members.len();
+        if world < 2 {
+            return None;
+        }
+        let rank = members.iter().position(|m| *m == me)?;
+        Some(Self {
+            rank,
+            world,
+            left: members[(rank + world - 1) % world].to_string(),
+            right: members[(rank + 1) % world].to_string(),
+        })
+    }
+}
+
 /// Configuration for decentralized averaging
 #[derive(Debug, Clone)]
 pub struct AveragingConfig {
@@ -67,6 +337,14 @@ pub struct AveragingConfig {
     pub quantization_block_size: usize,
     /// Enable compression
     pub enable_compression: bool,
+    /// Chunking and flow control for gradient exchange streams
+    pub transfer: TransferConfig,
+    /// How the group combines gradients once formed
+    pub allreduce_strategy: AllReduceStrategy,
+    /// Parameter blocks whose change stays within this are not resent
+    pub delta_tolerance: f32,
+    /// Resend full parameters every this many versions (0 = only when needed)
+    pub delta_keyframe_interval: u64,
 }
 
 impl Default for AveragingConfig {
@@ -77,6 +355,10 @@ impl Default for AveragingConfig {
             exchange_timeout: Duration::from_secs(60),
             quantization_block_size: 64,
             enable_compression: true,
+            transfer: TransferConfig::default(),
+            allreduce_strategy: AllReduceStrategy::default(),
+            delta_tolerance: 0.01,
+            delta_keyframe_interval: 32,
         }
     }
 }
@@ -90,7 +372,6 @@ impl Default for AveragingConfig {
 /// 4. Average and apply
 pub struct DecentralizedAverager {
     /// Configuration
-    #[allow(dead_code)]
     config: AveragingConfig,
     /// Accumulated gradients
     accumulated: Vec<Tensor>,
@@ -98,6 +379,10 @@ pub struct DecentralizedAverager {
     compressor: BlockwiseQuantizer,
     /// Number of accumulation steps
     accumulation_count: usize,
+    /// Delta encoders for parameters sent to each peer
+    outgoing_deltas: HashMap<String, DeltaCompressor>,
+    /// Delta decoders for parameters received from each peer
+    incoming_deltas: HashMap<String, DeltaCompressor>,
 }
 
 impl DecentralizedAverager {
@@ -106,6 +391,7 @@ impl DecentralizedAverager {
         info!(
             group_size = config.group_size,
             compression = config.enable_compression,
+            strategy = ?config.allreduce_strategy,
             "Creating DecentralizedAverager"
         );
         let compressor = BlockwiseQuantizer::new(config.quantization_block_size);
@@ -114,9 +400,33 @@ impl DecentralizedAverager {
             accumulated: Vec::new(),
             compressor,
             accumulation_count: 0,
+            outgoing_deltas: HashMap::new(),
+            incoming_deltas: HashMap::new(),
         }
     }
 
+    /// All-reduce strategy this averager was configured with
+    pub fn allreduce_strategy(&self) -> AllReduceStrategy {
+        self.config.allreduce_strategy
+    }
+
+    /// Whether to start an averaging round, given the swarm's progress
+    ///
+    /// A round is due once the swarm has accumulated its target batch, or
+    /// immediately when this peer (at `local_epoch`) has fallen behind.
+    pub fn should_average(&self, local_epoch: u64, global: &GlobalProgress) -> bool {
+        let due = local_epoch < global.epoch || global.ready_to_advance();
+        debug!(
+            local_epoch,
+            global_epoch = global.epoch,
+            samples = global.samples_accumulated,
+            target = global.target_batch_size,
+            due,
+            "Checked swarm progress"
+        );
+        due
+    }
+
     /// Compress gradients for transmission
     pub fn compress_gradients(
         &self,
@@ -145,6 +455,550 @@ impl DecentralizedAverager {
             .collect()
     }
 
+    /// Encode one gradient for the wire (8-bit blockwise when compression
+    /// is enabled)
+    fn encode_wire(&self, gradient: &Tensor) -> DistributedResult<TensorWire> {
+        if self.config.enable_compression {
+            Ok(TensorWire::from_quantized(
+                &self.compressor.compress(gradient)?,
+            ))
+        } else {
+            Ok(TensorWire::from_tensor(gradient, WireDType::F32)?)
+        }
+    }
+
+    /// Send gradients to an averaging partner over `stream`
+    ///
+    /// Gradients are encoded as [`TensorWire`]s one at a time as their
+    /// chunks go out (see [`GradientSink`] for the receiving side), so only
+    /// the tensor being sent is held encoded. `round` identifies the
+    /// exchange; the partner uses it to resume an interrupted transfer.
+    pub async fn send_gradients<S>(
+        &self,
+        stream: &mut S,
+        gradients: &[Tensor],
+        round: u64,
+    ) -> DistributedResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut source = GradientSource::new(self, gradients)?;
+        debug!(
+            tensors = gradients.len(),
+            bytes = source.offsets.last().copied().unwrap_or(0),
+            round,
+            "Sending gradients"
+        );
+        chunked::send_chunked(stream, &mut source, round, &self.config.transfer).await?;
+        Ok(())
+    }
+
+    /// Receive gradients sent with [`send_gradients`](Self::send_gradients)
+    ///
+    /// `sink` and `progress` hold the partial transfer between attempts:
+    /// pass both again after a dropped connection to resume it. They are
+    /// reset when a different round is offered.
+    pub async fn receive_gradients<S>(
+        &self,
+        stream: &mut S,
+        sink: &mut GradientSink,
+        progress: &mut Option<TransferProgress>,
+    ) -> DistributedResult<Vec<Tensor>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        chunked::receive_chunked(stream, sink, progress, &self.config.transfer).await?;
+        if !sink.record.is_empty() {
+            return Err(DistributedError::Internal(format!(
+                "decode gradients: {} trailing bytes",
+                sink.record.len()
+            )));
+        }
+        let gradients = std::mem::take(&mut sink.tensors);
+        debug!(tensors = gradients.len(), "Received gradients");
+        Ok(gradients)
+    }
+
+    /// Send parameters to `peer` over `stream`, delta-encoded
+    ///
+    /// Only blocks that changed since the version last sent to `peer` go
+    /// out, 8-bit quantized; the first send and every
+    /// `delta_keyframe_interval`-th one carry full parameters. When `peer`
+    /// rejects the deltas it asks for a keyframe, which is sent straight
+    /// away. A failed send forgets the link, so the next one is a keyframe.
+    pub async fn send_parameters<S>(
+        &mut self,
+        stream: &mut S,
+        peer: &str,
+        parameters: &[Tensor],
+        round: u64,
+    ) -> DistributedResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        loop {
+            let encoder = self
+                .outgoing_deltas
+                .entry(peer.to_string())
+                .or_insert_with(|| {
+                    DeltaCompressor::new(self.config.quantization_block_size)
+                        .with_tolerance(self.config.delta_tolerance)
+                        .with_keyframe_interval(self.config.delta_keyframe_interval)
+                });
+            let deltas = encoder.encode(parameters)?;
+            let keyframes = deltas.iter().filter(|d| d.is_keyframe()).count();
+            let payload = bincode::serialize(&deltas)
+                .map_err(|e| DistributedError::Internal(format!("encode parameters: {e}")))?;
+            debug!(
+                peer,
+                tensors = deltas.len(),
+                keyframes,
+                bytes = payload.len(),
+                round,
+                "Sending parameter deltas"
+            );
+            let answer = async {
+                chunked::send_bytes(stream, &payload, round, &self.config.transfer).await?;
+                stream.read_u8().await.map_err(|e| {
+                    DistributedError::NetworkError(format!("parameter answer from {peer}: {e}"))
+                })
+            }
+            .await;
+            match answer {
+                Ok(PARAMS_APPLIED) => return Ok(()),
+                Ok(PARAMS_NEED_KEYFRAME) if keyframes < deltas.len() => {
+                    debug!(peer, "Peer rejected the deltas, sending a keyframe");
+                    self.outgoing_deltas.remove(peer);
+                }
+                Ok(_) => {
+                    self.outgoing_deltas.remove(peer);
+                    return Err(DistributedError::RemoteCallFailed(format!(
+                        "{peer} rejected the parameters"
+                    )));
+                }
+                Err(e) => {
+                    self.outgoing_deltas.remove(peer);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Receive parameters sent with [`send_parameters`](Self::send_parameters)
+    ///
+    /// `buffer` and `progress` resume an interrupted transfer as in
+    /// [`receive_gradients`](Self::receive_gradients). Deltas that do not
+    /// apply to the version held for `peer` are rejected without touching
+    /// it, and a keyframe is requested in their place.
+    pub async fn receive_parameters<S>(
+        &mut self,
+        stream: &mut S,
+        peer: &str,
+        buffer: &mut Vec<u8>,
+        progress: &mut Option<TransferProgress>,
+    ) -> DistributedResult<Vec<Tensor>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        loop {
+            chunked::receive_chunked(stream, buffer, progress, &self.config.transfer).await?;
+            let (answer, decoded) = match bincode::deserialize::<Vec<DeltaTensor>>(buffer) {
+                Err(e) => (
+                    PARAMS_REJECTED,
+                    Err(DistributedError::Internal(format!(
+                        "decode parameters: {e}"
+                    ))),
+                ),
+                Ok(deltas) => {
+                    let decoder =
+                        self.incoming_deltas
+                            .entry(peer.to_string())
+                            .or_insert_with(|| {
+                                DeltaCompressor::new(self.config.quantization_block_size)
+                            });
+                    match decoder.decode(&deltas) {
+                        Ok(parameters) => (PARAMS_APPLIED, Ok(parameters)),
+                        // Keyframes fix deltas against a base we do not hold
+                        Err(e) if !deltas.iter().all(|d| d.is_keyframe()) => {
+                            (PARAMS_NEED_KEYFRAME, Err(e.into()))
+                        }
+                        Err(e) => (PARAMS_REJECTED, Err(e.into())),
+                    }
+                }
+            };
+            let answered = async {
+                stream.write_u8(answer).await?;
+                stream.flush().await
+            }
+            .await;
+            answered.map_err(|e| {
+                DistributedError::NetworkError(format!("parameter answer to {peer}: {e}"))
+            })?;
+            match decoded {
+                Ok(parameters) => {
+                    debug!(
+                        peer,
+                        tensors = parameters.len(),
+                        "Received parameter deltas"
+                    );
+                    return Ok(parameters);
+                }
+                Err(e) if answer == PARAMS_NEED_KEYFRAME => {
+                    warn!(peer, error = %e, "Parameter deltas rejected, requesting a keyframe");
+                    self.incoming_deltas.remove(peer);
+                    buffer.clear();
+                    *progress = None;
+                }
+                Err(e) => {
+                    warn!(peer, error = %e, "Dropping parameters");
+                    self.incoming_deltas.remove(peer);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Exchange parameters with `peer` over `transport`, delta-encoded
+    ///
+    /// Both sides call this for the same `round`; the one with the smaller
+    /// peer id sends first (see [`send_parameters`](Self::send_parameters)).
+    /// Returns the parameters `peer` sent.
+    pub async fn exchange_parameters<T>(
+        &mut self,
+        parameters: &[Tensor],
+        me: &str,
+        peer: &str,
+        round: u64,
+        transport: &T,
+    ) -> DistributedResult<Vec<Tensor>>
+    where
+        T: AveragingTransport + ?Sized,
+    {
+        let mut stream = transport.connect(peer, round).await?;
+        let (mut buffer, mut progress) = (Vec::new(), None);
+        if me < peer {
+            self.send_parameters(&mut stream, peer, parameters, round)
+                .await?;
+            self.receive_parameters(&mut stream, peer, &mut buffer, &mut progress)
+                .await
+        } else {
+            let theirs = self
+                .receive_parameters(&mut stream, peer, &mut buffer, &mut progress)
+                .await?;
+            self.send_parameters(&mut stream, peer, parameters, round)
+                .await?;
+            Ok(theirs)
+        }
+    }
+
+    /// Drop the parameter delta state kept for `peer`
+    pub fn forget_peer(&mut self, peer: &str) {
+        self.outgoing_deltas.remove(peer);
+        self.incoming_deltas.remove(peer);
+    }
+
+    /// Pick averaging partners from the peers that advertised readiness
+    ///
+    /// Returns up to `group_size - 1` peers (this node fills the last slot),
+    /// best local reputation first, so flaky or slow peers are only drawn in
+    /// when nobody better is ready.
+    pub fn form_group(&self, ready_peers: &[String], reputation: &ReputationStore) -> Vec<String> {
+        let mut ranked = ready_peers.to_vec();
+        reputation.rank(&mut ranked, |p| p.as_str());
+        ranked.truncate(self.config.group_size.saturating_sub(1));
+        debug!(
+            candidates = ready_peers.len(),
+            selected = ranked.len(),
+            "Formed averaging group"
+        );
+        ranked
+    }
+
+    /// Run one averaging round through `swarm`, if one is due
+    ///
+    /// Checks the swarm's progress with [`should_average`](Self::should_average),
+    /// finds a group for the swarm's epoch with [`Matchmaker::form_group`],
+    /// then reduces `gradients` with it (see [`all_reduce`](Self::all_reduce)).
+    /// Returns `None` when no round is due or no group formed; otherwise the
+    /// caller moves on to the epoch after [`GlobalProgress::epoch`].
+    pub async fn average<D, T>(
+        &self,
+        gradients: &[Tensor],
+        swarm: &mut AveragingSwarm<'_, D, T>,
+    ) -> DistributedResult<Option<Vec<Tensor>>>
+    where
+        D: DhtOperations + ?Sized,
+        T: AveragingTransport + ?Sized,
+    {
+        let global = swarm.progress.fetch(&*swarm.dht).await?;
+        if !self.should_average(swarm.progress.local().epoch, &global) {
+            return Ok(None);
+        }
+        let matchmaker = swarm.matchmaker;
+        let Some(group) = matchmaker
+            .form_group(&mut *swarm.dht, swarm.rpc, global.epoch)
+            .await?
+        else {
+            return Ok(None);
+        };
+        self.all_reduce(gradients, matchmaker.peer_id(), &group, swarm.transport)
+            .await
+            .map(Some)
+    }
+
+    /// Average `gradients` with the other members of `group`
+    ///
+    /// `me` is this node's peer id. The group reduces as configured by
+    /// [`AllReduceStrategy`]: a ring (see [`ring_all_reduce`](Self::ring_all_reduce))
+    /// needs at least three members, so smaller groups, and the
+    /// [`Naive`](AllReduceStrategy::Naive) strategy, exchange full gradients
+    /// with every other member instead. Every member gets the same result.
+    pub async fn all_reduce<T>(
+        &self,
+        gradients: &[Tensor],
+        me: &str,
+        group: &GroupInfo,
+        transport: &T,
+    ) -> DistributedResult<Vec<Tensor>>
+    where
+        T: AveragingTransport + ?Sized,
+    {
+        let others: Vec<String> = group.members.iter().filter(|m| *m != me).cloned().collect();
+        if others.is_empty() {
+            debug!("Averaging alone, gradients unchanged");
+            return Ok(gradients.to_vec());
+        }
+        let round = group.round;
+        let reduce = async {
+            match (
+                self.config.allreduce_strategy,
+                RingPosition::of(me, &others),
+            ) {
+                (AllReduceStrategy::Ring, Some(ring)) if ring.world >= 3 => {
+                    let (mut left, mut right) = tokio::try_join!(
+                        transport.connect(&ring.left, round),
+                        transport.connect(&ring.right, round)
+                    )?;
+                    self.ring_all_reduce(
+                        gradients, ring.rank, ring.world, &mut left, &mut right, round,
+                    )
+                    .await
+                }
+                _ => {
+                    self.exchange_all(gradients, me, &group.members, transport, round)
+                        .await
+                }
+            }
+        };
+        let averaged = tokio::time::timeout(self.config.exchange_timeout, reduce)
+            .await
+            .map_err(|_| {
+                DistributedError::Timeout(self.config.exchange_timeout.as_millis() as u64)
+            })??;
+        info!(
+            group = %group.group_id,
+            members = group.members.len(),
+            strategy = ?self.config.allreduce_strategy,
+            "Averaging round completed"
+        );
+        Ok(averaged)
+    }
+
+    /// Exchange full gradients with every other member and average them
+    ///
+    /// Within a pair the member with the smaller peer id sends first. The
+    /// local gradients go through the same compression round trip as the
+    /// copies the others receive, and sets are summed in member order, so
+    /// every member computes exactly the same average.
+    async fn exchange_all<T>(
+        &self,
+        gradients: &[Tensor],
+        me: &str,
+        members: &[String],
+        transport: &T,
+        round: u64,
+    ) -> DistributedResult<Vec<Tensor>>
+    where
+        T: AveragingTransport + ?Sized,
+    {
+        let exchanges = members.iter().map(|peer| async move {
+            if peer == me {
+                return if self.config.enable_compression {
+                    self.decompress_gradients(&self.compress_gradients(gradients)?)
+                } else {
+                    Ok(gradients.to_vec())
+                };
+            }
+            let mut stream = transport.connect(peer, round).await?;
+            let theirs = if me < peer.as_str() {
+                self.send_gradients(&mut stream, gradients, round).await?;
+                self.receive_gradients(&mut stream, &mut GradientSink::default(), &mut None)
+                    .await?
+            } else {
+                let theirs = self
+                    .receive_gradients(&mut stream, &mut GradientSink::default(), &mut None)
+                    .await?;
+                self.send_gradients(&mut stream, gradients, round).await?;
+                theirs
+            };
+            let matches = theirs.len() == gradients.len()
+                && theirs
+                    .iter()
+                    .zip(gradients)
+                    .all(|(t, g)| t.dims() == g.dims());
+            if !matches {
+                return Err(DistributedError::AveragingFailed(format!(
+                    "gradients from {peer} do not match the local shapes"
+                )));
+            }
+            Ok(theirs)
+        });
+        let sets = futures::future::try_join_all(exchanges).await?;
+        self.average_gradients(&sets)
+    }
+
+    /// Average `gradients` with the rest of a ring (see [`RingPosition`])
+    ///
+    /// The gradients are flattened and cut into `world` chunks. During
+    /// reduce-scatter each member sends one chunk to its right neighbour
+    /// and adds the chunk arriving from its left, so after `world - 1`
+    /// steps every member owns one fully summed chunk; all-gather then
+    /// circulates those for another `world - 1` steps. Each chunk travels
+    /// as its own transfer, compressed like [`send_gradients`](Self::send_gradients).
+    ///
+    /// `left` and `right` are the streams to the neighbours. Every member
+    /// must call this with the same `round`, `world` and tensor shapes.
+    pub async fn ring_all_reduce<L, R>(
+        &self,
+        gradients: &[Tensor],
+        rank: usize,
+        world: usize,
+        left: &mut L,
+        right: &mut R,
+        round: u64,
+    ) -> DistributedResult<Vec<Tensor>>
+    where
+        L: AsyncRead + AsyncWrite + Unpin,
+        R: AsyncRead + AsyncWrite + Unpin,
+    {
+        if rank >= world {
+            return Err(DistributedError::AveragingFailed(format!(
+                "rank {rank} outside a ring of {world}"
+            )));
+        }
+
+        let mut flat = Vec::new();
+        for g in gradients {
+            flat.extend(g.flatten_all()?.to_dtype(DType::F32)?.to_vec1::<f32>()?);
+        }
+        let len = flat.len();
+        let chunk = |c: usize| c * len / world..(c + 1) * len / world;
+        let transfer_id = |step: usize| {
+            round
+                .wrapping_mul(2 * world as u64)
+                .wrapping_add(step as u64)
+        };
+        debug!(rank, world, values = len, round, "Starting ring all-reduce");
+
+        // Reduce-scatter: after step s, chunk (rank - s - 1) holds s + 2 contributions
+        for step in 0..world - 1 {
+            let send = chunk((rank + world - step) % world);
+            let recv = chunk((rank + world - step - 1) % world);
+            let incoming = self
+                .ring_step(&flat[send], recv.len(), left, right, transfer_id(step))
+                .await?;
+            for (acc, v) in flat[recv].iter_mut().zip(incoming) {
+                *acc += v;
+            }
+        }
+
+        // The owned chunk goes through the same compression round trip as
+        // the copies the other members receive, so the ring agrees exactly
+        let owned = chunk((rank + 1) % world);
+        if self.config.enable_compression && !owned.is_empty() {
+            let t = Tensor::from_slice(&flat[owned.clone()], owned.len(), &Device::Cpu)?;
+            let restored = self.decompress_gradients(&self.compress_gradients(&[t])?)?;
+            flat[owned].copy_from_slice(&restored[0].to_vec1::<f32>()?);
+        }
+
+        // All-gather: pass the summed chunks around the ring
+        for step in 0..world - 1 {
+            let send = chunk((rank + 1 + world - step) % world);
+            let recv = chunk((rank + world - step) % world);
+            let incoming = self
+                .ring_step(
+                    &flat[send],
+                    recv.len(),
+                    left,
+                    right,
+                    transfer_id(world - 1 + step),
+                )
+                .await?;
+            flat[recv].copy_from_slice(&incoming);
+        }
+
+        let scale = 1.0 / world as f32;
+        let mut offset = 0;
+        let averaged = gradients
+            .iter()
+            .map(|g| {
+                let n = g.elem_count();
+                let values: Vec<f32> = flat[offset..offset + n].iter().map(|v| v * scale).collect();
+                offset += n;
+                Ok(Tensor::from_vec(values, g.dims(), g.device())?.to_dtype(g.dtype())?)
+            })
+            .collect::<DistributedResult<Vec<_>>>()?;
+        info!(rank, world, values = len, "Ring all-reduce completed");
+        Ok(averaged)
+    }
+
+    /// Send one ring chunk to the right while receiving one from the left
+    ///
+    /// Both directions run concurrently so a full ring cannot deadlock on
+    /// stream buffers. Empty chunks (more members than values) are skipped
+    /// by both ends.
+    async fn ring_step<L, R>(
+        &self,
+        outgoing: &[f32],
+        incoming: usize,
+        left: &mut L,
+        right: &mut R,
+        transfer_id: u64,
+    ) -> DistributedResult<Vec<f32>>
+    where
+        L: AsyncRead + AsyncWrite + Unpin,
+        R: AsyncRead + AsyncWrite + Unpin,
+    {
+        let send = async {
+            if outgoing.is_empty() {
+                return Ok(());
+            }
+            let t = Tensor::from_slice(outgoing, outgoing.len(), &Device::Cpu)?;
+            self.send_gradients(right, &[t], transfer_id).await
+        };
+        let recv = async {
+            if incoming == 0 {
+                return Ok(Vec::new());
+            }
+            let got = self
+                .receive_gradients(left, &mut GradientSink::default(), &mut None)
+                .await?;
+            let values = match got.first() {
+                Some(t) => t.flatten_all()?.to_vec1::<f32>()?,
+                None => Vec::new(),
+            };
+            if values.len() != incoming {
+                return Err(DistributedError::AveragingFailed(format!(
+                    "ring chunk has {} values, expected {incoming}",
+                    values.len()
+                )));
+            }
+            Ok(values)
+        };
+        let ((), values) = tokio::try_join!(send, recv)?;
+        Ok(values)
+    }
+
     /// Average multiple gradient sets
     pub fn average_gradients(
         &self,
@@ -210,12 +1064,8 @@ impl ParameterAverager for DecentralizedAverager {
             return Ok(AveragingResult::NoPeersAvailable);
         }
 
-        // TODO: Actual P2P implementation would:
-        // 1. Advertise readiness in DHT
-        // 2. Find other ready peers
-        // 3. Form averaging group
-        // 4. Exchange compressed gradients
-        // 5. Average and apply
+        // Averaging with peers goes through `average`, which needs the
+        // group and a transport to it; this step only averages locally.
 
         // For now, just return success with local-only averaging
         if self.accumulation_count > 0 {
@@ -249,7 +1099,10 @@ impl ParameterAverager for DecentralizedAverager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use candle_core::Device;
+    use crate::matchmaking::MatchmakingConfig;
+    use crate::progress::ProgressConfig;
+    use crate::testing::{LocalRpc, MemoryDht, MemoryTransport};
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn test_averaging() {
@@ -289,6 +1142,48 @@ mod tests {
         assert!(matches!(result, AveragingResult::NoPeersAvailable));
     }
 
+    #[test]
+    fn test_form_group_prefers_reputable_peers() {
+        use kwaai_p2p::PeerObservation;
+
+        let averager = DecentralizedAverager::new(AveragingConfig {
+            group_size: 3,
+            ..AveragingConfig::default()
+        });
+        let mut reputation = ReputationStore::default();
+        for i in 0..10 {
+            let obs = |latency_ms, success| PeerObservation {
+                timestamp_secs: i,
+                latency_ms,
+                success,
+                observed_tps: None,
+                claimed_tps: None,
+            };
+            reputation.observe("fast", "", obs(20.0, true));
+            reputation.observe("failing", "", obs(900.0, false));
+        }
+
+        let ready = ["failing", "newcomer", "fast"].map(String::from);
+        let group = averager.form_group(&ready, &reputation);
+        assert_eq!(group, ["fast", "newcomer"]);
+    }
+
+    #[test]
+    fn test_should_average_follows_swarm_progress() {
+        let averager = DecentralizedAverager::new(AveragingConfig::default());
+        let global = |epoch, samples_accumulated| GlobalProgress {
+            epoch,
+            samples_accumulated,
+            target_batch_size: 100,
+            num_peers: 3,
+            eta_next_epoch: None,
+        };
+        assert!(!averager.should_average(2, &global(2, 60)));
+        assert!(averager.should_average(2, &global(2, 100)));
+        // Behind the swarm: catch up without waiting for a full batch
+        assert!(averager.should_average(1, &global(2, 0)));
+    }
+
     #[test]
     fn test_clear_resets_state() {
         let mut averager = DecentralizedAverager::new(AveragingConfig::default());
@@ -319,7 +1214,9 @@ mod tests {
             &Device::Cpu,
         )
         .unwrap();
-        let compressed = averager.compress_gradients(&[g.clone()]).unwrap();
+        let compressed = averager
+            .compress_gradients(std::slice::from_ref(&g))
+            .unwrap();
         let recovered = averager.decompress_gradients(&compressed).unwrap();
         let orig: Vec<f32> = g.to_vec1().unwrap();
         let got: Vec<f32> = recovered[0].to_vec1().unwrap();
@@ -328,6 +1225,482 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_gradient_exchange_over_stream() {
+        let config = AveragingConfig {
+            transfer: TransferConfig {
+                chunk_size: 64,
+                window: 2,
+                ..TransferConfig::default()
+            },
+            ..AveragingConfig::default()
+        };
+        let sender = DecentralizedAverager::new(config.clone());
+        let receiver = DecentralizedAverager::new(config);
+        let g = Tensor::from_vec(
+            (0..256).map(|i| i as f32 * 0.01).collect::<Vec<_>>(),
+            &[16, 16],
+            &Device::Cpu,
+        )
+        .unwrap();
+
+        let (mut a, mut b) = tokio::io::duplex(256);
+        let sent = g.clone();
+        let task = tokio::spawn(async move { sender.send_gradients(&mut a, &[sent], 1).await });
+        let mut progress = None;
+        let got = receiver
+            .receive_gradients(&mut b, &mut GradientSink::default(), &mut progress)
+            .await
+            .unwrap();
+        task.await.unwrap().unwrap();
+
+        assert!(progress.unwrap().is_complete());
+        assert_eq!(got[0].dims(), &[16, 16]);
+        let orig: Vec<f32> = g.flatten_all().unwrap().to_vec1().unwrap();
+        let recv: Vec<f32> = got[0].flatten_all().unwrap().to_vec1().unwrap();
+        for (o, r) in orig.iter().zip(recv.iter()) {
+            assert!((o - r).abs() < 0.05, "orig={o} recovered={r}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gradients_stream_one_tensor_at_a_time() {
+        let config = AveragingConfig {
+            enable_compression: false,
+            transfer: TransferConfig {
+                chunk_size: 50,
+                window: 2,
+                ..TransferConfig::default()
+            },
+            ..AveragingConfig::default()
+        };
+        let averager = DecentralizedAverager::new(config);
+        let grads = vec![
+            Tensor::arange(0f32, 30., &Device::Cpu).unwrap(),
+            Tensor::ones(&[4, 5], DType::F32, &Device::Cpu).unwrap(),
+            Tensor::zeros(&[1], DType::F32, &Device::Cpu).unwrap(),
+        ];
+
+        // Records straddle chunk boundaries; each is decoded once complete
+        let mut source = GradientSource::new(&averager, &grads).unwrap();
+        let total = source.total_len().unwrap();
+        let mut sink = GradientSink::default();
+        sink.prepare(total).unwrap();
+        let mut offset = 0;
+        while offset < total {
+            let mut chunk = vec![0u8; (total - offset).min(50) as usize];
+            source.read_at(offset, &mut chunk).unwrap();
+            sink.write_at(offset, &chunk).unwrap();
+            offset += chunk.len() as u64;
+            assert!(sink.record.len() < source.offsets[1] as usize + 50);
+        }
+        assert_eq!(sink.tensors.len(), 3);
+
+        let (mut a, mut b) = tokio::io::duplex(128);
+        let (mut sink, mut progress) = (GradientSink::default(), None);
+        let (sent, got) = tokio::join!(
+            averager.send_gradients(&mut a, &grads, 2),
+            averager.receive_gradients(&mut b, &mut sink, &mut progress)
+        );
+        sent.unwrap();
+        let got = got.unwrap();
+        for (g, want) in got.iter().zip(&grads) {
+            assert_eq!(g.dims(), want.dims());
+            let g: Vec<f32> = g.flatten_all().unwrap().to_vec1().unwrap();
+            let want: Vec<f32> = want.flatten_all().unwrap().to_vec1().unwrap();
+            assert_eq!(g, want);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parameter_sync_sends_only_changes() {
+        let mut sender = DecentralizedAverager::new(AveragingConfig::default());
+        let mut receiver = DecentralizedAverager::new(AveragingConfig::default());
+        let mut values: Vec<f32> = (0..1024).map(|i| (i as f32 * 0.37).sin()).collect();
+
+        let mut payload_sizes = Vec::new();
+        for round in 0..2 {
+            let params = Tensor::from_vec(values.clone(), &[32, 32], &Device::Cpu).unwrap();
+            let (mut a, mut b) = tokio::io::duplex(4096);
+            let (mut buffer, mut progress) = (Vec::new(), None);
+            let (sent, got) = tokio::join!(
+                sender.send_parameters(&mut a, "receiver", std::slice::from_ref(&params), round),
+                receiver.receive_parameters(&mut b, "sender", &mut buffer, &mut progress)
+            );
+            sent.unwrap();
+            let got: Vec<f32> = got.unwrap()[0].flatten_all().unwrap().to_vec1().unwrap();
+            for (v, g) in values.iter().zip(&got) {
+                assert!((v - g).abs() < 0.02, "want={v} got={g}");
+            }
+            payload_sizes.push(progress.unwrap().total_len);
+
+            // Only one block changes before the next round
+            values[100] += 0.5;
+        }
+        assert!(
+            payload_sizes[1] * 5 < payload_sizes[0],
+            "delta {} vs keyframe {}",
+            payload_sizes[1],
+            payload_sizes[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_delta_requests_a_keyframe() {
+        let mut sender = DecentralizedAverager::new(AveragingConfig::default());
+        let mut receiver = DecentralizedAverager::new(AveragingConfig::default());
+        let transport = MemoryTransport::default();
+        let values: Vec<f32> = (0..256).map(|i| i as f32 * 0.01).collect();
+
+        let mut versions = Vec::new();
+        for round in 0..3 {
+            let params = Tensor::from_vec(values.clone(), 256, &Device::Cpu).unwrap();
+            let (a, b) = (transport.for_peer("a"), transport.for_peer("b"));
+            let (mine, theirs) = tokio::join!(
+                sender.exchange_parameters(std::slice::from_ref(&params), "a", "b", round, &a),
+                receiver.exchange_parameters(std::slice::from_ref(&params), "b", "a", round, &b)
+            );
+            let got: Vec<f32> = theirs.unwrap()[0].to_vec1().unwrap();
+            for (v, g) in values.iter().zip(&got) {
+                assert!((v - g).abs() < 0.02, "round {round}: want={v} got={g}");
+            }
+            mine.unwrap();
+            versions.push(receiver.incoming_deltas["a"].version(0));
+            // The receiver restarts once and loses its copy
+            if round == 1 {
+                receiver.forget_peer("a");
+            }
+        }
+        // Round 2 started from a keyframe again
+        assert_eq!(versions, vec![Some(1), Some(2), Some(1)]);
+    }
+
+    /// Run a ring all-reduce over in-memory pipes, one task per member
+    async fn ring_average(
+        world: usize,
+        sets: Vec<Vec<Tensor>>,
+        compress: bool,
+    ) -> Vec<Vec<Tensor>> {
+        let config = AveragingConfig {
+            enable_compression: compress,
+            allreduce_strategy: AllReduceStrategy::Ring,
+            transfer: TransferConfig {
+                chunk_size: 64,
+                window: 2,
+                ..TransferConfig::default()
+            },
+            ..AveragingConfig::default()
+        };
+        // Pipe r links member r (right end) to member r + 1 (left end)
+        let (mut rights, mut lefts): (Vec<_>, Vec<_>) =
+            (0..world).map(|_| tokio::io::duplex(128)).unzip();
+        lefts.rotate_right(1);
+        let tasks: Vec<_> = sets
+            .into_iter()
+            .enumerate()
+            .map(|(rank, grads)| {
+                let averager = DecentralizedAverager::new(config.clone());
+                let mut right = rights.remove(0);
+                let mut left = lefts.remove(0);
+                tokio::spawn(async move {
+                    averager
+                        .ring_all_reduce(&grads, rank, world, &mut left, &mut right, 7)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        results
+    }
+
+    fn member_gradients(rank: usize) -> Vec<Tensor> {
+        let a: Vec<f32> = (0..150).map(|i| (i * (rank + 1)) as f32 * 0.01).collect();
+        vec![
+            Tensor::from_vec(a, &[10, 15], &Device::Cpu).unwrap(),
+            Tensor::from_vec(vec![rank as f32; 7], &[7], &Device::Cpu).unwrap(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_ring_all_reduce_matches_naive_average() {
+        let world = 4;
+        let sets: Vec<_> = (0..world).map(member_gradients).collect();
+        let averager = DecentralizedAverager::new(AveragingConfig::default());
+        let expected = averager.average_gradients(&sets).unwrap();
+
+        for (compress, tolerance) in [(false, 1e-5), (true, 0.05)] {
+            let results = ring_average(world, sets.clone(), compress).await;
+            for result in &results {
+                for (got, want) in result.iter().zip(&expected) {
+                    assert_eq!(got.dims(), want.dims());
+                    let got: Vec<f32> = got.flatten_all().unwrap().to_vec1().unwrap();
+                    let want: Vec<f32> = want.flatten_all().unwrap().to_vec1().unwrap();
+                    for (g, w) in got.iter().zip(&want) {
+                        assert!((g - w).abs() < tolerance, "compress={compress} {g} vs {w}");
+                    }
+                }
+            }
+            // Every member ends up with the same parameters
+            let first: Vec<f32> = results[0][0].flatten_all().unwrap().to_vec1().unwrap();
+            for result in &results[1..] {
+                let other: Vec<f32> = result[0].flatten_all().unwrap().to_vec1().unwrap();
+                assert_eq!(first, other);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ring_all_reduce_with_more_members_than_values() {
+        let world = 3;
+        let sets: Vec<_> = (0..world)
+            .map(|r| vec![Tensor::from_vec(vec![r as f32 * 3.0, 1.0], &[2], &Device::Cpu).unwrap()])
+            .collect();
+        for result in ring_average(world, sets, false).await {
+            let vals: Vec<f32> = result[0].to_vec1().unwrap();
+            assert!((vals[0] - 3.0).abs() < 1e-5);
+            assert!((vals[1] - 1.0).abs() < 1e-5);
+        }
+    }
+
+    /// Run `average` for every member of one group, one task each
+    async fn group_average(
+        strategy: AllReduceStrategy,
+        sets: Vec<Vec<Tensor>>,
+    ) -> Vec<Vec<Tensor>> {
+        let config = AveragingConfig {
+            allreduce_strategy: strategy,
+            transfer: TransferConfig {
+                chunk_size: 64,
+                window: 2,
+                ..TransferConfig::default()
+            },
+            ..AveragingConfig::default()
+        };
+        let members: Vec<String> = (0..sets.len()).map(|i| format!("peer-{i}")).collect();
+        let group = GroupInfo {
+            group_id: "peer-0:5".to_string(),
+            round: 5,
+            members: members.clone(),
+        };
+        let transport = MemoryTransport::default();
+        let tasks: Vec<_> = sets
+            .into_iter()
+            .zip(members)
+            .map(|(grads, me)| {
+                let averager = DecentralizedAverager::new(config.clone());
+                let transport = transport.for_peer(&me);
+                let group = group.clone();
+                tokio::spawn(async move {
+                    averager
+                        .all_reduce(&grads, &me, &group, &transport)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn test_average_reduces_with_the_configured_strategy() {
+        for world in [2, 4] {
+            let sets: Vec<_> = (0..world).map(member_gradients).collect();
+            let expected = DecentralizedAverager::new(AveragingConfig::default())
+                .average_gradients(&sets)
+                .unwrap();
+            for strategy in [AllReduceStrategy::Naive, AllReduceStrategy::Ring] {
+                let results = group_average(strategy, sets.clone()).await;
+                for result in &results {
+                    for (got, want) in result.iter().zip(&expected) {
+                        assert_eq!(got.dims(), want.dims());
+                        let got: Vec<f32> = got.flatten_all().unwrap().to_vec1().unwrap();
+                        let want: Vec<f32> = want.flatten_all().unwrap().to_vec1().unwrap();
+                        for (g, w) in got.iter().zip(&want) {
+                            assert!((g - w).abs() < 0.05, "{strategy:?}/{world}: {g} vs {w}");
+                        }
+                    }
+                }
+                let first: Vec<f32> = results[0][0].flatten_all().unwrap().to_vec1().unwrap();
+                for result in &results[1..] {
+                    let other: Vec<f32> = result[0].flatten_all().unwrap().to_vec1().unwrap();
+                    assert_eq!(first, other, "{strategy:?}/{world}: members disagree");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_average_forms_a_group_then_reduces() {
+        let config = AveragingConfig {
+            group_size: 3,
+            allreduce_strategy: AllReduceStrategy::Ring,
+            ..AveragingConfig::default()
+        };
+        let matchmaking = MatchmakingConfig {
+            poll_interval: Duration::from_millis(10),
+            ..MatchmakingConfig::for_averaging("test", &config)
+        };
+        let (dht, rpc, transport) = (
+            MemoryDht::default(),
+            Arc::new(LocalRpc::default()),
+            MemoryTransport::default(),
+        );
+        let progress = ProgressConfig {
+            prefix: "test".to_string(),
+            target_batch_size: 30,
+            ..ProgressConfig::default()
+        };
+        // Together the members reach the target batch; none does alone
+        let mut members = Vec::new();
+        for _ in 0..3 {
+            let peer = PeerId::random();
+            let mut tracker = ProgressTracker::new(peer.to_string(), 7, progress.clone());
+            tracker.report_samples(10);
+            tracker.publish(&mut dht.for_peer(peer)).await.unwrap();
+            members.push((peer, tracker));
+        }
+        let tasks: Vec<_> = members
+            .into_iter()
+            .enumerate()
+            .map(|(i, (peer, tracker))| {
+                let matchmaker = Arc::new(Matchmaker::new(peer.to_string(), matchmaking.clone()));
+                rpc.add(matchmaker.clone());
+                let (mut dht, rpc) = (dht.for_peer(peer), rpc.clone());
+                let transport = transport.for_peer(&peer.to_string());
+                let averager = DecentralizedAverager::new(config.clone());
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(5 * i as u64)).await;
+                    let mut swarm = AveragingSwarm {
+                        progress: &tracker,
+                        matchmaker: &matchmaker,
+                        dht: &mut dht,
+                        rpc: rpc.as_ref(),
+                        transport: &transport,
+                    };
+                    averager
+                        .average(&member_gradients(i), &mut swarm)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap().expect("group formed"));
+        }
+        let first: Vec<f32> = results[0][0].flatten_all().unwrap().to_vec1().unwrap();
+        for result in &results[1..] {
+            let other: Vec<f32> = result[0].flatten_all().unwrap().to_vec1().unwrap();
+            assert_eq!(first, other);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_average_waits_until_a_round_is_due() {
+        let averager = DecentralizedAverager::new(AveragingConfig::default());
+        let peer = PeerId::random();
+        let mut dht = MemoryDht::default().for_peer(peer);
+        let mut tracker = ProgressTracker::new(peer.to_string(), 0, ProgressConfig::default());
+        tracker.report_samples(1);
+        let matchmaker = Matchmaker::new(peer.to_string(), MatchmakingConfig::default());
+        let mut swarm = AveragingSwarm {
+            progress: &tracker,
+            matchmaker: &matchmaker,
+            dht: &mut dht,
+            rpc: &LocalRpc::default(),
+            transport: &MemoryTransport::default(),
+        };
+        let result = averager
+            .average(&member_gradients(0), &mut swarm)
+            .await
+            .unwrap();
+        assert!(result.is_none());
+        // Not due, so no readiness was announced
+        assert!(swarm
+            .dht
+            .get_providers(&matchmaker.group_key())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_all_reduce_over_encrypted_transport() {
+        let averager = DecentralizedAverager::new(AveragingConfig::default());
+        let keys: Vec<Keypair> = (0..2).map(|_| Keypair::generate_ed25519()).collect();
+        let members: Vec<String> = keys
+            .iter()
+            .map(|k| k.public().to_peer_id().to_string())
+            .collect();
+        let group = GroupInfo {
+            group_id: format!("{}:1", members[0]),
+            round: 1,
+            members: members.clone(),
+        };
+        let pipes = MemoryTransport::default();
+        let transports: Vec<_> = keys
+            .into_iter()
+            .zip(&members)
+            .map(|(key, me)| EncryptedTransport::new(pipes.for_peer(me), key))
+            .collect();
+        let sets = [member_gradients(0), member_gradients(1)];
+        let (a, b) = tokio::join!(
+            averager.all_reduce(&sets[0], &members[0], &group, &transports[0]),
+            averager.all_reduce(&sets[1], &members[1], &group, &transports[1])
+        );
+        let expected = averager.average_gradients(&sets).unwrap();
+        for result in [a.unwrap(), b.unwrap()] {
+            let got: Vec<f32> = result[1].to_vec1().unwrap();
+            let want: Vec<f32> = expected[1].to_vec1().unwrap();
+            for (g, w) in got.iter().zip(&want) {
+                assert!((g - w).abs() < 0.05, "{g} vs {w}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_average_alone_keeps_gradients() {
+        let averager = DecentralizedAverager::new(AveragingConfig::default());
+        let grads = member_gradients(1);
+        let group = GroupInfo {
+            group_id: "me:0".to_string(),
+            round: 0,
+            members: vec!["me".to_string()],
+        };
+        let got = averager
+            .all_reduce(&grads, "me", &group, &MemoryTransport::default())
+            .await
+            .unwrap();
+        let want: Vec<f32> = grads[1].to_vec1().unwrap();
+        assert_eq!(got[1].to_vec1::<f32>().unwrap(), want);
+    }
+
+    #[test]
+    fn test_ring_position_is_agreed_by_all_members() {
+        let peers = ["carol", "alice", "bob"].map(String::from);
+        let pos = |me: &str| {
+            let others: Vec<String> = peers.iter().filter(|p| *p != me).cloned().collect();
+            RingPosition::of(me, &others).unwrap()
+        };
+        let alice = pos("alice");
+        assert_eq!((alice.rank, alice.world), (0, 3));
+        assert_eq!(
+            (alice.left.as_str(), alice.right.as_str()),
+            ("carol", "bob")
+        );
+        assert_eq!(pos("bob").left, "alice");
+        assert_eq!(pos("carol").right, "alice");
+        assert!(RingPosition::of("alice", &[]).is_none());
+    }
+
     #[test]
     fn test_average_gradients_two_sets() {
         let averager = DecentralizedAverager::new(AveragingConfig::default());
@@ -0,0 +1,319 @@
+//! Checkpointing for distributed training state
+//!
+//! A contributor that crashes mid-training restarts from its newest
+//! snapshot instead of from scratch. Each snapshot is one safetensors file,
+//! `{dir}/checkpoint-{round}.safetensors`, holding:
+//!
+//! ```text
+//! param.{i}       averaged parameters, in order
+//! optim.{name}    optimizer state (moments, step sizes, ...)
+//! meta.round      averaging rounds completed (i64 scalar)
+//! meta.step       local optimizer steps taken (i64 scalar)
+//! ```
+//!
+//! Files are written under a temporary name and renamed into place, so a
+//! crash while saving never leaves a truncated newest checkpoint.
+
+use crate::error::{DistributedError, DistributedResult};
+use candle_core::{Device, Tensor};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+const PARAM_PREFIX: &str = "param.";
+const OPTIM_PREFIX: &str = "optim.";
+const META_ROUND: &str = "meta.round";
+const META_STEP: &str = "meta.step";
+
+/// Training state captured by a checkpoint
+#[derive(Debug, Clone, Default)]
+pub struct TrainingState {
+    /// Averaged parameters, in model order
+    pub parameters: Vec<Tensor>,
+    /// Optimizer state by name
+    pub optimizer: BTreeMap<String, Tensor>,
+    /// Averaging rounds completed
+    pub round: u64,
+    /// Local optimizer steps taken
+    pub step: u64,
+}
+
+/// Configuration for periodic checkpoints
+#[derive(Debug, Clone)]
+pub struct CheckpointConfig {
+    /// Directory checkpoints are written to
+    pub dir: PathBuf,
+    /// Save once this many rounds have passed since the last checkpoint
+    pub every_rounds: u64,
+    /// Number of checkpoints to keep (oldest are deleted)
+    pub keep: usize,
+}
+
+impl CheckpointConfig {
+    /// Checkpoint into `dir` with default cadence and retention
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            every_rounds: 10,
+            keep: 3,
+        }
+    }
+}
+
+/// Writes, prunes and restores training checkpoints
+#[derive(Debug)]
+pub struct CheckpointManager {
+    config: CheckpointConfig,
+    last_saved_round: Option<u64>,
+}
+
+impl CheckpointManager {
+    /// Create a manager (the directory is created on first save)
+    pub fn new(config: CheckpointConfig) -> Self {
+        Self {
+            config,
+            last_saved_round: None,
+        }
+    }
+
+    /// Directory checkpoints are written to
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+
+    /// Save `state` if `every_rounds` have passed since the last checkpoint
+    pub fn maybe_save(&mut self, state: &TrainingState) -> DistributedResult<Option<PathBuf>> {
+        let due = match self.last_saved_round {
+            Some(last) => state.round >= last + self.config.every_rounds.max(1),
+            None => state.round > 0,
+        };
+        if due {
+            self.save(state).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Save `state` now, then prune old checkpoints
+    pub fn save(&mut self, state: &TrainingState) -> DistributedResult<PathBuf> {
+        std::fs::create_dir_all(&self.config.dir)
+            .map_err(|e| io_err("create", &self.config.dir, e))?;
+
+        let mut tensors: HashMap<String, Tensor> = HashMap::new();
+        for (i, p) in state.parameters.iter().enumerate() {
+            tensors.insert(format!("{PARAM_PREFIX}{i}"), p.clone());
+        }
+        for (name, t) in &state.optimizer {
+            tensors.insert(format!("{OPTIM_PREFIX}{name}"), t.clone());
+        }
+        tensors.insert(
+            META_ROUND.to_string(),
+            Tensor::new(state.round as i64, &Device::Cpu)?,
+        );
+        tensors.insert(
+            META_STEP.to_string(),
+            Tensor::new(state.step as i64, &Device::Cpu)?,
+        );
+
+        let path = self.path_for(state.round);
+        let tmp = path.with_extension("safetensors.tmp");
+        candle_core::safetensors::save(&tensors, &tmp)?;
+        std::fs::rename(&tmp, &path).map_err(|e| io_err("rename", &tmp, e))?;
+        self.last_saved_round = Some(state.round);
+        info!(
+            round = state.round,
+            params = state.parameters.len(),
+            path = %path.display(),
+            "Saved training checkpoint"
+        );
+
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Load the newest readable checkpoint onto `device`
+    ///
+    /// Unreadable checkpoints are skipped with a warning, falling back to
+    /// older ones. Returns `None` when there is nothing to resume from.
+    pub fn restore(&mut self, device: &Device) -> DistributedResult<Option<TrainingState>> {
+        for (round, path) in self.list()?.into_iter().rev() {
+            match load(&path, device) {
+                Ok(state) => {
+                    self.last_saved_round = Some(state.round);
+                    info!(
+                        round,
+                        step = state.step,
+                        path = %path.display(),
+                        "Restored training checkpoint"
+                    );
+                    return Ok(Some(state));
+                }
+                Err(e) => warn!(path = %path.display(), "Skipping unreadable checkpoint: {e}"),
+            }
+        }
+        debug!(dir = %self.config.dir.display(), "No checkpoint to restore");
+        Ok(None)
+    }
+
+    /// Checkpoints on disk as `(round, path)`, oldest first
+    pub fn list(&self) -> DistributedResult<Vec<(u64, PathBuf)>> {
+        let entries = match std::fs::read_dir(&self.config.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_err("read", &self.config.dir, e)),
+        };
+        let mut found: Vec<(u64, PathBuf)> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let round = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix("checkpoint-")?
+                    .strip_suffix(".safetensors")?
+                    .parse()
+                    .ok()?;
+                Some((round, path))
+            })
+            .collect();
+        found.sort();
+        Ok(found)
+    }
+
+    fn prune(&self) -> DistributedResult<()> {
+        let found = self.list()?;
+        let excess = found.len().saturating_sub(self.config.keep.max(1));
+        for (round, path) in &found[..excess] {
+            std::fs::remove_file(path).map_err(|e| io_err("remove", path, e))?;
+            debug!(round, "Pruned old checkpoint");
+        }
+        Ok(())
+    }
+
+    fn path_for(&self, round: u64) -> PathBuf {
+        self.config
+            .dir
+            .join(format!("checkpoint-{round:010}.safetensors"))
+    }
+}
+
+fn load(path: &Path, device: &Device) -> DistributedResult<TrainingState> {
+    let tensors = candle_core::safetensors::load(path, device)?;
+    let counter = |name: &str| -> DistributedResult<u64> {
+        let t = tensors
+            .get(name)
+            .ok_or_else(|| DistributedError::Internal(format!("checkpoint has no {name}")))?;
+        Ok(t.to_scalar::<i64>()? as u64)
+    };
+    let mut state = TrainingState {
+        round: counter(META_ROUND)?,
+        step: counter(META_STEP)?,
+        ..TrainingState::default()
+    };
+
+    let mut params = BTreeMap::new();
+    for (name, t) in tensors {
+        if let Some(i) = name.strip_prefix(PARAM_PREFIX) {
+            let i: usize = i
+                .parse()
+                .map_err(|_| DistributedError::Internal(format!("bad parameter name {name}")))?;
+            params.insert(i, t);
+        } else if let Some(key) = name.strip_prefix(OPTIM_PREFIX) {
+            state.optimizer.insert(key.to_string(), t);
+        }
+    }
+    if params.keys().copied().ne(0..params.len()) {
+        return Err(DistributedError::Internal(
+            "checkpoint parameters are not contiguous".to_string(),
+        ));
+    }
+    state.parameters = params.into_values().collect();
+    Ok(state)
+}
+
+fn io_err(action: &str, path: &Path, e: std::io::Error) -> DistributedError {
+    DistributedError::Internal(format!("checkpoint {action} {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::DType;
+
+    fn state(round: u64) -> TrainingState {
+        let mut optimizer = BTreeMap::new();
+        optimizer.insert(
+            "exp_avg.0".to_string(),
+            Tensor::new(&[0.5f32, -0.5], &Device::Cpu).unwrap(),
+        );
+        TrainingState {
+            parameters: (0..12)
+                .map(|i| Tensor::new(&[i as f32, round as f32], &Device::Cpu).unwrap())
+                .chain([Tensor::ones((2, 2), DType::BF16, &Device::Cpu).unwrap()])
+                .collect(),
+            optimizer,
+            round,
+            step: round * 4,
+        }
+    }
+
+    #[test]
+    fn restore_returns_saved_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = CheckpointManager::new(CheckpointConfig::new(dir.path()));
+        manager.save(&state(7)).unwrap();
+
+        let mut fresh = CheckpointManager::new(CheckpointConfig::new(dir.path()));
+        let restored = fresh.restore(&Device::Cpu).unwrap().unwrap();
+        assert_eq!((restored.round, restored.step), (7, 28));
+        assert_eq!(restored.parameters.len(), 13);
+        assert_eq!(
+            restored.parameters[11].to_vec1::<f32>().unwrap(),
+            [11.0, 7.0]
+        );
+        assert_eq!(restored.parameters[12].dtype(), DType::BF16);
+        assert_eq!(
+            restored.optimizer["exp_avg.0"].to_vec1::<f32>().unwrap(),
+            [0.5, -0.5]
+        );
+    }
+
+    #[test]
+    fn saves_periodically_and_keeps_the_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = CheckpointManager::new(CheckpointConfig {
+            every_rounds: 2,
+            keep: 2,
+            ..CheckpointConfig::new(dir.path())
+        });
+        let saved: Vec<u64> = (0..=7)
+            .filter(|&r| manager.maybe_save(&state(r)).unwrap().is_some())
+            .collect();
+        assert_eq!(saved, [1, 3, 5, 7]);
+        let rounds: Vec<u64> = manager
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|(r, _)| r)
+            .collect();
+        assert_eq!(rounds, [5, 7]);
+    }
+
+    #[test]
+    fn corrupt_newest_falls_back_to_older() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = CheckpointManager::new(CheckpointConfig::new(dir.path()));
+        manager.save(&state(1)).unwrap();
+        let newest = manager.save(&state(2)).unwrap();
+        std::fs::write(&newest, b"not a checkpoint").unwrap();
+
+        let restored = manager.restore(&Device::Cpu).unwrap().unwrap();
+        assert_eq!(restored.round, 1);
+    }
+
+    #[test]
+    fn restore_without_checkpoints_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = CheckpointManager::new(CheckpointConfig::new(dir.path().join("missing")));
+        assert!(manager.restore(&Device::Cpu).unwrap().is_none());
+    }
+}
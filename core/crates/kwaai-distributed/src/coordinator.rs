@@ -1,9 +1,13 @@
 //! Distributed operations coordinator
 
 use crate::averaging::{AveragingConfig, DecentralizedAverager};
+use crate::checkpoint::{CheckpointManager, TrainingState};
 use crate::error::DistributedResult;
 use crate::moe::DistributedMoE;
 use crate::DistributedConfig;
+use candle_core::Device;
+use kwaai_p2p::DhtBackend;
+use std::path::PathBuf;
 use tracing::{debug, info};
 
 /// Coordinator for all distributed ML operations
@@ -14,6 +18,12 @@ pub struct DistributedCoordinator {
     moe: Option<DistributedMoE>,
     /// Parameter averager (if enabled)
     averager: Option<DecentralizedAverager>,
+    /// Checkpoint writer (if configured)
+    checkpoints: Option<CheckpointManager>,
+    /// State restored from the newest checkpoint, until taken
+    restored: Option<TrainingState>,
+    /// DHT used for matchmaking and progress (if attached)
+    dht: Option<Box<dyn DhtBackend>>,
     /// Whether coordinator is running
     is_running: bool,
 }
@@ -25,6 +35,9 @@ impl DistributedCoordinator {
             config,
             moe: None,
             averager: None,
+            checkpoints: None,
+            restored: None,
+            dht: None,
             is_running: false,
         }
     }
@@ -39,15 +52,23 @@ impl DistributedCoordinator {
         if self.config.enable_averaging {
             let averaging_config = AveragingConfig {
                 group_size: self.config.averaging_group_size,
+                allreduce_strategy: self.config.allreduce_strategy,
                 ..Default::default()
             };
             self.averager = Some(DecentralizedAverager::new(averaging_config));
             debug!(
                 group_size = self.config.averaging_group_size,
+                strategy = ?self.config.allreduce_strategy,
                 "Parameter averager initialized"
             );
         }
 
+        if let Some(config) = &self.config.checkpoint {
+            let mut checkpoints = CheckpointManager::new(config.clone());
+            self.restored = checkpoints.restore(&Device::Cpu)?;
+            self.checkpoints = Some(checkpoints);
+        }
+
         // MoE initialization would require router weights
         // Left as None for now, to be initialized when model is loaded
 
@@ -81,6 +102,32 @@ impl DistributedCoordinator {
         self.averager.as_mut()
     }
 
+    /// Attach the node's DHT backend, whichever kind it is
+    pub fn attach_dht(&mut self, dht: Box<dyn DhtBackend>) {
+        debug!(kind = ?dht.kind(), "DistributedCoordinator using DHT");
+        self.dht = Some(dht);
+    }
+
+    /// Get the attached DHT mutably
+    pub fn dht_mut(&mut self) -> Option<&mut (dyn DhtBackend + 'static)> {
+        self.dht.as_deref_mut()
+    }
+
+    /// Take the training state restored during [`initialize`](Self::initialize)
+    pub fn take_restored_state(&mut self) -> Option<TrainingState> {
+        self.restored.take()
+    }
+
+    /// Checkpoint `state` if one is due
+    ///
+    /// Call after every averaging round; returns the file written, if any.
+    pub fn checkpoint(&mut self, state: &TrainingState) -> DistributedResult<Option<PathBuf>> {
+        match self.checkpoints.as_mut() {
+            Some(checkpoints) => checkpoints.maybe_save(state),
+            None => Ok(None),
+        }
+    }
+
     /// Check if coordinator is running
     pub fn is_running(&self) -> bool {
         self.is_running
@@ -102,6 +149,9 @@ impl Default for DistributedCoordinator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::averaging::AllReduceStrategy;
+    use crate::testing::MemoryDht;
+    use kwaai_p2p::DhtOperations;
 
     fn disabled_config() -> DistributedConfig {
         DistributedConfig {
@@ -151,6 +201,33 @@ mod tests {
         assert!(coord.moe().is_none());
     }
 
+    #[test]
+    fn test_allreduce_strategy_reaches_averager() {
+        let cfg = DistributedConfig {
+            allreduce_strategy: AllReduceStrategy::Ring,
+            ..DistributedConfig::default()
+        };
+        let mut coord = DistributedCoordinator::new(cfg);
+        coord.initialize().unwrap();
+        let averager = coord.averager().unwrap();
+        assert_eq!(averager.allreduce_strategy(), AllReduceStrategy::Ring);
+    }
+
+    #[tokio::test]
+    async fn test_attached_dht_is_used() {
+        let dht = MemoryDht::default();
+        let mut coord = DistributedCoordinator::default();
+        assert!(coord.dht_mut().is_none());
+        coord.attach_dht(Box::new(dht.clone()));
+        coord
+            .dht_mut()
+            .unwrap()
+            .put("round", b"1".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(dht.get("round").await.unwrap(), Some(b"1".to_vec()));
+    }
+
     #[test]
     fn test_averaging_disabled_no_averager() {
         let mut coord = DistributedCoordinator::new(disabled_config());
@@ -169,6 +246,37 @@ mod tests {
         assert!(coord.is_enabled());
     }
 
+    #[test]
+    fn test_restart_resumes_from_checkpoint() {
+        use crate::checkpoint::CheckpointConfig;
+        use candle_core::Tensor;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = DistributedConfig {
+            checkpoint: Some(CheckpointConfig {
+                every_rounds: 1,
+                ..CheckpointConfig::new(dir.path())
+            }),
+            ..DistributedConfig::default()
+        };
+        let mut coord = DistributedCoordinator::new(cfg.clone());
+        coord.initialize().unwrap();
+        assert!(coord.take_restored_state().is_none());
+        let state = TrainingState {
+            parameters: vec![Tensor::new(&[1.0f32, 2.0], &Device::Cpu).unwrap()],
+            round: 3,
+            ..TrainingState::default()
+        };
+        assert!(coord.checkpoint(&state).unwrap().is_some());
+
+        // Crash and restart
+        let mut coord = DistributedCoordinator::new(cfg);
+        coord.initialize().unwrap();
+        let restored = coord.take_restored_state().unwrap();
+        assert_eq!(restored.round, 3);
+        assert_eq!(restored.parameters[0].to_vec1::<f32>().unwrap(), [1.0, 2.0]);
+    }
+
     #[test]
     fn test_default_coordinator() {
         let coord = DistributedCoordinator::default();
@@ -48,6 +48,10 @@ pub enum DistributedError {
     #[error("Tensor error: {0}")]
     TensorError(String),
 
+    /// Not enough attention cache left for a session
+    #[error("Attention cache full: {requested} tokens requested, {available} available")]
+    CacheFull { requested: u64, available: u64 },
+
     /// Internal error
     #[error("Internal error: {0}")]
     Internal(String),
@@ -8,7 +8,7 @@ use std::collections::HashMap;
 use tracing::{debug, info, warn};
 
 /// Unique identifier for an expert
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ExpertId(pub u64);
 
 impl ExpertId {
@@ -16,6 +16,17 @@ impl ExpertId {
     pub fn new(id: u64) -> Self {
         Self(id)
     }
+
+    /// ID of expert `expert` in MoE layer `layer` of a model (the layer in
+    /// the high 32 bits)
+    pub fn moe(layer: usize, expert: usize) -> Self {
+        Self(((layer as u64) << 32) | expert as u64)
+    }
+
+    /// The `(layer, expert)` pair of an ID built with [`ExpertId::moe`]
+    pub fn moe_parts(self) -> (usize, usize) {
+        ((self.0 >> 32) as usize, (self.0 & 0xffff_ffff) as usize)
+    }
 }
 
 impl std::fmt::Display for ExpertId {
@@ -48,11 +59,14 @@ pub struct ExpertRegistry {
     /// Local experts (hosted on this node)
     local_experts: HashMap<ExpertId, Box<dyn Expert>>,
 
-    /// Remote expert locations (expert_id -> peer_id)
-    remote_experts: HashMap<ExpertId, String>,
+    /// Remote expert locations (expert_id -> peer_ids, preferred first)
+    remote_experts: HashMap<ExpertId, Vec<String>>,
 
     /// Fallback experts for fault tolerance
     fallbacks: HashMap<ExpertId, Vec<ExpertId>>,
+
+    /// Failures reported per expert
+    failures: HashMap<ExpertId, u64>,
 }
 
 impl ExpertRegistry {
@@ -62,6 +76,7 @@ impl ExpertRegistry {
             local_experts: HashMap::new(),
             remote_experts: HashMap::new(),
             fallbacks: HashMap::new(),
+            failures: HashMap::new(),
         }
     }
 
@@ -73,12 +88,18 @@ impl ExpertRegistry {
     }
 
     /// Register a remote expert location
+    ///
+    /// Registering the same expert at several peers adds alternate
+    /// providers, tried in registration order.
     pub fn register_remote(&mut self, expert_id: ExpertId, peer_id: String) {
         info!(
             "Registering remote expert {} at peer {}",
             expert_id, peer_id
         );
-        self.remote_experts.insert(expert_id, peer_id);
+        let peers = self.remote_experts.entry(expert_id).or_default();
+        if !peers.contains(&peer_id) {
+            peers.push(peer_id);
+        }
     }
 
     /// Check if an expert is local
@@ -91,9 +112,16 @@ impl ExpertRegistry {
         self.local_experts.get(&expert_id).map(|e| e.as_ref())
     }
 
-    /// Get the peer ID for a remote expert
+    /// Get the preferred peer ID for a remote expert
     pub fn get_remote_peer(&self, expert_id: ExpertId) -> Option<&String> {
-        self.remote_experts.get(&expert_id)
+        self.get_remote_peers(expert_id).first()
+    }
+
+    /// All peers providing a remote expert, preferred first
+    pub fn get_remote_peers(&self, expert_id: ExpertId) -> &[String] {
+        self.remote_experts
+            .get(&expert_id)
+            .map_or(&[], |peers| peers.as_slice())
     }
 
     /// Register fallback experts
@@ -107,9 +135,25 @@ impl ExpertRegistry {
     }
 
     /// Report a failure for health tracking
-    pub fn report_failure(&mut self, _expert_id: ExpertId) {
-        warn!("Expert {} reported failure", _expert_id);
-        // TODO: Track failures and update health scores
+    pub fn report_failure(&mut self, expert_id: ExpertId) {
+        warn!("Expert {} reported failure", expert_id);
+        *self.failures.entry(expert_id).or_default() += 1;
+    }
+
+    /// Report that `peer_id` failed to serve an expert: it becomes the
+    /// expert's last choice of provider
+    pub fn report_peer_failure(&mut self, expert_id: ExpertId, peer_id: &str) {
+        if let Some(peers) = self.remote_experts.get_mut(&expert_id) {
+            if let Some(i) = peers.iter().position(|p| p == peer_id) {
+                let peer = peers.remove(i);
+                peers.push(peer);
+            }
+        }
+    }
+
+    /// Failures reported for an expert
+    pub fn failure_count(&self, expert_id: ExpertId) -> u64 {
+        self.failures.get(&expert_id).copied().unwrap_or(0)
     }
 
     /// List all available experts
@@ -169,6 +213,48 @@ impl Expert for LocalExpert {
     }
 }
 
+/// An expert of a Mixtral GGUF model, loaded to serve it to other peers
+pub struct GgufExpert {
+    id: ExpertId,
+    ffn: kwaai_inference::moe::ExpertFfn,
+    hidden_dim: usize,
+}
+
+impl GgufExpert {
+    /// Wrap expert `expert` of MoE layer `layer`
+    pub fn new(
+        layer: usize,
+        expert: usize,
+        ffn: kwaai_inference::moe::ExpertFfn,
+        hidden_dim: usize,
+    ) -> Self {
+        Self {
+            id: ExpertId::moe(layer, expert),
+            ffn,
+            hidden_dim,
+        }
+    }
+}
+
+#[async_trait]
+impl Expert for GgufExpert {
+    fn id(&self) -> ExpertId {
+        self.id
+    }
+
+    async fn forward(&self, input: &Tensor) -> DistributedResult<Tensor> {
+        Ok(self.ffn.forward(input)?)
+    }
+
+    fn hidden_dim(&self) -> usize {
+        self.hidden_dim
+    }
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,6 +279,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_moe_expert_ids_are_distinct_per_layer() {
+        assert_eq!(ExpertId::moe(0, 3), ExpertId::new(3));
+        assert_ne!(ExpertId::moe(1, 3), ExpertId::moe(0, 3));
+        assert_eq!(ExpertId::moe(2, 5).0, (2 << 32) | 5);
+        assert_eq!(ExpertId::moe(2, 5).moe_parts(), (2, 5));
+    }
+
     #[test]
     fn test_expert_id_display() {
         let id = ExpertId::new(7);
@@ -250,6 +344,21 @@ mod tests {
         let mut registry = ExpertRegistry::new();
         // Reporting failure on an unknown expert should not panic
         registry.report_failure(ExpertId::new(999));
+        assert_eq!(registry.failure_count(ExpertId::new(999)), 1);
+    }
+
+    #[test]
+    fn test_failed_provider_moves_to_the_back() {
+        let mut registry = ExpertRegistry::new();
+        let id = ExpertId::new(4);
+        registry.register_remote(id, "peer-a".to_string());
+        registry.register_remote(id, "peer-b".to_string());
+        registry.register_remote(id, "peer-a".to_string());
+        assert_eq!(registry.get_remote_peers(id), ["peer-a", "peer-b"]);
+
+        registry.report_peer_failure(id, "peer-a");
+        assert_eq!(registry.get_remote_peer(id), Some(&"peer-b".to_string()));
+        assert_eq!(registry.get_remote_peers(id), ["peer-b", "peer-a"]);
     }
 
     #[test]
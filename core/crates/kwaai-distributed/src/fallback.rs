@@ -0,0 +1,157 @@
+//! Fault tolerance for expert calls
+//!
+//! A routed expert is tried in this order: on this node if the registry
+//! hosts it, then each peer providing it, then each of its registered
+//! fallback experts the same way. [`DistributedMoE`](crate::moe::DistributedMoE)
+//! adds two more steps when all of those fail: an optional local fallback
+//! expert, and finally dropping the expert and re-weighting the gating
+//! outputs of its tokens over the experts that did answer.
+//! [`FallbackMetrics`] counts how often each step was needed.
+
+use crate::error::{DistributedError, DistributedResult};
+use crate::expert::{ExpertId, ExpertRegistry};
+use crate::moe::ExpertClient;
+use candle_core::Tensor;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+/// Counters of expert calls and the fallbacks they needed
+#[derive(Debug, Default)]
+pub struct FallbackMetrics {
+    expert_calls: AtomicU64,
+    provider_retries: AtomicU64,
+    fallback_experts: AtomicU64,
+    local_fallbacks: AtomicU64,
+    dropped_experts: AtomicU64,
+}
+
+/// Snapshot of [`FallbackMetrics`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FallbackStats {
+    /// Routed experts called
+    pub expert_calls: u64,
+    /// Calls answered by an alternate provider of the same expert
+    pub provider_retries: u64,
+    /// Calls answered by a registered fallback expert
+    pub fallback_experts: u64,
+    /// Calls answered by the local fallback expert
+    pub local_fallbacks: u64,
+    /// Calls nobody answered; the expert was left out of the gating
+    pub dropped_experts: u64,
+}
+
+impl FallbackStats {
+    /// Share of expert calls that needed any fallback
+    pub fn fallback_rate(&self) -> f64 {
+        let degraded = self.provider_retries
+            + self.fallback_experts
+            + self.local_fallbacks
+            + self.dropped_experts;
+        degraded as f64 / self.expert_calls.max(1) as f64
+    }
+}
+
+/// Counter updated by [`FallbackMetrics::record`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackEvent {
+    Call,
+    ProviderRetry,
+    FallbackExpert,
+    LocalFallback,
+    Dropped,
+}
+
+impl FallbackMetrics {
+    /// Count one event
+    pub fn record(&self, event: FallbackEvent) {
+        let counter = match event {
+            FallbackEvent::Call => &self.expert_calls,
+            FallbackEvent::ProviderRetry => &self.provider_retries,
+            FallbackEvent::FallbackExpert => &self.fallback_experts,
+            FallbackEvent::LocalFallback => &self.local_fallbacks,
+            FallbackEvent::Dropped => &self.dropped_experts,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Current counts
+    pub fn stats(&self) -> FallbackStats {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        FallbackStats {
+            expert_calls: get(&self.expert_calls),
+            provider_retries: get(&self.provider_retries),
+            fallback_experts: get(&self.fallback_experts),
+            local_fallbacks: get(&self.local_fallbacks),
+            dropped_experts: get(&self.dropped_experts),
+        }
+    }
+}
+
+/// A failed attempt: the expert tried, and the peer if it was remote
+pub type FailedAttempt = (ExpertId, Option<String>);
+
+/// Run `expert` over `input`, trying its providers and then its fallback
+/// experts (see the module docs). Each failed attempt is appended to
+/// `failed`; the error is the last attempt's.
+pub(crate) async fn run_expert(
+    registry: &ExpertRegistry,
+    client: Option<&dyn ExpertClient>,
+    expert: ExpertId,
+    input: &Tensor,
+    timeout_ms: u64,
+    metrics: &FallbackMetrics,
+    failed: &mut Vec<FailedAttempt>,
+) -> DistributedResult<Tensor> {
+    metrics.record(FallbackEvent::Call);
+    let fallbacks = registry.get_fallbacks(expert).cloned().unwrap_or_default();
+    let mut last_err = DistributedError::ExpertNotFound(expert.to_string());
+    for candidate in std::iter::once(expert).chain(fallbacks) {
+        let is_fallback = candidate != expert;
+        if let Some(local) = registry.get_local(candidate) {
+            match local.forward(input).await {
+                Ok(output) => {
+                    if is_fallback {
+                        metrics.record(FallbackEvent::FallbackExpert);
+                    }
+                    return Ok(output);
+                }
+                Err(e) => {
+                    warn!("Local expert {} failed: {}", candidate, e);
+                    failed.push((candidate, None));
+                    last_err = e;
+                }
+            }
+        }
+        for (attempt, peer_id) in registry.get_remote_peers(candidate).iter().enumerate() {
+            let result = match client {
+                Some(client) => tokio::time::timeout(
+                    Duration::from_millis(timeout_ms),
+                    client.forward(peer_id, candidate, input),
+                )
+                .await
+                .unwrap_or(Err(DistributedError::Timeout(timeout_ms))),
+                None => Err(DistributedError::RemoteCallFailed(format!(
+                    "{candidate} is on peer {peer_id} but no expert client is set"
+                ))),
+            };
+            match result {
+                Ok(output) => {
+                    if is_fallback {
+                        metrics.record(FallbackEvent::FallbackExpert);
+                    } else if attempt > 0 || registry.is_local(candidate) {
+                        metrics.record(FallbackEvent::ProviderRetry);
+                    }
+                    return Ok(output);
+                }
+                Err(e) => {
+                    warn!("Expert {} on peer {} failed: {}", candidate, peer_id, e);
+                    failed.push((candidate, Some(peer_id.clone())));
+                    last_err = e;
+                }
+            }
+        }
+    }
+    Err(last_err)
+}
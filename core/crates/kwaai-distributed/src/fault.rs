@@ -0,0 +1,198 @@
+//! Failure injection for the distributed transports
+//!
+//! Wraps [`ExpertClient`] and [`MatchmakingRpc`] with a seeded
+//! [`FaultInjector`] from `kwaai-p2p`, so MoE provider retries and fallbacks
+//! and averaging regrouping can be driven through exact, repeatable failure
+//! sequences. Calls are reported to the injector under the peer id they are
+//! addressed to; wrap the DHT with [`FaultyDht`].
+//!
+//! Enabled with the `fault-injection` feature.
+
+use crate::error::{DistributedError, DistributedResult};
+use crate::expert::ExpertId;
+use crate::matchmaking::{JoinRequest, JoinResponse, MatchmakingRpc};
+use crate::moe::ExpertClient;
+use async_trait::async_trait;
+use candle_core::Tensor;
+use std::sync::Arc;
+
+pub use kwaai_p2p::fault::{Fault, FaultInjector, FaultPlan, FaultRecord, FaultyDht, DHT_TARGET};
+
+fn injected(peer: &str, fault: Fault) -> DistributedError {
+    DistributedError::RemoteCallFailed(format!("{peer}: injected fault: {fault}"))
+}
+
+/// [`ExpertClient`] whose calls pass through a [`FaultInjector`]
+pub struct FaultyExpertClient {
+    inner: Arc<dyn ExpertClient>,
+    faults: Arc<FaultInjector>,
+}
+
+impl FaultyExpertClient {
+    /// Wrap `inner`
+    pub fn new(inner: Arc<dyn ExpertClient>, faults: Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl ExpertClient for FaultyExpertClient {
+    async fn forward(
+        &self,
+        peer_id: &str,
+        expert: ExpertId,
+        input: &Tensor,
+    ) -> DistributedResult<Tensor> {
+        self.faults
+            .gate(peer_id)
+            .await
+            .map_err(|fault| injected(peer_id, fault))?;
+        self.inner.forward(peer_id, expert, input).await
+    }
+}
+
+/// [`MatchmakingRpc`] whose join requests pass through a [`FaultInjector`]
+pub struct FaultyMatchmakingRpc<R> {
+    inner: R,
+    faults: Arc<FaultInjector>,
+}
+
+impl<R> FaultyMatchmakingRpc<R> {
+    /// Wrap `inner`
+    pub fn new(inner: R, faults: Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl<R: MatchmakingRpc> MatchmakingRpc for FaultyMatchmakingRpc<R> {
+    async fn join(&self, leader: &str, request: JoinRequest) -> DistributedResult<JoinResponse> {
+        self.faults
+            .gate(leader)
+            .await
+            .map_err(|fault| injected(leader, fault))?;
+        self.inner.join(leader, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expert::LocalExpert;
+    use crate::matchmaking::{Matchmaker, MatchmakingConfig};
+    use crate::moe::{DistributedMoE, MixtureOfExperts, MoEConfig, TopKRouter};
+    use crate::testing::MemoryDht;
+    use candle_core::Device;
+    use libp2p::PeerId;
+    use std::time::Duration;
+
+    struct Doubling;
+
+    #[async_trait]
+    impl ExpertClient for Doubling {
+        async fn forward(&self, _: &str, _: ExpertId, input: &Tensor) -> DistributedResult<Tensor> {
+            Ok((input * 2.0)?)
+        }
+    }
+
+    /// Every token goes to expert 1.
+    fn moe(faults: &Arc<FaultInjector>, timeout_ms: u64) -> DistributedMoE {
+        let gate = Tensor::new(&[[-10f32, 10.0], [0.0, 0.0]], &Device::Cpu).unwrap();
+        let client = FaultyExpertClient::new(Arc::new(Doubling), faults.clone());
+        let config = MoEConfig {
+            hidden_dim: 2,
+            num_experts: 2,
+            top_k: 1,
+            timeout_ms,
+        };
+        DistributedMoE::new(Box::new(TopKRouter::new(gate, 1, 2, 0.01)), config)
+            .with_client(Arc::new(client))
+    }
+
+    #[tokio::test]
+    async fn moe_retries_the_next_provider_of_a_killed_peer() {
+        let faults = Arc::new(FaultInjector::new(FaultPlan::new(3).kill_at(0, "peer-a")));
+        let mut moe = moe(&faults, 1000);
+        moe.register_remote_expert(ExpertId::new(1), "peer-a".to_string());
+        moe.register_remote_expert(ExpertId::new(1), "peer-b".to_string());
+
+        let input = Tensor::new(&[[1f32, 2.0]], &Device::Cpu).unwrap();
+        let output = moe.forward(&input).await.unwrap();
+        assert_eq!(output.to_vec2::<f32>().unwrap(), vec![vec![2.0, 4.0]]);
+        assert_eq!(moe.fallback_stats().provider_retries, 1);
+        let fates: Vec<(String, Fault)> = faults
+            .history()
+            .into_iter()
+            .map(|r| (r.target, r.fault))
+            .collect();
+        assert_eq!(
+            fates,
+            [
+                ("peer-a".to_string(), Fault::Dead),
+                ("peer-b".to_string(), Fault::Deliver)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn moe_falls_back_locally_when_rpcs_stall() {
+        let plan = FaultPlan::new(11).delay(1.0, Duration::from_secs(10));
+        let faults = Arc::new(FaultInjector::new(plan));
+        let mut moe = moe(&faults, 50).with_local_fallback(Box::new(LocalExpert::new(9, 2)));
+        moe.register_remote_expert(ExpertId::new(1), "peer-a".to_string());
+
+        let input = Tensor::new(&[[1f32, 2.0]], &Device::Cpu).unwrap();
+        let output = moe.forward(&input).await.unwrap();
+        assert_eq!(output.to_vec2::<f32>().unwrap(), vec![vec![1.0, 2.0]]);
+        assert_eq!(moe.fallback_stats().local_fallbacks, 1);
+    }
+
+    /// Never reached: the injector drops every join.
+    struct Unreachable;
+
+    #[async_trait]
+    impl MatchmakingRpc for Unreachable {
+        async fn join(&self, leader: &str, _: JoinRequest) -> DistributedResult<JoinResponse> {
+            unreachable!("join to {leader} should have been dropped")
+        }
+    }
+
+    #[tokio::test]
+    async fn matchmaking_regroups_when_joins_are_lost() {
+        let faults = Arc::new(FaultInjector::new(FaultPlan::new(5).drop_rate(1.0)));
+        let rpc = Arc::new(FaultyMatchmakingRpc::new(Unreachable, faults.clone()));
+        let config = MatchmakingConfig {
+            prefix: "fault".to_string(),
+            group_size: 2,
+            match_timeout: Duration::from_millis(200),
+            poll_interval: Duration::from_millis(10),
+            ..MatchmakingConfig::default()
+        };
+        let dht = MemoryDht::default();
+        let mut tasks = Vec::new();
+        for i in 0..2u64 {
+            let peer = PeerId::random();
+            let node = Matchmaker::new(peer.to_string(), config.clone());
+            let mut dht = dht.for_peer(peer);
+            let rpc = rpc.clone();
+            tasks.push(tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20 * i)).await;
+                node.form_group(&mut dht, rpc.as_ref(), 1).await.unwrap()
+            }));
+        }
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), None);
+        }
+        let history = faults.history();
+        assert!(!history.is_empty());
+        assert!(history.iter().all(|r| r.fault == Fault::Drop));
+    }
+
+    #[tokio::test]
+    async fn matchmaking_reports_a_dead_dht() {
+        let faults = Arc::new(FaultInjector::new(FaultPlan::new(0).kill_at(0, DHT_TARGET)));
+        let mut dht = FaultyDht::new(MemoryDht::default(), faults);
+        let node = Matchmaker::new("solo", MatchmakingConfig::default());
+        assert!(node.form_group(&mut dht, &Unreachable, 1).await.is_err());
+    }
+}
@@ -5,8 +5,13 @@
 //! This crate provides:
 //!
 //! - **Mixture of Experts (MoE)**: Distributed model layers across network
+//! - **Expert offload**: Mixtral inference with some experts on remote peers
 //! - **Decentralized Averaging**: Parameter sync without master node
+//! - **Matchmaking**: DHT-based averaging groups in open networks
+//! - **Progress tracking**: Swarm-wide epoch and sample counts
+//! - **Sessions**: Attention-cache accounting for remote inference clients
 //! - **Fault Tolerance**: Graceful handling of node failures
+//! - **Checkpointing**: Resume training state after a restart
 //!
 //! ## Architecture
 //!
@@ -23,16 +28,38 @@
 //! ```
 
 pub mod averaging;
+pub mod checkpoint;
 pub mod coordinator;
 pub mod error;
 pub mod expert;
+pub mod fallback;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault;
+pub mod matchmaking;
 pub mod moe;
+pub mod offload;
+pub mod progress;
+pub mod session;
 
-pub use averaging::{AveragingResult, DecentralizedAverager, ParameterAverager};
+#[cfg(test)]
+mod testing;
+
+pub use averaging::{
+    AllReduceStrategy, AveragingResult, AveragingSwarm, AveragingTransport, DecentralizedAverager,
+    EncryptedTransport, GradientSink, ParameterAverager, RingPosition,
+};
+pub use checkpoint::{CheckpointConfig, CheckpointManager, TrainingState};
 pub use coordinator::DistributedCoordinator;
 pub use error::{DistributedError, DistributedResult};
-pub use expert::{Expert, ExpertId, ExpertRegistry};
-pub use moe::{ExpertRouter, MixtureOfExperts, Routing};
+pub use expert::{Expert, ExpertId, ExpertRegistry, GgufExpert};
+pub use fallback::{FallbackMetrics, FallbackStats};
+pub use matchmaking::{
+    GroupInfo, JoinRequest, JoinResponse, Matchmaker, MatchmakingConfig, MatchmakingRpc,
+};
+pub use moe::{ExpertClient, ExpertRouter, MixtureOfExperts, Routing};
+pub use offload::PeerExperts;
+pub use progress::{GlobalProgress, ProgressConfig, ProgressTracker};
+pub use session::{SessionConfig, SessionManager};
 
 /// Configuration for distributed operations
 #[derive(Debug, Clone)]
@@ -45,10 +72,14 @@ pub struct DistributedConfig {
     pub moe_top_k: usize,
     /// Target averaging group size
     pub averaging_group_size: usize,
+    /// How averaging groups combine gradients; ring pays off in larger groups
+    pub allreduce_strategy: AllReduceStrategy,
     /// Timeout for remote operations (ms)
     pub timeout_ms: u64,
     /// Maximum retry attempts
     pub max_retries: usize,
+    /// Where to checkpoint training state (disabled when `None`)
+    pub checkpoint: Option<CheckpointConfig>,
 }
 
 impl Default for DistributedConfig {
@@ -58,8 +89,10 @@ impl Default for DistributedConfig {
             enable_averaging: true,
             moe_top_k: 2,
             averaging_group_size: 4,
+            allreduce_strategy: AllReduceStrategy::default(),
             timeout_ms: 5000,
             max_retries: 3,
+            checkpoint: None,
         }
     }
 }
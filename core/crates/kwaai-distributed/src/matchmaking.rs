@@ -0,0 +1,566 @@
+//! Group matchmaking for averaging rounds (Hivemind pattern)
+//!
+//! Nodes that are ready to average announce themselves in the DHT under a
+//! schema key carrying their *group bits*:
+//!
+//! ```text
+//! {prefix}_averaging.0b{bits}            providers: every ready node
+//! {prefix}_averaging.0b{bits}.{peer_id}  ReadyAnnouncement (round, expiry)
+//! ```
+//!
+//! Announcements are ordered by expiry, so nodes that started looking first
+//! become leaders. Every other node asks earlier nodes, best first, to take
+//! it in ([`MatchmakingRpc::join`]); a leader starts the round once it has
+//! `group_size` members, or at its expiry if it has at least
+//! `min_group_size`. A node that cannot form a group before `match_timeout`
+//! gives up and regroups on its next attempt. After each round the group
+//! bits shift, so consecutive rounds mix different peers.
+
+use crate::averaging::AveragingConfig;
+use crate::error::{DistributedError, DistributedResult};
+use async_trait::async_trait;
+use kwaai_p2p::DhtOperations;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Configuration for averaging group matchmaking
+#[derive(Debug, Clone)]
+pub struct MatchmakingConfig {
+    /// Namespace for the DHT keys, e.g. the model being trained
+    pub prefix: String,
+    /// Members a leader gathers before starting the round
+    pub group_size: usize,
+    /// Smallest group a leader starts when its time runs out
+    pub min_group_size: usize,
+    /// How long to look for a group before regrouping
+    pub match_timeout: Duration,
+    /// Group bits to start from (a string of `0`/`1`)
+    pub initial_group_bits: String,
+    /// How often to re-read announcements while looking
+    pub poll_interval: Duration,
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "kwaainet".to_string(),
+            group_size: 4,
+            min_group_size: 2,
+            match_timeout: Duration::from_secs(30),
+            initial_group_bits: String::new(),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl MatchmakingConfig {
+    /// Matchmaking for groups of an averager's size and patience
+    pub fn for_averaging(prefix: impl Into<String>, averaging: &AveragingConfig) -> Self {
+        Self {
+            prefix: prefix.into(),
+            group_size: averaging.group_size,
+            match_timeout: averaging.match_timeout,
+            ..Self::default()
+        }
+    }
+}
+
+/// Readiness record a node stores in the DHT while looking for a group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadyAnnouncement {
+    /// Announcing peer
+    pub peer_id: String,
+    /// Averaging round the peer is ready for
+    pub round: u64,
+    /// Unix time (ms) at which the peer stops looking
+    pub expires_at_ms: u64,
+}
+
+impl ReadyAnnouncement {
+    /// Whether this peer should lead a group `other` is in
+    fn precedes(&self, other: &ReadyAnnouncement) -> bool {
+        (self.expires_at_ms, &self.peer_id) < (other.expires_at_ms, &other.peer_id)
+    }
+}
+
+/// Request to join a leader's group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinRequest {
+    /// Peer asking to join
+    pub peer_id: String,
+    /// Averaging round
+    pub round: u64,
+    /// DHT key the follower found the leader under
+    pub group_key: String,
+}
+
+/// A formed averaging group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupInfo {
+    /// Identifier shared by all members
+    pub group_id: String,
+    /// Averaging round
+    pub round: u64,
+    /// Members, leader first
+    pub members: Vec<String>,
+}
+
+/// Leader's answer to a [`JoinRequest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinResponse {
+    /// The leader started the round with this group
+    Begin(GroupInfo),
+    /// The leader is not taking this follower
+    Rejected(String),
+}
+
+/// Transport for matchmaking requests
+///
+/// Implementations deliver the request to the leader's
+/// [`Matchmaker::handle_join`] and return its answer, which only arrives
+/// once the leader starts or abandons its group.
+#[async_trait]
+pub trait MatchmakingRpc: Send + Sync {
+    /// Ask `leader` to take this node into its group
+    async fn join(&self, leader: &str, request: JoinRequest) -> DistributedResult<JoinResponse>;
+}
+
+/// How a leader's gathering ended
+#[derive(Debug, Clone)]
+enum Outcome {
+    Pending,
+    Begun(GroupInfo),
+    Disbanded,
+}
+
+/// Matchmaking state for the current round
+struct Gathering {
+    round: u64,
+    group_key: String,
+    followers: Vec<String>,
+    /// A join request of ours is in flight, so nobody can join us
+    following: bool,
+    /// The round has started or been abandoned
+    closed: bool,
+    outcome: watch::Sender<Outcome>,
+}
+
+/// Finds averaging partners through the DHT
+///
+/// Share it (`Arc`) between the averaging loop, which calls
+/// [`form_group`](Self::form_group), and the RPC server, which forwards
+/// incoming join requests to [`handle_join`](Self::handle_join).
+pub struct Matchmaker {
+    peer_id: String,
+    config: MatchmakingConfig,
+    group_bits: Mutex<String>,
+    gathering: Mutex<Option<Gathering>>,
+    joined: Notify,
+}
+
+impl Matchmaker {
+    /// Create a matchmaker for the local peer
+    pub fn new(peer_id: impl Into<String>, config: MatchmakingConfig) -> Self {
+        let group_bits = Mutex::new(config.initial_group_bits.clone());
+        Self {
+            peer_id: peer_id.into(),
+            config,
+            group_bits,
+            gathering: Mutex::new(None),
+            joined: Notify::new(),
+        }
+    }
+
+    /// Peer id of the local node
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Current group bits
+    pub fn group_bits(&self) -> String {
+        self.group_bits.lock().unwrap().clone()
+    }
+
+    /// DHT key ready peers announce themselves under
+    pub fn group_key(&self) -> String {
+        format!("{}_averaging.0b{}", self.config.prefix, self.group_bits())
+    }
+
+    /// Look for an averaging group for `round`
+    ///
+    /// Returns `None` when no group of at least `min_group_size` formed
+    /// within `match_timeout`; call again to regroup.
+    pub async fn form_group<D>(
+        &self,
+        dht: &mut D,
+        rpc: &dyn MatchmakingRpc,
+        round: u64,
+    ) -> DistributedResult<Option<GroupInfo>>
+    where
+        D: DhtOperations + ?Sized,
+    {
+        let group_key = self.group_key();
+        let deadline = Instant::now() + self.config.match_timeout;
+        let me = ReadyAnnouncement {
+            peer_id: self.peer_id.clone(),
+            round,
+            expires_at_ms: now_ms() + self.config.match_timeout.as_millis() as u64,
+        };
+        self.open(round, &group_key);
+
+        let record = bincode::serialize(&me)
+            .map_err(|e| DistributedError::Internal(format!("encode announcement: {e}")))?;
+        dht.put(&record_key(&group_key, &self.peer_id), record)
+            .await?;
+        dht.provide(&group_key).await?;
+        debug!(key = %group_key, round, "Announced averaging readiness");
+
+        loop {
+            let followers = self.follower_count();
+            let now = Instant::now();
+            if followers + 1 >= self.config.group_size {
+                return Ok(Some(self.begin()));
+            }
+            if now >= deadline {
+                if followers + 1 >= self.config.min_group_size.max(2) {
+                    return Ok(Some(self.begin()));
+                }
+                self.disband();
+                info!(key = %group_key, round, "No averaging group formed, regrouping");
+                return Ok(None);
+            }
+
+            if followers == 0 {
+                for leader in self.leaders(dht, &group_key, &me).await? {
+                    if !self.start_following() {
+                        break;
+                    }
+                    let request = JoinRequest {
+                        peer_id: self.peer_id.clone(),
+                        round,
+                        group_key: group_key.clone(),
+                    };
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match tokio::time::timeout(remaining, rpc.join(&leader, request)).await {
+                        Ok(Ok(JoinResponse::Begin(group))) => {
+                            self.close(Outcome::Disbanded);
+                            self.advance_bits(&group);
+                            info!(
+                                group = %group.group_id,
+                                size = group.members.len(),
+                                "Joined averaging group"
+                            );
+                            return Ok(Some(group));
+                        }
+                        Ok(Ok(JoinResponse::Rejected(reason))) => {
+                            debug!(%leader, %reason, "Join rejected");
+                        }
+                        Ok(Err(e)) => warn!(%leader, "Join request failed: {e}"),
+                        Err(_) => debug!(%leader, "Join request timed out"),
+                    }
+                    self.stop_following();
+                }
+            }
+
+            let wait = self
+                .config
+                .poll_interval
+                .min(deadline.saturating_duration_since(Instant::now()));
+            tokio::select! {
+                _ = self.joined.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+
+    /// Serve a follower's [`JoinRequest`]
+    ///
+    /// Resolves once this node starts its group (or stops gathering).
+    pub async fn handle_join(&self, request: JoinRequest) -> JoinResponse {
+        let mut outcome = {
+            let mut gathering = self.gathering.lock().unwrap();
+            let Some(g) = gathering.as_mut() else {
+                return JoinResponse::Rejected("not looking for a group".to_string());
+            };
+            if g.round != request.round || g.group_key != request.group_key {
+                return JoinResponse::Rejected(format!(
+                    "gathering round {} under {}",
+                    g.round, g.group_key
+                ));
+            }
+            if g.closed || g.following {
+                return JoinResponse::Rejected("not leading a group".to_string());
+            }
+            if g.followers.len() + 1 >= self.config.group_size {
+                return JoinResponse::Rejected("group is full".to_string());
+            }
+            if !g.followers.contains(&request.peer_id) {
+                g.followers.push(request.peer_id.clone());
+            }
+            debug!(follower = %request.peer_id, "Follower joined");
+            g.outcome.subscribe()
+        };
+        self.joined.notify_one();
+
+        loop {
+            match &*outcome.borrow_and_update() {
+                Outcome::Begun(group) => return JoinResponse::Begin(group.clone()),
+                Outcome::Disbanded => {
+                    return JoinResponse::Rejected("leader disbanded the group".to_string())
+                }
+                Outcome::Pending => {}
+            }
+            if outcome.changed().await.is_err() {
+                return JoinResponse::Rejected("leader stopped gathering".to_string());
+            }
+        }
+    }
+
+    /// Earlier announcers for `round` under `group_key`, best leader first
+    async fn leaders<D>(
+        &self,
+        dht: &D,
+        group_key: &str,
+        me: &ReadyAnnouncement,
+    ) -> DistributedResult<Vec<String>>
+    where
+        D: DhtOperations + ?Sized,
+    {
+        let now = now_ms();
+        let mut leaders = Vec::new();
+        for peer in dht.get_providers(group_key).await? {
+            let peer = peer.to_string();
+            if peer == self.peer_id {
+                continue;
+            }
+            let Some(bytes) = dht.get(&record_key(group_key, &peer)).await? else {
+                continue;
+            };
+            let Ok(announcement) = bincode::deserialize::<ReadyAnnouncement>(&bytes) else {
+                warn!(%peer, "Ignoring malformed readiness announcement");
+                continue;
+            };
+            if announcement.round == me.round
+                && announcement.expires_at_ms > now
+                && announcement.precedes(me)
+            {
+                leaders.push(announcement);
+            }
+        }
+        leaders.sort_by(|a, b| (a.expires_at_ms, &a.peer_id).cmp(&(b.expires_at_ms, &b.peer_id)));
+        Ok(leaders.into_iter().map(|a| a.peer_id).collect())
+    }
+
+    fn open(&self, round: u64, group_key: &str) {
+        let mut gathering = self.gathering.lock().unwrap();
+        if let Some(previous) = gathering.take() {
+            previous.outcome.send_replace(Outcome::Disbanded);
+        }
+        *gathering = Some(Gathering {
+            round,
+            group_key: group_key.to_string(),
+            followers: Vec::new(),
+            following: false,
+            closed: false,
+            outcome: watch::channel(Outcome::Pending).0,
+        });
+    }
+
+    fn follower_count(&self) -> usize {
+        let gathering = self.gathering.lock().unwrap();
+        gathering.as_ref().map_or(0, |g| g.followers.len())
+    }
+
+    /// Switch to following unless someone joined us meanwhile
+    fn start_following(&self) -> bool {
+        let mut gathering = self.gathering.lock().unwrap();
+        match gathering.as_mut() {
+            Some(g) if g.followers.is_empty() && !g.closed => {
+                g.following = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn stop_following(&self) {
+        if let Some(g) = self.gathering.lock().unwrap().as_mut() {
+            g.following = false;
+        }
+    }
+
+    /// Start the round with the current followers
+    fn begin(&self) -> GroupInfo {
+        let group = {
+            let gathering = self.gathering.lock().unwrap();
+            let (round, followers) = gathering
+                .as_ref()
+                .map_or((0, Vec::new()), |g| (g.round, g.followers.clone()));
+            let mut members = vec![self.peer_id.clone()];
+            members.extend(followers);
+            GroupInfo {
+                group_id: format!("{}:{}", self.peer_id, round),
+                round,
+                members,
+            }
+        };
+        self.close(Outcome::Begun(group.clone()));
+        self.advance_bits(&group);
+        info!(
+            group = %group.group_id,
+            size = group.members.len(),
+            "Leading averaging group"
+        );
+        group
+    }
+
+    fn disband(&self) {
+        self.close(Outcome::Disbanded);
+    }
+
+    fn close(&self, outcome: Outcome) {
+        if let Some(g) = self.gathering.lock().unwrap().as_mut() {
+            g.closed = true;
+            g.following = false;
+            g.outcome.send_replace(outcome);
+        }
+    }
+
+    /// Shift the group bits by this node's position in `group`
+    ///
+    /// Members of one group spread over both halves of the next key space,
+    /// so the next round pairs them with different peers.
+    fn advance_bits(&self, group: &GroupInfo) {
+        let mut bits = self.group_bits.lock().unwrap();
+        if bits.is_empty() {
+            return;
+        }
+        let index = group
+            .members
+            .iter()
+            .position(|m| *m == self.peer_id)
+            .unwrap_or(0);
+        let next = format!("{}{}", &bits[1..], index % 2);
+        debug!(from = %bits, to = %next, "Advanced group bits");
+        *bits = next;
+    }
+}
+
+fn record_key(group_key: &str, peer_id: &str) -> String {
+    format!("{group_key}.{peer_id}")
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{LocalRpc, MemoryDht};
+    use libp2p::PeerId;
+    use std::sync::Arc;
+
+    fn config(group_size: usize, timeout_ms: u64) -> MatchmakingConfig {
+        MatchmakingConfig {
+            prefix: "test".to_string(),
+            group_size,
+            match_timeout: Duration::from_millis(timeout_ms),
+            initial_group_bits: "01".to_string(),
+            poll_interval: Duration::from_millis(10),
+            ..MatchmakingConfig::default()
+        }
+    }
+
+    async fn run_nodes(count: usize, config: MatchmakingConfig) -> Vec<Option<GroupInfo>> {
+        let dht = MemoryDht::default();
+        let rpc = Arc::new(LocalRpc::default());
+        let mut tasks = Vec::new();
+        for i in 0..count {
+            let peer = PeerId::random();
+            let node = Arc::new(Matchmaker::new(peer.to_string(), config.clone()));
+            rpc.add(node.clone());
+            let mut dht = dht.for_peer(peer);
+            let rpc = rpc.clone();
+            tasks.push(tokio::spawn(async move {
+                // Stagger starts so expiries (and leadership) differ
+                tokio::time::sleep(Duration::from_millis(5 * i as u64)).await;
+                node.form_group(&mut dht, rpc.as_ref(), 3).await.unwrap()
+            }));
+        }
+        let mut groups = Vec::new();
+        for task in tasks {
+            groups.push(task.await.unwrap());
+        }
+        groups
+    }
+
+    #[tokio::test]
+    async fn nodes_gather_into_one_group() {
+        let groups = run_nodes(3, config(3, 2_000)).await;
+        let first = groups[0].clone().expect("group formed");
+        assert_eq!(first.members.len(), 3);
+        assert_eq!(first.round, 3);
+        for group in &groups {
+            assert_eq!(group.as_ref(), Some(&first));
+        }
+    }
+
+    #[tokio::test]
+    async fn full_groups_leave_others_to_regroup_or_lead() {
+        let groups = run_nodes(5, config(2, 300)).await;
+        let mut members: Vec<&String> = groups.iter().flatten().flat_map(|g| &g.members).collect();
+        for group in groups.iter().flatten() {
+            assert_eq!(group.members.len(), 2);
+        }
+        let grouped = members.len();
+        members.sort();
+        members.dedup();
+        // Every member of a formed group reports the same group
+        assert_eq!(members.len() * 2, grouped);
+        assert!(
+            members.len() >= 4,
+            "only {} of 5 nodes were grouped",
+            members.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn lone_node_times_out_and_regroups() {
+        let groups = run_nodes(1, config(3, 50)).await;
+        assert_eq!(groups, vec![None]);
+    }
+
+    #[tokio::test]
+    async fn join_is_rejected_for_another_round() {
+        let node = Matchmaker::new("leader", config(3, 1_000));
+        node.open(1, &node.group_key());
+        let response = node
+            .handle_join(JoinRequest {
+                peer_id: "late".to_string(),
+                round: 2,
+                group_key: node.group_key(),
+            })
+            .await;
+        assert!(matches!(response, JoinResponse::Rejected(_)));
+    }
+
+    #[test]
+    fn group_bits_shift_by_position() {
+        let node = Matchmaker::new("b", config(2, 1_000));
+        assert_eq!(node.group_key(), "test_averaging.0b01");
+        node.advance_bits(&GroupInfo {
+            group_id: "a:0".to_string(),
+            round: 0,
+            members: vec!["a".to_string(), "b".to_string()],
+        });
+        assert_eq!(node.group_bits(), "11");
+    }
+}
@@ -4,18 +4,21 @@
 //! across network participants.
 
 use crate::error::{DistributedError, DistributedResult};
-use crate::expert::{ExpertId, ExpertRegistry};
+use crate::expert::{Expert, ExpertId, ExpertRegistry};
+use crate::fallback::{run_expert, FallbackEvent, FallbackMetrics, FallbackStats};
 use async_trait::async_trait;
-use candle_core::Tensor;
+use candle_core::{DType, Tensor, D};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
 
 /// Routing information for MoE layer
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Routing {
-    /// Expert indices for each token [batch, seq_len, top_k]
+    /// Expert indices for each token [batch * seq_len, top_k], best first
     pub expert_indices: Vec<Vec<ExpertId>>,
-    /// Expert weights for each token [batch, seq_len, top_k]
+    /// Expert weights for each token [batch * seq_len, top_k], summing to one
     pub expert_weights: Vec<Vec<f32>>,
     /// Auxiliary load balancing loss
     pub aux_loss: f32,
@@ -63,7 +66,6 @@ pub struct TopKRouter {
     /// Total number of experts
     num_experts: usize,
     /// Auxiliary loss coefficient
-    #[allow(dead_code)]
     aux_loss_coef: f32,
 }
 
@@ -82,30 +84,53 @@ impl TopKRouter {
 #[async_trait]
 impl ExpertRouter for TopKRouter {
     fn route(&self, hidden_states: &Tensor) -> DistributedResult<Routing> {
-        // Compute gating scores
-        let scores = hidden_states
-            .matmul(&self.gate_weights)
-            .map_err(|e| DistributedError::RoutingFailed(e.to_string()))?;
-
-        // Get dimensions
-        let dims = scores.dims();
-        let _batch_size = if dims.len() > 2 { dims[0] } else { 1 };
-        let seq_len = if dims.len() > 2 { dims[1] } else { dims[0] };
-
-        // Placeholder routing - in real implementation, would do proper softmax + top-k
-        // For now, return uniform routing to first top_k experts
-        let expert_indices: Vec<Vec<ExpertId>> = (0..seq_len)
-            .map(|_| (0..self.top_k).map(|i| ExpertId::new(i as u64)).collect())
-            .collect();
-
-        let expert_weights: Vec<Vec<f32>> = (0..seq_len)
-            .map(|_| vec![1.0 / self.top_k as f32; self.top_k])
-            .collect();
+        let err = |e: candle_core::Error| DistributedError::RoutingFailed(e.to_string());
+
+        // Softmax over the gating scores of every token
+        let hidden = hidden_states.dim(D::Minus1).map_err(err)?;
+        let probs = hidden_states
+            .reshape(((), hidden))
+            .and_then(|x| x.matmul(&self.gate_weights))
+            .and_then(|scores| candle_nn::ops::softmax_last_dim(&scores))
+            .and_then(|p| p.to_dtype(DType::F32))
+            .and_then(|p| p.to_vec2::<f32>())
+            .map_err(err)?;
+
+        let num_experts = probs.first().map_or(self.num_experts, Vec::len);
+        let mut expert_indices = Vec::with_capacity(probs.len());
+        let mut expert_weights = Vec::with_capacity(probs.len());
+        let mut top1_count = vec![0f32; num_experts];
+        let mut prob_sum = vec![0f32; num_experts];
+        for p in &probs {
+            let picked = kwaai_inference::moe::top_k(p, self.top_k);
+            if let Some(&(e, _)) = picked.first() {
+                top1_count[e] += 1.0;
+            }
+            for (sum, v) in prob_sum.iter_mut().zip(p) {
+                *sum += v;
+            }
+            expert_indices.push(
+                picked
+                    .iter()
+                    .map(|&(e, _)| ExpertId::new(e as u64))
+                    .collect(),
+            );
+            expert_weights.push(picked.iter().map(|&(_, w)| w).collect());
+        }
+
+        // Switch Transformer load balancing: num_experts * sum over experts of
+        // (share of tokens sent there first) * (mean router probability).
+        let n = probs.len().max(1) as f32;
+        let balance: f32 = top1_count
+            .iter()
+            .zip(&prob_sum)
+            .map(|(count, sum)| (count / n) * (sum / n))
+            .sum();
 
         Ok(Routing {
             expert_indices,
             expert_weights,
-            aux_loss: 0.0,
+            aux_loss: self.aux_loss_coef * num_experts as f32 * balance,
         })
     }
 
@@ -118,14 +143,31 @@ impl ExpertRouter for TopKRouter {
     }
 }
 
+/// Transport for running experts hosted by other peers
+#[async_trait]
+pub trait ExpertClient: Send + Sync {
+    /// Run `expert` on peer `peer_id` over `input` ([tokens, hidden])
+    async fn forward(
+        &self,
+        peer_id: &str,
+        expert: ExpertId,
+        input: &Tensor,
+    ) -> DistributedResult<Tensor>;
+}
+
 /// Distributed MoE layer implementation
 pub struct DistributedMoE {
     /// Expert router
     router: Box<dyn ExpertRouter>,
     /// Expert registry
     registry: ExpertRegistry,
+    /// Transport for remote experts
+    client: Option<Arc<dyn ExpertClient>>,
+    /// Stand-in for experts whose providers and fallbacks all failed
+    local_fallback: Option<Box<dyn Expert>>,
+    /// Fallback counters
+    metrics: Arc<FallbackMetrics>,
     /// Configuration
-    #[allow(dead_code)]
     config: MoEConfig,
 }
 
@@ -165,12 +207,33 @@ impl DistributedMoE {
         Self {
             router,
             registry: ExpertRegistry::new(),
+            client: None,
+            local_fallback: None,
+            metrics: Arc::default(),
             config,
         }
     }
 
+    /// Use `client` to call experts registered on remote peers
+    pub fn with_client(mut self, client: Arc<dyn ExpertClient>) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Run `expert` in place of any routed expert that cannot be reached,
+    /// instead of dropping it from the gating
+    pub fn with_local_fallback(mut self, expert: Box<dyn Expert>) -> Self {
+        self.local_fallback = Some(expert);
+        self
+    }
+
+    /// How often expert calls needed a fallback
+    pub fn fallback_stats(&self) -> FallbackStats {
+        self.metrics.stats()
+    }
+
     /// Register an expert (local or remote)
-    pub fn register_expert(&mut self, expert: Box<dyn crate::expert::Expert>) {
+    pub fn register_expert(&mut self, expert: Box<dyn Expert>) {
         debug!("Registering local expert in MoE layer");
         self.registry.register_local(expert);
     }
@@ -189,18 +252,91 @@ impl DistributedMoE {
 impl MixtureOfExperts for DistributedMoE {
     async fn forward(&mut self, input: &Tensor) -> DistributedResult<Tensor> {
         debug!("MoE forward pass, input shape: {:?}", input.dims());
+        let hidden = input.dim(D::Minus1)?;
+        let xs = input.reshape(((), hidden))?;
+
         // 1. Route tokens to experts
-        let routing = self.router.route(input)?;
+        let routing = self.router.route(&xs)?;
         debug!("Routing computed: aux_loss={:.4}", routing.aux_loss);
 
-        // 2. For now, just return input (placeholder)
-        // Real implementation would:
-        // - Partition tokens by expert assignment
-        // - Call local experts directly
-        // - Call remote experts via P2P
-        // - Combine results weighted by routing weights
+        // 2. Partition tokens by expert assignment
+        let mut assigned: BTreeMap<ExpertId, (Vec<u32>, Vec<f32>)> = BTreeMap::new();
+        for (row, (ids, weights)) in routing
+            .expert_indices
+            .iter()
+            .zip(&routing.expert_weights)
+            .enumerate()
+        {
+            for (&id, &w) in ids.iter().zip(weights) {
+                let (rows, ws) = assigned.entry(id).or_default();
+                rows.push(row as u32);
+                ws.push(w);
+            }
+        }
+
+        // 3. Run each expert on its tokens, falling back as needed
+        let mut answered = Vec::new();
+        let mut kept_weight = vec![0f32; routing.expert_indices.len()];
+        let mut failed = Vec::new();
+        for (id, (rows, weights)) in assigned {
+            let row_ids = Tensor::new(rows.as_slice(), xs.device())?;
+            let tokens = xs.index_select(&row_ids, 0)?;
+            let mut output = run_expert(
+                &self.registry,
+                self.client.as_deref(),
+                id,
+                &tokens,
+                self.config.timeout_ms,
+                &self.metrics,
+                &mut failed,
+            )
+            .await;
+            for (expert, peer) in failed.drain(..) {
+                self.registry.report_failure(expert);
+                if let Some(peer) = peer {
+                    self.registry.report_peer_failure(expert, &peer);
+                }
+            }
+            if let (Err(_), Some(fallback)) = (&output, &self.local_fallback) {
+                output = fallback.forward(&tokens).await;
+                if output.is_ok() {
+                    self.metrics.record(FallbackEvent::LocalFallback);
+                }
+            }
+            match output {
+                Ok(output) => {
+                    for (&row, &w) in rows.iter().zip(&weights) {
+                        kept_weight[row as usize] += w;
+                    }
+                    answered.push((row_ids, rows, weights, output));
+                }
+                Err(e) => {
+                    warn!("Dropping {} for {} tokens: {}", id, rows.len(), e);
+                    self.metrics.record(FallbackEvent::Dropped);
+                }
+            }
+        }
+
+        // 4. Combine, re-weighting each token over the experts that answered
+        if let Some(row) = kept_weight.iter().position(|&w| w <= 0.0) {
+            return Err(DistributedError::RetriesExhausted(format!(
+                "every expert routed for token {row} failed"
+            )));
+        }
+        let mut ys = xs.zeros_like()?;
+        for (row_ids, rows, weights, output) in answered {
+            let weights: Vec<f32> = rows
+                .iter()
+                .zip(&weights)
+                .map(|(&row, &w)| w / kept_weight[row as usize])
+                .collect();
+            let weights = Tensor::new(weights.as_slice(), xs.device())?
+                .to_dtype(output.dtype())?
+                .reshape(((), 1))?;
+            ys = ys.index_add(&row_ids, &output.broadcast_mul(&weights)?, 0)?;
+        }
 
-        Ok(input.clone())
+        Ok(ys.reshape(input.shape())?)
     }
 
     fn registry(&self) -> &ExpertRegistry {
@@ -292,8 +428,152 @@ mod tests {
             timeout_ms: 1000,
         };
         let mut moe = DistributedMoE::new(Box::new(router), cfg);
+        moe.register_expert(Box::new(LocalExpert::new(0, 8)));
         let input = Tensor::zeros((2usize, 8usize), DType::F32, &Device::Cpu).unwrap();
         let output = moe.forward(&input).await.unwrap();
         assert_eq!(output.dims(), input.dims());
     }
+
+    /// Doubles its input; peer "down" fails and peer "slow" hangs.
+    struct DoublingClient;
+
+    #[async_trait]
+    impl ExpertClient for DoublingClient {
+        async fn forward(
+            &self,
+            peer_id: &str,
+            _expert: ExpertId,
+            input: &Tensor,
+        ) -> DistributedResult<Tensor> {
+            match peer_id {
+                "down" => return Err(DistributedError::RemoteCallFailed("unreachable".into())),
+                "slow" => tokio::time::sleep(std::time::Duration::from_secs(5)).await,
+                _ => {}
+            }
+            Ok((input * 2.0)?)
+        }
+    }
+
+    /// Sends tokens whose first feature is positive to expert 1, the rest to expert 0.
+    fn sign_router() -> TopKRouter {
+        let gate = Tensor::new(&[[-10f32, 10.0], [0.0, 0.0]], &Device::Cpu).unwrap();
+        TopKRouter::new(gate, 1, 2, 0.01)
+    }
+
+    #[test]
+    fn test_router_picks_highest_scoring_expert() {
+        let input = Tensor::new(&[[1f32, 0.0], [-1.0, 0.0], [2.0, 5.0]], &Device::Cpu).unwrap();
+        let routing = sign_router().route(&input).unwrap();
+        let firsts: Vec<u64> = routing.expert_indices.iter().map(|r| r[0].0).collect();
+        assert_eq!(firsts, vec![1, 0, 1]);
+        assert!(routing.aux_loss > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_moe_forward_combines_local_and_remote_experts() {
+        let cfg = MoEConfig {
+            hidden_dim: 2,
+            num_experts: 2,
+            top_k: 1,
+            timeout_ms: 1000,
+        };
+        let mut moe =
+            DistributedMoE::new(Box::new(sign_router()), cfg).with_client(Arc::new(DoublingClient));
+        moe.register_expert(Box::new(LocalExpert::new(0, 2)));
+        moe.register_remote_expert(ExpertId::new(1), "peer-a".to_string());
+
+        let input = Tensor::new(&[[[1f32, 3.0], [-1.0, 4.0]]], &Device::Cpu).unwrap();
+        let output = moe.forward(&input).await.unwrap();
+        assert_eq!(output.dims(), &[1, 2, 2]);
+        // Token 0 went to the remote (doubling) expert, token 1 stayed local.
+        assert_eq!(
+            output.to_vec3::<f32>().unwrap(),
+            vec![vec![vec![2.0, 6.0], vec![-1.0, 4.0]]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_moe_forward_falls_back_when_a_peer_fails() {
+        let mut moe = DistributedMoE::new(Box::new(sign_router()), MoEConfig::default())
+            .with_client(Arc::new(DoublingClient));
+        moe.register_remote_expert(ExpertId::new(0), "down".to_string());
+        moe.register_remote_expert(ExpertId::new(1), "peer-a".to_string());
+        moe.register_remote_expert(ExpertId::new(7), "peer-b".to_string());
+
+        let input = Tensor::new(&[[-1f32, 1.0]], &Device::Cpu).unwrap();
+        assert!(moe.forward(&input).await.is_err(), "no fallback yet");
+
+        moe.registry
+            .register_fallback(ExpertId::new(0), vec![ExpertId::new(7)]);
+        let output = moe.forward(&input).await.unwrap();
+        assert_eq!(output.to_vec2::<f32>().unwrap(), vec![vec![-2.0, 2.0]]);
+        let stats = moe.fallback_stats();
+        assert_eq!((stats.expert_calls, stats.fallback_experts), (2, 1));
+        assert_eq!(stats.dropped_experts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_moe_forward_retries_alternate_providers() {
+        let cfg = MoEConfig {
+            timeout_ms: 50,
+            ..MoEConfig::default()
+        };
+        let mut moe =
+            DistributedMoE::new(Box::new(sign_router()), cfg).with_client(Arc::new(DoublingClient));
+        let id = ExpertId::new(1);
+        moe.register_remote_expert(id, "slow".to_string());
+        moe.register_remote_expert(id, "down".to_string());
+        moe.register_remote_expert(id, "peer-a".to_string());
+
+        let input = Tensor::new(&[[1f32, 1.0]], &Device::Cpu).unwrap();
+        let output = moe.forward(&input).await.unwrap();
+        assert_eq!(output.to_vec2::<f32>().unwrap(), vec![vec![2.0, 2.0]]);
+        assert_eq!(moe.fallback_stats().provider_retries, 1);
+        // The failed providers are now tried last.
+        assert_eq!(
+            moe.registry().get_remote_peers(id),
+            ["peer-a", "slow", "down"]
+        );
+        assert_eq!(moe.registry().failure_count(id), 2);
+    }
+
+    #[tokio::test]
+    async fn test_moe_forward_reweights_around_a_dropped_expert() {
+        let gate = Tensor::new(&[[-1f32, 1.0], [0.0, 0.0]], &Device::Cpu).unwrap();
+        let router = TopKRouter::new(gate, 2, 2, 0.01);
+        let mut moe = DistributedMoE::new(Box::new(router), MoEConfig::default())
+            .with_client(Arc::new(DoublingClient));
+        moe.register_expert(Box::new(LocalExpert::new(0, 2)));
+        moe.register_remote_expert(ExpertId::new(1), "down".to_string());
+
+        // Both experts are routed; expert 1 is unreachable, so expert 0
+        // (a passthrough) takes the tokens' whole weight.
+        let input = Tensor::new(&[[0.5f32, 3.0], [-2.0, 1.0]], &Device::Cpu).unwrap();
+        let output = moe.forward(&input).await.unwrap();
+        let output = output.to_vec2::<f32>().unwrap();
+        for (got, want) in output
+            .iter()
+            .flatten()
+            .zip(input.to_vec2::<f32>().unwrap().iter().flatten())
+        {
+            assert!((got - want).abs() < 1e-6, "{output:?}");
+        }
+        let stats = moe.fallback_stats();
+        assert_eq!((stats.expert_calls, stats.dropped_experts), (2, 1));
+        assert!((stats.fallback_rate() - 0.5).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_moe_forward_uses_the_local_fallback_expert() {
+        let mut moe = DistributedMoE::new(Box::new(sign_router()), MoEConfig::default())
+            .with_client(Arc::new(DoublingClient))
+            .with_local_fallback(Box::new(LocalExpert::new(99, 2)));
+        moe.register_remote_expert(ExpertId::new(1), "down".to_string());
+
+        let input = Tensor::new(&[[1f32, 4.0]], &Device::Cpu).unwrap();
+        let output = moe.forward(&input).await.unwrap();
+        assert_eq!(output.to_vec2::<f32>().unwrap(), vec![vec![1.0, 4.0]]);
+        let stats = moe.fallback_stats();
+        assert_eq!((stats.local_fallbacks, stats.dropped_experts), (1, 0));
+    }
 }
@@ -0,0 +1,194 @@
+//! Expert offload for the Mixtral runner in `kwaai-inference`
+//!
+//! The runner loads only the experts this node hosts and hands the others to
+//! a [`RemoteExperts`] implementation. [`PeerExperts`] is that
+//! implementation: it looks experts up in an [`ExpertRegistry`] keyed by
+//! [`ExpertId::moe`] and runs them on their peers through an
+//! [`ExpertClient`], trying alternate providers and fallback experts as
+//! [`DistributedMoE`](crate::moe::DistributedMoE) does. When none answers the
+//! runner drops the expert and re-weights its tokens over the others.
+
+use crate::expert::{ExpertId, ExpertRegistry};
+use crate::fallback::{run_expert, FallbackEvent, FallbackMetrics};
+use crate::moe::ExpertClient;
+use candle_core::Tensor;
+use kwaai_inference::moe::{ExpertOffload, RemoteExperts};
+use std::collections::BTreeSet;
+use std::sync::{mpsc, Arc};
+use tokio::sync::mpsc as tokio_mpsc;
+
+/// One remote expert call handed to the offload runtime
+struct Call {
+    layer: usize,
+    expert: usize,
+    input: Tensor,
+    reply: mpsc::Sender<candle_core::Result<Tensor>>,
+}
+
+/// What each call needs, shared by the calls in flight
+struct Shared {
+    registry: ExpertRegistry,
+    client: Arc<dyn ExpertClient>,
+    timeout_ms: u64,
+    metrics: Arc<FallbackMetrics>,
+}
+
+impl Shared {
+    async fn call(&self, layer: usize, expert: usize, x: &Tensor) -> candle_core::Result<Tensor> {
+        let mut failed = Vec::new();
+        run_expert(
+            &self.registry,
+            Some(self.client.as_ref()),
+            ExpertId::moe(layer, expert),
+            x,
+            self.timeout_ms,
+            &self.metrics,
+            &mut failed,
+        )
+        .await
+        .map_err(|e| {
+            self.metrics.record(FallbackEvent::Dropped);
+            candle_core::Error::Msg(e.to_string())
+        })
+    }
+}
+
+/// Remote experts reached through the P2P network
+///
+/// The runner calls experts synchronously from its forward pass, which may
+/// run on any thread — including a Tokio worker. Calls are therefore handed
+/// over a channel to a runtime on a dedicated thread, and the forward pass
+/// waits on a plain reply channel, never blocking a runtime it runs on.
+pub struct PeerExperts {
+    calls: tokio_mpsc::UnboundedSender<Call>,
+    metrics: Arc<FallbackMetrics>,
+}
+
+impl PeerExperts {
+    /// Experts in `registry`, called through `client`
+    ///
+    /// Starts the thread the calls run on; it exits once this instance (and
+    /// the offload built from it) is dropped.
+    pub fn new(
+        registry: ExpertRegistry,
+        client: Arc<dyn ExpertClient>,
+        timeout_ms: u64,
+    ) -> std::io::Result<Self> {
+        let metrics = Arc::<FallbackMetrics>::default();
+        let shared = Arc::new(Shared {
+            registry,
+            client,
+            timeout_ms,
+            metrics: metrics.clone(),
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (calls, mut rx) = tokio_mpsc::unbounded_channel::<Call>();
+        std::thread::Builder::new()
+            .name("kwaai-expert-offload".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    while let Some(call) = rx.recv().await {
+                        let shared = shared.clone();
+                        tokio::spawn(async move {
+                            let y = shared.call(call.layer, call.expert, &call.input).await;
+                            let _ = call.reply.send(y);
+                        });
+                    }
+                })
+            })?;
+        Ok(Self { calls, metrics })
+    }
+
+    /// Fallback counters, shared with this instance
+    pub fn metrics(&self) -> Arc<FallbackMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Offload that keeps the `local` experts of every layer on this node
+    /// and runs the rest through these peers
+    pub fn into_offload(self, local: BTreeSet<usize>) -> ExpertOffload {
+        ExpertOffload {
+            local: Some(local),
+            remote: Arc::new(self),
+        }
+    }
+}
+
+impl RemoteExperts for PeerExperts {
+    fn forward(&self, layer: usize, expert: usize, x: &Tensor) -> candle_core::Result<Tensor> {
+        let (reply, result) = mpsc::channel();
+        let call = Call {
+            layer,
+            expert,
+            input: x.clone(),
+            reply,
+        };
+        if self.calls.send(call).is_err() {
+            return Err(candle_core::Error::Msg(
+                "expert offload thread has stopped".to_string(),
+            ));
+        }
+        result.recv().unwrap_or_else(|_| {
+            Err(candle_core::Error::Msg(
+                "expert offload thread has stopped".to_string(),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DistributedResult;
+    use async_trait::async_trait;
+    use candle_core::Device;
+
+    /// Adds the expert's index to its input.
+    struct AddIndexClient;
+
+    #[async_trait]
+    impl ExpertClient for AddIndexClient {
+        async fn forward(
+            &self,
+            _peer_id: &str,
+            expert: ExpertId,
+            input: &Tensor,
+        ) -> DistributedResult<Tensor> {
+            Ok((input + (expert.0 & 0xffff_ffff) as f64)?)
+        }
+    }
+
+    fn peers() -> PeerExperts {
+        let mut registry = ExpertRegistry::new();
+        registry.register_remote(ExpertId::moe(1, 3), "peer-a".to_string());
+        PeerExperts::new(registry, Arc::new(AddIndexClient), 1000).unwrap()
+    }
+
+    #[test]
+    fn test_runs_remote_experts_from_a_blocking_thread() {
+        let peers = peers();
+        let metrics = peers.metrics();
+        let offload = peers.into_offload(BTreeSet::from([0, 1]));
+        assert!(offload.is_local(1));
+        assert!(!offload.is_local(3));
+
+        let x = Tensor::zeros((2, 4), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let y = offload.remote.forward(1, 3, &x).unwrap();
+        assert_eq!(y.to_vec2::<f32>().unwrap(), vec![vec![3.0; 4]; 2]);
+        // Layer 0's expert 3 is not registered anywhere.
+        assert!(offload.remote.forward(0, 3, &x).is_err());
+        let stats = metrics.stats();
+        assert_eq!((stats.expert_calls, stats.dropped_experts), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_runs_remote_experts_inside_the_runtime() {
+        // A current-thread runtime: blocking here must not need the runtime.
+        let remote = peers();
+        let x = Tensor::ones((1, 4), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let y = remote.forward(1, 3, &x).unwrap();
+        assert_eq!(y.to_vec2::<f32>().unwrap(), vec![vec![4.0; 4]]);
+    }
+}
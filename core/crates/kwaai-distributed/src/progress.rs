@@ -0,0 +1,347 @@
+//! Swarm-wide training progress (Hivemind `ProgressTracker` pattern)
+//!
+//! Peers train at their own pace and only loosely agree on an *epoch*: the
+//! number of averaging rounds the swarm has completed. Each peer publishes
+//! its epoch, the samples it has accumulated towards the next round and its
+//! throughput:
+//!
+//! ```text
+//! {prefix}_progress            providers: every training peer
+//! {prefix}_progress.{peer_id}  LocalProgress
+//! ```
+//!
+//! Aggregating those records gives the [`GlobalProgress`]: the swarm's
+//! epoch (the newest any live peer reports, ignoring epochs implausibly far
+//! beyond the median), the samples accumulated in it
+//! and an ETA for reaching the target batch size. The averager starts a
+//! round once that target is reached, or straight away when this peer has
+//! fallen behind the swarm's epoch.
+
+use crate::error::{DistributedError, DistributedResult};
+use kwaai_p2p::DhtOperations;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Weight of the newest measurement in the throughput average
+const RATE_SMOOTHING: f64 = 0.3;
+
+/// Epochs more than this far beyond the median of live peers are ignored
+const MAX_EPOCH_LEAD: u64 = 2;
+
+/// Configuration for progress tracking
+#[derive(Debug, Clone)]
+pub struct ProgressConfig {
+    /// Namespace for the DHT keys, e.g. the model being trained
+    pub prefix: String,
+    /// Samples the swarm accumulates per epoch before averaging
+    pub target_batch_size: u64,
+    /// Records not refreshed for this long are ignored
+    pub expiration: Duration,
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "kwaainet".to_string(),
+            target_batch_size: 4096,
+            expiration: Duration::from_secs(60),
+        }
+    }
+}
+
+/// One peer's progress, as published in the DHT
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalProgress {
+    /// Publishing peer
+    pub peer_id: String,
+    /// Averaging rounds this peer has completed
+    pub epoch: u64,
+    /// Samples accumulated since the last round
+    pub samples_accumulated: u64,
+    /// Smoothed training throughput
+    pub samples_per_second: f64,
+    /// Unix time (ms) of the last update
+    pub updated_at_ms: u64,
+}
+
+/// Progress of the whole swarm towards the next averaging round
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalProgress {
+    /// Newest epoch any live peer reports, within reach of the median
+    pub epoch: u64,
+    /// Samples accumulated by peers in that epoch
+    pub samples_accumulated: u64,
+    /// Samples needed to finish the epoch
+    pub target_batch_size: u64,
+    /// Live peers (any plausible epoch)
+    pub num_peers: usize,
+    /// Expected time until the target is reached, if anyone is training
+    pub eta_next_epoch: Option<Duration>,
+}
+
+impl GlobalProgress {
+    /// Whether the swarm has accumulated enough samples to average
+    pub fn ready_to_advance(&self) -> bool {
+        self.samples_accumulated >= self.target_batch_size
+    }
+}
+
+/// Publishes local progress and aggregates the swarm's
+pub struct ProgressTracker {
+    config: ProgressConfig,
+    local: LocalProgress,
+    last_report: Option<Instant>,
+}
+
+impl ProgressTracker {
+    /// Start tracking at `epoch` (0, or the round restored from a checkpoint)
+    pub fn new(peer_id: impl Into<String>, epoch: u64, config: ProgressConfig) -> Self {
+        Self {
+            config,
+            local: LocalProgress {
+                peer_id: peer_id.into(),
+                epoch,
+                samples_accumulated: 0,
+                samples_per_second: 0.0,
+                updated_at_ms: now_ms(),
+            },
+            last_report: None,
+        }
+    }
+
+    /// This peer's progress
+    pub fn local(&self) -> &LocalProgress {
+        &self.local
+    }
+
+    /// DHT key peers publish progress under
+    pub fn progress_key(&self) -> String {
+        format!("{}_progress", self.config.prefix)
+    }
+
+    /// Record `samples` more processed since the last report
+    pub fn report_samples(&mut self, samples: u64) {
+        let now = Instant::now();
+        if let Some(last) = self.last_report {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = samples as f64 / elapsed;
+                self.local.samples_per_second = if self.local.samples_per_second == 0.0 {
+                    rate
+                } else {
+                    RATE_SMOOTHING * rate + (1.0 - RATE_SMOOTHING) * self.local.samples_per_second
+                };
+            }
+        }
+        self.last_report = Some(now);
+        self.local.samples_accumulated += samples;
+        self.local.updated_at_ms = now_ms();
+    }
+
+    /// Move to `epoch` after an averaging round, starting a new batch
+    pub fn advance_epoch(&mut self, epoch: u64) {
+        debug!(
+            from = self.local.epoch,
+            to = epoch,
+            "Advancing training epoch"
+        );
+        self.local.epoch = epoch;
+        self.local.samples_accumulated = 0;
+        self.local.updated_at_ms = now_ms();
+    }
+
+    /// Publish this peer's progress to the DHT
+    pub async fn publish<D>(&self, dht: &mut D) -> DistributedResult<()>
+    where
+        D: DhtOperations + ?Sized,
+    {
+        let key = self.progress_key();
+        let record = bincode::serialize(&self.local)
+            .map_err(|e| DistributedError::Internal(format!("encode progress: {e}")))?;
+        dht.put(&record_key(&key, &self.local.peer_id), record)
+            .await?;
+        dht.provide(&key).await?;
+        debug!(
+            epoch = self.local.epoch,
+            samples = self.local.samples_accumulated,
+            "Published training progress"
+        );
+        Ok(())
+    }
+
+    /// Read every peer's progress from the DHT and aggregate it
+    pub async fn fetch<D>(&self, dht: &D) -> DistributedResult<GlobalProgress>
+    where
+        D: DhtOperations + ?Sized,
+    {
+        let key = self.progress_key();
+        let mut records = vec![self.local.clone()];
+        for peer in dht.get_providers(&key).await? {
+            let peer = peer.to_string();
+            if peer == self.local.peer_id {
+                continue;
+            }
+            let Some(bytes) = dht.get(&record_key(&key, &peer)).await? else {
+                continue;
+            };
+            match bincode::deserialize::<LocalProgress>(&bytes) {
+                Ok(progress) => records.push(progress),
+                Err(_) => warn!(%peer, "Ignoring malformed progress record"),
+            }
+        }
+        Ok(self.aggregate(&records, now_ms()))
+    }
+
+    /// Combine live peer records into the swarm's progress
+    fn aggregate(&self, records: &[LocalProgress], now_ms: u64) -> GlobalProgress {
+        let expiration = self.config.expiration.as_millis() as u64;
+        let mut live: Vec<&LocalProgress> = records
+            .iter()
+            .filter(|r| {
+                r.peer_id == self.local.peer_id
+                    || r.updated_at_ms.saturating_add(expiration) >= now_ms
+            })
+            .collect();
+        // Epochs are peer-reported: a peer far ahead of the median is out of
+        // sync or lying, and must not mark everyone else as behind.
+        let mut epochs: Vec<u64> = live.iter().map(|r| r.epoch).collect();
+        epochs.sort_unstable();
+        if let Some(&median) = epochs.get(epochs.len().saturating_sub(1) / 2) {
+            live.retain(|r| {
+                let plausible = r.epoch <= median.saturating_add(MAX_EPOCH_LEAD);
+                if !plausible {
+                    debug!(peer = %r.peer_id, epoch = r.epoch, median, "Ignoring epoch far ahead");
+                }
+                plausible
+            });
+        }
+        let epoch = live
+            .iter()
+            .map(|r| r.epoch)
+            .max()
+            .unwrap_or(self.local.epoch);
+        let current = live.iter().filter(|r| r.epoch == epoch);
+        let samples_accumulated = current.clone().map(|r| r.samples_accumulated).sum();
+        let rate: f64 = current.map(|r| r.samples_per_second).sum();
+
+        let target = self.config.target_batch_size;
+        let remaining = target.saturating_sub(samples_accumulated);
+        let eta_next_epoch = if remaining == 0 {
+            Some(Duration::ZERO)
+        } else if rate > 0.0 {
+            Some(Duration::from_secs_f64(remaining as f64 / rate))
+        } else {
+            None
+        };
+
+        GlobalProgress {
+            epoch,
+            samples_accumulated,
+            target_batch_size: target,
+            num_peers: live.len(),
+            eta_next_epoch,
+        }
+    }
+}
+
+fn record_key(progress_key: &str, peer_id: &str) -> String {
+    format!("{progress_key}.{peer_id}")
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryDht;
+    use libp2p::PeerId;
+
+    fn config(target: u64) -> ProgressConfig {
+        ProgressConfig {
+            prefix: "test".to_string(),
+            target_batch_size: target,
+            expiration: Duration::from_secs(10),
+        }
+    }
+
+    fn record(peer: &str, epoch: u64, samples: u64, rate: f64, at: u64) -> LocalProgress {
+        LocalProgress {
+            peer_id: peer.to_string(),
+            epoch,
+            samples_accumulated: samples,
+            samples_per_second: rate,
+            updated_at_ms: at,
+        }
+    }
+
+    #[tokio::test]
+    async fn swarm_progress_sums_peers_in_the_current_epoch() {
+        let dht = MemoryDht::default();
+        let mut trackers = Vec::new();
+        for samples in [100, 200, 300] {
+            let peer = PeerId::random();
+            let mut tracker = ProgressTracker::new(peer.to_string(), 2, config(1_000));
+            tracker.report_samples(samples);
+            tracker.publish(&mut dht.for_peer(peer)).await.unwrap();
+            trackers.push(tracker);
+        }
+
+        let global = trackers[0].fetch(&dht).await.unwrap();
+        assert_eq!(global.epoch, 2);
+        assert_eq!(global.samples_accumulated, 600);
+        assert_eq!(global.num_peers, 3);
+        assert!(!global.ready_to_advance());
+
+        trackers[1].report_samples(400);
+        let peer: PeerId = trackers[1].local().peer_id.parse().unwrap();
+        trackers[1].publish(&mut dht.for_peer(peer)).await.unwrap();
+        assert!(trackers[0].fetch(&dht).await.unwrap().ready_to_advance());
+    }
+
+    #[test]
+    fn aggregate_ignores_stale_peers_and_older_epochs() {
+        let tracker = ProgressTracker::new("me", 4, config(1_000));
+        let now = 100_000;
+        let records = [
+            record("me", 4, 50, 10.0, now),
+            record("ahead", 5, 300, 20.0, now - 1_000),
+            record("behind", 4, 900, 5.0, now - 2_000),
+            record("stale", 5, 5_000, 50.0, now - 60_000),
+        ];
+        let global = tracker.aggregate(&records, now);
+        assert_eq!(global.epoch, 5);
+        assert_eq!(global.samples_accumulated, 300);
+        assert_eq!(global.num_peers, 3);
+        assert_eq!(global.eta_next_epoch, Some(Duration::from_secs(35)));
+    }
+
+    #[test]
+    fn aggregate_ignores_epochs_far_beyond_the_median() {
+        let tracker = ProgressTracker::new("me", 4, config(1_000));
+        let now = u64::MAX - 10;
+        let records = [
+            record("me", 4, 50, 10.0, now),
+            record("peer", 4, 100, 10.0, now),
+            record("liar", u64::MAX, 5_000, 50.0, u64::MAX),
+        ];
+        let global = tracker.aggregate(&records, now);
+        assert_eq!(global.epoch, 4);
+        assert_eq!(global.samples_accumulated, 150);
+        assert_eq!(global.num_peers, 2);
+    }
+
+    #[test]
+    fn advance_epoch_starts_a_new_batch() {
+        let mut tracker = ProgressTracker::new("me", 0, config(10));
+        tracker.report_samples(8);
+        tracker.advance_epoch(1);
+        assert_eq!(tracker.local().epoch, 1);
+        assert_eq!(tracker.local().samples_accumulated, 0);
+    }
+}
@@ -0,0 +1,288 @@
+//! Server-side bookkeeping of remote inference sessions
+//!
+//! A block server holds an attention cache for every client session running
+//! through its blocks, and advertises how much room is left as
+//! `cache_tokens_left` in its DHT record. [`SessionManager`] keeps that figure
+//! honest: each session reserves the tokens its cache holds, a session that
+//! does not fit is refused instead of overcommitting the device, and sessions
+//! a client abandoned are expired after an idle timeout.
+//!
+//! ```text
+//! capacity 8192 ── session 7: 1200 ── session 9: 300 ── left: 6692
+//! ```
+//!
+//! Changes in capacity are published on a [`watch`] channel so the server
+//! can re-announce itself when it fills up or frees room.
+
+use crate::error::{DistributedError, DistributedResult};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::debug;
+
+/// Configuration for session tracking
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Attention-cache tokens shared by all sessions
+    pub cache_tokens: u64,
+    /// Sessions untouched for this long are expired
+    pub idle_timeout: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cache_tokens: 100_000,
+            idle_timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SessionEntry {
+    tokens: u64,
+    last_access: Instant,
+}
+
+#[derive(Debug, Default)]
+struct SessionTable {
+    sessions: HashMap<u64, SessionEntry>,
+    allocated: u64,
+}
+
+/// Tracks remote sessions and their share of the attention cache
+#[derive(Debug)]
+pub struct SessionManager {
+    config: SessionConfig,
+    table: Mutex<SessionTable>,
+    capacity: watch::Sender<u64>,
+}
+
+impl SessionManager {
+    /// Manager with all of `config.cache_tokens` free
+    pub fn new(config: SessionConfig) -> Self {
+        let (capacity, _) = watch::channel(config.cache_tokens);
+        Self {
+            config,
+            table: Mutex::new(SessionTable::default()),
+            capacity,
+        }
+    }
+
+    /// Total attention-cache tokens
+    pub fn cache_tokens(&self) -> u64 {
+        self.config.cache_tokens
+    }
+
+    /// Tokens not reserved by any session, as advertised in the DHT
+    pub fn cache_tokens_left(&self) -> u64 {
+        let table = self.table.lock().unwrap();
+        self.config.cache_tokens - table.allocated
+    }
+
+    /// Number of live sessions
+    pub fn active_sessions(&self) -> usize {
+        self.table.lock().unwrap().sessions.len()
+    }
+
+    /// Tokens reserved by `session_id`, if it is live
+    pub fn session_tokens(&self, session_id: u64) -> Option<u64> {
+        let table = self.table.lock().unwrap();
+        table.sessions.get(&session_id).map(|s| s.tokens)
+    }
+
+    /// Size `session_id`'s reservation to `tokens`, opening the session if
+    /// needed
+    ///
+    /// A session's cache holds every position it has processed, so callers
+    /// pass the sequence length after the current step. Shrinking (a client
+    /// rolling back) always succeeds; growing fails with
+    /// [`DistributedError::CacheFull`] when the other sessions leave too
+    /// little room, and the reservation is left unchanged.
+    pub fn reserve(&self, session_id: u64, tokens: u64) -> DistributedResult<()> {
+        let left = {
+            let mut table = self.table.lock().unwrap();
+            let held = table.sessions.get(&session_id).map_or(0, |s| s.tokens);
+            let available = self.config.cache_tokens - table.allocated + held;
+            if tokens > available {
+                return Err(DistributedError::CacheFull {
+                    requested: tokens,
+                    available,
+                });
+            }
+            table.allocated = table.allocated - held + tokens;
+            let entry = table.sessions.entry(session_id).or_insert(SessionEntry {
+                tokens: 0,
+                last_access: Instant::now(),
+            });
+            entry.tokens = tokens;
+            entry.last_access = Instant::now();
+            self.config.cache_tokens - table.allocated
+        };
+        self.publish(left);
+        Ok(())
+    }
+
+    /// Mark `session_id` as used now; returns whether it is live
+    pub fn touch(&self, session_id: u64) -> bool {
+        let mut table = self.table.lock().unwrap();
+        match table.sessions.get_mut(&session_id) {
+            Some(entry) => {
+                entry.last_access = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// End `session_id` and free its tokens; returns whether it was live
+    pub fn release(&self, session_id: u64) -> bool {
+        let left = {
+            let mut table = self.table.lock().unwrap();
+            let Some(entry) = table.sessions.remove(&session_id) else {
+                return false;
+            };
+            table.allocated -= entry.tokens;
+            self.config.cache_tokens - table.allocated
+        };
+        self.publish(left);
+        true
+    }
+
+    /// End every session, e.g. when the server drops its shard; returns how
+    /// many were live
+    pub fn clear(&self) -> usize {
+        let cleared = {
+            let mut table = self.table.lock().unwrap();
+            table.allocated = 0;
+            table.sessions.drain().count()
+        };
+        self.publish(self.config.cache_tokens);
+        cleared
+    }
+
+    /// Expire sessions idle for longer than the timeout and return their
+    /// IDs, so the caller can drop their caches
+    pub fn expire_idle(&self) -> Vec<u64> {
+        self.expire_idle_at(Instant::now())
+    }
+
+    fn expire_idle_at(&self, now: Instant) -> Vec<u64> {
+        let (expired, left) = {
+            let mut table = self.table.lock().unwrap();
+            let timeout = self.config.idle_timeout;
+            let expired: Vec<u64> = table
+                .sessions
+                .iter()
+                .filter(|(_, s)| now.saturating_duration_since(s.last_access) > timeout)
+                .map(|(&id, _)| id)
+                .collect();
+            for id in &expired {
+                if let Some(entry) = table.sessions.remove(id) {
+                    table.allocated -= entry.tokens;
+                }
+            }
+            (expired, self.config.cache_tokens - table.allocated)
+        };
+        if !expired.is_empty() {
+            debug!(
+                "Expired {} idle session(s), {} cache tokens left",
+                expired.len(),
+                left
+            );
+            self.publish(left);
+        }
+        expired
+    }
+
+    /// Receiver of `cache_tokens_left` whenever it changes
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.capacity.subscribe()
+    }
+
+    fn publish(&self, left: u64) {
+        self.capacity.send_if_modified(|current| {
+            let changed = *current != left;
+            *current = left;
+            changed
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(cache_tokens: u64) -> SessionManager {
+        SessionManager::new(SessionConfig {
+            cache_tokens,
+            idle_timeout: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn reservations_count_against_capacity() {
+        let sessions = manager(1000);
+        sessions.reserve(1, 600).unwrap();
+        sessions.reserve(2, 300).unwrap();
+        assert_eq!(sessions.cache_tokens_left(), 100);
+
+        // Growing past the room left fails and keeps the old reservation.
+        let err = sessions.reserve(2, 500).unwrap_err();
+        assert!(matches!(
+            err,
+            DistributedError::CacheFull {
+                requested: 500,
+                available: 400
+            }
+        ));
+        assert_eq!(sessions.session_tokens(2), Some(300));
+
+        // Rolling back shrinks; releasing frees everything.
+        sessions.reserve(1, 200).unwrap();
+        assert_eq!(sessions.cache_tokens_left(), 500);
+        assert!(sessions.release(1));
+        assert!(!sessions.release(1));
+        assert_eq!(sessions.cache_tokens_left(), 700);
+        assert_eq!(sessions.active_sessions(), 1);
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let sessions = manager(1000);
+        sessions.reserve(1, 100).unwrap();
+        sessions.reserve(2, 200).unwrap();
+        assert!(sessions.expire_idle().is_empty());
+
+        let later = Instant::now() + Duration::from_secs(61);
+        let mut expired = sessions.expire_idle_at(later);
+        expired.sort_unstable();
+        assert_eq!(expired, [1, 2]);
+        assert_eq!(sessions.cache_tokens_left(), 1000);
+        assert!(!sessions.touch(1));
+    }
+
+    #[tokio::test]
+    async fn capacity_changes_are_published() {
+        let sessions = manager(1000);
+        let mut rx = sessions.subscribe();
+        assert_eq!(*rx.borrow_and_update(), 1000);
+
+        sessions.reserve(5, 250).unwrap();
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow_and_update(), 750);
+
+        // Re-reserving the same size is not a change.
+        sessions.reserve(5, 250).unwrap();
+        assert!(!rx.has_changed().unwrap());
+
+        sessions.release(5);
+        assert_eq!(*rx.borrow_and_update(), 1000);
+
+        sessions.reserve(6, 10).unwrap();
+        assert_eq!(sessions.clear(), 1);
+        assert_eq!(*rx.borrow_and_update(), 1000);
+        assert_eq!(sessions.active_sessions(), 0);
+    }
+}
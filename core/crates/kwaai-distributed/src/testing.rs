@@ -0,0 +1,140 @@
+//! Test doubles shared by the unit tests in this crate
+
+use crate::averaging::AveragingTransport;
+use crate::error::{DistributedError, DistributedResult};
+use crate::matchmaking::{JoinRequest, JoinResponse, Matchmaker, MatchmakingRpc};
+use async_trait::async_trait;
+use kwaai_p2p::{DhtBackend, DhtBackendKind, DhtOperations, P2PResult};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::DuplexStream;
+
+/// DHT shared by every simulated node
+///
+/// Clones share one record store; [`for_peer`](Self::for_peer) gives each
+/// node a handle that provides under its own peer id.
+#[derive(Clone, Default)]
+pub(crate) struct MemoryDht {
+    local: Option<PeerId>,
+    records: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    providers: Arc<Mutex<HashMap<String, Vec<PeerId>>>>,
+}
+
+impl MemoryDht {
+    /// Handle onto the same DHT for `peer`
+    pub(crate) fn for_peer(&self, peer: PeerId) -> Self {
+        Self {
+            local: Some(peer),
+            ..self.clone()
+        }
+    }
+}
+
+#[async_trait]
+impl DhtOperations for MemoryDht {
+    async fn put(&mut self, key: &str, value: Vec<u8>) -> P2PResult<()> {
+        self.records.lock().unwrap().insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> P2PResult<Option<Vec<u8>>> {
+        Ok(self.records.lock().unwrap().get(key).cloned())
+    }
+
+    async fn provide(&mut self, key: &str) -> P2PResult<()> {
+        let mut providers = self.providers.lock().unwrap();
+        let entry = providers.entry(key.to_string()).or_default();
+        if let Some(local) = self.local.filter(|p| !entry.contains(p)) {
+            entry.push(local);
+        }
+        Ok(())
+    }
+
+    async fn get_providers(&self, key: &str) -> P2PResult<Vec<PeerId>> {
+        Ok(self
+            .providers
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl DhtBackend for MemoryDht {
+    fn kind(&self) -> DhtBackendKind {
+        DhtBackendKind::HivemindNative
+    }
+}
+
+/// A pair of members (smaller peer id first) and the round
+type PipeKey = (String, String, u64);
+
+/// In-memory pipes between simulated group members
+///
+/// Like [`MemoryDht`], clones share the pipes and
+/// [`for_peer`](Self::for_peer) gives each member its own handle: the first
+/// of a pair to connect gets one end of a fresh pipe, the other the far end.
+#[derive(Clone, Default)]
+pub(crate) struct MemoryTransport {
+    local: String,
+    pending: Arc<Mutex<HashMap<PipeKey, DuplexStream>>>,
+}
+
+impl MemoryTransport {
+    /// Handle onto the same pipes for `peer`
+    pub(crate) fn for_peer(&self, peer: &str) -> Self {
+        Self {
+            local: peer.to_string(),
+            ..self.clone()
+        }
+    }
+}
+
+#[async_trait]
+impl AveragingTransport for MemoryTransport {
+    type Stream = DuplexStream;
+
+    async fn connect(&self, peer: &str, round: u64) -> DistributedResult<DuplexStream> {
+        let (a, b) = if self.local.as_str() < peer {
+            (self.local.clone(), peer.to_string())
+        } else {
+            (peer.to_string(), self.local.clone())
+        };
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(end) = pending.remove(&(a.clone(), b.clone(), round)) {
+            return Ok(end);
+        }
+        let (near, far) = tokio::io::duplex(256);
+        pending.insert((a, b, round), far);
+        Ok(near)
+    }
+}
+
+/// Routes join requests straight to the leader's matchmaker
+#[derive(Default)]
+pub(crate) struct LocalRpc {
+    nodes: Mutex<HashMap<String, Arc<Matchmaker>>>,
+}
+
+impl LocalRpc {
+    /// Make `node` reachable as a leader
+    pub(crate) fn add(&self, node: Arc<Matchmaker>) {
+        self.nodes
+            .lock()
+            .unwrap()
+            .insert(node.peer_id().to_string(), node);
+    }
+}
+
+#[async_trait]
+impl MatchmakingRpc for LocalRpc {
+    async fn join(&self, leader: &str, request: JoinRequest) -> DistributedResult<JoinResponse> {
+        let node = self.nodes.lock().unwrap().get(leader).cloned();
+        match node {
+            Some(node) => Ok(node.handle_join(request).await),
+            None => Err(DistributedError::RemoteCallFailed(leader.to_string())),
+        }
+    }
+}
@@ -0,0 +1,580 @@
+//! Hivemind request authorization with signed access tokens
+//!
+//! Mirrors `hivemind.utils.auth.TokenAuthorizerBase`. An authority issues each
+//! member of a private swarm an [`AccessToken`] binding a username and an
+//! Ed25519 public key to an expiration time. Every RPC then carries:
+//!
+//! - **Requests** ([`RequestAuthInfo`]): the client's token, the current DHT
+//!   time, a fresh 8-byte nonce, and the client's signature over the whole
+//!   request (serialized with `signature` empty).
+//! - **Responses** ([`ResponseAuthInfo`]): the service's token, the request's
+//!   nonce echoed back, and the service's signature over the response.
+//!
+//! The receiver checks the authority's signature and expiry on the token,
+//! then the message signature against the key inside the token. Requests
+//! are additionally rejected when their time is more than
+//! [`MAX_CLOCK_SKEW`] seconds off or their nonce was seen recently (replay).
+//!
+//! Public networks such as Petals send [`RequestAuthInfo::new`] with no
+//! token; authorization only applies where a node is given a
+//! [`TokenAuthorizer`].
+
+use crate::client::DhtRpc;
+use crate::codec::{DHTRequest, DHTResponse};
+use crate::protocol::*;
+use crate::value::get_dht_time;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use libp2p::identity::ed25519;
+use libp2p::PeerId;
+use prost::Message;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Largest accepted difference between a request's `time` and ours, in
+/// seconds (Hivemind's `_MAX_CLIENT_SERVICER_TIME_DIFF`).
+pub const MAX_CLOCK_SKEW: f64 = 60.0;
+
+/// Length of request nonces, in bytes.
+const NONCE_LEN: usize = 8;
+
+/// Format of `AccessToken.expiration_time`: Python's `str(datetime)`.
+const EXPIRATION_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+/// Message types that carry a [`RequestAuthInfo`]
+pub trait AuthRequest {
+    fn request_auth(&self) -> Option<&RequestAuthInfo>;
+    fn request_auth_mut(&mut self) -> &mut Option<RequestAuthInfo>;
+    /// Protobuf encoding with `auth.signature` cleared — the signed payload
+    fn signing_bytes(&self) -> Vec<u8>;
+}
+
+/// Message types that carry a [`ResponseAuthInfo`]
+pub trait AuthResponse {
+    fn response_auth(&self) -> Option<&ResponseAuthInfo>;
+    fn response_auth_mut(&mut self) -> &mut Option<ResponseAuthInfo>;
+    /// Protobuf encoding with `auth.signature` cleared — the signed payload
+    fn signing_bytes(&self) -> Vec<u8>;
+}
+
+macro_rules! impl_auth_request {
+    ($($t:ty),*) => {$(
+        impl AuthRequest for $t {
+            fn request_auth(&self) -> Option<&RequestAuthInfo> {
+                self.auth.as_ref()
+            }
+            fn request_auth_mut(&mut self) -> &mut Option<RequestAuthInfo> {
+                &mut self.auth
+            }
+            fn signing_bytes(&self) -> Vec<u8> {
+                let mut unsigned = self.clone();
+                if let Some(auth) = unsigned.auth.as_mut() {
+                    auth.signature.clear();
+                }
+                unsigned.encode_to_vec()
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_auth_response {
+    ($($t:ty),*) => {$(
+        impl AuthResponse for $t {
+            fn response_auth(&self) -> Option<&ResponseAuthInfo> {
+                self.auth.as_ref()
+            }
+            fn response_auth_mut(&mut self) -> &mut Option<ResponseAuthInfo> {
+                &mut self.auth
+            }
+            fn signing_bytes(&self) -> Vec<u8> {
+                let mut unsigned = self.clone();
+                if let Some(auth) = unsigned.auth.as_mut() {
+                    auth.signature.clear();
+                }
+                unsigned.encode_to_vec()
+            }
+        }
+    )*};
+}
+
+impl_auth_request!(PingRequest, StoreRequest, FindRequest);
+impl_auth_response!(PingResponse, StoreResponse, FindResponse);
+
+impl AuthRequest for DHTRequest {
+    fn request_auth(&self) -> Option<&RequestAuthInfo> {
+        match self {
+            Self::Ping(r) => r.request_auth(),
+            Self::Store(r) => r.request_auth(),
+            Self::Find(r) => r.request_auth(),
+        }
+    }
+    fn request_auth_mut(&mut self) -> &mut Option<RequestAuthInfo> {
+        match self {
+            Self::Ping(r) => r.request_auth_mut(),
+            Self::Store(r) => r.request_auth_mut(),
+            Self::Find(r) => r.request_auth_mut(),
+        }
+    }
+    fn signing_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ping(r) => AuthRequest::signing_bytes(r),
+            Self::Store(r) => AuthRequest::signing_bytes(r),
+            Self::Find(r) => AuthRequest::signing_bytes(r),
+        }
+    }
+}
+
+impl AuthResponse for DHTResponse {
+    fn response_auth(&self) -> Option<&ResponseAuthInfo> {
+        match self {
+            Self::Ping(r) => r.response_auth(),
+            Self::Store(r) => r.response_auth(),
+            Self::Find(r) => r.response_auth(),
+        }
+    }
+    fn response_auth_mut(&mut self) -> &mut Option<ResponseAuthInfo> {
+        match self {
+            Self::Ping(r) => r.response_auth_mut(),
+            Self::Store(r) => r.response_auth_mut(),
+            Self::Find(r) => r.response_auth_mut(),
+        }
+    }
+    fn signing_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ping(r) => AuthResponse::signing_bytes(r),
+            Self::Store(r) => AuthResponse::signing_bytes(r),
+            Self::Find(r) => AuthResponse::signing_bytes(r),
+        }
+    }
+}
+
+/// Issue an access token for `public_key`, signed by the swarm `authority`
+///
+/// The token expires `ttl` from now. Used by authority services and tests;
+/// ordinary nodes receive their token from the authority instead.
+pub fn issue_access_token(
+    authority: &ed25519::Keypair,
+    username: &str,
+    public_key: &ed25519::PublicKey,
+    ttl: Duration,
+) -> AccessToken {
+    let expires = Utc::now().naive_utc() + chrono::Duration::from_std(ttl).unwrap_or_default();
+    let mut token = AccessToken {
+        username: username.to_string(),
+        public_key: public_key.to_bytes().to_vec(),
+        expiration_time: expires.format(EXPIRATION_FORMAT).to_string(),
+        signature: vec![],
+    };
+    token.signature = authority.sign(&token_signing_bytes(&token));
+    token
+}
+
+/// Signs outgoing RPCs with this node's token and validates incoming ones
+pub struct TokenAuthorizer {
+    keypair: ed25519::Keypair,
+    local_token: AccessToken,
+    authority: ed25519::PublicKey,
+    /// Nonces of recently accepted requests → time they may be forgotten
+    recent_nonces: Mutex<HashMap<Vec<u8>, f64>>,
+}
+
+impl fmt::Debug for TokenAuthorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenAuthorizer")
+            .field("username", &self.local_token.username)
+            .field("expiration_time", &self.local_token.expiration_time)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenAuthorizer {
+    /// Create an authorizer for a node holding `keypair` and `local_token`
+    ///
+    /// Fails if the token is invalid, expired, or issued for another key.
+    pub fn new(
+        keypair: ed25519::Keypair,
+        local_token: AccessToken,
+        authority: ed25519::PublicKey,
+    ) -> Result<Self> {
+        let authorizer = Self {
+            keypair,
+            local_token,
+            authority,
+            recent_nonces: Mutex::new(HashMap::new()),
+        };
+        authorizer.validate_access_token(&authorizer.local_token)?;
+        if authorizer.local_token.public_key != authorizer.local_public_key() {
+            return Err(Error::Unauthorized(
+                "access token was issued for a different key".to_string(),
+            ));
+        }
+        Ok(authorizer)
+    }
+
+    /// This node's raw Ed25519 public key, as carried in access tokens
+    pub fn local_public_key(&self) -> Vec<u8> {
+        self.keypair.public().to_bytes().to_vec()
+    }
+
+    /// This node's access token
+    pub fn local_token(&self) -> &AccessToken {
+        &self.local_token
+    }
+
+    /// Check the authority's signature on `token` and that it has not expired
+    pub fn validate_access_token(&self, token: &AccessToken) -> Result<()> {
+        if !self
+            .authority
+            .verify(&token_signing_bytes(token), &token.signature)
+        {
+            return Err(unauthorized("access token not signed by the authority"));
+        }
+        let expires = parse_expiration(&token.expiration_time)
+            .ok_or_else(|| unauthorized("malformed access token expiration time"))?;
+        if expires < Utc::now().naive_utc() {
+            return Err(unauthorized("access token expired"));
+        }
+        Ok(())
+    }
+
+    /// Fill in and sign the request's auth info
+    ///
+    /// `service_public_key` pins the expected responder; pass `None` when it
+    /// is not known in advance (e.g. DHT peers found by routing).
+    pub fn sign_request<R: AuthRequest>(&self, request: &mut R, service_public_key: Option<&[u8]>) {
+        *request.request_auth_mut() = Some(RequestAuthInfo {
+            client_access_token: Some(self.local_token.clone()),
+            service_public_key: service_public_key.map(<[u8]>::to_vec).unwrap_or_default(),
+            time: get_dht_time(),
+            nonce: uuid::Uuid::new_v4().as_bytes()[..NONCE_LEN].to_vec(),
+            signature: vec![],
+        });
+        let signature = self.keypair.sign(&request.signing_bytes());
+        if let Some(auth) = request.request_auth_mut() {
+            auth.signature = signature;
+        }
+    }
+
+    /// Validate an incoming request's token, signature, time and nonce
+    pub fn validate_request<R: AuthRequest>(&self, request: &R) -> Result<()> {
+        let auth = request
+            .request_auth()
+            .ok_or_else(|| unauthorized("request has no auth info"))?;
+        let token = auth
+            .client_access_token
+            .as_ref()
+            .ok_or_else(|| unauthorized("request has no access token"))?;
+        self.validate_access_token(token)?;
+
+        let client_key = ed25519::PublicKey::try_from_bytes(&token.public_key)
+            .map_err(|_| unauthorized("malformed client public key"))?;
+        if !client_key.verify(&request.signing_bytes(), &auth.signature) {
+            return Err(unauthorized("invalid request signature"));
+        }
+        if !auth.service_public_key.is_empty() && auth.service_public_key != self.local_public_key()
+        {
+            return Err(unauthorized("request addressed to a different service key"));
+        }
+
+        let now = get_dht_time();
+        if (auth.time - now).abs() > MAX_CLOCK_SKEW {
+            return Err(unauthorized(&format!(
+                "request time differs from ours by {:.0}s",
+                auth.time - now
+            )));
+        }
+        if auth.nonce.is_empty() {
+            return Err(unauthorized("request has no nonce"));
+        }
+        let mut nonces = self
+            .recent_nonces
+            .lock()
+            .map_err(|_| unauthorized("nonce cache poisoned"))?;
+        nonces.retain(|_, forget_at| *forget_at > now);
+        if nonces.contains_key(&auth.nonce) {
+            return Err(unauthorized("replayed request nonce"));
+        }
+        nonces.insert(auth.nonce.clone(), now + 3.0 * MAX_CLOCK_SKEW);
+        Ok(())
+    }
+
+    /// Fill in and sign the response's auth info, echoing `request_nonce`
+    pub fn sign_response<R: AuthResponse>(&self, response: &mut R, request_nonce: &[u8]) {
+        *response.response_auth_mut() = Some(ResponseAuthInfo {
+            service_access_token: Some(self.local_token.clone()),
+            nonce: request_nonce.to_vec(),
+            signature: vec![],
+        });
+        let signature = self.keypair.sign(&response.signing_bytes());
+        if let Some(auth) = response.response_auth_mut() {
+            auth.signature = signature;
+        }
+    }
+
+    /// Validate a response to a request this node signed with `request_nonce`
+    pub fn validate_response<R: AuthResponse>(
+        &self,
+        response: &R,
+        request_nonce: &[u8],
+    ) -> Result<()> {
+        let auth = response
+            .response_auth()
+            .ok_or_else(|| unauthorized("response has no auth info"))?;
+        let token = auth
+            .service_access_token
+            .as_ref()
+            .ok_or_else(|| unauthorized("response has no access token"))?;
+        self.validate_access_token(token)?;
+
+        let service_key = ed25519::PublicKey::try_from_bytes(&token.public_key)
+            .map_err(|_| unauthorized("malformed service public key"))?;
+        if !service_key.verify(&response.signing_bytes(), &auth.signature) {
+            return Err(unauthorized("invalid response signature"));
+        }
+        if auth.nonce != request_nonce {
+            return Err(unauthorized("response nonce does not match the request"));
+        }
+        Ok(())
+    }
+}
+
+/// [`DhtRpc`] that signs every request and rejects unauthorized responses
+pub struct AuthorizedRpc<R> {
+    inner: R,
+    authorizer: Arc<TokenAuthorizer>,
+}
+
+impl<R> AuthorizedRpc<R> {
+    pub fn new(inner: R, authorizer: Arc<TokenAuthorizer>) -> Self {
+        Self { inner, authorizer }
+    }
+}
+
+#[async_trait]
+impl<R: DhtRpc> DhtRpc for AuthorizedRpc<R> {
+    async fn find(&mut self, peer: PeerId, mut request: FindRequest) -> Result<FindResponse> {
+        self.authorizer.sign_request(&mut request, None);
+        let nonce = request_nonce(&request);
+        let response = self.inner.find(peer, request).await?;
+        self.authorizer.validate_response(&response, &nonce)?;
+        Ok(response)
+    }
+
+    async fn store(&mut self, peer: PeerId, mut request: StoreRequest) -> Result<StoreResponse> {
+        self.authorizer.sign_request(&mut request, None);
+        let nonce = request_nonce(&request);
+        let response = self.inner.store(peer, request).await?;
+        self.authorizer.validate_response(&response, &nonce)?;
+        Ok(response)
+    }
+}
+
+/// Nonce of a signed request, for matching its response
+pub fn request_nonce<R: AuthRequest>(request: &R) -> Vec<u8> {
+    request
+        .request_auth()
+        .map(|a| a.nonce.clone())
+        .unwrap_or_default()
+}
+
+/// Peer whose access token signed `request`, derived from the Ed25519 key in
+/// the token
+///
+/// Only meaningful once [`TokenAuthorizer::validate_request`] has accepted
+/// the request.
+pub fn request_signer<R: AuthRequest>(request: &R) -> Option<PeerId> {
+    let token = request.request_auth()?.client_access_token.as_ref()?;
+    let key = ed25519::PublicKey::try_from_bytes(&token.public_key).ok()?;
+    Some(PeerId::from_public_key(&key.into()))
+}
+
+fn unauthorized(reason: &str) -> Error {
+    Error::Unauthorized(reason.to_string())
+}
+
+/// Payload the authority signs: Hivemind's `_token_to_bytes`, i.e. the
+/// Python f-string `f"{username} {public_key} {expiration_time}"`, in which
+/// the key bytes appear as their `repr` (`b'...'`).
+fn token_signing_bytes(token: &AccessToken) -> Vec<u8> {
+    format!(
+        "{} {} {}",
+        token.username,
+        py_bytes_repr(&token.public_key),
+        token.expiration_time
+    )
+    .into_bytes()
+}
+
+/// Python's `repr(bytes)`
+fn py_bytes_repr(bytes: &[u8]) -> String {
+    let quote = if bytes.contains(&b'\'') && !bytes.contains(&b'"') {
+        '"'
+    } else {
+        '\''
+    };
+    let mut out = format!("b{quote}");
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'\t' => out.push_str("\\t"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            _ if b as char == quote => {
+                out.push('\\');
+                out.push(quote);
+            }
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    out.push(quote);
+    out
+}
+
+/// Parse Python's `str(datetime)` (`2026-01-31 12:00:00[.ffffff]`), also
+/// accepting the ISO `T` separator.
+fn parse_expiration(s: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    fn member(authority: &ed25519::Keypair, name: &str) -> TokenAuthorizer {
+        let keypair = ed25519::Keypair::generate();
+        let token = issue_access_token(authority, name, &keypair.public(), DAY);
+        TokenAuthorizer::new(keypair, token, authority.public()).unwrap()
+    }
+
+    fn find_request() -> FindRequest {
+        FindRequest::new(
+            NodeInfo::from_peer_id(PeerId::random()),
+            vec![b"k".to_vec()],
+        )
+    }
+
+    #[test]
+    fn signed_request_and_response_round_trip() {
+        let authority = ed25519::Keypair::generate();
+        let client = member(&authority, "alice");
+        let service = member(&authority, "bob");
+
+        let mut request = find_request();
+        client.sign_request(&mut request, Some(&service.local_public_key()));
+        service.validate_request(&request).unwrap();
+
+        let mut response = FindResponse::default();
+        service.sign_response(&mut response, &request_nonce(&request));
+        client
+            .validate_response(&response, &request_nonce(&request))
+            .unwrap();
+        // A response to some other request is rejected.
+        assert!(client.validate_response(&response, b"other").is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_replayed_and_unsigned_requests() {
+        let authority = ed25519::Keypair::generate();
+        let client = member(&authority, "alice");
+        let service = member(&authority, "bob");
+
+        let mut request = find_request();
+        client.sign_request(&mut request, None);
+        let mut tampered = request.clone();
+        tampered.keys.push(b"extra".to_vec());
+        assert!(service.validate_request(&tampered).is_err());
+
+        service.validate_request(&request).unwrap();
+        assert!(service.validate_request(&request).is_err(), "replay");
+
+        assert!(service.validate_request(&find_request()).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_expired_and_misaddressed() {
+        let authority = ed25519::Keypair::generate();
+        let service = member(&authority, "bob");
+
+        let outsider = member(&ed25519::Keypair::generate(), "mallory");
+        let mut request = find_request();
+        outsider.sign_request(&mut request, None);
+        assert!(service.validate_request(&request).is_err());
+
+        let keypair = ed25519::Keypair::generate();
+        let mut expired = issue_access_token(&authority, "eve", &keypair.public(), DAY);
+        expired.expiration_time = "2020-01-01 00:00:00".to_string();
+        expired.signature = authority.sign(&token_signing_bytes(&expired));
+        assert!(service.validate_access_token(&expired).is_err());
+        assert!(TokenAuthorizer::new(keypair, expired, authority.public()).is_err());
+
+        let client = member(&authority, "alice");
+        let mut request = find_request();
+        client.sign_request(&mut request, Some(&client.local_public_key()));
+        assert!(service.validate_request(&request).is_err());
+    }
+
+    #[test]
+    fn rejects_skewed_clock() {
+        let authority = ed25519::Keypair::generate();
+        let client = member(&authority, "alice");
+        let service = member(&authority, "bob");
+        let mut request = find_request();
+        client.sign_request(&mut request, None);
+        let auth = request.auth.as_mut().unwrap();
+        auth.time -= 2.0 * MAX_CLOCK_SKEW;
+        auth.signature.clear();
+        let signature = client.keypair.sign(&AuthRequest::signing_bytes(&request));
+        request.auth.as_mut().unwrap().signature = signature;
+        assert!(service.validate_request(&request).is_err());
+    }
+
+    #[test]
+    fn storage_enforces_auth_when_required() {
+        use crate::server::DHTStorage;
+        let authority = ed25519::Keypair::generate();
+        let client = member(&authority, "alice");
+        let service = Arc::new(member(&authority, "bob"));
+
+        let open = DHTStorage::new(PeerId::random()).with_authorizer(service.clone(), false);
+        assert!(open
+            .handle_request(DHTRequest::Find(find_request()))
+            .is_ok());
+
+        let strict = DHTStorage::new(PeerId::random()).with_authorizer(service, true);
+        assert!(strict
+            .handle_request(DHTRequest::Find(find_request()))
+            .is_err());
+
+        let mut request = find_request();
+        client.sign_request(&mut request, None);
+        let nonce = request_nonce(&request);
+        let response = strict.handle_request(DHTRequest::Find(request)).unwrap();
+        client.validate_response(&response, &nonce).unwrap();
+    }
+
+    #[test]
+    fn token_payload_matches_python_repr() {
+        assert_eq!(py_bytes_repr(b"ab\x00'\\\n\xff"), r#"b"ab\x00'\\\n\xff""#);
+        assert_eq!(py_bytes_repr(b"a'\"b"), r#"b'a\'"b'"#);
+        let token = AccessToken {
+            username: "alice".into(),
+            public_key: vec![0x01, b'A'],
+            expiration_time: "2030-01-01 00:00:00".into(),
+            signature: vec![],
+        };
+        assert_eq!(
+            token_signing_bytes(&token),
+            b"alice b'\\x01A' 2030-01-01 00:00:00".to_vec()
+        );
+        assert!(parse_expiration("2030-01-01 00:00:00.123456").is_some());
+        assert!(parse_expiration("2030-01-01T00:00:00").is_some());
+    }
+}
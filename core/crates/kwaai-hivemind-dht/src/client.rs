@@ -1,12 +1,32 @@
 //! Hivemind DHT client for get/store operations
+//!
+//! Besides the libp2p request-response client ([`HivemindDHT`]), this module
+//! implements Kademlia iterative routing over any [`DhtRpc`] transport:
+//! [`find_closest`] walks from a set of seed peers toward the peers whose node
+//! IDs are XOR-closest to each key, using the nearest-node lists returned by
+//! FIND, and [`store_on_closest`] stores each record on the closest of them —
+//! the same replication strategy as Python Hivemind's `DHTNode.store`.
+//! [`find_values`] reads many keys from several peers at once, batching keys
+//! into each FIND and keeping several requests in flight, so aggregating the
+//! records of every block of a model takes one round trip instead of one per
+//! block.
+//!
+//! [`HivemindDHT`] also keeps a bounded read-through cache of the values its
+//! FIND requests return, so repeated lookups of the same key (e.g. per-block
+//! routing) can be answered locally via [`HivemindDHT::get_cached`] until the
+//! value expires.
 
 use crate::codec::{DHTRequest, DHTResponse, HivemindCodec};
 use crate::protocol::*;
-use crate::value::{DHTExpiration, DHTValue};
+use crate::value::{get_dht_time, DHTExpiration, DHTValue};
 use crate::{Error, Result, PROTOCOL_FIND, PROTOCOL_STORE};
+use async_trait::async_trait;
+use futures::StreamExt;
 use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport};
 use libp2p::{PeerId, StreamProtocol};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::{Duration, Instant};
 use tracing::{debug, warn};
 
 /// Hivemind DHT client for storing and retrieving values
@@ -22,12 +42,33 @@ pub struct HivemindDHT {
 
     /// Pending requests
     pending_requests: HashMap<OutboundRequestId, PendingRequest>,
+
+    /// Values returned by FIND, served by [`get_cached`](Self::get_cached)
+    cache: ValueCache,
 }
 
 #[derive(Debug)]
 enum PendingRequest {
-    Get { keys: Vec<Vec<u8>> },
-    Store { keys: Vec<Vec<u8>> },
+    Get {
+        keys: Vec<Vec<u8>>,
+    },
+    Store {
+        keys: Vec<Vec<u8>>,
+        values: Vec<DHTValue>,
+    },
+}
+
+/// Default number of values kept by the [`HivemindDHT`] read-through cache
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Outcome of [`HivemindDHT::get_cached`]
+#[derive(Debug)]
+pub enum CachedGet {
+    /// An unexpired cached value; no request was sent
+    Hit(DHTValue),
+    /// Not cached; a FIND was sent and its answer is cached by
+    /// [`HivemindDHT::handle_response`]
+    Pending(OutboundRequestId),
 }
 
 impl HivemindDHT {
@@ -46,9 +87,19 @@ impl HivemindDHT {
             local_peer_id,
             behaviour,
             pending_requests: HashMap::new(),
+            cache: ValueCache::new(DEFAULT_CACHE_CAPACITY),
         }
     }
 
+    /// Keep at most `max_entries` values in the read-through cache
+    ///
+    /// `0` disables caching; [`get_cached`](Self::get_cached) then always
+    /// sends a FIND.
+    pub fn with_cache_capacity(mut self, max_entries: usize) -> Self {
+        self.cache.set_capacity(max_entries);
+        self
+    }
+
     /// Get the underlying request-response behaviour
     pub fn behaviour(&self) -> &request_response::Behaviour<HivemindCodec> {
         &self.behaviour
@@ -78,6 +129,7 @@ impl HivemindDHT {
         entries: Vec<(Vec<u8>, DHTValue)>,
     ) -> OutboundRequestId {
         let keys: Vec<Vec<u8>> = entries.iter().map(|(k, _)| k.clone()).collect();
+        let cached: Vec<DHTValue> = entries.iter().map(|(_, v)| v.clone()).collect();
         let values: Vec<Vec<u8>> = entries.iter().map(|(_, v)| v.value.clone()).collect();
         let expiration_times: Vec<DHTExpiration> =
             entries.iter().map(|(_, v)| v.expiration_time).collect();
@@ -100,8 +152,13 @@ impl HivemindDHT {
             .behaviour
             .send_request(&peer, DHTRequest::Store(request));
 
-        self.pending_requests
-            .insert(req_id, PendingRequest::Store { keys });
+        self.pending_requests.insert(
+            req_id,
+            PendingRequest::Store {
+                keys,
+                values: cached,
+            },
+        );
 
         req_id
     }
@@ -135,6 +192,30 @@ impl HivemindDHT {
         req_id
     }
 
+    /// Get a value, answering from the cache while it holds an unexpired copy
+    ///
+    /// On a miss this behaves like [`get`](Self::get); the value is cached
+    /// when its response goes through [`handle_response`](Self::handle_response).
+    pub fn get_cached(&mut self, peer: PeerId, key: Vec<u8>) -> CachedGet {
+        match self.cache.get(&key) {
+            Some(value) => {
+                debug!("DHT cache hit for {} byte key", key.len());
+                CachedGet::Hit(value)
+            }
+            None => CachedGet::Pending(self.get(peer, key)),
+        }
+    }
+
+    /// Drop the cached value of `key`, so the next lookup hits the network
+    pub fn invalidate_cached(&mut self, key: &[u8]) {
+        self.cache.remove(key);
+    }
+
+    /// Drop every cached value
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
     /// Handle a response from the DHT
     pub fn handle_response(
         &mut self,
@@ -147,13 +228,23 @@ impl HivemindDHT {
             .ok_or_else(|| Error::Network("Unknown request ID".to_string()))?;
 
         match (pending, response) {
-            (PendingRequest::Store { keys }, DHTResponse::Store(store_res)) => {
+            (PendingRequest::Store { keys, values }, DHTResponse::Store(store_res)) => {
                 debug!("Store response: {} results", store_res.store_ok.len());
 
                 let results: Vec<StoreResult> = keys
                     .into_iter()
+                    .zip(values)
                     .zip(store_res.store_ok)
-                    .map(|(key, stored)| StoreResult { key, stored })
+                    .map(|((key, value), stored)| {
+                        // A rejected store means the peer holds a fresher
+                        // value than ours, so the cached copy may be stale.
+                        if stored {
+                            self.cache.insert(key.clone(), value);
+                        } else {
+                            self.cache.remove(&key);
+                        }
+                        StoreResult { key, stored }
+                    })
                     .collect();
 
                 Ok(ResponseData::Store(results))
@@ -192,6 +283,7 @@ impl HivemindDHT {
                                     warn!("Received expired value");
                                     GetResult { key, value: None }
                                 } else {
+                                    self.cache.insert(key.clone(), dht_value.clone());
                                     GetResult {
                                         key,
                                         value: Some(dht_value),
@@ -215,6 +307,103 @@ impl HivemindDHT {
     }
 }
 
+/// A cached value and when it was last read
+#[derive(Debug)]
+struct CacheEntry {
+    value: DHTValue,
+    last_used: u64,
+}
+
+/// Bounded cache of DHT values
+///
+/// Entries are dropped once their DHT expiration passes. When full, expired
+/// entries are purged first and then the least recently used one is evicted.
+/// Of two values for one key the later-expiring one wins, as in Hivemind.
+#[derive(Debug)]
+struct ValueCache {
+    entries: HashMap<Vec<u8>, CacheEntry>,
+    capacity: usize,
+    /// Logical clock for LRU ordering
+    tick: u64,
+}
+
+impl ValueCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            tick: 0,
+        }
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<DHTValue> {
+        if self.entries.get(key)?.value.is_expired() {
+            self.entries.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.tick;
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: DHTValue) {
+        if self.capacity == 0 || value.is_expired() {
+            return;
+        }
+        match self.entries.get(&key) {
+            Some(entry) if entry.value.expiration_time > value.expiration_time => return,
+            Some(_) => {}
+            None => {
+                if self.entries.len() >= self.capacity {
+                    self.evict();
+                }
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    /// Make room for one entry
+    fn evict(&mut self) {
+        let now = get_dht_time();
+        self.entries
+            .retain(|_, entry| entry.value.expiration_time >= now);
+        if self.entries.len() < self.capacity {
+            return;
+        }
+        let lru = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = lru {
+            self.entries.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.entries.remove(key);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
 /// Response data from DHT operations
 #[derive(Debug)]
 pub enum ResponseData {
@@ -243,6 +432,484 @@ pub struct FindResponseData {
     pub nearest_peers: Vec<PeerId>,
 }
 
+// ============================================================================
+// Iterative routing
+// ============================================================================
+
+/// Transport for unary DHT RPCs
+///
+/// Implemented over whatever carries Hivemind's `rpc_find` / `rpc_store`
+/// handlers (e.g. p2pd unary streams).
+#[async_trait]
+pub trait DhtRpc: Send {
+    /// Send a FIND request to `peer`
+    async fn find(&mut self, peer: PeerId, request: FindRequest) -> Result<FindResponse>;
+
+    /// Send a STORE request to `peer`
+    async fn store(&mut self, peer: PeerId, request: StoreRequest) -> Result<StoreResponse>;
+}
+
+/// Tuning for [`find_closest`] and [`store_on_closest`]
+#[derive(Debug, Clone)]
+pub struct RoutingConfig {
+    /// Number of closest peers each record is stored on
+    pub replicas: usize,
+    /// Unqueried peers contacted per key in each lookup round (Kademlia's α)
+    pub parallelism: usize,
+    /// Upper bound on lookup rounds
+    pub max_rounds: usize,
+    /// Timeout for a single RPC
+    pub rpc_timeout: Duration,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            // Hivemind's DHTNode defaults: num_replicas=5, parallel_rpc=3
+            replicas: 5,
+            parallelism: 3,
+            max_rounds: 8,
+            rpc_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// XOR distance between two DHT IDs
+///
+/// The shorter ID is zero-padded on the right, so results of mixed-length IDs
+/// still compare lexicographically.
+pub fn xor_distance(a: &[u8], b: &[u8]) -> Vec<u8> {
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0) ^ b.get(i).unwrap_or(&0))
+        .collect()
+}
+
+/// A peer known to a lookup; `distance` is `None` until its node ID is known
+#[derive(Debug, Clone)]
+struct Candidate {
+    peer: PeerId,
+    distance: Option<Vec<u8>>,
+}
+
+/// Lookup state for one key
+#[derive(Debug)]
+struct KeyLookup {
+    key: Vec<u8>,
+    /// Sorted closest first; peers of unknown distance last
+    candidates: Vec<Candidate>,
+    queried: HashSet<PeerId>,
+    responded: HashSet<PeerId>,
+}
+
+impl KeyLookup {
+    fn new(key: Vec<u8>, seeds: &[PeerId]) -> Self {
+        let mut lookup = Self {
+            key,
+            candidates: Vec::new(),
+            queried: HashSet::new(),
+            responded: HashSet::new(),
+        };
+        for seed in seeds {
+            lookup.insert(*seed, None);
+        }
+        lookup
+    }
+
+    /// Add `peer`, or fill in its distance if it was known without one
+    fn insert(&mut self, peer: PeerId, node_id: Option<&[u8]>) {
+        let distance = node_id.map(|id| xor_distance(&self.key, id));
+        match self.candidates.iter_mut().find(|c| c.peer == peer) {
+            Some(c) if c.distance.is_none() => c.distance = distance,
+            Some(_) => return,
+            None => self.candidates.push(Candidate { peer, distance }),
+        }
+        self.candidates
+            .sort_by(|a, b| match (&a.distance, &b.distance) {
+                (Some(x), Some(y)) => x.cmp(y),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            });
+    }
+
+    fn remove(&mut self, peer: &PeerId) {
+        self.candidates.retain(|c| c.peer != *peer);
+    }
+
+    /// Up to `alpha` unqueried peers among the `k` closest known
+    fn next_to_query(&self, k: usize, alpha: usize) -> Vec<PeerId> {
+        self.candidates
+            .iter()
+            .take(k)
+            .filter(|c| !self.queried.contains(&c.peer))
+            .take(alpha)
+            .map(|c| c.peer)
+            .collect()
+    }
+
+    /// The `k` closest peers that answered a FIND
+    fn closest_responded(&self, k: usize) -> Vec<PeerId> {
+        self.candidates
+            .iter()
+            .filter(|c| self.responded.contains(&c.peer))
+            .take(k)
+            .map(|c| c.peer)
+            .collect()
+    }
+}
+
+/// Find the `config.replicas` closest reachable peers to each key
+///
+/// Starts from `seeds` (typically the bootstrap peers) and repeatedly sends
+/// FIND to the closest not-yet-queried peers, merging the nearest-node lists
+/// they return, until the closest known peers have all been queried or
+/// `config.max_rounds` is reached. Keys are batched into one FIND per peer per
+/// round. Only peers that answered are returned, closest first; `local_peer_id`
+/// is never returned.
+pub async fn find_closest<R: DhtRpc + ?Sized>(
+    rpc: &mut R,
+    local_peer_id: PeerId,
+    keys: &[Vec<u8>],
+    seeds: &[PeerId],
+    config: &RoutingConfig,
+) -> HashMap<Vec<u8>, Vec<PeerId>> {
+    let seeds: Vec<PeerId> = seeds
+        .iter()
+        .copied()
+        .filter(|p| *p != local_peer_id)
+        .collect();
+    let mut lookups: Vec<KeyLookup> = Vec::new();
+    for key in keys {
+        if !lookups.iter().any(|l| l.key == *key) {
+            lookups.push(KeyLookup::new(key.clone(), &seeds));
+        }
+    }
+    let mut failed: HashSet<PeerId> = HashSet::new();
+
+    for round in 0..config.max_rounds {
+        // peer → indices of the lookups that want to query it
+        let mut plan: Vec<(PeerId, Vec<usize>)> = Vec::new();
+        for (i, lookup) in lookups.iter().enumerate() {
+            for peer in lookup.next_to_query(config.replicas, config.parallelism) {
+                match plan.iter_mut().find(|(p, _)| *p == peer) {
+                    Some((_, idxs)) => idxs.push(i),
+                    None => plan.push((peer, vec![i])),
+                }
+            }
+        }
+        if plan.is_empty() {
+            break;
+        }
+        debug!("DHT lookup round {}: querying {} peers", round, plan.len());
+
+        for (peer, idxs) in plan {
+            for &i in &idxs {
+                lookups[i].queried.insert(peer);
+            }
+            let request = FindRequest {
+                auth: Some(RequestAuthInfo::new()),
+                keys: idxs.iter().map(|&i| lookups[i].key.clone()).collect(),
+                peer: Some(NodeInfo::from_peer_id(local_peer_id)),
+            };
+            let response =
+                match tokio::time::timeout(config.rpc_timeout, rpc.find(peer, request)).await {
+                    Ok(Ok(r)) => r,
+                    Ok(Err(e)) => {
+                        debug!("FIND to {} failed: {}", peer, e);
+                        failed.insert(peer);
+                        lookups.iter_mut().for_each(|l| l.remove(&peer));
+                        continue;
+                    }
+                    Err(_) => {
+                        debug!("FIND to {} timed out", peer);
+                        failed.insert(peer);
+                        lookups.iter_mut().for_each(|l| l.remove(&peer));
+                        continue;
+                    }
+                };
+
+            let responder_id = response.peer.map(|p| p.node_id);
+            for (n, &i) in idxs.iter().enumerate() {
+                let lookup = &mut lookups[i];
+                lookup.responded.insert(peer);
+                lookup.insert(peer, responder_id.as_deref());
+                let Some(result) = response.results.get(n) else {
+                    continue;
+                };
+                for (node_id, peer_bytes) in
+                    result.nearest_node_ids.iter().zip(&result.nearest_peer_ids)
+                {
+                    match PeerId::from_bytes(peer_bytes) {
+                        Ok(p) if p != local_peer_id && !failed.contains(&p) => {
+                            lookup.insert(p, Some(node_id))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    lookups
+        .into_iter()
+        .map(|l| {
+            let closest = l.closest_responded(config.replicas);
+            (l.key, closest)
+        })
+        .collect()
+}
+
+/// Tuning for [`find_values`]
+#[derive(Debug, Clone)]
+pub struct FanOutConfig {
+    /// Keys carried by one FIND request
+    pub keys_per_request: usize,
+    /// FIND requests in flight at once
+    pub concurrency: usize,
+    /// Timeout for a single RPC
+    pub rpc_timeout: Duration,
+}
+
+impl Default for FanOutConfig {
+    fn default() -> Self {
+        Self {
+            keys_per_request: 32,
+            concurrency: 16,
+            rpc_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Outcome of [`find_values`]
+#[derive(Debug, Clone, Default)]
+pub struct FoundValues {
+    /// For each key some peer holds, the results carrying a value, one per
+    /// peer, in the order the peers were given
+    pub values: HashMap<Vec<u8>, Vec<(PeerId, FindResult)>>,
+    /// Peers that answered at least one request
+    pub responded: HashSet<PeerId>,
+    /// Requests that failed or timed out
+    pub failed: usize,
+}
+
+impl FoundValues {
+    /// Every result carrying a value, across keys and peers
+    pub fn results(&self) -> impl Iterator<Item = &FindResult> {
+        self.values.values().flatten().map(|(_, result)| result)
+    }
+}
+
+/// Read `keys` from every peer in `peers` with concurrent, batched FINDs
+///
+/// Keys are split into requests of `config.keys_per_request`, every chunk is
+/// sent to every peer, and up to `config.concurrency` requests run at once
+/// through `find` (which signs and sends one request). Unlike
+/// [`find_closest`] this does not walk the DHT: it collects what the given
+/// peers hold, e.g. the bootstrap peers every node announces to.
+/// `requester` identifies this node in each request.
+pub async fn find_values<F, Fut>(
+    peers: &[PeerId],
+    keys: &[Vec<u8>],
+    requester: Option<NodeInfo>,
+    config: &FanOutConfig,
+    find: F,
+) -> FoundValues
+where
+    F: Fn(PeerId, FindRequest) -> Fut,
+    Fut: Future<Output = Result<FindResponse>>,
+{
+    let jobs: Vec<(PeerId, Vec<Vec<u8>>)> = peers
+        .iter()
+        .flat_map(|&peer| {
+            keys.chunks(config.keys_per_request.max(1))
+                .map(move |chunk| (peer, chunk.to_vec()))
+        })
+        .collect();
+    debug!(
+        "Fanning out {} keys as {} FIND requests to {} peers",
+        keys.len(),
+        jobs.len(),
+        peers.len()
+    );
+
+    let find = &find;
+    let mut responses = futures::stream::iter(jobs.into_iter().map(|(peer, chunk)| {
+        let request = FindRequest {
+            auth: Some(RequestAuthInfo::new()),
+            keys: chunk.clone(),
+            peer: requester.clone(),
+        };
+        let call = tokio::time::timeout(config.rpc_timeout, find(peer, request));
+        async move { (peer, chunk, call.await) }
+    }))
+    .buffer_unordered(config.concurrency.max(1));
+
+    let mut found = FoundValues::default();
+    while let Some((peer, chunk, outcome)) = responses.next().await {
+        match outcome {
+            Ok(Ok(response)) => {
+                found.responded.insert(peer);
+                for (key, result) in chunk.into_iter().zip(response.results) {
+                    if result.value.is_empty() || result.result_type == ResultType::NotFound as i32
+                    {
+                        continue;
+                    }
+                    found.values.entry(key).or_default().push((peer, result));
+                }
+            }
+            Ok(Err(e)) => {
+                debug!("FIND to {} failed: {}", peer, e);
+                found.failed += 1;
+            }
+            Err(_) => {
+                debug!("FIND to {} timed out", peer);
+                found.failed += 1;
+            }
+        }
+    }
+
+    // Responses arrive in completion order; report them in peer order so
+    // callers preferring the first peer's record stay deterministic.
+    let rank = |p: &PeerId| peers.iter().position(|q| q == p);
+    for results in found.values.values_mut() {
+        results.sort_by_key(|(peer, _)| rank(peer));
+    }
+    found
+}
+
+/// One STORE RPC issued by [`store_on_closest`]
+#[derive(Debug, Clone)]
+pub struct StoreAttempt {
+    /// Target peer
+    pub peer: PeerId,
+    /// Round-trip time of the RPC (or time until it failed)
+    pub latency: Duration,
+    /// Whether the RPC itself succeeded
+    pub ok: bool,
+}
+
+/// Outcome of [`store_on_closest`]
+#[derive(Debug, Clone, Default)]
+pub struct RoutedStore {
+    /// For each key of the request (in order), the peers that accepted it
+    pub stored_on: Vec<Vec<PeerId>>,
+    /// Every STORE RPC attempted
+    pub attempts: Vec<StoreAttempt>,
+}
+
+impl RoutedStore {
+    /// Whether at least one key was accepted by at least one peer
+    pub fn any_stored(&self) -> bool {
+        self.stored_on.iter().any(|peers| !peers.is_empty())
+    }
+
+    /// Number of distinct peers that accepted at least one key
+    pub fn peers_reached(&self) -> usize {
+        self.stored_on
+            .iter()
+            .flatten()
+            .collect::<HashSet<_>>()
+            .len()
+    }
+}
+
+/// Store every record of `request` on the peers closest to its key
+///
+/// Runs [`find_closest`] for the request's keys and sends each closest peer
+/// one STORE carrying the records it should hold. When routing finds no
+/// responsive peer for a key, that key falls back to the seeds so that a
+/// network whose peers do not answer FIND still receives the record.
+pub async fn store_on_closest<R: DhtRpc + ?Sized>(
+    rpc: &mut R,
+    local_peer_id: PeerId,
+    request: StoreRequest,
+    seeds: &[PeerId],
+    config: &RoutingConfig,
+) -> RoutedStore {
+    let closest = find_closest(rpc, local_peer_id, &request.keys, seeds, config).await;
+
+    // peer → indices of the records it should hold
+    let mut plan: Vec<(PeerId, Vec<usize>)> = Vec::new();
+    for (i, key) in request.keys.iter().enumerate() {
+        let targets = match closest.get(key) {
+            Some(peers) if !peers.is_empty() => peers.clone(),
+            _ => seeds
+                .iter()
+                .copied()
+                .filter(|p| *p != local_peer_id)
+                .collect(),
+        };
+        for peer in targets {
+            match plan.iter_mut().find(|(p, _)| *p == peer) {
+                Some((_, idxs)) => idxs.push(i),
+                None => plan.push((peer, vec![i])),
+            }
+        }
+    }
+
+    let mut outcome = RoutedStore {
+        stored_on: vec![Vec::new(); request.keys.len()],
+        attempts: Vec::new(),
+    };
+    for (peer, idxs) in plan {
+        let pick = |v: &Vec<Vec<u8>>| -> Vec<Vec<u8>> {
+            idxs.iter()
+                .map(|&i| v.get(i).cloned().unwrap_or_default())
+                .collect()
+        };
+        let sub = StoreRequest {
+            auth: request.auth.clone(),
+            keys: pick(&request.keys),
+            subkeys: pick(&request.subkeys),
+            values: pick(&request.values),
+            expiration_time: idxs
+                .iter()
+                .map(|&i| request.expiration_time.get(i).copied().unwrap_or_default())
+                .collect(),
+            in_cache: idxs
+                .iter()
+                .map(|&i| request.in_cache.get(i).copied().unwrap_or_default())
+                .collect(),
+            peer: request.peer.clone(),
+        };
+
+        let t0 = Instant::now();
+        let result = tokio::time::timeout(config.rpc_timeout, rpc.store(peer, sub)).await;
+        let latency = t0.elapsed();
+        match result {
+            Ok(Ok(resp)) => {
+                for (&i, &ok) in idxs.iter().zip(&resp.store_ok) {
+                    if ok {
+                        outcome.stored_on[i].push(peer);
+                    }
+                }
+                outcome.attempts.push(StoreAttempt {
+                    peer,
+                    latency,
+                    ok: true,
+                });
+            }
+            Ok(Err(e)) => {
+                warn!("STORE to {} failed: {}", peer, e);
+                outcome.attempts.push(StoreAttempt {
+                    peer,
+                    latency,
+                    ok: false,
+                });
+            }
+            Err(_) => {
+                warn!("STORE to {} timed out", peer);
+                outcome.attempts.push(StoreAttempt {
+                    peer,
+                    latency,
+                    ok: false,
+                });
+            }
+        }
+    }
+    outcome
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -252,4 +919,298 @@ mod tests {
         let peer_id = PeerId::random();
         let _client = HivemindDHT::new(peer_id);
     }
+
+    #[test]
+    fn test_get_cached_serves_unexpired_values() {
+        let mut client = HivemindDHT::new(PeerId::random());
+        let peer = PeerId::random();
+        let key = b"model.0".to_vec();
+
+        assert!(matches!(
+            client.get_cached(peer, key.clone()),
+            CachedGet::Pending(_)
+        ));
+        client
+            .cache
+            .insert(key.clone(), DHTValue::with_ttl(b"v".to_vec(), 60.0));
+        match client.get_cached(peer, key.clone()) {
+            CachedGet::Hit(value) => assert_eq!(value.value, b"v"),
+            CachedGet::Pending(_) => panic!("expected a cache hit"),
+        }
+
+        client.invalidate_cached(&key);
+        assert!(matches!(
+            client.get_cached(peer, key),
+            CachedGet::Pending(_)
+        ));
+    }
+
+    #[test]
+    fn test_value_cache_drops_expired_and_keeps_fresher() {
+        let mut cache = ValueCache::new(4);
+        let now = get_dht_time();
+        cache.insert(b"old".to_vec(), DHTValue::new(b"x".to_vec(), now - 1.0));
+        assert!(cache.get(b"old").is_none());
+
+        cache.insert(b"k".to_vec(), DHTValue::new(b"late".to_vec(), now + 60.0));
+        cache.insert(b"k".to_vec(), DHTValue::new(b"early".to_vec(), now + 30.0));
+        assert_eq!(cache.get(b"k").unwrap().value, b"late");
+
+        // Values that expire while cached are purged on read
+        cache
+            .entries
+            .get_mut(&b"k"[..])
+            .unwrap()
+            .value
+            .expiration_time = now - 1.0;
+        assert!(cache.get(b"k").is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_value_cache_evicts_least_recently_used() {
+        let mut cache = ValueCache::new(2);
+        cache.insert(b"a".to_vec(), DHTValue::with_ttl(vec![1], 60.0));
+        cache.insert(b"b".to_vec(), DHTValue::with_ttl(vec![2], 60.0));
+        assert!(cache.get(b"a").is_some());
+        cache.insert(b"c".to_vec(), DHTValue::with_ttl(vec![3], 60.0));
+
+        assert!(cache.get(b"a").is_some());
+        assert!(cache.get(b"b").is_none());
+        assert!(cache.get(b"c").is_some());
+
+        cache.set_capacity(0);
+        assert!(cache.entries.is_empty());
+        cache.insert(b"d".to_vec(), DHTValue::with_ttl(vec![4], 60.0));
+        assert!(cache.get(b"d").is_none());
+    }
+
+    /// 16 nodes with 4-bit node IDs (in the high nibble of a 20-byte ID);
+    /// node `x` knows `x ^ 8`, `x ^ 4`, `x ^ 2`, `x ^ 1` — one peer per
+    /// k-bucket, as in a converged Kademlia routing table.
+    struct Hypercube {
+        peers: Vec<PeerId>,
+        down: HashSet<PeerId>,
+        find_fails: HashSet<PeerId>,
+        stored: HashMap<PeerId, Vec<Vec<u8>>>,
+    }
+
+    fn node_id(x: usize) -> Vec<u8> {
+        let mut id = vec![0u8; 20];
+        id[0] = (x as u8) << 4;
+        id
+    }
+
+    impl Hypercube {
+        fn new() -> Self {
+            Self {
+                peers: (0..16).map(|_| PeerId::random()).collect(),
+                down: HashSet::new(),
+                find_fails: HashSet::new(),
+                stored: HashMap::new(),
+            }
+        }
+
+        fn index(&self, peer: PeerId) -> usize {
+            self.peers.iter().position(|p| *p == peer).unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl DhtRpc for Hypercube {
+        async fn find(&mut self, peer: PeerId, request: FindRequest) -> Result<FindResponse> {
+            if self.down.contains(&peer) || self.find_fails.contains(&peer) {
+                return Err(Error::Network("unreachable".into()));
+            }
+            let x = self.index(peer);
+            let neighbours = [x ^ 8, x ^ 4, x ^ 2, x ^ 1];
+            let results = request
+                .keys
+                .iter()
+                .map(|_| {
+                    FindResult::not_found(
+                        neighbours.iter().map(|&n| node_id(n)).collect(),
+                        neighbours
+                            .iter()
+                            .map(|&n| self.peers[n].to_bytes())
+                            .collect(),
+                    )
+                })
+                .collect();
+            Ok(FindResponse {
+                auth: None,
+                results,
+                peer: Some(NodeInfo {
+                    node_id: node_id(x),
+                }),
+            })
+        }
+
+        async fn store(&mut self, peer: PeerId, request: StoreRequest) -> Result<StoreResponse> {
+            if self.down.contains(&peer) {
+                return Err(Error::Network("unreachable".into()));
+            }
+            let n = request.keys.len();
+            self.stored.entry(peer).or_default().extend(request.keys);
+            Ok(StoreResponse {
+                auth: None,
+                store_ok: vec![true; n],
+                peer: None,
+            })
+        }
+    }
+
+    fn config(replicas: usize) -> RoutingConfig {
+        RoutingConfig {
+            replicas,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_xor_distance_pads_shorter_id() {
+        assert_eq!(xor_distance(&[0xF0, 0x01], &[0x0F]), vec![0xFF, 0x01]);
+        assert!(xor_distance(&node_id(5), &node_id(4)) < xor_distance(&node_id(5), &node_id(7)));
+    }
+
+    #[tokio::test]
+    async fn test_find_values_batches_and_runs_concurrently() {
+        let peers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        let down = peers[2];
+        let keys: Vec<Vec<u8>> = (0..80u8).map(|b| vec![b]).collect();
+        let requests = std::sync::atomic::AtomicUsize::new(0);
+
+        let start = Instant::now();
+        let found = find_values(
+            &peers,
+            &keys,
+            None,
+            &FanOutConfig::default(),
+            |peer, request| {
+                requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let holder = peers[0];
+                async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    if peer == down {
+                        return Err(Error::Network("unreachable".into()));
+                    }
+                    // Every peer holds the even blocks; only the first the odd.
+                    let results = request
+                        .keys
+                        .iter()
+                        .map(|k| {
+                            if k[0] % 2 == 0 || peer == holder {
+                                FindResult::found_regular(k.clone(), 0.0, vec![], vec![])
+                            } else {
+                                FindResult::not_found(vec![], vec![])
+                            }
+                        })
+                        .collect();
+                    Ok(FindResponse {
+                        auth: None,
+                        results,
+                        peer: None,
+                    })
+                }
+            },
+        )
+        .await;
+
+        // 80 keys in chunks of 32 → 3 requests per peer, all in flight at
+        // once (one after another they would take 900 ms).
+        assert_eq!(requests.into_inner(), 9);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(found.failed, 3);
+        assert_eq!(found.responded.len(), 2);
+        assert_eq!(found.values.len(), 80);
+        let even: Vec<PeerId> = found.values[&vec![4u8]].iter().map(|(p, _)| *p).collect();
+        assert_eq!(even, [peers[0], peers[1]]);
+        assert_eq!(found.values[&vec![5u8]].len(), 1);
+        assert_eq!(found.results().count(), 120);
+    }
+
+    #[tokio::test]
+    async fn test_find_closest_walks_to_key() {
+        let mut net = Hypercube::new();
+        let local = PeerId::random();
+        let key = node_id(5);
+        // Seed with the node farthest from the key.
+        let seed = net.peers[5 ^ 15];
+
+        let found = find_closest(
+            &mut net,
+            local,
+            std::slice::from_ref(&key),
+            &[seed],
+            &config(3),
+        )
+        .await;
+        let expected: Vec<PeerId> = [5, 4, 7].iter().map(|&i| net.peers[i]).collect();
+        assert_eq!(found[&key], expected);
+    }
+
+    #[tokio::test]
+    async fn test_find_closest_skips_unreachable_peers() {
+        let mut net = Hypercube::new();
+        net.down.insert(net.peers[5]);
+        let key = node_id(5);
+        let seed = net.peers[0];
+
+        let found = find_closest(
+            &mut net,
+            PeerId::random(),
+            std::slice::from_ref(&key),
+            &[seed],
+            &config(3),
+        )
+        .await;
+        let expected: Vec<PeerId> = [4, 7, 6].iter().map(|&i| net.peers[i]).collect();
+        assert_eq!(found[&key], expected);
+    }
+
+    #[tokio::test]
+    async fn test_store_on_closest_replicates_per_key() {
+        let mut net = Hypercube::new();
+        let seed = net.peers[0];
+        let (k1, k2) = (node_id(5), node_id(12));
+        let request = StoreRequest {
+            auth: None,
+            keys: vec![k1.clone(), k2.clone()],
+            subkeys: vec![vec![], vec![]],
+            values: vec![b"a".to_vec(), b"b".to_vec()],
+            expiration_time: vec![1.0, 1.0],
+            in_cache: vec![false, false],
+            peer: None,
+        };
+
+        let outcome =
+            store_on_closest(&mut net, PeerId::random(), request, &[seed], &config(2)).await;
+        assert!(outcome.any_stored());
+        assert_eq!(outcome.stored_on[0], vec![net.peers[5], net.peers[4]]);
+        assert_eq!(outcome.stored_on[1], vec![net.peers[12], net.peers[13]]);
+        assert_eq!(outcome.peers_reached(), 4);
+        assert_eq!(net.stored[&net.peers[5]], vec![k1]);
+        assert_eq!(net.stored[&net.peers[12]], vec![k2]);
+    }
+
+    #[tokio::test]
+    async fn test_store_falls_back_to_seeds_without_routing() {
+        let mut net = Hypercube::new();
+        let seed = net.peers[3];
+        net.find_fails.insert(seed);
+        let request = StoreRequest {
+            auth: None,
+            keys: vec![node_id(9)],
+            subkeys: vec![vec![]],
+            values: vec![b"v".to_vec()],
+            expiration_time: vec![1.0],
+            in_cache: vec![false],
+            peer: None,
+        };
+
+        let outcome =
+            store_on_closest(&mut net, PeerId::random(), request, &[seed], &config(3)).await;
+        assert_eq!(outcome.stored_on[0], vec![seed]);
+        assert_eq!(outcome.attempts.len(), 1);
+    }
 }
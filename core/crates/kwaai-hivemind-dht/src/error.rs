@@ -30,6 +30,12 @@ pub enum Error {
     #[error("Network error: {0}")]
     Network(String),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+
     #[error("Invalid DHT time: {0}")]
     InvalidTime(f64),
 
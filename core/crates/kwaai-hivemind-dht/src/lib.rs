@@ -9,20 +9,29 @@
 //! - Batch STORE operations
 //! - MessagePack serialization for values
 //! - Protobuf wire format for RPC messages
+//! - Optional Hivemind token authorization for private swarms
+//! - Optional on-disk persistence of stored records (feature `persistence`)
+//! - Pluggable validation of stored records
 
+pub mod auth;
 pub mod client;
 pub mod codec;
 pub mod error;
+#[cfg(feature = "persistence")]
+pub mod persist;
 pub mod protocol;
 pub mod server;
+pub mod validate;
 pub mod value;
 
-pub use client::HivemindDHT;
+pub use auth::{AuthorizedRpc, TokenAuthorizer};
+pub use client::{find_values, CachedGet, FanOutConfig, FoundValues, HivemindDHT};
 pub use error::{Error, Result};
 pub use protocol::{
     AccessToken, FindResult, NodeInfo, RequestAuthInfo, ResponseAuthInfo, ResultType,
 };
-pub use server::DHTStorage;
+pub use server::{DHTStorage, MaintenanceConfig, MaintenanceReport, StorageMetrics};
+pub use validate::{KeyValidator, Record};
 pub use value::{DHTExpiration, DHTValue};
 
 /// Hivemind DHT protocol handlers
@@ -0,0 +1,193 @@
+//! On-disk persistence of DHT records (feature `persistence`)
+//!
+//! [`DHTStorage`](crate::DHTStorage) keeps its records in memory; with a
+//! [`RecordStore`] attached every accepted STORE is also written to a sled
+//! database, and the records still alive are loaded back when the node
+//! restarts. Expired records are compacted away on load and by the regular
+//! maintenance pass, so the database does not grow past what the node would
+//! hold in memory.
+//!
+//! Each record is stored under its DHT key as
+//!
+//! ```text
+//! [expiration_time: f64 LE][in_cache: u8][value bytes]
+//! ```
+
+use crate::error::{Error, Result};
+use std::path::Path;
+use tracing::{debug, info};
+
+/// Length of the record header before the value bytes
+const HEADER_LEN: usize = 9;
+
+/// A record as kept on disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistedRecord {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub expiration_time: f64,
+    pub in_cache: bool,
+}
+
+fn encode(value: &[u8], expiration_time: f64, in_cache: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + value.len());
+    bytes.extend_from_slice(&expiration_time.to_le_bytes());
+    bytes.push(in_cache as u8);
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+fn decode(key: &[u8], bytes: &[u8]) -> Option<PersistedRecord> {
+    if bytes.len() < HEADER_LEN {
+        return None;
+    }
+    let expiration_time = f64::from_le_bytes(bytes[..8].try_into().ok()?);
+    Some(PersistedRecord {
+        key: key.to_vec(),
+        value: bytes[HEADER_LEN..].to_vec(),
+        expiration_time,
+        in_cache: bytes[8] != 0,
+    })
+}
+
+fn db_error(e: sled::Error) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+/// sled database holding the records of one node
+#[derive(Debug, Clone)]
+pub struct RecordStore {
+    db: sled::Db,
+}
+
+impl RecordStore {
+    /// Open (or create) the database in directory `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path).map_err(db_error)?;
+        info!("DHT records persisted in {}", path.display());
+        Ok(Self { db })
+    }
+
+    /// Write a record, replacing any earlier value under `key`
+    pub fn insert(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        expiration_time: f64,
+        in_cache: bool,
+    ) -> Result<()> {
+        self.db
+            .insert(key, encode(value, expiration_time, in_cache))
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Records not yet expired at `now`; expired and unreadable ones are
+    /// deleted on the way
+    pub fn load(&self, now: f64) -> Result<Vec<PersistedRecord>> {
+        let mut live = Vec::new();
+        let mut stale = Vec::new();
+        for entry in self.db.iter() {
+            let (key, bytes) = entry.map_err(db_error)?;
+            match decode(&key, &bytes) {
+                Some(record) if record.expiration_time > now => live.push(record),
+                _ => stale.push(key),
+            }
+        }
+        for key in &stale {
+            self.db.remove(key).map_err(db_error)?;
+        }
+        if !stale.is_empty() {
+            debug!("Dropped {} expired persisted DHT records", stale.len());
+        }
+        Ok(live)
+    }
+
+    /// Delete records that expired by `now`, returning how many
+    pub fn compact(&self, now: f64) -> Result<usize> {
+        let mut removed = 0;
+        for entry in self.db.iter() {
+            let (key, bytes) = entry.map_err(db_error)?;
+            let expired = decode(&key, &bytes).is_none_or(|r| r.expiration_time <= now);
+            // Skip records a concurrent STORE just renewed.
+            if expired
+                && self
+                    .db
+                    .compare_and_swap(&key, Some(&bytes), None as Option<&[u8]>)
+                    .map_err(db_error)?
+                    .is_ok()
+            {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Records on disk, including expired ones not yet compacted
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    /// Whether the database holds no records
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    /// Write pending changes to disk
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush().map_err(db_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("kwaai-dht-persist-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn records_survive_reopen_and_expire() {
+        let dir = temp_dir("reopen");
+        {
+            let store = RecordStore::open(&dir).unwrap();
+            store.insert(b"alive", b"value", 200.0, false).unwrap();
+            store.insert(b"cached", b"v2", 300.0, true).unwrap();
+            store.insert(b"stale", b"old", 50.0, false).unwrap();
+            store.flush().unwrap();
+        }
+
+        let store = RecordStore::open(&dir).unwrap();
+        let mut live = store.load(100.0).unwrap();
+        live.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            live,
+            [
+                PersistedRecord {
+                    key: b"alive".to_vec(),
+                    value: b"value".to_vec(),
+                    expiration_time: 200.0,
+                    in_cache: false,
+                },
+                PersistedRecord {
+                    key: b"cached".to_vec(),
+                    value: b"v2".to_vec(),
+                    expiration_time: 300.0,
+                    in_cache: true,
+                },
+            ]
+        );
+        // Loading dropped the expired record from disk.
+        assert_eq!(store.len(), 2);
+
+        assert_eq!(store.compact(250.0).unwrap(), 1);
+        assert_eq!(store.load(250.0).unwrap().len(), 1);
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
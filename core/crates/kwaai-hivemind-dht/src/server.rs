@@ -1,13 +1,20 @@
 //! Hivemind DHT server for responding to FIND and STORE requests
 
+use crate::auth::{request_nonce, request_signer, TokenAuthorizer};
 use crate::codec::{DHTRequest, DHTResponse};
 use crate::protocol::*;
+use crate::validate::{KeyValidator, Record};
 use crate::value::get_dht_time;
 use crate::Result;
 use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use tracing::{debug, warn};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
 
 /// DHT storage backend
 #[derive(Debug, Clone)]
@@ -20,6 +27,104 @@ pub struct DHTStorage {
 
     /// Known peers (for nearest peer queries)
     peers: Arc<RwLock<Vec<PeerId>>>,
+
+    /// Records originated by this node, kept for republishing
+    own_records: Arc<RwLock<HashMap<Vec<u8>, OwnRecord>>>,
+
+    /// Storage churn counters
+    counters: Arc<ChurnCounters>,
+
+    /// Signs responses and checks incoming requests in private swarms
+    auth: Option<ServerAuth>,
+
+    /// Largest value STORE accepts, in bytes
+    max_value_size: Option<usize>,
+
+    /// Rules every STORE record must pass
+    validators: Vec<Arc<dyn KeyValidator>>,
+
+    /// On-disk copy of the stored records, restored after a restart
+    #[cfg(feature = "persistence")]
+    persist: Option<crate::persist::RecordStore>,
+}
+
+#[derive(Debug, Clone)]
+struct ServerAuth {
+    authorizer: Arc<TokenAuthorizer>,
+    /// Reject requests that fail validation instead of just logging them
+    require: bool,
+}
+
+/// A record this node published itself
+#[derive(Debug, Clone)]
+struct OwnRecord {
+    subkey: Vec<u8>,
+    value: Vec<u8>,
+    expiration_time: f64,
+    /// Lifetime requested at publish time; reused on every renewal
+    ttl: f64,
+}
+
+#[derive(Debug, Default)]
+struct ChurnCounters {
+    stored: AtomicU64,
+    rejected: AtomicU64,
+    oversized: AtomicU64,
+    invalid: AtomicU64,
+    pruned: AtomicU64,
+    republished: AtomicU64,
+    maintenance_runs: AtomicU64,
+}
+
+/// Storage churn metrics, cumulative since the storage was created
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageMetrics {
+    /// Entries currently held (including not-yet-pruned expired ones)
+    pub entries: usize,
+    /// Entries held that this node originated
+    pub own_records: usize,
+    /// Values accepted by STORE
+    pub stored: u64,
+    /// Values rejected by STORE because they were already expired
+    pub rejected: u64,
+    /// Values rejected by STORE because they exceeded the size limit
+    pub oversized: u64,
+    /// Values rejected by STORE because a validator refused them
+    pub invalid: u64,
+    /// Expired entries removed by maintenance
+    pub pruned: u64,
+    /// Own records renewed by maintenance
+    pub republished: u64,
+    /// Completed maintenance passes
+    pub maintenance_runs: u64,
+}
+
+/// Settings for the background maintenance task
+#[derive(Debug, Clone)]
+pub struct MaintenanceConfig {
+    /// How often to prune and republish
+    pub interval: Duration,
+    /// Renew own records whose remaining lifetime is below this many seconds
+    pub republish_window: f64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            republish_window: 90.0,
+        }
+    }
+}
+
+/// Outcome of a single [`DHTStorage::maintain`] pass
+#[derive(Debug, Default)]
+pub struct MaintenanceReport {
+    /// Expired entries removed
+    pub pruned: usize,
+    /// Renewed own records as a STORE request ready to send to remote peers,
+    /// or `None` if nothing needed renewal
+    pub republish: Option<StoreRequest>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,7 +142,80 @@ impl DHTStorage {
             storage: Arc::new(RwLock::new(HashMap::new())),
             local_peer_id,
             peers: Arc::new(RwLock::new(Vec::new())),
+            own_records: Arc::new(RwLock::new(HashMap::new())),
+            counters: Arc::new(ChurnCounters::default()),
+            auth: None,
+            max_value_size: None,
+            validators: Vec::new(),
+            #[cfg(feature = "persistence")]
+            persist: None,
+        }
+    }
+
+    /// Persist stored records in the sled database at `path`, first loading
+    /// the records it holds that have not expired yet
+    ///
+    /// Lets a restarting node keep serving the values it was holding for
+    /// the swarm instead of waiting for their publishers to store them again.
+    #[cfg(feature = "persistence")]
+    pub fn with_persistence(mut self, path: &std::path::Path) -> Result<Self> {
+        let store = crate::persist::RecordStore::open(path)?;
+        let records = store.load(get_dht_time())?;
+        let restored = records.len();
+        if let Ok(mut storage) = self.storage.write() {
+            for record in records {
+                storage.insert(
+                    record.key,
+                    StoredValue {
+                        value: record.value,
+                        expiration_time: record.expiration_time,
+                        in_cache: record.in_cache,
+                    },
+                );
+            }
+        }
+        info!("Restored {} DHT records from disk", restored);
+        self.persist = Some(store);
+        Ok(self)
+    }
+
+    /// Write an accepted record through to disk when persistence is enabled
+    fn persist_record(&self, key: &[u8], stored: &StoredValue) {
+        #[cfg(feature = "persistence")]
+        if let Some(store) = &self.persist {
+            if let Err(e) =
+                store.insert(key, &stored.value, stored.expiration_time, stored.in_cache)
+            {
+                warn!("Failed to persist DHT record: {}", e);
+            }
         }
+        #[cfg(not(feature = "persistence"))]
+        let _ = (key, stored);
+    }
+
+    /// Sign responses with `authorizer` and validate incoming requests
+    ///
+    /// With `require` set, [`handle_request`](Self::handle_request) rejects
+    /// requests without a valid token and signature; otherwise failures are
+    /// only logged, so unauthenticated peers can still use this node.
+    pub fn with_authorizer(mut self, authorizer: Arc<TokenAuthorizer>, require: bool) -> Self {
+        self.auth = Some(ServerAuth {
+            authorizer,
+            require,
+        });
+        self
+    }
+
+    /// Reject STORE values larger than `bytes`
+    pub fn with_max_value_size(mut self, bytes: usize) -> Self {
+        self.max_value_size = Some(bytes);
+        self
+    }
+
+    /// Add a rule STORE records must pass; see [`crate::validate`]
+    pub fn with_validator(mut self, validator: impl KeyValidator + 'static) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
     }
 
     /// Update known peers (from Kademlia routing table)
@@ -47,16 +225,175 @@ impl DHTStorage {
         }
     }
 
-    /// Clean up expired values
-    pub fn cleanup_expired(&self) {
+    /// Clean up expired values, returning how many were removed
+    ///
+    /// Expired records are compacted out of the on-disk copy as well.
+    pub fn cleanup_expired(&self) -> usize {
         let now = get_dht_time();
-        if let Ok(mut storage) = self.storage.write() {
+        let pruned = if let Ok(mut storage) = self.storage.write() {
+            let before = storage.len();
             storage.retain(|_, v| v.expiration_time > now);
+            before - storage.len()
+        } else {
+            0
+        };
+        if let Ok(mut own) = self.own_records.write() {
+            own.retain(|_, r| r.expiration_time > now);
+        }
+        #[cfg(feature = "persistence")]
+        if let Some(store) = &self.persist {
+            if let Err(e) = store.compact(now) {
+                warn!("Failed to compact persisted DHT records: {}", e);
+            }
+        }
+        self.counters
+            .pruned
+            .fetch_add(pruned as u64, Ordering::Relaxed);
+        pruned
+    }
+
+    /// Handle a STORE request for records this node originated
+    ///
+    /// Behaves like [`handle_store`](Self::handle_store) but also remembers
+    /// each accepted record so [`maintain`](Self::maintain) can renew it
+    /// before it expires.
+    pub fn handle_store_own(&self, request: StoreRequest) -> StoreResponse {
+        let now = get_dht_time();
+        let response = self.handle_store(request.clone());
+        if let Ok(mut own) = self.own_records.write() {
+            for (i, key) in request.keys.iter().enumerate() {
+                if !response.store_ok.get(i).copied().unwrap_or(false) {
+                    continue;
+                }
+                let expiration_time = request.expiration_time[i];
+                own.insert(
+                    key.clone(),
+                    OwnRecord {
+                        subkey: request.subkeys.get(i).cloned().unwrap_or_default(),
+                        value: request.values.get(i).cloned().unwrap_or_default(),
+                        expiration_time,
+                        ttl: expiration_time - now,
+                    },
+                );
+            }
         }
+        response
     }
 
-    /// Handle a STORE request
+    /// Run one maintenance pass: prune expired entries and renew own records
+    /// expiring within `republish_window` seconds.
+    ///
+    /// Renewed records are re-stored locally with their original TTL and
+    /// returned as a STORE request so the caller can push them to remote peers.
+    pub fn maintain(&self, republish_window: f64) -> MaintenanceReport {
+        let pruned = self.cleanup_expired();
+        let now = get_dht_time();
+
+        let mut keys = Vec::new();
+        let mut subkeys = Vec::new();
+        let mut values = Vec::new();
+        let mut expirations = Vec::new();
+        if let Ok(mut own) = self.own_records.write() {
+            for (key, record) in own.iter_mut() {
+                if record.expiration_time - now >= republish_window {
+                    continue;
+                }
+                record.expiration_time = now + record.ttl;
+                keys.push(key.clone());
+                subkeys.push(record.subkey.clone());
+                values.push(record.value.clone());
+                expirations.push(record.expiration_time);
+            }
+        }
+
+        let republish = if keys.is_empty() {
+            None
+        } else {
+            let n = keys.len();
+            let request = StoreRequest::new(
+                NodeInfo::from_peer_id(self.local_peer_id),
+                keys,
+                subkeys,
+                values,
+                expirations,
+                vec![false; n],
+            );
+            let _ = self.handle_store(request.clone());
+            self.counters
+                .republished
+                .fetch_add(n as u64, Ordering::Relaxed);
+            Some(request)
+        };
+
+        self.counters
+            .maintenance_runs
+            .fetch_add(1, Ordering::Relaxed);
+        MaintenanceReport { pruned, republish }
+    }
+
+    /// Spawn a tokio task that runs [`maintain`](Self::maintain) every
+    /// `config.interval`.
+    ///
+    /// Renewed own records are sent on `republish_tx` (if given) so the caller
+    /// can forward them to remote peers. The task stops when the receiver is
+    /// dropped or the handle is aborted.
+    pub fn spawn_maintenance(
+        &self,
+        config: MaintenanceConfig,
+        republish_tx: Option<mpsc::UnboundedSender<StoreRequest>>,
+    ) -> JoinHandle<()> {
+        let storage = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(config.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await; // first tick fires immediately
+            loop {
+                ticker.tick().await;
+                let report = storage.maintain(config.republish_window);
+                if report.pruned > 0 {
+                    debug!("DHT maintenance: pruned {} expired entries", report.pruned);
+                }
+                if let Some(request) = report.republish {
+                    info!(
+                        "DHT maintenance: republishing {} own records",
+                        request.keys.len()
+                    );
+                    if let Some(tx) = &republish_tx {
+                        if tx.send(request).is_err() {
+                            debug!("DHT maintenance: republish receiver dropped, stopping");
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Storage churn metrics
+    pub fn metrics(&self) -> StorageMetrics {
+        StorageMetrics {
+            entries: self.storage.read().map(|s| s.len()).unwrap_or(0),
+            own_records: self.own_records.read().map(|o| o.len()).unwrap_or(0),
+            stored: self.counters.stored.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+            oversized: self.counters.oversized.load(Ordering::Relaxed),
+            invalid: self.counters.invalid.load(Ordering::Relaxed),
+            pruned: self.counters.pruned.load(Ordering::Relaxed),
+            republished: self.counters.republished.load(Ordering::Relaxed),
+            maintenance_runs: self.counters.maintenance_runs.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Handle a STORE request made by this node
+    ///
+    /// Records are validated as if this node had signed the request; remote
+    /// requests go through [`handle_request`](Self::handle_request).
     pub fn handle_store(&self, request: StoreRequest) -> StoreResponse {
+        self.store(request, Some(self.local_peer_id))
+    }
+
+    /// Store the records of `request`, signed by `signer` if known
+    fn store(&self, request: StoreRequest, signer: Option<PeerId>) -> StoreResponse {
         debug!("Handling STORE request with {} keys", request.keys.len());
 
         let mut store_ok = Vec::new();
@@ -71,17 +408,42 @@ impl DHTStorage {
                     .unwrap_or(get_dht_time() + 3600.0);
                 let in_cache = request.in_cache.get(i).copied().unwrap_or(false);
 
+                if self.max_value_size.is_some_and(|max| value.len() > max) {
+                    debug!("Rejected oversized value ({} bytes)", value.len());
+                    store_ok.push(false);
+                    self.counters.oversized.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+
+                let record = Record {
+                    key,
+                    subkey: request.subkeys.get(i).map_or(&[], Vec::as_slice),
+                    value: &value,
+                    expiration_time,
+                    signer,
+                };
+                if let Some(e) = self
+                    .validators
+                    .iter()
+                    .find_map(|v| v.validate(&record).err())
+                {
+                    debug!("Rejected DHT record: {}", e);
+                    store_ok.push(false);
+                    self.counters.invalid.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+
                 // Only store if not expired
                 if expiration_time > get_dht_time() {
-                    storage.insert(
-                        key.clone(),
-                        StoredValue {
-                            value,
-                            expiration_time,
-                            in_cache,
-                        },
-                    );
+                    let stored = StoredValue {
+                        value,
+                        expiration_time,
+                        in_cache,
+                    };
+                    self.persist_record(key, &stored);
+                    storage.insert(key.clone(), stored);
                     store_ok.push(true);
+                    self.counters.stored.fetch_add(1, Ordering::Relaxed);
 
                     // // Verbose logging commented - key is SHA1 hash (gibberish)
                     // let key_str = String::from_utf8_lossy(key);
@@ -89,6 +451,7 @@ impl DHTStorage {
                 } else {
                     warn!("Rejected expired value");
                     store_ok.push(false);
+                    self.counters.rejected.fetch_add(1, Ordering::Relaxed);
                 }
             }
         } else {
@@ -175,20 +538,39 @@ impl DHTStorage {
     }
 
     /// Handle any DHT request
+    ///
+    /// With an authorizer set, the request is validated first and the
+    /// response is signed; see [`with_authorizer`](Self::with_authorizer).
     pub fn handle_request(&self, request: DHTRequest) -> Result<DHTResponse> {
-        match request {
-            DHTRequest::Store(store_req) => Ok(DHTResponse::Store(self.handle_store(store_req))),
-            DHTRequest::Find(find_req) => Ok(DHTResponse::Find(self.handle_find(find_req))),
+        let nonce = request_nonce(&request);
+        let mut signer = None;
+        if let Some(auth) = &self.auth {
+            match auth.authorizer.validate_request(&request) {
+                Ok(()) => signer = request_signer(&request),
+                Err(e) if auth.require => {
+                    warn!("Rejected unauthorized DHT request: {}", e);
+                    return Err(e);
+                }
+                Err(e) => debug!("Serving unauthenticated DHT request: {}", e),
+            }
+        }
+        let mut response = match request {
+            DHTRequest::Store(store_req) => DHTResponse::Store(self.store(store_req, signer)),
+            DHTRequest::Find(find_req) => DHTResponse::Find(self.handle_find(find_req)),
             DHTRequest::Ping(_ping_req) => {
                 // Simple ping response
-                Ok(DHTResponse::Ping(PingResponse {
+                DHTResponse::Ping(PingResponse {
                     auth: Some(ResponseAuthInfo::new()),
                     peer: Some(NodeInfo::from_peer_id(self.local_peer_id)),
                     dht_time: get_dht_time(),
                     available: true,
-                }))
+                })
             }
+        };
+        if let Some(auth) = &self.auth {
+            auth.authorizer.sign_response(&mut response, &nonce);
         }
+        Ok(response)
     }
 
     /// Get statistics about stored data
@@ -264,5 +646,147 @@ mod tests {
 
         let store_res = storage.handle_store(store_req);
         assert!(!store_res.store_ok[0]); // Should not be stored
+        assert_eq!(storage.metrics().rejected, 1);
+    }
+
+    #[test]
+    fn test_validators_reject_records() {
+        let peer_id = PeerId::random();
+        let storage = DHTStorage::new(peer_id)
+            .with_validator(crate::validate::ServerInfoSchema::new([b"block".to_vec()]));
+        let request = StoreRequest::new(
+            NodeInfo::from_peer_id(peer_id),
+            vec![b"block".to_vec(), b"other".to_vec()],
+            vec![vec![], vec![]],
+            vec![b"junk".to_vec(), b"junk".to_vec()],
+            vec![get_dht_time() + 60.0; 2],
+            vec![false; 2],
+        );
+
+        let response = storage.handle_store(request);
+        assert_eq!(response.store_ok, vec![false, true]);
+        assert_eq!(storage.metrics().invalid, 1);
+    }
+
+    #[test]
+    fn test_oversized_value_rejected() {
+        let peer_id = PeerId::random();
+        let storage = DHTStorage::new(peer_id).with_max_value_size(8);
+        let request = StoreRequest::new(
+            NodeInfo::from_peer_id(peer_id),
+            vec![b"small".to_vec(), b"large".to_vec()],
+            vec![vec![], vec![]],
+            vec![b"value".to_vec(), vec![0u8; 9]],
+            vec![get_dht_time() + 60.0; 2],
+            vec![false; 2],
+        );
+
+        let response = storage.handle_store(request);
+        assert_eq!(response.store_ok, vec![true, false]);
+        let metrics = storage.metrics();
+        assert_eq!(metrics.stored, 1);
+        assert_eq!(metrics.oversized, 1);
+    }
+
+    fn own_store(peer_id: PeerId, key: &[u8], ttl: f64) -> StoreRequest {
+        StoreRequest::new(
+            NodeInfo::from_peer_id(peer_id),
+            vec![key.to_vec()],
+            vec![b"subkey".to_vec()],
+            vec![b"value".to_vec()],
+            vec![get_dht_time() + ttl],
+            vec![false],
+        )
+    }
+
+    #[test]
+    fn test_maintain_prunes_expired() {
+        let peer_id = PeerId::random();
+        let storage = DHTStorage::new(peer_id);
+        storage.handle_store(own_store(peer_id, b"short", 0.05));
+        storage.handle_store(own_store(peer_id, b"long", 3600.0));
+        std::thread::sleep(Duration::from_millis(100));
+
+        let report = storage.maintain(0.0);
+        assert_eq!(report.pruned, 1);
+        assert!(report.republish.is_none());
+        assert_eq!(storage.stats(), (1, 1));
+        assert_eq!(storage.metrics().pruned, 1);
+    }
+
+    #[test]
+    fn test_maintain_republishes_own_records() {
+        let peer_id = PeerId::random();
+        let storage = DHTStorage::new(peer_id);
+        storage.handle_store_own(own_store(peer_id, b"mine", 60.0));
+        storage.handle_store(own_store(peer_id, b"theirs", 60.0));
+
+        // Outside the window: nothing to renew.
+        assert!(storage.maintain(10.0).republish.is_none());
+
+        // Inside the window: only our own record is renewed, with its subkey.
+        let request = storage.maintain(120.0).republish.expect("republish");
+        assert_eq!(request.keys, vec![b"mine".to_vec()]);
+        assert_eq!(request.subkeys, vec![b"subkey".to_vec()]);
+        assert!(request.expiration_time[0] > get_dht_time() + 59.0);
+
+        let metrics = storage.metrics();
+        assert_eq!(metrics.own_records, 1);
+        assert_eq!(metrics.republished, 1);
+        assert_eq!(metrics.maintenance_runs, 2);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_maintenance_sends_republish() {
+        let peer_id = PeerId::random();
+        let storage = DHTStorage::new(peer_id);
+        storage.handle_store_own(own_store(peer_id, b"mine", 60.0));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let config = MaintenanceConfig {
+            interval: Duration::from_millis(10),
+            republish_window: 120.0,
+        };
+        let handle = storage.spawn_maintenance(config, Some(tx));
+        let request = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+            .await
+            .expect("maintenance tick")
+            .expect("republish request");
+        assert_eq!(request.keys, vec![b"mine".to_vec()]);
+        handle.abort();
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn persisted_records_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("kwaai-dht-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let peer_id = PeerId::random();
+        let request = |key: &[u8], ttl: f64| StoreRequest {
+            auth: Some(RequestAuthInfo::new()),
+            keys: vec![key.to_vec()],
+            subkeys: vec![vec![]],
+            values: vec![b"held for the swarm".to_vec()],
+            expiration_time: vec![get_dht_time() + ttl],
+            in_cache: vec![false],
+            peer: None,
+        };
+        {
+            let storage = DHTStorage::new(peer_id).with_persistence(&dir).unwrap();
+            storage.handle_store(request(b"third_party", 3600.0));
+            storage.handle_store(request(b"short_lived", 0.2));
+        }
+        std::thread::sleep(Duration::from_millis(300));
+
+        let storage = DHTStorage::new(peer_id).with_persistence(&dir).unwrap();
+        assert_eq!(storage.stats(), (1, 1));
+        let found = storage.handle_find(FindRequest {
+            auth: Some(RequestAuthInfo::new()),
+            keys: vec![b"third_party".to_vec()],
+            peer: None,
+        });
+        assert_eq!(found.results[0].value, b"held for the swarm");
+        drop(storage);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,283 @@
+//! Validation of STORE records
+//!
+//! [`DHTStorage`](crate::DHTStorage) passes every record of a STORE request
+//! to its [`KeyValidator`]s before storing it. A record any validator rejects
+//! gets `store_ok = false` and is never stored, so it is neither served to
+//! FIND requests nor republished.
+//!
+//! Built-in rules:
+//!
+//! - [`SizeLimit`]: caps key and subkey lengths
+//! - [`ExpirationLimit`]: refuses expirations that are not finite or lie
+//!   further ahead than the longest record TTL in use
+//! - [`ServerInfoSchema`]: requires block keys to hold a Petals `ServerInfo`
+//! - [`SubkeyMatchesSigner`]: in authenticated swarms, a record whose subkey
+//!   names a peer must be stored by that peer
+
+use crate::value::{get_dht_time, DHTExpiration};
+use crate::{Error, Result};
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::fmt;
+
+/// One record of a STORE request, as seen by validators
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub key: &'a [u8],
+    pub subkey: &'a [u8],
+    pub value: &'a [u8],
+    pub expiration_time: DHTExpiration,
+    /// Peer whose access token signed the request; `None` when the request
+    /// carried no valid signature
+    pub signer: Option<PeerId>,
+}
+
+/// A rule a record must satisfy to be stored
+pub trait KeyValidator: Send + Sync + fmt::Debug {
+    /// Check `record`; the error explains why it is refused
+    fn validate(&self, record: &Record<'_>) -> Result<()>;
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidRecord(reason.into())
+}
+
+/// Limits on key and subkey length, in bytes
+///
+/// Keys are normally 20-byte DHT IDs and subkeys short msgpack strings.
+#[derive(Debug, Clone)]
+pub struct SizeLimit {
+    pub max_key: usize,
+    pub max_subkey: usize,
+}
+
+impl Default for SizeLimit {
+    fn default() -> Self {
+        Self {
+            max_key: 64,
+            max_subkey: 256,
+        }
+    }
+}
+
+impl KeyValidator for SizeLimit {
+    fn validate(&self, record: &Record<'_>) -> Result<()> {
+        if record.key.len() > self.max_key {
+            return Err(invalid(format!("key of {} bytes", record.key.len())));
+        }
+        if record.subkey.len() > self.max_subkey {
+            return Err(invalid(format!("subkey of {} bytes", record.subkey.len())));
+        }
+        Ok(())
+    }
+}
+
+/// Refuses expirations more than `max_ttl` seconds ahead
+#[derive(Debug, Clone)]
+pub struct ExpirationLimit {
+    pub max_ttl: f64,
+}
+
+impl KeyValidator for ExpirationLimit {
+    fn validate(&self, record: &Record<'_>) -> Result<()> {
+        if !record.expiration_time.is_finite() {
+            return Err(invalid("expiration is not a finite time"));
+        }
+        let ttl = record.expiration_time - get_dht_time();
+        if ttl > self.max_ttl {
+            return Err(invalid(format!(
+                "expires in {:.0}s, more than {:.0}s ahead",
+                ttl, self.max_ttl
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Requires the values under `block_keys` to be Petals `ServerInfo` records
+///
+/// A `ServerInfo` is msgpack `ExtType(64, [state, throughput, {fields}])`
+/// with `state` from -1 (offline) to 2 (online), a finite non-negative
+/// throughput and string field names. When the fields name a block range,
+/// `start_block` must be below `end_block`.
+#[derive(Debug, Clone, Default)]
+pub struct ServerInfoSchema {
+    block_keys: HashSet<Vec<u8>>,
+}
+
+/// Hivemind's msgpack extension code for tuples
+const TUPLE_EXT: i8 = 64;
+
+impl ServerInfoSchema {
+    /// Check the values stored under `block_keys` (DHT IDs)
+    pub fn new(block_keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self {
+            block_keys: block_keys.into_iter().collect(),
+        }
+    }
+
+    /// Whether `value` is a well-formed `ServerInfo`
+    pub fn check(value: &[u8]) -> Result<()> {
+        let decoded = rmpv::decode::read_value(&mut &value[..])
+            .map_err(|e| invalid(format!("value is not msgpack: {e}")))?;
+        let rmpv::Value::Ext(TUPLE_EXT, inner) = decoded else {
+            return Err(invalid("server info is not a tuple"));
+        };
+        let inner = rmpv::decode::read_value(&mut &inner[..])
+            .map_err(|e| invalid(format!("server info tuple is not msgpack: {e}")))?;
+        let items = inner
+            .as_array()
+            .ok_or_else(|| invalid("server info tuple is not an array"))?;
+
+        match items.first().and_then(rmpv::Value::as_i64) {
+            Some(state) if (-1..=2).contains(&state) => {}
+            _ => return Err(invalid("server info state is not -1..=2")),
+        }
+        match items.get(1).and_then(rmpv::Value::as_f64) {
+            Some(throughput) if throughput.is_finite() && throughput >= 0.0 => {}
+            _ => {
+                return Err(invalid(
+                    "server info throughput is not a non-negative number",
+                ))
+            }
+        }
+        let Some(fields) = items.get(2) else {
+            return Ok(());
+        };
+        let fields = fields
+            .as_map()
+            .ok_or_else(|| invalid("server info fields are not a map"))?;
+        if fields.iter().any(|(k, _)| !k.is_str()) {
+            return Err(invalid("server info field name is not a string"));
+        }
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(name))
+                .map(|(_, v)| v)
+        };
+        match (field("start_block"), field("end_block")) {
+            (None, None) => Ok(()),
+            (Some(start), Some(end)) => match (start.as_i64(), end.as_i64()) {
+                (Some(start), Some(end)) if 0 <= start && start < end => Ok(()),
+                _ => Err(invalid("server info block range is empty or not integers")),
+            },
+            _ => Err(invalid("server info names only one end of its block range")),
+        }
+    }
+}
+
+impl KeyValidator for ServerInfoSchema {
+    fn validate(&self, record: &Record<'_>) -> Result<()> {
+        if self.block_keys.contains(record.key) {
+            Self::check(record.value)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Requires records whose subkey is a peer ID (msgpack base58 string, as in
+/// Petals server records) to be stored by that peer
+///
+/// Meant for authenticated swarms, where the signer is known: a request
+/// without a valid signature cannot store such records at all.
+#[derive(Debug, Clone, Default)]
+pub struct SubkeyMatchesSigner;
+
+impl KeyValidator for SubkeyMatchesSigner {
+    fn validate(&self, record: &Record<'_>) -> Result<()> {
+        let Some(owner) = rmp_serde::from_slice::<String>(record.subkey)
+            .ok()
+            .and_then(|s| s.parse::<PeerId>().ok())
+        else {
+            return Ok(());
+        };
+        match record.signer {
+            Some(signer) if signer == owner => Ok(()),
+            Some(signer) => Err(invalid(format!("record for {owner} stored by {signer}"))),
+            None => Err(invalid(format!("unsigned record for {owner}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_info(state: i64, start: i64, end: i64) -> Vec<u8> {
+        let inner = rmpv::Value::Array(vec![
+            rmpv::Value::from(state),
+            rmpv::Value::from(1.5),
+            rmpv::Value::Map(vec![
+                (rmpv::Value::from("start_block"), rmpv::Value::from(start)),
+                (rmpv::Value::from("end_block"), rmpv::Value::from(end)),
+            ]),
+        ]);
+        let mut inner_bytes = Vec::new();
+        rmpv::encode::write_value(&mut inner_bytes, &inner).unwrap();
+        let mut out = Vec::new();
+        rmpv::encode::write_value(&mut out, &rmpv::Value::Ext(TUPLE_EXT, inner_bytes)).unwrap();
+        out
+    }
+
+    fn record<'a>(key: &'a [u8], subkey: &'a [u8], value: &'a [u8]) -> Record<'a> {
+        Record {
+            key,
+            subkey,
+            value,
+            expiration_time: get_dht_time() + 60.0,
+            signer: None,
+        }
+    }
+
+    #[test]
+    fn test_server_info_schema() {
+        let schema = ServerInfoSchema::new([b"block".to_vec()]);
+        let good = server_info(2, 0, 8);
+        assert!(schema.validate(&record(b"block", b"", &good)).is_ok());
+        assert!(schema
+            .validate(&record(b"block", b"", &server_info(7, 0, 8)))
+            .is_err());
+        assert!(schema
+            .validate(&record(b"block", b"", &server_info(2, 8, 8)))
+            .is_err());
+        assert!(schema.validate(&record(b"block", b"", b"junk")).is_err());
+        // Other keys are not checked.
+        assert!(schema.validate(&record(b"other", b"", b"junk")).is_ok());
+    }
+
+    #[test]
+    fn test_subkey_matches_signer() {
+        let owner = PeerId::random();
+        let subkey = rmp_serde::to_vec(&owner.to_base58()).unwrap();
+        let mut rec = record(b"key", &subkey, b"value");
+
+        assert!(SubkeyMatchesSigner.validate(&rec).is_err());
+        rec.signer = Some(PeerId::random());
+        assert!(SubkeyMatchesSigner.validate(&rec).is_err());
+        rec.signer = Some(owner);
+        assert!(SubkeyMatchesSigner.validate(&rec).is_ok());
+
+        // Subkeys that are not peer IDs are left alone.
+        let prefix = rmp_serde::to_vec("Llama-3-1-8B-Instruct-hf").unwrap();
+        assert!(SubkeyMatchesSigner
+            .validate(&record(b"key", &prefix, b"value"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_size_and_expiration_limits() {
+        let size = SizeLimit::default();
+        assert!(size.validate(&record(&[0; 20], b"sub", b"")).is_ok());
+        assert!(size.validate(&record(&[0; 65], b"sub", b"")).is_err());
+
+        let expiry = ExpirationLimit { max_ttl: 120.0 };
+        let mut rec = record(b"key", b"", b"");
+        assert!(expiry.validate(&rec).is_ok());
+        rec.expiration_time = get_dht_time() + 3600.0;
+        assert!(expiry.validate(&rec).is_err());
+        rec.expiration_time = f64::NAN;
+        assert!(expiry.validate(&rec).is_err());
+    }
+}
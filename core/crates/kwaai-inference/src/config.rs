@@ -1,7 +1,8 @@
 //! Configuration for the inference engine
 
-use crate::DeviceType;
+use crate::{DeviceMap, DeviceType};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 /// Configuration for the inference engine
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +30,25 @@ pub struct EngineConfig {
 
     /// Number of threads for CPU inference
     pub num_threads: usize,
+
+    /// Snapshot directory (config.json, tokenizer.json, *.safetensors) of a
+    /// small draft model for speculative decoding. Must share the target's
+    /// vocabulary.
+    #[serde(default)]
+    pub draft_model: Option<PathBuf>,
+
+    /// Tokens the draft model proposes per verification round.
+    #[serde(default = "default_speculative_tokens")]
+    pub speculative_tokens: usize,
+
+    /// Spread shard blocks over several local devices (see [`DeviceMap`]).
+    /// `None` keeps everything on `device`.
+    #[serde(default)]
+    pub device_map: Option<DeviceMap>,
+}
+
+fn default_speculative_tokens() -> usize {
+    4
 }
 
 impl Default for EngineConfig {
@@ -42,11 +62,22 @@ impl Default for EngineConfig {
             max_seq_len: 4096,
             use_flash_attention: true,
             num_threads: num_cpus::get(),
+            draft_model: None,
+            speculative_tokens: default_speculative_tokens(),
+            device_map: None,
         }
     }
 }
 
 impl EngineConfig {
+    /// Devices a shard's blocks are placed on: `device_map` if set,
+    /// otherwise `device` alone.
+    pub fn shard_device_map(&self) -> DeviceMap {
+        self.device_map
+            .clone()
+            .unwrap_or_else(|| DeviceMap::single(self.device))
+    }
+
     /// Create configuration optimized for browser (WASM)
     pub fn browser_optimized() -> Self {
         Self {
@@ -58,6 +89,9 @@ impl EngineConfig {
             max_seq_len: 2048,
             use_flash_attention: false,
             num_threads: 4,
+            draft_model: None,
+            speculative_tokens: default_speculative_tokens(),
+            device_map: None,
         }
     }
 
@@ -72,6 +106,9 @@ impl EngineConfig {
             max_seq_len: 1024,
             use_flash_attention: false,
             num_threads: 2,
+            draft_model: None,
+            speculative_tokens: default_speculative_tokens(),
+            device_map: None,
         }
     }
 
@@ -86,6 +123,9 @@ impl EngineConfig {
             max_seq_len: 8192,
             use_flash_attention: true,
             num_threads: num_cpus::get(),
+            draft_model: None,
+            speculative_tokens: default_speculative_tokens(),
+            device_map: None,
         }
     }
 }
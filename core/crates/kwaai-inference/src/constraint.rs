@@ -0,0 +1,511 @@
+//! Constrained decoding: JSON mode.
+//!
+//! With [`ResponseFormat::JsonObject`] every sampling step is restricted to
+//! tokens whose text keeps the completion a prefix of a valid JSON object,
+//! and the stop token is only allowed once the object is closed. The model
+//! still chooses among the allowed tokens by its own logits, so the output
+//! reads as it would unconstrained, but always parses.
+//!
+//! [`JsonValidator`] is a character-level pushdown recognizer for RFC 8259
+//! JSON. [`mask_logits`] applies it to a logits vector using the text each
+//! candidate token would add to the output, from [`token_piece`]. A token is
+//! decoded after the last few output tokens rather than alone: SentencePiece
+//! (Metaspace) decoders drop a leading `▁` from the first token they see, so
+//! `▁"` decodes to `"` alone but appends ` "` mid-output.
+//!
+//! Tokens whose text is not valid UTF-8 on its own (fragments of a
+//! multi-byte character) are never allowed, so string values are limited
+//! to characters the vocabulary spells in whole tokens.
+
+use crate::error::{InferenceError, InferenceResult};
+use crate::tokenizer::Tokenizer;
+use serde::{Deserialize, Serialize};
+
+/// Shape the completion must take.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseFormat {
+    /// Free text.
+    #[default]
+    Text,
+    /// A single JSON object (OpenAI `response_format: {"type": "json_object"}`).
+    JsonObject,
+}
+
+/// Deepest nesting of objects and arrays accepted.
+pub const MAX_DEPTH: usize = 64;
+
+/// Longest run of whitespace accepted between tokens. Without a cap a model
+/// unsure how to continue can pad with newlines until `max_new_tokens`.
+pub const MAX_WHITESPACE_RUN: usize = 16;
+
+/// Most tokens considered per step, best logits first. Sampling only ever
+/// draws from the head of the distribution, so scanning further is waste.
+const MAX_CANDIDATES: usize = 64;
+
+/// Output tokens a candidate is decoded after by [`token_piece`].
+pub const PIECE_CONTEXT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Backslash,
+    /// Hex digits of a `\u` escape read so far
+    Unicode(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Number {
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Frac,
+    Exp,
+    ExpSign,
+    ExpDigits,
+}
+
+impl Number {
+    /// Whether the number may end here.
+    fn is_complete(self) -> bool {
+        matches!(
+            self,
+            Number::Zero | Number::Int | Number::Frac | Number::ExpDigits
+        )
+    }
+
+    fn next(self, c: char) -> Option<Number> {
+        use Number::*;
+        match (self, c) {
+            (Minus, '0') => Some(Zero),
+            (Minus, '1'..='9') | (Int, '0'..='9') => Some(Int),
+            (Zero | Int, '.') => Some(Dot),
+            (Dot | Frac, '0'..='9') => Some(Frac),
+            (Zero | Int | Frac, 'e' | 'E') => Some(Exp),
+            (Exp, '+' | '-') => Some(ExpSign),
+            (Exp | ExpSign | ExpDigits, '0'..='9') => Some(ExpDigits),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the top-level object
+    Start,
+    /// A value is required (after `:` or `,` in an array)
+    Value,
+    /// After `{`: a key or `}`
+    FirstKeyOrEnd,
+    /// After `,` in an object
+    Key,
+    Colon,
+    /// After a member value: `,` or `}`
+    ObjectNext,
+    /// After `[`: a value or `]`
+    FirstValueOrEnd,
+    /// After an element: `,` or `]`
+    ArrayNext,
+    Str {
+        key: bool,
+        escape: Escape,
+    },
+    Num(Number),
+    Literal {
+        word: &'static str,
+        matched: usize,
+    },
+    /// The top-level object is closed; only whitespace may follow
+    Done,
+}
+
+/// Incremental recognizer for a JSON object.
+#[derive(Debug, Clone)]
+pub struct JsonValidator {
+    state: State,
+    stack: Vec<Container>,
+    whitespace_run: usize,
+}
+
+impl Default for JsonValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonValidator {
+    pub fn new() -> Self {
+        Self {
+            state: State::Start,
+            stack: Vec::new(),
+            whitespace_run: 0,
+        }
+    }
+
+    /// `true` once a complete object has been read.
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Consume `text`; `false` (leaving `self` in an unspecified state) if
+    /// it cannot continue a JSON object.
+    pub fn push_str(&mut self, text: &str) -> bool {
+        text.chars().all(|c| self.push(c))
+    }
+
+    /// Whether `text` would continue the object, without consuming it.
+    pub fn accepts(&self, text: &str) -> bool {
+        self.clone().push_str(text)
+    }
+
+    /// Consume one character; `false` if it cannot continue a JSON object.
+    pub fn push(&mut self, c: char) -> bool {
+        if let State::Str { key, escape } = self.state {
+            return self.push_string(key, escape, c);
+        }
+        if is_whitespace(c) && !matches!(self.state, State::Num(_) | State::Literal { .. }) {
+            self.whitespace_run += 1;
+            return self.whitespace_run <= MAX_WHITESPACE_RUN;
+        }
+        self.whitespace_run = 0;
+
+        match self.state {
+            State::Start => c == '{' && self.open(Container::Object),
+            State::Value => self.start_value(c),
+            State::FirstValueOrEnd if c == ']' => self.close(Container::Array),
+            State::FirstValueOrEnd => self.start_value(c),
+            State::FirstKeyOrEnd if c == '}' => self.close(Container::Object),
+            State::FirstKeyOrEnd | State::Key => self.start_string(c, true),
+            State::Colon => self.expect(c, ':', State::Value),
+            State::ObjectNext if c == '}' => self.close(Container::Object),
+            State::ObjectNext => self.expect(c, ',', State::Key),
+            State::ArrayNext if c == ']' => self.close(Container::Array),
+            State::ArrayNext => self.expect(c, ',', State::Value),
+            State::Num(n) => match n.next(c) {
+                Some(next) => {
+                    self.state = State::Num(next);
+                    true
+                }
+                // The character ends the number and belongs to what follows.
+                None if n.is_complete() => {
+                    self.value_done();
+                    self.push(c)
+                }
+                None => false,
+            },
+            State::Literal { word, matched } => {
+                if word[matched..].starts_with(c) {
+                    if matched + 1 == word.len() {
+                        self.value_done();
+                    } else {
+                        self.state = State::Literal {
+                            word,
+                            matched: matched + 1,
+                        };
+                    }
+                    true
+                } else {
+                    false
+                }
+            }
+            State::Done => false,
+            State::Str { .. } => unreachable!("handled above"),
+        }
+    }
+
+    fn push_string(&mut self, key: bool, escape: Escape, c: char) -> bool {
+        let escape = match (escape, c) {
+            (Escape::None, '"') => {
+                if key {
+                    self.state = State::Colon;
+                } else {
+                    self.value_done();
+                }
+                return true;
+            }
+            (Escape::None, '\\') => Escape::Backslash,
+            (Escape::None, c) if (c as u32) < 0x20 => return false,
+            (Escape::None, _) => Escape::None,
+            (Escape::Backslash, '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => Escape::None,
+            (Escape::Backslash, 'u') => Escape::Unicode(0),
+            (Escape::Unicode(3), c) if c.is_ascii_hexdigit() => Escape::None,
+            (Escape::Unicode(n), c) if c.is_ascii_hexdigit() => Escape::Unicode(n + 1),
+            _ => return false,
+        };
+        self.state = State::Str { key, escape };
+        true
+    }
+
+    fn start_value(&mut self, c: char) -> bool {
+        let literal = |word| State::Literal { word, matched: 1 };
+        self.state = match c {
+            '{' => return self.open(Container::Object),
+            '[' => return self.open(Container::Array),
+            '"' => State::Str {
+                key: false,
+                escape: Escape::None,
+            },
+            '-' => State::Num(Number::Minus),
+            '0' => State::Num(Number::Zero),
+            '1'..='9' => State::Num(Number::Int),
+            't' => literal("true"),
+            'f' => literal("false"),
+            'n' => literal("null"),
+            _ => return false,
+        };
+        true
+    }
+
+    fn start_string(&mut self, c: char, key: bool) -> bool {
+        if c != '"' {
+            return false;
+        }
+        self.state = State::Str {
+            key,
+            escape: Escape::None,
+        };
+        true
+    }
+
+    fn expect(&mut self, c: char, want: char, next: State) -> bool {
+        if c != want {
+            return false;
+        }
+        self.state = next;
+        true
+    }
+
+    fn open(&mut self, container: Container) -> bool {
+        if self.stack.len() >= MAX_DEPTH {
+            return false;
+        }
+        self.stack.push(container);
+        self.state = match container {
+            Container::Object => State::FirstKeyOrEnd,
+            Container::Array => State::FirstValueOrEnd,
+        };
+        true
+    }
+
+    fn close(&mut self, container: Container) -> bool {
+        if self.stack.pop() != Some(container) {
+            return false;
+        }
+        self.value_done();
+        true
+    }
+
+    fn value_done(&mut self) {
+        self.state = match self.stack.last() {
+            Some(Container::Object) => State::ObjectNext,
+            Some(Container::Array) => State::ArrayNext,
+            None => State::Done,
+        };
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+/// Text token `id` appends to an output ending in `output`.
+///
+/// The difference between decoding the last [`PIECE_CONTEXT`] output tokens
+/// with and without `id`; `None` when the decode does not simply extend
+/// (the context ends inside a multi-byte character).
+pub fn token_piece<T: Tokenizer + ?Sized>(
+    tokenizer: &T,
+    output: &[u32],
+    id: u32,
+) -> InferenceResult<Option<String>> {
+    let context = &output[output.len().saturating_sub(PIECE_CONTEXT)..];
+    if context.is_empty() {
+        return tokenizer.decode(&[id]).map(Some);
+    }
+    let base = tokenizer.decode(context)?;
+    let mut tokens = context.to_vec();
+    tokens.push(id);
+    let full = tokenizer.decode(&tokens)?;
+    Ok(full.strip_prefix(base.as_str()).map(str::to_string))
+}
+
+/// Restrict `logits` to the tokens that keep `validator` valid.
+///
+/// `piece(id)` is the text token `id` would add to the output (see
+/// [`token_piece`]). Once the object is complete only `stop_id` remains.
+/// Disallowed entries become `-inf`.
+pub fn mask_logits(
+    logits: &mut [f32],
+    validator: &JsonValidator,
+    mut piece: impl FnMut(u32) -> InferenceResult<Option<String>>,
+    stop_id: Option<u32>,
+) -> InferenceResult<()> {
+    let allowed: Vec<usize> = if validator.is_complete() {
+        stop_id.map(|id| id as usize).into_iter().collect()
+    } else {
+        let mut order: Vec<usize> = (0..logits.len()).collect();
+        order.sort_unstable_by(|&a, &b| logits[b].total_cmp(&logits[a]));
+        let mut allowed = Vec::new();
+        for id in order {
+            if allowed.len() == MAX_CANDIDATES {
+                break;
+            }
+            let fits = piece(id as u32)?.is_some_and(|text| {
+                !text.is_empty() && !text.contains('\u{FFFD}') && validator.accepts(&text)
+            });
+            if fits {
+                allowed.push(id);
+            }
+        }
+        allowed
+    };
+    if allowed.is_empty() {
+        return Err(InferenceError::InferenceFailed(
+            "no token can continue the JSON output".to_string(),
+        ));
+    }
+    let kept: Vec<(usize, f32)> = allowed.iter().map(|&id| (id, logits[id])).collect();
+    logits.fill(f32::NEG_INFINITY);
+    for (id, value) in kept {
+        logits[id] = value;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::BpeTokenizer;
+
+    fn valid(text: &str) -> bool {
+        let mut v = JsonValidator::new();
+        v.push_str(text) && v.is_complete()
+    }
+
+    #[test]
+    fn accepts_json_objects() {
+        assert!(valid("{}"));
+        assert!(valid(
+            r#" {"a": 1, "b": [true, false, null], "c": {"d": "e\"é"}} "#
+        ));
+        assert!(valid(r#"{"n": [-0.5e+10, 0, 12, 3.25E2]}"#));
+        assert!(valid(r#"{"s": "tab\t escaped \\ ok 🦀"}"#));
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        for bad in [
+            "[1]",
+            "{a: 1}",
+            r#"{"a" 1}"#,
+            r#"{"a": 01}"#,
+            r#"{"a": 1,}"#,
+            r#"{"a": tru}"#,
+            r#"{"a": [1}"#,
+            r#"{"a": "\x"}"#,
+            "{} {}",
+            "{\"a\": \"line\nbreak\"}",
+        ] {
+            assert!(!valid(bad), "{bad}");
+        }
+    }
+
+    #[test]
+    fn prefixes_are_accepted_but_incomplete() {
+        let mut v = JsonValidator::new();
+        assert!(v.push_str(r#"{"key": [1, 2"#));
+        assert!(!v.is_complete());
+        assert!(v.accepts("]}"));
+        assert!(!v.accepts("}"));
+        assert!(v.push_str("]}"));
+        assert!(v.is_complete());
+    }
+
+    #[test]
+    fn whitespace_runs_are_capped() {
+        let mut v = JsonValidator::new();
+        assert!(v.push_str(&" ".repeat(MAX_WHITESPACE_RUN)));
+        assert!(!v.accepts(" "));
+        assert!(v.accepts("{"));
+    }
+
+    #[test]
+    fn mask_keeps_only_valid_continuations() {
+        let vocab: Vec<String> = ["{", "}", "hello", "\"", "", "{\"", " "]
+            .map(String::from)
+            .to_vec();
+        let piece = |id: u32| Ok(vocab.get(id as usize).cloned());
+        let stop = 4;
+        let mut logits = vec![1.0, 5.0, 9.0, 2.0, 8.0, 3.0, 0.5];
+        mask_logits(&mut logits, &JsonValidator::new(), piece, Some(stop)).unwrap();
+        let allowed: Vec<usize> = (0..vocab.len())
+            .filter(|&i| logits[i].is_finite())
+            .collect();
+        assert_eq!(allowed, [0, 5, 6]);
+
+        let mut done = JsonValidator::new();
+        assert!(done.push_str("{}"));
+        let mut logits = vec![1.0; vocab.len()];
+        mask_logits(&mut logits, &done, piece, Some(stop)).unwrap();
+        let allowed: Vec<usize> = (0..vocab.len())
+            .filter(|&i| logits[i].is_finite())
+            .collect();
+        assert_eq!(allowed, [stop as usize]);
+    }
+
+    /// SentencePiece-style vocabulary: Metaspace pre-tokenizer and decoder.
+    fn metaspace_tokenizer() -> BpeTokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": {"type": "Metaspace", "replacement": "▁", "prepend_scheme": "always", "split": true},
+            "post_processor": null,
+            "decoder": {"type": "Metaspace", "replacement": "▁", "prepend_scheme": "always", "split": true},
+            "model": {
+                "type": "WordLevel",
+                "vocab": {"<unk>": 0, "{": 1, "\"a\":": 2, "1": 3, "▁1": 4, "}": 5},
+                "unk_token": "<unk>"
+            }
+        }"#;
+        let path = std::env::temp_dir().join(format!(
+            "kwaai-metaspace-tokenizer-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, json).unwrap();
+        let tokenizer = BpeTokenizer::from_file(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        tokenizer
+    }
+
+    #[test]
+    fn metaspace_pieces_keep_their_leading_space() {
+        let tokenizer = metaspace_tokenizer();
+        // Alone, the decoder strips the `▁`; after other output it is a space.
+        assert_eq!(tokenizer.decode(&[4]).unwrap(), "1");
+        assert_eq!(token_piece(&tokenizer, &[1, 2], 4).unwrap().unwrap(), " 1");
+        // The first token of the output is stripped, as in the final text.
+        assert_eq!(token_piece(&tokenizer, &[], 4).unwrap().unwrap(), "1");
+
+        // After `{"a":1` another `1` extends the number, but ` 1` cannot
+        // follow it, although `▁1` decoded alone reads `1`.
+        let output = [1, 2, 3];
+        let mut v = JsonValidator::new();
+        assert!(v.push_str(&tokenizer.decode(&output).unwrap()));
+        let piece = |id: u32| token_piece(&tokenizer, &output, id);
+        let mut logits = vec![0.0; tokenizer.vocab_size()];
+        mask_logits(&mut logits, &v, piece, None).unwrap();
+        let allowed: Vec<usize> = (0..logits.len())
+            .filter(|&i| logits[i].is_finite())
+            .collect();
+        assert_eq!(allowed, [3, 5]);
+    }
+}
@@ -0,0 +1,703 @@
+//! Quantized GGUF runner for Phi-3, Gemma 1/2 and Mixtral.
+//!
+//! candle-transformers runs Llama, Qwen2 and Gemma 3 GGUF checkpoints (see
+//! [`crate::loader::GgufWeights`]). Phi-3, the earlier Gemma generations and
+//! Mixtral are decoder-only transformers that differ from Llama in a handful
+//! of places, so one configurable decoder covers them:
+//!
+//! | | Phi-3 | Gemma | Gemma 2 | Mixtral |
+//! |---|---|---|---|---|
+//! | QKV and gate/up projections | fused | separate | separate | separate |
+//! | MLP | SiLU | GELU | GELU | sparse MoE of SiLU experts |
+//! | Embeddings | — | scaled by √hidden, tied output | same | — |
+//! | Extra norms | — | — | after attention and MLP | — |
+//! | Logit soft-capping | — | — | attention and final | — |
+//! | Sliding-window attention | every layer, if set | — | every other layer | — |
+//! | RoPE | NeoX, LongRoPE factors | NeoX | NeoX | interleaved |
+//!
+//! Gemma's RMSNorm weights are stored with the `+1` offset already folded in
+//! by the GGUF converter, so a plain RMSNorm applies them. Mixtral's experts
+//! live in [`crate::moe`], which can leave some of them to remote peers.
+
+use candle_core::{
+    quantized::{gguf_file, QMatMul, QTensor},
+    DType, Device, IndexOp, Module, Result, Tensor, D,
+};
+use candle_nn::Embedding;
+use candle_transformers::quantized_nn::RmsNorm;
+
+use crate::loader::{meta_f32, meta_str, meta_usize};
+use crate::moe::{ExpertOffload, SparseMoe};
+
+/// GGUF architectures this runner handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderArch {
+    Phi3,
+    Gemma,
+    Gemma2,
+    /// A `llama` GGUF with `llama.expert_count` > 1.
+    Mixtral,
+}
+
+impl DecoderArch {
+    pub fn from_architecture(arch: &str) -> Option<Self> {
+        match arch {
+            "phi3" => Some(Self::Phi3),
+            "gemma" => Some(Self::Gemma),
+            "gemma2" => Some(Self::Gemma2),
+            _ => None,
+        }
+    }
+
+    /// Metadata key prefix (`general.architecture`).
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Phi3 => "phi3",
+            Self::Gemma => "gemma",
+            Self::Gemma2 => "gemma2",
+            Self::Mixtral => "llama",
+        }
+    }
+
+    fn is_gemma(self) -> bool {
+        matches!(self, Self::Gemma | Self::Gemma2)
+    }
+}
+
+/// Hyperparameters read from the GGUF metadata.
+#[derive(Debug, Clone)]
+struct Hparams {
+    arch: DecoderArch,
+    n_layer: usize,
+    n_head: usize,
+    n_kv_head: usize,
+    head_dim: usize,
+    hidden: usize,
+    ffn: usize,
+    eps: f64,
+    rope_theta: f32,
+    context_length: usize,
+    sliding_window: Option<usize>,
+    attn_softcap: Option<f64>,
+    final_softcap: Option<f64>,
+    n_expert: usize,
+    n_expert_used: usize,
+}
+
+impl Hparams {
+    fn from_gguf(ct: &gguf_file::Content, arch: DecoderArch) -> Result<Self> {
+        let pfx = arch.prefix();
+        let required = |key: &str| {
+            meta_usize(ct, &format!("{pfx}.{key}"))
+                .ok_or_else(|| candle_core::Error::Msg(format!("cannot find {pfx}.{key}")))
+        };
+        let n_head = required("attention.head_count")?;
+        let hidden = required("embedding_length")?;
+        let head_dim = meta_usize(ct, &format!("{pfx}.attention.key_length"))
+            .unwrap_or(hidden / n_head.max(1));
+        if let Some(rot) = meta_usize(ct, &format!("{pfx}.rope.dimension_count")) {
+            if rot != head_dim {
+                candle_core::bail!(
+                    "partial rotary embeddings ({rot} of {head_dim}) are not supported"
+                );
+            }
+        }
+        let softcap = |key: &str| {
+            meta_f32(ct, &format!("{pfx}.{key}"))
+                .filter(|c| *c > 0.0)
+                .map(f64::from)
+        };
+        Ok(Self {
+            arch,
+            n_layer: required("block_count")?,
+            n_head,
+            n_kv_head: meta_usize(ct, &format!("{pfx}.attention.head_count_kv")).unwrap_or(n_head),
+            head_dim,
+            hidden,
+            ffn: required("feed_forward_length")?,
+            eps: meta_f32(ct, &format!("{pfx}.attention.layer_norm_rms_epsilon")).unwrap_or(1e-6)
+                as f64,
+            rope_theta: meta_f32(ct, &format!("{pfx}.rope.freq_base")).unwrap_or(10_000.0),
+            context_length: required("context_length")?,
+            sliding_window: meta_usize(ct, &format!("{pfx}.attention.sliding_window")),
+            attn_softcap: softcap("attn_logit_softcapping"),
+            final_softcap: softcap("final_logit_softcapping"),
+            n_expert: meta_usize(ct, &format!("{pfx}.expert_count")).unwrap_or(0),
+            n_expert_used: meta_usize(ct, &format!("{pfx}.expert_used_count")).unwrap_or(2),
+        })
+    }
+
+    /// Whether layer `i` attends only within the sliding window.
+    fn is_sliding(&self, i: usize) -> bool {
+        match self.arch {
+            DecoderArch::Phi3 => self.sliding_window.is_some(),
+            DecoderArch::Gemma | DecoderArch::Mixtral => false,
+            // Gemma 2 alternates local and global layers, starting local.
+            DecoderArch::Gemma2 => self.sliding_window.is_some() && i.is_multiple_of(2),
+        }
+    }
+
+    /// Scale applied to attention scores.
+    fn query_scale(&self) -> f64 {
+        // Gemma 2 27B (46 layers) scales by hidden / heads instead of the
+        // head size, as llama.cpp does; the config value isn't in the GGUF.
+        let d = if self.arch == DecoderArch::Gemma2 && self.n_layer == 46 {
+            self.hidden / self.n_head
+        } else {
+            self.head_dim
+        };
+        1.0 / (d as f64).sqrt()
+    }
+}
+
+/// RoPE tables for every position up to the context length.
+fn rope_tables(
+    hp: &Hparams,
+    ct: &gguf_file::Content,
+    reader: &mut (impl std::io::Seek + std::io::Read),
+    device: &Device,
+) -> Result<(Tensor, Tensor)> {
+    let pfx = hp.arch.prefix();
+    let half = hp.head_dim / 2;
+    let mut inv_freq: Vec<f32> = (0..half)
+        .map(|i| 1.0 / hp.rope_theta.powf(2.0 * i as f32 / hp.head_dim as f32))
+        .collect();
+    let mut positions_scale = 1.0f32;
+    let mut attn_factor = 1.0f32;
+
+    // LongRoPE (Phi-3 128k): per-frequency factors, the long set when the
+    // model runs past the context it was pretrained with.
+    let original = meta_usize(ct, &format!("{pfx}.rope.scaling.original_context_length"));
+    let long = original.is_some_and(|o| hp.context_length > o);
+    let factors_name = if long {
+        "rope_factors_long.weight"
+    } else {
+        "rope_factors_short.weight"
+    };
+    if ct.tensor_infos.contains_key(factors_name) {
+        let factors = ct
+            .tensor(reader, factors_name, device)?
+            .dequantize(device)?
+            .to_vec1::<f32>()?;
+        if factors.len() != half {
+            candle_core::bail!(
+                "{factors_name} has {} entries, expected {half}",
+                factors.len()
+            );
+        }
+        for (f, factor) in inv_freq.iter_mut().zip(factors) {
+            *f /= factor;
+        }
+        attn_factor = match meta_f32(ct, &format!("{pfx}.rope.scaling.attn_factor")) {
+            Some(f) => f,
+            None => match original {
+                Some(o) if long => {
+                    let scale = hp.context_length as f32 / o as f32;
+                    (1.0 + scale.ln() / (o as f32).ln()).sqrt()
+                }
+                _ => 1.0,
+            },
+        };
+    } else if meta_str(ct, &format!("{pfx}.rope.scaling.type")).as_deref() == Some("linear") {
+        if let Some(factor) = meta_f32(ct, &format!("{pfx}.rope.scaling.factor")) {
+            positions_scale = 1.0 / factor;
+        }
+    }
+
+    let n = hp.context_length;
+    let inv_freq = Tensor::from_vec(inv_freq, (1, half), device)?;
+    let positions = (Tensor::arange(0u32, n as u32, device)?
+        .to_dtype(DType::F32)?
+        .reshape((n, 1))?
+        * positions_scale as f64)?;
+    let freqs = positions.matmul(&inv_freq)?;
+    let cos = (freqs.cos()? * attn_factor as f64)?;
+    let sin = (freqs.sin()? * attn_factor as f64)?;
+    Ok((cos, sin))
+}
+
+#[derive(Debug, Clone)]
+enum Qkv {
+    Fused(QMatMul),
+    Split { q: QMatMul, k: QMatMul, v: QMatMul },
+}
+
+#[derive(Debug, Clone)]
+enum Mlp {
+    /// Phi-3: `ffn_up` holds the gate and up projections back to back.
+    FusedSilu {
+        gate_up: QMatMul,
+        down: QMatMul,
+    },
+    Gelu {
+        gate: QMatMul,
+        up: QMatMul,
+        down: QMatMul,
+    },
+    Moe(SparseMoe),
+}
+
+impl Mlp {
+    fn forward(&self, x: &Tensor, ffn: usize) -> Result<Tensor> {
+        match self {
+            Mlp::FusedSilu { gate_up, down } => {
+                let gu = gate_up.forward(x)?;
+                let gate = gu.narrow(D::Minus1, 0, ffn)?;
+                let up = gu.narrow(D::Minus1, ffn, ffn)?;
+                down.forward(&(candle_nn::ops::silu(&gate)? * up)?)
+            }
+            Mlp::Gelu { gate, up, down } => {
+                let g = gate.forward(x)?.gelu()?;
+                down.forward(&(g * up.forward(x)?)?)
+            }
+            Mlp::Moe(moe) => moe.forward(x),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    qkv: Qkv,
+    wo: QMatMul,
+    attn_norm: RmsNorm,
+    post_attn_norm: Option<RmsNorm>,
+    ffn_norm: RmsNorm,
+    post_ffn_norm: Option<RmsNorm>,
+    mlp: Mlp,
+    sliding: bool,
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+/// Quantized weights and KV-cache of a Phi-3, Gemma 1/2 or Mixtral model.
+///
+/// Cloning shares the weight tensors and gives the copy its own KV-cache.
+#[derive(Debug, Clone)]
+pub struct DecoderWeights {
+    hp: Hparams,
+    tok_embeddings: Embedding,
+    layers: Vec<Layer>,
+    norm: RmsNorm,
+    output: QMatMul,
+    cos: Tensor,
+    sin: Tensor,
+}
+
+impl DecoderWeights {
+    pub fn from_gguf<R: std::io::Seek + std::io::Read>(
+        arch: DecoderArch,
+        ct: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        Self::from_gguf_with_offload(arch, ct, reader, device, None)
+    }
+
+    /// Like [`Self::from_gguf`], loading only the Mixtral experts `offload`
+    /// keeps local and running the rest through its remote.
+    pub fn from_gguf_with_offload<R: std::io::Seek + std::io::Read>(
+        arch: DecoderArch,
+        ct: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+        offload: Option<&ExpertOffload>,
+    ) -> Result<Self> {
+        let hp = Hparams::from_gguf(&ct, arch)?;
+        if arch == DecoderArch::Mixtral && hp.n_expert < 2 {
+            candle_core::bail!("llama.expert_count must be at least 2 for a mixture of experts");
+        }
+        let (cos, sin) = rope_tables(&hp, &ct, reader, device)?;
+        let tensor =
+            |reader: &mut R, name: &str| -> Result<QTensor> { ct.tensor(reader, name, device) };
+
+        let embd = tensor(reader, "token_embd.weight")?;
+        let tok_embeddings = Embedding::new(embd.dequantize(device)?, hp.hidden);
+        // Gemma ties the output projection to the embeddings.
+        let output = match tensor(reader, "output.weight") {
+            Ok(t) => t,
+            Err(_) => embd,
+        };
+        let norm = RmsNorm::from_qtensor(tensor(reader, "output_norm.weight")?, hp.eps)?;
+
+        let mut layers = Vec::with_capacity(hp.n_layer);
+        for i in 0..hp.n_layer {
+            let t = |reader: &mut R, name: &str| tensor(reader, &format!("blk.{i}.{name}.weight"));
+            let mm = |q: QTensor| QMatMul::from_qtensor(q);
+            let qkv = match arch {
+                DecoderArch::Phi3 => Qkv::Fused(mm(t(reader, "attn_qkv")?)?),
+                _ => Qkv::Split {
+                    q: mm(t(reader, "attn_q")?)?,
+                    k: mm(t(reader, "attn_k")?)?,
+                    v: mm(t(reader, "attn_v")?)?,
+                },
+            };
+            let mlp = match arch {
+                DecoderArch::Phi3 => Mlp::FusedSilu {
+                    gate_up: mm(t(reader, "ffn_up")?)?,
+                    down: mm(t(reader, "ffn_down")?)?,
+                },
+                DecoderArch::Mixtral => Mlp::Moe(SparseMoe::load(
+                    &ct,
+                    reader,
+                    i,
+                    hp.n_expert,
+                    hp.n_expert_used,
+                    offload,
+                    device,
+                )?),
+                _ => Mlp::Gelu {
+                    gate: mm(t(reader, "ffn_gate")?)?,
+                    up: mm(t(reader, "ffn_up")?)?,
+                    down: mm(t(reader, "ffn_down")?)?,
+                },
+            };
+            let (post_attn_norm, post_ffn_norm) = if arch == DecoderArch::Gemma2 {
+                (
+                    Some(RmsNorm::from_qtensor(
+                        t(reader, "post_attention_norm")?,
+                        hp.eps,
+                    )?),
+                    Some(RmsNorm::from_qtensor(t(reader, "post_ffw_norm")?, hp.eps)?),
+                )
+            } else {
+                (None, None)
+            };
+            layers.push(Layer {
+                qkv,
+                wo: mm(t(reader, "attn_output")?)?,
+                attn_norm: RmsNorm::from_qtensor(t(reader, "attn_norm")?, hp.eps)?,
+                post_attn_norm,
+                ffn_norm: RmsNorm::from_qtensor(t(reader, "ffn_norm")?, hp.eps)?,
+                post_ffn_norm,
+                mlp,
+                sliding: hp.is_sliding(i),
+                kv_cache: None,
+            });
+        }
+
+        Ok(Self {
+            tok_embeddings,
+            layers,
+            norm,
+            output: QMatMul::from_qtensor(output)?,
+            cos,
+            sin,
+            hp,
+        })
+    }
+
+    /// Run `x` (`[batch, seq_len]` token ids) starting at `index_pos` and
+    /// return the logits of the last position, `[batch, vocab]`.
+    ///
+    /// `index_pos == 0` starts a new sequence and drops the KV-cache.
+    pub fn forward(&mut self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let (_b, seq_len) = x.dims2()?;
+        if index_pos + seq_len > self.hp.context_length {
+            candle_core::bail!(
+                "sequence of {} tokens exceeds the context length {}",
+                index_pos + seq_len,
+                self.hp.context_length
+            );
+        }
+        let device = x.device();
+        let global_mask = attention_mask(seq_len, index_pos, None, device)?;
+        let local_mask = attention_mask(seq_len, index_pos, self.hp.sliding_window, device)?;
+        let cos = self.cos.narrow(0, index_pos, seq_len)?;
+        let sin = self.sin.narrow(0, index_pos, seq_len)?;
+
+        let mut h = self.tok_embeddings.forward(x)?;
+        if self.hp.arch.is_gemma() {
+            h = (h * (self.hp.hidden as f64).sqrt())?;
+        }
+        for layer in self.layers.iter_mut() {
+            let mask = if layer.sliding {
+                local_mask.as_ref()
+            } else {
+                global_mask.as_ref()
+            };
+            let residual = &h;
+            let a = layer.attn_norm.forward(&h)?;
+            let a = attention(layer, &self.hp, &a, index_pos, mask, &cos, &sin)?;
+            let a = match &layer.post_attn_norm {
+                Some(n) => n.forward(&a)?,
+                None => a,
+            };
+            let h1 = (a + residual)?;
+            let m = layer.ffn_norm.forward(&h1)?;
+            let m = layer.mlp.forward(&m, self.hp.ffn)?;
+            let m = match &layer.post_ffn_norm {
+                Some(n) => n.forward(&m)?,
+                None => m,
+            };
+            h = (m + h1)?;
+        }
+        let h = self.norm.forward(&h)?.i((.., seq_len - 1, ..))?;
+        let logits = self.output.forward(&h)?;
+        match self.hp.final_softcap {
+            Some(c) => (logits / c)?.tanh()? * c,
+            None => Ok(logits),
+        }
+    }
+}
+
+fn attention(
+    layer: &mut Layer,
+    hp: &Hparams,
+    x: &Tensor,
+    index_pos: usize,
+    mask: Option<&Tensor>,
+    cos: &Tensor,
+    sin: &Tensor,
+) -> Result<Tensor> {
+    let (b, seq_len, _) = x.dims3()?;
+    let q_dim = hp.n_head * hp.head_dim;
+    let kv_dim = hp.n_kv_head * hp.head_dim;
+    let (q, k, v) = match &layer.qkv {
+        Qkv::Fused(qkv) => {
+            let qkv = qkv.forward(x)?;
+            (
+                qkv.narrow(D::Minus1, 0, q_dim)?,
+                qkv.narrow(D::Minus1, q_dim, kv_dim)?,
+                qkv.narrow(D::Minus1, q_dim + kv_dim, kv_dim)?,
+            )
+        }
+        Qkv::Split { q, k, v } => (q.forward(x)?, k.forward(x)?, v.forward(x)?),
+    };
+    let heads = |t: Tensor, n: usize| -> Result<Tensor> {
+        t.reshape((b, seq_len, n, hp.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()
+    };
+    // llama.cpp's converter permutes Llama-family Q/K weights for the
+    // interleaved rotation; the others keep the rotate-half layout.
+    let rope = match hp.arch {
+        DecoderArch::Mixtral => candle_nn::rotary_emb::rope_i,
+        _ => candle_nn::rotary_emb::rope,
+    };
+    let q = rope(&heads(q, hp.n_head)?, cos, sin)?;
+    let k = rope(&heads(k, hp.n_kv_head)?, cos, sin)?;
+    let v = heads(v, hp.n_kv_head)?;
+
+    let (k, v) = match &layer.kv_cache {
+        Some((kc, vc)) if index_pos > 0 => (Tensor::cat(&[kc, &k], 2)?, Tensor::cat(&[vc, &v], 2)?),
+        _ => (k, v),
+    };
+    layer.kv_cache = Some((k.clone(), v.clone()));
+
+    let n_rep = hp.n_head / hp.n_kv_head;
+    let k = candle_transformers::utils::repeat_kv(k, n_rep)?;
+    let v = candle_transformers::utils::repeat_kv(v, n_rep)?.contiguous()?;
+    let mut att = (q.matmul(&k.t()?)? * hp.query_scale())?;
+    if let Some(c) = hp.attn_softcap {
+        att = ((att / c)?.tanh()? * c)?;
+    }
+    if let Some(mask) = mask {
+        let neg_inf = Tensor::new(f32::NEG_INFINITY, att.device())?.broadcast_as(att.shape())?;
+        att = mask.broadcast_as(att.shape())?.where_cond(&neg_inf, &att)?;
+    }
+    let att = candle_nn::ops::softmax_last_dim(&att)?;
+    let y = att
+        .matmul(&v)?
+        .transpose(1, 2)?
+        .reshape((b, seq_len, q_dim))?;
+    layer.wo.forward(&y)
+}
+
+/// `[seq_len, index_pos + seq_len]` mask, 1 where a query may not attend:
+/// later positions, and with a `window`, positions that far back or more.
+/// `None` when nothing is masked.
+fn attention_mask(
+    seq_len: usize,
+    index_pos: usize,
+    window: Option<usize>,
+    device: &Device,
+) -> Result<Option<Tensor>> {
+    let kv_len = index_pos + seq_len;
+    if seq_len == 1 && window.is_none_or(|w| kv_len <= w) {
+        return Ok(None);
+    }
+    let mask: Vec<u8> = (0..seq_len)
+        .flat_map(|i| {
+            let q = index_pos + i;
+            (0..kv_len).map(move |j| u8::from(j > q || window.is_some_and(|w| q - j >= w)))
+        })
+        .collect();
+    Ok(Some(Tensor::from_vec(mask, (seq_len, kv_len), device)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::quantized::GgmlDType;
+    use gguf_file::Value;
+
+    const VOCAB: usize = 16;
+    const HIDDEN: usize = 8;
+    const FFN: usize = 12;
+    const EXPERTS: usize = 4;
+
+    /// A tiny random model of `arch`, written to GGUF and loaded back.
+    fn tiny_model(arch: DecoderArch, sliding_window: Option<u32>) -> DecoderWeights {
+        let dev = Device::Cpu;
+        let pfx = arch.prefix();
+        let (heads, kv_heads, head_dim) = (2usize, 1usize, 4usize);
+        let q_dim = heads * head_dim;
+        let kv_dim = kv_heads * head_dim;
+        let rand = |shape: &[usize]| {
+            let t = Tensor::randn(0f32, 0.5, shape, &dev).unwrap();
+            QTensor::quantize(&t, GgmlDType::F32).unwrap()
+        };
+        let ones = |n: usize| {
+            QTensor::quantize(&Tensor::ones(n, DType::F32, &dev).unwrap(), GgmlDType::F32).unwrap()
+        };
+
+        let mut tensors: Vec<(String, QTensor)> = vec![
+            ("token_embd.weight".into(), rand(&[VOCAB, HIDDEN])),
+            ("output_norm.weight".into(), ones(HIDDEN)),
+        ];
+        if arch == DecoderArch::Phi3 {
+            tensors.push(("output.weight".into(), rand(&[VOCAB, HIDDEN])));
+        }
+        for i in 0..2 {
+            let mut add =
+                |name: &str, t: QTensor| tensors.push((format!("blk.{i}.{name}.weight"), t));
+            add("attn_norm", ones(HIDDEN));
+            add("ffn_norm", ones(HIDDEN));
+            add("attn_output", rand(&[HIDDEN, q_dim]));
+            if arch == DecoderArch::Phi3 {
+                add("attn_qkv", rand(&[q_dim + 2 * kv_dim, HIDDEN]));
+            } else {
+                add("attn_q", rand(&[q_dim, HIDDEN]));
+                add("attn_k", rand(&[kv_dim, HIDDEN]));
+                add("attn_v", rand(&[kv_dim, HIDDEN]));
+            }
+            match arch {
+                DecoderArch::Phi3 => {
+                    add("ffn_up", rand(&[2 * FFN, HIDDEN]));
+                    add("ffn_down", rand(&[HIDDEN, FFN]));
+                }
+                DecoderArch::Mixtral => {
+                    add("ffn_gate_inp", rand(&[EXPERTS, HIDDEN]));
+                    add("ffn_gate_exps", rand(&[EXPERTS, FFN, HIDDEN]));
+                    add("ffn_up_exps", rand(&[EXPERTS, FFN, HIDDEN]));
+                    add("ffn_down_exps", rand(&[EXPERTS, HIDDEN, FFN]));
+                }
+                _ => {
+                    add("ffn_gate", rand(&[FFN, HIDDEN]));
+                    add("ffn_up", rand(&[FFN, HIDDEN]));
+                    add("ffn_down", rand(&[HIDDEN, FFN]));
+                }
+            }
+            if arch == DecoderArch::Gemma2 {
+                add("post_attention_norm", ones(HIDDEN));
+                add("post_ffw_norm", ones(HIDDEN));
+            }
+        }
+
+        let mut metadata: Vec<(String, Value)> = vec![
+            ("general.architecture".into(), Value::String(pfx.into())),
+            (format!("{pfx}.block_count"), Value::U32(2)),
+            (format!("{pfx}.embedding_length"), Value::U32(HIDDEN as u32)),
+            (format!("{pfx}.feed_forward_length"), Value::U32(FFN as u32)),
+            (format!("{pfx}.context_length"), Value::U32(64)),
+            (
+                format!("{pfx}.attention.head_count"),
+                Value::U32(heads as u32),
+            ),
+            (
+                format!("{pfx}.attention.head_count_kv"),
+                Value::U32(kv_heads as u32),
+            ),
+            (
+                format!("{pfx}.attention.key_length"),
+                Value::U32(head_dim as u32),
+            ),
+        ];
+        if let Some(w) = sliding_window {
+            metadata.push((format!("{pfx}.attention.sliding_window"), Value::U32(w)));
+        }
+        if arch == DecoderArch::Mixtral {
+            metadata.push((format!("{pfx}.expert_count"), Value::U32(EXPERTS as u32)));
+            metadata.push((format!("{pfx}.expert_used_count"), Value::U32(2)));
+        }
+        if arch == DecoderArch::Gemma2 {
+            metadata.push((format!("{pfx}.attn_logit_softcapping"), Value::F32(50.0)));
+            metadata.push((format!("{pfx}.final_logit_softcapping"), Value::F32(30.0)));
+        }
+
+        let mut buf = std::io::Cursor::new(Vec::new());
+        let md: Vec<(&str, &Value)> = metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let ts: Vec<(&str, &QTensor)> = tensors.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        gguf_file::write(&mut buf, &md, &ts).unwrap();
+        buf.set_position(0);
+        let ct = gguf_file::Content::read(&mut buf).unwrap();
+        DecoderWeights::from_gguf(arch, ct, &mut buf, &dev).unwrap()
+    }
+
+    /// Logits of the last token after prefilling all of `tokens` at once,
+    /// and after prefilling all but the last and decoding it.
+    fn prefill_and_decode(model: &mut DecoderWeights, tokens: &[u32]) -> (Vec<f32>, Vec<f32>) {
+        let dev = Device::Cpu;
+        let all = Tensor::new(tokens, &dev).unwrap().unsqueeze(0).unwrap();
+        let full = model.forward(&all, 0).unwrap();
+        assert_eq!(full.dims(), &[1, VOCAB]);
+
+        let n = tokens.len();
+        let head = Tensor::new(&tokens[..n - 1], &dev)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap();
+        model.forward(&head, 0).unwrap();
+        let last = Tensor::new(&tokens[n - 1..], &dev)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap();
+        let step = model.forward(&last, n - 1).unwrap();
+        (
+            full.flatten_all().unwrap().to_vec1().unwrap(),
+            step.flatten_all().unwrap().to_vec1().unwrap(),
+        )
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < 1e-4, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn kv_cache_matches_a_full_prefill_for_each_architecture() {
+        let tokens = [1u32, 5, 9, 3, 7, 2];
+        for (arch, window) in [
+            (DecoderArch::Phi3, Some(4)),
+            (DecoderArch::Gemma, None),
+            (DecoderArch::Gemma2, Some(3)),
+            (DecoderArch::Mixtral, None),
+        ] {
+            let mut model = tiny_model(arch, window);
+            let (full, step) = prefill_and_decode(&mut model, &tokens);
+            assert_close(&full, &step);
+            if arch == DecoderArch::Gemma2 {
+                assert!(full.iter().all(|l| l.abs() <= 30.0), "final soft-cap");
+            }
+        }
+    }
+
+    #[test]
+    fn sliding_window_masks_distant_tokens() {
+        let mask = attention_mask(1, 5, Some(3), &Device::Cpu)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mask.to_vec2::<u8>().unwrap(), vec![vec![1, 1, 1, 0, 0, 0]]);
+        assert!(attention_mask(1, 5, None, &Device::Cpu).unwrap().is_none());
+
+        // A window narrower than the prompt changes what it attends to.
+        let mut narrow = tiny_model(DecoderArch::Phi3, Some(2));
+        let mut wide = narrow.clone();
+        wide.hp.sliding_window = Some(64);
+        let tokens = Tensor::new(&[[4u32, 8, 15, 1, 2]], &Device::Cpu).unwrap();
+        let a = narrow.forward(&tokens, 0).unwrap();
+        let b = wide.forward(&tokens, 0).unwrap();
+        let diff = (a - b).unwrap().abs().unwrap().sum_all().unwrap();
+        assert!(diff.to_scalar::<f32>().unwrap() > 1e-6);
+    }
+}
@@ -0,0 +1,229 @@
+//! Layer-split placement of a shard across several local devices
+//!
+//! A [`DeviceMap`] lists the devices a node computes on, in pipeline order,
+//! with an optional relative weight each (e.g. GiB of VRAM). A shard's blocks
+//! are split into contiguous ranges proportional to those weights, so a node
+//! with 2×24 GB can serve a block range that does not fit on one card:
+//!
+//! ```text
+//! cuda:0,cuda:1         even split
+//! cuda:0=24,cuda:1=12   two thirds of the blocks on GPU 0
+//! ```
+//!
+//! Hidden states move to the next device at each range boundary; every
+//! block keeps its weights and KV cache on its own device.
+
+use crate::{
+    error::{InferenceError, InferenceResult},
+    DeviceType,
+};
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Local devices a shard's blocks are spread over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceMap {
+    /// Devices in pipeline order: the first runs the earliest blocks
+    pub devices: Vec<DeviceType>,
+    /// Relative share of blocks per device (even split when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weights: Vec<f64>,
+}
+
+impl DeviceMap {
+    /// Everything on one device
+    pub fn single(device: DeviceType) -> Self {
+        Self {
+            devices: vec![device],
+            weights: Vec::new(),
+        }
+    }
+
+    /// Whether blocks are spread over more than one device
+    pub fn is_split(&self) -> bool {
+        self.devices.len() > 1
+    }
+
+    /// Contiguous block range (relative to the shard) for each device
+    ///
+    /// Blocks are apportioned by weight with largest remainders; a device
+    /// may end up with an empty range when there are fewer blocks than
+    /// devices.
+    pub fn split(&self, num_blocks: usize) -> Vec<Range<usize>> {
+        let n = self.devices.len().max(1);
+        let weights: Vec<f64> = if self.weights.len() == n {
+            self.weights.iter().map(|w| w.max(0.0)).collect()
+        } else {
+            vec![1.0; n]
+        };
+        let total: f64 = weights.iter().sum();
+        let shares: Vec<f64> = if total > 0.0 {
+            weights
+                .iter()
+                .map(|w| w / total * num_blocks as f64)
+                .collect()
+        } else {
+            vec![num_blocks as f64 / n as f64; n]
+        };
+
+        let mut counts: Vec<usize> = shares.iter().map(|s| s.floor() as usize).collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| {
+            let ra = shares[a] - shares[a].floor();
+            let rb = shares[b] - shares[b].floor();
+            rb.total_cmp(&ra).then(a.cmp(&b))
+        });
+        let assigned: usize = counts.iter().sum();
+        for &i in order.iter().take(num_blocks - assigned) {
+            counts[i] += 1;
+        }
+
+        let mut start = 0;
+        counts
+            .into_iter()
+            .map(|c| {
+                let range = start..start + c;
+                start += c;
+                range
+            })
+            .collect()
+    }
+
+    /// Open every device in the map
+    pub fn candle_devices(&self) -> InferenceResult<Vec<Device>> {
+        if self.devices.is_empty() {
+            return Err(InferenceError::InvalidInput(
+                "device map lists no devices".to_string(),
+            ));
+        }
+        if self.devices.contains(&DeviceType::Mlx) {
+            return Err(InferenceError::InvalidInput(
+                "MLX cannot be part of a device map".to_string(),
+            ));
+        }
+        self.devices
+            .iter()
+            .map(DeviceType::to_candle_device)
+            .collect()
+    }
+}
+
+fn parse_device(s: &str) -> Result<DeviceType, String> {
+    let (kind, ordinal) = match s.split_once(':') {
+        Some((kind, ord)) => (
+            kind,
+            ord.parse::<usize>()
+                .map_err(|_| format!("bad device ordinal in '{s}'"))?,
+        ),
+        None => (s, 0),
+    };
+    match kind.to_ascii_lowercase().as_str() {
+        "cpu" => Ok(DeviceType::Cpu),
+        "cuda" => Ok(DeviceType::Cuda(ordinal)),
+        "metal" => Ok(DeviceType::Metal(ordinal)),
+        _ => Err(format!(
+            "unknown device '{s}' (expected cpu, cuda:N or metal:N)"
+        )),
+    }
+}
+
+impl FromStr for DeviceMap {
+    type Err = InferenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut devices = Vec::new();
+        let mut weights = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (device, weight) = match entry.split_once('=') {
+                Some((d, w)) => {
+                    let w: f64 = w.trim().parse().map_err(|_| {
+                        InferenceError::InvalidInput(format!("bad weight in '{entry}'"))
+                    })?;
+                    if !(w.is_finite() && w > 0.0) {
+                        return Err(InferenceError::InvalidInput(format!(
+                            "weight in '{entry}' must be positive"
+                        )));
+                    }
+                    (d.trim(), Some(w))
+                }
+                None => (entry, None),
+            };
+            devices.push(parse_device(device).map_err(InferenceError::InvalidInput)?);
+            weights.push(weight);
+        }
+        if devices.is_empty() {
+            return Err(InferenceError::InvalidInput(
+                "device map lists no devices".to_string(),
+            ));
+        }
+        let weights = if weights.iter().all(Option::is_none) {
+            Vec::new()
+        } else if weights.iter().all(Option::is_some) {
+            weights.into_iter().flatten().collect()
+        } else {
+            return Err(InferenceError::InvalidInput(
+                "give a weight for every device in the map or for none".to_string(),
+            ));
+        };
+        Ok(Self { devices, weights })
+    }
+}
+
+impl fmt::Display for DeviceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, device) in self.devices.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match device {
+                DeviceType::Cpu => f.write_str("cpu")?,
+                DeviceType::Cuda(n) => write!(f, "cuda:{n}")?,
+                DeviceType::Metal(n) => write!(f, "metal:{n}")?,
+                DeviceType::Mlx => f.write_str("mlx")?,
+            }
+            if let Some(w) = self.weights.get(i) {
+                write!(f, "={w}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_maps() {
+        let map: DeviceMap = "cuda:0=24, cuda:1=12".parse().unwrap();
+        assert_eq!(map.devices, [DeviceType::Cuda(0), DeviceType::Cuda(1)]);
+        assert_eq!(map.weights, [24.0, 12.0]);
+        assert_eq!(map.to_string(), "cuda:0=24,cuda:1=12");
+
+        let even: DeviceMap = "cuda,cuda:1".parse().unwrap();
+        assert!(even.weights.is_empty());
+        assert!(even.is_split());
+
+        for bad in ["", "tpu:0", "cuda:x", "cuda:0=24,cuda:1", "cuda:0=-1"] {
+            assert!(bad.parse::<DeviceMap>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn splits_blocks_by_weight() {
+        let even: DeviceMap = "cpu,cpu".parse().unwrap();
+        assert_eq!(even.split(7), [0..4, 4..7]);
+
+        let weighted: DeviceMap = "cuda:0=24,cuda:1=12".parse().unwrap();
+        assert_eq!(weighted.split(30), [0..20, 20..30]);
+
+        let three: DeviceMap = "cpu,cpu,cpu".parse().unwrap();
+        assert_eq!(three.split(2), [0..1, 1..2, 2..2]);
+        let single = DeviceMap::single(DeviceType::Cpu).split(5);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0], 0..5);
+    }
+}
@@ -0,0 +1,528 @@
+//! Sentence-embedding models (BERT family).
+//!
+//! Embedding models are encoder-only: a single forward pass over the input
+//! produces one hidden state per token, which is pooled (mean or `[CLS]`)
+//! into a fixed-size vector and L2-normalised so cosine similarity reduces
+//! to a dot product.
+//!
+//! Supported sources:
+//! - GGUF `bert` (all-MiniLM, bge, e5, …) and `nomic-bert` (nomic-embed-text)
+//!   as shipped by Ollama. Weights are dequantized to F32 at load time —
+//!   these models are small (≤ 0.5 B parameters) so the cost is negligible.
+//! - SafeTensors HuggingFace snapshots whose `config.json` declares
+//!   `"model_type": "bert"`.
+
+use crate::{
+    error::{InferenceError, InferenceResult},
+    loader::{meta_f32, meta_str, meta_usize},
+    tokenizer::{BpeTokenizer, Tokenizer},
+    ModelConfig,
+};
+use candle_core::{quantized::gguf_file, DType, Device, IndexOp, Module, Tensor};
+use candle_nn::{Embedding, LayerNorm, Linear, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config as BertConfig, HiddenAct};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+
+/// GGUF architectures handled by this module rather than the causal-LM loader.
+pub const EMBEDDING_ARCHITECTURES: &[&str] = &["bert", "nomic-bert"];
+
+/// Returns `true` if `arch` (GGUF `general.architecture` or HF `model_type`)
+/// is an encoder-only embedding architecture.
+pub fn is_embedding_architecture(arch: &str) -> bool {
+    EMBEDDING_ARCHITECTURES.contains(&arch)
+}
+
+/// How per-token hidden states are reduced to a single vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pooling {
+    /// Average over all tokens (sentence-transformers default).
+    Mean,
+    /// Hidden state of the leading `[CLS]` token (bge, e5).
+    Cls,
+}
+
+impl Pooling {
+    /// Map the llama.cpp `{arch}.pooling_type` metadata value.
+    /// 1 = mean, 2 = cls; anything else falls back to mean.
+    fn from_gguf(v: Option<usize>) -> Self {
+        match v {
+            Some(2) => Pooling::Cls,
+            _ => Pooling::Mean,
+        }
+    }
+}
+
+/// Result of an [`InferenceEngine::embed`](crate::InferenceEngine::embed) call.
+#[derive(Debug, Clone, Default)]
+pub struct Embeddings {
+    /// One L2-normalised vector per input, in input order.
+    pub vectors: Vec<Vec<f32>>,
+    /// Total tokens consumed across all inputs (including `[CLS]`/`[SEP]`).
+    pub prompt_tokens: usize,
+}
+
+enum Encoder {
+    Bert(BertModel),
+    NomicBert(NomicBert),
+}
+
+/// A loaded embedding model.
+pub struct EmbeddingModel {
+    encoder: Encoder,
+    /// Architecture config (for `ModelInfo`).
+    pub config: ModelConfig,
+    /// Vocabulary size.
+    pub vocab_size: usize,
+    /// Number of encoder layers.
+    pub num_layers: usize,
+    /// Pooling strategy read from model metadata.
+    pub pooling: Pooling,
+    /// WordPiece tokenizer; BOS/EOS are `[CLS]`/`[SEP]`.
+    pub tokenizer: BpeTokenizer,
+}
+
+impl EmbeddingModel {
+    /// Embed a single text, returning the normalised vector and its token count.
+    ///
+    /// Inputs longer than the model's context are truncated (keeping `[SEP]`).
+    pub fn embed_one(&self, text: &str, device: &Device) -> InferenceResult<(Vec<f32>, usize)> {
+        let mut tokens = self.tokenizer.encode(text)?;
+        let max_body = self.config.max_seq_len.saturating_sub(2).max(1);
+        tokens.truncate(max_body);
+        if let Some(cls) = self.tokenizer.bos_token_id() {
+            tokens.insert(0, cls);
+        }
+        if let Some(sep) = self.tokenizer.eos_token_id() {
+            tokens.push(sep);
+        }
+        if tokens.is_empty() {
+            return Err(InferenceError::InvalidInput(
+                "input encodes to zero tokens".to_string(),
+            ));
+        }
+        let n_tokens = tokens.len();
+
+        let ids = Tensor::new(tokens.as_slice(), device)?.unsqueeze(0)?; // [1, seq]
+        let hidden = match &self.encoder {
+            Encoder::Bert(m) => {
+                let type_ids = ids.zeros_like()?;
+                m.forward(&ids, &type_ids, None)?
+            }
+            Encoder::NomicBert(m) => m.forward(&ids)?,
+        }; // [1, seq, hidden]
+
+        let pooled = match self.pooling {
+            Pooling::Mean => hidden.mean(1)?,
+            Pooling::Cls => hidden.i((.., 0))?,
+        }; // [1, hidden]
+        let vector = pooled.squeeze(0)?.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+        Ok((l2_normalize(vector), n_tokens))
+    }
+}
+
+/// Scale `v` to unit length (left unchanged if it is all zeros).
+pub(crate) fn l2_normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+// ── GGUF ─────────────────────────────────────────────────────────────────────
+
+/// Load a `bert` or `nomic-bert` GGUF file.
+pub fn load_gguf(path: &Path, device: &Device) -> InferenceResult<EmbeddingModel> {
+    let mut file = std::fs::File::open(path).map_err(|e| {
+        InferenceError::ModelLoadError(format!("Cannot open {}: {e}", path.display()))
+    })?;
+    let gguf = gguf_file::Content::read(&mut file).map_err(|e| {
+        InferenceError::ModelLoadError(format!(
+            "Cannot parse GGUF header in {}: {e}",
+            path.display()
+        ))
+    })?;
+
+    let arch = meta_str(&gguf, "general.architecture").unwrap_or_else(|| "bert".to_string());
+    let pfx = arch.as_str();
+
+    let tokenizer = BpeTokenizer::for_gguf(path, &gguf)?;
+    let vocab_size = tokenizer.vocab_size();
+    let num_layers = meta_usize(&gguf, &format!("{pfx}.block_count")).unwrap_or(12);
+    let num_heads = meta_usize(&gguf, &format!("{pfx}.attention.head_count")).unwrap_or(12);
+    let hidden_dim = meta_usize(&gguf, &format!("{pfx}.embedding_length")).unwrap_or(768);
+    let inter_dim = meta_usize(&gguf, &format!("{pfx}.feed_forward_length")).unwrap_or(3_072);
+    let max_seq_len = meta_usize(&gguf, &format!("{pfx}.context_length")).unwrap_or(512);
+    let eps = meta_f32(&gguf, &format!("{pfx}.attention.layer_norm_epsilon")).unwrap_or(1e-12);
+    let rope_theta = meta_f32(&gguf, &format!("{pfx}.rope.freq_base")).unwrap_or(1_000.0);
+    let pooling = Pooling::from_gguf(meta_usize(&gguf, &format!("{pfx}.pooling_type")));
+
+    info!(
+        "GGUF embedding arch={arch}: {num_layers} layers, {num_heads} heads, \
+         hidden={hidden_dim}, vocab={vocab_size}, pooling={pooling:?}"
+    );
+
+    // Dequantize every tensor up front.
+    let mut tensors: HashMap<String, Tensor> = HashMap::new();
+    let names: Vec<String> = gguf.tensor_infos.keys().cloned().collect();
+    for name in names {
+        let t = gguf
+            .tensor(&mut file, &name, device)
+            .and_then(|q| q.dequantize(device))
+            .map_err(|e| {
+                InferenceError::ModelLoadError(format!("Cannot read tensor {name}: {e}"))
+            })?;
+        tensors.insert(name, t);
+    }
+
+    let config = ModelConfig {
+        architecture: arch.clone(),
+        max_seq_len,
+        vocab_size,
+        num_heads,
+        num_kv_heads: num_heads,
+        hidden_dim,
+        intermediate_dim: inter_dim,
+        rope_theta,
+        layer_norm_eps: eps,
+    };
+
+    let encoder = match arch.as_str() {
+        "bert" => {
+            let type_vocab_size = tensors
+                .get("token_types.weight")
+                .and_then(|t| t.dims().first().copied())
+                .unwrap_or(2);
+            let bert_config = BertConfig {
+                vocab_size,
+                hidden_size: hidden_dim,
+                num_hidden_layers: num_layers,
+                num_attention_heads: num_heads,
+                intermediate_size: inter_dim,
+                hidden_act: HiddenAct::Gelu,
+                max_position_embeddings: max_seq_len,
+                type_vocab_size,
+                layer_norm_eps: eps as f64,
+                ..Default::default()
+            };
+            let renamed: HashMap<String, Tensor> = tensors
+                .into_iter()
+                .filter_map(|(k, v)| gguf_bert_to_hf(&k).map(|hf| (hf, v)))
+                .collect();
+            let vb = VarBuilder::from_tensors(renamed, DType::F32, device);
+            let model = BertModel::load(vb, &bert_config).map_err(|e| {
+                InferenceError::ModelLoadError(format!("Cannot build bert weights: {e}"))
+            })?;
+            Encoder::Bert(model)
+        }
+        "nomic-bert" => {
+            let vb = VarBuilder::from_tensors(tensors, DType::F32, device);
+            let model = NomicBert::load(vb, &config, vocab_size, num_layers).map_err(|e| {
+                InferenceError::ModelLoadError(format!("Cannot build nomic-bert weights: {e}"))
+            })?;
+            Encoder::NomicBert(model)
+        }
+        other => {
+            return Err(InferenceError::InvalidFormat(format!(
+                "GGUF embedding architecture '{other}' is not supported. \
+                 Supported: {}.",
+                EMBEDDING_ARCHITECTURES.join(", ")
+            )));
+        }
+    };
+
+    Ok(EmbeddingModel {
+        encoder,
+        config,
+        vocab_size,
+        num_layers,
+        pooling,
+        tokenizer,
+    })
+}
+
+/// Map a llama.cpp BERT tensor name onto the HuggingFace name that
+/// [`BertModel::load`] expects. Returns `None` for tensors it does not use.
+fn gguf_bert_to_hf(name: &str) -> Option<String> {
+    let fixed = match name {
+        "token_embd.weight" => Some("embeddings.word_embeddings.weight"),
+        "position_embd.weight" => Some("embeddings.position_embeddings.weight"),
+        "token_types.weight" => Some("embeddings.token_type_embeddings.weight"),
+        "token_embd_norm.weight" => Some("embeddings.LayerNorm.weight"),
+        "token_embd_norm.bias" => Some("embeddings.LayerNorm.bias"),
+        _ => None,
+    };
+    if let Some(f) = fixed {
+        return Some(f.to_string());
+    }
+
+    let rest = name.strip_prefix("blk.")?;
+    let (layer, rest) = rest.split_once('.')?;
+    let (module, param) = rest.rsplit_once('.')?;
+    let hf_module = match module {
+        "attn_q" => "attention.self.query",
+        "attn_k" => "attention.self.key",
+        "attn_v" => "attention.self.value",
+        "attn_output" => "attention.output.dense",
+        "attn_output_norm" => "attention.output.LayerNorm",
+        "ffn_up" => "intermediate.dense",
+        "ffn_down" => "output.dense",
+        "layer_output_norm" => "output.LayerNorm",
+        _ => return None,
+    };
+    Some(format!("encoder.layer.{layer}.{hf_module}.{param}"))
+}
+
+// ── SafeTensors ───────────────────────────────────────────────────────────────
+
+/// Load a HuggingFace BERT snapshot (e.g. a sentence-transformers model).
+///
+/// Pooling is read from `1_Pooling/config.json` when present.
+pub fn load_safetensors(
+    safetensors_paths: &[&Path],
+    config_json_path: &Path,
+    device: &Device,
+) -> InferenceResult<EmbeddingModel> {
+    let config_str = std::fs::read_to_string(config_json_path).map_err(|e| {
+        InferenceError::ModelLoadError(format!("Cannot read {}: {e}", config_json_path.display()))
+    })?;
+    let bert_config: BertConfig = serde_json::from_str(&config_str)
+        .map_err(|e| InferenceError::ModelLoadError(format!("Cannot parse config.json: {e}")))?;
+
+    // SAFETY: callers must ensure no other process writes to these files
+    // while the model is loaded.
+    let vb = unsafe {
+        VarBuilder::from_mmaped_safetensors(safetensors_paths, DType::F32, device).map_err(|e| {
+            InferenceError::ModelLoadError(format!("Cannot mmap SafeTensors shards: {e}"))
+        })?
+    };
+    let model = BertModel::load(vb, &bert_config)
+        .map_err(|e| InferenceError::ModelLoadError(format!("Cannot build BERT model: {e}")))?;
+
+    let dir = config_json_path.parent().unwrap_or(Path::new("."));
+    let tokenizer = BpeTokenizer::from_file(&dir.join("tokenizer.json"))?;
+    let pooling = read_st_pooling(&dir.join("1_Pooling").join("config.json"));
+
+    info!(
+        "SafeTensors embedding: {} layers, hidden={}, vocab={}, pooling={:?}",
+        bert_config.num_hidden_layers, bert_config.hidden_size, bert_config.vocab_size, pooling,
+    );
+
+    let config = ModelConfig {
+        architecture: "bert".to_string(),
+        max_seq_len: bert_config.max_position_embeddings,
+        vocab_size: bert_config.vocab_size,
+        num_heads: bert_config.num_attention_heads,
+        num_kv_heads: bert_config.num_attention_heads,
+        hidden_dim: bert_config.hidden_size,
+        intermediate_dim: bert_config.intermediate_size,
+        rope_theta: 0.0,
+        layer_norm_eps: bert_config.layer_norm_eps as f32,
+    };
+
+    Ok(EmbeddingModel {
+        encoder: Encoder::Bert(model),
+        config,
+        vocab_size: bert_config.vocab_size,
+        num_layers: bert_config.num_hidden_layers,
+        pooling,
+        tokenizer,
+    })
+}
+
+/// Read the sentence-transformers pooling config; defaults to mean pooling.
+fn read_st_pooling(path: &Path) -> Pooling {
+    let cls = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v.get("pooling_mode_cls_token")?.as_bool())
+        .unwrap_or(false);
+    if cls {
+        Pooling::Cls
+    } else {
+        Pooling::Mean
+    }
+}
+
+// ── nomic-bert ────────────────────────────────────────────────────────────────
+
+/// NomicBERT encoder: BERT with rotary position embeddings, a fused QKV
+/// projection and a SwiGLU feed-forward, all without biases. Uses the
+/// llama.cpp GGUF tensor names directly.
+struct NomicBert {
+    word_embeddings: Embedding,
+    token_type_embeddings: Option<Embedding>,
+    emb_norm: LayerNorm,
+    layers: Vec<NomicLayer>,
+    num_heads: usize,
+    head_dim: usize,
+    rope_theta: f32,
+}
+
+struct NomicLayer {
+    qkv: Linear,
+    out: Linear,
+    attn_norm: LayerNorm,
+    up: Linear,
+    gate: Linear,
+    down: Linear,
+    out_norm: LayerNorm,
+}
+
+impl NomicBert {
+    fn load(
+        vb: VarBuilder,
+        cfg: &ModelConfig,
+        vocab_size: usize,
+        num_layers: usize,
+    ) -> candle_core::Result<Self> {
+        use candle_nn::{embedding, layer_norm, linear_no_bias};
+
+        let h = cfg.hidden_dim;
+        let eps = cfg.layer_norm_eps as f64;
+        let word_embeddings = embedding(vocab_size, h, vb.pp("token_embd"))?;
+        // Optional: some exports drop the (all-zero-index) token-type table.
+        let token_type_embeddings = embedding(2, h, vb.pp("token_types")).ok();
+        let emb_norm = layer_norm(h, eps, vb.pp("token_embd_norm"))?;
+
+        let layers = (0..num_layers)
+            .map(|i| {
+                let b = vb.pp(format!("blk.{i}"));
+                Ok(NomicLayer {
+                    qkv: linear_no_bias(h, 3 * h, b.pp("attn_qkv"))?,
+                    out: linear_no_bias(h, h, b.pp("attn_output"))?,
+                    attn_norm: layer_norm(h, eps, b.pp("attn_output_norm"))?,
+                    up: linear_no_bias(h, cfg.intermediate_dim, b.pp("ffn_up"))?,
+                    gate: linear_no_bias(h, cfg.intermediate_dim, b.pp("ffn_gate"))?,
+                    down: linear_no_bias(cfg.intermediate_dim, h, b.pp("ffn_down"))?,
+                    out_norm: layer_norm(h, eps, b.pp("layer_output_norm"))?,
+                })
+            })
+            .collect::<candle_core::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            word_embeddings,
+            token_type_embeddings,
+            emb_norm,
+            layers,
+            num_heads: cfg.num_heads,
+            head_dim: h / cfg.num_heads,
+            rope_theta: cfg.rope_theta,
+        })
+    }
+
+    /// `ids` — `[1, seq]` token IDs. Returns hidden states `[1, seq, hidden]`.
+    fn forward(&self, ids: &Tensor) -> candle_core::Result<Tensor> {
+        let (b, seq) = ids.dims2()?;
+        let mut x = self.word_embeddings.forward(ids)?;
+        if let Some(tt) = &self.token_type_embeddings {
+            x = x.broadcast_add(&tt.forward(&ids.zeros_like()?)?)?;
+        }
+        let mut x = self.emb_norm.forward(&x)?;
+
+        let (cos, sin) = self.rope_tables(seq, ids.device())?;
+        let scale = 1.0 / (self.head_dim as f64).sqrt();
+        let hidden = self.num_heads * self.head_dim;
+
+        for layer in &self.layers {
+            let qkv = layer
+                .qkv
+                .forward(&x)?
+                .reshape((b, seq, 3, self.num_heads, self.head_dim))?;
+            let split = |i: usize| qkv.i((.., .., i))?.transpose(1, 2)?.contiguous();
+            let q = candle_nn::rotary_emb::rope(&split(0)?, &cos, &sin)?;
+            let k = candle_nn::rotary_emb::rope(&split(1)?, &cos, &sin)?;
+            let v = split(2)?;
+
+            let att = (q.matmul(&k.t()?)? * scale)?;
+            let att = candle_nn::ops::softmax_last_dim(&att)?;
+            let ctx = att.matmul(&v)?.transpose(1, 2)?.reshape((b, seq, hidden))?;
+
+            x = layer.attn_norm.forward(&(x + layer.out.forward(&ctx)?)?)?;
+            let ff = (candle_nn::ops::silu(&layer.gate.forward(&x)?)? * layer.up.forward(&x)?)?;
+            x = layer.out_norm.forward(&(x + layer.down.forward(&ff)?)?)?;
+        }
+        Ok(x)
+    }
+
+    /// Non-interleaved rotary tables of shape `[seq, head_dim / 2]`.
+    fn rope_tables(&self, seq: usize, device: &Device) -> candle_core::Result<(Tensor, Tensor)> {
+        let half = self.head_dim / 2;
+        let inv_freq: Vec<f32> = (0..half)
+            .map(|i| 1.0 / self.rope_theta.powf(2.0 * i as f32 / self.head_dim as f32))
+            .collect();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, half), device)?;
+        let t = Tensor::arange(0u32, seq as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((seq, 1))?;
+        let freqs = t.matmul(&inv_freq)?;
+        Ok((freqs.cos()?, freqs.sin()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn l2_normalize_yields_unit_vector() {
+        let v = l2_normalize(vec![3.0, 4.0]);
+        assert!((v[0] - 0.6).abs() < 1e-6);
+        assert!((v[1] - 0.8).abs() < 1e-6);
+        assert_eq!(l2_normalize(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn gguf_bert_names_map_to_hf() {
+        assert_eq!(
+            gguf_bert_to_hf("blk.3.attn_q.bias").as_deref(),
+            Some("encoder.layer.3.attention.self.query.bias")
+        );
+        assert_eq!(
+            gguf_bert_to_hf("blk.11.layer_output_norm.weight").as_deref(),
+            Some("encoder.layer.11.output.LayerNorm.weight")
+        );
+        assert_eq!(
+            gguf_bert_to_hf("token_embd_norm.bias").as_deref(),
+            Some("embeddings.LayerNorm.bias")
+        );
+        assert_eq!(gguf_bert_to_hf("blk.0.unknown.weight"), None);
+    }
+
+    #[test]
+    fn embedding_architectures() {
+        assert!(is_embedding_architecture("bert"));
+        assert!(is_embedding_architecture("nomic-bert"));
+        assert!(!is_embedding_architecture("llama"));
+    }
+
+    #[test]
+    fn nomic_bert_forward_shape() {
+        let device = Device::Cpu;
+        let cfg = ModelConfig {
+            architecture: "nomic-bert".to_string(),
+            max_seq_len: 16,
+            vocab_size: 10,
+            num_heads: 2,
+            num_kv_heads: 2,
+            hidden_dim: 8,
+            intermediate_dim: 16,
+            rope_theta: 1_000.0,
+            layer_norm_eps: 1e-12,
+        };
+        let vb = VarBuilder::zeros(DType::F32, &device);
+        let model = NomicBert::load(vb, &cfg, 10, 2).unwrap();
+        let ids = Tensor::new(&[[1u32, 2, 3, 4]], &device).unwrap();
+        let out = model.forward(&ids).unwrap();
+        assert_eq!(out.dims(), &[1, 4, 8]);
+    }
+
+    #[test]
+    fn pooling_from_gguf_metadata() {
+        assert_eq!(Pooling::from_gguf(Some(2)), Pooling::Cls);
+        assert_eq!(Pooling::from_gguf(Some(1)), Pooling::Mean);
+        assert_eq!(Pooling::from_gguf(None), Pooling::Mean);
+    }
+}
@@ -2,10 +2,20 @@
 
 use crate::{
     config::EngineConfig,
+    constraint::{mask_logits, token_piece, JsonValidator, ResponseFormat},
+    embedding::{self, EmbeddingModel, Embeddings},
     error::{InferenceError, InferenceResult},
-    loader::{self, GgufModel, SafeTensorsModel},
+    generation::{
+        logprobs_from_logits, FinishReason, GenerateOptions, Generation, TokenLogprob, TopLogprob,
+        MAX_TOP_LOGPROBS,
+    },
+    loader::{self, GgufModel, GgufWeights, SafeTensorsModel},
     model::{ModelFormat, ModelHandle, ModelInfo},
-    tokenizer::Tokenizer,
+    moe::ExpertOffload,
+    shard::TransformerShard,
+    speculative::{speculative_generate, CausalLm, ShardSession, Speculation},
+    stop::StopCriteria,
+    tokenizer::{BpeTokenizer, ChatMessage, ChatTemplate, Tokenizer},
     InferenceProvider, ModelConfig,
 };
 use async_trait::async_trait;
@@ -13,10 +23,12 @@ use candle_core::{DType, Device, Tensor};
 use candle_transformers::generation::LogitsProcessor;
 use candle_transformers::models::llama::Cache;
 use std::collections::HashMap;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Mutex;
-use tracing::{debug, info};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{debug, info, warn};
 
 // ── Loaded weights ────────────────────────────────────────────────────────────
 
@@ -32,6 +44,11 @@ enum LoadedWeights {
     Gguf(Mutex<GgufModel>),
     /// Full-precision model from SafeTensors shards (F16 / F32)
     SafeTensors(Mutex<SafeTensorsModel>),
+    /// Encoder-only sentence-embedding model (BERT family)
+    Embedding(Mutex<EmbeddingModel>),
+    /// A range of transformer blocks served to the swarm (see
+    /// [`InferenceEngine::load_model_blocks`])
+    Blocks(Arc<TransformerShard>),
 }
 
 struct LoadedModelEntry {
@@ -53,20 +70,38 @@ pub struct InferenceEngine {
     /// Decode throughput in tok/s from the most recent `generate()` call.
     /// Stored as the raw bits of an `f64` so it can live in an `AtomicU64`.
     last_decode_tps: AtomicU64,
+    /// Draft model for speculative decoding, loaded on first use.
+    draft: Mutex<Option<Arc<TransformerShard>>>,
+    /// Expert placement for Mixtral models loaded after it is set.
+    expert_offload: Option<ExpertOffload>,
 }
 
 impl InferenceEngine {
     pub fn new(config: EngineConfig) -> InferenceResult<Self> {
         let device = config.device.to_candle_device()?;
         info!("Inference engine initialised on {:?}", config.device);
-        Ok(Self {
+        Ok(Self::with_device(config, device))
+    }
+
+    /// Engine computing on an already-open `device`, so its tensors can be
+    /// mixed with the caller's (a CUDA device opened twice is two devices).
+    pub fn with_device(config: EngineConfig, device: Device) -> Self {
+        Self {
             config,
             device,
             models: HashMap::new(),
             next_model_id: AtomicU64::new(1),
             current_memory: 0,
             last_decode_tps: AtomicU64::new(0),
-        })
+            draft: Mutex::new(None),
+            expert_offload: None,
+        }
+    }
+
+    /// Load only some experts of Mixtral models loaded from now on and run
+    /// the others through `offload.remote`; `None` loads every expert.
+    pub fn set_expert_offload(&mut self, offload: Option<ExpertOffload>) {
+        self.expert_offload = offload;
     }
 
     /// Tokens/second measured during the decode phase of the last `generate()` call.
@@ -80,6 +115,10 @@ impl InferenceEngine {
         }
     }
 
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
     pub fn device(&self) -> &Device {
         &self.device
     }
@@ -255,12 +294,947 @@ impl InferenceEngine {
                 );
                 tps
             }
+
+            LoadedWeights::Embedding(_) | LoadedWeights::Blocks(_) => {
+                return Err(not_generative(handle))
+            }
         };
 
         Ok(tps)
     }
 }
 
+// ── Generation (token accounting + logprobs) ──────────────────────────────────
+
+impl InferenceEngine {
+    /// Encode `text` with the model's own tokenizer (no BOS is added).
+    pub fn tokenize(&self, handle: &ModelHandle, text: &str) -> InferenceResult<Vec<u32>> {
+        with_tokenizer(self.entry(handle)?, |t| t.encode(text))
+    }
+
+    /// Decode `tokens` with the model's own tokenizer (special tokens skipped).
+    pub fn detokenize(&self, handle: &ModelHandle, tokens: &[u32]) -> InferenceResult<String> {
+        with_tokenizer(self.entry(handle)?, |t| t.decode(tokens))
+    }
+
+    /// Number of tokens `text` encodes to with the model's tokenizer.
+    pub fn count_tokens(&self, handle: &ModelHandle, text: &str) -> InferenceResult<usize> {
+        with_tokenizer(self.entry(handle)?, |t| t.count_tokens(text))
+    }
+
+    /// Chat prompt format of the model, detected from its vocabulary.
+    pub fn chat_template(&self, handle: &ModelHandle) -> InferenceResult<ChatTemplate> {
+        with_tokenizer(self.entry(handle)?, |t| Ok(t.chat_template()))
+    }
+
+    /// Render `messages` in the model's chat format, ready for
+    /// [`generate_with`](Self::generate_with).
+    pub fn apply_chat_template(
+        &self,
+        handle: &ModelHandle,
+        messages: &[ChatMessage],
+    ) -> InferenceResult<String> {
+        Ok(self.chat_template(handle)?.render(messages))
+    }
+
+    fn entry(&self, handle: &ModelHandle) -> InferenceResult<&LoadedModelEntry> {
+        self.models
+            .get(&handle.id())
+            .ok_or(InferenceError::InvalidHandle(handle.id()))
+    }
+
+    /// Generate a completion for `prompt`, returning exact prompt/completion
+    /// token counts and, if `opts.logprobs` is set, per-token log-probabilities.
+    pub fn generate_with(
+        &self,
+        handle: &ModelHandle,
+        prompt: &str,
+        opts: &GenerateOptions,
+    ) -> InferenceResult<Generation> {
+        let mut seq = self.start_sequence(handle, prompt, opts)?;
+        while !self.step(&mut seq)? {}
+        self.finish_sequence(seq)
+    }
+}
+
+// ── Step-wise generation ──────────────────────────────────────────────────────
+
+/// KV-cache state owned by one [`Sequence`].
+enum SequenceState {
+    /// A private copy of the GGUF weights: the tensors are shared with the
+    /// engine, but the internal KV-cache belongs to this sequence alone.
+    Gguf(Box<GgufWeights>),
+    /// The engine's own GGUF weights, for architectures that cannot be
+    /// copied. Their KV-cache is shared, so the sequence must run alone.
+    GgufShared,
+    /// A private KV-cache for the shared SafeTensors model.
+    SafeTensors(Box<Cache>),
+}
+
+/// One in-flight generation, advanced a forward pass at a time with
+/// [`InferenceEngine::step`].
+///
+/// Each sequence owns its KV-cache, so a scheduler can interleave the decode
+/// steps of many requests on one model (continuous batching).
+pub struct Sequence {
+    handle: ModelHandle,
+    label: &'static str,
+    state: SequenceState,
+    logits_processor: LogitsProcessor,
+    /// JSON mode: the output so far and the token that may end it.
+    json: Option<(JsonValidator, u32)>,
+    opts: GenerateOptions,
+    prompt_tokens: Vec<u32>,
+    /// Stop tokens, stop strings and budget; holds the generated tokens.
+    criteria: StopCriteria,
+    /// Final text not yet collected with [`Sequence::take_text`].
+    unread: String,
+    token_logprobs: Vec<TokenLogprob>,
+    /// Logits of the last forward pass and the token sampled from them, not
+    /// yet emitted. `None` until the prefill has run.
+    pending: Option<(Tensor, u32)>,
+    pos: usize,
+    finished: bool,
+    truncated_by_deadline: bool,
+    decode_start: Option<Instant>,
+}
+
+impl Sequence {
+    /// Model this sequence runs on.
+    pub fn handle(&self) -> ModelHandle {
+        self.handle
+    }
+
+    /// `true` if this sequence uses the model's shared KV-cache, so no other
+    /// sequence of the same model may step until it finishes.
+    pub fn is_exclusive(&self) -> bool {
+        matches!(self.state, SequenceState::GgufShared)
+    }
+
+    /// `true` once [`InferenceEngine::step`] has reported completion.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Tokens generated so far.
+    pub fn completion_tokens(&self) -> usize {
+        self.criteria.tokens().len()
+    }
+
+    /// Completion text that became final since the last call, for
+    /// streaming. Text that might begin a stop string is held back until
+    /// the next step settles it, so the pieces add up to the final
+    /// [`Generation::text`].
+    pub fn take_text(&mut self) -> String {
+        std::mem::take(&mut self.unread)
+    }
+}
+
+impl InferenceEngine {
+    /// Encode `prompt` and set up a [`Sequence`] for it. No forward pass runs
+    /// until the first [`step`](Self::step).
+    pub fn start_sequence(
+        &self,
+        handle: &ModelHandle,
+        prompt: &str,
+        opts: &GenerateOptions,
+    ) -> InferenceResult<Sequence> {
+        if opts.top_logprobs > MAX_TOP_LOGPROBS {
+            return Err(InferenceError::InvalidInput(format!(
+                "top_logprobs must be at most {MAX_TOP_LOGPROBS}"
+            )));
+        }
+        StopCriteria::validate(&opts.stop)?;
+
+        let entry = self
+            .models
+            .get(&handle.id())
+            .ok_or(InferenceError::InvalidHandle(handle.id()))?;
+
+        let (label, state, (prompt_tokens, stop_ids)) = match &entry.weights {
+            // ── Quantized GGUF path ───────────────────────────────────────────
+            LoadedWeights::Gguf(m) => {
+                let guard = m.lock().unwrap();
+                let state = match guard.weights.fork() {
+                    Some(w) => SequenceState::Gguf(Box::new(w)),
+                    None => SequenceState::GgufShared,
+                };
+                ("GGUF", state, encode_prompt(&guard.tokenizer, prompt)?)
+            }
+
+            // ── Full-precision SafeTensors path ───────────────────────────────
+            LoadedWeights::SafeTensors(m) => {
+                let guard = m.lock().unwrap();
+                let cache = Cache::new(true, DType::F16, &guard.llama_config, &self.device)
+                    .map_err(InferenceError::from)?;
+                (
+                    "SafeTensors",
+                    SequenceState::SafeTensors(Box::new(cache)),
+                    encode_prompt(&guard.tokenizer, prompt)?,
+                )
+            }
+
+            LoadedWeights::Embedding(_) | LoadedWeights::Blocks(_) => {
+                return Err(not_generative(handle))
+            }
+        };
+        let json = match opts.response_format {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => {
+                let stop = stop_ids.first().copied().ok_or_else(|| {
+                    InferenceError::InvalidInput(
+                        "JSON mode needs a model with an end-of-sequence token".to_string(),
+                    )
+                })?;
+                Some((JsonValidator::new(), stop))
+            }
+        };
+        let bos_id = with_tokenizer(entry, |t| Ok(t.bos_token_id()))?;
+        let prompt_tokens = fit_to_context(prompt_tokens, bos_id, entry.info.context_length, opts)?;
+
+        info!(
+            "generate() {} handle {}: {} prompt tokens, stop={:?}",
+            label,
+            handle.id(),
+            prompt_tokens.len(),
+            stop_ids,
+        );
+
+        Ok(Sequence {
+            handle: *handle,
+            label,
+            state,
+            logits_processor: LogitsProcessor::new(42, Some(opts.temperature), None),
+            json,
+            opts: opts.clone(),
+            prompt_tokens,
+            criteria: StopCriteria::new(stop_ids, &opts.stop, opts.max_new_tokens),
+            unread: String::new(),
+            token_logprobs: Vec::new(),
+            pending: None,
+            pos: 0,
+            finished: false,
+            truncated_by_deadline: false,
+            decode_start: None,
+        })
+    }
+
+    /// Advance `seq` by one forward pass: the whole prompt on the first call,
+    /// then one token per call. Returns `true` once the sequence has finished
+    /// (stop token, `max_new_tokens` or deadline); collect the result with
+    /// [`finish_sequence`](Self::finish_sequence).
+    pub fn step(&self, seq: &mut Sequence) -> InferenceResult<bool> {
+        if seq.finished {
+            return Ok(true);
+        }
+        let entry = self
+            .models
+            .get(&seq.handle.id())
+            .ok_or(InferenceError::InvalidHandle(seq.handle.id()))?;
+
+        let Some((logits, next_token)) = seq.pending.take() else {
+            // A request that waited out its deadline in the queue gets an empty
+            // completion instead of paying for a prefill it cannot use.
+            if seq.opts.deadline_passed() {
+                info!(
+                    "generate() {} handle {}: deadline passed before prefill",
+                    seq.label,
+                    seq.handle.id()
+                );
+                seq.criteria.finish(FinishReason::Deadline);
+                seq.truncated_by_deadline = true;
+                seq.finished = true;
+                return Ok(true);
+            }
+
+            // Prefill: process the entire prompt in one forward pass.
+            let logits = self.forward_sequence(entry, &mut seq.state, &seq.prompt_tokens, 0)?;
+            let next = sample_next(entry, seq, &logits)?;
+            seq.pending = Some((logits, next));
+            seq.pos = seq.prompt_tokens.len();
+            seq.decode_start = Some(Instant::now());
+            return Ok(false);
+        };
+
+        let recorded = seq.criteria.tokens().len();
+        let piece = with_tokenizer(entry, |t| seq.criteria.push(t, next_token))?;
+        seq.unread.push_str(&piece);
+        if seq.opts.logprobs && seq.criteria.tokens().len() > recorded {
+            // Raw-logit log-probabilities for the sampled token.
+            let values = logits
+                .to_dtype(DType::F32)
+                .and_then(|l| l.to_vec1::<f32>())
+                .map_err(InferenceError::from)?;
+            let lp = with_tokenizer(entry, |t| {
+                token_logprob(t, &values, next_token, seq.opts.top_logprobs)
+            })?;
+            seq.token_logprobs.push(lp);
+        }
+        if seq.criteria.is_finished() {
+            seq.finished = true;
+            return Ok(true);
+        }
+        if seq.opts.deadline_passed() {
+            let rest = seq.criteria.finish(FinishReason::Deadline);
+            seq.unread.push_str(&rest);
+            seq.truncated_by_deadline = true;
+            seq.finished = true;
+            return Ok(true);
+        }
+
+        // Decode: feed the new token, sample the next.
+        let logits = self.forward_sequence(entry, &mut seq.state, &[next_token], seq.pos)?;
+        let next = sample_next(entry, seq, &logits)?;
+        seq.pending = Some((logits, next));
+        seq.pos += 1;
+        Ok(false)
+    }
+
+    /// Consume a finished (or abandoned) sequence and decode its result.
+    ///
+    /// Decode throughput is measured over the sequence's wall-clock decode
+    /// time, so it reflects sharing the model with interleaved sequences.
+    pub fn finish_sequence(&self, seq: Sequence) -> InferenceResult<Generation> {
+        self.entry(&seq.handle)?;
+        let decode_secs = seq.decode_start.map_or(0.0, |t| t.elapsed().as_secs_f64());
+        let generated = seq.criteria.tokens().len();
+
+        if generated > 0 && decode_secs > 0.0 {
+            let tps = generated as f64 / decode_secs;
+            self.last_decode_tps.store(tps.to_bits(), Ordering::Relaxed);
+        }
+
+        if seq.truncated_by_deadline && seq.decode_start.is_some() {
+            info!(
+                "generate() {} handle {}: deadline reached after {} tokens",
+                seq.label,
+                seq.handle.id(),
+                generated
+            );
+        }
+
+        debug!(
+            "generate() {} handle {}: {} tokens in {:.2}s ({:.1} tok/s)",
+            seq.label,
+            seq.handle.id(),
+            generated,
+            decode_secs,
+            if decode_secs > 0.0 {
+                generated as f64 / decode_secs
+            } else {
+                0.0
+            },
+        );
+
+        Ok(Generation {
+            text: seq.criteria.text().to_string(),
+            prompt_tokens: seq.prompt_tokens.len(),
+            completion_tokens: generated,
+            logprobs: seq.opts.logprobs.then_some(seq.token_logprobs),
+            truncated_by_deadline: seq.truncated_by_deadline,
+            finish_reason: seq.criteria.finish_reason().unwrap_or_default(),
+            speculative: None,
+        })
+    }
+
+    /// Run `tokens` at `pos` through the model with `state`'s KV-cache and
+    /// return logits of shape `[vocab_size]` for the last position.
+    fn forward_sequence(
+        &self,
+        entry: &LoadedModelEntry,
+        state: &mut SequenceState,
+        tokens: &[u32],
+        pos: usize,
+    ) -> InferenceResult<Tensor> {
+        let input = Tensor::new(tokens, &self.device)
+            .map_err(InferenceError::from)?
+            .unsqueeze(0)
+            .map_err(InferenceError::from)?; // [1, seq_len]
+
+        // index_pos=0 on the prefill resets a GGUF model's internal KV-cache.
+        let logits = match (state, &entry.weights) {
+            (SequenceState::Gguf(weights), _) => weights.forward(&input, pos),
+            (SequenceState::GgufShared, LoadedWeights::Gguf(m)) => {
+                m.lock().unwrap().weights.forward(&input, pos)
+            }
+            (SequenceState::SafeTensors(cache), LoadedWeights::SafeTensors(m)) => {
+                m.lock().unwrap().model.forward(&input, pos, cache)
+            }
+            _ => {
+                return Err(InferenceError::InferenceFailed(
+                    "sequence state does not match its model".to_string(),
+                ))
+            }
+        }
+        .map_err(InferenceError::from)?; // [1, vocab_size]
+        logits.squeeze(0).map_err(InferenceError::from)
+    }
+}
+
+/// Sample the token after `logits`, restricted to tokens that keep the
+/// output valid in JSON mode.
+fn sample_next(
+    entry: &LoadedModelEntry,
+    seq: &mut Sequence,
+    logits: &Tensor,
+) -> InferenceResult<u32> {
+    let Some((validator, stop)) = &mut seq.json else {
+        return seq
+            .logits_processor
+            .sample(logits)
+            .map_err(InferenceError::from);
+    };
+    let output = seq.criteria.tokens();
+    let mut values = logits
+        .to_dtype(DType::F32)
+        .and_then(|l| l.to_vec1::<f32>())
+        .map_err(InferenceError::from)?;
+    with_tokenizer(entry, |t| {
+        mask_logits(
+            &mut values,
+            validator,
+            |id| token_piece(t, output, id),
+            Some(*stop),
+        )
+    })?;
+    let masked = Tensor::new(values, logits.device()).map_err(InferenceError::from)?;
+    let token = seq
+        .logits_processor
+        .sample(&masked)
+        .map_err(InferenceError::from)?;
+    if token != *stop {
+        let piece = with_tokenizer(entry, |t| token_piece(t, output, token))?;
+        validator.push_str(&piece.unwrap_or_default());
+    }
+    Ok(token)
+}
+
+/// Run `f` with the tokenizer of a generative model.
+fn with_tokenizer<R>(
+    entry: &LoadedModelEntry,
+    f: impl FnOnce(&BpeTokenizer) -> InferenceResult<R>,
+) -> InferenceResult<R> {
+    match &entry.weights {
+        LoadedWeights::Gguf(m) => f(&m.lock().unwrap().tokenizer),
+        LoadedWeights::SafeTensors(m) => f(&m.lock().unwrap().tokenizer),
+        LoadedWeights::Embedding(m) => f(&m.lock().unwrap().tokenizer),
+        LoadedWeights::Blocks(shard) => f(&shard.tokenizer),
+    }
+}
+
+/// Encode `prompt` for generation, returning the prompt tokens (with BOS
+/// where appropriate) and the set of stop-token IDs.
+fn encode_prompt(tokenizer: &BpeTokenizer, prompt: &str) -> InferenceResult<(Vec<u32>, Vec<u32>)> {
+    let mut prompt_tokens = tokenizer.encode(prompt)?;
+    let eos_id = tokenizer.eos_token_id();
+    let bos_id = tokenizer.bos_token_id();
+
+    // Prepend BOS only when it is a distinct token from EOS.
+    // Models like Qwen2 set BOS == EOS (both are <|endoftext|>=151643);
+    // prepending EOS as BOS causes the model to immediately terminate.
+    if let Some(bos) = bos_id {
+        if Some(bos) != eos_id {
+            prompt_tokens.insert(0, bos);
+        }
+    }
+
+    // Build the full stop-token set: the registered EOS plus common
+    // ChatML/instruct stop tokens that the vocab may contain.
+    let mut stop_ids: Vec<u32> = eos_id.into_iter().collect();
+    for candidate in &["<|im_end|>", "<|eot_id|>", "<|end_of_text|>"] {
+        if let Some(id) = tokenizer.token_to_id(candidate) {
+            if !stop_ids.contains(&id) {
+                stop_ids.push(id);
+            }
+        }
+    }
+    Ok((prompt_tokens, stop_ids))
+}
+
+/// Make `prompt_tokens` leave room for `opts.max_new_tokens` in a context of
+/// `context_length` tokens (0 = unknown, no limit).
+///
+/// With `opts.truncate_prompt` the oldest tokens are dropped, keeping a
+/// leading `bos_id`; otherwise an over-long prompt is an error.
+fn fit_to_context(
+    mut prompt_tokens: Vec<u32>,
+    bos_id: Option<u32>,
+    context_length: usize,
+    opts: &GenerateOptions,
+) -> InferenceResult<Vec<u32>> {
+    if context_length == 0 || prompt_tokens.len() + opts.max_new_tokens <= context_length {
+        return Ok(prompt_tokens);
+    }
+    let exceeded = InferenceError::ContextLengthExceeded {
+        prompt_tokens: prompt_tokens.len(),
+        max_new_tokens: opts.max_new_tokens,
+        context_length,
+    };
+    let keep = usize::from(bos_id.is_some() && prompt_tokens.first() == bos_id.as_ref());
+    let budget = context_length.saturating_sub(opts.max_new_tokens);
+    if !opts.truncate_prompt || budget <= keep {
+        return Err(exceeded);
+    }
+    let dropped = prompt_tokens.len() - budget;
+    prompt_tokens.drain(keep..keep + dropped);
+    warn!("Prompt truncated by {dropped} tokens to fit a {context_length}-token context");
+    Ok(prompt_tokens)
+}
+
+/// Log-probability record for `token` given raw `logits`.
+fn token_logprob(
+    tokenizer: &BpeTokenizer,
+    logits: &[f32],
+    token: u32,
+    top_n: usize,
+) -> InferenceResult<TokenLogprob> {
+    let (logprob, top) = logprobs_from_logits(logits, token, top_n);
+    let top_logprobs = top
+        .into_iter()
+        .map(|(id, lp)| {
+            Ok(TopLogprob {
+                token_id: id,
+                token: tokenizer.decode(&[id])?,
+                logprob: lp,
+            })
+        })
+        .collect::<InferenceResult<Vec<_>>>()?;
+    Ok(TokenLogprob {
+        token_id: token,
+        token: tokenizer.decode(&[token])?,
+        logprob,
+        top_logprobs,
+    })
+}
+
+// ── Speculative decoding ──────────────────────────────────────────────────────
+
+impl InferenceEngine {
+    /// The draft model from [`EngineConfig::draft_model`], loaded on first use.
+    pub fn draft_model(&self) -> InferenceResult<Arc<TransformerShard>> {
+        let mut slot = self.draft.lock().unwrap();
+        if let Some(shard) = slot.as_ref() {
+            return Ok(Arc::clone(shard));
+        }
+        let dir =
+            self.config.draft_model.as_deref().ok_or_else(|| {
+                InferenceError::InvalidInput("no draft model configured".to_string())
+            })?;
+        let shard = Arc::new(load_full_shard(dir, &self.device)?);
+        info!(
+            "Draft model loaded from {} ({} layers)",
+            dir.display(),
+            shard.cfg.num_total_blocks
+        );
+        *slot = Some(Arc::clone(&shard));
+        Ok(shard)
+    }
+
+    /// Generate a completion for `prompt` with speculative decoding: the
+    /// configured draft model proposes [`EngineConfig::speculative_tokens`]
+    /// tokens per round and `target` verifies them in a single pass.
+    ///
+    /// `target` may be local ([`ShardSession`](crate::ShardSession)) or a
+    /// remote block chain. The prompt is encoded with the draft model's
+    /// tokenizer, so both models must share a vocabulary.
+    pub fn generate_speculative(
+        &self,
+        target: &mut dyn CausalLm,
+        prompt: &str,
+        opts: &GenerateOptions,
+    ) -> InferenceResult<Generation> {
+        if opts.top_logprobs > MAX_TOP_LOGPROBS {
+            return Err(InferenceError::InvalidInput(format!(
+                "top_logprobs must be at most {MAX_TOP_LOGPROBS}"
+            )));
+        }
+        StopCriteria::validate(&opts.stop)?;
+        if opts.response_format != ResponseFormat::Text {
+            return Err(InferenceError::InvalidInput(
+                "speculative decoding does not support constrained output".to_string(),
+            ));
+        }
+        let draft = self.draft_model()?;
+        let tokenizer = &draft.tokenizer;
+        let (prompt_tokens, stop_ids) = encode_prompt(tokenizer, prompt)?;
+        let prompt_len = prompt_tokens.len();
+        if opts.deadline_passed() {
+            return Ok(Generation {
+                prompt_tokens: prompt_len,
+                logprobs: opts.logprobs.then(Vec::new),
+                truncated_by_deadline: true,
+                finish_reason: FinishReason::Deadline,
+                ..Default::default()
+            });
+        }
+
+        let spec = Speculation {
+            draft_tokens: self.config.speculative_tokens.max(1),
+            temperature: opts.temperature,
+            seed: 42,
+        };
+        let mut draft_session = ShardSession::new(&draft, self.next_id());
+        let mut token_logprobs = Vec::new();
+        let mut criteria = StopCriteria::new(stop_ids.clone(), &opts.stop, opts.max_new_tokens);
+        let mut truncated_by_deadline = false;
+
+        let start = std::time::Instant::now();
+        let (generated, stats) = speculative_generate(
+            &mut draft_session,
+            target,
+            &prompt_tokens,
+            &stop_ids,
+            opts.max_new_tokens,
+            &spec,
+            |token, logits| {
+                if opts.logprobs {
+                    token_logprobs.push(token_logprob(
+                        tokenizer,
+                        logits,
+                        token,
+                        opts.top_logprobs,
+                    )?);
+                }
+                criteria.push(tokenizer, token)?;
+                if criteria.is_finished() {
+                    return Ok(false);
+                }
+                truncated_by_deadline = opts.deadline_passed();
+                if truncated_by_deadline {
+                    criteria.finish(FinishReason::Deadline);
+                }
+                Ok(!truncated_by_deadline)
+            },
+        )?;
+        let secs = start.elapsed().as_secs_f64();
+
+        if !generated.is_empty() && secs > 0.0 {
+            let tps = generated.len() as f64 / secs;
+            self.last_decode_tps.store(tps.to_bits(), Ordering::Relaxed);
+        }
+        debug!(
+            "generate_speculative(): {} tokens in {:.2}s, {}/{} drafts accepted over {} rounds",
+            generated.len(),
+            secs,
+            stats.accepted,
+            stats.drafted,
+            stats.rounds,
+        );
+
+        Ok(Generation {
+            text: criteria.text().to_string(),
+            prompt_tokens: prompt_len,
+            completion_tokens: generated.len(),
+            logprobs: opts.logprobs.then_some(token_logprobs),
+            truncated_by_deadline,
+            // A stop token ends the run inside `speculative_generate`
+            finish_reason: criteria.finish_reason().unwrap_or_default(),
+            speculative: Some(stats),
+        })
+    }
+}
+
+/// Load every layer of the HuggingFace snapshot in `dir` as one shard.
+fn load_full_shard(dir: &Path, device: &Device) -> InferenceResult<TransformerShard> {
+    let config_path = dir.join("config.json");
+    let config: serde_json::Value = std::fs::read_to_string(&config_path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .ok_or_else(|| {
+            InferenceError::ModelLoadError(format!("Cannot read {}", config_path.display()))
+        })?;
+    let num_layers = config["num_hidden_layers"].as_u64().ok_or_else(|| {
+        InferenceError::ModelLoadError(format!(
+            "{} has no num_hidden_layers",
+            config_path.display()
+        ))
+    })? as usize;
+
+    let mut weights: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| InferenceError::ModelLoadError(format!("{}: {e}", dir.display())))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|x| x == "safetensors"))
+        .collect();
+    weights.sort();
+    if weights.is_empty() {
+        return Err(InferenceError::ModelNotFound(format!(
+            "no .safetensors files in {}",
+            dir.display()
+        )));
+    }
+    let paths: Vec<&Path> = weights.iter().map(PathBuf::as_path).collect();
+    TransformerShard::load(&paths, &config_path, device, 0, num_layers)
+}
+
+// ── Embeddings ────────────────────────────────────────────────────────────────
+
+impl InferenceEngine {
+    /// Returns `true` if `handle` refers to an encoder-only embedding model.
+    pub fn is_embedding_model(&self, handle: &ModelHandle) -> bool {
+        self.models
+            .get(&handle.id())
+            .is_some_and(|e| matches!(e.weights, LoadedWeights::Embedding(_)))
+    }
+
+    /// Embed each of `inputs` into an L2-normalised vector.
+    ///
+    /// Only models loaded as embedding models (GGUF `bert` / `nomic-bert`,
+    /// SafeTensors BERT) are supported; generative models return
+    /// [`InferenceError::InvalidInput`].
+    pub fn embed(&self, handle: &ModelHandle, inputs: &[String]) -> InferenceResult<Embeddings> {
+        let entry = self
+            .models
+            .get(&handle.id())
+            .ok_or(InferenceError::InvalidHandle(handle.id()))?;
+
+        let LoadedWeights::Embedding(m) = &entry.weights else {
+            return Err(InferenceError::InvalidInput(format!(
+                "model '{}' ({}) is not an embedding model",
+                entry.info.name, entry.info.architecture
+            )));
+        };
+        let guard = m.lock().unwrap();
+
+        let mut out = Embeddings::default();
+        for text in inputs {
+            let (vector, n_tokens) = guard.embed_one(text, &self.device)?;
+            out.vectors.push(vector);
+            out.prompt_tokens += n_tokens;
+        }
+        debug!(
+            "embed() handle {}: {} inputs, {} tokens",
+            handle.id(),
+            inputs.len(),
+            out.prompt_tokens
+        );
+        Ok(out)
+    }
+
+    /// Load SafeTensors shards, routing BERT snapshots to the embedding loader.
+    fn load_safetensors_weights(
+        &self,
+        paths: &[&Path],
+        config_path: &Path,
+    ) -> InferenceResult<(LoadedWeights, ModelConfig, usize, usize, bool)> {
+        let model_type = loader::config_model_type(config_path).unwrap_or_default();
+        if embedding::is_embedding_architecture(&model_type) {
+            let m = embedding::load_safetensors(paths, config_path, &self.device)?;
+            let (c, v, l) = (m.config.clone(), m.vocab_size, m.num_layers);
+            return Ok((LoadedWeights::Embedding(Mutex::new(m)), c, v, l, false));
+        }
+        let m = loader::load_safetensors(paths, config_path, &self.device)?;
+        let (c, v, l) = (m.config.clone(), m.vocab_size, m.num_layers);
+        Ok((LoadedWeights::SafeTensors(Mutex::new(m)), c, v, l, false))
+    }
+}
+
+// ── Partial (block-range) loading ─────────────────────────────────────────────
+
+impl InferenceEngine {
+    /// Load only transformer blocks `blocks` of the model at `path`, plus
+    /// the embedding when the range starts at block 0 and the final norm and
+    /// LM head when it ends at the last block.
+    ///
+    /// `path` is a SafeTensors snapshot directory (or a `.safetensors` file
+    /// next to its `config.json`) or a Llama-family `.gguf` file; either is
+    /// memory-mapped, so weights of other blocks are never read. Blocks are
+    /// split across `device_map` when the engine config sets one. The blocks
+    /// are served through [`Self::blocks`]; they cannot generate text alone.
+    pub fn load_model_blocks(
+        &mut self,
+        path: &Path,
+        blocks: Range<usize>,
+    ) -> InferenceResult<ModelHandle> {
+        if !path.exists() {
+            return Err(InferenceError::ModelNotFound(path.display().to_string()));
+        }
+        let format = if path.is_dir() {
+            ModelFormat::SafeTensors
+        } else {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .and_then(ModelFormat::from_extension)
+                .ok_or_else(|| {
+                    InferenceError::InvalidFormat(format!(
+                        "cannot tell the format of {}",
+                        path.display()
+                    ))
+                })?
+        };
+        info!(
+            "Loading blocks [{}..{}) of {} ({:?})",
+            blocks.start,
+            blocks.end,
+            path.display(),
+            format
+        );
+
+        let shard = match format {
+            ModelFormat::Gguf => {
+                TransformerShard::load_gguf(path, &self.device, blocks.start, blocks.end)?
+            }
+            ModelFormat::SafeTensors => {
+                let (paths, config_path) = if path.is_dir() {
+                    (safetensors_in(path)?, path.join("config.json"))
+                } else {
+                    let dir = path.parent().unwrap_or(Path::new("."));
+                    (vec![path.to_path_buf()], dir.join("config.json"))
+                };
+                let refs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+                let map = self.config.shard_device_map();
+                if map.is_split() {
+                    TransformerShard::load_mapped(
+                        &refs,
+                        &config_path,
+                        &map,
+                        blocks.start,
+                        blocks.end,
+                    )?
+                } else {
+                    TransformerShard::load(
+                        &refs,
+                        &config_path,
+                        &self.device,
+                        blocks.start,
+                        blocks.end,
+                    )?
+                }
+            }
+            other => {
+                return Err(InferenceError::InvalidFormat(format!(
+                    "{other:?} models cannot be loaded by block range"
+                )))
+            }
+        };
+
+        let memory_bytes = shard_weight_bytes(&shard);
+        self.check_memory(memory_bytes)?;
+
+        let cfg = &shard.cfg;
+        let config = ModelConfig {
+            architecture: "llama".to_string(),
+            max_seq_len: cfg.max_seq_len,
+            vocab_size: cfg.vocab_size,
+            num_heads: cfg.num_heads,
+            num_kv_heads: cfg.num_kv_heads,
+            hidden_dim: cfg.hidden_dim,
+            intermediate_dim: cfg.intermediate_dim,
+            rope_theta: cfg.rope_theta as f32,
+            layer_norm_eps: cfg.rms_norm_eps as f32,
+        };
+        let id = self.next_id();
+        let info = ModelInfo {
+            id: id.to_string(),
+            name: path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string(),
+            architecture: config.architecture.clone(),
+            format,
+            memory_bytes,
+            vocab_size: cfg.vocab_size,
+            context_length: cfg.max_seq_len,
+            hidden_dim: cfg.hidden_dim,
+            ..Default::default()
+        };
+        self.models.insert(
+            id,
+            LoadedModelEntry {
+                info,
+                weights: LoadedWeights::Blocks(Arc::new(shard)),
+                config,
+            },
+        );
+        self.current_memory += memory_bytes;
+        info!(
+            "Blocks loaded — handle {id}, ~{:.1} GB",
+            memory_bytes as f64 / 1e9
+        );
+        Ok(ModelHandle::new(id))
+    }
+
+    /// The block range loaded under `handle` by [`Self::load_model_blocks`].
+    pub fn blocks(&self, handle: &ModelHandle) -> InferenceResult<Arc<TransformerShard>> {
+        let entry = self
+            .models
+            .get(&handle.id())
+            .ok_or(InferenceError::InvalidHandle(handle.id()))?;
+        match &entry.weights {
+            LoadedWeights::Blocks(shard) => Ok(shard.clone()),
+            _ => Err(InferenceError::InvalidInput(format!(
+                "model handle {} was not loaded by block range",
+                handle.id()
+            ))),
+        }
+    }
+
+    /// Run `hidden` (`[batch, seq_len, hidden_dim]`, from the previous
+    /// segment of a pipeline) through the blocks loaded under `handle` and
+    /// return the hidden states after them.
+    ///
+    /// `session_id` names the remote session: each keeps its own KV-cache
+    /// here, extended by every call. `position` is the sequence position of
+    /// `hidden`'s first token; `0` starts the session over.
+    pub fn forward_hidden(
+        &self,
+        handle: &ModelHandle,
+        session_id: u64,
+        hidden: &Tensor,
+        position: usize,
+    ) -> InferenceResult<Tensor> {
+        self.blocks(handle)?
+            .forward_hidden(session_id, hidden, position)
+    }
+
+    /// Free the KV-cache a remote session holds on the blocks under `handle`.
+    pub fn end_session(&self, handle: &ModelHandle, session_id: u64) -> InferenceResult<()> {
+        self.blocks(handle)?.close_session(session_id);
+        Ok(())
+    }
+}
+
+/// `.safetensors` files of a snapshot directory, sorted by name.
+fn safetensors_in(dir: &Path) -> InferenceResult<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(InferenceError::from)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|x| x.to_str()) == Some("safetensors"))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(InferenceError::ModelNotFound(format!(
+            "No .safetensors shards found in {}",
+            dir.display()
+        )));
+    }
+    Ok(paths)
+}
+
+/// Bytes of weights a shard holds, from its dimensions.
+fn shard_weight_bytes(shard: &TransformerShard) -> usize {
+    let cfg = &shard.cfg;
+    let (h, inter) = (cfg.hidden_dim, cfg.intermediate_dim);
+    let kv = cfg.num_kv_heads * cfg.head_dim;
+    let per_block = 2 * h + 2 * h * h + 2 * kv * h + 3 * h * inter;
+    let mut params = (shard.end_block - shard.start_block) * per_block;
+    if shard.is_first() {
+        params += cfg.vocab_size * h;
+    }
+    if shard.is_last() {
+        params += h + cfg.vocab_size * h;
+    }
+    params * cfg.dtype.size_in_bytes()
+}
+
+fn not_generative(handle: &ModelHandle) -> InferenceError {
+    InferenceError::InvalidInput(format!(
+        "model handle {} is an embedding model or a block range and cannot generate text",
+        handle.id()
+    ))
+}
+
 // ── InferenceProvider impl ────────────────────────────────────────────────────
 
 #[async_trait]
@@ -313,8 +1287,23 @@ impl InferenceProvider for InferenceEngine {
 
         // ── Dispatch to the real loader ──────────────────────────────────────
         let (weights, config, vocab_size, _num_layers, is_quantized) = match format {
+            ModelFormat::Gguf | ModelFormat::Ggml
+                if loader::gguf_architecture(path)?
+                    .is_some_and(|a| embedding::is_embedding_architecture(&a)) =>
+            {
+                let m = embedding::load_gguf(path, &self.device)?;
+                let c = m.config.clone();
+                let v = m.vocab_size;
+                let l = m.num_layers;
+                (LoadedWeights::Embedding(Mutex::new(m)), c, v, l, true)
+            }
+
             ModelFormat::Gguf | ModelFormat::Ggml => {
-                let m = loader::load_gguf(path, &self.device)?;
+                let m = loader::load_gguf_with_offload(
+                    path,
+                    &self.device,
+                    self.expert_offload.as_ref(),
+                )?;
                 let c = m.config.clone();
                 let v = m.vocab_size;
                 let l = m.num_layers;
@@ -324,38 +1313,15 @@ impl InferenceProvider for InferenceEngine {
             ModelFormat::SafeTensors => {
                 if path.is_dir() {
                     // Sharded model directory (e.g. a HuggingFace snapshot).
-                    // Collect all .safetensors shard files, sorted by name.
-                    let mut shard_paths: Vec<std::path::PathBuf> = std::fs::read_dir(path)
-                        .map_err(InferenceError::from)?
-                        .filter_map(|e| e.ok())
-                        .map(|e| e.path())
-                        .filter(|p| p.extension().and_then(|x| x.to_str()) == Some("safetensors"))
-                        .collect();
-                    shard_paths.sort();
-
-                    if shard_paths.is_empty() {
-                        return Err(InferenceError::ModelNotFound(format!(
-                            "No .safetensors shards found in {}",
-                            path.display()
-                        )));
-                    }
-
+                    let shard_paths = safetensors_in(path)?;
                     let config_path = path.join("config.json");
                     let path_refs: Vec<&Path> = shard_paths.iter().map(|p| p.as_path()).collect();
-                    let m = loader::load_safetensors(&path_refs, &config_path, &self.device)?;
-                    let c = m.config.clone();
-                    let v = m.vocab_size;
-                    let l = m.num_layers;
-                    (LoadedWeights::SafeTensors(Mutex::new(m)), c, v, l, false)
+                    self.load_safetensors_weights(&path_refs, &config_path)?
                 } else {
                     // Single-shard: config.json must sit alongside the .safetensors file.
                     let config_path = path.parent().unwrap_or(Path::new(".")).join("config.json");
                     let path_slice = [path];
-                    let m = loader::load_safetensors(&path_slice, &config_path, &self.device)?;
-                    let c = m.config.clone();
-                    let v = m.vocab_size;
-                    let l = m.num_layers;
-                    (LoadedWeights::SafeTensors(Mutex::new(m)), c, v, l, false)
+                    self.load_safetensors_weights(&path_slice, &config_path)?
                 }
             }
 
@@ -421,227 +1387,8 @@ impl InferenceProvider for InferenceEngine {
     }
 
     fn generate(&self, handle: &ModelHandle, prompt: &str) -> InferenceResult<String> {
-        /// Maximum new tokens to generate per call.
-        const MAX_NEW_TOKENS: usize = 256;
-        /// Sampling temperature (0 → greedy, higher → more random).
-        const TEMPERATURE: f64 = 0.8;
-
-        let entry = self
-            .models
-            .get(&handle.id())
-            .ok_or(InferenceError::InvalidHandle(handle.id()))?;
-
-        let mut logits_processor = LogitsProcessor::new(42, Some(TEMPERATURE), None);
-
-        let text = match &entry.weights {
-            // ── Quantized GGUF path ───────────────────────────────────────────
-            LoadedWeights::Gguf(m) => {
-                let mut guard = m.lock().unwrap();
-
-                // Encode prompt.
-                let mut prompt_tokens = guard.tokenizer.encode(prompt)?;
-                let eos_id = guard.tokenizer.eos_token_id();
-                let bos_id = guard.tokenizer.bos_token_id();
-
-                // Prepend BOS only when it is a distinct token from EOS.
-                // Models like Qwen2 set BOS == EOS (both are <|endoftext|>=151643);
-                // prepending EOS as BOS causes the model to immediately terminate.
-                if let Some(bos) = bos_id {
-                    if Some(bos) != eos_id {
-                        prompt_tokens.insert(0, bos);
-                    }
-                }
-                let prompt_len = prompt_tokens.len();
-
-                // Build the full stop-token set: the registered EOS plus common
-                // ChatML/instruct stop tokens that the vocab may contain.
-                let mut stop_ids: Vec<u32> = eos_id.into_iter().collect();
-                for candidate in &["<|im_end|>", "<|eot_id|>", "<|end_of_text|>"] {
-                    if let Some(id) = guard.tokenizer.token_to_id(candidate) {
-                        if !stop_ids.contains(&id) {
-                            stop_ids.push(id);
-                        }
-                    }
-                }
-
-                info!(
-                    "generate() GGUF handle {}: {} prompt tokens, stop={:?}",
-                    handle.id(),
-                    prompt_len,
-                    stop_ids,
-                );
-
-                // Prefill: process the entire prompt in one forward pass.
-                // index_pos=0 resets the model's internal KV-cache.
-                let prompt_tensor = Tensor::new(prompt_tokens.as_slice(), &self.device)
-                    .map_err(InferenceError::from)?
-                    .unsqueeze(0)
-                    .map_err(InferenceError::from)?; // [1, prompt_len]
-
-                let logits = guard
-                    .weights
-                    .forward(&prompt_tensor, 0)
-                    .map_err(InferenceError::from)?; // [1, vocab_size]
-                let logits = logits.squeeze(0).map_err(InferenceError::from)?; // [vocab_size]
-
-                let mut next_token = logits_processor
-                    .sample(&logits)
-                    .map_err(InferenceError::from)?;
-
-                let mut generated: Vec<u32> = Vec::new();
-                let mut pos = prompt_len;
-
-                // Decode loop: feed one token at a time, sample the next.
-                let decode_start = std::time::Instant::now();
-                loop {
-                    if stop_ids.contains(&next_token) || generated.len() >= MAX_NEW_TOKENS {
-                        break;
-                    }
-                    generated.push(next_token);
-
-                    let token_tensor = Tensor::new(&[next_token], &self.device)
-                        .map_err(InferenceError::from)?
-                        .unsqueeze(0)
-                        .map_err(InferenceError::from)?; // [1, 1]
-
-                    let logits = guard
-                        .weights
-                        .forward(&token_tensor, pos)
-                        .map_err(InferenceError::from)?;
-                    let logits = logits.squeeze(0).map_err(InferenceError::from)?;
-
-                    next_token = logits_processor
-                        .sample(&logits)
-                        .map_err(InferenceError::from)?;
-                    pos += 1;
-                }
-                let decode_secs = decode_start.elapsed().as_secs_f64();
-
-                if !generated.is_empty() && decode_secs > 0.0 {
-                    let tps = generated.len() as f64 / decode_secs;
-                    self.last_decode_tps.store(tps.to_bits(), Ordering::Relaxed);
-                }
-
-                debug!(
-                    "generate() GGUF handle {}: {} tokens in {:.2}s ({:.1} tok/s)",
-                    handle.id(),
-                    generated.len(),
-                    decode_secs,
-                    if decode_secs > 0.0 {
-                        generated.len() as f64 / decode_secs
-                    } else {
-                        0.0
-                    },
-                );
-
-                guard.tokenizer.decode(&generated)?
-            }
-
-            // ── Full-precision SafeTensors path ───────────────────────────────
-            LoadedWeights::SafeTensors(m) => {
-                let guard = m.lock().unwrap();
-
-                // Encode prompt.
-                let mut prompt_tokens = guard.tokenizer.encode(prompt)?;
-                let eos_id = guard.tokenizer.eos_token_id();
-                let bos_id = guard.tokenizer.bos_token_id();
-
-                // Only add BOS when it differs from EOS (same guard as GGUF path).
-                if let Some(bos) = bos_id {
-                    if Some(bos) != eos_id {
-                        prompt_tokens.insert(0, bos);
-                    }
-                }
-                let prompt_len = prompt_tokens.len();
-
-                // Build the full stop-token set.
-                let mut stop_ids: Vec<u32> = eos_id.into_iter().collect();
-                for candidate in &["<|im_end|>", "<|eot_id|>", "<|end_of_text|>"] {
-                    if let Some(id) = guard.tokenizer.token_to_id(candidate) {
-                        if !stop_ids.contains(&id) {
-                            stop_ids.push(id);
-                        }
-                    }
-                }
-
-                info!(
-                    "generate() SafeTensors handle {}: {} prompt tokens, stop={:?}",
-                    handle.id(),
-                    prompt_len,
-                    stop_ids,
-                );
-
-                // Create a fresh KV-cache for this generation session.
-                let mut cache = Cache::new(true, DType::F16, &guard.llama_config, &self.device)
-                    .map_err(InferenceError::from)?;
-
-                // Prefill.
-                let prompt_tensor = Tensor::new(prompt_tokens.as_slice(), &self.device)
-                    .map_err(InferenceError::from)?
-                    .unsqueeze(0)
-                    .map_err(InferenceError::from)?; // [1, prompt_len]
-
-                let logits = guard
-                    .model
-                    .forward(&prompt_tensor, 0, &mut cache)
-                    .map_err(InferenceError::from)?; // [1, vocab_size]
-                let logits = logits.squeeze(0).map_err(InferenceError::from)?; // [vocab_size]
-
-                let mut next_token = logits_processor
-                    .sample(&logits)
-                    .map_err(InferenceError::from)?;
-
-                let mut generated: Vec<u32> = Vec::new();
-                let mut pos = prompt_len;
-
-                // Decode loop.
-                let decode_start = std::time::Instant::now();
-                loop {
-                    if stop_ids.contains(&next_token) || generated.len() >= MAX_NEW_TOKENS {
-                        break;
-                    }
-                    generated.push(next_token);
-
-                    let token_tensor = Tensor::new(&[next_token], &self.device)
-                        .map_err(InferenceError::from)?
-                        .unsqueeze(0)
-                        .map_err(InferenceError::from)?; // [1, 1]
-
-                    let logits = guard
-                        .model
-                        .forward(&token_tensor, pos, &mut cache)
-                        .map_err(InferenceError::from)?;
-                    let logits = logits.squeeze(0).map_err(InferenceError::from)?;
-
-                    next_token = logits_processor
-                        .sample(&logits)
-                        .map_err(InferenceError::from)?;
-                    pos += 1;
-                }
-                let decode_secs = decode_start.elapsed().as_secs_f64();
-
-                if !generated.is_empty() && decode_secs > 0.0 {
-                    let tps = generated.len() as f64 / decode_secs;
-                    self.last_decode_tps.store(tps.to_bits(), Ordering::Relaxed);
-                }
-
-                debug!(
-                    "generate() SafeTensors handle {}: {} tokens in {:.2}s ({:.1} tok/s)",
-                    handle.id(),
-                    generated.len(),
-                    decode_secs,
-                    if decode_secs > 0.0 {
-                        generated.len() as f64 / decode_secs
-                    } else {
-                        0.0
-                    },
-                );
-
-                guard.tokenizer.decode(&generated)?
-            }
-        };
-
-        Ok(text)
+        self.generate_with(handle, prompt, &GenerateOptions::default())
+            .map(|g| g.text)
     }
 
     fn unload(&mut self, handle: ModelHandle) -> InferenceResult<()> {
@@ -710,6 +1457,111 @@ mod tests {
         assert!(engine.list_models().is_empty());
     }
 
+    #[test]
+    fn test_generate_with_rejects_excess_top_logprobs() {
+        let engine = InferenceEngine::new(EngineConfig::default()).unwrap();
+        let opts = GenerateOptions {
+            logprobs: true,
+            top_logprobs: MAX_TOP_LOGPROBS + 1,
+            ..Default::default()
+        };
+        let result = engine.generate_with(&ModelHandle::new(1), "hi", &opts);
+        assert!(matches!(result, Err(InferenceError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_generate_speculative_requires_draft_model() {
+        let engine = InferenceEngine::new(EngineConfig::default()).unwrap();
+        assert_eq!(engine.config.speculative_tokens, 4);
+        struct NoTarget;
+        impl CausalLm for NoTarget {
+            fn forward_all(&mut self, _: &[u32], _: usize) -> InferenceResult<Tensor> {
+                unreachable!("no draft model, so the target is never run")
+            }
+        }
+        let result = engine.generate_speculative(&mut NoTarget, "hi", &GenerateOptions::default());
+        assert!(matches!(result, Err(InferenceError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_start_sequence_validates_like_generate() {
+        let engine = InferenceEngine::new(EngineConfig::default()).unwrap();
+        let opts = GenerateOptions::default();
+        let result = engine.start_sequence(&ModelHandle::new(5), "hi", &opts);
+        assert!(matches!(result, Err(InferenceError::InvalidHandle(5))));
+        let opts = GenerateOptions {
+            top_logprobs: MAX_TOP_LOGPROBS + 1,
+            ..opts
+        };
+        let result = engine.start_sequence(&ModelHandle::new(5), "hi", &opts);
+        assert!(matches!(result, Err(InferenceError::InvalidInput(_))));
+        let opts = GenerateOptions {
+            top_logprobs: 0,
+            stop: vec!["x".to_string(); crate::stop::MAX_STOP_SEQUENCES + 1],
+            ..opts
+        };
+        let result = engine.start_sequence(&ModelHandle::new(5), "hi", &opts);
+        assert!(matches!(result, Err(InferenceError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_fit_to_context() {
+        let opts = GenerateOptions {
+            max_new_tokens: 4,
+            ..Default::default()
+        };
+        let prompt: Vec<u32> = (1..=10).collect();
+        assert_eq!(
+            fit_to_context(prompt.clone(), Some(1), 14, &opts).unwrap(),
+            prompt
+        );
+        assert_eq!(
+            fit_to_context(prompt.clone(), Some(1), 0, &opts).unwrap(),
+            prompt
+        );
+
+        let err = fit_to_context(prompt.clone(), Some(1), 12, &opts).unwrap_err();
+        assert!(matches!(
+            err,
+            InferenceError::ContextLengthExceeded {
+                prompt_tokens: 10,
+                max_new_tokens: 4,
+                context_length: 12,
+            }
+        ));
+
+        let opts = GenerateOptions {
+            truncate_prompt: true,
+            ..opts
+        };
+        // BOS survives, the oldest text tokens go
+        assert_eq!(
+            fit_to_context(prompt.clone(), Some(1), 8, &opts).unwrap(),
+            [1, 8, 9, 10]
+        );
+        assert_eq!(
+            fit_to_context(prompt.clone(), None, 8, &opts).unwrap(),
+            [7, 8, 9, 10]
+        );
+        // No room left for any prompt text
+        assert!(fit_to_context(prompt, Some(1), 5, &opts).is_err());
+    }
+
+    #[test]
+    fn test_tokenize_invalid_handle() {
+        let engine = InferenceEngine::new(EngineConfig::default()).unwrap();
+        let result = engine.count_tokens(&ModelHandle::new(7), "hello");
+        assert!(matches!(result, Err(InferenceError::InvalidHandle(7))));
+    }
+
+    #[test]
+    fn test_embed_invalid_handle() {
+        let engine = InferenceEngine::new(EngineConfig::default()).unwrap();
+        let result = engine.embed(&ModelHandle::new(3), &["hello".to_string()]);
+        assert!(matches!(result, Err(InferenceError::InvalidHandle(3))));
+        assert!(!engine.is_embedding_model(&ModelHandle::new(3)));
+    }
+
     #[test]
     fn test_invalid_handle_error() {
         let engine = InferenceEngine::new(EngineConfig::default()).unwrap();
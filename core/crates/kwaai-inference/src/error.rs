@@ -20,6 +20,13 @@ pub enum InferenceError {
     #[error("Invalid model format: {0}")]
     InvalidFormat(String),
 
+    /// The model's architecture has no runner in this build
+    #[error("Unsupported model architecture '{architecture}' (supported: {supported})")]
+    UnsupportedArchitecture {
+        architecture: String,
+        supported: String,
+    },
+
     /// Inference failed
     #[error("Inference failed: {0}")]
     InferenceFailed(String),
@@ -44,6 +51,17 @@ pub enum InferenceError {
     #[error("Invalid input: {0}")]
     InvalidInput(String),
 
+    /// Prompt plus requested completion does not fit the context window
+    #[error(
+        "Prompt is {prompt_tokens} tokens but the model's context is {context_length} tokens \
+         ({max_new_tokens} reserved for the completion); shorten the prompt or enable truncation"
+    )]
+    ContextLengthExceeded {
+        prompt_tokens: usize,
+        max_new_tokens: usize,
+        context_length: usize,
+    },
+
     /// Model handle invalid
     #[error("Invalid model handle: {0}")]
     InvalidHandle(u64),
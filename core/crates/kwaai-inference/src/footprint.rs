@@ -0,0 +1,243 @@
+//! Memory footprint of a model's transformer blocks, read from its files
+//!
+//! [`ModelFootprint`] sizes every block from the tensor shapes in the model
+//! files — safetensors headers or GGUF tensor infos, never the weights — and
+//! adds the KV cache a block holds for a given number of tokens:
+//!
+//! ```text
+//! block bytes = Σ elements of blk.i.* × compute dtype size
+//! KV bytes    = 2 (K and V) × kv_heads × head_dim × tokens × dtype size
+//! ```
+//!
+//! Shards compute in f16 whatever the file stores, and GGUF weights are
+//! dequantized on load, so sizes are taken at the compute dtype rather than
+//! the on-disk one.
+
+use crate::error::{InferenceError, InferenceResult};
+use crate::lazy_gguf::{map_file, read_header};
+use crate::loader::meta_usize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes per element of the shard compute dtype (f16).
+pub const COMPUTE_DTYPE_BYTES: u64 = 2;
+
+/// Per-block memory of one model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelFootprint {
+    /// Weight bytes of each block at the compute dtype, by block index
+    pub block_weight_bytes: Vec<u64>,
+    /// Embedding, final norm and LM head bytes (first and last shard only)
+    pub head_bytes: u64,
+    pub num_kv_heads: usize,
+    pub head_dim: usize,
+    /// Context length the model was trained for
+    pub context_length: usize,
+}
+
+impl ModelFootprint {
+    /// Blocks in the model
+    pub fn num_blocks(&self) -> usize {
+        self.block_weight_bytes.len()
+    }
+
+    /// Mean weight bytes of one block
+    pub fn mean_block_weight_bytes(&self) -> u64 {
+        let n = self.block_weight_bytes.len().max(1) as u64;
+        self.block_weight_bytes.iter().sum::<u64>() / n
+    }
+
+    /// KV-cache bytes one block holds for `tokens` cached tokens
+    pub fn kv_cache_bytes(&self, tokens: u64) -> u64 {
+        2 * self.num_kv_heads as u64 * self.head_dim as u64 * tokens * COMPUTE_DTYPE_BYTES
+    }
+
+    /// Memory one block takes with `tokens` cached tokens
+    pub fn bytes_per_block(&self, tokens: u64) -> u64 {
+        self.mean_block_weight_bytes() + self.kv_cache_bytes(tokens)
+    }
+
+    /// Read a HuggingFace snapshot: `config.json` plus the headers of the
+    /// `*.safetensors` files present. Blocks whose weight files are not
+    /// downloaded yet are sized like the mean of those that are.
+    pub fn from_safetensors_dir(dir: &Path) -> InferenceResult<Self> {
+        let config_path = dir.join("config.json");
+        let config: serde_json::Value = std::fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .ok_or_else(|| {
+                InferenceError::ModelLoadError(format!("Cannot read {}", config_path.display()))
+            })?;
+        let get = |key: &str| config[key].as_u64().map(|v| v as usize);
+        let num_blocks = get("num_hidden_layers").ok_or_else(|| {
+            InferenceError::ModelLoadError("config.json has no num_hidden_layers".to_string())
+        })?;
+        let hidden = get("hidden_size").unwrap_or(0);
+        let num_heads = get("num_attention_heads").unwrap_or(1).max(1);
+
+        let mut blocks: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut head_bytes = 0;
+        let mut files = 0;
+        for entry in std::fs::read_dir(dir)?.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("safetensors") {
+                continue;
+            }
+            // A snapshot entry may be a dangling symlink to a missing blob.
+            let Ok(shapes) = safetensors_shapes(&path) else {
+                continue;
+            };
+            files += 1;
+            for (name, elements) in shapes {
+                let bytes = elements * COMPUTE_DTYPE_BYTES;
+                match layer_index(&name, "model.layers.") {
+                    Some(i) => *blocks.entry(i).or_default() += bytes,
+                    None => head_bytes += bytes,
+                }
+            }
+        }
+        if files == 0 {
+            return Err(InferenceError::ModelLoadError(format!(
+                "No readable .safetensors files in {}",
+                dir.display()
+            )));
+        }
+
+        Ok(Self {
+            block_weight_bytes: fill_blocks(blocks, num_blocks),
+            head_bytes,
+            num_kv_heads: get("num_key_value_heads").unwrap_or(num_heads),
+            head_dim: get("head_dim").unwrap_or(hidden / num_heads),
+            context_length: get("max_position_embeddings").unwrap_or(0),
+        })
+    }
+
+    /// Read the header of a GGUF file (tensor data is not touched).
+    pub fn from_gguf(path: &Path) -> InferenceResult<Self> {
+        let mmap = map_file(path)?;
+        let ct = read_header(&mmap, path)?;
+        let arch = match ct.metadata.get("general.architecture") {
+            Some(candle_core::quantized::gguf_file::Value::String(a)) => a.clone(),
+            _ => "llama".to_string(),
+        };
+        let meta = |key: &str| meta_usize(&ct, &format!("{arch}.{key}"));
+        let num_heads = meta("attention.head_count").unwrap_or(1).max(1);
+        let hidden = meta("embedding_length").unwrap_or(0);
+
+        let mut blocks: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut head_bytes = 0;
+        for (name, info) in &ct.tensor_infos {
+            let bytes = info.shape.elem_count() as u64 * COMPUTE_DTYPE_BYTES;
+            match layer_index(name, "blk.") {
+                Some(i) => *blocks.entry(i).or_default() += bytes,
+                None => head_bytes += bytes,
+            }
+        }
+        let num_blocks = meta("block_count").unwrap_or(blocks.len());
+
+        Ok(Self {
+            block_weight_bytes: fill_blocks(blocks, num_blocks),
+            head_bytes,
+            num_kv_heads: meta("attention.head_count_kv").unwrap_or(num_heads),
+            head_dim: meta("attention.key_length").unwrap_or(hidden / num_heads),
+            context_length: meta("context_length").unwrap_or(0),
+        })
+    }
+}
+
+/// Block index of `name` under `prefix` (`model.layers.12.mlp…` → 12)
+fn layer_index(name: &str, prefix: &str) -> Option<usize> {
+    name.strip_prefix(prefix)?.split('.').next()?.parse().ok()
+}
+
+/// Sizes of blocks `0..num_blocks`, with blocks that have no tensors
+/// (weight file not present) sized like the mean of the rest
+fn fill_blocks(blocks: BTreeMap<usize, u64>, num_blocks: usize) -> Vec<u64> {
+    let known: Vec<u64> = blocks.values().copied().filter(|&b| b > 0).collect();
+    let mean = known.iter().sum::<u64>() / known.len().max(1) as u64;
+    (0..num_blocks)
+        .map(|i| blocks.get(&i).copied().filter(|&b| b > 0).unwrap_or(mean))
+        .collect()
+}
+
+/// Element count of every tensor in a safetensors file, read from its
+/// JSON header only
+fn safetensors_shapes(path: &Path) -> InferenceResult<Vec<(String, u64)>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut len = [0u8; 8];
+    file.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    // Headers are a few hundred KB at most; anything larger is not one.
+    if len > 100 * 1024 * 1024 {
+        return Err(InferenceError::ModelLoadError(format!(
+            "{}: implausible safetensors header ({len} bytes)",
+            path.display()
+        )));
+    }
+    let mut header = vec![0u8; len as usize];
+    file.read_exact(&mut header)?;
+    let header: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&header)
+        .map_err(|e| InferenceError::ModelLoadError(format!("{}: {e}", path.display())))?;
+    Ok(header
+        .into_iter()
+        .filter(|(name, _)| name != "__metadata__")
+        .filter_map(|(name, info)| {
+            let shape = info["shape"].as_array()?;
+            let elements = shape.iter().map(|d| d.as_u64().unwrap_or(0)).product();
+            Some((name, elements))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::{DType, Device, Tensor};
+    use std::collections::HashMap;
+
+    #[test]
+    fn sizes_blocks_from_safetensors_headers() {
+        let dir = std::env::temp_dir().join(format!("kwaai-footprint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{"num_hidden_layers": 3, "hidden_size": 64, "num_attention_heads": 8,
+                "num_key_value_heads": 2, "max_position_embeddings": 2048}"#,
+        )
+        .unwrap();
+        // Stored as f32: sizes are still counted at the f16 compute dtype.
+        let t = |rows: usize, cols: usize| Tensor::zeros((rows, cols), DType::F32, &Device::Cpu);
+        let tensors: HashMap<String, Tensor> = [
+            ("model.embed_tokens.weight", t(100, 64)),
+            ("model.layers.0.self_attn.q_proj.weight", t(64, 64)),
+            ("model.layers.0.mlp.up_proj.weight", t(128, 64)),
+            ("model.layers.1.self_attn.q_proj.weight", t(64, 64)),
+            ("model.layers.1.mlp.up_proj.weight", t(128, 64)),
+        ]
+        .into_iter()
+        .map(|(n, t)| (n.to_string(), t.unwrap()))
+        .collect();
+        candle_core::safetensors::save(&tensors, dir.join("model-00001.safetensors")).unwrap();
+
+        let fp = ModelFootprint::from_safetensors_dir(&dir).unwrap();
+        let block = (64 * 64 + 128 * 64) * COMPUTE_DTYPE_BYTES;
+        // Block 2's weights are not downloaded: sized like the others.
+        assert_eq!(fp.block_weight_bytes, [block, block, block]);
+        assert_eq!(fp.head_bytes, 100 * 64 * COMPUTE_DTYPE_BYTES);
+        assert_eq!((fp.num_kv_heads, fp.head_dim), (2, 8));
+        assert_eq!(fp.kv_cache_bytes(1000), 2 * 2 * 8 * 1000 * 2);
+        assert_eq!(fp.bytes_per_block(1000), block + fp.kv_cache_bytes(1000));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn layer_index_parses_hf_and_gguf_names() {
+        assert_eq!(
+            layer_index("model.layers.12.mlp.up_proj.weight", "model.layers."),
+            Some(12)
+        );
+        assert_eq!(layer_index("blk.3.attn_q.weight", "blk."), Some(3));
+        assert_eq!(layer_index("token_embd.weight", "blk."), None);
+    }
+}
@@ -0,0 +1,220 @@
+//! Per-call generation options and results.
+//!
+//! [`InferenceEngine::generate_with`](crate::InferenceEngine::generate_with)
+//! takes a [`GenerateOptions`] and returns a [`Generation`] carrying the
+//! decoded text together with exact token accounting and, when requested,
+//! per-token log-probabilities (OpenAI `logprobs` / `top_logprobs`).
+//!
+//! Generation ends on a stop token, on one of [`GenerateOptions::stop`] or
+//! after `max_new_tokens`; [`Generation::finish_reason`] says which (see
+//! [`StopCriteria`](crate::stop::StopCriteria)).
+//!
+//! A [`GenerateOptions::deadline`] bounds wall-clock time: once it passes,
+//! decoding stops and the tokens produced so far are returned with
+//! [`Generation::truncated_by_deadline`] set rather than an error.
+
+use crate::constraint::ResponseFormat;
+use crate::speculative::SpeculativeStats;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Upper bound on `top_logprobs`, matching the OpenAI API limit.
+pub const MAX_TOP_LOGPROBS: usize = 20;
+
+/// Knobs for a single generation call.
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Maximum number of new tokens to produce.
+    pub max_new_tokens: usize,
+    /// Sampling temperature (0 → greedy, higher → more random).
+    pub temperature: f64,
+    /// Record the log-probability of every sampled token.
+    pub logprobs: bool,
+    /// Number of most likely alternatives to record per position
+    /// (0..=[`MAX_TOP_LOGPROBS`]). Ignored unless `logprobs` is set.
+    pub top_logprobs: usize,
+    /// Stop decoding once this instant passes and return the partial result.
+    pub deadline: Option<Instant>,
+    /// Strings that end the completion when generated (up to
+    /// [`MAX_STOP_SEQUENCES`](crate::stop::MAX_STOP_SEQUENCES)). The match
+    /// and everything after it are left out of the text.
+    pub stop: Vec<String>,
+    /// Constrain the completion to a format, e.g. a JSON object (see
+    /// [`constraint`](crate::constraint)).
+    pub response_format: ResponseFormat,
+    /// When the prompt does not leave room for `max_new_tokens` in the
+    /// model's context, drop its oldest tokens instead of failing with
+    /// [`InferenceError::ContextLengthExceeded`](crate::InferenceError::ContextLengthExceeded).
+    pub truncate_prompt: bool,
+}
+
+impl GenerateOptions {
+    /// Returns `true` if a deadline is set and has passed.
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            max_new_tokens: 256,
+            temperature: 0.8,
+            logprobs: false,
+            top_logprobs: 0,
+            deadline: None,
+            stop: Vec::new(),
+            response_format: ResponseFormat::Text,
+            truncate_prompt: false,
+        }
+    }
+}
+
+/// Why a generation ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinishReason {
+    /// The model produced a stop token (EOS or end of turn).
+    #[default]
+    Stop,
+    /// The text reached one of [`GenerateOptions::stop`].
+    StopSequence,
+    /// `max_new_tokens` were generated.
+    Length,
+    /// [`GenerateOptions::deadline`] passed.
+    Deadline,
+}
+
+impl FinishReason {
+    /// The OpenAI `finish_reason` value: `"stop"` or `"length"`.
+    pub fn as_openai(&self) -> &'static str {
+        match self {
+            FinishReason::Stop | FinishReason::StopSequence => "stop",
+            FinishReason::Length | FinishReason::Deadline => "length",
+        }
+    }
+}
+
+/// One alternative token at a sampled position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token_id: u32,
+    /// Decoded text of the token.
+    pub token: String,
+    /// Natural-log probability under the model's raw (untempered) distribution.
+    pub logprob: f32,
+}
+
+/// Log-probability information for one generated token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token_id: u32,
+    /// Decoded text of the token.
+    pub token: String,
+    /// Natural-log probability of the sampled token.
+    pub logprob: f32,
+    /// Most likely alternatives at this position, best first.
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// Result of a generation call.
+#[derive(Debug, Clone, Default)]
+pub struct Generation {
+    /// Decoded completion text (special tokens skipped).
+    pub text: String,
+    /// Tokens fed to the model for the prompt, including any BOS token.
+    pub prompt_tokens: usize,
+    /// Tokens produced by the model, excluding the terminating stop token.
+    pub completion_tokens: usize,
+    /// Per-token log-probabilities; `Some` only when requested.
+    pub logprobs: Option<Vec<TokenLogprob>>,
+    /// Decoding was cut short by [`GenerateOptions::deadline`]; `text` holds
+    /// only the tokens produced before it passed.
+    pub truncated_by_deadline: bool,
+    /// Why decoding ended.
+    pub finish_reason: FinishReason,
+    /// Draft acceptance counters; `Some` only for speculative generation.
+    pub speculative: Option<SpeculativeStats>,
+}
+
+/// Compute the log-probability of `chosen` and the `top_n` most likely
+/// token IDs from a raw logits vector, using a numerically stable
+/// log-softmax. Returned alternatives are sorted best first.
+pub(crate) fn logprobs_from_logits(
+    logits: &[f32],
+    chosen: u32,
+    top_n: usize,
+) -> (f32, Vec<(u32, f32)>) {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum_exp = max + logits.iter().map(|&l| (l - max).exp()).sum::<f32>().ln();
+
+    let chosen_lp = logits
+        .get(chosen as usize)
+        .map(|&l| l - log_sum_exp)
+        .unwrap_or(f32::NEG_INFINITY);
+
+    let mut top: Vec<(u32, f32)> = Vec::new();
+    if top_n > 0 {
+        let mut indexed: Vec<(u32, f32)> = logits
+            .iter()
+            .enumerate()
+            .map(|(i, &l)| (i as u32, l - log_sum_exp))
+            .collect();
+        let n = top_n.min(indexed.len());
+        if n > 0 {
+            indexed.select_nth_unstable_by(n - 1, |a, b| b.1.total_cmp(&a.1));
+            indexed.truncate(n);
+            indexed.sort_by(|a, b| b.1.total_cmp(&a.1));
+        }
+        top = indexed;
+    }
+    (chosen_lp, top)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logprobs_sum_to_one() {
+        let logits = [1.0f32, 2.0, 3.0, 0.5];
+        let (_, top) = logprobs_from_logits(&logits, 0, logits.len());
+        let total: f32 = top.iter().map(|(_, lp)| lp.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn top_logprobs_sorted_best_first() {
+        let logits = [0.1f32, 5.0, -2.0, 3.0];
+        let (chosen, top) = logprobs_from_logits(&logits, 3, 2);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0, 1);
+        assert_eq!(top[1].0, 3);
+        assert!((chosen - top[1].1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn out_of_range_token_is_neg_infinity() {
+        let (lp, top) = logprobs_from_logits(&[0.0, 1.0], 7, 0);
+        assert_eq!(lp, f32::NEG_INFINITY);
+        assert!(top.is_empty());
+    }
+
+    #[test]
+    fn default_options_match_previous_behaviour() {
+        let opts = GenerateOptions::default();
+        assert_eq!(opts.max_new_tokens, 256);
+        assert!(!opts.logprobs);
+        assert!(opts.deadline.is_none());
+        assert!(opts.stop.is_empty());
+    }
+
+    #[test]
+    fn deadline_passed() {
+        let mut opts = GenerateOptions::default();
+        assert!(!opts.deadline_passed());
+        opts.deadline = Some(Instant::now() + std::time::Duration::from_secs(60));
+        assert!(!opts.deadline_passed());
+        opts.deadline = Some(Instant::now());
+        assert!(opts.deadline_passed());
+    }
+}
@@ -0,0 +1,111 @@
+//! Resource governor for background contribution
+//!
+//! Two knobs keep a contributing node from taking over its host:
+//!
+//! - [`limit_cpu_threads`] caps the threads candle's CPU kernels (and the
+//!   rayon pool behind them) use. It must run before the first forward pass.
+//! - [`DutyCycle`] caps accelerator utilization by idling between forward
+//!   passes: at 40% utilization a 60 ms pass is followed by 90 ms of rest
+//!   before the next one may start.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Environment variable candle and rayon read their thread count from
+const THREADS_ENV: &str = "RAYON_NUM_THREADS";
+
+/// Cap the threads used for CPU inference at `max` and return the count in
+/// effect
+///
+/// An explicit `RAYON_NUM_THREADS` set by the operator takes precedence.
+/// Call early at startup: the rayon pool is sized on first use.
+pub fn limit_cpu_threads(max: usize) -> usize {
+    if std::env::var_os(THREADS_ENV).is_none() {
+        let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let max = max.clamp(1, available);
+        std::env::set_var(THREADS_ENV, max.to_string());
+        tracing::info!("Limiting CPU inference to {} thread(s)", max);
+    }
+    candle_core::utils::get_num_threads()
+}
+
+/// Idles between forward passes to hold average utilization at a target
+#[derive(Debug)]
+pub struct DutyCycle {
+    utilization: f64,
+    resume_at: Mutex<Option<Instant>>,
+}
+
+impl DutyCycle {
+    /// Duty cycle targeting `utilization` (a fraction, clamped to 0.05..=1.0)
+    pub fn new(utilization: f64) -> Self {
+        Self {
+            utilization: utilization.clamp(0.05, 1.0),
+            resume_at: Mutex::new(None),
+        }
+    }
+
+    /// Target utilization as a fraction
+    pub fn utilization(&self) -> f64 {
+        self.utilization
+    }
+
+    /// Rest owed after `busy` worth of compute
+    pub fn idle_after(&self, busy: Duration) -> Duration {
+        busy.mul_f64((1.0 - self.utilization) / self.utilization)
+    }
+
+    /// Record a finished forward pass that took `busy`
+    pub fn record_busy(&self, busy: Duration) {
+        let idle = self.idle_after(busy);
+        if idle.is_zero() {
+            return;
+        }
+        let mut resume_at = self.resume_at.lock().unwrap();
+        let from = resume_at.map_or(Instant::now(), |t| t.max(Instant::now()));
+        *resume_at = Some(from + idle);
+    }
+
+    /// Wait out the rest owed by earlier forward passes
+    pub async fn wait_turn(&self) {
+        let resume_at = *self.resume_at.lock().unwrap();
+        if let Some(at) = resume_at {
+            tokio::time::sleep_until(at.into()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_time_follows_the_target() {
+        let duty = DutyCycle::new(0.4);
+        assert_eq!(
+            duty.idle_after(Duration::from_millis(60)),
+            Duration::from_millis(90)
+        );
+        assert_eq!(
+            DutyCycle::new(1.0).idle_after(Duration::from_secs(1)),
+            Duration::ZERO
+        );
+        assert_eq!(DutyCycle::new(0.0).utilization(), 0.05);
+    }
+
+    #[tokio::test]
+    async fn wait_turn_rests_after_busy_passes() {
+        let duty = DutyCycle::new(0.5);
+        duty.wait_turn().await;
+
+        let start = Instant::now();
+        duty.record_busy(Duration::from_millis(40));
+        duty.record_busy(Duration::from_millis(40));
+        duty.wait_turn().await;
+        assert!(
+            start.elapsed() >= Duration::from_millis(80),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Memory-mapped GGUF files with per-layer materialization
+//!
+//! [`LazyGguf`] maps the file instead of reading it: opening one parses only
+//! the header, and tensor bytes are paged in by the OS when a tensor is
+//! materialized. A node serving a block range of a 70B model touches just
+//! those blocks' bytes, so it starts in seconds rather than after reading
+//! the whole file:
+//!
+//! ```text
+//! let gguf = LazyGguf::open(path, &device)?;
+//! let blocks = gguf.layers(40..48)?;   // only blk.40.* – blk.47.* are read
+//! ```
+//!
+//! Materialized layers stay cached until [`LazyGguf::release`]d; the
+//! mapping itself costs no resident memory beyond the pages in use.
+
+use crate::error::{InferenceError, InferenceResult};
+use candle_core::{
+    quantized::{gguf_file, QTensor},
+    Device,
+};
+use memmap2::Mmap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Map `path` read-only
+pub(crate) fn map_file(path: &Path) -> InferenceResult<Mmap> {
+    let file = std::fs::File::open(path).map_err(|e| {
+        InferenceError::ModelLoadError(format!("Cannot open {}: {e}", path.display()))
+    })?;
+    // SAFETY: model files are not modified while a node serves them; a
+    // truncated file surfaces as a read error when a tensor is materialized.
+    unsafe { Mmap::map(&file) }
+        .map_err(|e| InferenceError::ModelLoadError(format!("Cannot map {}: {e}", path.display())))
+}
+
+/// Parse the GGUF header at the start of `bytes`
+pub(crate) fn read_header(bytes: &[u8], path: &Path) -> InferenceResult<gguf_file::Content> {
+    gguf_file::Content::read(&mut Cursor::new(bytes)).map_err(|e| {
+        InferenceError::ModelLoadError(format!(
+            "Cannot parse GGUF header in {}: {e}",
+            path.display()
+        ))
+    })
+}
+
+/// Tensors of one transformer block, keyed without their `blk.{i}.` prefix
+#[derive(Debug)]
+pub struct GgufLayer {
+    index: usize,
+    tensors: HashMap<String, Arc<QTensor>>,
+}
+
+impl GgufLayer {
+    /// Block index in the model
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Tensor `name` (e.g. `attn_q.weight`), if the block has one
+    pub fn get(&self, name: &str) -> Option<&Arc<QTensor>> {
+        self.tensors.get(name)
+    }
+
+    /// Tensor `name`, failing when the block lacks it
+    pub fn tensor(&self, name: &str) -> InferenceResult<Arc<QTensor>> {
+        self.get(name).cloned().ok_or_else(|| {
+            InferenceError::ModelLoadError(format!("blk.{}.{name} not found", self.index))
+        })
+    }
+
+    /// Names of the block's tensors, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tensors.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Bytes held by the block's (quantized) tensors
+    pub fn size_in_bytes(&self) -> usize {
+        self.tensors
+            .values()
+            .map(|t| t.storage_size_in_bytes())
+            .sum()
+    }
+}
+
+/// A GGUF file mapped into memory, materializing blocks on demand
+pub struct LazyGguf {
+    path: PathBuf,
+    mmap: Mmap,
+    content: gguf_file::Content,
+    device: Device,
+    /// Tensor names of each block, grouped once at open
+    blocks: BTreeMap<usize, Vec<String>>,
+    resident: Mutex<HashMap<usize, Arc<GgufLayer>>>,
+}
+
+impl std::fmt::Debug for LazyGguf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyGguf")
+            .field("path", &self.path)
+            .field("blocks", &self.blocks.len())
+            .field("resident", &self.resident_layers())
+            .finish()
+    }
+}
+
+/// Split `blk.{i}.{rest}` into the block index and `rest`
+fn block_of(name: &str) -> Option<(usize, &str)> {
+    let (index, rest) = name.strip_prefix("blk.")?.split_once('.')?;
+    Some((index.parse().ok()?, rest))
+}
+
+impl LazyGguf {
+    /// Map `path` and parse its header; tensors materialize on `device`
+    pub fn open(path: &Path, device: &Device) -> InferenceResult<Self> {
+        let mmap = map_file(path)?;
+        let content = read_header(&mmap, path)?;
+        let mut blocks: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for name in content.tensor_infos.keys() {
+            if let Some((index, _)) = block_of(name) {
+                blocks.entry(index).or_default().push(name.clone());
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            mmap,
+            content,
+            device: device.clone(),
+            blocks,
+            resident: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Parsed header: metadata and tensor table
+    pub fn content(&self) -> &gguf_file::Content {
+        &self.content
+    }
+
+    /// Reader over the mapped file, for loaders that take `Read + Seek`
+    pub fn reader(&self) -> Cursor<&[u8]> {
+        Cursor::new(&self.mmap[..])
+    }
+
+    /// Number of transformer blocks with tensors in the file
+    pub fn num_layers(&self) -> usize {
+        self.blocks.keys().next_back().map_or(0, |&last| last + 1)
+    }
+
+    /// Materialize a single tensor by its full GGUF name (not cached)
+    pub fn tensor(&self, name: &str) -> InferenceResult<QTensor> {
+        self.content
+            .tensor(&mut self.reader(), name, &self.device)
+            .map_err(|e| InferenceError::ModelLoadError(format!("{name}: {e}")))
+    }
+
+    /// Block `index`, materializing it on first use
+    pub fn layer(&self, index: usize) -> InferenceResult<Arc<GgufLayer>> {
+        if let Some(layer) = self.resident.lock().unwrap().get(&index) {
+            return Ok(layer.clone());
+        }
+        let names = self.blocks.get(&index).ok_or_else(|| {
+            InferenceError::InvalidInput(format!(
+                "block {index} is not in {} ({} blocks)",
+                self.path.display(),
+                self.num_layers()
+            ))
+        })?;
+        let mut tensors = HashMap::with_capacity(names.len());
+        for name in names {
+            let (_, rest) = block_of(name).expect("grouped by block prefix");
+            tensors.insert(rest.to_string(), Arc::new(self.tensor(name)?));
+        }
+        let layer = Arc::new(GgufLayer { index, tensors });
+        // Two callers racing on the same block both read it; keep the first.
+        Ok(self
+            .resident
+            .lock()
+            .unwrap()
+            .entry(index)
+            .or_insert(layer)
+            .clone())
+    }
+
+    /// Materialize the blocks in `range`, in order
+    pub fn layers(&self, range: Range<usize>) -> InferenceResult<Vec<Arc<GgufLayer>>> {
+        for index in range.clone() {
+            self.prefetch(index);
+        }
+        range.map(|index| self.layer(index)).collect()
+    }
+
+    /// Drop the cached tensors of block `index`; returns whether it was
+    /// resident. Callers still holding the layer keep it alive.
+    pub fn release(&self, index: usize) -> bool {
+        self.resident.lock().unwrap().remove(&index).is_some()
+    }
+
+    /// Indices of the materialized blocks, sorted
+    pub fn resident_layers(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = self.resident.lock().unwrap().keys().copied().collect();
+        indices.sort_unstable();
+        indices
+    }
+
+    /// Byte span of block `index`'s tensors within the file
+    fn block_span(&self, index: usize) -> Option<Range<usize>> {
+        let base = self.content.tensor_data_offset as usize;
+        self.blocks.get(&index)?.iter().fold(None, |span, name| {
+            let info = &self.content.tensor_infos[name];
+            let len = info.shape.elem_count() / info.ggml_dtype.block_size()
+                * info.ggml_dtype.type_size();
+            let start = base + info.offset as usize;
+            let end = (start + len).min(self.mmap.len());
+            Some(match span {
+                Some(Range { start: s, end: e }) => s.min(start)..e.max(end),
+                None => start..end,
+            })
+        })
+    }
+
+    /// Ask the OS to start paging in block `index` ahead of use
+    pub fn prefetch(&self, index: usize) {
+        #[cfg(unix)]
+        if let Some(span) = self.block_span(index) {
+            let _ = self.mmap.advise_range(
+                memmap2::Advice::WillNeed,
+                span.start,
+                span.end.saturating_sub(span.start),
+            );
+        }
+        #[cfg(not(unix))]
+        let _ = self.block_span(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::{quantized::GgmlDType, Tensor};
+    use gguf_file::Value;
+
+    fn write_model(path: &Path, n_layer: usize) -> Vec<Tensor> {
+        let dev = Device::Cpu;
+        let mut tensors = vec![(
+            "token_embd.weight".to_string(),
+            Tensor::randn(0f32, 1.0, (16, 32), &dev).unwrap(),
+        )];
+        for i in 0..n_layer {
+            for name in ["attn_q", "ffn_up"] {
+                tensors.push((
+                    format!("blk.{i}.{name}.weight"),
+                    Tensor::randn(0f32, 1.0, (8, 32), &dev).unwrap(),
+                ));
+            }
+        }
+        let q: Vec<(String, QTensor)> = tensors
+            .iter()
+            .map(|(n, t)| (n.clone(), QTensor::quantize(t, GgmlDType::F32).unwrap()))
+            .collect();
+        let ts: Vec<(&str, &QTensor)> = q.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let arch = Value::String("llama".into());
+        let mut file = std::fs::File::create(path).unwrap();
+        gguf_file::write(&mut file, &[("general.architecture", &arch)], &ts).unwrap();
+        tensors.into_iter().map(|(_, t)| t).collect()
+    }
+
+    #[test]
+    fn materializes_only_requested_blocks() {
+        let dir = std::env::temp_dir().join(format!("kwaai-lazy-gguf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("model.gguf");
+        let original = write_model(&path, 4);
+
+        let gguf = LazyGguf::open(&path, &Device::Cpu).unwrap();
+        assert_eq!(gguf.num_layers(), 4);
+        assert!(gguf.resident_layers().is_empty());
+
+        let layers = gguf.layers(2..4).unwrap();
+        assert_eq!(gguf.resident_layers(), [2, 3]);
+        assert_eq!(layers[0].index(), 2);
+        assert_eq!(layers[0].names(), ["attn_q.weight", "ffn_up.weight"]);
+        assert_eq!(layers[0].size_in_bytes(), 2 * 8 * 32 * 4);
+
+        // blk.2.attn_q is the 6th tensor written (after token_embd and two per block).
+        let got = layers[0]
+            .tensor("attn_q.weight")
+            .unwrap()
+            .dequantize(&Device::Cpu)
+            .unwrap();
+        let diff = (got - &original[5])
+            .unwrap()
+            .abs()
+            .unwrap()
+            .sum_all()
+            .unwrap();
+        assert_eq!(diff.to_scalar::<f32>().unwrap(), 0.0);
+
+        // Cached: the same tensors come back without another read.
+        assert!(Arc::ptr_eq(&gguf.layer(2).unwrap(), &layers[0]));
+        assert!(gguf.release(2));
+        assert!(!gguf.release(2));
+        assert_eq!(gguf.resident_layers(), [3]);
+        assert!(gguf.layer(9).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,7 +6,10 @@
 //!
 //! - **Model Loading**: Support for GGUF, SafeTensors formats
 //! - **Inference**: Text generation, embeddings, and more
-//! - **Resource Management**: Memory-aware model loading
+//! - **LoRA Adapters**: PEFT adapters applied per session on top of a shard
+//! - **Speculative Decoding**: a local draft model proposes tokens the target verifies
+//! - **Resource Management**: Memory-aware model loading, CPU thread caps and
+//!   GPU duty-cycling for background contribution
 //!
 //! ## Example
 //!
@@ -30,21 +33,44 @@
 //! ```
 
 pub mod config;
+pub mod constraint;
+pub mod decoder;
+pub mod device_map;
+pub mod embedding;
 pub mod engine;
 pub mod error;
+pub mod footprint;
+pub mod generation;
+pub mod governor;
+pub mod lazy_gguf;
 pub mod loader;
+pub mod lora;
 pub mod model;
+pub mod moe;
 pub mod shard;
+pub mod speculative;
+pub mod stop;
 pub mod tokenizer;
 
 #[cfg(feature = "mlx")]
 pub mod mlx_shard;
 
 pub use config::EngineConfig;
-pub use engine::InferenceEngine;
+pub use constraint::ResponseFormat;
+pub use device_map::DeviceMap;
+pub use embedding::Embeddings;
+pub use engine::{InferenceEngine, Sequence};
 pub use error::{InferenceError, InferenceResult};
+pub use footprint::ModelFootprint;
+pub use generation::{FinishReason, GenerateOptions, Generation, TokenLogprob, TopLogprob};
+pub use governor::{limit_cpu_threads, DutyCycle};
+pub use lazy_gguf::{GgufLayer, LazyGguf};
+pub use lora::{LoraAdapter, LoraConfig};
 pub use model::{ModelFormat, ModelHandle, ModelInfo};
-pub use shard::{ShardConfig, TransformerShard};
+pub use shard::{BlockThroughput, ShardConfig, TransformerShard};
+pub use speculative::{CausalLm, ShardSession, SpeculativeStats};
+pub use stop::StopCriteria;
+pub use tokenizer::{ChatMessage, ChatTemplate, Tokenizer};
 
 use async_trait::async_trait;
 use candle_core::Tensor;
@@ -104,6 +130,8 @@ pub struct ModelConfig {
     pub architecture: String,
     /// Maximum sequence length
     pub max_seq_len: usize,
+    /// Vocabulary size
+    pub vocab_size: usize,
     /// Number of attention heads
     pub num_heads: usize,
     /// Number of key-value heads (for GQA)
@@ -123,6 +151,7 @@ impl Default for ModelConfig {
         Self {
             architecture: "llama".to_string(),
             max_seq_len: 4096,
+            vocab_size: 32_000,
             num_heads: 32,
             num_kv_heads: 8,
             hidden_dim: 4096,
@@ -270,6 +299,48 @@ impl DeviceType {
         !matches!(self, DeviceType::Cpu)
     }
 
+    /// Short device kind as announced to peers: `cpu`, `cuda`, `metal`, `mlx`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeviceType::Cpu => "cpu",
+            DeviceType::Cuda(_) => "cuda",
+            DeviceType::Metal(_) => "metal",
+            DeviceType::Mlx => "mlx",
+        }
+    }
+
+    /// Backend that runs inference on this device.
+    pub fn backend(&self) -> &'static str {
+        match self {
+            DeviceType::Cpu => "candle-cpu",
+            DeviceType::Cuda(_) => "candle-cuda",
+            DeviceType::Metal(_) => "candle-metal",
+            DeviceType::Mlx => "mlx",
+        }
+    }
+
+    /// Dtypes the backend computes in on this device (torch names).
+    pub fn supported_dtypes(&self) -> &'static [&'static str] {
+        match self {
+            DeviceType::Cpu | DeviceType::Cuda(_) | DeviceType::Mlx => {
+                &["float16", "bfloat16", "float32"]
+            }
+            DeviceType::Metal(_) => &["float16", "float32"],
+        }
+    }
+
+    /// Quantized weight formats the backend can serve (`none` = unquantized).
+    pub fn supported_quant_types(&self) -> &'static [&'static str] {
+        match self {
+            // GGUF block formats candle's quantized matmul handles.
+            DeviceType::Cpu | DeviceType::Cuda(_) | DeviceType::Metal(_) => &[
+                "none", "q4_0", "q4_1", "q5_0", "q5_1", "q8_0", "q2_k", "q3_k", "q4_k", "q5_k",
+                "q6_k",
+            ],
+            DeviceType::Mlx => &["none"],
+        }
+    }
+
     /// Convert to Candle device
     pub fn to_candle_device(&self) -> InferenceResult<candle_core::Device> {
         match self {
@@ -295,6 +366,49 @@ impl DeviceType {
     }
 }
 
+/// A CUDA device as the driver reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CudaDeviceInfo {
+    /// Device ordinal (`cuda:N`)
+    pub ordinal: usize,
+    /// Device model, e.g. "NVIDIA GeForce RTX 4090"
+    pub name: String,
+    /// Device memory in bytes
+    pub total_memory: u64,
+    /// Free device memory in bytes
+    pub free_memory: u64,
+}
+
+/// CUDA devices visible to this process, in ordinal order.
+///
+/// Empty when the binary was built without CUDA or no driver is loaded.
+/// Briefly creates a context on each device to read its free memory.
+pub fn cuda_devices() -> Vec<CudaDeviceInfo> {
+    #[cfg(feature = "cuda")]
+    {
+        use candle_core::cuda_backend::cudarc::driver::{result, CudaContext};
+
+        let count = CudaContext::device_count().unwrap_or(0).max(0) as usize;
+        return (0..count)
+            .filter_map(|ordinal| {
+                let ctx = CudaContext::new(ordinal).ok()?;
+                let name = ctx.name().ok()?;
+                // Reports on the context just bound to this thread.
+                let (free, total) = result::mem_get_info().ok()?;
+                Some(CudaDeviceInfo {
+                    ordinal,
+                    name,
+                    total_memory: total as u64,
+                    free_memory: free as u64,
+                })
+            })
+            .collect();
+    }
+
+    #[allow(unreachable_code)]
+    Vec::new()
+}
+
 impl std::fmt::Display for DeviceType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
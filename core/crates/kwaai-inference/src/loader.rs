@@ -5,13 +5,17 @@
 //! `candle_transformers`.
 
 use crate::{
+    decoder::{DecoderArch, DecoderWeights},
     error::{InferenceError, InferenceResult},
+    lazy_gguf::{map_file, read_header},
+    moe::ExpertOffload,
     tokenizer::BpeTokenizer,
     ModelConfig,
 };
 use candle_core::{quantized::gguf_file, Device, Tensor};
+use std::io::Cursor;
 use std::path::Path;
-use tracing::info;
+use tracing::{info, warn};
 
 // ── GGUF ─────────────────────────────────────────────────────────────────────
 
@@ -20,6 +24,8 @@ pub enum GgufWeights {
     Llama(candle_transformers::models::quantized_llama::ModelWeights),
     Qwen2(candle_transformers::models::quantized_qwen2::ModelWeights),
     Gemma3(candle_transformers::models::quantized_gemma3::ModelWeights),
+    /// Phi-3, Gemma and Gemma 2 (see [`crate::decoder`]).
+    Decoder(crate::decoder::DecoderWeights),
 }
 
 impl GgufWeights {
@@ -33,6 +39,17 @@ impl GgufWeights {
             GgufWeights::Llama(w) => w.forward(x, index_pos),
             GgufWeights::Qwen2(w) => w.forward(x, index_pos),
             GgufWeights::Gemma3(w) => w.forward(x, index_pos),
+            GgufWeights::Decoder(w) => w.forward(x, index_pos),
+        }
+    }
+    /// A copy with its own KV-cache, sharing the weight tensors. `None` for
+    /// architectures whose candle implementation cannot be cloned (Qwen2).
+    pub fn fork(&self) -> Option<Self> {
+        match self {
+            GgufWeights::Llama(w) => Some(GgufWeights::Llama(w.clone())),
+            GgufWeights::Qwen2(_) => None,
+            GgufWeights::Gemma3(w) => Some(GgufWeights::Gemma3(w.clone())),
+            GgufWeights::Decoder(w) => Some(GgufWeights::Decoder(w.clone())),
         }
     }
 }
@@ -51,91 +68,195 @@ pub struct GgufModel {
     pub tokenizer: BpeTokenizer,
 }
 
+/// The candle implementation that runs a GGUF architecture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GgufRunner {
+    Llama,
+    Qwen2,
+    Gemma3,
+    Decoder(DecoderArch),
+}
+
+/// `general.architecture` values [`load_gguf`] can run, for error messages.
+pub const SUPPORTED_GGUF_ARCHITECTURES: &str = "llama, mistral, qwen2, phi3, gemma, gemma2, gemma3";
+
+impl GgufRunner {
+    /// The runner for `arch`, if this build has one.
+    pub fn for_architecture(arch: &str) -> Option<Self> {
+        match arch {
+            "llama" | "mistral" | "llama3" => Some(Self::Llama),
+            "qwen2" => Some(Self::Qwen2),
+            "gemma3" | "gemma4" => Some(Self::Gemma3),
+            _ => DecoderArch::from_architecture(arch).map(Self::Decoder),
+        }
+    }
+
+    /// The runner for the model in `ct`, read off `general.architecture`
+    /// and the expert count alone so an unsupported model is rejected
+    /// before the rest of its metadata is parsed.
+    pub fn for_gguf(ct: &gguf_file::Content) -> InferenceResult<Self> {
+        let arch = meta_str(ct, "general.architecture").ok_or_else(|| {
+            InferenceError::InvalidFormat("GGUF metadata has no general.architecture".to_string())
+        })?;
+        let runner = Self::for_architecture(&arch).ok_or_else(|| {
+            InferenceError::UnsupportedArchitecture {
+                architecture: arch.clone(),
+                supported: SUPPORTED_GGUF_ARCHITECTURES.to_string(),
+            }
+        })?;
+        // Mixtral keeps the `llama` architecture and adds experts.
+        let n_expert = meta_usize(ct, &format!("{arch}.expert_count")).unwrap_or(0);
+        Ok(match runner {
+            Self::Llama if n_expert > 1 => Self::Decoder(DecoderArch::Mixtral),
+            runner => runner,
+        })
+    }
+}
+
+impl ModelConfig {
+    /// Architecture config from GGUF metadata (the `<arch>.*` keys).
+    ///
+    /// Fails when `general.architecture` or a dimension every runner needs
+    /// is missing, rather than guessing Llama-7B shapes. Keys that are
+    /// optional in the format fall back to the values llama.cpp assumes;
+    /// the feed-forward width and vocabulary size are read off the tensors
+    /// when the metadata omits them.
+    pub fn from_gguf(ct: &gguf_file::Content) -> InferenceResult<Self> {
+        let arch = meta_str(ct, "general.architecture").ok_or_else(|| {
+            InferenceError::InvalidFormat("GGUF metadata has no general.architecture".to_string())
+        })?;
+        let required = |key: &str| {
+            meta_usize(ct, &format!("{arch}.{key}")).ok_or_else(|| {
+                InferenceError::InvalidFormat(format!("GGUF metadata is missing {arch}.{key}"))
+            })
+        };
+        let tensor_dim = |name: &str, dim: usize| {
+            ct.tensor_infos
+                .get(name)
+                .and_then(|t| t.shape.dims().get(dim).copied())
+        };
+
+        let hidden_dim = required("embedding_length")?;
+        let num_heads = required("attention.head_count")?;
+        let num_kv_heads =
+            meta_usize(ct, &format!("{arch}.attention.head_count_kv")).unwrap_or(num_heads);
+        // candle reports GGUF shapes as [out, in], so the up projection is [ffn, hidden].
+        let intermediate_dim = meta_usize(ct, &format!("{arch}.feed_forward_length"))
+            .or_else(|| tensor_dim("blk.0.ffn_up.weight", 0))
+            .ok_or_else(|| {
+                InferenceError::InvalidFormat(format!(
+                    "GGUF metadata is missing {arch}.feed_forward_length"
+                ))
+            })?;
+        let vocab_size = meta_usize(ct, &format!("{arch}.vocab_size"))
+            .or_else(|| match ct.metadata.get("tokenizer.ggml.tokens") {
+                Some(gguf_file::Value::Array(tokens)) => Some(tokens.len()),
+                _ => None,
+            })
+            .or_else(|| tensor_dim("token_embd.weight", 0))
+            .ok_or_else(|| {
+                InferenceError::InvalidFormat("GGUF metadata has no vocabulary size".to_string())
+            })?;
+        let max_seq_len = meta_usize(ct, &format!("{arch}.context_length")).unwrap_or_else(|| {
+            warn!("GGUF metadata has no {arch}.context_length; assuming 4096");
+            4_096
+        });
+        let rope_theta = meta_f32(ct, &format!("{arch}.rope.freq_base")).unwrap_or(10_000.0);
+        let layer_norm_eps = meta_f32(ct, &format!("{arch}.attention.layer_norm_rms_epsilon"))
+            .or_else(|| meta_f32(ct, &format!("{arch}.attention.layer_norm_epsilon")))
+            .unwrap_or(1e-5);
+
+        if num_kv_heads == 0 || num_heads % num_kv_heads != 0 {
+            return Err(InferenceError::InvalidFormat(format!(
+                "{num_heads} attention heads cannot be shared by {num_kv_heads} KV heads"
+            )));
+        }
+        Ok(Self {
+            architecture: arch,
+            max_seq_len,
+            vocab_size,
+            num_heads,
+            num_kv_heads,
+            hidden_dim,
+            intermediate_dim,
+            rope_theta,
+            layer_norm_eps,
+        })
+    }
+}
+
 /// Load a GGUF model file into memory.
 ///
-/// Reads `general.architecture` from the GGUF metadata to pick the right
-/// weight loader, then extracts architecture config and loads real weights.
+/// Picks the runner for `general.architecture` (failing with
+/// [`InferenceError::UnsupportedArchitecture`] before anything else is
+/// parsed when there is none), builds the [`ModelConfig`] from the GGUF
+/// metadata, then loads the real weights.
 pub fn load_gguf(path: &Path, device: &Device) -> InferenceResult<GgufModel> {
-    use candle_transformers::models::{quantized_gemma3, quantized_llama, quantized_qwen2};
+    load_gguf_with_offload(path, device, None)
+}
 
-    let mut file = std::fs::File::open(path).map_err(|e| {
-        InferenceError::ModelLoadError(format!("Cannot open {}: {e}", path.display()))
-    })?;
+/// Like [`load_gguf`]; a Mixtral model loads only the experts `offload`
+/// keeps local. Ignored for dense models.
+pub fn load_gguf_with_offload(
+    path: &Path,
+    device: &Device,
+    offload: Option<&ExpertOffload>,
+) -> InferenceResult<GgufModel> {
+    use candle_transformers::models::{quantized_gemma3, quantized_llama, quantized_qwen2};
 
-    let gguf = gguf_file::Content::read(&mut file).map_err(|e| {
-        InferenceError::ModelLoadError(format!(
-            "Cannot parse GGUF header in {}: {e}",
-            path.display()
-        ))
+    // Weights are copied out of the mapping as each runner asks for them,
+    // without an extra buffered read of the whole file.
+    let mmap = map_file(path)?;
+    let gguf = read_header(&mmap, path)?;
+    let mut file = Cursor::new(&mmap[..]);
+
+    let runner = GgufRunner::for_gguf(&gguf)?;
+    let config = ModelConfig::from_gguf(&gguf)?;
+    let arch = config.architecture.clone();
+    let n_expert = meta_usize(&gguf, &format!("{arch}.expert_count")).unwrap_or(0);
+    let num_layers = meta_usize(&gguf, &format!("{arch}.block_count")).ok_or_else(|| {
+        InferenceError::InvalidFormat(format!("GGUF metadata is missing {arch}.block_count"))
     })?;
-
-    // Detect architecture from the GGUF general metadata.
-    let arch = meta_str(&gguf, "general.architecture").unwrap_or_else(|| "llama".to_string());
-
-    // Architecture-specific metadata key prefix.
-    let pfx = arch.as_str();
-
-    let vocab_size = meta_usize(&gguf, "llama.vocab_size")
-        .or_else(|| meta_usize(&gguf, &format!("{pfx}.vocab_size")))
-        .unwrap_or(32_000);
-    let num_layers = meta_usize(&gguf, &format!("{pfx}.block_count")).unwrap_or(32);
-    let num_heads = meta_usize(&gguf, &format!("{pfx}.attention.head_count")).unwrap_or(32);
-    let num_kv_heads =
-        meta_usize(&gguf, &format!("{pfx}.attention.head_count_kv")).unwrap_or(num_heads);
-    let hidden_dim = meta_usize(&gguf, &format!("{pfx}.embedding_length")).unwrap_or(4_096);
-    let inter_dim = meta_usize(&gguf, &format!("{pfx}.feed_forward_length")).unwrap_or(11_008);
-    let rope_theta = meta_f32(&gguf, &format!("{pfx}.rope.freq_base")).unwrap_or(10_000.0);
-    let max_seq_len = meta_usize(&gguf, &format!("{pfx}.context_length")).unwrap_or(4_096);
+    let vocab_size = config.vocab_size;
 
     info!(
-        "GGUF arch={arch}: {num_layers} layers, {num_heads} heads ({num_kv_heads} kv), \
-         hidden={hidden_dim}, vocab={vocab_size}"
+        "GGUF arch={arch}: {num_layers} layers, {} heads ({} kv), hidden={}, vocab={vocab_size}, \
+         context={}, rope_theta={}",
+        config.num_heads,
+        config.num_kv_heads,
+        config.hidden_dim,
+        config.max_seq_len,
+        config.rope_theta,
     );
+    if n_expert > 1 {
+        let local = offload
+            .and_then(|o| o.local.as_ref())
+            .map_or(n_expert, |l| l.iter().filter(|&&e| e < n_expert).count());
+        info!("Mixture of experts: {n_expert} experts per layer, {local} loaded locally");
+    }
 
     // Build the BPE tokenizer from GGUF metadata BEFORE consuming `gguf`
     // in the weight loader below (which moves it by value).
-    let tokenizer = BpeTokenizer::from_gguf(&gguf)?;
+    let tokenizer = BpeTokenizer::for_gguf(path, &gguf)?;
 
-    let config = ModelConfig {
-        architecture: arch.clone(),
-        max_seq_len,
-        num_heads,
-        num_kv_heads,
-        hidden_dim,
-        intermediate_dim: inter_dim,
-        rope_theta,
-        layer_norm_eps: 1e-5,
+    let build_err = |e: candle_core::Error| {
+        InferenceError::ModelLoadError(format!("Cannot build {arch} weights: {e}"))
     };
-
-    // Dispatch to the architecture-specific quantized weight loader.
-    let weights = match arch.as_str() {
-        "llama" | "mistral" | "llama3" => {
-            let w =
-                quantized_llama::ModelWeights::from_gguf(gguf, &mut file, device).map_err(|e| {
-                    InferenceError::ModelLoadError(format!("Cannot build {arch} weights: {e}"))
-                })?;
-            GgufWeights::Llama(w)
-        }
-        "qwen2" => {
-            let w =
-                quantized_qwen2::ModelWeights::from_gguf(gguf, &mut file, device).map_err(|e| {
-                    InferenceError::ModelLoadError(format!("Cannot build qwen2 weights: {e}"))
-                })?;
-            GgufWeights::Qwen2(w)
-        }
-        "gemma3" | "gemma2" | "gemma" | "gemma4" => {
-            let w = quantized_gemma3::ModelWeights::from_gguf(gguf, &mut file, device).map_err(
-                |e| InferenceError::ModelLoadError(format!("Cannot build {arch} weights: {e}")),
-            )?;
-            GgufWeights::Gemma3(w)
-        }
-        other => {
-            return Err(InferenceError::InvalidFormat(format!(
-                "GGUF architecture '{other}' is not yet supported. \
-                 Supported: llama, mistral, qwen2, gemma3. \
-                 See CONTRIBUTORS.md to add support."
-            )));
-        }
+    let weights = match runner {
+        GgufRunner::Llama => GgufWeights::Llama(
+            quantized_llama::ModelWeights::from_gguf(gguf, &mut file, device).map_err(build_err)?,
+        ),
+        GgufRunner::Qwen2 => GgufWeights::Qwen2(
+            quantized_qwen2::ModelWeights::from_gguf(gguf, &mut file, device).map_err(build_err)?,
+        ),
+        GgufRunner::Gemma3 => GgufWeights::Gemma3(
+            quantized_gemma3::ModelWeights::from_gguf(gguf, &mut file, device)
+                .map_err(build_err)?,
+        ),
+        GgufRunner::Decoder(arch) => GgufWeights::Decoder(
+            DecoderWeights::from_gguf_with_offload(arch, gguf, &mut file, device, offload)
+                .map_err(build_err)?,
+        ),
     };
 
     Ok(GgufModel {
@@ -147,6 +268,16 @@ pub fn load_gguf(path: &Path, device: &Device) -> InferenceResult<GgufModel> {
     })
 }
 
+/// Read only the GGUF header of `path` and return `general.architecture`.
+///
+/// Used to route encoder-only embedding models to [`crate::embedding`]
+/// before committing to a weight loader.
+pub fn gguf_architecture(path: &Path) -> InferenceResult<Option<String>> {
+    let mmap = map_file(path)?;
+    let gguf = read_header(&mmap, path)?;
+    Ok(meta_str(&gguf, "general.architecture"))
+}
+
 // ── SafeTensors ───────────────────────────────────────────────────────────────
 
 /// Full-precision model loaded from SafeTensors shards.
@@ -225,6 +356,7 @@ pub fn load_safetensors(
     let config = ModelConfig {
         architecture: "llama".to_string(),
         max_seq_len,
+        vocab_size,
         num_heads,
         num_kv_heads,
         hidden_dim,
@@ -250,9 +382,16 @@ pub fn load_safetensors(
     })
 }
 
+/// Read `model_type` from a HuggingFace `config.json`, if present.
+pub fn config_model_type(config_json_path: &Path) -> Option<String> {
+    let s = std::fs::read_to_string(config_json_path).ok()?;
+    let v: serde_json::Value = serde_json::from_str(&s).ok()?;
+    v.get("model_type")?.as_str().map(str::to_string)
+}
+
 // ── GGUF metadata helpers ─────────────────────────────────────────────────────
 
-fn meta_str(ct: &gguf_file::Content, key: &str) -> Option<String> {
+pub(crate) fn meta_str(ct: &gguf_file::Content, key: &str) -> Option<String> {
     use gguf_file::Value;
     match ct.metadata.get(key)? {
         Value::String(s) => Some(s.clone()),
@@ -260,7 +399,7 @@ fn meta_str(ct: &gguf_file::Content, key: &str) -> Option<String> {
     }
 }
 
-fn meta_usize(ct: &gguf_file::Content, key: &str) -> Option<usize> {
+pub(crate) fn meta_usize(ct: &gguf_file::Content, key: &str) -> Option<usize> {
     use gguf_file::Value;
     match ct.metadata.get(key)? {
         Value::U8(v) => Some(*v as usize),
@@ -275,7 +414,7 @@ fn meta_usize(ct: &gguf_file::Content, key: &str) -> Option<usize> {
     }
 }
 
-fn meta_f32(ct: &gguf_file::Content, key: &str) -> Option<f32> {
+pub(crate) fn meta_f32(ct: &gguf_file::Content, key: &str) -> Option<f32> {
     use gguf_file::Value;
     match ct.metadata.get(key)? {
         Value::F32(v) => Some(*v),
@@ -283,3 +422,107 @@ fn meta_f32(ct: &gguf_file::Content, key: &str) -> Option<f32> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::quantized::GgmlDType;
+    use gguf_file::{TensorInfo, Value, VersionedMagic};
+    use std::collections::HashMap;
+
+    fn content(metadata: &[(&str, Value)]) -> gguf_file::Content {
+        gguf_file::Content {
+            magic: VersionedMagic::GgufV3,
+            metadata: metadata
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+            tensor_infos: HashMap::new(),
+            tensor_data_offset: 0,
+        }
+    }
+
+    #[test]
+    fn config_comes_from_gguf_metadata() {
+        let mut ct = content(&[
+            ("general.architecture", Value::String("qwen2".into())),
+            ("qwen2.embedding_length", Value::U32(896)),
+            ("qwen2.attention.head_count", Value::U32(14)),
+            ("qwen2.attention.head_count_kv", Value::U32(2)),
+            ("qwen2.context_length", Value::U32(32_768)),
+            ("qwen2.rope.freq_base", Value::F32(1_000_000.0)),
+            ("qwen2.attention.layer_norm_rms_epsilon", Value::F32(1e-6)),
+            (
+                "tokenizer.ggml.tokens",
+                Value::Array(vec![Value::String("a".into()); 5]),
+            ),
+        ]);
+        ct.tensor_infos.insert(
+            "blk.0.ffn_up.weight".into(),
+            TensorInfo {
+                ggml_dtype: GgmlDType::F32,
+                shape: (4_864, 896).into(),
+                offset: 0,
+            },
+        );
+
+        let config = ModelConfig::from_gguf(&ct).unwrap();
+        assert_eq!(config.architecture, "qwen2");
+        assert_eq!((config.num_heads, config.num_kv_heads), (14, 2));
+        assert_eq!(config.hidden_dim, 896);
+        assert_eq!(config.intermediate_dim, 4_864);
+        assert_eq!(config.max_seq_len, 32_768);
+        assert_eq!(config.vocab_size, 5);
+        assert_eq!(config.rope_theta, 1_000_000.0);
+        assert_eq!(config.layer_norm_eps, 1e-6);
+        assert_eq!(
+            GgufRunner::for_architecture(&config.architecture),
+            Some(GgufRunner::Qwen2)
+        );
+    }
+
+    #[test]
+    fn missing_metadata_is_an_error_not_a_default() {
+        let ct = content(&[("llama.embedding_length", Value::U32(4_096))]);
+        assert!(matches!(
+            ModelConfig::from_gguf(&ct),
+            Err(InferenceError::InvalidFormat(_))
+        ));
+
+        let ct = content(&[
+            ("general.architecture", Value::String("llama".into())),
+            ("llama.embedding_length", Value::U32(4_096)),
+        ]);
+        let err = ModelConfig::from_gguf(&ct).unwrap_err().to_string();
+        assert!(err.contains("llama.attention.head_count"), "{err}");
+
+        assert_eq!(
+            GgufRunner::for_architecture("phi3"),
+            Some(GgufRunner::Decoder(DecoderArch::Phi3))
+        );
+        assert_eq!(
+            GgufRunner::for_architecture("gemma2"),
+            Some(GgufRunner::Decoder(DecoderArch::Gemma2))
+        );
+        assert_eq!(GgufRunner::for_architecture("phi2"), None);
+    }
+
+    #[test]
+    fn unsupported_architecture_is_rejected_before_its_metadata() {
+        // No dimensions at all: the runner check must fail first.
+        let ct = content(&[("general.architecture", Value::String("phi2".into()))]);
+        assert!(matches!(
+            GgufRunner::for_gguf(&ct),
+            Err(InferenceError::UnsupportedArchitecture { .. })
+        ));
+
+        let ct = content(&[
+            ("general.architecture", Value::String("llama".into())),
+            ("llama.expert_count", Value::U32(8)),
+        ]);
+        assert_eq!(
+            GgufRunner::for_gguf(&ct).unwrap(),
+            GgufRunner::Decoder(DecoderArch::Mixtral)
+        );
+    }
+}
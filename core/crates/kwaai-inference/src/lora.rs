@@ -0,0 +1,392 @@
+//! LoRA adapters on top of a loaded [`TransformerShard`](crate::TransformerShard).
+//!
+//! Adapters are read from a PEFT checkpoint directory:
+//!
+//! - `adapter_config.json` — `r`, `lora_alpha`, `target_modules`, …
+//! - `adapter_model.safetensors` — `lora_A` / `lora_B` pairs keyed like
+//!   `base_model.model.model.layers.{i}.self_attn.q_proj.lora_A.weight`.
+//!
+//! Only the layers a shard serves are kept. A projection `W x` with an adapter
+//! becomes `W x + scale · B (A x)` where `scale = lora_alpha / r`
+//! (`lora_alpha / √r` with rsLoRA). The base weights are never modified, so any
+//! number of adapters can be loaded side by side and chosen per session.
+
+use crate::{
+    error::{InferenceError, InferenceResult},
+    shard::ShardConfig,
+};
+use candle_core::{Device, Tensor};
+use candle_nn::{Linear, Module};
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use tracing::info;
+
+/// `adapter_config.json` fields this module uses.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoraConfig {
+    /// Rank of the update matrices.
+    pub r: usize,
+    pub lora_alpha: f64,
+    #[serde(default)]
+    pub target_modules: Vec<String>,
+    #[serde(default)]
+    pub use_rslora: bool,
+    /// HuggingFace ID of the model the adapter was trained on.
+    #[serde(default)]
+    pub base_model_name_or_path: Option<String>,
+}
+
+impl LoraConfig {
+    /// Factor applied to `B (A x)`.
+    pub fn scale(&self) -> f64 {
+        if self.use_rslora {
+            self.lora_alpha / (self.r as f64).sqrt()
+        } else {
+            self.lora_alpha / self.r as f64
+        }
+    }
+}
+
+/// Projections inside a transformer block that an adapter can target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum LoraTarget {
+    Q,
+    K,
+    V,
+    O,
+    Gate,
+    Up,
+    Down,
+}
+
+impl LoraTarget {
+    /// Parse the module path below `model.layers.{i}`.
+    fn from_module(path: &str) -> Option<Self> {
+        Some(match path {
+            "self_attn.q_proj" => Self::Q,
+            "self_attn.k_proj" => Self::K,
+            "self_attn.v_proj" => Self::V,
+            "self_attn.o_proj" => Self::O,
+            "mlp.gate_proj" => Self::Gate,
+            "mlp.up_proj" => Self::Up,
+            "mlp.down_proj" => Self::Down,
+            _ => return None,
+        })
+    }
+
+    /// `(in_features, out_features)` of the base projection.
+    fn dims(self, cfg: &ShardConfig) -> (usize, usize) {
+        let h = cfg.hidden_dim;
+        let kv = cfg.num_kv_heads * cfg.head_dim;
+        let inter = cfg.intermediate_dim;
+        match self {
+            Self::Q | Self::O => (h, h),
+            Self::K | Self::V => (h, kv),
+            Self::Gate | Self::Up => (h, inter),
+            Self::Down => (inter, h),
+        }
+    }
+}
+
+/// One `A` (`[r, in]`) / `B` (`[out, r]`) pair.
+struct LoraPair {
+    a: Linear,
+    b: Linear,
+}
+
+/// The adapter weights for one transformer block.
+pub(crate) struct BlockLora {
+    modules: HashMap<LoraTarget, LoraPair>,
+    scale: f64,
+}
+
+impl BlockLora {
+    /// Add the low-rank update for `target` to `base`, the output of the base
+    /// projection applied to `x`.
+    pub(crate) fn apply(
+        &self,
+        target: LoraTarget,
+        x: &Tensor,
+        base: Tensor,
+    ) -> InferenceResult<Tensor> {
+        let Some(pair) = self.modules.get(&target) else {
+            return Ok(base);
+        };
+        let delta = pair.b.forward(&pair.a.forward(x)?)?;
+        Ok((base + (delta * self.scale)?)?)
+    }
+}
+
+/// A loaded LoRA adapter, restricted to the layers of one shard.
+pub struct LoraAdapter {
+    pub name: String,
+    pub config: LoraConfig,
+    /// Keyed by global layer index.
+    layers: HashMap<usize, BlockLora>,
+}
+
+impl LoraAdapter {
+    /// Load a PEFT adapter directory for layers `[start_block, end_block)`.
+    pub fn load(
+        name: &str,
+        dir: &Path,
+        cfg: &ShardConfig,
+        start_block: usize,
+        end_block: usize,
+        device: &Device,
+    ) -> InferenceResult<Self> {
+        let config_path = dir.join("adapter_config.json");
+        let config_str = std::fs::read_to_string(&config_path).map_err(|e| {
+            InferenceError::ModelLoadError(format!("Cannot read {}: {e}", config_path.display()))
+        })?;
+        let config: LoraConfig = serde_json::from_str(&config_str).map_err(|e| {
+            InferenceError::ModelLoadError(format!("Cannot parse adapter_config.json: {e}"))
+        })?;
+
+        let weights_path = dir.join("adapter_model.safetensors");
+        let tensors = candle_core::safetensors::load(&weights_path, device).map_err(|e| {
+            InferenceError::ModelLoadError(format!("{}: {e}", weights_path.display()))
+        })?;
+
+        let adapter = Self::from_tensors(name, config, tensors, cfg, start_block, end_block)?;
+        info!(
+            "Loaded LoRA adapter '{name}' (r={}, alpha={}) for {} layer(s)",
+            adapter.config.r,
+            adapter.config.lora_alpha,
+            adapter.layers.len()
+        );
+        Ok(adapter)
+    }
+
+    /// Build an adapter from PEFT-named tensors, keeping layers in
+    /// `[start_block, end_block)` and converting them to the shard's dtype.
+    pub fn from_tensors(
+        name: &str,
+        config: LoraConfig,
+        tensors: HashMap<String, Tensor>,
+        cfg: &ShardConfig,
+        start_block: usize,
+        end_block: usize,
+    ) -> InferenceResult<Self> {
+        if config.r == 0 {
+            return Err(InferenceError::ModelLoadError(format!(
+                "adapter '{name}' has rank 0"
+            )));
+        }
+
+        let mut halves: HashMap<(usize, LoraTarget), (Option<Tensor>, Option<Tensor>)> =
+            HashMap::new();
+        for (key, tensor) in tensors {
+            let Some((layer, target, is_a)) = parse_key(&key) else {
+                continue;
+            };
+            if !(start_block..end_block).contains(&layer) {
+                continue;
+            }
+            let tensor = tensor.to_dtype(cfg.dtype)?;
+            let slot = halves.entry((layer, target)).or_default();
+            if is_a {
+                slot.0 = Some(tensor);
+            } else {
+                slot.1 = Some(tensor);
+            }
+        }
+
+        let scale = config.scale();
+        let mut layers: HashMap<usize, BlockLora> = HashMap::new();
+        for ((layer, target), pair) in halves {
+            let (Some(a), Some(b)) = pair else {
+                return Err(InferenceError::ModelLoadError(format!(
+                    "adapter '{name}': layer {layer} {target:?} is missing lora_A or lora_B"
+                )));
+            };
+            let (in_dim, out_dim) = target.dims(cfg);
+            if a.dims() != [config.r, in_dim] || b.dims() != [out_dim, config.r] {
+                return Err(InferenceError::ModelLoadError(format!(
+                    "adapter '{name}': layer {layer} {target:?} has shapes {:?}/{:?}, \
+                     expected [{}, {in_dim}]/[{out_dim}, {}]",
+                    a.dims(),
+                    b.dims(),
+                    config.r,
+                    config.r
+                )));
+            }
+            layers
+                .entry(layer)
+                .or_insert_with(|| BlockLora {
+                    modules: HashMap::new(),
+                    scale,
+                })
+                .modules
+                .insert(
+                    target,
+                    LoraPair {
+                        a: Linear::new(a, None),
+                        b: Linear::new(b, None),
+                    },
+                );
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            config,
+            layers,
+        })
+    }
+
+    /// Adapter weights for global layer `idx`, if the adapter touches it.
+    pub(crate) fn block(&self, idx: usize) -> Option<&BlockLora> {
+        self.layers.get(&idx)
+    }
+
+    /// Take over the layers of `other`, a part of the same adapter loaded
+    /// for other blocks (on another device).
+    pub(crate) fn absorb(&mut self, other: LoraAdapter) {
+        self.layers.extend(other.layers);
+    }
+
+    /// Number of layers in the shard this adapter modifies.
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+}
+
+/// Split a PEFT tensor name into `(layer, target, is_lora_a)`.
+///
+/// Accepts both `…q_proj.lora_A.weight` and the multi-adapter form
+/// `…q_proj.lora_A.default.weight`.
+fn parse_key(key: &str) -> Option<(usize, LoraTarget, bool)> {
+    let rest = &key[key.find("layers.")? + "layers.".len()..];
+    let (layer, rest) = rest.split_once('.')?;
+    let layer = layer.parse().ok()?;
+    let (module, is_a, suffix) = match rest.split_once(".lora_A.") {
+        Some((m, s)) => (m, true, s),
+        None => {
+            let (m, s) = rest.split_once(".lora_B.")?;
+            (m, false, s)
+        }
+    };
+    if !suffix.ends_with("weight") {
+        return None;
+    }
+    Some((layer, LoraTarget::from_module(module)?, is_a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::DType;
+
+    fn test_cfg() -> ShardConfig {
+        ShardConfig {
+            num_total_blocks: 4,
+            hidden_dim: 4,
+            num_heads: 2,
+            num_kv_heads: 1,
+            head_dim: 2,
+            intermediate_dim: 8,
+            vocab_size: 16,
+            rope_theta: 10000.0,
+            max_seq_len: 16,
+            rms_norm_eps: 1e-5,
+            dtype: DType::F32,
+        }
+    }
+
+    fn config(r: usize, alpha: f64) -> LoraConfig {
+        LoraConfig {
+            r,
+            lora_alpha: alpha,
+            target_modules: vec!["q_proj".into()],
+            use_rslora: false,
+            base_model_name_or_path: None,
+        }
+    }
+
+    fn pair(layer: usize, module: &str, a: Tensor, b: Tensor) -> [(String, Tensor); 2] {
+        let base = format!("base_model.model.model.layers.{layer}.{module}");
+        [
+            (format!("{base}.lora_A.weight"), a),
+            (format!("{base}.lora_B.weight"), b),
+        ]
+    }
+
+    #[test]
+    fn parses_peft_tensor_names() {
+        assert_eq!(
+            parse_key("base_model.model.model.layers.3.self_attn.q_proj.lora_A.weight"),
+            Some((3, LoraTarget::Q, true))
+        );
+        assert_eq!(
+            parse_key("base_model.model.model.layers.12.mlp.down_proj.lora_B.default.weight"),
+            Some((12, LoraTarget::Down, false))
+        );
+        assert_eq!(
+            parse_key("base_model.model.model.layers.0.self_attn.rotary_emb.lora_A.weight"),
+            None
+        );
+        assert_eq!(parse_key("base_model.model.lm_head.weight"), None);
+    }
+
+    #[test]
+    fn scale_follows_alpha_over_rank() {
+        assert_eq!(config(8, 16.0).scale(), 2.0);
+        let rs = LoraConfig {
+            use_rslora: true,
+            ..config(4, 8.0)
+        };
+        assert_eq!(rs.scale(), 4.0);
+    }
+
+    #[test]
+    fn adds_scaled_low_rank_update() {
+        let cfg = test_cfg();
+        let device = Device::Cpu;
+        // A picks x[0]; B writes it to output 1.
+        let a = Tensor::new(&[[1f32, 0., 0., 0.]], &device).unwrap();
+        let b = Tensor::new(&[[0f32], [1.], [0.], [0.]], &device).unwrap();
+        let tensors = pair(1, "self_attn.q_proj", a, b).into_iter().collect();
+        let adapter =
+            LoraAdapter::from_tensors("test", config(1, 2.0), tensors, &cfg, 0, 4).unwrap();
+        assert_eq!(adapter.num_layers(), 1);
+
+        let x = Tensor::new(&[[[3f32, 5., 7., 9.]]], &device).unwrap();
+        let base = Tensor::zeros((1, 1, 4), DType::F32, &device).unwrap();
+        let block = adapter.block(1).unwrap();
+        let out = block.apply(LoraTarget::Q, &x, base.clone()).unwrap();
+        assert_eq!(
+            out.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+            [0., 6., 0., 0.]
+        );
+        // Untargeted projections pass through.
+        let out = block.apply(LoraTarget::V, &x, base).unwrap();
+        assert_eq!(
+            out.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+            [0.; 4]
+        );
+        assert!(adapter.block(0).is_none());
+    }
+
+    #[test]
+    fn keeps_only_shard_layers_and_checks_shapes() {
+        let cfg = test_cfg();
+        let device = Device::Cpu;
+        let a = || Tensor::zeros((2, 4), DType::F32, &device).unwrap();
+        let b = || Tensor::zeros((4, 2), DType::F32, &device).unwrap();
+        let tensors: HashMap<_, _> = (0..4)
+            .flat_map(|l| pair(l, "self_attn.o_proj", a(), b()))
+            .collect();
+        let adapter =
+            LoraAdapter::from_tensors("t", config(2, 2.0), tensors.clone(), &cfg, 2, 4).unwrap();
+        assert_eq!(adapter.num_layers(), 2);
+        assert!(adapter.block(1).is_none() && adapter.block(3).is_some());
+
+        // k_proj outputs kv_dim (2), not hidden (4).
+        let bad: HashMap<_, _> = pair(0, "self_attn.k_proj", a(), b()).into_iter().collect();
+        assert!(LoraAdapter::from_tensors("t", config(2, 2.0), bad, &cfg, 0, 4).is_err());
+
+        let mut half = tensors;
+        half.retain(|k, _| !k.contains("layers.2.") || k.contains("lora_A"));
+        assert!(LoraAdapter::from_tensors("t", config(2, 2.0), half, &cfg, 2, 4).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! Sparse mixture-of-experts feed-forward (Mixtral) with expert offload.
+//!
+//! Each MoE layer scores every token against its experts, sends the token to
+//! the `top_k` best, and sums their outputs weighted by the renormalised
+//! router softmax. A node does not have to hold every expert: the ones
+//! outside [`ExpertOffload::local`] are never read from the GGUF file and are
+//! run through [`RemoteExperts`] instead, which `kwaai-distributed`
+//! implements on top of its expert registry and P2P calls.
+
+use candle_core::{
+    quantized::{ggml_file, gguf_file, QMatMul, QTensor},
+    DType, Device, Module, Result, Tensor,
+};
+use std::collections::BTreeSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Runs experts this node did not load.
+pub trait RemoteExperts: Send + Sync {
+    /// Expert `expert` of MoE layer `layer` applied to `x`
+    /// (`[n_tokens, hidden]`); returns the same shape.
+    fn forward(&self, layer: usize, expert: usize, x: &Tensor) -> Result<Tensor>;
+}
+
+/// Which experts to load locally and where the others run.
+#[derive(Clone)]
+pub struct ExpertOffload {
+    /// Expert indices to load, the same in every layer; `None` loads all.
+    pub local: Option<BTreeSet<usize>>,
+    pub remote: Arc<dyn RemoteExperts>,
+}
+
+impl ExpertOffload {
+    pub fn is_local(&self, expert: usize) -> bool {
+        self.local.as_ref().is_none_or(|l| l.contains(&expert))
+    }
+}
+
+impl std::fmt::Debug for ExpertOffload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExpertOffload")
+            .field("local", &self.local)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The `top_k` largest entries of one token's router probabilities, with
+/// their weights renormalised to sum to one, best first.
+pub fn top_k(probs: &[f32], top_k: usize) -> Vec<(usize, f32)> {
+    let mut order: Vec<usize> = (0..probs.len()).collect();
+    order.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+    order.truncate(top_k);
+    let sum: f32 = order.iter().map(|&e| probs[e]).sum();
+    order
+        .into_iter()
+        .map(|e| (e, if sum > 0.0 { probs[e] / sum } else { 0.0 }))
+        .collect()
+}
+
+/// One expert: a SwiGLU feed-forward block.
+#[derive(Debug, Clone)]
+pub struct ExpertFfn {
+    gate: QMatMul,
+    up: QMatMul,
+    down: QMatMul,
+}
+
+impl ExpertFfn {
+    /// Load expert `expert` of block `layer`, from either the per-expert
+    /// tensors of older conversions (`blk.N.ffn_gate.E.weight`) or the
+    /// stacked ones llama.cpp writes now (`blk.N.ffn_gate_exps.weight`).
+    /// Only this expert's bytes are read.
+    pub fn load<R: Read + Seek>(
+        ct: &gguf_file::Content,
+        reader: &mut R,
+        layer: usize,
+        expert: usize,
+        device: &Device,
+    ) -> Result<Self> {
+        let mut load = |name: &str| -> Result<QMatMul> {
+            QMatMul::from_qtensor(expert_tensor(ct, reader, layer, name, expert, device)?)
+        };
+        Ok(Self {
+            gate: load("ffn_gate")?,
+            up: load("ffn_up")?,
+            down: load("ffn_down")?,
+        })
+    }
+
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let gate = candle_nn::ops::silu(&self.gate.forward(x)?)?;
+        self.down.forward(&(gate * self.up.forward(x)?)?)
+    }
+}
+
+fn expert_tensor<R: Read + Seek>(
+    ct: &gguf_file::Content,
+    reader: &mut R,
+    layer: usize,
+    name: &str,
+    expert: usize,
+    device: &Device,
+) -> Result<QTensor> {
+    let single = format!("blk.{layer}.{name}.{expert}.weight");
+    if ct.tensor_infos.contains_key(&single) {
+        return ct.tensor(reader, &single, device);
+    }
+    let stacked = format!("blk.{layer}.{name}_exps.weight");
+    let Some(info) = ct.tensor_infos.get(&stacked) else {
+        candle_core::bail!("cannot find tensor info for {single} or {stacked}");
+    };
+    let Some((&n_expert, dims)) = info.shape.dims().split_first() else {
+        candle_core::bail!("{stacked} has no dimensions");
+    };
+    if expert >= n_expert {
+        candle_core::bail!("{stacked} holds {n_expert} experts, not {}", expert + 1);
+    }
+    let elems: usize = dims.iter().product();
+    let block_size = info.ggml_dtype.block_size();
+    if !elems.is_multiple_of(block_size) {
+        candle_core::bail!(
+            "{stacked}: {elems} elements per expert is not a whole number of blocks"
+        );
+    }
+    let bytes = elems / block_size * info.ggml_dtype.type_size();
+    let mut raw = vec![0u8; bytes];
+    reader.seek(SeekFrom::Start(
+        ct.tensor_data_offset + info.offset + (expert * bytes) as u64,
+    ))?;
+    reader.read_exact(&mut raw)?;
+    ggml_file::qtensor_from_ggml(info.ggml_dtype, &raw, dims.to_vec(), device)
+}
+
+/// A sparse MoE feed-forward layer.
+#[derive(Clone)]
+pub struct SparseMoe {
+    layer: usize,
+    router: QMatMul,
+    /// `None` for experts that run remotely.
+    experts: Vec<Option<ExpertFfn>>,
+    top_k: usize,
+    remote: Option<Arc<dyn RemoteExperts>>,
+}
+
+impl std::fmt::Debug for SparseMoe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SparseMoe")
+            .field("layer", &self.layer)
+            .field("experts", &self.experts.len())
+            .field("local", &self.local_experts())
+            .field("top_k", &self.top_k)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SparseMoe {
+    /// Load block `layer`'s router and the experts `offload` keeps local
+    /// (all of them without offload).
+    pub fn load<R: Read + Seek>(
+        ct: &gguf_file::Content,
+        reader: &mut R,
+        layer: usize,
+        n_expert: usize,
+        top_k: usize,
+        offload: Option<&ExpertOffload>,
+        device: &Device,
+    ) -> Result<Self> {
+        let router = ct.tensor(reader, &format!("blk.{layer}.ffn_gate_inp.weight"), device)?;
+        let experts = (0..n_expert)
+            .map(|e| {
+                if offload.is_some_and(|o| !o.is_local(e)) {
+                    Ok(None)
+                } else {
+                    ExpertFfn::load(ct, reader, layer, e, device).map(Some)
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            layer,
+            router: QMatMul::from_qtensor(router)?,
+            experts,
+            top_k,
+            remote: offload.map(|o| o.remote.clone()),
+        })
+    }
+
+    /// Indices of the experts held in memory.
+    pub fn local_experts(&self) -> Vec<usize> {
+        (0..self.experts.len())
+            .filter(|&e| self.experts[e].is_some())
+            .collect()
+    }
+
+    /// `x` is `[batch, seq_len, hidden]`.
+    ///
+    /// A remote expert that fails is left out: its tokens are re-weighted
+    /// over their other experts. Only a token with no expert left fails the
+    /// pass.
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let (b, seq_len, hidden) = x.dims3()?;
+        let xs = x.reshape(((), hidden))?;
+        let probs = candle_nn::ops::softmax_last_dim(&self.router.forward(&xs)?)?
+            .to_dtype(DType::F32)?
+            .to_vec2::<f32>()?;
+
+        // Tokens and routing weights per expert.
+        let mut rows = vec![Vec::new(); self.experts.len()];
+        let mut weights = vec![Vec::new(); self.experts.len()];
+        for (row, p) in probs.iter().enumerate() {
+            for (e, w) in top_k(p, self.top_k) {
+                rows[e].push(row as u32);
+                weights[e].push(w);
+            }
+        }
+
+        let mut answered = Vec::new();
+        let mut kept_weight = vec![0f32; probs.len()];
+        for (e, rows) in rows.iter().enumerate() {
+            if rows.is_empty() {
+                continue;
+            }
+            let row_ids = Tensor::new(rows.as_slice(), xs.device())?;
+            let input = xs.index_select(&row_ids, 0)?;
+            let out = match (&self.experts[e], &self.remote) {
+                (Some(ffn), _) => ffn.forward(&input)?,
+                (None, Some(remote)) => match remote.forward(self.layer, e, &input) {
+                    Ok(out) => out,
+                    Err(err) => {
+                        warn!(
+                            "Layer {} expert {e} unavailable, re-weighting: {err}",
+                            self.layer
+                        );
+                        continue;
+                    }
+                },
+                (None, None) => candle_core::bail!(
+                    "expert {e} of layer {} is not loaded and there is no remote to run it",
+                    self.layer
+                ),
+            };
+            for (&row, &w) in rows.iter().zip(&weights[e]) {
+                kept_weight[row as usize] += w;
+            }
+            answered.push((e, row_ids, out));
+        }
+        if let Some(row) = kept_weight.iter().position(|&w| w <= 0.0) {
+            candle_core::bail!("no expert of layer {} answered for token {row}", self.layer);
+        }
+
+        let mut ys = xs.zeros_like()?;
+        for (e, row_ids, out) in answered {
+            let w: Vec<f32> = rows[e]
+                .iter()
+                .zip(&weights[e])
+                .map(|(&row, &w)| w / kept_weight[row as usize])
+                .collect();
+            let w = Tensor::new(w.as_slice(), xs.device())?
+                .to_dtype(out.dtype())?
+                .reshape(((), 1))?;
+            ys = ys.index_add(&row_ids, &out.broadcast_mul(&w)?, 0)?;
+        }
+        ys.reshape((b, seq_len, hidden))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::quantized::GgmlDType;
+    use gguf_file::Value;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    fn qrand(shape: &[usize]) -> QTensor {
+        let t = Tensor::randn(0f32, 0.5, shape, &Device::Cpu).unwrap();
+        QTensor::quantize(&t, GgmlDType::F32).unwrap()
+    }
+
+    /// A one-layer MoE GGUF, with stacked or per-expert expert tensors.
+    fn moe_file(stacked: bool) -> Cursor<Vec<u8>> {
+        let (n_expert, hidden, ffn) = (4, 8, 6);
+        let mut tensors = vec![(
+            "blk.0.ffn_gate_inp.weight".to_string(),
+            qrand(&[n_expert, hidden]),
+        )];
+        for (name, shape) in [
+            ("ffn_gate", [ffn, hidden]),
+            ("ffn_up", [ffn, hidden]),
+            ("ffn_down", [hidden, ffn]),
+        ] {
+            if stacked {
+                tensors.push((
+                    format!("blk.0.{name}_exps.weight"),
+                    qrand(&[n_expert, shape[0], shape[1]]),
+                ));
+            } else {
+                for e in 0..n_expert {
+                    tensors.push((format!("blk.0.{name}.{e}.weight"), qrand(&shape)));
+                }
+            }
+        }
+        let arch = Value::String("llama".into());
+        let ts: Vec<(&str, &QTensor)> = tensors.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let mut buf = Cursor::new(Vec::new());
+        gguf_file::write(&mut buf, &[("general.architecture", &arch)], &ts).unwrap();
+        buf.set_position(0);
+        buf
+    }
+
+    /// Runs "remote" experts from a full local copy, recording each call.
+    struct Loopback {
+        full: SparseMoe,
+        calls: Mutex<Vec<(usize, usize)>>,
+    }
+
+    impl RemoteExperts for Loopback {
+        fn forward(&self, layer: usize, expert: usize, x: &Tensor) -> Result<Tensor> {
+            self.calls.lock().unwrap().push((layer, expert));
+            self.full.experts[expert].as_ref().unwrap().forward(x)
+        }
+    }
+
+    /// A peer that never answers.
+    struct Unreachable;
+
+    impl RemoteExperts for Unreachable {
+        fn forward(&self, _layer: usize, _expert: usize, _x: &Tensor) -> Result<Tensor> {
+            candle_core::bail!("timed out")
+        }
+    }
+
+    #[test]
+    fn top_k_renormalises_the_best_experts() {
+        let picked = top_k(&[0.1, 0.4, 0.2, 0.3], 2);
+        assert_eq!(picked.iter().map(|p| p.0).collect::<Vec<_>>(), vec![1, 3]);
+        assert!((picked[0].1 - 4.0 / 7.0).abs() < 1e-6);
+        assert!((picked[0].1 + picked[1].1 - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stacked_experts_load_one_slice() {
+        let mut buf = moe_file(true);
+        let ct = gguf_file::Content::read(&mut buf).unwrap();
+        let all = ct
+            .tensor(&mut buf, "blk.0.ffn_up_exps.weight", &Device::Cpu)
+            .unwrap();
+        let all = all.dequantize(&Device::Cpu).unwrap();
+        let one = expert_tensor(&ct, &mut buf, 0, "ffn_up", 2, &Device::Cpu).unwrap();
+        let one = one.dequantize(&Device::Cpu).unwrap();
+        let diff = (all.get(2).unwrap() - one)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .sum_all()
+            .unwrap();
+        assert_eq!(diff.to_scalar::<f32>().unwrap(), 0.0);
+        assert!(expert_tensor(&ct, &mut buf, 0, "ffn_up", 4, &Device::Cpu).is_err());
+    }
+
+    #[test]
+    fn offloaded_experts_run_remotely_with_the_same_result() {
+        for stacked in [false, true] {
+            let mut buf = moe_file(stacked);
+            let ct = gguf_file::Content::read(&mut buf).unwrap();
+            let dev = Device::Cpu;
+            let full = SparseMoe::load(&ct, &mut buf, 0, 4, 2, None, &dev).unwrap();
+            let remote = Arc::new(Loopback {
+                full: full.clone(),
+                calls: Mutex::new(Vec::new()),
+            });
+            let offload = ExpertOffload {
+                local: Some(BTreeSet::from([0, 1])),
+                remote: remote.clone(),
+            };
+            let split = SparseMoe::load(&ct, &mut buf, 0, 4, 2, Some(&offload), &dev).unwrap();
+            assert_eq!(split.local_experts(), vec![0, 1]);
+
+            let x = Tensor::randn(0f32, 1.0, (1, 16, 8), &dev).unwrap();
+            let a = full.forward(&x).unwrap();
+            let b = split.forward(&x).unwrap();
+            let diff = (a - b).unwrap().abs().unwrap().max_all().unwrap();
+            assert!(diff.to_scalar::<f32>().unwrap() < 1e-5);
+            let calls = remote.calls.lock().unwrap();
+            assert!(!calls.is_empty());
+            assert!(calls.iter().all(|&(l, e)| l == 0 && e >= 2));
+
+            // Without a remote, a missing expert is an error, not a zero.
+            let mut alone = split.clone();
+            alone.remote = None;
+            assert!(alone.forward(&x).is_err());
+        }
+    }
+
+    #[test]
+    fn failed_remote_experts_are_reweighted_away() {
+        let mut buf = moe_file(true);
+        let ct = gguf_file::Content::read(&mut buf).unwrap();
+        let dev = Device::Cpu;
+        let offload = |local: &[usize]| ExpertOffload {
+            local: Some(local.iter().copied().collect()),
+            remote: Arc::new(Unreachable),
+        };
+        let full = SparseMoe::load(&ct, &mut buf, 0, 4, 2, None, &dev).unwrap();
+        let x = Tensor::randn(0f32, 1.0, (1, 6, 8), &dev).unwrap();
+        let xs = x.reshape((6, 8)).unwrap();
+
+        // Route every token to all four experts; only 0 and 1 answer, so
+        // each token gets their outputs weighted p0 : p1.
+        let mut split =
+            SparseMoe::load(&ct, &mut buf, 0, 4, 2, Some(&offload(&[0, 1])), &dev).unwrap();
+        split.top_k = 4;
+        let y = split.forward(&x).unwrap().reshape((6, 8)).unwrap();
+        let probs = candle_nn::ops::softmax_last_dim(&full.router.forward(&xs).unwrap())
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        let e0 = full.experts[0].as_ref().unwrap().forward(&xs).unwrap();
+        let e1 = full.experts[1].as_ref().unwrap().forward(&xs).unwrap();
+        for (r, p) in probs.iter().enumerate() {
+            let (w0, w1) = (p[0] / (p[0] + p[1]), p[1] / (p[0] + p[1]));
+            let want = ((e0.get(r).unwrap() * w0 as f64).unwrap()
+                + (e1.get(r).unwrap() * w1 as f64).unwrap())
+            .unwrap();
+            let diff = (y.get(r).unwrap() - want)
+                .unwrap()
+                .abs()
+                .unwrap()
+                .max_all()
+                .unwrap();
+            assert!(diff.to_scalar::<f32>().unwrap() < 1e-5);
+        }
+
+        // A token none of whose experts answers fails the pass.
+        let none = SparseMoe::load(&ct, &mut buf, 0, 4, 2, Some(&offload(&[])), &dev).unwrap();
+        assert!(none.forward(&x).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 //! Distributed block sharding — Petals-style inter-node transformer inference.
 //!
 //! A [`TransformerShard`] holds a contiguous range of transformer blocks from a
-//! SafeTensors Llama model.  Three forward modes cover the roles a node can play:
+//! SafeTensors (or Llama GGUF) model.  Three forward modes cover the roles a node can play:
 //!
 //! * **First node** (`start_block == 0`): embeds token IDs, runs its blocks, returns
 //!   hidden states `[1, seq_len, hidden_dim]`.
@@ -11,16 +11,30 @@
 //!
 //! KV-cache is managed per session (`session_id: u64`).  Sessions expire after 600 s of
 //! inactivity; call [`TransformerShard::gc_sessions`] periodically.
+//!
+//! A shard may be spread over several local devices with
+//! [`TransformerShard::load_mapped`] (see [`DeviceMap`]): each block lives on one
+//! device and hidden states hop to the next device at range boundaries.
 
 use crate::{
+    device_map::DeviceMap,
     error::{InferenceError, InferenceResult},
+    lazy_gguf::LazyGguf,
+    loader::{meta_str, meta_usize},
+    lora::{BlockLora, LoraAdapter, LoraTarget},
     tokenizer::BpeTokenizer,
+    ModelConfig,
 };
-use candle_core::{DType, Device, Tensor};
+use candle_core::{quantized::QTensor, DType, Device, Tensor};
 #[cfg(feature = "flash-attn")]
 use candle_flash_attn;
 use candle_nn::{Module, VarBuilder};
-use std::{collections::HashMap, path::Path, sync::Mutex, time::Instant};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
 use tracing::{debug, info};
 
 // ── Model hyperparameters ─────────────────────────────────────────────────────
@@ -175,6 +189,20 @@ fn repeat_kv(t: &Tensor, n_rep: usize) -> InferenceResult<Tensor> {
 
 // ── Single transformer block ──────────────────────────────────────────────────
 
+/// Apply `linear` to `x`, plus the session adapter's update for `target`.
+fn project(
+    linear: &candle_nn::Linear,
+    x: &Tensor,
+    lora: Option<&BlockLora>,
+    target: LoraTarget,
+) -> InferenceResult<Tensor> {
+    let out = linear.forward(x).map_err(InferenceError::from)?;
+    match lora {
+        Some(l) => l.apply(target, x, out),
+        None => Ok(out),
+    }
+}
+
 pub(crate) struct ShardBlock {
     input_layernorm: candle_nn::RmsNorm,
     q_proj: candle_nn::Linear,
@@ -238,12 +266,14 @@ impl ShardBlock {
     /// * `seq_pos` — global sequence position of the first token in `x`
     /// * `kv`      — mutable per-block KV-cache (initialised to `None` for a new session)
     /// * `rope`    — shared precomputed RoPE tables
+    /// * `lora`    — this block's weights in the session's LoRA adapter, if any
     pub(crate) fn forward(
         &self,
         x: &Tensor,
         seq_pos: usize,
         kv: &mut Option<(Tensor, Tensor)>,
         rope: &RopeCache,
+        lora: Option<&BlockLora>,
     ) -> InferenceResult<Tensor> {
         let (b, s, _h) = x.dims3().map_err(InferenceError::from)?;
         let n_h = self.cfg.num_heads;
@@ -260,9 +290,9 @@ impl ShardBlock {
             .map_err(InferenceError::from)?;
 
         // Project to Q, K, V
-        let q = self.q_proj.forward(&normed).map_err(InferenceError::from)?; // [b, s, h]
-        let k = self.k_proj.forward(&normed).map_err(InferenceError::from)?; // [b, s, kv_dim]
-        let v = self.v_proj.forward(&normed).map_err(InferenceError::from)?;
+        let q = project(&self.q_proj, &normed, lora, LoraTarget::Q)?; // [b, s, h]
+        let k = project(&self.k_proj, &normed, lora, LoraTarget::K)?; // [b, s, kv_dim]
+        let v = project(&self.v_proj, &normed, lora, LoraTarget::V)?;
 
         // Reshape to multi-head: [b, n_heads, s, head_dim]
         let q = q
@@ -358,10 +388,7 @@ impl ShardBlock {
             .map_err(InferenceError::from)?
             .reshape((b, s, h))
             .map_err(InferenceError::from)?;
-        let attn_out = self
-            .o_proj
-            .forward(&attn_out)
-            .map_err(InferenceError::from)?;
+        let attn_out = project(&self.o_proj, &attn_out, lora, LoraTarget::O)?;
         let x = (&residual + &attn_out).map_err(InferenceError::from)?;
         let t_attn = t3.elapsed();
 
@@ -373,18 +400,12 @@ impl ShardBlock {
             .forward(&x)
             .map_err(InferenceError::from)?;
 
-        let gate = self
-            .gate_proj
-            .forward(&normed)
-            .map_err(InferenceError::from)?;
-        let up = self
-            .up_proj
-            .forward(&normed)
-            .map_err(InferenceError::from)?;
+        let gate = project(&self.gate_proj, &normed, lora, LoraTarget::Gate)?;
+        let up = project(&self.up_proj, &normed, lora, LoraTarget::Up)?;
         // SwiGLU: silu(gate) * up
         let gate = candle_nn::ops::silu(&gate).map_err(InferenceError::from)?;
         let ff = (gate * up).map_err(InferenceError::from)?;
-        let ff = self.down_proj.forward(&ff).map_err(InferenceError::from)?;
+        let ff = project(&self.down_proj, &ff, lora, LoraTarget::Down)?;
         let result = (&residual + &ff).map_err(InferenceError::from)?;
         let t_mlp = t4.elapsed();
 
@@ -410,6 +431,8 @@ struct Session {
     /// One `Option<(k_cache, v_cache)>` per block in the shard.
     kv: Vec<Option<(Tensor, Tensor)>>,
     last_access: Instant,
+    /// LoRA adapter applied to every forward pass of this session.
+    adapter: Option<Arc<LoraAdapter>>,
 }
 
 impl Session {
@@ -417,12 +440,144 @@ impl Session {
         Self {
             kv: vec![None; num_blocks],
             last_access: Instant::now(),
+            adapter: None,
+        }
+    }
+
+    /// Keep only the first `len` cached positions of every block.
+    fn truncate(&mut self, len: usize) -> InferenceResult<()> {
+        for kv in &mut self.kv {
+            let Some((k, v)) = kv.as_ref() else { continue };
+            if k.dim(2).map_err(InferenceError::from)? <= len {
+                continue;
+            }
+            *kv = if len == 0 {
+                None
+            } else {
+                Some((
+                    k.narrow(2, 0, len).map_err(InferenceError::from)?,
+                    v.narrow(2, 0, len).map_err(InferenceError::from)?,
+                ))
+            };
+        }
+        Ok(())
+    }
+}
+
+// ── Throughput benchmark ──────────────────────────────────────────────────────
+
+/// Forward-pass rates of a shard's blocks, measured by
+/// [`TransformerShard::benchmark`].
+///
+/// Both rates are per block, following the Petals convention: a span of `n`
+/// blocks processes `rate / n` tokens per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockThroughput {
+    /// Single-token decode steps per second through one block.
+    pub inference_rps: f64,
+    /// Prompt tokens per second through one block during prefill.
+    pub forward_rps: f64,
+}
+
+/// Run random hidden states through `blocks` with a throwaway KV cache:
+/// one prefill of `prefill_tokens`, then `decode_steps` single-token steps.
+///
+/// Each block is paired with the device and RoPE tables it runs with.
+fn benchmark_blocks(
+    blocks: &[(&ShardBlock, &Device, &RopeCache)],
+    cfg: &ShardConfig,
+    decode_steps: usize,
+    prefill_tokens: usize,
+) -> InferenceResult<BlockThroughput> {
+    let Some(&(_, device, _)) = blocks.first() else {
+        return Err(InferenceError::InvalidInput(
+            "benchmark needs at least one block".to_string(),
+        ));
+    };
+    // Leave room in the RoPE table for the decode steps that follow.
+    let prefill_tokens =
+        prefill_tokens.clamp(1, cfg.max_seq_len.saturating_sub(decode_steps + 1).max(1));
+    let decode_steps = decode_steps.max(1);
+    let hidden = |len: usize| {
+        Tensor::randn(0f32, 1f32, (1, len, cfg.hidden_dim), device)
+            .and_then(|t| t.to_dtype(cfg.dtype))
+            .map_err(InferenceError::from)
+    };
+    // Reading a scalar back forces asynchronous backends (CUDA, Metal) to
+    // finish the queued work, so the timings cover the actual compute.
+    let sync = |t: &Tensor| {
+        t.sum_all()
+            .and_then(|t| t.to_dtype(DType::F32))
+            .and_then(|t| t.to_scalar::<f32>())
+            .map_err(InferenceError::from)
+    };
+    let run = |x: Tensor, seq_pos: usize, kv: &mut [Option<(Tensor, Tensor)>]| {
+        let mut x = x;
+        for (&(block, device, rope), kv) in blocks.iter().zip(kv.iter_mut()) {
+            x = to_block_device(x, device)?;
+            x = block.forward(&x, seq_pos, kv, rope, None)?;
         }
+        sync(&x)
+    };
+
+    // Warm-up: first use of a kernel pays for compilation and allocation.
+    run(hidden(1)?, 0, &mut vec![None; blocks.len()])?;
+
+    let mut kv = vec![None; blocks.len()];
+    let x = hidden(prefill_tokens)?;
+    let t = Instant::now();
+    run(x, 0, &mut kv)?;
+    let prefill_secs = t.elapsed().as_secs_f64();
+
+    let mut decode_secs = 0.0;
+    for step in 0..decode_steps {
+        let x = hidden(1)?;
+        let t = Instant::now();
+        run(x, prefill_tokens + step, &mut kv)?;
+        decode_secs += t.elapsed().as_secs_f64();
+    }
+
+    let n = blocks.len() as f64;
+    Ok(BlockThroughput {
+        inference_rps: decode_steps as f64 * n / decode_secs.max(1e-9),
+        forward_rps: prefill_tokens as f64 * n / prefill_secs.max(1e-9),
+    })
+}
+
+/// Move hidden states to the device of the next block, if they are elsewhere.
+fn to_block_device(x: Tensor, device: &Device) -> InferenceResult<Tensor> {
+    if x.device().same_device(device) {
+        Ok(x)
+    } else {
+        x.to_device(device).map_err(InferenceError::from)
     }
 }
 
 // ── TransformerShard ──────────────────────────────────────────────────────────
 
+/// Block tensors of a Llama GGUF file and their HuggingFace names.
+const GGUF_BLOCK_TENSORS: &[(&str, &str)] = &[
+    ("attn_norm.weight", "input_layernorm.weight"),
+    ("ffn_norm.weight", "post_attention_layernorm.weight"),
+    ("attn_q.weight", "self_attn.q_proj.weight"),
+    ("attn_k.weight", "self_attn.k_proj.weight"),
+    ("attn_v.weight", "self_attn.v_proj.weight"),
+    ("attn_output.weight", "self_attn.o_proj.weight"),
+    ("ffn_gate.weight", "mlp.gate_proj.weight"),
+    ("ffn_up.weight", "mlp.up_proj.weight"),
+    ("ffn_down.weight", "mlp.down_proj.weight"),
+];
+
+/// Undo llama.cpp's Q/K permutation: GGUF interleaves the two rotary halves
+/// of each head's rows, HuggingFace keeps them as contiguous halves.
+fn unpermute_rope_rows(w: &Tensor, n_heads: usize) -> InferenceResult<Tensor> {
+    let (rows, cols) = w.dims2()?;
+    let half = rows / n_heads / 2;
+    Ok(w.reshape((n_heads, half, 2, cols))?
+        .transpose(1, 2)?
+        .reshape((rows, cols))?)
+}
+
 /// A partial transformer model that serves blocks `[start_block..end_block)`.
 ///
 /// Load with [`TransformerShard::load`] then call the appropriate forward method
@@ -432,13 +587,21 @@ pub struct TransformerShard {
     blocks: Vec<ShardBlock>,                 // blocks [start_block..end_block)
     norm: Option<candle_nn::RmsNorm>,        // last node only
     lm_head: Option<candle_nn::Linear>,      // last node only
-    rope: RopeCache,
+    /// Devices the blocks are spread over, in pipeline order.
+    devices: Vec<Device>,
+    /// Index into `devices` (and `ropes`) for each block.
+    placement: Vec<usize>,
+    /// RoPE tables, one copy per device.
+    ropes: Vec<RopeCache>,
     /// Tokenizer — all nodes load it; only the coordinator uses it actively.
     pub tokenizer: BpeTokenizer,
     pub start_block: usize,
     pub end_block: usize,
     pub cfg: ShardConfig,
     sessions: Mutex<HashMap<u64, Session>>,
+    /// Loaded LoRA adapters by name; sessions pick one with
+    /// [`TransformerShard::set_session_adapter`].
+    adapters: RwLock<HashMap<String, Arc<LoraAdapter>>>,
 }
 
 impl TransformerShard {
@@ -454,6 +617,162 @@ impl TransformerShard {
         device: &Device,
         start_block: usize,
         end_block: usize,
+    ) -> InferenceResult<Self> {
+        let placement = vec![0; end_block.saturating_sub(start_block)];
+        Self::load_placed(
+            safetensors_paths,
+            config_path,
+            vec![device.clone()],
+            placement,
+            start_block,
+            end_block,
+        )
+    }
+
+    /// Load a shard with its blocks split across the devices in `map`.
+    ///
+    /// The embedding lives with the first block and the final norm + LM head
+    /// with the last, so inputs and outputs stay on the ends of the pipeline.
+    pub fn load_mapped(
+        safetensors_paths: &[&Path],
+        config_path: &Path,
+        map: &DeviceMap,
+        start_block: usize,
+        end_block: usize,
+    ) -> InferenceResult<Self> {
+        let devices = map.candle_devices()?;
+        let mut placement = Vec::with_capacity(end_block.saturating_sub(start_block));
+        for (device_idx, range) in map
+            .split(end_block.saturating_sub(start_block))
+            .into_iter()
+            .enumerate()
+        {
+            if !range.is_empty() {
+                info!(
+                    "  Blocks [{}..{}) → {}",
+                    start_block + range.start,
+                    start_block + range.end,
+                    map.devices[device_idx]
+                );
+            }
+            placement.extend(range.map(|_| device_idx));
+        }
+        Self::load_placed(
+            safetensors_paths,
+            config_path,
+            devices,
+            placement,
+            start_block,
+            end_block,
+        )
+    }
+
+    /// Load a shard from a GGUF file, reading only the tensors it needs.
+    ///
+    /// The file is memory-mapped ([`LazyGguf`]), so blocks outside
+    /// `[start_block..end_block)` are never read. Llama-family files only:
+    /// weights are dequantized to the shard's compute dtype, and the Q/K
+    /// rows llama.cpp permutes for its interleaved RoPE are put back in
+    /// HuggingFace order.
+    pub fn load_gguf(
+        path: &Path,
+        device: &Device,
+        start_block: usize,
+        end_block: usize,
+    ) -> InferenceResult<Self> {
+        let gguf = LazyGguf::open(path, device)?;
+        let ct = gguf.content();
+        let arch = meta_str(ct, "general.architecture").unwrap_or_default();
+        let n_expert = meta_usize(ct, &format!("{arch}.expert_count")).unwrap_or(0);
+        if !matches!(arch.as_str(), "llama" | "mistral") || n_expert > 1 {
+            return Err(InferenceError::UnsupportedArchitecture {
+                architecture: arch,
+                supported: "llama, mistral".to_string(),
+            });
+        }
+        let model = ModelConfig::from_gguf(ct)?;
+        let arch = model.architecture.as_str();
+        let num_total_blocks =
+            meta_usize(ct, &format!("{arch}.block_count")).unwrap_or_else(|| gguf.num_layers());
+        if start_block >= end_block || end_block > num_total_blocks {
+            return Err(InferenceError::ModelLoadError(format!(
+                "Invalid shard range [{start_block}..{end_block}) for \
+                 model with {num_total_blocks} layers"
+            )));
+        }
+
+        let cfg = ShardConfig {
+            num_total_blocks,
+            hidden_dim: model.hidden_dim,
+            num_heads: model.num_heads,
+            num_kv_heads: model.num_kv_heads,
+            head_dim: model.hidden_dim / model.num_heads,
+            intermediate_dim: model.intermediate_dim,
+            vocab_size: model.vocab_size,
+            rope_theta: f64::from(model.rope_theta),
+            max_seq_len: model.max_seq_len,
+            rms_norm_eps: f64::from(model.layer_norm_eps),
+            dtype: DType::F16,
+        };
+        info!(
+            "Loading shard [{start_block}..{end_block}) of {num_total_blocks} from {}",
+            path.display()
+        );
+
+        let dense = |q: &QTensor| -> InferenceResult<Tensor> {
+            Ok(q.dequantize(device)?.to_dtype(cfg.dtype)?)
+        };
+        let mut tensors = HashMap::new();
+        if start_block == 0 {
+            let embd = gguf.tensor("token_embd.weight")?;
+            tensors.insert("model.embed_tokens.weight".to_string(), dense(&embd)?);
+        }
+        for layer in gguf.layers(start_block..end_block)? {
+            let i = layer.index();
+            info!("  Loading block {i}");
+            for (gguf_name, hf_name) in GGUF_BLOCK_TENSORS {
+                let mut t = dense(&*layer.tensor(gguf_name)?)?;
+                match *gguf_name {
+                    "attn_q.weight" => t = unpermute_rope_rows(&t, cfg.num_heads)?,
+                    "attn_k.weight" => t = unpermute_rope_rows(&t, cfg.num_kv_heads)?,
+                    _ => {}
+                }
+                tensors.insert(format!("model.layers.{i}.{hf_name}"), t);
+            }
+            // Only the dequantized copies are kept.
+            gguf.release(i);
+        }
+        if end_block == num_total_blocks {
+            let norm = gguf.tensor("output_norm.weight")?;
+            tensors.insert("model.norm.weight".to_string(), dense(&norm)?);
+            // Some models tie the LM head to the embeddings.
+            let head = gguf
+                .tensor("output.weight")
+                .or_else(|_| gguf.tensor("token_embd.weight"))?;
+            tensors.insert("lm_head.weight".to_string(), dense(&head)?);
+        }
+
+        let vb = VarBuilder::from_tensors(tensors, cfg.dtype, device);
+        let tokenizer = BpeTokenizer::for_gguf(path, ct)?;
+        let placement = vec![0; end_block - start_block];
+        Self::assemble(
+            cfg,
+            &[vb],
+            vec![device.clone()],
+            placement,
+            tokenizer,
+            start_block,
+            end_block,
+        )
+    }
+
+    fn load_placed(
+        safetensors_paths: &[&Path],
+        config_path: &Path,
+        devices: Vec<Device>,
+        placement: Vec<usize>,
+        start_block: usize,
+        end_block: usize,
     ) -> InferenceResult<Self> {
         use candle_transformers::models::llama::LlamaConfig;
 
@@ -502,18 +821,55 @@ impl TransformerShard {
              hidden={hidden_dim} heads={num_heads} ({num_kv_heads} kv)"
         );
 
-        // Memory-map all safetensors shards (only accessed pages are read).
+        // Memory-map all safetensors shards (only accessed pages are read),
+        // once per device so each block's weights land on its own device.
         // SAFETY: files must not be modified while the model is loaded.
-        let vb = unsafe {
-            VarBuilder::from_mmaped_safetensors(safetensors_paths, cfg.dtype, device)
-                .map_err(|e| InferenceError::ModelLoadError(format!("mmap safetensors: {e}")))?
-        };
+        let vbs = devices
+            .iter()
+            .map(|device| unsafe {
+                VarBuilder::from_mmaped_safetensors(safetensors_paths, cfg.dtype, device)
+                    .map_err(|e| InferenceError::ModelLoadError(format!("mmap safetensors: {e}")))
+            })
+            .collect::<InferenceResult<Vec<_>>>()?;
+
+        // Tokenizer
+        let tokenizer_path = config_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join("tokenizer.json");
+        let tokenizer = BpeTokenizer::from_file(&tokenizer_path)?;
+
+        Self::assemble(
+            cfg,
+            &vbs,
+            devices,
+            placement,
+            tokenizer,
+            start_block,
+            end_block,
+        )
+    }
+
+    /// Build the shard's components from one `VarBuilder` per device, in
+    /// HuggingFace Llama tensor naming.
+    fn assemble(
+        cfg: ShardConfig,
+        vbs: &[VarBuilder],
+        devices: Vec<Device>,
+        placement: Vec<usize>,
+        tokenizer: BpeTokenizer,
+        start_block: usize,
+        end_block: usize,
+    ) -> InferenceResult<Self> {
+        let (hidden_dim, vocab_size) = (cfg.hidden_dim, cfg.vocab_size);
+        let first_vb = &vbs[placement.first().copied().unwrap_or(0)];
+        let last_vb = &vbs[placement.last().copied().unwrap_or(0)];
 
         // Embedding: only for the first node in the chain
         let embedding = if start_block == 0 {
             info!("  Loading embedding (vocab={vocab_size}, dim={hidden_dim})");
             Some(
-                candle_nn::embedding(vocab_size, hidden_dim, vb.pp("model.embed_tokens"))
+                candle_nn::embedding(vocab_size, hidden_dim, first_vb.pp("model.embed_tokens"))
                     .map_err(|e| InferenceError::ModelLoadError(format!("embedding: {e}")))?,
             )
         } else {
@@ -523,33 +879,29 @@ impl TransformerShard {
         // Transformer blocks
         let shard_size = end_block - start_block;
         let mut blocks = Vec::with_capacity(shard_size);
-        for global_idx in start_block..end_block {
+        for (global_idx, &device_idx) in (start_block..end_block).zip(&placement) {
             info!("  Loading block {global_idx}");
-            let block_vb = vb.pp(format!("model.layers.{global_idx}"));
+            let block_vb = vbs[device_idx].pp(format!("model.layers.{global_idx}"));
             blocks.push(ShardBlock::load(block_vb, &cfg)?);
         }
 
         // Final norm + LM head: only for the last node in the chain
-        let (norm, lm_head) = if end_block == num_total_blocks {
+        let (norm, lm_head) = if end_block == cfg.num_total_blocks {
             info!("  Loading norm + lm_head");
-            let n = candle_nn::rms_norm(hidden_dim, rms_norm_eps, vb.pp("model.norm"))
+            let n = candle_nn::rms_norm(hidden_dim, cfg.rms_norm_eps, last_vb.pp("model.norm"))
                 .map_err(|e| InferenceError::ModelLoadError(format!("final norm: {e}")))?;
-            let lh = candle_nn::linear_no_bias(hidden_dim, vocab_size, vb.pp("lm_head"))
+            let lh = candle_nn::linear_no_bias(hidden_dim, vocab_size, last_vb.pp("lm_head"))
                 .map_err(|e| InferenceError::ModelLoadError(format!("lm_head: {e}")))?;
             (Some(n), Some(lh))
         } else {
             (None, None)
         };
 
-        // Tokenizer
-        let tokenizer_path = config_path
-            .parent()
-            .unwrap_or(Path::new("."))
-            .join("tokenizer.json");
-        let tokenizer = BpeTokenizer::from_file(&tokenizer_path)?;
-
-        // Precompute RoPE tables on the same device as the weights.
-        let rope = RopeCache::new(&cfg, device)?;
+        // Precompute RoPE tables on the same device(s) as the weights.
+        let ropes = devices
+            .iter()
+            .map(|device| RopeCache::new(&cfg, device))
+            .collect::<InferenceResult<Vec<_>>>()?;
 
         info!(
             "Shard [{start_block}..{end_block}) ready — \
@@ -563,12 +915,15 @@ impl TransformerShard {
             blocks,
             norm,
             lm_head,
-            rope,
+            devices,
+            placement,
+            ropes,
             tokenizer,
             start_block,
             end_block,
             cfg,
             sessions: Mutex::new(HashMap::new()),
+            adapters: RwLock::new(HashMap::new()),
         })
     }
 
@@ -613,9 +968,109 @@ impl TransformerShard {
         }
     }
 
+    // ── LoRA adapters ─────────────────────────────────────────────────────────
+
+    /// Load the PEFT adapter in `dir` under `name`, replacing any adapter
+    /// already loaded under that name. Only this shard's layers are kept.
+    pub fn load_adapter(&self, name: &str, dir: &Path) -> InferenceResult<()> {
+        // Each device gets the adapter layers of the blocks it runs.
+        let mut adapter: Option<LoraAdapter> = None;
+        for (device_idx, device) in self.devices.iter().enumerate() {
+            let Some(first) = self.placement.iter().position(|&d| d == device_idx) else {
+                continue;
+            };
+            let count = self.placement.iter().filter(|&&d| d == device_idx).count();
+            let start = self.start_block + first;
+            let part = LoraAdapter::load(name, dir, &self.cfg, start, start + count, device)?;
+            match adapter.as_mut() {
+                Some(a) => a.absorb(part),
+                None => adapter = Some(part),
+            }
+        }
+        let adapter = adapter.ok_or_else(|| {
+            InferenceError::ModelLoadError(format!("adapter '{name}': shard has no blocks"))
+        })?;
+        self.adapters
+            .write()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(adapter));
+        Ok(())
+    }
+
+    /// Unload adapter `name`. Sessions already using it keep their copy.
+    pub fn unload_adapter(&self, name: &str) -> bool {
+        self.adapters.write().unwrap().remove(name).is_some()
+    }
+
+    /// Names of the loaded adapters, sorted.
+    pub fn adapter_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.adapters.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Run session `session_id` with adapter `name` (`None` = base model),
+    /// creating the session if needed.
+    ///
+    /// Switching adapters clears the session's KV-cache, since the cached
+    /// keys and values were computed with different weights.
+    pub fn set_session_adapter(&self, session_id: u64, name: Option<&str>) -> InferenceResult<()> {
+        let adapter =
+            match name {
+                Some(n) => Some(self.adapters.read().unwrap().get(n).cloned().ok_or_else(
+                    || {
+                        InferenceError::InvalidInput(format!(
+                            "adapter '{n}' is not loaded on this server"
+                        ))
+                    },
+                )?),
+                None => None,
+            };
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .entry(session_id)
+            .or_insert_with(|| Session::new(self.blocks.len()));
+        let current = session.adapter.as_ref().map(|a| a.name.as_str());
+        if current != name {
+            debug!("Session {session_id}: adapter {current:?} → {name:?}");
+            session.kv = vec![None; self.blocks.len()];
+            session.adapter = adapter;
+        }
+        Ok(())
+    }
+
+    /// Measure the forward-pass throughput of this shard's blocks on the
+    /// device they are loaded on.
+    ///
+    /// Uses random hidden states and a private KV cache, so live sessions are
+    /// not touched. Takes a few seconds on CPU for a handful of 8B blocks;
+    /// run it on a blocking thread.
+    pub fn benchmark(
+        &self,
+        decode_steps: usize,
+        prefill_tokens: usize,
+    ) -> InferenceResult<BlockThroughput> {
+        let placed: Vec<_> = self
+            .blocks
+            .iter()
+            .zip(&self.placement)
+            .map(|(block, &d)| (block, &self.devices[d], &self.ropes[d]))
+            .collect();
+        benchmark_blocks(&placed, &self.cfg, decode_steps, prefill_tokens)
+    }
+
+    /// Devices this shard's blocks run on, in pipeline order.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
     // ── Core block execution ──────────────────────────────────────────────────
 
     /// Run the hidden state through all blocks in this shard, updating the KV-cache.
+    ///
+    /// Anything cached at or after `seq_pos` is dropped first, so resending from
+    /// an earlier position rolls the session back (e.g. rejected speculative
+    /// draft tokens).
     fn run_blocks(
         &self,
         mut x: Tensor,
@@ -628,9 +1083,22 @@ impl TransformerShard {
             .entry(session_id)
             .or_insert_with(|| Session::new(self.blocks.len()));
         session.last_access = Instant::now();
+        session.truncate(seq_pos)?;
+        let adapter = session.adapter.clone();
 
         for (local_idx, block) in self.blocks.iter().enumerate() {
-            x = block.forward(&x, seq_pos, &mut session.kv[local_idx], &self.rope)?;
+            let lora = adapter
+                .as_deref()
+                .and_then(|a| a.block(self.start_block + local_idx));
+            let d = self.placement[local_idx];
+            x = to_block_device(x, &self.devices[d])?;
+            x = block.forward(
+                &x,
+                seq_pos,
+                &mut session.kv[local_idx],
+                &self.ropes[d],
+                lora,
+            )?;
         }
 
         let total = run_start.elapsed();
@@ -694,6 +1162,46 @@ impl TransformerShard {
         self.run_blocks(hidden, seq_pos, session_id)
     }
 
+    /// **Block server**: run hidden states received from a remote pipeline
+    /// through this shard's blocks and return the hidden states after them,
+    /// on any node (the last node skips its norm + LM head).
+    ///
+    /// Unlike [`forward_middle`](Self::forward_middle) the input is checked
+    /// against the model width and cast to the shard's dtype, so it may come
+    /// straight off the wire.
+    ///
+    /// * `hidden`  — `[batch, seq_len, hidden_dim]`
+    /// * `seq_pos` — global sequence position of `hidden`'s first token; the
+    ///   session's KV-cache beyond it is discarded (`0` starts the session over)
+    pub fn forward_hidden(
+        &self,
+        session_id: u64,
+        hidden: &Tensor,
+        seq_pos: usize,
+    ) -> InferenceResult<Tensor> {
+        let (_, seq_len, width) = hidden.dims3().map_err(|_| {
+            InferenceError::InvalidInput(format!(
+                "hidden states must be [batch, seq_len, hidden_dim], got {:?}",
+                hidden.dims()
+            ))
+        })?;
+        if width != self.cfg.hidden_dim {
+            return Err(InferenceError::InvalidInput(format!(
+                "hidden states are {width} wide, the model is {}",
+                self.cfg.hidden_dim
+            )));
+        }
+        if seq_pos + seq_len > self.cfg.max_seq_len {
+            return Err(InferenceError::InvalidInput(format!(
+                "positions {seq_pos}..{} exceed the context length {}",
+                seq_pos + seq_len,
+                self.cfg.max_seq_len
+            )));
+        }
+        let hidden = hidden.to_dtype(self.cfg.dtype)?;
+        self.run_blocks(hidden, seq_pos, session_id)
+    }
+
     /// **Single-node** (first AND last): embed token IDs, run all blocks, return logits.
     ///
     /// Convenience method for when one node serves the entire model.
@@ -734,8 +1242,7 @@ impl TransformerShard {
         let t_head = Instant::now();
         let seq_len = x.dim(1).map_err(InferenceError::from)?;
         let x_last = x.narrow(1, seq_len - 1, 1).map_err(InferenceError::from)?;
-        let x_last = norm.forward(&x_last).map_err(InferenceError::from)?;
-        let logits = lm_head.forward(&x_last).map_err(InferenceError::from)?;
+        let logits = Self::apply_head(norm, lm_head, &x_last)?;
         let t_head = t_head.elapsed();
 
         let total = t_start.elapsed();
@@ -778,10 +1285,53 @@ impl TransformerShard {
 
         // Keep only the last-token hidden state for efficient logit computation
         let x_last = x.narrow(1, seq_len - 1, 1).map_err(InferenceError::from)?; // [1, 1, h]
-        let x_last = norm.forward(&x_last).map_err(InferenceError::from)?;
-        let logits = lm_head.forward(&x_last).map_err(InferenceError::from)?; // [1, 1, vocab]
+        Self::apply_head(norm, lm_head, &x_last) // [1, 1, vocab]
+    }
 
-        Ok(logits)
+    /// **Single-node**, every position: like [`forward_full`](Self::forward_full)
+    /// but returns logits for each input token, `[1, seq_len, vocab_size]`.
+    /// Used to verify speculative draft tokens in one pass.
+    pub fn forward_full_all(
+        &self,
+        session_id: u64,
+        token_ids: &[u32],
+        seq_pos: usize,
+    ) -> InferenceResult<Tensor> {
+        let hidden = self.forward_first(session_id, token_ids, seq_pos)?;
+        let (norm, lm_head) = self.head("forward_full_all")?;
+        Self::apply_head(norm, lm_head, &hidden)
+    }
+
+    /// **Last node**, every position: like [`forward_last`](Self::forward_last)
+    /// but returns logits for each input position, `[1, seq_len, vocab_size]`.
+    pub fn forward_last_all(
+        &self,
+        session_id: u64,
+        hidden: Tensor,
+        seq_pos: usize,
+    ) -> InferenceResult<Tensor> {
+        let (norm, lm_head) = self.head("forward_last_all")?;
+        let x = self.run_blocks(hidden, seq_pos, session_id)?;
+        Self::apply_head(norm, lm_head, &x)
+    }
+
+    fn head(&self, caller: &str) -> InferenceResult<(&candle_nn::RmsNorm, &candle_nn::Linear)> {
+        match (&self.norm, &self.lm_head) {
+            (Some(n), Some(h)) => Ok((n, h)),
+            _ => Err(InferenceError::InferenceFailed(format!(
+                "{caller}() called on a shard that is not the last node"
+            ))),
+        }
+    }
+
+    /// Final RMSNorm + LM head.
+    fn apply_head(
+        norm: &candle_nn::RmsNorm,
+        lm_head: &candle_nn::Linear,
+        x: &Tensor,
+    ) -> InferenceResult<Tensor> {
+        let x = norm.forward(x).map_err(InferenceError::from)?;
+        lm_head.forward(&x).map_err(InferenceError::from)
     }
 }
 
@@ -812,6 +1362,283 @@ mod tests {
         assert_eq!(rope.sin.dims(), &[64, 8]);
     }
 
+    #[test]
+    fn benchmark_reports_per_block_rates() {
+        let cfg = ShardConfig {
+            num_total_blocks: 4,
+            hidden_dim: 32,
+            num_heads: 4,
+            num_kv_heads: 2,
+            head_dim: 8,
+            intermediate_dim: 64,
+            vocab_size: 16,
+            rope_theta: 10000.0,
+            max_seq_len: 64,
+            rms_norm_eps: 1e-5,
+            dtype: DType::F32,
+        };
+        let device = Device::Cpu;
+        let vb = VarBuilder::zeros(DType::F32, &device);
+        let blocks: Vec<ShardBlock> = (0..2)
+            .map(|i| ShardBlock::load(vb.pp(format!("model.layers.{i}")), &cfg).unwrap())
+            .collect();
+        let rope = RopeCache::new(&cfg, &device).unwrap();
+
+        // Prefill is clamped so prefill + decode still fits max_seq_len.
+        let placed: Vec<_> = blocks.iter().map(|b| (b, &device, &rope)).collect();
+        let rates = benchmark_blocks(&placed, &cfg, 4, 1000).unwrap();
+        assert!(rates.inference_rps.is_finite() && rates.inference_rps > 0.0);
+        assert!(rates.forward_rps.is_finite() && rates.forward_rps > 0.0);
+    }
+
+    /// Write a random 4-layer Llama snapshot (weights, config, tokenizer).
+    fn write_tiny_llama(dir: &Path) {
+        let (h, kv, inter, vocab) = (32usize, 16usize, 64usize, 16usize);
+        let device = Device::Cpu;
+        let mut tensors = HashMap::new();
+        let mut add = |name: String, shape: &[usize]| {
+            let t = Tensor::randn(0f32, 0.2, shape, &device).unwrap();
+            tensors.insert(name, t);
+        };
+        add("model.embed_tokens.weight".into(), &[vocab, h]);
+        add("model.norm.weight".into(), &[h]);
+        add("lm_head.weight".into(), &[vocab, h]);
+        for i in 0..4 {
+            let p = format!("model.layers.{i}");
+            add(format!("{p}.input_layernorm.weight"), &[h]);
+            add(format!("{p}.post_attention_layernorm.weight"), &[h]);
+            add(format!("{p}.self_attn.q_proj.weight"), &[h, h]);
+            add(format!("{p}.self_attn.k_proj.weight"), &[kv, h]);
+            add(format!("{p}.self_attn.v_proj.weight"), &[kv, h]);
+            add(format!("{p}.self_attn.o_proj.weight"), &[h, h]);
+            add(format!("{p}.mlp.gate_proj.weight"), &[inter, h]);
+            add(format!("{p}.mlp.up_proj.weight"), &[inter, h]);
+            add(format!("{p}.mlp.down_proj.weight"), &[h, inter]);
+        }
+        candle_core::safetensors::save(&tensors, dir.join("model.safetensors")).unwrap();
+        let config = serde_json::json!({
+            "hidden_size": h,
+            "intermediate_size": inter,
+            "vocab_size": vocab,
+            "num_hidden_layers": 4,
+            "num_attention_heads": 4,
+            "num_key_value_heads": 2,
+            "rms_norm_eps": 1e-5,
+            "max_position_embeddings": 64,
+        });
+        std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+        let vocab_map: serde_json::Map<String, serde_json::Value> = (0..vocab)
+            .map(|i| (format!("t{i}"), serde_json::json!(i)))
+            .collect();
+        let tokenizer = serde_json::json!({
+            "version": "1.0",
+            "model": { "type": "BPE", "vocab": vocab_map, "merges": [] },
+        });
+        std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+    }
+
+    #[test]
+    fn mapped_shard_matches_single_device() {
+        let dir = std::env::temp_dir().join(format!("kwaai-shard-map-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_tiny_llama(&dir);
+        let weights = dir.join("model.safetensors");
+        let paths = [weights.as_path()];
+        let config = dir.join("config.json");
+
+        let single = TransformerShard::load(&paths, &config, &Device::Cpu, 0, 4).unwrap();
+        let map: DeviceMap = "cpu=1,cpu=3".parse().unwrap();
+        let split = TransformerShard::load_mapped(&paths, &config, &map, 0, 4).unwrap();
+        assert_eq!(split.placement, [0, 1, 1, 1]);
+        assert_eq!(split.devices().len(), 2);
+
+        let tokens = [1u32, 5, 7];
+        let a = single.forward_full(1, &tokens, 0).unwrap();
+        let b = split.forward_full(1, &tokens, 0).unwrap();
+        let diff = (a - b)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .to_dtype(DType::F32)
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert_eq!(diff, 0.0);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Convert the tiny Llama in `dir` to `model.gguf`, permuting Q/K the
+    /// way llama.cpp's converter does.
+    fn write_tiny_gguf(dir: &Path) -> std::path::PathBuf {
+        use candle_core::quantized::{gguf_file, GgmlDType};
+        use gguf_file::Value;
+
+        let st =
+            candle_core::safetensors::load(dir.join("model.safetensors"), &Device::Cpu).unwrap();
+        let permute = |w: &Tensor, n_heads: usize| {
+            let (rows, cols) = w.dims2().unwrap();
+            w.reshape((n_heads, 2, rows / n_heads / 2, cols))
+                .unwrap()
+                .transpose(1, 2)
+                .unwrap()
+                .reshape((rows, cols))
+                .unwrap()
+        };
+        let mut tensors = vec![
+            (
+                "token_embd.weight".to_string(),
+                st["model.embed_tokens.weight"].clone(),
+            ),
+            (
+                "output_norm.weight".to_string(),
+                st["model.norm.weight"].clone(),
+            ),
+            ("output.weight".to_string(), st["lm_head.weight"].clone()),
+        ];
+        for i in 0..4 {
+            for (gguf_name, hf_name) in GGUF_BLOCK_TENSORS {
+                let mut t = st[&format!("model.layers.{i}.{hf_name}")].clone();
+                match *gguf_name {
+                    "attn_q.weight" => t = permute(&t, 4),
+                    "attn_k.weight" => t = permute(&t, 2),
+                    _ => {}
+                }
+                tensors.push((format!("blk.{i}.{gguf_name}"), t));
+            }
+        }
+        let q: Vec<(String, QTensor)> = tensors
+            .into_iter()
+            .map(|(n, t)| (n, QTensor::quantize(&t, GgmlDType::F32).unwrap()))
+            .collect();
+        let ts: Vec<(&str, &QTensor)> = q.iter().map(|(k, v)| (k.as_str(), v)).collect();
+
+        let mut tokens = vec!["a".to_string(), "b".to_string(), "ab".to_string()];
+        tokens.extend((3..16).map(|i| format!("t{i}")));
+        let str_array = |v: &[String]| Value::Array(v.iter().cloned().map(Value::String).collect());
+        let meta: Vec<(&str, Value)> = vec![
+            ("general.architecture", Value::String("llama".into())),
+            ("llama.block_count", Value::U32(4)),
+            ("llama.embedding_length", Value::U32(32)),
+            ("llama.feed_forward_length", Value::U32(64)),
+            ("llama.attention.head_count", Value::U32(4)),
+            ("llama.attention.head_count_kv", Value::U32(2)),
+            ("llama.context_length", Value::U32(64)),
+            ("llama.attention.layer_norm_rms_epsilon", Value::F32(1e-5)),
+            ("tokenizer.ggml.model", Value::String("gpt2".into())),
+            ("tokenizer.ggml.tokens", str_array(&tokens)),
+            ("tokenizer.ggml.merges", str_array(&["a b".to_string()])),
+        ];
+        let meta: Vec<(&str, &Value)> = meta.iter().map(|(k, v)| (*k, v)).collect();
+        let path = dir.join("model.gguf");
+        let mut file = std::fs::File::create(&path).unwrap();
+        gguf_file::write(&mut file, &meta, &ts).unwrap();
+        path
+    }
+
+    #[test]
+    fn gguf_shard_matches_safetensors() {
+        let dir = std::env::temp_dir().join(format!("kwaai-shard-gguf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_tiny_llama(&dir);
+        let gguf = write_tiny_gguf(&dir);
+        let weights = dir.join("model.safetensors");
+        let config = dir.join("config.json");
+
+        let st = TransformerShard::load(&[weights.as_path()], &config, &Device::Cpu, 0, 4).unwrap();
+        let gg = TransformerShard::load_gguf(&gguf, &Device::Cpu, 0, 4).unwrap();
+        let tokens = [1u32, 5, 7];
+        let a = st.forward_full(1, &tokens, 0).unwrap();
+        let b = gg.forward_full(1, &tokens, 0).unwrap();
+        let diff = (a - b)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .to_dtype(DType::F32)
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert_eq!(diff, 0.0);
+
+        // A middle range holds neither the embedding nor the LM head.
+        let mid = TransformerShard::load_gguf(&gguf, &Device::Cpu, 1, 3).unwrap();
+        assert!(!mid.is_first() && !mid.is_last());
+        assert!(TransformerShard::load_gguf(&gguf, &Device::Cpu, 2, 5).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn engine_loads_a_block_range() {
+        use crate::{engine::InferenceEngine, DeviceType, EngineConfig};
+
+        let dir = std::env::temp_dir().join(format!("kwaai-engine-blocks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_tiny_llama(&dir);
+        let mut engine = InferenceEngine::new(EngineConfig {
+            device: DeviceType::Cpu,
+            ..EngineConfig::default()
+        })
+        .unwrap();
+
+        let handle = engine.load_model_blocks(&dir, 2..4).unwrap();
+        let shard = engine.blocks(&handle).unwrap();
+        assert_eq!((shard.start_block, shard.end_block), (2, 4));
+        assert!(!shard.is_first() && shard.is_last());
+        assert!(engine.memory_usage() > 0);
+        assert!(engine
+            .generate_with(&handle, "ab", &Default::default())
+            .is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn forward_hidden_continues_a_remote_pipeline() {
+        use crate::{engine::InferenceEngine, DeviceType, EngineConfig};
+
+        let dir = std::env::temp_dir().join(format!("kwaai-fwd-hidden-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_tiny_llama(&dir);
+        let weights = dir.join("model.safetensors");
+        let paths = [weights.as_path()];
+        let config = dir.join("config.json");
+        let whole = TransformerShard::load(&paths, &config, &Device::Cpu, 0, 4).unwrap();
+        let head = TransformerShard::load(&paths, &config, &Device::Cpu, 0, 2).unwrap();
+        let mut engine = InferenceEngine::new(EngineConfig {
+            device: DeviceType::Cpu,
+            ..EngineConfig::default()
+        })
+        .unwrap();
+        let tail = engine.load_model_blocks(&dir, 2..4).unwrap();
+
+        let max_diff = |a: &Tensor, b: &Tensor| {
+            (a - b)
+                .unwrap()
+                .abs()
+                .unwrap()
+                .to_dtype(DType::F32)
+                .unwrap()
+                .max_all()
+                .unwrap()
+                .to_scalar::<f32>()
+                .unwrap()
+        };
+        // Prefill, then one decode step that relies on the session's KV-cache.
+        for (tokens, pos) in [(&[1u32, 5, 7][..], 0), (&[3u32][..], 3)] {
+            let expected = whole.forward_first(9, tokens, pos).unwrap();
+            let mid = head.forward_first(9, tokens, pos).unwrap();
+            let got = engine.forward_hidden(&tail, 9, &mid, pos).unwrap();
+            assert_eq!(max_diff(&expected, &got), 0.0);
+        }
+
+        let narrow = Tensor::zeros((1, 1, 8), DType::F16, &Device::Cpu).unwrap();
+        assert!(engine.forward_hidden(&tail, 9, &narrow, 4).is_err());
+        engine.end_session(&tail, 9).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn causal_mask_shape() {
         let mask = causal_mask(3, 5, 2, &Device::Cpu, DType::F32).unwrap();
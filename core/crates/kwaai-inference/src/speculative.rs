@@ -0,0 +1,464 @@
+//! Speculative decoding: a small draft model proposes tokens, the target
+//! model verifies them in one forward pass.
+//!
+//! Each round the draft autoregressively proposes up to
+//! [`Speculation::draft_tokens`] tokens. The target runs once over all of
+//! them and returns logits for every position; the longest prefix it agrees
+//! with is kept, plus one token from the target itself (the correction at
+//! the first mismatch, or a bonus token when every draft was accepted).
+//!
+//! With temperature 0 the output is exactly the target's greedy decode.
+//! With sampling, drafts are accepted with probability `min(1, p/q)` and
+//! rejections resample from `max(p - q, 0)`, which leaves the output
+//! distributed exactly as if the target had sampled alone.
+//!
+//! Both models are driven through [`CausalLm`], whose implementors keep a
+//! KV-cache and roll it back when called with an earlier position, so the
+//! target can be a local [`TransformerShard`] or a remote block chain.
+
+use crate::{
+    error::{InferenceError, InferenceResult},
+    shard::TransformerShard,
+};
+use candle_core::{DType, Tensor};
+use serde::{Deserialize, Serialize};
+
+/// Temperatures below this decode greedily, as in candle's `LogitsProcessor`.
+const GREEDY_TEMPERATURE: f64 = 1e-7;
+
+/// A causal language model with a rewindable KV-cache.
+pub trait CausalLm {
+    /// Feed `tokens` starting at sequence position `pos` and return logits
+    /// for every one of them, `[tokens.len(), vocab_size]`.
+    ///
+    /// Cached positions at or after `pos` are discarded first, so calling
+    /// with an earlier `pos` than the cache length rewinds the model.
+    fn forward_all(&mut self, tokens: &[u32], pos: usize) -> InferenceResult<Tensor>;
+}
+
+/// [`CausalLm`] over one session of a single-node [`TransformerShard`]
+/// (all blocks, embedding and LM head).
+pub struct ShardSession<'a> {
+    shard: &'a TransformerShard,
+    session_id: u64,
+}
+
+impl<'a> ShardSession<'a> {
+    /// Open `session_id` on `shard`; the session is closed on drop.
+    pub fn new(shard: &'a TransformerShard, session_id: u64) -> Self {
+        shard.open_session(session_id);
+        Self { shard, session_id }
+    }
+}
+
+impl CausalLm for ShardSession<'_> {
+    fn forward_all(&mut self, tokens: &[u32], pos: usize) -> InferenceResult<Tensor> {
+        self.shard
+            .forward_full_all(self.session_id, tokens, pos)?
+            .squeeze(0)
+            .map_err(InferenceError::from)
+    }
+}
+
+impl Drop for ShardSession<'_> {
+    fn drop(&mut self) {
+        self.shard.close_session(self.session_id);
+    }
+}
+
+/// Knobs for [`speculative_generate`].
+#[derive(Debug, Clone)]
+pub struct Speculation {
+    /// Tokens the draft proposes per round.
+    pub draft_tokens: usize,
+    /// Sampling temperature shared by draft and target (0 → greedy).
+    pub temperature: f64,
+    /// Seed for acceptance tests and sampling.
+    pub seed: u64,
+}
+
+/// Draft acceptance counters for one generation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpeculativeStats {
+    /// Draft/verify rounds run.
+    pub rounds: usize,
+    /// Tokens proposed by the draft.
+    pub drafted: usize,
+    /// Proposed tokens the target accepted.
+    pub accepted: usize,
+}
+
+impl SpeculativeStats {
+    /// Fraction of drafted tokens that were accepted.
+    pub fn acceptance_rate(&self) -> f64 {
+        if self.drafted == 0 {
+            0.0
+        } else {
+            self.accepted as f64 / self.drafted as f64
+        }
+    }
+
+    /// Average tokens produced per target forward pass.
+    pub fn tokens_per_round(&self) -> f64 {
+        if self.rounds == 0 {
+            0.0
+        } else {
+            // Every round adds one target-chosen token on top of the accepted drafts.
+            (self.accepted + self.rounds) as f64 / self.rounds as f64
+        }
+    }
+}
+
+/// Generate up to `max_new` tokens after `prompt`, drafting with `draft`
+/// and verifying with `target`.
+///
+/// `on_token(token, target_logits)` is called for every emitted token with
+/// the target's raw logits at that position; returning `false` stops
+/// generation (e.g. a passed deadline). A token in `stop_ids` ends
+/// generation without being emitted.
+pub fn speculative_generate<F>(
+    draft: &mut dyn CausalLm,
+    target: &mut dyn CausalLm,
+    prompt: &[u32],
+    stop_ids: &[u32],
+    max_new: usize,
+    spec: &Speculation,
+    mut on_token: F,
+) -> InferenceResult<(Vec<u32>, SpeculativeStats)>
+where
+    F: FnMut(u32, &[f32]) -> InferenceResult<bool>,
+{
+    if prompt.is_empty() {
+        return Err(InferenceError::InvalidInput(
+            "speculative decoding needs a non-empty prompt".to_string(),
+        ));
+    }
+    let greedy = spec.temperature < GREEDY_TEMPERATURE;
+    let mut rng = SplitMix64(spec.seed);
+    let mut stats = SpeculativeStats::default();
+    let mut ctx = prompt.to_vec();
+    let mut generated = Vec::new();
+    // Positions each model has cached that are still part of `ctx`.
+    let mut draft_valid = 0;
+    let mut target_valid = 0;
+
+    while generated.len() < max_new {
+        let n = ctx.len();
+        let k = spec.draft_tokens.clamp(1, max_new - generated.len());
+
+        // Draft k tokens autoregressively.
+        let mut drafts = Vec::with_capacity(k);
+        let mut draft_probs = Vec::with_capacity(k);
+        let mut feed = ctx[draft_valid..].to_vec();
+        let mut pos = draft_valid;
+        for _ in 0..k {
+            let rows = to_rows(&draft.forward_all(&feed, pos)?)?;
+            pos += feed.len();
+            let last = rows.last().ok_or_else(empty_logits)?;
+            let token = if greedy {
+                argmax(last)
+            } else {
+                let q = softmax(last, spec.temperature);
+                let t = sample(&q, rng.next_f32());
+                draft_probs.push(q);
+                t
+            };
+            drafts.push(token);
+            feed = vec![token];
+        }
+
+        // Verify all drafts with one target pass. Row `base + i` holds the
+        // target's prediction for position `n + i`.
+        let mut verify = ctx[target_valid..].to_vec();
+        verify.extend_from_slice(&drafts);
+        let rows = to_rows(&target.forward_all(&verify, target_valid)?)?;
+        let base = n - target_valid - 1;
+        if rows.len() != verify.len() {
+            return Err(InferenceError::InferenceFailed(format!(
+                "target returned {} logit rows for {} tokens",
+                rows.len(),
+                verify.len()
+            )));
+        }
+
+        let mut accepted = 0;
+        let mut emit = Vec::with_capacity(k + 1);
+        for (i, &token) in drafts.iter().enumerate() {
+            let row = &rows[base + i];
+            if greedy {
+                let best = argmax(row);
+                if best == token {
+                    accepted += 1;
+                    emit.push((token, base + i));
+                    continue;
+                }
+                emit.push((best, base + i));
+                break;
+            }
+            let p = softmax(row, spec.temperature);
+            let q = &draft_probs[i];
+            let t = token as usize;
+            if rng.next_f32() * q[t] < p[t] {
+                accepted += 1;
+                emit.push((token, base + i));
+                continue;
+            }
+            // Rejected: resample from the residual max(p - q, 0).
+            let residual: Vec<f32> = p.iter().zip(q).map(|(a, b)| (a - b).max(0.0)).collect();
+            let total: f32 = residual.iter().sum();
+            let fixed = if total > 0.0 {
+                let norm: Vec<f32> = residual.iter().map(|r| r / total).collect();
+                sample(&norm, rng.next_f32())
+            } else {
+                sample(&p, rng.next_f32())
+            };
+            emit.push((fixed, base + i));
+            break;
+        }
+        if accepted == k {
+            let row = &rows[base + k];
+            let bonus = if greedy {
+                argmax(row)
+            } else {
+                sample(&softmax(row, spec.temperature), rng.next_f32())
+            };
+            emit.push((bonus, base + k));
+        }
+
+        stats.rounds += 1;
+        stats.drafted += k;
+        stats.accepted += accepted;
+
+        for (token, row) in emit {
+            if stop_ids.contains(&token) {
+                return Ok((generated, stats));
+            }
+            generated.push(token);
+            ctx.push(token);
+            if !on_token(token, &rows[row])? || generated.len() >= max_new {
+                return Ok((generated, stats));
+            }
+        }
+
+        // The target cached every draft but only the accepted ones survive;
+        // the draft never fed its own last proposal.
+        target_valid = n + accepted;
+        draft_valid = n + accepted.min(k - 1);
+    }
+    Ok((generated, stats))
+}
+
+/// `[len, vocab]` logits as f32 rows.
+fn to_rows(logits: &Tensor) -> InferenceResult<Vec<Vec<f32>>> {
+    logits
+        .to_dtype(DType::F32)
+        .and_then(|l| l.to_vec2::<f32>())
+        .map_err(InferenceError::from)
+}
+
+fn empty_logits() -> InferenceError {
+    InferenceError::InferenceFailed("model returned no logits".to_string())
+}
+
+fn argmax(row: &[f32]) -> u32 {
+    row.iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i as u32)
+        .unwrap_or(0)
+}
+
+fn softmax(row: &[f32], temperature: f64) -> Vec<f32> {
+    let t = temperature as f32;
+    let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<f32> = row.iter().map(|&l| ((l - max) / t).exp()).collect();
+    let sum: f32 = exp.iter().sum();
+    exp.into_iter().map(|e| e / sum).collect()
+}
+
+/// Inverse-CDF sample from `probs` with `u` in `[0, 1)`.
+fn sample(probs: &[f32], u: f32) -> u32 {
+    let mut acc = 0.0;
+    for (i, &p) in probs.iter().enumerate() {
+        acc += p;
+        if u < acc {
+            return i as u32;
+        }
+    }
+    // Rounding left `u` past the last bucket: take the last non-zero one.
+    probs.iter().rposition(|&p| p > 0.0).unwrap_or(0) as u32
+}
+
+/// Small deterministic RNG so runs are reproducible from a seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    const VOCAB: usize = 16;
+
+    /// Deterministic toy model: the next token is `rule(history)`, with a
+    /// sharply peaked distribution. Tracks its cache like a real model and
+    /// checks callers never skip positions.
+    struct Toy {
+        history: Vec<u32>,
+        rule: fn(&[u32]) -> u32,
+        calls: usize,
+    }
+
+    impl Toy {
+        fn new(rule: fn(&[u32]) -> u32) -> Self {
+            Self {
+                history: Vec::new(),
+                rule,
+                calls: 0,
+            }
+        }
+    }
+
+    impl CausalLm for Toy {
+        fn forward_all(&mut self, tokens: &[u32], pos: usize) -> InferenceResult<Tensor> {
+            assert!(pos <= self.history.len(), "skipped uncached positions");
+            self.calls += 1;
+            self.history.truncate(pos);
+            let mut rows = Vec::with_capacity(tokens.len() * VOCAB);
+            for &t in tokens {
+                self.history.push(t);
+                let next = (self.rule)(&self.history) as usize;
+                rows.extend((0..VOCAB).map(|v| if v == next { 8.0f32 } else { 0.0 }));
+            }
+            Ok(Tensor::from_vec(rows, (tokens.len(), VOCAB), &Device::Cpu).unwrap())
+        }
+    }
+
+    fn target_rule(h: &[u32]) -> u32 {
+        (h[h.len() - 1] * 3 + h.len() as u32) % VOCAB as u32
+    }
+
+    /// Agrees with the target except at every fourth length.
+    fn draft_rule(h: &[u32]) -> u32 {
+        if h.len().is_multiple_of(4) {
+            (target_rule(h) + 1) % VOCAB as u32
+        } else {
+            target_rule(h)
+        }
+    }
+
+    fn greedy(temperature: f64) -> Speculation {
+        Speculation {
+            draft_tokens: 4,
+            temperature,
+            seed: 7,
+        }
+    }
+
+    #[test]
+    fn greedy_output_matches_target_alone() {
+        let prompt = [1, 2, 3];
+        let mut expected = prompt.to_vec();
+        for _ in 0..20 {
+            expected.push(target_rule(&expected));
+        }
+
+        let mut draft = Toy::new(draft_rule);
+        let mut target = Toy::new(target_rule);
+        let (out, stats) = speculative_generate(
+            &mut draft,
+            &mut target,
+            &prompt,
+            &[],
+            20,
+            &greedy(0.0),
+            |_, _| Ok(true),
+        )
+        .unwrap();
+        assert_eq!(out, expected[3..]);
+        assert!(stats.accepted > 0 && stats.accepted < stats.drafted);
+        assert!(target.calls < 20, "{} target passes", target.calls);
+    }
+
+    #[test]
+    fn identical_models_accept_every_draft() {
+        let mut draft = Toy::new(target_rule);
+        let mut target = Toy::new(target_rule);
+        let (out, stats) = speculative_generate(
+            &mut draft,
+            &mut target,
+            &[5],
+            &[],
+            10,
+            &greedy(0.7),
+            |_, _| Ok(true),
+        )
+        .unwrap();
+        assert_eq!(out.len(), 10);
+        assert_eq!(stats.accepted, stats.drafted);
+        assert_eq!(stats.acceptance_rate(), 1.0);
+        assert_eq!(stats.rounds, 2);
+    }
+
+    #[test]
+    fn stop_token_and_callback_end_generation() {
+        let prompt = [1, 2, 3];
+        let mut expected = prompt.to_vec();
+        for _ in 0..6 {
+            expected.push(target_rule(&expected));
+        }
+        let stop = expected[7];
+
+        let (out, _) = speculative_generate(
+            &mut Toy::new(draft_rule),
+            &mut Toy::new(target_rule),
+            &prompt,
+            &[stop],
+            20,
+            &greedy(0.0),
+            |_, _| Ok(true),
+        )
+        .unwrap();
+        assert_eq!(out, expected[3..7]);
+
+        let mut seen = 0;
+        let (out, _) = speculative_generate(
+            &mut Toy::new(draft_rule),
+            &mut Toy::new(target_rule),
+            &prompt,
+            &[],
+            20,
+            &greedy(0.0),
+            |_, row| {
+                assert_eq!(row.len(), VOCAB);
+                seen += 1;
+                Ok(seen < 2)
+            },
+        )
+        .unwrap();
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn residual_sampling_prefers_target_mass() {
+        let p = softmax(&[0.0, 2.0, 0.0], 1.0);
+        let q = softmax(&[2.0, 0.0, 0.0], 1.0);
+        let residual: Vec<f32> = p.iter().zip(&q).map(|(a, b)| (a - b).max(0.0)).collect();
+        assert_eq!(residual[0], 0.0);
+        assert_eq!(argmax(&residual), 1);
+        assert_eq!(sample(&[0.0, 1.0, 0.0], 0.999_999), 1);
+    }
+}
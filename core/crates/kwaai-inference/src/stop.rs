@@ -0,0 +1,276 @@
+//! Stop criteria for a generation loop.
+//!
+//! A [`StopCriteria`] is fed every sampled token and decides whether
+//! generation ends there: on a stop token (the model's EOS and the
+//! instruct end-of-turn tokens), on a user-supplied stop string (the
+//! OpenAI `stop` field), or once `max_new_tokens` have been produced.
+//!
+//! It also tracks which part of the decoded text is safe to hand to a
+//! streaming client. Text that could still turn out to be the start of a
+//! stop string is held back until the next token settles it, so a stream
+//! never shows a stop string, or any text after it, and ends exactly where
+//! the non-streaming result does.
+
+use crate::error::{InferenceError, InferenceResult};
+use crate::generation::FinishReason;
+use crate::tokenizer::Tokenizer;
+
+/// Upper bound on stop strings per request, matching the OpenAI API limit.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Stop tokens, stop strings and token budget of one generation.
+#[derive(Debug, Clone)]
+pub struct StopCriteria {
+    stop_ids: Vec<u32>,
+    stop: Vec<String>,
+    max_new_tokens: usize,
+    tokens: Vec<u32>,
+    text: String,
+    /// Bytes of `text` already handed out by [`push`](Self::push).
+    released: usize,
+    finish: Option<FinishReason>,
+}
+
+impl StopCriteria {
+    /// Criteria ending on any of `stop_ids`, any of `stop` or after
+    /// `max_new_tokens` tokens.
+    pub fn new(stop_ids: Vec<u32>, stop: &[String], max_new_tokens: usize) -> Self {
+        Self {
+            stop_ids,
+            stop: stop.to_vec(),
+            max_new_tokens,
+            tokens: Vec::new(),
+            text: String::new(),
+            released: 0,
+            finish: None,
+        }
+    }
+
+    /// Check `stop` against [`MAX_STOP_SEQUENCES`] and reject empty strings.
+    pub fn validate(stop: &[String]) -> InferenceResult<()> {
+        if stop.len() > MAX_STOP_SEQUENCES {
+            return Err(InferenceError::InvalidInput(format!(
+                "at most {MAX_STOP_SEQUENCES} stop sequences are allowed"
+            )));
+        }
+        if stop.iter().any(String::is_empty) {
+            return Err(InferenceError::InvalidInput(
+                "stop sequences must not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Record the next sampled `token` and return the text that became
+    /// final with it, which may be empty.
+    ///
+    /// A stop token is not recorded. A stop string is cut from the text
+    /// together with everything after it. Once finished, further tokens
+    /// are ignored.
+    pub fn push<T: Tokenizer + ?Sized>(
+        &mut self,
+        tokenizer: &T,
+        token: u32,
+    ) -> InferenceResult<String> {
+        if self.finish.is_some() {
+            return Ok(String::new());
+        }
+        if self.tokens.len() >= self.max_new_tokens {
+            return Ok(self.finish(FinishReason::Length));
+        }
+        if self.stop_ids.contains(&token) {
+            return Ok(self.finish(FinishReason::Stop));
+        }
+        self.tokens.push(token);
+        // Decode the whole completion: byte-level pieces only form valid
+        // text together, and some decoders drop a leading space.
+        self.text = tokenizer.decode(&self.tokens)?;
+
+        // A new match can only start in text not yet released.
+        let from = floor_char_boundary(&self.text, self.released);
+        let hit = self
+            .stop
+            .iter()
+            .filter_map(|s| self.text[from..].find(s.as_str()))
+            .min();
+        if let Some(at) = hit {
+            self.text.truncate(from + at);
+            return Ok(self.finish(FinishReason::StopSequence));
+        }
+        if self.tokens.len() >= self.max_new_tokens {
+            return Ok(self.finish(FinishReason::Length));
+        }
+
+        let safe = self.text.len() - self.held_back();
+        if safe <= self.released {
+            return Ok(String::new());
+        }
+        let piece = self.text[self.released..safe].to_string();
+        self.released = safe;
+        Ok(piece)
+    }
+
+    /// End generation early (e.g. a passed deadline) and return the text
+    /// still held back.
+    pub fn finish(&mut self, reason: FinishReason) -> String {
+        if self.finish.is_none() {
+            self.finish = Some(reason);
+        }
+        let start = self.released.min(self.text.len());
+        self.released = self.text.len();
+        self.text[start..].to_string()
+    }
+
+    /// Why generation ended; `None` while it should continue.
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish
+    }
+
+    /// `true` once a stop condition has been met.
+    pub fn is_finished(&self) -> bool {
+        self.finish.is_some()
+    }
+
+    /// Tokens recorded so far (stop tokens excluded).
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// Completion text so far, cut at a matched stop string.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Bytes at the end of the text that must not be streamed yet: an
+    /// incomplete UTF-8 sequence, or a suffix that begins a stop string.
+    fn held_back(&self) -> usize {
+        let unreleased = &self.text[floor_char_boundary(&self.text, self.released)..];
+        if unreleased.ends_with('\u{FFFD}') {
+            return unreleased.len();
+        }
+        self.stop
+            .iter()
+            .filter_map(|s| {
+                unreleased
+                    .char_indices()
+                    .map(|(i, _)| &unreleased[i..])
+                    .find(|tail| s.starts_with(tail))
+                    .map(str::len)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn floor_char_boundary(s: &str, mut i: usize) -> usize {
+    i = i.min(s.len());
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token per character of a fixed alphabet.
+    struct Chars;
+
+    const ALPHABET: &str = "abcdefghij XYZ";
+
+    impl Tokenizer for Chars {
+        fn encode(&self, text: &str) -> InferenceResult<Vec<u32>> {
+            Ok(text
+                .chars()
+                .map(|c| ALPHABET.find(c).unwrap() as u32)
+                .collect())
+        }
+        fn decode(&self, tokens: &[u32]) -> InferenceResult<String> {
+            Ok(tokens
+                .iter()
+                .map(|&t| ALPHABET.as_bytes()[t as usize] as char)
+                .collect())
+        }
+        fn vocab_size(&self) -> usize {
+            ALPHABET.len() + 1
+        }
+        fn bos_token_id(&self) -> Option<u32> {
+            None
+        }
+        fn eos_token_id(&self) -> Option<u32> {
+            Some(EOS)
+        }
+        fn pad_token_id(&self) -> Option<u32> {
+            None
+        }
+        fn token_to_id(&self, _: &str) -> Option<u32> {
+            None
+        }
+    }
+
+    const EOS: u32 = ALPHABET.len() as u32;
+
+    /// Feed `text` then EOS, returning the streamed pieces.
+    fn run(criteria: &mut StopCriteria, text: &str) -> Vec<String> {
+        let mut pieces = Vec::new();
+        for token in Chars.encode(text).unwrap().into_iter().chain([EOS]) {
+            pieces.push(criteria.push(&Chars, token).unwrap());
+            if criteria.is_finished() {
+                break;
+            }
+        }
+        pieces
+    }
+
+    #[test]
+    fn stops_on_eos_without_recording_it() {
+        let mut criteria = StopCriteria::new(vec![EOS], &[], 100);
+        assert_eq!(run(&mut criteria, "abc").concat(), "abc");
+        assert_eq!(criteria.finish_reason(), Some(FinishReason::Stop));
+        assert_eq!(criteria.tokens().len(), 3);
+    }
+
+    #[test]
+    fn stop_string_is_cut_and_never_streamed() {
+        let stop = ["XYZ".to_string()];
+        let mut criteria = StopCriteria::new(vec![EOS], &stop, 100);
+        let pieces = run(&mut criteria, "ab XYZ cd");
+        assert_eq!(criteria.finish_reason(), Some(FinishReason::StopSequence));
+        assert_eq!(criteria.text(), "ab ");
+        assert_eq!(pieces.concat(), "ab ");
+        // Generation ends on the token completing the stop string
+        assert_eq!(criteria.tokens().len(), 6);
+    }
+
+    #[test]
+    fn partial_match_is_held_back_then_released() {
+        let stop = ["XYZ".to_string()];
+        let mut criteria = StopCriteria::new(vec![EOS], &stop, 100);
+        let pieces = run(&mut criteria, "aXYb");
+        assert_eq!(pieces, ["a", "", "", "XYb", ""]);
+        assert_eq!(criteria.finish_reason(), Some(FinishReason::Stop));
+    }
+
+    #[test]
+    fn max_new_tokens_finishes_on_the_last_token() {
+        let stop = ["XYZ".to_string()];
+        let mut criteria = StopCriteria::new(vec![EOS], &stop, 3);
+        let pieces = run(&mut criteria, "abXYZ");
+        assert_eq!(criteria.finish_reason(), Some(FinishReason::Length));
+        assert_eq!(pieces.concat(), "abX");
+
+        let mut none = StopCriteria::new(vec![EOS], &[], 0);
+        assert_eq!(none.push(&Chars, 0).unwrap(), "");
+        assert_eq!(none.finish_reason(), Some(FinishReason::Length));
+        assert!(none.tokens().is_empty());
+    }
+
+    #[test]
+    fn validate_limits_stop_sequences() {
+        let stop = vec!["a".to_string(); MAX_STOP_SEQUENCES];
+        assert!(StopCriteria::validate(&stop).is_ok());
+        assert!(StopCriteria::validate(&[stop, vec!["b".to_string()]].concat()).is_err());
+        assert!(StopCriteria::validate(&[String::new()]).is_err());
+    }
+}
@@ -4,7 +4,14 @@
 //!   - [`BpeTokenizer::from_file`] — loads a `tokenizer.json` from a
 //!     HuggingFace model snapshot (used with SafeTensors models)
 //!   - [`BpeTokenizer::from_gguf`] — builds BPE from the vocabulary and
-//!     merge rules embedded in a GGUF file (used with Ollama GGUF models)
+//!     merge rules embedded in a GGUF file (used with Ollama GGUF models),
+//!     or WordPiece for BERT-family embedding models
+//!
+//! [`BpeTokenizer::for_gguf`] picks between the two for a GGUF model: a
+//! `tokenizer.json` next to the file wins over the embedded vocabulary.
+//!
+//! [`ChatTemplate`] renders chat messages in the prompt format a tokenizer's
+//! vocabulary was trained for (Llama 3, ChatML, Mistral).
 
 use crate::error::{InferenceError, InferenceResult};
 use candle_core::quantized::gguf_file;
@@ -38,6 +45,111 @@ pub trait Tokenizer: Send + Sync {
     /// Look up the numeric ID for a token string (e.g. `"<|im_end|>"`).
     /// Returns `None` if the token is not in the vocabulary.
     fn token_to_id(&self, token: &str) -> Option<u32>;
+
+    /// Number of tokens `text` encodes to (no BOS is counted).
+    fn count_tokens(&self, text: &str) -> InferenceResult<usize> {
+        self.encode(text).map(|t| t.len())
+    }
+
+    /// Chat prompt format matching this vocabulary.
+    fn chat_template(&self) -> ChatTemplate {
+        ChatTemplate::detect(self)
+    }
+}
+
+// ── Chat templates ────────────────────────────────────────────────────────────
+
+/// One turn of a chat conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// `"system"`, `"user"` or `"assistant"`.
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+        }
+    }
+}
+
+/// Prompt format of an instruct model, recognised from its special tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatTemplate {
+    /// `<|start_header_id|>role<|end_header_id|>` … `<|eot_id|>`
+    Llama3,
+    /// `<|im_start|>role` … `<|im_end|>` (Qwen, Phi, Yi, …)
+    ChatMl,
+    /// `[INST] … [/INST]` (Llama 2, Mistral)
+    Mistral,
+    /// No special tokens: `role: content` lines.
+    Plain,
+}
+
+impl ChatTemplate {
+    /// Pick the template whose control tokens are in `tokenizer`'s vocabulary.
+    pub fn detect<T: Tokenizer + ?Sized>(tokenizer: &T) -> Self {
+        if tokenizer.token_to_id("<|start_header_id|>").is_some() {
+            ChatTemplate::Llama3
+        } else if tokenizer.token_to_id("<|im_start|>").is_some() {
+            ChatTemplate::ChatMl
+        } else if tokenizer.token_to_id("[INST]").is_some() {
+            ChatTemplate::Mistral
+        } else {
+            ChatTemplate::Plain
+        }
+    }
+
+    /// Render `messages` as a prompt ending where the assistant's reply
+    /// starts. BOS is not included; generation prepends it.
+    pub fn render(&self, messages: &[ChatMessage]) -> String {
+        let mut out = String::new();
+        match self {
+            ChatTemplate::Llama3 => {
+                for m in messages {
+                    out.push_str(&format!(
+                        "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                        m.role, m.content
+                    ));
+                }
+                out.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            }
+            ChatTemplate::ChatMl => {
+                for m in messages {
+                    out.push_str(&format!(
+                        "<|im_start|>{}\n{}<|im_end|>\n",
+                        m.role, m.content
+                    ));
+                }
+                out.push_str("<|im_start|>assistant\n");
+            }
+            ChatTemplate::Mistral => {
+                // No system role: the system prompt is folded into the next
+                // user turn.
+                let mut system = String::new();
+                for m in messages {
+                    match m.role.as_str() {
+                        "system" => system = format!("{}\n\n", m.content),
+                        "assistant" => out.push_str(&format!(" {}</s>", m.content)),
+                        _ => {
+                            out.push_str(&format!("[INST] {system}{} [/INST]", m.content));
+                            system.clear();
+                        }
+                    }
+                }
+            }
+            ChatTemplate::Plain => {
+                for m in messages {
+                    out.push_str(&format!("{}: {}\n", m.role, m.content));
+                }
+                out.push_str("assistant:");
+            }
+        }
+        out
+    }
 }
 
 // ── BpeTokenizer ──────────────────────────────────────────────────────────────
@@ -68,13 +180,15 @@ impl BpeTokenizer {
         // Special token IDs — try the most common names for each role.
         let bos_id = inner
             .token_to_id("<|begin_of_text|>") // Llama 3
-            .or_else(|| inner.token_to_id("<s>")); // Llama 2, Mistral
+            .or_else(|| inner.token_to_id("<s>")) // Llama 2, Mistral
+            .or_else(|| inner.token_to_id("[CLS]")); // BERT
 
         let eos_id = inner
             .token_to_id("<|eot_id|>") // Llama 3 instruct
             .or_else(|| inner.token_to_id("<|endoftext|>")) // GPT-2 / Qwen
             .or_else(|| inner.token_to_id("</s>")) // Llama 2, Mistral
-            .or_else(|| inner.token_to_id("<|im_end|>")); // ChatML
+            .or_else(|| inner.token_to_id("<|im_end|>")) // ChatML
+            .or_else(|| inner.token_to_id("[SEP]")); // BERT
 
         let pad_id = inner
             .token_to_id("<|finetune_right_pad_id|>") // Llama 3 fine-tunes
@@ -96,6 +210,16 @@ impl BpeTokenizer {
         })
     }
 
+    /// Tokenizer for the GGUF model at `path`: a `tokenizer.json` in the same
+    /// directory if there is one, otherwise the vocabulary embedded in `gguf`.
+    pub fn for_gguf(path: &Path, gguf: &gguf_file::Content) -> InferenceResult<Self> {
+        let sidecar = path.with_file_name("tokenizer.json");
+        if sidecar.is_file() {
+            return Self::from_file(&sidecar);
+        }
+        Self::from_gguf(gguf)
+    }
+
     /// Build a BPE tokenizer from the vocabulary and merge rules embedded
     /// inside a GGUF file (`tokenizer.ggml.tokens` / `tokenizer.ggml.merges`).
     ///
@@ -114,6 +238,11 @@ impl BpeTokenizer {
         })?;
         let n = token_strs.len();
 
+        // BERT-family embedding models ship a WordPiece vocabulary, not BPE.
+        if gguf_string(gguf, "tokenizer.ggml.model").as_deref() == Some("bert") {
+            return Self::from_gguf_wordpiece(gguf, &token_strs);
+        }
+
         // ── Merge rules ──────────────────────────────────────────────────────
         // Each GGUF merge entry is the string "token_a token_b".
         let merges_raw = gguf_string_array(gguf, "tokenizer.ggml.merges").unwrap_or_default();
@@ -236,6 +365,109 @@ impl BpeTokenizer {
             pad_id,
         })
     }
+
+    /// Build a WordPiece tokenizer from a GGUF `bert` / `nomic-bert` vocab.
+    ///
+    /// llama.cpp stores BERT vocabularies in "phantom space" form: word-initial
+    /// pieces carry a leading `▁` and continuation pieces have their `##`
+    /// stripped.  This reverses that mapping to recover the original WordPiece
+    /// vocabulary.  `[CLS]` / `[SEP]` are exposed as BOS / EOS.
+    fn from_gguf_wordpiece(
+        gguf: &gguf_file::Content,
+        token_strs: &[String],
+    ) -> InferenceResult<Self> {
+        use serde_json::{json, Map, Value};
+
+        let vocab_map: Map<String, Value> = token_strs
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (wordpiece_from_phantom(s), Value::Number(i.into())))
+            .collect();
+
+        let bos_id = gguf_u32(gguf, "tokenizer.ggml.cls_token_id")
+            .or_else(|| find_token_id(token_strs, &["[CLS]"]));
+        // llama.cpp spells this key "seperator".
+        let eos_id = gguf_u32(gguf, "tokenizer.ggml.seperator_token_id")
+            .or_else(|| find_token_id(token_strs, &["[SEP]"]));
+        let pad_id = gguf_u32(gguf, "tokenizer.ggml.padding_token_id")
+            .or_else(|| find_token_id(token_strs, &["[PAD]"]));
+
+        let added_tokens: Vec<Value> = token_strs
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.starts_with('[') && s.ends_with(']'))
+            .map(|(i, s)| {
+                json!({
+                    "id": i,
+                    "content": s,
+                    "single_word": false,
+                    "lstrip": false,
+                    "rstrip": false,
+                    "normalized": false,
+                    "special": true
+                })
+            })
+            .collect();
+
+        // GGUF does not record the casing flag; every BERT embedding model
+        // Ollama ships (MiniLM, bge, nomic) is uncased, so lowercase.
+        let tokenizer_json = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": added_tokens,
+            "normalizer": {
+                "type": "BertNormalizer",
+                "clean_text": true,
+                "handle_chinese_chars": true,
+                "strip_accents": null,
+                "lowercase": true
+            },
+            "pre_tokenizer": { "type": "BertPreTokenizer" },
+            "post_processor": null,
+            "decoder": { "type": "WordPiece", "prefix": "##", "cleanup": true },
+            "model": {
+                "type": "WordPiece",
+                "unk_token": "[UNK]",
+                "continuing_subword_prefix": "##",
+                "max_input_chars_per_word": 100,
+                "vocab": Value::Object(vocab_map)
+            }
+        });
+
+        let inner: HfTokenizer =
+            serde_json::from_str(&tokenizer_json.to_string()).map_err(|e| {
+                InferenceError::ModelLoadError(format!(
+                    "Cannot build WordPiece tokenizer from GGUF: {e}"
+                ))
+            })?;
+
+        info!(
+            "Built GGUF WordPiece tokenizer: vocab={}, cls={:?}, sep={:?}",
+            token_strs.len(),
+            bos_id,
+            eos_id,
+        );
+
+        Ok(Self {
+            inner,
+            bos_id,
+            eos_id,
+            pad_id,
+        })
+    }
+}
+
+/// Undo llama.cpp's phantom-space BERT vocab encoding (see
+/// [`BpeTokenizer::from_gguf_wordpiece`]).
+fn wordpiece_from_phantom(tok: &str) -> String {
+    if tok.starts_with('[') && tok.ends_with(']') {
+        tok.to_string()
+    } else if let Some(word) = tok.strip_prefix('\u{2581}') {
+        word.to_string()
+    } else {
+        format!("##{tok}")
+    }
 }
 
 // ── Tokenizer trait impl ──────────────────────────────────────────────────────
@@ -292,6 +524,13 @@ fn gguf_string_array(ct: &gguf_file::Content, key: &str) -> Result<Vec<String>,
     }
 }
 
+fn gguf_string(ct: &gguf_file::Content, key: &str) -> Option<String> {
+    match ct.metadata.get(key)? {
+        gguf_file::Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
 fn gguf_u32_array(ct: &gguf_file::Content, key: &str) -> Option<Vec<u32>> {
     use gguf_file::Value;
     match ct.metadata.get(key)? {
@@ -343,4 +582,73 @@ mod tests {
         fn assert_send_sync<T: Send + Sync>() {}
         assert_send_sync::<BpeTokenizer>();
     }
+
+    /// Vocabulary-only tokenizer for template detection.
+    struct Vocab(&'static [&'static str]);
+
+    impl Tokenizer for Vocab {
+        fn encode(&self, text: &str) -> InferenceResult<Vec<u32>> {
+            Ok(text.split_whitespace().map(|_| 0).collect())
+        }
+        fn decode(&self, _: &[u32]) -> InferenceResult<String> {
+            Ok(String::new())
+        }
+        fn vocab_size(&self) -> usize {
+            self.0.len()
+        }
+        fn bos_token_id(&self) -> Option<u32> {
+            None
+        }
+        fn eos_token_id(&self) -> Option<u32> {
+            None
+        }
+        fn pad_token_id(&self) -> Option<u32> {
+            None
+        }
+        fn token_to_id(&self, token: &str) -> Option<u32> {
+            self.0.iter().position(|t| *t == token).map(|i| i as u32)
+        }
+    }
+
+    #[test]
+    fn test_chat_template_detection() {
+        let llama3 = Vocab(&["<|begin_of_text|>", "<|start_header_id|>"]);
+        let qwen = Vocab(&["<|endoftext|>", "<|im_start|>", "<|im_end|>"]);
+        let mistral = Vocab(&["<s>", "[INST]", "[/INST]"]);
+        assert_eq!(llama3.chat_template(), ChatTemplate::Llama3);
+        assert_eq!(qwen.chat_template(), ChatTemplate::ChatMl);
+        assert_eq!(mistral.chat_template(), ChatTemplate::Mistral);
+        assert_eq!(Vocab(&["a"]).chat_template(), ChatTemplate::Plain);
+        assert_eq!(qwen.count_tokens("two words").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_chat_template_render() {
+        let messages = [
+            ChatMessage::new("system", "Be brief."),
+            ChatMessage::new("user", "Hi"),
+        ];
+        assert_eq!(
+            ChatTemplate::Llama3.render(&messages),
+            "<|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&messages),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Mistral.render(&messages),
+            "[INST] Be brief.\n\nHi [/INST]"
+        );
+    }
+
+    #[test]
+    fn test_wordpiece_from_phantom() {
+        assert_eq!(wordpiece_from_phantom("\u{2581}hello"), "hello");
+        assert_eq!(wordpiece_from_phantom("ing"), "##ing");
+        assert_eq!(wordpiece_from_phantom("[CLS]"), "[CLS]");
+    }
 }
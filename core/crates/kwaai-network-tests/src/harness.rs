@@ -16,10 +16,15 @@
 //!   to test against.
 //! - `RelayHarness::new()` — relay + two clients (one "NAT", one direct). Used by
 //!   the relay connectivity tests.
+//! - `SupervisedNode::new(bootstrap)` — DHT client whose p2pd runs under a
+//!   `DaemonSupervisor`, with a watchdog that re-registers its unary handler,
+//!   redials and re-announces. Used by the chaos tests.
 
 use anyhow::{Context, Result};
-use kwaai_p2p_daemon::{DaemonBuilder, P2PClient, P2PDaemon};
+use kwaai_p2p_daemon::{DaemonBuilder, DaemonSupervisor, P2PClient, P2PDaemon};
 use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempfile::TempDir;
 
 /// Find a free TCP port on localhost.
@@ -31,6 +36,15 @@ pub fn free_port() -> u16 {
         .port()
 }
 
+/// Write a fresh Ed25519 identity key for `DaemonBuilder::with_identity_key`,
+/// so a node keeps its PeerId across daemon restarts.
+pub fn write_identity_key(path: &Path) -> Result<()> {
+    let bytes = libp2p::identity::Keypair::generate_ed25519()
+        .to_protobuf_encoding()
+        .context("encode identity key")?;
+    std::fs::write(path, bytes).with_context(|| format!("write {}", path.display()))
+}
+
 /// A single test p2pd instance with its own socket and tmpdir.
 pub struct TestNode {
     pub daemon: P2PDaemon,
@@ -41,6 +55,8 @@ pub struct TestNode {
     pub socket_addr: String,
     /// TCP P2P listen port (if a specific port was requested via host_addrs)
     pub p2p_port: Option<u16>,
+    /// Identity key file (relay servers only), keeps the PeerId across `restart`
+    identity_key: Option<PathBuf>,
     // Keeps the tempdir alive for the lifetime of the node.
     _tmpdir: TempDir,
 }
@@ -54,9 +70,31 @@ impl TestNode {
         let tmpdir = TempDir::new()?;
         let socket_path = tmpdir.path().join("p2pd.sock");
         let socket_addr = format!("/unix/{}", socket_path.display());
+        let identity_key = tmpdir.path().join("identity.key");
+        write_identity_key(&identity_key)?;
+
+        let (daemon, client, peer_id_hex) =
+            Self::spawn_relay_server(&socket_addr, p2p_port, &identity_key).await?;
+
+        Ok(Self {
+            daemon,
+            client,
+            peer_id_hex,
+            socket_addr,
+            p2p_port: Some(p2p_port),
+            identity_key: Some(identity_key),
+            _tmpdir: tmpdir,
+        })
+    }
 
+    async fn spawn_relay_server(
+        socket_addr: &str,
+        p2p_port: u16,
+        identity_key: &Path,
+    ) -> Result<(P2PDaemon, P2PClient, String)> {
         let mut daemon = DaemonBuilder::new()
-            .with_listen_addr(&socket_addr)
+            .with_listen_addr(socket_addr)
+            .with_identity_key(identity_key)
             .dht_server(true)
             .relay(true)
             .bootstrap(false) // no external bootstrap for local test
@@ -65,17 +103,34 @@ impl TestNode {
             .await
             .context("spawn relay server")?;
 
-        let mut client = daemon.client().await.context("relay client")?;
+        let client = daemon.client().await.context("relay client")?;
         let peer_id_hex = client.identify().await.context("relay identify")?;
+        Ok((daemon, client, peer_id_hex))
+    }
 
-        Ok(Self {
-            daemon,
-            client,
-            peer_id_hex,
-            socket_addr,
-            p2p_port: Some(p2p_port),
-            _tmpdir: tmpdir,
-        })
+    /// Take a relay server down and bring it back on the same port and PeerId.
+    ///
+    /// Every peer loses its connection to this node for `downtime`, which is
+    /// what a network interface flap on the bootstrap host looks like from the
+    /// other side.
+    pub async fn restart(&mut self, downtime: std::time::Duration) -> Result<()> {
+        let (port, key) = match (self.p2p_port, &self.identity_key) {
+            (Some(port), Some(key)) => (port, key.clone()),
+            _ => anyhow::bail!("only relay servers can be restarted in place"),
+        };
+        self.daemon.shutdown().await.context("relay shutdown")?;
+        remove_socket(&self.socket_addr);
+        tokio::time::sleep(downtime).await;
+
+        let (daemon, client, peer_id_hex) =
+            Self::spawn_relay_server(&self.socket_addr, port, &key).await?;
+        anyhow::ensure!(
+            peer_id_hex == self.peer_id_hex,
+            "relay came back with a different PeerId"
+        );
+        self.daemon = daemon;
+        self.client = client;
+        Ok(())
     }
 
     /// Bootstrap multiaddr for this node (only valid when p2p_port is set).
@@ -106,7 +161,7 @@ impl TestNode {
             .await
             .context("spawn dht client")?;
 
-        let mut client = daemon.client().await.context("dht client connect")?;
+        let client = daemon.client().await.context("dht client connect")?;
         let peer_id_hex = client.identify().await.context("dht client identify")?;
 
         Ok(Self {
@@ -115,6 +170,7 @@ impl TestNode {
             peer_id_hex,
             socket_addr,
             p2p_port: None,
+            identity_key: None,
             _tmpdir: tmpdir,
         })
     }
@@ -142,7 +198,7 @@ impl TestNode {
             .await
             .context("spawn nat client")?;
 
-        let mut client = daemon.client().await.context("nat client connect")?;
+        let client = daemon.client().await.context("nat client connect")?;
         let peer_id_hex = client.identify().await.context("nat client identify")?;
 
         Ok(Self {
@@ -151,6 +207,7 @@ impl TestNode {
             peer_id_hex,
             socket_addr,
             p2p_port: None,
+            identity_key: None,
             _tmpdir: tmpdir,
         })
     }
@@ -225,6 +282,214 @@ impl RelayHarness {
     }
 }
 
+/// Unary protocol served by every [`SupervisedNode`]; answers with [`CHAOS_PONG`].
+pub const CHAOS_ECHO_PROTO: &str = "kwaai.test.chaos.echo";
+
+/// Response body of [`CHAOS_ECHO_PROTO`].
+pub const CHAOS_PONG: &[u8] = b"pong";
+
+/// First restart backoff of a [`SupervisedNode`]'s supervisor; the real
+/// default (5 s) would make every chaos round wait on it.
+pub const CHAOS_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Restart backoff cap of a [`SupervisedNode`]'s supervisor.
+pub const CHAOS_MAX_BACKOFF: Duration = Duration::from_secs(4);
+
+/// A DHT client node under a watchdog, for chaos tests.
+///
+/// Models what `kwaainet start` keeps alive for a serving node: p2pd under
+/// the same [`DaemonSupervisor`] with a stable identity key and socket (so
+/// restarts keep the PeerId), a unary handler that other peers call, a
+/// bootstrap connection, and a DHT provider record.
+/// [`SupervisedNode::supervise`] runs one watchdog pass that repairs whatever
+/// the fault injectors (`kill_daemon`, `drop_persistent_connection`,
+/// [`TestNode::restart`] on the bootstrap node) broke.
+pub struct SupervisedNode {
+    pub supervisor: DaemonSupervisor,
+    pub client: P2PClient,
+    /// Peer ID (hex-encoded bytes), stable across restarts
+    pub peer_id_hex: String,
+    /// Provider record this node announces
+    pub cid: Vec<u8>,
+    /// p2pd restarts performed by the supervisor
+    pub restarts: u32,
+    /// Unary handler re-registrations: by the supervisor after a restart, or
+    /// by `supervise` after a dropped connection
+    pub handler_reregistrations: u32,
+    /// Bootstrap reconnects performed by `supervise`
+    pub reconnects: u32,
+    bootstrap_addr: String,
+    _tmpdir: TempDir,
+}
+
+impl SupervisedNode {
+    /// Start the node, register its handler and announce `cid`.
+    pub async fn new(bootstrap_addr: &str, cid: Vec<u8>) -> Result<Self> {
+        Self::start(bootstrap_addr, cid, None).await
+    }
+
+    /// Like [`new`](Self::new), but the supervisor gives up after `limit`
+    /// consecutive failed or short-lived restarts.
+    pub async fn with_restart_limit(
+        bootstrap_addr: &str,
+        cid: Vec<u8>,
+        limit: u32,
+    ) -> Result<Self> {
+        Self::start(bootstrap_addr, cid, Some(limit)).await
+    }
+
+    async fn start(bootstrap_addr: &str, cid: Vec<u8>, limit: Option<u32>) -> Result<Self> {
+        let tmpdir = TempDir::new()?;
+        let socket_path = tmpdir.path().join("p2pd.sock");
+        let identity_key = tmpdir.path().join("identity.key");
+        write_identity_key(&identity_key)?;
+
+        let builder = DaemonBuilder::new()
+            .with_listen_addr(format!("/unix/{}", socket_path.display()))
+            .with_identity_key(&identity_key)
+            .dht(true)
+            .bootstrap(true)
+            .bootstrap_peer(bootstrap_addr);
+        let (supervisor, client) = DaemonSupervisor::spawn(builder)
+            .await
+            .context("spawn supervised node")?;
+        let mut supervisor = supervisor.with_backoff(CHAOS_INITIAL_BACKOFF, CHAOS_MAX_BACKOFF);
+        if let Some(limit) = limit {
+            supervisor = supervisor.with_restart_limit(limit);
+        }
+        let peer_id_hex = client.identify().await.context("supervised identify")?;
+        supervisor
+            .add_unary_handler(&client, CHAOS_ECHO_PROTO, echo, false)
+            .await
+            .context("register echo handler")?;
+
+        let mut node = Self {
+            supervisor,
+            client,
+            peer_id_hex,
+            cid,
+            restarts: 0,
+            handler_reregistrations: 0,
+            reconnects: 0,
+            bootstrap_addr: bootstrap_addr.to_string(),
+            _tmpdir: tmpdir,
+        };
+        node.announce().await?;
+        Ok(node)
+    }
+
+    /// SIGKILL the p2pd child, as an OOM kill or crash would.
+    #[cfg(unix)]
+    pub fn kill_daemon(&mut self) -> Result<()> {
+        let pid = self
+            .supervisor
+            .daemon()
+            .pid()
+            .context("p2pd already exited")?;
+        // SAFETY: signalling a child process we spawned and still own.
+        let rc = unsafe { libc::kill(pid as i32, libc::SIGKILL) };
+        anyhow::ensure!(rc == 0, "kill({pid}) failed");
+        Ok(())
+    }
+
+    /// Close the persistent connection; p2pd drops the unary handler with it.
+    pub async fn drop_persistent_connection(&self) {
+        self.client.close_persistent_connection().await;
+    }
+
+    /// One watchdog pass.
+    ///
+    /// Lets the supervisor restart p2pd if it died (re-registering the unary
+    /// handler), re-registers the handler if only the persistent connection
+    /// is gone, redials the bootstrap node if the node has no peers, and
+    /// re-announces the provider record. Returns whether anything had to be
+    /// repaired.
+    pub async fn supervise(&mut self) -> Result<bool> {
+        let mut repaired = false;
+
+        anyhow::ensure!(
+            self.supervisor.check(&mut self.client).await,
+            "p2pd is down, restart pending"
+        );
+        let restarts = self.supervisor.restarts();
+        if restarts > self.restarts {
+            let peer_id_hex = self
+                .client
+                .identify()
+                .await
+                .context("identify after restart")?;
+            anyhow::ensure!(
+                peer_id_hex == self.peer_id_hex,
+                "p2pd came back with a different PeerId"
+            );
+            self.handler_reregistrations += restarts - self.restarts;
+            self.restarts = restarts;
+            repaired = true;
+        }
+
+        if !self.client.has_persistent_connection().await {
+            self.client
+                .add_unary_handler(CHAOS_ECHO_PROTO, echo, false)
+                .await
+                .context("register echo handler")?;
+            self.handler_reregistrations += 1;
+            repaired = true;
+        }
+
+        if self.client.list_peers().await?.is_empty() {
+            self.client
+                .connect_peer(&self.bootstrap_addr)
+                .await
+                .context("redial bootstrap")?;
+            self.reconnects += 1;
+            repaired = true;
+        }
+
+        self.announce().await?;
+        Ok(repaired)
+    }
+
+    async fn announce(&mut self) -> Result<()> {
+        self.client
+            .dht_provide(self.cid.clone(), Some(10))
+            .await
+            .context("announce provider record")
+    }
+
+    /// Whether `target` is fully serving as seen from this node: its unary
+    /// handler answers and its provider record resolves.
+    pub async fn sees_serving(&mut self, target: &SupervisedNode) -> bool {
+        let Ok(peer) = hex::decode(&target.peer_id_hex) else {
+            return false;
+        };
+        let echo = self
+            .client
+            .call_unary_handler(&peer, CHAOS_ECHO_PROTO, b"ping")
+            .await;
+        if !matches!(echo.as_deref(), Ok(CHAOS_PONG)) {
+            return false;
+        }
+        matches!(
+            self.client
+                .dht_find_providers(target.cid.clone(), 1, Some(5))
+                .await,
+            Ok(Some(_))
+        )
+    }
+}
+
+/// Handler of [`CHAOS_ECHO_PROTO`].
+async fn echo(_req: Vec<u8>) -> kwaai_p2p_daemon::Result<Vec<u8>> {
+    Ok(CHAOS_PONG.to_vec())
+}
+
+/// Remove a dead daemon's Unix socket so a restart can bind the same path.
+fn remove_socket(socket_addr: &str) {
+    if let Some(path) = socket_addr.strip_prefix("/unix/") {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
 /// Decode a hex peer_id back to a libp2p PeerId (for multiaddr construction).
 pub fn peer_id_from_hex(hex_str: &str) -> Option<libp2p::PeerId> {
     let bytes = hex::decode(hex_str).ok()?;
@@ -1,12 +1,15 @@
 //! kwaai-network-tests — connectivity, uptime, and robustness test suite
 //!
-//! # Three test tiers
+//! # Test tiers
 //!
 //! | Tier        | Gate env var               | What runs                          |
 //! |-------------|----------------------------|------------------------------------|
-//! | unit        | (always)                   | codec, value, storage, protocol    |
+//! | unit        | (always)                   | codec, value, storage, protocol,   |
+//! |             |                            | in-process multi-node simulation   |
 //! | integration | `KWAAI_INTEGRATION_TESTS=1`| daemon spawn, DHT, relay topology  |
 //! | network     | `KWAAI_NETWORK_TESTS=1`    | real bootstrap, peer count metrics |
+//! | chaos       | `KWAAI_CHAOS_TESTS=1`      | repeated p2pd kills, connection and |
+//! |             |                            | bootstrap-link faults, recovery    |
 //!
 //! # Running
 //!
@@ -20,6 +23,9 @@
 //! # All tiers
 //! cd core && KWAAI_INTEGRATION_TESTS=1 KWAAI_NETWORK_TESTS=1 cargo test -p kwaai-network-tests
 //!
+//! # Chaos supervision scenario only (slow: minutes)
+//! cd core && KWAAI_CHAOS_TESTS=1 cargo test -p kwaai-network-tests --test 07_chaos_supervision
+//!
 //! # View historical metrics
 //! cd core && cargo run -p kwaai-network-tests --bin metrics-report
 //! ```
@@ -37,12 +43,18 @@ pub fn network_enabled() -> bool {
     std::env::var("KWAAI_NETWORK_TESTS").is_ok()
 }
 
+/// Returns true when chaos (fault-injection) tests should run.
+pub fn chaos_enabled() -> bool {
+    std::env::var("KWAAI_CHAOS_TESTS").is_ok()
+}
+
 /// Convenience: skip a test at runtime if a tier is not enabled.
 ///
 /// Call at the start of integration/network tests:
 /// ```ignore
 /// require_integration!();
 /// require_network!();
+/// require_chaos!();
 /// ```
 #[macro_export]
 macro_rules! require_integration {
@@ -63,3 +75,13 @@ macro_rules! require_network {
         }
     };
 }
+
+#[macro_export]
+macro_rules! require_chaos {
+    () => {
+        if !$crate::chaos_enabled() {
+            eprintln!("Skipping chaos test — set KWAAI_CHAOS_TESTS=1 to enable");
+            return;
+        }
+    };
+}
@@ -71,12 +71,14 @@ fn client_frame_shard_run_roundtrip() {
         content: "What is KwaaiNet?".to_string(),
         model: Some("llama3.2:3b".to_string()),
         conversation_id: None,
+        timeout_ms: Some(30_000),
     };
     let decoded = encode_decode_client_frame(client_frame::Body::ShardRun(req));
     if let Some(client_frame::Body::ShardRun(s)) = decoded.body {
         assert_eq!(s.role, "user");
         assert_eq!(s.model.as_deref(), Some("llama3.2:3b"));
         assert!(s.conversation_id.is_none());
+        assert_eq!(s.timeout_ms, Some(30_000));
     } else {
         panic!("wrong variant");
     }
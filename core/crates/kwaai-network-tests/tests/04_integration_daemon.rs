@@ -69,7 +69,7 @@ async fn daemon_identify_returns_stable_peer_id() {
         "integration",
     );
 
-    let mut node = TestNode::new_relay_server().await.expect("node start");
+    let node = TestNode::new_relay_server().await.expect("node start");
 
     let id1 = node.client.identify().await.expect("identify 1");
     let id2 = node.client.identify().await.expect("identify 2");
@@ -87,7 +87,7 @@ async fn daemon_identify_with_addrs_returns_listen_addrs() {
     require_integration!();
     let mut rec = MetricsRecorder::start("integration::daemon::identify_with_addrs", "integration");
 
-    let mut node = TestNode::new_relay_server().await.expect("node start");
+    let node = TestNode::new_relay_server().await.expect("node start");
 
     let (peer_id, addrs) = node
         .client
@@ -155,7 +155,7 @@ async fn dht_provide_and_find_connected_node() {
     let relay = TestNode::new_relay_server().await.expect("relay start");
     let bootstrap = relay.bootstrap_multiaddr().expect("relay p2p addr");
 
-    let mut node = TestNode::new_dht_client(&bootstrap)
+    let node = TestNode::new_dht_client(&bootstrap)
         .await
         .expect("dht client start");
 
@@ -197,10 +197,10 @@ async fn dht_provide_node_a_find_node_b() {
     let relay = TestNode::new_relay_server().await.expect("relay start");
     let bootstrap = relay.bootstrap_multiaddr().expect("relay p2p addr");
 
-    let mut node_a = TestNode::new_dht_client(&bootstrap)
+    let node_a = TestNode::new_dht_client(&bootstrap)
         .await
         .expect("node_a start");
-    let mut node_b = TestNode::new_dht_client(&bootstrap)
+    let node_b = TestNode::new_dht_client(&bootstrap)
         .await
         .expect("node_b start");
 
@@ -242,7 +242,7 @@ async fn dht_get_missing_key_returns_error() {
         "integration",
     );
 
-    let mut node = TestNode::new_relay_server().await.expect("node start");
+    let node = TestNode::new_relay_server().await.expect("node start");
 
     // dht_get_value returns Err when key is not found
     let result = node
@@ -269,7 +269,7 @@ async fn list_peers_after_connect() {
     let relay = TestNode::new_relay_server().await.expect("relay start");
     let bootstrap = relay.bootstrap_multiaddr().expect("relay p2p addr");
 
-    let mut client = TestNode::new_dht_client(&bootstrap)
+    let client = TestNode::new_dht_client(&bootstrap)
         .await
         .expect("client start");
 
@@ -298,7 +298,7 @@ async fn find_peer_returns_known_peer() {
     let relay = TestNode::new_relay_server().await.expect("relay start");
     let bootstrap = relay.bootstrap_multiaddr().expect("relay p2p addr");
 
-    let mut client = TestNode::new_dht_client(&bootstrap)
+    let client = TestNode::new_dht_client(&bootstrap)
         .await
         .expect("client start");
 
@@ -116,7 +116,7 @@ async fn observer_finds_nat_node_via_dht() {
         "integration",
     );
 
-    let mut harness = RelayHarness::new().await.expect("relay harness startup");
+    let harness = RelayHarness::new().await.expect("relay harness startup");
 
     // Give DHT routing tables time to propagate
     tokio::time::sleep(Duration::from_secs(2)).await;
@@ -216,7 +216,7 @@ async fn dht_provide_by_nat_node_findable_by_observer() {
         "integration",
     );
 
-    let mut harness = RelayHarness::new().await.expect("relay harness startup");
+    let harness = RelayHarness::new().await.expect("relay harness startup");
 
     // Let DHT bootstrap and relay reservation settle
     tokio::time::sleep(Duration::from_secs(2)).await;
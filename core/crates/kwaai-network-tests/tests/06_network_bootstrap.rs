@@ -59,7 +59,7 @@ async fn peer_count_after_settling() {
     let mut rec = MetricsRecorder::start("network::bootstrap::peer_count", "network");
 
     let bootstrap_addr = KWAAI_BOOTSTRAP_SERVERS[0];
-    let mut node = TestNode::new_dht_client(bootstrap_addr)
+    let node = TestNode::new_dht_client(bootstrap_addr)
         .await
         .expect("node start");
 
@@ -93,7 +93,7 @@ async fn dht_roundtrip_latency() {
     let mut rec = MetricsRecorder::start("network::dht::roundtrip_latency", "network");
 
     let bootstrap_addr = KWAAI_BOOTSTRAP_SERVERS[0];
-    let mut node = TestNode::new_dht_client(bootstrap_addr)
+    let node = TestNode::new_dht_client(bootstrap_addr)
         .await
         .expect("node start");
 
@@ -138,7 +138,7 @@ async fn vpk_nodes_discoverable() {
     let mut rec = MetricsRecorder::start("network::dht::vpk_nodes_discoverable", "network");
 
     let bootstrap_addr = KWAAI_BOOTSTRAP_SERVERS[0];
-    let mut node = TestNode::new_dht_client(bootstrap_addr)
+    let node = TestNode::new_dht_client(bootstrap_addr)
         .await
         .expect("node start");
 
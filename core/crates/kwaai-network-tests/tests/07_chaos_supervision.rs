@@ -0,0 +1,232 @@
+//! Chaos scenario for daemon supervision.
+//!
+//! Repeatedly breaks a serving node and asserts that its watchdog
+//! (`SupervisedNode::supervise`, around the node's real `DaemonSupervisor`)
+//! restores full service within a bounded time. "Full service" means a second
+//! node can call the target's unary handler and resolve its DHT provider
+//! record. A second scenario kills p2pd in a tight loop and checks that the
+//! supervisor backs off between restarts and gives up at its restart limit.
+//!
+//! Faults injected each round, one at a time:
+//!
+//!   1. `kill`  — SIGKILL the target's p2pd child.
+//!   2. `drop`  — close the target's persistent connection, so p2pd drops its
+//!      unary handler.
+//!   3. `flap`  — disconnect the target from every peer and bounce the bootstrap
+//!      node for a few seconds. A real interface flap needs root and a network
+//!      namespace; from the target's side this produces the same effect: every
+//!      connection drops and the bootstrap address is briefly unreachable.
+//!
+//! Topology:
+//! ```text
+//! [relay_server]  (DHT server + relay, known TCP port, stable PeerId)
+//!     │                  │
+//! [target]           [observer]   (both SupervisedNode)
+//! ```
+//!
+//! Gate: `KWAAI_CHAOS_TESTS=1` (slow: several minutes)
+
+#![cfg(unix)]
+
+use kwaai_network_tests::{
+    harness::{SupervisedNode, TestNode, CHAOS_INITIAL_BACKOFF},
+    metrics::MetricsRecorder,
+    require_chaos,
+};
+use kwaai_p2p_daemon::DaemonEvent;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+/// Rounds of the full fault cycle.
+const ROUNDS: u32 = 3;
+/// Upper bound on time from fault injection to full service.
+const RECOVERY_BUDGET: Duration = Duration::from_secs(45);
+/// Watchdog period while recovering.
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the bootstrap node stays down during a flap.
+const FLAP_DOWNTIME: Duration = Duration::from_secs(3);
+/// Consecutive quick crashes the restart-limit scenario tolerates.
+const RESTART_LIMIT: u32 = 3;
+/// How often the restart-limit scenario polls the supervisor.
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+fn sha256_multihash(data: &[u8]) -> Vec<u8> {
+    let hash = Sha256::digest(data);
+    let mut mh = vec![0x12u8, 0x20]; // sha2-256 function code + 32-byte length
+    mh.extend_from_slice(&hash);
+    mh
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    Kill,
+    Drop,
+    Flap,
+}
+
+impl Fault {
+    const ALL: [Fault; 3] = [Fault::Kill, Fault::Drop, Fault::Flap];
+
+    fn name(self) -> &'static str {
+        match self {
+            Fault::Kill => "kill",
+            Fault::Drop => "drop",
+            Fault::Flap => "flap",
+        }
+    }
+}
+
+async fn inject(fault: Fault, target: &mut SupervisedNode, relay: &mut TestNode) {
+    match fault {
+        Fault::Kill => target.kill_daemon().expect("kill p2pd"),
+        Fault::Drop => target.drop_persistent_connection().await,
+        Fault::Flap => {
+            for peer in target.client.list_peers().await.unwrap_or_default() {
+                let _ = target.client.disconnect_peer(&peer.id).await;
+            }
+            relay.restart(FLAP_DOWNTIME).await.expect("bounce relay");
+        }
+    }
+}
+
+/// Run both watchdogs until `observer` sees `target` serving. Returns the
+/// time taken, or `None` if the budget ran out.
+async fn recover(target: &mut SupervisedNode, observer: &mut SupervisedNode) -> Option<Duration> {
+    let start = Instant::now();
+    while start.elapsed() < RECOVERY_BUDGET {
+        // A failed pass (e.g. bootstrap still down) is retried on the next tick.
+        let _ = target.supervise().await;
+        let _ = observer.supervise().await;
+        if observer.sees_serving(target).await {
+            return Some(start.elapsed());
+        }
+        tokio::time::sleep(SUPERVISE_INTERVAL).await;
+    }
+    None
+}
+
+#[tokio::test]
+async fn supervision_restores_service_after_repeated_faults() {
+    require_chaos!();
+    let mut rec = MetricsRecorder::start("chaos::supervision::repeated_faults", "chaos");
+
+    let mut relay = TestNode::new_relay_server().await.expect("relay start");
+    let bootstrap = relay.bootstrap_multiaddr().expect("relay p2p addr");
+
+    let mut target = SupervisedNode::new(&bootstrap, sha256_multihash(b"kwaai.chaos.target"))
+        .await
+        .expect("target start");
+    let mut observer = SupervisedNode::new(&bootstrap, sha256_multihash(b"kwaai.chaos.observer"))
+        .await
+        .expect("observer start");
+
+    let initial = recover(&mut target, &mut observer)
+        .await
+        .expect("target never became reachable before any fault");
+    rec.metric("initial_ms", initial.as_millis() as u64);
+
+    let mut worst = Duration::ZERO;
+    for round in 1..=ROUNDS {
+        for fault in Fault::ALL {
+            inject(fault, &mut target, &mut relay).await;
+            let took = recover(&mut target, &mut observer)
+                .await
+                .unwrap_or_else(|| {
+                    panic!(
+                        "round {round}: service not restored within {:?} after {}",
+                        RECOVERY_BUDGET,
+                        fault.name()
+                    )
+                });
+            rec.metric(
+                format!("round{round}_{}_ms", fault.name()),
+                took.as_millis() as u64,
+            );
+            worst = worst.max(took);
+        }
+    }
+
+    rec.metric("worst_recovery_ms", worst.as_millis() as u64);
+    rec.metric("restarts", target.restarts);
+    rec.metric("handler_reregistrations", target.handler_reregistrations);
+    rec.metric("reconnects", target.reconnects);
+
+    // Every kill must have been caught by the watchdog, and every kill or drop
+    // must have been followed by re-registering the handler.
+    assert_eq!(target.restarts, ROUNDS);
+    assert!(target.handler_reregistrations >= 2 * ROUNDS);
+    rec.finish(true);
+}
+
+/// Poll the supervisor until it has restarted p2pd `restarts` times.
+/// Returns the time taken, or `None` if the budget ran out.
+async fn await_restart(node: &mut SupervisedNode, restarts: u32) -> Option<Duration> {
+    let start = Instant::now();
+    while start.elapsed() < RECOVERY_BUDGET {
+        if node.supervisor.check(&mut node.client).await && node.supervisor.restarts() == restarts {
+            return Some(start.elapsed());
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+    None
+}
+
+#[tokio::test]
+async fn supervisor_backs_off_and_stops_at_restart_limit() {
+    require_chaos!();
+    let mut rec = MetricsRecorder::start("chaos::supervision::restart_limit", "chaos");
+
+    let relay = TestNode::new_relay_server().await.expect("relay start");
+    let bootstrap = relay.bootstrap_multiaddr().expect("relay p2p addr");
+    let mut node = SupervisedNode::with_restart_limit(
+        &bootstrap,
+        sha256_multihash(b"kwaai.chaos.crashloop"),
+        RESTART_LIMIT,
+    )
+    .await
+    .expect("node start");
+    let mut events = node.supervisor.subscribe();
+
+    // Every kill lands well within a minute of the last start, so each one
+    // counts as a failure and the backoff doubles.
+    for failures in 1..=RESTART_LIMIT {
+        node.kill_daemon().expect("kill p2pd");
+        let took = await_restart(&mut node, failures)
+            .await
+            .unwrap_or_else(|| panic!("crash {failures}: p2pd not restarted"));
+        let backoff = CHAOS_INITIAL_BACKOFF * (1 << (failures - 1));
+        assert!(
+            took >= backoff,
+            "crash {failures}: restarted after {took:?}, before its {backoff:?} backoff"
+        );
+        rec.metric(
+            format!("crash{failures}_restart_ms"),
+            took.as_millis() as u64,
+        );
+
+        // The supervisor registered the handler again on the new daemon.
+        node.supervise().await.expect("watchdog pass");
+        assert_eq!(node.restarts, failures);
+        assert_eq!(node.handler_reregistrations, failures);
+    }
+
+    // One quick crash more is over the limit: p2pd stays down.
+    node.kill_daemon().expect("kill p2pd");
+    let start = Instant::now();
+    while node.supervisor.check(&mut node.client).await {
+        assert!(start.elapsed() < RECOVERY_BUDGET, "kill not noticed");
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+    tokio::time::sleep(CHAOS_INITIAL_BACKOFF * (1 << RESTART_LIMIT)).await;
+    assert!(!node.supervisor.check(&mut node.client).await);
+    assert_eq!(node.supervisor.restarts(), RESTART_LIMIT);
+
+    let mut gave_up = None;
+    while let Ok(event) = events.try_recv() {
+        if let DaemonEvent::GaveUp { failures } = event {
+            gave_up = Some(failures);
+        }
+    }
+    assert_eq!(gave_up, Some(RESTART_LIMIT + 1));
+    rec.finish(true);
+}
@@ -0,0 +1,97 @@
+//! Multi-node tests on the in-process simulation harness
+//! (`kwaai_p2p::testing`): N `KwaaiNetwork` nodes on the memory transport.
+//!
+//! No sockets, no daemon, no env vars; these run on every
+//! `cargo test -p kwaai-network-tests`.
+
+use kwaai_network_tests::metrics::MetricsRecorder;
+use kwaai_p2p::{
+    hivemind::ExpertUID,
+    rpc::{RpcRequest, RpcResponse},
+    testing::SimNetwork,
+    ServerInfo,
+};
+use std::time::Duration;
+
+const CONVERGE: Duration = Duration::from_secs(20);
+
+#[tokio::test]
+async fn star_bootstrap_converges() {
+    let mut rec = MetricsRecorder::start("unit::sim::star_bootstrap_converges", "unit");
+    let mut sim = SimNetwork::new(6).await.unwrap();
+    sim.bootstrap_star().await.unwrap();
+    sim.await_convergence(CONVERGE).await.unwrap();
+
+    for i in 0..sim.len() {
+        for j in 0..sim.len() {
+            assert!(i == j || sim.knows(i, &sim.peer_id(j)), "{i} misses {j}");
+        }
+    }
+    rec.metric("nodes", sim.len());
+    rec.finish(true);
+}
+
+#[tokio::test]
+async fn announce_then_lookup_from_every_node() {
+    let rec = MetricsRecorder::start("unit::sim::announce_then_lookup", "unit");
+    let mut sim = SimNetwork::new(5).await.unwrap();
+    sim.bootstrap_star().await.unwrap();
+    sim.await_convergence(CONVERGE).await.unwrap();
+
+    sim.announce(2, "llama.0", b"server-2".to_vec())
+        .await
+        .unwrap();
+    for i in 0..sim.len() {
+        assert_eq!(
+            sim.lookup(i, "llama.0").await.unwrap(),
+            Some(b"server-2".to_vec()),
+            "lookup from node {i}"
+        );
+    }
+    assert_eq!(sim.lookup(4, "llama.1").await.unwrap(), None);
+    rec.finish(true);
+}
+
+#[tokio::test]
+async fn providers_found_across_a_chain() {
+    let mut rec = MetricsRecorder::start("unit::sim::providers_across_chain", "unit");
+    let mut sim = SimNetwork::new(4).await.unwrap();
+    sim.bootstrap_chain().await.unwrap();
+    sim.await_convergence(CONVERGE).await.unwrap();
+
+    sim.provide(0, "inference:llama").await.unwrap();
+    sim.provide(1, "inference:llama").await.unwrap();
+    let providers = sim.find_providers(3, "inference:llama").await.unwrap();
+    assert!(providers.contains(&sim.peer_id(0)));
+    assert!(providers.contains(&sim.peer_id(1)));
+    rec.metric("providers", providers.len());
+    rec.finish(true);
+}
+
+#[tokio::test]
+async fn rpc_routes_to_the_serving_node() {
+    let rec = MetricsRecorder::start("unit::sim::rpc_routes_to_server", "unit");
+    let mut sim = SimNetwork::new(3).await.unwrap();
+    sim.serve_rpc(2, ServerInfo::new("sim-server").with_span(0, 8));
+
+    let info = |uid: &str| RpcRequest::Info(ExpertUID { uid: uid.into() });
+    match sim.rpc(0, 2, info("")).await.unwrap() {
+        RpcResponse::Info(_) => {}
+        other => panic!("expected server info, got {other:?}"),
+    }
+    // A node without a handler answers with an error, not silence.
+    match sim.rpc(0, 1, info("")).await.unwrap() {
+        RpcResponse::Error(_) => {}
+        other => panic!("expected an error, got {other:?}"),
+    }
+    rec.finish(true);
+}
+
+#[tokio::test]
+async fn peer_ids_are_stable_across_runs() {
+    let a = SimNetwork::new(2).await.unwrap();
+    let b = SimNetwork::new(2).await.unwrap();
+    assert_eq!(a.peer_id(0), b.peer_id(0));
+    assert_eq!(a.peer_id(1), b.peer_id(1));
+    assert_ne!(a.peer_id(0), a.peer_id(1));
+}
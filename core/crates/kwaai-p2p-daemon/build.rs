@@ -156,6 +156,46 @@ fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=proto/p2pd.proto");
 
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    // Without the `bundled-p2pd` feature Go is never invoked: the daemon is
+    // whatever `P2PD_PATH` names at build time, else `p2pd` looked up on PATH.
+    let daemon_binary = if env::var_os("CARGO_FEATURE_BUNDLED_P2PD").is_some() {
+        build_bundled_p2pd(&out_dir)
+    } else {
+        println!("cargo:rerun-if-env-changed=P2PD_PATH");
+        PathBuf::from(env::var("P2PD_PATH").unwrap_or_else(|_| "p2pd".to_string()))
+    };
+
+    let proto_dst = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("proto")
+        .join("p2pd.proto");
+
+    // 6. Ensure protoc is available
+    ensure_protoc(&out_dir);
+
+    // 7. Generate Rust protobuf code
+    if proto_dst.exists() {
+        println!("cargo:warning=Generating Rust code from p2pd.proto...");
+
+        prost_build::Config::new()
+            .out_dir(&out_dir)
+            .compile_protos(
+                &[proto_dst],
+                &[PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("proto")],
+            )
+            .expect("Failed to compile protobuf");
+
+        println!("cargo:warning=Successfully generated protobuf Rust code");
+    }
+
+    // 7. Set environment variable for runtime daemon path
+    println!("cargo:rustc-env=P2PD_PATH={}", daemon_binary.display());
+}
+
+/// Clone go-libp2p-daemon and build `p2pd` into the target directory.
+/// Returns the path of the built binary.
+fn build_bundled_p2pd(out_dir: &Path) -> PathBuf {
     // 1. Check if Go is installed
     let go_version = Command::new("go").arg("version").output();
 
@@ -180,7 +220,6 @@ fn main() {
     }
 
     // 2. Setup paths
-    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let repo_dir = out_dir.join("go-libp2p-daemon");
 
     let profile = env::var("PROFILE").unwrap_or_else(|_| "debug".to_string());
@@ -298,25 +337,6 @@ fn main() {
         println!("cargo:warning=Copied p2pd.proto to proto/");
     }
 
-    // 6. Ensure protoc is available
-    ensure_protoc(&out_dir);
-
-    // 7. Generate Rust protobuf code
-    if proto_dst.exists() {
-        println!("cargo:warning=Generating Rust code from p2pd.proto...");
-
-        prost_build::Config::new()
-            .out_dir(&out_dir)
-            .compile_protos(
-                &[proto_dst],
-                &[PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("proto")],
-            )
-            .expect("Failed to compile protobuf");
-
-        println!("cargo:warning=Successfully generated protobuf Rust code");
-    }
-
-    // 7. Set environment variable for runtime daemon path
-    println!("cargo:rustc-env=P2PD_PATH={}", daemon_binary.display());
     println!("cargo:rustc-env=P2PD_REPO={}", repo_dir.display());
+    daemon_binary
 }
@@ -1,7 +1,8 @@
 //! Client for communicating with the p2p daemon via IPC
 //!
 //! This module provides async I/O over:
-//! - Windows: TCP socket (since Go daemon doesn't support named pipes in multiaddr format)
+//! - Windows: named pipes (`/pipe/<name>`), or a localhost TCP socket when
+//!   the p2pd binary cannot listen on a pipe
 //! - Unix: Unix domain sockets
 
 use crate::error::{Error, Result};
@@ -12,9 +13,11 @@ use crate::protocol::p2pd::{
 use bytes::{BufMut, BytesMut};
 use prost::Message;
 use std::{
+    collections::HashMap,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, OnceLock, Weak},
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
@@ -22,14 +25,167 @@ use tokio::sync::Mutex;
 use tracing::{debug, trace};
 use unsigned_varint::encode as varint_encode;
 
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
 #[cfg(unix)]
 use tokio::net::UnixStream;
 
+/// Address prefix for Windows named pipes: `/pipe/<name>` means
+/// `\\.\pipe\<name>`. Multiaddrs have no pipe protocol, so this form is only
+/// understood by this crate and by p2pd's `-listenPipe` flag.
+pub const PIPE_ADDR_PREFIX: &str = "/pipe/";
+
+/// Windows path of the named pipe behind a `/pipe/<name>` address, or `None`
+/// if `addr` is not a valid pipe address.
+pub fn pipe_path(addr: &str) -> Option<String> {
+    let name = addr.strip_prefix(PIPE_ADDR_PREFIX)?;
+    (!name.is_empty() && !name.contains(['/', '\\'])).then(|| format!(r"\\.\pipe\{name}"))
+}
+
+/// Idle control connections kept per daemon address
+const MAX_IDLE_CONNECTIONS: usize = 8;
+
 /// Client for communicating with the p2p daemon
+///
+/// Requests run on pooled control connections shared by every client of the
+/// same daemon address, so concurrent requests don't wait on each other.
+/// Clones are cheap and share the pool and the persistent connection.
+#[derive(Clone)]
 pub struct P2PClient {
-    stream: DaemonStream,
+    pool: Arc<ConnectionPool>,
     persistent: Arc<Mutex<Option<Arc<PersistentConnection>>>>,
-    daemon_addr: String,
+    timeout: Option<Duration>,
+}
+
+/// Control connections to one daemon. p2pd answers one request at a time
+/// per connection, so each in-flight request checks out its own.
+struct ConnectionPool {
+    addr: String,
+    idle: std::sync::Mutex<Vec<DaemonStream>>,
+}
+
+impl ConnectionPool {
+    /// The pool for `addr`, shared with every live client of that daemon
+    fn for_addr(addr: &str) -> Arc<Self> {
+        static POOLS: OnceLock<std::sync::Mutex<HashMap<String, Weak<ConnectionPool>>>> =
+            OnceLock::new();
+        let mut pools = POOLS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(pool) = pools.get(addr).and_then(Weak::upgrade) {
+            return pool;
+        }
+        pools.retain(|_, pool| pool.strong_count() > 0);
+        let pool = Arc::new(Self {
+            addr: addr.to_string(),
+            idle: std::sync::Mutex::new(Vec::new()),
+        });
+        pools.insert(addr.to_string(), Arc::downgrade(&pool));
+        pool
+    }
+
+    /// An idle connection, or a new one if none is left
+    async fn checkout(self: &Arc<Self>) -> Result<PooledConn> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        match idle {
+            Some(stream) => Ok(PooledConn {
+                stream,
+                reused: true,
+                pool: self.clone(),
+            }),
+            None => self.connect().await,
+        }
+    }
+
+    async fn connect(self: &Arc<Self>) -> Result<PooledConn> {
+        Ok(PooledConn {
+            stream: P2PClient::connect_stream(&self.addr).await?,
+            reused: false,
+            pool: self.clone(),
+        })
+    }
+
+    fn checkin(&self, stream: DaemonStream) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(stream);
+        }
+    }
+}
+
+/// A control connection checked out of the pool
+///
+/// Dropping it closes the connection; [`release`](Self::release) hands it back
+/// for reuse once a request has been read completely.
+pub(crate) struct PooledConn {
+    stream: DaemonStream,
+    reused: bool,
+    pool: Arc<ConnectionPool>,
+}
+
+impl PooledConn {
+    /// Write one framed request and read the framed response
+    async fn round_trip(&mut self, request: &Request) -> Result<Response> {
+        debug!("Request type field = {}", request.r#type);
+
+        let mut buf = BytesMut::new();
+        request
+            .encode(&mut buf)
+            .map_err(|e| Error::Protocol(format!("Failed to encode request: {}", e)))?;
+
+        // Write framed message: [varint length][protobuf]
+        let mut len_buf = varint_encode::u64_buffer();
+        let len_bytes = varint_encode::u64(buf.len() as u64, &mut len_buf);
+        let mut frame = BytesMut::with_capacity(len_bytes.len() + buf.len());
+        frame.put_slice(len_bytes);
+        frame.put_slice(&buf);
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+
+        let response_bytes = self.read_framed().await?;
+        trace!("Received response ({} bytes)", response_bytes.len());
+
+        Response::decode(&response_bytes[..])
+            .map_err(|e| Error::Protocol(format!("Failed to decode response: {}", e)))
+    }
+
+    /// Read a framed message (varint length + payload)
+    pub(crate) async fn read_framed(&mut self) -> Result<Vec<u8>> {
+        let mut len_bytes = Vec::new();
+        let mut byte = [0u8; 1];
+
+        // Read varint byte by byte (up to 10 bytes for u64)
+        for _ in 0..10 {
+            self.stream.read_exact(&mut byte).await?;
+            len_bytes.push(byte[0]);
+
+            // Check if this is the last byte (MSB is 0)
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+
+        let mut cursor = &len_bytes[..];
+        let len = match unsigned_varint::io::read_u64(&mut cursor) {
+            Ok(l) => l as usize,
+            Err(e) => return Err(Error::Protocol(format!("Failed to decode varint: {}", e))),
+        };
+
+        if len > 10 * 1024 * 1024 {
+            // 10MB sanity check
+            return Err(Error::Protocol(format!("Message too large: {} bytes", len)));
+        }
+
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+
+    /// Return the connection to the pool
+    pub(crate) fn release(self) {
+        self.pool.checkin(self.stream);
+    }
 }
 
 /// Platform-specific stream abstraction (private implementation detail)
@@ -37,6 +193,8 @@ enum DaemonStream {
     Tcp(TcpStream),
     #[cfg(unix)]
     UnixSocket(UnixStream),
+    #[cfg(windows)]
+    NamedPipe(NamedPipeClient),
 }
 
 /// Raw bidirectional stream to the p2p daemon, usable as a data channel after `stream_open_raw`.
@@ -45,74 +203,142 @@ enum DaemonStream {
 /// independent send and receive halves for concurrent I/O.
 pub struct P2PStream(DaemonStream);
 
-impl AsyncRead for P2PStream {
+impl AsyncRead for DaemonStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        match &mut self.0 {
+        match &mut *self {
             DaemonStream::Tcp(tcp) => Pin::new(tcp).poll_read(cx, buf),
             #[cfg(unix)]
             DaemonStream::UnixSocket(sock) => Pin::new(sock).poll_read(cx, buf),
+            #[cfg(windows)]
+            DaemonStream::NamedPipe(pipe) => Pin::new(pipe).poll_read(cx, buf),
         }
     }
 }
 
-impl AsyncWrite for P2PStream {
+impl AsyncWrite for DaemonStream {
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        match &mut self.0 {
+        match &mut *self {
             DaemonStream::Tcp(tcp) => Pin::new(tcp).poll_write(cx, buf),
             #[cfg(unix)]
             DaemonStream::UnixSocket(sock) => Pin::new(sock).poll_write(cx, buf),
+            #[cfg(windows)]
+            DaemonStream::NamedPipe(pipe) => Pin::new(pipe).poll_write(cx, buf),
         }
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        match &mut self.0 {
+        match &mut *self {
             DaemonStream::Tcp(tcp) => Pin::new(tcp).poll_flush(cx),
             #[cfg(unix)]
             DaemonStream::UnixSocket(sock) => Pin::new(sock).poll_flush(cx),
+            #[cfg(windows)]
+            DaemonStream::NamedPipe(pipe) => Pin::new(pipe).poll_flush(cx),
         }
     }
 
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        match &mut self.0 {
+        match &mut *self {
             DaemonStream::Tcp(tcp) => Pin::new(tcp).poll_shutdown(cx),
             #[cfg(unix)]
             DaemonStream::UnixSocket(sock) => Pin::new(sock).poll_shutdown(cx),
+            #[cfg(windows)]
+            DaemonStream::NamedPipe(pipe) => Pin::new(pipe).poll_shutdown(cx),
         }
     }
 }
 
+impl AsyncRead for P2PStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for P2PStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
 impl P2PClient {
     /// Connect to the daemon at the given address
     ///
-    /// - **Windows**: `addr` should be a multiaddr like `/ip4/127.0.0.1/tcp/5005`
+    /// - **Windows**: `addr` should be a pipe address like `/pipe/kwaai-p2pd`,
+    ///   or a multiaddr like `/ip4/127.0.0.1/tcp/5005`
     /// - **Unix**: `addr` should be a multiaddr like `/unix/tmp/p2pd.sock`
     pub async fn connect(addr: &str) -> Result<Self> {
         debug!("Connecting to daemon at: {}", addr);
 
-        let stream = Self::connect_stream(addr).await?;
+        // Dial once up front so an unreachable daemon fails here rather than
+        // on the first request.
+        let pool = ConnectionPool::for_addr(addr);
+        pool.checkin(Self::connect_stream(addr).await?);
 
         debug!("Connected to daemon");
 
         Ok(Self {
-            stream,
+            pool,
             persistent: Arc::new(Mutex::new(None)),
-            daemon_addr: addr.to_string(),
+            timeout: None,
         })
     }
 
+    /// Address of the daemon this client is connected to
+    pub fn daemon_addr(&self) -> &str {
+        &self.pool.addr
+    }
+
+    /// A client sharing this one's connections whose requests fail with
+    /// [`Error::Timeout`] after `timeout`
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self.clone()
+        }
+    }
+
+    /// Run `fut` under this client's request timeout, if any
+    pub(crate) async fn timed<T>(
+        &self,
+        fut: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| Error::Timeout)?,
+            None => fut.await,
+        }
+    }
+
     async fn connect_stream(addr: &str) -> Result<DaemonStream> {
         // Parse multiaddr to get the actual address
         // For simplicity, we'll support:
         // - /ip4/127.0.0.1/tcp/PORT -> TCP connection
         // - /unix/path -> Unix socket connection
+        // - /pipe/name -> Windows named pipe connection
 
         // Retry connection in case daemon is still starting
         let mut attempts = 0;
@@ -182,6 +408,46 @@ impl P2PClient {
                     "Unix sockets not supported on this platform".to_string(),
                 ));
             }
+        } else if let Some(pipe) = pipe_path(addr) {
+            #[cfg(windows)]
+            {
+                // Every pipe instance is connected; the daemon creates another
+                // as soon as it accepts, so retry like a refused connection.
+                const ERROR_PIPE_BUSY: i32 = 231;
+
+                loop {
+                    match ClientOptions::new().open(&pipe) {
+                        Ok(client) => {
+                            return Ok(DaemonStream::NamedPipe(client));
+                        }
+                        Err(e)
+                            if attempts < max_attempts
+                                && (e.kind() == std::io::ErrorKind::NotFound
+                                    || e.raw_os_error() == Some(ERROR_PIPE_BUSY)) =>
+                        {
+                            attempts += 1;
+                            debug!(
+                                "Named pipe connection attempt {} failed: {}, retrying...",
+                                attempts, e
+                            );
+                            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                        }
+                        Err(e) => {
+                            return Err(Error::Connection(format!(
+                                "Failed to connect to named pipe {}: {}",
+                                pipe, e
+                            )));
+                        }
+                    }
+                }
+            }
+            #[cfg(not(windows))]
+            {
+                Err(Error::Connection(format!(
+                    "Named pipes not supported on this platform: {}",
+                    pipe
+                )))
+            }
         } else {
             Err(Error::Connection(format!(
                 "Unsupported multiaddr format: {}",
@@ -191,37 +457,9 @@ impl P2PClient {
     }
 
     /// Send a request to the daemon and receive a response
-    pub async fn send_request(&mut self, request: Request) -> Result<Response> {
-        // Debug: print request type
-        debug!("Request type field = {}", request.r#type);
-
-        // Serialize request
-        let mut buf = BytesMut::new();
-        request
-            .encode(&mut buf)
-            .map_err(|e| Error::Protocol(format!("Failed to encode request: {}", e)))?;
-
-        debug!(
-            "Encoded {} bytes - hex: {}",
-            buf.len(),
-            buf.iter()
-                .take(20)
-                .map(|b| format!("{:02x}", b))
-                .collect::<Vec<_>>()
-                .join(" ")
-        );
-
-        // Write framed message: [8-byte length][protobuf]
-        self.write_framed(&buf).await?;
-
-        // Read response
-        let response_bytes = self.read_framed().await?;
-
-        trace!("Received response ({} bytes)", response_bytes.len());
-
-        // Decode response
-        let response = Response::decode(&response_bytes[..])
-            .map_err(|e| Error::Protocol(format!("Failed to decode response: {}", e)))?;
+    pub async fn send_request(&self, request: Request) -> Result<Response> {
+        let (response, conn) = self.timed(self.exchange(&request)).await?;
+        conn.release();
 
         // Check for error response
         if let Some(err) = &response.error {
@@ -231,92 +469,31 @@ impl P2PClient {
         Ok(response)
     }
 
-    /// Write a framed message (varint length + payload)
-    async fn write_framed(&mut self, payload: &[u8]) -> Result<()> {
-        let len = payload.len();
-
-        // Encode length as varint
-        let mut len_buf = varint_encode::u64_buffer();
-        let len_bytes = varint_encode::u64(len as u64, &mut len_buf);
-
-        // Build frame: varint length + payload
-        let mut frame = BytesMut::with_capacity(len_bytes.len() + payload.len());
-        frame.put_slice(len_bytes);
-        frame.put_slice(payload);
-
-        match &mut self.stream {
-            DaemonStream::Tcp(tcp) => {
-                tcp.write_all(&frame).await?;
-                tcp.flush().await?;
-            }
-            #[cfg(unix)]
-            DaemonStream::UnixSocket(socket) => {
-                socket.write_all(&frame).await?;
-                socket.flush().await?;
-            }
-        }
-
-        Ok(())
-    }
-
-    /// Read a framed message (varint length + payload)
-    async fn read_framed(&mut self) -> Result<Vec<u8>> {
-        // Read varint length prefix (up to 10 bytes for u64)
-        let mut len_bytes = Vec::new();
-        let mut byte = [0u8; 1];
-
-        // Read varint byte by byte
-        for _ in 0..10 {
-            match &mut self.stream {
-                DaemonStream::Tcp(tcp) => {
-                    tcp.read_exact(&mut byte).await?;
-                }
-                #[cfg(unix)]
-                DaemonStream::UnixSocket(socket) => {
-                    socket.read_exact(&mut byte).await?;
-                }
-            }
-
-            len_bytes.push(byte[0]);
-
-            // Check if this is the last byte (MSB is 0)
-            if byte[0] & 0x80 == 0 {
-                break;
-            }
-        }
-
-        // Decode varint
-        let mut cursor = &len_bytes[..];
-        let len = match unsigned_varint::io::read_u64(&mut cursor) {
-            Ok(l) => l as usize,
-            Err(e) => return Err(Error::Protocol(format!("Failed to decode varint: {}", e))),
-        };
-
-        if len > 10 * 1024 * 1024 {
-            // 10MB sanity check
-            return Err(Error::Protocol(format!("Message too large: {} bytes", len)));
-        }
-
-        // Read payload
-        let mut payload = vec![0u8; len];
-
-        match &mut self.stream {
-            DaemonStream::Tcp(tcp) => {
-                tcp.read_exact(&mut payload).await?;
-            }
-            #[cfg(unix)]
-            DaemonStream::UnixSocket(socket) => {
-                socket.read_exact(&mut payload).await?;
+    /// Send `request` on a pooled connection and return the first response
+    /// together with the connection, which the caller must release once any
+    /// further frames have been read
+    ///
+    /// A pooled connection may have been closed by the daemon since its last
+    /// use (e.g. after a daemon restart); such failures are retried once on a
+    /// fresh connection.
+    pub(crate) async fn exchange(&self, request: &Request) -> Result<(Response, PooledConn)> {
+        let mut conn = self.pool.checkout().await?;
+        match conn.round_trip(request).await {
+            Ok(response) => Ok((response, conn)),
+            Err(Error::Io(e)) if conn.reused => {
+                debug!("Pooled daemon connection is stale ({}), reconnecting", e);
+                let mut conn = self.pool.connect().await?;
+                let response = conn.round_trip(request).await?;
+                Ok((response, conn))
             }
+            Err(e) => Err(e),
         }
-
-        Ok(payload)
     }
 
     /// Send an IDENTIFY request to get our peer ID
     ///
     /// Returns the peer ID as hex-encoded string
-    pub async fn identify(&mut self) -> Result<String> {
+    pub async fn identify(&self) -> Result<String> {
         let (peer_id, _) = self.identify_with_addrs().await?;
         Ok(peer_id)
     }
@@ -326,7 +503,7 @@ impl P2PClient {
     /// Returns `(peer_id_hex, addrs)` where `addrs` is the list of multiaddr bytes
     /// that p2pd has observed for itself (populated by the libp2p IDENTIFY protocol
     /// as peers connect and report our external address).
-    pub async fn identify_with_addrs(&mut self) -> Result<(String, Vec<Vec<u8>>)> {
+    pub async fn identify_with_addrs(&self) -> Result<(String, Vec<Vec<u8>>)> {
         let request = Request {
             r#type: request::Type::Identify as i32,
             connect: None,
@@ -360,7 +537,7 @@ impl P2PClient {
     /// alongside another in-flight branch (feature/public-ip) that
     /// independently added the latter name. Rename to `identify_with_addrs`
     /// once whichever branch lands second is updated.
-    pub async fn identify_full(&mut self) -> Result<(String, Vec<Vec<u8>>)> {
+    pub async fn identify_full(&self) -> Result<(String, Vec<Vec<u8>>)> {
         let request = Request {
             r#type: request::Type::Identify as i32,
             connect: None,
@@ -388,7 +565,7 @@ impl P2PClient {
     /// Connect to a peer using a multiaddr
     ///
     /// The multiaddr should be in the format: /ip4/1.2.3.4/tcp/1234/p2p/QmPeerID
-    pub async fn connect_peer(&mut self, peer_multiaddr: &str) -> Result<()> {
+    pub async fn connect_peer(&self, peer_multiaddr: &str) -> Result<()> {
         // Parse the multiaddr to extract peer ID and address
         let maddr: libp2p::Multiaddr = peer_multiaddr
             .parse()
@@ -433,7 +610,7 @@ impl P2PClient {
     }
 
     /// Disconnect from a peer
-    pub async fn disconnect_peer(&mut self, peer_id: &[u8]) -> Result<()> {
+    pub async fn disconnect_peer(&self, peer_id: &[u8]) -> Result<()> {
         let request = Request {
             r#type: request::Type::Disconnect as i32,
             connect: None,
@@ -456,7 +633,7 @@ impl P2PClient {
     ///
     /// Returns a list of PeerInfo containing peer IDs and their addresses.
     /// This is a fast local query to the daemon's connection table.
-    pub async fn list_peers(&mut self) -> Result<Vec<PeerInfo>> {
+    pub async fn list_peers(&self) -> Result<Vec<PeerInfo>> {
         let request = Request {
             r#type: request::Type::ListPeers as i32,
             connect: None,
@@ -482,7 +659,7 @@ impl P2PClient {
     /// * `listen_addr` - Local multiaddr where we'll accept connections (e.g., "/ip4/127.0.0.1/tcp/9000")
     /// * `protocols` - List of protocol names to handle (e.g., ["DHTProtocol.rpc_store"])
     pub async fn register_stream_handler(
-        &mut self,
+        &self,
         listen_addr: &str,
         protocols: Vec<String>,
     ) -> Result<()> {
@@ -528,7 +705,7 @@ impl P2PClient {
 
     /// Remove a previously registered stream handler
     pub async fn remove_stream_handler(
-        &mut self,
+        &self,
         listen_addr: &str,
         protocols: Vec<String>,
     ) -> Result<()> {
@@ -572,7 +749,7 @@ impl P2PClient {
     /// Returns a TcpStream connected to the daemon-managed protocol stream
     /// that can be used to send requests and receive responses.
     pub async fn stream_open(
-        &mut self,
+        &self,
         peer_id: &[u8],
         protocols: Vec<String>,
     ) -> Result<tokio::net::TcpStream> {
@@ -593,7 +770,12 @@ impl P2PClient {
         };
 
         debug!("Opening stream for protocols: {:?}", protocols);
-        let response = self.send_request(request).await?;
+        // The daemon pipes the stream over the control connection, so it
+        // must not go back to the pool.
+        let (response, _conn) = self.timed(self.exchange(&request)).await?;
+        if let Some(err) = &response.error {
+            return Err(Error::Protocol(format!("Daemon error: {}", err.msg)));
+        }
 
         // Extract StreamInfo from response
         let stream_info = response
@@ -656,11 +838,12 @@ impl P2PClient {
     /// 3. Daemon calls `doStreamPipe(socket, libp2p_stream)` — raw bytes of the libp2p stream
     ///    now flow directly on the daemon socket (no separate TCP connection needed).
     ///
-    /// This method consumes `self` and returns the socket as a [`P2PStream`].
+    /// This method consumes `self` and returns a control socket taken out of
+    /// the connection pool as a [`P2PStream`]; it never goes back to the pool.
     /// The `StreamInfo.addr` field (the remote peer's multiaddr) is intentionally ignored —
     /// connecting to it would reach the relay node, not a local proxy.
     pub async fn stream_open_raw(
-        mut self,
+        self,
         peer_id: &[u8],
         protocols: Vec<String>,
     ) -> Result<P2PStream> {
@@ -681,7 +864,10 @@ impl P2PClient {
         };
 
         debug!("Opening raw stream for protocols: {:?}", protocols);
-        let response = self.send_request(request).await?;
+        let (response, conn) = self.timed(self.exchange(&request)).await?;
+        if let Some(err) = &response.error {
+            return Err(Error::Protocol(format!("Daemon error: {}", err.msg)));
+        }
 
         let stream_info = response
             .stream_info
@@ -695,7 +881,7 @@ impl P2PClient {
 
         // The daemon has set up doStreamPipe on its end: from this point raw libp2p stream
         // bytes flow directly on the daemon socket. Return it as the data channel.
-        Ok(P2PStream(self.stream))
+        Ok(P2PStream(conn.stream))
     }
 
     // ===== Persistent Connection / Unary Handler Support =====
@@ -719,7 +905,7 @@ impl P2PClient {
         debug!("Upgrading to persistent connection for unary handlers");
 
         // Open a new connection to the daemon
-        let stream = Self::connect_stream(&self.daemon_addr).await?;
+        let stream = Self::connect_stream(&self.pool.addr).await?;
 
         // Send PERSISTENT_CONN_UPGRADE request
         let (mut reader, mut writer): (
@@ -741,6 +927,14 @@ impl P2PClient {
                     Box::new(w) as Box<dyn tokio::io::AsyncWrite + Unpin + Send>,
                 )
             }
+            #[cfg(windows)]
+            DaemonStream::NamedPipe(pipe) => {
+                let (r, w) = tokio::io::split(pipe);
+                (
+                    Box::new(r) as Box<dyn tokio::io::AsyncRead + Unpin + Send>,
+                    Box::new(w) as Box<dyn tokio::io::AsyncWrite + Unpin + Send>,
+                )
+            }
         };
 
         // Build and send upgrade request
@@ -788,8 +982,32 @@ impl P2PClient {
         Ok(conn)
     }
 
+    /// Whether a live persistent connection is currently open
+    ///
+    /// Unary handlers are registered per connection, so `false` after handlers
+    /// were added means the daemon has dropped them and they must be re-added.
+    pub async fn has_persistent_connection(&self) -> bool {
+        self.persistent
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|conn| conn.is_alive())
+    }
+
+    /// Close the persistent connection, if one is open
+    ///
+    /// The daemon unregisters every unary handler added over it; the next
+    /// unary call or registration opens a fresh connection.
+    pub async fn close_persistent_connection(&self) {
+        if self.persistent.lock().await.take().is_some() {
+            debug!("Closed persistent connection");
+        }
+    }
+
     /// Helper to read varint-framed messages (static version for upgrading)
-    async fn read_varint_framed_static<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    pub(crate) async fn read_varint_framed_static<R: AsyncReadExt + Unpin>(
+        reader: &mut R,
+    ) -> Result<Vec<u8>> {
         // Read varint length prefix
         let mut len_bytes = Vec::new();
         let mut byte = [0u8; 1];
@@ -866,6 +1084,30 @@ mod tests {
     use super::*;
     use bytes::Buf;
 
+    #[test]
+    fn pipe_addresses_map_to_pipe_paths() {
+        assert_eq!(
+            pipe_path("/pipe/kwaai-p2pd").as_deref(),
+            Some(r"\\.\pipe\kwaai-p2pd")
+        );
+        assert_eq!(pipe_path("/pipe/"), None);
+        assert_eq!(pipe_path("/pipe/a/b"), None);
+        assert_eq!(pipe_path("/unix//tmp/kwaai-p2pd.sock"), None);
+    }
+
+    #[test]
+    fn clients_of_one_daemon_share_a_pool() {
+        let a = ConnectionPool::for_addr("/ip4/127.0.0.1/tcp/1");
+        let b = ConnectionPool::for_addr("/ip4/127.0.0.1/tcp/1");
+        let other = ConnectionPool::for_addr("/ip4/127.0.0.1/tcp/2");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &other));
+
+        let weak = Arc::downgrade(&a);
+        drop((a, b));
+        assert!(weak.upgrade().is_none());
+    }
+
     #[test]
     fn test_frame_encoding() {
         let payload = b"test payload";
@@ -3,7 +3,7 @@
 //! This module handles spawning, monitoring, and shutting down the
 //! go-libp2p-daemon process.
 
-use crate::client::P2PClient;
+use crate::client::{pipe_path, P2PClient};
 use crate::error::{Error, Result};
 use std::path::PathBuf;
 use std::process::Stdio;
@@ -13,7 +13,7 @@ use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
 /// Configuration builder for the p2p daemon
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct DaemonBuilder {
     binary_path: Option<PathBuf>,
     listen_addr: Option<String>,
@@ -34,6 +34,9 @@ pub struct DaemonBuilder {
     /// Path to a protobuf-encoded Ed25519 private key file (`-id` flag).
     /// When set, p2pd uses this key so the PeerId is stable across restarts.
     identity_key_path: Option<PathBuf>,
+    /// Path to a go-ipfs style `swarm.key` file (`-psk` flag). When set, p2pd
+    /// only talks to peers that hold the same pre-shared key.
+    swarm_key_path: Option<PathBuf>,
 }
 
 impl DaemonBuilder {
@@ -52,8 +55,9 @@ impl DaemonBuilder {
 
     /// Set the IPC listen address
     ///
-    /// - **Windows**: Named pipe path (e.g., `//./pipe/kwaai-p2pd`)
-    /// - **Unix**: Unix socket path (e.g., `/tmp/kwaai-p2pd.sock`)
+    /// - **Windows**: Named pipe address (e.g., `/pipe/kwaai-p2pd`) or a
+    ///   localhost TCP multiaddr (e.g., `/ip4/127.0.0.1/tcp/5005`)
+    /// - **Unix**: Unix socket multiaddr (e.g., `/unix//tmp/kwaai-p2pd.sock`)
     pub fn with_listen_addr<S: Into<String>>(mut self, addr: S) -> Self {
         self.listen_addr = Some(addr.into());
         self
@@ -127,6 +131,13 @@ impl DaemonBuilder {
         self
     }
 
+    /// Drop every announce address set so far, e.g. before replacing them with
+    /// freshly discovered ones
+    pub fn without_announce_addrs(mut self) -> Self {
+        self.announce_addrs.clear();
+        self
+    }
+
     /// Set trusted relay peers for AutoRelay (`-trustedRelays` flag)
     ///
     /// These peers are tried as circuit relay servers. Typically the bootstrap
@@ -211,6 +222,20 @@ impl DaemonBuilder {
         self
     }
 
+    /// Set the path to a pre-shared swarm key file (`-psk` flag)
+    ///
+    /// Turns on libp2p private networking: every connection starts with a
+    /// handshake keyed by the PSK, so peers without the key cannot connect.
+    /// The file uses the go-ipfs `swarm.key` format
+    /// (`/key/swarm/psk/1.0.0/`, `/base16/`, then 64 hex digits).
+    ///
+    /// [`spawn`](Self::spawn) fails if the p2pd binary has no `-psk` flag
+    /// rather than silently joining the public network.
+    pub fn with_swarm_key<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.swarm_key_path = Some(path.into());
+        self
+    }
+
     /// Spawn the daemon process
     pub async fn spawn(self) -> Result<P2PDaemon> {
         let binary_path = self
@@ -218,18 +243,10 @@ impl DaemonBuilder {
             .unwrap_or_else(|| PathBuf::from(crate::DAEMON_BINARY_PATH));
 
         // Use platform-specific default if no listen address provided
-        // On Windows, use TCP since Go libp2p doesn't support Windows named pipes in multiaddr format
-        // On Unix, use Unix domain sockets
-        let listen_addr = self.listen_addr.unwrap_or_else(|| {
-            #[cfg(windows)]
-            {
-                "/ip4/127.0.0.1/tcp/5005".to_string() // Use TCP on Windows
-            }
-            #[cfg(unix)]
-            {
-                "/unix//tmp/kwaai-p2pd.sock".to_string() // Use Unix socket on Linux/macOS
-            }
-        });
+        let listen_addr = match self.listen_addr {
+            Some(addr) => addr,
+            None => default_listen_addr(&binary_path).await,
+        };
 
         info!("Starting p2pd daemon from: {}", binary_path.display());
         info!("Listen address: {}", listen_addr);
@@ -246,8 +263,21 @@ impl DaemonBuilder {
         // Build command
         let mut cmd = Command::new(&binary_path);
 
-        // Set listen address
-        cmd.arg("-listen").arg(&listen_addr);
+        // Set listen address. Multiaddrs have no named-pipe protocol, so pipes
+        // go through a dedicated flag.
+        if let Some(pipe) = pipe_path(&listen_addr) {
+            if !supports_flag(&binary_path, "listenPipe").await {
+                return Err(Error::Process(format!(
+                    "{} does not support -listenPipe; rebuild p2pd with named-pipe support \
+                     or listen on {}",
+                    binary_path.display(),
+                    crate::WINDOWS_TCP_FALLBACK_ADDR
+                )));
+            }
+            cmd.arg("-listenPipe").arg(pipe);
+        } else {
+            cmd.arg("-listen").arg(&listen_addr);
+        }
 
         // DHT mode. -dhtServer takes precedence over -dht: server mode forces
         // the node into the routing table regardless of AutoNAT verdict, while
@@ -329,6 +359,19 @@ impl DaemonBuilder {
             cmd.arg("-id").arg(key_path);
         }
 
+        // Private swarm pre-shared key
+        if let Some(ref psk_path) = self.swarm_key_path {
+            if !supports_flag(&binary_path, "psk").await {
+                return Err(Error::Process(format!(
+                    "{} does not support -psk; rebuild p2pd with private network support \
+                     or unset swarm_psk",
+                    binary_path.display()
+                )));
+            }
+            info!("Using private swarm key: {}", psk_path.display());
+            cmd.arg("-psk").arg(psk_path);
+        }
+
         // Forward GOLOG_LOG_LEVEL to the Go daemon for diagnostics
         if let Ok(level) = std::env::var("GOLOG_LOG_LEVEL") {
             cmd.env("GOLOG_LOG_LEVEL", level);
@@ -418,7 +461,7 @@ impl P2PDaemon {
         #[cfg(unix)]
         let socket_path = self.listen_addr.strip_prefix("/unix/").map(PathBuf::from);
         #[cfg(not(unix))]
-        let socket_path: Option<PathBuf> = None; // TCP — no file to poll
+        let socket_path = pipe_path(&self.listen_addr).map(PathBuf::from);
 
         let poll_interval = tokio::time::Duration::from_millis(100);
         let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
@@ -440,7 +483,7 @@ impl P2PDaemon {
                 }
             }
 
-            // Check if the socket file exists yet (Unix only)
+            // Check if the socket file or named pipe exists yet
             if let Some(ref path) = socket_path {
                 if path.exists() {
                     debug!("Socket ready: {}", path.display());
@@ -467,6 +510,11 @@ impl P2PDaemon {
         }
     }
 
+    /// OS process id of the daemon, if it is still attached to this handle
+    pub fn pid(&self) -> Option<u32> {
+        self.process.as_ref().and_then(|child| child.id())
+    }
+
     /// Return whatever stderr the daemon has written so far (non-destructive).
     /// Useful for surfacing Go panic stack traces when the daemon crashes.
     pub async fn captured_stderr(&self) -> String {
@@ -533,6 +581,48 @@ impl Drop for P2PDaemon {
     }
 }
 
+/// Default IPC address: a Unix socket, or on Windows a named pipe when the
+/// daemon supports one. Older p2pd builds only take multiaddrs, so they fall
+/// back to a localhost TCP port.
+async fn default_listen_addr(binary: &std::path::Path) -> String {
+    #[cfg(windows)]
+    {
+        if supports_flag(binary, "listenPipe").await {
+            format!(
+                "{}{}",
+                crate::client::PIPE_ADDR_PREFIX,
+                crate::DEFAULT_SOCKET_NAME
+            )
+        } else {
+            warn!(
+                "{} does not support -listenPipe; falling back to {}",
+                binary.display(),
+                crate::WINDOWS_TCP_FALLBACK_ADDR
+            );
+            crate::WINDOWS_TCP_FALLBACK_ADDR.to_string()
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = binary;
+        format!("/unix/{}", crate::DEFAULT_SOCKET_NAME)
+    }
+}
+
+/// Whether `binary` lists `-<flag>` in its usage text. Go's `flag` package
+/// prints usage to stderr on `-h`.
+async fn supports_flag(binary: &std::path::Path, flag: &str) -> bool {
+    let Ok(output) = Command::new(binary).arg("-h").output().await else {
+        return false;
+    };
+    let needle = format!("-{flag}");
+    [&output.stdout, &output.stderr].iter().any(|out| {
+        String::from_utf8_lossy(out)
+            .split_whitespace()
+            .any(|word| word == needle)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -547,5 +637,17 @@ mod tests {
         assert!(builder.dht);
         assert!(builder.relay);
         assert_eq!(builder.bootstrap_peers.len(), 1);
+        assert!(builder.swarm_key_path.is_none());
+        let builder = builder.with_swarm_key("/tmp/swarm.key");
+        assert_eq!(
+            builder.swarm_key_path.as_deref(),
+            Some(std::path::Path::new("/tmp/swarm.key"))
+        );
+
+        let builder = builder
+            .announce_addrs(["/ip4/203.0.113.1/tcp/8080"])
+            .without_announce_addrs()
+            .announce_addrs(["/ip4/203.0.113.2/tcp/8080"]);
+        assert_eq!(builder.announce_addrs, ["/ip4/203.0.113.2/tcp/8080"]);
     }
 }
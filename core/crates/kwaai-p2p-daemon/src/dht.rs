@@ -9,7 +9,10 @@
 
 use crate::client::P2PClient;
 use crate::error::{Error, Result};
-use crate::protocol::p2pd::{dht_request, request, DhtRequest, Request};
+use crate::protocol::p2pd::{
+    dht_request, dht_response, request, DhtRequest, DhtResponse, PeerInfo, Request,
+};
+use prost::Message;
 use tracing::{debug, trace};
 
 /// DHT peer information
@@ -38,7 +41,7 @@ impl P2PClient {
     /// * `value` - The value to store (binary)
     /// * `timeout_secs` - Optional timeout in seconds
     pub async fn dht_put_value(
-        &mut self,
+        &self,
         key: Vec<u8>,
         value: Vec<u8>,
         timeout_secs: Option<i64>,
@@ -89,11 +92,7 @@ impl P2PClient {
     ///
     /// # Returns
     /// The value if found, or an error if not found
-    pub async fn dht_get_value(
-        &mut self,
-        key: Vec<u8>,
-        timeout_secs: Option<i64>,
-    ) -> Result<DhtValue> {
+    pub async fn dht_get_value(&self, key: Vec<u8>, timeout_secs: Option<i64>) -> Result<DhtValue> {
         debug!("DHT GET_VALUE: key len={}", key.len());
 
         let dht_request = DhtRequest {
@@ -142,7 +141,7 @@ impl P2PClient {
     /// # Returns
     /// Peer info with addresses
     pub async fn dht_find_peer(
-        &mut self,
+        &self,
         peer_id: Vec<u8>,
         timeout_secs: Option<i64>,
     ) -> Result<DhtPeerInfo> {
@@ -193,13 +192,31 @@ impl P2PClient {
     /// * `timeout_secs` - Optional timeout in seconds
     ///
     /// # Returns
-    /// Peer info if a provider is found (note: daemon returns one peer at a time)
+    /// The first provider found, if any (see [`Self::dht_find_providers_all`])
     pub async fn dht_find_providers(
-        &mut self,
+        &self,
         cid: Vec<u8>,
         count: i32,
         timeout_secs: Option<i64>,
     ) -> Result<Option<DhtPeerInfo>> {
+        Ok(self
+            .dht_find_providers_all(cid, count, timeout_secs)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Find up to `count` providers for content in the DHT
+    ///
+    /// The daemon streams providers: a `BEGIN` response, one `DHTResponse`
+    /// per provider, then `END`. All of it is read so the control socket
+    /// is left ready for the next request.
+    pub async fn dht_find_providers_all(
+        &self,
+        cid: Vec<u8>,
+        count: i32,
+        timeout_secs: Option<i64>,
+    ) -> Result<Vec<DhtPeerInfo>> {
         debug!("DHT FIND_PROVIDERS: cid len={}, count={}", cid.len(), count);
 
         let dht_request = DhtRequest {
@@ -224,19 +241,40 @@ impl P2PClient {
             pubsub: None,
         };
 
-        let response = self.send_request(request).await?;
+        self.timed(async {
+            let (response, mut conn) = self.exchange(&request).await?;
+            if let Some(err) = &response.error {
+                return Err(Error::Protocol(format!("Daemon error: {}", err.msg)));
+            }
+            let Some(first) = response.dht else {
+                conn.release();
+                return Ok(Vec::new());
+            };
+            if first.r#type != dht_response::Type::Begin as i32 {
+                // Not streamed: a single provider (or none) in the response itself.
+                conn.release();
+                return Ok(first.peer.map(peer_info).into_iter().collect());
+            }
 
-        if let Some(dht_response) = response.dht {
-            if let Some(peer_info) = dht_response.peer {
-                trace!("DHT FIND_PROVIDERS successful");
-                return Ok(Some(DhtPeerInfo {
-                    id: peer_info.id,
-                    addrs: peer_info.addrs,
-                }));
+            let mut providers = Vec::new();
+            loop {
+                let bytes = conn.read_framed().await?;
+                let msg = DhtResponse::decode(&bytes[..]).map_err(|e| {
+                    Error::Protocol(format!("Failed to decode DHT response: {}", e))
+                })?;
+                if msg.r#type == dht_response::Type::End as i32 {
+                    break;
+                }
+                if let Some(peer) = msg.peer {
+                    providers.push(peer_info(peer));
+                }
             }
-        }
+            conn.release();
 
-        Ok(None)
+            trace!("DHT FIND_PROVIDERS found {} providers", providers.len());
+            Ok(providers)
+        })
+        .await
     }
 
     /// Announce that we provide content
@@ -244,7 +282,7 @@ impl P2PClient {
     /// # Arguments
     /// * `cid` - Content identifier (binary)
     /// * `timeout_secs` - Optional timeout in seconds
-    pub async fn dht_provide(&mut self, cid: Vec<u8>, timeout_secs: Option<i64>) -> Result<()> {
+    pub async fn dht_provide(&self, cid: Vec<u8>, timeout_secs: Option<i64>) -> Result<()> {
         debug!("DHT PROVIDE: cid len={}", cid.len());
 
         let dht_request = DhtRequest {
@@ -276,6 +314,13 @@ impl P2PClient {
     }
 }
 
+fn peer_info(peer: PeerInfo) -> DhtPeerInfo {
+    DhtPeerInfo {
+        id: peer.id,
+        addrs: peer.addrs,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -7,7 +7,8 @@
 //!
 //! The go-libp2p-daemon runs as a separate process and communicates with
 //! our Rust code via IPC:
-//! - **Windows**: Named pipes (`//./pipe/name`)
+//! - **Windows**: Named pipes (`/pipe/name`, i.e. `\\.\pipe\name`), or a
+//!   localhost TCP port when p2pd lacks `-listenPipe`
 //! - **Linux/macOS**: Unix domain sockets (`/tmp/name.sock`)
 //!
 //! ## Usage
@@ -43,13 +44,16 @@ pub mod dht;
 pub mod error;
 pub mod hello;
 pub mod persistent;
+pub mod probe;
 pub mod protocol;
 pub mod stream;
+pub mod supervisor;
 
 pub use client::{P2PClient, P2PStream};
 pub use daemon::{DaemonBuilder, P2PDaemon};
 pub use dht::{DhtPeerInfo, DhtValue};
 pub use error::{Error, Result};
+pub use supervisor::{DaemonEvent, DaemonSupervisor};
 
 // Re-export commonly used types
 pub use protocol::p2pd;
@@ -65,3 +69,25 @@ pub const DEFAULT_SOCKET_NAME: &str = "kwaai-p2pd";
 
 #[cfg(unix)]
 pub const DEFAULT_SOCKET_NAME: &str = "/tmp/kwaai-p2pd.sock";
+
+/// Localhost TCP control address used on Windows when p2pd cannot listen on
+/// a named pipe
+pub const WINDOWS_TCP_FALLBACK_ADDR: &str = "/ip4/127.0.0.1/tcp/5005";
+
+/// IPC address of a daemon started with the default listen address
+///
+/// On Windows this is the named pipe if it exists, otherwise the TCP fallback.
+#[cfg(windows)]
+pub fn default_daemon_addr() -> String {
+    let pipe = format!("{}{}", client::PIPE_ADDR_PREFIX, DEFAULT_SOCKET_NAME);
+    match client::pipe_path(&pipe) {
+        Some(path) if std::path::Path::new(&path).exists() => pipe,
+        _ => WINDOWS_TCP_FALLBACK_ADDR.to_string(),
+    }
+}
+
+/// IPC address of a daemon started with the default listen address
+#[cfg(unix)]
+pub fn default_daemon_addr() -> String {
+    format!("/unix/{}", DEFAULT_SOCKET_NAME)
+}
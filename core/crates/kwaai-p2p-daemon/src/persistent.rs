@@ -31,6 +31,16 @@ pub type UnaryHandlerFn = Arc<
         + Sync,
 >;
 
+tokio::task_local! {
+    static CALLER: Vec<u8>;
+}
+
+/// Raw peer ID of the remote whose unary request the current handler is
+/// serving; `None` outside a handler
+pub fn caller_peer() -> Option<Vec<u8>> {
+    CALLER.try_with(Clone::clone).ok()
+}
+
 /// Response future for pending RPC calls
 type ResponseFuture = oneshot::Sender<Result<PersistentConnectionResponse>>;
 
@@ -156,6 +166,7 @@ impl PersistentConnection {
 
                         let proto = req.proto.clone();
                         let data = req.data.clone();
+                        let peer = req.peer.clone();
                         let call_id_bytes = response.call_id.clone();
 
                         // Look up handler
@@ -168,7 +179,10 @@ impl PersistentConnection {
 
                             // Execute handler in background
                             tokio::spawn(async move {
-                                let result = handler(data).await;
+                                // Call the handler inside the scope too, so
+                                // checks made before its future starts see it.
+                                let result =
+                                    CALLER.scope(peer, async move { handler(data).await }).await;
 
                                 // Send response back
                                 let response = match result {
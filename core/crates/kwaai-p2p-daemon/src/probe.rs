@@ -0,0 +1,178 @@
+//! Active probes against connected peers: libp2p ping and identify.
+//!
+//! `LIST_PEERS` only reports `(peer id, remote addr)` per connection. The
+//! daemon has no request type for latency or a peer's supported protocols,
+//! but every go-libp2p host serves the standard ping (`/ipfs/ping/1.0.0`)
+//! and identify (`/ipfs/id/1.0.0`) protocols. Both are reachable through
+//! `STREAM_OPEN` like any other protocol, so the probes here open a fresh
+//! control connection to the running daemon, switch it to pipe mode with
+//! [`P2PClient::stream_open_raw`], and speak the protocol directly.
+//!
+//! Neither probe touches the caller's control connection, so they can run
+//! concurrently with other requests on the same [`P2PClient`].
+
+use crate::client::P2PClient;
+use crate::error::{Error, Result};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// libp2p ping protocol: the dialer writes 32 bytes, the listener echoes them.
+pub const PING_PROTO: &str = "/ipfs/ping/1.0.0";
+
+/// libp2p identify protocol: the listener writes one length-prefixed
+/// `Identify` message and closes the stream.
+pub const IDENTIFY_PROTO: &str = "/ipfs/id/1.0.0";
+
+const PING_SIZE: usize = 32;
+
+/// Round trips per ping probe; the fastest one is reported.
+const PING_ROUNDS: usize = 3;
+
+/// Identify messages are small; anything larger is not a well-behaved peer.
+const MAX_IDENTIFY_SIZE: usize = 64 * 1024;
+
+/// `Identify` message from libp2p's identify spec (fields we read only).
+#[derive(Clone, PartialEq, prost::Message)]
+struct IdentifyMessage {
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    listen_addrs: Vec<Vec<u8>>,
+    #[prost(string, repeated, tag = "3")]
+    protocols: Vec<String>,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    observed_addr: Option<Vec<u8>>,
+    #[prost(string, optional, tag = "5")]
+    protocol_version: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    agent_version: Option<String>,
+}
+
+/// What a peer reports about itself over identify.
+#[derive(Debug, Clone, Default)]
+pub struct PeerIdentity {
+    /// Protocols the peer has handlers registered for
+    pub protocols: Vec<String>,
+    /// Addresses the peer is listening on (binary multiaddrs)
+    pub listen_addrs: Vec<Vec<u8>>,
+    /// Our address as observed by the peer (binary multiaddr)
+    pub observed_addr: Option<Vec<u8>>,
+    /// e.g. `ipfs/0.1.0`
+    pub protocol_version: Option<String>,
+    /// e.g. `github.com/libp2p/go-libp2p-daemon/p2pd`
+    pub agent_version: Option<String>,
+}
+
+impl From<IdentifyMessage> for PeerIdentity {
+    fn from(m: IdentifyMessage) -> Self {
+        Self {
+            protocols: m.protocols,
+            listen_addrs: m.listen_addrs,
+            observed_addr: m.observed_addr,
+            protocol_version: m.protocol_version,
+            agent_version: m.agent_version,
+        }
+    }
+}
+
+impl P2PClient {
+    /// Measure round-trip time to a connected peer with the libp2p ping protocol
+    ///
+    /// Stream setup is excluded; the fastest of a few echo round trips is
+    /// returned. Fails with [`Error::Timeout`] if the whole probe exceeds
+    /// `timeout`.
+    pub async fn ping_peer(&self, peer_id: &[u8], timeout: Duration) -> Result<Duration> {
+        let addr = self.daemon_addr().to_string();
+        let peer_id = peer_id.to_vec();
+        let probe = async move {
+            let client = P2PClient::connect(&addr).await?;
+            let mut stream = client
+                .stream_open_raw(&peer_id, vec![PING_PROTO.to_string()])
+                .await?;
+
+            let mut best = Duration::MAX;
+            for _ in 0..PING_ROUNDS {
+                let mut payload = [0u8; PING_SIZE];
+                payload[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+                payload[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+
+                let start = Instant::now();
+                stream.write_all(&payload).await?;
+                stream.flush().await?;
+                let mut echo = [0u8; PING_SIZE];
+                stream.read_exact(&mut echo).await?;
+                let rtt = start.elapsed();
+
+                if echo != payload {
+                    return Err(Error::InvalidResponse("ping echo mismatch".to_string()));
+                }
+                best = best.min(rtt);
+            }
+            Ok(best)
+        };
+        tokio::time::timeout(timeout, probe)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Ask a connected peer what it supports with the libp2p identify protocol
+    pub async fn identify_peer(&self, peer_id: &[u8], timeout: Duration) -> Result<PeerIdentity> {
+        let addr = self.daemon_addr().to_string();
+        let peer_id = peer_id.to_vec();
+        let probe = async move {
+            let client = P2PClient::connect(&addr).await?;
+            let mut stream = client
+                .stream_open_raw(&peer_id, vec![IDENTIFY_PROTO.to_string()])
+                .await?;
+            let payload = P2PClient::read_varint_framed_static(&mut stream).await?;
+            decode_identify(&payload)
+        };
+        tokio::time::timeout(timeout, probe)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+}
+
+fn decode_identify(payload: &[u8]) -> Result<PeerIdentity> {
+    if payload.len() > MAX_IDENTIFY_SIZE {
+        return Err(Error::InvalidResponse(format!(
+            "identify message too large ({} bytes)",
+            payload.len()
+        )));
+    }
+    let msg = <IdentifyMessage as prost::Message>::decode(payload)?;
+    Ok(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn decodes_identify_and_skips_unknown_fields() {
+        // Field 1 (publicKey) and 8 (signedPeerRecord) are not modelled and
+        // must be ignored rather than rejected.
+        let mut buf = Vec::new();
+        prost::encoding::bytes::encode(1, &vec![0xaa; 36], &mut buf);
+        IdentifyMessage {
+            listen_addrs: vec![vec![0x04, 127, 0, 0, 1, 0x06, 0x0f, 0xa1]],
+            protocols: vec![PING_PROTO.to_string(), IDENTIFY_PROTO.to_string()],
+            observed_addr: None,
+            protocol_version: Some("ipfs/0.1.0".to_string()),
+            agent_version: Some("p2pd".to_string()),
+        }
+        .encode(&mut buf)
+        .unwrap();
+        prost::encoding::bytes::encode(8, &vec![0xbb; 8], &mut buf);
+
+        let id = decode_identify(&buf).unwrap();
+        assert_eq!(id.protocols, vec![PING_PROTO, IDENTIFY_PROTO]);
+        assert_eq!(id.listen_addrs.len(), 1);
+        assert_eq!(id.agent_version.as_deref(), Some("p2pd"));
+        assert!(id.observed_addr.is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_identify() {
+        assert!(decode_identify(&vec![0u8; MAX_IDENTIFY_SIZE + 1]).is_err());
+    }
+}
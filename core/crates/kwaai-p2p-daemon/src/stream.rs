@@ -69,6 +69,12 @@ pub async fn parse_stream_info(stream: &mut TcpStream) -> Result<StreamInfo> {
     Ok(stream_info)
 }
 
+/// Encode `info` the way the daemon sends it ahead of a forwarded stream,
+/// for handlers fed by something other than p2pd (tests, local bypasses).
+pub fn encode_stream_info(info: &StreamInfo) -> Vec<u8> {
+    info.encode_length_delimited_to_vec()
+}
+
 /// Write a varint-framed message to the stream
 pub async fn write_varint_framed(stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
     let len = payload.len();
@@ -0,0 +1,381 @@
+//! Crash supervision for the p2p daemon
+//!
+//! [`DaemonSupervisor`] owns the [`P2PDaemon`] process together with the
+//! builder that spawned it and every handler registered through it. When the
+//! process dies it is respawned with exponential backoff, the handlers are
+//! registered again on the fresh daemon, and a [`DaemonEvent`] is broadcast so
+//! callers can react (e.g. re-announce to the DHT, whose routing table starts
+//! empty after a restart). With a restart limit it gives up after that many
+//! consecutive failures instead of retrying forever.
+//!
+//! The supervisor does not poll on its own: the caller drives it by calling
+//! [`DaemonSupervisor::check`] from its event loop, because the
+//! [`P2PClient`] it replaces after a restart belongs to that loop.
+
+use crate::client::P2PClient;
+use crate::daemon::{DaemonBuilder, P2PDaemon};
+use crate::error::Result;
+use crate::persistent::UnaryHandlerFn;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// Default delay before the first retry after a failed restart or a quick
+/// re-crash
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Default upper bound for the restart backoff
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// A daemon that stays up this long is considered healthy again, so its next
+/// crash is restarted immediately instead of continuing the backoff.
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+/// Lifecycle events emitted by [`DaemonSupervisor`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DaemonEvent {
+    /// The daemon process exited unexpectedly
+    Crashed {
+        /// Whatever the daemon wrote to stderr before exiting
+        stderr: String,
+    },
+    /// A fresh daemon is running and every handler was registered again.
+    /// Its routing table is empty, so DHT records must be re-announced.
+    Restarted {
+        /// Restarts since the supervisor was created
+        restarts: u32,
+    },
+    /// A restart attempt failed; the next one runs after `retry_in`
+    RestartFailed {
+        /// Consecutive failures, including crashes shortly after a restart
+        failures: u32,
+        error: String,
+        retry_in: Duration,
+    },
+    /// The restart limit was reached; the daemon stays down until
+    /// [`DaemonSupervisor::restart`] is called
+    GaveUp {
+        /// Consecutive failures, including crashes shortly after a restart
+        failures: u32,
+    },
+}
+
+/// Delay before restart attempt number `failures + 1`: immediate for a
+/// healthy daemon, then doubling from `initial` up to `max`.
+fn backoff_delay(initial: Duration, max: Duration, failures: u32) -> Duration {
+    match failures {
+        0 => Duration::ZERO,
+        n => initial.saturating_mul(1u32 << (n - 1).min(16)).min(max),
+    }
+}
+
+struct UnaryRegistration {
+    proto: String,
+    handler: UnaryHandlerFn,
+    balanced: bool,
+}
+
+/// Keeps a p2p daemon running and its handlers registered
+pub struct DaemonSupervisor {
+    builder: DaemonBuilder,
+    daemon: P2PDaemon,
+    started_at: Instant,
+    stream_handlers: Vec<(String, Vec<String>)>,
+    unary_handlers: Vec<UnaryRegistration>,
+    /// Set once the current process has been seen dead, so the crash is
+    /// reported only once
+    exited: bool,
+    /// Set by [`shutdown`](Self::shutdown) or on reaching the restart limit;
+    /// a stopped daemon stays down
+    stopped: bool,
+    failures: u32,
+    next_attempt: Option<Instant>,
+    restarts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    /// Consecutive failures after which restarts stop; `None` retries forever
+    restart_limit: Option<u32>,
+    events: broadcast::Sender<DaemonEvent>,
+}
+
+impl DaemonSupervisor {
+    /// Spawn the daemon from `builder` and connect a client to it
+    ///
+    /// The builder is kept and reused for every restart.
+    pub async fn spawn(builder: DaemonBuilder) -> Result<(Self, P2PClient)> {
+        let mut daemon = builder.clone().spawn().await?;
+        let client = daemon.client().await?;
+        let (events, _) = broadcast::channel(16);
+        let supervisor = Self {
+            builder,
+            daemon,
+            started_at: Instant::now(),
+            stream_handlers: Vec::new(),
+            unary_handlers: Vec::new(),
+            exited: false,
+            stopped: false,
+            failures: 0,
+            next_attempt: None,
+            restarts: 0,
+            initial_backoff: INITIAL_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+            restart_limit: None,
+            events,
+        };
+        Ok((supervisor, client))
+    }
+
+    /// Back off from `initial` up to `max` between failing restarts
+    /// (default 5 s up to 300 s)
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Stop restarting after `limit` consecutive failures: restarts that
+    /// failed, or daemons that crashed again within a minute
+    pub fn with_restart_limit(mut self, limit: u32) -> Self {
+        self.restart_limit = Some(limit);
+        self
+    }
+
+    /// Receive lifecycle events from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<DaemonEvent> {
+        self.events.subscribe()
+    }
+
+    /// Register a stream handler and re-register it after every restart
+    pub async fn register_stream_handler(
+        &mut self,
+        client: &P2PClient,
+        listen_addr: &str,
+        protocols: Vec<String>,
+    ) -> Result<()> {
+        client
+            .register_stream_handler(listen_addr, protocols.clone())
+            .await?;
+        self.stream_handlers
+            .push((listen_addr.to_string(), protocols));
+        Ok(())
+    }
+
+    /// Register a unary handler and re-register it after every restart
+    pub async fn add_unary_handler<F, Fut>(
+        &mut self,
+        client: &P2PClient,
+        proto: &str,
+        handler: F,
+        balanced: bool,
+    ) -> Result<()>
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Vec<u8>>> + Send + 'static,
+    {
+        let handler: UnaryHandlerFn = Arc::new(move |data| Box::pin(handler(data)));
+        let registered = handler.clone();
+        client
+            .add_unary_handler(proto, move |data| registered(data), balanced)
+            .await?;
+        self.unary_handlers.push(UnaryRegistration {
+            proto: proto.to_string(),
+            handler,
+            balanced,
+        });
+        Ok(())
+    }
+
+    /// Check if the daemon process is still running
+    pub fn is_running(&mut self) -> bool {
+        self.daemon.is_running()
+    }
+
+    /// Restarts since the supervisor was created
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// The supervised daemon
+    pub fn daemon(&self) -> &P2PDaemon {
+        &self.daemon
+    }
+
+    /// Restart the daemon if it has died and its backoff delay has elapsed
+    ///
+    /// Cheap while the daemon is healthy; call it periodically. Returns
+    /// whether a live daemon is running afterwards. On a successful restart
+    /// `client` is replaced with one connected to the new daemon.
+    pub async fn check(&mut self, client: &mut P2PClient) -> bool {
+        if self.daemon.is_running() {
+            return true;
+        }
+        if self.stopped {
+            return false;
+        }
+        self.note_exit().await;
+        if self.stopped {
+            return false;
+        }
+        let due = self.next_attempt.is_none_or(|at| Instant::now() >= at);
+        due && self.respawn(client).await.is_ok()
+    }
+
+    /// Restart the daemon now, whether or not it is still running
+    ///
+    /// Ignores the backoff; a failure schedules the next [`check`] retry.
+    ///
+    /// [`check`]: Self::check
+    pub async fn restart(&mut self, client: &mut P2PClient) -> Result<()> {
+        if !self.stopped && !self.daemon.is_running() {
+            self.note_exit().await;
+        }
+        self.respawn(client).await
+    }
+
+    /// Change the daemon configuration and restart with it
+    ///
+    /// The new configuration also applies to every later crash restart, even
+    /// if this restart fails.
+    pub async fn restart_with(
+        &mut self,
+        client: &mut P2PClient,
+        configure: impl FnOnce(DaemonBuilder) -> DaemonBuilder,
+    ) -> Result<()> {
+        self.builder = configure(std::mem::take(&mut self.builder));
+        self.restart(client).await
+    }
+
+    /// Shutdown the daemon gracefully; it is not restarted afterwards
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stopped = true;
+        self.daemon.shutdown().await
+    }
+
+    /// Record the exit of the current process once and schedule its restart
+    async fn note_exit(&mut self) {
+        if std::mem::replace(&mut self.exited, true) {
+            return;
+        }
+        let stderr = self.daemon.captured_stderr().await;
+        if !stderr.is_empty() {
+            warn!("p2pd crash output:\n{}", stderr.trim());
+        }
+        if self.started_at.elapsed() >= STABLE_UPTIME {
+            self.failures = 0;
+        } else {
+            self.failures += 1;
+        }
+        let _ = self.events.send(DaemonEvent::Crashed { stderr });
+        if self.over_restart_limit() {
+            return;
+        }
+        let delay = self.backoff_delay();
+        warn!("⚠️  p2pd process died — restarting in {}s", delay.as_secs());
+        self.next_attempt = Some(Instant::now() + delay);
+    }
+
+    fn backoff_delay(&self) -> Duration {
+        backoff_delay(self.initial_backoff, self.max_backoff, self.failures)
+    }
+
+    /// Give up, once, if the failures exceed the restart limit
+    fn over_restart_limit(&mut self) -> bool {
+        if self
+            .restart_limit
+            .is_none_or(|limit| self.failures <= limit)
+        {
+            return false;
+        }
+        if !self.stopped {
+            self.stopped = true;
+            error!(
+                "p2pd failed {} times in a row — no longer restarting it",
+                self.failures
+            );
+            let _ = self.events.send(DaemonEvent::GaveUp {
+                failures: self.failures,
+            });
+        }
+        true
+    }
+
+    async fn respawn(&mut self, client: &mut P2PClient) -> Result<()> {
+        match self.spawn_and_register().await {
+            Ok((daemon, new_client)) => {
+                self.daemon = daemon;
+                self.stopped = false;
+                *client = new_client;
+                self.started_at = Instant::now();
+                self.exited = false;
+                self.next_attempt = None;
+                self.restarts += 1;
+                info!(
+                    "✅ p2pd restarted and {} handler(s) re-registered",
+                    self.stream_handlers.len() + self.unary_handlers.len()
+                );
+                let _ = self.events.send(DaemonEvent::Restarted {
+                    restarts: self.restarts,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                self.failures += 1;
+                if self.over_restart_limit() {
+                    return Err(e);
+                }
+                let retry_in = self.backoff_delay();
+                warn!(
+                    "p2pd restart failed: {} — retrying in {}s",
+                    e,
+                    retry_in.as_secs()
+                );
+                self.next_attempt = Some(Instant::now() + retry_in);
+                let _ = self.events.send(DaemonEvent::RestartFailed {
+                    failures: self.failures,
+                    error: e.to_string(),
+                    retry_in,
+                });
+                Err(e)
+            }
+        }
+    }
+
+    async fn spawn_and_register(&mut self) -> Result<(P2PDaemon, P2PClient)> {
+        // Reaps a dead process, stops a live one before its replacement
+        // claims the same IPC address.
+        let _ = self.daemon.shutdown().await;
+
+        let mut daemon = self.builder.clone().spawn().await?;
+        let client = daemon.client().await?;
+        for (addr, protocols) in &self.stream_handlers {
+            client
+                .register_stream_handler(addr, protocols.clone())
+                .await?;
+        }
+        for reg in &self.unary_handlers {
+            let handler = reg.handler.clone();
+            client
+                .add_unary_handler(&reg.proto, move |data| handler(data), reg.balanced)
+                .await?;
+        }
+        Ok((daemon, client))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_restarts_healthy_daemons_immediately_then_doubles() {
+        let delay = |n| backoff_delay(INITIAL_BACKOFF, MAX_BACKOFF, n);
+        assert_eq!(delay(0), Duration::ZERO);
+        assert_eq!(delay(1), Duration::from_secs(5));
+        assert_eq!(delay(2), Duration::from_secs(10));
+        assert_eq!(delay(4), Duration::from_secs(40));
+        assert_eq!(delay(7), MAX_BACKOFF);
+        assert_eq!(delay(u32::MAX), MAX_BACKOFF);
+    }
+}
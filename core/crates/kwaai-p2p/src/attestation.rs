@@ -0,0 +1,517 @@
+//! Signed, time-limited capability attestations.
+//!
+//! [`NodeCapabilities`] on its own is a self-reported claim with no expiry:
+//! anyone can advertise any compute power forever. A
+//! [`CapabilityAttestation`] wraps the claim with an issue/expiry window and
+//! the subject's signature, made with the same libp2p identity key that
+//! derives its `PeerId`. Verification therefore needs no key registry — the
+//! embedded public key must hash to the claimed peer ID.
+//!
+//! Another peer can **co-sign** an attestation after running a verification
+//! benchmark against the subject. A co-signature binds the verifier's
+//! measured numbers to the exact signed claim, so it cannot be moved onto a
+//! different or renewed attestation.
+//!
+//! Consumers:
+//! - **ranking** — [`ReputationStore::rank_attested`] lifts peers whose claims
+//!   a verifier has confirmed;
+//! - **admission** — [`AdmissionPolicy::admit`] gates a peer on a valid,
+//!   unexpired attestation meeting minimum (optionally co-signed) capability.
+//!
+//! [`ReputationStore::rank_attested`]: crate::ReputationStore::rank_attested
+
+use crate::error::{P2PError, P2PResult};
+use crate::reputation::now_secs;
+use crate::NodeCapabilities;
+use libp2p::identity::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Longest lifetime an attestation may claim. Capabilities drift (GPUs get
+/// shared, memory fills up), so claims must be re-issued regularly.
+pub const MAX_ATTESTATION_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Tolerated clock skew between issuer and verifier.
+pub const CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+/// Domain separators so a capability signature can never be replayed as a
+/// signature over some other KwaaiNet message, and vice versa.
+const CLAIM_DOMAIN: &[u8] = b"kwaai/capability-attestation/v1";
+const COSIGN_DOMAIN: &[u8] = b"kwaai/capability-cosignature/v1";
+
+/// Why an attestation was rejected.
+#[derive(Debug, Error, PartialEq)]
+pub enum AttestationError {
+    #[error("malformed public key")]
+    BadPublicKey,
+    #[error("public key does not match claimed peer ID {0}")]
+    PeerIdMismatch(String),
+    #[error("invalid subject signature")]
+    BadSignature,
+    #[error("invalid co-signature from {0}")]
+    BadCosignature(String),
+    #[error("attestation is not valid until {0}")]
+    NotYetValid(u64),
+    #[error("attestation expired at {0}")]
+    Expired(u64),
+    #[error("attestation lifetime exceeds {}s", MAX_ATTESTATION_TTL.as_secs())]
+    TtlTooLong,
+    #[error("peer co-signed its own attestation")]
+    SelfCosigned,
+    #[error("no co-signature from a trusted verifier")]
+    NotCosigned,
+    #[error("compute power {have} TFLOPS below required {need}")]
+    InsufficientCompute { have: f32, need: f32 },
+    #[error("memory {have} MB below required {need}")]
+    InsufficientMemory { have: u64, need: u64 },
+}
+
+/// Numbers a verifier measured while benchmarking the subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    /// Measured compute power (TFLOPS)
+    pub compute_power: f32,
+    /// Measured available memory (MB)
+    pub available_memory: u64,
+    /// When the benchmark ran (Unix seconds)
+    pub measured_at: u64,
+}
+
+/// A verifier's signature over the subject's claim plus its own measurements.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cosignature {
+    /// Verifier's peer ID (base58)
+    pub verifier: String,
+    /// Protobuf-encoded libp2p public key of the verifier
+    pub public_key: Vec<u8>,
+    pub benchmark: BenchmarkResult,
+    pub signature: Vec<u8>,
+}
+
+/// A [`NodeCapabilities`] claim signed by its subject, valid for a bounded
+/// window, optionally co-signed by verifiers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityAttestation {
+    pub capabilities: NodeCapabilities,
+    /// Unix seconds
+    pub issued_at: u64,
+    /// Unix seconds
+    pub expires_at: u64,
+    /// Protobuf-encoded libp2p public key of the subject
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+    #[serde(default)]
+    pub cosignatures: Vec<Cosignature>,
+}
+
+impl CapabilityAttestation {
+    /// Sign `capabilities` with the node's identity key, valid for `ttl`
+    /// (capped at [`MAX_ATTESTATION_TTL`]). `capabilities.peer_id` is
+    /// overwritten with the key's peer ID.
+    pub fn issue(
+        keypair: &Keypair,
+        mut capabilities: NodeCapabilities,
+        ttl: Duration,
+    ) -> P2PResult<Self> {
+        let public = keypair.public();
+        capabilities.peer_id = public.to_peer_id().to_base58();
+        let issued_at = now_secs();
+        let expires_at = issued_at + ttl.min(MAX_ATTESTATION_TTL).as_secs();
+
+        let mut att = Self {
+            capabilities,
+            issued_at,
+            expires_at,
+            public_key: public.encode_protobuf(),
+            signature: Vec::new(),
+            cosignatures: Vec::new(),
+        };
+        att.signature = keypair
+            .sign(&att.claim_bytes()?)
+            .map_err(|e| P2PError::Internal(format!("signing attestation: {e}")))?;
+        Ok(att)
+    }
+
+    /// Co-sign after benchmarking the subject. Replaces any earlier
+    /// co-signature by the same verifier.
+    pub fn cosign(&mut self, verifier: &Keypair, benchmark: BenchmarkResult) -> P2PResult<()> {
+        let public = verifier.public();
+        let verifier_id = public.to_peer_id().to_base58();
+        let signature = verifier
+            .sign(&self.cosign_bytes(&benchmark)?)
+            .map_err(|e| P2PError::Internal(format!("co-signing attestation: {e}")))?;
+        self.cosignatures.retain(|c| c.verifier != verifier_id);
+        self.cosignatures.push(Cosignature {
+            verifier: verifier_id,
+            public_key: public.encode_protobuf(),
+            benchmark,
+            signature,
+        });
+        Ok(())
+    }
+
+    /// Check signatures and the validity window against the current time.
+    pub fn verify(&self) -> Result<VerifiedCapabilities, AttestationError> {
+        self.verify_at(now_secs())
+    }
+
+    /// As [`verify`](Self::verify), at an explicit Unix time.
+    pub fn verify_at(&self, now: u64) -> Result<VerifiedCapabilities, AttestationError> {
+        let subject = PublicKey::try_decode_protobuf(&self.public_key)
+            .map_err(|_| AttestationError::BadPublicKey)?;
+        let subject_id = subject.to_peer_id().to_base58();
+        if subject_id != self.capabilities.peer_id {
+            return Err(AttestationError::PeerIdMismatch(
+                self.capabilities.peer_id.clone(),
+            ));
+        }
+        let claim = self
+            .claim_bytes()
+            .map_err(|_| AttestationError::BadSignature)?;
+        if !subject.verify(&claim, &self.signature) {
+            return Err(AttestationError::BadSignature);
+        }
+
+        if self.expires_at.saturating_sub(self.issued_at) > MAX_ATTESTATION_TTL.as_secs() {
+            return Err(AttestationError::TtlTooLong);
+        }
+        if self.issued_at > now + CLOCK_SKEW.as_secs() {
+            return Err(AttestationError::NotYetValid(self.issued_at));
+        }
+        if now > self.expires_at + CLOCK_SKEW.as_secs() {
+            return Err(AttestationError::Expired(self.expires_at));
+        }
+
+        let mut benchmarks = Vec::with_capacity(self.cosignatures.len());
+        for c in &self.cosignatures {
+            let key = PublicKey::try_decode_protobuf(&c.public_key)
+                .map_err(|_| AttestationError::BadCosignature(c.verifier.clone()))?;
+            if key.to_peer_id().to_base58() != c.verifier {
+                return Err(AttestationError::BadCosignature(c.verifier.clone()));
+            }
+            if c.verifier == subject_id {
+                return Err(AttestationError::SelfCosigned);
+            }
+            let msg = self
+                .cosign_bytes(&c.benchmark)
+                .map_err(|_| AttestationError::BadCosignature(c.verifier.clone()))?;
+            if !key.verify(&msg, &c.signature) {
+                return Err(AttestationError::BadCosignature(c.verifier.clone()));
+            }
+            benchmarks.push((c.verifier.clone(), c.benchmark.clone()));
+        }
+
+        Ok(VerifiedCapabilities {
+            capabilities: self.capabilities.clone(),
+            expires_at: self.expires_at,
+            benchmarks,
+        })
+    }
+
+    /// Encode for DHT storage.
+    pub fn encode(&self) -> P2PResult<Vec<u8>> {
+        bincode::serialize(self).map_err(|e| P2PError::Serialization(e.to_string()))
+    }
+
+    /// Decode from DHT. Does not verify — call [`verify`](Self::verify).
+    pub fn decode(data: &[u8]) -> P2PResult<Self> {
+        bincode::deserialize(data).map_err(|e| P2PError::Serialization(e.to_string()))
+    }
+
+    /// Bytes the subject signs: the claim and its validity window.
+    fn claim_bytes(&self) -> P2PResult<Vec<u8>> {
+        let body = bincode::serialize(&(&self.capabilities, self.issued_at, self.expires_at))
+            .map_err(|e| P2PError::Serialization(e.to_string()))?;
+        Ok([CLAIM_DOMAIN, &body].concat())
+    }
+
+    /// Bytes a verifier signs: the subject's signed claim (including its
+    /// signature, pinning the exact attestation) and the measurements.
+    fn cosign_bytes(&self, benchmark: &BenchmarkResult) -> P2PResult<Vec<u8>> {
+        let body = bincode::serialize(&(self.claim_bytes()?, &self.signature, benchmark))
+            .map_err(|e| P2PError::Serialization(e.to_string()))?;
+        Ok([COSIGN_DOMAIN, &body].concat())
+    }
+}
+
+/// Output of a successful [`CapabilityAttestation::verify`].
+#[derive(Debug, Clone)]
+pub struct VerifiedCapabilities {
+    pub capabilities: NodeCapabilities,
+    pub expires_at: u64,
+    /// `(verifier peer ID, measurements)` for every valid co-signature
+    pub benchmarks: Vec<(String, BenchmarkResult)>,
+}
+
+impl VerifiedCapabilities {
+    /// Drop co-signatures from verifiers not in `trusted`.
+    pub fn retain_verifiers(&mut self, trusted: &HashSet<String>) {
+        self.benchmarks.retain(|(v, _)| trusted.contains(v));
+    }
+
+    pub fn is_cosigned(&self) -> bool {
+        !self.benchmarks.is_empty()
+    }
+
+    /// Compute power a verifier has measured, capped at the claim so
+    /// over-performing never inflates it. `None` if not co-signed.
+    pub fn verified_compute_power(&self) -> Option<f32> {
+        self.benchmarks
+            .iter()
+            .map(|(_, b)| b.compute_power)
+            .reduce(f32::max)
+            .map(|m| m.min(self.capabilities.compute_power))
+    }
+
+    /// Memory a verifier has measured, capped at the claim.
+    pub fn verified_memory(&self) -> Option<u64> {
+        self.benchmarks
+            .iter()
+            .map(|(_, b)| b.available_memory)
+            .max()
+            .map(|m| m.min(self.capabilities.available_memory))
+    }
+
+    /// Verified compute power when co-signed, otherwise the signed claim.
+    pub fn effective_compute_power(&self) -> f32 {
+        self.verified_compute_power()
+            .unwrap_or(self.capabilities.compute_power)
+    }
+
+    /// Verified memory when co-signed, otherwise the signed claim.
+    pub fn effective_memory(&self) -> u64 {
+        self.verified_memory()
+            .unwrap_or(self.capabilities.available_memory)
+    }
+
+    /// Fraction of the compute claim a verifier confirmed (1.0 = fully).
+    /// `None` if not co-signed.
+    pub fn confirmation_ratio(&self) -> Option<f64> {
+        let claimed = self.capabilities.compute_power;
+        self.verified_compute_power().map(|v| {
+            if claimed > 0.0 {
+                (v / claimed) as f64
+            } else {
+                1.0
+            }
+        })
+    }
+}
+
+/// Minimum requirements for admitting a peer (e.g. into a serving chain or
+/// an averaging group).
+#[derive(Debug, Clone, Default)]
+pub struct AdmissionPolicy {
+    /// Minimum effective compute power (TFLOPS)
+    pub min_compute_power: f32,
+    /// Minimum effective memory (MB)
+    pub min_memory_mb: u64,
+    /// Require at least one co-signature from a (trusted) verifier
+    pub require_cosigned: bool,
+    /// Only count co-signatures from these peer IDs; empty trusts any
+    /// verifier other than the subject
+    pub trusted_verifiers: HashSet<String>,
+}
+
+impl AdmissionPolicy {
+    /// Verify `attestation` and check it against the policy.
+    pub fn admit(
+        &self,
+        attestation: &CapabilityAttestation,
+    ) -> Result<VerifiedCapabilities, AttestationError> {
+        self.admit_at(attestation, now_secs())
+    }
+
+    /// As [`admit`](Self::admit), at an explicit Unix time.
+    pub fn admit_at(
+        &self,
+        attestation: &CapabilityAttestation,
+        now: u64,
+    ) -> Result<VerifiedCapabilities, AttestationError> {
+        let mut verified = attestation.verify_at(now)?;
+        if !self.trusted_verifiers.is_empty() {
+            verified.retain_verifiers(&self.trusted_verifiers);
+        }
+        if self.require_cosigned && !verified.is_cosigned() {
+            return Err(AttestationError::NotCosigned);
+        }
+        let compute = verified.effective_compute_power();
+        if compute < self.min_compute_power {
+            return Err(AttestationError::InsufficientCompute {
+                have: compute,
+                need: self.min_compute_power,
+            });
+        }
+        let memory = verified.effective_memory();
+        if memory < self.min_memory_mb {
+            return Err(AttestationError::InsufficientMemory {
+                have: memory,
+                need: self.min_memory_mb,
+            });
+        }
+        Ok(verified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(compute: f32, memory: u64) -> NodeCapabilities {
+        let mut c = NodeCapabilities::new(String::new());
+        c.can_inference = true;
+        c.compute_power = compute;
+        c.available_memory = memory;
+        c
+    }
+
+    fn bench(compute: f32, memory: u64) -> BenchmarkResult {
+        BenchmarkResult {
+            compute_power: compute,
+            available_memory: memory,
+            measured_at: now_secs(),
+        }
+    }
+
+    #[test]
+    fn issue_and_verify_round_trip() {
+        let key = Keypair::generate_ed25519();
+        let att = CapabilityAttestation::issue(&key, caps(10.0, 8192), Duration::from_secs(3600))
+            .unwrap();
+        let decoded = CapabilityAttestation::decode(&att.encode().unwrap()).unwrap();
+        let v = decoded.verify().unwrap();
+        assert_eq!(
+            v.capabilities.peer_id,
+            key.public().to_peer_id().to_base58()
+        );
+        assert!(!v.is_cosigned());
+        assert_eq!(v.effective_compute_power(), 10.0);
+    }
+
+    #[test]
+    fn tampered_claim_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let mut att =
+            CapabilityAttestation::issue(&key, caps(10.0, 8192), Duration::from_secs(3600))
+                .unwrap();
+        att.capabilities.compute_power = 100.0;
+        assert_eq!(att.verify().unwrap_err(), AttestationError::BadSignature);
+    }
+
+    #[test]
+    fn claim_for_another_peer_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let mut att =
+            CapabilityAttestation::issue(&key, caps(1.0, 1), Duration::from_secs(60)).unwrap();
+        att.public_key = other.public().encode_protobuf();
+        assert!(matches!(
+            att.verify(),
+            Err(AttestationError::PeerIdMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn expiry_and_ttl_cap() {
+        let key = Keypair::generate_ed25519();
+        let att =
+            CapabilityAttestation::issue(&key, caps(1.0, 1), Duration::from_secs(60)).unwrap();
+        let late = att.expires_at + CLOCK_SKEW.as_secs() + 1;
+        assert!(matches!(
+            att.verify_at(late),
+            Err(AttestationError::Expired(_))
+        ));
+
+        let long =
+            CapabilityAttestation::issue(&key, caps(1.0, 1), Duration::from_secs(10 * 86400))
+                .unwrap();
+        assert_eq!(
+            long.expires_at - long.issued_at,
+            MAX_ATTESTATION_TTL.as_secs()
+        );
+    }
+
+    #[test]
+    fn cosignature_caps_effective_capability() {
+        let subject = Keypair::generate_ed25519();
+        let verifier = Keypair::generate_ed25519();
+        let mut att =
+            CapabilityAttestation::issue(&subject, caps(10.0, 8192), Duration::from_secs(3600))
+                .unwrap();
+        att.cosign(&verifier, bench(6.0, 16384)).unwrap();
+
+        let v = att.verify().unwrap();
+        assert!(v.is_cosigned());
+        assert_eq!(v.effective_compute_power(), 6.0);
+        // Measuring more than claimed never inflates the claim.
+        assert_eq!(v.effective_memory(), 8192);
+        assert!((v.confirmation_ratio().unwrap() - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cosignature_does_not_transfer_to_renewed_attestation() {
+        let subject = Keypair::generate_ed25519();
+        let verifier = Keypair::generate_ed25519();
+        let mut old =
+            CapabilityAttestation::issue(&subject, caps(10.0, 1), Duration::from_secs(60)).unwrap();
+        old.cosign(&verifier, bench(10.0, 1)).unwrap();
+
+        let mut renewed =
+            CapabilityAttestation::issue(&subject, caps(50.0, 1), Duration::from_secs(120))
+                .unwrap();
+        renewed.cosignatures = old.cosignatures.clone();
+        assert!(matches!(
+            renewed.verify(),
+            Err(AttestationError::BadCosignature(_))
+        ));
+    }
+
+    #[test]
+    fn self_cosignature_is_rejected() {
+        let subject = Keypair::generate_ed25519();
+        let mut att =
+            CapabilityAttestation::issue(&subject, caps(1.0, 1), Duration::from_secs(60)).unwrap();
+        att.cosign(&subject, bench(1.0, 1)).unwrap();
+        assert_eq!(att.verify().unwrap_err(), AttestationError::SelfCosigned);
+    }
+
+    #[test]
+    fn admission_policy() {
+        let subject = Keypair::generate_ed25519();
+        let verifier = Keypair::generate_ed25519();
+        let stranger = Keypair::generate_ed25519();
+        let mut att =
+            CapabilityAttestation::issue(&subject, caps(10.0, 8192), Duration::from_secs(3600))
+                .unwrap();
+
+        let mut policy = AdmissionPolicy {
+            min_compute_power: 5.0,
+            min_memory_mb: 4096,
+            require_cosigned: true,
+            trusted_verifiers: HashSet::from([verifier.public().to_peer_id().to_base58()]),
+        };
+        assert_eq!(
+            policy.admit(&att).unwrap_err(),
+            AttestationError::NotCosigned
+        );
+
+        // A co-signature from an untrusted peer doesn't count.
+        att.cosign(&stranger, bench(10.0, 8192)).unwrap();
+        assert_eq!(
+            policy.admit(&att).unwrap_err(),
+            AttestationError::NotCosigned
+        );
+
+        att.cosign(&verifier, bench(4.0, 8192)).unwrap();
+        assert!(matches!(
+            policy.admit(&att),
+            Err(AttestationError::InsufficientCompute { .. })
+        ));
+
+        policy.min_compute_power = 3.0;
+        let admitted = policy.admit(&att).unwrap();
+        assert_eq!(admitted.benchmarks.len(), 1);
+    }
+}
@@ -0,0 +1,544 @@
+//! Backend-agnostic DHT access
+//!
+//! Higher layers (capability discovery, averaging matchmaking and progress)
+//! program against [`DhtOperations`]; [`DhtBackend`] adds which
+//! implementation is behind it, and [`open_backend`] picks the one named by
+//! [`NetworkConfig::dht_backend`]:
+//!
+//! - `kademlia`: libp2p Kademlia in [`KwaaiNetwork`]
+//! - `hivemind_native`: [`HivemindBackend`] over [`NativeDhtRpc`], a libp2p
+//!   swarm speaking the Hivemind DHT protocol itself and serving FIND / STORE
+//!   from a local [`DHTStorage`]
+//! - `hivemind_daemon`: [`HivemindBackend`] over the caller's p2pd transport
+//!   (this crate does not link the daemon client; `kwaainet` implements
+//!   [`DhtRpc`] over p2pd unary handlers and builds the backend itself)
+//!
+//! Every backend runs under the node's own identity, so records and
+//! provider entries carry the same peer ID as the rest of its traffic.
+//!
+//! On the Hivemind backends, string keys are hashed the way Hivemind's
+//! `DHTID.generate()` does, and providers of a key are kept as one msgpack
+//! list of base58 peer IDs stored under `"{key}.providers"`.
+
+use crate::{
+    config::{DhtBackendKind, NetworkConfig},
+    error::{P2PError, P2PResult},
+    health::HealthSnapshot,
+    network::{build_swarm, extract_peer_id},
+    DhtOperations, KwaaiNetwork, NetworkBehaviour,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use kwaai_hivemind_dht::{
+    client::{find_closest, store_on_closest, DhtRpc, RoutingConfig},
+    codec::{DHTRequest, DHTResponse, HivemindCodec},
+    protocol::{FindRequest, FindResponse, NodeInfo, StoreRequest, StoreResponse},
+    value::get_dht_time,
+    DHTStorage, ResultType, PROTOCOL_FIND, PROTOCOL_STORE,
+};
+use libp2p::{
+    identity,
+    request_response::{self, ProtocolSupport},
+    swarm::SwarmEvent,
+    Multiaddr, PeerId, StreamProtocol,
+};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{debug, info, warn};
+
+/// A DHT implementation usable by any higher layer
+#[async_trait]
+pub trait DhtBackend: DhtOperations {
+    /// Which implementation this is
+    fn kind(&self) -> DhtBackendKind;
+
+    /// Health of the swarm behind this backend, for backends that own one
+    async fn health_snapshot(&self) -> Option<HealthSnapshot> {
+        None
+    }
+}
+
+#[async_trait]
+impl DhtBackend for KwaaiNetwork {
+    fn kind(&self) -> DhtBackendKind {
+        DhtBackendKind::Kademlia
+    }
+
+    async fn health_snapshot(&self) -> Option<HealthSnapshot> {
+        KwaaiNetwork::health_snapshot(self).await.ok()
+    }
+}
+
+/// Open the backend selected by `config.dht_backend` as the node identity
+/// `keypair` and join the network through `config.bootstrap_peers`
+///
+/// `hivemind_daemon` cannot be opened here: build a [`HivemindBackend`] over
+/// the daemon's [`DhtRpc`] transport instead.
+pub async fn open_backend(
+    config: &NetworkConfig,
+    keypair: identity::Keypair,
+) -> P2PResult<Box<dyn DhtBackend>> {
+    let bootstrap: Vec<Multiaddr> = config
+        .bootstrap_peers
+        .iter()
+        .map(|a| {
+            a.parse()
+                .map_err(|e: libp2p::multiaddr::Error| P2PError::InvalidAddress(e.to_string()))
+        })
+        .collect::<P2PResult<_>>()?;
+    match config.dht_backend {
+        DhtBackendKind::Kademlia => {
+            let mut network = KwaaiNetwork::with_keypair(config.clone(), keypair).await?;
+            network.start().await?;
+            if !bootstrap.is_empty() {
+                network.bootstrap(bootstrap).await?;
+            }
+            network.spawn_event_loop();
+            Ok(Box::new(network))
+        }
+        DhtBackendKind::HivemindNative => {
+            let peer_id = PeerId::from(keypair.public());
+            let rpc =
+                NativeDhtRpc::spawn(keypair, config, Arc::new(DHTStorage::new(peer_id))).await?;
+            for addr in bootstrap {
+                if let Some(peer) = extract_peer_id(&addr) {
+                    rpc.add_address(peer, addr);
+                }
+            }
+            Ok(Box::new(HivemindBackend::new(rpc, peer_id, config)?))
+        }
+        DhtBackendKind::HivemindDaemon => Err(P2PError::InvalidConfig(
+            "the hivemind_daemon backend needs a p2pd transport; \
+             build a HivemindBackend over it"
+                .to_string(),
+        )),
+    }
+}
+
+/// DHT ID of a string key: SHA1 of its msgpack encoding
+pub fn dht_key(key: &str) -> Vec<u8> {
+    let packed = rmp_serde::to_vec(key).expect("msgpack string");
+    Sha1::new().chain_update(&packed).finalize().to_vec()
+}
+
+/// [`DhtOperations`] on the Hivemind DHT, over any [`DhtRpc`] transport
+///
+/// Records are stored on the peers closest to their key with
+/// [`store_on_closest`] and read back from them, newest expiration winning.
+/// Lookups are seeded with the bootstrap peers of the [`NetworkConfig`].
+pub struct HivemindBackend<R> {
+    kind: DhtBackendKind,
+    rpc: Mutex<R>,
+    local_peer_id: PeerId,
+    seeds: Vec<PeerId>,
+    routing: RoutingConfig,
+    record_ttl: f64,
+}
+
+impl<R: DhtRpc> HivemindBackend<R> {
+    /// Backend of kind `config.dht_backend`, which must be a Hivemind one
+    pub fn new(rpc: R, local_peer_id: PeerId, config: &NetworkConfig) -> P2PResult<Self> {
+        if config.dht_backend == DhtBackendKind::Kademlia {
+            return Err(P2PError::InvalidConfig(
+                "HivemindBackend needs a hivemind_* dht_backend".to_string(),
+            ));
+        }
+        let seeds = config
+            .bootstrap_peers
+            .iter()
+            .filter_map(|a| extract_peer_id(&a.parse().ok()?))
+            .collect();
+        Ok(Self {
+            kind: config.dht_backend,
+            rpc: Mutex::new(rpc),
+            local_peer_id,
+            seeds,
+            routing: RoutingConfig {
+                replicas: config.dht_replication.max(1),
+                rpc_timeout: config.request_timeout,
+                ..RoutingConfig::default()
+            },
+            record_ttl: config.record_ttl.as_secs_f64(),
+        })
+    }
+
+    /// Store `value` under the DHT ID `key`
+    async fn store(&self, key: Vec<u8>, value: Vec<u8>) -> P2PResult<()> {
+        let request = StoreRequest::new(
+            NodeInfo::from_peer_id(self.local_peer_id),
+            vec![key],
+            vec![vec![]],
+            vec![value],
+            vec![get_dht_time() + self.record_ttl],
+            vec![false],
+        );
+        let mut rpc = self.rpc.lock().await;
+        let outcome = store_on_closest(
+            &mut *rpc,
+            self.local_peer_id,
+            request,
+            &self.seeds,
+            &self.routing,
+        )
+        .await;
+        if outcome.any_stored() {
+            Ok(())
+        } else {
+            Err(P2PError::DhtError(
+                "no peer accepted the record".to_string(),
+            ))
+        }
+    }
+
+    /// Freshest value under the DHT ID `key` held by the closest peers
+    async fn find(&self, key: Vec<u8>) -> P2PResult<Option<Vec<u8>>> {
+        let mut rpc = self.rpc.lock().await;
+        let keys = [key];
+        let peers = find_closest(
+            &mut *rpc,
+            self.local_peer_id,
+            &keys,
+            &self.seeds,
+            &self.routing,
+        )
+        .await
+        .remove(&keys[0])
+        .filter(|peers| !peers.is_empty())
+        .unwrap_or_else(|| self.seeds.clone());
+        let [key] = keys;
+
+        let mut best: Option<(f64, Vec<u8>)> = None;
+        for peer in peers {
+            let request = FindRequest::new(
+                NodeInfo::from_peer_id(self.local_peer_id),
+                vec![key.clone()],
+            );
+            let response =
+                match tokio::time::timeout(self.routing.rpc_timeout, rpc.find(peer, request)).await
+                {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(e)) => {
+                        debug!("FIND to {} failed: {}", peer, e);
+                        continue;
+                    }
+                    Err(_) => {
+                        debug!("FIND to {} timed out", peer);
+                        continue;
+                    }
+                };
+            for result in response.results {
+                let newer = match &best {
+                    Some((expiration, _)) => result.expiration_time > *expiration,
+                    None => true,
+                };
+                if result.result_type == ResultType::FoundRegular as i32 && newer {
+                    best = Some((result.expiration_time, result.value));
+                }
+            }
+        }
+        Ok(best.map(|(_, value)| value))
+    }
+
+    async fn providers(&self, key: &str) -> P2PResult<Vec<String>> {
+        match self.find(dht_key(&format!("{key}.providers"))).await? {
+            Some(bytes) => rmp_serde::from_slice(&bytes)
+                .map_err(|e| P2PError::Serialization(format!("provider list: {e}"))),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl<R: DhtRpc> DhtOperations for HivemindBackend<R> {
+    async fn put(&mut self, key: &str, value: Vec<u8>) -> P2PResult<()> {
+        self.store(dht_key(key), value).await
+    }
+
+    async fn get(&self, key: &str) -> P2PResult<Option<Vec<u8>>> {
+        self.find(dht_key(key)).await
+    }
+
+    async fn provide(&mut self, key: &str) -> P2PResult<()> {
+        // Read-merge-write: a provider racing us may be dropped until its
+        // next provide.
+        let mut providers = self.providers(key).await?;
+        let me = self.local_peer_id.to_base58();
+        if !providers.contains(&me) {
+            providers.push(me);
+        }
+        let list =
+            rmp_serde::to_vec(&providers).map_err(|e| P2PError::Serialization(e.to_string()))?;
+        self.store(dht_key(&format!("{key}.providers")), list).await
+    }
+
+    async fn get_providers(&self, key: &str) -> P2PResult<Vec<PeerId>> {
+        Ok(self
+            .providers(key)
+            .await?
+            .iter()
+            .filter_map(|p| p.parse().ok())
+            .collect())
+    }
+}
+
+impl<R: DhtRpc> DhtBackend for HivemindBackend<R> {
+    fn kind(&self) -> DhtBackendKind {
+        self.kind
+    }
+}
+
+type Reply = oneshot::Sender<P2PResult<DHTResponse>>;
+
+enum NativeCommand {
+    Call {
+        peer: PeerId,
+        request: Box<DHTRequest>,
+        reply: Reply,
+    },
+    AddAddress {
+        peer: PeerId,
+        addr: Multiaddr,
+    },
+}
+
+/// [`DhtRpc`] on an in-process libp2p swarm speaking the Hivemind DHT
+/// protocol, which also answers other peers' FIND and STORE from `storage`
+///
+/// The swarm runs on its own task until every handle is dropped.
+#[derive(Clone)]
+pub struct NativeDhtRpc {
+    local_peer_id: PeerId,
+    listen_addrs: Vec<Multiaddr>,
+    commands: mpsc::UnboundedSender<NativeCommand>,
+}
+
+impl NativeDhtRpc {
+    /// Start a swarm for `key` listening on `config.listen_addrs`
+    pub async fn spawn(
+        key: identity::Keypair,
+        config: &NetworkConfig,
+        storage: Arc<DHTStorage>,
+    ) -> P2PResult<Self> {
+        let local_peer_id = PeerId::from(key.public());
+        let behaviour = request_response::Behaviour::<HivemindCodec>::new(
+            [
+                (StreamProtocol::new(PROTOCOL_STORE), ProtocolSupport::Full),
+                (StreamProtocol::new(PROTOCOL_FIND), ProtocolSupport::Full),
+            ],
+            request_response::Config::default().with_request_timeout(config.request_timeout),
+        );
+        let mut swarm = build_swarm(key, config, behaviour)?;
+        for addr in &config.listen_addrs {
+            let addr: Multiaddr = addr
+                .parse()
+                .map_err(|e: libp2p::multiaddr::Error| P2PError::InvalidAddress(e.to_string()))?;
+            swarm
+                .listen_on(addr)
+                .map_err(|e| P2PError::Transport(e.to_string()))?;
+        }
+
+        // Wait for every listener to bind, so callers can hand the actual
+        // addresses (e.g. for port 0) to their peers.
+        let mut listen_addrs = Vec::new();
+        while listen_addrs.len() < config.listen_addrs.len() {
+            match swarm.select_next_some().await {
+                SwarmEvent::NewListenAddr { address, .. } => listen_addrs.push(address),
+                SwarmEvent::ListenerError { error, .. } => {
+                    return Err(P2PError::Transport(error.to_string()))
+                }
+                _ => {}
+            }
+        }
+        info!(
+            "Hivemind DHT swarm {} listening on {:?}",
+            local_peer_id, listen_addrs
+        );
+
+        let (commands, mut rx) = mpsc::unbounded_channel::<NativeCommand>();
+        tokio::spawn(async move {
+            let mut pending: HashMap<request_response::OutboundRequestId, Reply> = HashMap::new();
+            let mut connected: HashSet<PeerId> = HashSet::new();
+            loop {
+                tokio::select! {
+                    command = rx.recv() => match command {
+                        Some(NativeCommand::Call { peer, request, reply }) => {
+                            let id = swarm.behaviour_mut().send_request(&peer, *request);
+                            pending.insert(id, reply);
+                        }
+                        Some(NativeCommand::AddAddress { peer, addr }) => {
+                            swarm.add_peer_address(peer, addr);
+                        }
+                        None => break,
+                    },
+                    event = swarm.select_next_some() => match event {
+                        SwarmEvent::Behaviour(request_response::Event::Message { message, .. }) => {
+                            match message {
+                                request_response::Message::Request { request, channel, .. } => {
+                                    match storage.handle_request(request) {
+                                        Ok(response) => {
+                                            let _ = swarm.behaviour_mut().send_response(channel, response);
+                                        }
+                                        Err(e) => debug!("Dropping DHT request: {}", e),
+                                    }
+                                }
+                                request_response::Message::Response { request_id, response } => {
+                                    if let Some(reply) = pending.remove(&request_id) {
+                                        let _ = reply.send(Ok(response));
+                                    }
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(request_response::Event::OutboundFailure {
+                            request_id, error, ..
+                        }) => {
+                            if let Some(reply) = pending.remove(&request_id) {
+                                let _ = reply.send(Err(P2PError::ConnectionFailed(error.to_string())));
+                            }
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                            connected.insert(peer_id);
+                            storage.update_peers(connected.iter().copied().collect());
+                        }
+                        SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                            connected.remove(&peer_id);
+                            storage.update_peers(connected.iter().copied().collect());
+                        }
+                        _ => {}
+                    },
+                }
+            }
+            debug!("Hivemind DHT swarm {} stopped", local_peer_id);
+        });
+
+        Ok(Self {
+            local_peer_id,
+            listen_addrs,
+            commands,
+        })
+    }
+
+    /// Peer ID of the swarm
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// Addresses the swarm listens on
+    pub fn listen_addrs(&self) -> &[Multiaddr] {
+        &self.listen_addrs
+    }
+
+    /// Remember `addr` as a way to reach `peer`
+    pub fn add_address(&self, peer: PeerId, addr: Multiaddr) {
+        let _ = self.commands.send(NativeCommand::AddAddress { peer, addr });
+    }
+
+    async fn call(&self, peer: PeerId, request: DHTRequest) -> P2PResult<DHTResponse> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(NativeCommand::Call {
+                peer,
+                request: Box::new(request),
+                reply,
+            })
+            .map_err(|_| P2PError::NotInitialized)?;
+        response.await.map_err(|_| P2PError::NotInitialized)?
+    }
+}
+
+#[async_trait]
+impl DhtRpc for NativeDhtRpc {
+    async fn find(
+        &mut self,
+        peer: PeerId,
+        request: FindRequest,
+    ) -> kwaai_hivemind_dht::Result<FindResponse> {
+        match self.call(peer, DHTRequest::Find(request)).await {
+            Ok(DHTResponse::Find(response)) => Ok(response),
+            Ok(_) => Err(unexpected_response()),
+            Err(e) => Err(kwaai_hivemind_dht::Error::Network(e.to_string())),
+        }
+    }
+
+    async fn store(
+        &mut self,
+        peer: PeerId,
+        request: StoreRequest,
+    ) -> kwaai_hivemind_dht::Result<StoreResponse> {
+        match self.call(peer, DHTRequest::Store(request)).await {
+            Ok(DHTResponse::Store(response)) => Ok(response),
+            Ok(_) => Err(unexpected_response()),
+            Err(e) => Err(kwaai_hivemind_dht::Error::Network(e.to_string())),
+        }
+    }
+}
+
+fn unexpected_response() -> kwaai_hivemind_dht::Error {
+    warn!("Hivemind DHT peer answered with the wrong response type");
+    kwaai_hivemind_dht::Error::Network("unexpected response type".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn native_config(bootstrap: &[String]) -> NetworkConfig {
+        NetworkConfig::builder()
+            .listen_addrs(vec!["/ip4/127.0.0.1/tcp/0".to_string()])
+            .bootstrap_peers(bootstrap.to_vec())
+            .dht_backend(DhtBackendKind::HivemindNative)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn hivemind_native_backends_share_records_and_providers() {
+        // A seed node that only serves its storage.
+        let seed_key = identity::Keypair::generate_ed25519();
+        let seed_id = PeerId::from(seed_key.public());
+        let seed = NativeDhtRpc::spawn(
+            seed_key,
+            &native_config(&[]),
+            Arc::new(DHTStorage::new(seed_id)),
+        )
+        .await
+        .unwrap();
+        let seed_addr = format!("{}/p2p/{}", seed.listen_addrs()[0], seed_id);
+
+        let config = native_config(&[seed_addr]);
+        let alice_key = identity::Keypair::generate_ed25519();
+        let alice_id = PeerId::from(alice_key.public());
+        let mut alice = open_backend(&config, alice_key).await.unwrap();
+        let mut bob = open_backend(&config, identity::Keypair::generate_ed25519())
+            .await
+            .unwrap();
+        assert_eq!(alice.kind(), DhtBackendKind::HivemindNative);
+
+        alice
+            .put("progress.run-1", b"step 7".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.get("progress.run-1").await.unwrap(),
+            Some(b"step 7".to_vec())
+        );
+        assert_eq!(bob.get("missing").await.unwrap(), None);
+
+        alice.provide("averaging.0b").await.unwrap();
+        bob.provide("averaging.0b").await.unwrap();
+        let providers = alice.get_providers("averaging.0b").await.unwrap();
+        assert_eq!(providers.len(), 2);
+        // Alice provides under the identity she was opened with.
+        assert!(providers.contains(&alice_id));
+    }
+
+    #[tokio::test]
+    async fn daemon_backend_is_not_opened_here() {
+        let config = NetworkConfig::builder()
+            .dht_backend(DhtBackendKind::HivemindDaemon)
+            .build();
+        assert!(open_backend(&config, identity::Keypair::generate_ed25519())
+            .await
+            .is_err());
+    }
+}
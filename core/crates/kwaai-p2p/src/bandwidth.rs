@@ -0,0 +1,287 @@
+//! Bandwidth limiting for P2P traffic
+//!
+//! Token buckets cap upload and download rates. Message-based traffic
+//! (unary RPCs, DHT requests) waits on [`BandwidthLimiter::upload`] /
+//! [`BandwidthLimiter::download`] around each message; streamed transfers
+//! wrap their stream in [`Throttled`], which paces every read and write.
+//!
+//! A bucket may go into debt for a message larger than its burst: the
+//! message passes and the *next* one waits until the debt is repaid, so
+//! the long-run rate holds without splitting messages.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+/// Largest write passed to the inner stream at once, so pacing stays smooth
+const MAX_WRITE: usize = 16 * 1024;
+
+/// Token bucket refilled at a fixed byte rate
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_sec: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Bucket passing `bytes_per_sec`, with up to one second of burst
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        Self {
+            bytes_per_sec: rate,
+            burst: rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Configured rate
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec as u64
+    }
+
+    /// Take `bytes` from the bucket and return how long to wait before the
+    /// caller may send more
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(state.refilled_at).as_secs_f64() * self.bytes_per_sec;
+        state.tokens = (state.tokens + refill).min(self.burst) - bytes as f64;
+        state.refilled_at = now;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.bytes_per_sec)
+        }
+    }
+
+    /// Wait until `bytes` fit within the rate
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Upload and download limits shared by all of a node's transfers
+#[derive(Debug, Default)]
+pub struct BandwidthLimiter {
+    upload: Option<TokenBucket>,
+    download: Option<TokenBucket>,
+}
+
+impl BandwidthLimiter {
+    /// Limiter with the given caps in bytes per second (`None` = unlimited)
+    pub fn new(upload_bytes_per_sec: Option<u64>, download_bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            upload: upload_bytes_per_sec.map(TokenBucket::new),
+            download: download_bytes_per_sec.map(TokenBucket::new),
+        }
+    }
+
+    /// Limiter that never waits
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Whether either direction is capped
+    pub fn is_limited(&self) -> bool {
+        self.upload.is_some() || self.download.is_some()
+    }
+
+    /// Upload cap in bytes per second
+    pub fn upload_limit(&self) -> Option<u64> {
+        self.upload.as_ref().map(TokenBucket::bytes_per_sec)
+    }
+
+    /// Download cap in bytes per second
+    pub fn download_limit(&self) -> Option<u64> {
+        self.download.as_ref().map(TokenBucket::bytes_per_sec)
+    }
+
+    /// Account for sending `bytes`, waiting if over the upload cap
+    pub async fn upload(&self, bytes: usize) {
+        if let Some(bucket) = &self.upload {
+            bucket.acquire(bytes).await;
+        }
+    }
+
+    /// Account for receiving `bytes`, waiting if over the download cap
+    pub async fn download(&self, bytes: usize) {
+        if let Some(bucket) = &self.download {
+            bucket.acquire(bytes).await;
+        }
+    }
+
+    fn reserve_upload(&self, bytes: usize) -> Duration {
+        self.upload
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.reserve(bytes))
+    }
+
+    fn reserve_download(&self, bytes: usize) -> Duration {
+        self.download
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.reserve(bytes))
+    }
+}
+
+/// Stream whose reads and writes are paced by a [`BandwidthLimiter`]
+pub struct Throttled<S> {
+    inner: S,
+    limiter: Arc<BandwidthLimiter>,
+    read_wait: Option<Pin<Box<Sleep>>>,
+    write_wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    /// Pace `inner` with `limiter`
+    pub fn new(inner: S, limiter: Arc<BandwidthLimiter>) -> Self {
+        Self {
+            inner,
+            limiter,
+            read_wait: None,
+            write_wait: None,
+        }
+    }
+
+    /// The wrapped stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap the stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Wait out a pending delay, clearing it once elapsed
+fn poll_wait(wait: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<()> {
+    if let Some(sleep) = wait {
+        if sleep.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        *wait = None;
+    }
+    Poll::Ready(())
+}
+
+fn delay(wait: Duration) -> Option<Pin<Box<Sleep>>> {
+    (!wait.is_zero()).then(|| Box::pin(tokio::time::sleep(wait)))
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if poll_wait(&mut this.read_wait, cx).is_pending() {
+            return Poll::Pending;
+        }
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = buf.filled().len() - before;
+            this.read_wait = delay(this.limiter.reserve_download(read));
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if poll_wait(&mut this.write_wait, cx).is_pending() {
+            return Poll::Pending;
+        }
+        let len = buf.len().min(MAX_WRITE);
+        let result = Pin::new(&mut this.inner).poll_write(cx, &buf[..len]);
+        if let Poll::Ready(Ok(written)) = result {
+            this.write_wait = delay(this.limiter.reserve_upload(written));
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn bucket_allows_a_burst_then_charges_debt() {
+        let bucket = TokenBucket::new(10_000);
+        assert_eq!(bucket.reserve(10_000), Duration::ZERO);
+        let wait = bucket.reserve(5_000);
+        assert!(
+            wait > Duration::from_millis(450) && wait <= Duration::from_millis(500),
+            "{wait:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unlimited_limiter_never_waits() {
+        let limiter = BandwidthLimiter::unlimited();
+        assert!(!limiter.is_limited());
+        let start = Instant::now();
+        limiter.upload(100 << 20).await;
+        limiter.download(100 << 20).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn throttled_stream_holds_the_upload_rate() {
+        let limiter = Arc::new(BandwidthLimiter::new(Some(40_000), None));
+        let (a, mut b) = tokio::io::duplex(1 << 20);
+        let mut writer = Throttled::new(a, limiter);
+
+        let start = Instant::now();
+        let reader = tokio::spawn(async move {
+            let mut got = Vec::new();
+            b.read_to_end(&mut got).await.unwrap();
+            got.len()
+        });
+        // One second of burst passes at once; all but the last 16 KiB
+        // write of the rest waits its turn (~0.6 s)
+        writer.write_all(&[7u8; 80_000]).await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+
+        assert_eq!(reader.await.unwrap(), 80_000);
+        assert!(
+            start.elapsed() >= Duration::from_millis(500),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+}
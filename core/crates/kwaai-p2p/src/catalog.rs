@@ -0,0 +1,244 @@
+//! Models the swarm serves, from the `_petals.models` DHT registry
+//!
+//! Every block server stores an entry for its model under the registry key
+//! ([`registry_key`]): the subkey is the model's DHT prefix, the value a
+//! msgpack map `{repository, num_blocks}`. [`ModelCatalog`] decodes the
+//! dictionaries found under that key into one [`CatalogEntry`] per prefix,
+//! so a node can pick a model by what the network actually serves and learn
+//! the prefix and block count its servers announce with.
+//!
+//! As in [`crate::state`], querying the DHT is left to the caller.
+
+use rmpv::Value;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// Raw DHT key of the model registry
+pub const MODEL_REGISTRY_KEY: &str = "_petals.models";
+
+/// DHT key of the model registry: SHA1 of the msgpack-encoded
+/// [`MODEL_REGISTRY_KEY`]
+pub fn registry_key() -> Vec<u8> {
+    let packed = rmp_serde::to_vec(MODEL_REGISTRY_KEY).expect("msgpack string");
+    Sha1::new().chain_update(&packed).finalize().to_vec()
+}
+
+/// One model registered by the swarm
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    /// Prefix of the model's block keys, e.g. `Llama-3-1-8B-Instruct-hf`
+    pub dht_prefix: String,
+    /// HuggingFace repository URL, e.g. `https://huggingface.co/org/name`
+    pub repository: String,
+    pub num_blocks: usize,
+}
+
+impl CatalogEntry {
+    /// HuggingFace model ID the repository URL points at (`org/name`)
+    pub fn model_id(&self) -> Option<&str> {
+        let id = self
+            .repository
+            .trim_end_matches('/')
+            .strip_prefix("https://huggingface.co/")?;
+        id.contains('/').then_some(id)
+    }
+
+    /// Whether `name` refers to this model: its DHT prefix, model ID,
+    /// repository URL or the model ID's base name, or a name whose base
+    /// derives the prefix ([`crate::hivemind::derive_dht_prefix`]), optionally
+    /// with `-hf`. Case-insensitive.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim().trim_end_matches('/');
+        let base = name.rsplit('/').next().unwrap_or(name);
+        let derived = crate::hivemind::derive_dht_prefix(base);
+        let prefix = self.dht_prefix.as_str();
+        [prefix, self.repository.trim_end_matches('/')]
+            .into_iter()
+            .chain(self.model_id())
+            .any(|candidate| candidate.eq_ignore_ascii_case(name))
+            || self
+                .model_id()
+                .and_then(|id| id.rsplit('/').next())
+                .is_some_and(|id_base| id_base.eq_ignore_ascii_case(base))
+            || prefix.eq_ignore_ascii_case(&derived)
+            || prefix
+                .strip_suffix("-hf")
+                .is_some_and(|p| p.eq_ignore_ascii_case(&derived))
+    }
+
+    /// Decode one registry value, `{repository, num_blocks}`
+    fn decode(dht_prefix: String, bytes: &[u8]) -> Option<Self> {
+        let value = rmpv::decode::read_value(&mut &bytes[..]).ok()?;
+        let map = value.as_map()?;
+        let get = |key: &str| {
+            map.iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v)
+        };
+        Some(Self {
+            dht_prefix,
+            repository: get("repository")?.as_str()?.to_string(),
+            num_blocks: get("num_blocks")?.as_u64()? as usize,
+        })
+    }
+}
+
+/// Registered models, one entry per DHT prefix
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelCatalog {
+    pub entries: Vec<CatalogEntry>,
+    /// Unix time the catalog was read from the network
+    pub fetched_at: u64,
+}
+
+impl ModelCatalog {
+    pub fn new(fetched_at: u64) -> Self {
+        Self {
+            entries: Vec::new(),
+            fetched_at,
+        }
+    }
+
+    /// Add a value found under [`registry_key`]. Only dictionaries carry
+    /// the prefix (as subkey); the first entry seen for a prefix is kept.
+    pub fn add_value(&mut self, result_type: i32, bytes: &[u8]) {
+        if result_type != 2 {
+            return;
+        }
+        let Ok(Value::Ext(_, data)) = rmpv::decode::read_value(&mut &bytes[..]) else {
+            return;
+        };
+        let Ok(inner) = rmpv::decode::read_value(&mut &data[..]) else {
+            return;
+        };
+        let Some(items) = inner
+            .as_array()
+            .and_then(|a| a.get(2))
+            .and_then(|e| e.as_array())
+        else {
+            return;
+        };
+        for item in items {
+            let Some(arr) = item.as_array() else { continue };
+            // The subkey is the prefix, either as a string or as the msgpack
+            // encoding of one.
+            let prefix = match arr.first() {
+                Some(Value::String(s)) => s.as_str().map(str::to_string),
+                Some(Value::Binary(b)) => match rmpv::decode::read_value(&mut b.as_slice()) {
+                    Ok(Value::String(s)) => s.as_str().map(str::to_string),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let (Some(prefix), Some(Value::Binary(value))) = (prefix, arr.get(1)) else {
+                continue;
+            };
+            if prefix.is_empty() || self.entries.iter().any(|e| e.dht_prefix == prefix) {
+                continue;
+            }
+            if let Some(entry) = CatalogEntry::decode(prefix, value) {
+                self.entries.push(entry);
+            }
+        }
+        self.entries.sort_by(|a, b| a.dht_prefix.cmp(&b.dht_prefix));
+    }
+
+    /// The entry `name` refers to (see [`CatalogEntry::matches`])
+    pub fn find(&self, name: &str) -> Option<&CatalogEntry> {
+        self.entries.iter().find(|e| e.matches(name))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(entries: &[(&str, &str, i64)]) -> Vec<u8> {
+        let items = entries
+            .iter()
+            .map(|(prefix, repo, blocks)| {
+                let mut value = Vec::new();
+                rmpv::encode::write_value(
+                    &mut value,
+                    &Value::Map(vec![
+                        (Value::from("repository"), Value::from(*repo)),
+                        (Value::from("num_blocks"), Value::from(*blocks)),
+                    ]),
+                )
+                .unwrap();
+                Value::Array(vec![
+                    Value::Binary(rmp_serde::to_vec(prefix).unwrap()),
+                    Value::Binary(value),
+                    Value::from(1e10),
+                ])
+            })
+            .collect();
+        let inner = Value::Array(vec![
+            Value::from(1e10),
+            Value::from(0.0),
+            Value::Array(items),
+        ]);
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &inner).unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &Value::Ext(80, data)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn decodes_registry_dictionary() {
+        let mut catalog = ModelCatalog::new(0);
+        catalog.add_value(
+            2,
+            &registry(&[
+                (
+                    "Llama-3-1-8B-Instruct-hf",
+                    "https://huggingface.co/unsloth/Llama-3.1-8B-Instruct",
+                    32,
+                ),
+                (
+                    "TinyLlama-1-1B-Chat-v1-0",
+                    "https://huggingface.co/TinyLlama/TinyLlama-1.1B-Chat-v1.0",
+                    22,
+                ),
+            ]),
+        );
+        // A second bootstrap peer's copy adds nothing new.
+        catalog.add_value(
+            2,
+            &registry(&[("TinyLlama-1-1B-Chat-v1-0", "https://example.com/other", 99)]),
+        );
+        catalog.add_value(1, b"ignored");
+        assert_eq!(catalog.entries.len(), 2);
+        let tiny = &catalog.entries[1];
+        assert_eq!(tiny.num_blocks, 22);
+        assert_eq!(tiny.model_id(), Some("TinyLlama/TinyLlama-1.1B-Chat-v1.0"));
+    }
+
+    #[test]
+    fn finds_models_by_prefix_id_or_name() {
+        let mut catalog = ModelCatalog::new(0);
+        catalog.add_value(
+            2,
+            &registry(&[(
+                "Llama-3-1-8B-Instruct-hf",
+                "https://huggingface.co/unsloth/Llama-3.1-8B-Instruct",
+                32,
+            )]),
+        );
+        for name in [
+            "Llama-3-1-8B-Instruct-hf",
+            "unsloth/llama-3.1-8b-instruct",
+            "https://huggingface.co/unsloth/Llama-3.1-8B-Instruct/",
+            "Llama-3.1-8B-Instruct",
+            "meta-llama/Llama-3.1-8B-Instruct",
+        ] {
+            assert!(catalog.find(name).is_some(), "{name}");
+        }
+        assert!(catalog.find("meta-llama/Llama-2-7b-hf").is_none());
+    }
+}
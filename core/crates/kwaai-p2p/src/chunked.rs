@@ -0,0 +1,625 @@
+//! Chunked streaming transfers for large payloads
+//!
+//! [`Request`](crate::Request)/[`Response`](crate::Response) carry a single
+//! `Vec<u8>`, which is fine for activations but not for multi-GB parameter
+//! exchanges or model shards. This module moves such payloads over any
+//! byte stream (a p2pd stream, a TCP socket, an in-memory duplex) as a
+//! sequence of numbered chunks:
+//!
+//! ```text
+//! sender                                receiver
+//!   ── Offer { id, total_len, chunk } ──▶
+//!   ◀────────── Accept { next_seq } ─────   (0, or where a previous attempt stopped)
+//!   ── Chunk { seq, data } ────────────▶
+//!   ◀────────── Ack { next_seq } ────────   (after the chunk is written to the sink)
+//!   ...
+//! ```
+//!
+//! At most [`TransferConfig::window`] chunks are unacknowledged at any time,
+//! so a slow receiver throttles the sender instead of buffering the whole
+//! payload in memory. The receiver records its [`TransferProgress`]; handing
+//! the same progress to a new [`receive_chunked`] call after a reconnect makes
+//! the sender resume from the first chunk that was not yet written.
+//!
+//! Frames are a 4-byte big-endian length followed by a bincode-encoded
+//! [`Frame`].
+
+use crate::error::{P2PError, P2PResult};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, trace};
+
+/// Default chunk size (1 MiB)
+pub const DEFAULT_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// Largest chunk size a receiver accepts (16 MiB)
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Bytes allowed on top of the chunk payload for the frame header
+const FRAME_OVERHEAD: u32 = 64;
+
+/// Tunables for a chunked transfer
+#[derive(Debug, Clone)]
+pub struct TransferConfig {
+    /// Payload bytes per chunk (sender side)
+    pub chunk_size: u32,
+    /// Maximum number of unacknowledged chunks in flight
+    pub window: usize,
+    /// Largest transfer a receiver accepts
+    pub max_transfer_len: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            window: 8,
+            max_transfer_len: 64 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// A single protocol frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Frame {
+    /// Sender announces a transfer
+    Offer {
+        /// Caller-chosen identifier, stable across reconnects
+        transfer_id: u64,
+        /// Total payload length in bytes
+        total_len: u64,
+        /// Payload bytes per chunk (the last chunk may be shorter)
+        chunk_size: u32,
+    },
+    /// Receiver asks for chunks starting at `next_seq`
+    Accept {
+        /// First chunk the receiver still needs
+        next_seq: u64,
+    },
+    /// One chunk of payload
+    Chunk {
+        /// Chunk sequence number
+        seq: u64,
+        /// Chunk payload
+        data: Vec<u8>,
+    },
+    /// Receiver has written every chunk before `next_seq`
+    Ack {
+        /// First chunk not yet written
+        next_seq: u64,
+    },
+}
+
+/// Receiver-side state of a transfer, kept across reconnects
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferProgress {
+    /// Transfer identifier from the sender's offer
+    pub transfer_id: u64,
+    /// Total payload length in bytes
+    pub total_len: u64,
+    /// Payload bytes per chunk
+    pub chunk_size: u32,
+    /// First chunk not yet written to the sink
+    pub next_seq: u64,
+}
+
+impl TransferProgress {
+    /// Number of chunks in the transfer
+    pub fn total_chunks(&self) -> u64 {
+        chunk_count(self.total_len, self.chunk_size)
+    }
+
+    /// Bytes written to the sink so far
+    pub fn received_bytes(&self) -> u64 {
+        (self.next_seq * self.chunk_size as u64).min(self.total_len)
+    }
+
+    /// Whether every chunk has been written
+    pub fn is_complete(&self) -> bool {
+        self.next_seq >= self.total_chunks()
+    }
+
+    fn matches(&self, transfer_id: u64, total_len: u64, chunk_size: u32) -> bool {
+        self.transfer_id == transfer_id
+            && self.total_len == total_len
+            && self.chunk_size == chunk_size
+    }
+}
+
+fn chunk_count(total_len: u64, chunk_size: u32) -> u64 {
+    total_len.div_ceil(chunk_size.max(1) as u64)
+}
+
+/// Random-access payload for the sending side
+pub trait ChunkSource {
+    /// Total payload length in bytes
+    fn total_len(&mut self) -> std::io::Result<u64>;
+
+    /// Fill `buf` with the bytes starting at `offset`
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()>;
+}
+
+/// Random-access destination for the receiving side
+pub trait ChunkSink {
+    /// Called once per offer with the transfer length, before any chunk
+    fn prepare(&mut self, total_len: u64) -> std::io::Result<()>;
+
+    /// Write `data` at `offset`
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()>;
+}
+
+impl ChunkSource for &[u8] {
+    fn total_len(&mut self) -> std::io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let start = offset as usize;
+        let src = self
+            .get(start..start + buf.len())
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+}
+
+impl ChunkSink for Vec<u8> {
+    fn prepare(&mut self, total_len: u64) -> std::io::Result<()> {
+        self.resize(total_len as usize, 0);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        let start = offset as usize;
+        self.get_mut(start..start + data.len())
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::WriteZero))?
+            .copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl ChunkSource for std::fs::File {
+    fn total_len(&mut self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+}
+
+impl ChunkSink for std::fs::File {
+    fn prepare(&mut self, total_len: u64) -> std::io::Result<()> {
+        self.set_len(total_len)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+}
+
+/// Write one length-prefixed frame
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &Frame) -> P2PResult<()> {
+    let bytes = bincode::serialize(frame).map_err(|e| P2PError::Serialization(e.to_string()))?;
+    writer
+        .write_all(&(bytes.len() as u32).to_be_bytes())
+        .await?;
+    writer.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Read one length-prefixed frame, refusing frames larger than `max_len`
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, max_len: u32) -> P2PResult<Frame> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len);
+    if len > max_len {
+        return Err(P2PError::Protocol(format!(
+            "frame of {} bytes exceeds limit of {}",
+            len, max_len
+        )));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf).await?;
+    bincode::deserialize(&buf).map_err(|e| P2PError::Serialization(e.to_string()))
+}
+
+fn unexpected(frame: &Frame, expected: &str) -> P2PError {
+    P2PError::Protocol(format!(
+        "expected {}, got {:?}",
+        expected,
+        frame_kind(frame)
+    ))
+}
+
+fn frame_kind(frame: &Frame) -> &'static str {
+    match frame {
+        Frame::Offer { .. } => "Offer",
+        Frame::Accept { .. } => "Accept",
+        Frame::Chunk { .. } => "Chunk",
+        Frame::Ack { .. } => "Ack",
+    }
+}
+
+/// Send the payload in `source` as transfer `transfer_id`
+///
+/// Starts wherever the receiver says it left off, keeps at most
+/// `config.window` chunks unacknowledged, and returns once the receiver
+/// has acknowledged the last chunk.
+pub async fn send_chunked<S, R>(
+    stream: &mut S,
+    source: &mut R,
+    transfer_id: u64,
+    config: &TransferConfig,
+) -> P2PResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: ChunkSource + ?Sized,
+{
+    if config.chunk_size == 0 || config.chunk_size > MAX_CHUNK_SIZE {
+        return Err(P2PError::InvalidConfig(format!(
+            "chunk_size must be in 1..={}",
+            MAX_CHUNK_SIZE
+        )));
+    }
+    let window = config.window.max(1);
+    let total_len = source.total_len()?;
+    let chunk_size = config.chunk_size;
+    let total_chunks = chunk_count(total_len, chunk_size);
+
+    write_frame(
+        stream,
+        &Frame::Offer {
+            transfer_id,
+            total_len,
+            chunk_size,
+        },
+    )
+    .await?;
+    stream.flush().await?;
+
+    let mut next_seq = match read_frame(stream, FRAME_OVERHEAD).await? {
+        Frame::Accept { next_seq } if next_seq <= total_chunks => next_seq,
+        Frame::Accept { next_seq } => {
+            return Err(P2PError::Protocol(format!(
+                "receiver asked for chunk {} of {}",
+                next_seq, total_chunks
+            )))
+        }
+        other => return Err(unexpected(&other, "Accept")),
+    };
+    if next_seq > 0 {
+        debug!(transfer_id, next_seq, total_chunks, "Resuming transfer");
+    }
+
+    let mut in_flight: VecDeque<u64> = VecDeque::with_capacity(window);
+    let mut buf = Vec::with_capacity(chunk_size as usize);
+    while next_seq < total_chunks || !in_flight.is_empty() {
+        if next_seq < total_chunks && in_flight.len() < window {
+            let offset = next_seq * chunk_size as u64;
+            buf.resize((total_len - offset).min(chunk_size as u64) as usize, 0);
+            source.read_at(offset, &mut buf)?;
+            let frame = Frame::Chunk {
+                seq: next_seq,
+                data: std::mem::take(&mut buf),
+            };
+            write_frame(stream, &frame).await?;
+            if let Frame::Chunk { data, .. } = frame {
+                buf = data;
+            }
+            trace!(transfer_id, seq = next_seq, "Sent chunk");
+            in_flight.push_back(next_seq);
+            next_seq += 1;
+            continue;
+        }
+
+        // Window full (or everything sent): wait for the receiver to catch up.
+        stream.flush().await?;
+        match read_frame(stream, FRAME_OVERHEAD).await? {
+            Frame::Ack { next_seq: acked } => {
+                while in_flight.front().is_some_and(|&seq| seq < acked) {
+                    in_flight.pop_front();
+                }
+            }
+            other => return Err(unexpected(&other, "Ack")),
+        }
+    }
+
+    debug!(transfer_id, total_len, total_chunks, "Transfer sent");
+    Ok(())
+}
+
+/// Receive one transfer into `sink`
+///
+/// `progress` is updated after every chunk is written. If the call fails
+/// part way, pass the same `progress` to the next call for the same
+/// transfer and only the missing chunks are requested. An offer for a
+/// different transfer starts from scratch.
+pub async fn receive_chunked<S, W>(
+    stream: &mut S,
+    sink: &mut W,
+    progress: &mut Option<TransferProgress>,
+    config: &TransferConfig,
+) -> P2PResult<TransferProgress>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    W: ChunkSink + ?Sized,
+{
+    let (transfer_id, total_len, chunk_size) = match read_frame(stream, FRAME_OVERHEAD).await? {
+        Frame::Offer {
+            transfer_id,
+            total_len,
+            chunk_size,
+        } => (transfer_id, total_len, chunk_size),
+        other => return Err(unexpected(&other, "Offer")),
+    };
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(P2PError::Protocol(format!(
+            "invalid chunk size {}",
+            chunk_size
+        )));
+    }
+    if total_len > config.max_transfer_len {
+        return Err(P2PError::Protocol(format!(
+            "transfer of {} bytes exceeds limit of {}",
+            total_len, config.max_transfer_len
+        )));
+    }
+
+    let resume = progress
+        .as_ref()
+        .is_some_and(|p| p.matches(transfer_id, total_len, chunk_size));
+    if !resume {
+        sink.prepare(total_len)?;
+        *progress = Some(TransferProgress {
+            transfer_id,
+            total_len,
+            chunk_size,
+            next_seq: 0,
+        });
+    }
+    let state = progress.as_mut().expect("progress set above");
+    let total_chunks = state.total_chunks();
+    if resume {
+        debug!(transfer_id, next_seq = state.next_seq, "Resuming receive");
+    }
+
+    write_frame(
+        stream,
+        &Frame::Accept {
+            next_seq: state.next_seq,
+        },
+    )
+    .await?;
+    stream.flush().await?;
+
+    while state.next_seq < total_chunks {
+        match read_frame(stream, chunk_size + FRAME_OVERHEAD).await? {
+            Frame::Chunk { seq, data } => {
+                let offset = seq * chunk_size as u64;
+                let expected_len = (total_len - offset.min(total_len)).min(chunk_size as u64);
+                if seq != state.next_seq || data.len() as u64 != expected_len {
+                    return Err(P2PError::Protocol(format!(
+                        "chunk {} ({} bytes) out of order, expected chunk {}",
+                        seq,
+                        data.len(),
+                        state.next_seq
+                    )));
+                }
+                sink.write_at(offset, &data)?;
+                state.next_seq += 1;
+                trace!(transfer_id, seq, "Received chunk");
+            }
+            other => return Err(unexpected(&other, "Chunk")),
+        }
+        write_frame(
+            stream,
+            &Frame::Ack {
+                next_seq: state.next_seq,
+            },
+        )
+        .await?;
+        stream.flush().await?;
+    }
+
+    debug!(transfer_id, total_len, total_chunks, "Transfer received");
+    Ok(state.clone())
+}
+
+/// Send an in-memory payload
+pub async fn send_bytes<S>(
+    stream: &mut S,
+    payload: &[u8],
+    transfer_id: u64,
+    config: &TransferConfig,
+) -> P2PResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut source = payload;
+    send_chunked(stream, &mut source, transfer_id, config).await
+}
+
+/// Receive a whole transfer into memory
+pub async fn receive_bytes<S>(stream: &mut S, config: &TransferConfig) -> P2PResult<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut payload = Vec::new();
+    receive_chunked(stream, &mut payload, &mut None, config).await?;
+    Ok(payload)
+}
+
+/// Send a file, e.g. a model shard
+pub async fn send_file<S>(
+    stream: &mut S,
+    path: &std::path::Path,
+    transfer_id: u64,
+    config: &TransferConfig,
+) -> P2PResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut file = std::fs::File::open(path)?;
+    send_chunked(stream, &mut file, transfer_id, config).await
+}
+
+/// Receive a transfer into the file at `path`
+///
+/// The file is created if missing and left in place on failure so a
+/// later call with the same `progress` can resume it.
+pub async fn receive_file<S>(
+    stream: &mut S,
+    path: &std::path::Path,
+    progress: &mut Option<TransferProgress>,
+    config: &TransferConfig,
+) -> P2PResult<TransferProgress>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let done = receive_chunked(stream, &mut file, progress, config).await?;
+    file.sync_all()?;
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config() -> TransferConfig {
+        TransferConfig {
+            chunk_size: 100,
+            window: 3,
+            ..TransferConfig::default()
+        }
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn transfers_payload_in_chunks() {
+        let data = payload(1050);
+        let (mut a, mut b) = tokio::io::duplex(512);
+        let config = small_config();
+
+        let sent = data.clone();
+        let cfg = config.clone();
+        let sender = tokio::spawn(async move { send_bytes(&mut a, &sent, 7, &cfg).await });
+        let received = receive_bytes(&mut b, &config).await.unwrap();
+        sender.await.unwrap().unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn empty_payload_completes() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let config = small_config();
+        let cfg = config.clone();
+        let sender = tokio::spawn(async move { send_bytes(&mut a, &[], 1, &cfg).await });
+        assert!(receive_bytes(&mut b, &config).await.unwrap().is_empty());
+        sender.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_after_dropped_connection() {
+        let data = payload(1000);
+        let config = small_config();
+        let mut sink = Vec::new();
+        let mut progress = None;
+
+        // First attempt: the connection dies after four chunks.
+        {
+            let (mut a, mut b) = tokio::io::duplex(4096);
+            let mut truncated = Vec::new();
+            let mut src: &[u8] = &data;
+            let total_len = src.total_len().unwrap();
+            write_frame(
+                &mut truncated,
+                &Frame::Offer {
+                    transfer_id: 9,
+                    total_len,
+                    chunk_size: 100,
+                },
+            )
+            .await
+            .unwrap();
+            for seq in 0..4u64 {
+                let mut buf = vec![0u8; 100];
+                src.read_at(seq * 100, &mut buf).unwrap();
+                write_frame(&mut truncated, &Frame::Chunk { seq, data: buf })
+                    .await
+                    .unwrap();
+            }
+            a.write_all(&truncated).await.unwrap();
+            a.shutdown().await.unwrap();
+            let err = receive_chunked(&mut b, &mut sink, &mut progress, &config).await;
+            assert!(err.is_err());
+        }
+        let partial = progress.clone().unwrap();
+        assert_eq!(partial.next_seq, 4);
+        assert_eq!(partial.received_bytes(), 400);
+        assert!(!partial.is_complete());
+
+        // Second attempt: the sender is told to start at chunk 4.
+        let (mut a, mut b) = tokio::io::duplex(512);
+        let sent = data.clone();
+        let cfg = config.clone();
+        let sender = tokio::spawn(async move {
+            let mut src: &[u8] = &sent;
+            send_chunked(&mut a, &mut src, 9, &cfg).await
+        });
+        let done = receive_chunked(&mut b, &mut sink, &mut progress, &config)
+            .await
+            .unwrap();
+        sender.await.unwrap().unwrap();
+        assert!(done.is_complete());
+        assert_eq!(sink, data);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_transfer() {
+        let (mut a, mut b) = tokio::io::duplex(4096);
+        let config = TransferConfig {
+            max_transfer_len: 10,
+            ..small_config()
+        };
+        let data = payload(50);
+        let cfg = small_config();
+        tokio::spawn(async move { send_bytes(&mut a, &data, 1, &cfg).await });
+        assert!(receive_bytes(&mut b, &config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn transfers_file() {
+        let dir = std::env::temp_dir().join(format!("kwaai-chunked-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("shard.safetensors");
+        let dst = dir.join("copy.safetensors");
+        let data = payload(777);
+        std::fs::write(&src, &data).unwrap();
+
+        let (mut a, mut b) = tokio::io::duplex(512);
+        let cfg = small_config();
+        let src_path = src.clone();
+        let sender = tokio::spawn(async move { send_file(&mut a, &src_path, 3, &cfg).await });
+        let done = receive_file(&mut b, &dst, &mut None, &small_config())
+            .await
+            .unwrap();
+        sender.await.unwrap().unwrap();
+        assert_eq!(done.total_len, 777);
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
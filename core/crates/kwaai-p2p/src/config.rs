@@ -1,8 +1,12 @@
 //! Configuration for P2P networking
 
+use crate::error::{P2PError, P2PResult};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+/// First line of a go-ipfs `swarm.key` file.
+const SWARM_KEY_HEADER: &str = "/key/swarm/psk/1.0.0/";
+
 /// KwaaiNet bootstrap servers for DHT discovery.
 /// These are the official KwaaiNet/Petals DHT entry points.
 pub const KWAAI_BOOTSTRAP_SERVERS: &[&str] = &[
@@ -12,6 +16,19 @@ pub const KWAAI_BOOTSTRAP_SERVERS: &[&str] = &[
     "/ip4/52.23.252.2/tcp/8000/p2p/Qmd3A8N5aQBATe2SYvNikaeCS9CAKN4E86jdCPacZ6RZJY",
 ];
 
+/// Default time between re-announcements of a node's DHT records.
+pub const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Default lifetime of a node's DHT records.
+pub const DEFAULT_RECORD_TTL: Duration = Duration::from_secs(360);
+
+/// Re-announcements are spread by up to this fraction of the interval either
+/// way, so nodes restarted together do not announce in lockstep.
+pub const ANNOUNCE_JITTER: f64 = 0.1;
+
+/// Shortest allowed announce interval.
+pub const MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Legacy Petals/Hivemind bootstrap servers (kept for reference).
 pub const PETALS_BOOTSTRAP_SERVERS: &[&str] = &[
     // bootstrap-1.kwaai.ai (18.219.43.67) - Primary Kwaai bootstrap
@@ -57,6 +74,46 @@ pub struct NetworkConfig {
 
     /// Agent version string
     pub agent_version: String,
+
+    /// Pre-shared key of a private swarm (libp2p pnet), as 64 hex digits or
+    /// the contents of a `swarm.key` file. Peers without the same key cannot
+    /// complete a connection, so public Petals peers never see the swarm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swarm_psk: Option<String>,
+
+    /// Time between re-announcements of DHT records, before jitter
+    #[serde(default = "default_announce_interval")]
+    pub announce_interval: Duration,
+
+    /// Lifetime of announced DHT records; must outlast the longest jittered
+    /// announce interval or records lapse between announcements
+    #[serde(default = "default_record_ttl")]
+    pub record_ttl: Duration,
+
+    /// DHT implementation behind [`open_backend`](crate::backend::open_backend)
+    #[serde(default)]
+    pub dht_backend: DhtBackendKind,
+}
+
+/// Which DHT carries records and provider announcements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DhtBackendKind {
+    /// libp2p Kademlia in this process ([`KwaaiNetwork`](crate::KwaaiNetwork))
+    #[default]
+    Kademlia,
+    /// Hivemind DHT RPCs through a go-libp2p-daemon, as Petals nodes run
+    HivemindDaemon,
+    /// Hivemind DHT RPCs on an in-process libp2p swarm
+    HivemindNative,
+}
+
+fn default_announce_interval() -> Duration {
+    DEFAULT_ANNOUNCE_INTERVAL
+}
+
+fn default_record_ttl() -> Duration {
+    DEFAULT_RECORD_TTL
 }
 
 impl Default for NetworkConfig {
@@ -73,6 +130,10 @@ impl Default for NetworkConfig {
             enable_relay_client: true,
             protocol_version: "kwaai/1.0.0".to_string(),
             agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            swarm_psk: None,
+            announce_interval: DEFAULT_ANNOUNCE_INTERVAL,
+            record_ttl: DEFAULT_RECORD_TTL,
+            dht_backend: DhtBackendKind::default(),
         }
     }
 }
@@ -106,6 +167,44 @@ impl NetworkConfig {
             ..Self::default()
         }
     }
+
+    /// Longest time between two announcements once jitter is applied
+    pub fn max_announce_gap(&self) -> Duration {
+        self.announce_interval.mul_f64(1.0 + ANNOUNCE_JITTER)
+    }
+
+    /// Check that records outlive the gap between announcements
+    pub fn validate_announce_timing(&self) -> P2PResult<()> {
+        if self.announce_interval < MIN_ANNOUNCE_INTERVAL {
+            return Err(P2PError::InvalidConfig(format!(
+                "announce interval must be at least {} s",
+                MIN_ANNOUNCE_INTERVAL.as_secs()
+            )));
+        }
+        let gap = self.max_announce_gap();
+        if self.record_ttl <= gap {
+            return Err(P2PError::InvalidConfig(format!(
+                "record TTL ({} s) must exceed the longest announce interval ({:.0} s with jitter)",
+                self.record_ttl.as_secs(),
+                gap.as_secs_f64()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Delay until the next announcement: the interval ± up to
+    /// [`ANNOUNCE_JITTER`] of it, drawn afresh on every call
+    pub fn next_announce_delay(&self) -> Duration {
+        use std::hash::{BuildHasher, Hasher};
+        // A freshly keyed SipHash is random enough for jitter without
+        // pulling in a RNG.
+        let r = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        let unit = (r >> 11) as f64 / (1u64 << 53) as f64; // [0, 1)
+        let spread = ANNOUNCE_JITTER * (2.0 * unit - 1.0);
+        self.announce_interval.mul_f64(1.0 + spread)
+    }
 }
 
 /// Builder for NetworkConfig
@@ -145,6 +244,30 @@ impl NetworkConfigBuilder {
         self
     }
 
+    /// Join a private swarm protected by a pre-shared key
+    pub fn swarm_psk(mut self, psk: impl Into<String>) -> Self {
+        self.config.swarm_psk = Some(psk.into());
+        self
+    }
+
+    /// Set the time between re-announcements of DHT records
+    pub fn announce_interval(mut self, interval: Duration) -> Self {
+        self.config.announce_interval = interval;
+        self
+    }
+
+    /// Set the lifetime of announced DHT records
+    pub fn record_ttl(mut self, ttl: Duration) -> Self {
+        self.config.record_ttl = ttl;
+        self
+    }
+
+    /// Set the DHT implementation
+    pub fn dht_backend(mut self, kind: DhtBackendKind) -> Self {
+        self.config.dht_backend = kind;
+        self
+    }
+
     /// Include Petals bootstrap servers for DHT discovery
     pub fn with_petals_bootstrap(mut self) -> Self {
         self.config
@@ -158,3 +281,84 @@ impl NetworkConfigBuilder {
         self.config
     }
 }
+
+/// Parse a swarm pre-shared key: 64 hex digits, or a go-ipfs `swarm.key`
+/// file (`/key/swarm/psk/1.0.0/`, `/base16/`, then the hex key).
+pub fn parse_swarm_psk(s: &str) -> P2PResult<[u8; 32]> {
+    let mut lines = s.lines().map(str::trim).filter(|l| !l.is_empty());
+    let hex = match lines.next() {
+        Some(SWARM_KEY_HEADER) => {
+            match lines.next() {
+                Some("/base16/") => {}
+                other => {
+                    return Err(P2PError::InvalidConfig(format!(
+                        "unsupported swarm key encoding {:?} (only /base16/ is supported)",
+                        other.unwrap_or_default()
+                    )))
+                }
+            }
+            lines.next().unwrap_or_default()
+        }
+        Some(line) => line,
+        None => "",
+    };
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(P2PError::InvalidConfig(
+            "swarm pre-shared key must be 64 hex digits (32 bytes)".to_string(),
+        ));
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|e| P2PError::InvalidConfig(e.to_string()))?;
+    }
+    Ok(key)
+}
+
+/// Render a key in the go-ipfs `swarm.key` format read by p2pd.
+pub fn swarm_key_file_contents(key: &[u8; 32]) -> String {
+    let hex: String = key.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{SWARM_KEY_HEADER}\n/base16/\n{hex}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swarm_psk_accepts_hex_and_swarm_key_file() {
+        let hex = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+        let key = parse_swarm_psk(hex).unwrap();
+        assert_eq!(key[1], 0x11);
+        assert_eq!(key[31], 0xff);
+
+        let file = swarm_key_file_contents(&key);
+        assert!(file.starts_with("/key/swarm/psk/1.0.0/\n/base16/\n0011"));
+        assert_eq!(parse_swarm_psk(&file).unwrap(), key);
+
+        assert!(parse_swarm_psk("abcd").is_err());
+        assert!(parse_swarm_psk("/key/swarm/psk/1.0.0/\n/base64/\nAAAA").is_err());
+    }
+
+    #[test]
+    fn announce_timing_is_validated_and_jittered() {
+        let config = NetworkConfig::default();
+        config.validate_announce_timing().unwrap();
+
+        // 300 s ± 10% can leave 330 s between announcements.
+        let too_short = NetworkConfig::builder()
+            .record_ttl(Duration::from_secs(320))
+            .build();
+        assert!(too_short.validate_announce_timing().is_err());
+        let too_fast = NetworkConfig::builder()
+            .announce_interval(Duration::from_secs(5))
+            .build();
+        assert!(too_fast.validate_announce_timing().is_err());
+
+        let delays: Vec<Duration> = (0..50).map(|_| config.next_announce_delay()).collect();
+        assert!(delays
+            .iter()
+            .all(|d| (270.0..=330.0).contains(&d.as_secs_f64())));
+        assert!(delays.iter().any(|d| *d != delays[0]));
+    }
+}
@@ -48,6 +48,10 @@ pub enum P2PError {
     #[error("Network not initialized")]
     NotInitialized,
 
+    /// Invalid configuration value
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
     /// Internal error
     #[error("Internal error: {0}")]
     Internal(String),
@@ -0,0 +1,380 @@
+//! Deterministic failure injection
+//!
+//! A [`FaultInjector`] sits in front of a transport and decides, call by
+//! call, whether the call goes through, is delayed, is dropped, or hits a
+//! peer that has been killed. Decisions come from a seeded generator and a
+//! schedule of kills and revivals keyed by call number, so the same seed and
+//! the same sequence of calls always produce the same faults: a failing CI
+//! run can be replayed from its seed.
+//!
+//! Wrappers turn an injector into a faulty transport: [`FaultyDht`] here,
+//! and the expert and matchmaking transports in `kwaai-distributed`.
+//!
+//! Enabled with the `fault-injection` feature.
+
+use crate::{
+    error::{P2PError, P2PResult},
+    DhtOperations,
+};
+use async_trait::async_trait;
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Target name [`FaultyDht`] reports its calls under
+pub const DHT_TARGET: &str = "dht";
+
+/// What happens to one call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Delivered untouched
+    Deliver,
+    /// Delivered after a delay
+    Delay(Duration),
+    /// Lost in transit
+    Drop,
+    /// The target has been killed
+    Dead,
+}
+
+impl Fault {
+    /// Whether the call fails
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Fault::Drop | Fault::Dead)
+    }
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Deliver => write!(f, "delivered"),
+            Fault::Delay(d) => write!(f, "delayed {}ms", d.as_millis()),
+            Fault::Drop => write!(f, "dropped"),
+            Fault::Dead => write!(f, "peer killed"),
+        }
+    }
+}
+
+/// A change of a target's liveness at a given call
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Scheduled {
+    at_call: u64,
+    target: String,
+    alive: bool,
+}
+
+/// Seed, fault rates and kill schedule of a [`FaultInjector`]
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    /// Seed of the decision generator
+    pub seed: u64,
+    /// Probability that a call is dropped
+    pub drop_rate: f64,
+    /// Probability that a delivered call is delayed
+    pub delay_rate: f64,
+    /// Upper bound of injected delays
+    pub max_delay: Duration,
+    /// Targets the rates apply to; all when empty
+    pub targets: Vec<String>,
+    schedule: Vec<Scheduled>,
+}
+
+impl FaultPlan {
+    /// Plan with no faults, drawing from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    /// Drop calls with probability `rate`
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Delay calls with probability `rate`, by up to `max`
+    pub fn delay(mut self, rate: f64, max: Duration) -> Self {
+        self.delay_rate = rate;
+        self.max_delay = max;
+        self
+    }
+
+    /// Apply drop and delay rates to `target` only (may be repeated)
+    pub fn only(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Kill `target` from call number `at_call` (0-based) on
+    pub fn kill_at(mut self, at_call: u64, target: impl Into<String>) -> Self {
+        self.schedule.push(Scheduled {
+            at_call,
+            target: target.into(),
+            alive: false,
+        });
+        self
+    }
+
+    /// Bring `target` back from call number `at_call` on
+    pub fn revive_at(mut self, at_call: u64, target: impl Into<String>) -> Self {
+        self.schedule.push(Scheduled {
+            at_call,
+            target: target.into(),
+            alive: true,
+        });
+        self
+    }
+}
+
+/// One decision of a [`FaultInjector`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultRecord {
+    /// Call number, from 0
+    pub call: u64,
+    /// Target of the call
+    pub target: String,
+    /// What happened to it
+    pub fault: Fault,
+}
+
+struct InjectorState {
+    rng: SplitMix64,
+    calls: u64,
+    dead: HashSet<String>,
+    history: Vec<FaultRecord>,
+}
+
+/// Seeded source of faults shared by the faulty transports of a test
+pub struct FaultInjector {
+    plan: FaultPlan,
+    state: Mutex<InjectorState>,
+}
+
+impl FaultInjector {
+    /// Injector following `plan`
+    pub fn new(plan: FaultPlan) -> Self {
+        let rng = SplitMix64(plan.seed);
+        Self {
+            plan,
+            state: Mutex::new(InjectorState {
+                rng,
+                calls: 0,
+                dead: HashSet::new(),
+                history: Vec::new(),
+            }),
+        }
+    }
+
+    /// Decide the fate of the next call to `target`
+    ///
+    /// Every call draws the same number of values from the generator,
+    /// whatever the outcome, so adding a kill to the schedule does not shift
+    /// the faults of later calls.
+    pub fn decide(&self, target: &str) -> Fault {
+        let mut state = self.state.lock().unwrap();
+        let call = state.calls;
+        state.calls += 1;
+        for s in self.plan.schedule.iter().filter(|s| s.at_call == call) {
+            if s.alive {
+                state.dead.remove(&s.target);
+            } else {
+                state.dead.insert(s.target.clone());
+            }
+        }
+
+        let (drop_draw, delay_draw, length_draw) = (
+            state.rng.next_f64(),
+            state.rng.next_f64(),
+            state.rng.next_f64(),
+        );
+        let targeted =
+            self.plan.targets.is_empty() || self.plan.targets.iter().any(|t| t == target);
+        let fault = if state.dead.contains(target) {
+            Fault::Dead
+        } else if targeted && drop_draw < self.plan.drop_rate {
+            Fault::Drop
+        } else if targeted && delay_draw < self.plan.delay_rate {
+            Fault::Delay(self.plan.max_delay.mul_f64(length_draw))
+        } else {
+            Fault::Deliver
+        };
+        state.history.push(FaultRecord {
+            call,
+            target: target.to_string(),
+            fault,
+        });
+        fault
+    }
+
+    /// Decide the next call to `target` and carry out the decision
+    ///
+    /// Sleeps through an injected delay; returns the fault when the call
+    /// must fail.
+    pub async fn gate(&self, target: &str) -> Result<(), Fault> {
+        match self.decide(target) {
+            Fault::Deliver => Ok(()),
+            Fault::Delay(delay) => {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+            fault => Err(fault),
+        }
+    }
+
+    /// Kill `target` now
+    pub fn kill(&self, target: &str) {
+        self.state.lock().unwrap().dead.insert(target.to_string());
+    }
+
+    /// Revive `target` now
+    pub fn revive(&self, target: &str) {
+        self.state.lock().unwrap().dead.remove(target);
+    }
+
+    /// Whether `target` is currently killed
+    pub fn is_dead(&self, target: &str) -> bool {
+        self.state.lock().unwrap().dead.contains(target)
+    }
+
+    /// Number of calls decided so far
+    pub fn calls(&self) -> u64 {
+        self.state.lock().unwrap().calls
+    }
+
+    /// Every decision so far, in call order
+    pub fn history(&self) -> Vec<FaultRecord> {
+        self.state.lock().unwrap().history.clone()
+    }
+}
+
+/// SplitMix64: tiny, seedable and the same on every platform
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// [`DhtOperations`] whose calls pass through a [`FaultInjector`] under
+/// [`DHT_TARGET`]
+pub struct FaultyDht<D> {
+    inner: D,
+    faults: std::sync::Arc<FaultInjector>,
+}
+
+impl<D> FaultyDht<D> {
+    /// Wrap `inner`
+    pub fn new(inner: D, faults: std::sync::Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+
+    /// The wrapped DHT
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    async fn gate(&self, op: &str, key: &str) -> P2PResult<()> {
+        self.faults
+            .gate(DHT_TARGET)
+            .await
+            .map_err(|fault| P2PError::DhtError(format!("{op} {key}: injected fault: {fault}")))
+    }
+}
+
+#[async_trait]
+impl<D: DhtOperations> DhtOperations for FaultyDht<D> {
+    async fn put(&mut self, key: &str, value: Vec<u8>) -> P2PResult<()> {
+        self.gate("put", key).await?;
+        self.inner.put(key, value).await
+    }
+
+    async fn get(&self, key: &str) -> P2PResult<Option<Vec<u8>>> {
+        self.gate("get", key).await?;
+        self.inner.get(key).await
+    }
+
+    async fn provide(&mut self, key: &str) -> P2PResult<()> {
+        self.gate("provide", key).await?;
+        self.inner.provide(key).await
+    }
+
+    async fn get_providers(&self, key: &str) -> P2PResult<Vec<PeerId>> {
+        self.gate("get_providers", key).await?;
+        self.inner.get_providers(key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KwaaiNetwork, NetworkConfig};
+    use std::sync::Arc;
+
+    fn run(plan: FaultPlan, targets: &[&str]) -> Vec<FaultRecord> {
+        let faults = FaultInjector::new(plan);
+        for t in targets {
+            faults.decide(t);
+        }
+        faults.history()
+    }
+
+    #[test]
+    fn same_seed_same_faults() {
+        let plan = || {
+            FaultPlan::new(42)
+                .drop_rate(0.3)
+                .delay(0.3, Duration::from_millis(100))
+        };
+        let calls = ["a", "b", "c"].repeat(20);
+        let first = run(plan(), &calls);
+        assert_eq!(first, run(plan(), &calls));
+        assert_ne!(first, run(FaultPlan { seed: 7, ..plan() }, &calls));
+
+        let faults: Vec<Fault> = first.iter().map(|r| r.fault).collect();
+        assert!(faults.contains(&Fault::Drop));
+        assert!(faults.iter().any(|f| matches!(f, Fault::Delay(_))));
+        assert!(faults.contains(&Fault::Deliver));
+    }
+
+    #[test]
+    fn kills_follow_the_schedule_without_shifting_other_faults() {
+        let plan = FaultPlan::new(1).drop_rate(0.5).only("a");
+        let calls = ["a", "b"].repeat(5);
+        let plain = run(plan.clone(), &calls);
+        let killed = run(plan.kill_at(2, "b").revive_at(6, "b"), &calls);
+
+        for (p, k) in plain.iter().zip(&killed) {
+            match (k.target.as_str(), k.call) {
+                ("b", 3 | 5) => assert_eq!(k.fault, Fault::Dead),
+                ("b", _) => assert_eq!(k.fault, Fault::Deliver),
+                _ => assert_eq!(p, k),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn faulty_dht_fails_dropped_calls() {
+        let faults = Arc::new(FaultInjector::new(FaultPlan::new(0).kill_at(1, DHT_TARGET)));
+        let network = KwaaiNetwork::new(NetworkConfig::default()).await.unwrap();
+        let mut dht = FaultyDht::new(network, faults.clone());
+        dht.put("k", b"v".to_vec()).await.unwrap();
+        assert!(dht.get("k").await.is_err());
+
+        faults.revive(DHT_TARGET);
+        assert_eq!(dht.get("k").await.unwrap(), Some(b"v".to_vec()));
+        assert_eq!(faults.calls(), 3);
+    }
+}
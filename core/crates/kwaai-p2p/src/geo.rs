@@ -0,0 +1,243 @@
+//! Country and ASN of peers, from the addresses they are reached at
+//!
+//! [`GeoIpDb`] reads an IP-to-ASN range table in the tab-separated format
+//! published by iptoasn.com (`ip2asn-combined.tsv`, public domain):
+//!
+//! ```text
+//! range_start  range_end  AS_number  country_code  AS_description
+//! 1.0.0.0      1.0.0.255  13335      US            CLOUDFLARENET
+//! ```
+//!
+//! [`public_ip`] picks the address worth looking up from a peer's multiaddrs;
+//! relayed, private and loopback addresses say nothing about where the peer
+//! is.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Where a peer's address is registered
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerIpInfo {
+    /// Address the peer was reached at
+    pub ip: String,
+    /// ISO 3166 country code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Autonomous system number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// Name of the autonomous system's operator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_name: Option<String>,
+}
+
+impl PeerIpInfo {
+    /// Info carrying only the address, for lookups that found nothing
+    pub fn unresolved(ip: IpAddr) -> Self {
+        Self {
+            ip: ip.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether a country or ASN is known
+    pub fn is_resolved(&self) -> bool {
+        self.country.is_some() || self.asn.is_some()
+    }
+}
+
+impl std::fmt::Display for PeerIpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.country.as_deref().unwrap_or("??"))?;
+        if let Some(asn) = self.asn {
+            write!(f, " · AS{}", asn)?;
+        }
+        if let Some(name) = &self.as_name {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The first globally routable IP in `addrs`, skipping relayed addresses
+pub fn public_ip(addrs: &[Multiaddr]) -> Option<IpAddr> {
+    addrs
+        .iter()
+        .filter(|m| !m.iter().any(|p| matches!(p, Protocol::P2pCircuit)))
+        .filter_map(|m| {
+            m.iter().find_map(|p| match p {
+                Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+                Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+        })
+        .find(is_global)
+}
+
+fn is_global(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                // 100.64.0.0/10, carrier-grade NAT
+                || (v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                // fc00::/7 unique local, fe80::/10 link local
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+/// Both address families on one scale: IPv4 as IPv4-mapped IPv6
+fn ip_key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IpRange {
+    start: u128,
+    end: u128,
+    asn: u32,
+    country: Option<String>,
+    as_name: Option<String>,
+}
+
+/// In-memory IP range table for offline lookups
+#[derive(Debug, Clone, Default)]
+pub struct GeoIpDb {
+    ranges: Vec<IpRange>,
+}
+
+impl GeoIpDb {
+    /// Load an `ip2asn` TSV file
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+
+    /// Parse `ip2asn` TSV lines; malformed lines are skipped
+    pub fn from_reader(reader: impl BufRead) -> std::io::Result<Self> {
+        let mut ranges = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut cols = line.split('\t');
+            let (Some(start), Some(end), Some(asn)) = (cols.next(), cols.next(), cols.next())
+            else {
+                continue;
+            };
+            let (Ok(start), Ok(end), Ok(asn)) = (
+                start.parse::<IpAddr>(),
+                end.parse::<IpAddr>(),
+                asn.parse::<u32>(),
+            ) else {
+                continue;
+            };
+            // "None" / "Not routed" mark unallocated space.
+            let country = cols
+                .next()
+                .filter(|c| !c.is_empty() && *c != "None")
+                .map(String::from);
+            let as_name = cols
+                .next()
+                .filter(|d| !d.is_empty() && *d != "Not routed")
+                .map(String::from);
+            ranges.push(IpRange {
+                start: ip_key(start),
+                end: ip_key(end),
+                asn,
+                country,
+                as_name,
+            });
+        }
+        ranges.sort_by_key(|r| r.start);
+        Ok(Self { ranges })
+    }
+
+    /// Number of ranges loaded
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Whether no ranges were loaded
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Country and ASN of `ip`, if a routed range holds it
+    pub fn lookup(&self, ip: IpAddr) -> Option<PeerIpInfo> {
+        let key = ip_key(ip);
+        let i = self
+            .ranges
+            .partition_point(|r| r.start <= key)
+            .checked_sub(1)?;
+        let range = &self.ranges[i];
+        if key > range.end || range.asn == 0 {
+            return None;
+        }
+        Some(PeerIpInfo {
+            ip: ip.to_string(),
+            country: range.country.clone(),
+            asn: Some(range.asn),
+            as_name: range.as_name.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+                         1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+                         8.8.8.0\t8.8.8.255\t15169\tUS\tGOOGLE\n\
+                         2001:4860::\t2001:4860:ffff:ffff:ffff:ffff:ffff:ffff\t15169\tUS\tGOOGLE\n\
+                         garbage line\n";
+
+    #[test]
+    fn looks_up_ranges_of_both_families() {
+        let db = GeoIpDb::from_reader(TABLE.as_bytes()).unwrap();
+        assert_eq!(db.len(), 4);
+
+        let info = db.lookup("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(info.country.as_deref(), Some("US"));
+        assert_eq!(info.asn, Some(15169));
+        assert_eq!(info.to_string(), "US · AS15169 GOOGLE");
+
+        assert!(db.lookup("2001:4860::8888".parse().unwrap()).is_some());
+        // Unrouted space and gaps between ranges resolve to nothing.
+        assert!(db.lookup("1.0.2.1".parse().unwrap()).is_none());
+        assert!(db.lookup("8.8.9.1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn public_ip_skips_relayed_and_private_addresses() {
+        let addrs: Vec<Multiaddr> = [
+            "/ip4/127.0.0.1/tcp/4001",
+            "/ip4/192.168.1.5/tcp/4001",
+            "/ip4/3.3.3.3/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit",
+            "/ip4/100.64.0.1/tcp/4001",
+            "/ip4/8.8.8.8/udp/4001/quic-v1",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        assert_eq!(public_ip(&addrs), Some("8.8.8.8".parse().unwrap()));
+        assert_eq!(public_ip(&addrs[..2]), None);
+    }
+}
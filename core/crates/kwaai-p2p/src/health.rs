@@ -0,0 +1,237 @@
+//! Point-in-time health snapshot of a [`KwaaiNetwork`](crate::KwaaiNetwork)
+//!
+//! [`KwaaiNetwork::health_snapshot`](crate::KwaaiNetwork::health_snapshot)
+//! gathers swarm state and request outcome counters into a single typed
+//! [`HealthSnapshot`], so health commands, control APIs and metrics exporters
+//! all read the same numbers instead of each computing a partial view.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of recent errors retained for [`HealthSnapshot::last_errors`].
+pub const MAX_RECENT_ERRORS: usize = 16;
+
+/// Operations whose outcomes are tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Kademlia `put_record`
+    DhtPut,
+    /// Kademlia `start_providing`
+    DhtProvide,
+    /// Kademlia `get_record` / `get_providers` lookups
+    DhtQuery,
+}
+
+/// Success / failure counters for one operation type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestStats {
+    /// Operations that completed without error
+    pub succeeded: u64,
+    /// Operations that returned an error
+    pub failed: u64,
+}
+
+impl RequestStats {
+    /// Total operations attempted
+    pub fn total(&self) -> u64 {
+        self.succeeded + self.failed
+    }
+
+    /// Fraction of operations that succeeded, or `None` if none were attempted
+    pub fn success_rate(&self) -> Option<f64> {
+        match self.total() {
+            0 => None,
+            n => Some(self.succeeded as f64 / n as f64),
+        }
+    }
+}
+
+/// A recently observed error
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedError {
+    /// Unix timestamp (seconds) when the error was recorded
+    pub at: u64,
+    /// Operation that failed
+    pub operation: Operation,
+    /// Error message
+    pub message: String,
+}
+
+/// Coarse health classification derived from a snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// Running, connected, and requests mostly succeeding
+    Healthy,
+    /// Running and connected, but more than half of some operation failed
+    Degraded,
+    /// Not running or no connected peers
+    Unhealthy,
+}
+
+/// Typed health view of the network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthSnapshot {
+    /// Unix timestamp (seconds) when the snapshot was taken
+    pub taken_at: u64,
+    /// Local peer ID
+    pub peer_id: String,
+    /// Whether the network has started listening
+    pub is_running: bool,
+    /// Peers with at least one open connection
+    pub connected_peers: usize,
+    /// Entries across all Kademlia k-buckets
+    pub routing_table_size: usize,
+    /// Kademlia queries still in flight
+    pub pending_dht_queries: usize,
+    /// `put_record` outcomes
+    pub dht_put: RequestStats,
+    /// `start_providing` outcomes
+    pub dht_provide: RequestStats,
+    /// Lookup outcomes
+    pub dht_query: RequestStats,
+    /// Most recent errors, oldest first (at most [`MAX_RECENT_ERRORS`])
+    pub last_errors: Vec<RecordedError>,
+}
+
+impl HealthSnapshot {
+    /// Classify this snapshot
+    pub fn status(&self) -> HealthStatus {
+        if !self.is_running || self.connected_peers == 0 {
+            return HealthStatus::Unhealthy;
+        }
+        let failing = [self.dht_put, self.dht_provide, self.dht_query]
+            .iter()
+            .any(|s| s.success_rate().is_some_and(|r| r < 0.5));
+        if failing {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        }
+    }
+}
+
+/// Outcome counters shared between the network and its snapshots
+#[derive(Debug, Default)]
+pub(crate) struct NetworkStats {
+    inner: Mutex<StatsInner>,
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    dht_put: RequestStats,
+    dht_provide: RequestStats,
+    dht_query: RequestStats,
+    errors: VecDeque<RecordedError>,
+}
+
+impl StatsInner {
+    fn counters(&mut self, op: Operation) -> &mut RequestStats {
+        match op {
+            Operation::DhtPut => &mut self.dht_put,
+            Operation::DhtProvide => &mut self.dht_provide,
+            Operation::DhtQuery => &mut self.dht_query,
+        }
+    }
+}
+
+impl NetworkStats {
+    /// Record the outcome of `op`
+    pub(crate) fn record<T, E: std::fmt::Display>(&self, op: Operation, result: &Result<T, E>) {
+        let mut inner = self.inner.lock().unwrap();
+        match result {
+            Ok(_) => inner.counters(op).succeeded += 1,
+            Err(e) => {
+                inner.counters(op).failed += 1;
+                if inner.errors.len() == MAX_RECENT_ERRORS {
+                    inner.errors.pop_front();
+                }
+                inner.errors.push_back(RecordedError {
+                    at: unix_now(),
+                    operation: op,
+                    message: e.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Fill the counter fields of `snapshot`
+    pub(crate) fn fill(&self, snapshot: &mut HealthSnapshot) {
+        let inner = self.inner.lock().unwrap();
+        snapshot.dht_put = inner.dht_put;
+        snapshot.dht_provide = inner.dht_provide;
+        snapshot.dht_query = inner.dht_query;
+        snapshot.last_errors = inner.errors.iter().cloned().collect();
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_snapshot() -> HealthSnapshot {
+        HealthSnapshot {
+            taken_at: 0,
+            peer_id: String::new(),
+            is_running: true,
+            connected_peers: 3,
+            routing_table_size: 3,
+            pending_dht_queries: 0,
+            dht_put: RequestStats::default(),
+            dht_provide: RequestStats::default(),
+            dht_query: RequestStats::default(),
+            last_errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn success_rate() {
+        assert_eq!(RequestStats::default().success_rate(), None);
+        let s = RequestStats {
+            succeeded: 3,
+            failed: 1,
+        };
+        assert_eq!(s.success_rate(), Some(0.75));
+    }
+
+    #[test]
+    fn recent_errors_are_bounded() {
+        let stats = NetworkStats::default();
+        for i in 0..MAX_RECENT_ERRORS + 4 {
+            stats.record::<(), _>(Operation::DhtPut, &Err(format!("e{i}")));
+        }
+        stats.record::<_, String>(Operation::DhtPut, &Ok(()));
+
+        let mut snap = empty_snapshot();
+        stats.fill(&mut snap);
+        assert_eq!(snap.last_errors.len(), MAX_RECENT_ERRORS);
+        assert_eq!(snap.last_errors[0].message, "e4");
+        assert_eq!(snap.dht_put.failed, (MAX_RECENT_ERRORS + 4) as u64);
+        assert_eq!(snap.dht_put.succeeded, 1);
+    }
+
+    #[test]
+    fn status_classification() {
+        let mut snap = empty_snapshot();
+        assert_eq!(snap.status(), HealthStatus::Healthy);
+
+        snap.dht_put = RequestStats {
+            succeeded: 1,
+            failed: 3,
+        };
+        assert_eq!(snap.status(), HealthStatus::Degraded);
+
+        snap.connected_peers = 0;
+        assert_eq!(snap.status(), HealthStatus::Unhealthy);
+    }
+}
@@ -107,6 +107,11 @@ pub struct ServerInfo {
     /// Next ping targets (peer_id -> optional latency)
     pub next_pings: HashMap<String, Option<f64>>,
 
+    /// Accelerator the server computes on (KwaaiNet extension; Petals
+    /// servers leave it out)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accelerator: Option<crate::AcceleratorInfo>,
+
     /// Block span this server provides [start, end) - legacy field
     #[serde(skip_serializing_if = "Option::is_none")]
     pub spans: Option<Vec<(u32, u32)>>,
@@ -125,9 +130,10 @@ impl Default for ServerInfo {
             start_block: 0,
             end_block: 1,
             throughput: 10.0,
-            network_rps: 100.0,
-            forward_rps: 50.0,
-            inference_rps: 10.0,
+            // Unmeasured until the caller supplies real figures (`with_rps`).
+            network_rps: 0.0,
+            forward_rps: 0.0,
+            inference_rps: 0.0,
             torch_dtype: "float16".to_string(),
             quant_type: "none".to_string(),
             cache_tokens_left: 32768,
@@ -135,6 +141,7 @@ impl Default for ServerInfo {
             dht_client_mode: Some(false),
             adapters: Vec::new(),
             next_pings: HashMap::new(),
+            accelerator: None,
             spans: Some(vec![(0, 1)]),
             extra: HashMap::new(),
         }
@@ -170,12 +177,26 @@ impl ServerInfo {
         self
     }
 
+    /// Set measured request rates (tokens/s; forward and inference per block)
+    pub fn with_rps(mut self, network_rps: f32, forward_rps: f32, inference_rps: f32) -> Self {
+        self.network_rps = network_rps;
+        self.forward_rps = forward_rps;
+        self.inference_rps = inference_rps;
+        self
+    }
+
     /// Set torch dtype
     pub fn with_dtype(mut self, dtype: impl Into<String>) -> Self {
         self.torch_dtype = dtype.into();
         self
     }
 
+    /// Set the accelerator the server computes on
+    pub fn with_accelerator(mut self, accelerator: crate::AcceleratorInfo) -> Self {
+        self.accelerator = Some(accelerator);
+        self
+    }
+
     /// Set relay usage flag
     pub fn with_relay(mut self, using_relay: bool) -> Self {
         self.using_relay = using_relay;
@@ -200,6 +221,75 @@ impl ServerInfo {
     }
 }
 
+// =============================================================================
+// Model Naming
+// =============================================================================
+
+/// Blocks assumed for a model that is neither on disk nor in the registry
+/// (Llama 7B/8B-class models, the most common on the swarm).
+pub const DEFAULT_NUM_BLOCKS: usize = 32;
+
+/// DHT prefix Petals derives from a model name: the base name with dots
+/// turned to dashes (`org/Model-Name.1B` → `Model-Name-1B`).
+pub fn derive_dht_prefix(model: &str) -> String {
+    let base = model.rsplit('/').next().unwrap_or(model);
+    base.replace('.', "-")
+}
+
+/// HuggingFace repository URL of a model ID; bare names are taken to be
+/// `meta-llama` models.
+pub fn derive_repository(model: &str) -> String {
+    if model.contains('/') {
+        format!("https://huggingface.co/{model}")
+    } else {
+        format!("https://huggingface.co/meta-llama/{model}")
+    }
+}
+
+/// Transformer blocks declared by a HuggingFace `config.json`
+pub fn num_blocks_from_config(config: &serde_json::Value) -> Option<usize> {
+    config["num_hidden_layers"].as_u64().map(|n| n as usize)
+}
+
+/// How a model is named on the swarm: the prefix of its block keys, the
+/// repository it registers under `_petals.models` and its block count.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelIdentity {
+    pub model: String,
+    pub dht_prefix: String,
+    pub repository: String,
+    pub num_blocks: usize,
+}
+
+impl ModelIdentity {
+    /// Resolve `model` against what is known about it, in order:
+    ///
+    /// - prefix and repository: the registry entry servers announce it
+    ///   under, else derived from the name;
+    /// - blocks: the local model files (`local_num_blocks`), else the
+    ///   registry entry, else [`DEFAULT_NUM_BLOCKS`].
+    pub fn resolve(
+        model: &str,
+        catalog: Option<&crate::ModelCatalog>,
+        local_num_blocks: Option<usize>,
+    ) -> Self {
+        let entry = catalog.and_then(|c| c.find(model));
+        Self {
+            model: model.to_string(),
+            dht_prefix: entry
+                .map(|e| e.dht_prefix.clone())
+                .unwrap_or_else(|| derive_dht_prefix(model)),
+            repository: entry
+                .map(|e| e.repository.clone())
+                .unwrap_or_else(|| derive_repository(model)),
+            num_blocks: local_num_blocks
+                .filter(|&n| n > 0)
+                .or(entry.map(|e| e.num_blocks))
+                .unwrap_or(DEFAULT_NUM_BLOCKS),
+        }
+    }
+}
+
 // =============================================================================
 // Message Framing
 // =============================================================================
@@ -296,6 +386,29 @@ mod tests {
         assert_eq!(decoded.throughput, 15.5);
     }
 
+    #[test]
+    fn server_info_carries_accelerator() {
+        let plain = ServerInfo::new("cpu-node").to_msgpack().unwrap();
+        assert!(ServerInfo::from_msgpack(&plain)
+            .unwrap()
+            .accelerator
+            .is_none());
+
+        let info = ServerInfo::new("gpu-node").with_accelerator(crate::AcceleratorInfo {
+            device_type: "cuda".into(),
+            device_name: Some("NVIDIA L4".into()),
+            vram_mb: 23034,
+            dtypes: vec!["bfloat16".into(), "float16".into(), "float32".into()],
+            quant_types: vec!["none".into()],
+            backend: "candle-cuda".into(),
+        });
+        let decoded = ServerInfo::from_msgpack(&info.to_msgpack().unwrap()).unwrap();
+        let accel = decoded.accelerator.unwrap();
+        assert!(accel.supports_dtype("bf16"));
+        assert!(!accel.supports_quant("q4_k"));
+        assert!(decoded.extra.is_empty());
+    }
+
     #[test]
     fn test_message_framing() {
         let uid = ExpertUID {
@@ -321,4 +434,46 @@ mod tests {
             "Something went wrong"
         );
     }
+
+    #[test]
+    fn derives_petals_names() {
+        assert_eq!(
+            derive_dht_prefix("TinyLlama/TinyLlama-1.1B-Chat-v1.0"),
+            "TinyLlama-1-1B-Chat-v1-0"
+        );
+        assert_eq!(
+            derive_repository("Llama-3.1-8B-Instruct"),
+            "https://huggingface.co/meta-llama/Llama-3.1-8B-Instruct"
+        );
+        let config = serde_json::json!({ "num_hidden_layers": 22 });
+        assert_eq!(num_blocks_from_config(&config), Some(22));
+    }
+
+    #[test]
+    fn identity_prefers_registry_names_and_local_block_count() {
+        let mut catalog = crate::ModelCatalog::new(0);
+        catalog.entries.push(crate::CatalogEntry {
+            dht_prefix: "Llama-3-1-70B-Instruct-hf".into(),
+            repository: "https://huggingface.co/unsloth/Llama-3.1-70B-Instruct".into(),
+            num_blocks: 80,
+        });
+
+        let id = ModelIdentity::resolve("meta-llama/Llama-3.1-70B-Instruct", Some(&catalog), None);
+        assert_eq!(id.dht_prefix, "Llama-3-1-70B-Instruct-hf");
+        assert_eq!(
+            id.repository,
+            "https://huggingface.co/unsloth/Llama-3.1-70B-Instruct"
+        );
+        assert_eq!(id.num_blocks, 80);
+
+        // Model files on disk outrank the registry.
+        let id = ModelIdentity::resolve("Llama-3.1-70B-Instruct", Some(&catalog), Some(79));
+        assert_eq!(id.num_blocks, 79);
+
+        // Unknown everywhere: derived names and the default block count.
+        let id = ModelIdentity::resolve("org/Some-Model.2B", Some(&catalog), None);
+        assert_eq!(id.dht_prefix, "Some-Model-2B");
+        assert_eq!(id.repository, "https://huggingface.co/org/Some-Model.2B");
+        assert_eq!(id.num_blocks, DEFAULT_NUM_BLOCKS);
+    }
 }
@@ -8,6 +8,10 @@
 //! - **Peer Discovery**: Kademlia DHT for finding nodes by capability
 //! - **Message Routing**: Request/response protocols for inference
 //! - **NAT Traversal**: Hole punching and relay circuits
+//! - **Large Transfers**: Chunked, resumable streaming for parameters and shards
+//! - **Bandwidth Limits**: Token-bucket caps on upload and download
+//! - **Swarm State**: Per-model server and block coverage, as on map.kwaai.ai
+//! - **DHT Backends**: Kademlia or the Hivemind DHT behind one [`DhtBackend`] trait
 //!
 //! ## Example
 //!
@@ -29,19 +33,50 @@
 //! }
 //! ```
 
+pub mod attestation;
+pub mod backend;
+pub mod bandwidth;
+pub mod catalog;
+pub mod chunked;
 pub mod config;
 pub mod dht;
 pub mod error;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault;
+pub mod geo;
+pub mod health;
 pub mod hivemind;
 pub mod network;
+pub mod path;
+pub mod payload;
 pub mod protocol;
+pub mod reputation;
 pub mod rpc;
+pub mod state;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod transport;
 
-pub use config::{NetworkConfig, PETALS_BOOTSTRAP_SERVERS};
+pub use attestation::{
+    AdmissionPolicy, AttestationError, CapabilityAttestation, VerifiedCapabilities,
+};
+pub use backend::{open_backend, DhtBackend, HivemindBackend, NativeDhtRpc};
+pub use bandwidth::{BandwidthLimiter, Throttled, TokenBucket};
+pub use catalog::{CatalogEntry, ModelCatalog};
+pub use chunked::{ChunkSink, ChunkSource, TransferConfig, TransferProgress};
+pub use config::{DhtBackendKind, NetworkConfig, PETALS_BOOTSTRAP_SERVERS};
 pub use error::{P2PError, P2PResult};
-pub use hivemind::ServerInfo;
-pub use network::KwaaiNetwork;
+pub use geo::{GeoIpDb, PeerIpInfo};
+pub use health::{HealthSnapshot, HealthStatus, RequestStats};
+pub use hivemind::{ModelIdentity, ServerInfo};
+pub use network::{ConnectionProber, KwaaiNetwork, PeerInfo};
+pub use path::{PathKind, PathSelectionConfig, PathSelector, SelectedPath};
+pub use payload::PayloadCodec;
+pub use protocol::{EncryptedStream, PayloadCipher, PayloadEncryption};
+pub use reputation::{PeerObservation, ReputationStore, TrustScore, TrustTier};
+pub use state::{
+    ModelHealth, ModelReport, ModelStateBuilder, ServerRecord, SignatureStatus, SwarmState,
+};
 
 use async_trait::async_trait;
 use libp2p::{Multiaddr, PeerId};
@@ -97,6 +132,10 @@ pub struct Request {
     pub request_type: RequestType,
     /// Request payload
     pub payload: Vec<u8>,
+    /// Set while `payload` is encrypted for the recipient
+    /// (see [`PayloadCipher`])
+    #[serde(default)]
+    pub encryption: Option<PayloadEncryption>,
 }
 
 /// Response message for P2P communication
@@ -108,6 +147,10 @@ pub struct Response {
     pub status: ResponseStatus,
     /// Response payload
     pub payload: Vec<u8>,
+    /// Set while `payload` is encrypted for the requester
+    /// (see [`PayloadCipher`])
+    #[serde(default)]
+    pub encryption: Option<PayloadEncryption>,
 }
 
 /// Types of requests supported by the network
@@ -124,9 +167,10 @@ pub enum RequestType {
 }
 
 /// Response status codes
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ResponseStatus {
     /// Request successful
+    #[default]
     Ok,
     /// Request failed with error
     Error(String),
@@ -137,6 +181,9 @@ pub enum ResponseStatus {
 }
 
 /// Node capabilities advertised in DHT
+///
+/// Self-reported and unbounded in time; wrap in a
+/// [`CapabilityAttestation`] to sign it with an expiry.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeCapabilities {
     /// Peer ID
@@ -153,6 +200,9 @@ pub struct NodeCapabilities {
     pub compute_power: f32,
     /// Available memory (MB)
     pub available_memory: u64,
+    /// Accelerator the node computes on; None when it did not say
+    #[serde(default)]
+    pub accelerator: Option<AcceleratorInfo>,
 }
 
 impl NodeCapabilities {
@@ -166,6 +216,7 @@ impl NodeCapabilities {
             expert_ids: Vec::new(),
             compute_power: 0.0,
             available_memory: 0,
+            accelerator: None,
         }
     }
 
@@ -179,3 +230,121 @@ impl NodeCapabilities {
         bincode::deserialize(data).map_err(|e| P2PError::Serialization(e.to_string()))
     }
 }
+
+/// Accelerator a node computes on, advertised in [`NodeCapabilities`] and
+/// the DHT server-info record so clients can route to compatible servers
+///
+/// Dtype names follow the torch spelling used by `torch_dtype` in server
+/// info (`float32`, `float16`, `bfloat16`); quantization names follow
+/// `quant_type` (`none`, `q8_0`, `q4_k`, …).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AcceleratorInfo {
+    /// `cpu`, `cuda`, `metal` or `mlx`
+    pub device_type: String,
+    /// Device model, e.g. `NVIDIA GeForce RTX 4090`
+    #[serde(default)]
+    pub device_name: Option<String>,
+    /// Device memory (MB); unified memory on Apple Silicon, 0 for CPU
+    #[serde(default)]
+    pub vram_mb: u64,
+    /// Dtypes the backend computes in on this device
+    #[serde(default)]
+    pub dtypes: Vec<String>,
+    /// Quantized weight formats the backend can serve
+    #[serde(default)]
+    pub quant_types: Vec<String>,
+    /// Inference backend, e.g. `candle-cuda` or `mlx`
+    #[serde(default)]
+    pub backend: String,
+}
+
+impl AcceleratorInfo {
+    /// Whether the device computes in `dtype` (`bf16` and `bfloat16` alike)
+    pub fn supports_dtype(&self, dtype: &str) -> bool {
+        let want = normalize_dtype(dtype);
+        self.dtypes.iter().any(|d| normalize_dtype(d) == want)
+    }
+
+    /// Whether the backend serves weights quantized as `quant_type`
+    pub fn supports_quant(&self, quant_type: &str) -> bool {
+        self.quant_types
+            .iter()
+            .any(|q| q.eq_ignore_ascii_case(quant_type))
+    }
+
+    /// Whether the device is a GPU (anything but `cpu`)
+    pub fn is_gpu(&self) -> bool {
+        !self.device_type.eq_ignore_ascii_case("cpu")
+    }
+
+    /// The `accelerator` map of a server-info record
+    pub fn to_msgpack_value(&self) -> rmpv::Value {
+        let strings = |v: &[String]| {
+            rmpv::Value::Array(v.iter().map(|s| rmpv::Value::from(s.as_str())).collect())
+        };
+        let mut fields = vec![(
+            rmpv::Value::from("device_type"),
+            rmpv::Value::from(self.device_type.as_str()),
+        )];
+        if let Some(ref name) = self.device_name {
+            fields.push((
+                rmpv::Value::from("device_name"),
+                rmpv::Value::from(name.as_str()),
+            ));
+        }
+        fields.extend([
+            (
+                rmpv::Value::from("vram_mb"),
+                rmpv::Value::from(self.vram_mb),
+            ),
+            (rmpv::Value::from("dtypes"), strings(&self.dtypes)),
+            (rmpv::Value::from("quant_types"), strings(&self.quant_types)),
+            (
+                rmpv::Value::from("backend"),
+                rmpv::Value::from(self.backend.as_str()),
+            ),
+        ]);
+        rmpv::Value::Map(fields)
+    }
+
+    /// Read the `accelerator` map of a server-info record; None unless it
+    /// names a device type
+    pub fn from_msgpack_value(value: &rmpv::Value) -> Option<Self> {
+        let fields = value.as_map()?;
+        let get = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v)
+        };
+        let get_str = |key: &str| get(key).and_then(|v| v.as_str()).map(String::from);
+        let get_strings = |key: &str| {
+            get(key)
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Some(Self {
+            device_type: get_str("device_type")?,
+            device_name: get_str("device_name"),
+            vram_mb: get("vram_mb").and_then(|v| v.as_u64()).unwrap_or(0),
+            dtypes: get_strings("dtypes"),
+            quant_types: get_strings("quant_types"),
+            backend: get_str("backend").unwrap_or_default(),
+        })
+    }
+}
+
+/// Torch spelling of a dtype name: `bf16` → `bfloat16`, `fp16` → `float16`
+pub fn normalize_dtype(dtype: &str) -> String {
+    match dtype.to_ascii_lowercase().as_str() {
+        "bf16" => "bfloat16".to_string(),
+        "f16" | "fp16" | "half" => "float16".to_string(),
+        "f32" | "fp32" | "float" => "float32".to_string(),
+        other => other.to_string(),
+    }
+}
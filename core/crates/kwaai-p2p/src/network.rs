@@ -1,28 +1,50 @@
 //! Main network implementation
 
 use crate::{
-    config::NetworkConfig,
+    config::{parse_swarm_psk, NetworkConfig},
     dht::{DhtCommand, DhtManager},
     error::{P2PError, P2PResult},
+    health::{unix_now, HealthSnapshot, NetworkStats, Operation, RequestStats},
+    path::{CandidatePath, PathProbe, PathProber, PathSelectionConfig, PathSelector, SelectedPath},
     protocol::KwaaiProtocol,
     rpc::HivemindCodec,
     DhtOperations, NetworkBehaviour, NodeCapabilities, Request, Response,
 };
 use async_trait::async_trait;
 use libp2p::{
+    core::{upgrade, Transport as _},
     identify, identity,
     kad::{self, store::MemoryStore, Mode, Quorum, Record, RecordKey},
-    noise, request_response,
-    swarm::NetworkBehaviour as SwarmBehaviour,
+    noise,
+    pnet::{PnetConfig, PreSharedKey},
+    request_response,
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId, NetworkBehaviour as SwarmBehaviour, SwarmEvent,
+    },
     tcp, yamux, Multiaddr, PeerId, Swarm,
 };
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
 use tracing::{debug, info};
 
+/// How long the event loop waits on the swarm before releasing it to other
+/// callers (DHT commands, path probes)
+const EVENT_POLL: Duration = Duration::from_millis(25);
+
+/// Dials made by [`ConnectionProber`], awaiting their setup time
+type PendingProbes =
+    Arc<std::sync::Mutex<HashMap<ConnectionId, oneshot::Sender<P2PResult<Duration>>>>>;
+
 /// The main KwaaiNet P2P network manager
+///
+/// Clones share the same swarm and state, so one can drive the event loop
+/// while another serves DHT operations.
+#[derive(Clone)]
 pub struct KwaaiNetwork {
     /// Local peer ID
     local_peer_id: PeerId,
@@ -31,20 +53,25 @@ pub struct KwaaiNetwork {
     config: NetworkConfig,
 
     /// Swarm (wrapped in Arc<Mutex> for thread-safe async access)
-    swarm: Arc<Mutex<Option<Swarm<KwaaiBehaviour>>>>,
+    pub(crate) swarm: Arc<Mutex<Option<Swarm<KwaaiBehaviour>>>>,
 
     /// DHT manager
     dht: Arc<RwLock<DhtManager>>,
 
     /// Connected peers
-    #[allow(dead_code)]
     connected_peers: Arc<RwLock<HashMap<PeerId, PeerInfo>>>,
 
     /// Is network running (atomic for thread-safe access)
-    is_running: AtomicBool,
+    is_running: Arc<AtomicBool>,
+
+    /// Dials made by [`ConnectionProber`], awaiting their outcome
+    pending_probes: PendingProbes,
 
     /// DHT command receiver
     dht_command_rx: Arc<Mutex<mpsc::UnboundedReceiver<DhtCommand>>>,
+
+    /// Request outcome counters and recent errors
+    stats: Arc<NetworkStats>,
 }
 
 /// Information about a connected peer
@@ -56,6 +83,67 @@ pub struct PeerInfo {
     pub capabilities: Option<NodeCapabilities>,
     /// Connection time
     pub connected_at: std::time::Instant,
+    /// Preferred path, once [`KwaaiNetwork::refresh_paths`] has probed the peer
+    pub path: Option<SelectedPath>,
+}
+
+impl PeerInfo {
+    fn connected_now() -> Self {
+        Self {
+            addresses: Vec::new(),
+            capabilities: None,
+            connected_at: std::time::Instant::now(),
+            path: None,
+        }
+    }
+}
+
+/// [`PathProber`] that dials each candidate path and reports how long the
+/// connection took to set up
+///
+/// Setup costs the same few round trips (transport, security, multiplexer
+/// handshakes) on every path, so it ranks paths by latency. It measures no
+/// bandwidth. Needs the network's event loop running to see the dial
+/// complete; the extra connection closes once idle.
+#[derive(Clone)]
+pub struct ConnectionProber {
+    network: KwaaiNetwork,
+}
+
+#[async_trait]
+impl PathProber for ConnectionProber {
+    async fn probe(&self, peer: PeerId, path: &CandidatePath) -> P2PResult<PathProbe> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut swarm_guard = self.network.swarm.lock().await;
+            let swarm = swarm_guard.as_mut().ok_or(P2PError::NotInitialized)?;
+            let opts = DialOpts::peer_id(peer)
+                .addresses(vec![path.addr.clone()])
+                .condition(PeerCondition::Always)
+                .build();
+            let connection = opts.connection_id();
+            self.network
+                .pending_probes
+                .lock()
+                .unwrap()
+                .insert(connection, tx);
+            if let Err(e) = swarm.dial(opts) {
+                self.network
+                    .pending_probes
+                    .lock()
+                    .unwrap()
+                    .remove(&connection);
+                return Err(P2PError::DialFailed(e.to_string()));
+            }
+        }
+        let rtt = rx
+            .await
+            .map_err(|_| P2PError::Internal("path probe abandoned".to_string()))??;
+        Ok(PathProbe {
+            rtt,
+            bandwidth_bps: None,
+        })
+    }
 }
 
 /// Combined network behaviour for libp2p swarm
@@ -114,36 +202,48 @@ impl From<request_response::Event<crate::rpc::RpcRequest, crate::rpc::RpcRespons
 }
 
 impl KwaaiNetwork {
-    /// Create a new network instance
+    /// Create a new network instance with a fresh identity
     pub async fn new(config: NetworkConfig) -> P2PResult<Self> {
-        // Generate identity keypair
-        let local_key = identity::Keypair::generate_ed25519();
+        Self::with_keypair(config, identity::Keypair::generate_ed25519()).await
+    }
+
+    /// Create a network instance for the node identity `local_key`
+    pub async fn with_keypair(
+        config: NetworkConfig,
+        local_key: identity::Keypair,
+    ) -> P2PResult<Self> {
         let local_peer_id = PeerId::from(local_key.public());
 
         info!("Local peer ID: {}", local_peer_id);
 
+        let behaviour = Self::create_behaviour(&local_key, &config);
+        let swarm = build_swarm(local_key, &config, behaviour)?;
+        Ok(Self::from_swarm(config, swarm))
+    }
+
+    /// Wrap an already built swarm, e.g. one on another transport
+    pub(crate) fn from_swarm(config: NetworkConfig, swarm: Swarm<KwaaiBehaviour>) -> Self {
         // Create DHT command channel
         let (dht_command_tx, dht_command_rx) = mpsc::unbounded_channel();
 
-        // Create the swarm
-        let swarm = Self::create_swarm(local_key.clone(), &config)?;
-
-        Ok(Self {
-            local_peer_id,
+        Self {
+            local_peer_id: *swarm.local_peer_id(),
             config,
             swarm: Arc::new(Mutex::new(Some(swarm))),
             dht: Arc::new(RwLock::new(DhtManager::with_channel(dht_command_tx))),
             connected_peers: Arc::new(RwLock::new(HashMap::new())),
-            is_running: AtomicBool::new(false),
+            is_running: Arc::new(AtomicBool::new(false)),
+            pending_probes: Arc::default(),
             dht_command_rx: Arc::new(Mutex::new(dht_command_rx)),
-        })
+            stats: Arc::new(NetworkStats::default()),
+        }
     }
 
-    /// Create the libp2p swarm with configured behaviours
-    fn create_swarm(
-        local_key: identity::Keypair,
+    /// Create the combined behaviour with configured protocols
+    pub(crate) fn create_behaviour(
+        local_key: &identity::Keypair,
         config: &NetworkConfig,
-    ) -> P2PResult<Swarm<KwaaiBehaviour>> {
+    ) -> KwaaiBehaviour {
         let local_peer_id = PeerId::from(local_key.public());
 
         // Create Kademlia behaviour
@@ -170,27 +270,12 @@ impl KwaaiNetwork {
         // Create RPC protocol for Hivemind compatibility
         let (rpc, _protocol) = crate::rpc::create_hivemind_protocol();
 
-        let behaviour = KwaaiBehaviour {
+        KwaaiBehaviour {
             kademlia,
             identify,
             kwaai,
             rpc,
-        };
-
-        // Build the swarm
-        let swarm = libp2p::SwarmBuilder::with_existing_identity(local_key)
-            .with_tokio()
-            .with_tcp(
-                tcp::Config::default(),
-                noise::Config::new,
-                yamux::Config::default,
-            )
-            .map_err(|e| P2PError::Transport(e.to_string()))?
-            .with_behaviour(|_| Ok(behaviour))
-            .map_err(|e| P2PError::Internal(e.to_string()))?
-            .build();
-
-        Ok(swarm)
+        }
     }
 
     /// Start listening on configured addresses
@@ -222,6 +307,284 @@ impl KwaaiNetwork {
         self.is_running.load(Ordering::SeqCst)
     }
 
+    /// Stop the event loop started by [`spawn_event_loop`](Self::spawn_event_loop)
+    pub fn stop(&self) {
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+
+    /// Addresses the swarm is listening on
+    pub async fn listen_addrs(&self) -> Vec<Multiaddr> {
+        match self.swarm.lock().await.as_ref() {
+            Some(swarm) => swarm.listeners().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Drive the swarm in the background until [`stop`](Self::stop)
+    ///
+    /// Runs [`run_event_loop`](Self::run_event_loop) and, alongside it,
+    /// [`run_path_selection`](Self::run_path_selection) with a
+    /// [`ConnectionProber`], so every connected peer's preferred path is kept
+    /// up to date. Call after [`start`](Self::start).
+    pub fn spawn_event_loop(&self) -> JoinHandle<()> {
+        let network = self.clone();
+        tokio::spawn(async move {
+            let selector =
+                PathSelector::new(PathSelectionConfig::default(), network.connection_prober());
+            tokio::select! {
+                result = network.run_event_loop() => {
+                    if let Err(e) = result {
+                        debug!("Network event loop stopped: {}", e);
+                    }
+                }
+                _ = network.run_path_selection(selector) => {}
+            }
+        })
+    }
+
+    /// Process DHT commands and swarm events until [`stop`](Self::stop)
+    pub async fn run_event_loop(&self) -> P2PResult<()> {
+        while self.is_running() {
+            while self.process_dht_command().await? {}
+            self.poll_event(EVENT_POLL).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for one swarm event and handle it
+    ///
+    /// Returns whether an event arrived.
+    pub async fn poll_event(&self, timeout: Duration) -> P2PResult<bool> {
+        use futures::StreamExt;
+
+        let mut swarm_guard = self.swarm.lock().await;
+        let swarm = swarm_guard.as_mut().ok_or(P2PError::NotInitialized)?;
+        let Ok(event) = tokio::time::timeout(timeout, swarm.select_next_some()).await else {
+            return Ok(false);
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                established_in,
+                ..
+            } => {
+                drop(swarm_guard);
+                self.resolve_probe(connection_id, Ok(established_in));
+                // Only the dialer knows an address the peer can be reached
+                // on; the listener learns them from identify.
+                if endpoint.is_dialer() {
+                    self.add_peer_address(peer_id, endpoint.get_remote_address().clone())
+                        .await;
+                } else {
+                    self.connected_peers
+                        .write()
+                        .await
+                        .entry(peer_id)
+                        .or_insert_with(PeerInfo::connected_now);
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                drop(swarm_guard);
+                self.connected_peers.write().await.remove(&peer_id);
+            }
+            SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                error,
+                ..
+            } => {
+                drop(swarm_guard);
+                self.resolve_probe(connection_id, Err(P2PError::DialFailed(error.to_string())));
+            }
+            SwarmEvent::Behaviour(KwaaiBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+                ..
+            })) => {
+                for addr in &info.listen_addrs {
+                    swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, addr.clone());
+                }
+                drop(swarm_guard);
+                if self.connected_peers.read().await.contains_key(&peer_id) {
+                    for addr in info.listen_addrs {
+                        self.add_peer_address(peer_id, addr).await;
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(KwaaiBehaviourEvent::Kademlia(
+                kad::Event::OutboundQueryProgressed { result, step, .. },
+            )) => {
+                drop(swarm_guard);
+                self.record_query_outcome(&result, step.last);
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Count a DHT query once it has finished, successfully or not
+    ///
+    /// Queries report intermediate steps (one per record or provider batch
+    /// found); only the last one, or an error, settles the outcome. A lookup
+    /// that completes without finding the record still succeeded.
+    fn record_query_outcome(&self, result: &kad::QueryResult, last: bool) {
+        let (op, outcome) = match result {
+            kad::QueryResult::PutRecord(r) => (
+                Operation::DhtPut,
+                r.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            ),
+            kad::QueryResult::StartProviding(r) => (
+                Operation::DhtProvide,
+                r.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            ),
+            kad::QueryResult::GetRecord(Err(kad::GetRecordError::NotFound { .. })) => {
+                (Operation::DhtQuery, Ok(()))
+            }
+            kad::QueryResult::GetRecord(r) => (
+                Operation::DhtQuery,
+                r.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            ),
+            kad::QueryResult::GetProviders(r) => (
+                Operation::DhtQuery,
+                r.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            ),
+            _ => return,
+        };
+        if last || outcome.is_err() {
+            self.stats.record(op, &outcome);
+        }
+    }
+
+    /// A [`PathProber`] timing connection setup over each candidate path
+    pub fn connection_prober(&self) -> ConnectionProber {
+        ConnectionProber {
+            network: self.clone(),
+        }
+    }
+
+    fn resolve_probe(&self, connection: ConnectionId, result: P2PResult<Duration>) {
+        if let Some(tx) = self.pending_probes.lock().unwrap().remove(&connection) {
+            let _ = tx.send(result);
+        }
+    }
+
+    /// Take a point-in-time health snapshot
+    ///
+    /// Combines live swarm state (connections, routing table, in-flight
+    /// queries) with request outcome counters and the most recent errors.
+    pub async fn health_snapshot(&self) -> P2PResult<HealthSnapshot> {
+        let mut snapshot = HealthSnapshot {
+            taken_at: unix_now(),
+            peer_id: self.local_peer_id.to_string(),
+            is_running: self.is_running(),
+            connected_peers: 0,
+            routing_table_size: 0,
+            pending_dht_queries: 0,
+            dht_put: RequestStats::default(),
+            dht_provide: RequestStats::default(),
+            dht_query: RequestStats::default(),
+            last_errors: Vec::new(),
+        };
+
+        {
+            let mut swarm_guard = self.swarm.lock().await;
+            let swarm = swarm_guard.as_mut().ok_or(P2PError::NotInitialized)?;
+            snapshot.connected_peers = swarm.network_info().num_peers();
+            let kademlia = &mut swarm.behaviour_mut().kademlia;
+            snapshot.routing_table_size = kademlia.kbuckets().map(|b| b.num_entries()).sum();
+            snapshot.pending_dht_queries = kademlia.iter_queries().count();
+        }
+
+        self.stats.fill(&mut snapshot);
+        Ok(snapshot)
+    }
+
+    /// Record a known address for `peer`
+    ///
+    /// Addresses accumulate per peer and become the candidate paths probed by
+    /// [`refresh_paths`](Self::refresh_paths).
+    pub async fn add_peer_address(&self, peer: PeerId, addr: Multiaddr) {
+        let mut peers = self.connected_peers.write().await;
+        let info = peers.entry(peer).or_insert_with(PeerInfo::connected_now);
+        if !info.addresses.contains(&addr) {
+            info.addresses.push(addr);
+        }
+    }
+
+    /// Information about a known peer
+    pub async fn peer_info(&self, peer: &PeerId) -> Option<PeerInfo> {
+        self.connected_peers.read().await.get(peer).cloned()
+    }
+
+    /// Re-evaluate the preferred path of every peer whose selection is stale
+    ///
+    /// Peers with no selection yet, or whose selection is older than the
+    /// selector's re-evaluation interval, have all their addresses probed.
+    /// When the winner changes, the new path is dialled so that traffic can
+    /// move onto it. Returns the number of peers whose path changed.
+    pub async fn refresh_paths<P: PathProber>(
+        &self,
+        selector: &PathSelector<P>,
+    ) -> P2PResult<usize> {
+        let due: Vec<(PeerId, Vec<Multiaddr>, Option<SelectedPath>)> = self
+            .connected_peers
+            .read()
+            .await
+            .iter()
+            .filter(|(_, info)| info.path.as_ref().is_none_or(|p| selector.is_stale(p)))
+            .map(|(peer, info)| (*peer, info.addresses.clone(), info.path.clone()))
+            .collect();
+
+        let mut changed = 0;
+        for (peer, addrs, current) in due {
+            let selected = match selector.select(peer, &addrs, current.as_ref()).await {
+                Ok(selected) => selected,
+                Err(e) => {
+                    debug!("Path selection for {} failed: {}", peer, e);
+                    continue;
+                }
+            };
+
+            if current.as_ref().map(|c| &c.path) != Some(&selected.path) {
+                info!("Preferred path to {} is now {}", peer, selected.path.kind);
+                let mut swarm_guard = self.swarm.lock().await;
+                let swarm = swarm_guard.as_mut().ok_or(P2PError::NotInitialized)?;
+                let opts = DialOpts::peer_id(peer)
+                    .addresses(vec![selected.path.addr.clone()])
+                    .condition(PeerCondition::Always)
+                    .build();
+                if let Err(e) = swarm.dial(opts) {
+                    debug!("Dialling preferred path to {} failed: {}", peer, e);
+                }
+                changed += 1;
+            }
+
+            if let Some(info) = self.connected_peers.write().await.get_mut(&peer) {
+                info.path = Some(selected);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Re-evaluate peer paths forever, once per re-evaluation interval
+    pub async fn run_path_selection<P: PathProber>(&self, selector: PathSelector<P>) {
+        let mut ticker = tokio::time::interval(selector.config().reevaluate_interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.refresh_paths(&selector).await {
+                debug!("Path re-evaluation failed: {}", e);
+            }
+        }
+    }
+
     /// Announce blocks to the DHT (Petals-compatible)
     ///
     /// This announces the node's availability to serve specific model blocks,
@@ -295,11 +658,17 @@ impl KwaaiNetwork {
                             expires: None,
                         };
 
-                        swarm
+                        // The outcome is recorded when the query finishes
+                        // (see record_query_outcome); only a local failure
+                        // is known now.
+                        let result = swarm
                             .behaviour_mut()
                             .kademlia
-                            .put_record(record, Quorum::One)
-                            .map_err(|e| P2PError::DhtError(e.to_string()))?;
+                            .put_record(record, Quorum::One);
+                        if result.is_err() {
+                            self.stats.record(Operation::DhtPut, &result);
+                        }
+                        result.map_err(|e| P2PError::DhtError(e.to_string()))?;
 
                         debug!("DHT record stored: {}", key);
                     }
@@ -308,11 +677,11 @@ impl KwaaiNetwork {
                         info!("Processing DHT StartProviding: {}", key);
 
                         let record_key = RecordKey::new(&key);
-                        swarm
-                            .behaviour_mut()
-                            .kademlia
-                            .start_providing(record_key)
-                            .map_err(|e| P2PError::DhtError(e.to_string()))?;
+                        let result = swarm.behaviour_mut().kademlia.start_providing(record_key);
+                        if result.is_err() {
+                            self.stats.record(Operation::DhtProvide, &result);
+                        }
+                        result.map_err(|e| P2PError::DhtError(e.to_string()))?;
 
                         debug!("Started providing: {}", key);
                     }
@@ -421,8 +790,62 @@ impl DhtOperations for KwaaiNetwork {
     }
 }
 
+/// How long a connection without open streams is kept, so request/response
+/// peers are not redialled for every RPC
+pub(crate) const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Build a TCP/Noise/Yamux swarm running `behaviour`
+///
+/// A private swarm runs the pnet handshake on every TCP connection before
+/// Noise, so peers without the key are dropped before they learn anything
+/// about this node.
+pub(crate) fn build_swarm<B: SwarmBehaviour>(
+    local_key: identity::Keypair,
+    config: &NetworkConfig,
+    behaviour: B,
+) -> P2PResult<Swarm<B>> {
+    let builder = libp2p::SwarmBuilder::with_existing_identity(local_key).with_tokio();
+    let swarm = match &config.swarm_psk {
+        Some(psk) => {
+            let psk = PreSharedKey::new(parse_swarm_psk(psk)?);
+            info!(
+                "Private swarm enabled (PSK fingerprint {})",
+                psk.fingerprint()
+            );
+            builder
+                .with_other_transport(|key| {
+                    Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                        tcp::tokio::Transport::new(tcp::Config::default())
+                            .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket))
+                            .upgrade(upgrade::Version::V1Lazy)
+                            .authenticate(noise::Config::new(key)?)
+                            .multiplex(yamux::Config::default()),
+                    )
+                })
+                .map_err(|e| P2PError::Transport(e.to_string()))?
+                .with_behaviour(|_| Ok(behaviour))
+                .map_err(|e| P2PError::Internal(e.to_string()))?
+                .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT))
+                .build()
+        }
+        None => builder
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(|e| P2PError::Transport(e.to_string()))?
+            .with_behaviour(|_| Ok(behaviour))
+            .map_err(|e| P2PError::Internal(e.to_string()))?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT))
+            .build(),
+    };
+
+    Ok(swarm)
+}
+
 /// Extract peer ID from a multiaddress if present
-fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
+pub(crate) fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
     addr.iter().find_map(|p| {
         if let libp2p::multiaddr::Protocol::P2p(peer_id) = p {
             Some(peer_id)
@@ -431,3 +854,155 @@ fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthStatus;
+    use libp2p::multiaddr::Protocol;
+
+    #[tokio::test]
+    async fn health_snapshot_of_fresh_network() {
+        let network = KwaaiNetwork::new(NetworkConfig::default()).await.unwrap();
+        let snap = network.health_snapshot().await.unwrap();
+        assert_eq!(snap.peer_id, network.local_peer_id().to_string());
+        assert!(!snap.is_running);
+        assert_eq!(snap.connected_peers, 0);
+        assert_eq!(snap.pending_dht_queries, 0);
+        assert!(snap.last_errors.is_empty());
+        assert_eq!(snap.status(), HealthStatus::Unhealthy);
+    }
+
+    struct RttProber;
+
+    #[async_trait]
+    impl PathProber for RttProber {
+        async fn probe(
+            &self,
+            _peer: PeerId,
+            path: &crate::path::CandidatePath,
+        ) -> P2PResult<crate::path::PathProbe> {
+            let rtt_ms = if path.kind.is_relayed() { 20 } else { 90 };
+            Ok(crate::path::PathProbe {
+                rtt: std::time::Duration::from_millis(rtt_ms),
+                bandwidth_bps: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_paths_records_selection_in_peer_info() {
+        let network = KwaaiNetwork::new(NetworkConfig::default()).await.unwrap();
+        let peer = PeerId::random();
+        let relay = PeerId::random();
+        let circuit: Multiaddr = format!("/ip4/10.0.0.2/tcp/4001/p2p/{relay}/p2p-circuit")
+            .parse()
+            .unwrap();
+        network
+            .add_peer_address(peer, "/ip4/1.2.3.4/tcp/4001".parse().unwrap())
+            .await;
+        network.add_peer_address(peer, circuit.clone()).await;
+
+        let selector = PathSelector::new(crate::path::PathSelectionConfig::default(), RttProber);
+        assert_eq!(network.refresh_paths(&selector).await.unwrap(), 1);
+
+        let path = network.peer_info(&peer).await.unwrap().path.unwrap();
+        assert_eq!(path.path.addr, circuit);
+        assert!(path.is_relayed());
+
+        // Fresh selections are not re-probed.
+        assert_eq!(network.refresh_paths(&selector).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn dht_queries_are_counted_when_they_finish() {
+        let network = KwaaiNetwork::new(NetworkConfig::default()).await.unwrap();
+        network.start().await.unwrap();
+        {
+            let mut swarm_guard = network.swarm.lock().await;
+            let kademlia = &mut swarm_guard.as_mut().unwrap().behaviour_mut().kademlia;
+            kademlia.get_record(RecordKey::new(&"missing"));
+            kademlia.get_providers(RecordKey::new(&"missing"));
+        }
+        // Sent, not yet answered.
+        let snapshot = network.health_snapshot().await.unwrap();
+        assert_eq!(snapshot.dht_query.total(), 0);
+        assert_eq!(snapshot.pending_dht_queries, 2);
+
+        // With no peers both lookups finish empty-handed, which is not an
+        // error.
+        while network.health_snapshot().await.unwrap().dht_query.total() < 2 {
+            network
+                .poll_event(Duration::from_millis(100))
+                .await
+                .unwrap();
+        }
+        let snapshot = network.health_snapshot().await.unwrap();
+        assert_eq!(snapshot.dht_query.succeeded, 2);
+        assert_eq!(snapshot.pending_dht_queries, 0);
+    }
+
+    async fn loopback_network() -> KwaaiNetwork {
+        let config = NetworkConfig {
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".into()],
+            ..NetworkConfig::default()
+        };
+        let network = KwaaiNetwork::new(config).await.unwrap();
+        network.start().await.unwrap();
+        network.spawn_event_loop();
+        network
+    }
+
+    async fn wait_for<F, Fut>(mut done: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !done().await {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("condition not reached in time");
+    }
+
+    #[tokio::test]
+    async fn connections_fill_peers_and_paths_are_probed() {
+        let a = loopback_network().await;
+        let b = loopback_network().await;
+        wait_for(|| async { !b.listen_addrs().await.is_empty() }).await;
+        let b_addr = b.listen_addrs().await[0].clone();
+
+        let mut dialer = a.clone();
+        dialer
+            .bootstrap(vec![b_addr.clone().with(Protocol::P2p(b.local_peer_id()))])
+            .await
+            .unwrap();
+        wait_for(|| async { a.peer_info(&b.local_peer_id()).await.is_some() }).await;
+        wait_for(|| async { b.peer_info(&a.local_peer_id()).await.is_some() }).await;
+        assert!(a
+            .peer_info(&b.local_peer_id())
+            .await
+            .unwrap()
+            .addresses
+            .iter()
+            .any(|addr| addr.to_string().starts_with(&b_addr.to_string())));
+
+        let path = CandidatePath::from_multiaddr(b_addr).unwrap();
+        let probe = a
+            .connection_prober()
+            .probe(b.local_peer_id(), &path)
+            .await
+            .unwrap();
+        assert!(probe.rtt < Duration::from_secs(5));
+
+        let selector = PathSelector::new(PathSelectionConfig::default(), a.connection_prober());
+        a.refresh_paths(&selector).await.unwrap();
+        let selected = a.peer_info(&b.local_peer_id()).await.unwrap().path.unwrap();
+        assert_eq!(selected.path.kind, crate::path::PathKind::Direct);
+
+        a.stop();
+        b.stop();
+    }
+}
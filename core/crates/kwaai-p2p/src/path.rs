@@ -0,0 +1,359 @@
+//! Path selection between direct and relayed connections
+//!
+//! When a peer is reachable both directly and through one or more circuit
+//! relays, libp2p keeps whichever connection happened to succeed first. A
+//! [`PathSelector`] instead probes every candidate path with a
+//! [`PathProber`], scores each by the estimated time to move a reference
+//! payload (round trip plus transfer time), and keeps the best one. The choice
+//! is re-evaluated once it is older than
+//! [`PathSelectionConfig::reevaluate_interval`], and only replaced when a
+//! challenger is better by [`PathSelectionConfig::switch_margin`] so that
+//! paths with similar scores do not flap.
+
+use crate::error::{P2PError, P2PResult};
+use async_trait::async_trait;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How a candidate path reaches the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PathKind {
+    /// Direct transport connection
+    Direct,
+    /// Circuit through a relay peer
+    Relayed {
+        /// The relay the circuit goes through
+        relay: PeerId,
+    },
+}
+
+impl PathKind {
+    /// Whether this path goes through a relay
+    pub fn is_relayed(&self) -> bool {
+        matches!(self, PathKind::Relayed { .. })
+    }
+}
+
+impl std::fmt::Display for PathKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathKind::Direct => write!(f, "direct"),
+            PathKind::Relayed { relay } => write!(f, "relay {relay}"),
+        }
+    }
+}
+
+/// One way of reaching a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidatePath {
+    /// Direct or relayed
+    pub kind: PathKind,
+    /// Address to dial for this path
+    pub addr: Multiaddr,
+}
+
+impl CandidatePath {
+    /// Classify `addr` as a direct or relayed path
+    ///
+    /// A relayed address has the form
+    /// `/…/p2p/<RELAY>/p2p-circuit[/p2p/<DEST>]`; the relay is the last
+    /// `/p2p/` component before `/p2p-circuit`. Returns `None` for a circuit
+    /// address that does not name its relay.
+    pub fn from_multiaddr(addr: Multiaddr) -> Option<Self> {
+        let mut last_peer = None;
+        for component in addr.iter() {
+            match component {
+                Protocol::P2p(peer) => last_peer = Some(peer),
+                Protocol::P2pCircuit => {
+                    let relay = last_peer?;
+                    return Some(Self {
+                        kind: PathKind::Relayed { relay },
+                        addr,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Some(Self {
+            kind: PathKind::Direct,
+            addr,
+        })
+    }
+}
+
+/// Measured quality of one path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathProbe {
+    /// Round-trip time
+    pub rtt: Duration,
+    /// Measured throughput in bytes per second, if the probe measured it
+    pub bandwidth_bps: Option<f64>,
+}
+
+/// Measures a candidate path
+///
+/// Implementations typically time a small echo for the round trip and a
+/// fixed-size transfer for bandwidth over a connection dialled on
+/// `path.addr`.
+#[async_trait]
+pub trait PathProber: Send + Sync {
+    /// Probe `path` to `peer`
+    async fn probe(&self, peer: PeerId, path: &CandidatePath) -> P2PResult<PathProbe>;
+}
+
+/// Tuning for [`PathSelector`]
+#[derive(Debug, Clone)]
+pub struct PathSelectionConfig {
+    /// Re-probe a peer once its selected path is older than this
+    pub reevaluate_interval: Duration,
+    /// Per-probe timeout; a path that does not answer in time is skipped
+    pub probe_timeout: Duration,
+    /// Payload size used to weigh bandwidth against round-trip time
+    pub reference_payload_bytes: u64,
+    /// Fraction by which a challenger's score must beat the current path
+    /// before the selection switches (0.2 = 20% better)
+    pub switch_margin: f64,
+}
+
+impl Default for PathSelectionConfig {
+    fn default() -> Self {
+        Self {
+            reevaluate_interval: Duration::from_secs(60),
+            probe_timeout: Duration::from_secs(5),
+            // Roughly one hidden-state activation for a 4k-wide model.
+            reference_payload_bytes: 64 * 1024,
+            switch_margin: 0.2,
+        }
+    }
+}
+
+/// The path currently preferred for a peer
+#[derive(Debug, Clone)]
+pub struct SelectedPath {
+    /// The chosen path
+    pub path: CandidatePath,
+    /// Probe result that won the selection
+    pub probe: PathProbe,
+    /// Estimated seconds to move the reference payload over this path
+    pub score: f64,
+    /// When the path was last evaluated
+    pub evaluated_at: Instant,
+}
+
+impl SelectedPath {
+    /// Whether this path goes through a relay
+    pub fn is_relayed(&self) -> bool {
+        self.path.kind.is_relayed()
+    }
+}
+
+/// Probes candidate paths and picks the best one
+pub struct PathSelector<P> {
+    config: PathSelectionConfig,
+    prober: P,
+}
+
+impl<P: PathProber> PathSelector<P> {
+    /// Create a selector using `prober` to measure paths
+    pub fn new(config: PathSelectionConfig, prober: P) -> Self {
+        Self { config, prober }
+    }
+
+    /// Selector configuration
+    pub fn config(&self) -> &PathSelectionConfig {
+        &self.config
+    }
+
+    /// Whether `current` is due for re-evaluation
+    pub fn is_stale(&self, current: &SelectedPath) -> bool {
+        current.evaluated_at.elapsed() >= self.config.reevaluate_interval
+    }
+
+    /// Estimated seconds to move the reference payload over a probed path
+    ///
+    /// Paths without a bandwidth measurement are scored on round-trip time
+    /// alone.
+    pub fn score(&self, probe: &PathProbe) -> f64 {
+        let transfer = match probe.bandwidth_bps {
+            Some(bps) if bps > 0.0 => self.config.reference_payload_bytes as f64 / bps,
+            Some(_) => f64::INFINITY,
+            None => 0.0,
+        };
+        probe.rtt.as_secs_f64() + transfer
+    }
+
+    /// Probe every candidate address of `peer` and pick the best path
+    ///
+    /// `current` is the previous selection, if any. It is kept unless a
+    /// challenger beats it by the configured margin, or unless its own path
+    /// is no longer among the candidates or fails its probe. Returns an error
+    /// only if no candidate could be probed.
+    pub async fn select(
+        &self,
+        peer: PeerId,
+        addrs: &[Multiaddr],
+        current: Option<&SelectedPath>,
+    ) -> P2PResult<SelectedPath> {
+        let mut probed = Vec::new();
+        for path in addrs
+            .iter()
+            .cloned()
+            .filter_map(CandidatePath::from_multiaddr)
+        {
+            let result =
+                tokio::time::timeout(self.config.probe_timeout, self.prober.probe(peer, &path))
+                    .await;
+            match result {
+                Ok(Ok(probe)) => {
+                    let score = self.score(&probe);
+                    debug!("Path to {} via {}: score {:.4}s", peer, path.kind, score);
+                    probed.push(SelectedPath {
+                        path,
+                        probe,
+                        score,
+                        evaluated_at: Instant::now(),
+                    });
+                }
+                Ok(Err(e)) => debug!("Probe of {} via {} failed: {}", peer, path.kind, e),
+                Err(_) => debug!("Probe of {} via {} timed out", peer, path.kind),
+            }
+        }
+
+        let best = probed
+            .iter()
+            .min_by(|a, b| a.score.total_cmp(&b.score))
+            .cloned()
+            .ok_or_else(|| P2PError::PeerNotFound(format!("no reachable path to {peer}")))?;
+
+        let incumbent = current.and_then(|c| probed.iter().find(|p| p.path == c.path));
+        match incumbent {
+            Some(inc) if best.score >= inc.score * (1.0 - self.config.switch_margin) => {
+                Ok(inc.clone())
+            }
+            _ => Ok(best),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct FixedProber(HashMap<Multiaddr, PathProbe>);
+
+    #[async_trait]
+    impl PathProber for FixedProber {
+        async fn probe(&self, _peer: PeerId, path: &CandidatePath) -> P2PResult<PathProbe> {
+            self.0
+                .get(&path.addr)
+                .copied()
+                .ok_or_else(|| P2PError::ConnectionFailed(path.addr.to_string()))
+        }
+    }
+
+    fn probe(rtt_ms: u64, bandwidth_bps: f64) -> PathProbe {
+        PathProbe {
+            rtt: Duration::from_millis(rtt_ms),
+            bandwidth_bps: Some(bandwidth_bps),
+        }
+    }
+
+    fn relayed(relay: PeerId, dest: PeerId) -> Multiaddr {
+        format!("/ip4/10.0.0.2/tcp/4001/p2p/{relay}/p2p-circuit/p2p/{dest}")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn classifies_direct_and_relayed_addresses() {
+        let relay = PeerId::random();
+        let dest = PeerId::random();
+
+        let direct = CandidatePath::from_multiaddr("/ip4/1.2.3.4/tcp/4001".parse().unwrap());
+        assert_eq!(direct.unwrap().kind, PathKind::Direct);
+
+        let circuit = CandidatePath::from_multiaddr(relayed(relay, dest)).unwrap();
+        assert_eq!(circuit.kind, PathKind::Relayed { relay });
+
+        let anonymous = "/ip4/1.2.3.4/tcp/4001/p2p-circuit".parse().unwrap();
+        assert!(CandidatePath::from_multiaddr(anonymous).is_none());
+    }
+
+    #[tokio::test]
+    async fn prefers_the_fastest_path() {
+        let peer = PeerId::random();
+        let (relay_a, relay_b) = (PeerId::random(), PeerId::random());
+        let direct: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+        let via_a = relayed(relay_a, peer);
+        let via_b = relayed(relay_b, peer);
+
+        // Direct has low latency but is throttled; relay B is the best trade-off.
+        let prober = FixedProber(HashMap::from([
+            (direct.clone(), probe(10, 100_000.0)),
+            (via_a.clone(), probe(80, 10_000_000.0)),
+            (via_b.clone(), probe(30, 10_000_000.0)),
+        ]));
+        let selector = PathSelector::new(PathSelectionConfig::default(), prober);
+
+        let chosen = selector
+            .select(peer, &[direct, via_a, via_b.clone()], None)
+            .await
+            .unwrap();
+        assert_eq!(chosen.path.addr, via_b);
+        assert_eq!(chosen.path.kind, PathKind::Relayed { relay: relay_b });
+    }
+
+    #[tokio::test]
+    async fn keeps_incumbent_within_margin() {
+        let peer = PeerId::random();
+        let direct: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+        let via_relay = relayed(PeerId::random(), peer);
+        let addrs = [direct.clone(), via_relay.clone()];
+
+        let selector = PathSelector::new(
+            PathSelectionConfig::default(),
+            FixedProber(HashMap::from([
+                (direct.clone(), probe(50, 10_000_000.0)),
+                (via_relay.clone(), probe(45, 10_000_000.0)),
+            ])),
+        );
+        let incumbent = SelectedPath {
+            path: CandidatePath::from_multiaddr(direct.clone()).unwrap(),
+            probe: probe(50, 10_000_000.0),
+            score: 0.0,
+            evaluated_at: Instant::now(),
+        };
+        let chosen = selector
+            .select(peer, &addrs, Some(&incumbent))
+            .await
+            .unwrap();
+        assert_eq!(chosen.path.addr, direct);
+
+        // A clearly better challenger wins.
+        let selector = PathSelector::new(
+            PathSelectionConfig::default(),
+            FixedProber(HashMap::from([
+                (direct.clone(), probe(50, 10_000_000.0)),
+                (via_relay.clone(), probe(5, 10_000_000.0)),
+            ])),
+        );
+        let chosen = selector
+            .select(peer, &addrs, Some(&incumbent))
+            .await
+            .unwrap();
+        assert_eq!(chosen.path.addr, via_relay);
+    }
+
+    #[tokio::test]
+    async fn errors_when_no_path_answers() {
+        let selector =
+            PathSelector::new(PathSelectionConfig::default(), FixedProber(HashMap::new()));
+        let addrs = ["/ip4/1.2.3.4/tcp/4001".parse().unwrap()];
+        assert!(selector
+            .select(PeerId::random(), &addrs, None)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Framed zstd compression for non-tensor payloads
+//!
+//! DHT values, `ServerInfo` blobs and RPC bodies are small, repetitive
+//! msgpack/protobuf documents that zstd shrinks several-fold. Every
+//! compressed payload carries an 8-byte header so a receiver can tell how to
+//! decode it and how large the result will be before inflating anything:
+//!
+//! ```text
+//! 0..2  magic  b"KZ"
+//! 2     frame version (1)
+//! 3     codec (0 = raw, 1 = zstd)
+//! 4..8  decoded length, u32 big-endian
+//! 8..   body
+//! ```
+//!
+//! The codec lives here rather than in `kwaai-compression` because the DHT
+//! stack must build without candle (see `tests/minimal_build.rs`).
+//! Peers opt into it per stream through protocol negotiation; see
+//! [`HIVEMIND_ZSTD_PROTOCOL`](crate::rpc::HIVEMIND_ZSTD_PROTOCOL).
+
+use crate::error::{P2PError, P2PResult};
+
+/// First two bytes of every frame
+pub const FRAME_MAGIC: [u8; 2] = *b"KZ";
+
+/// Frame layout version
+pub const FRAME_VERSION: u8 = 1;
+
+/// Size of the frame header
+pub const FRAME_HEADER_LEN: usize = 8;
+
+/// Largest decoded payload accepted, matching the RPC message limit
+pub const MAX_PAYLOAD_LEN: usize = 10_000_000;
+
+/// zstd level used when none is configured
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+const CODEC_RAW: u8 = 0;
+const CODEC_ZSTD: u8 = 1;
+
+/// How the body of a frame is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadCodec {
+    /// Stored as is
+    #[default]
+    Raw,
+    /// zstd at the given level
+    Zstd { level: i32 },
+}
+
+impl PayloadCodec {
+    /// zstd at [`DEFAULT_ZSTD_LEVEL`]
+    pub fn zstd() -> Self {
+        PayloadCodec::Zstd {
+            level: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+
+    /// Frame `data`
+    ///
+    /// A zstd body that would not be smaller than the input is stored raw,
+    /// so framing never costs more than the header.
+    pub fn encode(self, data: &[u8]) -> P2PResult<Vec<u8>> {
+        if data.len() > MAX_PAYLOAD_LEN {
+            return Err(P2PError::Serialization(format!(
+                "payload of {} bytes exceeds the {MAX_PAYLOAD_LEN} byte limit",
+                data.len()
+            )));
+        }
+        let (codec, body) = match self {
+            PayloadCodec::Raw => (CODEC_RAW, None),
+            PayloadCodec::Zstd { level } => {
+                let packed = zstd::bulk::compress(data, level)
+                    .map_err(|e| P2PError::Serialization(format!("zstd compress: {e}")))?;
+                if packed.len() < data.len() {
+                    (CODEC_ZSTD, Some(packed))
+                } else {
+                    (CODEC_RAW, None)
+                }
+            }
+        };
+        let body = body.as_deref().unwrap_or(data);
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+        frame.extend_from_slice(&FRAME_MAGIC);
+        frame.push(FRAME_VERSION);
+        frame.push(codec);
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(body);
+        Ok(frame)
+    }
+}
+
+/// Whether `data` starts with a frame header
+pub fn is_framed(data: &[u8]) -> bool {
+    data.len() >= FRAME_HEADER_LEN && data[..2] == FRAME_MAGIC
+}
+
+/// Decode a frame produced by [`PayloadCodec::encode`]
+pub fn decode(frame: &[u8]) -> P2PResult<Vec<u8>> {
+    if !is_framed(frame) {
+        return Err(P2PError::Serialization(
+            "payload frame: missing header".to_string(),
+        ));
+    }
+    if frame[2] != FRAME_VERSION {
+        return Err(P2PError::Serialization(format!(
+            "payload frame: unsupported version {}",
+            frame[2]
+        )));
+    }
+    let len = u32::from_be_bytes(frame[4..8].try_into().unwrap()) as usize;
+    if len > MAX_PAYLOAD_LEN {
+        return Err(P2PError::Serialization(format!(
+            "payload frame: declared length {len} exceeds the {MAX_PAYLOAD_LEN} byte limit"
+        )));
+    }
+    let body = &frame[FRAME_HEADER_LEN..];
+
+    let data = match frame[3] {
+        CODEC_RAW => body.to_vec(),
+        CODEC_ZSTD => zstd::bulk::decompress(body, len)
+            .map_err(|e| P2PError::Serialization(format!("zstd decompress: {e}")))?,
+        other => {
+            return Err(P2PError::Serialization(format!(
+                "payload frame: unknown codec {other}"
+            )))
+        }
+    };
+    if data.len() != len {
+        return Err(P2PError::Serialization(format!(
+            "payload frame: decoded {} bytes, header says {len}",
+            data.len()
+        )));
+    }
+    Ok(data)
+}
+
+/// Decode `data` if it is framed, or return it unchanged
+///
+/// For stores that hold values written both before and after compression
+/// was enabled.
+pub fn decode_or_raw(data: &[u8]) -> P2PResult<Vec<u8>> {
+    if is_framed(data) {
+        decode(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerInfo;
+
+    #[test]
+    fn zstd_round_trip_shrinks_server_info() {
+        let blob = ServerInfo::new("compress-me")
+            .with_span(0, 80)
+            .to_msgpack()
+            .unwrap()
+            .repeat(8);
+        let frame = PayloadCodec::zstd().encode(&blob).unwrap();
+        assert_eq!(frame[3], CODEC_ZSTD);
+        assert!(frame.len() < blob.len());
+        assert_eq!(decode(&frame).unwrap(), blob);
+    }
+
+    #[test]
+    fn incompressible_input_is_stored_raw() {
+        let data = [7u8, 1, 200];
+        let frame = PayloadCodec::zstd().encode(&data).unwrap();
+        assert_eq!(frame[3], CODEC_RAW);
+        assert_eq!(frame.len(), FRAME_HEADER_LEN + data.len());
+        assert_eq!(decode(&frame).unwrap(), data);
+        assert_eq!(decode_or_raw(&data).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        let mut frame = PayloadCodec::zstd().encode(&[0u8; 256]).unwrap();
+        assert!(decode(&frame[..4]).is_err());
+
+        let mut lying = frame.clone();
+        lying[4..8].copy_from_slice(&100u32.to_be_bytes());
+        assert!(decode(&lying).is_err());
+
+        frame[4..8].copy_from_slice(&(MAX_PAYLOAD_LEN as u32 + 1).to_be_bytes());
+        assert!(decode(&frame).is_err());
+    }
+}
@@ -1,8 +1,37 @@
 //! Custom KwaaiNet protocol for inference and averaging
+//!
+//! Also provides end-to-end encryption of [`Request`]/[`Response`] payloads.
+//! Transport encryption (Noise) ends at every hop, so a relay forwarding a
+//! circuit sees prompts and activations in the clear. [`PayloadCipher`]
+//! encrypts them for the destination peer only: both sides run X25519 on
+//! their Ed25519 identity keys, derive a ChaCha20-Poly1305 key with
+//! HKDF-SHA256, and no extra round trip is needed. [`EncryptedStream`]
+//! applies the same cipher to a byte stream, e.g. an averaging exchange.
 
+use crate::error::{P2PError, P2PResult};
+use crate::{Request, Response};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use libp2p::identity::{Keypair, PublicKey};
 use libp2p::swarm::NetworkBehaviour;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tracing::debug;
 
+/// HKDF salt, binding derived keys to this scheme and version.
+const PAYLOAD_KDF_SALT: &[u8] = b"kwaai-payload-encryption-v1";
+
+/// Multihash code of the identity hash, which inlines the public key in
+/// Ed25519 peer IDs (`12D3KooW...`).
+const IDENTITY_MULTIHASH: u64 = 0x00;
+
 /// Custom protocol for KwaaiNet operations
 ///
 /// This protocol handles:
@@ -65,3 +94,524 @@ impl NetworkBehaviour for KwaaiProtocol {
         std::task::Poll::Pending
     }
 }
+
+// =============================================================================
+// Payload encryption
+// =============================================================================
+
+/// Marks a payload as encrypted by [`PayloadCipher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayloadEncryption {
+    /// ChaCha20-Poly1305 nonce, random per message
+    pub nonce: [u8; 12],
+}
+
+/// Encrypts payloads exchanged with one remote peer.
+///
+/// The key is the same on both ends: X25519 between our Ed25519 identity
+/// (converted to Montgomery form) and the peer's, run through HKDF-SHA256
+/// with both peer IDs. Only Ed25519 identities are supported.
+pub struct PayloadCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for PayloadCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PayloadCipher").finish_non_exhaustive()
+    }
+}
+
+impl PayloadCipher {
+    /// Derive the cipher shared between `local` and the peer owning `remote`.
+    pub fn new(local: &Keypair, remote: &PublicKey) -> P2PResult<Self> {
+        let not_ed25519 = |who: &str| {
+            P2PError::Protocol(format!("payload encryption needs an Ed25519 {who} key"))
+        };
+        let local_ed = local
+            .clone()
+            .try_into_ed25519()
+            .map_err(|_| not_ed25519("local"))?;
+        let remote_ed = remote
+            .clone()
+            .try_into_ed25519()
+            .map_err(|_| not_ed25519("remote"))?;
+
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&local_ed.to_bytes()[..32]);
+        let scalar = ed25519_dalek::SigningKey::from_bytes(&seed).to_scalar_bytes();
+        let point = ed25519_dalek::VerifyingKey::from_bytes(&remote_ed.to_bytes())
+            .map_err(|e| P2PError::Protocol(format!("invalid remote key: {e}")))?
+            .to_montgomery();
+        let shared = x25519_dalek::x25519(scalar, point.to_bytes());
+        if shared == [0u8; 32] {
+            return Err(P2PError::Protocol(
+                "remote key is a low-order point".to_string(),
+            ));
+        }
+
+        // Sorted so both ends feed HKDF the same info.
+        let mut ids = [
+            local.public().to_peer_id().to_bytes(),
+            remote.to_peer_id().to_bytes(),
+        ];
+        ids.sort();
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(PAYLOAD_KDF_SALT), &shared)
+            .expand_multi_info(&[&ids[0], &ids[1]], &mut key)
+            .map_err(|e| P2PError::Internal(format!("HKDF expand failed: {e}")))?;
+        Ok(Self {
+            cipher: ChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// Like [`new`](Self::new), taking the public key from `remote` itself.
+    /// Fails for peer IDs that hash the key instead of inlining it (RSA).
+    pub fn for_peer(local: &Keypair, remote: &PeerId) -> P2PResult<Self> {
+        let multihash = remote.as_ref();
+        if multihash.code() != IDENTITY_MULTIHASH {
+            return Err(P2PError::Protocol(format!(
+                "peer {remote} does not embed its public key"
+            )));
+        }
+        let key = PublicKey::try_decode_protobuf(multihash.digest())
+            .map_err(|e| P2PError::Protocol(format!("peer {remote}: {e}")))?;
+        Self::new(local, &key)
+    }
+
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> P2PResult<(PayloadEncryption, Vec<u8>)> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| P2PError::Internal("payload encryption failed".to_string()))?;
+        Ok((
+            PayloadEncryption {
+                nonce: nonce.into(),
+            },
+            ciphertext,
+        ))
+    }
+
+    fn open(&self, aad: &[u8], enc: &PayloadEncryption, ciphertext: &[u8]) -> P2PResult<Vec<u8>> {
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&enc.nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| P2PError::Protocol("payload decryption failed".to_string()))
+    }
+}
+
+/// Associated data binding a payload to its message header, so ciphertext
+/// cannot be moved to another request or turned into a response.
+fn associated_data(kind: u8, id: u64, header: &impl Serialize) -> P2PResult<Vec<u8>> {
+    let mut aad = vec![kind];
+    aad.extend_from_slice(&id.to_be_bytes());
+    aad.extend(bincode::serialize(header).map_err(|e| P2PError::Serialization(e.to_string()))?);
+    Ok(aad)
+}
+
+impl Request {
+    /// Encrypt the payload in place for the peer `cipher` was derived for.
+    pub fn encrypt(&mut self, cipher: &PayloadCipher) -> P2PResult<()> {
+        if self.encryption.is_some() {
+            return Err(P2PError::Protocol("payload already encrypted".to_string()));
+        }
+        let aad = associated_data(b'q', self.id, &self.request_type)?;
+        let (enc, ciphertext) = cipher.seal(&aad, &self.payload)?;
+        self.payload = ciphertext;
+        self.encryption = Some(enc);
+        Ok(())
+    }
+
+    /// Decrypt the payload in place. Fails on plaintext payloads, so a
+    /// receiver that calls this always gets data from the expected peer.
+    pub fn decrypt(&mut self, cipher: &PayloadCipher) -> P2PResult<()> {
+        let enc = self
+            .encryption
+            .ok_or_else(|| P2PError::Protocol("payload is not encrypted".to_string()))?;
+        let aad = associated_data(b'q', self.id, &self.request_type)?;
+        self.payload = cipher.open(&aad, &enc, &self.payload)?;
+        self.encryption = None;
+        Ok(())
+    }
+}
+
+impl Response {
+    /// Encrypt the payload in place for the peer `cipher` was derived for.
+    pub fn encrypt(&mut self, cipher: &PayloadCipher) -> P2PResult<()> {
+        if self.encryption.is_some() {
+            return Err(P2PError::Protocol("payload already encrypted".to_string()));
+        }
+        let aad = associated_data(b'r', self.request_id, &self.status)?;
+        let (enc, ciphertext) = cipher.seal(&aad, &self.payload)?;
+        self.payload = ciphertext;
+        self.encryption = Some(enc);
+        Ok(())
+    }
+
+    /// Decrypt the payload in place. Fails on plaintext payloads.
+    pub fn decrypt(&mut self, cipher: &PayloadCipher) -> P2PResult<()> {
+        let enc = self
+            .encryption
+            .ok_or_else(|| P2PError::Protocol("payload is not encrypted".to_string()))?;
+        let aad = associated_data(b'r', self.request_id, &self.status)?;
+        self.payload = cipher.open(&aad, &enc, &self.payload)?;
+        self.encryption = None;
+        Ok(())
+    }
+}
+
+/// Largest plaintext carried in one [`EncryptedStream`] frame
+const STREAM_FRAME: usize = 64 * 1024;
+
+/// ChaCha20-Poly1305 nonce and tag sizes
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Associated data of stream frame `seq` written by `sender`
+fn frame_aad(sender: &[u8], seq: u64) -> Vec<u8> {
+    let mut aad = vec![b's'];
+    aad.extend_from_slice(&seq.to_be_bytes());
+    aad.extend_from_slice(sender);
+    aad
+}
+
+/// A byte stream encrypted for one remote peer with [`PayloadCipher`]
+///
+/// Writes are sealed in frames of up to 64 KiB: a 4-byte big-endian
+/// length, the nonce, then the ciphertext. A frame goes out once full or on
+/// flush. Its associated data names the sending peer and the frame's
+/// position, so frames cannot be reordered, replayed or reflected back to
+/// their sender.
+pub struct EncryptedStream<S> {
+    inner: S,
+    cipher: PayloadCipher,
+    local: Vec<u8>,
+    remote: Vec<u8>,
+    /// Plaintext written but not yet sealed
+    pending: Vec<u8>,
+    /// Sealed frame going out, and how much of it has
+    outgoing: Vec<u8>,
+    written: usize,
+    sent: u64,
+    /// Raw bytes of the frame coming in
+    incoming: Vec<u8>,
+    /// Opened plaintext, and how much of it has been read
+    plain: Vec<u8>,
+    consumed: usize,
+    received: u64,
+}
+
+impl<S> std::fmt::Debug for EncryptedStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedStream")
+            .field("sent", &self.sent)
+            .field("received", &self.received)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> EncryptedStream<S> {
+    /// Encrypt `inner` between `local` and `remote`, the peers `cipher`
+    /// was derived for
+    pub fn new(inner: S, cipher: PayloadCipher, local: &PeerId, remote: &PeerId) -> Self {
+        Self {
+            inner,
+            cipher,
+            local: local.to_bytes(),
+            remote: remote.to_bytes(),
+            pending: Vec::new(),
+            outgoing: Vec::new(),
+            written: 0,
+            sent: 0,
+            incoming: Vec::new(),
+            plain: Vec::new(),
+            consumed: 0,
+            received: 0,
+        }
+    }
+
+    /// Seal the pending plaintext, unless a frame is still going out
+    fn seal_pending(&mut self) -> std::io::Result<()> {
+        if self.written < self.outgoing.len() || self.pending.is_empty() {
+            return Ok(());
+        }
+        let aad = frame_aad(&self.local, self.sent);
+        let (enc, ciphertext) = self
+            .cipher
+            .seal(&aad, &self.pending)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        self.outgoing.clear();
+        self.outgoing
+            .extend_from_slice(&((NONCE_LEN + ciphertext.len()) as u32).to_be_bytes());
+        self.outgoing.extend_from_slice(&enc.nonce);
+        self.outgoing.extend_from_slice(&ciphertext);
+        self.written = 0;
+        self.pending.clear();
+        self.sent += 1;
+        Ok(())
+    }
+
+    /// Open the complete frame in `incoming`
+    fn open_incoming(&mut self) -> std::io::Result<()> {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&self.incoming[4..4 + NONCE_LEN]);
+        let aad = frame_aad(&self.remote, self.received);
+        self.plain = self
+            .cipher
+            .open(
+                &aad,
+                &PayloadEncryption { nonce },
+                &self.incoming[4 + NONCE_LEN..],
+            )
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+        self.consumed = 0;
+        self.received += 1;
+        self.incoming.clear();
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> EncryptedStream<S> {
+    /// Write out the sealed frame
+    fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.written < self.outgoing.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.outgoing[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for EncryptedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if this.pending.len() >= STREAM_FRAME {
+            ready!(this.poll_outgoing(cx))?;
+            this.seal_pending()?;
+        }
+        let n = buf.len().min(STREAM_FRAME - this.pending.len());
+        this.pending.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_outgoing(cx))?;
+        this.seal_pending()?;
+        ready!(this.poll_outgoing(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for EncryptedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.consumed < this.plain.len() {
+                let n = (this.plain.len() - this.consumed).min(buf.remaining());
+                buf.put_slice(&this.plain[this.consumed..this.consumed + n]);
+                this.consumed += n;
+                return Poll::Ready(Ok(()));
+            }
+            let need = match this.incoming.get(..4) {
+                Some(len) => {
+                    let len = u32::from_be_bytes(len.try_into().expect("4 bytes")) as usize;
+                    if !(NONCE_LEN + TAG_LEN..=NONCE_LEN + STREAM_FRAME + TAG_LEN).contains(&len) {
+                        return Poll::Ready(Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("encrypted frame of {len} bytes"),
+                        )));
+                    }
+                    4 + len
+                }
+                None => 4,
+            };
+            if this.incoming.len() == need && need > 4 {
+                this.open_incoming()?;
+                continue;
+            }
+            let mut chunk = [0u8; 8192];
+            let want = (need - this.incoming.len()).min(chunk.len());
+            let mut read = ReadBuf::new(&mut chunk[..want]);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(if this.incoming.is_empty() {
+                    Ok(())
+                } else {
+                    Err(std::io::ErrorKind::UnexpectedEof.into())
+                });
+            }
+            this.incoming.extend_from_slice(read.filled());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestType, ResponseStatus};
+
+    fn request(payload: &[u8]) -> Request {
+        Request {
+            id: 7,
+            request_type: RequestType::InferenceRequest,
+            payload: payload.to_vec(),
+            encryption: None,
+        }
+    }
+
+    #[test]
+    fn peers_derive_the_same_key_and_outsiders_cannot_read() {
+        let client = Keypair::generate_ed25519();
+        let server = Keypair::generate_ed25519();
+        let to_server = PayloadCipher::for_peer(&client, &server.public().to_peer_id()).unwrap();
+        let to_client = PayloadCipher::new(&server, &client.public()).unwrap();
+
+        let mut req = request(b"The capital of France is");
+        req.encrypt(&to_server).unwrap();
+        assert_ne!(req.payload, b"The capital of France is");
+        assert!(req.encrypt(&to_server).is_err(), "double encryption");
+
+        let relay = Keypair::generate_ed25519();
+        let eavesdropper = PayloadCipher::new(&relay, &client.public()).unwrap();
+        assert!(req.clone().decrypt(&eavesdropper).is_err());
+
+        req.decrypt(&to_client).unwrap();
+        assert_eq!(req.payload, b"The capital of France is");
+        assert!(req.decrypt(&to_client).is_err(), "plaintext is rejected");
+
+        let mut resp = Response {
+            request_id: 7,
+            status: ResponseStatus::Ok,
+            payload: b" Paris".to_vec(),
+            encryption: None,
+        };
+        resp.encrypt(&to_client).unwrap();
+        resp.decrypt(&to_server).unwrap();
+        assert_eq!(resp.payload, b" Paris");
+    }
+
+    #[test]
+    fn ciphertext_is_bound_to_its_header() {
+        let a = Keypair::generate_ed25519();
+        let b = Keypair::generate_ed25519();
+        let ab = PayloadCipher::new(&a, &b.public()).unwrap();
+        let ba = PayloadCipher::new(&b, &a.public()).unwrap();
+
+        let mut req = request(b"activations");
+        req.encrypt(&ab).unwrap();
+
+        let mut moved = req.clone();
+        moved.id = 8;
+        assert!(moved.decrypt(&ba).is_err());
+
+        let mut tampered = req.clone();
+        tampered.payload[0] ^= 1;
+        assert!(tampered.decrypt(&ba).is_err());
+
+        let mut as_response = Response {
+            request_id: req.id,
+            status: ResponseStatus::Ok,
+            payload: req.payload.clone(),
+            encryption: req.encryption,
+        };
+        assert!(as_response.decrypt(&ba).is_err());
+    }
+
+    #[tokio::test]
+    async fn encrypted_stream_round_trips_and_hides_the_payload() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let a = Keypair::generate_ed25519();
+        let b = Keypair::generate_ed25519();
+        let (a_id, b_id) = (a.public().to_peer_id(), b.public().to_peer_id());
+        let message: Vec<u8> = b"gradient "
+            .iter()
+            .copied()
+            .cycle()
+            .take(3 * STREAM_FRAME / 2)
+            .collect();
+
+        // What a relay in the middle sees
+        let (mut near, mut relay) = tokio::io::duplex(1 << 20);
+        let mut writer = EncryptedStream::new(
+            &mut near,
+            PayloadCipher::new(&a, &b.public()).unwrap(),
+            &a_id,
+            &b_id,
+        );
+        writer.write_all(&message).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        drop(near);
+        let mut wire = Vec::new();
+        relay.read_to_end(&mut wire).await.unwrap();
+        assert!(!wire.windows(9).any(|w| w == b"gradient "));
+
+        let mut reader = EncryptedStream::new(
+            wire.as_slice(),
+            PayloadCipher::new(&b, &a.public()).unwrap(),
+            &b_id,
+            &a_id,
+        );
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got, message);
+
+        // Frames reflected back to their sender do not open
+        let mut reflected = EncryptedStream::new(
+            wire.as_slice(),
+            PayloadCipher::new(&a, &b.public()).unwrap(),
+            &a_id,
+            &b_id,
+        );
+        assert!(reflected.read_to_end(&mut Vec::new()).await.is_err());
+
+        let mut tampered = wire.clone();
+        tampered[20] ^= 1;
+        let mut reader = EncryptedStream::new(
+            tampered.as_slice(),
+            PayloadCipher::new(&b, &a.public()).unwrap(),
+            &b_id,
+            &a_id,
+        );
+        assert!(reader.read_to_end(&mut Vec::new()).await.is_err());
+    }
+
+    #[test]
+    fn rejects_peers_without_inline_ed25519_keys() {
+        let local = Keypair::generate_ed25519();
+        let hashed: PeerId = "QmQhRuheeCLEsVD3RsnknM75gPDDqxAb8DhnWgro7KhaJc"
+            .parse()
+            .unwrap();
+        assert!(PayloadCipher::for_peer(&local, &hashed).is_err());
+    }
+}
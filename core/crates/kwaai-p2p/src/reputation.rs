@@ -0,0 +1,579 @@
+//! Local peer reputation — subjective, evidence-based trust scores.
+//!
+//! Each node maintains its own view of peer reliability: success rate,
+//! latency, and observed vs. claimed throughput.  No global scores, no
+//! central authority.
+//!
+//! A [`ReputationStore`] opened with [`ReputationStore::load`] remembers its
+//! file and rewrites it on every [`ReputationStore::record`].  Callers that
+//! pick peers — inference routing, averaging group formation — rank
+//! candidates with [`ReputationStore::rank`], which scores peers without
+//! enough history at a neutral prior so newcomers still get traffic.
+//! [`ReputationStore::rank_attested`] additionally lifts peers whose
+//! capability claims a verifier has co-signed (see [`crate::attestation`]).
+
+use crate::attestation::VerifiedCapabilities;
+use crate::error::{P2PError, P2PResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Observations needed before a peer leaves [`TrustTier::Unknown`].
+pub const MIN_SAMPLES: usize = 5;
+
+/// Selection score assumed for a peer with no observations.
+pub const NEUTRAL_PRIOR: f64 = 0.5;
+
+/// Largest selection-score lift from a fully confirmed capability
+/// attestation. Small enough that it breaks ties between similar peers
+/// without outweighing observed reliability.
+pub const ATTESTATION_BONUS: f64 = 0.1;
+
+// ---------------------------------------------------------------------------
+// Observation (single measurement sample)
+// ---------------------------------------------------------------------------
+
+/// One interaction with a peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerObservation {
+    pub timestamp_secs: u64,
+    pub latency_ms: f64,
+    pub success: bool,
+    /// Tokens/sec measured by the caller for this hop (None when not applicable).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_tps: Option<f64>,
+    /// Tokens/sec the peer claimed in its DHT announcement (None when unknown).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_tps: Option<f64>,
+}
+
+// ---------------------------------------------------------------------------
+// PeerRecord (ring buffer of observations per peer)
+// ---------------------------------------------------------------------------
+
+/// Recent observations of one peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub peer_id_b58: String,
+    pub public_name: String,
+    pub observations: VecDeque<PeerObservation>,
+    pub first_seen_secs: u64,
+    pub last_seen_secs: u64,
+}
+
+impl PeerRecord {
+    fn new(peer_id_b58: &str, public_name: &str, now: u64) -> Self {
+        Self {
+            peer_id_b58: peer_id_b58.to_string(),
+            public_name: public_name.to_string(),
+            observations: VecDeque::new(),
+            first_seen_secs: now,
+            last_seen_secs: now,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TrustTier
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TrustTier {
+    Unknown,
+    Known,
+    Verified,
+    Trusted,
+}
+
+impl TrustTier {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrustTier::Unknown => "UNKNOWN",
+            TrustTier::Known => "KNOWN",
+            TrustTier::Verified => "VERIFIED",
+            TrustTier::Trusted => "TRUSTED",
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TrustScore
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct TrustScore {
+    pub score: f64,
+    pub tier: TrustTier,
+    pub sample_count: usize,
+    pub avg_latency_ms: f64,
+    pub availability: f64,
+    pub throughput_ratio: Option<f64>,
+}
+
+impl TrustScore {
+    fn unknown() -> Self {
+        Self {
+            score: 0.0,
+            tier: TrustTier::Unknown,
+            sample_count: 0,
+            avg_latency_ms: 0.0,
+            availability: 0.0,
+            throughput_ratio: None,
+        }
+    }
+
+    /// Score used to rank peers for selection.
+    ///
+    /// Blends the measured score with [`NEUTRAL_PRIOR`] until the peer has
+    /// [`MIN_SAMPLES`] observations, so one early failure does not starve a
+    /// new peer and one lucky call does not make it preferred.
+    pub fn selection_score(&self) -> f64 {
+        let n = self.sample_count.min(MIN_SAMPLES) as f64;
+        let m = MIN_SAMPLES as f64;
+        (n * self.score + (m - n) * NEUTRAL_PRIOR) / m
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ReputationStore
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReputationStore {
+    peers: HashMap<String, PeerRecord>,
+    #[serde(default = "default_max_obs")]
+    max_observations: usize,
+    /// File this store was loaded from and is saved back to.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+fn default_max_obs() -> usize {
+    100
+}
+
+impl Default for ReputationStore {
+    fn default() -> Self {
+        Self {
+            peers: HashMap::new(),
+            max_observations: default_max_obs(),
+            path: None,
+        }
+    }
+}
+
+impl ReputationStore {
+    /// In-memory store keeping at most `max` observations per peer.
+    pub fn with_max_observations(max: usize) -> Self {
+        Self {
+            max_observations: max.max(1),
+            ..Self::default()
+        }
+    }
+
+    // ── Persistence ──────────────────────────────────────────────────────────
+
+    /// Load the store from `path`, starting fresh if the file is missing or
+    /// unreadable.  Later [`record`](Self::record) calls save back to `path`.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut store = if path.exists() {
+            match std::fs::read_to_string(&path)
+                .map_err(P2PError::from)
+                .and_then(|s| {
+                    serde_json::from_str::<Self>(&s)
+                        .map_err(|e| P2PError::Serialization(e.to_string()))
+                }) {
+                Ok(store) => store,
+                Err(e) => {
+                    tracing::warn!("Could not load reputation store: {e} — starting fresh");
+                    Self::default()
+                }
+            }
+        } else {
+            Self::default()
+        };
+        store.path = Some(path);
+        store
+    }
+
+    /// File backing this store, if it was opened with [`load`](Self::load).
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Write the store to its file.  No-op for an in-memory store.
+    pub fn save(&self) -> P2PResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| P2PError::Serialization(e.to_string()))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    // ── Mutation ─────────────────────────────────────────────────────────────
+
+    /// Record an observation and save the store.
+    pub fn record(&mut self, peer_id_b58: &str, public_name: &str, obs: PeerObservation) {
+        self.observe(peer_id_b58, public_name, obs);
+        if let Err(e) = self.save() {
+            tracing::debug!("Could not save reputation store: {e}");
+        }
+    }
+
+    /// Record an observation without saving.
+    pub fn observe(&mut self, peer_id_b58: &str, public_name: &str, obs: PeerObservation) {
+        let now = obs.timestamp_secs;
+        let record = self
+            .peers
+            .entry(peer_id_b58.to_string())
+            .or_insert_with(|| PeerRecord::new(peer_id_b58, public_name, now));
+
+        // Update display name if it changed.
+        if !public_name.is_empty() {
+            record.public_name = public_name.to_string();
+        }
+        record.last_seen_secs = now;
+
+        // Cap ring buffer at max_observations.
+        while record.observations.len() >= self.max_observations {
+            record.observations.pop_front();
+        }
+        record.observations.push_back(obs);
+    }
+
+    /// Forget a peer and save the store.  Returns whether it was known.
+    pub fn reset(&mut self, peer_id_b58: &str) -> bool {
+        let removed = self.peers.remove(peer_id_b58).is_some();
+        if removed {
+            let _ = self.save();
+        }
+        removed
+    }
+
+    // ── Scoring ──────────────────────────────────────────────────────────────
+
+    /// Compute a local trust score for a peer.
+    ///
+    /// Scoring formula (from reputation.md):
+    /// ```text
+    /// s_availability  = successes / total
+    /// s_throughput    = (avg_observed_tps / claimed_tps).clamp(0, 1)   (0 when no tps data)
+    /// s_latency       = exp(−avg_latency_ms / 500)
+    /// score_metrics   = 0.5 × s_avail + 0.3 × s_tput + 0.2 × s_lat
+    /// ```
+    pub fn score(&self, peer_id_b58: &str) -> TrustScore {
+        let Some(record) = self.peers.get(peer_id_b58) else {
+            return TrustScore::unknown();
+        };
+
+        let n = record.observations.len();
+        if n == 0 {
+            return TrustScore::unknown();
+        }
+
+        let successes = record.observations.iter().filter(|o| o.success).count() as f64;
+        let s_availability = successes / n as f64;
+
+        let total_latency: f64 = record.observations.iter().map(|o| o.latency_ms).sum();
+        let avg_latency_ms = total_latency / n as f64;
+        let s_latency = (-avg_latency_ms / 500.0_f64).exp();
+
+        // Throughput ratio: use observations that have both observed and claimed tps.
+        let tps_pairs: Vec<(f64, f64)> = record
+            .observations
+            .iter()
+            .filter_map(|o| o.observed_tps.zip(o.claimed_tps))
+            .collect();
+
+        let throughput_ratio = if tps_pairs.is_empty() {
+            None
+        } else {
+            let avg_observed: f64 =
+                tps_pairs.iter().map(|(o, _)| o).sum::<f64>() / tps_pairs.len() as f64;
+            let avg_claimed: f64 =
+                tps_pairs.iter().map(|(_, c)| c).sum::<f64>() / tps_pairs.len() as f64;
+            Some((avg_observed / avg_claimed).clamp(0.0, 1.0))
+        };
+        let s_throughput = throughput_ratio.unwrap_or(0.0);
+
+        let score = 0.5 * s_availability + 0.3 * s_throughput + 0.2 * s_latency;
+
+        let tier = if n < MIN_SAMPLES {
+            TrustTier::Unknown
+        } else if score < 0.40 {
+            TrustTier::Known
+        } else if score < 0.70 {
+            TrustTier::Verified
+        } else {
+            TrustTier::Trusted
+        };
+
+        TrustScore {
+            score,
+            tier,
+            sample_count: n,
+            avg_latency_ms,
+            availability: s_availability,
+            throughput_ratio,
+        }
+    }
+
+    /// [`TrustScore::selection_score`] for a peer.
+    pub fn selection_score(&self, peer_id_b58: &str) -> f64 {
+        self.score(peer_id_b58).selection_score()
+    }
+
+    // ── Queries ──────────────────────────────────────────────────────────────
+
+    /// Order `candidates` best first by selection score.  Ties keep their
+    /// input order.
+    pub fn rank<T>(&self, candidates: &mut [T], peer_id_b58: impl Fn(&T) -> &str) {
+        candidates.sort_by(|a, b| {
+            self.selection_score(peer_id_b58(b))
+                .total_cmp(&self.selection_score(peer_id_b58(a)))
+        });
+    }
+
+    /// Selection score lifted by a verified capability attestation.
+    ///
+    /// The lift is [`ATTESTATION_BONUS`] scaled by how much of the compute
+    /// claim a verifier confirmed; self-signed-only attestations and peers
+    /// without one score as in [`selection_score`](Self::selection_score).
+    pub fn attested_selection_score(
+        &self,
+        peer_id_b58: &str,
+        attestation: Option<&VerifiedCapabilities>,
+    ) -> f64 {
+        let bonus = attestation
+            .and_then(VerifiedCapabilities::confirmation_ratio)
+            .map_or(0.0, |r| ATTESTATION_BONUS * r.clamp(0.0, 1.0));
+        (self.selection_score(peer_id_b58) + bonus).min(1.0)
+    }
+
+    /// Like [`rank`](Self::rank), scoring with
+    /// [`attested_selection_score`](Self::attested_selection_score).
+    pub fn rank_attested<T>(
+        &self,
+        candidates: &mut [T],
+        peer_id_b58: impl Fn(&T) -> &str,
+        attestation: impl Fn(&T) -> Option<&VerifiedCapabilities>,
+    ) {
+        candidates.sort_by(|a, b| {
+            self.attested_selection_score(peer_id_b58(b), attestation(b))
+                .total_cmp(&self.attested_selection_score(peer_id_b58(a), attestation(a)))
+        });
+    }
+
+    /// All known peers with their computed trust score, sorted by score descending.
+    pub fn all_scored(&self) -> Vec<(String, TrustScore)> {
+        let mut scored: Vec<(String, TrustScore)> = self
+            .peers
+            .keys()
+            .map(|id| (id.clone(), self.score(id)))
+            .collect();
+        scored.sort_by(|a, b| {
+            b.1.score
+                .partial_cmp(&a.1.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        scored
+    }
+
+    pub fn peer_record(&self, peer_id_b58: &str) -> Option<&PeerRecord> {
+        self.peers.get(peer_id_b58)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Timestamp helper
+// ---------------------------------------------------------------------------
+
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(latency_ms: f64, success: bool) -> PeerObservation {
+        PeerObservation {
+            timestamp_secs: 1_000_000,
+            latency_ms,
+            success,
+            observed_tps: None,
+            claimed_tps: None,
+        }
+    }
+
+    #[test]
+    fn unknown_with_no_data() {
+        let store = ReputationStore::default();
+        let s = store.score("nonexistent");
+        assert_eq!(s.tier, TrustTier::Unknown);
+        assert_eq!(s.sample_count, 0);
+    }
+
+    #[test]
+    fn unknown_with_few_samples() {
+        let mut store = ReputationStore::default();
+        for _ in 0..4 {
+            store.observe("peer1", "Alice", obs(10.0, true));
+        }
+        let s = store.score("peer1");
+        assert_eq!(s.tier, TrustTier::Unknown);
+        assert_eq!(s.sample_count, 4);
+    }
+
+    #[test]
+    fn trusted_with_good_data() {
+        let mut store = ReputationStore::default();
+        for _ in 0..10 {
+            store.observe("peer2", "Bob", obs(50.0, true));
+        }
+        let s = store.score("peer2");
+        // availability=1.0, latency=exp(-0.1)≈0.905, tput=0 → score≈0.5+0+0.181≈0.681
+        assert!(s.score >= 0.60, "expected ≥ 0.60, got {}", s.score);
+        assert!(s.tier >= TrustTier::Verified);
+    }
+
+    #[test]
+    fn known_with_high_latency_and_failures() {
+        let mut store = ReputationStore::default();
+        for i in 0..10 {
+            store.observe("peer3", "Eve", obs(2000.0, i % 2 == 0));
+        }
+        let s = store.score("peer3");
+        // availability≈0.5, latency=exp(-4)≈0.018, tput=0 → score≈0.25+0+0.0036≈0.25
+        assert!(s.score < 0.40, "expected < 0.40, got {}", s.score);
+        assert_eq!(s.tier, TrustTier::Known);
+    }
+
+    #[test]
+    fn throughput_ratio_recorded() {
+        let mut store = ReputationStore::default();
+        for _ in 0..10 {
+            store.observe(
+                "peer4",
+                "Test",
+                PeerObservation {
+                    timestamp_secs: 1_000_000,
+                    latency_ms: 50.0,
+                    success: true,
+                    observed_tps: Some(6.0),
+                    claimed_tps: Some(10.0),
+                },
+            );
+        }
+        let s = store.score("peer4");
+        assert!((s.throughput_ratio.unwrap() - 0.6).abs() < 0.01);
+    }
+
+    #[test]
+    fn ring_buffer_capped() {
+        let mut store = ReputationStore::with_max_observations(5);
+        for i in 0..10u64 {
+            store.observe(
+                "peer5",
+                "Capped",
+                PeerObservation {
+                    timestamp_secs: i,
+                    latency_ms: 10.0,
+                    success: true,
+                    observed_tps: None,
+                    claimed_tps: None,
+                },
+            );
+        }
+        let record = store.peer_record("peer5").unwrap();
+        assert_eq!(record.observations.len(), 5);
+        // Should contain the 5 most recent (timestamps 5..9)
+        assert_eq!(record.observations.front().unwrap().timestamp_secs, 5);
+    }
+
+    #[test]
+    fn reset_clears_peer() {
+        let mut store = ReputationStore::default();
+        store.observe("peer6", "Clear", obs(10.0, true));
+        assert!(store.peer_record("peer6").is_some());
+        store.reset("peer6");
+        assert!(store.peer_record("peer6").is_none());
+    }
+
+    #[test]
+    fn rank_prefers_reliable_peers_over_newcomers_over_failing_ones() {
+        let mut store = ReputationStore::default();
+        for i in 0..10 {
+            store.observe("good", "", obs(20.0, true));
+            store.observe("flaky", "", obs(1500.0, i % 3 == 0));
+        }
+        let mut peers = vec!["flaky", "new", "good"];
+        store.rank(&mut peers, |p| p);
+        assert_eq!(peers, ["good", "new", "flaky"]);
+    }
+
+    #[test]
+    fn cosigned_attestation_lifts_rank() {
+        use crate::attestation::{BenchmarkResult, CapabilityAttestation};
+        use crate::NodeCapabilities;
+        use libp2p::identity::Keypair;
+
+        let subject = Keypair::generate_ed25519();
+        let verifier = Keypair::generate_ed25519();
+        let mut caps = NodeCapabilities::new(String::new());
+        caps.compute_power = 10.0;
+        let mut att =
+            CapabilityAttestation::issue(&subject, caps, std::time::Duration::from_secs(60))
+                .unwrap();
+        att.cosign(
+            &verifier,
+            BenchmarkResult {
+                compute_power: 10.0,
+                available_memory: 0,
+                measured_at: now_secs(),
+            },
+        )
+        .unwrap();
+        let verified = att.verify().unwrap();
+
+        // Two newcomers: only the attested one gets the lift.
+        let store = ReputationStore::default();
+        let mut peers = vec![("plain", None), ("attested", Some(&verified))];
+        store.rank_attested(&mut peers, |p| p.0, |p| p.1);
+        assert_eq!(peers[0].0, "attested");
+        assert!(
+            (store.attested_selection_score("x", Some(&verified))
+                - (NEUTRAL_PRIOR + ATTESTATION_BONUS))
+                .abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = std::env::temp_dir().join(format!("kwaai-rep-{}", std::process::id()));
+        let path = dir.join("reputation.json");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = ReputationStore::load(&path);
+        store.record("peer7", "Persisted", obs(30.0, true));
+
+        let reloaded = ReputationStore::load(&path);
+        assert_eq!(reloaded.score("peer7").sample_count, 1);
+        assert_eq!(reloaded.path(), Some(path.as_path()));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
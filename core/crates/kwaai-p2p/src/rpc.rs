@@ -3,10 +3,16 @@
 //! Implements the Hivemind/Petals RPC protocol using libp2p request-response.
 //! This allows KwaaiNet nodes to respond to health monitor queries and other
 //! Hivemind protocol requests.
+//!
+//! KwaaiNet peers additionally speak [`HIVEMIND_ZSTD_PROTOCOL`], the same RPC
+//! with each message body wrapped in a zstd payload frame. Both protocols are
+//! offered on every stream, compressed first, so two KwaaiNet nodes settle on
+//! compression while Python Hivemind peers keep negotiating the plain one.
 
 use crate::hivemind::{
     encode_error, encode_message, ExpertInfo, ExpertUID, ServerInfo, HIVEMIND_PROTOCOL,
 };
+use crate::payload::{self, PayloadCodec};
 use async_trait::async_trait;
 use futures::prelude::*;
 use libp2p::{
@@ -21,14 +27,59 @@ use tracing::{debug, error, info};
 // Protocol Codec
 // =============================================================================
 
+/// Hivemind RPC with zstd-framed message bodies
+pub const HIVEMIND_ZSTD_PROTOCOL: &str = "/hivemind/0.0.0/rpc+zstd";
+
 /// Hivemind RPC protocol codec
 ///
 /// Implements the Hivemind message framing:
 /// - 8 bytes: length (big-endian)
 /// - 1 byte: marker (0x00=message, 0x01=error)
 /// - N bytes: protobuf payload
-#[derive(Debug, Clone, Default)]
-pub struct HivemindCodec;
+///
+/// On [`HIVEMIND_ZSTD_PROTOCOL`] the marker and payload are replaced by a
+/// [`payload`] frame and the length prefix counts the frame.
+#[derive(Debug, Clone)]
+pub struct HivemindCodec {
+    compression: PayloadCodec,
+}
+
+impl HivemindCodec {
+    /// Codec compressing negotiated streams with `compression`
+    pub fn new(compression: PayloadCodec) -> Self {
+        Self { compression }
+    }
+
+    /// Wrap the body of a length-prefixed message in a payload frame
+    fn compress(&self, message: Vec<u8>) -> io::Result<Vec<u8>> {
+        let frame = self
+            .compression
+            .encode(&message[8..])
+            .map_err(io::Error::other)?;
+        let mut out = Vec::with_capacity(8 + frame.len());
+        out.extend_from_slice(&(frame.len() as u64).to_be_bytes());
+        out.extend_from_slice(&frame);
+        Ok(out)
+    }
+}
+
+impl Default for HivemindCodec {
+    fn default() -> Self {
+        Self::new(PayloadCodec::zstd())
+    }
+}
+
+fn is_compressed(protocol: &StreamProtocol) -> bool {
+    protocol.as_ref() == HIVEMIND_ZSTD_PROTOCOL
+}
+
+/// Unwrap a message body read from `protocol`
+fn decompress(protocol: &StreamProtocol, buf: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !is_compressed(protocol) {
+        return Ok(buf);
+    }
+    payload::decode(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
 
 #[async_trait]
 impl Codec for HivemindCodec {
@@ -38,7 +89,7 @@ impl Codec for HivemindCodec {
 
     async fn read_request<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Request>
     where
@@ -60,6 +111,7 @@ impl Codec for HivemindCodec {
         // Read marker + payload
         let mut buf = vec![0u8; len];
         io.read_exact(&mut buf).await?;
+        let buf = decompress(protocol, buf)?;
 
         if buf.is_empty() {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty message"));
@@ -91,7 +143,7 @@ impl Codec for HivemindCodec {
 
     async fn read_response<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response>
     where
@@ -112,6 +164,7 @@ impl Codec for HivemindCodec {
         // Read marker + payload
         let mut buf = vec![0u8; len];
         io.read_exact(&mut buf).await?;
+        let buf = decompress(protocol, buf)?;
 
         if buf.is_empty() {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty response"));
@@ -143,7 +196,7 @@ impl Codec for HivemindCodec {
 
     async fn write_request<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         req: Self::Request,
     ) -> io::Result<()>
@@ -153,6 +206,11 @@ impl Codec for HivemindCodec {
         let data = match req {
             RpcRequest::Info(expert_uid) => encode_message(&expert_uid),
         };
+        let data = if is_compressed(protocol) {
+            self.compress(data)?
+        } else {
+            data
+        };
 
         io.write_all(&data).await?;
         io.close().await?;
@@ -161,7 +219,7 @@ impl Codec for HivemindCodec {
 
     async fn write_response<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         res: Self::Response,
     ) -> io::Result<()>
@@ -172,6 +230,11 @@ impl Codec for HivemindCodec {
             RpcResponse::Info(expert_info) => encode_message(&expert_info),
             RpcResponse::Error(error_msg) => encode_error(&error_msg),
         };
+        let data = if is_compressed(protocol) {
+            self.compress(data)?
+        } else {
+            data
+        };
 
         io.write_all(&data).await?;
         io.close().await?;
@@ -252,17 +315,23 @@ impl RpcHandler {
 // =============================================================================
 
 /// Create a Hivemind RPC protocol configuration
+///
+/// Offers [`HIVEMIND_ZSTD_PROTOCOL`] ahead of the plain protocol; the
+/// returned protocol is the preferred one.
 pub fn create_hivemind_protocol() -> (request_response::Behaviour<HivemindCodec>, StreamProtocol) {
-    let protocol = StreamProtocol::new(HIVEMIND_PROTOCOL);
-    let codec = HivemindCodec;
+    let compressed = StreamProtocol::new(HIVEMIND_ZSTD_PROTOCOL);
+    let plain = StreamProtocol::new(HIVEMIND_PROTOCOL);
 
     let behaviour = request_response::Behaviour::with_codec(
-        codec,
-        [(protocol.clone(), ProtocolSupport::Full)],
+        HivemindCodec::default(),
+        [
+            (compressed.clone(), ProtocolSupport::Full),
+            (plain, ProtocolSupport::Full),
+        ],
         request_response::Config::default(),
     );
 
-    (behaviour, protocol)
+    (behaviour, compressed)
 }
 
 #[cfg(test)]
@@ -290,4 +359,38 @@ mod tests {
             RpcResponse::Error(e) => panic!("Expected Info response, got Error: {}", e),
         }
     }
+
+    #[tokio::test]
+    async fn compressed_protocol_round_trips_and_shrinks() {
+        let info = ServerInfo::new("zstd-node").with_span(0, 80);
+        let response = RpcResponse::Info(info.to_expert_info().unwrap());
+        let mut codec = HivemindCodec::default();
+
+        let mut sizes = Vec::new();
+        for protocol in [HIVEMIND_PROTOCOL, HIVEMIND_ZSTD_PROTOCOL] {
+            let protocol = StreamProtocol::new(protocol);
+            let mut wire = futures::io::Cursor::new(Vec::new());
+            codec
+                .write_response(&protocol, &mut wire, response.clone())
+                .await
+                .unwrap();
+            let bytes = wire.into_inner();
+            sizes.push(bytes.len());
+
+            let mut reader = futures::io::Cursor::new(bytes);
+            match codec.read_response(&protocol, &mut reader).await.unwrap() {
+                RpcResponse::Info(decoded) => {
+                    let decoded = ServerInfo::from_msgpack(&decoded.serialized_info).unwrap();
+                    assert_eq!(decoded.public_name, Some("zstd-node".to_string()));
+                }
+                RpcResponse::Error(e) => panic!("Expected Info response, got Error: {}", e),
+            }
+        }
+        assert!(
+            sizes[1] < sizes[0],
+            "compressed {} vs plain {}",
+            sizes[1],
+            sizes[0]
+        );
+    }
 }
@@ -0,0 +1,829 @@
+//! Aggregated view of the servers hosting a model, as shown on map.kwaai.ai
+//!
+//! Block servers announce a server-info record under the DHT key of every
+//! block they serve (`{dht_prefix}.{block}`). [`ModelStateBuilder`] decodes
+//! the values found under those keys and folds them into a [`ModelReport`]:
+//! one row per server with the widest span it announced, how many online
+//! servers cover each block, and an overall [`ModelHealth`]. [`SwarmState`]
+//! gathers the reports of several models.
+//!
+//! Querying the DHT is left to the caller, which knows how it reaches the
+//! network; [`block_key`] gives the keys to look up.
+//!
+//! Servers may sign their record with their identity key
+//! ([`sign_server_info`]). Decoding checks the signature against the peer
+//! ID the record claims, so a report can tell authentic servers from
+//! entries announced under someone else's peer ID ([`SignatureStatus`]).
+//! The signed payload carries its own validity window, and signed records
+//! past it are dropped rather than replayed.
+//!
+//! ```text
+//! blocks   0 ──────── 16 ──────── 32
+//! server A ███████████
+//! server B            ████████████
+//! coverage 1 1 1 … 1 1 1 1 … 1 1 1    → healthy
+//! ```
+
+use crate::attestation::CLOCK_SKEW;
+use crate::geo::PeerIpInfo;
+use crate::reputation::now_secs;
+use crate::{normalize_dtype, AcceleratorInfo};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use rmpv::Value;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// DHT key of block `block` of the model announced under `dht_prefix`:
+/// SHA1 of the msgpack-encoded `"{dht_prefix}.{block}"`, as Hivemind's
+/// `DHTID.generate()` computes it
+pub fn block_key(dht_prefix: &str, block: usize) -> Vec<u8> {
+    let raw = format!("{}.{}", dht_prefix, block);
+    let packed = rmp_serde::to_vec(&raw).expect("msgpack string");
+    Sha1::new().chain_update(&packed).finalize().to_vec()
+}
+
+/// Petals server state, as announced in a server-info record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    Offline,
+    Joining,
+    Online,
+    Unknown,
+}
+
+impl ServerState {
+    /// State for the numeric code used on the wire
+    pub fn from_code(code: i64) -> Self {
+        match code {
+            0 => ServerState::Offline,
+            1 => ServerState::Joining,
+            2 => ServerState::Online,
+            _ => ServerState::Unknown,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Signed announcements
+// ---------------------------------------------------------------------------
+
+/// Server-info field holding the announcement signature
+pub const SIGNATURE_FIELD: &str = "signature";
+
+/// Server-info field holding the signer's protobuf-encoded public key, for
+/// peer IDs that do not embed it (RSA)
+pub const PUBLIC_KEY_FIELD: &str = "public_key";
+
+/// Signed server-info field: Unix seconds the signature was made
+pub const ISSUED_AT_FIELD: &str = "issued_at";
+
+/// Signed server-info field: Unix seconds after which the signature no
+/// longer vouches for the record
+pub const EXPIRES_AT_FIELD: &str = "expires_at";
+
+/// Domain separator of the signed payload
+const SIGNATURE_DOMAIN: &[u8] = b"kwaainet-server-info-v1\n";
+
+/// Whether a server record carried a valid signature
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// No signature — legacy or Python servers
+    #[default]
+    Unsigned,
+    /// Signed by the key of the peer ID the record claims
+    Valid,
+    /// Signed, but not by that peer, or the record was altered
+    Invalid,
+    /// Validly signed, but outside its `issued_at..expires_at` window
+    Expired,
+}
+
+/// Fields map of a server-info tuple `[state, throughput, {fields}]`
+fn fields_mut(tuple: &mut Value) -> Option<&mut Vec<(Value, Value)>> {
+    match tuple {
+        Value::Array(items) => match items.get_mut(2)? {
+            Value::Map(fields) => Some(fields),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Bytes a server signs: the domain, its peer ID and the msgpack encoding
+/// of `[state, throughput, {fields}]` without the signature fields
+fn signing_bytes(peer_id: &str, tuple: &Value) -> Option<Vec<u8>> {
+    let mut unsigned = tuple.clone();
+    if let Some(fields) = fields_mut(&mut unsigned) {
+        fields
+            .retain(|(k, _)| !matches!(k.as_str(), Some(SIGNATURE_FIELD) | Some(PUBLIC_KEY_FIELD)));
+    }
+    let mut out = SIGNATURE_DOMAIN.to_vec();
+    out.extend_from_slice(peer_id.as_bytes());
+    out.push(b'\n');
+    rmpv::encode::write_value(&mut out, &unsigned).ok()?;
+    Some(out)
+}
+
+/// Public key embedded in `peer_id` (identity multihash, as for Ed25519)
+fn inline_public_key(peer_id: &PeerId) -> Option<PublicKey> {
+    let multihash = peer_id.as_ref();
+    if multihash.code() != 0 {
+        return None;
+    }
+    PublicKey::try_decode_protobuf(multihash.digest()).ok()
+}
+
+/// Sign a server-info tuple `[state, throughput, {fields}]` in place with
+/// `keypair`, valid for `ttl`: adds [`ISSUED_AT_FIELD`] and
+/// [`EXPIRES_AT_FIELD`], which the signature covers, then
+/// [`SIGNATURE_FIELD`] (and [`PUBLIC_KEY_FIELD`] when the peer ID does not
+/// embed the key) to its fields
+///
+/// Returns `false`, leaving the tuple unsigned, when it has no fields map
+/// or the key cannot sign.
+pub fn sign_server_info(tuple: &mut Value, keypair: &Keypair, ttl: Duration) -> bool {
+    let public = keypair.public();
+    let peer_id = public.to_peer_id();
+    let mut timed = tuple.clone();
+    let Some(fields) = fields_mut(&mut timed) else {
+        return false;
+    };
+    let issued_at = now_secs();
+    fields.retain(|(k, _)| !matches!(k.as_str(), Some(ISSUED_AT_FIELD) | Some(EXPIRES_AT_FIELD)));
+    fields.push((Value::from(ISSUED_AT_FIELD), Value::from(issued_at)));
+    fields.push((
+        Value::from(EXPIRES_AT_FIELD),
+        Value::from(issued_at.saturating_add(ttl.as_secs())),
+    ));
+    let Some(payload) = signing_bytes(&peer_id.to_base58(), &timed) else {
+        return false;
+    };
+    let Ok(signature) = keypair.sign(&payload) else {
+        return false;
+    };
+    *tuple = timed;
+    let Some(fields) = fields_mut(tuple) else {
+        return false;
+    };
+    if inline_public_key(&peer_id).is_none() {
+        fields.push((
+            Value::from(PUBLIC_KEY_FIELD),
+            Value::Binary(public.encode_protobuf()),
+        ));
+    }
+    fields.push((Value::from(SIGNATURE_FIELD), Value::Binary(signature)));
+    true
+}
+
+/// Check the signature of a server-info tuple claiming to come from
+/// `peer_id` (base58)
+pub fn verify_server_info(tuple: &Value, peer_id: &str) -> SignatureStatus {
+    verify_server_info_at(tuple, peer_id, now_secs())
+}
+
+/// As [`verify_server_info`], at an explicit Unix time. A signature without
+/// a validity window is [`SignatureStatus::Invalid`].
+pub fn verify_server_info_at(tuple: &Value, peer_id: &str, now: u64) -> SignatureStatus {
+    let Some(fields) = tuple
+        .as_array()
+        .and_then(|a| a.get(2))
+        .and_then(Value::as_map)
+    else {
+        return SignatureStatus::Unsigned;
+    };
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+    };
+    let get = |key: &str| field(key).and_then(Value::as_slice);
+    let Some(signature) = get(SIGNATURE_FIELD) else {
+        return SignatureStatus::Unsigned;
+    };
+    let Ok(claimed) = peer_id.parse::<PeerId>() else {
+        return SignatureStatus::Invalid;
+    };
+    let key = match get(PUBLIC_KEY_FIELD) {
+        Some(bytes) => PublicKey::try_decode_protobuf(bytes)
+            .ok()
+            .filter(|key| key.to_peer_id() == claimed),
+        None => inline_public_key(&claimed),
+    };
+    let valid = match (key, signing_bytes(peer_id, tuple)) {
+        (Some(key), Some(payload)) => key.verify(&payload, signature),
+        _ => false,
+    };
+    let issued_at = field(ISSUED_AT_FIELD).and_then(Value::as_u64);
+    let expires_at = field(EXPIRES_AT_FIELD).and_then(Value::as_u64);
+    match (valid, issued_at, expires_at) {
+        (true, Some(issued_at), Some(expires_at)) => {
+            let skew = CLOCK_SKEW.as_secs();
+            if issued_at > now.saturating_add(skew) || now > expires_at.saturating_add(skew) {
+                SignatureStatus::Expired
+            } else {
+                SignatureStatus::Valid
+            }
+        }
+        _ => SignatureStatus::Invalid,
+    }
+}
+
+/// One server's announcement, decoded from a DHT value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerRecord {
+    /// Base58 peer ID; empty when the record did not carry one
+    pub peer_id: String,
+    /// Whether the record was signed by that peer
+    #[serde(default)]
+    pub signature: SignatureStatus,
+    pub state: ServerState,
+    /// Tokens/sec claimed by the server
+    pub throughput: f64,
+    /// First block served
+    pub start_block: usize,
+    /// One past the last block served
+    pub end_block: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_rps: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_rps: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inference_rps: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub torch_dtype: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quant_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub using_relay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_tokens_left: Option<u64>,
+    /// Accelerator the server announced (KwaaiNet servers only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accelerator: Option<AcceleratorInfo>,
+}
+
+impl ServerRecord {
+    /// Decode a regular server-info value
+    ///
+    /// Accepts the Petals `Ext(64, [state, throughput, {fields}])` encoding
+    /// and the older KwaaiNet flat array `[state, throughput, start_block,
+    /// end_block, public_name, …]`.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        Self::decode_as(bytes, None)
+    }
+
+    /// Decode a value announced under `subkey_peer` (a dictionary subkey),
+    /// which takes precedence over the peer ID inside the record. Signed
+    /// records past their validity window are rejected.
+    fn decode_as(bytes: &[u8], subkey_peer: Option<String>) -> Option<Self> {
+        match rmpv::decode::read_value(&mut &bytes[..]).ok()? {
+            Value::Ext(_, data) => {
+                let inner = rmpv::decode::read_value(&mut &data[..]).ok()?;
+                let arr = inner.as_array()?;
+                if arr.len() < 3 {
+                    return None;
+                }
+                let fields = arr[2].as_map()?;
+                let get = |key: &str| {
+                    fields
+                        .iter()
+                        .find(|(k, _)| k.as_str() == Some(key))
+                        .map(|(_, v)| v)
+                };
+                let get_str = |key: &str| get(key).and_then(|v| v.as_str()).map(String::from);
+                let get_f64 = |key: &str| get(key).and_then(|v| v.as_f64());
+                let peer_id = subkey_peer
+                    .or_else(|| get_str("peer_id"))
+                    .unwrap_or_default();
+                let signature = verify_server_info(&inner, &peer_id);
+                if signature == SignatureStatus::Expired {
+                    return None;
+                }
+                Some(Self {
+                    signature,
+                    peer_id,
+                    state: ServerState::from_code(arr[0].as_i64().unwrap_or(0)),
+                    throughput: arr[1].as_f64().unwrap_or(0.0),
+                    start_block: get("start_block").and_then(|v| v.as_u64())? as usize,
+                    end_block: get("end_block").and_then(|v| v.as_u64())? as usize,
+                    public_name: get_str("public_name"),
+                    version: get_str("version"),
+                    network_rps: get_f64("network_rps"),
+                    forward_rps: get_f64("forward_rps"),
+                    inference_rps: get_f64("inference_rps"),
+                    torch_dtype: get_str("torch_dtype"),
+                    quant_type: get_str("quant_type"),
+                    using_relay: get("using_relay").and_then(|v| v.as_bool()),
+                    cache_tokens_left: get("cache_tokens_left").and_then(|v| v.as_u64()),
+                    accelerator: get("accelerator").and_then(AcceleratorInfo::from_msgpack_value),
+                })
+            }
+            Value::Array(arr) if arr.len() >= 10 => {
+                let get_str = |i: usize| arr.get(i).and_then(|v| v.as_str()).map(String::from);
+                Some(Self {
+                    peer_id: subkey_peer.unwrap_or_default(),
+                    signature: SignatureStatus::Unsigned,
+                    state: ServerState::from_code(arr[0].as_i64().unwrap_or(0)),
+                    throughput: arr[1].as_f64().unwrap_or(0.0),
+                    start_block: arr[2].as_u64()? as usize,
+                    end_block: arr[3].as_u64()? as usize,
+                    public_name: get_str(4),
+                    version: get_str(5),
+                    network_rps: arr[6].as_f64(),
+                    forward_rps: arr[7].as_f64(),
+                    inference_rps: arr[8].as_f64(),
+                    torch_dtype: get_str(9),
+                    quant_type: None,
+                    using_relay: arr.get(11).and_then(|v| v.as_bool()),
+                    cache_tokens_left: arr.get(12).and_then(|v| v.as_u64()),
+                    accelerator: None,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Decode a dictionary value, `Ext(80, [expiry, created, [[subkey,
+    /// value, expiry], …]])`, whose subkeys are the servers' peer IDs
+    pub fn decode_dictionary(bytes: &[u8]) -> Vec<Self> {
+        let Ok(Value::Ext(_, data)) = rmpv::decode::read_value(&mut &bytes[..]) else {
+            return Vec::new();
+        };
+        let Ok(inner) = rmpv::decode::read_value(&mut &data[..]) else {
+            return Vec::new();
+        };
+        let Some(entries) = inner
+            .as_array()
+            .and_then(|a| a.get(2))
+            .and_then(|e| e.as_array())
+        else {
+            return Vec::new();
+        };
+
+        entries
+            .iter()
+            .filter_map(|entry| {
+                let arr = entry.as_array()?;
+                // The subkey is the peer ID, either as a string or as the
+                // msgpack encoding of one.
+                let peer_id = match arr.first()? {
+                    Value::String(s) => s.as_str()?.to_string(),
+                    Value::Binary(b) => match rmpv::decode::read_value(&mut b.as_slice()).ok()? {
+                        Value::String(s) => s.as_str()?.to_string(),
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                let Value::Binary(value) = arr.get(1)? else {
+                    return None;
+                };
+                Self::decode_as(value, Some(peer_id))
+            })
+            .collect()
+    }
+
+    /// Blocks `[start_block, end_block)` as a range
+    pub fn span(&self) -> std::ops::Range<usize> {
+        self.start_block..self.end_block
+    }
+
+    /// Whether the server can compute in `dtype`: one its accelerator
+    /// supports, or else the `torch_dtype` it announced
+    pub fn supports_dtype(&self, dtype: &str) -> bool {
+        match (&self.accelerator, &self.torch_dtype) {
+            (Some(accel), _) => accel.supports_dtype(dtype),
+            (None, Some(announced)) => normalize_dtype(announced) == normalize_dtype(dtype),
+            (None, None) => false,
+        }
+    }
+}
+
+/// Overall state of a model's swarm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelHealth {
+    /// Every block is served by at least one online server
+    Healthy,
+    /// Some servers are online but blocks are missing
+    Broken,
+    /// No online servers
+    Offline,
+}
+
+/// A server in a [`ModelReport`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerRow {
+    /// Last characters of the peer ID, for display
+    pub short_peer_id: String,
+    /// Country and ASN of the server's address, when enrichment is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_ip_info: Option<PeerIpInfo>,
+    #[serde(flatten)]
+    pub record: ServerRecord,
+}
+
+/// State of one model's swarm
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelReport {
+    /// Model name as given by the caller
+    pub name: String,
+    pub dht_prefix: String,
+    pub state: ModelHealth,
+    /// Transformer blocks in the model
+    pub num_blocks: usize,
+    /// Blocks served by at least one online server
+    pub blocks_covered: usize,
+    /// Online servers covering each block
+    pub block_coverage: Vec<u32>,
+    /// Servers, by start block then peer ID
+    pub server_rows: Vec<ServerRow>,
+}
+
+impl ModelReport {
+    /// First block no online server covers
+    pub fn first_gap(&self) -> Option<usize> {
+        self.block_coverage.iter().position(|&n| n == 0)
+    }
+}
+
+/// State of every model queried, like map.kwaai.ai's `/api/v1/state`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwarmState {
+    /// Unix timestamp (seconds) when the state was gathered
+    pub taken_at: u64,
+    pub model_reports: Vec<ModelReport>,
+    /// Distinct servers across all models
+    pub num_peers: usize,
+}
+
+impl SwarmState {
+    /// Combine per-model reports
+    pub fn new(model_reports: Vec<ModelReport>) -> Self {
+        let mut peers: Vec<&str> = model_reports
+            .iter()
+            .flat_map(|r| r.server_rows.iter().map(|s| s.record.peer_id.as_str()))
+            .collect();
+        peers.sort_unstable();
+        peers.dedup();
+        Self {
+            taken_at: crate::health::unix_now(),
+            num_peers: peers.len(),
+            model_reports,
+        }
+    }
+}
+
+/// Folds DHT values found under a model's block keys into a [`ModelReport`]
+#[derive(Debug)]
+pub struct ModelStateBuilder {
+    name: String,
+    dht_prefix: String,
+    num_blocks: usize,
+    servers: HashMap<String, ServerRecord>,
+}
+
+impl ModelStateBuilder {
+    pub fn new(name: impl Into<String>, dht_prefix: impl Into<String>, num_blocks: usize) -> Self {
+        Self {
+            name: name.into(),
+            dht_prefix: dht_prefix.into(),
+            num_blocks,
+            servers: HashMap::new(),
+        }
+    }
+
+    /// Keys to look up, block by block
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        (0..self.num_blocks)
+            .map(|b| block_key(&self.dht_prefix, b))
+            .collect()
+    }
+
+    /// Add a value found under one of the [`keys`](Self::keys); `result_type`
+    /// is the FIND result type (1 regular, 2 dictionary)
+    pub fn add_value(&mut self, result_type: i32, bytes: &[u8]) {
+        match result_type {
+            1 => {
+                if let Some(record) = ServerRecord::decode(bytes) {
+                    self.add_record(record);
+                }
+            }
+            2 => {
+                for record in ServerRecord::decode_dictionary(bytes) {
+                    self.add_record(record);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Add a decoded record; a server seen several times keeps the widest
+    /// span it announced. Once a server has a validly signed record, records
+    /// for its peer ID that are not validly signed are ignored.
+    pub fn add_record(&mut self, record: ServerRecord) {
+        let key = if record.peer_id.is_empty() {
+            format!(
+                "{}@{}",
+                record.public_name.as_deref().unwrap_or(""),
+                record.start_block
+            )
+        } else {
+            record.peer_id.clone()
+        };
+        let valid = |r: &ServerRecord| r.signature == SignatureStatus::Valid;
+        match self.servers.get_mut(&key) {
+            None => {
+                self.servers.insert(key, record);
+            }
+            Some(existing) if valid(existing) && !valid(&record) => {}
+            Some(existing) if valid(&record) && !valid(existing) => *existing = record,
+            Some(existing) => {
+                existing.start_block = existing.start_block.min(record.start_block);
+                existing.end_block = existing.end_block.max(record.end_block);
+            }
+        }
+    }
+
+    pub fn finish(self) -> ModelReport {
+        let mut block_coverage = vec![0u32; self.num_blocks];
+        for record in self.servers.values() {
+            if record.state != ServerState::Online {
+                continue;
+            }
+            let end = record.end_block.min(self.num_blocks);
+            for n in block_coverage.iter_mut().take(end).skip(record.start_block) {
+                *n += 1;
+            }
+        }
+        let blocks_covered = block_coverage.iter().filter(|&&n| n > 0).count();
+        let state = if blocks_covered == 0 {
+            ModelHealth::Offline
+        } else if blocks_covered < self.num_blocks {
+            ModelHealth::Broken
+        } else {
+            ModelHealth::Healthy
+        };
+
+        let mut server_rows: Vec<ServerRow> = self
+            .servers
+            .into_values()
+            .map(|record| ServerRow {
+                short_peer_id: short_peer_id(&record.peer_id),
+                peer_ip_info: None,
+                record,
+            })
+            .collect();
+        server_rows.sort_by(|a, b| {
+            (a.record.start_block, &a.record.peer_id)
+                .cmp(&(b.record.start_block, &b.record.peer_id))
+        });
+
+        ModelReport {
+            name: self.name,
+            dht_prefix: self.dht_prefix,
+            state,
+            num_blocks: self.num_blocks,
+            blocks_covered,
+            block_coverage,
+            server_rows,
+        }
+    }
+}
+
+fn short_peer_id(peer_id: &str) -> String {
+    // Peer IDs are base58, so byte offsets are character offsets.
+    if peer_id.len() > 10 && peer_id.is_ascii() {
+        format!("...{}", &peer_id[peer_id.len() - 6..])
+    } else {
+        peer_id.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_info(state: i64, start: u64, end: u64, peer_id: &str) -> Vec<u8> {
+        let fields = Value::Map(vec![
+            (Value::from("start_block"), Value::from(start)),
+            (Value::from("end_block"), Value::from(end)),
+            (Value::from("public_name"), Value::from("node")),
+            (Value::from("peer_id"), Value::from(peer_id)),
+            (Value::from("cache_tokens_left"), Value::from(4096u64)),
+        ]);
+        let inner = Value::Array(vec![Value::from(state), Value::from(12.5), fields]);
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &inner).unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &Value::Ext(64, data)).unwrap();
+        bytes
+    }
+
+    fn dictionary(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let entries = entries
+            .iter()
+            .map(|(peer, value)| {
+                let subkey = rmp_serde::to_vec(peer).unwrap();
+                Value::Array(vec![
+                    Value::Binary(subkey),
+                    Value::Binary(value.clone()),
+                    Value::from(1e10),
+                ])
+            })
+            .collect();
+        let inner = Value::Array(vec![
+            Value::from(1e10),
+            Value::from(0.0),
+            Value::Array(entries),
+        ]);
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &inner).unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &Value::Ext(80, data)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn decodes_regular_and_dictionary_values() {
+        let record = ServerRecord::decode(&server_info(2, 0, 16, "peerA")).unwrap();
+        assert_eq!(record.peer_id, "peerA");
+        assert_eq!(record.state, ServerState::Online);
+        assert_eq!(record.span(), 0..16);
+        assert_eq!(record.cache_tokens_left, Some(4096));
+
+        let records = ServerRecord::decode_dictionary(&dictionary(&[
+            ("peerB", server_info(2, 16, 32, "")),
+            ("peerC", server_info(1, 0, 8, "")),
+        ]));
+        let peers: Vec<_> = records.iter().map(|r| r.peer_id.as_str()).collect();
+        assert_eq!(peers, ["peerB", "peerC"]);
+        assert!(ServerRecord::decode(b"junk").is_none());
+    }
+
+    #[test]
+    fn decodes_announced_accelerator() {
+        let accel = AcceleratorInfo {
+            device_type: "cuda".into(),
+            device_name: None,
+            vram_mb: 24564,
+            dtypes: vec!["bfloat16".into(), "float16".into()],
+            quant_types: vec!["none".into()],
+            backend: "candle-cuda".into(),
+        };
+        let fields = Value::Map(vec![
+            (Value::from("start_block"), Value::from(0)),
+            (Value::from("end_block"), Value::from(8)),
+            (Value::from("torch_dtype"), Value::from("float16")),
+            (Value::from("accelerator"), accel.to_msgpack_value()),
+        ]);
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &Value::Array(vec![2.into(), 1.0.into(), fields]))
+            .unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &Value::Ext(64, data)).unwrap();
+
+        let record = ServerRecord::decode(&bytes).unwrap();
+        assert_eq!(record.accelerator.as_ref(), Some(&accel));
+        assert!(record.supports_dtype("bf16"));
+        assert!(!record.supports_dtype("float32"));
+
+        // Without an accelerator map, only the announced dtype counts.
+        let plain = ServerRecord::decode(&server_info(2, 0, 16, "peerA")).unwrap();
+        assert!(plain.accelerator.is_none());
+        assert!(!plain.supports_dtype("float16"));
+    }
+
+    fn signed(keypair: &Keypair, state: i64, start: u64, end: u64) -> Vec<u8> {
+        let bytes = server_info(
+            state,
+            start,
+            end,
+            &keypair.public().to_peer_id().to_base58(),
+        );
+        let Ok(Value::Ext(code, data)) = rmpv::decode::read_value(&mut &bytes[..]) else {
+            unreachable!()
+        };
+        let mut tuple = rmpv::decode::read_value(&mut &data[..]).unwrap();
+        assert!(sign_server_info(
+            &mut tuple,
+            keypair,
+            Duration::from_secs(600)
+        ));
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &tuple).unwrap();
+        let mut out = Vec::new();
+        rmpv::encode::write_value(&mut out, &Value::Ext(code, data)).unwrap();
+        out
+    }
+
+    #[test]
+    fn verifies_signed_records_and_ignores_spoofs() {
+        let ed25519 = Keypair::generate_ed25519();
+        let peer = ed25519.public().to_peer_id().to_base58();
+        let record = ServerRecord::decode(&signed(&ed25519, 2, 0, 8)).unwrap();
+        assert_eq!(record.signature, SignatureStatus::Valid);
+        assert_eq!(
+            ServerRecord::decode(&server_info(2, 0, 8, &peer))
+                .unwrap()
+                .signature,
+            SignatureStatus::Unsigned
+        );
+
+        // Signed by another key, or claimed under another peer's subkey.
+        let other = Keypair::generate_ed25519();
+        let forged = ServerRecord::decode_dictionary(&dictionary(&[(
+            peer.as_str(),
+            signed(&other, 2, 8, 32),
+        )]));
+        assert_eq!(forged[0].signature, SignatureStatus::Invalid);
+
+        // The spoofed record neither replaces nor widens the authentic one.
+        let mut model = ModelStateBuilder::new("Llama", "Llama-hf", 32);
+        model.add_value(1, &signed(&ed25519, 2, 0, 8));
+        model.add_record(forged[0].clone());
+        model.add_value(1, &server_info(2, 0, 32, &peer));
+        let report = model.finish();
+        assert_eq!(report.server_rows.len(), 1);
+        assert_eq!(report.server_rows[0].record.span(), 0..8);
+        assert_eq!(
+            report.server_rows[0].record.signature,
+            SignatureStatus::Valid
+        );
+    }
+
+    #[test]
+    fn signed_records_expire_and_their_window_cannot_be_extended() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().to_peer_id().to_base58();
+        let bytes = server_info(2, 0, 8, &peer);
+        let Ok(Value::Ext(_, data)) = rmpv::decode::read_value(&mut &bytes[..]) else {
+            unreachable!()
+        };
+        let mut tuple = rmpv::decode::read_value(&mut &data[..]).unwrap();
+        assert!(sign_server_info(
+            &mut tuple,
+            &keypair,
+            Duration::from_secs(600)
+        ));
+
+        let now = now_secs();
+        let late = now + 600 + CLOCK_SKEW.as_secs() + 1;
+        assert_eq!(
+            verify_server_info_at(&tuple, &peer, now),
+            SignatureStatus::Valid
+        );
+        assert_eq!(
+            verify_server_info_at(&tuple, &peer, late),
+            SignatureStatus::Expired
+        );
+
+        // Pushing expires_at out breaks the signature.
+        let fields = fields_mut(&mut tuple).unwrap();
+        for (k, v) in fields.iter_mut() {
+            if k.as_str() == Some(EXPIRES_AT_FIELD) {
+                *v = Value::from(u64::MAX);
+            }
+        }
+        assert_eq!(
+            verify_server_info_at(&tuple, &peer, late),
+            SignatureStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn report_merges_spans_and_classifies_health() {
+        let mut model = ModelStateBuilder::new("Llama", "Llama-hf", 32);
+        model.add_value(1, &server_info(2, 0, 8, "peerA"));
+        model.add_value(1, &server_info(2, 8, 16, "peerA"));
+        model.add_value(2, &dictionary(&[("peerB", server_info(1, 16, 32, ""))]));
+        let report = model.finish();
+
+        // peerA's two announcements merge; the joining peerB covers nothing.
+        assert_eq!(report.server_rows.len(), 2);
+        assert_eq!(report.server_rows[0].record.span(), 0..16);
+        assert_eq!(report.blocks_covered, 16);
+        assert_eq!(report.first_gap(), Some(16));
+        assert_eq!(report.state, ModelHealth::Broken);
+
+        let mut model = ModelStateBuilder::new("Llama", "Llama-hf", 32);
+        model.add_value(2, &dictionary(&[("peerB", server_info(2, 0, 32, ""))]));
+        let healthy = model.finish();
+        assert_eq!(healthy.state, ModelHealth::Healthy);
+
+        let empty = ModelStateBuilder::new("Other", "Other-hf", 4).finish();
+        assert_eq!(empty.state, ModelHealth::Offline);
+
+        let swarm = SwarmState::new(vec![report, healthy, empty]);
+        assert_eq!(swarm.num_peers, 2);
+    }
+}
@@ -0,0 +1,527 @@
+//! In-process multi-node simulation for integration tests
+//!
+//! [`SimNetwork`] runs N [`KwaaiNetwork`] nodes in one process, wired through
+//! libp2p's in-memory transport: no sockets, no p2pd, no external bootstrap
+//! peers. Nothing runs in the background; the swarms only make progress
+//! inside [`SimNetwork::run_until`] and the helpers built on it, so a test
+//! decides exactly when the network moves and every wait has a timeout.
+//!
+//! Node identities are derived from the node index, so peer IDs (and thus
+//! XOR distances and record placement) are the same on every run.
+//!
+//! ```rust,no_run
+//! # async fn demo() -> kwaai_p2p::P2PResult<()> {
+//! use kwaai_p2p::testing::SimNetwork;
+//! use std::time::Duration;
+//!
+//! let mut sim = SimNetwork::new(5).await?;
+//! sim.bootstrap_star().await?;
+//! sim.await_convergence(Duration::from_secs(10)).await?;
+//!
+//! sim.announce(1, "llama.0", b"server-1".to_vec()).await?;
+//! assert_eq!(sim.lookup(4, "llama.0").await?, Some(b"server-1".to_vec()));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Enabled with the `testing` feature.
+
+use crate::{
+    config::NetworkConfig,
+    error::{P2PError, P2PResult},
+    network::{KwaaiBehaviour, KwaaiBehaviourEvent, IDLE_CONNECTION_TIMEOUT},
+    rpc::{RpcHandler, RpcRequest, RpcResponse},
+    DhtOperations, KwaaiNetwork, NetworkBehaviour, ServerInfo,
+};
+use futures::{future::poll_fn, StreamExt};
+use libp2p::{
+    core::{
+        transport::{MemoryTransport, Transport as _},
+        upgrade,
+    },
+    identify, identity,
+    kad::{self, GetProvidersOk, GetRecordOk, QueryId, QueryResult, RecordKey},
+    noise, request_response,
+    swarm::SwarmEvent,
+    yamux, Multiaddr, PeerId, Swarm,
+};
+use std::collections::{HashMap, HashSet};
+use std::task::Poll;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Default wait for helpers that take no explicit timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of a Kademlia query issued by the harness
+#[derive(Debug, Clone)]
+enum QueryOutcome {
+    Record(Option<Vec<u8>>),
+    Providers(HashSet<PeerId>),
+}
+
+/// What the harness has observed of one node's swarm
+#[derive(Default)]
+struct NodeState {
+    /// Peers added to the routing table
+    routing: HashSet<PeerId>,
+    /// Kademlia queries still running
+    pending_queries: usize,
+    /// Results of lookups issued by the harness
+    outcomes: HashMap<QueryId, QueryOutcome>,
+    /// Lookups whose last result has arrived
+    finished: HashSet<QueryId>,
+    /// Failed PUT / provide queries, in order
+    failed_writes: Vec<String>,
+    /// Answers `rpc_info` when set
+    rpc_handler: Option<RpcHandler>,
+    /// RPC results by outbound request
+    rpc_results: HashMap<request_response::OutboundRequestId, P2PResult<RpcResponse>>,
+}
+
+/// One simulated node
+pub struct SimNode {
+    network: KwaaiNetwork,
+    addr: Multiaddr,
+}
+
+/// N in-process nodes on the memory transport
+pub struct SimNetwork {
+    nodes: Vec<SimNode>,
+    states: Vec<NodeState>,
+    /// Node polled first in the next step, rotated for fairness
+    next_poll: usize,
+}
+
+impl SimNetwork {
+    /// Start `n` listening nodes with the default configuration
+    pub async fn new(n: usize) -> P2PResult<Self> {
+        Self::with_config(n, NetworkConfig::default()).await
+    }
+
+    /// Start `n` listening nodes sharing `config`
+    ///
+    /// Listen addresses in `config` are ignored: every node listens on a
+    /// fresh `/memory` port.
+    pub async fn with_config(n: usize, config: NetworkConfig) -> P2PResult<Self> {
+        let mut nodes = Vec::with_capacity(n);
+        for i in 0..n {
+            let key = node_key(i);
+            let behaviour = KwaaiNetwork::create_behaviour(&key, &config);
+            let mut swarm = memory_swarm(key, behaviour)?;
+            swarm
+                .listen_on("/memory/0".parse().expect("memory multiaddr"))
+                .map_err(|e| P2PError::Transport(e.to_string()))?;
+            let addr = loop {
+                if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                    break address;
+                }
+            };
+            let network = KwaaiNetwork::from_swarm(config.clone(), swarm);
+            nodes.push(SimNode { network, addr });
+        }
+        let states = (0..n).map(|_| NodeState::default()).collect();
+        Ok(Self {
+            nodes,
+            states,
+            next_poll: 0,
+        })
+    }
+
+    /// Number of nodes
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the network has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Node `i`
+    pub fn node(&self, i: usize) -> &KwaaiNetwork {
+        &self.nodes[i].network
+    }
+
+    /// Node `i`, mutably (for [`DhtOperations`] and [`NetworkBehaviour`])
+    pub fn node_mut(&mut self, i: usize) -> &mut KwaaiNetwork {
+        &mut self.nodes[i].network
+    }
+
+    /// Peer ID of node `i`
+    pub fn peer_id(&self, i: usize) -> PeerId {
+        self.nodes[i].network.local_peer_id()
+    }
+
+    /// Dialable address of node `i`, including its `/p2p` suffix
+    pub fn addr(&self, i: usize) -> Multiaddr {
+        self.nodes[i]
+            .addr
+            .clone()
+            .with(libp2p::multiaddr::Protocol::P2p(self.peer_id(i)))
+    }
+
+    /// Whether node `i` has `peer` in its routing table
+    pub fn knows(&self, i: usize, peer: &PeerId) -> bool {
+        self.states[i].routing.contains(peer)
+    }
+
+    /// Bootstrap every node from node 0
+    pub async fn bootstrap_star(&mut self) -> P2PResult<()> {
+        let hub = self.addr(0);
+        for i in 1..self.len() {
+            self.nodes[i].network.bootstrap(vec![hub.clone()]).await?;
+        }
+        Ok(())
+    }
+
+    /// Bootstrap node `i` from node `i - 1`, forming a chain
+    pub async fn bootstrap_chain(&mut self) -> P2PResult<()> {
+        for i in 1..self.len() {
+            let prev = self.addr(i - 1);
+            self.nodes[i].network.bootstrap(vec![prev]).await?;
+        }
+        Ok(())
+    }
+
+    /// Drive every node until `done` holds, or fail after `timeout`
+    ///
+    /// DHT commands queued through [`DhtOperations`] are handed to the swarms
+    /// before each step.
+    pub async fn run_until<F>(&mut self, timeout: Duration, mut done: F) -> P2PResult<()>
+    where
+        F: FnMut(&SimNetwork) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.flush_commands().await?;
+            if done(self) {
+                return Ok(());
+            }
+            if !self.step(deadline).await? {
+                return Err(P2PError::Timeout(timeout.as_millis() as u64));
+            }
+        }
+    }
+
+    /// Run until no node has a Kademlia query in flight
+    pub async fn settle(&mut self, timeout: Duration) -> P2PResult<()> {
+        self.run_until(timeout, |sim| {
+            sim.states.iter().all(|s| s.pending_queries == 0)
+        })
+        .await
+    }
+
+    /// Run until every node has every other node in its routing table
+    ///
+    /// Only meaningful while the network fits in the routing table's
+    /// buckets (k = 20 peers per distance).
+    pub async fn await_convergence(&mut self, timeout: Duration) -> P2PResult<()> {
+        let peers: Vec<PeerId> = (0..self.len()).map(|i| self.peer_id(i)).collect();
+        self.run_until(timeout, |sim| {
+            (0..peers.len()).all(|i| {
+                peers
+                    .iter()
+                    .enumerate()
+                    .all(|(j, p)| i == j || sim.knows(i, p))
+            })
+        })
+        .await
+    }
+
+    /// PUT `value` under `key` from node `i` and wait for replication
+    ///
+    /// Goes through [`DhtOperations::put`], i.e. the same path as
+    /// [`KwaaiNetwork::announce_blocks`].
+    pub async fn announce(&mut self, i: usize, key: &str, value: Vec<u8>) -> P2PResult<()> {
+        let failed = self.states[i].failed_writes.len();
+        self.nodes[i].network.put(key, value).await?;
+        self.settle(DEFAULT_TIMEOUT).await?;
+        match self.states[i].failed_writes.get(failed) {
+            Some(e) => Err(P2PError::DhtError(e.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Register node `i` as a provider of `key` and wait for the records
+    pub async fn provide(&mut self, i: usize, key: &str) -> P2PResult<()> {
+        let failed = self.states[i].failed_writes.len();
+        self.nodes[i].network.provide(key).await?;
+        self.settle(DEFAULT_TIMEOUT).await?;
+        match self.states[i].failed_writes.get(failed) {
+            Some(e) => Err(P2PError::DhtError(e.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Look `key` up in the DHT from node `i`
+    ///
+    /// Runs a Kademlia GET_VALUE on the swarm, since
+    /// [`DhtOperations::get`] only reads the node's local cache.
+    pub async fn lookup(&mut self, i: usize, key: &str) -> P2PResult<Option<Vec<u8>>> {
+        let id = self
+            .with_swarm(i, |swarm| {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_record(RecordKey::new(&key))
+            })
+            .await?;
+        match self.await_query(i, id).await? {
+            QueryOutcome::Record(value) => Ok(value),
+            QueryOutcome::Providers(_) => unreachable!("GET_VALUE yields a record"),
+        }
+    }
+
+    /// Providers of `key` as found from node `i`
+    pub async fn find_providers(&mut self, i: usize, key: &str) -> P2PResult<HashSet<PeerId>> {
+        let id = self
+            .with_swarm(i, |swarm| {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_providers(RecordKey::new(&key))
+            })
+            .await?;
+        match self.await_query(i, id).await? {
+            QueryOutcome::Providers(peers) => Ok(peers),
+            QueryOutcome::Record(_) => unreachable!("GET_PROVIDERS yields providers"),
+        }
+    }
+
+    /// Answer `rpc_info` requests on node `i` with `info`
+    pub fn serve_rpc(&mut self, i: usize, info: ServerInfo) {
+        self.states[i].rpc_handler = Some(RpcHandler::new(info));
+    }
+
+    /// Send `request` from node `from` to node `to` and wait for the answer
+    pub async fn rpc(
+        &mut self,
+        from: usize,
+        to: usize,
+        request: RpcRequest,
+    ) -> P2PResult<RpcResponse> {
+        let (peer, addr) = (self.peer_id(to), self.nodes[to].addr.clone());
+        let id = self
+            .with_swarm(from, |swarm| {
+                swarm.add_peer_address(peer, addr);
+                swarm.behaviour_mut().rpc.send_request(&peer, request)
+            })
+            .await?;
+        self.run_until(DEFAULT_TIMEOUT, |sim| {
+            sim.states[from].rpc_results.contains_key(&id)
+        })
+        .await?;
+        self.states[from]
+            .rpc_results
+            .remove(&id)
+            .expect("waited for the result")
+    }
+
+    async fn with_swarm<T>(
+        &self,
+        i: usize,
+        f: impl FnOnce(&mut Swarm<KwaaiBehaviour>) -> T,
+    ) -> P2PResult<T> {
+        let mut guard = self.nodes[i].network.swarm.lock().await;
+        let swarm = guard.as_mut().ok_or(P2PError::NotInitialized)?;
+        Ok(f(swarm))
+    }
+
+    async fn await_query(&mut self, i: usize, id: QueryId) -> P2PResult<QueryOutcome> {
+        self.run_until(DEFAULT_TIMEOUT, |sim| sim.states[i].finished.contains(&id))
+            .await?;
+        let state = &mut self.states[i];
+        state.finished.remove(&id);
+        Ok(state
+            .outcomes
+            .remove(&id)
+            .expect("finished queries have an outcome"))
+    }
+
+    /// Hand queued DHT commands of every node to its swarm
+    async fn flush_commands(&mut self) -> P2PResult<()> {
+        for (node, state) in self.nodes.iter().zip(&mut self.states) {
+            let mut flushed = false;
+            while node.network.process_dht_command().await? {
+                flushed = true;
+            }
+            if flushed {
+                let mut guard = node.network.swarm.lock().await;
+                if let Some(swarm) = guard.as_mut() {
+                    state.pending_queries = swarm.behaviour_mut().kademlia.iter_queries().count();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle the next event of any node; false once `deadline` passes
+    async fn step(&mut self, deadline: Instant) -> P2PResult<bool> {
+        let n = self.nodes.len();
+        let mut guards = Vec::with_capacity(n);
+        for node in &self.nodes {
+            guards.push(node.network.swarm.lock().await);
+        }
+        let start = self.next_poll;
+        let next = poll_fn(|cx| {
+            for k in 0..n {
+                let i = (start + k) % n;
+                if let Some(swarm) = guards[i].as_mut() {
+                    if let Poll::Ready(Some(event)) = swarm.poll_next_unpin(cx) {
+                        return Poll::Ready((i, event));
+                    }
+                }
+            }
+            Poll::Pending
+        });
+        let Ok((i, event)) = tokio::time::timeout_at(deadline, next).await else {
+            return Ok(false);
+        };
+        self.next_poll = (i + 1) % n.max(1);
+        let swarm = guards[i].as_mut().ok_or(P2PError::NotInitialized)?;
+        handle_event(&mut self.states[i], swarm, event);
+        Ok(true)
+    }
+}
+
+fn handle_event(
+    state: &mut NodeState,
+    swarm: &mut Swarm<KwaaiBehaviour>,
+    event: SwarmEvent<KwaaiBehaviourEvent>,
+) {
+    match event {
+        // The listener side of a connection only learns where the dialer
+        // can be reached from identify.
+        SwarmEvent::Behaviour(KwaaiBehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => {
+            for addr in info.listen_addrs {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+            }
+        }
+        SwarmEvent::Behaviour(KwaaiBehaviourEvent::Kademlia(event)) => {
+            handle_kad_event(state, swarm, event)
+        }
+        SwarmEvent::Behaviour(KwaaiBehaviourEvent::Rpc(event)) => {
+            handle_rpc_event(state, swarm, event)
+        }
+        _ => {}
+    }
+    state.pending_queries = swarm.behaviour_mut().kademlia.iter_queries().count();
+}
+
+fn handle_kad_event(state: &mut NodeState, swarm: &mut Swarm<KwaaiBehaviour>, event: kad::Event) {
+    match event {
+        kad::Event::RoutingUpdated { peer, .. } => {
+            state.routing.insert(peer);
+        }
+        kad::Event::OutboundQueryProgressed {
+            id, result, step, ..
+        } => {
+            match result {
+                QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(found))) => {
+                    state
+                        .outcomes
+                        .insert(id, QueryOutcome::Record(Some(found.record.value)));
+                    // The first record is enough.
+                    if let Some(mut query) = swarm.behaviour_mut().kademlia.query_mut(&id) {
+                        query.finish();
+                    }
+                }
+                QueryResult::GetRecord(_) => {
+                    state
+                        .outcomes
+                        .entry(id)
+                        .or_insert(QueryOutcome::Record(None));
+                }
+                QueryResult::GetProviders(result) => {
+                    let entry = state
+                        .outcomes
+                        .entry(id)
+                        .or_insert_with(|| QueryOutcome::Providers(HashSet::new()));
+                    if let (
+                        Ok(GetProvidersOk::FoundProviders { providers, .. }),
+                        QueryOutcome::Providers(all),
+                    ) = (result, entry)
+                    {
+                        all.extend(providers);
+                    }
+                }
+                QueryResult::PutRecord(Err(e)) => state.failed_writes.push(e.to_string()),
+                QueryResult::StartProviding(Err(e)) => state.failed_writes.push(e.to_string()),
+                _ => {}
+            }
+            if step.last {
+                state.finished.insert(id);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn handle_rpc_event(
+    state: &mut NodeState,
+    swarm: &mut Swarm<KwaaiBehaviour>,
+    event: request_response::Event<RpcRequest, RpcResponse>,
+) {
+    match event {
+        request_response::Event::Message { message, .. } => match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } => {
+                let response = match &state.rpc_handler {
+                    Some(handler) => handler.handle_request(request),
+                    None => RpcResponse::Error("node serves no RPC".to_string()),
+                };
+                let _ = swarm.behaviour_mut().rpc.send_response(channel, response);
+            }
+            request_response::Message::Response {
+                request_id,
+                response,
+            } => {
+                state.rpc_results.insert(request_id, Ok(response));
+            }
+        },
+        request_response::Event::OutboundFailure {
+            request_id, error, ..
+        } => {
+            state.rpc_results.insert(
+                request_id,
+                Err(P2PError::ConnectionFailed(error.to_string())),
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Identity of node `i`, the same on every run
+fn node_key(i: usize) -> identity::Keypair {
+    let mut seed = [0u8; 32];
+    seed[..8].copy_from_slice(&(i as u64 + 1).to_be_bytes());
+    identity::Keypair::ed25519_from_bytes(seed).expect("32-byte ed25519 seed")
+}
+
+/// Noise/Yamux over the in-memory transport
+fn memory_swarm(
+    key: identity::Keypair,
+    behaviour: KwaaiBehaviour,
+) -> P2PResult<Swarm<KwaaiBehaviour>> {
+    Ok(libp2p::SwarmBuilder::with_existing_identity(key)
+        .with_tokio()
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                MemoryTransport::default()
+                    .upgrade(upgrade::Version::V1)
+                    .authenticate(noise::Config::new(key)?)
+                    .multiplex(yamux::Config::default()),
+            )
+        })
+        .map_err(|e| P2PError::Transport(e.to_string()))?
+        .with_behaviour(|_| Ok(behaviour))
+        .map_err(|e| P2PError::Internal(e.to_string()))?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT))
+        .build())
+}
@@ -0,0 +1,417 @@
+//! Transformer blocks served from the browser
+//!
+//! A [`BlockSpan`] holds a few consecutive Llama decoder blocks loaded from a
+//! SafeTensors buffer and runs them for hidden states coming from the
+//! previous server in a chain. Browser nodes only take middle positions: they
+//! hold neither the embedding nor the LM head.
+//!
+//! This is the block of `kwaai_inference::shard` cut down to what runs on the
+//! WASM CPU backend: f32 weights, no LoRA and no wall-clock session expiry
+//! (`std::time::Instant` panics on `wasm32-unknown-unknown`), so the KV
+//! caches of the least recently used sessions are dropped instead.
+
+use candle_core::{DType, Device, Result, Tensor};
+use candle_nn::{Linear, Module, RmsNorm, VarBuilder};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Sessions whose KV caches are kept at once
+pub const MAX_SESSIONS: usize = 4;
+
+/// Model hyperparameters, as found in a Hugging Face `config.json`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockConfig {
+    pub hidden_size: usize,
+    pub num_attention_heads: usize,
+    /// Defaults to `num_attention_heads` (no GQA)
+    #[serde(default)]
+    pub num_key_value_heads: Option<usize>,
+    pub intermediate_size: usize,
+    #[serde(default = "default_rope_theta")]
+    pub rope_theta: f64,
+    #[serde(default = "default_rms_norm_eps")]
+    pub rms_norm_eps: f64,
+    #[serde(default = "default_max_position_embeddings")]
+    pub max_position_embeddings: usize,
+}
+
+fn default_rope_theta() -> f64 {
+    10_000.0
+}
+
+fn default_rms_norm_eps() -> f64 {
+    1e-5
+}
+
+fn default_max_position_embeddings() -> usize {
+    2048
+}
+
+impl BlockConfig {
+    fn num_kv_heads(&self) -> usize {
+        self.num_key_value_heads.unwrap_or(self.num_attention_heads)
+    }
+
+    fn head_dim(&self) -> usize {
+        self.hidden_size / self.num_attention_heads
+    }
+}
+
+/// One Llama decoder block
+struct Block {
+    input_layernorm: RmsNorm,
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    o_proj: Linear,
+    post_attention_layernorm: RmsNorm,
+    gate_proj: Linear,
+    up_proj: Linear,
+    down_proj: Linear,
+}
+
+impl Block {
+    /// Load from `vb` scoped to `model.layers.{index}`
+    fn load(vb: VarBuilder, cfg: &BlockConfig) -> Result<Self> {
+        let h = cfg.hidden_size;
+        let kv_dim = cfg.num_kv_heads() * cfg.head_dim();
+        let inter = cfg.intermediate_size;
+        let eps = cfg.rms_norm_eps;
+        Ok(Self {
+            input_layernorm: candle_nn::rms_norm(h, eps, vb.pp("input_layernorm"))?,
+            q_proj: candle_nn::linear_no_bias(h, h, vb.pp("self_attn.q_proj"))?,
+            k_proj: candle_nn::linear_no_bias(h, kv_dim, vb.pp("self_attn.k_proj"))?,
+            v_proj: candle_nn::linear_no_bias(h, kv_dim, vb.pp("self_attn.v_proj"))?,
+            o_proj: candle_nn::linear_no_bias(h, h, vb.pp("self_attn.o_proj"))?,
+            post_attention_layernorm: candle_nn::rms_norm(
+                h,
+                eps,
+                vb.pp("post_attention_layernorm"),
+            )?,
+            gate_proj: candle_nn::linear_no_bias(h, inter, vb.pp("mlp.gate_proj"))?,
+            up_proj: candle_nn::linear_no_bias(h, inter, vb.pp("mlp.up_proj"))?,
+            down_proj: candle_nn::linear_no_bias(inter, h, vb.pp("mlp.down_proj"))?,
+        })
+    }
+
+    /// `x` is `[1, seq_len, hidden]`; `kv` is this block's cache for the session
+    fn forward(
+        &self,
+        x: &Tensor,
+        seq_pos: usize,
+        kv: &mut Option<(Tensor, Tensor)>,
+        rope: &Rope,
+        cfg: &BlockConfig,
+    ) -> Result<Tensor> {
+        let (b, s, h) = x.dims3()?;
+        let (n_h, n_kv, hd) = (cfg.num_attention_heads, cfg.num_kv_heads(), cfg.head_dim());
+
+        let normed = self.input_layernorm.forward(x)?;
+        let heads = |t: Tensor, n: usize| t.reshape((b, s, n, hd))?.transpose(1, 2)?.contiguous();
+        let q = heads(self.q_proj.forward(&normed)?, n_h)?;
+        let k = heads(self.k_proj.forward(&normed)?, n_kv)?;
+        let v = heads(self.v_proj.forward(&normed)?, n_kv)?;
+        let (q, k) = rope.apply(&q, &k, seq_pos)?;
+
+        let (k, v) = match kv.take() {
+            Some((ck, cv)) => (
+                Tensor::cat(&[&ck, &k], 2)?.contiguous()?,
+                Tensor::cat(&[&cv, &v], 2)?.contiguous()?,
+            ),
+            None => (k, v),
+        };
+        *kv = Some((k.clone(), v.clone()));
+
+        let n_rep = n_h / n_kv;
+        let k = repeat_kv(&k, n_rep)?;
+        let v = repeat_kv(&v, n_rep)?;
+        let scores = (q.matmul(&k.t()?)? * (hd as f64).sqrt().recip())?;
+        let scores = if s > 1 {
+            let mask = causal_mask(s, k.dim(2)?, seq_pos, x.device())?;
+            scores.broadcast_add(&mask)?
+        } else {
+            scores
+        };
+        let attn = candle_nn::ops::softmax_last_dim(&scores)?.matmul(&v)?;
+        let attn = attn.transpose(1, 2)?.reshape((b, s, h))?;
+        let x = (x + self.o_proj.forward(&attn)?)?;
+
+        let normed = self.post_attention_layernorm.forward(&x)?;
+        let gate = candle_nn::ops::silu(&self.gate_proj.forward(&normed)?)?;
+        let ff = self
+            .down_proj
+            .forward(&(gate * self.up_proj.forward(&normed)?)?)?;
+        x + ff
+    }
+}
+
+/// Precomputed rotary embedding tables, `[max_positions, head_dim / 2]`
+struct Rope {
+    cos: Tensor,
+    sin: Tensor,
+}
+
+impl Rope {
+    fn new(cfg: &BlockConfig, device: &Device) -> Result<Self> {
+        let hd = cfg.head_dim();
+        let max = cfg.max_position_embeddings;
+        let inv_freq: Vec<f32> = (0..hd / 2)
+            .map(|i| 1.0 / cfg.rope_theta.powf(2.0 * i as f64 / hd as f64) as f32)
+            .collect();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, hd / 2), device)?;
+        let positions = Tensor::arange(0u32, max as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((max, 1))?;
+        let angles = positions.broadcast_mul(&inv_freq)?;
+        Ok(Self {
+            cos: angles.cos()?,
+            sin: angles.sin()?,
+        })
+    }
+
+    fn apply(&self, q: &Tensor, k: &Tensor, seq_pos: usize) -> Result<(Tensor, Tensor)> {
+        let s = q.dim(2)?;
+        let cos = self.cos.narrow(0, seq_pos, s)?;
+        let sin = self.sin.narrow(0, seq_pos, s)?;
+        Ok((
+            candle_nn::rotary_emb::rope(q, &cos, &sin)?,
+            candle_nn::rotary_emb::rope(k, &cos, &sin)?,
+        ))
+    }
+}
+
+/// `[1, 1, q_len, kv_len]` mask hiding keys after each query's position
+fn causal_mask(q_len: usize, kv_len: usize, seq_pos: usize, device: &Device) -> Result<Tensor> {
+    let data: Vec<f32> = (0..q_len)
+        .flat_map(|i| {
+            (0..kv_len).map(move |j| {
+                if j <= seq_pos + i {
+                    0.0
+                } else {
+                    f32::NEG_INFINITY
+                }
+            })
+        })
+        .collect();
+    Tensor::from_vec(data, (1, 1, q_len, kv_len), device)
+}
+
+fn repeat_kv(t: &Tensor, n_rep: usize) -> Result<Tensor> {
+    if n_rep == 1 {
+        return Ok(t.clone());
+    }
+    let (b, n_kv, s, hd) = t.dims4()?;
+    t.unsqueeze(2)?
+        .expand((b, n_kv, n_rep, s, hd))?
+        .reshape((b, n_kv * n_rep, s, hd))
+}
+
+/// KV caches of one session, one entry per block
+struct Session {
+    kv: Vec<Option<(Tensor, Tensor)>>,
+    last_used: u64,
+}
+
+/// Consecutive transformer blocks `[start_block, end_block)` of one model
+pub struct BlockSpan {
+    pub start_block: usize,
+    pub end_block: usize,
+    blocks: Vec<Block>,
+    rope: Rope,
+    cfg: BlockConfig,
+    sessions: HashMap<u64, Session>,
+    /// Logical clock for LRU session eviction
+    tick: u64,
+}
+
+impl BlockSpan {
+    /// Load blocks `[start_block, end_block)` from a SafeTensors buffer
+    ///
+    /// The buffer may hold the whole model or only these blocks; tensors are
+    /// looked up by their Hugging Face names (`model.layers.{i}.…`).
+    pub fn from_safetensors(
+        weights: Vec<u8>,
+        cfg: BlockConfig,
+        start_block: usize,
+        end_block: usize,
+    ) -> Result<Self> {
+        let vb = VarBuilder::from_buffered_safetensors(weights, DType::F32, &Device::Cpu)?;
+        Self::load(vb, cfg, start_block, end_block)
+    }
+
+    fn load(
+        vb: VarBuilder,
+        cfg: BlockConfig,
+        start_block: usize,
+        end_block: usize,
+    ) -> Result<Self> {
+        let blocks = (start_block..end_block)
+            .map(|i| Block::load(vb.pp(format!("model.layers.{i}")), &cfg))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            start_block,
+            end_block,
+            blocks,
+            rope: Rope::new(&cfg, vb.device())?,
+            cfg,
+            sessions: HashMap::new(),
+            tick: 0,
+        })
+    }
+
+    /// Number of blocks in the span
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.cfg.hidden_size
+    }
+
+    /// Prepare `session_id` for a forward pass starting at `seq_pos`
+    ///
+    /// Cached positions at or after `seq_pos` are dropped, so resending from
+    /// an earlier position rolls the session back. Fails when the cache no
+    /// longer reaches `seq_pos` (e.g. the session was evicted).
+    pub fn begin(&mut self, session_id: u64, seq_pos: usize) -> Result<()> {
+        if !self.sessions.contains_key(&session_id) && self.sessions.len() >= MAX_SESSIONS {
+            if let Some(lru) = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(id, _)| *id)
+            {
+                self.sessions.remove(&lru);
+            }
+        }
+        self.tick += 1;
+        let num_blocks = self.blocks.len();
+        let session = self.sessions.entry(session_id).or_insert_with(|| Session {
+            kv: vec![None; num_blocks],
+            last_used: 0,
+        });
+        session.last_used = self.tick;
+
+        let cached = match session.kv.first() {
+            Some(Some((k, _))) => k.dim(2)?,
+            _ => 0,
+        };
+        if cached < seq_pos {
+            self.sessions.remove(&session_id);
+            candle_core::bail!(
+                "session {session_id} has {cached} cached positions, cannot continue at {seq_pos}"
+            );
+        }
+        if cached > seq_pos {
+            for kv in &mut session.kv {
+                *kv = match kv.take() {
+                    Some((k, v)) if seq_pos > 0 => {
+                        Some((k.narrow(2, 0, seq_pos)?, v.narrow(2, 0, seq_pos)?))
+                    }
+                    _ => None,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Run block `index` (0-based within the span) for a session prepared
+    /// with [`begin`](Self::begin)
+    ///
+    /// Blocks run one call at a time so the caller can hand the thread back
+    /// to the page in between.
+    pub fn forward_block(
+        &mut self,
+        index: usize,
+        session_id: u64,
+        x: &Tensor,
+        seq_pos: usize,
+    ) -> Result<Tensor> {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            candle_core::bail!("session {session_id} was not started");
+        };
+        let Some(block) = self.blocks.get(index) else {
+            candle_core::bail!("block {index} is outside the span");
+        };
+        block.forward(x, seq_pos, &mut session.kv[index], &self.rope, &self.cfg)
+    }
+
+    /// Run every block of the span
+    pub fn forward(&mut self, session_id: u64, x: Tensor, seq_pos: usize) -> Result<Tensor> {
+        self.begin(session_id, seq_pos)?;
+        (0..self.blocks.len()).try_fold(x, |x, i| self.forward_block(i, session_id, &x, seq_pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_nn::VarMap;
+
+    fn tiny_span() -> BlockSpan {
+        let cfg = BlockConfig {
+            hidden_size: 8,
+            num_attention_heads: 2,
+            num_key_value_heads: Some(1),
+            intermediate_size: 16,
+            rope_theta: 10_000.0,
+            rms_norm_eps: 1e-5,
+            max_position_embeddings: 32,
+        };
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        BlockSpan::load(vb, cfg, 2, 4).unwrap()
+    }
+
+    fn hidden(seq_len: usize, offset: f32) -> Tensor {
+        let data: Vec<f32> = (0..seq_len * 8)
+            .map(|i| (i as f32 * 0.37 + offset).sin())
+            .collect();
+        Tensor::from_vec(data, (1, seq_len, 8), &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn decode_with_cache_matches_full_prefill() {
+        let mut span = tiny_span();
+        assert_eq!(span.len(), 2);
+        let prompt = hidden(5, 0.0);
+
+        let full = span.forward(1, prompt.clone(), 0).unwrap();
+        assert_eq!(full.dims(), &[1, 5, 8]);
+
+        span.forward(2, prompt.narrow(1, 0, 4).unwrap(), 0).unwrap();
+        let step = span.forward(2, prompt.narrow(1, 4, 1).unwrap(), 4).unwrap();
+
+        let expected: Vec<f32> = full
+            .narrow(1, 4, 1)
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .to_vec1()
+            .unwrap();
+        let got: Vec<f32> = step.flatten_all().unwrap().to_vec1().unwrap();
+        for (a, b) in expected.iter().zip(&got) {
+            assert!((a - b).abs() < 1e-4, "{a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn sessions_roll_back_and_evict() {
+        let mut span = tiny_span();
+        span.forward(7, hidden(3, 1.0), 0).unwrap();
+        // Continuing past the cache is refused, rolling back is allowed
+        assert!(span.forward(7, hidden(1, 2.0), 5).is_err());
+        span.forward(8, hidden(3, 1.0), 0).unwrap();
+        span.forward(8, hidden(1, 2.0), 2).unwrap();
+
+        for id in 100..100 + MAX_SESSIONS as u64 {
+            span.forward(id, hidden(1, 0.5), 0).unwrap();
+        }
+        assert_eq!(span.sessions.len(), MAX_SESSIONS);
+        assert!(!span.sessions.contains_key(&8));
+    }
+}
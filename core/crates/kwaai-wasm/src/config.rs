@@ -0,0 +1,304 @@
+//! Configuration parsing with per-field validation errors
+//!
+//! Objects passed in from JavaScript are checked field by field, so a bad
+//! config is rejected with every offending field and the reason instead of
+//! being silently replaced by defaults. In JavaScript the failure is an
+//! `Error` named `ConfigValidationError` whose `errors` property lists
+//! `{ field, reason }` pairs.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Services `KwaaiNetConfig.services` may name
+pub const KNOWN_SERVICES: [&str; 4] = ["compute", "storage", "identity", "carbon"];
+
+/// Smallest accepted `max_memory_mb`
+pub const MIN_MEMORY_MB: usize = 64;
+
+#[wasm_bindgen(typescript_custom_section)]
+const CONFIG_TS: &str = r#"
+export type Service = "compute" | "storage" | "identity" | "carbon";
+
+/** Options of `KwaaiNet.initialize()`; omitted fields keep their defaults. */
+export interface KwaaiNetConfig {
+  /** Services to enable (default `["compute"]`) */
+  services?: Service[];
+  /** Maximum memory usage in MB, at least 64 (default 1024) */
+  max_memory_mb?: number;
+  /** Enable distributed inference (default false) */
+  enable_distributed?: boolean;
+}
+
+/** One rejected config field; nested fields use dotted paths. */
+export interface ConfigFieldError {
+  field: string;
+  reason: string;
+}
+
+/** Thrown by methods that take a config object. */
+export interface ConfigValidationError extends Error {
+  name: "ConfigValidationError";
+  errors: ConfigFieldError[];
+}
+"#;
+
+/// One rejected config field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Field name; nested fields use dotted paths, the whole object is `""`
+    pub field: String,
+    pub reason: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Result of parsing a config; the error lists every invalid field
+pub type ValidationResult<T> = Result<T, Vec<FieldError>>;
+
+/// Configuration for KwaaiNet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KwaaiNetConfig {
+    /// Services to enable
+    pub services: Vec<String>,
+    /// Maximum memory usage (MB)
+    pub max_memory_mb: usize,
+    /// Enable distributed inference
+    pub enable_distributed: bool,
+}
+
+impl Default for KwaaiNetConfig {
+    fn default() -> Self {
+        Self {
+            services: vec!["compute".to_string()],
+            max_memory_mb: 1024,
+            enable_distributed: false,
+        }
+    }
+}
+
+impl KwaaiNetConfig {
+    /// Parse and validate a config object; `null` gives the defaults
+    pub fn from_json(value: serde_json::Value) -> ValidationResult<Self> {
+        let mut config = Self::default();
+        if value.is_null() {
+            return Ok(config);
+        }
+        let mut fields = Fields::new(value, "")?;
+        if let Some(services) = fields.optional::<Vec<String>>("services") {
+            if services.is_empty() {
+                fields.invalid("services", "must name at least one service");
+            }
+            for service in &services {
+                if !KNOWN_SERVICES.contains(&service.as_str()) {
+                    fields.invalid(
+                        "services",
+                        format!(
+                            "unknown service `{}`, expected one of {}",
+                            service,
+                            KNOWN_SERVICES.join(", ")
+                        ),
+                    );
+                }
+            }
+            config.services = services;
+        }
+        if let Some(mb) = fields.optional::<usize>("max_memory_mb") {
+            if mb < MIN_MEMORY_MB {
+                fields.invalid(
+                    "max_memory_mb",
+                    format!("must be at least {}", MIN_MEMORY_MB),
+                );
+            }
+            config.max_memory_mb = mb;
+        }
+        if let Some(enabled) = fields.optional("enable_distributed") {
+            config.enable_distributed = enabled;
+        }
+        fields.finish()?;
+        Ok(config)
+    }
+}
+
+/// Field-by-field reader for a config object
+///
+/// Every problem is recorded rather than returned, so [`finish`](Self::finish)
+/// reports all invalid fields at once; keys that were never read are
+/// reported as unknown.
+pub(crate) struct Fields {
+    map: serde_json::Map<String, serde_json::Value>,
+    prefix: String,
+    errors: Vec<FieldError>,
+}
+
+impl Fields {
+    /// `prefix` is prepended to field names in errors (e.g. `"model_config."`)
+    pub(crate) fn new(value: serde_json::Value, prefix: &str) -> ValidationResult<Self> {
+        match value {
+            serde_json::Value::Object(map) => Ok(Self {
+                map,
+                prefix: prefix.to_string(),
+                errors: Vec::new(),
+            }),
+            other => Err(vec![FieldError::new(
+                prefix.trim_end_matches('.'),
+                format!("expected an object, got {}", json_type(&other)),
+            )]),
+        }
+    }
+
+    /// Read `name` if present; a value of the wrong type is recorded
+    pub(crate) fn optional<T: DeserializeOwned>(&mut self, name: &str) -> Option<T> {
+        let value = self.map.remove(name)?;
+        if value.is_null() {
+            return None;
+        }
+        match serde_json::from_value(value) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.invalid(name, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Read `name`, recording it as missing when absent
+    pub(crate) fn required<T: DeserializeOwned>(&mut self, name: &str) -> Option<T> {
+        if self.map.get(name).is_none_or(|v| v.is_null()) {
+            self.invalid(name, "is required");
+            return None;
+        }
+        self.optional(name)
+    }
+
+    /// Record `name` as invalid
+    pub(crate) fn invalid(&mut self, name: &str, reason: impl Into<String>) {
+        self.errors
+            .push(FieldError::new(format!("{}{}", self.prefix, name), reason));
+    }
+
+    /// Record the errors of a nested object read with its own [`Fields`]
+    pub(crate) fn extend(&mut self, errors: Vec<FieldError>) {
+        self.errors.extend(errors);
+    }
+
+    /// Report unknown keys and every recorded error
+    pub(crate) fn finish(mut self) -> ValidationResult<()> {
+        let unknown: Vec<String> = self.map.keys().cloned().collect();
+        for key in unknown {
+            self.invalid(&key, "unknown field");
+        }
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Array(_) => "an array",
+        serde_json::Value::Object(_) => "an object",
+    }
+}
+
+/// Convert a JavaScript config object; `undefined` and `null` become `null`
+///
+/// Functions and other values JSON cannot hold are rejected here, so read
+/// callback options off the object before calling this.
+pub(crate) fn from_js(value: JsValue) -> ValidationResult<serde_json::Value> {
+    if value.is_undefined() || value.is_null() {
+        return Ok(serde_json::Value::Null);
+    }
+    serde_wasm_bindgen::from_value(value).map_err(|e| vec![FieldError::new("", e.to_string())])
+}
+
+/// The `ConfigValidationError` thrown to JavaScript
+pub(crate) fn to_js_error(errors: &[FieldError]) -> JsValue {
+    let summary: Vec<String> = errors
+        .iter()
+        .map(|e| match e.field.as_str() {
+            "" => e.reason.clone(),
+            field => format!("{}: {}", field, e.reason),
+        })
+        .collect();
+    let error = js_sys::Error::new(&format!("Invalid config: {}", summary.join("; ")));
+    error.set_name("ConfigValidationError");
+    let list = serde_wasm_bindgen::to_value(errors).unwrap_or(JsValue::NULL);
+    let _ = js_sys::Reflect::set(&error, &"errors".into(), &list);
+    error.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn null_gives_defaults_and_fields_override() {
+        let config = KwaaiNetConfig::from_json(serde_json::Value::Null).unwrap();
+        assert_eq!(config.services, vec!["compute"]);
+
+        let config =
+            KwaaiNetConfig::from_json(json!({ "max_memory_mb": 2048, "services": ["storage"] }))
+                .unwrap();
+        assert_eq!(config.max_memory_mb, 2048);
+        assert_eq!(config.services, vec!["storage"]);
+        assert!(!config.enable_distributed);
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let errors = KwaaiNetConfig::from_json(json!({
+            "services": ["compute", "mining"],
+            "max_memory_mb": 16,
+            "enable_distributed": "yes",
+            "max_memory": 512,
+        }))
+        .unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "services",
+                "max_memory_mb",
+                "enable_distributed",
+                "max_memory"
+            ]
+        );
+        assert!(errors[0].reason.contains("mining"));
+        assert_eq!(errors[3].reason, "unknown field");
+    }
+
+    #[test]
+    fn rejects_non_objects() {
+        let errors = KwaaiNetConfig::from_json(json!(["compute"])).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![FieldError::new("", "expected an object, got an array")]
+        );
+    }
+
+    #[test]
+    fn required_fields_use_prefix() {
+        let mut fields = Fields::new(json!({ "b": 1 }), "outer.").unwrap();
+        assert_eq!(fields.required::<u32>("b"), Some(1));
+        assert_eq!(fields.required::<u32>("a"), None);
+        assert_eq!(
+            fields.finish().unwrap_err(),
+            vec![FieldError::new("outer.a", "is required")]
+        );
+    }
+}
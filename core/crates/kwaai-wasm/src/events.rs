@@ -0,0 +1,158 @@
+//! Events pushed to JavaScript listeners registered with `KwaaiNet.on()`
+//!
+//! Every listener is called as `callback(event, data)`, where `event` is one of
+//! the names below and `data` is a plain object:
+//!
+//! | event        | data                                              |
+//! |--------------|---------------------------------------------------|
+//! | `connection` | `{ state }`: `connecting`, `connected` or `disconnected` |
+//! | `peers`      | `{ count }`                                       |
+//! | `download`   | `{ model, downloaded_bytes, total_bytes }`        |
+//! | `token`      | `{ index, text }`                                 |
+//!
+//! Listening to `*` receives every event.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Event name that matches every event
+pub const ANY_EVENT: &str = "*";
+
+/// Names accepted by `KwaaiNet.on()`
+pub const EVENT_NAMES: [&str; 4] = ["connection", "peers", "download", "token"];
+
+#[wasm_bindgen(typescript_custom_section)]
+const EVENTS_TS: &str = r#"
+export type EventName = "connection" | "peers" | "download" | "token";
+
+export interface ConnectionEvent {
+  state: "connecting" | "connected" | "disconnected";
+}
+
+export interface PeersEvent {
+  count: number;
+}
+
+export interface DownloadEvent {
+  model: string;
+  downloaded_bytes: number;
+  /** Omitted while the size is unknown */
+  total_bytes?: number;
+}
+
+export interface TokenEvent {
+  /** Position of the token in the generation, from 0 */
+  index: number;
+  text: string;
+}
+
+export type EventData = ConnectionEvent | PeersEvent | DownloadEvent | TokenEvent;
+"#;
+
+/// P2P connection state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// An event emitted to JavaScript
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+    Connection {
+        state: ConnectionState,
+    },
+    Peers {
+        count: usize,
+    },
+    Download {
+        model: String,
+        downloaded_bytes: u64,
+        /// `None` while the size is unknown
+        total_bytes: Option<u64>,
+    },
+    Token {
+        /// Position of the token in the generation, from 0
+        index: usize,
+        text: String,
+    },
+}
+
+impl Event {
+    /// Name passed to listeners as the first argument
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Connection { .. } => "connection",
+            Event::Peers { .. } => "peers",
+            Event::Download { .. } => "download",
+            Event::Token { .. } => "token",
+        }
+    }
+}
+
+/// Registered listeners, dispatched in registration order
+#[derive(Default)]
+pub(crate) struct EventEmitter {
+    listeners: Vec<Listener>,
+    next_id: u32,
+}
+
+struct Listener {
+    id: u32,
+    event: String,
+    callback: js_sys::Function,
+}
+
+impl EventEmitter {
+    /// Add a listener for `event` (or [`ANY_EVENT`]) and return its id
+    pub(crate) fn add(&mut self, event: &str, callback: js_sys::Function) -> Option<u32> {
+        if event != ANY_EVENT && !EVENT_NAMES.contains(&event) {
+            return None;
+        }
+        self.next_id += 1;
+        self.listeners.push(Listener {
+            id: self.next_id,
+            event: event.to_string(),
+            callback,
+        });
+        Some(self.next_id)
+    }
+
+    /// Remove a listener; returns whether it was registered
+    pub(crate) fn remove(&mut self, id: u32) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|l| l.id != id);
+        self.listeners.len() != before
+    }
+
+    /// Call every listener of `event`
+    ///
+    /// A throwing listener is logged and does not stop the others.
+    pub(crate) fn emit(&self, event: &Event) {
+        let name = event.name();
+        let mut targets = self
+            .listeners
+            .iter()
+            .filter(|l| l.event == name || l.event == ANY_EVENT)
+            .peekable();
+        if targets.peek().is_none() {
+            return;
+        }
+        let data = match serde_wasm_bindgen::to_value(event) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("Failed to serialize {} event: {}", name, e);
+                return;
+            }
+        };
+        let name = JsValue::from_str(name);
+        for listener in targets {
+            if let Err(e) = listener.callback.call2(&JsValue::NULL, &name, &data) {
+                tracing::warn!("Event listener threw: {:?}", e);
+            }
+        }
+    }
+}
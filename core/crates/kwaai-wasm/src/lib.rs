@@ -15,13 +15,27 @@
 //!     const kwaainet = new KwaaiNet();
 //!     await kwaainet.initialize({ services: ['compute'] });
 //!
+//!     kwaainet.on("token", (event, data) => console.log(data.text));
+//!     kwaainet.on("connection", (event, data) => console.log(data.state));
+//!
 //!     const result = await kwaainet.generate("Hello, world!");
 //!     console.log(result);
 //! }
 //! ```
+//!
+//! See [`events`] for every event and its payload, and [`worker`] for
+//! contributing compute from the browser.
+
+pub mod block;
+pub mod config;
+pub mod events;
+pub mod worker;
 
+pub use config::KwaaiNetConfig;
+use events::{ConnectionState, Event, EventEmitter};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
+use worker::{Contribution, ContributionOptions, ContributionTransport};
 
 #[cfg(target_arch = "wasm32")]
 use tracing_wasm;
@@ -45,27 +59,14 @@ pub struct KwaaiNet {
     initialized: bool,
     /// Configuration
     config: KwaaiNetConfig,
-}
-
-/// Configuration for KwaaiNet
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct KwaaiNetConfig {
-    /// Services to enable
-    pub services: Vec<String>,
-    /// Maximum memory usage (MB)
-    pub max_memory_mb: usize,
-    /// Enable distributed inference
-    pub enable_distributed: bool,
-}
-
-impl Default for KwaaiNetConfig {
-    fn default() -> Self {
-        Self {
-            services: vec!["compute".to_string()],
-            max_memory_mb: 1024,
-            enable_distributed: false,
-        }
-    }
+    /// P2P connection state
+    connection: ConnectionState,
+    /// Connected peers
+    peer_count: usize,
+    /// Listeners registered with `on()`
+    events: EventEmitter,
+    /// Blocks served while contributing
+    contribution: Option<Contribution>,
 }
 
 #[wasm_bindgen]
@@ -76,17 +77,46 @@ impl KwaaiNet {
         Self {
             initialized: false,
             config: KwaaiNetConfig::default(),
+            connection: ConnectionState::Disconnected,
+            peer_count: 0,
+            events: EventEmitter::default(),
+            contribution: None,
         }
     }
 
+    /// Call `callback(event, data)` whenever `event` occurs
+    ///
+    /// `event` is `connection`, `peers`, `download`, `token`, or `*` for all
+    /// of them. Returns an id for `off()`.
+    #[wasm_bindgen]
+    pub fn on(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "EventName | \"*\"")] event: String,
+        callback: EventCallback,
+    ) -> Result<u32, JsError> {
+        self.events
+            .add(&event, callback.unchecked_into())
+            .ok_or_else(|| JsError::new(&format!("Unknown event: {}", event)))
+    }
+
+    /// Remove a listener added by `on()`; returns whether it existed
+    #[wasm_bindgen]
+    pub fn off(&mut self, listener_id: u32) -> bool {
+        self.events.remove(listener_id)
+    }
+
     /// Initialize with configuration
+    ///
+    /// Throws a `ConfigValidationError` listing every invalid field; omitted
+    /// fields keep their defaults.
     #[wasm_bindgen]
-    pub async fn initialize(&mut self, config: JsValue) -> Result<(), JsError> {
-        // Parse configuration
-        if !config.is_undefined() && !config.is_null() {
-            self.config = serde_wasm_bindgen::from_value(config)
-                .map_err(|e| JsError::new(&format!("Invalid config: {}", e)))?;
-        }
+    pub async fn initialize(
+        &mut self,
+        #[wasm_bindgen(unchecked_optional_param_type = "KwaaiNetConfig")] config: JsValue,
+    ) -> Result<(), JsValue> {
+        self.config = config::from_js(config)
+            .and_then(KwaaiNetConfig::from_json)
+            .map_err(|errors| config::to_js_error(&errors))?;
 
         tracing::info!("Initializing KwaaiNet with config: {:?}", self.config);
 
@@ -112,7 +142,14 @@ impl KwaaiNet {
         tracing::debug!("Generate called with prompt: {}", prompt);
 
         // TODO: Actual inference implementation
-        Ok(format!("[Generated response for: {}]", prompt))
+        let response = format!("[Generated response for: {}]", prompt);
+        for (index, text) in response.split_inclusive(' ').enumerate() {
+            self.events.emit(&Event::Token {
+                index,
+                text: text.to_string(),
+            });
+        }
+        Ok(response)
     }
 
     /// Connect to the P2P network
@@ -123,21 +160,49 @@ impl KwaaiNet {
         }
 
         tracing::info!("Connecting to {} bootstrap peers", bootstrap_peers.len());
+        self.set_connection(ConnectionState::Connecting);
 
         // TODO: P2P connection implementation
+        self.set_connection(ConnectionState::Connected);
+        self.set_peer_count(bootstrap_peers.len());
         Ok(())
     }
 
+    /// Disconnect from the P2P network
+    #[wasm_bindgen]
+    pub fn disconnect(&mut self) {
+        tracing::info!("Disconnecting from the network");
+        self.set_peer_count(0);
+        self.set_connection(ConnectionState::Disconnected);
+    }
+
     /// Start contributing compute to the network
+    ///
+    /// Serves blocks `[options.start_block, options.end_block)` loaded from
+    /// `weights` (SafeTensors) and announces them through `transport`; see
+    /// [`worker`] for the options and the transport interface. Invalid
+    /// options throw a `ConfigValidationError`.
     #[wasm_bindgen]
-    pub async fn start_contributing(&mut self) -> Result<(), JsError> {
+    pub async fn start_contributing(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "ContributionOptions")] options: JsValue,
+        weights: Vec<u8>,
+        transport: ContributionTransport,
+    ) -> Result<(), JsValue> {
         if !self.initialized {
-            return Err(JsError::new("KwaaiNet not initialized"));
+            return Err(JsError::new("KwaaiNet not initialized").into());
+        }
+        if self.contribution.is_some() {
+            return Err(JsError::new("Already contributing").into());
         }
 
+        let (options, scheduler) =
+            ContributionOptions::from_js(options).map_err(|errors| config::to_js_error(&errors))?;
         tracing::info!("Starting compute contribution");
-
-        // TODO: Register as compute provider
+        let contribution = Contribution::start(options, scheduler, weights, transport)
+            .await
+            .map_err(|e| JsError::new(&e))?;
+        self.contribution = Some(contribution);
         Ok(())
     }
 
@@ -145,22 +210,41 @@ impl KwaaiNet {
     #[wasm_bindgen]
     pub fn stop_contributing(&mut self) {
         tracing::info!("Stopping compute contribution");
-        // TODO: Unregister from network
+        if let Some(contribution) = self.contribution.take() {
+            contribution.stop();
+        }
     }
 
     /// Get current status
-    #[wasm_bindgen]
+    #[wasm_bindgen(unchecked_return_type = "Status")]
     pub fn status(&self) -> JsValue {
         let status = Status {
             initialized: self.initialized,
-            connected: false, // TODO: Check actual connection
-            contributing: false,
+            connected: self.connection == ConnectionState::Connected,
+            peers: self.peer_count,
+            contributing: self.contribution.is_some(),
             models_loaded: 0,
         };
         serde_wasm_bindgen::to_value(&status).unwrap_or(JsValue::NULL)
     }
 }
 
+impl KwaaiNet {
+    fn set_connection(&mut self, state: ConnectionState) {
+        if self.connection != state {
+            self.connection = state;
+            self.events.emit(&Event::Connection { state });
+        }
+    }
+
+    fn set_peer_count(&mut self, count: usize) {
+        if self.peer_count != count {
+            self.peer_count = count;
+            self.events.emit(&Event::Peers { count });
+        }
+    }
+}
+
 impl Default for KwaaiNet {
     fn default() -> Self {
         Self::new()
@@ -172,15 +256,28 @@ impl Default for KwaaiNet {
 struct Status {
     initialized: bool,
     connected: bool,
+    peers: usize,
     contributing: bool,
     models_loaded: usize,
 }
 
+#[wasm_bindgen(typescript_custom_section)]
+const STATUS_TS: &str = r#"
+/** Returned by `KwaaiNet.status()`. */
+export interface Status {
+  initialized: boolean;
+  connected: boolean;
+  peers: number;
+  contributing: boolean;
+  models_loaded: number;
+}
+"#;
+
 /// Event callback type
 #[wasm_bindgen]
 extern "C" {
     /// JavaScript callback function type
-    #[wasm_bindgen(typescript_type = "(event: string, data: any) => void")]
+    #[wasm_bindgen(typescript_type = "(event: EventName, data: EventData) => void")]
     pub type EventCallback;
 }
 
@@ -192,14 +289,14 @@ pub fn version() -> String {
 
 /// Get supported features
 #[wasm_bindgen]
-pub fn supported_features() -> JsValue {
-    let features = vec![
+pub fn supported_features() -> Vec<String> {
+    let features = [
         "inference",
         "p2p",
         "compression",
         // "distributed", // TODO: Enable when ready
     ];
-    serde_wasm_bindgen::to_value(&features).unwrap_or(JsValue::NULL)
+    features.iter().map(|f| f.to_string()).collect()
 }
 
 #[cfg(test)]
@@ -217,4 +314,35 @@ mod tests {
         let v = version();
         assert!(!v.is_empty());
     }
+
+    #[test]
+    fn test_state_changes_without_listeners() {
+        let mut kwaainet = KwaaiNet::new();
+        kwaainet.set_connection(ConnectionState::Connected);
+        kwaainet.set_peer_count(3);
+        assert_eq!(kwaainet.connection, ConnectionState::Connected);
+        assert_eq!(kwaainet.peer_count, 3);
+        assert!(!kwaainet.off(1));
+    }
+
+    #[test]
+    fn test_event_names() {
+        let events = [
+            Event::Connection {
+                state: ConnectionState::Connecting,
+            },
+            Event::Peers { count: 0 },
+            Event::Download {
+                model: "m".to_string(),
+                downloaded_bytes: 0,
+                total_bytes: None,
+            },
+            Event::Token {
+                index: 0,
+                text: String::new(),
+            },
+        ];
+        let names: Vec<&str> = events.iter().map(Event::name).collect();
+        assert_eq!(names, events::EVENT_NAMES);
+    }
 }
@@ -0,0 +1,758 @@
+//! Browser worker mode: serve a small block range to the network
+//!
+//! `KwaaiNet.start_contributing()` loads a [`BlockSpan`], announces it in the
+//! Hivemind DHT and answers `/kwaai/inference/1.0.0` requests, all through a
+//! [`ContributionTransport`] supplied by the page. A browser cannot accept
+//! inbound connections, so the transport is expected to be reachable through
+//! a libp2p circuit relay (e.g. js-libp2p with a circuit relay transport),
+//! and the announcement says so via `using_relay`.
+//!
+//! Forward passes run one block at a time. Each request first waits for the
+//! page to go idle, and whenever a slice of work uses up its time budget the
+//! worker yields again through a `requestIdleCallback`-style scheduler, so
+//! serving never freezes the UI. Pages can pass their own `scheduler`.
+
+use crate::block::{BlockConfig, BlockSpan, MAX_SESSIONS};
+use crate::config::{self, FieldError, Fields, ValidationResult};
+use candle_core::{DType, Device};
+use futures::lock::Mutex;
+use js_sys::{Function, Object, Promise, Reflect, Uint8Array};
+use kwaai_compression::{TensorWire, WireDType};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, spawn_local, JsFuture};
+
+/// Protocol the block servers of the network answer on
+pub const INFERENCE_PROTO: &str = "/kwaai/inference/1.0.0";
+
+/// Most blocks a browser node may serve
+pub const MAX_BROWSER_BLOCKS: usize = 4;
+
+/// Lifetime of a block announcement; refreshed every [`ANNOUNCE_INTERVAL_MS`]
+const ANNOUNCE_TTL_SECS: f64 = 360.0;
+const ANNOUNCE_INTERVAL_MS: i32 = 120_000;
+
+/// Longest a forward pass waits for the page to go idle before running anyway
+const IDLE_TIMEOUT_MS: i32 = 1_000;
+
+/// Hivemind `ServerState` values
+const STATE_OFFLINE: i32 = 0;
+const STATE_ONLINE: i32 = 2;
+
+#[wasm_bindgen(typescript_custom_section)]
+const CONTRIBUTION_TRANSPORT_TS: &str = r#"
+/** Network access for `start_contributing`, e.g. js-libp2p behind a circuit relay. */
+export interface ContributionTransport {
+  /** Base58 peer ID of this node */
+  peerId(): string;
+  /** Send one Hivemind DHT STORE; resolves to whether a peer accepted it */
+  store(key: Uint8Array, subkey: Uint8Array, value: Uint8Array, expirationTime: number): Promise<boolean>;
+  /** Answer unary requests on `protocol` */
+  handle(protocol: string, handler: (request: Uint8Array) => Promise<Uint8Array>): void;
+  unhandle(protocol: string): void;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// JavaScript transport used while contributing
+    #[wasm_bindgen(typescript_type = "ContributionTransport")]
+    #[derive(Clone)]
+    pub type ContributionTransport;
+
+    #[wasm_bindgen(method, js_name = peerId)]
+    fn peer_id(this: &ContributionTransport) -> String;
+
+    #[wasm_bindgen(method, catch)]
+    async fn store(
+        this: &ContributionTransport,
+        key: Vec<u8>,
+        subkey: Vec<u8>,
+        value: Vec<u8>,
+        expiration_time: f64,
+    ) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method)]
+    fn handle(
+        this: &ContributionTransport,
+        protocol: &str,
+        handler: &Closure<dyn FnMut(Uint8Array) -> Promise>,
+    );
+
+    #[wasm_bindgen(method)]
+    fn unhandle(this: &ContributionTransport, protocol: &str);
+}
+
+// ── Options ───────────────────────────────────────────────────────────────────
+
+#[wasm_bindgen(typescript_custom_section)]
+const CONTRIBUTION_OPTIONS_TS: &str = r#"
+/** Options of `start_contributing`. */
+export interface ContributionOptions {
+  /** DHT prefix of the model, e.g. `Llama-3-2-1B-Instruct-hf` */
+  dht_prefix: string;
+  /** First served block; browser nodes serve middle blocks, so at least 1 */
+  start_block: number;
+  /** End of the served range (exclusive), at most 4 blocks after `start_block` */
+  end_block: number;
+  /** The model's Hugging Face `config.json` */
+  model_config: BlockConfig;
+  public_name?: string;
+  /** Whether the node is reached through a circuit relay (default true) */
+  using_relay?: boolean;
+  /** Longest stretch of compute in ms between yields to the page (default 10) */
+  slice_ms?: number;
+  /**
+   * Calls `callback` when the page can spare time, optionally with an
+   * `IdleDeadline`. Defaults to `requestIdleCallback`, or `setTimeout`.
+   */
+  scheduler?: (callback: (deadline?: IdleDeadline) => void) => void;
+}
+
+/** Fields of a Hugging Face `config.json` used to run blocks; others are ignored. */
+export interface BlockConfig {
+  hidden_size: number;
+  num_attention_heads: number;
+  num_key_value_heads?: number;
+  intermediate_size: number;
+  rope_theta?: number;
+  rms_norm_eps?: number;
+  max_position_embeddings?: number;
+  [key: string]: unknown;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// A [`ContributionOptions`] object
+    #[wasm_bindgen(typescript_type = "ContributionOptions")]
+    pub type ContributionOptionsObject;
+}
+
+/// Options of `start_contributing()`
+///
+/// `scheduler` is read separately: a `(callback) => void` function that calls
+/// `callback` when the page can spare time, optionally with an
+/// `IdleDeadline`. Defaults to `requestIdleCallback`, or `setTimeout` where
+/// that is missing (e.g. in workers).
+#[derive(Debug, Clone)]
+pub struct ContributionOptions {
+    /// DHT prefix of the model, e.g. `Llama-3-2-1B-Instruct-hf`
+    pub dht_prefix: String,
+    pub start_block: usize,
+    pub end_block: usize,
+    /// The model's Hugging Face `config.json`
+    pub model_config: BlockConfig,
+    pub public_name: Option<String>,
+    pub using_relay: bool,
+    /// Longest stretch of compute between yields to the page
+    pub slice_ms: f64,
+}
+
+impl ContributionOptions {
+    /// Parse and validate an options object, reporting every invalid field
+    pub fn from_json(value: serde_json::Value) -> ValidationResult<Self> {
+        let mut fields = Fields::new(value, "")?;
+        let dht_prefix = fields.required::<String>("dht_prefix");
+        if dht_prefix.as_ref().is_some_and(|p| p.is_empty()) {
+            fields.invalid("dht_prefix", "must not be empty");
+        }
+        let start_block = fields.required::<usize>("start_block");
+        let end_block = fields.required::<usize>("end_block");
+        if start_block == Some(0) {
+            fields.invalid(
+                "start_block",
+                "browser nodes serve middle blocks; must be > 0",
+            );
+        }
+        if let (Some(start), Some(end)) = (start_block, end_block) {
+            let blocks = end.saturating_sub(start);
+            if blocks == 0 {
+                fields.invalid(
+                    "end_block",
+                    format!("empty block range [{}, {})", start, end),
+                );
+            } else if blocks > MAX_BROWSER_BLOCKS {
+                fields.invalid(
+                    "end_block",
+                    format!(
+                        "browser nodes serve at most {} blocks, got {}",
+                        MAX_BROWSER_BLOCKS, blocks
+                    ),
+                );
+            }
+        }
+        let model_config = fields
+            .required::<serde_json::Value>("model_config")
+            .and_then(|value| match parse_block_config(value) {
+                Ok(config) => Some(config),
+                Err(errors) => {
+                    fields.extend(errors);
+                    None
+                }
+            });
+        let public_name = fields.optional::<String>("public_name");
+        let using_relay = fields.optional("using_relay").unwrap_or(true);
+        let slice_ms = fields.optional::<f64>("slice_ms").unwrap_or(10.0);
+        if slice_ms.is_nan() || slice_ms <= 0.0 {
+            fields.invalid("slice_ms", "must be positive");
+        }
+        fields.finish()?;
+
+        let (Some(dht_prefix), Some(start_block), Some(end_block), Some(model_config)) =
+            (dht_prefix, start_block, end_block, model_config)
+        else {
+            unreachable!("missing fields are reported by finish()");
+        };
+        Ok(Self {
+            dht_prefix,
+            start_block,
+            end_block,
+            model_config,
+            public_name,
+            using_relay,
+            slice_ms,
+        })
+    }
+
+    /// Parse a JavaScript options object, splitting off its `scheduler`
+    pub(crate) fn from_js(options: JsValue) -> ValidationResult<(Self, Option<Function>)> {
+        if !options.is_object() {
+            return Self::from_json(config::from_js(options)?).map(|o| (o, None));
+        }
+        let scheduler = Reflect::get(&options, &"scheduler".into()).unwrap_or(JsValue::UNDEFINED);
+        let scheduler = if scheduler.is_undefined() || scheduler.is_null() {
+            None
+        } else if let Some(f) = scheduler.dyn_ref::<Function>() {
+            Some(f.clone())
+        } else {
+            return Err(vec![FieldError::new("scheduler", "expected a function")]);
+        };
+        // Functions have no JSON form, so parse a copy without the scheduler
+        let copy = Object::assign(&Object::new(), options.unchecked_ref());
+        let _ = Reflect::delete_property(&copy, &"scheduler".into());
+        let parsed = Self::from_json(config::from_js(copy.into())?)?;
+        Ok((parsed, scheduler))
+    }
+}
+
+/// Parse `model_config`; unknown keys are allowed, as in any `config.json`
+fn parse_block_config(value: serde_json::Value) -> ValidationResult<BlockConfig> {
+    if !value.is_object() {
+        return Err(Fields::new(value, "model_config.")
+            .err()
+            .unwrap_or_default());
+    }
+    let config: BlockConfig = serde_json::from_value(value)
+        .map_err(|e| vec![FieldError::new("model_config", e.to_string())])?;
+    let mut errors = Vec::new();
+    if config.num_attention_heads == 0
+        || !config
+            .hidden_size
+            .is_multiple_of(config.num_attention_heads)
+    {
+        errors.push(FieldError::new(
+            "model_config.num_attention_heads",
+            format!("must divide hidden_size ({})", config.hidden_size),
+        ));
+    }
+    if let Some(kv_heads) = config.num_key_value_heads {
+        if kv_heads == 0 || !config.num_attention_heads.is_multiple_of(kv_heads) {
+            errors.push(FieldError::new(
+                "model_config.num_key_value_heads",
+                format!(
+                    "must divide num_attention_heads ({})",
+                    config.num_attention_heads
+                ),
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
+    }
+}
+
+// ── DHT announcement ──────────────────────────────────────────────────────────
+
+/// DHT key of a raw key string: SHA-1 of its msgpack encoding, as in Hivemind
+pub fn dht_id(raw_key: &str) -> Vec<u8> {
+    let packed = rmp_serde::to_vec(raw_key).expect("msgpack key");
+    Sha1::new().chain_update(&packed).finalize().to_vec()
+}
+
+/// Server info for the block records: `ExtType(64, [state, throughput, {fields}])`,
+/// the encoding native nodes announce
+pub fn server_record(options: &ContributionOptions, peer_id: &str, state: i32) -> Vec<u8> {
+    let cache_tokens = MAX_SESSIONS * options.model_config.max_position_embeddings;
+    let mut fields: Vec<(rmpv::Value, rmpv::Value)> = vec![
+        ("start_block".into(), (options.start_block as i64).into()),
+        ("end_block".into(), (options.end_block as i64).into()),
+        (
+            "version".into(),
+            concat!("kwaai-wasm-", env!("CARGO_PKG_VERSION")).into(),
+        ),
+        ("torch_dtype".into(), "float32".into()),
+        ("using_relay".into(), options.using_relay.into()),
+        ("cache_tokens_left".into(), (cache_tokens as i64).into()),
+        ("adapters".into(), rmpv::Value::Array(Vec::new())),
+        ("peer_id".into(), peer_id.into()),
+    ];
+    if let Some(name) = &options.public_name {
+        fields.push(("public_name".into(), name.as_str().into()));
+    }
+    let inner = rmpv::Value::Array(vec![
+        state.into(),
+        // Unmeasured; clients route around slow spans on their own.
+        1.0f64.into(),
+        rmpv::Value::Map(fields),
+    ]);
+    let mut inner_bytes = Vec::new();
+    rmpv::encode::write_value(&mut inner_bytes, &inner).expect("write to Vec");
+    let mut out = Vec::new();
+    rmpv::encode::write_value(&mut out, &rmpv::Value::Ext(64, inner_bytes)).expect("write to Vec");
+    out
+}
+
+/// Store the block records through `transport`; returns how many were accepted
+async fn announce(
+    transport: &ContributionTransport,
+    options: &ContributionOptions,
+    peer_id: &str,
+    state: i32,
+) -> usize {
+    let subkey = rmp_serde::to_vec(peer_id).expect("msgpack subkey");
+    let value = server_record(options, peer_id, state);
+    let expiration = js_sys::Date::now() / 1000.0 + ANNOUNCE_TTL_SECS;
+    let mut accepted = 0;
+    for block in options.start_block..options.end_block {
+        let key = dht_id(&format!("{}.{}", options.dht_prefix, block));
+        match transport
+            .store(key, subkey.clone(), value.clone(), expiration)
+            .await
+        {
+            Ok(ok) if ok.as_bool() == Some(true) => accepted += 1,
+            Ok(_) => {}
+            Err(e) => tracing::warn!("DHT store for block {} failed: {:?}", block, e),
+        }
+    }
+    accepted
+}
+
+// ── Wire types ────────────────────────────────────────────────────────────────
+//
+// The subset of the native block protocol a middle server needs; field names
+// and encoding match `kwaainet`'s `block_rpc` so coordinators can route
+// through browser nodes unchanged.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PayloadType {
+    TokenIds,
+    HiddenStates,
+}
+
+#[derive(Debug, Deserialize)]
+struct InferenceRequest {
+    session_id: u64,
+    seq_pos: u32,
+    payload_type: PayloadType,
+    #[serde(flatten)]
+    tensor: TensorWire,
+    #[serde(default)]
+    active_adapter: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct InferenceResponse {
+    session_id: u64,
+    response_type: &'static str,
+    #[serde(flatten)]
+    tensor: TensorWire,
+    error: Option<String>,
+    draining: bool,
+}
+
+// ── Throttling ────────────────────────────────────────────────────────────────
+
+/// Time the worker may compute before yielding to the page again
+#[derive(Debug, Clone, Copy)]
+struct SliceBudget {
+    started_ms: f64,
+    budget_ms: f64,
+}
+
+impl SliceBudget {
+    fn new(started_ms: f64, budget_ms: f64) -> Self {
+        Self {
+            started_ms,
+            budget_ms,
+        }
+    }
+
+    fn exhausted(&self, now_ms: f64) -> bool {
+        now_ms - self.started_ms >= self.budget_ms
+    }
+}
+
+/// Call `callback` when the page has time to spare
+fn schedule(scheduler: Option<&Function>, callback: &Function) -> Result<(), JsValue> {
+    if let Some(scheduler) = scheduler {
+        return scheduler.call1(&JsValue::NULL, callback).map(drop);
+    }
+    let global = js_sys::global();
+    let idle = Reflect::get(&global, &"requestIdleCallback".into())?;
+    if let Some(idle) = idle.dyn_ref::<Function>() {
+        let options = js_sys::Object::new();
+        Reflect::set(&options, &"timeout".into(), &IDLE_TIMEOUT_MS.into())?;
+        return idle.call2(&global, callback, &options).map(drop);
+    }
+    let set_timeout: Function = Reflect::get(&global, &"setTimeout".into())?.dyn_into()?;
+    set_timeout.call2(&global, callback, &0.into()).map(drop)
+}
+
+/// Wait for idle time and return the budget for the next slice
+///
+/// The budget is the idle deadline's `timeRemaining()` when the scheduler
+/// passes one, capped at `slice_ms`.
+async fn yield_to_page(
+    scheduler: Option<&Function>,
+    slice_ms: f64,
+) -> Result<SliceBudget, JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        if let Err(e) = schedule(scheduler, &resolve) {
+            let _ = reject.call1(&JsValue::NULL, &e);
+        }
+    });
+    let deadline = JsFuture::from(promise).await?;
+    let remaining = Reflect::get(&deadline, &"timeRemaining".into())
+        .ok()
+        .and_then(|f| f.dyn_into::<Function>().ok())
+        .and_then(|f| f.call0(&deadline).ok())
+        .and_then(|v| v.as_f64())
+        .unwrap_or(slice_ms);
+    Ok(SliceBudget::new(
+        js_sys::Date::now(),
+        remaining.min(slice_ms),
+    ))
+}
+
+// ── Serving ───────────────────────────────────────────────────────────────────
+
+struct Worker {
+    /// Held for a whole request, so requests run one after another
+    span: Mutex<BlockSpan>,
+    scheduler: Option<Function>,
+    slice_ms: f64,
+}
+
+impl Worker {
+    async fn serve(&self, raw: &[u8]) -> Vec<u8> {
+        let response = match rmp_serde::from_slice::<InferenceRequest>(raw) {
+            Ok(request) => {
+                let session_id = request.session_id;
+                match self.forward(request).await {
+                    Ok(tensor) => InferenceResponse {
+                        session_id,
+                        response_type: "hidden_states",
+                        tensor,
+                        error: None,
+                        draining: false,
+                    },
+                    Err(e) => error_response(session_id, e),
+                }
+            }
+            Err(e) => error_response(0, format!("deserialise InferenceRequest: {e}")),
+        };
+        rmp_serde::to_vec_named(&response).unwrap_or_default()
+    }
+
+    async fn forward(&self, request: InferenceRequest) -> Result<TensorWire, String> {
+        if request.payload_type != PayloadType::HiddenStates {
+            return Err("browser nodes serve middle blocks only; send hidden states".to_string());
+        }
+        if request.active_adapter.is_some() {
+            return Err("browser nodes do not serve LoRA adapters".to_string());
+        }
+        let mut x = request
+            .tensor
+            .to_tensor(&Device::Cpu)
+            .map_err(|e| e.to_string())
+            .and_then(|t| t.to_dtype(DType::F32).map_err(|e| e.to_string()))
+            .map_err(|e| format!("decode hidden states: {e}"))?;
+        let session_id = request.session_id;
+        let seq_pos = request.seq_pos as usize;
+
+        let mut span = self.span.lock().await;
+        span.begin(session_id, seq_pos).map_err(|e| e.to_string())?;
+        let mut budget = SliceBudget::new(f64::NEG_INFINITY, 0.0);
+        for i in 0..span.len() {
+            if budget.exhausted(js_sys::Date::now()) {
+                budget = yield_to_page(self.scheduler.as_ref(), self.slice_ms)
+                    .await
+                    .map_err(|e| format!("scheduler failed: {e:?}"))?;
+            }
+            x = span
+                .forward_block(i, session_id, &x, seq_pos)
+                .map_err(|e| e.to_string())?;
+        }
+        drop(span);
+        TensorWire::from_tensor(&x, WireDType::F16).map_err(|e| e.to_string())
+    }
+}
+
+fn error_response(session_id: u64, error: String) -> InferenceResponse {
+    tracing::warn!("Inference request failed: {}", error);
+    InferenceResponse {
+        session_id,
+        response_type: "hidden_states",
+        tensor: TensorWire::default(),
+        error: Some(error),
+        draining: false,
+    }
+}
+
+/// A running contribution; dropping it without [`stop`](Self::stop) leaves
+/// the handler registered
+pub(crate) struct Contribution {
+    transport: ContributionTransport,
+    options: ContributionOptions,
+    peer_id: String,
+    _handler: Closure<dyn FnMut(Uint8Array) -> Promise>,
+    timer: JsValue,
+    _tick: Closure<dyn FnMut()>,
+}
+
+impl Contribution {
+    /// Load the blocks from `weights` (SafeTensors), register the inference
+    /// handler and announce the span
+    pub(crate) async fn start(
+        options: ContributionOptions,
+        scheduler: Option<Function>,
+        weights: Vec<u8>,
+        transport: ContributionTransport,
+    ) -> Result<Self, String> {
+        let span = BlockSpan::from_safetensors(
+            weights,
+            options.model_config.clone(),
+            options.start_block,
+            options.end_block,
+        )
+        .map_err(|e| format!("Failed to load blocks: {e}"))?;
+        tracing::info!(
+            "Serving blocks [{}, {}) of {}",
+            options.start_block,
+            options.end_block,
+            options.dht_prefix
+        );
+
+        let worker = Rc::new(Worker {
+            span: Mutex::new(span),
+            scheduler,
+            slice_ms: options.slice_ms,
+        });
+        let handler =
+            Closure::<dyn FnMut(Uint8Array) -> Promise>::new(move |request: Uint8Array| {
+                let worker = worker.clone();
+                future_to_promise(async move {
+                    let response = worker.serve(&request.to_vec()).await;
+                    Ok(Uint8Array::from(response.as_slice()).into())
+                })
+            });
+        transport.handle(INFERENCE_PROTO, &handler);
+
+        let peer_id = transport.peer_id();
+        let accepted = announce(&transport, &options, &peer_id, STATE_ONLINE).await;
+        if accepted == 0 {
+            tracing::warn!("No DHT peer accepted the block announcement; retrying later");
+        }
+
+        let tick = {
+            let (transport, options, peer_id) =
+                (transport.clone(), options.clone(), peer_id.clone());
+            Closure::<dyn FnMut()>::new(move || {
+                let (transport, options, peer_id) =
+                    (transport.clone(), options.clone(), peer_id.clone());
+                spawn_local(async move {
+                    announce(&transport, &options, &peer_id, STATE_ONLINE).await;
+                });
+            })
+        };
+        let timer = call_global(
+            "setInterval",
+            tick.as_ref().unchecked_ref(),
+            &ANNOUNCE_INTERVAL_MS.into(),
+        )
+        .map_err(|e| format!("setInterval failed: {e:?}"))?;
+
+        Ok(Self {
+            transport,
+            options,
+            peer_id,
+            _handler: handler,
+            timer,
+            _tick: tick,
+        })
+    }
+
+    /// Unregister the handler and announce the span as offline
+    pub(crate) fn stop(self) {
+        self.transport.unhandle(INFERENCE_PROTO);
+        if let Err(e) = call_global("clearInterval", &self.timer, &JsValue::UNDEFINED) {
+            tracing::warn!("clearInterval failed: {:?}", e);
+        }
+        spawn_local(async move {
+            announce(&self.transport, &self.options, &self.peer_id, STATE_OFFLINE).await;
+        });
+    }
+}
+
+/// Call a timer function of the global scope (window or worker)
+fn call_global(name: &str, a: &JsValue, b: &JsValue) -> Result<JsValue, JsValue> {
+    let global = js_sys::global();
+    let f: Function = Reflect::get(&global, &name.into())?.dyn_into()?;
+    f.call2(&global, a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Tensor;
+    use serde_json::json;
+
+    fn options(start_block: usize, end_block: usize) -> ContributionOptions {
+        ContributionOptions {
+            dht_prefix: "Llama-3-2-1B-Instruct-hf".to_string(),
+            start_block,
+            end_block,
+            model_config: BlockConfig {
+                hidden_size: 8,
+                num_attention_heads: 2,
+                num_key_value_heads: None,
+                intermediate_size: 16,
+                rope_theta: 10_000.0,
+                rms_norm_eps: 1e-5,
+                max_position_embeddings: 32,
+            },
+            public_name: None,
+            using_relay: true,
+            slice_ms: 10.0,
+        }
+    }
+
+    #[test]
+    fn validates_block_range() {
+        let parse = |start: usize, end: usize| {
+            ContributionOptions::from_json(json!({
+                "dht_prefix": "Llama-3-2-1B-Instruct-hf",
+                "start_block": start,
+                "end_block": end,
+                "model_config": {
+                    "hidden_size": 8,
+                    "num_attention_heads": 2,
+                    "intermediate_size": 16,
+                    "model_type": "llama",
+                },
+            }))
+        };
+        let options = parse(4, 6).unwrap();
+        assert!(options.using_relay);
+        assert_eq!(options.slice_ms, 10.0);
+
+        let field = |errors: Vec<FieldError>| errors[0].field.clone();
+        assert_eq!(field(parse(4, 4).unwrap_err()), "end_block");
+        assert_eq!(field(parse(0, 2).unwrap_err()), "start_block");
+        assert_eq!(
+            field(parse(1, 2 + MAX_BROWSER_BLOCKS).unwrap_err()),
+            "end_block"
+        );
+    }
+
+    #[test]
+    fn reports_missing_and_nested_fields() {
+        let errors = ContributionOptions::from_json(json!({
+            "start_block": 1,
+            "end_block": 2,
+            "model_config": { "hidden_size": 8, "num_attention_heads": 3, "intermediate_size": 16 },
+            "slice_ms": -1,
+        }))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                FieldError::new("dht_prefix", "is required"),
+                FieldError::new(
+                    "model_config.num_attention_heads",
+                    "must divide hidden_size (8)"
+                ),
+                FieldError::new("slice_ms", "must be positive"),
+            ]
+        );
+    }
+
+    #[test]
+    fn server_record_is_hivemind_ext_tuple() {
+        let bytes = server_record(&options(4, 6), "12D3KooWTest", STATE_ONLINE);
+        let rmpv::Value::Ext(64, inner) = rmpv::decode::read_value(&mut &bytes[..]).unwrap() else {
+            panic!("expected ExtType(64)");
+        };
+        let rmpv::Value::Array(tuple) = rmpv::decode::read_value(&mut &inner[..]).unwrap() else {
+            panic!("expected a tuple");
+        };
+        assert_eq!(tuple[0].as_i64(), Some(STATE_ONLINE as i64));
+        let fields = tuple[2].as_map().unwrap();
+        let field = |k: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key.as_str() == Some(k))
+                .map(|(_, v)| v)
+        };
+        assert_eq!(field("start_block").and_then(|v| v.as_i64()), Some(4));
+        assert_eq!(field("using_relay").and_then(|v| v.as_bool()), Some(true));
+        assert_eq!(
+            field("peer_id").and_then(|v| v.as_str()),
+            Some("12D3KooWTest")
+        );
+    }
+
+    #[test]
+    fn slice_budget_runs_out() {
+        let budget = SliceBudget::new(100.0, 10.0);
+        assert!(!budget.exhausted(105.0));
+        assert!(budget.exhausted(110.0));
+        // The initial budget forces a yield before the first block
+        assert!(SliceBudget::new(f64::NEG_INFINITY, 0.0).exhausted(0.0));
+    }
+
+    #[test]
+    fn requests_decode_from_native_wire_format() {
+        #[derive(Serialize)]
+        struct NativeRequest {
+            session_id: u64,
+            seq_pos: u32,
+            payload_type: PayloadType,
+            #[serde(flatten)]
+            tensor: TensorWire,
+        }
+        let tensor = TensorWire::from_tensor(
+            &Tensor::zeros((1, 2, 8), DType::F32, &Device::Cpu).unwrap(),
+            WireDType::F16,
+        )
+        .unwrap();
+        let bytes = rmp_serde::to_vec_named(&NativeRequest {
+            session_id: 9,
+            seq_pos: 3,
+            payload_type: PayloadType::HiddenStates,
+            tensor: tensor.clone(),
+        })
+        .unwrap();
+        let request: InferenceRequest = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(request.session_id, 9);
+        assert_eq!(request.payload_type, PayloadType::HiddenStates);
+        assert_eq!(request.tensor, tensor);
+        assert!(request.active_adapter.is_none());
+    }
+}
@@ -1,7 +1,8 @@
 //! Background DHT crawler.
 //!
-//! Every 60 seconds this task dials each bootstrap peer via p2pd and sends a
-//! `FindRequest` for all known DHT key prefixes. Results are decoded from the
+//! Every 60 seconds this task dials each bootstrap peer via p2pd and reads
+//! the keys of all known DHT prefixes with one batched, concurrent FIND
+//! fan-out. Results are decoded from the
 //! Hivemind Ext(64) wire format and stored in the [`NodeCache`].
 //!
 //! Wire format (from node.rs / shard_cmd.rs):
@@ -17,6 +18,7 @@ use std::{collections::HashMap, sync::Arc, time::Duration};
 use anyhow::Result;
 use chrono::Utc;
 use kwaai_hivemind_dht::protocol::{FindRequest, FindResponse, NodeInfo, RequestAuthInfo};
+use kwaai_hivemind_dht::{find_values, FanOutConfig};
 use kwaai_p2p::NetworkConfig;
 use kwaai_p2p_daemon::{P2PClient, DEFAULT_SOCKET_NAME};
 use libp2p::PeerId;
@@ -57,7 +59,7 @@ async fn crawl_once(cache: &NodeCache, bootstrap_peers: &[String]) -> Result<()>
     let socket = if cfg!(unix) {
         format!("/unix/{}", raw_sock)
     } else {
-        "/ip4/127.0.0.1/tcp/5005".to_string()
+        kwaai_p2p_daemon::default_daemon_addr()
     };
     let mut client = match P2PClient::connect(&socket).await {
         Ok(c) => c,
@@ -103,80 +105,80 @@ async fn crawl_once(cache: &NodeCache, bootstrap_peers: &[String]) -> Result<()>
     // Step 2: also crawl VPK nodes registry
     active_prefixes.push("_kwaai.vpk.nodes".to_string());
 
-    for prefix in &active_prefixes {
-        let keys: Vec<Vec<u8>> = if prefix.starts_with("_kwaai") {
-            vec![dht_key(prefix)]
-        } else {
-            (0..SCAN_BLOCKS)
-                .map(|b| dht_key(&format!("{}.{}", prefix, b)))
-                .collect()
-        };
-
-        let find_req = FindRequest {
-            auth: Some(RequestAuthInfo::new()),
-            keys,
-            peer: Some(NodeInfo {
-                node_id: our_dhtid.clone(),
-            }),
-        };
-        let mut req_bytes = Vec::new();
-        find_req.encode(&mut req_bytes)?;
-
-        for addr in &effective_bootstrap {
-            let Some(peer_str) = addr.split("/p2p/").nth(1) else {
-                continue;
-            };
-            let bp = match peer_str.parse::<PeerId>() {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
-
-            if client.connect_peer(addr).await.is_err() {
-                continue;
+    let keys: Vec<Vec<u8>> = active_prefixes
+        .iter()
+        .flat_map(|prefix| {
+            if prefix.starts_with("_kwaai") {
+                vec![dht_key(prefix)]
+            } else {
+                (0..SCAN_BLOCKS)
+                    .map(|b| dht_key(&format!("{}.{}", prefix, b)))
+                    .collect()
             }
-            tokio::time::sleep(Duration::from_millis(400)).await;
-
-            let resp_bytes = match client
-                .call_unary_handler(&bp.to_bytes(), "DHTProtocol.rpc_find", &req_bytes)
+        })
+        .collect();
+
+    let client = &client;
+    let dials = effective_bootstrap.iter().filter_map(|addr| {
+        let peer = addr.split("/p2p/").nth(1)?.parse::<PeerId>().ok()?;
+        Some(async move { client.connect_peer(addr).await.ok().map(|_| peer) })
+    });
+    let peers: Vec<PeerId> = futures::future::join_all(dials)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    // All prefixes go out as one batched fan-out instead of a sequential
+    // request per prefix and peer.
+    let found = find_values(
+        &peers,
+        &keys,
+        Some(NodeInfo {
+            node_id: our_dhtid.clone(),
+        }),
+        &FanOutConfig::default(),
+        |peer, request| async move {
+            let bytes = client
+                .call_unary_handler(
+                    &peer.to_bytes(),
+                    "DHTProtocol.rpc_find",
+                    &request.encode_to_vec(),
+                )
                 .await
-            {
-                Ok(b) => b,
-                Err(e) => {
-                    warn!("rpc_find failed for {addr}: {e}");
-                    continue;
-                }
-            };
-
-            tracing::debug!("rpc_find response {} bytes from {addr}", resp_bytes.len());
-
-            let Ok(resp) = FindResponse::decode(&resp_bytes[..]) else {
-                warn!("FindResponse decode failed for {addr}");
-                continue;
-            };
-
-            tracing::debug!("{} results in FindResponse from {addr}", resp.results.len());
+                .map_err(|e| kwaai_hivemind_dht::Error::Network(e.to_string()))?;
+            Ok(FindResponse::decode(&bytes[..])?)
+        },
+    )
+    .await;
+    if found.failed > 0 {
+        warn!("{} rpc_find request(s) failed", found.failed);
+    }
+    tracing::debug!(
+        "{} key(s) with values from {} peer(s)",
+        found.values.len(),
+        found.responded.len()
+    );
 
-            for result in &resp.results {
-                if result.value.is_empty() {
-                    continue;
-                }
-                tracing::debug!(
-                    "  result rt={} value_len={}",
-                    result.result_type,
-                    result.value.len()
-                );
-                match result.result_type {
-                    1 => {
-                        if let Some(entry) = decode_regular(&result.value) {
-                            tracing::debug!("  → decoded peer {}", entry.peer_id);
-                            discovered.entry(entry.peer_id.clone()).or_insert(entry);
-                        } else {
-                            tracing::debug!("  → decode_regular returned None");
-                        }
+    for key in &keys {
+        for (_, result) in found.values.get(key).into_iter().flatten() {
+            tracing::debug!(
+                "  result rt={} value_len={}",
+                result.result_type,
+                result.value.len()
+            );
+            match result.result_type {
+                1 => {
+                    if let Some(entry) = decode_regular(&result.value) {
+                        tracing::debug!("  → decoded peer {}", entry.peer_id);
+                        discovered.entry(entry.peer_id.clone()).or_insert(entry);
+                    } else {
+                        tracing::debug!("  → decode_regular returned None");
                     }
-                    2 => decode_dictionary(&result.value, &mut discovered),
-                    _ => {}
                 }
+                2 => decode_dictionary(&result.value, &mut discovered),
+                _ => {}
             }
         }
     }
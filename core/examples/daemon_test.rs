@@ -40,7 +40,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     println!("[DAEMON] Spawned at: {}", daemon.listen_addr());
 
-    let mut client = daemon.client().await?;
+    let client = daemon.client().await?;
     let peer_id = client.identify().await?;
     println!("[PEER ID] {}", peer_id);
 
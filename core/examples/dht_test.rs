@@ -32,7 +32,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Connect client
     info!("\n[2/4] Connecting client...");
-    let mut client = daemon.client().await?;
+    let client = daemon.client().await?;
 
     let peer_id = client.identify().await?;
     info!("Our Peer ID: {}", peer_id);
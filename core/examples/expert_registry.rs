@@ -7,13 +7,30 @@
 //!
 //! Run with: cargo run --example expert_registry
 
+use async_trait::async_trait;
 use candle_core::{Device, Tensor};
 use kwaai_distributed::{
     expert::{Expert, ExpertId, ExpertRegistry, LocalExpert},
-    moe::{DistributedMoE, ExpertRouter, MixtureOfExperts, MoEConfig, TopKRouter},
-    DistributedConfig,
+    moe::{DistributedMoE, ExpertClient, ExpertRouter, MixtureOfExperts, MoEConfig, TopKRouter},
+    DistributedConfig, DistributedResult,
 };
 use std::error::Error;
+use std::sync::Arc;
+
+/// Stands in for the P2P transport: every "peer" echoes its input back.
+struct LoopbackClient;
+
+#[async_trait]
+impl ExpertClient for LoopbackClient {
+    async fn forward(
+        &self,
+        _peer_id: &str,
+        _expert: ExpertId,
+        input: &Tensor,
+    ) -> DistributedResult<Tensor> {
+        Ok(input.clone())
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -250,7 +267,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let gate_weights = Tensor::randn(0f32, 0.02, &[hidden_dim, num_experts], &device)?;
     let router = TopKRouter::new(gate_weights, 2, num_experts, 0.01);
-    let mut moe = DistributedMoE::new(Box::new(router), config);
+    let mut moe =
+        DistributedMoE::new(Box::new(router), config).with_client(Arc::new(LoopbackClient));
 
     // Register experts
     for i in 0..4 {
@@ -265,7 +283,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     println!("  Remote experts: 4");
     println!("  Router top-k:   {}", moe.router().top_k());
 
-    // Forward pass (placeholder implementation)
+    // Forward pass: experts 0-3 run here, 4-7 through the loopback "peers"
     let input = Tensor::randn(0f32, 1.0, &[4, 16, hidden_dim], &device)?;
     println!("\nForward pass:");
     println!("  Input shape: {:?}", input.dims());
@@ -11,7 +11,7 @@
 use candle_core::{Device, Tensor};
 use kwaai_compression::{BlockwiseQuantizer, CompressedData, Compressor};
 use kwaai_distributed::averaging::{
-    AveragingConfig, AveragingResult, DecentralizedAverager, ParameterAverager,
+    AllReduceStrategy, AveragingConfig, AveragingResult, DecentralizedAverager, ParameterAverager,
 };
 use std::error::Error;
 use std::time::Duration;
@@ -180,6 +180,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 exchange_timeout: Duration::from_secs(30),
                 quantization_block_size: 32,
                 enable_compression: true,
+                ..Default::default()
             },
         ),
         (
@@ -190,6 +191,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 exchange_timeout: Duration::from_secs(120),
                 quantization_block_size: 128,
                 enable_compression: true,
+                allreduce_strategy: AllReduceStrategy::Ring,
+                ..Default::default()
             },
         ),
     ];
@@ -204,6 +207,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             config.quantization_block_size
         );
         println!("  Compression:      {}", config.enable_compression);
+        println!("  All-reduce:       {:?}", config.allreduce_strategy);
         println!();
     }
 
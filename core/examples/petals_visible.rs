@@ -11,15 +11,18 @@
 //!
 //! Run with: cargo run --release --example petals_visible -- --name "My-Node"
 //!
+//! The DHT prefix and block count come from the `_petals.models` registry;
+//! pass `--num-blocks N` for a model no server has registered yet.
+//!
 //! After running, check map.petals.dev to see if your node appears.
 
 use kwaai_hivemind_dht::{
     codec::DHTRequest,
-    protocol::{NodeInfo, RequestAuthInfo, StoreRequest},
+    protocol::{FindRequest, FindResponse, NodeInfo, RequestAuthInfo, StoreRequest},
     value::get_dht_time,
     DHTStorage,
 };
-use kwaai_p2p::{hivemind::ServerInfo, NetworkConfig};
+use kwaai_p2p::{catalog, hivemind::ServerInfo, ModelCatalog, ModelIdentity, NetworkConfig};
 use kwaai_p2p_daemon::{stream, P2PDaemon};
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
@@ -341,44 +344,61 @@ async fn fetch_most_popular_model() -> Result<String, Box<dyn Error>> {
     Ok(best_model)
 }
 
+/// Read the `_petals.models` registry from the first bootstrap peer; empty
+/// when it cannot be reached.
+async fn fetch_model_catalog(
+    client: &kwaai_p2p_daemon::P2PClient,
+    config: &NetworkConfig,
+) -> ModelCatalog {
+    use prost::Message;
+    let mut catalog = ModelCatalog::new(get_dht_time() as u64);
+    let Some(bootstrap_peer_id) = config
+        .bootstrap_peers
+        .first()
+        .and_then(|addr| addr.split("/p2p/").nth(1))
+        .and_then(|s| s.parse::<PeerId>().ok())
+    else {
+        return catalog;
+    };
+    let request = FindRequest {
+        auth: Some(RequestAuthInfo::new()),
+        keys: vec![catalog::registry_key()],
+        peer: None,
+    };
+    match client
+        .call_unary_handler(
+            &bootstrap_peer_id.to_bytes(),
+            "DHTProtocol.rpc_find",
+            &request.encode_to_vec(),
+        )
+        .await
+    {
+        Ok(bytes) => match FindResponse::decode(&bytes[..]) {
+            Ok(response) => {
+                for result in response.results {
+                    catalog.add_value(result.result_type, &result.value);
+                }
+            }
+            Err(e) => warn!("Bad model registry response: {}", e),
+        },
+        Err(e) => warn!("Failed to read the model registry: {}", e),
+    }
+    catalog
+}
+
 /// Announce server blocks and model info to the DHT
 #[allow(clippy::too_many_arguments)]
 async fn announce_to_dht(
-    client: &mut kwaai_p2p_daemon::P2PClient,
+    client: &kwaai_p2p_daemon::P2PClient,
     peer_id: PeerId,
     storage: &SharedStorage,
     config: &NetworkConfig,
     dht_server_info: &DHTServerInfo,
-    model_name: &str,
+    model: &ModelIdentity,
     start_block: i32,
     end_block: i32,
 ) -> Result<(), Box<dyn Error>> {
-    // Build DHT prefix from model name
-    // Convert model name to DHT prefix format:
-    // "Llama-3.1-8B-Instruct" -> "Meta-Llama-3-1-8B-Instruct-hf"
-    let dht_prefix = if !model_name.contains("/") {
-        // Add Meta- prefix if not present for Llama models
-        //let prefix = if model_name.starts_with("Llama") && !model_name.starts_with("Meta-") {
-        //    format!("Meta-{}", model_name)
-        //} else {
-        //    model_name.to_string()
-        //};
-
-        let prefix = model_name.to_string();
-
-        // Replace dots with hyphens
-        let prefix = prefix.replace(".", "-");
-
-        // Add -hf suffix if not present
-        if prefix.ends_with("-hf") {
-            prefix
-        } else {
-            format!("{}-hf", prefix)
-        }
-    } else {
-        // If it contains /, assume it's already in org/model format
-        model_name.replace(".", "-").replace("/", "-")
-    };
+    let dht_prefix = &model.dht_prefix;
 
     info!("📋 DHT Prefix: {}", dht_prefix);
     info!(
@@ -408,7 +428,7 @@ async fn announce_to_dht(
         keys.push(hashed_key);
         subkeys.push(subkey.clone());
         values.push(info_bytes.clone());
-        expiration_times.push(get_dht_time() + 360.0);
+        expiration_times.push(get_dht_time() + config.record_ttl.as_secs_f64());
         in_cache_flags.push(false);
     }
 
@@ -437,9 +457,6 @@ async fn announce_to_dht(
             Ok(_) => {
                 info!("Connected to bootstrap peer");
 
-                // Give connection a moment to stabilize
-                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-
                 // Now send STORE request
                 if let Some(peer_id_str) = bootstrap_addr.split("/p2p/").nth(1) {
                     if let Ok(bootstrap_peer_id) = peer_id_str.parse::<PeerId>() {
@@ -470,21 +487,13 @@ async fn announce_to_dht(
     // Announce model info to _petals.models registry
     // NOTE: This should be the TOTAL blocks in the complete model, not what this node serves
     // Individual ServerInfo entries contain start_block/end_block for what this node serves
-    let total_model_blocks = match model_name {
-        "Llama-3.3-70B-Instruct" => 80,
-        "Llama-3.1-8B-Instruct" => 32,
-        "Llama-3.1-70B-Instruct" => 80,
-        "Llama-2-70B-hf" => 80,
-        _ => 80,
-    };
-
     let model_info = ModelInfo {
-        num_blocks: total_model_blocks,
-        repository: format!("https://huggingface.co/meta-llama/{}", model_name),
+        num_blocks: model.num_blocks as i32,
+        repository: model.repository.clone(),
     };
 
     let registry_key = generate_dht_id("_petals.models");
-    let registry_subkey = rmp_serde::to_vec(&dht_prefix)?;
+    let registry_subkey = rmp_serde::to_vec(dht_prefix)?;
     let registry_value = model_info.to_msgpack()?;
 
     let model_store_request = StoreRequest {
@@ -492,7 +501,7 @@ async fn announce_to_dht(
         keys: vec![registry_key],
         subkeys: vec![registry_subkey],
         values: vec![registry_value],
-        expiration_time: vec![get_dht_time() + 360.0],
+        expiration_time: vec![get_dht_time() + config.record_ttl.as_secs_f64()],
         in_cache: vec![false],
         peer: Some(node_info.clone()),
     };
@@ -593,6 +602,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(8);
 
+    // Total blocks in the model, when the registry does not list it yet
+    let num_blocks: Option<usize> = args
+        .iter()
+        .position(|a| a == "--num-blocks")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|s| s.parse().ok());
+
     println!("KwaaiNet Petals-Visible Node");
     println!("============================\n");
 
@@ -645,7 +661,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     println!("[DAEMON] Spawned at: {}", daemon.listen_addr());
 
-    let mut client = daemon.client().await?;
+    let client = daemon.client().await?;
     let peer_id_hex = client.identify().await?;
 
     // Convert to PeerId and print in base58 format (human-readable)
@@ -697,14 +713,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // =========================================================================
     info!("[5/5] Announcing server blocks and model info to DHT...");
 
+    // Announce under the prefix and block count the model's other servers
+    // registered, falling back to names derived from the model name.
+    let catalog = fetch_model_catalog(&client, &config).await;
+    let model = ModelIdentity::resolve(&model_name, Some(&catalog), num_blocks);
+    println!(
+        "[MODEL] {} → prefix {}, {} blocks",
+        model.model, model.dht_prefix, model.num_blocks
+    );
+
     // Make initial announcement
     announce_to_dht(
-        &mut client,
+        &client,
         peer_id,
         &storage,
         &config,
         &dht_server_info,
-        &model_name,
+        &model,
         start_block,
         end_block,
     )
@@ -728,9 +753,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Accept incoming streams from daemon
     let storage_clone = storage.clone();
 
-    // Setup periodic re-announcement interval
-    let mut announce_interval = tokio::time::interval(Duration::from_secs(120)); // Re-announce every 120 seconds
-    announce_interval.tick().await; // Skip first tick (already announced above)
+    // Re-announce on a jittered timer (already announced above)
+    let mut next_announce = Box::pin(tokio::time::sleep(config.next_announce_delay()));
 
     loop {
         tokio::select! {
@@ -763,16 +787,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 info!("📊 DHT Storage: {} total entries, {} valid", total, valid);
             }
 
-            // Re-announce to DHT periodically (every 120 seconds)
-            _ = announce_interval.tick() => {
+            // Re-announce to DHT periodically (announce_interval ± jitter)
+            _ = &mut next_announce => {
+                next_announce
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + config.next_announce_delay());
                 info!("⏰ Re-announcing to DHT network...");
                 match announce_to_dht(
-                    &mut client,
+                    &client,
                     peer_id,
                     &storage,
                     &config,
                     &dht_server_info,
-                    &model_name,
+                    &model,
                     start_block,
                     end_block,
                 ).await {
@@ -895,7 +922,7 @@ async fn handle_find(
 
 /// Send a STORE request to a peer via Hivemind protocol
 async fn send_store_to_peer(
-    client: &mut kwaai_p2p_daemon::P2PClient,
+    client: &kwaai_p2p_daemon::P2PClient,
     peer_id_bytes: &[u8],
     store_request: StoreRequest,
 ) -> Result<(), Box<dyn Error>> {
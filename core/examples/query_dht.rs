@@ -46,7 +46,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .spawn()
         .await?;
 
-    let mut client = daemon.client().await?;
+    let client = daemon.client().await?;
     let peer_id_hex = client.identify().await?;
     let peer_id = PeerId::from_bytes(&hex::decode(&peer_id_hex)?)?;
     println!("[PEER ID] {}\n", peer_id.to_base58());
@@ -56,7 +56,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     if let Some(bootstrap_addr) = config.bootstrap_peers.first() {
         client.connect_peer(bootstrap_addr).await?;
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
         if let Some(peer_id_str) = bootstrap_addr.split("/p2p/").nth(1) {
             if let Ok(bootstrap_peer_id) = peer_id_str.parse::<PeerId>() {
@@ -1,7 +1,7 @@
 //! Day 10: P2P Tensor Exchange Example
 //!
 //! Demonstrates sending tensors between P2P nodes:
-//! - Serialize tensors for network transmission
+//! - Serialize tensors for network transmission as `TensorWire`
 //! - Compress using 8-bit quantization
 //! - Exchange via libp2p request-response
 //! - Reconstruct tensors on receiving end
@@ -22,7 +22,7 @@
 
 use candle_core::{Device, Tensor};
 use futures::StreamExt;
-use kwaai_compression::{BlockwiseQuantizer, CompressedData, Compressor, QuantizedTensor};
+use kwaai_compression::{BlockwiseQuantizer, CompressedData, Compressor, TensorWire};
 use libp2p::{
     identify, identity,
     kad::{self, store::MemoryStore, Mode},
@@ -44,10 +44,8 @@ struct TensorMessage {
     msg_type: TensorMessageType,
     /// Tensor name/identifier
     name: String,
-    /// Compressed tensor data (if applicable)
-    data: Option<QuantizedTensor>,
-    /// Original shape
-    shape: Vec<usize>,
+    /// Tensor data, quantized on the wire (if applicable)
+    data: Option<TensorWire>,
     /// Metadata
     metadata: String,
 }
@@ -317,21 +315,15 @@ async fn main() -> Result<(), Box<dyn Error>> {
                             TensorMessageType::TensorData => {
                                 println!("\n  RECEIVED TENSOR:");
                                 println!("  Name: {}", request.name);
-                                println!("  Shape: {:?}", request.shape);
                                 println!("  Metadata: {}", request.metadata);
 
-                                if let Some(compressed) = &request.data {
-                                    println!(
-                                        "  Compressed size: {} bytes",
-                                        compressed.size_bytes()
-                                    );
-                                    println!(
-                                        "  Compression ratio: {:.2}x",
-                                        compressed.compression_ratio()
-                                    );
-
-                                    // Decompress
-                                    match compressor.decompress(compressed) {
+                                if let Some(wire) = &request.data {
+                                    println!("  Shape: {:?}", wire.shape);
+                                    println!("  Wire dtype: {:?}", wire.dtype);
+                                    println!("  Payload size: {} bytes", wire.size_bytes());
+
+                                    // Decode (dequantizes 8-bit payloads)
+                                    match wire.to_tensor(&device) {
                                         Ok(tensor) => {
                                             let data: Vec<f32> = tensor.flatten_all()?.to_vec1()?;
                                             let mean = data.iter().sum::<f32>() / data.len() as f32;
@@ -363,7 +355,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                     msg_type: TensorMessageType::Ack,
                                     name: request.name.clone(),
                                     data: None,
-                                    shape: vec![],
                                     metadata: "received".to_string(),
                                 };
                                 if swarm
@@ -428,8 +419,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                             let msg = TensorMessage {
                                 msg_type: TensorMessageType::TensorData,
                                 name: "gradient_layer_1".to_string(),
-                                data: Some(compressed),
-                                shape,
+                                data: Some(TensorWire::from_quantized(&compressed)),
                                 metadata: "training_step_42".to_string(),
                             };
 
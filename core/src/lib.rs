@@ -5,14 +5,28 @@
 //! ## Crates
 //!
 //! - [`kwaai_p2p`]: P2P networking with libp2p and Kademlia DHT
-//! - [`kwaai_inference`]: ML inference engine with Candle
-//! - [`kwaai_distributed`]: Distributed ML operations (MoE, averaging)
-//! - [`kwaai_compression`]: Gradient compression utilities
+//! - [`kwaai_hivemind_dht`]: Hivemind DHT protocol (Petals-compatible)
+//! - `kwaai_inference`: ML inference engine with Candle (`inference` feature)
+//! - `kwaai_distributed`: Distributed ML operations — MoE, averaging (`inference` feature)
+//! - `kwaai_compression`: Gradient compression utilities (`inference` feature)
+//! - `kwaai_p2p_daemon`: go-libp2p-daemon client (`daemon` feature)
+//!
+//! ## Minimal builds
+//!
+//! Embedders that only need the DHT stack build with
+//! `--no-default-features --features minimal`, which leaves out candle and
+//! the Go daemon build entirely.
 
+#[cfg(feature = "inference")]
 pub use kwaai_compression as compression;
+#[cfg(feature = "inference")]
 pub use kwaai_distributed as distributed;
+pub use kwaai_hivemind_dht as hivemind_dht;
+#[cfg(feature = "inference")]
 pub use kwaai_inference as inference;
 pub use kwaai_p2p as p2p;
+#[cfg(feature = "daemon")]
+pub use kwaai_p2p_daemon as p2p_daemon;
 
 /// KwaaiNet version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,137 @@
+//! Minimal-build invariant: the DHT stack builds without ML or daemon crates.
+//!
+//! Walks the resolved dependency graph from `cargo metadata`, following normal
+//! and build edges, and fails if a crate that pulls in candle or the Go daemon
+//! build is reachable from `kwaai-p2p`, `kwaai-hivemind-dht`, or this crate
+//! built with `--no-default-features --features minimal`.
+
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::process::Command;
+
+/// Crates that must build on their own.
+const STANDALONE: &[&str] = &["kwaai-p2p", "kwaai-hivemind-dht"];
+
+/// Crates that must not be reachable from a minimal build.
+const FORBIDDEN: &[&str] = &[
+    "candle-core",
+    "candle-nn",
+    "candle-transformers",
+    "tokenizers",
+    "kwaai-inference",
+    "kwaai-distributed",
+    "kwaai-compression",
+    "kwaai-p2p-daemon",
+];
+
+struct Graph {
+    names: HashMap<String, String>,
+    deps: HashMap<String, Vec<String>>,
+    members: Vec<String>,
+}
+
+impl Graph {
+    fn load(feature_args: &[&str]) -> Self {
+        let output = Command::new(env!("CARGO"))
+            .args(["metadata", "--format-version", "1", "--manifest-path"])
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+            .args(feature_args)
+            .output()
+            .expect("run cargo metadata");
+        assert!(
+            output.status.success(),
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let meta: Value = serde_json::from_slice(&output.stdout).expect("parse cargo metadata");
+
+        let names = meta["packages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| {
+                (
+                    p["id"].as_str().unwrap().to_string(),
+                    p["name"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+
+        let deps = meta["resolve"]["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| {
+                let edges = node["deps"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .filter(|dep| {
+                        dep["dep_kinds"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .any(|k| k["kind"].as_str() != Some("dev"))
+                    })
+                    .map(|dep| dep["pkg"].as_str().unwrap().to_string())
+                    .collect();
+                (node["id"].as_str().unwrap().to_string(), edges)
+            })
+            .collect();
+
+        let members = meta["workspace_members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| id.as_str().unwrap().to_string())
+            .collect();
+
+        Self {
+            names,
+            deps,
+            members,
+        }
+    }
+
+    fn member(&self, name: &str) -> &str {
+        self.members
+            .iter()
+            .find(|id| self.names[*id] == name)
+            .unwrap_or_else(|| panic!("{name} is not a workspace member"))
+    }
+
+    /// Forbidden crates reachable from `root`, each with the crate that pulled it in.
+    fn forbidden_from(&self, root: &str) -> Vec<String> {
+        let mut seen = HashSet::from([root.to_string()]);
+        let mut queue = VecDeque::from([root.to_string()]);
+        let mut found = Vec::new();
+        while let Some(id) = queue.pop_front() {
+            for dep in self.deps.get(&id).into_iter().flatten() {
+                if !seen.insert(dep.clone()) {
+                    continue;
+                }
+                if FORBIDDEN.contains(&self.names[dep].as_str()) {
+                    found.push(format!("{} (via {})", self.names[dep], self.names[&id]));
+                }
+                queue.push_back(dep.clone());
+            }
+        }
+        found
+    }
+}
+
+#[test]
+fn dht_crates_build_without_ml_or_daemon() {
+    let graph = Graph::load(&[]);
+    for name in STANDALONE {
+        let leaked = graph.forbidden_from(graph.member(name));
+        assert!(leaked.is_empty(), "{name} pulls in {leaked:?}");
+    }
+}
+
+#[test]
+fn minimal_feature_leaves_out_ml_and_daemon() {
+    let graph = Graph::load(&["--no-default-features", "--features", "minimal"]);
+    let leaked = graph.forbidden_from(graph.member("kwaai-core"));
+    assert!(leaked.is_empty(), "kwaai-core[minimal] pulls in {leaked:?}");
+}